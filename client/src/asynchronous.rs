@@ -0,0 +1,627 @@
+// src/asynchronous.rs
+
+//! An async client on tokio, mirroring the blocking one.
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io::ErrorKind;
+use std::marker::PhantomData;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use silentdb_data_encoding::{from_bytes, Document, Framer, FromValue, IntoValue, Value};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{self, TcpStream, ToSocketAddrs};
+use tokio::sync::{Mutex, MutexGuard};
+use tokio_rustls::TlsConnector;
+
+use crate::command::{self, Commands, ServerInfo};
+use crate::error::ClientError;
+use crate::options::{
+    ClientOptions, DeleteResult, FindOptions, InsertManyResult, InsertOneResult, UpdateOptions,
+    UpdateResult,
+};
+use crate::scram::Scram;
+
+/// Bytes read from the stream at a time.
+const READ_CHUNK: usize = 64 * 1024;
+
+/// A plain or TLS stream to a server.
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> Stream for S {}
+
+struct Connection {
+    stream: Box<dyn Stream>,
+    framer: Framer,
+    server: ServerInfo,
+    timeout: Option<Duration>,
+    /// Whether a cursor was dropped before the server finished streaming
+    /// its matches, which must be read off the stream before anything else.
+    draining: bool,
+    /// Whether the stream failed, so that the connection must be replaced.
+    broken: bool,
+}
+
+/// Runs `future`, failing with `Timeout` if it takes longer than `timeout`.
+async fn within<F, T>(timeout: Option<Duration>, future: F) -> Result<T, ClientError>
+where
+    F: Future<Output = Result<T, ClientError>>,
+{
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, future)
+            .await
+            .map_err(|_| ClientError::Timeout)?,
+        None => future.await,
+    }
+}
+
+impl std::fmt::Debug for Connection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Connection")
+            .field("server", &self.server)
+            .field("draining", &self.draining)
+            .field("broken", &self.broken)
+            .finish()
+    }
+}
+
+impl Connection {
+    /// Connects to the first of `addrs` to accept, with `options`, and
+    /// shakes hands with the server there.
+    async fn open(
+        addrs: &[SocketAddr],
+        options: &ClientOptions,
+    ) -> Result<Connection, ClientError> {
+        let stream = within(options.connect_timeout, async {
+            Ok::<_, ClientError>(TcpStream::connect(addrs).await?)
+        })
+        .await?;
+        stream.set_nodelay(true)?;
+        let stream: Box<dyn Stream> = match &options.tls {
+            Some(tls) => {
+                let (config, name) = tls.config()?;
+                let connect = TlsConnector::from(config).connect(name, stream);
+                let stream = within(options.timeout, async {
+                    Ok::<_, ClientError>(connect.await?)
+                })
+                .await?;
+                Box::new(stream)
+            }
+            None => Box::new(stream),
+        };
+        let mut connection = Connection {
+            stream,
+            framer: Framer::new(),
+            server: ServerInfo {
+                version: String::new(),
+                max_document_len: usize::MAX,
+            },
+            timeout: options.timeout,
+            draining: false,
+            broken: false,
+        };
+        connection.server = command::handshake(connection.run(&command::hello()).await?)?;
+        if let Some(credentials) = &options.credentials {
+            let mut scram = Scram::new(credentials);
+            let proof = scram.prove(connection.run(&scram.start()).await?)?;
+            scram.finish(connection.run(&proof).await?)?;
+        }
+        Ok(connection)
+    }
+
+    async fn send(&mut self, command: &Document) -> Result<(), ClientError> {
+        let bytes = command::encode(command, self.server.max_document_len)?;
+        let sent = within(self.timeout, async {
+            self.stream.write_all(&bytes).await?;
+            Ok::<_, ClientError>(())
+        })
+        .await;
+        if sent.is_err() {
+            self.broken = true;
+        }
+        sent
+    }
+
+    async fn reply(&mut self) -> Result<Document, ClientError> {
+        let reply = self.read_reply().await;
+        if reply.as_ref().is_err_and(ClientError::breaks_connection) {
+            self.broken = true;
+        }
+        reply
+    }
+
+    async fn read_reply(&mut self) -> Result<Document, ClientError> {
+        let mut chunk = vec![0; READ_CHUNK];
+        loop {
+            if let Some(frame) = self.framer.next_frame()? {
+                return Ok(from_bytes(&frame)?);
+            }
+            let read = within(self.timeout, async {
+                Ok::<_, ClientError>(self.stream.read(&mut chunk).await?)
+            })
+            .await?;
+            if read == 0 {
+                return Err(ClientError::Io(ErrorKind::UnexpectedEof.into()));
+            }
+            self.framer.push(&chunk[..read]);
+        }
+    }
+
+    /// Sends `command` and returns its reply, first reading off the rest of
+    /// any stream left behind by a dropped cursor.
+    async fn run(&mut self, command: &Document) -> Result<Document, ClientError> {
+        while self.draining {
+            // An error reply also ends a stream
+            self.draining = matches!(command::batch(self.reply().await?), Ok((_, true)));
+        }
+        self.send(command).await?;
+        self.reply().await
+    }
+}
+
+/// An async client connected to a server over the native protocol, the
+/// counterpart of the blocking `Client`.
+///
+/// Its clones and collections share the one connection, taking turns
+/// running commands on it; a cursor holds it until dropped. Commands are
+/// retried, and broken connections replaced, as the blocking client's are.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use silentdb_client::asynchronous::Client;
+/// # use silentdb_data_encoding::Document;
+/// # async fn example() {
+/// let client = Client::connect("127.0.0.1:27117").await.unwrap();
+/// let users = client.collection::<Document>("users");
+/// let mut cursor = users.find(&Document::new()).await.unwrap();
+/// while let Some(user) = cursor.next().await {
+///     println!("{:?}", user.unwrap());
+/// }
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Client {
+    connection: Arc<Mutex<Connection>>,
+    server: ServerInfo,
+    /// Where the server was found, and how, to reconnect to it.
+    addrs: Arc<[SocketAddr]>,
+    options: Arc<ClientOptions>,
+}
+
+impl Client {
+    /// Connects to the server at `addr`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no connection can be made, or the handshake
+    /// finds something other than a server on the other end.
+    pub async fn connect<A: ToSocketAddrs>(addr: A) -> Result<Client, ClientError> {
+        Client::connect_with(addr, ClientOptions::new()).await
+    }
+
+    /// Connects to the server at `addr` with `options`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no connection can be made within the connect
+    /// timeout, the TLS handshake fails, or the handshake with the server
+    /// does, or the server turns down the credentials, with code 18.
+    pub async fn connect_with<A: ToSocketAddrs>(
+        addr: A,
+        options: ClientOptions,
+    ) -> Result<Client, ClientError> {
+        let addrs: Arc<[SocketAddr]> = net::lookup_host(addr).await?.collect();
+        let connection = Connection::open(&addrs, &options).await?;
+        Ok(Client {
+            server: connection.server.clone(),
+            connection: Arc::new(Mutex::new(connection)),
+            addrs,
+            options: Arc::new(options),
+        })
+    }
+
+    /// Returns the version of the server connected to.
+    pub fn server_version(&self) -> &str {
+        &self.server.version
+    }
+
+    /// Returns the largest request the server accepts, in bytes.
+    pub fn max_document_len(&self) -> usize {
+        self.server.max_document_len
+    }
+
+    /// Returns a handle to the collection `name`, reading and writing its
+    /// documents as `T`.
+    pub fn collection<T>(&self, name: &str) -> Collection<T> {
+        Collection {
+            client: self.clone(),
+            name: name.to_string(),
+            marker: PhantomData,
+        }
+    }
+
+    /// Runs the read `command`, retrying it if the options say to.
+    async fn read(&self, command: &Document) -> Result<Document, ClientError> {
+        let mut connection = self.connection.lock().await;
+        self.run(&mut connection, command, self.options.retry_reads)
+            .await
+    }
+
+    /// Runs the write `command`, with an operation id to retry it by if
+    /// the options say to.
+    async fn write(&self, mut command: Document) -> Result<Document, ClientError> {
+        let retry = self.options.retry_writes;
+        if retry {
+            command::add_op_id(&mut command);
+        }
+        let mut connection = self.connection.lock().await;
+        self.run(&mut connection, &command, retry).await
+    }
+
+    /// Runs `command` on `connection`, as the blocking `Client` does.
+    async fn run(
+        &self,
+        connection: &mut Connection,
+        command: &Document,
+        retry: bool,
+    ) -> Result<Document, ClientError> {
+        if connection.broken {
+            *connection = Connection::open(&self.addrs, &self.options).await?;
+        }
+        match connection.run(command).await.and_then(command::check) {
+            Err(e) if retry && e.is_retryable() => {
+                self.recover(connection).await?;
+                connection.run(command).await.and_then(command::check)
+            }
+            result => result,
+        }
+    }
+
+    /// Waits out the backoff before a retry on `connection`, reconnecting
+    /// if it broke, with the backoff doubling after each failed attempt.
+    async fn recover(&self, connection: &mut Connection) -> Result<(), ClientError> {
+        let mut attempt = 0;
+        loop {
+            tokio::time::sleep(command::backoff(self.options.retry_backoff, attempt)).await;
+            if !connection.broken {
+                return Ok(());
+            }
+            match Connection::open(&self.addrs, &self.options).await {
+                Ok(reconnected) => {
+                    *connection = reconnected;
+                    return Ok(());
+                }
+                Err(e) if e.is_retryable() && attempt + 1 < command::RECONNECT_ATTEMPTS => {
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// An async handle to a collection on the server, the counterpart of the
+/// blocking `Collection`.
+pub struct Collection<T = Document> {
+    client: Client,
+    name: String,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for Collection<T> {
+    fn clone(&self) -> Self {
+        Collection {
+            client: self.client.clone(),
+            name: self.name.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for Collection<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Collection")
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+impl<T> Collection<T> {
+    /// Returns the collection's name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn commands(&self) -> Commands<'_> {
+        Commands {
+            collection: &self.name,
+        }
+    }
+
+    /// Returns the documents matching `filter`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the command cannot be sent, or the server
+    /// rejects it.
+    pub async fn find(&self, filter: &Document) -> Result<Cursor<'_, T>, ClientError>
+    where
+        T: FromValue,
+    {
+        self.find_with(filter, &FindOptions::new()).await
+    }
+
+    /// Returns the documents matching `filter`, with `options`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the command cannot be sent, or the server
+    /// rejects it.
+    pub async fn find_with(
+        &self,
+        filter: &Document,
+        options: &FindOptions,
+    ) -> Result<Cursor<'_, T>, ClientError>
+    where
+        T: FromValue,
+    {
+        let mut connection = self.client.connection.lock().await;
+        let find = self.commands().find(filter, options);
+        let retry = self.client.options.retry_reads;
+        let reply = self.client.run(&mut connection, &find, retry).await?;
+        let (batch, more) = command::batch(reply)?;
+        Ok(Cursor {
+            connection,
+            batch,
+            more,
+            marker: PhantomData,
+        })
+    }
+
+    /// Returns the first document matching `filter`, if there is one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the command fails, or the document cannot be
+    /// converted.
+    pub async fn find_one(&self, filter: &Document) -> Result<Option<T>, ClientError>
+    where
+        T: FromValue,
+    {
+        let mut cursor = self.find_with(filter, &FindOptions::new().limit(1)).await?;
+        cursor.next().await.transpose()
+    }
+
+    /// Inserts `document`, giving it an `_id` if it has none.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `document` does not convert into a document, or
+    /// the server rejects it, such as for a duplicate key.
+    pub async fn insert_one(&self, document: T) -> Result<InsertOneResult, ClientError>
+    where
+        T: IntoValue,
+    {
+        let mut result = self.insert_many([document]).await?;
+        match result.inserted_ids.pop() {
+            Some(inserted_id) => Ok(InsertOneResult { inserted_id }),
+            None => Err(ClientError::Protocol("no inserted id".to_string())),
+        }
+    }
+
+    /// Inserts `documents` in one command.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a document does not convert, or the server
+    /// rejects the insert.
+    pub async fn insert_many<I>(&self, documents: I) -> Result<InsertManyResult, ClientError>
+    where
+        I: IntoIterator<Item = T>,
+        T: IntoValue,
+    {
+        let documents = documents
+            .into_iter()
+            .map(command::to_document)
+            .collect::<Result<Vec<_>, _>>()?;
+        let commands = self.commands();
+        commands.inserted(self.client.write(commands.insert(documents)).await?)
+    }
+
+    /// Applies the update operators of `update` to the first document
+    /// matching `filter`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server rejects the update.
+    pub async fn update_one(
+        &self,
+        filter: &Document,
+        update: &Document,
+    ) -> Result<UpdateResult, ClientError> {
+        self.update_one_with(filter, update, &UpdateOptions::new())
+            .await
+    }
+
+    /// Applies the update operators of `update` to every document matching
+    /// `filter`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server rejects the update.
+    pub async fn update_many(
+        &self,
+        filter: &Document,
+        update: &Document,
+    ) -> Result<UpdateResult, ClientError> {
+        self.update_many_with(filter, update, &UpdateOptions::new())
+            .await
+    }
+
+    /// Replaces the first document matching `filter` with `replacement`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `replacement` does not convert, or the server
+    /// rejects it.
+    pub async fn replace_one(
+        &self,
+        filter: &Document,
+        replacement: T,
+    ) -> Result<UpdateResult, ClientError>
+    where
+        T: IntoValue,
+    {
+        self.replace_one_with(filter, replacement, &UpdateOptions::new())
+            .await
+    }
+
+    /// `update_one` with `options`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server rejects the update.
+    pub async fn update_one_with(
+        &self,
+        filter: &Document,
+        update: &Document,
+        options: &UpdateOptions,
+    ) -> Result<UpdateResult, ClientError> {
+        self.update(filter, update.clone(), options, false).await
+    }
+
+    /// `update_many` with `options`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server rejects the update.
+    pub async fn update_many_with(
+        &self,
+        filter: &Document,
+        update: &Document,
+        options: &UpdateOptions,
+    ) -> Result<UpdateResult, ClientError> {
+        self.update(filter, update.clone(), options, true).await
+    }
+
+    /// `replace_one` with `options`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `replacement` does not convert, or the server
+    /// rejects it.
+    pub async fn replace_one_with(
+        &self,
+        filter: &Document,
+        replacement: T,
+        options: &UpdateOptions,
+    ) -> Result<UpdateResult, ClientError>
+    where
+        T: IntoValue,
+    {
+        let replacement = command::to_document(replacement)?;
+        self.update(filter, replacement, options, false).await
+    }
+
+    async fn update(
+        &self,
+        filter: &Document,
+        update: Document,
+        options: &UpdateOptions,
+        multi: bool,
+    ) -> Result<UpdateResult, ClientError> {
+        let commands = self.commands();
+        let command = commands.update(filter, update, options, multi);
+        commands.updated(self.client.write(command).await?)
+    }
+
+    /// Deletes the first document matching `filter`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server rejects the delete.
+    pub async fn delete_one(&self, filter: &Document) -> Result<DeleteResult, ClientError> {
+        let commands = self.commands();
+        commands.deleted(self.client.write(commands.delete(filter, true)).await?)
+    }
+
+    /// Deletes every document matching `filter`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server rejects the delete.
+    pub async fn delete_many(&self, filter: &Document) -> Result<DeleteResult, ClientError> {
+        let commands = self.commands();
+        commands.deleted(self.client.write(commands.delete(filter, false)).await?)
+    }
+
+    /// Returns the number of documents matching `filter`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server rejects the count.
+    pub async fn count(&self, filter: &Document) -> Result<u64, ClientError> {
+        let commands = self.commands();
+        commands.counted(self.client.read(&commands.count(filter)).await?)
+    }
+}
+
+/// The matches of a find, read from the server a batch at a time with
+/// `next`. The cursor holds its client's connection until it is dropped.
+pub struct Cursor<'a, T> {
+    connection: MutexGuard<'a, Connection>,
+    batch: VecDeque<Value>,
+    more: bool,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> std::fmt::Debug for Cursor<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Cursor")
+            .field("buffered", &self.batch.len())
+            .field("more", &self.more)
+            .finish()
+    }
+}
+
+impl<T: FromValue> Cursor<'_, T> {
+    /// Returns the next match, or `None` after the last.
+    pub async fn next(&mut self) -> Option<Result<T, ClientError>> {
+        while self.batch.is_empty() && self.more {
+            // Whatever the outcome, this reply was the stream's next
+            self.more = false;
+            match self.connection.reply().await.and_then(command::batch) {
+                Ok((batch, more)) => {
+                    self.batch = batch;
+                    self.more = more;
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        self.batch.pop_front().map(|value| command::decode(&value))
+    }
+
+    /// Reads every remaining match.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error reading or converting a match.
+    pub async fn collect(mut self) -> Result<Vec<T>, ClientError> {
+        let mut matches = Vec::new();
+        while let Some(document) = self.next().await {
+            matches.push(document?);
+        }
+        Ok(matches)
+    }
+}
+
+impl<T> Drop for Cursor<'_, T> {
+    fn drop(&mut self) {
+        if self.more {
+            self.connection.draining = true;
+        }
+    }
+}
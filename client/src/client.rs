@@ -0,0 +1,618 @@
+// src/client.rs
+
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Read, Write};
+use std::marker::PhantomData;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+
+use rustls::{ClientConnection, StreamOwned};
+use silentdb_data_encoding::{from_bytes, Document, Framer, FromValue, IntoValue, Value};
+
+use crate::command::{self, Commands, ServerInfo};
+use crate::error::ClientError;
+use crate::options::{
+    ClientOptions, DeleteResult, FindOptions, InsertManyResult, InsertOneResult, UpdateOptions,
+    UpdateResult,
+};
+use crate::scram::Scram;
+
+/// A plain or TLS stream to a server.
+trait Stream: Read + Write + Send {}
+
+impl<S: Read + Write + Send> Stream for S {}
+
+/// A connection to a server, which runs one command at a time.
+struct Connection {
+    stream: Box<dyn Stream>,
+    framer: Framer,
+    server: ServerInfo,
+    /// Whether a cursor was dropped before the server finished streaming
+    /// its matches, which must be read off the stream before anything else.
+    draining: bool,
+    /// Whether the stream failed, so that the connection must be replaced.
+    broken: bool,
+}
+
+impl std::fmt::Debug for Connection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Connection")
+            .field("server", &self.server)
+            .field("draining", &self.draining)
+            .field("broken", &self.broken)
+            .finish()
+    }
+}
+
+impl Connection {
+    /// Connects to the first of `addrs` to accept, with `options`, and
+    /// shakes hands with the server there.
+    fn open(addrs: &[SocketAddr], options: &ClientOptions) -> Result<Connection, ClientError> {
+        let stream = match options.connect_timeout {
+            Some(timeout) => {
+                let mut last = None;
+                let mut connected = None;
+                for addr in addrs {
+                    match TcpStream::connect_timeout(addr, timeout) {
+                        Ok(stream) => {
+                            connected = Some(stream);
+                            break;
+                        }
+                        Err(e) => last = Some(e),
+                    }
+                }
+                match (connected, last) {
+                    (Some(stream), _) => stream,
+                    (None, Some(e)) => return Err(e.into()),
+                    (None, None) => {
+                        return Err(ClientError::Io(ErrorKind::AddrNotAvailable.into()))
+                    }
+                }
+            }
+            None => TcpStream::connect(addrs)?,
+        };
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(options.timeout)?;
+        stream.set_write_timeout(options.timeout)?;
+        let stream: Box<dyn Stream> = match &options.tls {
+            Some(tls) => {
+                let (config, name) = tls.config()?;
+                let tls = ClientConnection::new(config, name)
+                    .map_err(|e| ClientError::Tls(e.to_string()))?;
+                Box::new(StreamOwned::new(tls, stream))
+            }
+            None => Box::new(stream),
+        };
+        let mut connection = Connection {
+            stream,
+            framer: Framer::new(),
+            server: ServerInfo {
+                version: String::new(),
+                max_document_len: usize::MAX,
+            },
+            draining: false,
+            broken: false,
+        };
+        connection.server = command::handshake(connection.run(&command::hello())?)?;
+        if let Some(credentials) = &options.credentials {
+            let mut scram = Scram::new(credentials);
+            let proof = scram.prove(connection.run(&scram.start())?)?;
+            scram.finish(connection.run(&proof)?)?;
+        }
+        Ok(connection)
+    }
+
+    fn send(&mut self, command: &Document) -> Result<(), ClientError> {
+        let bytes = command::encode(command, self.server.max_document_len)?;
+        if let Err(e) = self.stream.write_all(&bytes) {
+            self.broken = true;
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    fn reply(&mut self) -> Result<Document, ClientError> {
+        let reply = self.read_reply();
+        if reply.as_ref().is_err_and(ClientError::breaks_connection) {
+            self.broken = true;
+        }
+        reply
+    }
+
+    fn read_reply(&mut self) -> Result<Document, ClientError> {
+        loop {
+            if let Some(frame) = self.framer.next_frame()? {
+                return Ok(from_bytes(&frame)?);
+            }
+            match self.framer.read_from(&mut self.stream) {
+                Ok(0) => return Err(ClientError::Io(ErrorKind::UnexpectedEof.into())),
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    return Err(ClientError::Timeout)
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Sends `command` and returns its reply, first reading off the rest of
+    /// any stream left behind by a dropped cursor.
+    fn run(&mut self, command: &Document) -> Result<Document, ClientError> {
+        while self.draining {
+            // An error reply also ends a stream
+            self.draining = matches!(command::batch(self.reply()?), Ok((_, true)));
+        }
+        self.send(command)?;
+        self.reply()
+    }
+}
+
+/// A client connected to a server over the native protocol.
+///
+/// A client is a single connection, shared by its clones and the
+/// collections taken from it, which take turns running commands on it. A
+/// cursor holds the connection while it is alive, so drop it before
+/// running another command on the same thread; a cursor dropped early is
+/// read to its end by the next command. Use a client per thread for
+/// commands to run in parallel.
+///
+/// A connection that fails or times out is replaced by a new one for the
+/// next command. Finds, counts and writes failing in a way that may pass,
+/// as `ClientError::is_retryable` tells, are run once more after a short
+/// backoff, unless `ClientOptions` says otherwise; writes carry an
+/// operation id so that the server never applies one twice. A cursor
+/// failing partway through its matches is not retried.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use silentdb_client::Client;
+/// # use silentdb_data_encoding::Document;
+/// let client = Client::connect("127.0.0.1:27117").unwrap();
+/// let users = client.collection::<Document>("users");
+/// let mut user = Document::new();
+/// user.insert("name", "ada");
+/// users.insert_one(user).unwrap();
+/// assert_eq!(users.count(&Document::new()).unwrap(), 1);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Client {
+    connection: Arc<Mutex<Connection>>,
+    server: ServerInfo,
+    /// Where the server was found, and how, to reconnect to it.
+    addrs: Arc<[SocketAddr]>,
+    options: Arc<ClientOptions>,
+}
+
+impl Client {
+    /// Connects to the server at `addr`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no connection can be made, or the handshake
+    /// finds something other than a server on the other end.
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<Client, ClientError> {
+        Client::connect_with(addr, ClientOptions::new())
+    }
+
+    /// Connects to the server at `addr` with `options`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no connection can be made within the connect
+    /// timeout, the TLS handshake fails, or the handshake with the server
+    /// does, or the server turns down the credentials, with code 18.
+    pub fn connect_with<A: ToSocketAddrs>(
+        addr: A,
+        options: ClientOptions,
+    ) -> Result<Client, ClientError> {
+        let addrs: Arc<[SocketAddr]> = addr.to_socket_addrs()?.collect();
+        let connection = Connection::open(&addrs, &options)?;
+        Ok(Client {
+            server: connection.server.clone(),
+            connection: Arc::new(Mutex::new(connection)),
+            addrs,
+            options: Arc::new(options),
+        })
+    }
+
+    /// Returns the version of the server connected to.
+    pub fn server_version(&self) -> &str {
+        &self.server.version
+    }
+
+    /// Returns the largest request the server accepts, in bytes.
+    pub fn max_document_len(&self) -> usize {
+        self.server.max_document_len
+    }
+
+    /// Returns a handle to the collection `name`, reading and writing its
+    /// documents as `T`. Use `Document` for untyped access.
+    pub fn collection<T>(&self, name: &str) -> Collection<T> {
+        Collection {
+            client: self.clone(),
+            name: name.to_string(),
+            marker: PhantomData,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Connection> {
+        self.connection.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Runs the read `command`, retrying it if the options say to.
+    fn read(&self, command: &Document) -> Result<Document, ClientError> {
+        self.run(&mut self.lock(), command, self.options.retry_reads)
+    }
+
+    /// Runs the write `command`, with an operation id to retry it by if
+    /// the options say to.
+    fn write(&self, mut command: Document) -> Result<Document, ClientError> {
+        let retry = self.options.retry_writes;
+        if retry {
+            command::add_op_id(&mut command);
+        }
+        self.run(&mut self.lock(), &command, retry)
+    }
+
+    /// Runs `command` on `connection`, replacing it first if it broke, and
+    /// if `retry` is set and the command fails in a way that may pass, runs
+    /// it once more after a backoff.
+    fn run(
+        &self,
+        connection: &mut Connection,
+        command: &Document,
+        retry: bool,
+    ) -> Result<Document, ClientError> {
+        if connection.broken {
+            *connection = Connection::open(&self.addrs, &self.options)?;
+        }
+        match connection.run(command).and_then(command::check) {
+            Err(e) if retry && e.is_retryable() => {
+                self.recover(connection)?;
+                connection.run(command).and_then(command::check)
+            }
+            result => result,
+        }
+    }
+
+    /// Waits out the backoff before a retry on `connection`, reconnecting
+    /// if it broke, with the backoff doubling after each failed attempt.
+    fn recover(&self, connection: &mut Connection) -> Result<(), ClientError> {
+        let mut attempt = 0;
+        loop {
+            thread::sleep(command::backoff(self.options.retry_backoff, attempt));
+            if !connection.broken {
+                return Ok(());
+            }
+            match Connection::open(&self.addrs, &self.options) {
+                Ok(reconnected) => {
+                    *connection = reconnected;
+                    return Ok(());
+                }
+                Err(e) if e.is_retryable() && attempt + 1 < command::RECONNECT_ATTEMPTS => {
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// A handle to a collection on the server, holding its documents as `T`:
+/// any type converting to and from a document, through `IntoValue` for the
+/// documents written and `FromValue` for those read.
+///
+/// The methods mirror the server's `Collection`, and run there as commands.
+pub struct Collection<T = Document> {
+    client: Client,
+    name: String,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for Collection<T> {
+    fn clone(&self) -> Self {
+        Collection {
+            client: self.client.clone(),
+            name: self.name.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for Collection<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Collection")
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+impl<T> Collection<T> {
+    /// Returns the collection's name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn commands(&self) -> Commands<'_> {
+        Commands {
+            collection: &self.name,
+        }
+    }
+
+    /// Returns the documents matching `filter`, streamed from the server
+    /// as the cursor is read.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the command cannot be sent, or the server
+    /// rejects it.
+    pub fn find(&self, filter: &Document) -> Result<Cursor<'_, T>, ClientError>
+    where
+        T: FromValue,
+    {
+        self.find_with(filter, &FindOptions::new())
+    }
+
+    /// Returns the documents matching `filter`, with `options`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the command cannot be sent, or the server
+    /// rejects it.
+    pub fn find_with(
+        &self,
+        filter: &Document,
+        options: &FindOptions,
+    ) -> Result<Cursor<'_, T>, ClientError>
+    where
+        T: FromValue,
+    {
+        let mut connection = self.client.lock();
+        let find = self.commands().find(filter, options);
+        let reply = self
+            .client
+            .run(&mut connection, &find, self.client.options.retry_reads)?;
+        let (batch, more) = command::batch(reply)?;
+        Ok(Cursor {
+            connection,
+            batch,
+            more,
+            marker: PhantomData,
+        })
+    }
+
+    /// Returns the first document matching `filter`, if there is one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the command fails, or the document cannot be
+    /// converted.
+    pub fn find_one(&self, filter: &Document) -> Result<Option<T>, ClientError>
+    where
+        T: FromValue,
+    {
+        self.find_with(filter, &FindOptions::new().limit(1))?
+            .next()
+            .transpose()
+    }
+
+    /// Inserts `document`, giving it an `_id` if it has none.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `document` does not convert into a document, or
+    /// the server rejects it, such as for a duplicate key.
+    pub fn insert_one(&self, document: T) -> Result<InsertOneResult, ClientError>
+    where
+        T: IntoValue,
+    {
+        let mut result = self.insert_many([document])?;
+        match result.inserted_ids.pop() {
+            Some(inserted_id) => Ok(InsertOneResult { inserted_id }),
+            None => Err(ClientError::Protocol("no inserted id".to_string())),
+        }
+    }
+
+    /// Inserts `documents` in one command.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a document does not convert, or the server
+    /// rejects the insert.
+    pub fn insert_many<I>(&self, documents: I) -> Result<InsertManyResult, ClientError>
+    where
+        I: IntoIterator<Item = T>,
+        T: IntoValue,
+    {
+        let documents = documents
+            .into_iter()
+            .map(command::to_document)
+            .collect::<Result<Vec<_>, _>>()?;
+        let commands = self.commands();
+        commands.inserted(self.client.write(commands.insert(documents))?)
+    }
+
+    /// Applies the update operators of `update` to the first document
+    /// matching `filter`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server rejects the update.
+    pub fn update_one(
+        &self,
+        filter: &Document,
+        update: &Document,
+    ) -> Result<UpdateResult, ClientError> {
+        self.update_one_with(filter, update, &UpdateOptions::new())
+    }
+
+    /// Applies the update operators of `update` to every document matching
+    /// `filter`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server rejects the update.
+    pub fn update_many(
+        &self,
+        filter: &Document,
+        update: &Document,
+    ) -> Result<UpdateResult, ClientError> {
+        self.update_many_with(filter, update, &UpdateOptions::new())
+    }
+
+    /// Replaces the first document matching `filter` with `replacement`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `replacement` does not convert, or the server
+    /// rejects it.
+    pub fn replace_one(
+        &self,
+        filter: &Document,
+        replacement: T,
+    ) -> Result<UpdateResult, ClientError>
+    where
+        T: IntoValue,
+    {
+        self.replace_one_with(filter, replacement, &UpdateOptions::new())
+    }
+
+    /// `update_one` with `options`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server rejects the update.
+    pub fn update_one_with(
+        &self,
+        filter: &Document,
+        update: &Document,
+        options: &UpdateOptions,
+    ) -> Result<UpdateResult, ClientError> {
+        self.update(filter, update.clone(), options, false)
+    }
+
+    /// `update_many` with `options`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server rejects the update.
+    pub fn update_many_with(
+        &self,
+        filter: &Document,
+        update: &Document,
+        options: &UpdateOptions,
+    ) -> Result<UpdateResult, ClientError> {
+        self.update(filter, update.clone(), options, true)
+    }
+
+    /// `replace_one` with `options`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `replacement` does not convert, or the server
+    /// rejects it.
+    pub fn replace_one_with(
+        &self,
+        filter: &Document,
+        replacement: T,
+        options: &UpdateOptions,
+    ) -> Result<UpdateResult, ClientError>
+    where
+        T: IntoValue,
+    {
+        self.update(filter, command::to_document(replacement)?, options, false)
+    }
+
+    fn update(
+        &self,
+        filter: &Document,
+        update: Document,
+        options: &UpdateOptions,
+        multi: bool,
+    ) -> Result<UpdateResult, ClientError> {
+        let commands = self.commands();
+        commands.updated(
+            self.client
+                .write(commands.update(filter, update, options, multi))?,
+        )
+    }
+
+    /// Deletes the first document matching `filter`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server rejects the delete.
+    pub fn delete_one(&self, filter: &Document) -> Result<DeleteResult, ClientError> {
+        let commands = self.commands();
+        commands.deleted(self.client.write(commands.delete(filter, true))?)
+    }
+
+    /// Deletes every document matching `filter`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server rejects the delete.
+    pub fn delete_many(&self, filter: &Document) -> Result<DeleteResult, ClientError> {
+        let commands = self.commands();
+        commands.deleted(self.client.write(commands.delete(filter, false))?)
+    }
+
+    /// Returns the number of documents matching `filter`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server rejects the count.
+    pub fn count(&self, filter: &Document) -> Result<u64, ClientError> {
+        let commands = self.commands();
+        commands.counted(self.client.read(&commands.count(filter))?)
+    }
+}
+
+/// The matches of a find, read from the server a batch at a time.
+///
+/// The cursor holds its client's connection until it is dropped.
+pub struct Cursor<'a, T> {
+    connection: MutexGuard<'a, Connection>,
+    batch: VecDeque<Value>,
+    more: bool,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> std::fmt::Debug for Cursor<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Cursor")
+            .field("buffered", &self.batch.len())
+            .field("more", &self.more)
+            .finish()
+    }
+}
+
+impl<T: FromValue> Iterator for Cursor<'_, T> {
+    type Item = Result<T, ClientError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.batch.is_empty() && self.more {
+            // Whatever the outcome, this reply was the stream's next
+            self.more = false;
+            match self.connection.reply().and_then(command::batch) {
+                Ok((batch, more)) => {
+                    self.batch = batch;
+                    self.more = more;
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        self.batch.pop_front().map(|value| command::decode(&value))
+    }
+}
+
+impl<T> Drop for Cursor<'_, T> {
+    fn drop(&mut self) {
+        if self.more {
+            self.connection.draining = true;
+        }
+    }
+}
@@ -0,0 +1,206 @@
+// src/command.rs
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use silentdb_data_encoding::{to_bytes, Array, Document, FromValue, IntoValue, Value};
+
+use crate::error::ClientError;
+use crate::options::{DeleteResult, FindOptions, InsertManyResult, UpdateOptions, UpdateResult};
+
+/// Times a client tries to reconnect to retry a command on a connection
+/// that broke.
+pub(crate) const RECONNECT_ATTEMPTS: u32 = 4;
+
+/// What the server told the client about itself in the handshake.
+#[derive(Debug, Clone)]
+pub(crate) struct ServerInfo {
+    pub(crate) version: String,
+    pub(crate) max_document_len: usize,
+}
+
+/// Builds the command documents sent for a collection's methods, and reads
+/// their replies. Shared by the blocking and async clients, which differ
+/// only in how they move the documents.
+pub(crate) struct Commands<'a> {
+    pub(crate) collection: &'a str,
+}
+
+pub(crate) fn hello() -> Document {
+    let mut command = Document::new();
+    command.insert("hello", 1);
+    command
+}
+
+/// Checks the reply to `hello` comes from a server this client can talk
+/// to, and returns what it says about the server.
+pub(crate) fn handshake(reply: Document) -> Result<ServerInfo, ClientError> {
+    let reply = check(reply)?;
+    if reply.get("server") != Some(&Value::String("silentdb".to_string())) {
+        return Err(ClientError::Protocol("not a silentdb server".to_string()));
+    }
+    Ok(ServerInfo {
+        version: reply.get_as("version")?,
+        max_document_len: reply.get_as::<u64>("maxDocumentLen")? as usize,
+    })
+}
+
+/// Encodes `command`, checking it is within the server's `max` length.
+pub(crate) fn encode(command: &Document, max: usize) -> Result<Vec<u8>, ClientError> {
+    let bytes = to_bytes(command)?;
+    if bytes.len() > max {
+        return Err(ClientError::RequestTooLarge {
+            len: bytes.len(),
+            max,
+        });
+    }
+    Ok(bytes)
+}
+
+/// Returns `reply` if it reports success, or the error it carries.
+pub(crate) fn check(reply: Document) -> Result<Document, ClientError> {
+    match reply.get("ok") {
+        Some(Value::Double(ok)) if *ok == 1.0 => Ok(reply),
+        Some(_) => Err(ClientError::Server {
+            code: reply.get_as("code").unwrap_or(1),
+            message: reply.get_as("errmsg").unwrap_or_default(),
+        }),
+        None => Err(ClientError::Protocol(format!("no ok in {:?}", reply))),
+    }
+}
+
+/// Gives the write `command` a new random operation id, by which the
+/// server recognizes a retry of it.
+pub(crate) fn add_op_id(command: &mut Document) {
+    let id: [u8; 16] = rand::random();
+    command.insert("opId", Value::Binary(id.to_vec()));
+}
+
+/// Returns how long to wait before the `attempt`th try, from 0, at a
+/// command, doubling `base` with each.
+pub(crate) fn backoff(base: Duration, attempt: u32) -> Duration {
+    base.saturating_mul(1 << attempt.min(16))
+}
+
+/// Converts `value` into the document to send for it.
+pub(crate) fn to_document<T: IntoValue>(value: T) -> Result<Document, ClientError> {
+    match value.into_value() {
+        Value::Document(document) => Ok(document),
+        other => Err(ClientError::NotADocument(format!("{:?}", other))),
+    }
+}
+
+/// A batch of matches streamed back for a find, and whether more follow.
+pub(crate) fn batch(reply: Document) -> Result<(VecDeque<Value>, bool), ClientError> {
+    let reply = check(reply)?;
+    let batch: Vec<Value> = reply.get_as("batch")?;
+    Ok((batch.into(), reply.get_as("more")?))
+}
+
+/// Converts a match into `T`.
+pub(crate) fn decode<T: FromValue>(value: &Value) -> Result<T, ClientError> {
+    Ok(T::from_value(value)?)
+}
+
+impl Commands<'_> {
+    fn command(&self, name: &str) -> Document {
+        let mut command = Document::new();
+        command.insert(name, self.collection);
+        command
+    }
+
+    pub(crate) fn find(&self, filter: &Document, options: &FindOptions) -> Document {
+        let mut command = self.command("find");
+        command.insert("filter", filter.clone());
+        if let Some(sort) = &options.sort {
+            command.insert("sort", sort.clone());
+        }
+        if let Some(projection) = &options.projection {
+            command.insert("projection", projection.clone());
+        }
+        if let Some(skip) = options.skip {
+            command.insert("skip", skip as i64);
+        }
+        if let Some(limit) = options.limit {
+            command.insert("limit", limit as i64);
+        }
+        if let Some(batch_size) = options.batch_size {
+            command.insert("batchSize", batch_size as i64);
+        }
+        command
+    }
+
+    pub(crate) fn insert(&self, documents: Vec<Document>) -> Document {
+        let mut command = self.command("insert");
+        let documents = documents.into_iter().map(Value::Document).collect();
+        command.insert("documents", Array::from_vec(documents));
+        command
+    }
+
+    pub(crate) fn inserted(&self, reply: Document) -> Result<InsertManyResult, ClientError> {
+        Ok(InsertManyResult {
+            inserted_ids: check(reply)?.get_as("insertedIds")?,
+        })
+    }
+
+    /// `update` holds update operators, or is a replacement if `multi` is
+    /// false.
+    pub(crate) fn update(
+        &self,
+        filter: &Document,
+        update: Document,
+        options: &UpdateOptions,
+        multi: bool,
+    ) -> Document {
+        let mut change = Document::new();
+        change.insert("q", filter.clone());
+        change.insert("u", update);
+        change.insert("upsert", options.upsert);
+        change.insert("multi", multi);
+        let mut command = self.command("update");
+        command.insert("updates", Array::from_vec(vec![Value::Document(change)]));
+        command
+    }
+
+    pub(crate) fn updated(&self, reply: Document) -> Result<UpdateResult, ClientError> {
+        let reply = check(reply)?;
+        let upserted: Option<Vec<Document>> = reply.get_as("upserted")?;
+        let upserted_id = match upserted {
+            Some(upserted) => upserted
+                .into_iter()
+                .next()
+                .and_then(|mut entry| entry.remove("_id")),
+            None => None,
+        };
+        Ok(UpdateResult {
+            matched_count: reply.get_as("n")?,
+            modified_count: reply.get_as("nModified")?,
+            upserted_id,
+        })
+    }
+
+    pub(crate) fn delete(&self, filter: &Document, one: bool) -> Document {
+        let mut delete = Document::new();
+        delete.insert("q", filter.clone());
+        delete.insert("limit", if one { 1 } else { 0 });
+        let mut command = self.command("delete");
+        command.insert("deletes", Array::from_vec(vec![Value::Document(delete)]));
+        command
+    }
+
+    pub(crate) fn deleted(&self, reply: Document) -> Result<DeleteResult, ClientError> {
+        Ok(DeleteResult {
+            deleted_count: check(reply)?.get_as("n")?,
+        })
+    }
+
+    pub(crate) fn count(&self, filter: &Document) -> Document {
+        let mut command = self.command("count");
+        command.insert("query", filter.clone());
+        command
+    }
+
+    pub(crate) fn counted(&self, reply: Document) -> Result<u64, ClientError> {
+        Ok(check(reply)?.get_as("n")?)
+    }
+}
@@ -0,0 +1,81 @@
+// src/error.rs
+
+use std::io::{self, ErrorKind};
+
+use silentdb_data_encoding::{DeserializeError, SerializeError, ValueConversionError};
+
+/// Codes of server errors a retry may not hit again: `LockTimeout`,
+/// `WriteConflict` and `NotLeader`.
+const RETRYABLE_CODES: [i32; 3] = [24, 112, 10107];
+
+/// Represents errors that can occur talking to a server.
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Malformed reply: {0}")]
+    Deserialize(#[from] DeserializeError),
+    #[error("Serialization error: {0}")]
+    Serialize(#[from] SerializeError),
+    #[error("Conversion error: {0}")]
+    Conversion(#[from] ValueConversionError),
+    #[error("Server error {code}: {message}")]
+    Server { code: i32, message: String },
+    #[error("Unexpected reply: {0}")]
+    Protocol(String),
+    #[error("Request of {len} bytes is over the server's limit of {max}")]
+    RequestTooLarge { len: usize, max: usize },
+    #[error("Not a document: {0}")]
+    NotADocument(String),
+    #[error("Timed out waiting for the server")]
+    Timeout,
+    #[error("TLS error: {0}")]
+    Tls(String),
+}
+
+impl ClientError {
+    /// Returns the code of an error the server replied with, which is
+    /// MongoDB's for the same failure where there is one, such as 11000 for
+    /// a duplicate key.
+    pub fn code(&self) -> Option<i32> {
+        match self {
+            ClientError::Server { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the error may be transient, so that the command may
+    /// succeed if run again: the connection dropping, being refused or
+    /// timing out, or the server failing for want of a lock, on a write
+    /// conflict or for not being the leader. Any other error, such as a
+    /// duplicate key or being unauthorized, would only happen again.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ClientError::Io(e) => matches!(
+                e.kind(),
+                ErrorKind::ConnectionRefused
+                    | ErrorKind::ConnectionReset
+                    | ErrorKind::ConnectionAborted
+                    | ErrorKind::NotConnected
+                    | ErrorKind::BrokenPipe
+                    | ErrorKind::UnexpectedEof
+                    | ErrorKind::TimedOut
+            ),
+            ClientError::Timeout => true,
+            ClientError::Server { code, .. } => RETRYABLE_CODES.contains(code),
+            _ => false,
+        }
+    }
+
+    /// Returns whether the error leaves the connection it happened on
+    /// unusable, with the stream failed or partway through a message.
+    pub(crate) fn breaks_connection(&self) -> bool {
+        matches!(
+            self,
+            ClientError::Io(_)
+                | ClientError::Deserialize(_)
+                | ClientError::Timeout
+                | ClientError::Tls(_)
+        )
+    }
+}
@@ -0,0 +1,20 @@
+// src/lib.rs
+
+// Declare modules
+mod client;
+mod command;
+mod error;
+mod options;
+mod scram;
+mod test;
+mod tls;
+
+#[cfg(feature = "async")]
+pub mod asynchronous;
+
+// Re-export commonly used items
+pub use client::{Client, Collection, Cursor};
+pub use error::ClientError;
+pub use options::{ClientOptions, FindOptions, UpdateOptions};
+pub use options::{DeleteResult, InsertManyResult, InsertOneResult, UpdateResult};
+pub use tls::TlsOptions;
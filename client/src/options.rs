@@ -0,0 +1,249 @@
+// src/options.rs
+
+use std::time::Duration;
+
+use silentdb_data_encoding::{Document, Value};
+
+use crate::tls::TlsOptions;
+
+/// How long to wait before retrying a command unless told otherwise.
+const DEFAULT_RETRY_BACKOFF: Duration = Duration::from_millis(50);
+
+/// Options for `Client::connect_with`.
+///
+/// # Examples
+///
+/// ```
+/// # use std::time::Duration;
+/// # use silentdb_client::ClientOptions;
+/// let options = ClientOptions::new()
+///     .connect_timeout(Duration::from_secs(5))
+///     .timeout(Duration::from_secs(30))
+///     .retry_backoff(Duration::from_millis(100))
+///     .credentials("alice", "wonderland");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientOptions {
+    pub(crate) connect_timeout: Option<Duration>,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) tls: Option<TlsOptions>,
+    pub(crate) credentials: Option<Credentials>,
+    pub(crate) retry_reads: bool,
+    pub(crate) retry_writes: bool,
+    pub(crate) retry_backoff: Duration,
+}
+
+/// A user name and password to authenticate with.
+#[derive(Clone, PartialEq, Eq)]
+pub(crate) struct Credentials {
+    pub(crate) user: String,
+    pub(crate) password: String,
+}
+
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("user", &self.user)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ClientOptions {
+    /// Creates options connecting over plain TCP, waiting on the server
+    /// for as long as it takes, and retrying reads and writes once.
+    pub fn new() -> Self {
+        ClientOptions {
+            connect_timeout: None,
+            timeout: None,
+            tls: None,
+            credentials: None,
+            retry_reads: true,
+            retry_writes: true,
+            retry_backoff: DEFAULT_RETRY_BACKOFF,
+        }
+    }
+
+    /// Sets how long to wait for a connection to be accepted.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets how long to wait for each read and write on the connection. A
+    /// command timing out leaves the connection unusable, so the next one
+    /// runs on a new connection.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Connects over TLS with `tls`, to a server set up for it.
+    pub fn tls(mut self, tls: TlsOptions) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Authenticates as `user` with `password` on connecting, through
+    /// SCRAM-SHA-256, to a server requiring it. Use TLS as well to keep
+    /// the commands that follow private.
+    pub fn credentials(mut self, user: &str, password: &str) -> Self {
+        self.credentials = Some(Credentials {
+            user: user.to_string(),
+            password: password.to_string(),
+        });
+        self
+    }
+
+    /// Sets whether a find or count failing in a way
+    /// `ClientError::is_retryable` says may pass is run once more, on a
+    /// new connection if the old one broke. On by default; reads change
+    /// nothing, so running one twice is harmless.
+    pub fn retry_reads(mut self, retry: bool) -> Self {
+        self.retry_reads = retry;
+        self
+    }
+
+    /// Sets whether a write failing in a way `ClientError::is_retryable`
+    /// says may pass is run once more, as reads are. On by default. Each
+    /// write carries an operation id, by which the server answers a retry
+    /// of one it already applied with its first reply rather than applying
+    /// it twice.
+    pub fn retry_writes(mut self, retry: bool) -> Self {
+        self.retry_writes = retry;
+        self
+    }
+
+    /// Sets how long to wait before retrying a command, 50 milliseconds by
+    /// default. Reconnecting for the retry waits twice as long again after
+    /// each failed attempt.
+    pub fn retry_backoff(mut self, backoff: Duration) -> Self {
+        self.retry_backoff = backoff;
+        self
+    }
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        ClientOptions::new()
+    }
+}
+
+/// Options for `Collection::find_with`.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb_client::FindOptions;
+/// # use silentdb_data_encoding::Document;
+/// let mut sort = Document::new();
+/// sort.insert("age", -1);
+/// let options = FindOptions::new().sort(sort).skip(20).limit(10);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FindOptions {
+    pub(crate) projection: Option<Document>,
+    pub(crate) sort: Option<Document>,
+    pub(crate) skip: Option<u64>,
+    pub(crate) limit: Option<u64>,
+    pub(crate) batch_size: Option<u32>,
+}
+
+impl FindOptions {
+    /// Creates options returning every match whole, in the server's order.
+    pub fn new() -> Self {
+        FindOptions::default()
+    }
+
+    /// Returns only the fields `projection` includes of each document, as
+    /// the server's `FindOptions::projection` does.
+    pub fn projection(mut self, projection: Document) -> Self {
+        self.projection = Some(projection);
+        self
+    }
+
+    /// Returns documents in the order `sort` asks for, such as
+    /// `{"age": -1}`, as the server's `FindOptions::sort` does.
+    pub fn sort(mut self, sort: Document) -> Self {
+        self.sort = Some(sort);
+        self
+    }
+
+    /// Skips the first `count` matches.
+    pub fn skip(mut self, count: u64) -> Self {
+        self.skip = Some(count);
+        self
+    }
+
+    /// Returns at most `count` matches, or every match for 0.
+    pub fn limit(mut self, count: u64) -> Self {
+        self.limit = Some(count);
+        self
+    }
+
+    /// Sets how many documents the server sends per reply. The default is
+    /// 101.
+    pub fn batch_size(mut self, count: u32) -> Self {
+        self.batch_size = Some(count);
+        self
+    }
+}
+
+/// Options for `Collection::update_one_with`, `Collection::update_many_with`
+/// and `Collection::replace_one_with`.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb_client::UpdateOptions;
+/// let options = UpdateOptions::new().upsert(true);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UpdateOptions {
+    pub(crate) upsert: bool,
+}
+
+impl UpdateOptions {
+    /// Creates options that leave a filter matching nothing alone.
+    pub fn new() -> Self {
+        UpdateOptions::default()
+    }
+
+    /// Sets whether a filter matching no documents inserts one instead.
+    pub fn upsert(mut self, upsert: bool) -> Self {
+        self.upsert = upsert;
+        self
+    }
+}
+
+/// The result of `Collection::insert_one`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InsertOneResult {
+    /// The `_id` of the inserted document.
+    pub inserted_id: Value,
+}
+
+/// The result of `Collection::insert_many`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InsertManyResult {
+    /// The `_id`s of the inserted documents, in insertion order.
+    pub inserted_ids: Vec<Value>,
+}
+
+/// The result of `Collection::update_one`, `Collection::update_many` and
+/// `Collection::replace_one`, and of their `_with` variants.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpdateResult {
+    /// The number of documents that matched the filter.
+    pub matched_count: u64,
+    /// The number of matched documents the update actually changed.
+    pub modified_count: u64,
+    /// The `_id` of the document inserted by an upsert, if any.
+    pub upserted_id: Option<Value>,
+}
+
+/// The result of `Collection::delete_one` and `Collection::delete_many`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeleteResult {
+    /// The number of documents deleted.
+    pub deleted_count: u64,
+}
@@ -0,0 +1,171 @@
+// src/scram.rs
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use silentdb_data_encoding::{Document, Value};
+
+use crate::command::check;
+use crate::error::ClientError;
+use crate::options::Credentials;
+
+/// The SASL mechanism the client authenticates with.
+const MECHANISM: &str = "SCRAM-SHA-256";
+/// The gs2-header of the client's first message: no channel binding and no
+/// separate authorization identity.
+const GS2_HEADER: &str = "n,,";
+/// Random bytes in the client's part of a nonce.
+const NONCE_LEN: usize = 24;
+
+/// The client's side of a SCRAM-SHA-256 conversation, as RFC 5802 and RFC
+/// 7677 describe it. Shared by the blocking and async clients, which only
+/// move the commands it builds and the replies it reads.
+///
+/// The password is used as given, without SASLprep, as the server does.
+pub(crate) struct Scram<'a> {
+    credentials: &'a Credentials,
+    client_nonce: String,
+    client_first_bare: String,
+    server_signature: Option<[u8; 32]>,
+}
+
+impl<'a> Scram<'a> {
+    /// Starts a conversation authenticating with `credentials`.
+    pub(crate) fn new(credentials: &'a Credentials) -> Self {
+        let nonce: [u8; NONCE_LEN] = rand::thread_rng().gen();
+        let client_nonce = BASE64.encode(nonce);
+        let name = credentials.user.replace('=', "=3D").replace(',', "=2C");
+        Scram {
+            credentials,
+            client_first_bare: format!("n={},r={}", name, client_nonce),
+            client_nonce,
+            server_signature: None,
+        }
+    }
+
+    /// Returns the `saslStart` command carrying the client's first message.
+    pub(crate) fn start(&self) -> Document {
+        let mut options = Document::new();
+        options.insert("skipEmptyExchange", true);
+        let mut command = Document::new();
+        command.insert("saslStart", 1);
+        command.insert("mechanism", MECHANISM);
+        let message = format!("{}{}", GS2_HEADER, self.client_first_bare);
+        command.insert("payload", Value::Binary(message.into_bytes()));
+        command.insert("options", options);
+        command
+    }
+
+    /// Reads the server's first message off the reply to `saslStart`, and
+    /// returns the `saslContinue` command carrying the proof of the
+    /// password.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server refused to start, or its message is
+    /// malformed or does not extend the client's nonce.
+    pub(crate) fn prove(&mut self, reply: Document) -> Result<Document, ClientError> {
+        let (conversation_id, server_first) = payload(reply)?;
+        let mut nonce = None;
+        let mut salt = None;
+        let mut iterations = None;
+        for attribute in server_first.split(',') {
+            if let Some(value) = attribute.strip_prefix("r=") {
+                nonce = Some(value);
+            } else if let Some(value) = attribute.strip_prefix("s=") {
+                salt = BASE64.decode(value).ok();
+            } else if let Some(value) = attribute.strip_prefix("i=") {
+                iterations = value.parse::<u32>().ok();
+            }
+        }
+        let (Some(nonce), Some(salt), Some(iterations)) = (nonce, salt, iterations) else {
+            return Err(malformed(&server_first));
+        };
+        // The server's nonce must extend the client's, so a replayed
+        // conversation cannot be passed off as this one
+        if !nonce.starts_with(&self.client_nonce) || nonce.len() == self.client_nonce.len() {
+            return Err(malformed(&server_first));
+        }
+
+        let mut salted = [0; 32];
+        pbkdf2::pbkdf2_hmac::<Sha256>(
+            self.credentials.password.as_bytes(),
+            &salt,
+            iterations,
+            &mut salted,
+        );
+        let client_key = hmac(&salted, b"Client Key");
+        let stored_key: [u8; 32] = Sha256::digest(client_key).into();
+        let without_proof = format!("c={},r={}", BASE64.encode(GS2_HEADER), nonce);
+        let auth_message = format!(
+            "{},{},{}",
+            self.client_first_bare, server_first, without_proof
+        );
+        let signature = hmac(&stored_key, auth_message.as_bytes());
+        let proof: Vec<u8> = client_key
+            .iter()
+            .zip(signature)
+            .map(|(a, b)| a ^ b)
+            .collect();
+        self.server_signature = Some(hmac(&hmac(&salted, b"Server Key"), auth_message.as_bytes()));
+
+        let message = format!("{},p={}", without_proof, BASE64.encode(proof));
+        let mut command = Document::new();
+        command.insert("saslContinue", 1);
+        command.insert("conversationId", conversation_id);
+        command.insert("payload", Value::Binary(message.into_bytes()));
+        Ok(command)
+    }
+
+    /// Checks the server's last message, in the reply to `saslContinue`,
+    /// proves it knows the password too, and that the conversation is done.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server refused the proof, with code 18, or
+    /// its signature does not match.
+    pub(crate) fn finish(&self, reply: Document) -> Result<(), ClientError> {
+        let done = matches!(reply.get("done"), Some(Value::Boolean(true)));
+        let (_, server_final) = payload(reply)?;
+        let expected = self
+            .server_signature
+            .map(|signature| BASE64.encode(signature));
+        match server_final.strip_prefix("v=") {
+            Some(signature) if Some(signature) == expected.as_deref() && done => Ok(()),
+            _ => Err(ClientError::Protocol(
+                "the server did not prove it knows the password".to_string(),
+            )),
+        }
+    }
+}
+
+/// Returns the conversation id and SASL message of a `saslStart` or
+/// `saslContinue` reply.
+fn payload(reply: Document) -> Result<(i32, String), ClientError> {
+    let reply = check(reply)?;
+    let conversation_id = reply.get_as("conversationId")?;
+    match reply.get("payload") {
+        Some(Value::Binary(bytes)) => match String::from_utf8(bytes.clone()) {
+            Ok(message) => Ok((conversation_id, message)),
+            Err(_) => Err(ClientError::Protocol(
+                "SASL message is not UTF-8".to_string(),
+            )),
+        },
+        _ => Err(ClientError::Protocol(format!(
+            "no SASL payload in {:?}",
+            reply
+        ))),
+    }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+fn malformed(message: &str) -> ClientError {
+    ClientError::Protocol(format!("malformed SCRAM message {:?}", message))
+}
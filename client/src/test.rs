@@ -0,0 +1,327 @@
+// src/test.rs
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use silentdb::{Database, Role, Server, ServerHandle, ServerOptions};
+    use silentdb_data_encoding::{Document, FromValue, IntoValue, Value, ValueConversionError};
+
+    use crate::{Client, ClientError, ClientOptions, FindOptions, TlsOptions, UpdateOptions};
+
+    fn start(name: &str) -> ServerHandle {
+        start_with(name, ServerOptions::new())
+    }
+
+    fn start_with(name: &str, options: ServerOptions) -> ServerHandle {
+        serve(open(name), options)
+    }
+
+    fn open(name: &str) -> Database {
+        let dir =
+            std::env::temp_dir().join(format!("silentdb-client-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        Database::open(dir).unwrap()
+    }
+
+    fn serve(db: Database, options: ServerOptions) -> ServerHandle {
+        Server::bind_with(db, "127.0.0.1:0", options)
+            .unwrap()
+            .spawn()
+            .unwrap()
+    }
+
+    fn doc(key: &str, value: impl Into<Value>) -> Document {
+        let mut document = Document::new();
+        document.insert(key, value);
+        document
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct User {
+        id: i32,
+        name: String,
+    }
+
+    impl FromValue for User {
+        fn from_value(value: &Value) -> Result<Self, ValueConversionError> {
+            Self::from_document(&Document::from_value(value)?)
+        }
+
+        fn from_document(document: &Document) -> Result<Self, ValueConversionError> {
+            Ok(User {
+                id: document.get_as("_id")?,
+                name: document.get_as("name")?,
+            })
+        }
+    }
+
+    impl IntoValue for User {
+        fn into_value(self) -> Value {
+            let mut document = doc("_id", self.id);
+            document.insert("name", self.name);
+            Value::Document(document)
+        }
+    }
+
+    fn user(id: i32) -> User {
+        User {
+            id,
+            name: format!("user{}", id),
+        }
+    }
+
+    #[test]
+    fn test_client_typed_collection() {
+        let server = start("typed");
+        let client = Client::connect(server.local_addr()).unwrap();
+        assert_eq!(client.server_version(), "0.1.0");
+        let users = client.collection::<User>("users");
+
+        assert_eq!(
+            users.insert_one(user(0)).unwrap().inserted_id,
+            Value::Int32(0)
+        );
+        let ids = users.insert_many((1..10).map(user)).unwrap().inserted_ids;
+        assert_eq!(ids.len(), 9);
+        let err = users.insert_one(user(3)).unwrap_err();
+        assert_eq!(err.code(), Some(11000));
+
+        // Matches arrive over several replies, and convert into `User`s
+        let options = FindOptions::new()
+            .sort(doc("_id", -1))
+            .skip(1)
+            .limit(5)
+            .batch_size(2);
+        let found = users
+            .find_with(&Document::new(), &options)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(found, (4..9).rev().map(user).collect::<Vec<_>>());
+
+        // A cursor dropped partway leaves the connection usable
+        let mut cursor = users
+            .find_with(&Document::new(), &FindOptions::new().batch_size(1))
+            .unwrap();
+        assert!(cursor.next().is_some());
+        drop(cursor);
+        assert_eq!(users.count(&Document::new()).unwrap(), 10);
+
+        let result = users
+            .update_many(
+                &doc("_id", doc("$lt", 3)),
+                &doc("$set", doc("name", "early")),
+            )
+            .unwrap();
+        assert_eq!(result.modified_count, 3);
+        let mut renamed = user(3);
+        renamed.name = "three".to_string();
+        users.replace_one(&doc("_id", 3), renamed.clone()).unwrap();
+        assert_eq!(users.find_one(&doc("_id", 3)).unwrap(), Some(renamed));
+        let result = users
+            .update_one_with(
+                &doc("_id", 42),
+                &doc("$set", doc("name", "new")),
+                &UpdateOptions::new().upsert(true),
+            )
+            .unwrap();
+        assert_eq!(result.upserted_id, Some(Value::Int32(42)));
+
+        assert_eq!(
+            users
+                .delete_many(&doc("name", "early"))
+                .unwrap()
+                .deleted_count,
+            3
+        );
+        assert_eq!(users.count(&Document::new()).unwrap(), 8);
+
+        // Documents not shaped like a `User` fail to convert, not the read
+        let raw = client.collection::<Document>("users");
+        raw.insert_one(doc("_id", 100)).unwrap();
+        let err = users.find_one(&doc("_id", 100)).unwrap_err();
+        assert!(matches!(err, ClientError::Conversion(_)));
+
+        server.shutdown().unwrap();
+    }
+
+    /// A certificate authority issuing certificates for tests.
+    struct Authority {
+        certificate: rcgen::Certificate,
+        key: KeyPair,
+    }
+
+    impl Authority {
+        fn new() -> Authority {
+            let key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            Authority {
+                certificate: params.self_signed(&key).unwrap(),
+                key,
+            }
+        }
+
+        fn pem(&self) -> Vec<u8> {
+            self.certificate.pem().into_bytes()
+        }
+
+        /// Issues a certificate for `name`, returning it and its key as PEM.
+        fn issue(&self, name: &str) -> (Vec<u8>, Vec<u8>) {
+            let key = KeyPair::generate().unwrap();
+            let certificate = CertificateParams::new(vec![name.to_string()])
+                .unwrap()
+                .signed_by(&key, &self.certificate, &self.key)
+                .unwrap();
+            (
+                certificate.pem().into_bytes(),
+                key.serialize_pem().into_bytes(),
+            )
+        }
+    }
+
+    #[test]
+    fn test_client_tls() {
+        let authority = Authority::new();
+        let (certificate, key) = authority.issue("localhost");
+        let (other_certificate, other_key) = authority.issue("other.test");
+        let (client_certificate, client_key) = authority.issue("client");
+        let tls = silentdb::TlsOptions::from_pem(&certificate, &key)
+            .server_name("other.test", &other_certificate, &other_key)
+            .client_ca(&authority.pem(), true);
+        let server = start_with("tls", ServerOptions::new().tls(tls));
+
+        let connect = |name: &str, identity: bool| {
+            let mut tls = TlsOptions::new(name).root_certificates(&authority.pem());
+            if identity {
+                tls = tls.client_certificate(&client_certificate, &client_key);
+            }
+            Client::connect_with(server.local_addr(), ClientOptions::new().tls(tls))
+        };
+        let client = connect("localhost", true).unwrap();
+        let users = client.collection::<User>("users");
+        users.insert_many((0..3).map(user)).unwrap();
+        assert_eq!(users.count(&Document::new()).unwrap(), 3);
+
+        // The certificate for the name asked for through SNI is presented
+        let client = connect("other.test", true).unwrap();
+        assert_eq!(
+            client
+                .collection::<User>("users")
+                .count(&Document::new())
+                .unwrap(),
+            3
+        );
+
+        // Clients need a certificate, and plain TCP is not spoken
+        assert!(connect("localhost", false).is_err());
+        assert!(Client::connect(server.local_addr()).is_err());
+
+        server.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_client_authentication() {
+        let db = open("authentication");
+        db.create_user("writer, admin", "p=ss", &[Role::ReadWrite])
+            .unwrap();
+        db.create_user("reader", "secret", &[Role::Read]).unwrap();
+        let server = serve(db, ServerOptions::new().authentication(true));
+        let connect = |user: &str, password: &str| {
+            let options = ClientOptions::new().credentials(user, password);
+            Client::connect_with(server.local_addr(), options)
+        };
+
+        // Names and passwords with SCRAM's separators in them still work
+        let writer = connect("writer, admin", "p=ss").unwrap();
+        let users = writer.collection::<User>("users");
+        users.insert_many((0..3).map(user)).unwrap();
+        assert_eq!(users.count(&Document::new()).unwrap(), 3);
+
+        let reader = connect("reader", "secret").unwrap();
+        let users = reader.collection::<User>("users");
+        assert_eq!(users.find_one(&doc("_id", 1)).unwrap(), Some(user(1)));
+        assert_eq!(users.insert_one(user(3)).unwrap_err().code(), Some(13));
+
+        assert_eq!(connect("reader", "wrong").unwrap_err().code(), Some(18));
+        assert_eq!(connect("nobody", "secret").unwrap_err().code(), Some(18));
+        let anonymous = Client::connect(server.local_addr()).unwrap();
+        let err = anonymous
+            .collection::<User>("users")
+            .count(&Document::new())
+            .unwrap_err();
+        assert_eq!(err.code(), Some(13));
+        server.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_client_retries() {
+        let server = start("retries");
+        let addr = server.local_addr();
+        let client = Client::connect(addr).unwrap();
+        let users = client.collection::<User>("users");
+        users.insert_one(user(1)).unwrap();
+        let err = users.insert_one(user(1)).unwrap_err();
+        assert_eq!(err.code(), Some(11000));
+        assert!(!err.is_retryable());
+
+        // A restart drops the connection, and the read is retried on a new
+        // one to the server back on the same address
+        server.shutdown().unwrap();
+        let server = Server::bind_with(open("retries-restarted"), addr, ServerOptions::new())
+            .unwrap()
+            .spawn()
+            .unwrap();
+        assert_eq!(users.count(&Document::new()).unwrap(), 0);
+        users.insert_one(user(2)).unwrap();
+
+        // Without retries the failure surfaces, and the next command
+        // reconnects
+        let options = ClientOptions::new().retry_reads(false);
+        let client = Client::connect_with(addr, options).unwrap();
+        let users = client.collection::<User>("users");
+        server.shutdown().unwrap();
+        let server = Server::bind_with(open("retries-again"), addr, ServerOptions::new())
+            .unwrap()
+            .spawn()
+            .unwrap();
+        let err = users.count(&Document::new()).unwrap_err();
+        assert!(err.is_retryable(), "not retryable: {:?}", err);
+        assert_eq!(users.count(&Document::new()).unwrap(), 0);
+        server.shutdown().unwrap();
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_async_client() {
+        let server = start("async");
+        let client = crate::asynchronous::Client::connect(server.local_addr())
+            .await
+            .unwrap();
+        let users = client.collection::<User>("users");
+        users.insert_many((0..5).map(user)).await.unwrap();
+        let cursor = users
+            .find_with(
+                &Document::new(),
+                &FindOptions::new().sort(doc("_id", 1)).batch_size(2),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            cursor.collect().await.unwrap(),
+            (0..5).map(user).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            users
+                .delete_one(&doc("_id", 0))
+                .await
+                .unwrap()
+                .deleted_count,
+            1
+        );
+        assert_eq!(users.count(&Document::new()).await.unwrap(), 4);
+        server.shutdown().unwrap();
+    }
+}
@@ -0,0 +1,128 @@
+// src/tls.rs
+
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::{ClientConfig, RootCertStore};
+
+use crate::error::ClientError;
+
+/// TLS settings for a connection, set with `ClientOptions::tls`.
+///
+/// Only the PEM root certificates added are trusted, so a server's
+/// certificate must be issued by one of them.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use silentdb_client::{ClientOptions, TlsOptions};
+/// let tls = TlsOptions::new("db.example.com")
+///     .root_certificates_pem_file("ca.crt")
+///     .unwrap()
+///     .client_certificate_pem_files("client.crt", "client.key")
+///     .unwrap();
+/// let options = ClientOptions::new().tls(tls);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsOptions {
+    pub(crate) server_name: String,
+    pub(crate) roots: Vec<u8>,
+    pub(crate) identity: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+impl TlsOptions {
+    /// Creates options expecting the server's certificate to be for the
+    /// host `server_name`, which is also sent to it through SNI.
+    pub fn new(server_name: &str) -> Self {
+        TlsOptions {
+            server_name: server_name.to_string(),
+            roots: Vec::new(),
+            identity: None,
+        }
+    }
+
+    /// Trusts the PEM certificates `certificates` as roots, besides those
+    /// already added.
+    pub fn root_certificates(mut self, certificates: &[u8]) -> Self {
+        self.roots.extend_from_slice(certificates);
+        self.roots.push(b'\n');
+        self
+    }
+
+    /// `root_certificates` with a PEM file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read.
+    pub fn root_certificates_pem_file<P: AsRef<Path>>(
+        self,
+        certificates: P,
+    ) -> Result<Self, ClientError> {
+        Ok(self.root_certificates(&fs::read(certificates)?))
+    }
+
+    /// Presents the PEM certificate chain `certificates`, with the PEM
+    /// private key `key`, to a server asking for one, for mutual TLS.
+    pub fn client_certificate(mut self, certificates: &[u8], key: &[u8]) -> Self {
+        self.identity = Some((certificates.to_vec(), key.to_vec()));
+        self
+    }
+
+    /// `client_certificate` with PEM files.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a file cannot be read.
+    pub fn client_certificate_pem_files<P: AsRef<Path>, Q: AsRef<Path>>(
+        self,
+        certificates: P,
+        key: Q,
+    ) -> Result<Self, ClientError> {
+        Ok(self.client_certificate(&fs::read(certificates)?, &fs::read(key)?))
+    }
+
+    /// Builds the rustls configuration the options describe, and the name
+    /// to verify the server's certificate against.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a certificate, key or the server name does not
+    /// parse.
+    pub(crate) fn config(&self) -> Result<(Arc<ClientConfig>, ServerName<'static>), ClientError> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut roots = RootCertStore::empty();
+        for certificate in certificates(&self.roots)? {
+            roots.add(certificate).map_err(tls_error)?;
+        }
+        let builder = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(tls_error)?
+            .with_root_certificates(roots);
+        let config = match &self.identity {
+            None => builder.with_no_client_auth(),
+            Some((chain, key)) => {
+                let key = PrivateKeyDer::from_pem_slice(key)
+                    .map_err(|e| ClientError::Tls(format!("bad private key: {}", e)))?;
+                builder
+                    .with_client_auth_cert(certificates(chain)?, key)
+                    .map_err(tls_error)?
+            }
+        };
+        let name = ServerName::try_from(self.server_name.clone()).map_err(tls_error)?;
+        Ok((Arc::new(config), name))
+    }
+}
+
+/// Returns the certificates in `pem`.
+fn certificates(pem: &[u8]) -> Result<Vec<CertificateDer<'static>>, ClientError> {
+    CertificateDer::pem_slice_iter(pem)
+        .collect::<Result<_, _>>()
+        .map_err(|e| ClientError::Tls(format!("bad certificate: {}", e)))
+}
+
+fn tls_error(error: impl std::fmt::Display) -> ClientError {
+    ClientError::Tls(error.to_string())
+}
@@ -0,0 +1,142 @@
+// benches/codec.rs
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use silentdb_data_encoding::{from_bytes, to_bytes, Array, Document, ObjectId, RawDocument, Value};
+
+/// A small record, such as a user.
+fn small() -> Document {
+    let mut document = Document::new();
+    document.insert("_id", Value::ObjectId(ObjectId::from_bytes([0x5f; 12])));
+    document.insert("name", "Ada Lovelace");
+    document.insert("email", "ada@example.com");
+    document.insert("age", 36);
+    document.insert("active", true);
+    document
+}
+
+/// A large flat record, with many fields of mixed types and a binary.
+fn large() -> Document {
+    let mut document = Document::new();
+    for i in 0..256 {
+        let key = format!("field_{}", i);
+        match i % 4 {
+            0 => document.insert(key, format!("value number {} with some longer text", i)),
+            1 => document.insert(key, i as i64 * 1_000_003),
+            2 => document.insert(key, i as f64 / 7.0),
+            _ => document.insert(key, i % 8 == 3),
+        };
+    }
+    document.insert("payload", Value::Binary(vec![0xAB; 16 * 1024]));
+    document
+}
+
+/// A record nested `depth` documents deep, with an array of small
+/// documents at each level.
+fn nested(depth: usize) -> Document {
+    let mut document = small();
+    for level in 0..depth {
+        let items = (0..4)
+            .map(|i| {
+                let mut item = Document::new();
+                item.insert("sku", format!("item-{}-{}", level, i));
+                item.insert("qty", i);
+                Value::Document(item)
+            })
+            .collect();
+        let mut outer = Document::new();
+        outer.insert("level", level as i32);
+        outer.insert("items", Value::Array(Array::from_vec(items)));
+        outer.insert("child", document);
+        document = outer;
+    }
+    document
+}
+
+fn shapes() -> [(&'static str, Document); 3] {
+    [
+        ("small", small()),
+        ("large", large()),
+        ("nested", nested(16)),
+    ]
+}
+
+fn encode(c: &mut Criterion) {
+    for (shape, document) in shapes() {
+        let len = to_bytes(&document).unwrap().len();
+        let mut group = c.benchmark_group(format!("encode/{}", shape));
+        group.throughput(Throughput::Bytes(len as u64));
+
+        group.bench_function("to_bytes", |b| {
+            b.iter(|| to_bytes(black_box(&document)).unwrap())
+        });
+        #[cfg(feature = "bson")]
+        {
+            let converted = bson::Document::try_from(document.clone()).unwrap();
+            group.bench_function("bson", |b| {
+                b.iter(|| {
+                    let mut bytes = Vec::new();
+                    black_box(&converted).to_writer(&mut bytes).unwrap();
+                    bytes
+                })
+            });
+        }
+        group.finish();
+    }
+}
+
+fn decode(c: &mut Criterion) {
+    for (shape, document) in shapes() {
+        let bytes = to_bytes(&document).unwrap();
+        let mut group = c.benchmark_group(format!("decode/{}", shape));
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+
+        group.bench_function("from_bytes", |b| {
+            b.iter(|| from_bytes(black_box(&bytes)).unwrap())
+        });
+        #[cfg(feature = "bson")]
+        group.bench_function("bson", |b| {
+            b.iter(|| bson::Document::from_reader(black_box(&bytes[..])).unwrap())
+        });
+        group.finish();
+    }
+}
+
+/// Reads one field, the last of the document, without decoding the rest.
+fn raw(c: &mut Criterion) {
+    for (shape, document) in shapes() {
+        let bytes = to_bytes(&document).unwrap();
+        let key = document.iter().last().unwrap().0.to_string();
+        let mut group = c.benchmark_group(format!("raw/{}", shape));
+
+        group.bench_function("from_bytes", |b| {
+            b.iter(|| from_bytes(black_box(&bytes)).unwrap().get(&key).is_some())
+        });
+        group.bench_function("get", |b| {
+            b.iter(|| {
+                RawDocument::from_bytes(black_box(&bytes))
+                    .unwrap()
+                    .get(&key)
+                    .unwrap()
+                    .is_some()
+            })
+        });
+        group.bench_function("index", |b| {
+            let index = RawDocument::from_bytes(&bytes).unwrap().index().unwrap();
+            b.iter(|| black_box(&index).get(&key).is_some())
+        });
+        #[cfg(feature = "bson")]
+        group.bench_function("bson", |b| {
+            b.iter(|| {
+                bson::RawDocument::from_bytes(black_box(&bytes))
+                    .unwrap()
+                    .get(&key)
+                    .unwrap()
+                    .is_some()
+            })
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, encode, decode, raw);
+criterion_main!(benches);
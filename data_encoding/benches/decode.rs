@@ -0,0 +1,133 @@
+// benches/decode.rs
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use silentdb_data_encoding::{
+    from_bytes, from_bytes_trusted, to_bytes, Decoder, DecoderOptions, Document, ObjectId, Value,
+};
+
+/// A string-heavy document resembling a typical stored record.
+fn record(fields: usize) -> Document {
+    let mut tags = Vec::new();
+    for i in 0..16 {
+        tags.push(Value::String(format!("tag-{}", i)));
+    }
+
+    let mut document = Document::new();
+    for i in 0..fields {
+        document.insert(
+            format!("field_{}", i),
+            format!("value number {} with some text", i),
+        );
+    }
+    document.insert("count", 42i64);
+    document.insert("score", 0.5f64);
+    document.insert("tags", Value::Array(tags.into()));
+    document
+}
+
+fn decode(c: &mut Criterion) {
+    for fields in [8, 64, 512] {
+        let bytes = to_bytes(&record(fields)).unwrap();
+        let mut group = c.benchmark_group(format!("decode/{}_fields", fields));
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+
+        group.bench_function("from_bytes", |b| {
+            b.iter(|| from_bytes(black_box(&bytes)).unwrap())
+        });
+        group.bench_function("from_bytes_trusted", |b| {
+            // The bytes were produced by `to_bytes` above.
+            b.iter(|| unsafe { from_bytes_trusted(black_box(&bytes)) }.unwrap())
+        });
+        group.finish();
+    }
+}
+
+fn intern(c: &mut Criterion) {
+    let bytes = to_bytes(&record(16)).unwrap();
+    let stream = bytes.repeat(1000);
+    let mut group = c.benchmark_group("decode/intern");
+    group.throughput(Throughput::Bytes(stream.len() as u64));
+
+    for intern_keys in [false, true] {
+        let name = if intern_keys { "interned" } else { "plain" };
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let options = DecoderOptions::new().intern_keys(intern_keys);
+                let mut decoder = Decoder::with_options(black_box(&stream[..]), options);
+                let mut documents = Vec::with_capacity(1000);
+                while let Some(document) = decoder.next_document().unwrap() {
+                    documents.push(document);
+                }
+                documents
+            })
+        });
+    }
+    group.finish();
+}
+
+fn document(c: &mut Criterion) {
+    for fields in [4, 16, 64] {
+        let names: Vec<String> = (0..fields).map(|i| format!("field_{}", i)).collect();
+        let full = record(fields);
+        let mut group = c.benchmark_group(format!("document/{}_fields", fields));
+
+        group.bench_function("build", |b| {
+            b.iter(|| {
+                let mut document = Document::new();
+                for (i, name) in names.iter().enumerate() {
+                    document.insert(name.as_str(), i as i32);
+                }
+                document
+            })
+        });
+        group.bench_function("get", |b| {
+            b.iter(|| {
+                names
+                    .iter()
+                    .filter(|name| black_box(&full).get(name).is_some())
+                    .count()
+            })
+        });
+        group.finish();
+    }
+}
+
+fn object_id(c: &mut Criterion) {
+    let id = ObjectId::from_bytes([0x5f; 12]);
+    let text = id.to_string();
+    let mut group = c.benchmark_group("object_id");
+
+    group.bench_function("to_string", |b| b.iter(|| black_box(&id).to_string()));
+    group.bench_function("from_str", |b| {
+        b.iter(|| ObjectId::from(black_box(text.as_str())))
+    });
+    group.finish();
+}
+
+#[cfg(feature = "bumpalo")]
+fn arena(c: &mut Criterion) {
+    use silentdb_data_encoding::from_bytes_in;
+
+    let bytes = to_bytes(&record(64)).unwrap();
+    let mut group = c.benchmark_group("decode/arena");
+    group.throughput(Throughput::Bytes(bytes.len() as u64));
+
+    group.bench_function("from_bytes", |b| {
+        b.iter(|| from_bytes(black_box(&bytes)).unwrap().len())
+    });
+    group.bench_function("from_bytes_in", |b| {
+        let mut arena = bumpalo::Bump::new();
+        b.iter(|| {
+            let len = from_bytes_in(black_box(&bytes), &arena).unwrap().len();
+            arena.reset();
+            len
+        })
+    });
+    group.finish();
+}
+
+#[cfg(not(feature = "bumpalo"))]
+criterion_group!(benches, decode, intern, document, object_id);
+#[cfg(feature = "bumpalo")]
+criterion_group!(benches, decode, intern, document, object_id, arena);
+criterion_main!(benches);
@@ -0,0 +1,74 @@
+// src/bin/bsondump.rs
+
+//! Prints a file of back-to-back BSON documents, such as a dump or an oplog
+//! segment, as Extended JSON, type and field statistics, or a hex dump.
+
+use std::fs::File;
+use std::io;
+use std::process::ExitCode;
+
+use silentdb_data_encoding::{inspect, InspectMode, InspectOptions};
+
+const USAGE: &str = "\
+Usage: bsondump [OPTIONS] [FILE]
+
+Reads BSON documents from FILE, or standard input, and prints them.
+
+Options:
+  --stats         Print element counts by type and bytes by field path
+  --hex           Print an annotated hex dump of each document
+  --field PATH    Print only the dotted field PATH; may be given more than once
+  --canonical     Print canonical rather than relaxed Extended JSON
+  --pretty        Pretty-print JSON
+  --help          Print this help
+";
+
+fn main() -> ExitCode {
+    let mut options = InspectOptions::new();
+    let mut path = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--stats" => options = options.mode(InspectMode::Stats),
+            "--hex" => options = options.mode(InspectMode::HexDump),
+            "--canonical" => options = options.canonical(true),
+            "--pretty" => options = options.pretty(true),
+            "--field" => match args.next() {
+                Some(field) => options = options.field(field),
+                None => return usage("--field needs a value"),
+            },
+            "--help" | "-h" => {
+                print!("{}", USAGE);
+                return ExitCode::SUCCESS;
+            }
+            option if option.starts_with('-') && option != "-" => {
+                return usage(&format!("unknown option {}", option))
+            }
+            _ if path.is_some() => return usage(&format!("unexpected argument {}", arg)),
+            _ => path = Some(arg),
+        }
+    }
+
+    let result = match path.as_deref() {
+        None | Some("-") => inspect(io::stdin().lock(), io::stdout().lock(), &options),
+        Some(path) => match File::open(path) {
+            Ok(file) => inspect(file, io::stdout().lock(), &options),
+            Err(e) => {
+                eprintln!("bsondump: {}: {}", path, e);
+                return ExitCode::FAILURE;
+            }
+        },
+    };
+    match result {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("bsondump: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn usage(message: &str) -> ExitCode {
+    eprintln!("bsondump: {}\n\n{}", message, USAGE);
+    ExitCode::from(2)
+}
@@ -0,0 +1,298 @@
+// src/deser/arena.rs
+
+use bumpalo::collections::Vec as BumpVec;
+use bumpalo::Bump;
+
+use super::error::DeserializeError;
+use super::header::split_header;
+use super::options::DecoderOptions;
+use super::MAX_NESTING_DEPTH;
+use crate::raw::{RawDocument, RawElement};
+use crate::types::{Array, Document, ObjectId, Value};
+use crate::utils::scan::{cstring_len, str_from_utf8};
+
+/// A document decoded into a `Bump` arena by `from_bytes_in`.
+///
+/// Its keys, strings, binaries and containers all live in the arena, and
+/// are freed together when the arena is reset or dropped rather than one
+/// by one. Fields are kept in encoded order; a repeated field name is kept
+/// each time, and `get` returns its last value, the one `from_bytes` keeps.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArenaDocument<'a> {
+    fields: BumpVec<'a, (&'a str, ArenaValue<'a>)>,
+}
+
+/// An array decoded into a `Bump` arena, held by an `ArenaValue`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArenaArray<'a> {
+    elements: BumpVec<'a, ArenaValue<'a>>,
+}
+
+/// A value of an `ArenaDocument`, borrowing its contents from the arena.
+/// The variants match `Value`'s, less the deprecated types, which
+/// `from_bytes_in` rejects as `from_bytes` does by default.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArenaValue<'a> {
+    Double(f64),
+    String(&'a str),
+    Document(ArenaDocument<'a>),
+    Array(ArenaArray<'a>),
+    Binary(&'a [u8]),
+    ObjectId(ObjectId),
+    Boolean(bool),
+    UTCDateTime(i64),
+    Null,
+    RegularExpression {
+        pattern: &'a str,
+        options: &'a str,
+    },
+    JavaScriptCode(&'a str),
+    JavaScriptCodeWithScope {
+        code: &'a str,
+        scope: ArenaDocument<'a>,
+    },
+    Int32(i32),
+    Timestamp(i64),
+    Int64(i64),
+    UInt64(u64),
+    MinKey,
+    MaxKey,
+}
+
+impl<'a> ArenaDocument<'a> {
+    /// Returns the value of the field named `key`.
+    pub fn get(&self, key: &str) -> Option<&ArenaValue<'a>> {
+        self.fields
+            .iter()
+            .rev()
+            .find(|(name, _)| *name == key)
+            .map(|(_, value)| value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Returns an iterator over the fields, in encoded order.
+    pub fn iter(&self) -> impl Iterator<Item = (&'a str, &ArenaValue<'a>)> {
+        self.fields.iter().map(|(key, value)| (*key, value))
+    }
+
+    /// Copies the document out of the arena.
+    pub fn to_document(&self) -> Document {
+        let mut document = Document::new_with_capacity(self.len());
+        for (key, value) in self.iter() {
+            document.insert(key, value.to_value());
+        }
+        document
+    }
+}
+
+impl<'a> ArenaArray<'a> {
+    /// Returns the element at `index`.
+    pub fn get(&self, index: usize) -> Option<&ArenaValue<'a>> {
+        self.elements.get(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ArenaValue<'a>> {
+        self.elements.iter()
+    }
+
+    /// Copies the array out of the arena.
+    pub fn to_array(&self) -> Array {
+        Array::from_vec(self.iter().map(ArenaValue::to_value).collect())
+    }
+}
+
+impl ArenaValue<'_> {
+    /// Copies the value out of the arena.
+    pub fn to_value(&self) -> Value {
+        match self {
+            ArenaValue::Double(value) => Value::Double(*value),
+            ArenaValue::String(value) => Value::String(value.to_string()),
+            ArenaValue::Document(value) => Value::Document(value.to_document()),
+            ArenaValue::Array(value) => Value::Array(value.to_array()),
+            ArenaValue::Binary(value) => Value::Binary(value.to_vec()),
+            ArenaValue::ObjectId(value) => Value::ObjectId(value.clone()),
+            ArenaValue::Boolean(value) => Value::Boolean(*value),
+            ArenaValue::UTCDateTime(value) => Value::UTCDateTime(*value),
+            ArenaValue::Null => Value::Null,
+            ArenaValue::RegularExpression { pattern, options } => Value::RegularExpression {
+                pattern: pattern.to_string(),
+                options: options.to_string(),
+            },
+            ArenaValue::JavaScriptCode(code) => Value::JavaScriptCode(code.to_string()),
+            ArenaValue::JavaScriptCodeWithScope { code, scope } => Value::JavaScriptCodeWithScope {
+                code: code.to_string(),
+                scope: scope.to_document(),
+            },
+            ArenaValue::Int32(value) => Value::Int32(*value),
+            ArenaValue::Timestamp(value) => Value::Timestamp(*value),
+            ArenaValue::Int64(value) => Value::Int64(*value),
+            ArenaValue::UInt64(value) => Value::UInt64(*value),
+            ArenaValue::MinKey => Value::MinKey,
+            ArenaValue::MaxKey => Value::MaxKey,
+        }
+    }
+}
+
+/// Decodes a single BSON document from `bytes` into `arena`.
+///
+/// Everything the document holds is allocated in the arena, so a request
+/// that decodes, processes and discards its documents frees them all at
+/// once by resetting the arena, and reuses its memory for the next one.
+/// The results must be dropped before the arena is reset, as they borrow
+/// it. `bytes` need not outlive the result. Input is checked as strictly as by
+/// `from_bytes`, and a leading `EncodingHeader` is detected the same way.
+///
+/// # Examples
+///
+/// ```
+/// # use bumpalo::Bump;
+/// # use silentdb_data_encoding::{from_bytes_in, to_bytes, ArenaValue, Document};
+/// let mut document = Document::new();
+/// document.insert("name", "Ada");
+/// let bytes = to_bytes(&document).unwrap();
+///
+/// let mut arena = Bump::new();
+/// let decoded = from_bytes_in(&bytes, &arena).unwrap();
+/// assert_eq!(decoded.get("name"), Some(&ArenaValue::String("Ada")));
+/// assert_eq!(decoded.to_document(), document);
+/// drop(decoded);
+/// arena.reset();
+/// ```
+///
+/// # Errors
+///
+/// Returns an error if the bytes are not exactly one well-formed document,
+/// or if it holds a deprecated type.
+pub fn from_bytes_in<'a>(
+    bytes: &[u8],
+    arena: &'a Bump,
+) -> Result<ArenaDocument<'a>, DeserializeError> {
+    let (_, bytes) = split_header(bytes, &DecoderOptions::default())?;
+    ArenaReader { arena, depth: 0 }.document(RawDocument::from_bytes(bytes)?)
+}
+
+/// Copies the elements of raw documents into an arena.
+struct ArenaReader<'a> {
+    arena: &'a Bump,
+    depth: usize,
+}
+
+impl<'a> ArenaReader<'a> {
+    fn document(&mut self, raw: RawDocument<'_>) -> Result<ArenaDocument<'a>, DeserializeError> {
+        self.enter()?;
+        let mut fields = BumpVec::new_in(self.arena);
+        for element in raw.iter() {
+            let element = element?;
+            let key: &str = self.arena.alloc_str(element.key());
+            fields.push((key, self.value(&element)?));
+        }
+        self.depth -= 1;
+        Ok(ArenaDocument { fields })
+    }
+
+    fn array(&mut self, raw: RawDocument<'_>) -> Result<ArenaArray<'a>, DeserializeError> {
+        self.enter()?;
+        let mut elements = BumpVec::new_in(self.arena);
+        for element in raw.iter() {
+            elements.push(self.value(&element?)?);
+        }
+        self.depth -= 1;
+        Ok(ArenaArray { elements })
+    }
+
+    fn enter(&mut self) -> Result<(), DeserializeError> {
+        if self.depth >= MAX_NESTING_DEPTH {
+            return Err(DeserializeError::InvalidDocument(
+                "maximum nesting depth exceeded".to_string(),
+            ));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    /// Reads the value of `element`, whose length the raw iterator checked.
+    fn value(&mut self, element: &RawElement<'_>) -> Result<ArenaValue<'a>, DeserializeError> {
+        let bytes = element.value_bytes();
+        let value = match element.element_type() {
+            0x01 => ArenaValue::Double(f64::from_le_bytes(fixed(bytes))),
+            0x02 => ArenaValue::String(self.string(bytes)?),
+            0x03 => ArenaValue::Document(self.document(RawDocument::from_bytes(bytes)?)?),
+            0x04 => ArenaValue::Array(self.array(RawDocument::from_bytes(bytes)?)?),
+            0x05 => ArenaValue::Binary(self.arena.alloc_slice_copy(&bytes[5..])),
+            0x07 => ArenaValue::ObjectId(ObjectId::from_bytes(fixed(bytes))),
+            0x08 => ArenaValue::Boolean(bytes[0] != 0),
+            0x09 => ArenaValue::UTCDateTime(i64::from_le_bytes(fixed(bytes))),
+            0x0A => ArenaValue::Null,
+            0x0B => {
+                let pattern_len = cstring_len(bytes).unwrap_or_default();
+                ArenaValue::RegularExpression {
+                    pattern: self.str(&bytes[..pattern_len])?,
+                    options: self.str(&bytes[pattern_len + 1..bytes.len() - 1])?,
+                }
+            }
+            0x0D => ArenaValue::JavaScriptCode(self.string(bytes)?),
+            0x0F => {
+                let (code, scope) = bytes
+                    .get(4..8)
+                    .and_then(|prefix| usize::try_from(i32::from_le_bytes(fixed(prefix))).ok())
+                    .and_then(|code_len| bytes[4..].split_at_checked(4 + code_len))
+                    .ok_or(DeserializeError::UnexpectedEof)?;
+                ArenaValue::JavaScriptCodeWithScope {
+                    code: self.string(code)?,
+                    scope: self.document(RawDocument::from_bytes(scope)?)?,
+                }
+            }
+            0x10 => ArenaValue::Int32(i32::from_le_bytes(fixed(bytes))),
+            0x11 => ArenaValue::Timestamp(i64::from_le_bytes(fixed(bytes))),
+            0x12 => ArenaValue::Int64(i64::from_le_bytes(fixed(bytes))),
+            0x13 => ArenaValue::UInt64(u64::from_le_bytes(fixed(bytes))),
+            0xFF => ArenaValue::MinKey,
+            0x7F => ArenaValue::MaxKey,
+            other => {
+                return Err(DeserializeError::NotSupported(format!(
+                    "deprecated BSON type {:#04x}",
+                    other
+                )))
+            }
+        };
+        Ok(value)
+    }
+
+    /// Copies a length-prefixed, null-terminated string into the arena.
+    fn string(&self, bytes: &[u8]) -> Result<&'a str, DeserializeError> {
+        match bytes.get(4..).and_then(<[u8]>::split_last) {
+            Some((0, contents)) => self.str(contents),
+            Some(_) => Err(DeserializeError::InvalidDocument(
+                "string is not null-terminated".to_string(),
+            )),
+            None => Err(DeserializeError::UnexpectedEof),
+        }
+    }
+
+    /// Copies UTF-8 `bytes` into the arena.
+    fn str(&self, bytes: &[u8]) -> Result<&'a str, DeserializeError> {
+        let text = str_from_utf8(bytes)
+            .map_err(|e| DeserializeError::InvalidDocument(format!("invalid UTF-8: {}", e)))?;
+        Ok(&*self.arena.alloc_str(text))
+    }
+}
+
+/// Returns the first `N` bytes of a value whose length was checked.
+fn fixed<const N: usize>(bytes: &[u8]) -> [u8; N] {
+    bytes[..N].try_into().unwrap()
+}
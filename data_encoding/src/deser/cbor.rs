@@ -0,0 +1,383 @@
+// src/deser/cbor.rs
+
+use super::error::DeserializeError;
+use super::MAX_NESTING_DEPTH;
+use crate::types::{Array, Document, ObjectId, UTCDateTime, Value};
+
+/* CBOR Tags */
+
+// Standard tags (RFC 8949).
+const TAG_DATE_TIME_STRING: u64 = 0;
+const TAG_EPOCH_DATE_TIME: u64 = 1;
+const TAG_REGEX: u64 = 35;
+const TAG_SELF_DESCRIBE: u64 = 55799;
+
+/// Base of the SilentDB tag range. Each tag is the base plus the BSON type byte
+/// of the value it carries.
+const SILENTDB_TAG_BASE: u64 = 0x5344_0000;
+
+/// Tag wrapping a 12-byte byte string holding an ObjectId.
+pub const CBOR_TAG_OBJECT_ID: u64 = SILENTDB_TAG_BASE + 0x07;
+/// Tag wrapping a two-element array `[pattern, options]` holding a regular expression.
+pub const CBOR_TAG_REGEX: u64 = SILENTDB_TAG_BASE + 0x0B;
+/// Tag wrapping an unsigned integer holding a BSON timestamp.
+pub const CBOR_TAG_TIMESTAMP: u64 = SILENTDB_TAG_BASE + 0x11;
+/// Tag marking MinKey. The tagged item is ignored (conventionally `null`).
+pub const CBOR_TAG_MIN_KEY: u64 = SILENTDB_TAG_BASE + 0xFF;
+/// Tag marking MaxKey. The tagged item is ignored (conventionally `null`).
+pub const CBOR_TAG_MAX_KEY: u64 = SILENTDB_TAG_BASE + 0x7F;
+
+/* Major Types */
+
+const MAJOR_UNSIGNED: u8 = 0;
+const MAJOR_NEGATIVE: u8 = 1;
+const MAJOR_BYTES: u8 = 2;
+const MAJOR_TEXT: u8 = 3;
+const MAJOR_ARRAY: u8 = 4;
+const MAJOR_MAP: u8 = 5;
+const MAJOR_TAG: u8 = 6;
+const MAJOR_SIMPLE: u8 = 7;
+
+const INDEFINITE: u8 = 31;
+const BREAK: u8 = 0xFF;
+
+/// Decodes a CBOR-encoded map into a `Document`.
+///
+/// Standard tags 0/1 map to `UTCDateTime` and tag 35 to a regular expression;
+/// SilentDB tags (`CBOR_TAG_*`) map back to ObjectId, Timestamp, MinKey and
+/// MaxKey. Unknown tags are ignored and their content decoded as-is.
+///
+/// # Errors
+///
+/// Returns an error if the input is not a single well-formed CBOR map, or if
+/// it contains values with no BSON equivalent (big integers, simple values).
+pub fn from_cbor_bytes(bytes: &[u8]) -> Result<Document, DeserializeError> {
+    let mut decoder = CborDecoder::new(bytes);
+    let document = match decoder.decode_value()? {
+        Value::Document(document) => document,
+        other => {
+            return Err(DeserializeError::InvalidDocument(format!(
+                "top-level CBOR item must be a map, found {}",
+                other
+            )))
+        }
+    };
+
+    if decoder.position != bytes.len() {
+        return Err(DeserializeError::InvalidDocument(format!(
+            "{} trailing bytes after CBOR document",
+            bytes.len() - decoder.position
+        )));
+    }
+
+    Ok(document)
+}
+
+/// A CBOR item header: major type plus its argument (or `None` for indefinite length).
+struct Header {
+    major: u8,
+    info: u8,
+    argument: Option<u64>,
+}
+
+struct CborDecoder<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    depth: usize,
+}
+
+impl<'a> CborDecoder<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        CborDecoder {
+            bytes,
+            position: 0,
+            depth: 0,
+        }
+    }
+
+    fn invalid(&self, message: &str) -> DeserializeError {
+        DeserializeError::InvalidDocument(format!("{} at offset {}", message, self.position))
+    }
+
+    fn read_u8(&mut self) -> Result<u8, DeserializeError> {
+        let byte = *self
+            .bytes
+            .get(self.position)
+            .ok_or(DeserializeError::UnexpectedEof)?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn read_slice(&mut self, len: u64) -> Result<&'a [u8], DeserializeError> {
+        let len = usize::try_from(len).map_err(|_| DeserializeError::UnexpectedEof)?;
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(DeserializeError::UnexpectedEof)?;
+        let slice = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(slice)
+    }
+
+    fn read_uint(&mut self, width: u64) -> Result<u64, DeserializeError> {
+        Ok(self
+            .read_slice(width)?
+            .iter()
+            .fold(0u64, |acc, byte| (acc << 8) | *byte as u64))
+    }
+
+    fn peek_break(&self) -> bool {
+        self.bytes.get(self.position) == Some(&BREAK)
+    }
+
+    fn read_header(&mut self) -> Result<Header, DeserializeError> {
+        let initial = self.read_u8()?;
+        let major = initial >> 5;
+        let info = initial & 0x1F;
+        let argument = match info {
+            0..=23 => Some(info as u64),
+            24 => Some(self.read_uint(1)?),
+            25 => Some(self.read_uint(2)?),
+            26 => Some(self.read_uint(4)?),
+            27 => Some(self.read_uint(8)?),
+            INDEFINITE if matches!(major, MAJOR_BYTES | MAJOR_TEXT | MAJOR_ARRAY | MAJOR_MAP) => {
+                None
+            }
+            _ => return Err(self.invalid("reserved additional information value")),
+        };
+        Ok(Header {
+            major,
+            info,
+            argument,
+        })
+    }
+
+    fn decode_value(&mut self) -> Result<Value, DeserializeError> {
+        let header = self.read_header()?;
+        match header.major {
+            MAJOR_UNSIGNED => Ok(unsigned_to_value(header.argument.unwrap_or_default())),
+            MAJOR_NEGATIVE => {
+                let argument = header.argument.unwrap_or_default();
+                if argument > i64::MAX as u64 {
+                    return Err(self.invalid("negative integer out of range"));
+                }
+                let value = -1 - argument as i64;
+                Ok(match i32::try_from(value) {
+                    Ok(value) => Value::Int32(value),
+                    Err(_) => Value::Int64(value),
+                })
+            }
+            MAJOR_BYTES => Ok(Value::Binary(self.read_string_bytes(MAJOR_BYTES, header.argument)?)),
+            MAJOR_TEXT => Ok(Value::String(self.read_text(header.argument)?)),
+            MAJOR_ARRAY => self.nested(|decoder| decoder.decode_array(header.argument)),
+            MAJOR_MAP => self.nested(|decoder| decoder.decode_map(header.argument)),
+            MAJOR_TAG => {
+                let tag = header.argument.unwrap_or_default();
+                self.nested(|decoder| decoder.decode_tagged(tag))
+            }
+            MAJOR_SIMPLE => self.decode_simple(&header),
+            _ => unreachable!("major type is three bits"),
+        }
+    }
+
+    fn nested<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<T, DeserializeError>,
+    ) -> Result<T, DeserializeError> {
+        if self.depth >= MAX_NESTING_DEPTH {
+            return Err(self.invalid("maximum nesting depth exceeded"));
+        }
+        self.depth += 1;
+        let result = f(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// Reads a byte or text string body, concatenating chunks of indefinite-length strings.
+    fn read_string_bytes(
+        &mut self,
+        major: u8,
+        length: Option<u64>,
+    ) -> Result<Vec<u8>, DeserializeError> {
+        if let Some(length) = length {
+            return Ok(self.read_slice(length)?.to_vec());
+        }
+
+        let mut bytes = Vec::new();
+        while !self.peek_break() {
+            let chunk = self.read_header()?;
+            match (chunk.major == major, chunk.argument) {
+                (true, Some(length)) => bytes.extend_from_slice(self.read_slice(length)?),
+                _ => return Err(self.invalid("invalid chunk in indefinite-length string")),
+            }
+        }
+        self.position += 1;
+        Ok(bytes)
+    }
+
+    fn read_text(&mut self, length: Option<u64>) -> Result<String, DeserializeError> {
+        let bytes = self.read_string_bytes(MAJOR_TEXT, length)?;
+        Ok(String::from_utf8(bytes)?)
+    }
+
+    fn decode_array(&mut self, length: Option<u64>) -> Result<Value, DeserializeError> {
+        let mut array = Array::new();
+        match length {
+            Some(length) => {
+                for _ in 0..length {
+                    array.push(self.decode_value()?);
+                }
+            }
+            None => {
+                while !self.peek_break() {
+                    array.push(self.decode_value()?);
+                }
+                self.position += 1;
+            }
+        }
+        Ok(Value::Array(array))
+    }
+
+    fn decode_map(&mut self, length: Option<u64>) -> Result<Value, DeserializeError> {
+        let mut document = Document::new();
+        match length {
+            Some(length) => {
+                for _ in 0..length {
+                    let key = self.decode_key()?;
+                    document.insert(key, self.decode_value()?);
+                }
+            }
+            None => {
+                while !self.peek_break() {
+                    let key = self.decode_key()?;
+                    document.insert(key, self.decode_value()?);
+                }
+                self.position += 1;
+            }
+        }
+        Ok(Value::Document(document))
+    }
+
+    /// Reads a map key. Text keys are used as-is; integer keys (common in
+    /// compact IoT payloads) are converted to their decimal representation.
+    fn decode_key(&mut self) -> Result<String, DeserializeError> {
+        let start = self.position;
+        let header = self.read_header()?;
+        match header.major {
+            MAJOR_TEXT => self.read_text(header.argument),
+            MAJOR_UNSIGNED | MAJOR_NEGATIVE => {
+                self.position = start;
+                Ok(self.decode_value()?.to_string())
+            }
+            _ => {
+                self.position = start;
+                Err(self.invalid("map key must be a text string or integer"))
+            }
+        }
+    }
+
+    fn decode_tagged(&mut self, tag: u64) -> Result<Value, DeserializeError> {
+        let start = self.position;
+        let content = self.decode_value()?;
+        let mismatch = |expected: &str, found: &Value| {
+            DeserializeError::InvalidDocument(format!(
+                "CBOR tag {} expects {}, found {} at offset {}",
+                tag, expected, found, start
+            ))
+        };
+
+        match tag {
+            TAG_DATE_TIME_STRING => content
+                .as_str()
+                .and_then(UTCDateTime::parse_rfc3339)
+                .map(Value::from)
+                .ok_or_else(|| mismatch("an RFC 3339 date-time string", &content)),
+            TAG_EPOCH_DATE_TIME => match content {
+                Value::Int32(secs) => Ok(Value::UTCDateTime(secs as i64)),
+                Value::Int64(secs) => Ok(Value::UTCDateTime(secs)),
+                Value::Double(secs) if secs.is_finite() => Ok(Value::UTCDateTime(secs as i64)),
+                other => Err(mismatch("a numeric epoch time", &other)),
+            },
+            TAG_REGEX => match content {
+                Value::String(pattern) => Ok(Value::RegularExpression {
+                    pattern,
+                    options: String::new(),
+                }),
+                other => Err(mismatch("a text pattern", &other)),
+            },
+            CBOR_TAG_REGEX => {
+                let parts = content.as_array().map(|array| (array.get(0), array.get(1)));
+                match parts {
+                    Some((Some(Value::String(pattern)), Some(Value::String(options)))) => {
+                        Ok(Value::RegularExpression {
+                            pattern: pattern.clone(),
+                            options: options.clone(),
+                        })
+                    }
+                    _ => Err(mismatch("a [pattern, options] array", &content)),
+                }
+            }
+            CBOR_TAG_OBJECT_ID => match content {
+                Value::Binary(bytes) if bytes.len() == 12 => {
+                    let mut inner = [0; 12];
+                    inner.copy_from_slice(&bytes);
+                    Ok(Value::ObjectId(ObjectId::from_bytes(inner)))
+                }
+                other => Err(mismatch("a 12-byte byte string", &other)),
+            },
+            CBOR_TAG_TIMESTAMP => match content {
+                Value::Int32(value) if value >= 0 => Ok(Value::Timestamp(value as i64)),
+                Value::Int64(value) if value >= 0 => Ok(Value::Timestamp(value)),
+                Value::UInt64(value) => Ok(Value::Timestamp(value as i64)),
+                other => Err(mismatch("an unsigned integer", &other)),
+            },
+            CBOR_TAG_MIN_KEY => Ok(Value::MinKey),
+            CBOR_TAG_MAX_KEY => Ok(Value::MaxKey),
+            TAG_SELF_DESCRIBE => Ok(content),
+            // Unknown tags carry no meaning for us; keep the content.
+            _ => Ok(content),
+        }
+    }
+
+    fn decode_simple(&mut self, header: &Header) -> Result<Value, DeserializeError> {
+        let argument = header.argument.unwrap_or_default();
+        match header.info {
+            20 => Ok(Value::Boolean(false)),
+            21 => Ok(Value::Boolean(true)),
+            22 | 23 => Ok(Value::Null),
+            25 => Ok(Value::Double(f16_to_f64(argument as u16))),
+            26 => Ok(Value::Double(f32::from_bits(argument as u32) as f64)),
+            27 => Ok(Value::Double(f64::from_bits(argument))),
+            _ => Err(DeserializeError::NotSupported(format!(
+                "CBOR simple value {}",
+                argument
+            ))),
+        }
+    }
+}
+
+/// Maps a CBOR unsigned integer to the narrowest signed BSON integer that holds it.
+pub(super) fn unsigned_to_value(value: u64) -> Value {
+    if value <= i32::MAX as u64 {
+        Value::Int32(value as i32)
+    } else if value <= i64::MAX as u64 {
+        Value::Int64(value as i64)
+    } else {
+        Value::UInt64(value)
+    }
+}
+
+/// Converts an IEEE 754 half-precision float to `f64`.
+fn f16_to_f64(half: u16) -> f64 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((half >> 10) & 0x1F) as i32;
+    let mantissa = (half & 0x03FF) as f64;
+    let magnitude = match exponent {
+        0 => mantissa * 2f64.powi(-24),
+        31 if mantissa == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f64.powi(exponent - 15),
+    };
+    sign * magnitude
+}
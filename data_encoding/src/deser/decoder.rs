@@ -0,0 +1,673 @@
+// src/deser/decoder.rs
+
+use std::collections::HashSet;
+use std::io::{self, Read};
+
+use super::error::{DecodeWarning, DeserializeError};
+use super::framing::{MAX_DOCUMENT_LEN, MIN_DOCUMENT_LEN};
+use super::header::split_header;
+use super::intern::{InternStats, KeyInterner};
+use super::options::{DecoderOptions, DuplicateKeyPolicy, LegacyTypes, Utf8Mode};
+use super::stats::DecodeStats;
+use super::MAX_NESTING_DEPTH;
+
+/// Upper bound on the buffers `Decoder::decode_into` keeps for reuse.
+const MAX_SPARE_BUFFERS: usize = 4096;
+use crate::types::{Array, Document, FieldName, ObjectId, Value};
+use crate::utils::buffer_pool::{BufferPool, MIN_POOLED_CAPACITY};
+use crate::utils::scan::string_from_utf8;
+
+/// Decodes BSON documents from a reader.
+///
+/// Every declared length is checked against the enclosing document, so a
+/// corrupt length prefix is reported as an error instead of causing a large
+/// allocation or a read past the end of the document.
+///
+/// Reads are small and frequent; wrap unbuffered readers in a `BufReader`.
+pub struct Decoder<R: Read> {
+    reader: R,
+    position: u64,
+    document_ends: Vec<u64>, // End offsets of the open documents, innermost last
+    options: DecoderOptions,
+    warnings: Vec<DecodeWarning>,
+    stats: Option<DecodeStats>,
+    path: Vec<String>, // Keys of the open fields, only tracked while collecting stats
+    spare: SpareBuffers, // Buffers recycled from documents passed to decode_into
+    interner: Option<KeyInterner>,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Creates a new decoder reading from `reader`.
+    pub fn new(reader: R) -> Self {
+        Decoder::with_options(reader, DecoderOptions::default())
+    }
+
+    /// Creates a new decoder reading from `reader` with the given options.
+    pub fn with_options(reader: R, options: DecoderOptions) -> Self {
+        Decoder {
+            reader,
+            position: 0,
+            document_ends: Vec::new(),
+            stats: options.collect_stats.then(DecodeStats::default),
+            interner: options.intern_keys.then(KeyInterner::default),
+            options,
+            warnings: Vec::new(),
+            path: Vec::new(),
+            spare: SpareBuffers::default(),
+        }
+    }
+
+    /// Takes buffers for large strings and binaries from `pool` when the
+    /// decoder has none of its own to reuse, and gives back those it cannot
+    /// keep, including all it holds when dropped.
+    pub fn with_pool(mut self, pool: &BufferPool) -> Self {
+        self.spare.pool = Some(pool.clone());
+        self
+    }
+
+    /// Returns the number of bytes consumed so far.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Returns the problems recovered from so far, in input order.
+    pub fn warnings(&self) -> &[DecodeWarning] {
+        &self.warnings
+    }
+
+    /// Removes and returns the warnings recorded so far.
+    pub fn take_warnings(&mut self) -> Vec<DecodeWarning> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// Returns the statistics collected so far, if enabled in the options.
+    pub fn stats(&self) -> Option<&DecodeStats> {
+        self.stats.as_ref()
+    }
+
+    /// Returns what interning field names saved so far, if enabled in the
+    /// options.
+    pub fn intern_stats(&self) -> Option<InternStats> {
+        self.interner.as_ref().map(KeyInterner::stats)
+    }
+
+    /// Returns a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Consumes the decoder, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Decodes the next document.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the input ends early or the document is malformed.
+    pub fn decode_document(&mut self) -> Result<Document, DeserializeError> {
+        self.begin_document()?;
+        self.read_document_body()
+    }
+
+    /// Decodes a document into `document`, replacing its contents.
+    ///
+    /// The map capacity of `document` is kept, and the buffers behind its
+    /// strings and binary values are reused for the new ones. Decoding many
+    /// similarly-shaped documents into the same `Document` avoids most
+    /// allocations, and with `DecoderOptions::intern_keys` those of its
+    /// field names too.
+    ///
+    /// On error `document` is left partially filled.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the input ends early or the document is malformed.
+    pub fn decode_into(&mut self, document: &mut Document) -> Result<(), DeserializeError> {
+        self.recycle_document(document);
+        self.begin_document()?;
+        self.read_document_body_into(document)
+    }
+
+    /// Decodes the next document, or returns `None` if the input ends cleanly
+    /// before it. Used to read back-to-back documents until end of input.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the input ends inside a document or the document is malformed.
+    pub fn next_document(&mut self) -> Result<Option<Document>, DeserializeError> {
+        if !self.try_begin_document()? {
+            return Ok(None);
+        }
+        self.read_document_body().map(Some)
+    }
+
+    /* Element Reads */
+
+    /// Reads a document's length prefix and opens it.
+    pub(crate) fn begin_document(&mut self) -> Result<(), DeserializeError> {
+        let mut prefix = [0; 4];
+        self.read_exact(&mut prefix)?;
+        self.open_document(i32::from_le_bytes(prefix))
+    }
+
+    /// Like `begin_document`, but returns `false` if the input is already at
+    /// its end. Only meaningful for top-level documents.
+    pub(crate) fn try_begin_document(&mut self) -> Result<bool, DeserializeError> {
+        let mut prefix = [0; 4];
+        let read = loop {
+            match self.reader.read(&mut prefix) {
+                Ok(read) => break read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        };
+        if read == 0 {
+            return Ok(false);
+        }
+        self.position += read as u64;
+        self.read_exact(&mut prefix[read..])?;
+        self.open_document(i32::from_le_bytes(prefix))?;
+        Ok(true)
+    }
+
+    fn open_document(&mut self, length: i32) -> Result<(), DeserializeError> {
+        let start = self.position - 4;
+        if (length as i64) < MIN_DOCUMENT_LEN as i64 {
+            return Err(DeserializeError::InvalidLength(length as i64));
+        }
+        if self.document_ends.is_empty() && length as usize > MAX_DOCUMENT_LEN {
+            return Err(DeserializeError::DocumentTooLarge {
+                length: length as usize,
+                max: MAX_DOCUMENT_LEN,
+            });
+        }
+        if self.document_ends.len() >= MAX_NESTING_DEPTH {
+            return Err(self.invalid("maximum nesting depth exceeded"));
+        }
+
+        let end = start + length as u64;
+        if let Some(enclosing_end) = self.document_ends.last() {
+            if end > *enclosing_end {
+                return Err(self.invalid("embedded document overruns its parent"));
+            }
+        }
+        self.document_ends.push(end);
+        if let Some(stats) = &mut self.stats {
+            if self.document_ends.len() == 1 {
+                stats.record_document();
+                self.path.clear();
+            }
+            stats.record_depth(self.document_ends.len());
+        }
+        Ok(())
+    }
+
+    /// Closes the innermost document after its terminator has been read,
+    /// checking that the terminator is where the length prefix said it is.
+    pub(crate) fn finish_document(&mut self) -> Result<(), DeserializeError> {
+        let end = self
+            .document_ends
+            .pop()
+            .expect("finish_document called without an open document");
+        if self.position != end {
+            return Err(DeserializeError::InvalidDocument(format!(
+                "document declared to end at offset {} but terminator found at offset {}",
+                end,
+                self.position - 1
+            )));
+        }
+        Ok(())
+    }
+
+    /// Reads the type byte and key of the next element, or returns `None`
+    /// after consuming the terminator of the current document.
+    pub(crate) fn read_element_header(&mut self) -> Result<Option<(u8, String)>, DeserializeError> {
+        let element_type = self.read_u8()?;
+        if element_type == 0x00 {
+            return Ok(None);
+        }
+        Ok(Some((element_type, self.read_cstring()?)))
+    }
+
+    /// Reads the value of an element whose header has just been read.
+    pub(crate) fn read_value(&mut self, element_type: u8) -> Result<Value, DeserializeError> {
+        let value = match element_type {
+            0x01 => Value::Double(f64::from_le_bytes(self.read_array()?)),
+            0x02 => self.read_string_value()?,
+            0x03 => Value::Document(self.decode_document()?),
+            0x04 => {
+                self.begin_document()?;
+                let mut array = Array::new();
+                while let Some((element_type, _)) = self.read_element_header()? {
+                    let value = self.read_value(element_type)?;
+                    if let Some(stats) = &mut self.stats {
+                        stats.record_element(&self.path, element_type, None, &value);
+                    }
+                    array.push(value);
+                }
+                self.finish_document()?;
+                Value::Array(array)
+            }
+            0x05 => {
+                let length = self.read_i32()?;
+                let length = self.check_length(length, 0)?;
+                let _subtype = self.read_u8()?;
+                Value::Binary(self.read_bytes(length)?)
+            }
+            0x07 => Value::ObjectId(ObjectId::from_bytes(self.read_array()?)),
+            0x08 => match self.read_u8()? {
+                0x00 => Value::Boolean(false),
+                0x01 => Value::Boolean(true),
+                other => {
+                    return Err(self.invalid(&format!("invalid boolean byte {:#04x}", other)))
+                }
+            },
+            0x09 => Value::UTCDateTime(self.read_i64()?),
+            0x0A => Value::Null,
+            0x0B => Value::RegularExpression {
+                pattern: self.read_cstring()?,
+                options: self.read_cstring()?,
+            },
+            0x0D => Value::JavaScriptCode(self.read_string()?),
+            0x0F => {
+                let start = self.position;
+                let length = self.read_i32()?;
+                let code = self.read_string()?;
+                let scope = self.decode_document()?;
+                if self.position - start != length as u64 {
+                    return Err(self.invalid("code with scope length mismatch"));
+                }
+                Value::JavaScriptCodeWithScope { code, scope }
+            }
+            0x10 => Value::Int32(self.read_i32()?),
+            0x11 => Value::Timestamp(self.read_i64()?),
+            0x12 => Value::Int64(self.read_i64()?),
+            0x13 => Value::UInt64(u64::from_le_bytes(self.read_array()?)),
+            0xFF => Value::MinKey,
+            0x7F => Value::MaxKey,
+            0x06 | 0x0C | 0x0E => return self.read_legacy_value(element_type),
+            other => return Err(DeserializeError::UnknownElementType(other)),
+        };
+        Ok(value)
+    }
+
+    /// Reads a deprecated value, mapped according to `DecoderOptions::legacy_types`.
+    fn read_legacy_value(&mut self, element_type: u8) -> Result<Value, DeserializeError> {
+        let mode = self.options.legacy_types;
+        if mode == LegacyTypes::Reject {
+            return Err(DeserializeError::NotSupported(format!(
+                "deprecated BSON type {:#04x}",
+                element_type
+            )));
+        }
+
+        let convert = mode == LegacyTypes::Convert;
+        let value = match element_type {
+            0x06 if convert => Value::Null,
+            0x06 => Value::Undefined,
+            0x0C => {
+                let (namespace, id) = self.read_db_pointer()?;
+                if convert {
+                    let mut pointer = Document::new_with_capacity(2);
+                    pointer.insert("collection", namespace);
+                    pointer.insert("id", id);
+                    Value::Document(pointer)
+                } else {
+                    Value::DbPointer { namespace, id }
+                }
+            }
+            _ if convert => Value::String(self.read_string()?),
+            _ => Value::Symbol(self.read_string()?),
+        };
+        Ok(value)
+    }
+
+    /// Reads a DBPointer value: a namespace string followed by an ObjectId.
+    pub(crate) fn read_db_pointer(&mut self) -> Result<(String, ObjectId), DeserializeError> {
+        let namespace = self.read_string()?;
+        Ok((namespace, ObjectId::from_bytes(self.read_array()?)))
+    }
+
+    /// Reads a length-prefixed, null-terminated UTF-8 string.
+    pub(crate) fn read_string(&mut self) -> Result<String, DeserializeError> {
+        Ok(string_from_utf8(self.read_string_bytes()?)?)
+    }
+
+    /// Reads a string value, handling invalid UTF-8 according to the options.
+    fn read_string_value(&mut self) -> Result<Value, DeserializeError> {
+        let offset = self.position;
+        let error = match string_from_utf8(self.read_string_bytes()?) {
+            Ok(value) => return Ok(Value::String(value)),
+            Err(error) => error,
+        };
+
+        let value = match self.options.utf8 {
+            Utf8Mode::Strict => return Err(error.into()),
+            Utf8Mode::Lossy => Value::String(String::from_utf8_lossy(error.as_bytes()).into_owned()),
+            Utf8Mode::Preserve => Value::Binary(error.into_bytes()),
+        };
+        self.warnings.push(DecodeWarning::InvalidUtf8 { offset });
+        Ok(value)
+    }
+
+    fn read_string_bytes(&mut self) -> Result<Vec<u8>, DeserializeError> {
+        let length = self.read_i32()?;
+        let length = self.check_length(length, 1)?;
+        let mut bytes = self.read_bytes(length)?;
+        if bytes.pop() != Some(0) {
+            return Err(self.invalid("string is missing its null terminator"));
+        }
+        Ok(bytes)
+    }
+
+    /// Reads a field's value while keeping the stats path up to date.
+    fn read_value_with_stats(
+        &mut self,
+        element_type: u8,
+        key: &str,
+        start: u64,
+    ) -> Result<Value, DeserializeError> {
+        self.path.push(key.to_string());
+        let value = self.read_value(element_type)?;
+        if let Some(stats) = &mut self.stats {
+            stats.record_element(&self.path, element_type, Some(self.position - start), &value);
+        }
+        self.path.pop();
+        Ok(value)
+    }
+
+    fn read_document_body(&mut self) -> Result<Document, DeserializeError> {
+        let mut document = Document::new();
+        self.read_document_body_into(&mut document)?;
+        Ok(document)
+    }
+
+    /// Reads the elements of the open document into `document`, which must be empty.
+    fn read_document_body_into(&mut self, document: &mut Document) -> Result<(), DeserializeError> {
+        let mut collected = HashSet::new();
+        loop {
+            let start = self.position;
+            let Some((element_type, key)) = self.read_element_header()? else {
+                break;
+            };
+            let value = if self.stats.is_some() {
+                self.read_value_with_stats(element_type, &key, start)?
+            } else {
+                self.read_value(element_type)?
+            };
+            let key = self.field_name(key);
+            if !document.contains_key(&key) {
+                document.insert(key, value);
+                continue;
+            }
+
+            match self.options.duplicate_keys {
+                DuplicateKeyPolicy::Error => {
+                    return Err(DeserializeError::DuplicateKey(key.to_string()))
+                }
+                DuplicateKeyPolicy::FirstWins => {}
+                DuplicateKeyPolicy::LastWins => {
+                    document.insert(key, value);
+                }
+                DuplicateKeyPolicy::CollectIntoArray => {
+                    let existing = document.get_mut(&key).unwrap();
+                    if !collected.contains(&key) {
+                        let first = std::mem::replace(existing, Value::Array(Array::new()));
+                        if let Value::Array(values) = existing {
+                            values.push(first);
+                        }
+                        collected.insert(key);
+                    }
+                    if let Value::Array(values) = existing {
+                        values.push(value);
+                    }
+                }
+            }
+        }
+        self.finish_document()?;
+        Ok(())
+    }
+
+    /// Returns the field name `key`, shared with the earlier ones spelled
+    /// the same if interning, and keeps the buffer it was read into.
+    fn field_name(&mut self, key: String) -> FieldName {
+        let name = match &mut self.interner {
+            Some(interner) => interner.intern(&key),
+            None => FieldName::from(key.as_str()),
+        };
+        self.recycle_buffer(key.into_bytes());
+        name
+    }
+
+    /* Buffer Reuse */
+
+    /// Empties `document`, keeping the buffers of its values for later reads.
+    fn recycle_document(&mut self, document: &mut Document) {
+        for (_, value) in document.drain() {
+            self.recycle_value(value);
+        }
+    }
+
+    fn recycle_value(&mut self, value: Value) {
+        match value {
+            Value::String(value) | Value::JavaScriptCode(value) | Value::Symbol(value) => {
+                self.recycle_buffer(value.into_bytes())
+            }
+            Value::Binary(bytes) => self.recycle_buffer(bytes),
+            Value::RegularExpression { pattern, options } => {
+                self.recycle_buffer(pattern.into_bytes());
+                self.recycle_buffer(options.into_bytes());
+            }
+            Value::JavaScriptCodeWithScope { code, mut scope } => {
+                self.recycle_buffer(code.into_bytes());
+                self.recycle_document(&mut scope);
+            }
+            Value::Document(mut document) => self.recycle_document(&mut document),
+            Value::Array(array) => {
+                let values: Vec<Value> = array.into();
+                for value in values {
+                    self.recycle_value(value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn recycle_buffer(&mut self, buffer: Vec<u8>) {
+        self.spare.put(buffer);
+    }
+
+    /* Primitive Reads */
+
+    fn invalid(&self, message: &str) -> DeserializeError {
+        DeserializeError::InvalidDocument(format!("{} at offset {}", message, self.position))
+    }
+
+    /// Validates a declared length against the bytes left in the innermost document.
+    fn check_length(&self, length: i32, min: i32) -> Result<usize, DeserializeError> {
+        if length < min {
+            return Err(DeserializeError::InvalidLength(length as i64));
+        }
+        if let Some(end) = self.document_ends.last() {
+            if self.position + length as u64 > *end {
+                return Err(self.invalid(&format!("length {} overruns the document", length)));
+            }
+        }
+        Ok(length as usize)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), DeserializeError> {
+        self.reader.read_exact(buf).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => DeserializeError::UnexpectedEof,
+            _ => e.into(),
+        })?;
+        self.position += buf.len() as u64;
+        Ok(())
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], DeserializeError> {
+        let mut array = [0; N];
+        self.read_exact(&mut array)?;
+        Ok(array)
+    }
+
+    fn read_bytes(&mut self, length: usize) -> Result<Vec<u8>, DeserializeError> {
+        let mut bytes = self.spare.take(length);
+        bytes.resize(length, 0);
+        self.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, DeserializeError> {
+        Ok(self.read_array::<1>()?[0])
+    }
+
+    fn read_i32(&mut self) -> Result<i32, DeserializeError> {
+        Ok(i32::from_le_bytes(self.read_array()?))
+    }
+
+    fn read_i64(&mut self) -> Result<i64, DeserializeError> {
+        Ok(i64::from_le_bytes(self.read_array()?))
+    }
+
+    fn read_cstring(&mut self) -> Result<String, DeserializeError> {
+        let mut bytes = self.spare.take(0);
+        loop {
+            match self.read_u8()? {
+                0 => break,
+                byte => bytes.push(byte),
+            }
+        }
+        Ok(string_from_utf8(bytes)?)
+    }
+}
+
+/// Buffers a decoder keeps for reuse, and the pool it shares them with.
+#[derive(Default)]
+struct SpareBuffers {
+    buffers: Vec<Vec<u8>>,
+    pool: Option<BufferPool>,
+}
+
+impl SpareBuffers {
+    /// Takes a kept buffer, or one of at least `capacity` bytes from the
+    /// pool if it is worth pooling.
+    fn take(&mut self, capacity: usize) -> Vec<u8> {
+        if let Some(buffer) = self.buffers.pop() {
+            return buffer;
+        }
+        match &self.pool {
+            Some(pool) if capacity >= MIN_POOLED_CAPACITY => pool.take(capacity),
+            _ => Vec::new(),
+        }
+    }
+
+    fn put(&mut self, mut buffer: Vec<u8>) {
+        if buffer.capacity() == 0 {
+            return;
+        }
+        if self.buffers.len() < MAX_SPARE_BUFFERS {
+            buffer.clear();
+            self.buffers.push(buffer);
+        } else if let Some(pool) = &self.pool {
+            pool.put(buffer);
+        }
+    }
+}
+
+impl Drop for SpareBuffers {
+    fn drop(&mut self) {
+        if let Some(pool) = &self.pool {
+            self.buffers
+                .drain(..)
+                .filter(|buffer| buffer.capacity() >= MIN_POOLED_CAPACITY)
+                .for_each(|buffer| pool.put(buffer));
+        }
+    }
+}
+
+/// Decodes a single BSON document from `bytes`.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb_data_encoding::{from_bytes, Value};
+/// let bytes = [12, 0, 0, 0, 0x10, b'a', 0, 1, 0, 0, 0, 0];
+/// let document = from_bytes(&bytes).unwrap();
+/// assert_eq!(document.get("a"), Some(&Value::Int32(1)));
+/// ```
+///
+/// # Errors
+///
+/// Returns an error if the bytes are not exactly one well-formed document.
+pub fn from_bytes(bytes: &[u8]) -> Result<Document, DeserializeError> {
+    from_bytes_with_options(bytes, &DecoderOptions::default())
+}
+
+/// Decodes a single BSON document from `bytes` using `options`.
+///
+/// A leading `EncodingHeader` is detected and checked against `options`.
+///
+/// # Errors
+///
+/// Returns an error if the bytes are not exactly one well-formed document,
+/// if the header cannot be decoded by this version, or if the document
+/// violates `options`.
+pub fn from_bytes_with_options(
+    bytes: &[u8],
+    options: &DecoderOptions,
+) -> Result<Document, DeserializeError> {
+    let (_, bytes) = split_header(bytes, options)?;
+    let mut decoder = Decoder::with_options(bytes, options.clone());
+    let document = decoder.decode_document()?;
+    if decoder.position() != bytes.len() as u64 {
+        return Err(DeserializeError::InvalidDocument(format!(
+            "{} trailing bytes after document",
+            bytes.len() as u64 - decoder.position()
+        )));
+    }
+    Ok(document)
+}
+
+/// Decodes a single BSON document from `bytes` into `document`, replacing
+/// its contents and reusing its allocations. See `Decoder::decode_into`.
+/// A leading `EncodingHeader` is detected like in `from_bytes`.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb_data_encoding::{decode_into, Document, Value};
+/// let mut document = Document::new();
+/// for n in 1..=3 {
+///     let bytes = [12, 0, 0, 0, 0x10, b'a', 0, n, 0, 0, 0, 0];
+///     decode_into(&bytes, &mut document).unwrap();
+///     assert_eq!(document.get("a"), Some(&Value::Int32(n as i32)));
+/// }
+/// ```
+///
+/// # Errors
+///
+/// Returns an error if the bytes are not exactly one well-formed document.
+pub fn decode_into(bytes: &[u8], document: &mut Document) -> Result<(), DeserializeError> {
+    let (_, bytes) = split_header(bytes, &DecoderOptions::default())?;
+    let mut decoder = Decoder::new(bytes);
+    decoder.decode_into(document)?;
+    if decoder.position() != bytes.len() as u64 {
+        return Err(DeserializeError::InvalidDocument(format!(
+            "{} trailing bytes after document",
+            bytes.len() as u64 - decoder.position()
+        )));
+    }
+    Ok(())
+}
+
+/// Decodes a single BSON document from `reader`.
+///
+/// # Errors
+///
+/// Returns an error if reading fails or the document is malformed.
+pub fn from_reader<R: Read>(reader: R) -> Result<Document, DeserializeError> {
+    Decoder::new(reader).decode_document()
+}
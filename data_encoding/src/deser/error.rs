@@ -0,0 +1,68 @@
+/// Deserialization Errors.
+
+use std::io;
+
+/// Represents errors that can occur during deserialization.
+#[derive(Debug, thiserror::Error)]
+pub enum DeserializeError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Unexpected end of input")]
+    UnexpectedEof,
+    #[error("Invalid document length: {0}")]
+    InvalidLength(i64),
+    #[error("Document length {length} exceeds maximum of {max}")]
+    DocumentTooLarge { length: usize, max: usize },
+    #[error("Invalid UTF-8 string: {0}")]
+    Utf8(#[from] std::string::FromUtf8Error),
+    #[error("Invalid BSON document: {0}")]
+    InvalidDocument(String),
+    #[error("Unknown element type {0:#04x}")]
+    UnknownElementType(u8),
+    #[error("Duplicate key '{0}'")]
+    DuplicateKey(String),
+    #[error("Invalid JSON at offset {offset}: {message}")]
+    Json { offset: usize, message: String },
+    #[error("Line {line}: {source}")]
+    Line {
+        line: usize,
+        #[source]
+        source: Box<DeserializeError>,
+    },
+    #[error("Unsupported encoding format version {0}")]
+    UnsupportedVersion(u8),
+    #[error("Unsupported required header flags {0:#04x}")]
+    UnsupportedFlags(u8),
+    #[error("Not Supported")]
+    NotSupported(String),
+}
+
+/// A problem the decoder recovered from instead of failing, as allowed by `DecoderOptions`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeWarning {
+    /// A string value at `offset` was not valid UTF-8.
+    InvalidUtf8 { offset: u64 },
+}
+
+pub type Result<T> = std::result::Result<T, DeserializeError>;
+
+/// Represents errors that can occur while transcoding between formats.
+#[derive(Debug, thiserror::Error)]
+pub enum TranscodeError {
+    #[error(transparent)]
+    Deserialize(#[from] DeserializeError),
+    #[error(transparent)]
+    Serialize(#[from] crate::ser::SerializeError),
+    #[error("Line {line}: {source}")]
+    Line {
+        line: usize,
+        #[source]
+        source: Box<TranscodeError>,
+    },
+}
+
+impl From<std::io::Error> for TranscodeError {
+    fn from(error: std::io::Error) -> Self {
+        TranscodeError::Serialize(error.into())
+    }
+}
@@ -0,0 +1,149 @@
+// src/deser/events.rs
+
+use super::error::DeserializeError;
+use super::MAX_NESTING_DEPTH;
+use crate::raw::{RawDocument, RawElement, RawIter};
+use crate::types::Value;
+
+/// An event produced by `BsonEvents`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BsonEvent<'a> {
+    /// Start of a document; the top-level document or the value of a field.
+    StartDocument,
+    /// End of the innermost open document.
+    EndDocument,
+    /// Start of an array.
+    StartArray,
+    /// End of the innermost open array.
+    EndArray,
+    /// Name of the next field in a document. Array elements have no `Field` event.
+    Field(&'a str),
+    /// Any value that is not a document or an array.
+    Scalar(Value),
+}
+
+/// Pull parser over an encoded BSON document.
+///
+/// Yields a flat stream of events instead of building a `Document`, e.g.
+/// `{"a": [1]}` produces `StartDocument, Field("a"), StartArray, Scalar(1),
+/// EndArray, EndDocument`. Field names borrow from the input.
+///
+/// After an error the iterator is exhausted.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb_data_encoding::{BsonEvent, BsonEvents, Value};
+/// let bytes = [12, 0, 0, 0, 0x10, b'a', 0, 1, 0, 0, 0, 0];
+/// let events: Vec<_> = BsonEvents::new(&bytes).collect::<Result<_, _>>().unwrap();
+/// assert_eq!(
+///     events,
+///     [
+///         BsonEvent::StartDocument,
+///         BsonEvent::Field("a"),
+///         BsonEvent::Scalar(Value::Int32(1)),
+///         BsonEvent::EndDocument,
+///     ]
+/// );
+/// ```
+pub struct BsonEvents<'a> {
+    root: Option<&'a [u8]>, // Input, until the first event is read
+    stack: Vec<Frame<'a>>,
+    pending: Option<RawElement<'a>>, // Value to emit after its `Field` event
+}
+
+/// An open document or array.
+struct Frame<'a> {
+    elements: RawIter<'a>,
+    is_array: bool,
+}
+
+impl<'a> BsonEvents<'a> {
+    /// Creates a pull parser over `bytes`, which must hold exactly one document.
+    pub fn new(bytes: &'a [u8]) -> Self {
+        BsonEvents {
+            root: Some(bytes),
+            stack: Vec::new(),
+            pending: None,
+        }
+    }
+
+    /// Returns the number of documents and arrays currently open.
+    pub fn depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    fn next_event(&mut self) -> Result<Option<BsonEvent<'a>>, DeserializeError> {
+        if let Some(bytes) = self.root.take() {
+            let document = RawDocument::from_bytes(bytes)?;
+            self.stack.push(Frame {
+                elements: document.iter(),
+                is_array: false,
+            });
+            return Ok(Some(BsonEvent::StartDocument));
+        }
+
+        if let Some(element) = self.pending.take() {
+            return self.value_event(element).map(Some);
+        }
+
+        let Some(frame) = self.stack.last_mut() else {
+            return Ok(None);
+        };
+        match frame.elements.next() {
+            Some(element) => {
+                let element = element?;
+                if frame.is_array {
+                    self.value_event(element).map(Some)
+                } else {
+                    self.pending = Some(element);
+                    Ok(Some(BsonEvent::Field(element.key())))
+                }
+            }
+            None => {
+                let frame = self.stack.pop().unwrap();
+                Ok(Some(if frame.is_array {
+                    BsonEvent::EndArray
+                } else {
+                    BsonEvent::EndDocument
+                }))
+            }
+        }
+    }
+
+    fn value_event(&mut self, element: RawElement<'a>) -> Result<BsonEvent<'a>, DeserializeError> {
+        let (elements, is_array) = match (element.as_document(), element.as_array()) {
+            (Some(document), _) => (document.iter(), false),
+            (_, Some(array)) => (array.iter(), true),
+            _ => return Ok(BsonEvent::Scalar(element.value()?)),
+        };
+
+        if self.stack.len() >= MAX_NESTING_DEPTH {
+            return Err(DeserializeError::InvalidDocument(
+                "maximum nesting depth exceeded".to_string(),
+            ));
+        }
+        self.stack.push(Frame { elements, is_array });
+        Ok(if is_array {
+            BsonEvent::StartArray
+        } else {
+            BsonEvent::StartDocument
+        })
+    }
+}
+
+impl<'a> Iterator for BsonEvents<'a> {
+    type Item = Result<BsonEvent<'a>, DeserializeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_event() {
+            Ok(event) => event.map(Ok),
+            Err(e) => {
+                self.root = None;
+                self.stack.clear();
+                self.pending = None;
+                Some(Err(e))
+            }
+        }
+    }
+}
@@ -0,0 +1,138 @@
+// src/deser/file_iter.rs
+
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use super::decoder::Decoder;
+use super::error::DeserializeError;
+use super::options::DecoderOptions;
+use crate::types::Document;
+
+/// Iterator over back-to-back BSON documents read from any `Read`, such as
+/// a backup file or an oplog segment.
+///
+/// `position` always reports the offset just past the last document that
+/// decoded successfully, so an interrupted or failed read can be resumed
+/// from there with `DocumentFileIterator::resume` or `open_at`.
+///
+/// Reads are not buffered; wrap unbuffered readers in a `BufReader`. After
+/// an error the iterator is finished.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb_data_encoding::{DocumentFileIterator, Document, to_bytes};
+/// let mut document = Document::new();
+/// document.insert("n", 1);
+/// let bytes = [to_bytes(&document).unwrap(), to_bytes(&document).unwrap()].concat();
+///
+/// let mut documents = DocumentFileIterator::new(&bytes[..]);
+/// assert_eq!(documents.next().unwrap().unwrap(), document);
+/// assert_eq!(documents.position(), bytes.len() as u64 / 2);
+/// ```
+pub struct DocumentFileIterator<R: Read> {
+    decoder: Decoder<R>,
+    base: u64,
+    position: u64,
+    documents_read: u64,
+    done: bool,
+}
+
+impl<R: Read> DocumentFileIterator<R> {
+    /// Creates an iterator reading documents from the start of `reader`.
+    pub fn new(reader: R) -> Self {
+        DocumentFileIterator::resume(reader, 0, DecoderOptions::default())
+    }
+
+    /// Creates an iterator for a reader already positioned at `offset`, so
+    /// that reported positions stay relative to the start of the file.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - The reader, positioned at the start of a document.
+    /// * `offset` - The offset `reader` is positioned at, usually a previously reported `position`.
+    /// * `options` - The options used to decode each document.
+    pub fn resume(reader: R, offset: u64, options: DecoderOptions) -> Self {
+        DocumentFileIterator {
+            decoder: Decoder::with_options(reader, options),
+            base: offset,
+            position: offset,
+            documents_read: 0,
+            done: false,
+        }
+    }
+
+    /// Returns the offset just past the last successfully decoded document.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Returns the number of documents decoded by this iterator.
+    pub fn documents_read(&self) -> u64 {
+        self.documents_read
+    }
+
+    /// Returns the underlying decoder, for its warnings and stats.
+    pub fn decoder(&self) -> &Decoder<R> {
+        &self.decoder
+    }
+
+    /// Consumes the iterator, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.decoder.into_inner()
+    }
+}
+
+impl DocumentFileIterator<BufReader<File>> {
+    /// Opens a file of concatenated documents for buffered reading.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be opened.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        DocumentFileIterator::open_at(path, 0)
+    }
+
+    /// Opens a file and seeks to `offset`, usually a `position` reported by
+    /// an earlier iterator over the same file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be opened or seeked.
+    pub fn open_at<P: AsRef<Path>>(path: P, offset: u64) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        Ok(DocumentFileIterator::resume(
+            BufReader::new(file),
+            offset,
+            DecoderOptions::default(),
+        ))
+    }
+}
+
+impl<R: Read> Iterator for DocumentFileIterator<R> {
+    type Item = Result<Document, DeserializeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.decoder.next_document() {
+            Ok(Some(document)) => {
+                self.position = self.base + self.decoder.position();
+                self.documents_read += 1;
+                Some(Ok(document))
+            }
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(error) => {
+                self.done = true;
+                Some(Err(error))
+            }
+        }
+    }
+}
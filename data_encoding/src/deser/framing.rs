@@ -0,0 +1,202 @@
+// src/deser/framing.rs
+
+use std::io::{self, Read};
+
+use super::error::DeserializeError;
+use crate::utils::buffer_pool::{BufferPool, PooledBuffer};
+
+/// Smallest possible encoded document: the 4-byte length prefix plus the trailing null.
+pub const MIN_DOCUMENT_LEN: usize = 5;
+
+/// Default upper bound for a single frame (16 MiB, the BSON maximum document size).
+pub const MAX_DOCUMENT_LEN: usize = 16 * 1024 * 1024;
+
+/// Size of the chunks `Framer::read_from` pulls from a reader.
+const READ_CHUNK_SIZE: usize = 8 * 1024;
+
+/// Returns the total length declared by the document starting at `bytes`.
+///
+/// Only the 4-byte little-endian length prefix is inspected; the rest of the
+/// document does not need to be present yet.
+///
+/// Returns `None` if fewer than four bytes are available or the prefix is negative.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb_data_encoding::peek_document_len;
+/// let bytes = [5, 0, 0, 0, 0];
+/// assert_eq!(peek_document_len(&bytes), Some(5));
+/// assert_eq!(peek_document_len(&bytes[..3]), None);
+/// ```
+pub fn peek_document_len(bytes: &[u8]) -> Option<usize> {
+    let prefix: [u8; 4] = bytes.get(..4)?.try_into().ok()?;
+    let length = i32::from_le_bytes(prefix);
+    if length < 0 {
+        return None;
+    }
+    Some(length as usize)
+}
+
+/// Splits a byte stream into complete, length-prefixed document frames.
+///
+/// Bytes are pushed in as they arrive (in whatever chunks the transport
+/// delivers) and whole documents are handed back one at a time, so callers
+/// never pass a partially-received frame to the decoder.
+///
+/// After `next_frame` returns an error the stream is considered out of sync
+/// and the framer should be discarded.
+#[derive(Debug, Clone)]
+pub struct Framer {
+    buffer: Vec<u8>,
+    start: usize,
+    max_document_len: usize,
+}
+
+impl Framer {
+    /// Creates a new `Framer` accepting documents up to `MAX_DOCUMENT_LEN` bytes.
+    pub fn new() -> Self {
+        Framer::with_max_document_len(MAX_DOCUMENT_LEN)
+    }
+
+    /// Creates a new `Framer` that rejects frames declaring more than `max` bytes.
+    pub fn with_max_document_len(max: usize) -> Self {
+        Framer {
+            buffer: Vec::new(),
+            start: 0,
+            max_document_len: max,
+        }
+    }
+
+    /// Appends received bytes to the internal buffer.
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.compact();
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Reads one chunk from `reader` into the internal buffer.
+    ///
+    /// Returns the number of bytes read; `0` means the reader hit end of stream.
+    pub fn read_from<R: Read>(&mut self, reader: &mut R) -> io::Result<usize> {
+        self.compact();
+        let old_len = self.buffer.len();
+        self.buffer.resize(old_len + READ_CHUNK_SIZE, 0);
+        match reader.read(&mut self.buffer[old_len..]) {
+            Ok(read) => {
+                self.buffer.truncate(old_len + read);
+                Ok(read)
+            }
+            Err(e) => {
+                self.buffer.truncate(old_len);
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns the length of the next frame if its prefix has been received.
+    pub fn peek_len(&self) -> Option<usize> {
+        peek_document_len(self.pending())
+    }
+
+    /// Removes and returns the next complete frame, if one is buffered.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the next frame declares an impossible length or
+    /// does not end with the document terminator.
+    pub fn next_frame(&mut self) -> Result<Option<Vec<u8>>, DeserializeError> {
+        let Some(length) = self.next_frame_len()? else {
+            return Ok(None);
+        };
+        let frame = self.pending()[..length].to_vec();
+        self.start += length;
+        Ok(Some(frame))
+    }
+
+    /// Removes and returns the next complete frame, if one is buffered, in
+    /// a buffer taken from `pool`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the next frame declares an impossible length or
+    /// does not end with the document terminator.
+    pub fn next_frame_in(
+        &mut self,
+        pool: &BufferPool,
+    ) -> Result<Option<PooledBuffer>, DeserializeError> {
+        let Some(length) = self.next_frame_len()? else {
+            return Ok(None);
+        };
+        let mut frame = pool.get(length);
+        frame.extend_from_slice(&self.pending()[..length]);
+        self.start += length;
+        Ok(Some(frame))
+    }
+
+    /// Returns the length of the next frame if it has been received whole,
+    /// after checking its length and terminator.
+    fn next_frame_len(&self) -> Result<Option<usize>, DeserializeError> {
+        let pending = self.pending();
+        if pending.len() < 4 {
+            return Ok(None);
+        }
+
+        let declared = i32::from_le_bytes([pending[0], pending[1], pending[2], pending[3]]);
+        if declared < MIN_DOCUMENT_LEN as i32 {
+            return Err(DeserializeError::InvalidLength(declared as i64));
+        }
+        let length = declared as usize;
+        if length > self.max_document_len {
+            return Err(DeserializeError::DocumentTooLarge {
+                length,
+                max: self.max_document_len,
+            });
+        }
+        if pending.len() < length {
+            return Ok(None);
+        }
+        if pending[length - 1] != 0 {
+            return Err(DeserializeError::InvalidDocument(
+                "frame is not terminated by a null byte".to_string(),
+            ));
+        }
+
+        Ok(Some(length))
+    }
+
+    /// Returns the number of buffered bytes not yet returned as frames.
+    pub fn buffered_len(&self) -> usize {
+        self.buffer.len() - self.start
+    }
+
+    /// Returns `true` if no bytes are waiting to be framed.
+    ///
+    /// A stream that ends while this is `false` was truncated mid-frame.
+    pub fn is_empty(&self) -> bool {
+        self.buffered_len() == 0
+    }
+
+    /// Discards all buffered bytes.
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+        self.start = 0;
+    }
+
+    fn pending(&self) -> &[u8] {
+        &self.buffer[self.start..]
+    }
+
+    /// Drops already-returned frames from the front of the buffer.
+    fn compact(&mut self) {
+        if self.start > 0 {
+            self.buffer.drain(..self.start);
+            self.start = 0;
+        }
+    }
+}
+
+impl Default for Framer {
+    fn default() -> Self {
+        Framer::new()
+    }
+}
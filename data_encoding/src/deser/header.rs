@@ -0,0 +1,190 @@
+// src/deser/header.rs
+
+use std::ops::BitOr;
+
+use super::error::DeserializeError;
+use super::options::{DecoderOptions, NewerVersionPolicy};
+
+/// Magic bytes opening an encoding header.
+///
+/// Read as a document length prefix these are negative, so a header can
+/// never be mistaken for the start of a plain document.
+pub const HEADER_MAGIC: [u8; 4] = [b'S', b'D', b'B', 0xFF];
+
+/// Length of an encoding header: magic, format version and flags.
+pub const HEADER_LEN: usize = 6;
+
+/// The format version written by this crate.
+pub const FORMAT_VERSION: u8 = 1;
+
+/// Flags describing how the document after an `EncodingHeader` is encoded.
+///
+/// The low four bits are required flags: a reader that does not understand
+/// one must refuse the document. The high four bits are advisory and may be
+/// ignored by readers that do not know them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct HeaderFlags(u8);
+
+impl HeaderFlags {
+    /// The document is compressed. Required.
+    pub const COMPRESSED: HeaderFlags = HeaderFlags(0x01);
+    /// Fields were written in canonical (sorted) order. Advisory.
+    pub const CANONICAL: HeaderFlags = HeaderFlags(0x10);
+
+    /// Mask of the flags readers must understand.
+    const REQUIRED_MASK: u8 = 0x0F;
+    /// Flags this version of the crate understands.
+    const KNOWN: u8 = HeaderFlags::COMPRESSED.0 | HeaderFlags::CANONICAL.0;
+
+    /// Returns an empty set of flags.
+    pub const fn empty() -> Self {
+        HeaderFlags(0)
+    }
+
+    /// Creates flags from their raw bits, including unknown ones.
+    pub const fn from_bits(bits: u8) -> Self {
+        HeaderFlags(bits)
+    }
+
+    /// Returns the raw bits.
+    pub const fn bits(&self) -> u8 {
+        self.0
+    }
+
+    /// Returns `true` if every flag in `other` is set.
+    pub const fn contains(&self, other: HeaderFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns the required flags this crate does not understand.
+    pub const fn unknown_required(&self) -> HeaderFlags {
+        HeaderFlags(self.0 & HeaderFlags::REQUIRED_MASK & !HeaderFlags::KNOWN)
+    }
+}
+
+impl BitOr for HeaderFlags {
+    type Output = HeaderFlags;
+
+    fn bitor(self, other: HeaderFlags) -> HeaderFlags {
+        HeaderFlags(self.0 | other.0)
+    }
+}
+
+/// An optional header written in front of an encoded document so the
+/// on-disk format can evolve without breaking existing data.
+///
+/// Decoding functions detect the header automatically; documents without
+/// one are read as plain BSON.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb_data_encoding::{from_bytes, to_bytes_with_header, Document, EncodingHeader, HeaderFlags};
+/// let header = EncodingHeader::new().with_flags(HeaderFlags::CANONICAL);
+/// let bytes = to_bytes_with_header(&Document::new(), &header).unwrap();
+///
+/// assert_eq!(EncodingHeader::detect(&bytes).unwrap(), Some(header));
+/// assert!(from_bytes(&bytes).unwrap().is_empty());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EncodingHeader {
+    version: u8,
+    flags: HeaderFlags,
+}
+
+impl EncodingHeader {
+    /// Creates a header for the current `FORMAT_VERSION` with no flags.
+    pub fn new() -> Self {
+        EncodingHeader {
+            version: FORMAT_VERSION,
+            flags: HeaderFlags::empty(),
+        }
+    }
+
+    /// Sets the flags.
+    pub fn with_flags(mut self, flags: HeaderFlags) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    /// Returns the format version.
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// Returns the flags.
+    pub fn flags(&self) -> HeaderFlags {
+        self.flags
+    }
+
+    /// Returns the encoded header.
+    pub fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let [m0, m1, m2, m3] = HEADER_MAGIC;
+        [m0, m1, m2, m3, self.version, self.flags.bits()]
+    }
+
+    /// Reads the header at the start of `bytes`, or returns `None` if the
+    /// bytes do not start with `HEADER_MAGIC`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `UnexpectedEof` if the magic is present but the header is cut short.
+    pub fn detect(bytes: &[u8]) -> Result<Option<EncodingHeader>, DeserializeError> {
+        if !bytes.starts_with(&HEADER_MAGIC) {
+            return Ok(None);
+        }
+        match bytes.get(4..HEADER_LEN) {
+            Some([version, flags]) => Ok(Some(EncodingHeader {
+                version: *version,
+                flags: HeaderFlags::from_bits(*flags),
+            })),
+            _ => Err(DeserializeError::UnexpectedEof),
+        }
+    }
+
+    /// Checks that a reader using `options` can decode the document after this header.
+    ///
+    /// # Errors
+    ///
+    /// Returns `UnsupportedVersion` for a newer format version unless the
+    /// options allow attempting it, `UnsupportedFlags` for unknown required
+    /// flags, and `NotSupported` for compressed documents.
+    pub fn check(&self, options: &DecoderOptions) -> Result<(), DeserializeError> {
+        if self.version == 0
+            || (self.version > FORMAT_VERSION
+                && options.newer_versions == NewerVersionPolicy::Reject)
+        {
+            return Err(DeserializeError::UnsupportedVersion(self.version));
+        }
+        let unknown = self.flags.unknown_required();
+        if unknown != HeaderFlags::empty() {
+            return Err(DeserializeError::UnsupportedFlags(unknown.bits()));
+        }
+        if self.flags.contains(HeaderFlags::COMPRESSED) {
+            return Err(DeserializeError::NotSupported(
+                "compressed documents".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Default for EncodingHeader {
+    fn default() -> Self {
+        EncodingHeader::new()
+    }
+}
+
+/// Detects and checks an encoding header, returning it and the document bytes after it.
+pub(crate) fn split_header<'a>(
+    bytes: &'a [u8],
+    options: &DecoderOptions,
+) -> Result<(Option<EncodingHeader>, &'a [u8]), DeserializeError> {
+    match EncodingHeader::detect(bytes)? {
+        Some(header) => {
+            header.check(options)?;
+            Ok((Some(header), &bytes[HEADER_LEN..]))
+        }
+        None => Ok((None, bytes)),
+    }
+}
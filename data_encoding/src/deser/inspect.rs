@@ -0,0 +1,516 @@
+// src/deser/inspect.rs
+
+use std::collections::HashMap;
+use std::io::{self, BufWriter, Read, Write};
+
+use super::error::{DeserializeError, TranscodeError};
+use super::framing::Framer;
+use super::projection::{decode_projected, Projection};
+use super::MAX_NESTING_DEPTH;
+use crate::raw::RawDocument;
+use crate::ser::{JsonSerializer, Serializer};
+use crate::types::Value;
+
+/// Bytes shown on each line of a hex dump.
+const HEX_BYTES_PER_LINE: usize = 16;
+
+/// Longest a value is shown in a hex dump annotation, in characters.
+const MAX_ANNOTATION_LEN: usize = 60;
+
+/// What `inspect` writes for a file of documents.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InspectMode {
+    /// Each document as Extended JSON, one a line unless pretty-printed.
+    #[default]
+    Json,
+    /// Element counts by type and encoded bytes by field path, totalled
+    /// over the whole file.
+    Stats,
+    /// Each document as an annotated hex dump, as `hex_dump` writes it,
+    /// with offsets from the start of the file.
+    HexDump,
+}
+
+/// Options for `inspect`.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb_data_encoding::{InspectMode, InspectOptions};
+/// let options = InspectOptions::new()
+///     .mode(InspectMode::Stats)
+///     .field("address.city");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InspectOptions {
+    pub(crate) mode: InspectMode,
+    pub(crate) fields: Vec<String>,
+    pub(crate) canonical: bool,
+    pub(crate) pretty: bool,
+}
+
+impl InspectOptions {
+    /// Creates the default options: every field, as relaxed JSON.
+    pub fn new() -> Self {
+        InspectOptions::default()
+    }
+
+    /// Sets what is written. Defaults to `Json`.
+    pub fn mode(mut self, mode: InspectMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Restricts the output to the dotted field path `path` and everything
+    /// below it; may be called more than once. As with `Projection`, a path
+    /// through an array applies to every embedded document in it.
+    pub fn field(mut self, path: impl Into<String>) -> Self {
+        self.fields.push(path.into());
+        self
+    }
+
+    /// Writes canonical rather than relaxed Extended JSON. Off by default.
+    pub fn canonical(mut self, canonical: bool) -> Self {
+        self.canonical = canonical;
+        self
+    }
+
+    /// Pretty-prints JSON over several lines. Off by default.
+    pub fn pretty(mut self, pretty: bool) -> Self {
+        self.pretty = pretty;
+        self
+    }
+}
+
+/// Reads back-to-back BSON documents from `reader`, such as a dump or an
+/// oplog segment, and writes them to `writer` as `options` has them.
+/// Returns the number of documents read.
+///
+/// Documents are framed one at a time, so a file of any size can be read.
+/// Output written before an error is kept, which places a corrupt document
+/// in the file. Both ends are buffered internally.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb_data_encoding::{inspect, InspectOptions};
+/// let bson = [12, 0, 0, 0, 0x10, b'a', 0, 1, 0, 0, 0, 0];
+/// let mut json = Vec::new();
+/// assert_eq!(inspect(&bson[..], &mut json, &InspectOptions::new()).unwrap(), 1);
+/// assert_eq!(json, b"{\"a\":1}\n");
+/// ```
+///
+/// # Errors
+///
+/// Returns an error if a document is malformed, the input ends part way
+/// through one, or reading or writing fails.
+pub fn inspect<R: Read, W: Write>(
+    mut reader: R,
+    writer: W,
+    options: &InspectOptions,
+) -> Result<u64, TranscodeError> {
+    let mut out = BufWriter::new(writer);
+    let projection = Projection::new(&options.fields);
+    let mut stats = InspectStats::default();
+    let mut framer = Framer::new();
+    let mut offset = 0;
+    let mut documents = 0;
+    loop {
+        let Some(frame) = framer.next_frame()? else {
+            if framer.read_from(&mut reader)? == 0 {
+                if !framer.is_empty() {
+                    return Err(DeserializeError::UnexpectedEof.into());
+                }
+                break;
+            }
+            continue;
+        };
+        let document = RawDocument::from_bytes(&frame)?;
+        match options.mode {
+            InspectMode::Json => {
+                let decoded = match projection.is_empty() {
+                    true => document.to_document()?,
+                    false => decode_projected(&frame, &projection)?,
+                };
+                let mut serializer = match options.canonical {
+                    true => JsonSerializer::canonical(&mut out, options.pretty),
+                    false => JsonSerializer::new(&mut out, options.pretty),
+                };
+                serializer.serialize_document(&decoded)?;
+                out.write_all(b"\n")?;
+            }
+            InspectMode::Stats => {
+                stats.tally(document, &options.fields, Place::top(&options.fields))?;
+            }
+            InspectMode::HexDump => {
+                if documents > 0 {
+                    writeln!(out)?;
+                }
+                let place = Place::top(&options.fields);
+                dump_document(&mut out, document, offset, &options.fields, place)?;
+            }
+        }
+        offset += frame.len() as u64;
+        documents += 1;
+    }
+    if options.mode == InspectMode::Stats {
+        stats.write(&mut out, documents, offset)?;
+    }
+    out.flush()?;
+    Ok(documents)
+}
+
+/// Returns an annotated hex dump of the document `bytes`: each line shows
+/// an offset, up to 16 bytes, and what they encode, indented by nesting.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb_data_encoding::hex_dump;
+/// let bson = [12, 0, 0, 0, 0x10, b'a', 0, 1, 0, 0, 0, 0];
+/// let dump = hex_dump(&bson).unwrap();
+/// let line = dump.lines().nth(1).unwrap();
+/// assert!(line.starts_with("00000004  10 61 00 "));
+/// assert!(line.ends_with("\"a\": int"));
+/// ```
+///
+/// # Errors
+///
+/// Returns an error if the document's framing is malformed. Values that do
+/// not decode are annotated as invalid instead.
+pub fn hex_dump(bytes: &[u8]) -> Result<String, DeserializeError> {
+    let mut out = Vec::new();
+    let document = RawDocument::from_bytes(bytes)?;
+    dump_document(&mut out, document, 0, &[], Place::top(&[]))?;
+    Ok(String::from_utf8(out).expect("hex dumps are UTF-8"))
+}
+
+/// Returns the name of a BSON element type, as `$type` queries use it.
+fn element_type_name(element_type: u8) -> &'static str {
+    match element_type {
+        0x01 => "double",
+        0x02 => "string",
+        0x03 => "document",
+        0x04 => "array",
+        0x05 => "binary",
+        0x06 => "undefined",
+        0x07 => "objectId",
+        0x08 => "bool",
+        0x09 => "date",
+        0x0A => "null",
+        0x0B => "regex",
+        0x0C => "dbPointer",
+        0x0D => "javascript",
+        0x0E => "symbol",
+        0x0F => "javascriptWithScope",
+        0x10 => "int",
+        0x11 => "timestamp",
+        0x12 => "long",
+        0x13 => "uint64",
+        0x7F => "maxKey",
+        0xFF => "minKey",
+        _ => "unknown",
+    }
+}
+
+/// How a field path relates to the fields being inspected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Selection {
+    /// The path is a selected field or lies below one.
+    Selected,
+    /// A selected field lies below the path.
+    Ancestor,
+    /// Neither.
+    Excluded,
+}
+
+fn selection(fields: &[String], path: &str) -> Selection {
+    let below = |outer: &str, inner: &str| {
+        inner.len() > outer.len()
+            && inner.starts_with(outer)
+            && inner.as_bytes()[outer.len()] == b'.'
+    };
+    if fields
+        .iter()
+        .any(|field| field == path || below(field, path))
+    {
+        Selection::Selected
+    } else if fields.iter().any(|field| below(path, field)) {
+        Selection::Ancestor
+    } else {
+        Selection::Excluded
+    }
+}
+
+/// Where a document being walked sits within its top-level document.
+#[derive(Debug, Clone, Copy)]
+struct Place<'a> {
+    /// The document's dotted path, empty at the top level.
+    path: &'a str,
+    /// Whether the whole document is selected, not just fields below it.
+    selected: bool,
+    /// Whether the document is an array, whose items share its path.
+    in_array: bool,
+    depth: usize,
+}
+
+impl Place<'_> {
+    fn top(fields: &[String]) -> Self {
+        Place {
+            path: "",
+            selected: fields.is_empty(),
+            in_array: false,
+            depth: 0,
+        }
+    }
+
+    /// Returns the path of `key` within this document.
+    fn child_path(&self, key: &str) -> String {
+        match (self.in_array, self.path.is_empty()) {
+            (true, _) => self.path.to_string(),
+            (false, true) => key.to_string(),
+            (false, false) => format!("{}.{}", self.path, key),
+        }
+    }
+
+    /// Returns the place of a document at `path` inside this one.
+    fn child<'b>(&self, path: &'b str, selected: bool, in_array: bool) -> Place<'b> {
+        Place {
+            path,
+            selected,
+            in_array,
+            depth: self.depth + 1,
+        }
+    }
+}
+
+fn check_depth(depth: usize) -> Result<(), DeserializeError> {
+    match depth >= MAX_NESTING_DEPTH {
+        true => Err(DeserializeError::InvalidDocument(
+            "maximum nesting depth exceeded".to_string(),
+        )),
+        false => Ok(()),
+    }
+}
+
+/// Totals gathered over a file in `InspectMode::Stats`.
+#[derive(Debug, Default)]
+struct InspectStats {
+    types: HashMap<u8, u64>,
+    paths: HashMap<String, (u64, u64)>,
+}
+
+impl InspectStats {
+    /// Counts the selected elements of `document`. Array items are counted
+    /// by type only, their bytes being part of their array's.
+    fn tally(
+        &mut self,
+        document: RawDocument,
+        fields: &[String],
+        place: Place,
+    ) -> Result<(), DeserializeError> {
+        check_depth(place.depth)?;
+        for element in document.iter() {
+            let element = element?;
+            let path = place.child_path(element.key());
+            let selected = place.selected
+                || match selection(fields, &path) {
+                    Selection::Selected => true,
+                    Selection::Ancestor => false,
+                    Selection::Excluded => continue,
+                };
+            if selected {
+                *self.types.entry(element.element_type()).or_default() += 1;
+                if !place.in_array {
+                    let encoded_len = 2 + element.key().len() + element.value_bytes().len();
+                    let (count, bytes) = self.paths.entry(path.clone()).or_default();
+                    *count += 1;
+                    *bytes += encoded_len as u64;
+                }
+            }
+            if let Some(embedded) = element.as_document() {
+                self.tally(embedded, fields, place.child(&path, selected, false))?;
+            } else if let Some(array) = element.as_array() {
+                self.tally(
+                    array.as_document(),
+                    fields,
+                    place.child(&path, selected, true),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the totals: types by count, then paths by bytes, the largest
+    /// first.
+    fn write(&self, out: &mut impl Write, documents: u64, bytes: u64) -> io::Result<()> {
+        writeln!(out, "documents: {}", documents)?;
+        writeln!(out, "bytes: {}", bytes)?;
+
+        let mut types: Vec<(&'static str, u64)> = self
+            .types
+            .iter()
+            .map(|(element_type, count)| (element_type_name(*element_type), *count))
+            .collect();
+        types.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        let width = types
+            .iter()
+            .map(|(name, _)| name.len())
+            .chain([4])
+            .max()
+            .unwrap_or(0);
+        writeln!(out)?;
+        writeln!(out, "{:<width$}  {:>10}", "type", "elements", width = width)?;
+        for (name, count) in types {
+            writeln!(out, "{:<width$}  {:>10}", name, count, width = width)?;
+        }
+
+        let mut paths: Vec<(&str, u64, u64)> = self
+            .paths
+            .iter()
+            .map(|(path, (count, bytes))| (path.as_str(), *count, *bytes))
+            .collect();
+        paths.sort_unstable_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(b.0)));
+        let width = paths
+            .iter()
+            .map(|(path, _, _)| path.len())
+            .chain([4])
+            .max()
+            .unwrap_or(0);
+        writeln!(out)?;
+        writeln!(
+            out,
+            "{:<width$}  {:>10}  {:>12}",
+            "path",
+            "elements",
+            "bytes",
+            width = width
+        )?;
+        for (path, count, bytes) in paths {
+            writeln!(
+                out,
+                "{:<width$}  {:>10}  {:>12}",
+                path,
+                count,
+                bytes,
+                width = width
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes a hex dump of `document`, which starts `base` bytes into the
+/// input, showing only the selected elements and their ancestors.
+fn dump_document(
+    out: &mut impl Write,
+    document: RawDocument,
+    base: u64,
+    fields: &[String],
+    place: Place,
+) -> Result<(), DeserializeError> {
+    check_depth(place.depth)?;
+    let bytes = document.as_bytes();
+    let kind = match place.in_array {
+        true => "array",
+        false => "document",
+    };
+    let length = format!("{}, {} bytes", kind, bytes.len());
+    write_hex_line(out, base, &bytes[..4], place.depth, &length)?;
+    for element in document.iter() {
+        let element = element?;
+        let path = place.child_path(element.key());
+        let nested = element.as_document().is_some() || element.as_array().is_some();
+        let selected = place.selected
+            || match selection(fields, &path) {
+                Selection::Selected => true,
+                // A path below a scalar selects nothing
+                Selection::Ancestor if nested => false,
+                Selection::Ancestor | Selection::Excluded => continue,
+            };
+
+        let start = element.offset();
+        let value_start = start + 2 + element.key().len();
+        let header = format!(
+            "{:?}: {}",
+            element.key(),
+            element_type_name(element.element_type())
+        );
+        let header_bytes = &bytes[start..value_start];
+        write_hex_line(out, base + start as u64, header_bytes, place.depth, &header)?;
+
+        let value_base = base + value_start as u64;
+        if let Some(embedded) = element.as_document() {
+            dump_document(
+                out,
+                embedded,
+                value_base,
+                fields,
+                place.child(&path, selected, false),
+            )?;
+        } else if let Some(array) = element.as_array() {
+            let items = array.as_document();
+            dump_document(
+                out,
+                items,
+                value_base,
+                fields,
+                place.child(&path, selected, true),
+            )?;
+        } else {
+            let value = match element.value() {
+                Ok(Value::String(text)) => format!("{:?}", text),
+                Ok(value) => value.to_string(),
+                Err(e) => format!("invalid: {}", e),
+            };
+            write_hex_line(
+                out,
+                value_base,
+                element.value_bytes(),
+                place.depth + 1,
+                &value,
+            )?;
+        }
+    }
+    let end = bytes.len() - 1;
+    write_hex_line(out, base + end as u64, &bytes[end..], place.depth, "end")?;
+    Ok(())
+}
+
+/// Writes `bytes` as hex, `HEX_BYTES_PER_LINE` to a line, annotating the
+/// first line with `note` indented by `depth`.
+fn write_hex_line(
+    out: &mut impl Write,
+    offset: u64,
+    bytes: &[u8],
+    depth: usize,
+    note: &str,
+) -> io::Result<()> {
+    let note = match note.chars().count() > MAX_ANNOTATION_LEN {
+        true => format!(
+            "{}…",
+            note.chars()
+                .take(MAX_ANNOTATION_LEN - 1)
+                .collect::<String>()
+        ),
+        false => note.to_string(),
+    };
+    let width = HEX_BYTES_PER_LINE * 3 - 1;
+    for (i, chunk) in bytes.chunks(HEX_BYTES_PER_LINE).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let line = format!(
+            "{:08x}  {:<width$}  {:indent$}{}",
+            offset + (i * HEX_BYTES_PER_LINE) as u64,
+            hex.join(" "),
+            "",
+            match i {
+                0 => note.as_str(),
+                _ => "",
+            },
+            width = width,
+            indent = depth * 2,
+        );
+        writeln!(out, "{}", line.trim_end())?;
+    }
+    Ok(())
+}
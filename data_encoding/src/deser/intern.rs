@@ -0,0 +1,59 @@
+// src/deser/intern.rs
+
+use std::collections::HashSet;
+
+use crate::types::FieldName;
+
+/// Upper bound on the distinct names a `KeyInterner` keeps, so that input
+/// with endless unique field names cannot grow it without limit.
+const MAX_INTERNED_KEYS: usize = 4096;
+
+/// Longest field name interned, in bytes; longer names are rarely repeated
+/// and are decoded as usual.
+const MAX_INTERNED_KEY_LEN: usize = 64;
+
+/// How much interning field names saved a decoder, returned by
+/// `Decoder::intern_stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InternStats {
+    /// Field names found in the table, which cost no allocation.
+    pub hits: u64,
+    /// Field names not found, which were allocated as usual.
+    pub misses: u64,
+    /// Bytes of names that did not need allocating, counting only the
+    /// text and not the allocator's overhead per allocation.
+    pub bytes_saved: u64,
+    /// Distinct names in the table.
+    pub interned: usize,
+}
+
+/// A table of the field names a decoder has read, handing out a shared
+/// `FieldName` for each repeat of one instead of allocating it again.
+#[derive(Debug, Default)]
+pub(crate) struct KeyInterner {
+    names: HashSet<FieldName>,
+    stats: InternStats,
+}
+
+impl KeyInterner {
+    /// Returns `key` as a field name, shared with the earlier ones spelled
+    /// the same.
+    pub(crate) fn intern(&mut self, key: &str) -> FieldName {
+        if let Some(name) = self.names.get(key) {
+            self.stats.hits += 1;
+            self.stats.bytes_saved += key.len() as u64;
+            return name.clone();
+        }
+        self.stats.misses += 1;
+        let name = FieldName::from(key);
+        if key.len() <= MAX_INTERNED_KEY_LEN && self.names.len() < MAX_INTERNED_KEYS {
+            self.names.insert(name.clone());
+            self.stats.interned = self.names.len();
+        }
+        name
+    }
+
+    pub(crate) fn stats(&self) -> InternStats {
+        self.stats
+    }
+}
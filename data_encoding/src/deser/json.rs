@@ -0,0 +1,582 @@
+// src/deser/json.rs
+
+use std::io::BufRead;
+
+use super::error::DeserializeError;
+use super::MAX_NESTING_DEPTH;
+use crate::types::{Array, Document, ObjectId, UTCDateTime, Value};
+use crate::utils::{base64, hex};
+
+/// Parses a JSON object into a `Document`.
+///
+/// Extended JSON type wrappers (canonical and relaxed) are recognized and
+/// mapped to the corresponding BSON values, e.g. `{"$oid": "..."}` becomes an
+/// ObjectId and `{"$date": "2024-01-31T00:00:00Z"}` a `UTCDateTime`. Objects
+/// with other `$`-prefixed keys (such as query operators) stay documents.
+///
+/// Extended JSON dates carry milliseconds; they are truncated to seconds.
+///
+/// # Errors
+///
+/// Returns an error if the input is not a single JSON object, or if a type
+/// wrapper is malformed.
+pub fn from_json_str(text: &str) -> Result<Document, DeserializeError> {
+    let mut parser = JsonParser::new(text);
+    parser.skip_whitespace();
+    if parser.peek() != Some(b'{') {
+        return Err(parser.error("expected a JSON object"));
+    }
+
+    let document = match parser.parse_value()? {
+        Value::Document(document) => document,
+        other => {
+            return Err(DeserializeError::InvalidDocument(format!(
+                "top-level JSON object is a type wrapper for {}",
+                other
+            )))
+        }
+    };
+
+    parser.skip_whitespace();
+    if parser.position != parser.bytes.len() {
+        return Err(parser.error("trailing characters after JSON object"));
+    }
+    Ok(document)
+}
+
+/// Parses any single JSON value, such as an array or a number, as
+/// `from_json_str` parses objects, Extended JSON type wrappers included.
+///
+/// # Errors
+///
+/// Returns an error if the input is not a single JSON value, or if a type
+/// wrapper is malformed.
+pub fn from_json_value_str(text: &str) -> Result<Value, DeserializeError> {
+    let mut parser = JsonParser::new(text);
+    parser.skip_whitespace();
+    let value = parser.parse_value()?;
+    parser.skip_whitespace();
+    if parser.position != parser.bytes.len() {
+        return Err(parser.error("trailing characters after JSON value"));
+    }
+    Ok(value)
+}
+
+/// Reads newline-delimited JSON (NDJSON / JSON Lines), yielding one `Document` per line.
+///
+/// Blank lines are skipped. Errors are reported with the 1-based line number
+/// they occurred on, and do not stop iteration: callers may skip a bad line
+/// and continue, or stop at the first error.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb_data_encoding::NdjsonReader;
+/// let input = "{\"a\": 1}\n{\"a\": 2}\n";
+/// let documents: Vec<_> = NdjsonReader::new(input.as_bytes()).collect();
+/// assert_eq!(documents.len(), 2);
+/// ```
+pub struct NdjsonReader<R: BufRead> {
+    reader: R,
+    line: String,
+    line_number: usize,
+}
+
+impl<R: BufRead> NdjsonReader<R> {
+    /// Creates a new reader over `reader`.
+    pub fn new(reader: R) -> Self {
+        NdjsonReader {
+            reader,
+            line: String::new(),
+            line_number: 0,
+        }
+    }
+
+    /// Returns the number of the last line read (1-based, `0` before the first read).
+    pub fn line_number(&self) -> usize {
+        self.line_number
+    }
+
+    /// Consumes the reader, returning the underlying `BufRead`.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: BufRead> Iterator for NdjsonReader<R> {
+    type Item = Result<Document, DeserializeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.line.clear();
+            let read = match self.reader.read_line(&mut self.line) {
+                Ok(read) => read,
+                Err(e) => {
+                    self.line_number += 1;
+                    return Some(Err(DeserializeError::Line {
+                        line: self.line_number,
+                        source: Box::new(e.into()),
+                    }));
+                }
+            };
+            if read == 0 {
+                return None;
+            }
+            self.line_number += 1;
+
+            let mut text = self.line.trim();
+            if self.line_number == 1 {
+                text = text.trim_start_matches('\u{FEFF}');
+            }
+            if text.is_empty() {
+                continue;
+            }
+
+            let line = self.line_number;
+            return Some(from_json_str(text).map_err(|e| DeserializeError::Line {
+                line,
+                source: Box::new(e),
+            }));
+        }
+    }
+}
+
+pub(crate) struct JsonParser<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    depth: usize,
+}
+
+impl<'a> JsonParser<'a> {
+    pub(crate) fn new(text: &'a str) -> Self {
+        JsonParser {
+            bytes: text.as_bytes(),
+            position: 0,
+            depth: 0,
+        }
+    }
+
+    pub(crate) fn error(&self, message: &str) -> DeserializeError {
+        DeserializeError::Json {
+            offset: self.position,
+            message: message.to_string(),
+        }
+    }
+
+    pub(crate) fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.position).copied()
+    }
+
+    pub(crate) fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.position += 1;
+        }
+    }
+
+    pub(crate) fn expect(&mut self, byte: u8) -> Result<(), DeserializeError> {
+        self.skip_whitespace();
+        if self.peek() != Some(byte) {
+            return Err(self.error(&format!("expected '{}'", byte as char)));
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    /// Consumes `byte` if it is the next non-whitespace byte.
+    pub(crate) fn eat(&mut self, byte: u8) -> bool {
+        self.skip_whitespace();
+        if self.peek() == Some(byte) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns `true` if the next object's first key starts with `$`, i.e.
+    /// the object may be an Extended JSON type wrapper.
+    pub(crate) fn at_operator_object(&self) -> bool {
+        let rest = &self.bytes[self.position..];
+        let Some(rest) = rest.strip_prefix(b"{") else {
+            return false;
+        };
+        let start = rest
+            .iter()
+            .position(|byte| !matches!(byte, b' ' | b'\t' | b'\n' | b'\r'))
+            .unwrap_or(rest.len());
+        rest[start..].starts_with(b"\"$")
+    }
+
+    fn consume_literal(&mut self, literal: &str) -> bool {
+        if self.bytes[self.position..].starts_with(literal.as_bytes()) {
+            self.position += literal.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    pub(crate) fn parse_value(&mut self) -> Result<Value, DeserializeError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'{') => self.nested(|parser| parser.parse_object()),
+            Some(b'[') => self.nested(|parser| parser.parse_array()),
+            Some(b'"') => Ok(Value::String(self.parse_string()?)),
+            Some(b'-' | b'0'..=b'9') => self.parse_number(),
+            Some(b't') if self.consume_literal("true") => Ok(Value::Boolean(true)),
+            Some(b'f') if self.consume_literal("false") => Ok(Value::Boolean(false)),
+            Some(b'n') if self.consume_literal("null") => Ok(Value::Null),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(DeserializeError::UnexpectedEof),
+        }
+    }
+
+    fn nested<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<T, DeserializeError>,
+    ) -> Result<T, DeserializeError> {
+        if self.depth >= MAX_NESTING_DEPTH {
+            return Err(self.error("maximum nesting depth exceeded"));
+        }
+        self.depth += 1;
+        let result = f(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn parse_object(&mut self) -> Result<Value, DeserializeError> {
+        let start = self.position;
+        self.expect(b'{')?;
+        let mut document = Document::new();
+
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.position += 1;
+            return Ok(Value::Document(document));
+        }
+
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.error("expected a string key"));
+            }
+            let key = self.parse_string()?;
+            self.expect(b':')?;
+            let value = self.parse_value()?;
+            document.insert(key, value);
+
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b'}') => {
+                    self.position += 1;
+                    break;
+                }
+                Some(_) => return Err(self.error("expected ',' or '}'")),
+                None => return Err(DeserializeError::UnexpectedEof),
+            }
+        }
+
+        extended_json_value(document).map_err(|message| DeserializeError::Json {
+            offset: start,
+            message,
+        })
+    }
+
+    fn parse_array(&mut self) -> Result<Value, DeserializeError> {
+        self.expect(b'[')?;
+        let mut array = Array::new();
+
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.position += 1;
+            return Ok(Value::Array(array));
+        }
+
+        loop {
+            array.push(self.parse_value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b']') => {
+                    self.position += 1;
+                    return Ok(Value::Array(array));
+                }
+                Some(_) => return Err(self.error("expected ',' or ']'")),
+                None => return Err(DeserializeError::UnexpectedEof),
+            }
+        }
+    }
+
+    pub(crate) fn parse_string(&mut self) -> Result<String, DeserializeError> {
+        self.position += 1; // opening quote
+        let mut output = String::new();
+        loop {
+            // Copy the run of plain characters in one go
+            let run = self.bytes[self.position..]
+                .iter()
+                .position(|b| *b == b'"' || *b == b'\\' || *b < 0x20)
+                .ok_or(DeserializeError::UnexpectedEof)?;
+            // Input came from a &str and the run ends on an ASCII byte, so it is valid UTF-8.
+            output.push_str(
+                std::str::from_utf8(&self.bytes[self.position..self.position + run])
+                    .map_err(|_| self.error("invalid UTF-8"))?,
+            );
+            self.position += run;
+
+            match self.bytes[self.position] {
+                b'"' => {
+                    self.position += 1;
+                    return Ok(output);
+                }
+                b'\\' => {
+                    self.position += 1;
+                    let escape = self.peek().ok_or(DeserializeError::UnexpectedEof)?;
+                    self.position += 1;
+                    match escape {
+                        b'"' => output.push('"'),
+                        b'\\' => output.push('\\'),
+                        b'/' => output.push('/'),
+                        b'b' => output.push('\u{8}'),
+                        b'f' => output.push('\u{c}'),
+                        b'n' => output.push('\n'),
+                        b'r' => output.push('\r'),
+                        b't' => output.push('\t'),
+                        b'u' => output.push(self.parse_unicode_escape()?),
+                        _ => return Err(self.error("invalid escape sequence")),
+                    }
+                }
+                _ => return Err(self.error("unescaped control character in string")),
+            }
+        }
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, DeserializeError> {
+        let digits = self
+            .bytes
+            .get(self.position..self.position + 4)
+            .ok_or(DeserializeError::UnexpectedEof)?;
+        let text = std::str::from_utf8(digits).map_err(|_| self.error("invalid \\u escape"))?;
+        let value = u32::from_str_radix(text, 16).map_err(|_| self.error("invalid \\u escape"))?;
+        self.position += 4;
+        Ok(value)
+    }
+
+    fn parse_unicode_escape(&mut self) -> Result<char, DeserializeError> {
+        let first = self.parse_hex4()?;
+        let code = if (0xD800..0xDC00).contains(&first) {
+            // High surrogate: must be followed by an escaped low surrogate
+            if !self.consume_literal("\\u") {
+                return Err(self.error("unpaired surrogate in \\u escape"));
+            }
+            let second = self.parse_hex4()?;
+            if !(0xDC00..0xE000).contains(&second) {
+                return Err(self.error("unpaired surrogate in \\u escape"));
+            }
+            0x10000 + ((first - 0xD800) << 10) + (second - 0xDC00)
+        } else {
+            first
+        };
+        char::from_u32(code).ok_or_else(|| self.error("invalid \\u escape"))
+    }
+
+    fn parse_number(&mut self) -> Result<Value, DeserializeError> {
+        let start = self.position;
+        let mut is_float = false;
+
+        if self.peek() == Some(b'-') {
+            self.position += 1;
+        }
+        match self.peek() {
+            Some(b'0') => self.position += 1,
+            Some(b'1'..=b'9') => self.skip_digits(),
+            _ => return Err(self.error("invalid number")),
+        }
+        if self.peek() == Some(b'.') {
+            is_float = true;
+            self.position += 1;
+            if !matches!(self.peek(), Some(b'0'..=b'9')) {
+                return Err(self.error("invalid number"));
+            }
+            self.skip_digits();
+        }
+        if matches!(self.peek(), Some(b'e' | b'E')) {
+            is_float = true;
+            self.position += 1;
+            if matches!(self.peek(), Some(b'+' | b'-')) {
+                self.position += 1;
+            }
+            if !matches!(self.peek(), Some(b'0'..=b'9')) {
+                return Err(self.error("invalid number"));
+            }
+            self.skip_digits();
+        }
+
+        // The grammar above only admits ASCII.
+        let text = std::str::from_utf8(&self.bytes[start..self.position]).unwrap();
+        if !is_float {
+            if let Ok(value) = text.parse::<i64>() {
+                return Ok(match i32::try_from(value) {
+                    Ok(value) => Value::Int32(value),
+                    Err(_) => Value::Int64(value),
+                });
+            }
+            if let Ok(value) = text.parse::<u64>() {
+                return Ok(Value::UInt64(value));
+            }
+        }
+        text.parse::<f64>()
+            .map(Value::Double)
+            .map_err(|_| self.error("invalid number"))
+    }
+
+    fn skip_digits(&mut self) {
+        while matches!(self.peek(), Some(b'0'..=b'9')) {
+            self.position += 1;
+        }
+    }
+}
+
+/* Extended JSON */
+
+/// Converts an Extended JSON type wrapper to the value it describes. Documents
+/// that are not type wrappers are returned unchanged.
+fn extended_json_value(document: Document) -> Result<Value, String> {
+    let mut keys: Vec<&str> = document.iter().map(|(key, _)| key.as_str()).collect();
+    keys.sort_unstable();
+    let get = |key: &str| document.get(key);
+
+    let value = match keys.as_slice() {
+        ["$oid"] => {
+            let hex = get("$oid").and_then(Value::as_str).ok_or("$oid must be a string")?;
+            Value::ObjectId(parse_object_id(hex)?)
+        }
+        ["$date"] => Value::UTCDateTime(parse_date(get("$date").unwrap())?),
+        ["$numberInt"] => {
+            let text = get("$numberInt").and_then(Value::as_str).ok_or("$numberInt must be a string")?;
+            Value::Int32(text.parse().map_err(|_| format!("invalid $numberInt '{}'", text))?)
+        }
+        ["$numberLong"] => Value::Int64(parse_number_long(get("$numberLong").unwrap())?),
+        ["$numberDouble"] => {
+            let text = get("$numberDouble")
+                .and_then(Value::as_str)
+                .ok_or("$numberDouble must be a string")?;
+            Value::Double(match text {
+                "Infinity" => f64::INFINITY,
+                "-Infinity" => f64::NEG_INFINITY,
+                "NaN" => f64::NAN,
+                _ => text.parse().map_err(|_| format!("invalid $numberDouble '{}'", text))?,
+            })
+        }
+        ["$numberDecimal"] => return Err("$numberDecimal is not supported".to_string()),
+        ["$binary"] => {
+            let binary = get("$binary")
+                .and_then(Value::as_document)
+                .ok_or("$binary must be a document")?;
+            let data = binary
+                .get("base64")
+                .and_then(Value::as_str)
+                .ok_or("$binary.base64 must be a string")?;
+            Value::Binary(base64::decode(data).ok_or("invalid base64 in $binary")?)
+        }
+        ["$binary", "$type"] => {
+            let data = get("$binary").and_then(Value::as_str).ok_or("$binary must be a string")?;
+            Value::Binary(base64::decode(data).ok_or("invalid base64 in $binary")?)
+        }
+        ["$regularExpression"] => {
+            let regex = get("$regularExpression")
+                .and_then(Value::as_document)
+                .ok_or("$regularExpression must be a document")?;
+            match (regex.get("pattern"), regex.get("options")) {
+                (Some(Value::String(pattern)), Some(Value::String(options))) => {
+                    Value::RegularExpression {
+                        pattern: pattern.clone(),
+                        options: options.clone(),
+                    }
+                }
+                _ => return Err("$regularExpression requires string pattern and options".to_string()),
+            }
+        }
+        ["$options", "$regex"] => match (get("$regex"), get("$options")) {
+            (Some(Value::String(pattern)), Some(Value::String(options))) => {
+                Value::RegularExpression {
+                    pattern: pattern.clone(),
+                    options: options.clone(),
+                }
+            }
+            // `$regex` is also a query operator, which may hold a regex value
+            _ => return Ok(Value::Document(document)),
+        },
+        ["$timestamp"] => {
+            let timestamp = get("$timestamp")
+                .and_then(Value::as_document)
+                .ok_or("$timestamp must be a document")?;
+            let part = |key: &str| match timestamp.get(key) {
+                Some(Value::Int32(value)) if *value >= 0 => Ok(*value as i64),
+                Some(Value::Int64(value)) if (0..=u32::MAX as i64).contains(value) => Ok(*value),
+                _ => Err(format!("$timestamp.{} must be an unsigned 32-bit integer", key)),
+            };
+            Value::Timestamp((part("t")? << 32) | part("i")?)
+        }
+        ["$minKey"] => Value::MinKey,
+        ["$maxKey"] => Value::MaxKey,
+        ["$code"] => Value::JavaScriptCode(
+            get("$code").and_then(Value::as_str).ok_or("$code must be a string")?.to_string(),
+        ),
+        ["$code", "$scope"] => Value::JavaScriptCodeWithScope {
+            code: get("$code").and_then(Value::as_str).ok_or("$code must be a string")?.to_string(),
+            scope: get("$scope")
+                .and_then(Value::as_document)
+                .ok_or("$scope must be a document")?
+                .clone(),
+        },
+        ["$symbol"] => Value::Symbol(
+            get("$symbol").and_then(Value::as_str).ok_or("$symbol must be a string")?.to_string(),
+        ),
+        ["$undefined"] => Value::Undefined,
+        ["$dbPointer"] => {
+            let pointer = get("$dbPointer")
+                .and_then(Value::as_document)
+                .ok_or("$dbPointer must be a document")?;
+            match (pointer.get("$ref"), pointer.get("$id")) {
+                (Some(Value::String(namespace)), Some(Value::ObjectId(id))) => Value::DbPointer {
+                    namespace: namespace.clone(),
+                    id: id.clone(),
+                },
+                _ => return Err("$dbPointer requires a string $ref and an $oid $id".to_string()),
+            }
+        }
+        _ => return Ok(Value::Document(document)),
+    };
+    Ok(value)
+}
+
+fn parse_object_id(hex: &str) -> Result<ObjectId, String> {
+    let bytes = hex::decode(hex).ok_or_else(|| format!("invalid $oid '{}'", hex))?;
+    let bytes: [u8; 12] = bytes
+        .try_into()
+        .map_err(|_| format!("$oid must be 24 hex digits, found '{}'", hex))?;
+    Ok(ObjectId::from_bytes(bytes))
+}
+
+fn parse_number_long(value: &Value) -> Result<i64, String> {
+    let text = value.as_str().ok_or("$numberLong must be a string")?;
+    text.parse().map_err(|_| format!("invalid $numberLong '{}'", text))
+}
+
+/// Parses the payload of `$date` into seconds since the epoch. A nested
+/// `{"$numberLong": ...}` has already been converted to `Int64` by then.
+fn parse_date(value: &Value) -> Result<i64, String> {
+    let millis = match value {
+        Value::String(text) => {
+            return UTCDateTime::parse_rfc3339(text)
+                .map(|date| date.as_secs())
+                .ok_or_else(|| format!("invalid $date '{}'", text))
+        }
+        Value::Int32(millis) => *millis as i64,
+        Value::Int64(millis) => *millis,
+        Value::Double(millis) if millis.is_finite() => *millis as i64,
+        _ => return Err("invalid $date value".to_string()),
+    };
+    Ok(millis.div_euclid(1000))
+}
@@ -0,0 +1,130 @@
+// src/deser/mmap.rs
+
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use memmap2::Mmap;
+
+use super::decoder::Decoder;
+use super::error::DeserializeError;
+use super::framing::{peek_document_len, MIN_DOCUMENT_LEN};
+use crate::raw::RawDocument;
+
+impl Decoder<&[u8]> {
+    /// Memory-maps a file of concatenated BSON documents.
+    ///
+    /// Nothing is read or copied up front; documents are framed lazily as
+    /// `MappedDocuments::iter` walks the mapping, so multi-gigabyte dumps can
+    /// be scanned without loading them into memory.
+    ///
+    /// The file must not be truncated or modified while it is mapped. Doing
+    /// so is undefined behaviour on most platforms, so only map files that no
+    /// other process writes to, such as closed dumps and backups.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file to map.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be opened or mapped.
+    pub fn open_mmap<P: AsRef<Path>>(path: P) -> io::Result<MappedDocuments> {
+        let file = File::open(path)?;
+        // SAFETY: callers are told not to modify the file while it is mapped.
+        let mmap = unsafe { Mmap::map(&file)? };
+        Ok(MappedDocuments { mmap })
+    }
+}
+
+/// A memory-mapped file of concatenated BSON documents, created with `Decoder::open_mmap`.
+#[derive(Debug)]
+pub struct MappedDocuments {
+    mmap: Mmap,
+}
+
+impl MappedDocuments {
+    /// Returns the mapped bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.mmap
+    }
+
+    /// Returns the size of the mapping in bytes.
+    pub fn len(&self) -> usize {
+        self.mmap.len()
+    }
+
+    /// Returns `true` if the mapped file is empty.
+    pub fn is_empty(&self) -> bool {
+        self.mmap.is_empty()
+    }
+
+    /// Returns a lazy iterator of `RawDocument` views over the mapping.
+    pub fn iter(&self) -> MappedIter<'_> {
+        MappedIter {
+            bytes: &self.mmap,
+            offset: 0,
+            done: false,
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a MappedDocuments {
+    type Item = Result<RawDocument<'a>, DeserializeError>;
+    type IntoIter = MappedIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Iterator over the documents of a `MappedDocuments`.
+///
+/// Each document's framing is validated as it is reached. After an error the
+/// iterator is finished, since the following bytes cannot be framed reliably.
+#[derive(Debug, Clone)]
+pub struct MappedIter<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    done: bool,
+}
+
+impl MappedIter<'_> {
+    /// Returns the byte offset of the next document in the mapping.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl<'a> Iterator for MappedIter<'a> {
+    type Item = Result<RawDocument<'a>, DeserializeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || self.offset == self.bytes.len() {
+            return None;
+        }
+
+        let rest = &self.bytes[self.offset..];
+        let result = match peek_document_len(rest) {
+            Some(length) if length < MIN_DOCUMENT_LEN => {
+                Err(DeserializeError::InvalidLength(length as i64))
+            }
+            Some(length) if length <= rest.len() => RawDocument::from_bytes(&rest[..length]),
+            None if rest.len() >= 4 => Err(DeserializeError::InvalidLength(i32::from_le_bytes(
+                rest[..4].try_into().unwrap(),
+            ) as i64)),
+            _ => Err(DeserializeError::UnexpectedEof),
+        };
+
+        match result {
+            Ok(document) => {
+                self.offset += document.as_bytes().len();
+                Some(Ok(document))
+            }
+            Err(error) => {
+                self.done = true;
+                Some(Err(error))
+            }
+        }
+    }
+}
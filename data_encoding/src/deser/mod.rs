@@ -0,0 +1,61 @@
+// src/deser/mod.rs
+
+#[cfg(feature = "bumpalo")]
+mod arena;
+mod cbor;
+mod decoder;
+mod error;
+mod events;
+mod file_iter;
+mod framing;
+mod header;
+mod inspect;
+mod intern;
+mod json;
+#[cfg(feature = "memmap2")]
+mod mmap;
+mod msgpack;
+mod options;
+mod pool;
+mod projection;
+mod recovery;
+mod stats;
+mod test;
+mod transcode;
+mod trusted;
+mod visitor;
+
+#[cfg(feature = "bumpalo")]
+pub use arena::{from_bytes_in, ArenaArray, ArenaDocument, ArenaValue};
+pub use cbor::{
+    from_cbor_bytes, CBOR_TAG_MAX_KEY, CBOR_TAG_MIN_KEY, CBOR_TAG_OBJECT_ID, CBOR_TAG_REGEX,
+    CBOR_TAG_TIMESTAMP,
+};
+pub use decoder::{decode_into, from_bytes, from_bytes_with_options, from_reader, Decoder};
+pub use error::{DecodeWarning, DeserializeError, TranscodeError};
+pub use events::{BsonEvent, BsonEvents};
+pub use file_iter::DocumentFileIterator;
+pub use framing::{peek_document_len, Framer, MAX_DOCUMENT_LEN, MIN_DOCUMENT_LEN};
+pub use header::{EncodingHeader, HeaderFlags, FORMAT_VERSION, HEADER_LEN, HEADER_MAGIC};
+pub use inspect::{hex_dump, inspect, InspectMode, InspectOptions};
+pub use intern::InternStats;
+pub use json::{from_json_str, from_json_value_str, NdjsonReader};
+#[cfg(feature = "memmap2")]
+pub use mmap::{MappedDocuments, MappedIter};
+pub use msgpack::{
+    from_msgpack_bytes, MSGPACK_EXT_BSON_TIMESTAMP, MSGPACK_EXT_MAX_KEY, MSGPACK_EXT_MIN_KEY,
+    MSGPACK_EXT_OBJECT_ID, MSGPACK_EXT_REGEX,
+};
+pub use options::{
+    DecoderOptions, DuplicateKeyPolicy, LegacyTypes, NewerVersionPolicy, Utf8Mode,
+};
+pub use pool::DocumentPool;
+pub use projection::{decode_projected, Projection};
+pub use recovery::{recover_documents, CorruptRegion, Recovery};
+pub use stats::DecodeStats;
+pub use transcode::{transcode_bson_to_json, transcode_document, transcode_json_to_bson};
+pub use trusted::from_bytes_trusted;
+pub use visitor::{visit, DocumentVisitor, VisitControl};
+
+/// Maximum depth of nested documents and arrays accepted by the decoders.
+pub const MAX_NESTING_DEPTH: usize = 100;
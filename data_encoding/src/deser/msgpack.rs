@@ -0,0 +1,300 @@
+// src/deser/msgpack.rs
+
+use super::cbor::unsigned_to_value;
+use super::error::DeserializeError;
+use super::MAX_NESTING_DEPTH;
+use crate::types::{Array, Document, ObjectId, Value};
+
+/* Extension Types */
+
+/// Standard MessagePack timestamp extension, mapped to `UTCDateTime`.
+const EXT_TIMESTAMP: i8 = -1;
+
+/// Extension carrying a 12-byte ObjectId. SilentDB extension types reuse the
+/// BSON type byte of the value they carry where it fits in `0..=127`.
+pub const MSGPACK_EXT_OBJECT_ID: i8 = 0x07;
+/// Extension carrying a regular expression as `pattern\0options\0`.
+pub const MSGPACK_EXT_REGEX: i8 = 0x0B;
+/// Extension carrying a BSON timestamp as a big-endian `u64`.
+pub const MSGPACK_EXT_BSON_TIMESTAMP: i8 = 0x11;
+/// Extension marking MinKey (BSON's `0xFF` would collide with the standard timestamp extension).
+pub const MSGPACK_EXT_MIN_KEY: i8 = 0x7E;
+/// Extension marking MaxKey.
+pub const MSGPACK_EXT_MAX_KEY: i8 = 0x7F;
+
+/// Decodes a MessagePack-encoded map into a `Document`.
+///
+/// The standard timestamp extension (`-1`) maps to `UTCDateTime`; SilentDB
+/// extension types (`MSGPACK_EXT_*`) map back to ObjectId, regular
+/// expressions, BSON timestamps, MinKey and MaxKey.
+///
+/// # Errors
+///
+/// Returns an error if the input is not a single well-formed MessagePack map,
+/// or if it contains an unknown extension type.
+pub fn from_msgpack_bytes(bytes: &[u8]) -> Result<Document, DeserializeError> {
+    let mut decoder = MsgpackDecoder::new(bytes);
+    let document = match decoder.decode_value()? {
+        Value::Document(document) => document,
+        other => {
+            return Err(DeserializeError::InvalidDocument(format!(
+                "top-level MessagePack item must be a map, found {}",
+                other
+            )))
+        }
+    };
+
+    if decoder.position != bytes.len() {
+        return Err(DeserializeError::InvalidDocument(format!(
+            "{} trailing bytes after MessagePack document",
+            bytes.len() - decoder.position
+        )));
+    }
+
+    Ok(document)
+}
+
+struct MsgpackDecoder<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    depth: usize,
+}
+
+impl<'a> MsgpackDecoder<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        MsgpackDecoder {
+            bytes,
+            position: 0,
+            depth: 0,
+        }
+    }
+
+    fn invalid(&self, message: &str) -> DeserializeError {
+        DeserializeError::InvalidDocument(format!("{} at offset {}", message, self.position))
+    }
+
+    fn read_slice(&mut self, len: usize) -> Result<&'a [u8], DeserializeError> {
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(DeserializeError::UnexpectedEof)?;
+        let slice = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(slice)
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], DeserializeError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.read_slice(N)?);
+        Ok(array)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, DeserializeError> {
+        Ok(self.read_array::<1>()?[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16, DeserializeError> {
+        Ok(u16::from_be_bytes(self.read_array()?))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, DeserializeError> {
+        Ok(u32::from_be_bytes(self.read_array()?))
+    }
+
+    fn read_u64(&mut self) -> Result<u64, DeserializeError> {
+        Ok(u64::from_be_bytes(self.read_array()?))
+    }
+
+    fn decode_value(&mut self) -> Result<Value, DeserializeError> {
+        let marker = self.read_u8()?;
+        match marker {
+            0x00..=0x7F => Ok(Value::Int32(marker as i32)),
+            0x80..=0x8F => self.nested(|decoder| decoder.decode_map((marker & 0x0F) as usize)),
+            0x90..=0x9F => self.nested(|decoder| decoder.decode_array((marker & 0x0F) as usize)),
+            0xA0..=0xBF => self.read_str((marker & 0x1F) as usize),
+            0xC0 => Ok(Value::Null),
+            0xC2 => Ok(Value::Boolean(false)),
+            0xC3 => Ok(Value::Boolean(true)),
+            0xC4 => {
+                let len = self.read_u8()? as usize;
+                self.read_bin(len)
+            }
+            0xC5 => {
+                let len = self.read_u16()? as usize;
+                self.read_bin(len)
+            }
+            0xC6 => {
+                let len = self.read_u32()? as usize;
+                self.read_bin(len)
+            }
+            0xC7 => {
+                let len = self.read_u8()? as usize;
+                self.read_ext(len)
+            }
+            0xC8 => {
+                let len = self.read_u16()? as usize;
+                self.read_ext(len)
+            }
+            0xC9 => {
+                let len = self.read_u32()? as usize;
+                self.read_ext(len)
+            }
+            0xCA => Ok(Value::Double(f32::from_bits(self.read_u32()?) as f64)),
+            0xCB => Ok(Value::Double(f64::from_bits(self.read_u64()?))),
+            0xCC => Ok(unsigned_to_value(self.read_u8()? as u64)),
+            0xCD => Ok(unsigned_to_value(self.read_u16()? as u64)),
+            0xCE => Ok(unsigned_to_value(self.read_u32()? as u64)),
+            0xCF => Ok(unsigned_to_value(self.read_u64()?)),
+            0xD0 => Ok(Value::Int32(self.read_u8()? as i8 as i32)),
+            0xD1 => Ok(Value::Int32(self.read_u16()? as i16 as i32)),
+            0xD2 => Ok(Value::Int32(self.read_u32()? as i32)),
+            0xD3 => Ok(signed_to_value(self.read_u64()? as i64)),
+            0xD4 => self.read_ext(1),
+            0xD5 => self.read_ext(2),
+            0xD6 => self.read_ext(4),
+            0xD7 => self.read_ext(8),
+            0xD8 => self.read_ext(16),
+            0xD9 => {
+                let len = self.read_u8()? as usize;
+                self.read_str(len)
+            }
+            0xDA => {
+                let len = self.read_u16()? as usize;
+                self.read_str(len)
+            }
+            0xDB => {
+                let len = self.read_u32()? as usize;
+                self.read_str(len)
+            }
+            0xDC => {
+                let len = self.read_u16()? as usize;
+                self.nested(|decoder| decoder.decode_array(len))
+            }
+            0xDD => {
+                let len = self.read_u32()? as usize;
+                self.nested(|decoder| decoder.decode_array(len))
+            }
+            0xDE => {
+                let len = self.read_u16()? as usize;
+                self.nested(|decoder| decoder.decode_map(len))
+            }
+            0xDF => {
+                let len = self.read_u32()? as usize;
+                self.nested(|decoder| decoder.decode_map(len))
+            }
+            0xE0..=0xFF => Ok(Value::Int32(marker as i8 as i32)),
+            0xC1 => {
+                self.position -= 1;
+                Err(self.invalid("reserved MessagePack marker 0xc1"))
+            }
+        }
+    }
+
+    fn nested<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<T, DeserializeError>,
+    ) -> Result<T, DeserializeError> {
+        if self.depth >= MAX_NESTING_DEPTH {
+            return Err(self.invalid("maximum nesting depth exceeded"));
+        }
+        self.depth += 1;
+        let result = f(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn read_str(&mut self, len: usize) -> Result<Value, DeserializeError> {
+        let bytes = self.read_slice(len)?;
+        Ok(Value::String(String::from_utf8(bytes.to_vec())?))
+    }
+
+    fn read_bin(&mut self, len: usize) -> Result<Value, DeserializeError> {
+        Ok(Value::Binary(self.read_slice(len)?.to_vec()))
+    }
+
+    fn decode_array(&mut self, len: usize) -> Result<Value, DeserializeError> {
+        // Don't trust the declared length for preallocation
+        let mut array = Array::with_capacity(len.min(self.bytes.len() - self.position));
+        for _ in 0..len {
+            array.push(self.decode_value()?);
+        }
+        Ok(Value::Array(array))
+    }
+
+    fn decode_map(&mut self, len: usize) -> Result<Value, DeserializeError> {
+        let mut document = Document::new();
+        for _ in 0..len {
+            let key_position = self.position;
+            let key = match self.decode_value()? {
+                Value::String(key) => key,
+                key @ (Value::Int32(_) | Value::Int64(_) | Value::UInt64(_)) => key.to_string(),
+                _ => {
+                    self.position = key_position;
+                    return Err(self.invalid("map key must be a string or integer"));
+                }
+            };
+            document.insert(key, self.decode_value()?);
+        }
+        Ok(Value::Document(document))
+    }
+
+    fn read_ext(&mut self, len: usize) -> Result<Value, DeserializeError> {
+        let ext_type = self.read_u8()? as i8;
+        let start = self.position;
+        let data = self.read_slice(len)?;
+        let malformed = |name: &str| {
+            DeserializeError::InvalidDocument(format!(
+                "malformed {} extension ({} bytes) at offset {}",
+                name, len, start
+            ))
+        };
+
+        match ext_type {
+            EXT_TIMESTAMP => {
+                let secs = match data.len() {
+                    4 => u32::from_be_bytes(data.try_into().unwrap()) as i64,
+                    // Upper 30 bits are nanoseconds, lower 34 bits are seconds
+                    8 => (u64::from_be_bytes(data.try_into().unwrap()) & 0x3_FFFF_FFFF) as i64,
+                    12 => i64::from_be_bytes(data[4..].try_into().unwrap()),
+                    _ => return Err(malformed("timestamp")),
+                };
+                Ok(Value::UTCDateTime(secs))
+            }
+            MSGPACK_EXT_OBJECT_ID => {
+                let bytes: [u8; 12] = data.try_into().map_err(|_| malformed("ObjectId"))?;
+                Ok(Value::ObjectId(ObjectId::from_bytes(bytes)))
+            }
+            MSGPACK_EXT_REGEX => {
+                let mut parts = data.split(|byte| *byte == 0);
+                match (parts.next(), parts.next(), parts.next(), parts.next()) {
+                    (Some(pattern), Some(options), Some([]), None) => {
+                        Ok(Value::RegularExpression {
+                            pattern: String::from_utf8(pattern.to_vec())?,
+                            options: String::from_utf8(options.to_vec())?,
+                        })
+                    }
+                    _ => Err(malformed("regex")),
+                }
+            }
+            MSGPACK_EXT_BSON_TIMESTAMP => {
+                let bytes: [u8; 8] = data.try_into().map_err(|_| malformed("BSON timestamp"))?;
+                Ok(Value::Timestamp(u64::from_be_bytes(bytes) as i64))
+            }
+            MSGPACK_EXT_MIN_KEY => Ok(Value::MinKey),
+            MSGPACK_EXT_MAX_KEY => Ok(Value::MaxKey),
+            _ => Err(DeserializeError::NotSupported(format!(
+                "MessagePack extension type {}",
+                ext_type
+            ))),
+        }
+    }
+}
+
+/// Maps a signed 64-bit integer to `Int32` when it fits, `Int64` otherwise.
+fn signed_to_value(value: i64) -> Value {
+    match i32::try_from(value) {
+        Ok(value) => Value::Int32(value),
+        Err(_) => Value::Int64(value),
+    }
+}
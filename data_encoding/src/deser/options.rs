@@ -0,0 +1,121 @@
+// src/deser/options.rs
+
+/// How the decoder handles a field name that appears more than once in a document.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicateKeyPolicy {
+    /// Fail with `DeserializeError::DuplicateKey`.
+    Error,
+    /// Keep the first value and ignore later ones.
+    FirstWins,
+    /// Keep the last value.
+    #[default]
+    LastWins,
+    /// Replace the field with an array of all its values, in wire order.
+    CollectIntoArray,
+}
+
+/// How the decoder handles string elements that are not valid UTF-8.
+///
+/// Only string values are affected; invalid keys and code strings are always errors.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Utf8Mode {
+    /// Fail with `DeserializeError::Utf8`.
+    #[default]
+    Strict,
+    /// Replace invalid sequences with U+FFFD and record a warning.
+    Lossy,
+    /// Keep the raw bytes as a `Binary` value and record a warning.
+    Preserve,
+}
+
+/// How the decoder handles the deprecated undefined, DBPointer and symbol types.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LegacyTypes {
+    /// Fail with `DeserializeError::NotSupported`.
+    #[default]
+    Reject,
+    /// Map them onto current types: symbols become strings, undefined
+    /// becomes null, and a DBPointer becomes a `{collection, id}` document.
+    Convert,
+    /// Keep them as `Value::Symbol`, `Value::Undefined` and
+    /// `Value::DbPointer`, so they are written back unchanged.
+    Preserve,
+}
+
+/// How the decoder handles an `EncodingHeader` with a newer format version than it knows.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NewerVersionPolicy {
+    /// Fail with `DeserializeError::UnsupportedVersion`.
+    #[default]
+    Reject,
+    /// Decode the document anyway, as long as it sets no unknown required flags.
+    Attempt,
+}
+
+/// Options controlling how `Decoder` maps the wire format onto `Document`.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb_data_encoding::{DecoderOptions, DuplicateKeyPolicy};
+/// let options = DecoderOptions::new().duplicate_keys(DuplicateKeyPolicy::Error);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DecoderOptions {
+    pub(crate) duplicate_keys: DuplicateKeyPolicy,
+    pub(crate) utf8: Utf8Mode,
+    pub(crate) collect_stats: bool,
+    pub(crate) newer_versions: NewerVersionPolicy,
+    pub(crate) legacy_types: LegacyTypes,
+    pub(crate) intern_keys: bool,
+}
+
+impl DecoderOptions {
+    /// Creates the default options.
+    pub fn new() -> Self {
+        DecoderOptions::default()
+    }
+
+    /// Sets the policy for repeated field names. Defaults to `LastWins`.
+    pub fn duplicate_keys(mut self, policy: DuplicateKeyPolicy) -> Self {
+        self.duplicate_keys = policy;
+        self
+    }
+
+    /// Sets how invalid UTF-8 in string values is handled. Defaults to `Strict`.
+    pub fn utf8(mut self, mode: Utf8Mode) -> Self {
+        self.utf8 = mode;
+        self
+    }
+
+    /// Enables collecting `DecodeStats`, available from `Decoder::stats`.
+    /// Off by default, since tracking field paths slows decoding down.
+    pub fn collect_stats(mut self, enabled: bool) -> Self {
+        self.collect_stats = enabled;
+        self
+    }
+
+    /// Sets how the deprecated undefined, DBPointer and symbol types are
+    /// decoded. Defaults to `Reject`.
+    pub fn legacy_types(mut self, mode: LegacyTypes) -> Self {
+        self.legacy_types = mode;
+        self
+    }
+
+    /// Enables interning field names: a decoder keeps a table of the names
+    /// it has read, and gives each repeat the same shared `FieldName`
+    /// rather than allocating it again, across every document it decodes.
+    /// Off by default. Worth it for many documents of a few shapes, such
+    /// as a collection's records or arrays of embedded documents; see
+    /// `Decoder::intern_stats` for what it saved.
+    pub fn intern_keys(mut self, enabled: bool) -> Self {
+        self.intern_keys = enabled;
+        self
+    }
+
+    /// Sets how headers with a newer format version are handled. Defaults to `Reject`.
+    pub fn newer_versions(mut self, policy: NewerVersionPolicy) -> Self {
+        self.newer_versions = policy;
+        self
+    }
+}
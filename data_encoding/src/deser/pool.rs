@@ -0,0 +1,82 @@
+// src/deser/pool.rs
+
+use super::decoder::decode_into;
+use super::error::DeserializeError;
+use crate::types::Document;
+
+/// A small pool of `Document`s for decoding loops that hand documents off
+/// and get them back later, such as batch processing or request handlers.
+///
+/// `decode` refills a pooled document with `decode_into`, so the map
+/// capacity and string buffers of returned documents are reused.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb_data_encoding::{DocumentPool, Value};
+/// let mut pool = DocumentPool::new(8);
+/// let bytes = [12, 0, 0, 0, 0x10, b'a', 0, 1, 0, 0, 0, 0];
+///
+/// let document = pool.decode(&bytes).unwrap();
+/// assert_eq!(document.get("a"), Some(&Value::Int32(1)));
+/// pool.put(document);
+/// assert_eq!(pool.idle(), 1);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct DocumentPool {
+    documents: Vec<Document>,
+    max_idle: usize,
+}
+
+impl DocumentPool {
+    /// Creates an empty pool that keeps at most `max_idle` returned documents.
+    pub fn new(max_idle: usize) -> Self {
+        DocumentPool {
+            documents: Vec::with_capacity(max_idle),
+            max_idle,
+        }
+    }
+
+    /// Returns the number of documents waiting in the pool.
+    pub fn idle(&self) -> usize {
+        self.documents.len()
+    }
+
+    /// Takes an empty document from the pool, or creates one if the pool is empty.
+    pub fn get(&mut self) -> Document {
+        match self.documents.pop() {
+            Some(mut document) => {
+                document.clear();
+                document
+            }
+            None => Document::new(),
+        }
+    }
+
+    /// Returns a document to the pool. It is dropped if the pool is full.
+    ///
+    /// The contents are kept until the document is reused, so that `decode`
+    /// can recycle its buffers.
+    pub fn put(&mut self, document: Document) {
+        if self.documents.len() < self.max_idle {
+            self.documents.push(document);
+        }
+    }
+
+    /// Decodes a single document from `bytes` into a pooled document.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the bytes are not exactly one well-formed document.
+    /// The pooled document is returned to the pool in that case.
+    pub fn decode(&mut self, bytes: &[u8]) -> Result<Document, DeserializeError> {
+        let mut document = self.documents.pop().unwrap_or_default();
+        match decode_into(bytes, &mut document) {
+            Ok(()) => Ok(document),
+            Err(error) => {
+                self.put(document);
+                Err(error)
+            }
+        }
+    }
+}
@@ -0,0 +1,139 @@
+// src/deser/projection.rs
+
+use std::collections::HashMap;
+
+use super::error::DeserializeError;
+use super::MAX_NESTING_DEPTH;
+use crate::raw::{RawDocument, RawElement};
+use crate::types::{Array, Document, Value};
+
+/// A set of dotted field paths to materialize, e.g. `["name", "address.city"]`.
+///
+/// Including a path includes everything below it. A path through an array
+/// applies to every embedded document in the array.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Projection {
+    root: ProjectionNode,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct ProjectionNode {
+    children: HashMap<String, ProjectionNode>,
+    include_all: bool,
+}
+
+impl Projection {
+    /// Creates a projection including each of `paths`.
+    pub fn new<I, S>(paths: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut projection = Projection::default();
+        for path in paths {
+            projection.include(path.as_ref());
+        }
+        projection
+    }
+
+    /// Adds a dotted path to the projection.
+    pub fn include(&mut self, path: &str) {
+        let mut node = &mut self.root;
+        for part in path.split('.') {
+            if node.include_all {
+                return;
+            }
+            node = node.children.entry(part.to_string()).or_default();
+        }
+        node.include_all = true;
+        node.children.clear();
+    }
+
+    /// Returns `true` if no paths are included.
+    pub fn is_empty(&self) -> bool {
+        self.root.children.is_empty()
+    }
+}
+
+/// Decodes only the fields of `bytes` selected by `projection`.
+///
+/// Elements outside the projection are skipped by length without being
+/// decoded or validated. An embedded document on a projected path is kept,
+/// possibly empty, even if none of the requested fields below it exist.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb_data_encoding::{decode_projected, to_bytes, Document, Projection, Value};
+/// let mut address = Document::new();
+/// address.insert("city", "Oslo");
+/// address.insert("street", "Storgata");
+/// let mut document = Document::new();
+/// document.insert("name", "Ada");
+/// document.insert("address", address);
+/// let bytes = to_bytes(&document).unwrap();
+///
+/// let projected = decode_projected(&bytes, &Projection::new(["address.city"])).unwrap();
+/// assert_eq!(projected.len(), 1);
+/// let address = projected.get("address").unwrap().as_document().unwrap();
+/// assert_eq!(address.get("city"), Some(&Value::from("Oslo")));
+/// assert_eq!(address.get("street"), None);
+/// ```
+///
+/// # Errors
+///
+/// Returns an error if a projected part of the document is malformed.
+pub fn decode_projected(bytes: &[u8], projection: &Projection) -> Result<Document, DeserializeError> {
+    project_document(RawDocument::from_bytes(bytes)?, &projection.root, 0)
+}
+
+fn project_document(
+    document: RawDocument,
+    node: &ProjectionNode,
+    depth: usize,
+) -> Result<Document, DeserializeError> {
+    if depth >= MAX_NESTING_DEPTH {
+        return Err(DeserializeError::InvalidDocument(
+            "maximum nesting depth exceeded".to_string(),
+        ));
+    }
+
+    let mut output = Document::new();
+    for element in document.iter() {
+        let element = element?;
+        let Some(child) = node.children.get(element.key()) else {
+            continue;
+        };
+        if let Some(value) = project_element(&element, child, depth)? {
+            output.insert(element.key(), value);
+        }
+    }
+    Ok(output)
+}
+
+/// Projects a single element, returning `None` if nothing below it is selected.
+fn project_element(
+    element: &RawElement,
+    node: &ProjectionNode,
+    depth: usize,
+) -> Result<Option<Value>, DeserializeError> {
+    if node.include_all {
+        return element.value().map(Some);
+    }
+
+    if let Some(document) = element.as_document() {
+        return Ok(Some(Value::Document(project_document(document, node, depth + 1)?)));
+    }
+    if let Some(array) = element.as_array() {
+        let mut output = Array::new();
+        for item in array.iter() {
+            if let Some(document) = item?.as_document() {
+                output.push(Value::Document(project_document(document, node, depth + 1)?));
+            }
+        }
+        return Ok(Some(Value::Array(output)));
+    }
+
+    // A sub-path of a scalar selects nothing
+    Ok(None)
+}
@@ -0,0 +1,109 @@
+// src/deser/recovery.rs
+
+use super::decoder::from_bytes;
+use super::error::DeserializeError;
+use super::framing::{peek_document_len, MAX_DOCUMENT_LEN, MIN_DOCUMENT_LEN};
+use crate::types::Document;
+
+/// A span of input that could not be decoded and was skipped.
+#[derive(Debug)]
+pub struct CorruptRegion {
+    /// Offset of the first skipped byte.
+    pub offset: usize,
+    /// Number of bytes skipped.
+    pub length: usize,
+    /// The error that started the region.
+    pub error: DeserializeError,
+}
+
+/// The result of `recover_documents`.
+#[derive(Debug, Default)]
+pub struct Recovery {
+    /// Documents that decoded cleanly, with their offsets, in input order.
+    pub documents: Vec<(usize, Document)>,
+    /// Regions that were skipped, in input order.
+    pub corrupt_regions: Vec<CorruptRegion>,
+}
+
+impl Recovery {
+    /// Returns `true` if no corruption was found.
+    pub fn is_clean(&self) -> bool {
+        self.corrupt_regions.is_empty()
+    }
+
+    /// Returns the total number of bytes skipped.
+    pub fn bytes_skipped(&self) -> usize {
+        self.corrupt_regions.iter().map(|region| region.length).sum()
+    }
+}
+
+/// Decodes back-to-back documents, skipping over corruption.
+///
+/// When a document fails to decode, the scanner moves forward one byte at a
+/// time until it finds an offset where a complete, well-formed document
+/// starts, and records the skipped bytes as a `CorruptRegion`. A corrupt
+/// element therefore costs the document containing it, not the rest of the
+/// input.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb_data_encoding::recover_documents;
+/// let mut bytes = vec![5, 0, 0, 0, 0];
+/// bytes.extend([0xDE, 0xAD]);
+/// bytes.extend([5, 0, 0, 0, 0]);
+///
+/// let recovery = recover_documents(&bytes);
+/// assert_eq!(recovery.documents.len(), 2);
+/// assert_eq!(recovery.documents[1].0, 7);
+/// assert_eq!(recovery.bytes_skipped(), 2);
+/// ```
+pub fn recover_documents(bytes: &[u8]) -> Recovery {
+    let mut recovery = Recovery::default();
+    let mut position = 0;
+
+    while position < bytes.len() {
+        match decode_at(bytes, position) {
+            Ok((document, length)) => {
+                recovery.documents.push((position, document));
+                position += length;
+            }
+            Err(error) => {
+                let next = (position + 1..bytes.len())
+                    .find(|offset| is_plausible_header(bytes, *offset) && decode_at(bytes, *offset).is_ok())
+                    .unwrap_or(bytes.len());
+                recovery.corrupt_regions.push(CorruptRegion {
+                    offset: position,
+                    length: next - position,
+                    error,
+                });
+                position = next;
+            }
+        }
+    }
+
+    recovery
+}
+
+/// Decodes the document starting at `offset`, returning it and its length.
+fn decode_at(bytes: &[u8], offset: usize) -> Result<(Document, usize), DeserializeError> {
+    let rest = &bytes[offset..];
+    let length = peek_document_len(rest).ok_or(DeserializeError::UnexpectedEof)?;
+    if length < MIN_DOCUMENT_LEN {
+        return Err(DeserializeError::InvalidLength(length as i64));
+    }
+    let document = rest.get(..length).ok_or(DeserializeError::UnexpectedEof)?;
+    Ok((from_bytes(document)?, length))
+}
+
+/// Cheap checks run before attempting a full decode at a resync candidate.
+fn is_plausible_header(bytes: &[u8], offset: usize) -> bool {
+    let rest = &bytes[offset..];
+    let Some(length) = peek_document_len(rest) else {
+        return false;
+    };
+    if !(MIN_DOCUMENT_LEN..=MAX_DOCUMENT_LEN).contains(&length) || length > rest.len() {
+        return false;
+    }
+    rest[length - 1] == 0 && matches!(rest[4], 0x00..=0x13 | 0x7F | 0xFF)
+}
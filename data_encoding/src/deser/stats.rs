@@ -0,0 +1,102 @@
+// src/deser/stats.rs
+
+use std::collections::HashMap;
+
+use crate::types::Value;
+
+/// Number of entries kept in `DecodeStats::largest_strings`.
+const LARGEST_STRINGS: usize = 10;
+
+/// Statistics gathered while decoding, enabled with `DecoderOptions::collect_stats`.
+///
+/// Paths are dotted field names. Array items do not add a path segment, so
+/// the fields of documents inside an `items` array are reported as `items.sku`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DecodeStats {
+    documents: u64,
+    element_counts: HashMap<u8, u64>,
+    bytes_per_path: HashMap<String, u64>,
+    max_depth: usize,
+    largest_strings: Vec<(String, usize)>,
+}
+
+impl DecodeStats {
+    /// Returns the number of top-level documents decoded.
+    pub fn documents(&self) -> u64 {
+        self.documents
+    }
+
+    /// Returns the number of elements decoded with the given BSON type byte,
+    /// including array items.
+    pub fn element_count(&self, element_type: u8) -> u64 {
+        self.element_counts.get(&element_type).copied().unwrap_or(0)
+    }
+
+    /// Returns the element counts keyed by BSON type byte.
+    pub fn element_counts(&self) -> &HashMap<u8, u64> {
+        &self.element_counts
+    }
+
+    /// Returns the total encoded bytes (type byte, key and value) seen at each path.
+    ///
+    /// The bytes of a field are also included in the totals of its parents.
+    pub fn bytes_per_path(&self) -> &HashMap<String, u64> {
+        &self.bytes_per_path
+    }
+
+    /// Returns the `n` paths with the most encoded bytes, largest first.
+    pub fn largest_paths(&self, n: usize) -> Vec<(&str, u64)> {
+        let mut paths: Vec<(&str, u64)> = self
+            .bytes_per_path
+            .iter()
+            .map(|(path, bytes)| (path.as_str(), *bytes))
+            .collect();
+        paths.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        paths.truncate(n);
+        paths
+    }
+
+    /// Returns the deepest nesting seen; a flat document has depth 1.
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    /// Returns the paths and byte lengths of the largest string values, largest first.
+    pub fn largest_strings(&self) -> &[(String, usize)] {
+        &self.largest_strings
+    }
+
+    pub(crate) fn record_document(&mut self) {
+        self.documents += 1;
+    }
+
+    pub(crate) fn record_depth(&mut self, depth: usize) {
+        self.max_depth = self.max_depth.max(depth);
+    }
+
+    /// Records an element. `encoded_len` is `None` for array items, whose
+    /// bytes are already counted under the array's path.
+    pub(crate) fn record_element(
+        &mut self,
+        path: &[String],
+        element_type: u8,
+        encoded_len: Option<u64>,
+        value: &Value,
+    ) {
+        *self.element_counts.entry(element_type).or_default() += 1;
+
+        let path = || path.join(".");
+        if let Some(encoded_len) = encoded_len {
+            *self.bytes_per_path.entry(path()).or_default() += encoded_len;
+        }
+
+        if let Value::String(value) = value {
+            let smallest = self.largest_strings.last().map_or(0, |(_, len)| *len);
+            if self.largest_strings.len() < LARGEST_STRINGS || value.len() > smallest {
+                let index = self.largest_strings.partition_point(|(_, len)| *len >= value.len());
+                self.largest_strings.insert(index, (path(), value.len()));
+                self.largest_strings.truncate(LARGEST_STRINGS);
+            }
+        }
+    }
+}
@@ -0,0 +1,1673 @@
+#[cfg(test)]
+mod tests {
+    use crate::deser::{
+        recover_documents, decode_into, from_bytes_trusted, LegacyTypes, EncodingHeader, HeaderFlags, NewerVersionPolicy, FORMAT_VERSION, DocumentPool, from_bytes_with_options, DecodeWarning, DecoderOptions, DuplicateKeyPolicy, Utf8Mode, decode_projected, Projection, visit, DocumentVisitor, VisitControl, BsonEvent, BsonEvents, from_bytes, from_reader, DocumentFileIterator, transcode_bson_to_json, transcode_json_to_bson, Decoder, TranscodeError,
+        from_cbor_bytes, from_json_str, from_json_value_str, hex_dump, inspect, InspectMode, InspectOptions, from_msgpack_bytes, NdjsonReader, peek_document_len, DeserializeError, Framer,
+        CBOR_TAG_MAX_KEY, CBOR_TAG_MIN_KEY, CBOR_TAG_OBJECT_ID, CBOR_TAG_TIMESTAMP,
+        MSGPACK_EXT_BSON_TIMESTAMP, MSGPACK_EXT_MAX_KEY, MSGPACK_EXT_MIN_KEY,
+        MSGPACK_EXT_OBJECT_ID, MSGPACK_EXT_REGEX,
+    };
+    use crate::ser::{to_bytes, to_bytes_with_header};
+    use crate::types::{Array, Document, FieldName, ObjectId, Value};
+
+    /// Builds a well-formed frame of `len` bytes: length prefix, filler, null terminator.
+    fn frame(len: usize, fill: u8) -> Vec<u8> {
+        let mut bytes = (len as i32).to_le_bytes().to_vec();
+        bytes.resize(len - 1, fill);
+        bytes.push(0);
+        bytes
+    }
+
+    // -------------------------------------
+    //          Framing Tests
+    // -------------------------------------
+
+    #[test]
+    fn test_peek_document_len() {
+        let bytes = frame(12, 1);
+        assert_eq!(peek_document_len(&bytes), Some(12));
+        assert_eq!(peek_document_len(&bytes[..4]), Some(12));
+        assert_eq!(peek_document_len(&bytes[..3]), None);
+        assert_eq!(peek_document_len(&[]), None);
+        assert_eq!(peek_document_len(&(-1i32).to_le_bytes()), None);
+    }
+
+    #[test]
+    fn test_framer_whole_frames() {
+        let mut framer = Framer::new();
+        let mut stream = frame(8, 1);
+        stream.extend(frame(10, 2));
+        framer.push(&stream);
+
+        assert_eq!(framer.next_frame().unwrap(), Some(frame(8, 1)));
+        assert_eq!(framer.next_frame().unwrap(), Some(frame(10, 2)));
+        assert_eq!(framer.next_frame().unwrap(), None);
+        assert!(framer.is_empty());
+    }
+
+    #[test]
+    fn test_framer_partial_frames() {
+        let mut framer = Framer::new();
+        let bytes = frame(16, 7);
+
+        framer.push(&bytes[..2]);
+        assert_eq!(framer.peek_len(), None);
+        assert_eq!(framer.next_frame().unwrap(), None);
+
+        framer.push(&bytes[2..9]);
+        assert_eq!(framer.peek_len(), Some(16));
+        assert_eq!(framer.next_frame().unwrap(), None);
+        assert_eq!(framer.buffered_len(), 9);
+
+        framer.push(&bytes[9..]);
+        assert_eq!(framer.next_frame().unwrap(), Some(bytes));
+        assert!(framer.is_empty());
+    }
+
+    #[test]
+    fn test_framer_byte_at_a_time() {
+        let mut framer = Framer::new();
+        let mut stream = frame(5, 0);
+        stream.extend(frame(9, 3));
+        stream.extend(frame(6, 4));
+
+        let mut frames = Vec::new();
+        for byte in stream {
+            framer.push(&[byte]);
+            while let Some(frame) = framer.next_frame().unwrap() {
+                frames.push(frame);
+            }
+        }
+        assert_eq!(frames, vec![frame(5, 0), frame(9, 3), frame(6, 4)]);
+    }
+
+    #[test]
+    fn test_framer_read_from() {
+        let mut stream = frame(20, 1);
+        stream.extend(frame(30, 2));
+        let mut reader = std::io::Cursor::new(stream);
+
+        let mut framer = Framer::new();
+        let mut frames = Vec::new();
+        while framer.read_from(&mut reader).unwrap() > 0 {
+            while let Some(frame) = framer.next_frame().unwrap() {
+                frames.push(frame);
+            }
+        }
+        assert_eq!(frames, vec![frame(20, 1), frame(30, 2)]);
+        assert!(framer.is_empty());
+    }
+
+    #[test]
+    fn test_framer_rejects_short_length() {
+        let mut framer = Framer::new();
+        framer.push(&[4, 0, 0, 0, 0]);
+        assert!(matches!(
+            framer.next_frame(),
+            Err(DeserializeError::InvalidLength(4))
+        ));
+    }
+
+    #[test]
+    fn test_framer_rejects_oversized_frame() {
+        let mut framer = Framer::with_max_document_len(64);
+        framer.push(&frame(128, 1)[..4]);
+        assert!(matches!(
+            framer.next_frame(),
+            Err(DeserializeError::DocumentTooLarge { length: 128, max: 64 })
+        ));
+    }
+
+    #[test]
+    fn test_framer_rejects_missing_terminator() {
+        let mut framer = Framer::new();
+        let mut bytes = frame(8, 1);
+        bytes[7] = 1;
+        framer.push(&bytes);
+        assert!(matches!(
+            framer.next_frame(),
+            Err(DeserializeError::InvalidDocument(_))
+        ));
+    }
+
+    // -------------------------------------
+    //            CBOR Tests
+    // -------------------------------------
+
+    /// Encodes a CBOR item header with the shortest argument encoding.
+    fn cbor_header(major: u8, argument: u64) -> Vec<u8> {
+        let major = major << 5;
+        match argument {
+            0..=23 => vec![major | argument as u8],
+            24..=0xFF => vec![major | 24, argument as u8],
+            0x100..=0xFFFF => [vec![major | 25], (argument as u16).to_be_bytes().to_vec()].concat(),
+            0x1_0000..=0xFFFF_FFFF => {
+                [vec![major | 26], (argument as u32).to_be_bytes().to_vec()].concat()
+            }
+            _ => [vec![major | 27], argument.to_be_bytes().to_vec()].concat(),
+        }
+    }
+
+    fn cbor_text(text: &str) -> Vec<u8> {
+        [cbor_header(3, text.len() as u64), text.as_bytes().to_vec()].concat()
+    }
+
+    fn cbor_map(entries: Vec<(&str, Vec<u8>)>) -> Vec<u8> {
+        let mut bytes = cbor_header(5, entries.len() as u64);
+        for (key, value) in entries {
+            bytes.extend(cbor_text(key));
+            bytes.extend(value);
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_cbor_scalars() {
+        let bytes = cbor_map(vec![
+            ("small", cbor_header(0, 10)),
+            ("negative", cbor_header(1, 99)),
+            ("wide", cbor_header(0, 1 << 40)),
+            ("huge", cbor_header(0, u64::MAX)),
+            ("text", cbor_text("hello")),
+            ("bytes", [cbor_header(2, 3), vec![1, 2, 3]].concat()),
+            ("yes", vec![0xF5]),
+            ("no", vec![0xF4]),
+            ("nothing", vec![0xF6]),
+            ("double", [vec![0xFB], 1.5f64.to_be_bytes().to_vec()].concat()),
+            ("single", [vec![0xFA], 2.25f32.to_be_bytes().to_vec()].concat()),
+            ("half", vec![0xF9, 0x3C, 0x00]),
+        ]);
+
+        let doc = from_cbor_bytes(&bytes).unwrap();
+        assert_eq!(doc.get("small"), Some(&Value::Int32(10)));
+        assert_eq!(doc.get("negative"), Some(&Value::Int32(-100)));
+        assert_eq!(doc.get("wide"), Some(&Value::Int64(1 << 40)));
+        assert_eq!(doc.get("huge"), Some(&Value::UInt64(u64::MAX)));
+        assert_eq!(doc.get("text"), Some(&Value::String("hello".to_string())));
+        assert_eq!(doc.get("bytes"), Some(&Value::Binary(vec![1, 2, 3])));
+        assert_eq!(doc.get("yes"), Some(&Value::Boolean(true)));
+        assert_eq!(doc.get("no"), Some(&Value::Boolean(false)));
+        assert_eq!(doc.get("nothing"), Some(&Value::Null));
+        assert_eq!(doc.get("double"), Some(&Value::Double(1.5)));
+        assert_eq!(doc.get("single"), Some(&Value::Double(2.25)));
+        assert_eq!(doc.get("half"), Some(&Value::Double(1.0)));
+    }
+
+    #[test]
+    fn test_cbor_nested() {
+        let inner = cbor_map(vec![("a", cbor_header(0, 1))]);
+        let array = [cbor_header(4, 2), cbor_text("x"), inner.clone()].concat();
+        let bytes = cbor_map(vec![("inner", inner), ("list", array)]);
+
+        let mut expected_inner = Document::new();
+        expected_inner.insert("a", 1);
+        let expected_list = Array::from_vec(vec!["x".into(), expected_inner.clone().into()]);
+
+        let doc = from_cbor_bytes(&bytes).unwrap();
+        assert_eq!(doc.get("inner"), Some(&Value::Document(expected_inner)));
+        assert_eq!(doc.get("list"), Some(&Value::Array(expected_list)));
+    }
+
+    #[test]
+    fn test_cbor_indefinite_lengths() {
+        // {_ "k": [_ 1, 2], "s": (_ "ab" "c")}
+        let mut bytes = vec![0xBF];
+        bytes.extend(cbor_text("k"));
+        bytes.extend([0x9F, 0x01, 0x02, 0xFF]);
+        bytes.extend(cbor_text("s"));
+        bytes.push(0x7F);
+        bytes.extend(cbor_text("ab"));
+        bytes.extend(cbor_text("c"));
+        bytes.extend([0xFF, 0xFF]);
+
+        let doc = from_cbor_bytes(&bytes).unwrap();
+        assert_eq!(
+            doc.get("k"),
+            Some(&Value::Array(Array::from_vec(vec![1.into(), 2.into()])))
+        );
+        assert_eq!(doc.get("s"), Some(&Value::String("abc".to_string())));
+    }
+
+    #[test]
+    fn test_cbor_tags() {
+        let oid_bytes = [7u8; 12];
+        let bytes = cbor_map(vec![
+            ("date", [cbor_header(6, 0), cbor_text("2009-02-13T23:31:30Z")].concat()),
+            ("epoch", [cbor_header(6, 1), cbor_header(0, 1234567890)].concat()),
+            ("re", [cbor_header(6, 35), cbor_text("^a+$")].concat()),
+            (
+                "oid",
+                [cbor_header(6, CBOR_TAG_OBJECT_ID), cbor_header(2, 12), oid_bytes.to_vec()].concat(),
+            ),
+            ("ts", [cbor_header(6, CBOR_TAG_TIMESTAMP), cbor_header(0, 42)].concat()),
+            ("min", [cbor_header(6, CBOR_TAG_MIN_KEY), vec![0xF6]].concat()),
+            ("max", [cbor_header(6, CBOR_TAG_MAX_KEY), vec![0xF6]].concat()),
+            ("unknown", [cbor_header(6, 9999), cbor_header(0, 5)].concat()),
+        ]);
+
+        let doc = from_cbor_bytes(&bytes).unwrap();
+        assert_eq!(doc.get("date"), Some(&Value::UTCDateTime(1234567890)));
+        assert_eq!(doc.get("epoch"), Some(&Value::UTCDateTime(1234567890)));
+        assert_eq!(
+            doc.get("re"),
+            Some(&Value::RegularExpression { pattern: "^a+$".to_string(), options: String::new() })
+        );
+        assert_eq!(doc.get("oid"), Some(&Value::ObjectId(ObjectId::from_bytes(oid_bytes))));
+        assert_eq!(doc.get("ts"), Some(&Value::Timestamp(42)));
+        assert_eq!(doc.get("min"), Some(&Value::MinKey));
+        assert_eq!(doc.get("max"), Some(&Value::MaxKey));
+        assert_eq!(doc.get("unknown"), Some(&Value::Int32(5)));
+    }
+
+    #[test]
+    fn test_cbor_integer_keys() {
+        let bytes = [cbor_header(5, 1), cbor_header(0, 7), cbor_text("seven")].concat();
+        let doc = from_cbor_bytes(&bytes).unwrap();
+        assert_eq!(doc.get("7"), Some(&Value::String("seven".to_string())));
+    }
+
+    #[test]
+    fn test_cbor_errors() {
+        // Top-level item is not a map
+        assert!(from_cbor_bytes(&cbor_header(0, 1)).is_err());
+        // Truncated text
+        assert!(from_cbor_bytes(&[0xA1, 0x63, b'a']).is_err());
+        // Trailing bytes
+        let mut bytes = cbor_map(vec![]);
+        bytes.push(0x00);
+        assert!(from_cbor_bytes(&bytes).is_err());
+        // Malformed ObjectId
+        let bytes = cbor_map(vec![(
+            "oid",
+            [cbor_header(6, CBOR_TAG_OBJECT_ID), cbor_header(2, 3), vec![1, 2, 3]].concat(),
+        )]);
+        assert!(from_cbor_bytes(&bytes).is_err());
+        // Excessive nesting
+        let bytes = [[0xA1, 0x61, b'a'].repeat(200), vec![0xA0]].concat();
+        assert!(matches!(
+            from_cbor_bytes(&bytes),
+            Err(DeserializeError::InvalidDocument(_))
+        ));
+    }
+
+    // -------------------------------------
+    //          MessagePack Tests
+    // -------------------------------------
+
+    fn msgpack_str(text: &str) -> Vec<u8> {
+        assert!(text.len() < 32);
+        [vec![0xA0 | text.len() as u8], text.as_bytes().to_vec()].concat()
+    }
+
+    fn msgpack_map(entries: Vec<(&str, Vec<u8>)>) -> Vec<u8> {
+        assert!(entries.len() < 16);
+        let mut bytes = vec![0x80 | entries.len() as u8];
+        for (key, value) in entries {
+            bytes.extend(msgpack_str(key));
+            bytes.extend(value);
+        }
+        bytes
+    }
+
+    fn msgpack_ext(ext_type: i8, data: &[u8]) -> Vec<u8> {
+        [vec![0xC7, data.len() as u8, ext_type as u8], data.to_vec()].concat()
+    }
+
+    #[test]
+    fn test_msgpack_scalars() {
+        let bytes = msgpack_map(vec![
+            ("fixint", vec![0x2A]),
+            ("negfix", vec![0xFF]),
+            ("u16", vec![0xCD, 0x01, 0x00]),
+            ("u64", [vec![0xCF], u64::MAX.to_be_bytes().to_vec()].concat()),
+            ("i64", [vec![0xD3], (-5_000_000_000i64).to_be_bytes().to_vec()].concat()),
+            ("i8", vec![0xD0, 0x80]),
+            ("f32", [vec![0xCA], 0.5f32.to_be_bytes().to_vec()].concat()),
+            ("f64", [vec![0xCB], 2.5f64.to_be_bytes().to_vec()].concat()),
+            ("nil", vec![0xC0]),
+            ("t", vec![0xC3]),
+            ("f", vec![0xC2]),
+            ("s", msgpack_str("hi")),
+            ("str8", [vec![0xD9, 3], b"abc".to_vec()].concat()),
+            ("bin", vec![0xC4, 2, 9, 8]),
+        ]);
+
+        let doc = from_msgpack_bytes(&bytes).unwrap();
+        assert_eq!(doc.get("fixint"), Some(&Value::Int32(42)));
+        assert_eq!(doc.get("negfix"), Some(&Value::Int32(-1)));
+        assert_eq!(doc.get("u16"), Some(&Value::Int32(256)));
+        assert_eq!(doc.get("u64"), Some(&Value::UInt64(u64::MAX)));
+        assert_eq!(doc.get("i64"), Some(&Value::Int64(-5_000_000_000)));
+        assert_eq!(doc.get("i8"), Some(&Value::Int32(-128)));
+        assert_eq!(doc.get("f32"), Some(&Value::Double(0.5)));
+        assert_eq!(doc.get("f64"), Some(&Value::Double(2.5)));
+        assert_eq!(doc.get("nil"), Some(&Value::Null));
+        assert_eq!(doc.get("t"), Some(&Value::Boolean(true)));
+        assert_eq!(doc.get("f"), Some(&Value::Boolean(false)));
+        assert_eq!(doc.get("s"), Some(&Value::String("hi".to_string())));
+        assert_eq!(doc.get("str8"), Some(&Value::String("abc".to_string())));
+        assert_eq!(doc.get("bin"), Some(&Value::Binary(vec![9, 8])));
+    }
+
+    #[test]
+    fn test_msgpack_nested() {
+        let inner = msgpack_map(vec![("a", vec![0x01])]);
+        let array = [vec![0x92], msgpack_str("x"), inner.clone()].concat();
+        let bytes = msgpack_map(vec![("inner", inner), ("list", array)]);
+
+        let mut expected_inner = Document::new();
+        expected_inner.insert("a", 1);
+        let expected_list = Array::from_vec(vec!["x".into(), expected_inner.clone().into()]);
+
+        let doc = from_msgpack_bytes(&bytes).unwrap();
+        assert_eq!(doc.get("inner"), Some(&Value::Document(expected_inner)));
+        assert_eq!(doc.get("list"), Some(&Value::Array(expected_list)));
+    }
+
+    #[test]
+    fn test_msgpack_ext_types() {
+        let oid_bytes = [3u8; 12];
+        let bytes = msgpack_map(vec![
+            ("ts32", [vec![0xD6, 0xFF], 1234567890u32.to_be_bytes().to_vec()].concat()),
+            (
+                "ts64",
+                [vec![0xD7, 0xFF], ((7u64 << 34) | 1234567890).to_be_bytes().to_vec()].concat(),
+            ),
+            (
+                "ts96",
+                msgpack_ext(-1, &[0u32.to_be_bytes().to_vec(), (-86_400i64).to_be_bytes().to_vec()].concat()),
+            ),
+            ("oid", msgpack_ext(MSGPACK_EXT_OBJECT_ID, &oid_bytes)),
+            ("re", msgpack_ext(MSGPACK_EXT_REGEX, b"^a\0i\0")),
+            ("bts", msgpack_ext(MSGPACK_EXT_BSON_TIMESTAMP, &42u64.to_be_bytes())),
+            ("min", [vec![0xD4], vec![MSGPACK_EXT_MIN_KEY as u8, 0]].concat()),
+            ("max", [vec![0xD4], vec![MSGPACK_EXT_MAX_KEY as u8, 0]].concat()),
+        ]);
+
+        let doc = from_msgpack_bytes(&bytes).unwrap();
+        assert_eq!(doc.get("ts32"), Some(&Value::UTCDateTime(1234567890)));
+        assert_eq!(doc.get("ts64"), Some(&Value::UTCDateTime(1234567890)));
+        assert_eq!(doc.get("ts96"), Some(&Value::UTCDateTime(-86_400)));
+        assert_eq!(doc.get("oid"), Some(&Value::ObjectId(ObjectId::from_bytes(oid_bytes))));
+        assert_eq!(
+            doc.get("re"),
+            Some(&Value::RegularExpression { pattern: "^a".to_string(), options: "i".to_string() })
+        );
+        assert_eq!(doc.get("bts"), Some(&Value::Timestamp(42)));
+        assert_eq!(doc.get("min"), Some(&Value::MinKey));
+        assert_eq!(doc.get("max"), Some(&Value::MaxKey));
+    }
+
+    #[test]
+    fn test_msgpack_errors() {
+        // Top-level item is not a map
+        assert!(from_msgpack_bytes(&[0x01]).is_err());
+        // Reserved marker
+        assert!(from_msgpack_bytes(&[0x81, 0xA1, b'a', 0xC1]).is_err());
+        // Truncated
+        assert!(matches!(
+            from_msgpack_bytes(&[0x81, 0xA1, b'a', 0xCD, 0x01]),
+            Err(DeserializeError::UnexpectedEof)
+        ));
+        // Trailing bytes
+        assert!(from_msgpack_bytes(&[0x80, 0x00]).is_err());
+        // Unknown extension type
+        let bytes = msgpack_map(vec![("x", msgpack_ext(42, &[1]))]);
+        assert!(matches!(
+            from_msgpack_bytes(&bytes),
+            Err(DeserializeError::NotSupported(_))
+        ));
+        // Malformed ObjectId extension
+        let bytes = msgpack_map(vec![("x", msgpack_ext(MSGPACK_EXT_OBJECT_ID, &[1, 2]))]);
+        assert!(from_msgpack_bytes(&bytes).is_err());
+    }
+
+    // -------------------------------------
+    //            JSON Tests
+    // -------------------------------------
+
+    #[test]
+    fn test_json_plain_values() {
+        let doc = from_json_str(
+            r#"{"int": 42, "long": 5000000000, "big": 18446744073709551615, "neg": -7,
+                "float": 1.5e2, "str": "a\"b\\cé😀", "t": true, "f": false,
+                "n": null, "list": [1, "two", {"three": 3}], "empty": {}}"#,
+        )
+        .unwrap();
+
+        let mut three = Document::new();
+        three.insert("three", 3);
+        assert_eq!(doc.get("int"), Some(&Value::Int32(42)));
+        assert_eq!(doc.get("long"), Some(&Value::Int64(5_000_000_000)));
+        assert_eq!(doc.get("big"), Some(&Value::UInt64(u64::MAX)));
+        assert_eq!(doc.get("neg"), Some(&Value::Int32(-7)));
+        assert_eq!(doc.get("float"), Some(&Value::Double(150.0)));
+        assert_eq!(doc.get("str"), Some(&Value::String("a\"b\\c\u{e9}\u{1F600}".to_string())));
+        assert_eq!(doc.get("t"), Some(&Value::Boolean(true)));
+        assert_eq!(doc.get("f"), Some(&Value::Boolean(false)));
+        assert_eq!(doc.get("n"), Some(&Value::Null));
+        assert_eq!(
+            doc.get("list"),
+            Some(&Value::Array(Array::from_vec(vec![1.into(), "two".into(), three.into()])))
+        );
+        assert_eq!(doc.get("empty"), Some(&Value::Document(Document::new())));
+    }
+
+    #[test]
+    fn test_json_value() {
+        assert_eq!(from_json_value_str(" 42 ").unwrap(), Value::Int32(42));
+        assert_eq!(from_json_value_str("\"a\"").unwrap(), Value::String("a".to_string()));
+        assert_eq!(
+            from_json_value_str(r#"[{"$numberLong": "7"}, null]"#).unwrap(),
+            Value::Array(Array::from_vec(vec![Value::Int64(7), Value::Null]))
+        );
+        assert!(from_json_value_str("1 2").is_err());
+        assert!(from_json_value_str("").is_err());
+    }
+
+    #[test]
+    fn test_json_extended_types() {
+        let doc = from_json_str(
+            r#"{
+                "oid": {"$oid": "5e4f2f2d7f3d2d2d2d2d2d2d"},
+                "iso": {"$date": "2009-02-13T23:31:30Z"},
+                "millis": {"$date": {"$numberLong": "1234567890123"}},
+                "relaxed": {"$date": 1234567890000},
+                "i32": {"$numberInt": "7"},
+                "i64": {"$numberLong": "-9"},
+                "inf": {"$numberDouble": "-Infinity"},
+                "bin": {"$binary": {"base64": "AQID", "subType": "00"}},
+                "legacy_bin": {"$binary": "AQID", "$type": "00"},
+                "re": {"$regularExpression": {"pattern": "^a", "options": "i"}},
+                "legacy_re": {"$regex": "^b", "$options": "m"},
+                "ts": {"$timestamp": {"t": 1, "i": 2}},
+                "min": {"$minKey": 1},
+                "max": {"$maxKey": 1},
+                "code": {"$code": "f()"},
+                "query": {"$gt": 5, "$lt": 10}
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(doc.get("oid"), Some(&Value::ObjectId(ObjectId::from("5e4f2f2d7f3d2d2d2d2d2d2d"))));
+        assert_eq!(doc.get("iso"), Some(&Value::UTCDateTime(1234567890)));
+        assert_eq!(doc.get("millis"), Some(&Value::UTCDateTime(1234567890)));
+        assert_eq!(doc.get("relaxed"), Some(&Value::UTCDateTime(1234567890)));
+        assert_eq!(doc.get("i32"), Some(&Value::Int32(7)));
+        assert_eq!(doc.get("i64"), Some(&Value::Int64(-9)));
+        assert_eq!(doc.get("inf"), Some(&Value::Double(f64::NEG_INFINITY)));
+        assert_eq!(doc.get("bin"), Some(&Value::Binary(vec![1, 2, 3])));
+        assert_eq!(doc.get("legacy_bin"), Some(&Value::Binary(vec![1, 2, 3])));
+        assert_eq!(
+            doc.get("re"),
+            Some(&Value::RegularExpression { pattern: "^a".to_string(), options: "i".to_string() })
+        );
+        assert_eq!(
+            doc.get("legacy_re"),
+            Some(&Value::RegularExpression { pattern: "^b".to_string(), options: "m".to_string() })
+        );
+        assert_eq!(doc.get("ts"), Some(&Value::Timestamp((1 << 32) | 2)));
+        assert_eq!(doc.get("min"), Some(&Value::MinKey));
+        assert_eq!(doc.get("max"), Some(&Value::MaxKey));
+        assert_eq!(doc.get("code"), Some(&Value::JavaScriptCode("f()".to_string())));
+
+        let query = doc.get("query").and_then(Value::as_document).unwrap();
+        assert_eq!(query.get("$gt"), Some(&Value::Int32(5)));
+    }
+
+    #[test]
+    fn test_json_errors() {
+        assert!(from_json_str("[1, 2]").is_err());
+        assert!(from_json_str(r#"{"a": 1,}"#).is_err());
+        assert!(from_json_str(r#"{"a": 01}"#).is_err());
+        assert!(from_json_str(r#"{"a": "unterminated}"#).is_err());
+        assert!(from_json_str(r#"{"a": 1} extra"#).is_err());
+        assert!(from_json_str(r#"{"a": "\ud800"}"#).is_err());
+        assert!(from_json_str(r#"{"oid": {"$oid": "xyz"}}"#).is_err());
+        assert!(from_json_str(r#"{"d": {"$date": "yesterday"}}"#).is_err());
+        assert!(from_json_str(&format!("{}{}", "{\"a\":[".repeat(200), "]}".repeat(200))).is_err());
+
+        match from_json_str(r#"{"a": tru}"#) {
+            Err(DeserializeError::Json { offset, .. }) => assert_eq!(offset, 6),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_ndjson_reader() {
+        let input = "\u{FEFF}{\"a\": 1}\r\n\n   \n{\"a\": 2}\n{\"a\": \n{\"a\": 4}";
+        let mut reader = NdjsonReader::new(input.as_bytes());
+
+        assert_eq!(reader.next().unwrap().unwrap().get("a"), Some(&Value::Int32(1)));
+        assert_eq!(reader.line_number(), 1);
+        assert_eq!(reader.next().unwrap().unwrap().get("a"), Some(&Value::Int32(2)));
+        assert_eq!(reader.line_number(), 4);
+        match reader.next().unwrap() {
+            Err(DeserializeError::Line { line, .. }) => assert_eq!(line, 5),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(reader.next().unwrap().unwrap().get("a"), Some(&Value::Int32(4)));
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn test_ndjson_error_message_has_line() {
+        let error = NdjsonReader::new("{}\n{oops}\n".as_bytes())
+            .find_map(Result::err)
+            .unwrap();
+        assert!(error.to_string().starts_with("Line 2: "));
+    }
+
+    // -------------------------------------
+    //          Decoder Tests
+    // -------------------------------------
+
+    fn sample_document() -> Document {
+        let mut inner = Document::new();
+        inner.insert("flag", Value::Boolean(true));
+        let mut array = Array::new();
+        array.push(Value::Int32(1));
+        array.push(Value::String("two".to_string()));
+        array.push(Value::Document(inner.clone()));
+
+        let mut document = Document::new();
+        document.insert("double", Value::Double(1.5));
+        document.insert("string", Value::String("héllo".to_string()));
+        document.insert("document", Value::Document(inner));
+        document.insert("array", Value::Array(array));
+        document.insert("binary", Value::Binary(vec![0, 1, 2]));
+        document.insert("id", Value::ObjectId(ObjectId::from_bytes([7; 12])));
+        document.insert("date", Value::UTCDateTime(1_700_000_000));
+        document.insert("null", Value::Null);
+        document.insert(
+            "regex",
+            Value::RegularExpression {
+                pattern: "^a".to_string(),
+                options: "i".to_string(),
+            },
+        );
+        document.insert("code", Value::JavaScriptCode("f()".to_string()));
+        document.insert("int32", Value::Int32(-3));
+        document.insert("timestamp", Value::Timestamp((1 << 32) | 2));
+        document.insert("int64", Value::Int64(i64::MIN));
+        document.insert("uint64", Value::UInt64(u64::MAX));
+        document.insert("min", Value::MinKey);
+        document.insert("max", Value::MaxKey);
+        document
+    }
+
+    #[test]
+    fn test_from_bytes_round_trip() {
+        let document = sample_document();
+        let bytes = to_bytes(&document).unwrap();
+        assert_eq!(from_bytes(&bytes).unwrap(), document);
+        assert_eq!(from_reader(&bytes[..]).unwrap(), document);
+    }
+
+    #[test]
+    fn test_from_bytes_rejects_trailing_bytes() {
+        let mut bytes = to_bytes(&Document::new()).unwrap();
+        bytes.push(0);
+        assert!(matches!(from_bytes(&bytes), Err(DeserializeError::InvalidDocument(_))));
+    }
+
+    #[test]
+    fn test_from_bytes_truncated() {
+        let bytes = to_bytes(&sample_document()).unwrap();
+        assert!(matches!(
+            from_bytes(&bytes[..bytes.len() - 1]),
+            Err(DeserializeError::UnexpectedEof)
+        ));
+    }
+
+    #[test]
+    fn test_from_bytes_length_mismatch() {
+        // Declares 6 bytes but the terminator is the fifth
+        assert!(matches!(
+            from_bytes(&[6, 0, 0, 0, 0, 0]),
+            Err(DeserializeError::InvalidDocument(_))
+        ));
+        assert!(matches!(
+            from_bytes(&[4, 0, 0, 0, 0]),
+            Err(DeserializeError::InvalidLength(4))
+        ));
+    }
+
+    #[test]
+    fn test_from_bytes_string_overruns_document() {
+        // String claims 100 bytes inside a 13-byte document
+        let bytes = [13, 0, 0, 0, 0x02, b's', 0, 100, 0, 0, 0, 0, 0];
+        assert!(matches!(from_bytes(&bytes), Err(DeserializeError::InvalidDocument(_))));
+    }
+
+    #[test]
+    fn test_from_bytes_unknown_type() {
+        let bytes = [8, 0, 0, 0, 0x42, b'x', 0, 0];
+        assert!(matches!(
+            from_bytes(&bytes),
+            Err(DeserializeError::UnknownElementType(0x42))
+        ));
+    }
+
+    #[test]
+    fn test_decoder_next_document_sequence() {
+        let mut bytes = to_bytes(&sample_document()).unwrap();
+        bytes.extend(to_bytes(&Document::new()).unwrap());
+
+        let mut decoder = Decoder::new(&bytes[..]);
+        assert_eq!(decoder.next_document().unwrap(), Some(sample_document()));
+        assert_eq!(decoder.next_document().unwrap(), Some(Document::new()));
+        assert_eq!(decoder.next_document().unwrap(), None);
+        assert_eq!(decoder.position(), bytes.len() as u64);
+    }
+
+    // -------------------------------------
+    //          Transcode Tests
+    // -------------------------------------
+
+    #[test]
+    fn test_transcode_bson_to_json() {
+        let mut document = Document::new();
+        document.insert("a", Value::Array(Array::from(vec![Value::Int32(1), Value::Null])));
+        let mut bson = to_bytes(&document).unwrap();
+        bson.extend(to_bytes(&Document::new()).unwrap());
+
+        let mut json = Vec::new();
+        assert_eq!(transcode_bson_to_json(&bson[..], &mut json).unwrap(), 2);
+        assert_eq!(String::from_utf8(json).unwrap(), "{\"a\":[1,null]}\n{}\n");
+    }
+
+    #[test]
+    fn test_transcode_bson_to_json_matches_parser() {
+        let document = sample_document();
+        let mut json = Vec::new();
+        transcode_bson_to_json(&to_bytes(&document).unwrap()[..], &mut json).unwrap();
+
+        let parsed = from_json_str(std::str::from_utf8(&json).unwrap()).unwrap();
+        assert_eq!(parsed.get("id"), document.get("id"));
+        assert_eq!(parsed.get("date"), document.get("date"));
+        assert_eq!(parsed.get("timestamp"), document.get("timestamp"));
+        assert_eq!(parsed.get("array"), document.get("array"));
+        assert_eq!(parsed.get("double"), document.get("double"));
+    }
+
+    #[test]
+    fn test_transcode_bson_to_json_deprecated_types() {
+        // {"s": Symbol("x"), "u": undefined}
+        let bson = [
+            17, 0, 0, 0, 0x0E, b's', 0, 2, 0, 0, 0, b'x', 0, 0x06, b'u', 0, 0,
+        ];
+
+        let mut json = Vec::new();
+        transcode_bson_to_json(&bson[..], &mut json).unwrap();
+        assert_eq!(
+            String::from_utf8(json).unwrap(),
+            "{\"s\":{\"$symbol\":\"x\"},\"u\":{\"$undefined\":true}}\n"
+        );
+    }
+
+    #[test]
+    fn test_transcode_json_to_bson() {
+        let input = "{\"a\": [1, {\"b\": {\"$oid\": \"0102030405060708090a0b0c\"}}]}\n\n{}\n";
+        let mut bson = Vec::new();
+        assert_eq!(transcode_json_to_bson(input.as_bytes(), &mut bson).unwrap(), 2);
+
+        let mut decoder = Decoder::new(&bson[..]);
+        let first = decoder.next_document().unwrap().unwrap();
+        assert_eq!(first, from_json_str("{\"a\": [1, {\"b\": {\"$oid\": \"0102030405060708090a0b0c\"}}]}").unwrap());
+        assert_eq!(decoder.next_document().unwrap(), Some(Document::new()));
+        assert_eq!(decoder.next_document().unwrap(), None);
+    }
+
+    #[test]
+    fn test_transcode_json_to_bson_operator_document() {
+        let mut bson = Vec::new();
+        transcode_json_to_bson("{\"$set\": {\"a\": 1}}".as_bytes(), &mut bson).unwrap();
+        let document = from_bytes(&bson).unwrap();
+        assert!(document.get("$set").unwrap().as_document().is_some());
+    }
+
+    #[test]
+    fn test_transcode_json_to_bson_reports_line() {
+        let mut bson = Vec::new();
+        let error = transcode_json_to_bson("{}\n[1]\n".as_bytes(), &mut bson).unwrap_err();
+        assert!(matches!(error, TranscodeError::Line { line: 2, .. }));
+    }
+
+    #[test]
+    fn test_transcode_round_trip() {
+        let document = sample_document();
+        let bson = to_bytes(&document).unwrap();
+
+        let mut json = Vec::new();
+        transcode_bson_to_json(&bson[..], &mut json).unwrap();
+        let mut round_tripped = Vec::new();
+        transcode_json_to_bson(&json[..], &mut round_tripped).unwrap();
+
+        // Relaxed JSON does not keep integer widths or unsigned values
+        let decoded = from_bytes(&round_tripped).unwrap();
+        for key in ["double", "string", "document", "binary", "id", "date", "regex", "code", "min", "max"] {
+            assert_eq!(decoded.get(key), document.get(key), "field {}", key);
+        }
+    }
+
+    // -------------------------------------
+    //          Event Tests
+    // -------------------------------------
+
+    #[test]
+    fn test_bson_events_nested() {
+        let mut inner = Document::new();
+        inner.insert("x", Value::Null);
+        let mut document = Document::new();
+        document.insert(
+            "a",
+            Value::Array(Array::from(vec![Value::Int32(1), Value::Document(inner)])),
+        );
+        let bytes = to_bytes(&document).unwrap();
+
+        let events: Vec<_> = BsonEvents::new(&bytes).collect::<Result<_, _>>().unwrap();
+        assert_eq!(
+            events,
+            [
+                BsonEvent::StartDocument,
+                BsonEvent::Field("a"),
+                BsonEvent::StartArray,
+                BsonEvent::Scalar(Value::Int32(1)),
+                BsonEvent::StartDocument,
+                BsonEvent::Field("x"),
+                BsonEvent::Scalar(Value::Null),
+                BsonEvent::EndDocument,
+                BsonEvent::EndArray,
+                BsonEvent::EndDocument,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bson_events_depth() {
+        let mut document = Document::new();
+        document.insert("a", Value::Document(Document::new()));
+        let bytes = to_bytes(&document).unwrap();
+
+        let mut events = BsonEvents::new(&bytes);
+        assert_eq!(events.depth(), 0);
+        events.next();
+        events.next();
+        assert_eq!(events.next().unwrap().unwrap(), BsonEvent::StartDocument);
+        assert_eq!(events.depth(), 2);
+    }
+
+    #[test]
+    fn test_bson_events_error_ends_iteration() {
+        // {"a": 1, ...} followed by an unknown element type
+        let bytes = [16, 0, 0, 0, 0x10, b'a', 0, 1, 0, 0, 0, 0x42, b'b', 0, 0, 0];
+        let mut events = BsonEvents::new(&bytes);
+        assert_eq!(events.next().unwrap().unwrap(), BsonEvent::StartDocument);
+        assert_eq!(events.next().unwrap().unwrap(), BsonEvent::Field("a"));
+        assert_eq!(events.next().unwrap().unwrap(), BsonEvent::Scalar(Value::Int32(1)));
+        assert!(matches!(
+            events.next(),
+            Some(Err(DeserializeError::UnknownElementType(0x42)))
+        ));
+        assert!(events.next().is_none());
+    }
+
+    // -------------------------------------
+    //          Visitor Tests
+    // -------------------------------------
+
+    /// Records every callback as a string.
+    #[derive(Default)]
+    struct RecordingVisitor {
+        calls: Vec<String>,
+        skip: &'static str,
+        stop_at: &'static str,
+    }
+
+    impl DocumentVisitor for RecordingVisitor {
+        fn visit_field(&mut self, key: &str, element_type: u8) -> VisitControl {
+            self.calls.push(format!("field {} {:#04x}", key, element_type));
+            match key {
+                k if k == self.skip => VisitControl::Skip,
+                k if k == self.stop_at => VisitControl::Stop,
+                _ => VisitControl::Continue,
+            }
+        }
+
+        fn visit_value(&mut self, key: &str, value: Value) -> VisitControl {
+            self.calls.push(format!("value {} {}", key, value));
+            VisitControl::Continue
+        }
+
+        fn end_embedded(&mut self, key: &str) -> VisitControl {
+            self.calls.push(format!("end {}", key));
+            VisitControl::Continue
+        }
+    }
+
+    /// `{"a": {"b": 1}}` with an unknown element type inside "a".
+    fn visitor_document(corrupt: bool) -> Vec<u8> {
+        let mut inner = Document::new();
+        inner.insert("b", Value::Int32(1));
+        let mut document = Document::new();
+        document.insert("a", Value::Document(inner));
+        let mut bytes = to_bytes(&document).unwrap();
+        if corrupt {
+            bytes[11] = 0x42;
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_visit_all() {
+        let mut visitor = RecordingVisitor::default();
+        assert!(visit(&visitor_document(false), &mut visitor).unwrap());
+        assert_eq!(visitor.calls, ["field a 0x03", "field b 0x10", "value b 1", "end a"]);
+    }
+
+    #[test]
+    fn test_visit_skip_does_not_decode_subtree() {
+        let mut visitor = RecordingVisitor {
+            skip: "a",
+            ..Default::default()
+        };
+        assert!(visit(&visitor_document(true), &mut visitor).unwrap());
+        assert_eq!(visitor.calls, ["field a 0x03"]);
+
+        let mut visitor = RecordingVisitor::default();
+        assert!(matches!(
+            visit(&visitor_document(true), &mut visitor),
+            Err(DeserializeError::UnknownElementType(0x42))
+        ));
+    }
+
+    #[test]
+    fn test_visit_stop() {
+        let mut visitor = RecordingVisitor {
+            stop_at: "b",
+            ..Default::default()
+        };
+        assert!(!visit(&visitor_document(false), &mut visitor).unwrap());
+        assert_eq!(visitor.calls, ["field a 0x03", "field b 0x10"]);
+    }
+
+    // -------------------------------------
+    //          Projection Tests
+    // -------------------------------------
+
+    fn projection_document() -> Document {
+        let mut item = Document::new();
+        item.insert("sku", "A1");
+        item.insert("qty", 2);
+        let mut customer = Document::new();
+        customer.insert("name", "Ada");
+        customer.insert("email", "ada@example.com");
+
+        let mut document = Document::new();
+        document.insert("_id", 1);
+        document.insert("customer", customer);
+        document.insert(
+            "items",
+            Array::from(vec![Value::Document(item), Value::Int32(5)]),
+        );
+        document.insert("total", 9.5);
+        document
+    }
+
+    #[test]
+    fn test_decode_projected_paths() {
+        let bytes = to_bytes(&projection_document()).unwrap();
+        let projection = Projection::new(["total", "customer.name", "items.sku"]);
+        let projected = decode_projected(&bytes, &projection).unwrap();
+
+        let mut customer = Document::new();
+        customer.insert("name", "Ada");
+        let mut item = Document::new();
+        item.insert("sku", "A1");
+        let mut expected = Document::new();
+        expected.insert("total", 9.5);
+        expected.insert("customer", customer);
+        expected.insert("items", Array::from(vec![Value::Document(item)]));
+        assert_eq!(projected, expected);
+    }
+
+    #[test]
+    fn test_decode_projected_prefix_wins() {
+        let bytes = to_bytes(&projection_document()).unwrap();
+        let mut projection = Projection::new(["customer.name"]);
+        projection.include("customer");
+        projection.include("customer.email");
+
+        let projected = decode_projected(&bytes, &projection).unwrap();
+        assert_eq!(projected.get("customer"), projection_document().get("customer"));
+    }
+
+    #[test]
+    fn test_decode_projected_missing_and_scalar_paths() {
+        let bytes = to_bytes(&projection_document()).unwrap();
+        let projected = decode_projected(&bytes, &Projection::new(["missing", "total.x"])).unwrap();
+        assert!(projected.is_empty());
+        assert!(decode_projected(&bytes, &Projection::default()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_decode_projected_skips_unselected_corruption() {
+        // {"a": {"b": 1}} with an unknown type inside "a"
+        let bytes = visitor_document(true);
+        assert!(decode_projected(&bytes, &Projection::new(["z"])).unwrap().is_empty());
+        assert!(decode_projected(&bytes, &Projection::new(["a"])).is_err());
+    }
+
+    // -------------------------------------
+    //          Duplicate Key Tests
+    // -------------------------------------
+
+    /// `{"a": 1, "a": 2, "a": 3}`
+    fn duplicate_key_bytes() -> Vec<u8> {
+        let mut bytes = 26i32.to_le_bytes().to_vec();
+        for value in 1..=3i32 {
+            bytes.extend([0x10, b'a', 0]);
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes.push(0);
+        bytes
+    }
+
+    fn decode_with_policy(policy: DuplicateKeyPolicy) -> Result<Document, DeserializeError> {
+        from_bytes_with_options(
+            &duplicate_key_bytes(),
+            &DecoderOptions::new().duplicate_keys(policy),
+        )
+    }
+
+    #[test]
+    fn test_duplicate_keys_default_last_wins() {
+        assert_eq!(from_bytes(&duplicate_key_bytes()).unwrap().get("a"), Some(&Value::Int32(3)));
+        assert_eq!(
+            decode_with_policy(DuplicateKeyPolicy::LastWins).unwrap().get("a"),
+            Some(&Value::Int32(3))
+        );
+    }
+
+    #[test]
+    fn test_duplicate_keys_first_wins() {
+        assert_eq!(
+            decode_with_policy(DuplicateKeyPolicy::FirstWins).unwrap().get("a"),
+            Some(&Value::Int32(1))
+        );
+    }
+
+    #[test]
+    fn test_duplicate_keys_error() {
+        match decode_with_policy(DuplicateKeyPolicy::Error) {
+            Err(DeserializeError::DuplicateKey(key)) => assert_eq!(key, "a"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_duplicate_keys_collect_into_array() {
+        let document = decode_with_policy(DuplicateKeyPolicy::CollectIntoArray).unwrap();
+        assert_eq!(
+            document.get("a"),
+            Some(&Value::Array(Array::from(vec![
+                Value::Int32(1),
+                Value::Int32(2),
+                Value::Int32(3)
+            ])))
+        );
+
+        // A single occurrence, even of an array, is left alone
+        let mut single = Document::new();
+        single.insert("a", Array::from(vec![Value::Null]));
+        let bytes = to_bytes(&single).unwrap();
+        let options = DecoderOptions::new().duplicate_keys(DuplicateKeyPolicy::CollectIntoArray);
+        assert_eq!(from_bytes_with_options(&bytes, &options).unwrap(), single);
+    }
+
+    // -------------------------------------
+    //          UTF-8 Mode Tests
+    // -------------------------------------
+
+    /// `{"s": "a\xFFb"}`
+    const INVALID_UTF8: [u8; 16] = [16, 0, 0, 0, 0x02, b's', 0, 4, 0, 0, 0, b'a', 0xFF, b'b', 0, 0];
+
+    #[test]
+    fn test_utf8_strict() {
+        assert!(matches!(from_bytes(&INVALID_UTF8), Err(DeserializeError::Utf8(_))));
+    }
+
+    #[test]
+    fn test_utf8_lossy() {
+        let mut decoder = Decoder::with_options(&INVALID_UTF8[..], DecoderOptions::new().utf8(Utf8Mode::Lossy));
+        let document = decoder.decode_document().unwrap();
+        assert_eq!(document.get("s"), Some(&Value::String("a\u{FFFD}b".to_string())));
+        assert_eq!(decoder.warnings(), [DecodeWarning::InvalidUtf8 { offset: 7 }]);
+        assert_eq!(decoder.take_warnings().len(), 1);
+        assert!(decoder.warnings().is_empty());
+    }
+
+    #[test]
+    fn test_utf8_preserve() {
+        let options = DecoderOptions::new().utf8(Utf8Mode::Preserve);
+        let document = from_bytes_with_options(&INVALID_UTF8, &options).unwrap();
+        assert_eq!(document.get("s"), Some(&Value::Binary(vec![b'a', 0xFF, b'b'])));
+    }
+
+    // -------------------------------------
+    //          Generated Document Tests
+    // -------------------------------------
+
+    #[cfg(feature = "arbitrary")]
+    #[test]
+    fn test_arbitrary_documents_decode() {
+        use arbitrary::{Arbitrary, Unstructured};
+
+        let data: Vec<u8> = (0..8192u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8).collect();
+        let mut u = Unstructured::new(&data);
+        for _ in 0..32 {
+            let document = Document::arbitrary(&mut u).unwrap();
+            from_bytes(&to_bytes(&document).unwrap()).unwrap();
+        }
+    }
+
+    #[cfg(feature = "proptest")]
+    proptest::proptest! {
+        #[test]
+        fn test_proptest_documents_round_trip(document in proptest::prelude::any::<Document>()) {
+            proptest::prop_assert_eq!(from_bytes(&to_bytes(&document).unwrap()).unwrap(), document);
+        }
+    }
+
+    // -------------------------------------
+    //          Recovery Tests
+    // -------------------------------------
+
+    fn numbered_document(n: i32) -> Document {
+        let mut document = Document::new();
+        document.insert("n", n);
+        document
+    }
+
+    fn numbered(n: i32) -> Vec<u8> {
+        to_bytes(&numbered_document(n)).unwrap()
+    }
+
+    #[test]
+    fn test_recover_documents_clean() {
+        let bytes = [numbered(1), numbered(2)].concat();
+        let recovery = recover_documents(&bytes);
+        assert!(recovery.is_clean());
+        assert_eq!(recovery.documents.len(), 2);
+        assert_eq!(recovery.documents[1].0, numbered(1).len());
+    }
+
+    #[test]
+    fn test_recover_documents_skips_corrupt_document() {
+        let mut corrupt = numbered(2);
+        corrupt[4] = 0x42; // unknown element type
+        let bytes = [numbered(1), corrupt.clone(), numbered(3)].concat();
+
+        let recovery = recover_documents(&bytes);
+        let values: Vec<_> = recovery
+            .documents
+            .iter()
+            .map(|(_, document)| document.get("n").cloned())
+            .collect();
+        assert_eq!(values, [Some(Value::Int32(1)), Some(Value::Int32(3))]);
+
+        assert_eq!(recovery.corrupt_regions.len(), 1);
+        let region = &recovery.corrupt_regions[0];
+        assert_eq!(region.offset, numbered(1).len());
+        assert_eq!(region.length, corrupt.len());
+        assert!(matches!(region.error, DeserializeError::UnknownElementType(0x42)));
+    }
+
+    #[test]
+    fn test_recover_documents_bad_length_prefix() {
+        let mut corrupt = numbered(2);
+        corrupt[0] = 0xFF; // length far past the end of the input
+        let bytes = [corrupt, numbered(3)].concat();
+
+        let recovery = recover_documents(&bytes);
+        assert_eq!(recovery.documents.len(), 1);
+        assert_eq!(recovery.documents[0].1.get("n"), Some(&Value::Int32(3)));
+    }
+
+    #[test]
+    fn test_recover_documents_truncated_tail() {
+        let full = numbered(1);
+        let bytes = [full.clone(), full[..6].to_vec()].concat();
+        let recovery = recover_documents(&bytes);
+        assert_eq!(recovery.documents.len(), 1);
+        assert_eq!(recovery.bytes_skipped(), 6);
+        assert!(matches!(recovery.corrupt_regions[0].error, DeserializeError::UnexpectedEof));
+    }
+
+    // -------------------------------------
+    //          Decode Stats Tests
+    // -------------------------------------
+
+    fn stats_document() -> Document {
+        let mut items = Array::new();
+        for sku in ["abc", "defgh"] {
+            let mut item = Document::new();
+            item.insert("sku", sku);
+            items.push(item);
+        }
+        let mut document = Document::new();
+        document.insert("items", items);
+        document
+    }
+
+    #[test]
+    fn test_decode_stats_disabled_by_default() {
+        let bytes = to_bytes(&stats_document()).unwrap();
+        let mut decoder = Decoder::new(&bytes[..]);
+        decoder.decode_document().unwrap();
+        assert!(decoder.stats().is_none());
+    }
+
+    #[test]
+    fn test_decode_stats() {
+        let bytes = to_bytes(&stats_document()).unwrap();
+        let options = DecoderOptions::new().collect_stats(true);
+        let mut decoder = Decoder::with_options(&bytes[..], options);
+        decoder.decode_document().unwrap();
+        let stats = decoder.stats().unwrap();
+
+        assert_eq!(stats.documents(), 1);
+        assert_eq!(stats.max_depth(), 3);
+        assert_eq!(stats.element_count(0x02), 2);
+        assert_eq!(stats.element_count(0x03), 2);
+        assert_eq!(stats.element_count(0x04), 1);
+        assert_eq!(stats.element_count(0x10), 0);
+
+        // Array items add no path segment; the item fields add up under items.sku
+        assert_eq!(stats.bytes_per_path().len(), 2);
+        assert_eq!(stats.largest_paths(1), [("items", 56)]);
+        assert_eq!(stats.bytes_per_path()["items.sku"], 28);
+        assert_eq!(
+            stats.largest_strings(),
+            [("items.sku".to_string(), 5), ("items.sku".to_string(), 3)]
+        );
+    }
+
+    #[test]
+    fn test_decode_stats_accumulate_across_documents() {
+        let mut bytes = to_bytes(&stats_document()).unwrap();
+        bytes.extend(to_bytes(&stats_document()).unwrap());
+        let options = DecoderOptions::new().collect_stats(true);
+        let mut decoder = Decoder::with_options(&bytes[..], options);
+        while decoder.next_document().unwrap().is_some() {}
+        let stats = decoder.stats().unwrap();
+
+        assert_eq!(stats.documents(), 2);
+        assert_eq!(stats.element_count(0x02), 4);
+        assert_eq!(stats.bytes_per_path()["items"], 112);
+        assert_eq!(stats.largest_strings().len(), 4);
+    }
+
+    // -------------------------------------
+    //          Memory-Mapped Tests
+    // -------------------------------------
+
+    #[cfg(feature = "memmap2")]
+    #[test]
+    fn test_open_mmap() {
+        let path = std::env::temp_dir().join(format!("silentdb-mmap-{}.bson", std::process::id()));
+        let mut bytes = [numbered(1), numbered(2)].concat();
+        bytes.extend_from_slice(&numbered(3)[..6]);
+        std::fs::write(&path, &bytes).unwrap();
+
+        let mapped = Decoder::open_mmap(&path).unwrap();
+        assert_eq!(mapped.len(), bytes.len());
+        let mut iter = mapped.iter();
+        for n in 1..=2 {
+            let document = iter.next().unwrap().unwrap().to_document().unwrap();
+            assert_eq!(document.get("n"), Some(&Value::Int32(n)));
+        }
+        assert_eq!(iter.offset(), 2 * numbered(1).len());
+        assert!(matches!(iter.next(), Some(Err(DeserializeError::UnexpectedEof))));
+        assert!(iter.next().is_none());
+
+        drop(mapped);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    // -------------------------------------
+    //          Arena Tests
+    // -------------------------------------
+
+    #[cfg(feature = "bumpalo")]
+    #[test]
+    fn test_from_bytes_in() {
+        use crate::deser::{from_bytes_in, ArenaValue};
+
+        let mut arena = bumpalo::Bump::new();
+        let document = sample_document();
+        let bytes = to_bytes(&document).unwrap();
+        let decoded = from_bytes_in(&bytes, &arena).unwrap();
+        assert_eq!(decoded.len(), document.len());
+        assert_eq!(decoded.get("string"), Some(&ArenaValue::String("héllo")));
+        assert_eq!(decoded.get("binary"), Some(&ArenaValue::Binary(&[0, 1, 2])));
+        assert_eq!(decoded.to_document(), document);
+        assert!(arena.allocated_bytes() > 0);
+
+        assert!(from_bytes_in(&bytes[..bytes.len() - 1], &arena).is_err());
+        let undefined = [8, 0, 0, 0, 0x06, b'u', 0, 0];
+        assert!(matches!(
+            from_bytes_in(&undefined, &arena),
+            Err(DeserializeError::NotSupported(_))
+        ));
+        drop(decoded);
+        arena.reset();
+    }
+
+    // -------------------------------------
+    //        Document File Iterator Tests
+    // -------------------------------------
+
+    #[test]
+    fn test_document_file_iterator() {
+        let bytes = [numbered(1), numbered(2), numbered(3)].concat();
+        let mut documents = DocumentFileIterator::new(&bytes[..]);
+        for n in 1..=3 {
+            let document = documents.next().unwrap().unwrap();
+            assert_eq!(document.get("n"), Some(&Value::Int32(n)));
+            assert_eq!(documents.position(), n as u64 * numbered(1).len() as u64);
+        }
+        assert!(documents.next().is_none());
+        assert_eq!(documents.documents_read(), 3);
+    }
+
+    #[test]
+    fn test_document_file_iterator_resume_after_error() {
+        let len = numbered(1).len() as u64;
+        let bytes = [numbered(1), numbered(2)[..7].to_vec()].concat();
+        let mut documents = DocumentFileIterator::new(&bytes[..]);
+        assert!(documents.next().unwrap().is_ok());
+        assert!(matches!(documents.next(), Some(Err(DeserializeError::UnexpectedEof))));
+        assert!(documents.next().is_none());
+        assert_eq!(documents.position(), len);
+
+        // Once the rest of the data is available, pick up from the reported position
+        let bytes = [numbered(1), numbered(2), numbered(3)].concat();
+        let offset = documents.position();
+        let mut documents =
+            DocumentFileIterator::resume(&bytes[offset as usize..], offset, DecoderOptions::new());
+        let values: Vec<_> = documents.by_ref().map(|d| d.unwrap().get("n").cloned()).collect();
+        assert_eq!(values, [Some(Value::Int32(2)), Some(Value::Int32(3))]);
+        assert_eq!(documents.position(), 3 * len);
+    }
+
+    #[test]
+    fn test_document_file_iterator_open_at() {
+        let path = std::env::temp_dir().join(format!("silentdb-iter-{}.bson", std::process::id()));
+        std::fs::write(&path, [numbered(1), numbered(2)].concat()).unwrap();
+
+        let offset = numbered(1).len() as u64;
+        let documents: Vec<_> = DocumentFileIterator::open_at(&path, offset)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(documents.len(), 1);
+        assert_eq!(documents[0].get("n"), Some(&Value::Int32(2)));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    // -------------------------------------
+    //          Decode Into Tests
+    // -------------------------------------
+
+    #[test]
+    fn test_decode_into_replaces_contents() {
+        let mut document = Document::new();
+        document.insert("stale", "value");
+        document.insert("nested", stats_document());
+
+        let bytes = to_bytes(&sample_document()).unwrap();
+        decode_into(&bytes, &mut document).unwrap();
+        assert_eq!(document, sample_document());
+
+        decode_into(&to_bytes(&stats_document()).unwrap(), &mut document).unwrap();
+        assert_eq!(document, stats_document());
+    }
+
+    #[test]
+    fn test_decode_into_keeps_capacity() {
+        let mut document = Document::new_with_capacity(64);
+        let capacity = document.capacity();
+        decode_into(&numbered(1), &mut document).unwrap();
+        assert_eq!(document.capacity(), capacity);
+        assert_eq!(document.get("n"), Some(&Value::Int32(1)));
+    }
+
+    #[test]
+    fn test_decode_into_errors() {
+        let mut document = Document::new();
+        let bytes = [numbered(1), vec![0]].concat();
+        assert!(matches!(
+            decode_into(&bytes, &mut document),
+            Err(DeserializeError::InvalidDocument(_))
+        ));
+        assert!(matches!(
+            decode_into(&numbered(1)[..6], &mut document),
+            Err(DeserializeError::UnexpectedEof)
+        ));
+    }
+
+    #[test]
+    fn test_document_pool() {
+        let mut pool = DocumentPool::new(1);
+        let first = pool.decode(&numbered(1)).unwrap();
+        let second = pool.decode(&numbered(2)).unwrap();
+        pool.put(first);
+        pool.put(second);
+        assert_eq!(pool.idle(), 1);
+
+        let document = pool.decode(&numbered(3)).unwrap();
+        assert_eq!(document.get("n"), Some(&Value::Int32(3)));
+        assert_eq!(document.len(), 1);
+        pool.put(document);
+
+        assert!(pool.decode(&numbered(4)[..6]).is_err());
+        assert_eq!(pool.idle(), 1);
+        assert!(pool.get().is_empty());
+        assert_eq!(pool.idle(), 0);
+    }
+
+    #[test]
+    fn test_intern_keys() {
+        let bytes = [numbered(1), numbered(2), numbered(3)].concat();
+        let mut decoder = Decoder::with_options(&bytes[..], DecoderOptions::new().intern_keys(true));
+        let first = decoder.decode_document().unwrap();
+        let second = decoder.decode_document().unwrap();
+        let third = decoder.decode_document().unwrap();
+        assert_eq!(third.get("n"), Some(&Value::Int32(3)));
+
+        let key = |document: &Document| document.iter().next().unwrap().0.clone();
+        assert!(FieldName::ptr_eq(&key(&first), &key(&second)));
+        assert!(FieldName::ptr_eq(&key(&first), &key(&third)));
+
+        let stats = decoder.intern_stats().unwrap();
+        assert_eq!((stats.hits, stats.misses, stats.interned), (2, 1, 1));
+        assert_eq!(stats.bytes_saved, 2);
+
+        let mut decoder = Decoder::new(&bytes[..]);
+        decoder.decode_document().unwrap();
+        assert_eq!(decoder.intern_stats(), None);
+    }
+
+    // -------------------------------------
+    //          Encoding Header Tests
+    // -------------------------------------
+
+    #[test]
+    fn test_encoding_header_round_trip() {
+        let header = EncodingHeader::new().with_flags(HeaderFlags::CANONICAL);
+        let bytes = to_bytes_with_header(&sample_document(), &header).unwrap();
+        assert_eq!(&bytes[..6], &header.to_bytes());
+        assert_eq!(from_bytes(&bytes[6..]).unwrap(), sample_document());
+
+        assert_eq!(EncodingHeader::detect(&bytes).unwrap(), Some(header));
+        assert_eq!(from_bytes(&bytes).unwrap(), sample_document());
+
+        // Documents without a header are still read as plain BSON
+        let plain = to_bytes(&sample_document()).unwrap();
+        assert_eq!(EncodingHeader::detect(&plain).unwrap(), None);
+        assert_eq!(from_bytes(&plain).unwrap(), sample_document());
+    }
+
+    #[test]
+    fn test_encoding_header_newer_version() {
+        let header = EncodingHeader::new();
+        let mut bytes = to_bytes_with_header(&numbered_document(1), &header).unwrap();
+        bytes[4] = FORMAT_VERSION + 1;
+
+        assert!(matches!(
+            from_bytes(&bytes),
+            Err(DeserializeError::UnsupportedVersion(v)) if v == FORMAT_VERSION + 1
+        ));
+        let options = DecoderOptions::new().newer_versions(NewerVersionPolicy::Attempt);
+        assert_eq!(from_bytes_with_options(&bytes, &options).unwrap(), numbered_document(1));
+
+        // Unknown required flags are refused even when attempting newer versions
+        bytes[5] = 0x08;
+        assert!(matches!(
+            from_bytes_with_options(&bytes, &options),
+            Err(DeserializeError::UnsupportedFlags(0x08))
+        ));
+    }
+
+    #[test]
+    fn test_encoding_header_flags() {
+        let flags = HeaderFlags::from_bits(0x80) | HeaderFlags::CANONICAL;
+        let header = EncodingHeader::new().with_flags(flags);
+        let bytes = to_bytes_with_header(&numbered_document(2), &header).unwrap();
+        assert_eq!(from_bytes(&bytes).unwrap(), numbered_document(2));
+
+        let header = EncodingHeader::new().with_flags(HeaderFlags::COMPRESSED);
+        let bytes = to_bytes_with_header(&numbered_document(2), &header).unwrap();
+        assert!(matches!(from_bytes(&bytes), Err(DeserializeError::NotSupported(_))));
+
+        assert!(matches!(
+            EncodingHeader::detect(&bytes[..5]),
+            Err(DeserializeError::UnexpectedEof)
+        ));
+    }
+
+    // -------------------------------------
+    //          Legacy Type Tests
+    // -------------------------------------
+
+    /// `{"u": undefined, "p": DBPointer("db.c", 0x07 * 12), "s": Symbol("x")}`
+    fn legacy_bytes() -> Vec<u8> {
+        let mut elements = vec![0x06, b'u', 0];
+        elements.extend_from_slice(&[0x0C, b'p', 0, 5, 0, 0, 0, b'd', b'b', b'.', b'c', 0]);
+        elements.extend_from_slice(&[7; 12]);
+        elements.extend_from_slice(&[0x0E, b's', 0, 2, 0, 0, 0, b'x', 0]);
+        let mut bytes = ((elements.len() + 5) as i32).to_le_bytes().to_vec();
+        bytes.extend(elements);
+        bytes.push(0);
+        bytes
+    }
+
+    #[test]
+    fn test_legacy_types_rejected_by_default() {
+        assert!(matches!(from_bytes(&legacy_bytes()), Err(DeserializeError::NotSupported(_))));
+    }
+
+    #[test]
+    fn test_legacy_types_convert() {
+        let options = DecoderOptions::new().legacy_types(LegacyTypes::Convert);
+        let document = from_bytes_with_options(&legacy_bytes(), &options).unwrap();
+        assert_eq!(document.get("u"), Some(&Value::Null));
+        assert_eq!(document.get("s"), Some(&Value::String("x".to_string())));
+
+        let mut pointer = Document::new();
+        pointer.insert("collection", "db.c");
+        pointer.insert("id", ObjectId::from_bytes([7; 12]));
+        assert_eq!(document.get("p"), Some(&Value::Document(pointer)));
+    }
+
+    #[test]
+    fn test_legacy_types_preserve_round_trip() {
+        let options = DecoderOptions::new().legacy_types(LegacyTypes::Preserve);
+        let document = from_bytes_with_options(&legacy_bytes(), &options).unwrap();
+        assert_eq!(document.get("u"), Some(&Value::Undefined));
+        assert_eq!(document.get("s"), Some(&Value::Symbol("x".to_string())));
+        assert_eq!(
+            document.get("p"),
+            Some(&Value::DbPointer {
+                namespace: "db.c".to_string(),
+                id: ObjectId::from_bytes([7; 12]),
+            })
+        );
+
+        let bytes = to_bytes(&document).unwrap();
+        assert_eq!(bytes.len(), legacy_bytes().len());
+        assert_eq!(from_bytes_with_options(&bytes, &options).unwrap(), document);
+    }
+
+    #[test]
+    fn test_legacy_types_from_json() {
+        let document = from_json_str(
+            r#"{"s": {"$symbol": "x"}, "u": {"$undefined": true},
+                "p": {"$dbPointer": {"$ref": "db.c", "$id": {"$oid": "070707070707070707070707"}}}}"#,
+        )
+        .unwrap();
+        assert_eq!(document.get("s"), Some(&Value::Symbol("x".to_string())));
+        assert_eq!(document.get("u"), Some(&Value::Undefined));
+        assert!(matches!(document.get("p"), Some(Value::DbPointer { .. })));
+    }
+
+    // -------------------------------------
+    //          Trusted Decoding Tests
+    // -------------------------------------
+
+    #[test]
+    fn test_from_bytes_trusted_matches_from_bytes() {
+        let document = sample_document();
+        let bytes = to_bytes(&document).unwrap();
+        let trusted = unsafe { from_bytes_trusted(&bytes) }.unwrap();
+        assert_eq!(trusted, from_bytes(&bytes).unwrap());
+        assert_eq!(trusted, document);
+    }
+
+    #[test]
+    fn test_from_bytes_trusted_with_header_and_legacy_types() {
+        let bytes = to_bytes_with_header(&sample_document(), &EncodingHeader::new()).unwrap();
+        assert_eq!(unsafe { from_bytes_trusted(&bytes) }.unwrap(), sample_document());
+
+        let document = unsafe { from_bytes_trusted(&legacy_bytes()) }.unwrap();
+        assert_eq!(document.get("u"), Some(&Value::Undefined));
+        assert_eq!(document.get("s"), Some(&Value::Symbol("x".to_string())));
+    }
+
+    #[test]
+    fn test_from_bytes_trusted_rejects_truncated_input() {
+        let bytes = to_bytes(&sample_document()).unwrap();
+        for len in [0, 3, bytes.len() / 2, bytes.len() - 1] {
+            assert!(unsafe { from_bytes_trusted(&bytes[..len]) }.is_err());
+        }
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(matches!(
+            unsafe { from_bytes_trusted(&trailing) },
+            Err(DeserializeError::InvalidDocument(_))
+        ));
+    }
+
+    // -------------------------------------
+    //          Inspection Tests
+    // -------------------------------------
+
+    /// Returns the lines of `output` with runs of spaces collapsed.
+    fn collapsed_lines(output: &[u8]) -> Vec<String> {
+        std::str::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+            .collect()
+    }
+
+    #[test]
+    fn test_inspect_json() {
+        let mut first = Document::new();
+        first.insert("name", "ada");
+        first.insert("id", Value::Int64(1));
+        let mut bytes = to_bytes(&first).unwrap();
+        bytes.extend(to_bytes(&stats_document()).unwrap());
+
+        let mut out = Vec::new();
+        assert_eq!(inspect(&bytes[..], &mut out, &InspectOptions::new()).unwrap(), 2);
+        let lines: Vec<Document> = std::str::from_utf8(&out)
+            .unwrap()
+            .lines()
+            .map(|line| from_json_str(line).unwrap())
+            .collect();
+        assert_eq!(lines[1], stats_document());
+
+        let mut out = Vec::new();
+        let options = InspectOptions::new().canonical(true).field("id").field("items.sku");
+        inspect(&bytes[..], &mut out, &options).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&out).unwrap(),
+            "{\"id\":{\"$numberLong\":\"1\"}}\n{\"items\":[{\"sku\":\"abc\"},{\"sku\":\"defgh\"}]}\n"
+        );
+
+        // A truncated file fails after the documents before the cut
+        let mut out = Vec::new();
+        let cut = &bytes[..bytes.len() - 3];
+        assert!(inspect(cut, &mut out, &InspectOptions::new()).is_err());
+        assert_eq!(collapsed_lines(&out).len(), 1);
+    }
+
+    #[test]
+    fn test_inspect_stats() {
+        let mut bytes = to_bytes(&stats_document()).unwrap();
+        let document_len = bytes.len();
+        bytes.extend(to_bytes(&stats_document()).unwrap());
+
+        let mut out = Vec::new();
+        let options = InspectOptions::new().mode(InspectMode::Stats);
+        inspect(&bytes[..], &mut out, &options).unwrap();
+        let lines = collapsed_lines(&out);
+        assert_eq!(lines[0], "documents: 2");
+        assert_eq!(lines[1], format!("bytes: {}", document_len * 2));
+        for expected in ["document 4", "string 4", "array 2", "items 2 112", "items.sku 4 56"] {
+            assert!(lines.iter().any(|line| line == expected), "{}", expected);
+        }
+
+        // Only the selected paths are counted
+        let mut out = Vec::new();
+        inspect(&bytes[..], &mut out, &options.field("items.sku")).unwrap();
+        let lines = collapsed_lines(&out);
+        assert!(lines.iter().any(|line| line == "string 4"));
+        assert!(lines.iter().all(|line| !line.starts_with("document ")));
+        assert!(lines.iter().all(|line| !line.starts_with("items ")));
+    }
+
+    #[test]
+    fn test_hex_dump() {
+        let mut inner = Document::new();
+        inner.insert("n", 1);
+        let mut document = Document::new();
+        document.insert("s", "hi");
+        document.insert("d", inner);
+        let bytes = to_bytes(&document).unwrap();
+
+        let lines = collapsed_lines(hex_dump(&bytes).unwrap().as_bytes());
+        assert_eq!(
+            lines,
+            [
+                format!("00000000 {:02x} 00 00 00 document, {} bytes", bytes.len(), bytes.len()),
+                "00000004 02 73 00 \"s\": string".to_string(),
+                "00000007 03 00 00 00 68 69 00 \"hi\"".to_string(),
+                "0000000e 03 64 00 \"d\": document".to_string(),
+                "00000011 0c 00 00 00 document, 12 bytes".to_string(),
+                "00000015 10 6e 00 \"n\": int".to_string(),
+                "00000018 01 00 00 00 1".to_string(),
+                "0000001c 00 end".to_string(),
+                "0000001d 00 end".to_string(),
+            ]
+        );
+
+        // In a file, offsets run on across documents and fields can be picked out
+        let mut file = bytes.clone();
+        file.extend(&bytes);
+        let mut out = Vec::new();
+        let options = InspectOptions::new().mode(InspectMode::HexDump).field("d.n");
+        assert_eq!(inspect(&file[..], &mut out, &options).unwrap(), 2);
+        let lines = collapsed_lines(&out);
+        assert_eq!(lines.len(), 15);
+        assert!(lines.iter().all(|line| !line.contains("\"s\"")));
+        assert_eq!(lines[7], "");
+        assert!(lines[8].starts_with(&format!("{:08x} ", bytes.len())));
+        assert_eq!(lines[14], format!("{:08x} 00 end", bytes.len() * 2 - 1));
+    }
+}
@@ -0,0 +1,257 @@
+// src/deser/transcode.rs
+
+use std::io::{BufRead, BufReader, BufWriter, Cursor, Read, Write};
+
+use super::decoder::Decoder;
+use super::error::TranscodeError;
+use super::json::JsonParser;
+use super::MAX_NESTING_DEPTH;
+use crate::ser::{BsonSerializer, JsonSerializer, Serializer};
+use crate::types::Value;
+
+/// Streams the next BSON document from `decoder` into `serializer`, element
+/// by element, without building a `Document`.
+///
+/// Returns `false` if the input ended cleanly before another document.
+/// Deprecated types (undefined, DBPointer, symbol) are passed through to the
+/// serializer, which decides whether it can represent them.
+///
+/// # Errors
+///
+/// Returns an error if the BSON is malformed or the serializer fails.
+pub fn transcode_document<R: Read, S: Serializer>(
+    decoder: &mut Decoder<R>,
+    serializer: &mut S,
+) -> Result<bool, TranscodeError> {
+    if !decoder.try_begin_document()? {
+        return Ok(false);
+    }
+    transcode_body(decoder, serializer, false)?;
+    Ok(true)
+}
+
+/// Transcodes back-to-back BSON documents into newline-delimited relaxed
+/// Extended JSON, returning the number of documents written.
+///
+/// Both ends are buffered internally.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb_data_encoding::transcode_bson_to_json;
+/// let bson = [12, 0, 0, 0, 0x10, b'a', 0, 1, 0, 0, 0, 0];
+/// let mut json = Vec::new();
+/// assert_eq!(transcode_bson_to_json(&bson[..], &mut json).unwrap(), 1);
+/// assert_eq!(json, b"{\"a\":1}\n");
+/// ```
+///
+/// # Errors
+///
+/// Returns an error if the input is malformed or writing fails.
+pub fn transcode_bson_to_json<R: Read, W: Write>(
+    reader: R,
+    writer: W,
+) -> Result<usize, TranscodeError> {
+    let mut decoder = Decoder::new(BufReader::new(reader));
+    let mut serializer = JsonSerializer::new(BufWriter::new(writer), false);
+
+    let mut count = 0;
+    while transcode_document(&mut decoder, &mut serializer)? {
+        serializer.get_mut().write_all(b"\n")?;
+        count += 1;
+    }
+
+    serializer.get_mut().flush()?;
+    Ok(count)
+}
+
+/// Transcodes newline-delimited Extended JSON into back-to-back BSON
+/// documents, returning the number of documents written.
+///
+/// Each line is parsed straight into the BSON serializer; only Extended JSON
+/// type wrappers such as `{"$oid": "..."}` are materialized as values. Blank
+/// lines are skipped.
+///
+/// # Errors
+///
+/// Returns an error, tagged with the line number, if a line is not a JSON
+/// object or cannot be encoded. I/O errors are returned as is.
+pub fn transcode_json_to_bson<R: BufRead, W: Write>(
+    mut reader: R,
+    mut writer: W,
+) -> Result<usize, TranscodeError> {
+    let mut line = String::new();
+    let mut line_number = 0;
+    let mut buffer = Vec::new();
+    let mut count = 0;
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
+        line_number += 1;
+
+        let mut text = line.trim();
+        if line_number == 1 {
+            text = text.trim_start_matches('\u{FEFF}');
+        }
+        if text.is_empty() {
+            continue;
+        }
+
+        buffer.clear();
+        let mut serializer = BsonSerializer::new(Cursor::new(buffer));
+        let result = stream_json_document(&mut JsonParser::new(text), &mut serializer);
+        buffer = serializer.into_inner().into_inner();
+        result.map_err(|e| TranscodeError::Line {
+            line: line_number,
+            source: Box::new(e),
+        })?;
+
+        writer.write_all(&buffer)?;
+        count += 1;
+    }
+
+    writer.flush()?;
+    Ok(count)
+}
+
+/// Streams the elements of an opened document or array up to its terminator.
+fn transcode_body<R: Read, S: Serializer>(
+    decoder: &mut Decoder<R>,
+    serializer: &mut S,
+    is_array: bool,
+) -> Result<(), TranscodeError> {
+    if is_array {
+        serializer.start_array()?;
+    } else {
+        serializer.start_document()?;
+    }
+
+    while let Some((element_type, key)) = decoder.read_element_header()? {
+        serializer.serialize_field_name(&key)?;
+        match element_type {
+            0x03 | 0x04 => {
+                decoder.begin_document()?;
+                transcode_body(decoder, serializer, element_type == 0x04)?;
+            }
+            0x06 => serializer.serialize_undefined()?,
+            0x0C => {
+                let (namespace, id) = decoder.read_db_pointer()?;
+                serializer.serialize_db_pointer(&namespace, id)?;
+            }
+            0x0E => serializer.serialize_symbol(&decoder.read_string()?)?,
+            _ => decoder.read_value(element_type)?.serialize(serializer)?,
+        }
+    }
+    decoder.finish_document()?;
+
+    if is_array {
+        serializer.end_array()?;
+    } else {
+        serializer.end_document()?;
+    }
+    Ok(())
+}
+
+/// Streams a whole line holding one JSON object into `serializer`.
+fn stream_json_document<S: Serializer>(
+    parser: &mut JsonParser,
+    serializer: &mut S,
+) -> Result<(), TranscodeError> {
+    parser.skip_whitespace();
+    if parser.peek() != Some(b'{') {
+        return Err(parser.error("expected a JSON object").into());
+    }
+
+    if parser.at_operator_object() {
+        // Small enough to materialize; may still turn out to be a plain document
+        match parser.parse_value()? {
+            Value::Document(document) => serializer.serialize_document(&document)?,
+            other => {
+                return Err(parser
+                    .error(&format!("top-level JSON object is a type wrapper for {}", other))
+                    .into())
+            }
+        }
+    } else {
+        stream_json_container(parser, serializer, false, 0)?;
+    }
+
+    parser.skip_whitespace();
+    if parser.peek().is_some() {
+        return Err(parser.error("trailing characters after JSON object").into());
+    }
+    Ok(())
+}
+
+fn stream_json_value<S: Serializer>(
+    parser: &mut JsonParser,
+    serializer: &mut S,
+    depth: usize,
+) -> Result<(), TranscodeError> {
+    parser.skip_whitespace();
+    match parser.peek() {
+        Some(b'{') if !parser.at_operator_object() => {
+            stream_json_container(parser, serializer, false, depth + 1)
+        }
+        Some(b'[') => stream_json_container(parser, serializer, true, depth + 1),
+        _ => Ok(parser.parse_value()?.serialize(serializer)?),
+    }
+}
+
+fn stream_json_container<S: Serializer>(
+    parser: &mut JsonParser,
+    serializer: &mut S,
+    is_array: bool,
+    depth: usize,
+) -> Result<(), TranscodeError> {
+    if depth >= MAX_NESTING_DEPTH {
+        return Err(parser.error("maximum nesting depth exceeded").into());
+    }
+
+    let (open, close) = if is_array { (b'[', b']') } else { (b'{', b'}') };
+    parser.expect(open)?;
+    if is_array {
+        serializer.start_array()?;
+    } else {
+        serializer.start_document()?;
+    }
+
+    let mut index = 0usize;
+    if !parser.eat(close) {
+        loop {
+            if is_array {
+                serializer.serialize_field_name(&index.to_string())?;
+                index += 1;
+            } else {
+                parser.skip_whitespace();
+                if parser.peek() != Some(b'"') {
+                    return Err(parser.error("expected a string key").into());
+                }
+                let key = parser.parse_string()?;
+                parser.expect(b':')?;
+                serializer.serialize_field_name(&key)?;
+            }
+            stream_json_value(parser, serializer, depth)?;
+
+            if parser.eat(b',') {
+                continue;
+            }
+            if parser.eat(close) {
+                break;
+            }
+            return Err(parser
+                .error(&format!("expected ',' or '{}'", close as char))
+                .into());
+        }
+    }
+
+    if is_array {
+        serializer.end_array()?;
+    } else {
+        serializer.end_document()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,201 @@
+// src/deser/trusted.rs
+
+use super::error::DeserializeError;
+use super::header::split_header;
+use super::options::DecoderOptions;
+use super::MAX_NESTING_DEPTH;
+use crate::types::{Array, Document, ObjectId, Value};
+use crate::utils::scan::cstring_len;
+
+/// Decodes a document that SilentDB encoded itself, skipping checks that
+/// only matter for untrusted input.
+///
+/// Strings and keys are not validated as UTF-8, and embedded lengths are
+/// trusted instead of being checked against their enclosing document.
+/// Reads are still bounded by `bytes`, so truncated input fails with
+/// `UnexpectedEof` rather than reading out of bounds. Duplicate keys keep
+/// the last value and legacy types are preserved, matching what the
+/// encoder writes. `from_bytes` remains the default for everything else.
+///
+/// # Safety
+///
+/// Every key and string in `bytes` must be valid UTF-8. This holds for
+/// output of `to_bytes` that has been checksummed since it was written;
+/// it does not hold for arbitrary input.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb_data_encoding::{from_bytes_trusted, to_bytes, Document, Value};
+/// let mut document = Document::new();
+/// document.insert("name", "Ada");
+/// let bytes = to_bytes(&document).unwrap();
+///
+/// // The bytes were just produced by `to_bytes`.
+/// let decoded = unsafe { from_bytes_trusted(&bytes) }.unwrap();
+/// assert_eq!(decoded.get("name"), Some(&Value::from("Ada")));
+/// ```
+///
+/// # Errors
+///
+/// Returns an error if the bytes are truncated, contain an unknown element
+/// type, are nested too deeply, or are followed by trailing bytes.
+pub unsafe fn from_bytes_trusted(bytes: &[u8]) -> Result<Document, DeserializeError> {
+    let (_, bytes) = split_header(bytes, &DecoderOptions::default())?;
+    let mut reader = TrustedReader {
+        bytes,
+        position: 0,
+        depth: 0,
+    };
+    let document = reader.document()?;
+    if reader.position != bytes.len() {
+        return Err(DeserializeError::InvalidDocument(format!(
+            "{} trailing bytes after document",
+            bytes.len() - reader.position
+        )));
+    }
+    Ok(document)
+}
+
+/// A slice reader whose strings are assumed to be valid UTF-8.
+struct TrustedReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    depth: usize,
+}
+
+impl<'a> TrustedReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], DeserializeError> {
+        let bytes = self
+            .bytes
+            .get(self.position..self.position + len)
+            .ok_or(DeserializeError::UnexpectedEof)?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], DeserializeError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, DeserializeError> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    fn i32(&mut self) -> Result<i32, DeserializeError> {
+        Ok(i32::from_le_bytes(self.array()?))
+    }
+
+    fn i64(&mut self) -> Result<i64, DeserializeError> {
+        Ok(i64::from_le_bytes(self.array()?))
+    }
+
+    fn length(&mut self) -> Result<usize, DeserializeError> {
+        let length = self.i32()?;
+        usize::try_from(length)
+            .map_err(|_| DeserializeError::InvalidDocument(format!("invalid length {}", length)))
+    }
+
+    fn string(&mut self) -> Result<String, DeserializeError> {
+        let length = self.length()?;
+        let bytes = self.take(length)?;
+        let contents = bytes.get(..length.wrapping_sub(1)).unwrap_or_default();
+        // SAFETY: `from_bytes_trusted` requires every string to be valid UTF-8.
+        Ok(unsafe { String::from_utf8_unchecked(contents.to_vec()) })
+    }
+
+    fn cstring(&mut self) -> Result<String, DeserializeError> {
+        let rest = &self.bytes[self.position..];
+        let len = cstring_len(rest).ok_or(DeserializeError::UnexpectedEof)?;
+        self.position += len + 1;
+        // SAFETY: `from_bytes_trusted` requires every key to be valid UTF-8.
+        Ok(unsafe { String::from_utf8_unchecked(rest[..len].to_vec()) })
+    }
+
+    /// Reads a document or array, calling `element` for each of its elements.
+    fn container(
+        &mut self,
+        mut element: impl FnMut(&mut Self, u8) -> Result<(), DeserializeError>,
+    ) -> Result<(), DeserializeError> {
+        if self.depth >= MAX_NESTING_DEPTH {
+            return Err(DeserializeError::InvalidDocument(
+                "maximum nesting depth exceeded".to_string(),
+            ));
+        }
+        self.depth += 1;
+        // The length prefix is trusted; the terminator ends the document
+        self.i32()?;
+        loop {
+            match self.u8()? {
+                0x00 => break,
+                element_type => element(self, element_type)?,
+            }
+        }
+        self.depth -= 1;
+        Ok(())
+    }
+
+    fn document(&mut self) -> Result<Document, DeserializeError> {
+        let mut document = Document::new();
+        self.container(|reader, element_type| {
+            let key = reader.cstring()?;
+            document.insert(key, reader.value(element_type)?);
+            Ok(())
+        })?;
+        Ok(document)
+    }
+
+    fn value(&mut self, element_type: u8) -> Result<Value, DeserializeError> {
+        let value = match element_type {
+            0x01 => Value::Double(f64::from_le_bytes(self.array()?)),
+            0x02 => Value::String(self.string()?),
+            0x03 => Value::Document(self.document()?),
+            0x04 => {
+                let mut array = Array::new();
+                self.container(|reader, element_type| {
+                    let key_len = cstring_len(&reader.bytes[reader.position..])
+                        .ok_or(DeserializeError::UnexpectedEof)?;
+                    reader.position += key_len + 1;
+                    array.push(reader.value(element_type)?);
+                    Ok(())
+                })?;
+                Value::Array(array)
+            }
+            0x05 => {
+                let length = self.length()?;
+                let _subtype = self.u8()?;
+                Value::Binary(self.take(length)?.to_vec())
+            }
+            0x06 => Value::Undefined,
+            0x07 => Value::ObjectId(ObjectId::from_bytes(self.array()?)),
+            0x08 => Value::Boolean(self.u8()? != 0),
+            0x09 => Value::UTCDateTime(self.i64()?),
+            0x0A => Value::Null,
+            0x0B => Value::RegularExpression {
+                pattern: self.cstring()?,
+                options: self.cstring()?,
+            },
+            0x0C => Value::DbPointer {
+                namespace: self.string()?,
+                id: ObjectId::from_bytes(self.array()?),
+            },
+            0x0D => Value::JavaScriptCode(self.string()?),
+            0x0E => Value::Symbol(self.string()?),
+            0x0F => {
+                let _length = self.i32()?;
+                Value::JavaScriptCodeWithScope {
+                    code: self.string()?,
+                    scope: self.document()?,
+                }
+            }
+            0x10 => Value::Int32(self.i32()?),
+            0x11 => Value::Timestamp(self.i64()?),
+            0x12 => Value::Int64(self.i64()?),
+            0x13 => Value::UInt64(u64::from_le_bytes(self.array()?)),
+            0xFF => Value::MinKey,
+            0x7F => Value::MaxKey,
+            other => return Err(DeserializeError::UnknownElementType(other)),
+        };
+        Ok(value)
+    }
+}
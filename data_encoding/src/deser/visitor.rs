@@ -0,0 +1,118 @@
+// src/deser/visitor.rs
+
+use super::error::DeserializeError;
+use super::MAX_NESTING_DEPTH;
+use crate::raw::{RawDocument, RawIter};
+use crate::types::Value;
+
+/// What `visit` should do after a visitor callback.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VisitControl {
+    /// Keep going; descend into documents and arrays, decode scalars.
+    Continue,
+    /// Skip the current element's value, including a whole subtree, without
+    /// decoding it. Only meaningful from `visit_field`.
+    Skip,
+    /// Stop visiting immediately.
+    Stop,
+}
+
+/// Callbacks for `visit`. Every method defaults to `Continue`.
+pub trait DocumentVisitor {
+    /// Called for every element before its value is read.
+    ///
+    /// Returning `Skip` moves to the next sibling using the element's length,
+    /// so skipped values are neither decoded nor validated.
+    fn visit_field(&mut self, _key: &str, _element_type: u8) -> VisitControl {
+        VisitControl::Continue
+    }
+
+    /// Called with the decoded value of a scalar element that was not skipped.
+    fn visit_value(&mut self, _key: &str, _value: Value) -> VisitControl {
+        VisitControl::Continue
+    }
+
+    /// Called after the last element of an embedded document or array that was
+    /// not skipped; `key` is the key it was found under.
+    fn end_embedded(&mut self, _key: &str) -> VisitControl {
+        VisitControl::Continue
+    }
+}
+
+/// Walks the encoded document in `bytes`, calling `visitor` for each element.
+///
+/// Returns `Ok(true)` if the whole document was visited and `Ok(false)` if
+/// the visitor returned `Stop`.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb_data_encoding::{visit, DocumentVisitor, VisitControl, Value};
+/// /// Extracts the top-level "a" field and ignores everything else.
+/// struct FieldA(Option<Value>);
+///
+/// impl DocumentVisitor for FieldA {
+///     fn visit_field(&mut self, key: &str, _element_type: u8) -> VisitControl {
+///         if key == "a" { VisitControl::Continue } else { VisitControl::Skip }
+///     }
+///
+///     fn visit_value(&mut self, _key: &str, value: Value) -> VisitControl {
+///         self.0 = Some(value);
+///         VisitControl::Stop
+///     }
+/// }
+///
+/// let bytes = [12, 0, 0, 0, 0x10, b'a', 0, 1, 0, 0, 0, 0];
+/// let mut visitor = FieldA(None);
+/// assert_eq!(visit(&bytes, &mut visitor).unwrap(), false);
+/// assert_eq!(visitor.0, Some(Value::Int32(1)));
+/// ```
+///
+/// # Errors
+///
+/// Returns an error if a visited part of the document is malformed.
+pub fn visit<V: DocumentVisitor>(bytes: &[u8], visitor: &mut V) -> Result<bool, DeserializeError> {
+    let document = RawDocument::from_bytes(bytes)?;
+    Ok(visit_elements(document.iter(), visitor, 0)? == VisitControl::Continue)
+}
+
+/// Visits the elements of one document or array, returning `Stop` if the
+/// visitor stopped.
+fn visit_elements<V: DocumentVisitor>(
+    elements: RawIter,
+    visitor: &mut V,
+    depth: usize,
+) -> Result<VisitControl, DeserializeError> {
+    for element in elements {
+        let element = element?;
+        match visitor.visit_field(element.key(), element.element_type()) {
+            VisitControl::Continue => {}
+            VisitControl::Skip => continue,
+            VisitControl::Stop => return Ok(VisitControl::Stop),
+        }
+
+        let nested = match (element.as_document(), element.as_array()) {
+            (Some(document), _) => Some(document.iter()),
+            (_, Some(array)) => Some(array.iter()),
+            _ => None,
+        };
+        let control = match nested {
+            Some(elements) => {
+                if depth >= MAX_NESTING_DEPTH {
+                    return Err(DeserializeError::InvalidDocument(
+                        "maximum nesting depth exceeded".to_string(),
+                    ));
+                }
+                if visit_elements(elements, visitor, depth + 1)? == VisitControl::Stop {
+                    return Ok(VisitControl::Stop);
+                }
+                visitor.end_embedded(element.key())
+            }
+            None => visitor.visit_value(element.key(), element.value()?),
+        };
+        if control == VisitControl::Stop {
+            return Ok(VisitControl::Stop);
+        }
+    }
+    Ok(VisitControl::Continue)
+}
@@ -3,22 +3,79 @@
 // Declare modules
 mod deser;
 mod raw;
+mod redact;
 mod ser;
 mod types;
 mod utils;
 
+pub mod testing;
+
 // Re-export commonly used items
-pub use deser::{Decoder, from_bytes, from_reader};
-pub use ser::{Encoder, to_bytes, to_writer};
+pub use deser::{Decoder, DocumentFileIterator, from_bytes, from_reader};
+pub use deser::{decode_into, DocumentPool};
+pub use deser::from_bytes_trusted;
+#[cfg(feature = "bumpalo")]
+pub use deser::{from_bytes_in, ArenaArray, ArenaDocument, ArenaValue};
+pub use deser::{from_bytes_with_options, DecoderOptions, DuplicateKeyPolicy, LegacyTypes, Utf8Mode};
+pub use deser::{EncodingHeader, HeaderFlags, NewerVersionPolicy, FORMAT_VERSION, HEADER_LEN, HEADER_MAGIC};
+pub use deser::{DecodeStats, DecodeWarning, InternStats};
+pub use deser::{BsonEvent, BsonEvents};
+pub use deser::{visit, DocumentVisitor, VisitControl};
+pub use deser::{decode_projected, Projection};
+pub use deser::{recover_documents, CorruptRegion, Recovery};
+pub use deser::{hex_dump, inspect, InspectMode, InspectOptions};
+#[cfg(feature = "memmap2")]
+pub use deser::{MappedDocuments, MappedIter};
+pub use deser::{DeserializeError, Framer, peek_document_len, MAX_DOCUMENT_LEN, MIN_DOCUMENT_LEN};
+pub use deser::{from_json_str, from_json_value_str, NdjsonReader};
+pub use deser::{
+    from_cbor_bytes,
+    CBOR_TAG_MAX_KEY,
+    CBOR_TAG_MIN_KEY,
+    CBOR_TAG_OBJECT_ID,
+    CBOR_TAG_REGEX,
+    CBOR_TAG_TIMESTAMP,
+};
+pub use deser::{
+    from_msgpack_bytes,
+    MSGPACK_EXT_BSON_TIMESTAMP,
+    MSGPACK_EXT_MAX_KEY,
+    MSGPACK_EXT_MIN_KEY,
+    MSGPACK_EXT_OBJECT_ID,
+    MSGPACK_EXT_REGEX,
+};
+pub use deser::{
+    transcode_bson_to_json,
+    transcode_document,
+    transcode_json_to_bson,
+    TranscodeError,
+};
+pub use utils::validator::{validate, IssueKind, ValidationIssue, ValidationReport};
+pub use utils::buffer_pool::{BufferPool, BufferPoolStats, PooledBuffer};
+pub use raw::{RawArray, RawDocument, RawDocumentIndex, RawElement, RawIter};
+pub use raw::{apply_encoded_patch, Patch, PatchError, PatchOp};
+pub use raw::{raw_diff, RawChange, RawChangeKind};
+pub use redact::{Redaction, Redactor, RedactingSerializer};
+pub use ser::{Encoder, to_bytes, to_bytes_with_header, to_writer};
+pub use ser::{BsonSerializer, JsonSerializer, SerializeError, Serializer};
 pub use types::{
     Document,
+    FieldName,
+    Key,
+    KeyRef,
     Value,
+    Array,
     ObjectId,
     Timestamp,
+    FromValue,
+    IntoValue,
+    ValueConversionError,
     Binary,
     Regex,
     // ... other types TODO: add other types
 };
+#[cfg(feature = "serde_json")]
+pub use types::{BinaryPolicy, DatePolicy, JsonValueOptions, ObjectIdPolicy};
 
 // Optional: create a prelude module for convenient imports
 pub mod prelude {
@@ -0,0 +1,43 @@
+// src/raw/array.rs
+
+use super::document::{RawDocument, RawElement, RawIter};
+use crate::deser::DeserializeError;
+
+/// A borrowed view of an encoded BSON array.
+///
+/// Arrays are encoded as documents keyed `"0"`, `"1"`, ...; the keys are not
+/// checked.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RawArray<'a> {
+    document: RawDocument<'a>,
+}
+
+impl<'a> RawArray<'a> {
+    pub(crate) fn new(document: RawDocument<'a>) -> Self {
+        RawArray { document }
+    }
+
+    /// Returns the encoded bytes, including the length prefix and terminator.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.document.as_bytes()
+    }
+
+    /// Returns the array viewed as the document it is encoded as.
+    pub fn as_document(&self) -> RawDocument<'a> {
+        self.document
+    }
+
+    /// Returns an iterator over the array's elements.
+    pub fn iter(&self) -> RawIter<'a> {
+        self.document.iter()
+    }
+
+    /// Returns the element at `index`, decoding only the elements before it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a malformed element is found before `index`.
+    pub fn get(&self, index: usize) -> Result<Option<RawElement<'a>>, DeserializeError> {
+        self.iter().nth(index).transpose()
+    }
+}
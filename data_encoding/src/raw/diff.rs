@@ -0,0 +1,176 @@
+// src/raw/diff.rs
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use super::document::{RawDocument, RawElement};
+use crate::deser::DeserializeError;
+
+/// How a field differs between two encoded documents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RawChangeKind {
+    /// The field is only in the new document.
+    Added,
+    /// The field is only in the old document.
+    Removed,
+    /// The field is in both documents with a different type or value.
+    Modified,
+}
+
+/// A field that differs between two encoded documents, found by `raw_diff`.
+///
+/// Ranges cover the whole element (type byte, key and value) and are
+/// offsets into the old and new byte slices respectively.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawChange {
+    pub path: String,
+    pub kind: RawChangeKind,
+    pub old_range: Option<Range<usize>>,
+    pub new_range: Option<Range<usize>>,
+}
+
+/// Compares two encoded documents field by field without decoding values.
+///
+/// Both documents are walked in lockstep; fields are matched by position
+/// while the keys line up and by name once they don't. Embedded documents
+/// and arrays present on both sides are compared recursively, so a change
+/// deep inside is reported at its dotted path rather than as a change to
+/// the whole parent. Changes are reported in the old document's field
+/// order, followed by added fields in the new document's order.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb_data_encoding::{raw_diff, to_bytes, Document, RawChangeKind};
+/// let mut old = Document::new();
+/// old.insert("n", 1);
+/// let mut new = Document::new();
+/// new.insert("n", 2);
+///
+/// let (a, b) = (to_bytes(&old).unwrap(), to_bytes(&new).unwrap());
+/// let changes = raw_diff(&a, &b).unwrap();
+/// assert_eq!(changes.len(), 1);
+/// assert_eq!(changes[0].path, "n");
+/// assert_eq!(changes[0].kind, RawChangeKind::Modified);
+/// assert_eq!(changes[0].new_range, Some(4..11));
+/// ```
+///
+/// # Errors
+///
+/// Returns an error if either input is not a well-formed document.
+pub fn raw_diff(a: &[u8], b: &[u8]) -> Result<Vec<RawChange>, DeserializeError> {
+    let mut differ = Differ {
+        old: a,
+        new: b,
+        changes: Vec::new(),
+    };
+    differ.documents("", RawDocument::from_bytes(a)?, RawDocument::from_bytes(b)?)?;
+    Ok(differ.changes)
+}
+
+struct Differ<'a> {
+    old: &'a [u8],
+    new: &'a [u8],
+    changes: Vec<RawChange>,
+}
+
+impl<'a> Differ<'a> {
+    fn documents(
+        &mut self,
+        prefix: &str,
+        old: RawDocument<'a>,
+        new: RawDocument<'a>,
+    ) -> Result<(), DeserializeError> {
+        let new_elements = new.iter().collect::<Result<Vec<_>, _>>()?;
+        let mut matched = vec![false; new_elements.len()];
+        let mut by_key: Option<HashMap<&str, usize>> = None;
+
+        for (i, old_element) in old.iter().enumerate() {
+            let old_element = old_element?;
+            let key = old_element.key();
+
+            // Stay in lockstep while the keys line up, only index by key once they diverge
+            let index = match new_elements.get(i) {
+                Some(element) if element.key() == key => Some(i),
+                _ => by_key
+                    .get_or_insert_with(|| {
+                        let mut index = HashMap::with_capacity(new_elements.len());
+                        for (i, element) in new_elements.iter().enumerate().rev() {
+                            index.insert(element.key(), i);
+                        }
+                        index
+                    })
+                    .get(key)
+                    .copied(),
+            };
+
+            let path = join(prefix, key);
+            match index.filter(|index| !matched[*index]) {
+                Some(index) => {
+                    matched[index] = true;
+                    self.elements(path, old_element, new_elements[index])?;
+                }
+                None => self.changes.push(RawChange {
+                    path,
+                    kind: RawChangeKind::Removed,
+                    old_range: Some(self.range(self.old, &old_element)),
+                    new_range: None,
+                }),
+            }
+        }
+
+        for (element, _) in new_elements.iter().zip(matched).filter(|(_, matched)| !matched) {
+            self.changes.push(RawChange {
+                path: join(prefix, element.key()),
+                kind: RawChangeKind::Added,
+                old_range: None,
+                new_range: Some(self.range(self.new, element)),
+            });
+        }
+        Ok(())
+    }
+
+    fn elements(
+        &mut self,
+        path: String,
+        old: RawElement<'a>,
+        new: RawElement<'a>,
+    ) -> Result<(), DeserializeError> {
+        if old.element_type() == new.element_type() && old.value_bytes() == new.value_bytes() {
+            return Ok(());
+        }
+
+        match (old.element_type(), new.element_type()) {
+            (0x03, 0x03) => {
+                return self.documents(&path, old.as_document().unwrap(), new.as_document().unwrap())
+            }
+            (0x04, 0x04) => {
+                let (old_array, new_array) = (old.as_array().unwrap(), new.as_array().unwrap());
+                return self.documents(&path, old_array.as_document(), new_array.as_document());
+            }
+            _ => {}
+        }
+
+        self.changes.push(RawChange {
+            path,
+            kind: RawChangeKind::Modified,
+            old_range: Some(self.range(self.old, &old)),
+            new_range: Some(self.range(self.new, &new)),
+        });
+        Ok(())
+    }
+
+    /// Returns the range of `element` within `base`, which its value bytes are a subslice of.
+    fn range(&self, base: &[u8], element: &RawElement<'_>) -> Range<usize> {
+        let value_start = element.value_bytes().as_ptr() as usize - base.as_ptr() as usize;
+        let start = value_start - element.key().len() - 2;
+        start..value_start + element.value_bytes().len()
+    }
+}
+
+fn join(prefix: &str, key: &str) -> String {
+    match prefix {
+        "" => key.to_string(),
+        prefix => format!("{}.{}", prefix, key),
+    }
+}
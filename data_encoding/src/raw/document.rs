@@ -0,0 +1,284 @@
+// src/raw/document.rs
+
+use super::array::RawArray;
+use crate::deser::{Decoder, DeserializeError, MAX_DOCUMENT_LEN};
+use crate::types::{Document, Value};
+use crate::utils::scan::{cstring_len, str_from_utf8};
+
+/// A borrowed view of an encoded BSON document.
+///
+/// Construction only checks the outer framing; elements are validated as
+/// they are iterated, so reading one field does not require decoding the rest.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RawDocument<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> RawDocument<'a> {
+    /// Wraps `bytes`, which must hold exactly one document.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the length prefix does not match `bytes.len()` or
+    /// the document is not null-terminated.
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self, DeserializeError> {
+        let prefix: [u8; 4] = bytes
+            .get(..4)
+            .ok_or(DeserializeError::UnexpectedEof)?
+            .try_into()
+            .unwrap();
+        let length = i32::from_le_bytes(prefix);
+        if length < 5 {
+            return Err(DeserializeError::InvalidLength(length as i64));
+        }
+        if length as usize > MAX_DOCUMENT_LEN {
+            return Err(DeserializeError::DocumentTooLarge {
+                length: length as usize,
+                max: MAX_DOCUMENT_LEN,
+            });
+        }
+        if length as usize != bytes.len() {
+            return Err(DeserializeError::InvalidDocument(format!(
+                "length prefix {} does not match {} available bytes",
+                length,
+                bytes.len()
+            )));
+        }
+        if bytes[bytes.len() - 1] != 0 {
+            return Err(DeserializeError::InvalidDocument(
+                "document is not null-terminated".to_string(),
+            ));
+        }
+        Ok(RawDocument { bytes })
+    }
+
+    /// Returns the encoded bytes, including the length prefix and terminator.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    /// Returns an iterator over the elements of the document.
+    pub fn iter(&self) -> RawIter<'a> {
+        RawIter {
+            bytes: self.bytes,
+            position: 4,
+            done: false,
+        }
+    }
+
+    /// Returns the first element named `key`, decoding only the elements before it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a malformed element is found before `key`.
+    pub fn get(&self, key: &str) -> Result<Option<RawElement<'a>>, DeserializeError> {
+        for element in self.iter() {
+            let element = element?;
+            if element.key() == key {
+                return Ok(Some(element));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Decodes the whole document.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any element is malformed.
+    pub fn to_document(&self) -> Result<Document, DeserializeError> {
+        crate::deser::from_bytes(self.bytes)
+    }
+}
+
+impl<'a> IntoIterator for RawDocument<'a> {
+    type Item = Result<RawElement<'a>, DeserializeError>;
+    type IntoIter = RawIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// A single element of a `RawDocument`: its key, type byte and value bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RawElement<'a> {
+    key: &'a str,
+    element_type: u8,
+    value: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> RawElement<'a> {
+    /// Returns the element's key.
+    pub fn key(&self) -> &'a str {
+        self.key
+    }
+
+    /// Returns the BSON type byte of the element.
+    pub fn element_type(&self) -> u8 {
+        self.element_type
+    }
+
+    /// Returns the encoded value, without the type byte and key.
+    pub fn value_bytes(&self) -> &'a [u8] {
+        self.value
+    }
+
+    /// Returns the offset of the element's type byte within its document.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns the value as a raw document if it is an embedded document.
+    pub fn as_document(&self) -> Option<RawDocument<'a>> {
+        match self.element_type {
+            0x03 => Some(RawDocument { bytes: self.value }),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as a raw array if it is an array.
+    pub fn as_array(&self) -> Option<RawArray<'a>> {
+        match self.element_type {
+            0x04 => Some(RawArray::new(RawDocument { bytes: self.value })),
+            _ => None,
+        }
+    }
+
+    /// Decodes the value.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value is malformed or of a deprecated type.
+    pub fn value(&self) -> Result<Value, DeserializeError> {
+        let mut decoder = Decoder::new(self.value);
+        let value = decoder.read_value(self.element_type)?;
+        if decoder.position() != self.value.len() as u64 {
+            return Err(DeserializeError::InvalidDocument(format!(
+                "value of '{}' has trailing bytes",
+                self.key
+            )));
+        }
+        Ok(value)
+    }
+}
+
+/// Iterator over the elements of a `RawDocument`.
+///
+/// Yields an error and then stops if an element is malformed.
+#[derive(Debug, Clone)]
+pub struct RawIter<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    done: bool,
+}
+
+impl<'a> RawIter<'a> {
+    fn invalid(&self, message: &str) -> DeserializeError {
+        DeserializeError::InvalidDocument(format!("{} at offset {}", message, self.position))
+    }
+
+    fn next_element(&mut self) -> Result<Option<RawElement<'a>>, DeserializeError> {
+        // The final byte is the terminator, so elements must end before it
+        let end = self.bytes.len() - 1;
+        let offset = self.position;
+        if offset == end {
+            // Embedded documents are only framed by their parent, so check here
+            if self.bytes[end] != 0 {
+                return Err(self.invalid("document is not null-terminated"));
+            }
+            return Ok(None);
+        }
+        let element_type = self.bytes[offset];
+        if element_type == 0x00 {
+            return Err(self.invalid("terminator before the end of the document"));
+        }
+
+        let key_start = offset + 1;
+        let key_len = cstring_len(&self.bytes[key_start..end])
+            .ok_or_else(|| self.invalid("unterminated key"))?;
+        let key = str_from_utf8(&self.bytes[key_start..key_start + key_len])
+            .map_err(|_| self.invalid("key is not valid UTF-8"))?;
+
+        let value_start = key_start + key_len + 1;
+        let value_len = element_value_len(element_type, &self.bytes[value_start..end])
+            .map_err(|e| match e {
+                DeserializeError::UnexpectedEof => self.invalid("element overruns the document"),
+                e => e,
+            })?;
+
+        self.position = value_start + value_len;
+        Ok(Some(RawElement {
+            key,
+            element_type,
+            value: &self.bytes[value_start..self.position],
+            offset,
+        }))
+    }
+}
+
+impl<'a> Iterator for RawIter<'a> {
+    type Item = Result<RawElement<'a>, DeserializeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.next_element() {
+            Ok(Some(element)) => Some(Ok(element)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Returns the encoded length of a value of `element_type` at the start of
+/// `bytes`, checking that it fits.
+///
+/// # Errors
+///
+/// Returns `UnexpectedEof` if the value does not fit in `bytes`, and an error
+/// for negative lengths or unknown types.
+pub(crate) fn element_value_len(element_type: u8, bytes: &[u8]) -> Result<usize, DeserializeError> {
+    let prefix = |min: i32| -> Result<usize, DeserializeError> {
+        let prefix: [u8; 4] = bytes
+            .get(..4)
+            .ok_or(DeserializeError::UnexpectedEof)?
+            .try_into()
+            .unwrap();
+        let length = i32::from_le_bytes(prefix);
+        if length < min {
+            return Err(DeserializeError::InvalidLength(length as i64));
+        }
+        Ok(length as usize)
+    };
+
+    let len = match element_type {
+        0x01 | 0x09 | 0x11 | 0x12 | 0x13 => 8,
+        0x02 | 0x0D | 0x0E => 4 + prefix(1)?,
+        0x03 | 0x04 | 0x0F => prefix(5)?,
+        0x05 => 5 + prefix(0)?,
+        0x06 | 0x0A | 0xFF | 0x7F => 0,
+        0x07 => 12,
+        0x08 => 1,
+        0x0B => {
+            let pattern = cstring_len(bytes).ok_or(DeserializeError::UnexpectedEof)? + 1;
+            pattern + cstring_len(&bytes[pattern..]).ok_or(DeserializeError::UnexpectedEof)? + 1
+        }
+        0x0C => 4 + prefix(1)? + 12,
+        0x10 => 4,
+        other => return Err(DeserializeError::UnknownElementType(other)),
+    };
+
+    if len > bytes.len() {
+        return Err(DeserializeError::UnexpectedEof);
+    }
+    Ok(len)
+}
@@ -0,0 +1,90 @@
+// src/raw/index.rs
+
+use std::collections::HashMap;
+
+use super::document::{RawDocument, RawElement};
+use crate::deser::DeserializeError;
+
+impl<'a> RawDocument<'a> {
+    /// Walks the document once and builds a `RawDocumentIndex` of its
+    /// top-level fields, so later lookups by key don't rescan the elements.
+    ///
+    /// Worth it when the same document is probed for several fields, as an
+    /// index scan does; for a single lookup `RawDocument::get` is cheaper.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use silentdb_data_encoding::{to_bytes, Document, RawDocument, Value};
+    /// let mut document = Document::new();
+    /// document.insert("a", 1);
+    /// document.insert("b", "two");
+    /// let bytes = to_bytes(&document).unwrap();
+    ///
+    /// let index = RawDocument::from_bytes(&bytes).unwrap().index().unwrap();
+    /// assert_eq!(index.len(), 2);
+    /// assert_eq!(index.get("a").unwrap().value().unwrap(), Value::Int32(1));
+    /// assert!(index.get("c").is_none());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any top-level element is malformed.
+    pub fn index(&self) -> Result<RawDocumentIndex<'a>, DeserializeError> {
+        let mut fields = HashMap::new();
+        for element in self.iter() {
+            let element = element?;
+            // Keep the first occurrence, matching RawDocument::get
+            fields.entry(element.key()).or_insert(element);
+        }
+        Ok(RawDocumentIndex {
+            document: *self,
+            fields,
+        })
+    }
+}
+
+/// A `RawDocument` with a side index of its top-level fields, built by
+/// `RawDocument::index`.
+#[derive(Debug, Clone)]
+pub struct RawDocumentIndex<'a> {
+    document: RawDocument<'a>,
+    fields: HashMap<&'a str, RawElement<'a>>,
+}
+
+impl<'a> RawDocumentIndex<'a> {
+    /// Returns the indexed document.
+    pub fn document(&self) -> RawDocument<'a> {
+        self.document
+    }
+
+    /// Returns the element named `key`.
+    pub fn get(&self, key: &str) -> Option<RawElement<'a>> {
+        self.fields.get(key).copied()
+    }
+
+    /// Returns the offset of the type byte of the element named `key` within the document.
+    pub fn offset(&self, key: &str) -> Option<usize> {
+        self.fields.get(key).map(RawElement::offset)
+    }
+
+    /// Returns `true` if the document has a field named `key`.
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.fields.contains_key(key)
+    }
+
+    /// Returns the number of distinct top-level keys.
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    /// Returns `true` if the document has no fields.
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Returns the indexed keys in arbitrary order.
+    pub fn keys(&self) -> impl Iterator<Item = &'a str> + '_ {
+        self.fields.keys().copied()
+    }
+}
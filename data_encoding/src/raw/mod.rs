@@ -0,0 +1,14 @@
+// src/raw/mod.rs
+
+mod array;
+mod diff;
+mod document;
+mod index;
+mod patch;
+mod test;
+
+pub use array::RawArray;
+pub use diff::{raw_diff, RawChange, RawChangeKind};
+pub use document::{RawDocument, RawElement, RawIter};
+pub use index::RawDocumentIndex;
+pub use patch::{apply_encoded_patch, Patch, PatchError, PatchOp};
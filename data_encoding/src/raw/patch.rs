@@ -0,0 +1,281 @@
+// src/raw/patch.rs
+
+use super::document::{RawDocument, RawElement};
+use crate::deser::DeserializeError;
+use crate::ser::{to_bytes, SerializeError};
+use crate::types::{Document, Value};
+
+/// Errors that can occur while applying a `Patch`.
+#[derive(Debug, thiserror::Error)]
+pub enum PatchError {
+    #[error(transparent)]
+    Deserialize(#[from] DeserializeError),
+    #[error(transparent)]
+    Serialize(#[from] SerializeError),
+    #[error("Cannot apply patch at '{0}': parent is not a document or array")]
+    InvalidPath(String),
+}
+
+/// A single change made by a `Patch`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatchOp {
+    /// Sets the field at a dotted path, creating missing parent documents.
+    Set { path: String, value: Value },
+    /// Removes the field at a dotted path, if present.
+    Unset { path: String },
+}
+
+/// A list of field changes to apply to an encoded document with `apply_encoded_patch`.
+///
+/// Paths are dotted field names; a numeric segment indexes into an array.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Patch {
+    ops: Vec<PatchOp>,
+}
+
+impl Patch {
+    /// Creates an empty patch.
+    pub fn new() -> Self {
+        Patch::default()
+    }
+
+    /// Adds an operation setting `path` to `value`.
+    pub fn set<V: Into<Value>>(mut self, path: &str, value: V) -> Self {
+        self.ops.push(PatchOp::Set {
+            path: path.to_string(),
+            value: value.into(),
+        });
+        self
+    }
+
+    /// Adds an operation removing `path`.
+    pub fn unset(mut self, path: &str) -> Self {
+        self.ops.push(PatchOp::Unset {
+            path: path.to_string(),
+        });
+        self
+    }
+
+    /// Returns the operations in the order they are applied.
+    pub fn ops(&self) -> &[PatchOp] {
+        &self.ops
+    }
+
+    /// Returns `true` if the patch makes no changes.
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+}
+
+/// Applies `patch` to the encoded document `raw_doc`, returning the patched bytes.
+///
+/// When every operation overwrites an existing fixed-width scalar with a
+/// value of the same type (a double, int, long, date, bool, ObjectId, ...),
+/// only those value bytes are rewritten and the rest of the document is
+/// copied as is. Otherwise the document is decoded, patched and re-encoded,
+/// which does not preserve field order.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb_data_encoding::{apply_encoded_patch, from_bytes, to_bytes, Document, Patch, Value};
+/// let mut document = Document::new();
+/// document.insert("count", 1);
+/// let bytes = to_bytes(&document).unwrap();
+///
+/// let patched = apply_encoded_patch(&bytes, &Patch::new().set("count", 2)).unwrap();
+/// assert_eq!(patched.len(), bytes.len());
+/// assert_eq!(from_bytes(&patched).unwrap().get("count"), Some(&Value::Int32(2)));
+/// ```
+///
+/// # Errors
+///
+/// Returns an error if `raw_doc` is malformed, if a path runs through a
+/// value that is not a document or array, or if the result cannot be encoded.
+pub fn apply_encoded_patch(raw_doc: &[u8], patch: &Patch) -> Result<Vec<u8>, PatchError> {
+    let document = RawDocument::from_bytes(raw_doc)?;
+
+    let mut overwrites = Vec::with_capacity(patch.ops.len());
+    for op in &patch.ops {
+        match in_place_overwrite(raw_doc, document, op)? {
+            Some(overwrite) => overwrites.push(overwrite),
+            None => return reencode(document, patch),
+        }
+    }
+
+    let mut bytes = raw_doc.to_vec();
+    for (offset, value) in overwrites {
+        bytes[offset..offset + value.len()].copy_from_slice(&value);
+    }
+    Ok(bytes)
+}
+
+/// Returns the absolute offset and new value bytes if `op` can be applied
+/// by overwriting an existing value of the same type and width.
+fn in_place_overwrite(
+    raw_doc: &[u8],
+    document: RawDocument<'_>,
+    op: &PatchOp,
+) -> Result<Option<(usize, Vec<u8>)>, PatchError> {
+    let PatchOp::Set { path, value } = op else {
+        return Ok(None);
+    };
+    let Some((element_type, bytes)) = fixed_width_bytes(value) else {
+        return Ok(None);
+    };
+    let Some(element) = find_raw(document, path)? else {
+        return Ok(None);
+    };
+    if element.element_type() != element_type {
+        return Ok(None);
+    }
+
+    // Element values are subslices of raw_doc, so the offset is their distance from its start
+    let offset = element.value_bytes().as_ptr() as usize - raw_doc.as_ptr() as usize;
+    Ok(Some((offset, bytes)))
+}
+
+/// Finds the element at a dotted path, descending through documents and arrays.
+fn find_raw<'a>(
+    document: RawDocument<'a>,
+    path: &str,
+) -> Result<Option<RawElement<'a>>, PatchError> {
+    let mut current = document;
+    let mut segments = path.split('.').peekable();
+    while let Some(segment) = segments.next() {
+        let Some(element) = current.get(segment)? else {
+            return Ok(None);
+        };
+        if segments.peek().is_none() {
+            return Ok(Some(element));
+        }
+        current = match (element.as_document(), element.as_array()) {
+            (Some(document), _) => document,
+            (_, Some(array)) => array.as_document(),
+            _ => return Ok(None),
+        };
+    }
+    Ok(None)
+}
+
+/// Returns the type byte and encoded bytes of a fixed-width scalar.
+fn fixed_width_bytes(value: &Value) -> Option<(u8, Vec<u8>)> {
+    let encoded = match value {
+        Value::Double(value) => (0x01, value.to_le_bytes().to_vec()),
+        Value::ObjectId(id) => (0x07, id.as_bytes().to_vec()),
+        Value::Boolean(value) => (0x08, vec![*value as u8]),
+        Value::UTCDateTime(value) => (0x09, value.to_le_bytes().to_vec()),
+        Value::Int32(value) => (0x10, value.to_le_bytes().to_vec()),
+        Value::Timestamp(value) => (0x11, value.to_le_bytes().to_vec()),
+        Value::Int64(value) => (0x12, value.to_le_bytes().to_vec()),
+        Value::UInt64(value) => (0x13, value.to_le_bytes().to_vec()),
+        _ => return None,
+    };
+    Some(encoded)
+}
+
+/* Re-encode Fallback */
+
+fn reencode(document: RawDocument<'_>, patch: &Patch) -> Result<Vec<u8>, PatchError> {
+    let mut document = document.to_document()?;
+    for op in &patch.ops {
+        match op {
+            PatchOp::Set { path, value } => set_path(&mut document, path, value.clone())?,
+            PatchOp::Unset { path } => unset_path(&mut document, path)?,
+        }
+    }
+    Ok(to_bytes(&document)?)
+}
+
+/// Splits a dotted path into its parent segments and final key.
+fn split_path(path: &str) -> (Vec<&str>, &str) {
+    let mut segments: Vec<&str> = path.split('.').collect();
+    let last = segments.pop().unwrap();
+    (segments, last)
+}
+
+fn set_path(document: &mut Document, path: &str, value: Value) -> Result<(), PatchError> {
+    let (parents, last) = split_path(path);
+    let mut current = Value::Document(std::mem::take(document));
+    let result = set_in(&mut current, &parents, last, value, path);
+    if let Value::Document(restored) = current {
+        *document = restored;
+    }
+    result
+}
+
+fn set_in(
+    target: &mut Value,
+    parents: &[&str],
+    last: &str,
+    value: Value,
+    path: &str,
+) -> Result<(), PatchError> {
+    let invalid = || PatchError::InvalidPath(path.to_string());
+    match (target, parents.split_first()) {
+        (Value::Document(document), None) => {
+            document.insert(last, value);
+            Ok(())
+        }
+        (Value::Array(array), None) => {
+            let slot = index(last).and_then(|i| array.get_mut(i)).ok_or_else(invalid)?;
+            *slot = value;
+            Ok(())
+        }
+        (Value::Document(document), Some((segment, rest))) => {
+            if !document.contains_key(segment) {
+                document.insert(*segment, Document::new());
+            }
+            let child = document.get_mut(segment).unwrap();
+            set_in(child, rest, last, value, path)
+        }
+        (Value::Array(array), Some((segment, rest))) => {
+            let child = index(segment).and_then(|i| array.get_mut(i)).ok_or_else(invalid)?;
+            set_in(child, rest, last, value, path)
+        }
+        _ => Err(invalid()),
+    }
+}
+
+fn unset_path(document: &mut Document, path: &str) -> Result<(), PatchError> {
+    let (parents, last) = split_path(path);
+    let Some((first, rest)) = parents.split_first() else {
+        document.remove(last);
+        return Ok(());
+    };
+
+    let mut current = match document.get_mut(first) {
+        Some(value) => value,
+        None => return Ok(()),
+    };
+    for segment in rest {
+        let next = match current {
+            Value::Document(document) => document.get_mut(segment),
+            Value::Array(array) => index(segment).and_then(|i| array.get_mut(i)),
+            _ => return Err(PatchError::InvalidPath(path.to_string())),
+        };
+        current = match next {
+            Some(value) => value,
+            None => return Ok(()),
+        };
+    }
+
+    match current {
+        Value::Document(document) => {
+            document.remove(last);
+            Ok(())
+        }
+        // Removing an array item would shift the others, so null it like $unset does
+        Value::Array(array) => {
+            if let Some(slot) = index(last).and_then(|i| array.get_mut(i)) {
+                *slot = Value::Null;
+            }
+            Ok(())
+        }
+        _ => Err(PatchError::InvalidPath(path.to_string())),
+    }
+}
+
+fn index(segment: &str) -> Option<usize> {
+    segment.parse().ok()
+}
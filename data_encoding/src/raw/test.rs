@@ -0,0 +1,297 @@
+#[cfg(test)]
+mod tests {
+    use crate::deser::DeserializeError;
+    use crate::deser::from_bytes;
+    use crate::raw::{
+        apply_encoded_patch, raw_diff, Patch, PatchError, RawChange, RawChangeKind, RawDocument,
+    };
+    use crate::ser::to_bytes;
+    use crate::types::{Array, Document, Value};
+
+    fn sample_bytes() -> Vec<u8> {
+        let mut inner = Document::new();
+        inner.insert("x", Value::Int64(9));
+        let mut document = Document::new();
+        document.insert("a", Value::String("text".to_string()));
+        document.insert("b", Value::Document(inner));
+        document.insert(
+            "c",
+            Value::Array(Array::from(vec![Value::Boolean(true), Value::Null])),
+        );
+        document.insert(
+            "r",
+            Value::RegularExpression {
+                pattern: "p".to_string(),
+                options: "".to_string(),
+            },
+        );
+        to_bytes(&document).unwrap()
+    }
+
+    // -------------------------------------
+    //          Raw Document Tests
+    // -------------------------------------
+
+    #[test]
+    fn test_raw_document_iter() {
+        let bytes = sample_bytes();
+        let document = RawDocument::from_bytes(&bytes).unwrap();
+        let mut keys: Vec<&str> = document.iter().map(|e| e.unwrap().key()).collect();
+        keys.sort_unstable();
+        assert_eq!(keys, ["a", "b", "c", "r"]);
+        assert_eq!(document.to_document().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_raw_document_get() {
+        let bytes = sample_bytes();
+        let document = RawDocument::from_bytes(&bytes).unwrap();
+
+        let a = document.get("a").unwrap().unwrap();
+        assert_eq!(a.element_type(), 0x02);
+        assert_eq!(a.value().unwrap(), Value::String("text".to_string()));
+        assert_eq!(bytes[a.offset()], 0x02);
+
+        let inner = document.get("b").unwrap().unwrap().as_document().unwrap();
+        assert_eq!(inner.get("x").unwrap().unwrap().value().unwrap(), Value::Int64(9));
+        assert!(document.get("missing").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_raw_array_get() {
+        let bytes = sample_bytes();
+        let document = RawDocument::from_bytes(&bytes).unwrap();
+        let array = document.get("c").unwrap().unwrap().as_array().unwrap();
+        assert_eq!(array.get(1).unwrap().unwrap().value().unwrap(), Value::Null);
+        assert!(array.get(2).unwrap().is_none());
+        assert!(document.get("a").unwrap().unwrap().as_array().is_none());
+    }
+
+    #[test]
+    fn test_raw_document_framing_errors() {
+        assert!(matches!(RawDocument::from_bytes(&[5, 0, 0]), Err(DeserializeError::UnexpectedEof)));
+        assert!(matches!(
+            RawDocument::from_bytes(&[6, 0, 0, 0, 0]),
+            Err(DeserializeError::InvalidDocument(_))
+        ));
+        assert!(matches!(
+            RawDocument::from_bytes(&[5, 0, 0, 0, 1]),
+            Err(DeserializeError::InvalidDocument(_))
+        ));
+    }
+
+    #[test]
+    fn test_raw_iter_element_overrun() {
+        // Int64 element with only four value bytes
+        let bytes = [12, 0, 0, 0, 0x12, b'a', 0, 1, 0, 0, 0, 0];
+        let document = RawDocument::from_bytes(&bytes).unwrap();
+        let mut iter = document.iter();
+        assert!(matches!(iter.next(), Some(Err(DeserializeError::InvalidDocument(_)))));
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn test_raw_iter_embedded_missing_terminator() {
+        // {"d": {}} with the embedded terminator replaced
+        let bytes = [13, 0, 0, 0, 0x03, b'd', 0, 5, 0, 0, 0, 1, 0];
+        let document = RawDocument::from_bytes(&bytes).unwrap();
+        let inner = document.get("d").unwrap().unwrap().as_document().unwrap();
+        assert!(matches!(inner.iter().next(), Some(Err(DeserializeError::InvalidDocument(_)))));
+    }
+
+    // -------------------------------------
+    //          Encoded Patch Tests
+    // -------------------------------------
+
+    #[test]
+    fn test_patch_in_place() {
+        let bytes = sample_bytes();
+        let patch = Patch::new().set("b.x", Value::Int64(-1)).set("c.0", false);
+        let patched = apply_encoded_patch(&bytes, &patch).unwrap();
+
+        // Same width, so only the value bytes change and field order is kept
+        assert_eq!(patched.len(), bytes.len());
+        let changed = bytes.iter().zip(&patched).filter(|(a, b)| a != b).count();
+        assert!(changed <= 9);
+
+        let document = from_bytes(&patched).unwrap();
+        let inner = document.get("b").unwrap().as_document().unwrap();
+        assert_eq!(inner.get("x"), Some(&Value::Int64(-1)));
+        let array = document.get("c").unwrap().as_array().unwrap();
+        assert_eq!(array.get(0), Some(&Value::Boolean(false)));
+    }
+
+    #[test]
+    fn test_patch_falls_back_to_reencode() {
+        let bytes = sample_bytes();
+        let patch = Patch::new()
+            .set("a", "a longer string")
+            .set("b.x", 5) // Int32 over Int64 changes the width
+            .set("new.field", 1)
+            .unset("r");
+        let document = from_bytes(&apply_encoded_patch(&bytes, &patch).unwrap()).unwrap();
+
+        assert_eq!(document.get("a"), Some(&Value::String("a longer string".to_string())));
+        let inner = document.get("b").unwrap().as_document().unwrap();
+        assert_eq!(inner.get("x"), Some(&Value::Int32(5)));
+        let new = document.get("new").unwrap().as_document().unwrap();
+        assert_eq!(new.get("field"), Some(&Value::Int32(1)));
+        assert!(!document.contains_key("r"));
+    }
+
+    #[test]
+    fn test_patch_errors() {
+        let bytes = sample_bytes();
+        assert!(matches!(
+            apply_encoded_patch(&bytes, &Patch::new().set("a.b", 1)),
+            Err(PatchError::InvalidPath(path)) if path == "a.b"
+        ));
+        assert!(matches!(
+            apply_encoded_patch(&bytes, &Patch::new().set("c.5", 1)),
+            Err(PatchError::InvalidPath(_))
+        ));
+        assert!(matches!(
+            apply_encoded_patch(&bytes[..bytes.len() - 1], &Patch::new()),
+            Err(PatchError::Deserialize(_))
+        ));
+
+        // Unsetting missing fields is not an error
+        let patched = apply_encoded_patch(&bytes, &Patch::new().unset("b.missing.x")).unwrap();
+        assert_eq!(from_bytes(&patched).unwrap(), from_bytes(&bytes).unwrap());
+    }
+
+    // -------------------------------------
+    //          Raw Diff Tests
+    // -------------------------------------
+
+    /// Encodes `{"x": x, "y": {"z": z}}` by hand so field order is fixed.
+    fn ordered_bytes(x: i32, z: &[u8]) -> Vec<u8> {
+        let mut inner = vec![0, 0, 0, 0, 0x05, b'z', 0];
+        inner.extend_from_slice(&(z.len() as i32).to_le_bytes());
+        inner.push(0);
+        inner.extend_from_slice(z);
+        inner.push(0);
+        let len = inner.len() as i32;
+        inner[..4].copy_from_slice(&len.to_le_bytes());
+
+        let mut bytes = vec![0, 0, 0, 0, 0x10, b'x', 0];
+        bytes.extend_from_slice(&x.to_le_bytes());
+        bytes.extend_from_slice(&[0x03, b'y', 0]);
+        bytes.extend_from_slice(&inner);
+        bytes.push(0);
+        let len = bytes.len() as i32;
+        bytes[..4].copy_from_slice(&len.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn test_raw_diff_identical() {
+        let bytes = sample_bytes();
+        assert!(raw_diff(&bytes, &bytes).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_raw_diff_nested_change() {
+        let old = ordered_bytes(1, &[1, 2]);
+        let new = ordered_bytes(1, &[1, 2, 3]);
+        let changes = raw_diff(&old, &new).unwrap();
+        assert_eq!(
+            changes,
+            [RawChange {
+                path: "y.z".to_string(),
+                kind: RawChangeKind::Modified,
+                old_range: Some(18..28),
+                new_range: Some(18..29),
+            }]
+        );
+        assert_eq!(old[18], 0x05);
+        assert_eq!(new[28], 3);
+    }
+
+    #[test]
+    fn test_raw_diff_added_removed_and_reordered() {
+        let mut old = Document::new();
+        old.insert("keep", 1);
+        old.insert("gone", 2);
+        old.insert("tags", Array::from(vec![Value::Int32(1), Value::Int32(2)]));
+        let mut new = Document::new();
+        new.insert("keep", 1);
+        new.insert("added", 3);
+        new.insert("tags", Array::from(vec![Value::Int32(1), Value::Int32(5)]));
+
+        let (old, new) = (to_bytes(&old).unwrap(), to_bytes(&new).unwrap());
+        let mut changes: Vec<(String, RawChangeKind)> = raw_diff(&old, &new)
+            .unwrap()
+            .into_iter()
+            .map(|change| (change.path, change.kind))
+            .collect();
+        changes.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            changes,
+            [
+                ("added".to_string(), RawChangeKind::Added),
+                ("gone".to_string(), RawChangeKind::Removed),
+                ("tags.1".to_string(), RawChangeKind::Modified),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_raw_diff_type_change() {
+        let mut old = Document::new();
+        old.insert("v", 1);
+        let mut new = Document::new();
+        new.insert("v", Value::Int64(1));
+        let changes = raw_diff(&to_bytes(&old).unwrap(), &to_bytes(&new).unwrap()).unwrap();
+        assert_eq!(changes[0].kind, RawChangeKind::Modified);
+        assert_eq!(changes[0].old_range, Some(4..11));
+        assert_eq!(changes[0].new_range, Some(4..15));
+
+        assert!(raw_diff(&[5, 0, 0, 0], &to_bytes(&new).unwrap()).is_err());
+    }
+
+    // -------------------------------------
+    //          Raw Index Tests
+    // -------------------------------------
+
+    #[test]
+    fn test_raw_document_index() {
+        let bytes = sample_bytes();
+        let document = RawDocument::from_bytes(&bytes).unwrap();
+        let index = document.index().unwrap();
+
+        assert_eq!(index.len(), 4);
+        let mut keys: Vec<&str> = index.keys().collect();
+        keys.sort_unstable();
+        assert_eq!(keys, ["a", "b", "c", "r"]);
+
+        for key in keys {
+            let indexed = index.get(key).unwrap();
+            assert_eq!(Some(indexed), document.get(key).unwrap());
+            assert_eq!(index.offset(key), Some(indexed.offset()));
+            assert_eq!(bytes[indexed.offset() + 1], key.as_bytes()[0]);
+        }
+        assert!(!index.contains_key("missing"));
+        assert_eq!(index.offset("missing"), None);
+    }
+
+    #[test]
+    fn test_raw_document_index_first_duplicate_wins() {
+        // {"k": 1, "k": 2}
+        let bytes = [
+            19, 0, 0, 0, 0x10, b'k', 0, 1, 0, 0, 0, 0x10, b'k', 0, 2, 0, 0, 0, 0,
+        ];
+        let index = RawDocument::from_bytes(&bytes).unwrap().index().unwrap();
+        assert_eq!(index.len(), 1);
+        assert_eq!(index.offset("k"), Some(4));
+        assert_eq!(index.get("k").unwrap().value().unwrap(), Value::Int32(1));
+    }
+
+    #[test]
+    fn test_raw_document_index_malformed() {
+        // Element type 0x42 is unknown
+        let bytes = [10, 0, 0, 0, 0x42, b'k', 0, 1, 0, 0];
+        let document = RawDocument::from_bytes(&bytes).unwrap();
+        assert!(document.index().is_err());
+    }
+}
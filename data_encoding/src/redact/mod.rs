@@ -0,0 +1,8 @@
+// src/redact/mod.rs
+
+mod redactor;
+mod serializer;
+mod test;
+
+pub use redactor::{Redaction, Redactor};
+pub use serializer::RedactingSerializer;
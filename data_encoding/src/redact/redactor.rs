@@ -0,0 +1,345 @@
+// src/redact/redactor.rs
+
+use std::borrow::Cow;
+use std::io::Cursor;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use super::serializer::RedactingSerializer;
+use crate::deser::{transcode_document, Decoder, DeserializeError, TranscodeError};
+use crate::ser::{BsonSerializer, Serializer};
+use crate::types::{Array, Document, Value};
+use crate::utils::hex;
+
+/// The mask standing in for values that are neither strings nor numbers.
+const OPAQUE_MASK: &str = "****";
+
+/// What a `Redactor` does with a field one of its patterns matches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Redaction {
+    /// Removes the field. Array elements after a removed one move down.
+    Drop,
+    /// Replaces the value with a hex string of its HMAC-SHA-256 keyed with
+    /// the redactor's salt, so equal values still hash alike.
+    Hash,
+    /// Replaces all but the first `keep_start` and last `keep_end`
+    /// characters of the value with `*`. Numbers are masked as their
+    /// decimal text; other values become `"****"`. Values too short to
+    /// keep anything of are masked whole.
+    Mask { keep_start: usize, keep_end: usize },
+}
+
+/// A segment of a field pattern.
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    /// `**`: any number of fields, including none.
+    Descendants,
+    /// A field name, where `*` stands for any run of characters.
+    Name(String),
+}
+
+/// A dotted field pattern, such as `address.*` or `**.password`.
+#[derive(Debug, Clone, PartialEq)]
+struct Pattern {
+    segments: Vec<Segment>,
+}
+
+impl Pattern {
+    fn parse(pattern: &str) -> Self {
+        let segments = pattern
+            .split('.')
+            .map(|segment| match segment {
+                "**" => Segment::Descendants,
+                name => Segment::Name(name.to_string()),
+            })
+            .collect();
+        Pattern { segments }
+    }
+
+    /// Returns whether the pattern matches `path`, a field's names from the
+    /// top of its document, each marked with whether it is an array index.
+    fn matches<N: AsRef<str>>(&self, path: &[(N, bool)]) -> bool {
+        matches_from(&self.segments, path)
+    }
+}
+
+fn matches_from<N: AsRef<str>>(segments: &[Segment], path: &[(N, bool)]) -> bool {
+    let Some(((name, index), rest)) = path.split_first() else {
+        return segments
+            .iter()
+            .all(|segment| *segment == Segment::Descendants);
+    };
+    let matched = match segments.split_first() {
+        Some((Segment::Descendants, tail)) => {
+            matches_from(tail, path) || matches_from(segments, rest)
+        }
+        Some((Segment::Name(pattern), tail)) => {
+            glob_matches(pattern.as_bytes(), name.as_ref().as_bytes()) && matches_from(tail, rest)
+        }
+        None => false,
+    };
+    // A pattern reaching an array applies to each of its elements
+    matched || (*index && matches_from(segments, rest))
+}
+
+/// Returns whether `name` matches `pattern`, where `*` matches any run of
+/// bytes.
+fn glob_matches(pattern: &[u8], name: &[u8]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    let mut star = None;
+    while n < name.len() {
+        if p < pattern.len() && pattern[p] == b'*' {
+            star = Some((p, n));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == name[n] {
+            p += 1;
+            n += 1;
+        } else if let Some((star_p, star_n)) = star {
+            // Let the last star match one more byte and try again
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, n));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&byte| byte == b'*')
+}
+
+/// Hides or removes chosen fields of documents, for writing them to logs
+/// or exporting them without the personal data they hold.
+///
+/// Fields are chosen by dotted patterns checked in the order they were
+/// added, the first that matches a field deciding its `Redaction`. A
+/// segment `*` matches any one field name, `*` within a segment any run of
+/// characters, and `**` any number of fields, so `**.password` matches a
+/// `password` field at any depth. A pattern reaching an array applies to
+/// each of its elements. A hashed or masked document or array has each
+/// value within it hashed or masked.
+///
+/// A redactor applies to documents with `redact`, to encoded documents
+/// with `redact_bytes`, and to anything written through a serializer it
+/// wraps with `serializer`, such as the JSON written to a log.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb_data_encoding::{Document, Redaction, Redactor, Value};
+/// let redactor = Redactor::new()
+///     .field("**.password", Redaction::Drop)
+///     .field("email", Redaction::Hash)
+///     .field("card", Redaction::Mask { keep_start: 0, keep_end: 4 })
+///     .salt("pepper");
+///
+/// let mut user = Document::new();
+/// user.insert("email", "ada@example.com");
+/// user.insert("card", "4111111111111111");
+/// user.insert("password", "hunter2");
+///
+/// let redacted = redactor.redact(&user);
+/// assert_eq!(redacted.get("password"), None);
+/// assert_eq!(
+///     redacted.get("card"),
+///     Some(&Value::String("************1111".to_string()))
+/// );
+/// assert_ne!(redacted.get("email"), user.get("email"));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Redactor {
+    rules: Vec<(Pattern, Redaction)>,
+    salt: Vec<u8>,
+}
+
+impl Redactor {
+    /// Creates a redactor that leaves every field as it is.
+    pub fn new() -> Self {
+        Redactor::default()
+    }
+
+    /// Applies `redaction` to the fields `pattern` matches that no earlier
+    /// pattern does.
+    pub fn field(mut self, pattern: &str, redaction: Redaction) -> Self {
+        self.rules.push((Pattern::parse(pattern), redaction));
+        self
+    }
+
+    /// Sets the salt values are hashed with, the key of their HMAC, so
+    /// hashes cannot be matched against those of guessed values by anyone
+    /// without it. Hashes made with the same salt are the same across
+    /// builds and machines.
+    pub fn salt(mut self, salt: impl AsRef<[u8]>) -> Self {
+        self.salt = salt.as_ref().to_vec();
+        self
+    }
+
+    /// Returns the redaction of the first pattern matching `path`, if any.
+    pub(crate) fn rule<N: AsRef<str>>(&self, path: &[(N, bool)]) -> Option<Redaction> {
+        self.rules
+            .iter()
+            .find(|(pattern, _)| pattern.matches(path))
+            .map(|(_, redaction)| *redaction)
+    }
+
+    /// Returns a copy of `document` with its chosen fields redacted.
+    pub fn redact(&self, document: &Document) -> Document {
+        self.redact_document(document, &mut Vec::new())
+    }
+
+    /// Redacts the encoded document `bytes` without decoding it into a
+    /// `Document`, streaming it through the transcoder into a new encoding.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `bytes` is not a well-formed document.
+    pub fn redact_bytes(&self, bytes: &[u8]) -> Result<Vec<u8>, TranscodeError> {
+        let mut decoder = Decoder::new(bytes);
+        let mut serializer = self.serializer(BsonSerializer::new(Cursor::new(Vec::new())));
+        if !transcode_document(&mut decoder, &mut serializer)? {
+            return Err(DeserializeError::UnexpectedEof.into());
+        }
+        Ok(serializer.into_inner().into_inner().into_inner())
+    }
+
+    /// Wraps `serializer` so that what is written through it is redacted
+    /// on the way.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use silentdb_data_encoding::{Document, JsonSerializer, Redaction, Redactor, Serializer};
+    /// let redactor = Redactor::new().field("token", Redaction::Drop);
+    /// let mut event = Document::new();
+    /// event.insert("user", "ada");
+    /// event.insert("token", "s3cret");
+    ///
+    /// let mut serializer = redactor.serializer(JsonSerializer::new(Vec::new(), false));
+    /// serializer.serialize_document(&event).unwrap();
+    /// let json = serializer.into_inner().into_inner();
+    /// assert_eq!(json, br#"{"user":"ada"}"#);
+    /// ```
+    pub fn serializer<S: Serializer>(&self, serializer: S) -> RedactingSerializer<'_, S> {
+        RedactingSerializer::new(self, serializer)
+    }
+
+    fn redact_document<'a>(
+        &self,
+        document: &'a Document,
+        path: &mut Vec<(Cow<'a, str>, bool)>,
+    ) -> Document {
+        let mut redacted = Document::new();
+        for (name, value) in document.iter() {
+            path.push((Cow::Borrowed(name.as_str()), false));
+            if let Some(value) = self.redact_field(value, path) {
+                redacted.insert(name.clone(), value);
+            }
+            path.pop();
+        }
+        redacted
+    }
+
+    /// Returns the value of the field at `path` redacted, or `None` if the
+    /// field is dropped.
+    fn redact_field<'a>(
+        &self,
+        value: &'a Value,
+        path: &mut Vec<(Cow<'a, str>, bool)>,
+    ) -> Option<Value> {
+        match self.rule(path) {
+            Some(Redaction::Drop) => None,
+            Some(redaction) => Some(self.apply(redaction, value)),
+            None => Some(match value {
+                Value::Document(document) => Value::Document(self.redact_document(document, path)),
+                Value::Array(array) => {
+                    let mut redacted = Array::new();
+                    for (index, value) in array.iter().enumerate() {
+                        path.push((Cow::Owned(index.to_string()), true));
+                        if let Some(value) = self.redact_field(value, path) {
+                            redacted.push(value);
+                        }
+                        path.pop();
+                    }
+                    Value::Array(redacted)
+                }
+                value => value.clone(),
+            }),
+        }
+    }
+
+    /// Applies `redaction` to `value` and everything within it.
+    fn apply(&self, redaction: Redaction, value: &Value) -> Value {
+        match value {
+            Value::Document(document) => {
+                let mut redacted = Document::new();
+                for (name, value) in document.iter() {
+                    redacted.insert(name.clone(), self.apply(redaction, value));
+                }
+                Value::Document(redacted)
+            }
+            Value::Array(array) => Value::Array(Array::from(
+                array
+                    .iter()
+                    .map(|value| self.apply(redaction, value))
+                    .collect::<Vec<_>>(),
+            )),
+            value => self.apply_scalar(redaction, value),
+        }
+    }
+
+    /// Applies `redaction` to `value`, which is neither a document nor an
+    /// array.
+    pub(crate) fn apply_scalar(&self, redaction: Redaction, value: &Value) -> Value {
+        match redaction {
+            Redaction::Drop => Value::Null,
+            Redaction::Hash => Value::String(self.hash(value)),
+            Redaction::Mask {
+                keep_start,
+                keep_end,
+            } => {
+                let text = match value {
+                    Value::String(text) => Cow::Borrowed(text.as_str()),
+                    Value::Int32(_) | Value::Int64(_) | Value::UInt64(_) | Value::Double(_) => {
+                        Cow::Owned(value.to_string())
+                    }
+                    _ => return Value::String(OPAQUE_MASK.to_string()),
+                };
+                Value::String(mask(&text, keep_start, keep_end))
+            }
+        }
+    }
+
+    /// Returns the HMAC-SHA-256 of `value`'s type and encoding keyed with
+    /// the salt, as hex.
+    fn hash(&self, value: &Value) -> String {
+        let mut serializer = BsonSerializer::new(Cursor::new(Vec::new()));
+        let written = serializer
+            .serialize_field_name("")
+            .and_then(|()| value.serialize(&mut serializer));
+        let encoded = match written {
+            Ok(()) => serializer.into_inner().into_inner(),
+            // Values the encoder rejects, such as deprecated types, hash by
+            // their text
+            Err(_) => format!("{:?}", value).into_bytes(),
+        };
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.salt).expect("HMAC takes keys of any length");
+        mac.update(&encoded);
+        hex::encode(&mac.finalize().into_bytes())
+    }
+}
+
+/// Returns `text` with all but its first `keep_start` and last `keep_end`
+/// characters replaced by `*`, or all of them if it has no more than that.
+fn mask(text: &str, keep_start: usize, keep_end: usize) -> String {
+    let length = text.chars().count();
+    if length <= keep_start + keep_end {
+        return "*".repeat(length);
+    }
+    text.chars()
+        .enumerate()
+        .map(|(i, c)| match i < keep_start || i >= length - keep_end {
+            true => c,
+            false => '*',
+        })
+        .collect()
+}
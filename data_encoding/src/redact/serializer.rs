@@ -0,0 +1,246 @@
+// src/redact/serializer.rs
+
+use super::redactor::{Redaction, Redactor};
+use crate::ser::{SerializeError, Serializer};
+use crate::types::{Array, Document, ObjectId, Value};
+
+/// What happens to the value after a field name.
+#[derive(Debug, Clone, Copy)]
+enum Action {
+    Keep,
+    Drop,
+    Redact(Redaction),
+}
+
+/// A document or array being written.
+#[derive(Debug)]
+struct Frame {
+    is_array: bool,
+    /// The index the next element written to an array is given, which is
+    /// less than its own once elements before it were dropped.
+    next_index: usize,
+    /// Whether it was written after a field name, which is on the path.
+    named: bool,
+    /// The redaction applied to everything within it.
+    redaction: Option<Redaction>,
+}
+
+/// A serializer redacting what is written through it before passing it on
+/// to another, as made by `Redactor::serializer`.
+///
+/// It is a hook for any output a serializer writes, such as JSON for logs
+/// or a file of encoded documents for an export, and is what
+/// `Redactor::redact_bytes` transcodes through.
+pub struct RedactingSerializer<'r, S> {
+    redactor: &'r Redactor,
+    inner: S,
+    /// The names of the fields being written, each marked with whether it
+    /// is an array index.
+    path: Vec<(String, bool)>,
+    frames: Vec<Frame>,
+    /// The action for the value after the last field name.
+    pending: Option<Action>,
+    /// How deep the writer is within a dropped document or array.
+    skipping: usize,
+}
+
+impl<'r, S: Serializer> RedactingSerializer<'r, S> {
+    pub(crate) fn new(redactor: &'r Redactor, inner: S) -> Self {
+        RedactingSerializer {
+            redactor,
+            inner,
+            path: Vec::new(),
+            frames: Vec::new(),
+            pending: None,
+            skipping: 0,
+        }
+    }
+
+    /// Returns the serializer redacted values are written to.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Consumes the redacting serializer, returning the one it wraps.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// Returns the action for a value that is neither a document nor an
+    /// array, which ends its field.
+    fn leaf_action(&mut self) -> Action {
+        if self.skipping > 0 {
+            return Action::Drop;
+        }
+        match self.pending.take() {
+            Some(action) => {
+                self.path.pop();
+                action
+            }
+            None => Action::Keep,
+        }
+    }
+
+    /// Writes `value` for a leaf whose action is not `Keep`.
+    fn redact_leaf(&mut self, action: Action, value: Value) -> Result<(), SerializeError> {
+        match action {
+            Action::Keep => value.serialize(&mut self.inner),
+            Action::Drop => Ok(()),
+            Action::Redact(redaction) => self
+                .redactor
+                .apply_scalar(redaction, &value)
+                .serialize(&mut self.inner),
+        }
+    }
+
+    fn start_container(&mut self, is_array: bool) -> Result<(), SerializeError> {
+        if self.skipping > 0 {
+            self.skipping += 1;
+            return Ok(());
+        }
+        let (named, action) = match self.pending.take() {
+            Some(action) => (true, action),
+            None => (false, Action::Keep),
+        };
+        let redaction = match action {
+            Action::Keep => None,
+            Action::Drop => {
+                self.skipping = 1;
+                return Ok(());
+            }
+            Action::Redact(redaction) => Some(redaction),
+        };
+        self.frames.push(Frame {
+            is_array,
+            next_index: 0,
+            named,
+            redaction,
+        });
+        match is_array {
+            true => self.inner.start_array(),
+            false => self.inner.start_document(),
+        }
+    }
+
+    fn end_container(&mut self, is_array: bool) -> Result<(), SerializeError> {
+        if self.skipping > 0 {
+            self.skipping -= 1;
+            if self.skipping == 0 {
+                self.path.pop();
+            }
+            return Ok(());
+        }
+        if self.frames.pop().is_some_and(|frame| frame.named) {
+            self.path.pop();
+        }
+        match is_array {
+            true => self.inner.end_array(),
+            false => self.inner.end_document(),
+        }
+    }
+}
+
+/// Implements a `Serializer` method writing a value that is neither a
+/// document nor an array, building it as a `Value` only to redact it.
+macro_rules! leaf {
+    ($method:ident($($arg:ident: $ty:ty),*) => $value:expr) => {
+        fn $method(&mut self, $($arg: $ty),*) -> Result<(), SerializeError> {
+            match self.leaf_action() {
+                Action::Keep => self.inner.$method($($arg),*),
+                action => self.redact_leaf(action, $value),
+            }
+        }
+    };
+}
+
+impl<S: Serializer> Serializer for RedactingSerializer<'_, S> {
+    leaf!(serialize_f64(value: f64) => Value::Double(value));
+    leaf!(serialize_string(value: &str) => Value::String(value.to_string()));
+    leaf!(serialize_binary(value: &[u8]) => Value::Binary(value.to_vec()));
+    leaf!(serialize_undefined() => Value::Undefined);
+    leaf!(serialize_object_id(value: ObjectId) => Value::ObjectId(value));
+    leaf!(serialize_boolean(value: bool) => Value::Boolean(value));
+    leaf!(serialize_utc_datetime(value: i64) => Value::UTCDateTime(value));
+    leaf!(serialize_null() => Value::Null);
+    leaf!(serialize_regex(pattern: &str, options: &str) => Value::RegularExpression {
+        pattern: pattern.to_string(),
+        options: options.to_string(),
+    });
+    leaf!(serialize_db_pointer(collection: &str, id: ObjectId) => Value::DbPointer {
+        namespace: collection.to_string(),
+        id,
+    });
+    leaf!(serialize_javascript_code(code: &str) => Value::JavaScriptCode(code.to_string()));
+    leaf!(serialize_symbol(symbol: &str) => Value::Symbol(symbol.to_string()));
+    leaf!(serialize_javascript_code_with_scope(code: &str, scope: &Document) => {
+        let (code, scope) = (code.to_string(), scope.clone());
+        Value::JavaScriptCodeWithScope { code, scope }
+    });
+    leaf!(serialize_i32(value: i32) => Value::Int32(value));
+    leaf!(serialize_timestamp(value: i64) => Value::Timestamp(value));
+    leaf!(serialize_i64(value: i64) => Value::Int64(value));
+    leaf!(serialize_u64(value: u64) => Value::UInt64(value));
+    leaf!(serialize_min_key() => Value::MinKey);
+    leaf!(serialize_max_key() => Value::MaxKey);
+
+    fn serialize_document(&mut self, value: &Document) -> Result<(), SerializeError> {
+        self.start_document()?;
+        for (key, value) in value.iter() {
+            self.serialize_field_name(key)?;
+            value.serialize(self)?;
+        }
+        self.end_document()
+    }
+
+    fn serialize_array(&mut self, value: &Array) -> Result<(), SerializeError> {
+        self.start_array()?;
+        for (index, value) in value.iter().enumerate() {
+            self.serialize_field_name(&index.to_string())?;
+            value.serialize(self)?;
+        }
+        self.end_array()
+    }
+
+    fn start_document(&mut self) -> Result<(), SerializeError> {
+        self.start_container(false)
+    }
+
+    fn end_document(&mut self) -> Result<(), SerializeError> {
+        self.end_container(false)
+    }
+
+    fn start_array(&mut self) -> Result<(), SerializeError> {
+        self.start_container(true)
+    }
+
+    fn end_array(&mut self) -> Result<(), SerializeError> {
+        self.end_container(true)
+    }
+
+    fn serialize_field_name(&mut self, name: &str) -> Result<(), SerializeError> {
+        if self.skipping > 0 {
+            return Ok(());
+        }
+        let parent = self.frames.last();
+        let is_index = parent.is_some_and(|frame| frame.is_array);
+        let inherited = parent.and_then(|frame| frame.redaction);
+        self.path.push((name.to_string(), is_index));
+        let action = match inherited.or_else(|| self.redactor.rule(&self.path)) {
+            None => Action::Keep,
+            Some(Redaction::Drop) => Action::Drop,
+            Some(redaction) => Action::Redact(redaction),
+        };
+        self.pending = Some(action);
+        if let Action::Drop = action {
+            return Ok(());
+        }
+        match self.frames.last_mut() {
+            Some(frame) if frame.is_array => {
+                let index = frame.next_index;
+                frame.next_index += 1;
+                self.inner.serialize_field_name(&index.to_string())
+            }
+            _ => self.inner.serialize_field_name(name),
+        }
+    }
+}
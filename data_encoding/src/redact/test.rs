@@ -0,0 +1,145 @@
+#[cfg(test)]
+mod tests {
+    use crate::deser::{from_bytes, from_json_str};
+    use crate::redact::{Redaction, Redactor};
+    use crate::ser::{to_bytes, JsonSerializer, Serializer};
+    use crate::types::{Document, Value};
+
+    fn customer() -> Document {
+        from_json_str(
+            r#"{"_id": 7, "name": "Ada Lovelace", "email": "ada@example.com",
+                "card": {"number": 4111111111111111, "cvc": "123"},
+                "contacts": [
+                    {"kind": "home", "phone": "555-0100", "secret_note": "x"},
+                    {"kind": "work", "phone": "555-0199"}
+                ],
+                "tags": ["vip", "late", "vip"],
+                "auth": {"password": "hunter2", "session": {"password": "p"}}}"#,
+        )
+        .unwrap()
+    }
+
+    fn redactor() -> Redactor {
+        Redactor::new()
+            .field("**.password", Redaction::Drop)
+            .field("contacts.*_note", Redaction::Drop)
+            .field(
+                "contacts.phone",
+                Redaction::Mask {
+                    keep_start: 0,
+                    keep_end: 2,
+                },
+            )
+            .field(
+                "card",
+                Redaction::Mask {
+                    keep_start: 0,
+                    keep_end: 0,
+                },
+            )
+            .field("email", Redaction::Hash)
+            .field(
+                "name",
+                Redaction::Mask {
+                    keep_start: 1,
+                    keep_end: 0,
+                },
+            )
+            .salt("pepper")
+    }
+
+    fn string(value: &str) -> Value {
+        Value::String(value.to_string())
+    }
+
+    // -------------------------------------
+    //          Redactor Tests
+    // -------------------------------------
+
+    #[test]
+    fn test_redact_document() {
+        let redacted = redactor().redact(&customer());
+
+        assert_eq!(redacted.get("_id"), Some(&Value::Int32(7)));
+        assert_eq!(redacted.get("name"), Some(&string("A***********")));
+        let Some(Value::Document(card)) = redacted.get("card") else {
+            panic!("card not a document: {:?}", redacted);
+        };
+        // A masked document has each value within it masked
+        assert_eq!(card.get("number"), Some(&string("****************")));
+        assert_eq!(card.get("cvc"), Some(&string("***")));
+        let Some(Value::Array(contacts)) = redacted.get("contacts") else {
+            panic!("contacts not an array: {:?}", redacted);
+        };
+        let Some(Value::Document(home)) = contacts.get(0) else {
+            panic!("contact not a document: {:?}", contacts);
+        };
+        assert_eq!(home.get("phone"), Some(&string("******00")));
+        assert_eq!(home.get("secret_note"), None);
+        assert_eq!(home.get("kind"), Some(&string("home")));
+        let auth: Document = from_json_str(r#"{"session": {}}"#).unwrap();
+        assert_eq!(redacted.get("auth"), Some(&Value::Document(auth)));
+
+        // Hashes are the HMAC of the value's element, stable for a salt, and
+        // differ between values and salts
+        let Some(Value::String(hash)) = redacted.get("email") else {
+            panic!("email not hashed: {:?}", redacted);
+        };
+        assert_eq!(
+            hash,
+            "9800725988e5ccb1c98f2c484f3e9790b3da6fbc05a454451da37bc5eee40666"
+        );
+        assert_eq!(
+            redactor().redact(&customer()).get("email"),
+            Some(&string(hash))
+        );
+        let other = redactor().salt("salt").redact(&customer());
+        assert_ne!(other.get("email"), Some(&string(hash)));
+        let mut user = Document::new();
+        user.insert("email", "bob@example.com");
+        assert_ne!(redactor().redact(&user).get("email"), Some(&string(hash)));
+    }
+
+    #[test]
+    fn test_redact_drops_array_elements() {
+        let redactor = Redactor::new()
+            .field("tags.1", Redaction::Drop)
+            .field("contacts.*", Redaction::Drop);
+        let document = customer();
+        let redacted = redactor.redact(&document);
+        let tags: Document = from_json_str(r#"{"tags": ["vip", "vip"], "contacts": []}"#).unwrap();
+        assert_eq!(redacted.get("tags"), tags.get("tags"));
+        assert_eq!(redacted.get("contacts"), tags.get("contacts"));
+
+        // Encoded documents are renumbered the same way
+        let bytes = redactor
+            .redact_bytes(&to_bytes(&document).unwrap())
+            .unwrap();
+        assert_eq!(from_bytes(&bytes).unwrap(), redacted);
+    }
+
+    #[test]
+    fn test_redact_bytes_and_serializer() {
+        let redactor = redactor();
+        let document = customer();
+        let expected = redactor.redact(&document);
+
+        let bytes = redactor
+            .redact_bytes(&to_bytes(&document).unwrap())
+            .unwrap();
+        assert_eq!(from_bytes(&bytes).unwrap(), expected);
+        assert!(redactor.redact_bytes(&[]).is_err());
+        assert!(redactor.redact_bytes(&[5, 0, 0]).is_err());
+
+        // Written through a serializer, the same fields are redacted
+        let mut serializer = redactor.serializer(JsonSerializer::new(Vec::new(), false));
+        serializer.serialize_document(&document).unwrap();
+        let json = serializer.into_inner().into_inner();
+        let mut plain = JsonSerializer::new(Vec::new(), false);
+        plain.serialize_document(&expected).unwrap();
+        assert_eq!(
+            String::from_utf8(json).unwrap(),
+            String::from_utf8(plain.into_inner()).unwrap()
+        );
+    }
+}
@@ -4,78 +4,133 @@ use std::io::{self, Write};
 use byteorder::{LittleEndian, WriteBytesExt};
 use super::error::SerializeError;
 use super::traits::Serializer;
+use crate::types::{Array, Document, ObjectId};
 
-/// TODO: Implement the Serializer trait for BsonSerializer. Mostly done, but needs error handling.
+/// Serializes values into the BSON wire format.
+///
+/// Every element except the top-level document is preceded by a call to
+/// `serialize_field_name`; the name is held until the element's type byte is
+/// known, so the element header is written as `type, name, 0x00`.
 pub struct BsonSerializer<W: Write + io::Seek> {
     writer: W,
     document_positions: Vec<u64>, // STack of document positions where length needs to be written
+    field_name: String,           // Name of the next element, written after its type byte
+    has_field_name: bool,
 }
 
-/// Implementation of the Serializer trait for BsonSerializer.
-/// TODO: Error handling
-/// TODO: Implement the remaining methods
 impl<W: Write + io::Seek> BsonSerializer<W> {
     /// Creates a new BSON serializer that writes serialized data to the specified writer.
     pub fn new(writer: W) -> Self {
         Self {
             writer,
             document_positions: Vec::new(),
+            field_name: String::new(),
+            has_field_name: false,
         }
     }
 
+    /// Consumes the serializer, returning the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
     /// Returns the current position of the writer.
     pub fn current_position(&mut self) -> Result<u64, SerializeError> {
-        Ok(self.writer.seek(io::SeekFrom::Current(0)).unwrap())
+        Ok(self.writer.stream_position()?)
     }
 
     /// Writes the document length to the current position.
     pub fn write_document_length(&mut self) -> Result<(), SerializeError> {
         let current_position = self.current_position()?;
-        let document_position = self.document_positions.pop().unwrap();
+        let document_position = self.document_positions.pop().ok_or_else(|| {
+            SerializeError::InvalidDocument("end of document without a matching start".to_string())
+        })?;
         let length = current_position - document_position;
-        self.writer
-            .seek(io::SeekFrom::Start(document_position))
-            .unwrap();
-        self.writer.write_u32::<LittleEndian>(length as u32)?;
-        self.writer
-            .seek(io::SeekFrom::Start(current_position))
-            .unwrap();
+        if length > i32::MAX as u64 {
+            return Err(SerializeError::BufferOverflow);
+        }
+        self.writer.seek(io::SeekFrom::Start(document_position))?;
+        self.writer.write_i32::<LittleEndian>(length as i32)?;
+        self.writer.seek(io::SeekFrom::Start(current_position))?;
         Ok(())
     }
-}
 
-impl<W: Write + io::Seek> Serializer for BsonSerializer<W> {
-    fn serialize_f64(&mut self, value: f64) -> Result<(), SerializeError> {
-        self.writer.write_u8(0x01)?;
-        self.writer.write_f64::<LittleEndian>(value)?;
+    /// Writes the element header (type byte and field name) for the next value.
+    ///
+    /// Only the top-level document may be written without a field name.
+    fn write_element_header(&mut self, element_type: u8) -> Result<(), SerializeError> {
+        if !self.has_field_name {
+            if element_type == 0x03 && self.document_positions.is_empty() {
+                return Ok(());
+            }
+            return Err(SerializeError::InvalidDocument(format!(
+                "element of type {:#04x} written without a field name",
+                element_type
+            )));
+        }
+        self.has_field_name = false;
+        self.writer.write_u8(element_type)?;
+        self.writer.write_all(self.field_name.as_bytes())?;
+        self.writer.write_u8(0)?;
         Ok(())
     }
 
-    fn serialize_string(&mut self, value: &str) -> Result<(), SerializeError> {
-        self.writer.write_u8(0x02)?;
-
-        // Write the string length
-        self.writer
-            .write_i32::<LittleEndian>(value.len() as i32 + 1)?;
-
-        // Write the string
+    /// Writes a length-prefixed, null-terminated string.
+    fn write_string(&mut self, value: &str) -> Result<(), SerializeError> {
+        let length = i32::try_from(value.len() + 1).map_err(|_| SerializeError::BufferOverflow)?;
+        self.writer.write_i32::<LittleEndian>(length)?;
         self.writer.write_all(value.as_bytes())?;
-
-        // Write the null terminator
         self.writer.write_u8(0)?;
+        Ok(())
+    }
 
+    /// Writes a null-terminated string, rejecting interior null bytes.
+    fn write_cstring(&mut self, value: &str) -> Result<(), SerializeError> {
+        if value.as_bytes().contains(&0) {
+            return Err(SerializeError::InvalidValue(format!(
+                "C string contains a null byte: {:?}",
+                value
+            )));
+        }
+        self.writer.write_all(value.as_bytes())?;
+        self.writer.write_u8(0)?;
         Ok(())
     }
 
-    fn serialize_document(&mut self, value: &Document) -> Result<(), SerializeError> {
-        self.writer.write_u8(0x03)?;
+    /// Starts a document or array body with a length placeholder.
+    fn start_container(&mut self, element_type: u8) -> Result<(), SerializeError> {
+        self.write_element_header(element_type)?;
 
         // Push the current position to the stack
-        let current_pos = self.current_position()?;
-        self.document_positions.push(current_pos);
+        let current_position = self.current_position()?;
+        self.document_positions.push(current_position);
+
+        // Write a placeholder for the length
+        self.writer.write_i32::<LittleEndian>(0)?;
+        Ok(())
+    }
+
+    /// Ends a document or array body and backpatches its length.
+    fn end_container(&mut self) -> Result<(), SerializeError> {
+        self.writer.write_u8(0)?;
+        self.write_document_length()
+    }
+}
+
+impl<W: Write + io::Seek> Serializer for BsonSerializer<W> {
+    fn serialize_f64(&mut self, value: f64) -> Result<(), SerializeError> {
+        self.write_element_header(0x01)?;
+        self.writer.write_f64::<LittleEndian>(value)?;
+        Ok(())
+    }
+
+    fn serialize_string(&mut self, value: &str) -> Result<(), SerializeError> {
+        self.write_element_header(0x02)?;
+        self.write_string(value)
+    }
 
-        // Write a placeholder for the document length
-        self.writer.write_u32::<LittleEndian>(0)?;
+    fn serialize_document(&mut self, value: &Document) -> Result<(), SerializeError> {
+        self.start_document()?;
 
         // Serialize the document
         for (key, value) in value.iter() {
@@ -84,20 +139,11 @@ impl<W: Write + io::Seek> Serializer for BsonSerializer<W> {
         }
 
         // End the document
-        self.end_document()?;
-
-        Ok(())
+        self.end_document()
     }
 
     fn serialize_array(&mut self, value: &Array) -> Result<(), SerializeError> {
-        self.writer.write_u8(0x04)?;
-
-        // Push the current position to the stack
-        let current_position = self.current_position()?;
-        self.document_positions.push(current_position);
-
-        // Write a placeholder for the array length
-        self.writer.write_u32::<LittleEndian>(0)?;
+        self.start_array()?;
 
         // Serialize the array
         for (index, value) in value.iter().enumerate() {
@@ -105,17 +151,16 @@ impl<W: Write + io::Seek> Serializer for BsonSerializer<W> {
             value.serialize(self)?;
         }
 
-        // End the document
-        self.end_document()?;
-
-        Ok(())
+        // End the array
+        self.end_array()
     }
 
     fn serialize_binary(&mut self, value: &[u8]) -> Result<(), SerializeError> {
-        self.writer.write_u8(0x05)?;
+        self.write_element_header(0x05)?;
 
         // Write the binary length
-        self.writer.write_i32::<LittleEndian>(value.len() as i32)?;
+        let length = i32::try_from(value.len()).map_err(|_| SerializeError::BufferOverflow)?;
+        self.writer.write_i32::<LittleEndian>(length)?;
 
         // Write the binary subtype
         // TODO: Implement BinarySubtype enum
@@ -128,21 +173,20 @@ impl<W: Write + io::Seek> Serializer for BsonSerializer<W> {
     }
 
     fn serialize_undefined(&mut self) -> Result<(), SerializeError> {
-        self.writer.write_u8(0x06)?;
-        Ok(())
+        self.write_element_header(0x06)
     }
 
     fn serialize_object_id(&mut self, value: ObjectId) -> Result<(), SerializeError> {
-        self.writer.write_u8(0x07)?;
+        self.write_element_header(0x07)?;
 
         // Write the object id
-        self.writer.write_all(&value.0)?;
+        self.writer.write_all(value.as_bytes())?;
 
         Ok(())
     }
 
     fn serialize_boolean(&mut self, value: bool) -> Result<(), SerializeError> {
-        self.writer.write_u8(0x08)?;
+        self.write_element_header(0x08)?;
 
         // Write the boolean value
         self.writer.write_u8(if value { 0x01 } else { 0x00 })?;
@@ -151,7 +195,7 @@ impl<W: Write + io::Seek> Serializer for BsonSerializer<W> {
     }
 
     fn serialize_utc_datetime(&mut self, value: i64) -> Result<(), SerializeError> {
-        self.writer.write_u8(0x09)?;
+        self.write_element_header(0x09)?;
 
         // Write the UTC datetime
         self.writer.write_i64::<LittleEndian>(value)?;
@@ -160,20 +204,15 @@ impl<W: Write + io::Seek> Serializer for BsonSerializer<W> {
     }
 
     fn serialize_null(&mut self) -> Result<(), SerializeError> {
-        self.writer.write_u8(0x0A)?;
-        Ok(())
+        self.write_element_header(0x0A)
     }
 
     fn serialize_regex(&mut self, pattern: &str, options: &str) -> Result<(), SerializeError> {
-        self.writer.write_u8(0x0B)?;
-
-        // Write the pattern
-        self.writer.write_all(pattern.as_bytes())?;
-        self.writer.write_u8(0)?;
+        self.write_element_header(0x0B)?;
 
-        // Write the options
-        self.writer.write_all(options.as_bytes())?;
-        self.writer.write_u8(0)?;
+        // Write the pattern and options
+        self.write_cstring(pattern)?;
+        self.write_cstring(options)?;
 
         Ok(())
     }
@@ -183,29 +222,22 @@ impl<W: Write + io::Seek> Serializer for BsonSerializer<W> {
         collection: &str,
         id: ObjectId,
     ) -> Result<(), SerializeError> {
-        // DEPRECATED
-        Err(SerializeError::Deprecated(format!(
-            "DBPointer is deprecated. Collection: {}, ID: {}",
-            collection, id
-        )))
+        // Deprecated, but written so legacy documents round-trip
+        self.write_element_header(0x0C)?;
+        self.write_string(collection)?;
+        self.writer.write_all(id.as_bytes())?;
+        Ok(())
     }
 
     fn serialize_javascript_code(&mut self, code: &str) -> Result<(), SerializeError> {
-        self.writer.write_u8(0x0D)?;
-
-        // Write the code
-        self.writer.write_all(code.as_bytes())?;
-        self.writer.write_u8(0)?;
-
-        Ok(())
+        self.write_element_header(0x0D)?;
+        self.write_string(code)
     }
 
     fn serialize_symbol(&mut self, symbol: &str) -> Result<(), SerializeError> {
-        // DEPRECATED
-        Err(SerializeError::Deprecated(format!(
-            "Symbol is deprecated. Symbol: {}",
-            symbol
-        )))
+        // Deprecated, but written so legacy documents round-trip
+        self.write_element_header(0x0E)?;
+        self.write_string(symbol)
     }
 
     fn serialize_javascript_code_with_scope(
@@ -230,7 +262,7 @@ impl<W: Write + io::Seek> Serializer for BsonSerializer<W> {
     }
 
     fn serialize_i32(&mut self, value: i32) -> Result<(), SerializeError> {
-        self.writer.write_u8(0x10)?;
+        self.write_element_header(0x10)?;
 
         // Write the 32-bit integer
         self.writer.write_i32::<LittleEndian>(value)?;
@@ -239,7 +271,7 @@ impl<W: Write + io::Seek> Serializer for BsonSerializer<W> {
     }
 
     fn serialize_timestamp(&mut self, value: i64) -> Result<(), SerializeError> {
-        self.writer.write_u8(0x11)?;
+        self.write_element_header(0x11)?;
 
         // Write the timestamp
         self.writer.write_i64::<LittleEndian>(value)?;
@@ -248,7 +280,7 @@ impl<W: Write + io::Seek> Serializer for BsonSerializer<W> {
     }
 
     fn serialize_i64(&mut self, value: i64) -> Result<(), SerializeError> {
-        self.writer.write_u8(0x12)?;
+        self.write_element_header(0x12)?;
 
         // Write the 64-bit integer
         self.writer.write_i64::<LittleEndian>(value)?;
@@ -257,7 +289,7 @@ impl<W: Write + io::Seek> Serializer for BsonSerializer<W> {
     }
 
     fn serialize_u64(&mut self, value: u64) -> Result<(), SerializeError> {
-        self.writer.write_u8(0x13)?;
+        self.write_element_header(0x13)?;
 
         // Write the 64-bit integer
         self.writer.write_u64::<LittleEndian>(value)?;
@@ -273,34 +305,39 @@ impl<W: Write + io::Seek> Serializer for BsonSerializer<W> {
     // }
 
     fn serialize_min_key(&mut self) -> Result<(), SerializeError> {
-        self.writer.write_u8(0xFF)?;
-        Ok(())
+        self.write_element_header(0xFF)
     }
 
     fn serialize_max_key(&mut self) -> Result<(), SerializeError> {
-        self.writer.write_u8(0x7F)?;
-        Ok(())
+        self.write_element_header(0x7F)
     }
 
     fn start_document(&mut self) -> Result<(), SerializeError> {
-        self.writer.write_u8(0x03)?;
+        self.start_container(0x03)
+    }
 
-        // Push the current position to the stack
-        let current_position = self.current_position()?;
-        self.document_positions.push(current_position);
+    fn end_document(&mut self) -> Result<(), SerializeError> {
+        self.end_container()
+    }
 
-        self.writer.write_u32::<LittleEndian>(0)?;
-        Ok(())
+    fn start_array(&mut self) -> Result<(), SerializeError> {
+        self.start_container(0x04)
     }
 
-    fn end_document(&mut self) -> Result<(), SerializeError> {
-        self.write_document_length()?;
-        Ok(())
+    fn end_array(&mut self) -> Result<(), SerializeError> {
+        self.end_container()
     }
 
     fn serialize_field_name(&mut self, name: &str) -> Result<(), SerializeError> {
-        self.writer.write_all(name.as_bytes())?;
-        self.writer.write_u8(0)?;
+        if name.as_bytes().contains(&0) {
+            return Err(SerializeError::InvalidValue(format!(
+                "field name contains a null byte: {:?}",
+                name
+            )));
+        }
+        self.field_name.clear();
+        self.field_name.push_str(name);
+        self.has_field_name = true;
         Ok(())
     }
 }
@@ -1 +1,127 @@
-/// BSON Encoding logic.
\ No newline at end of file
+// src/ser/encoder.rs
+
+use std::io::{Cursor, Write};
+
+use super::bson::BsonSerializer;
+use super::error::SerializeError;
+use super::traits::Serializer;
+use crate::deser::EncodingHeader;
+use crate::types::Document;
+use crate::utils::buffer_pool::BufferPool;
+
+/// Encodes documents into BSON, reusing one output buffer across calls.
+#[derive(Debug, Default)]
+pub struct Encoder {
+    buffer: Vec<u8>,
+    pool: Option<BufferPool>, // Where the buffer came from, and goes back to on drop
+}
+
+impl Encoder {
+    /// Creates a new encoder with an empty buffer.
+    pub fn new() -> Self {
+        Encoder {
+            buffer: Vec::new(),
+            pool: None,
+        }
+    }
+
+    /// Creates a new encoder whose buffer is taken from `pool`, and given
+    /// back to it when the encoder is dropped.
+    pub fn with_pool(pool: &BufferPool) -> Self {
+        Encoder {
+            buffer: pool.take(0),
+            pool: Some(pool.clone()),
+        }
+    }
+
+    /// Encodes `document`, returning the bytes in the encoder's buffer.
+    ///
+    /// The returned slice is only valid until the next call.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the document contains a value that cannot be
+    /// encoded, such as a field name with an interior null byte.
+    pub fn encode(&mut self, document: &Document) -> Result<&[u8], SerializeError> {
+        let mut buffer = std::mem::take(&mut self.buffer);
+        buffer.clear();
+
+        let mut serializer = BsonSerializer::new(Cursor::new(buffer));
+        let result = serializer.serialize_document(document);
+        self.buffer = serializer.into_inner().into_inner();
+        result?;
+
+        Ok(&self.buffer)
+    }
+
+    /// Encodes `document` and writes it to `writer`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if encoding fails or the writer returns an error.
+    pub fn encode_to<W: Write>(
+        &mut self,
+        document: &Document,
+        writer: &mut W,
+    ) -> Result<(), SerializeError> {
+        let bytes = self.encode(document)?;
+        writer.write_all(bytes)?;
+        Ok(())
+    }
+}
+
+impl Drop for Encoder {
+    fn drop(&mut self) {
+        if let Some(pool) = &self.pool {
+            pool.put(std::mem::take(&mut self.buffer));
+        }
+    }
+}
+
+/// Encodes a document into a new BSON byte vector.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb_data_encoding::{to_bytes, Document, Value};
+/// let mut document = Document::new();
+/// document.insert("a", Value::Int32(1));
+///
+/// let bytes = to_bytes(&document).unwrap();
+/// assert_eq!(bytes, [12, 0, 0, 0, 0x10, b'a', 0, 1, 0, 0, 0, 0]);
+/// ```
+///
+/// # Errors
+///
+/// Returns an error if the document contains a value that cannot be encoded.
+pub fn to_bytes(document: &Document) -> Result<Vec<u8>, SerializeError> {
+    let mut serializer = BsonSerializer::new(Cursor::new(Vec::new()));
+    serializer.serialize_document(document)?;
+    Ok(serializer.into_inner().into_inner())
+}
+
+/// Encodes a document into a new byte vector, preceded by `header`.
+///
+/// # Errors
+///
+/// Returns an error if the document contains a value that cannot be encoded.
+pub fn to_bytes_with_header(
+    document: &Document,
+    header: &EncodingHeader,
+) -> Result<Vec<u8>, SerializeError> {
+    let mut buffer = Cursor::new(header.to_bytes().to_vec());
+    buffer.set_position(buffer.get_ref().len() as u64);
+    let mut serializer = BsonSerializer::new(buffer);
+    serializer.serialize_document(document)?;
+    Ok(serializer.into_inner().into_inner())
+}
+
+/// Encodes a document as BSON and writes it to `writer`.
+///
+/// # Errors
+///
+/// Returns an error if encoding fails or the writer returns an error.
+pub fn to_writer<W: Write>(writer: &mut W, document: &Document) -> Result<(), SerializeError> {
+    writer.write_all(&to_bytes(document)?)?;
+    Ok(())
+}
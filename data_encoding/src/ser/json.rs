@@ -1,148 +1,344 @@
-pub struct JsonSerializer{
-    output: String,
+// src/ser/json.rs
+
+use std::io::Write;
+
+use super::error::SerializeError;
+use super::traits::Serializer;
+use crate::types::{Array, Document, ObjectId, UTCDateTime};
+use crate::utils::{base64, hex};
+
+/// Serializes values as Extended JSON, writing directly to a writer.
+///
+/// By default output is relaxed Extended JSON: numbers are written as plain
+/// JSON numbers and dates in range as RFC 3339 strings. Canonical mode wraps
+/// every number and date (`{"$numberInt": "1"}`, ...) so the output parses back
+/// to exactly the same types. BSON's `UInt64` has no Extended JSON form and is
+/// always written as a plain number.
+pub struct JsonSerializer<W: Write> {
+    writer: W,
     pretty: bool,
-    indent_level: usize,
+    canonical: bool,
+    containers: Vec<Container>, // Open documents and arrays, innermost last
 }
 
-impl JsonSerializer {
-    pub fn new(pretty: bool) -> Self {
+/// An open document or array.
+struct Container {
+    is_array: bool,
+    has_elements: bool,
+}
+
+impl<W: Write> JsonSerializer<W> {
+    /// Creates a new JSON serializer writing relaxed Extended JSON to `writer`.
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - The writer to write JSON to.
+    /// * `pretty` - Whether to indent nested documents and arrays.
+    pub fn new(writer: W, pretty: bool) -> Self {
         JsonSerializer {
-            output: String::new(),
+            writer,
             pretty,
-            indent_level: 0,
+            canonical: false,
+            containers: Vec::new(),
         }
     }
 
-    fn write_indent(&mut self) {
-        if self.pretty {
-            self.output.push_str(&"  ".repeat(self.indent_level));
+    /// Creates a new JSON serializer writing canonical Extended JSON to `writer`.
+    pub fn canonical(writer: W, pretty: bool) -> Self {
+        JsonSerializer {
+            canonical: true,
+            ..JsonSerializer::new(writer, pretty)
         }
     }
 
-    fn write_newline(&mut self) {
-        if self.pretty {
-            self.output.push('\n');
-        }
+    /// Returns a mutable reference to the underlying writer.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Consumes the serializer, returning the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn write_str(&mut self, value: &str) -> Result<(), SerializeError> {
+        self.writer.write_all(value.as_bytes())?;
+        Ok(())
     }
 
-    fn write_separator(&mut self) {
+    fn write_indent(&mut self) -> Result<(), SerializeError> {
         if self.pretty {
-            self.output.push_str(",\n");
-        } else {
-            self.output.push(',');
+            self.writer.write_all(b"\n")?;
+            for _ in 0..self.containers.len() {
+                self.writer.write_all(b"  ")?;
+            }
         }
+        Ok(())
     }
-}
 
-impl Serializer for JsonSerializer{
+    fn write_quoted(&mut self, value: &str) -> Result<(), SerializeError> {
+        self.writer.write_all(b"\"")?;
 
-    fn serialize_f64(&mut self, value: f64) -> Result<(), SerializeError> {
-        if value.is_finite() {
-            self.output.push_str(&value.to_string());
-        } else if value.is_nan() {
-            self.output.push_str("null");
-        } else if value.is_infinite() {
-            if value.is_sign_positive() {
-                self.output.push_str("null"); 
+        // Properly escape the string, copying unescaped runs in one go
+        let mut start = 0;
+        for (index, c) in value.char_indices() {
+            let escaped = match c {
+                '"' => "\\\"",
+                '\\' => "\\\\",
+                '\n' => "\\n",
+                '\r' => "\\r",
+                '\t' => "\\t",
+                c if c.is_control() => "",
+                _ => continue,
+            };
+            self.writer.write_all(&value.as_bytes()[start..index])?;
+            if escaped.is_empty() {
+                write!(self.writer, "\\u{:04x}", c as u32)?;
             } else {
-                self.output.push_str("null");
+                self.writer.write_all(escaped.as_bytes())?;
             }
+            start = index + c.len_utf8();
         }
+        self.writer.write_all(&value.as_bytes()[start..])?;
+
+        self.writer.write_all(b"\"")?;
         Ok(())
     }
 
-    fn serialize_i32(&mut self, value: i32) -> Result<(), SerializeError> {
-        self.output.push_str(&value.to_string());
+    /// Writes a single-key type wrapper such as `{"$numberLong": "1"}`, where
+    /// `value` is already valid JSON.
+    fn write_wrapper(&mut self, key: &str, value: &str) -> Result<(), SerializeError> {
+        write!(self.writer, "{{\"{}\":{}}}", key, value)?;
         Ok(())
     }
 
-    fn serialize_i64(&mut self, value: i64) -> Result<(), SerializeError> {
-        self.output.push_str(&value.to_string());
+    fn start_container(&mut self, is_array: bool) -> Result<(), SerializeError> {
+        self.write_str(if is_array { "[" } else { "{" })?;
+        self.containers.push(Container {
+            is_array,
+            has_elements: false,
+        });
         Ok(())
     }
 
-    fn serialize_string(&mut self, value: &str) -> Result<(), SerializeError> {
-        self.output.push('"');
-        
-        // Properly escape the string
-        for c in value.chars() {
-            match c {
-                '"' => self.output.push_str("\\\""),
-                '\\' => self.output.push_str("\\\\"),
-                '\n' => self.output.push_str("\\n"),
-                '\r' => self.output.push_str("\\r"),
-                '\t' => self.output.push_str("\\t"),
-                c if c.is_control() => {
-                    self.output.push_str(&format!("\\u{:04x}", c as u32));
-                }
-                c => self.output.push(c),
+    fn end_container(&mut self, is_array: bool) -> Result<(), SerializeError> {
+        let container = match self.containers.pop() {
+            Some(container) if container.is_array == is_array => container,
+            _ => {
+                return Err(SerializeError::InvalidDocument(
+                    "unbalanced document or array end".to_string(),
+                ))
             }
+        };
+        if container.has_elements {
+            self.write_indent()?;
         }
-        
-        self.output.push('"');
-        Ok(())
+        self.write_str(if is_array { "]" } else { "}" })
+    }
+}
+
+impl<W: Write> Serializer for JsonSerializer<W> {
+    fn serialize_f64(&mut self, value: f64) -> Result<(), SerializeError> {
+        let text = if value.is_nan() {
+            "NaN".to_string()
+        } else if value.is_infinite() {
+            if value.is_sign_positive() { "Infinity" } else { "-Infinity" }.to_string()
+        } else if value.fract() == 0.0 && value.abs() < 1e16 {
+            // Keep a fraction so the value reads back as a double
+            format!("{:.1}", value)
+        } else {
+            format!("{:?}", value)
+        };
+
+        if self.canonical || !value.is_finite() {
+            self.write_wrapper("$numberDouble", &format!("\"{}\"", text))
+        } else {
+            self.write_str(&text)
+        }
+    }
+
+    fn serialize_string(&mut self, value: &str) -> Result<(), SerializeError> {
+        self.write_quoted(value)
     }
 
     fn serialize_document(&mut self, value: &Document) -> Result<(), SerializeError> {
         self.start_document()?;
-        
-        let mut first = true;
         for (key, value) in value.iter() {
-            if !first {
-                self.write_separator();
-            }
-            first = false;
-            
             self.serialize_field_name(key)?;
             value.serialize(self)?;
         }
-        
-        self.end_document()?;
-        Ok(())
+        self.end_document()
     }
 
     fn serialize_array(&mut self, value: &Array) -> Result<(), SerializeError> {
-        self.output.push('[');
-        self.indent_level += 1;
-        self.write_newline();
-        
-        let mut first = true;
-        for value in value.iter() {
-            if !first {
-                self.write_separator();
-            }
-            first = false;
-            
-            self.write_indent();
+        self.start_array()?;
+        for (index, value) in value.iter().enumerate() {
+            self.serialize_field_name(&index.to_string())?;
             value.serialize(self)?;
         }
-        
-        self.indent_level -= 1;
-        self.write_newline();
-        self.write_indent();
-        self.output.push(']');
+        self.end_array()
+    }
+
+    fn serialize_binary(&mut self, value: &[u8]) -> Result<(), SerializeError> {
+        write!(
+            self.writer,
+            "{{\"$binary\":{{\"base64\":\"{}\",\"subType\":\"00\"}}}}",
+            base64::encode(value)
+        )?;
         Ok(())
     }
 
-    fn start_document(&mut self) -> Result<(), SerializeError> {
-        self.output.push('{');
-        self.indent_level += 1;
-        self.write_newline();
+    fn serialize_undefined(&mut self) -> Result<(), SerializeError> {
+        self.write_wrapper("$undefined", "true")
+    }
+
+    fn serialize_object_id(&mut self, value: ObjectId) -> Result<(), SerializeError> {
+        self.write_wrapper("$oid", &format!("\"{}\"", hex::encode(value.as_bytes())))
+    }
+
+    fn serialize_boolean(&mut self, value: bool) -> Result<(), SerializeError> {
+        self.write_str(if value { "true" } else { "false" })
+    }
+
+    fn serialize_utc_datetime(&mut self, value: i64) -> Result<(), SerializeError> {
+        // Relaxed mode uses RFC 3339 for years 1970 through 9999
+        if !self.canonical && (0..=253_402_300_799).contains(&value) {
+            let date = UTCDateTime::from_secs(value).to_rfc3339();
+            return self.write_wrapper("$date", &format!("\"{}\"", date));
+        }
+        let millis = value.saturating_mul(1000);
+        self.write_wrapper("$date", &format!("{{\"$numberLong\":\"{}\"}}", millis))
+    }
+
+    fn serialize_null(&mut self) -> Result<(), SerializeError> {
+        self.write_str("null")
+    }
+
+    fn serialize_regex(&mut self, pattern: &str, options: &str) -> Result<(), SerializeError> {
+        self.write_str("{\"$regularExpression\":{\"pattern\":")?;
+        self.write_quoted(pattern)?;
+        self.write_str(",\"options\":")?;
+        self.write_quoted(options)?;
+        self.write_str("}}")
+    }
+
+    fn serialize_db_pointer(
+        &mut self,
+        collection: &str,
+        id: ObjectId,
+    ) -> Result<(), SerializeError> {
+        self.write_str("{\"$dbPointer\":{\"$ref\":")?;
+        self.write_quoted(collection)?;
+        write!(
+            self.writer,
+            ",\"$id\":{{\"$oid\":\"{}\"}}}}}}",
+            hex::encode(id.as_bytes())
+        )?;
         Ok(())
     }
 
-    fn end_document(&mut self) -> Result<(), SerializeError> {
-        self.indent_level -= 1;
-        self.write_newline();
-        self.write_indent();
-        self.output.push('}');
+    fn serialize_javascript_code(&mut self, code: &str) -> Result<(), SerializeError> {
+        self.write_str("{\"$code\":")?;
+        self.write_quoted(code)?;
+        self.write_str("}")
+    }
+
+    fn serialize_symbol(&mut self, symbol: &str) -> Result<(), SerializeError> {
+        self.write_str("{\"$symbol\":")?;
+        self.write_quoted(symbol)?;
+        self.write_str("}")
+    }
+
+    fn serialize_javascript_code_with_scope(
+        &mut self,
+        code: &str,
+        scope: &Document,
+    ) -> Result<(), SerializeError> {
+        self.write_str("{\"$code\":")?;
+        self.write_quoted(code)?;
+        self.write_str(",\"$scope\":")?;
+
+        // The scope is written compactly, independent of the surrounding indentation
+        let pretty = std::mem::replace(&mut self.pretty, false);
+        let result = self.serialize_document(scope);
+        self.pretty = pretty;
+        result?;
+
+        self.write_str("}")
+    }
+
+    fn serialize_i32(&mut self, value: i32) -> Result<(), SerializeError> {
+        if self.canonical {
+            self.write_wrapper("$numberInt", &format!("\"{}\"", value))
+        } else {
+            self.write_str(&value.to_string())
+        }
+    }
+
+    fn serialize_timestamp(&mut self, value: i64) -> Result<(), SerializeError> {
+        let value = value as u64;
+        write!(
+            self.writer,
+            "{{\"$timestamp\":{{\"t\":{},\"i\":{}}}}}",
+            value >> 32,
+            value & 0xFFFF_FFFF
+        )?;
         Ok(())
     }
 
+    fn serialize_i64(&mut self, value: i64) -> Result<(), SerializeError> {
+        if self.canonical {
+            self.write_wrapper("$numberLong", &format!("\"{}\"", value))
+        } else {
+            self.write_str(&value.to_string())
+        }
+    }
+
+    fn serialize_u64(&mut self, value: u64) -> Result<(), SerializeError> {
+        self.write_str(&value.to_string())
+    }
+
+    fn serialize_min_key(&mut self) -> Result<(), SerializeError> {
+        self.write_wrapper("$minKey", "1")
+    }
+
+    fn serialize_max_key(&mut self) -> Result<(), SerializeError> {
+        self.write_wrapper("$maxKey", "1")
+    }
+
+    fn start_document(&mut self) -> Result<(), SerializeError> {
+        self.start_container(false)
+    }
+
+    fn end_document(&mut self) -> Result<(), SerializeError> {
+        self.end_container(false)
+    }
+
+    fn start_array(&mut self) -> Result<(), SerializeError> {
+        self.start_container(true)
+    }
+
+    fn end_array(&mut self) -> Result<(), SerializeError> {
+        self.end_container(true)
+    }
+
+    /// Writes the separator before the next element, followed by the quoted
+    /// key for document fields. Array indices are not written.
     fn serialize_field_name(&mut self, name: &str) -> Result<(), SerializeError> {
-        self.write_indent();
-        self.serialize_string(name)?;
-        self.output.push_str(": ");
+        let container = self.containers.last_mut().ok_or_else(|| {
+            SerializeError::InvalidDocument("field name outside of a document".to_string())
+        })?;
+        let needs_separator = std::mem::replace(&mut container.has_elements, true);
+        let is_array = container.is_array;
+
+        if needs_separator {
+            self.write_str(",")?;
+        }
+        self.write_indent()?;
+        if !is_array {
+            self.write_quoted(name)?;
+            self.write_str(if self.pretty { ": " } else { ":" })?;
+        }
         Ok(())
     }
-}
\ No newline at end of file
+}
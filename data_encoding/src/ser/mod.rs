@@ -4,8 +4,11 @@ mod error;
 mod traits;
 mod bson;
 mod encoder;
+mod json;
+mod test;
 
 pub use error::SerializeError;
 pub use traits::Serializer;
 pub use bson::BsonSerializer;
-
+pub use encoder::{Encoder, to_bytes, to_bytes_with_header, to_writer};
+pub use json::JsonSerializer;
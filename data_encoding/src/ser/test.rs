@@ -0,0 +1,174 @@
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::ser::{to_bytes, BsonSerializer, Encoder, JsonSerializer, SerializeError, Serializer};
+    use crate::types::{Array, Document, ObjectId, Value};
+
+    fn single(key: &str, value: Value) -> Document {
+        let mut document = Document::new();
+        document.insert(key, value);
+        document
+    }
+
+    fn to_json(value: &Value, canonical: bool) -> String {
+        let mut serializer = if canonical {
+            JsonSerializer::canonical(Vec::new(), false)
+        } else {
+            JsonSerializer::new(Vec::new(), false)
+        };
+        serializer.serialize_document(&single("v", value.clone())).unwrap();
+        String::from_utf8(serializer.into_inner()).unwrap()
+    }
+
+    // -------------------------------------
+    //          BSON Serializer Tests
+    // -------------------------------------
+
+    #[test]
+    fn test_to_bytes_empty_document() {
+        assert_eq!(to_bytes(&Document::new()).unwrap(), [5, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_to_bytes_element_layout() {
+        let bytes = to_bytes(&single("s", Value::String("hi".to_string()))).unwrap();
+        assert_eq!(
+            bytes,
+            [15, 0, 0, 0, 0x02, b's', 0, 3, 0, 0, 0, b'h', b'i', 0, 0]
+        );
+    }
+
+    #[test]
+    fn test_to_bytes_nested_lengths() {
+        let mut array = Array::new();
+        array.push(Value::Boolean(true));
+        let bytes = to_bytes(&single("a", Value::Array(array))).unwrap();
+        assert_eq!(
+            bytes,
+            [17, 0, 0, 0, 0x04, b'a', 0, 9, 0, 0, 0, 0x08, b'0', 0, 1, 0, 0]
+        );
+    }
+
+    #[test]
+    fn test_to_bytes_utc_datetime_and_code() {
+        let bytes = to_bytes(&single("d", Value::UTCDateTime(1))).unwrap();
+        assert_eq!(bytes[4], 0x09);
+
+        let bytes = to_bytes(&single("c", Value::JavaScriptCode("x".to_string()))).unwrap();
+        assert_eq!(&bytes[4..], [0x0D, b'c', 0, 2, 0, 0, 0, b'x', 0, 0]);
+    }
+
+    #[test]
+    fn test_to_bytes_rejects_null_in_key() {
+        let result = to_bytes(&single("a\0b", Value::Null));
+        assert!(matches!(result, Err(SerializeError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn test_bson_serializer_requires_field_name() {
+        let mut serializer = BsonSerializer::new(Cursor::new(Vec::new()));
+        serializer.start_document().unwrap();
+        assert!(matches!(
+            serializer.serialize_i32(1),
+            Err(SerializeError::InvalidDocument(_))
+        ));
+    }
+
+    #[test]
+    fn test_encoder_reuses_buffer() {
+        let mut encoder = Encoder::new();
+        let first = encoder.encode(&single("a", Value::Int64(7))).unwrap().to_vec();
+        let second = encoder.encode(&Document::new()).unwrap().to_vec();
+        assert_eq!(first, to_bytes(&single("a", Value::Int64(7))).unwrap());
+        assert_eq!(second, [5, 0, 0, 0, 0]);
+
+        let mut output = Vec::new();
+        encoder.encode_to(&Document::new(), &mut output).unwrap();
+        assert_eq!(output, [5, 0, 0, 0, 0]);
+    }
+
+    // -------------------------------------
+    //          JSON Serializer Tests
+    // -------------------------------------
+
+    #[test]
+    fn test_json_relaxed_values() {
+        assert_eq!(to_json(&Value::Int32(1), false), r#"{"v":1}"#);
+        assert_eq!(to_json(&Value::Int64(-2), false), r#"{"v":-2}"#);
+        assert_eq!(to_json(&Value::Double(1.0), false), r#"{"v":1.0}"#);
+        assert_eq!(to_json(&Value::Double(0.5), false), r#"{"v":0.5}"#);
+        assert_eq!(
+            to_json(&Value::Double(f64::NAN), false),
+            r#"{"v":{"$numberDouble":"NaN"}}"#
+        );
+        assert_eq!(
+            to_json(&Value::UTCDateTime(0), false),
+            r#"{"v":{"$date":"1970-01-01T00:00:00Z"}}"#
+        );
+        assert_eq!(
+            to_json(&Value::UTCDateTime(-1), false),
+            r#"{"v":{"$date":{"$numberLong":"-1000"}}}"#
+        );
+    }
+
+    #[test]
+    fn test_json_canonical_values() {
+        assert_eq!(to_json(&Value::Int32(1), true), r#"{"v":{"$numberInt":"1"}}"#);
+        assert_eq!(to_json(&Value::Int64(1), true), r#"{"v":{"$numberLong":"1"}}"#);
+        assert_eq!(to_json(&Value::Double(1.0), true), r#"{"v":{"$numberDouble":"1.0"}}"#);
+        assert_eq!(
+            to_json(&Value::UTCDateTime(1), true),
+            r#"{"v":{"$date":{"$numberLong":"1000"}}}"#
+        );
+    }
+
+    #[test]
+    fn test_json_type_wrappers() {
+        let id = ObjectId::from_bytes([0xAB; 12]);
+        assert_eq!(
+            to_json(&Value::ObjectId(id), false),
+            r#"{"v":{"$oid":"abababababababababababab"}}"#
+        );
+        assert_eq!(
+            to_json(&Value::Binary(vec![1, 2, 3, 4]), false),
+            r#"{"v":{"$binary":{"base64":"AQIDBA==","subType":"00"}}}"#
+        );
+        assert_eq!(
+            to_json(&Value::Timestamp((5 << 32) | 6), false),
+            r#"{"v":{"$timestamp":{"t":5,"i":6}}}"#
+        );
+        assert_eq!(
+            to_json(
+                &Value::RegularExpression {
+                    pattern: "a\"b".to_string(),
+                    options: "i".to_string()
+                },
+                false
+            ),
+            r#"{"v":{"$regularExpression":{"pattern":"a\"b","options":"i"}}}"#
+        );
+        assert_eq!(to_json(&Value::MinKey, false), r#"{"v":{"$minKey":1}}"#);
+    }
+
+    #[test]
+    fn test_json_string_escapes() {
+        assert_eq!(
+            to_json(&Value::String("a\n\u{1}é".to_string()), false),
+            "{\"v\":\"a\\n\\u0001é\"}"
+        );
+    }
+
+    #[test]
+    fn test_json_pretty_nesting() {
+        let mut array = Array::new();
+        array.push(Value::Int32(1));
+        array.push(Value::Document(Document::new()));
+        let mut serializer = JsonSerializer::new(Vec::new(), true);
+        serializer.serialize_document(&single("a", Value::Array(array))).unwrap();
+        assert_eq!(
+            String::from_utf8(serializer.into_inner()).unwrap(),
+            "{\n  \"a\": [\n    1,\n    {}\n  ]\n}"
+        );
+    }
+}
@@ -1,4 +1,5 @@
 use super::SerializeError;
+use crate::types::{Array, Document, ObjectId};
 
 /// The main Serializer trait. Defines methods for serializing BSON values.
 ///
@@ -252,6 +253,19 @@ pub trait Serializer {
     /// Returns an error if the serialization fails.
     fn end_document(&mut self) -> Result<(), SerializeError>;
 
+    /// Starts a new array. Array elements are written like document fields,
+    /// each preceded by `serialize_field_name` with its index.
+    ///
+    /// # Errors
+    /// Returns an error if the serialization fails.
+    fn start_array(&mut self) -> Result<(), SerializeError>;
+
+    /// Ends the current array.
+    ///
+    /// # Errors
+    /// Returns an error if the serialization fails.
+    fn end_array(&mut self) -> Result<(), SerializeError>;
+
     /// Serializes a field name.
     ///
     /// # Arguments
@@ -0,0 +1,107 @@
+// src/testing/diff.rs
+
+use std::fmt;
+
+use crate::types::{Array, Document, FieldName, Value};
+
+/// A single difference between two documents, addressed by dotted path.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Difference {
+    /// The field exists only in the expected document.
+    Missing { path: String, expected: Value },
+    /// The field exists only in the actual document.
+    Unexpected { path: String, actual: Value },
+    /// The field exists in both with different values.
+    Changed {
+        path: String,
+        expected: Value,
+        actual: Value,
+    },
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Difference::Missing { path, expected } => write!(f, "- {}: {:?}", path, expected),
+            Difference::Unexpected { path, actual } => write!(f, "+ {}: {:?}", path, actual),
+            Difference::Changed {
+                path,
+                expected,
+                actual,
+            } => write!(f, "~ {}: expected {:?}, found {:?}", path, expected, actual),
+        }
+    }
+}
+
+/// Compares two documents field by field, returning the differences sorted by path.
+///
+/// Field order is ignored and NaN doubles compare equal to each other.
+pub fn diff_documents(expected: &Document, actual: &Document) -> Vec<Difference> {
+    let mut differences = Vec::new();
+    diff_document(expected, actual, "", &mut differences);
+    differences
+}
+
+fn join(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", prefix, key)
+    }
+}
+
+fn diff_document(expected: &Document, actual: &Document, prefix: &str, out: &mut Vec<Difference>) {
+    let mut keys: Vec<&FieldName> = expected.iter().map(|(key, _)| key).collect();
+    keys.extend(actual.iter().map(|(key, _)| key).filter(|key| !expected.contains_key(key)));
+    keys.sort_unstable();
+
+    for key in keys {
+        let path = join(prefix, key);
+        match (expected.get(key), actual.get(key)) {
+            (Some(expected), Some(actual)) => diff_value(expected, actual, path, out),
+            (Some(expected), None) => out.push(Difference::Missing {
+                path,
+                expected: expected.clone(),
+            }),
+            (None, Some(actual)) => out.push(Difference::Unexpected {
+                path,
+                actual: actual.clone(),
+            }),
+            (None, None) => unreachable!(),
+        }
+    }
+}
+
+fn diff_array(expected: &Array, actual: &Array, prefix: &str, out: &mut Vec<Difference>) {
+    for index in 0..expected.len().max(actual.len()) {
+        let path = join(prefix, &index.to_string());
+        match (expected.get(index), actual.get(index)) {
+            (Some(expected), Some(actual)) => diff_value(expected, actual, path, out),
+            (Some(expected), None) => out.push(Difference::Missing {
+                path,
+                expected: expected.clone(),
+            }),
+            (None, Some(actual)) => out.push(Difference::Unexpected {
+                path,
+                actual: actual.clone(),
+            }),
+            (None, None) => unreachable!(),
+        }
+    }
+}
+
+fn diff_value(expected: &Value, actual: &Value, path: String, out: &mut Vec<Difference>) {
+    match (expected, actual) {
+        (Value::Document(expected), Value::Document(actual)) => {
+            diff_document(expected, actual, &path, out)
+        }
+        (Value::Array(expected), Value::Array(actual)) => diff_array(expected, actual, &path, out),
+        (Value::Double(a), Value::Double(b)) if a.is_nan() && b.is_nan() => {}
+        _ if expected == actual => {}
+        _ => out.push(Difference::Changed {
+            path,
+            expected: expected.clone(),
+            actual: actual.clone(),
+        }),
+    }
+}
@@ -0,0 +1,157 @@
+// src/testing/mod.rs
+
+//! Round-trip conformance checks for encoders, decoders and custom
+//! `Serializer` backends.
+//!
+//! The `check_*` functions return a `RoundTripFailure` describing what went
+//! wrong, for use in property tests; the `assert_*` functions panic with the
+//! same readable report.
+
+mod diff;
+mod test;
+
+use std::fmt;
+
+pub use diff::{diff_documents, Difference};
+
+use crate::deser::{from_bytes, DeserializeError};
+use crate::ser::{to_bytes, SerializeError, Serializer};
+use crate::types::Document;
+
+/// Why a round-trip check failed.
+#[derive(Debug)]
+pub enum RoundTripFailure {
+    /// The document could not be encoded.
+    Encode(SerializeError),
+    /// The encoded bytes could not be decoded.
+    Decode(DeserializeError),
+    /// The decoded document differs from the original.
+    Mismatch(Vec<Difference>),
+}
+
+impl fmt::Display for RoundTripFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RoundTripFailure::Encode(e) => write!(f, "encoding failed: {}", e),
+            RoundTripFailure::Decode(e) => write!(f, "decoding failed: {}", e),
+            RoundTripFailure::Mismatch(differences) => {
+                write!(f, "decoded document differs in {} place(s):", differences.len())?;
+                for difference in differences {
+                    write!(f, "\n  {}", difference)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for RoundTripFailure {}
+
+/// Checks that two documents are equal, ignoring field order.
+///
+/// # Errors
+///
+/// Returns `RoundTripFailure::Mismatch` listing every difference.
+pub fn check_documents_equivalent(
+    expected: &Document,
+    actual: &Document,
+) -> Result<(), RoundTripFailure> {
+    let differences = diff_documents(expected, actual);
+    if differences.is_empty() {
+        Ok(())
+    } else {
+        Err(RoundTripFailure::Mismatch(differences))
+    }
+}
+
+/// Checks that `bytes` decode to a document equivalent to `expected`.
+///
+/// # Errors
+///
+/// Returns an error if decoding fails or the documents differ.
+pub fn check_decodes_to(bytes: &[u8], expected: &Document) -> Result<(), RoundTripFailure> {
+    let decoded = from_bytes(bytes).map_err(RoundTripFailure::Decode)?;
+    check_documents_equivalent(expected, &decoded)
+}
+
+/// Checks that `document` survives encoding with `to_bytes` and decoding with `from_bytes`.
+///
+/// # Errors
+///
+/// Returns an error describing the first stage that failed.
+pub fn check_round_trip(document: &Document) -> Result<(), RoundTripFailure> {
+    let bytes = to_bytes(document).map_err(RoundTripFailure::Encode)?;
+    check_decodes_to(&bytes, document)
+}
+
+/// Checks a custom BSON `Serializer` backend: serializes `document` with
+/// `serializer`, takes the output with `into_bytes`, and checks that it
+/// decodes back to `document`.
+///
+/// # Errors
+///
+/// Returns an error describing the first stage that failed.
+pub fn check_serializer_round_trip<S, F>(
+    document: &Document,
+    mut serializer: S,
+    into_bytes: F,
+) -> Result<(), RoundTripFailure>
+where
+    S: Serializer,
+    F: FnOnce(S) -> Vec<u8>,
+{
+    serializer
+        .serialize_document(document)
+        .map_err(RoundTripFailure::Encode)?;
+    check_decodes_to(&into_bytes(serializer), document)
+}
+
+/// Asserts that two documents are equal, ignoring field order.
+///
+/// # Panics
+///
+/// Panics with a per-field diff if they differ.
+#[track_caller]
+pub fn assert_documents_equivalent(expected: &Document, actual: &Document) {
+    if let Err(failure) = check_documents_equivalent(expected, actual) {
+        panic!("{}", failure);
+    }
+}
+
+/// Asserts that `document` survives an encode/decode round trip.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb_data_encoding::{testing::assert_round_trip, Document, Value};
+/// let mut document = Document::new();
+/// document.insert("a", Value::Int64(1));
+/// assert_round_trip(&document);
+/// ```
+///
+/// # Panics
+///
+/// Panics with the error or a per-field diff if the round trip fails.
+#[track_caller]
+pub fn assert_round_trip(document: &Document) {
+    if let Err(failure) = check_round_trip(document) {
+        panic!("{}", failure);
+    }
+}
+
+/// Asserts that a custom `Serializer` backend produces BSON that decodes back
+/// to `document`. See `check_serializer_round_trip`.
+///
+/// # Panics
+///
+/// Panics with the error or a per-field diff if the round trip fails.
+#[track_caller]
+pub fn assert_serializer_round_trip<S, F>(document: &Document, serializer: S, into_bytes: F)
+where
+    S: Serializer,
+    F: FnOnce(S) -> Vec<u8>,
+{
+    if let Err(failure) = check_serializer_round_trip(document, serializer, into_bytes) {
+        panic!("{}", failure);
+    }
+}
@@ -0,0 +1,122 @@
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::ser::{BsonSerializer, JsonSerializer};
+    use crate::testing::{
+        assert_round_trip, assert_serializer_round_trip, check_decodes_to, check_documents_equivalent,
+        check_serializer_round_trip, diff_documents, Difference, RoundTripFailure,
+    };
+    use crate::types::{Array, Document, Value};
+
+    fn document(fields: &[(&str, Value)]) -> Document {
+        let mut document = Document::new();
+        for (key, value) in fields {
+            document.insert(*key, value.clone());
+        }
+        document
+    }
+
+    // -------------------------------------
+    //          Diff Tests
+    // -------------------------------------
+
+    #[test]
+    fn test_diff_documents_paths() {
+        let expected = document(&[
+            ("a", Value::Int32(1)),
+            ("b", Value::Document(document(&[("c", Value::Null)]))),
+            ("d", Value::Array(Array::from(vec![Value::Int32(1), Value::Int32(2)]))),
+        ]);
+        let actual = document(&[
+            ("a", Value::Int64(1)),
+            ("b", Value::Document(Document::new())),
+            ("d", Value::Array(Array::from(vec![Value::Int32(1)]))),
+            ("e", Value::Boolean(true)),
+        ]);
+
+        assert_eq!(
+            diff_documents(&expected, &actual),
+            [
+                Difference::Changed {
+                    path: "a".to_string(),
+                    expected: Value::Int32(1),
+                    actual: Value::Int64(1),
+                },
+                Difference::Missing {
+                    path: "b.c".to_string(),
+                    expected: Value::Null,
+                },
+                Difference::Missing {
+                    path: "d.1".to_string(),
+                    expected: Value::Int32(2),
+                },
+                Difference::Unexpected {
+                    path: "e".to_string(),
+                    actual: Value::Boolean(true),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_documents_nan_equal() {
+        let nan = document(&[("x", Value::Double(f64::NAN))]);
+        assert!(diff_documents(&nan, &nan.clone()).is_empty());
+    }
+
+    #[test]
+    fn test_mismatch_report() {
+        let failure = check_documents_equivalent(
+            &document(&[("a", Value::Int32(1))]),
+            &Document::new(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            failure.to_string(),
+            "decoded document differs in 1 place(s):\n  - a: Int32(1)"
+        );
+    }
+
+    // -------------------------------------
+    //          Round Trip Tests
+    // -------------------------------------
+
+    #[test]
+    fn test_assert_round_trip() {
+        assert_round_trip(&document(&[
+            ("a", Value::String("x".to_string())),
+            ("b", Value::Array(Array::from(vec![Value::MinKey]))),
+        ]));
+    }
+
+    #[test]
+    fn test_check_decodes_to_errors() {
+        assert!(matches!(
+            check_decodes_to(&[1, 2, 3], &Document::new()),
+            Err(RoundTripFailure::Decode(_))
+        ));
+    }
+
+    #[test]
+    fn test_serializer_round_trip() {
+        let original = document(&[("a", Value::Int32(1))]);
+        assert_serializer_round_trip(&original, BsonSerializer::new(Cursor::new(Vec::new())), |s| {
+            s.into_inner().into_inner()
+        });
+
+        // JSON output is not BSON
+        assert!(matches!(
+            check_serializer_round_trip(&original, JsonSerializer::new(Vec::new(), false), |s| {
+                s.into_inner()
+            }),
+            Err(RoundTripFailure::Decode(_))
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "- a: Int32(1)")]
+    fn test_assert_documents_equivalent_panics_with_diff() {
+        crate::testing::assert_documents_equivalent(&document(&[("a", Value::Int32(1))]), &Document::new());
+    }
+}
@@ -0,0 +1,216 @@
+// src/types/arbitrary.rs
+
+//! Structured random generation for fuzzing (`arbitrary` feature) and
+//! property tests (`proptest` feature).
+//!
+//! Generated documents are nested at most `MAX_DEPTH` levels deep with at most
+//! `MAX_FIELDS` fields or items per level, and always encode successfully: keys
+//! and regex strings contain no null bytes, and deprecated JavaScript code with
+//! scope is never generated.
+
+use super::{Array, Document, ObjectId, Timestamp, UTCDateTime, Value};
+
+/// Maximum nesting of generated documents and arrays.
+const MAX_DEPTH: usize = 4;
+/// Maximum number of fields in a generated document or items in an array.
+const MAX_FIELDS: usize = 8;
+
+#[cfg(feature = "arbitrary")]
+mod fuzz {
+    use arbitrary::{Arbitrary, Result, Unstructured};
+
+    use super::*;
+
+    /// A string without null bytes, usable as a key or C string.
+    fn cstring(u: &mut Unstructured) -> Result<String> {
+        Ok(String::arbitrary(u)?.replace('\0', ""))
+    }
+
+    fn value(u: &mut Unstructured, depth: usize) -> Result<Value> {
+        // Documents and arrays are the last two choices, excluded at the depth bound
+        let last = if depth >= MAX_DEPTH { 14 } else { 16 };
+        Ok(match u.int_in_range(0..=last)? {
+            0 => Value::Double(f64::arbitrary(u)?),
+            1 => Value::String(String::arbitrary(u)?),
+            2 => Value::Binary(Vec::arbitrary(u)?),
+            3 => Value::ObjectId(ObjectId::arbitrary(u)?),
+            4 => Value::Boolean(bool::arbitrary(u)?),
+            5 => Value::UTCDateTime(i64::arbitrary(u)?),
+            6 => Value::Null,
+            7 => Value::RegularExpression {
+                pattern: cstring(u)?,
+                options: cstring(u)?,
+            },
+            8 => Value::JavaScriptCode(String::arbitrary(u)?),
+            9 => Value::Int32(i32::arbitrary(u)?),
+            10 => Value::Timestamp(i64::arbitrary(u)?),
+            11 => Value::Int64(i64::arbitrary(u)?),
+            12 => Value::UInt64(u64::arbitrary(u)?),
+            13 => Value::MinKey,
+            14 => Value::MaxKey,
+            15 => Value::Document(document(u, depth + 1)?),
+            _ => Value::Array(array(u, depth + 1)?),
+        })
+    }
+
+    fn document(u: &mut Unstructured, depth: usize) -> Result<Document> {
+        let mut document = Document::new();
+        for _ in 0..u.int_in_range(0..=MAX_FIELDS)? {
+            let key = cstring(u)?;
+            document.insert(key, value(u, depth)?);
+        }
+        Ok(document)
+    }
+
+    fn array(u: &mut Unstructured, depth: usize) -> Result<Array> {
+        let mut array = Array::new();
+        for _ in 0..u.int_in_range(0..=MAX_FIELDS)? {
+            array.push(value(u, depth)?);
+        }
+        Ok(array)
+    }
+
+    impl<'a> Arbitrary<'a> for Value {
+        fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+            value(u, 0)
+        }
+    }
+
+    impl<'a> Arbitrary<'a> for Document {
+        fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+            document(u, 0)
+        }
+    }
+
+    impl<'a> Arbitrary<'a> for Array {
+        fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+            array(u, 0)
+        }
+    }
+
+    impl<'a> Arbitrary<'a> for ObjectId {
+        fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+            Ok(ObjectId::from_bytes(<[u8; 12]>::arbitrary(u)?))
+        }
+    }
+
+    impl<'a> Arbitrary<'a> for UTCDateTime {
+        fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+            Ok(UTCDateTime::from_secs(i64::arbitrary(u)?))
+        }
+    }
+
+    impl<'a> Arbitrary<'a> for Timestamp {
+        fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+            Ok(Timestamp::from_secs(i64::arbitrary(u)?))
+        }
+    }
+}
+
+#[cfg(feature = "proptest")]
+mod property {
+    use proptest::collection::{hash_map, vec};
+    use proptest::prelude::*;
+
+    use super::*;
+
+    /// Short strings without null bytes, for keys and C strings.
+    fn cstring() -> impl Strategy<Value = String> {
+        "[^\\x00]{0,8}"
+    }
+
+    fn leaf() -> impl Strategy<Value = Value> {
+        prop_oneof![
+            any::<f64>().prop_map(Value::Double),
+            any::<String>().prop_map(Value::String),
+            vec(any::<u8>(), 0..16).prop_map(Value::Binary),
+            any::<ObjectId>().prop_map(Value::ObjectId),
+            any::<bool>().prop_map(Value::Boolean),
+            any::<i64>().prop_map(Value::UTCDateTime),
+            Just(Value::Null),
+            (cstring(), cstring())
+                .prop_map(|(pattern, options)| Value::RegularExpression { pattern, options }),
+            any::<String>().prop_map(Value::JavaScriptCode),
+            any::<i32>().prop_map(Value::Int32),
+            any::<i64>().prop_map(Value::Timestamp),
+            any::<i64>().prop_map(Value::Int64),
+            any::<u64>().prop_map(Value::UInt64),
+            Just(Value::MinKey),
+            Just(Value::MaxKey),
+        ]
+    }
+
+    fn to_document(fields: std::collections::HashMap<String, Value>) -> Document {
+        let mut document = Document::new();
+        for (key, value) in fields {
+            document.insert(key, value);
+        }
+        document
+    }
+
+    impl Arbitrary for Value {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Value>;
+
+        fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+            leaf()
+                .prop_recursive(MAX_DEPTH as u32, 64, MAX_FIELDS as u32, |inner| {
+                    prop_oneof![
+                        hash_map(cstring(), inner.clone(), 0..=MAX_FIELDS)
+                            .prop_map(|fields| Value::Document(to_document(fields))),
+                        vec(inner, 0..=MAX_FIELDS).prop_map(|items| Value::Array(Array::from(items))),
+                    ]
+                })
+                .boxed()
+        }
+    }
+
+    impl Arbitrary for Document {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Document>;
+
+        fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+            hash_map(cstring(), any::<Value>(), 0..=MAX_FIELDS)
+                .prop_map(to_document)
+                .boxed()
+        }
+    }
+
+    impl Arbitrary for Array {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Array>;
+
+        fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+            vec(any::<Value>(), 0..=MAX_FIELDS)
+                .prop_map(Array::from)
+                .boxed()
+        }
+    }
+
+    impl Arbitrary for ObjectId {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<ObjectId>;
+
+        fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+            any::<[u8; 12]>().prop_map(ObjectId::from_bytes).boxed()
+        }
+    }
+
+    impl Arbitrary for UTCDateTime {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<UTCDateTime>;
+
+        fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+            any::<i64>().prop_map(UTCDateTime::from_secs).boxed()
+        }
+    }
+
+    impl Arbitrary for Timestamp {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Timestamp>;
+
+        fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+            any::<i64>().prop_map(Timestamp::from_secs).boxed()
+        }
+    }
+}
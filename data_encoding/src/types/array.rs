@@ -3,6 +3,10 @@ use crate::types::Value;
 
 
 /// Represents a BSON array.
+///
+/// Elements are kept contiguously in a single vector, which allocates
+/// nothing while the array is empty, so small arrays need no special form
+/// as small documents do.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Array {
     inner: Vec<Value>,
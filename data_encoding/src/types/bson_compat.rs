@@ -0,0 +1,197 @@
+// src/types/bson_compat.rs
+
+use bson::spec::BinarySubtype;
+use bson::{Binary, Bson, DateTime, JavaScriptCodeWithScope, Regex};
+
+use super::convert::type_name;
+use crate::deser::{from_bytes_with_options, DecoderOptions, LegacyTypes};
+use crate::ser::to_bytes;
+use crate::types::{Array, Document, ObjectId, Value, ValueConversionError};
+
+/// Converts a value of the `bson` crate, as used by the MongoDB driver.
+///
+/// Dates keep their instant, though this crate's hold whole seconds, so
+/// milliseconds are dropped. Binaries lose their subtype. `Decimal128` has
+/// no counterpart here and fails to convert.
+impl TryFrom<Bson> for Value {
+    type Error = ValueConversionError;
+
+    fn try_from(value: Bson) -> Result<Self, Self::Error> {
+        let value = match value {
+            Bson::Double(value) => Value::Double(value),
+            Bson::String(value) => Value::String(value),
+            Bson::Array(values) => Value::Array(Array::from_vec(
+                values
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, value)| {
+                        Value::try_from(value).map_err(|e| e.in_field(&index.to_string()))
+                    })
+                    .collect::<Result<_, _>>()?,
+            )),
+            Bson::Document(document) => Value::Document(Document::try_from(document)?),
+            Bson::Boolean(value) => Value::Boolean(value),
+            Bson::Null => Value::Null,
+            Bson::RegularExpression(regex) => Value::RegularExpression {
+                pattern: regex.pattern,
+                options: regex.options,
+            },
+            Bson::JavaScriptCode(code) => Value::JavaScriptCode(code),
+            Bson::JavaScriptCodeWithScope(code) => Value::JavaScriptCodeWithScope {
+                code: code.code,
+                scope: Document::try_from(code.scope)?,
+            },
+            Bson::Int32(value) => Value::Int32(value),
+            Bson::Int64(value) => Value::Int64(value),
+            Bson::Timestamp(timestamp) => {
+                Value::Timestamp(((timestamp.time as i64) << 32) | timestamp.increment as i64)
+            }
+            Bson::Binary(binary) => Value::Binary(binary.bytes),
+            Bson::ObjectId(id) => Value::ObjectId(ObjectId::from_bytes(id.bytes())),
+            Bson::DateTime(date) => Value::UTCDateTime(date.timestamp_millis().div_euclid(1000)),
+            Bson::Symbol(value) => Value::Symbol(value),
+            Bson::Decimal128(_) => {
+                return Err(ValueConversionError::TypeMismatch {
+                    expected: "a type this crate supports",
+                    found: "decimal128",
+                })
+            }
+            Bson::Undefined => Value::Undefined,
+            Bson::MaxKey => Value::MaxKey,
+            Bson::MinKey => Value::MinKey,
+            pointer @ Bson::DbPointer(_) => db_pointer_from_bson(pointer)?,
+        };
+        Ok(value)
+    }
+}
+
+impl TryFrom<bson::Document> for Document {
+    type Error = ValueConversionError;
+
+    fn try_from(document: bson::Document) -> Result<Self, Self::Error> {
+        let mut converted = Document::new_with_capacity(document.len());
+        for (key, value) in document {
+            let value = Value::try_from(value).map_err(|e| e.in_field(&key))?;
+            converted.insert(key, value);
+        }
+        Ok(converted)
+    }
+}
+
+/// Converts to a value of the `bson` crate, as used by the MongoDB driver.
+///
+/// Dates keep their instant, and binaries get the generic subtype.
+/// `UInt64` has no counterpart in the `bson` crate, so becomes an `Int64`
+/// if it fits one, and fails to convert if not.
+impl TryFrom<Value> for Bson {
+    type Error = ValueConversionError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        let value = match value {
+            Value::Double(value) => Bson::Double(value),
+            Value::String(value) => Bson::String(value),
+            Value::Document(document) => Bson::Document(bson::Document::try_from(document)?),
+            Value::Array(array) => {
+                let values: Vec<Value> = array.into();
+                Bson::Array(
+                    values
+                        .into_iter()
+                        .enumerate()
+                        .map(|(index, value)| {
+                            Bson::try_from(value).map_err(|e| e.in_field(&index.to_string()))
+                        })
+                        .collect::<Result<_, _>>()?,
+                )
+            }
+            Value::Binary(bytes) => Bson::Binary(Binary {
+                subtype: BinarySubtype::Generic,
+                bytes,
+            }),
+            Value::Undefined => Bson::Undefined,
+            Value::ObjectId(id) => Bson::ObjectId(bson::oid::ObjectId::from_bytes(*id.as_bytes())),
+            Value::Boolean(value) => Bson::Boolean(value),
+            Value::UTCDateTime(secs) => {
+                Bson::DateTime(DateTime::from_millis(secs.saturating_mul(1000)))
+            }
+            Value::Null => Bson::Null,
+            Value::RegularExpression { pattern, options } => {
+                Bson::RegularExpression(Regex { pattern, options })
+            }
+            pointer @ Value::DbPointer { .. } => db_pointer_to_bson(pointer)?,
+            Value::JavaScriptCode(code) => Bson::JavaScriptCode(code),
+            Value::Symbol(value) => Bson::Symbol(value),
+            Value::JavaScriptCodeWithScope { code, scope } => {
+                Bson::JavaScriptCodeWithScope(JavaScriptCodeWithScope {
+                    code,
+                    scope: bson::Document::try_from(scope)?,
+                })
+            }
+            Value::Int32(value) => Bson::Int32(value),
+            Value::Timestamp(value) => Bson::Timestamp(bson::Timestamp {
+                time: (value as u64 >> 32) as u32,
+                increment: value as u32,
+            }),
+            Value::Int64(value) => Bson::Int64(value),
+            Value::UInt64(value) => match i64::try_from(value) {
+                Ok(value) => Bson::Int64(value),
+                Err(_) => {
+                    return Err(ValueConversionError::OutOfRange {
+                        value: value.to_string(),
+                        target: "bson Int64",
+                    })
+                }
+            },
+            Value::MinKey => Bson::MinKey,
+            Value::MaxKey => Bson::MaxKey,
+        };
+        Ok(value)
+    }
+}
+
+impl TryFrom<Document> for bson::Document {
+    type Error = ValueConversionError;
+
+    fn try_from(mut document: Document) -> Result<Self, Self::Error> {
+        let mut converted = bson::Document::new();
+        for (key, value) in document.drain() {
+            let value = Bson::try_from(value).map_err(|e| e.in_field(&key))?;
+            converted.insert(key.as_str(), value);
+        }
+        Ok(converted)
+    }
+}
+
+/* DBPointers */
+
+// The `bson` crate's DbPointer can be neither built nor taken apart, so it
+// is carried across as encoded BSON, which both crates can read.
+
+fn db_pointer_from_bson(pointer: Bson) -> Result<Value, ValueConversionError> {
+    let mut bytes = Vec::new();
+    bson::doc! { "": pointer }
+        .to_writer(&mut bytes)
+        .ok()
+        .and_then(|()| {
+            let options = DecoderOptions::new().legacy_types(LegacyTypes::Preserve);
+            from_bytes_with_options(&bytes, &options).ok()
+        })
+        .and_then(|mut document| document.remove(""))
+        .ok_or(ValueConversionError::TypeMismatch {
+            expected: "a readable DBPointer",
+            found: "dbPointer",
+        })
+}
+
+fn db_pointer_to_bson(pointer: Value) -> Result<Bson, ValueConversionError> {
+    let found = type_name(&pointer);
+    let mut document = Document::new();
+    document.insert("", pointer);
+    to_bytes(&document)
+        .ok()
+        .and_then(|bytes| bson::Document::from_reader(bytes.as_slice()).ok())
+        .and_then(|mut document| document.remove(""))
+        .ok_or(ValueConversionError::TypeMismatch {
+            expected: "a readable DBPointer",
+            found,
+        })
+}
@@ -0,0 +1,438 @@
+// src/types/convert.rs
+
+use std::collections::HashMap;
+
+use crate::types::{Array, Document, ObjectId, Timestamp, UTCDateTime, Value};
+
+/// Error returned when a `Value` cannot be converted into the requested type.
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum ValueConversionError {
+    #[error("Expected {expected}, found {found}")]
+    TypeMismatch {
+        expected: &'static str,
+        found: &'static str,
+    },
+    #[error("{value} is out of range for {target}")]
+    OutOfRange { value: String, target: &'static str },
+    #[error("Expected an array of length {expected}, found length {found}")]
+    LengthMismatch { expected: usize, found: usize },
+    #[error("Missing field '{0}'")]
+    MissingField(String),
+    #[error("Field '{field}': {source}")]
+    Field {
+        field: String,
+        #[source]
+        source: Box<ValueConversionError>,
+    },
+}
+
+impl ValueConversionError {
+    fn mismatch(expected: &'static str, found: &Value) -> Self {
+        ValueConversionError::TypeMismatch {
+            expected,
+            found: type_name(found),
+        }
+    }
+
+    /// Wraps the error with the name of the field it occurred in.
+    pub fn in_field(self, field: &str) -> Self {
+        ValueConversionError::Field {
+            field: field.to_string(),
+            source: Box::new(self),
+        }
+    }
+}
+
+/// Types that can be extracted from a `Value`.
+///
+/// Implemented for primitives, `Option`, `Vec`, `HashMap<String, T>`,
+/// tuples and the crate's own types. Integer conversions accept any integer
+/// value that fits the target type.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb_data_encoding::{Document, FromValue, ValueConversionError, Value};
+/// struct User {
+///     name: String,
+///     age: u8,
+///     email: Option<String>,
+/// }
+///
+/// impl FromValue for User {
+///     fn from_value(value: &Value) -> Result<Self, ValueConversionError> {
+///         let document = Document::from_value(value)?;
+///         Self::from_document(&document)
+///     }
+///
+///     fn from_document(document: &Document) -> Result<Self, ValueConversionError> {
+///         Ok(User {
+///             name: document.get_as("name")?,
+///             age: document.get_as("age")?,
+///             email: document.get_as("email")?,
+///         })
+///     }
+/// }
+///
+/// let mut document = Document::new();
+/// document.insert("name", "ada");
+/// document.insert("age", 36);
+/// let user: User = document.decode_as().unwrap();
+/// assert_eq!(user.name, "ada");
+/// assert_eq!(user.age, 36);
+/// assert_eq!(user.email, None);
+/// ```
+pub trait FromValue: Sized {
+    /// Converts a value into `Self`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value has the wrong type or is out of range.
+    fn from_value(value: &Value) -> Result<Self, ValueConversionError>;
+
+    /// Converts a document into `Self`. Document-shaped types should
+    /// override this to avoid wrapping the document in a `Value`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the document cannot be converted.
+    fn from_document(document: &Document) -> Result<Self, ValueConversionError> {
+        Self::from_value(&Value::Document(document.clone()))
+    }
+}
+
+/// Types that can be converted into a `Value`.
+///
+/// Unlike `From<Vec<u8>> for Value`, which produces `Binary`, a `Vec<u8>`
+/// converts into an array of integers like any other `Vec`.
+pub trait IntoValue {
+    /// Converts `self` into a `Value`.
+    fn into_value(self) -> Value;
+}
+
+/// Returns a short name for the type of `value`, used in error messages.
+pub(crate) fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Double(_) => "double",
+        Value::String(_) => "string",
+        Value::Document(_) => "document",
+        Value::Array(_) => "array",
+        Value::Binary(_) => "binary",
+        Value::Undefined => "undefined",
+        Value::ObjectId(_) => "objectId",
+        Value::Boolean(_) => "bool",
+        Value::UTCDateTime(_) => "date",
+        Value::Null => "null",
+        Value::RegularExpression { .. } => "regex",
+        Value::DbPointer { .. } => "dbPointer",
+        Value::JavaScriptCode(_) => "javascript",
+        Value::Symbol(_) => "symbol",
+        Value::JavaScriptCodeWithScope { .. } => "javascriptWithScope",
+        Value::Int32(_) => "int",
+        Value::Timestamp(_) => "timestamp",
+        Value::Int64(_) => "long",
+        Value::UInt64(_) => "uint64",
+        Value::MinKey => "minKey",
+        Value::MaxKey => "maxKey",
+    }
+}
+
+/* Document Extraction */
+
+impl Document {
+    /// Converts the document into `T`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the document cannot be converted.
+    pub fn decode_as<T: FromValue>(&self) -> Result<T, ValueConversionError> {
+        T::from_document(self)
+    }
+
+    /// Converts the value of `key` into `T`. A missing key is treated like
+    /// `null`, so it converts into `None` for `Option` fields.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MissingField` if the key is absent and `T` does not accept
+    /// `null`, or a `Field` error if the value cannot be converted.
+    pub fn get_as<T: FromValue>(&self, key: &str) -> Result<T, ValueConversionError> {
+        match self.get(key) {
+            Some(value) => T::from_value(value).map_err(|error| error.in_field(key)),
+            None => T::from_value(&Value::Null)
+                .map_err(|_| ValueConversionError::MissingField(key.to_string())),
+        }
+    }
+}
+
+/* Primitive Implementations */
+
+/// Reads any integer variant as an `i128` so every target range can be checked.
+fn integer(value: &Value, expected: &'static str) -> Result<i128, ValueConversionError> {
+    match value {
+        Value::Int32(value) => Ok(*value as i128),
+        Value::Int64(value) => Ok(*value as i128),
+        Value::UInt64(value) => Ok(*value as i128),
+        other => Err(ValueConversionError::mismatch(expected, other)),
+    }
+}
+
+macro_rules! impl_integer {
+    ($($target:ty => $variant:ident as $stored:ty),* $(,)?) => {$(
+        impl FromValue for $target {
+            fn from_value(value: &Value) -> Result<Self, ValueConversionError> {
+                let number = integer(value, stringify!($target))?;
+                <$target>::try_from(number).map_err(|_| ValueConversionError::OutOfRange {
+                    value: number.to_string(),
+                    target: stringify!($target),
+                })
+            }
+        }
+
+        impl IntoValue for $target {
+            fn into_value(self) -> Value {
+                Value::$variant(self as $stored)
+            }
+        }
+    )*};
+}
+
+impl_integer! {
+    i8 => Int32 as i32,
+    i16 => Int32 as i32,
+    i32 => Int32 as i32,
+    i64 => Int64 as i64,
+    u8 => Int32 as i32,
+    u16 => Int32 as i32,
+    u32 => Int64 as i64,
+    u64 => UInt64 as u64,
+}
+
+impl FromValue for f64 {
+    fn from_value(value: &Value) -> Result<Self, ValueConversionError> {
+        match value {
+            Value::Double(value) => Ok(*value),
+            Value::Int32(value) => Ok(*value as f64),
+            other => Err(ValueConversionError::mismatch("f64", other)),
+        }
+    }
+}
+
+impl FromValue for f32 {
+    fn from_value(value: &Value) -> Result<Self, ValueConversionError> {
+        f64::from_value(value).map(|value| value as f32)
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: &Value) -> Result<Self, ValueConversionError> {
+        match value {
+            Value::Boolean(value) => Ok(*value),
+            other => Err(ValueConversionError::mismatch("bool", other)),
+        }
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> Result<Self, ValueConversionError> {
+        match value {
+            Value::String(value) => Ok(value.clone()),
+            other => Err(ValueConversionError::mismatch("string", other)),
+        }
+    }
+}
+
+impl IntoValue for f32 {
+    fn into_value(self) -> Value {
+        Value::Double(self as f64)
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> Value {
+        Value::String(self.to_string())
+    }
+}
+
+macro_rules! impl_into_value_via_from {
+    ($($source:ty),* $(,)?) => {$(
+        impl IntoValue for $source {
+            fn into_value(self) -> Value {
+                Value::from(self)
+            }
+        }
+    )*};
+}
+
+impl_into_value_via_from!(f64, bool, String, ObjectId, Document, Array, UTCDateTime, Timestamp);
+
+/* Crate Type Implementations */
+
+impl FromValue for Value {
+    fn from_value(value: &Value) -> Result<Self, ValueConversionError> {
+        Ok(value.clone())
+    }
+}
+
+impl IntoValue for Value {
+    fn into_value(self) -> Value {
+        self
+    }
+}
+
+impl FromValue for Document {
+    fn from_value(value: &Value) -> Result<Self, ValueConversionError> {
+        match value {
+            Value::Document(document) => Ok(document.clone()),
+            other => Err(ValueConversionError::mismatch("document", other)),
+        }
+    }
+
+    fn from_document(document: &Document) -> Result<Self, ValueConversionError> {
+        Ok(document.clone())
+    }
+}
+
+impl FromValue for Array {
+    fn from_value(value: &Value) -> Result<Self, ValueConversionError> {
+        match value {
+            Value::Array(array) => Ok(array.clone()),
+            other => Err(ValueConversionError::mismatch("array", other)),
+        }
+    }
+}
+
+impl FromValue for ObjectId {
+    fn from_value(value: &Value) -> Result<Self, ValueConversionError> {
+        match value {
+            Value::ObjectId(id) => Ok(id.clone()),
+            other => Err(ValueConversionError::mismatch("objectId", other)),
+        }
+    }
+}
+
+impl FromValue for UTCDateTime {
+    fn from_value(value: &Value) -> Result<Self, ValueConversionError> {
+        match value {
+            Value::UTCDateTime(secs) => Ok(UTCDateTime::from_secs(*secs)),
+            other => Err(ValueConversionError::mismatch("date", other)),
+        }
+    }
+}
+
+impl FromValue for Timestamp {
+    fn from_value(value: &Value) -> Result<Self, ValueConversionError> {
+        match value {
+            Value::Timestamp(secs) => Ok(Timestamp::from_secs(*secs)),
+            other => Err(ValueConversionError::mismatch("timestamp", other)),
+        }
+    }
+}
+
+/* Container Implementations */
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &Value) -> Result<Self, ValueConversionError> {
+        match value {
+            Value::Null => Ok(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self) -> Value {
+        match self {
+            Some(value) => value.into_value(),
+            None => Value::Null,
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: &Value) -> Result<Self, ValueConversionError> {
+        match value {
+            Value::Array(array) => array
+                .iter()
+                .enumerate()
+                .map(|(i, value)| T::from_value(value).map_err(|e| e.in_field(&i.to_string())))
+                .collect(),
+            other => Err(ValueConversionError::mismatch("array", other)),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> Value {
+        let values: Vec<Value> = self.into_iter().map(IntoValue::into_value).collect();
+        Value::Array(Array::from(values))
+    }
+}
+
+impl<T: FromValue> FromValue for HashMap<String, T> {
+    fn from_value(value: &Value) -> Result<Self, ValueConversionError> {
+        match value {
+            Value::Document(document) => Self::from_document(document),
+            other => Err(ValueConversionError::mismatch("document", other)),
+        }
+    }
+
+    fn from_document(document: &Document) -> Result<Self, ValueConversionError> {
+        document
+            .iter()
+            .map(|(key, value)| {
+                let value = T::from_value(value).map_err(|e| e.in_field(key))?;
+                Ok((key.to_string(), value))
+            })
+            .collect()
+    }
+}
+
+impl<T: IntoValue> IntoValue for HashMap<String, T> {
+    fn into_value(self) -> Value {
+        let mut document = Document::new_with_capacity(self.len());
+        for (key, value) in self {
+            document.insert(key, value.into_value());
+        }
+        Value::Document(document)
+    }
+}
+
+/// Tuples convert from and into arrays of exactly their length.
+macro_rules! impl_tuple {
+    ($len:expr => $($name:ident $index:tt),+) => {
+        impl<$($name: FromValue),+> FromValue for ($($name,)+) {
+            fn from_value(value: &Value) -> Result<Self, ValueConversionError> {
+                let array = match value {
+                    Value::Array(array) => array,
+                    other => return Err(ValueConversionError::mismatch("array", other)),
+                };
+                if array.len() != $len {
+                    return Err(ValueConversionError::LengthMismatch {
+                        expected: $len,
+                        found: array.len(),
+                    });
+                }
+                Ok(($(
+                    $name::from_value(array.get($index).unwrap())
+                        .map_err(|e| e.in_field(stringify!($index)))?,
+                )+))
+            }
+        }
+
+        impl<$($name: IntoValue),+> IntoValue for ($($name,)+) {
+            fn into_value(self) -> Value {
+                let mut array = Array::with_capacity($len);
+                $(array.push(self.$index.into_value());)+
+                Value::Array(array)
+            }
+        }
+    };
+}
+
+impl_tuple!(1 => A 0);
+impl_tuple!(2 => A 0, B 1);
+impl_tuple!(3 => A 0, B 1, C 2);
+impl_tuple!(4 => A 0, B 1, C 2, D 3);
+impl_tuple!(5 => A 0, B 1, C 2, D 3, E 4);
+impl_tuple!(6 => A 0, B 1, C 2, D 3, E 4, F 5);
@@ -1,17 +1,68 @@
 // src/types/document.rs
 use std::{collections::HashMap, fmt};
-use crate::types::Value;
+use hashbrown::HashTable;
+use crate::types::key::hash_name;
+use crate::types::{FieldName, KeyRef, Value};
 
+/// Most fields a document keeps in a vector of pairs before switching to a
+/// hash table. Below it a scan is faster than hashing, and the fields sit
+/// in one allocation in insertion order.
+pub(crate) const SMALL_DOCUMENT_FIELDS: usize = 16;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Clone)]
 pub struct Document {
-    inner: HashMap<String, Value>,
+    inner: Fields,
+}
+
+/// The fields of a document: a vector while small, a map once it has held
+/// more than `SMALL_DOCUMENT_FIELDS`. A document never switches back, so
+/// that one reused by `decode_into` keeps its capacity.
+///
+/// The map is a table of pairs hashed with `hash_name`, rather than a
+/// `HashMap`, so that `get_key` can look a name up by a hash computed once.
+#[derive(Clone)]
+enum Fields {
+    Small(Vec<(FieldName, Value)>),
+    Map(HashTable<(FieldName, Value)>),
+}
+
+/// Rehashes a stored field when the table grows.
+fn rehash(field: &(FieldName, Value)) -> u64 {
+    hash_name(&field.0)
+}
+
+/// An iterator over either representation's fields.
+enum Iter<S, M> {
+    Small(S),
+    Map(M),
+}
+
+impl<S, M, T> Iterator for Iter<S, M>
+where
+    S: Iterator<Item = T>,
+    M: Iterator<Item = T>,
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        match self {
+            Iter::Small(iter) => iter.next(),
+            Iter::Map(iter) => iter.next(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            Iter::Small(iter) => iter.size_hint(),
+            Iter::Map(iter) => iter.size_hint(),
+        }
+    }
 }
 
 impl Document {
     pub fn new() -> Self {
         Document {
-            inner: HashMap::new()
+            inner: Fields::Small(Vec::new())
         }
     }
 
@@ -19,60 +70,199 @@ impl Document {
     
     /// Createsa document with capacity.
     pub fn new_with_capacity(capacity: usize) -> Self {
-        Document {
-            inner: HashMap::with_capacity(capacity)
-        }
+        let inner = match capacity <= SMALL_DOCUMENT_FIELDS {
+            true => Fields::Small(Vec::with_capacity(capacity)),
+            false => Fields::Map(HashTable::with_capacity(capacity)),
+        };
+        Document { inner }
     }
 
     pub fn insert<K, V>(&mut self, key: K, value: V) -> Option<Value>
     where
-        K: Into<String>,
+        K: Into<FieldName>,
         V: Into<Value>,
     {
-        self.inner.insert(key.into(), value.into())
+        let (key, value) = (key.into(), value.into());
+        let fields = match &mut self.inner {
+            Fields::Map(map) => return map_insert(map, key, value),
+            Fields::Small(fields) => fields,
+        };
+        if let Some((_, old)) = fields.iter_mut().find(|(name, _)| *name == key) {
+            return Some(std::mem::replace(old, value));
+        }
+        if fields.len() < SMALL_DOCUMENT_FIELDS {
+            fields.push((key, value));
+            return None;
+        }
+        let mut map = HashTable::with_capacity(fields.capacity().max(fields.len() * 2));
+        for field in fields.drain(..) {
+            map.insert_unique(rehash(&field), field, rehash);
+        }
+        map_insert(&mut map, key, value);
+        self.inner = Fields::Map(map);
+        None
     }
 
     pub fn get(&self, key: &str) -> Option<&Value> {
-        self.inner.get(key)
+        match &self.inner {
+            Fields::Small(fields) => fields
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            Fields::Map(map) => map
+                .find(hash_name(key), |(name, _)| name == key)
+                .map(|(_, value)| value),
+        }
+    }
+
+    /// Looks a field up by a name hashed beforehand, as when one `Key` is
+    /// looked up in many documents.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use silentdb_data_encoding::{Document, Key};
+    /// let age = Key::new("age");
+    /// let mut document = Document::new();
+    /// document.insert("age", 36);
+    /// assert_eq!(document.get_key(&age), document.get("age"));
+    /// assert_eq!(document.get_key("name"), None);
+    /// ```
+    pub fn get_key<'k>(&self, key: impl Into<KeyRef<'k>>) -> Option<&Value> {
+        let key = key.into();
+        match &self.inner {
+            Fields::Small(fields) => fields
+                .iter()
+                .find(|(name, _)| {
+                    std::ptr::eq(name.as_str(), key.as_str()) || name == key.as_str()
+                })
+                .map(|(_, value)| value),
+            Fields::Map(map) => map
+                .find(key.hash(), |(name, _)| name == key.as_str())
+                .map(|(_, value)| value),
+        }
     }
 
     pub fn get_mut(&mut self, key: &str) -> Option<&mut Value> {
-        self.inner.get_mut(key)
+        match &mut self.inner {
+            Fields::Small(fields) => fields
+                .iter_mut()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            Fields::Map(map) => map
+                .find_mut(hash_name(key), |(name, _)| name == key)
+                .map(|(_, value)| value),
+        }
     }
 
     pub fn remove(&mut self, key: &str) -> Option<Value> {
-        self.inner.remove(key)
+        match &mut self.inner {
+            Fields::Small(fields) => {
+                let index = fields.iter().position(|(name, _)| name == key)?;
+                Some(fields.remove(index).1)
+            }
+            Fields::Map(map) => {
+                let entry = map
+                    .find_entry(hash_name(key), |(name, _)| name == key)
+                    .ok()?;
+                Some(entry.remove().0 .1)
+            }
+        }
     }
 
     pub fn contains_key(&self, key: &str) -> bool {
-        self.inner.contains_key(key)
+        self.get(key).is_some()
     }
 
     pub fn capacity(&self) -> usize {
-        self.inner.capacity()
+        match &self.inner {
+            Fields::Small(fields) => fields.capacity(),
+            Fields::Map(map) => map.capacity(),
+        }
     }
 
     pub fn len(&self) -> usize {
-        self.inner.len()
+        match &self.inner {
+            Fields::Small(fields) => fields.len(),
+            Fields::Map(map) => map.len(),
+        }
     }
 
     pub fn is_empty(&self) -> bool {
-        self.inner.is_empty()
+        self.len() == 0
     }
 
     pub fn clear(&mut self) {
-        self.inner.clear()
+        match &mut self.inner {
+            Fields::Small(fields) => fields.clear(),
+            Fields::Map(map) => map.clear(),
+        }
+    }
+
+    /// Removes and yields every field, keeping the allocated capacity.
+    pub fn drain(&mut self) -> impl Iterator<Item = (FieldName, Value)> + '_ {
+        match &mut self.inner {
+            Fields::Small(fields) => Iter::Small(fields.drain(..)),
+            Fields::Map(map) => Iter::Map(map.drain()),
+        }
     }
 
-    pub fn iter(&self) -> impl Iterator<Item = (&String, &Value)> {
-        self.inner.iter()
+    /// Yields every field: in insertion order while the document is small,
+    /// in no particular order otherwise.
+    pub fn iter(&self) -> impl Iterator<Item = (&FieldName, &Value)> {
+        match &self.inner {
+            Fields::Small(fields) => Iter::Small(fields.iter().map(|(key, value)| (key, value))),
+            Fields::Map(map) => Iter::Map(map.iter().map(|(key, value)| (key, value))),
+        }
     }
 
-    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&String, &mut Value)> {
-        self.inner.iter_mut()
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&FieldName, &mut Value)> {
+        match &mut self.inner {
+            Fields::Small(fields) => {
+                Iter::Small(fields.iter_mut().map(|(key, value)| (&*key, value)))
+            }
+            Fields::Map(map) => Iter::Map(map.iter_mut().map(|(key, value)| (&*key, value))),
+        }
     }
 }
 
+// Documents are equal if they hold the same fields, whatever their order
+impl PartialEq for Document {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self.iter().all(|(key, value)| other.get(key) == Some(value))
+    }
+}
+
+impl fmt::Debug for Document {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        struct Inner<'a>(&'a Document);
+
+        impl fmt::Debug for Inner<'_> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.debug_map().entries(self.0.iter()).finish()
+            }
+        }
+
+        f.debug_struct("Document").field("inner", &Inner(self)).finish()
+    }
+}
+
+/// Inserts a field into a document's table, returning the value it
+/// replaced.
+fn map_insert(
+    map: &mut HashTable<(FieldName, Value)>,
+    key: FieldName,
+    value: Value,
+) -> Option<Value> {
+    let hash = hash_name(&key);
+    if let Some((_, old)) = map.find_mut(hash, |(name, _)| *name == key) {
+        return Some(std::mem::replace(old, value));
+    }
+    map.insert_unique(hash, (key, value), rehash);
+    None
+}
+
 impl Default for Document {
     fn default() -> Self {
         Document::new()
@@ -81,22 +271,35 @@ impl Default for Document {
 
 // Convert HashMap<String, Value> to Document
 impl From<HashMap<String, Value>> for Document {
-    fn from(inner: HashMap<String, Value>) -> Self {
-        Document { inner }
+    fn from(map: HashMap<String, Value>) -> Self {
+        let mut document = Document::new_with_capacity(map.len());
+        for (key, value) in map {
+            document.insert(key, value);
+        }
+        document
     }
 }
 
 // Convert Document to HashMap<String, Value>
 impl Into<HashMap<String, Value>> for Document {
     fn into(self) -> HashMap<String, Value> {
-        self.inner
+        match self.inner {
+            Fields::Small(fields) => fields
+                .into_iter()
+                .map(|(key, value)| (key.into(), value))
+                .collect(),
+            Fields::Map(map) => map
+                .into_iter()
+                .map(|(key, value)| (key.into(), value))
+                .collect(),
+        }
     }
 }
 
 impl fmt::Display for Document {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{{")?;
-        for (i, (key, value)) in self.inner.iter().enumerate() {
+        for (i, (key, value)) in self.iter().enumerate() {
             if i > 0 {
                 write!(f, ", ")?;
             }
@@ -0,0 +1,161 @@
+// src/types/field_name.rs
+use std::borrow::{Borrow, Cow};
+use std::fmt;
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// The name of a field of a `Document`.
+///
+/// A name is an immutable, reference-counted string, so that cloning one
+/// is cheap and the documents a decoder reads with
+/// `DecoderOptions::intern_keys` can share one allocation per distinct
+/// name instead of each holding its own copy. It dereferences to `str`,
+/// and hashes and compares as one, so documents are still looked up by
+/// `&str`.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb_data_encoding::FieldName;
+/// let name = FieldName::from("email");
+/// let copy = name.clone();
+/// assert_eq!(copy, "email");
+/// assert!(FieldName::ptr_eq(&name, &copy));
+/// ```
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FieldName(Arc<str>);
+
+impl FieldName {
+    /// Returns the name as a string slice.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns whether `a` and `b` share one allocation, as clones and
+    /// interned names do.
+    pub fn ptr_eq(a: &FieldName, b: &FieldName) -> bool {
+        Arc::ptr_eq(&a.0, &b.0)
+    }
+}
+
+impl Deref for FieldName {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for FieldName {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for FieldName {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for FieldName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&*self.0, f)
+    }
+}
+
+impl fmt::Display for FieldName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<&str> for FieldName {
+    fn from(name: &str) -> Self {
+        FieldName(Arc::from(name))
+    }
+}
+
+impl From<&String> for FieldName {
+    fn from(name: &String) -> Self {
+        FieldName::from(name.as_str())
+    }
+}
+
+impl From<String> for FieldName {
+    fn from(name: String) -> Self {
+        FieldName(Arc::from(name))
+    }
+}
+
+impl From<Box<str>> for FieldName {
+    fn from(name: Box<str>) -> Self {
+        FieldName(Arc::from(name))
+    }
+}
+
+impl From<Cow<'_, str>> for FieldName {
+    fn from(name: Cow<'_, str>) -> Self {
+        FieldName::from(&*name)
+    }
+}
+
+impl From<Arc<str>> for FieldName {
+    fn from(name: Arc<str>) -> Self {
+        FieldName(name)
+    }
+}
+
+impl From<&FieldName> for FieldName {
+    fn from(name: &FieldName) -> Self {
+        name.clone()
+    }
+}
+
+impl From<FieldName> for String {
+    fn from(name: FieldName) -> Self {
+        name.0.to_string()
+    }
+}
+
+impl From<&FieldName> for String {
+    fn from(name: &FieldName) -> Self {
+        name.0.to_string()
+    }
+}
+
+impl PartialEq<str> for FieldName {
+    fn eq(&self, other: &str) -> bool {
+        &*self.0 == other
+    }
+}
+
+impl PartialEq<&str> for FieldName {
+    fn eq(&self, other: &&str) -> bool {
+        &*self.0 == *other
+    }
+}
+
+impl PartialEq<String> for FieldName {
+    fn eq(&self, other: &String) -> bool {
+        &*self.0 == other.as_str()
+    }
+}
+
+impl PartialEq<FieldName> for str {
+    fn eq(&self, other: &FieldName) -> bool {
+        self == &*other.0
+    }
+}
+
+impl PartialEq<FieldName> for &str {
+    fn eq(&self, other: &FieldName) -> bool {
+        *self == &*other.0
+    }
+}
+
+impl PartialEq<FieldName> for String {
+    fn eq(&self, other: &FieldName) -> bool {
+        self.as_str() == &*other.0
+    }
+}
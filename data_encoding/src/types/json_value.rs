@@ -0,0 +1,310 @@
+// src/types/json_value.rs
+
+use serde_json::{json, Map, Number};
+
+use super::convert::type_name;
+use crate::types::{Array, Document, UTCDateTime, Value, ValueConversionError};
+use crate::utils::{base64, hex};
+
+/// Latest date `DatePolicy::Rfc3339` writes as a string: the end of 9999,
+/// as in relaxed Extended JSON.
+const MAX_RFC3339_SECS: i64 = 253_402_300_799;
+
+/// How `Value::to_json_value` writes binaries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BinaryPolicy {
+    /// A base64 string.
+    #[default]
+    Base64,
+    /// A string of lowercase hex.
+    Hex,
+    /// `{"$binary": {"base64": ..., "subType": "00"}}`, as in Extended JSON.
+    ExtendedJson,
+    /// Fail the conversion.
+    Reject,
+}
+
+/// How `Value::to_json_value` writes ObjectIds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ObjectIdPolicy {
+    /// A string of 24 hex digits.
+    #[default]
+    Hex,
+    /// `{"$oid": ...}`, as in Extended JSON.
+    ExtendedJson,
+    /// Fail the conversion.
+    Reject,
+}
+
+/// How `Value::to_json_value` writes dates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DatePolicy {
+    /// An RFC 3339 string, such as `"2024-01-31T12:30:00Z"`, for the years
+    /// 1970 through 9999, and milliseconds since the epoch otherwise.
+    #[default]
+    Rfc3339,
+    /// Milliseconds since the epoch.
+    EpochMillis,
+    /// `{"$date": ...}`, as in relaxed Extended JSON.
+    ExtendedJson,
+    /// Fail the conversion.
+    Reject,
+}
+
+/// Options for converting a `Value` to a `serde_json::Value`.
+///
+/// JSON has no binaries, ObjectIds or dates, so each is written as the
+/// policy set for it says. By default they become plain strings, which is
+/// what a web client usually wants. The remaining BSON types, such as
+/// regular expressions and timestamps, are written in their Extended JSON
+/// forms, and doubles JSON cannot hold, NaN and the infinities, as
+/// `{"$numberDouble": ...}`.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb_data_encoding::{BinaryPolicy, Document, JsonValueOptions, Value};
+/// let mut document = Document::new();
+/// document.insert("data", Value::Binary(vec![0xCA, 0xFE]));
+///
+/// let options = JsonValueOptions::new().binary(BinaryPolicy::Hex);
+/// let json = document.to_json_value(&options).unwrap();
+/// assert_eq!(json.to_string(), r#"{"data":"cafe"}"#);
+///
+/// let options = JsonValueOptions::new().binary(BinaryPolicy::Reject);
+/// assert!(document.to_json_value(&options).is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonValueOptions {
+    pub(crate) binary: BinaryPolicy,
+    pub(crate) object_id: ObjectIdPolicy,
+    pub(crate) date: DatePolicy,
+}
+
+impl JsonValueOptions {
+    /// Creates options writing binaries as base64, ObjectIds as hex, and
+    /// dates as RFC 3339 strings.
+    pub fn new() -> Self {
+        JsonValueOptions {
+            binary: BinaryPolicy::Base64,
+            object_id: ObjectIdPolicy::Hex,
+            date: DatePolicy::Rfc3339,
+        }
+    }
+
+    /// Sets how binaries are written.
+    pub fn binary(mut self, policy: BinaryPolicy) -> Self {
+        self.binary = policy;
+        self
+    }
+
+    /// Sets how ObjectIds are written.
+    pub fn object_id(mut self, policy: ObjectIdPolicy) -> Self {
+        self.object_id = policy;
+        self
+    }
+
+    /// Sets how dates are written.
+    pub fn date(mut self, policy: DatePolicy) -> Self {
+        self.date = policy;
+        self
+    }
+}
+
+impl Default for JsonValueOptions {
+    fn default() -> Self {
+        JsonValueOptions::new()
+    }
+}
+
+impl Value {
+    /// Converts the value to a `serde_json::Value`, writing the types JSON
+    /// lacks as `options` says.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the field of a binary, ObjectId or date
+    /// whose policy is `Reject`.
+    pub fn to_json_value(
+        &self,
+        options: &JsonValueOptions,
+    ) -> Result<serde_json::Value, ValueConversionError> {
+        let json = match self {
+            Value::Double(value) => match Number::from_f64(*value) {
+                Some(number) => serde_json::Value::Number(number),
+                None => json!({ "$numberDouble": non_finite(*value) }),
+            },
+            Value::String(value) | Value::Symbol(value) => serde_json::Value::String(value.clone()),
+            Value::Document(document) => serde_json::Value::Object(document.to_json_map(options)?),
+            Value::Array(array) => serde_json::Value::Array(
+                array
+                    .iter()
+                    .enumerate()
+                    .map(|(index, value)| {
+                        value
+                            .to_json_value(options)
+                            .map_err(|e| e.in_field(&index.to_string()))
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            Value::Binary(bytes) => match options.binary {
+                BinaryPolicy::Base64 => serde_json::Value::String(base64::encode(bytes)),
+                BinaryPolicy::Hex => serde_json::Value::String(hex::encode(bytes)),
+                BinaryPolicy::ExtendedJson => {
+                    json!({ "$binary": { "base64": base64::encode(bytes), "subType": "00" } })
+                }
+                BinaryPolicy::Reject => return Err(rejected(self)),
+            },
+            Value::Undefined => json!({ "$undefined": true }),
+            Value::ObjectId(id) => match options.object_id {
+                ObjectIdPolicy::Hex => serde_json::Value::String(hex::encode(id.as_bytes())),
+                ObjectIdPolicy::ExtendedJson => json!({ "$oid": hex::encode(id.as_bytes()) }),
+                ObjectIdPolicy::Reject => return Err(rejected(self)),
+            },
+            Value::Boolean(value) => serde_json::Value::Bool(*value),
+            Value::UTCDateTime(secs) => {
+                let millis = secs.saturating_mul(1000);
+                let in_range = (0..=MAX_RFC3339_SECS).contains(secs);
+                match options.date {
+                    DatePolicy::Rfc3339 if in_range => {
+                        serde_json::Value::String(UTCDateTime::from_secs(*secs).to_rfc3339())
+                    }
+                    DatePolicy::Rfc3339 | DatePolicy::EpochMillis => json!(millis),
+                    DatePolicy::ExtendedJson if in_range => {
+                        json!({ "$date": UTCDateTime::from_secs(*secs).to_rfc3339() })
+                    }
+                    DatePolicy::ExtendedJson => {
+                        json!({ "$date": { "$numberLong": millis.to_string() } })
+                    }
+                    DatePolicy::Reject => return Err(rejected(self)),
+                }
+            }
+            Value::Null => serde_json::Value::Null,
+            Value::RegularExpression {
+                pattern,
+                options: flags,
+            } => json!({ "$regularExpression": { "pattern": pattern, "options": flags } }),
+            Value::DbPointer { namespace, id } => json!({
+                "$dbPointer": { "$ref": namespace, "$id": { "$oid": hex::encode(id.as_bytes()) } }
+            }),
+            Value::JavaScriptCode(code) => json!({ "$code": code }),
+            Value::JavaScriptCodeWithScope { code, scope } => json!({
+                "$code": code,
+                "$scope": scope.to_json_map(options)?,
+            }),
+            Value::Int32(value) => json!(value),
+            Value::Timestamp(value) => {
+                let value = *value as u64;
+                json!({ "$timestamp": { "t": value >> 32, "i": value & 0xFFFF_FFFF } })
+            }
+            Value::Int64(value) => json!(value),
+            Value::UInt64(value) => json!(value),
+            Value::MinKey => json!({ "$minKey": 1 }),
+            Value::MaxKey => json!({ "$maxKey": 1 }),
+        };
+        Ok(json)
+    }
+}
+
+impl Document {
+    /// Converts the document to a `serde_json::Value` object, writing the
+    /// types JSON lacks as `options` says.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the field of a binary, ObjectId or date
+    /// whose policy is `Reject`.
+    pub fn to_json_value(
+        &self,
+        options: &JsonValueOptions,
+    ) -> Result<serde_json::Value, ValueConversionError> {
+        Ok(serde_json::Value::Object(self.to_json_map(options)?))
+    }
+
+    fn to_json_map(
+        &self,
+        options: &JsonValueOptions,
+    ) -> Result<Map<String, serde_json::Value>, ValueConversionError> {
+        self.iter()
+            .map(|(key, value)| {
+                let json = value.to_json_value(options).map_err(|e| e.in_field(key))?;
+                Ok((key.as_str().to_string(), json))
+            })
+            .collect()
+    }
+}
+
+/// Converts JSON as it stands: numbers become `Int32`, `Int64`, `UInt64` or
+/// `Double`, whichever is the first to hold them, objects become documents,
+/// and strings stay strings. Extended JSON wrappers such as `{"$oid": ...}`
+/// are kept as documents; parse the text with `from_json_value_str` to
+/// read them as the types they stand for.
+impl From<serde_json::Value> for Value {
+    fn from(json: serde_json::Value) -> Self {
+        match json {
+            serde_json::Value::Null => Value::Null,
+            serde_json::Value::Bool(value) => Value::Boolean(value),
+            serde_json::Value::Number(number) => {
+                if let Some(value) = number.as_i64() {
+                    match i32::try_from(value) {
+                        Ok(value) => Value::Int32(value),
+                        Err(_) => Value::Int64(value),
+                    }
+                } else if let Some(value) = number.as_u64() {
+                    Value::UInt64(value)
+                } else {
+                    Value::Double(number.as_f64().unwrap_or(f64::NAN))
+                }
+            }
+            serde_json::Value::String(value) => Value::String(value),
+            serde_json::Value::Array(values) => Value::Array(Array::from_vec(
+                values.into_iter().map(Value::from).collect(),
+            )),
+            serde_json::Value::Object(map) => Value::Document(Document::from(map)),
+        }
+    }
+}
+
+impl From<Map<String, serde_json::Value>> for Document {
+    fn from(map: Map<String, serde_json::Value>) -> Self {
+        let mut document = Document::new_with_capacity(map.len());
+        for (key, value) in map {
+            document.insert(key, Value::from(value));
+        }
+        document
+    }
+}
+
+/// Converts with the default `JsonValueOptions`, which never fail.
+impl TryFrom<Value> for serde_json::Value {
+    type Error = ValueConversionError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        value.to_json_value(&JsonValueOptions::default())
+    }
+}
+
+/// Converts with the default `JsonValueOptions`, which never fail.
+impl TryFrom<Document> for serde_json::Value {
+    type Error = ValueConversionError;
+
+    fn try_from(document: Document) -> Result<Self, Self::Error> {
+        document.to_json_value(&JsonValueOptions::default())
+    }
+}
+
+fn rejected(value: &Value) -> ValueConversionError {
+    ValueConversionError::TypeMismatch {
+        expected: "a JSON value",
+        found: type_name(value),
+    }
+}
+
+/// Names a double JSON has no number for, as Extended JSON does.
+fn non_finite(value: f64) -> &'static str {
+    match value {
+        value if value.is_nan() => "NaN",
+        value if value > 0.0 => "Infinity",
+        _ => "-Infinity",
+    }
+}
@@ -0,0 +1,138 @@
+// src/types/key.rs
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::sync::OnceLock;
+
+use crate::types::FieldName;
+
+/// Hashes field names for every document's map, with keys chosen randomly
+/// once per process so that a `Key` hashed up front stays valid for every
+/// document it is looked up in.
+fn hasher() -> &'static RandomState {
+    static HASHER: OnceLock<RandomState> = OnceLock::new();
+    HASHER.get_or_init(RandomState::new)
+}
+
+/// Returns the hash a document's map files `name` under.
+pub(crate) fn hash_name(name: &str) -> u64 {
+    hasher().hash_one(name)
+}
+
+/// A field name hashed once, for looking the same field up in many
+/// documents with `Document::get_key`.
+///
+/// Looking up a `&str` hashes it again for every document; a key carries
+/// its hash, and shares its allocation with the names of the fields it is
+/// inserted as, so small documents holding them compare by pointer.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb_data_encoding::{Document, Key};
+/// let email = Key::new("email");
+/// let mut document = Document::new();
+/// document.insert(&email, "ada@example.com");
+/// assert_eq!(document.get_key(&email), document.get("email"));
+/// ```
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Key {
+    name: FieldName,
+    hash: u64,
+}
+
+impl Key {
+    pub fn new(name: impl Into<FieldName>) -> Self {
+        let name = name.into();
+        let hash = hash_name(&name);
+        Key { name, hash }
+    }
+
+    /// Returns the name as a string slice.
+    pub fn as_str(&self) -> &str {
+        self.name.as_str()
+    }
+
+    pub fn name(&self) -> &FieldName {
+        &self.name
+    }
+
+    /// Borrows the key, for APIs that take a `KeyRef`.
+    pub fn as_key_ref(&self) -> KeyRef<'_> {
+        KeyRef {
+            name: self.name.as_str(),
+            hash: self.hash,
+        }
+    }
+}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.name, f)
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.name, f)
+    }
+}
+
+impl From<&str> for Key {
+    fn from(name: &str) -> Self {
+        Key::new(name)
+    }
+}
+
+impl From<String> for Key {
+    fn from(name: String) -> Self {
+        Key::new(name)
+    }
+}
+
+impl From<FieldName> for Key {
+    fn from(name: FieldName) -> Self {
+        Key::new(name)
+    }
+}
+
+impl From<&Key> for FieldName {
+    fn from(key: &Key) -> Self {
+        key.name.clone()
+    }
+}
+
+/// A borrowed field name and its hash, as taken by `Document::get_key`.
+///
+/// Made from a `&Key` without hashing, or from a `&str` by hashing it.
+#[derive(Clone, Copy, Debug)]
+pub struct KeyRef<'a> {
+    name: &'a str,
+    hash: u64,
+}
+
+impl<'a> KeyRef<'a> {
+    /// Returns the name as a string slice.
+    pub fn as_str(&self) -> &'a str {
+        self.name
+    }
+
+    pub(crate) fn hash(&self) -> u64 {
+        self.hash
+    }
+}
+
+impl<'a> From<&'a Key> for KeyRef<'a> {
+    fn from(key: &'a Key) -> Self {
+        key.as_key_ref()
+    }
+}
+
+impl<'a> From<&'a str> for KeyRef<'a> {
+    fn from(name: &'a str) -> Self {
+        KeyRef {
+            name,
+            hash: hash_name(name),
+        }
+    }
+}
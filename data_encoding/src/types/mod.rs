@@ -1,15 +1,30 @@
 // src/types/mod.rs
 mod value;
 mod document;
+mod field_name;
+mod key;
 mod object_id;
 mod time;
 mod array;
+mod convert;
+mod ordering;
+#[cfg(feature = "serde_json")]
+mod json_value;
+#[cfg(feature = "bson")]
+mod bson_compat;
+#[cfg(any(feature = "arbitrary", feature = "proptest"))]
+mod arbitrary;
 mod test;
 
 // TODO: Implement Value, Document, ObjectId, and Timestamp
 pub use self::value::Value;
 pub use self::document::Document;
+pub use self::field_name::FieldName;
+pub use self::key::{Key, KeyRef};
 pub use self::object_id::ObjectId;
 pub use self::time::Timestamp;
 pub use self::time::UTCDateTime;
-pub use self::array::Array;
\ No newline at end of file
+pub use self::array::Array;
+pub use self::convert::{FromValue, IntoValue, ValueConversionError};
+#[cfg(feature = "serde_json")]
+pub use self::json_value::{BinaryPolicy, DatePolicy, JsonValueOptions, ObjectIdPolicy};
\ No newline at end of file
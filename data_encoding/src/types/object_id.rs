@@ -1,4 +1,4 @@
-use hex;
+use crate::utils::hex;
 
 /// BSON object ID implementation.
 #[derive(Debug, Clone, PartialEq)]
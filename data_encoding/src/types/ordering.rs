@@ -0,0 +1,196 @@
+// src/types/ordering.rs
+
+use std::cmp::Ordering;
+
+use crate::types::{Array, Document, Value};
+
+/* BSON Comparison Order */
+
+impl Value {
+    /// Returns the rank of this value's type in the BSON comparison order.
+    ///
+    /// Values of different types compare by rank: MinKey, Null and
+    /// Undefined, numbers, strings and symbols, documents, arrays, binary,
+    /// ObjectId, booleans, dates, timestamps, regular expressions,
+    /// DBPointers, code, code with scope, and MaxKey last. Numbers of
+    /// different types share a rank and compare by value.
+    pub fn type_order(&self) -> u8 {
+        match self {
+            Value::MinKey => 0,
+            Value::Null | Value::Undefined => 1,
+            Value::Int32(_) | Value::Int64(_) | Value::UInt64(_) | Value::Double(_) => 2,
+            Value::String(_) | Value::Symbol(_) => 3,
+            Value::Document(_) => 4,
+            Value::Array(_) => 5,
+            Value::Binary(_) => 6,
+            Value::ObjectId(_) => 7,
+            Value::Boolean(_) => 8,
+            Value::UTCDateTime(_) => 9,
+            Value::Timestamp(_) => 10,
+            Value::RegularExpression { .. } => 11,
+            Value::DbPointer { .. } => 12,
+            Value::JavaScriptCode(_) => 13,
+            Value::JavaScriptCodeWithScope { .. } => 14,
+            Value::MaxKey => 15,
+        }
+    }
+
+    /// Compares two values in the BSON comparison order.
+    ///
+    /// Unlike `PartialEq`, this is a total order: numbers compare by value
+    /// across types (so `Int32(1)` equals `Double(1.0)`), NaN sorts below
+    /// every other number, strings compare by their UTF-8 bytes, and
+    /// documents compare field by field in key order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use silentdb_data_encoding::Value;
+    /// use std::cmp::Ordering;
+    ///
+    /// assert_eq!(Value::Int32(2).bson_cmp(&Value::Double(2.5)), Ordering::Less);
+    /// assert_eq!(Value::Int64(1).bson_cmp(&Value::Double(1.0)), Ordering::Equal);
+    /// assert_eq!(Value::Null.bson_cmp(&Value::from("a")), Ordering::Less);
+    /// ```
+    pub fn bson_cmp(&self, other: &Value) -> Ordering {
+        let by_type = self.type_order().cmp(&other.type_order());
+        if by_type != Ordering::Equal {
+            return by_type;
+        }
+
+        match (self, other) {
+            (Value::String(a) | Value::Symbol(a), Value::String(b) | Value::Symbol(b)) => a.cmp(b),
+            (Value::Document(a), Value::Document(b)) => a.bson_cmp(b),
+            (Value::Array(a), Value::Array(b)) => compare_arrays(a, b),
+            (Value::Binary(a), Value::Binary(b)) => a.len().cmp(&b.len()).then_with(|| a.cmp(b)),
+            (Value::ObjectId(a), Value::ObjectId(b)) => a.as_bytes().cmp(b.as_bytes()),
+            (Value::Boolean(a), Value::Boolean(b)) => a.cmp(b),
+            (Value::UTCDateTime(a), Value::UTCDateTime(b)) => a.cmp(b),
+            (Value::Timestamp(a), Value::Timestamp(b)) => a.cmp(b),
+            (
+                Value::RegularExpression {
+                    pattern: a,
+                    options: a_options,
+                },
+                Value::RegularExpression {
+                    pattern: b,
+                    options: b_options,
+                },
+            ) => a.cmp(b).then_with(|| a_options.cmp(b_options)),
+            (
+                Value::DbPointer {
+                    namespace: a,
+                    id: a_id,
+                },
+                Value::DbPointer {
+                    namespace: b,
+                    id: b_id,
+                },
+            ) => a.cmp(b).then_with(|| a_id.as_bytes().cmp(b_id.as_bytes())),
+            (Value::JavaScriptCode(a), Value::JavaScriptCode(b)) => a.cmp(b),
+            (
+                Value::JavaScriptCodeWithScope {
+                    code: a,
+                    scope: a_scope,
+                },
+                Value::JavaScriptCodeWithScope {
+                    code: b,
+                    scope: b_scope,
+                },
+            ) => a.cmp(b).then_with(|| a_scope.bson_cmp(b_scope)),
+            (a, b) => match (Number::of(a), Number::of(b)) {
+                (Some(a), Some(b)) => a.cmp(&b),
+                // MinKey, MaxKey, Null and Undefined are equal to their own kind
+                _ => Ordering::Equal,
+            },
+        }
+    }
+}
+
+impl Document {
+    /// Compares two documents in the BSON comparison order.
+    ///
+    /// Fields are compared in key order, key first and then value, and a
+    /// document that is a prefix of another sorts first.
+    pub fn bson_cmp(&self, other: &Document) -> Ordering {
+        let mut a: Vec<_> = self.iter().collect();
+        let mut b: Vec<_> = other.iter().collect();
+        a.sort_by(|x, y| x.0.cmp(y.0));
+        b.sort_by(|x, y| x.0.cmp(y.0));
+
+        for ((a_key, a_value), (b_key, b_value)) in a.iter().zip(&b) {
+            let ordering = a_key.cmp(b_key).then_with(|| a_value.bson_cmp(b_value));
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        a.len().cmp(&b.len())
+    }
+}
+
+fn compare_arrays(a: &Array, b: &Array) -> Ordering {
+    for (a, b) in a.iter().zip(b.iter()) {
+        let ordering = a.bson_cmp(b);
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    a.len().cmp(&b.len())
+}
+
+/// A numeric value compared exactly across the integer and double types.
+#[derive(Debug, Clone, Copy)]
+enum Number {
+    Integer(i128),
+    Double(f64),
+}
+
+impl Number {
+    fn of(value: &Value) -> Option<Number> {
+        match value {
+            Value::Int32(n) => Some(Number::Integer(*n as i128)),
+            Value::Int64(n) => Some(Number::Integer(*n as i128)),
+            Value::UInt64(n) => Some(Number::Integer(*n as i128)),
+            Value::Double(n) => Some(Number::Double(*n)),
+            _ => None,
+        }
+    }
+
+    fn cmp(&self, other: &Number) -> Ordering {
+        match (*self, *other) {
+            (Number::Integer(a), Number::Integer(b)) => a.cmp(&b),
+            (Number::Double(a), Number::Double(b)) => compare_doubles(a, b),
+            (Number::Integer(a), Number::Double(b)) => compare_integer_to_double(a, b),
+            (Number::Double(a), Number::Integer(b)) => compare_integer_to_double(b, a).reverse(),
+        }
+    }
+}
+
+/// Compares doubles with NaN below every other value and equal to itself.
+fn compare_doubles(a: f64, b: f64) -> Ordering {
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Less,
+        (false, true) => Ordering::Greater,
+        (false, false) => a.partial_cmp(&b).unwrap(),
+    }
+}
+
+/// Compares an integer to a double without losing precision on either side.
+fn compare_integer_to_double(a: i128, b: f64) -> Ordering {
+    if b.is_nan() {
+        return Ordering::Greater;
+    }
+    // Every integer this crate stores fits well inside this range
+    if b >= 1e30 {
+        return Ordering::Less;
+    }
+    if b <= -1e30 {
+        return Ordering::Greater;
+    }
+    let floor = b.floor();
+    match a.cmp(&(floor as i128)) {
+        Ordering::Equal if b > floor => Ordering::Less,
+        ordering => ordering,
+    }
+}
@@ -2,10 +2,15 @@
 mod tests {
     use crate::types::array::Array;
     use crate::types::document::Document;
+    use crate::types::field_name::FieldName;
+    use crate::types::key::Key;
     use crate::types::object_id::ObjectId;
     use crate::types::time::Timestamp;
     use crate::types::time::UTCDateTime;
     use crate::types::value::Value;
+    use crate::types::convert::{FromValue, IntoValue, ValueConversionError};
+    use std::cmp::Ordering;
+    use std::collections::HashMap;
 
     // -------------------------------------
     //          Document Tests
@@ -45,6 +50,53 @@ mod tests {
         assert!(doc.is_empty());
     }
 
+    #[test]
+    fn test_document_grows_past_small_size() {
+        let mut forward = Document::new();
+        let mut backward = Document::new();
+        for i in 0..40 {
+            forward.insert(format!("field_{}", i), i);
+            backward.insert(format!("field_{}", 39 - i), 39 - i);
+            assert_eq!(forward.len(), i as usize + 1);
+        }
+        assert_eq!(forward, backward);
+        assert_eq!(forward.get("field_0"), Some(&0.into()));
+        assert_eq!(forward.insert("field_39", -1), Some(39.into()));
+        assert_eq!(forward.remove("field_39"), Some((-1).into()));
+        assert_ne!(forward, backward);
+
+        let mut small = Document::new();
+        small.insert("b", 1);
+        small.insert("a", 2);
+        let keys: Vec<&str> = small.iter().map(|(key, _)| key.as_str()).collect();
+        assert_eq!(keys, ["b", "a"]);
+    }
+
+    #[test]
+    fn test_document_get_key() {
+        let keys: Vec<Key> = (0..40).map(|i| Key::new(format!("field_{}", i))).collect();
+        let mut small = Document::new();
+        let mut large = Document::new();
+        for (i, key) in keys.iter().enumerate() {
+            large.insert(key, i as i32);
+            if i < 4 {
+                small.insert(key.as_str(), i as i32);
+            }
+        }
+        for document in [&small, &large] {
+            for (i, key) in keys.iter().enumerate() {
+                assert_eq!(document.get_key(key), document.get(key.as_str()));
+                assert_eq!(document.get_key(key.as_str()), document.get(key.as_str()));
+                if i < document.len() {
+                    assert_eq!(document.get_key(key), Some(&(i as i32).into()));
+                }
+            }
+            assert_eq!(document.get_key("missing"), None);
+        }
+        let (name, _) = large.iter().next().unwrap();
+        assert!(keys.iter().any(|key| FieldName::ptr_eq(key.name(), name)));
+    }
+
     #[test]
     fn test_document_add_get_all_values() {
         let mut doc = Document::new();
@@ -355,6 +407,29 @@ mod tests {
         assert_eq!(utc_date_time.into(), "1234567890");
     }
 
+    #[test]
+    fn test_utc_date_time_parse_rfc3339() {
+        let parsed = UTCDateTime::parse_rfc3339("2009-02-13T23:31:30Z").unwrap();
+        assert_eq!(parsed.as_secs(), 1234567890);
+
+        let parsed = UTCDateTime::parse_rfc3339("2009-02-14T01:31:30.999+02:00").unwrap();
+        assert_eq!(parsed.as_secs(), 1234567890);
+
+        let parsed = UTCDateTime::parse_rfc3339("1969-12-31T23:59:59Z").unwrap();
+        assert_eq!(parsed.as_secs(), -1);
+
+        assert!(UTCDateTime::parse_rfc3339("2009-02-30T00:00:00Z").is_none());
+        assert!(UTCDateTime::parse_rfc3339("2009-02-13 23:31").is_none());
+        assert!(UTCDateTime::parse_rfc3339("2009-02-13T23:31:30").is_none());
+    }
+
+    #[test]
+    fn test_utc_date_time_to_rfc3339() {
+        assert_eq!(UTCDateTime::from_secs(1234567890).to_rfc3339(), "2009-02-13T23:31:30Z");
+        assert_eq!(UTCDateTime::from_secs(-1).to_rfc3339(), "1969-12-31T23:59:59Z");
+        assert_eq!(UTCDateTime::from_secs(951782400).to_rfc3339(), "2000-02-29T00:00:00Z");
+    }
+
     // -------------------------------------
     //          Timestamp Tests
     // -------------------------------------
@@ -562,4 +637,266 @@ mod tests {
         let value = ("code".to_string(), Document::new());
         assert_eq!(value.into(), ("code".to_string(), Document::new()));
     }
+
+    // -------------------------------------
+    //        FromValue / IntoValue Tests
+    // -------------------------------------
+
+    #[test]
+    fn test_from_value_integers() {
+        assert_eq!(i64::from_value(&Value::Int32(7)), Ok(7));
+        assert_eq!(u8::from_value(&Value::Int64(255)), Ok(255));
+        assert_eq!(i32::from_value(&Value::UInt64(5)), Ok(5));
+        assert_eq!(
+            u8::from_value(&Value::Int32(-1)),
+            Err(ValueConversionError::OutOfRange { value: "-1".to_string(), target: "u8" })
+        );
+        assert_eq!(
+            i32::from_value(&Value::String("7".to_string())),
+            Err(ValueConversionError::TypeMismatch { expected: "i32", found: "string" })
+        );
+    }
+
+    #[test]
+    fn test_from_value_primitives() {
+        assert_eq!(f64::from_value(&Value::Int32(2)), Ok(2.0));
+        assert_eq!(bool::from_value(&Value::Boolean(true)), Ok(true));
+        assert_eq!(String::from_value(&Value::String("a".to_string())), Ok("a".to_string()));
+        assert_eq!(Option::<i32>::from_value(&Value::Null), Ok(None));
+        assert_eq!(Option::<i32>::from_value(&Value::Int32(1)), Ok(Some(1)));
+        assert_eq!(
+            UTCDateTime::from_value(&Value::UTCDateTime(10)),
+            Ok(UTCDateTime::from_secs(10))
+        );
+        assert_eq!(Timestamp::from_value(&Value::Timestamp(3)), Ok(Timestamp::from_secs(3)));
+    }
+
+    #[test]
+    fn test_from_value_containers() {
+        let array = Value::Array(Array::from(vec![Value::Int32(1), Value::String("b".to_string())]));
+        assert_eq!(<(i32, String)>::from_value(&array), Ok((1, "b".to_string())));
+        assert_eq!(
+            <(i32,)>::from_value(&array),
+            Err(ValueConversionError::LengthMismatch { expected: 1, found: 2 })
+        );
+        assert_eq!(
+            Vec::<i32>::from_value(&array),
+            Err(ValueConversionError::TypeMismatch { expected: "i32", found: "string" }.in_field("1"))
+        );
+
+        let mut document = Document::new();
+        document.insert("a", 1);
+        let map: HashMap<String, i64> = document.decode_as().unwrap();
+        assert_eq!(map["a"], 1);
+    }
+
+    #[test]
+    fn test_into_value_round_trip() {
+        let value = vec![(1u8, Some("x")), (2u8, None)].into_value();
+        let expected = Array::from(vec![
+            Value::Array(Array::from(vec![Value::Int32(1), Value::String("x".to_string())])),
+            Value::Array(Array::from(vec![Value::Int32(2), Value::Null])),
+        ]);
+        assert_eq!(value, Value::Array(expected));
+        assert_eq!(
+            Vec::<(u8, Option<String>)>::from_value(&value),
+            Ok(vec![(1, Some("x".to_string())), (2, None)])
+        );
+
+        let mut map = HashMap::new();
+        map.insert("n".to_string(), 4u32);
+        assert_eq!(HashMap::<String, u32>::from_value(&map.clone().into_value()), Ok(map));
+    }
+
+    #[test]
+    fn test_document_get_as() {
+        let mut document = Document::new();
+        document.insert("age", 36);
+        assert_eq!(document.get_as::<u8>("age"), Ok(36));
+        assert_eq!(document.get_as::<Option<String>>("email"), Ok(None));
+        assert_eq!(
+            document.get_as::<String>("email"),
+            Err(ValueConversionError::MissingField("email".to_string()))
+        );
+        assert_eq!(
+            document.get_as::<bool>("age"),
+            Err(ValueConversionError::TypeMismatch { expected: "bool", found: "int" }.in_field("age"))
+        );
+    }
+
+    // -------------------------------------
+    //          BSON Ordering Tests
+    // -------------------------------------
+
+    #[test]
+    fn test_bson_cmp_orders_types() {
+        let values = vec![
+            Value::MinKey,
+            Value::Null,
+            Value::Int32(1),
+            Value::String("a".to_string()),
+            Value::Document(Document::new()),
+            Value::Array(Array::new()),
+            Value::Binary(vec![]),
+            Value::ObjectId(ObjectId::from_bytes([0; 12])),
+            Value::Boolean(false),
+            Value::UTCDateTime(0),
+            Value::Timestamp(0),
+            Value::RegularExpression {
+                pattern: "a".to_string(),
+                options: String::new(),
+            },
+            Value::MaxKey,
+        ];
+        for pair in values.windows(2) {
+            assert_eq!(pair[0].bson_cmp(&pair[1]), Ordering::Less, "{:?}", pair);
+            assert_eq!(pair[1].bson_cmp(&pair[0]), Ordering::Greater, "{:?}", pair);
+        }
+    }
+
+    #[test]
+    fn test_bson_cmp_numbers_across_types() {
+        assert_eq!(Value::Int32(1).bson_cmp(&Value::Int64(1)), Ordering::Equal);
+        assert_eq!(Value::Int64(1).bson_cmp(&Value::Double(1.0)), Ordering::Equal);
+        assert_eq!(Value::Int32(1).bson_cmp(&Value::Double(1.5)), Ordering::Less);
+        assert_eq!(Value::Double(-0.5).bson_cmp(&Value::Int32(-1)), Ordering::Greater);
+        assert_eq!(Value::UInt64(u64::MAX).bson_cmp(&Value::Int64(i64::MAX)), Ordering::Greater);
+        assert_eq!(Value::Double(f64::NAN).bson_cmp(&Value::Double(f64::NEG_INFINITY)), Ordering::Less);
+        assert_eq!(Value::Double(f64::NAN).bson_cmp(&Value::Double(f64::NAN)), Ordering::Equal);
+        assert_eq!(
+            Value::Int64((1 << 53) + 1).bson_cmp(&Value::Double((1u64 << 53) as f64)),
+            Ordering::Greater
+        );
+    }
+
+    #[test]
+    fn test_bson_cmp_containers() {
+        let mut a = Document::new();
+        a.insert("a", 1);
+        let mut b = a.clone();
+        b.insert("b", 1);
+        assert_eq!(a.bson_cmp(&b), Ordering::Less);
+        assert_eq!(a.bson_cmp(&a.clone()), Ordering::Equal);
+
+        let mut c = Document::new();
+        c.insert("a", 2);
+        assert_eq!(b.bson_cmp(&c), Ordering::Less);
+
+        let short = Value::Array(Array::from_vec(vec![Value::Int32(1)]));
+        let long = Value::Array(Array::from_vec(vec![Value::Int32(1), Value::Int32(0)]));
+        assert_eq!(short.bson_cmp(&long), Ordering::Less);
+        assert_eq!(
+            Value::Binary(vec![9]).bson_cmp(&Value::Binary(vec![0, 0])),
+            Ordering::Less
+        );
+    }
+
+    // -------------------------------------
+    //          serde_json Tests
+    // -------------------------------------
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn test_serde_json_round_trip() {
+        let text = r#"{"a":1,"b":[1.5,"x",null,true,5000000000,18446744073709551615],"c":{"d":-3}}"#;
+        let json: serde_json::Value = serde_json::from_str(text).unwrap();
+        let Value::Document(document) = Value::from(json.clone()) else {
+            panic!("not a document");
+        };
+        assert_eq!(document.get("a"), Some(&Value::Int32(1)));
+        let Some(Value::Array(b)) = document.get("b") else {
+            panic!("no array in {:?}", document);
+        };
+        assert_eq!(b.get(4), Some(&Value::Int64(5_000_000_000)));
+        assert_eq!(b.get(5), Some(&Value::UInt64(u64::MAX)));
+        assert_eq!(serde_json::Value::try_from(document).unwrap(), json);
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn test_serde_json_policies() {
+        use crate::types::json_value::{BinaryPolicy, DatePolicy, JsonValueOptions, ObjectIdPolicy};
+        use serde_json::json;
+
+        let mut document = Document::new();
+        document.insert("bin", Value::Binary(vec![0xCA, 0xFE]));
+        document.insert("id", ObjectId::from_bytes([1; 12]));
+        document.insert("date", Value::UTCDateTime(86_400));
+        document.insert("nan", f64::NAN);
+        assert_eq!(
+            document.to_json_value(&JsonValueOptions::new()).unwrap(),
+            json!({
+                "bin": "yv4=",
+                "id": "010101010101010101010101",
+                "date": "1970-01-02T00:00:00Z",
+                "nan": { "$numberDouble": "NaN" },
+            })
+        );
+
+        let options = JsonValueOptions::new()
+            .binary(BinaryPolicy::ExtendedJson)
+            .object_id(ObjectIdPolicy::ExtendedJson)
+            .date(DatePolicy::EpochMillis);
+        assert_eq!(
+            document.to_json_value(&options).unwrap(),
+            json!({
+                "bin": { "$binary": { "base64": "yv4=", "subType": "00" } },
+                "id": { "$oid": "010101010101010101010101" },
+                "date": 86_400_000,
+                "nan": { "$numberDouble": "NaN" },
+            })
+        );
+
+        // A rejected value names the path to it
+        let mut outer = Document::new();
+        outer.insert("list", Array::from_vec(vec![document.into()]));
+        let error = outer
+            .to_json_value(&JsonValueOptions::new().object_id(ObjectIdPolicy::Reject))
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Field 'list': Field '0': Field 'id': Expected a JSON value, found objectId"
+        );
+    }
+
+    // -------------------------------------
+    //          bson Crate Tests
+    // -------------------------------------
+
+    #[cfg(feature = "bson")]
+    #[test]
+    fn test_bson_crate_round_trip() {
+        let id = ObjectId::from_bytes([7; 12]);
+        let mut document = Document::new();
+        document.insert("id", id.clone());
+        document.insert("date", Value::UTCDateTime(86_400));
+        document.insert("ts", Value::Timestamp((7 << 32) | 3));
+        document.insert("list", Array::from_vec(vec![Value::Int64(1), Value::Binary(vec![1])]));
+        document.insert("pointer", Value::DbPointer { namespace: "db.users".to_string(), id });
+
+        let converted = bson::Document::try_from(document.clone()).unwrap();
+        assert_eq!(converted.get_datetime("date").unwrap().timestamp_millis(), 86_400_000);
+        assert_eq!(
+            converted.get_timestamp("ts").unwrap(),
+            bson::Timestamp { time: 7, increment: 3 }
+        );
+        assert_eq!(Document::try_from(converted).unwrap(), document);
+    }
+
+    #[cfg(feature = "bson")]
+    #[test]
+    fn test_bson_crate_unsupported_types() {
+        let decimal = bson::doc! { "price": bson::Decimal128::from_bytes([0; 16]) };
+        assert!(matches!(
+            Document::try_from(decimal),
+            Err(ValueConversionError::Field { field, .. }) if field == "price"
+        ));
+
+        // A UInt64 becomes an Int64 if it fits one
+        assert_eq!(bson::Bson::try_from(Value::UInt64(5)), Ok(bson::Bson::Int64(5)));
+        assert!(matches!(
+            bson::Bson::try_from(Value::UInt64(u64::MAX)),
+            Err(ValueConversionError::OutOfRange { .. })
+        ));
+    }
 }
@@ -16,12 +16,18 @@ pub enum Value {
     Document(Document),
     Array(Array),
     Binary(Vec<u8>),
+    /// Deprecated `undefined`, only produced when decoding with `LegacyTypes::Preserve`.
+    Undefined,
     ObjectId(ObjectId),
     Boolean(bool),
     UTCDateTime(i64),
     Null,
     RegularExpression { pattern: String, options: String },
+    /// Deprecated DBPointer, only produced when decoding with `LegacyTypes::Preserve`.
+    DbPointer { namespace: String, id: ObjectId },
     JavaScriptCode(String),
+    /// Deprecated symbol, only produced when decoding with `LegacyTypes::Preserve`.
+    Symbol(String),
     JavaScriptCodeWithScope { code: String, scope: Document },
     Int32(i32),
     Timestamp(i64),
@@ -40,14 +46,19 @@ impl Value {
             Value::Document(value) => serializer.serialize_document(value),
             Value::Array(value) => serializer.serialize_array(value),
             Value::Binary(value) => serializer.serialize_binary(value),
-            Value::ObjectId(value) => serializer.serialize_object_id(value),
+            Value::Undefined => serializer.serialize_undefined(),
+            Value::ObjectId(value) => serializer.serialize_object_id(value.clone()),
             Value::Boolean(value) => serializer.serialize_boolean(*value),
-            Value::UTCDateTime(value) => serializer.serialize_timestamp(*value),
+            Value::UTCDateTime(value) => serializer.serialize_utc_datetime(*value),
             Value::Null => serializer.serialize_null(),
             Value::RegularExpression { pattern, options } => {
                 serializer.serialize_regex(pattern, options)
             }
+            Value::DbPointer { namespace, id } => {
+                serializer.serialize_db_pointer(namespace, id.clone())
+            }
             Value::JavaScriptCode(value) => serializer.serialize_javascript_code(value),
+            Value::Symbol(value) => serializer.serialize_symbol(value),
             Value::JavaScriptCodeWithScope { code, scope } => {
                 serializer.serialize_javascript_code_with_scope(code, scope)
             }
@@ -201,6 +212,9 @@ impl fmt::Display for Value {
                 write!(f, "]")
             }
             Value::Binary(v) => write!(f, "Binary(len: {})", v.len()),
+            Value::Undefined => write!(f, "undefined"),
+            Value::DbPointer { namespace, id } => write!(f, "DBPointer(\"{}\", {})", namespace, id),
+            Value::Symbol(v) => write!(f, "Symbol({})", v),
             Value::ObjectId(v) => write!(f, "ObjectID(\"{}\")", v),
             Value::Boolean(v) => write!(f, "{}", v),
             Value::UTCDateTime(v) => write!(f, "DateTime({})", v),
@@ -0,0 +1,255 @@
+// src/utils/buffer_pool.rs
+
+use std::io::Cursor;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::deser::MAX_DOCUMENT_LEN;
+use crate::ser::{BsonSerializer, SerializeError, Serializer};
+use crate::types::Document;
+
+/// Capacity of the smallest size class. Smaller buffers are not worth
+/// pooling, and are not handed out by decoders.
+pub(crate) const MIN_POOLED_CAPACITY: usize = 256;
+/// Number of size classes: powers of two from `MIN_POOLED_CAPACITY` up to
+/// `MAX_DOCUMENT_LEN`. Larger buffers are freed when returned.
+const SIZE_CLASSES: usize = (MAX_DOCUMENT_LEN.next_power_of_two().trailing_zeros()
+    - MIN_POOLED_CAPACITY.trailing_zeros()
+    + 1) as usize;
+/// Buffers kept per size class by `BufferPool::default`.
+const DEFAULT_MAX_IDLE: usize = 32;
+
+/// A thread-safe pool of byte buffers, shared by the encoders, decoders
+/// and framers of a service so that each request reuses the buffers of
+/// earlier ones instead of allocating its own.
+///
+/// Buffers are kept by size class, the powers of two from 256 bytes to
+/// `MAX_DOCUMENT_LEN`, and each request is handed the smallest buffer the
+/// pool holds that is large enough. Cloning a pool is cheap, and the
+/// clones share its buffers.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb_data_encoding::{BufferPool, Document};
+/// let pool = BufferPool::new(16);
+/// let mut document = Document::new();
+/// document.insert("a", 1);
+///
+/// let bytes = pool.encode(&document).unwrap();
+/// assert_eq!(bytes.len(), 12);
+/// drop(bytes);
+///
+/// // The second encoding reuses the first one's buffer
+/// pool.encode(&document).unwrap();
+/// assert_eq!(pool.stats().hits, 1);
+/// ```
+#[derive(Debug, Clone)]
+pub struct BufferPool {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    classes: Vec<Mutex<Vec<Vec<u8>>>>,
+    max_idle: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    returned: AtomicU64,
+    discarded: AtomicU64,
+}
+
+/// Counts of what a `BufferPool` has done, from `BufferPool::stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BufferPoolStats {
+    /// Buffers handed out from the pool.
+    pub hits: u64,
+    /// Buffers allocated because the pool had none of the size asked for.
+    pub misses: u64,
+    /// Buffers given back and kept.
+    pub returned: u64,
+    /// Buffers given back and freed, because their size class was full or
+    /// they were too small or too large to pool.
+    pub discarded: u64,
+    /// Buffers waiting in the pool.
+    pub idle: usize,
+    /// Capacity of the buffers waiting in the pool, in bytes.
+    pub idle_bytes: usize,
+}
+
+impl BufferPool {
+    /// Creates an empty pool that keeps at most `max_idle` buffers of each
+    /// size class.
+    pub fn new(max_idle: usize) -> Self {
+        BufferPool {
+            shared: Arc::new(Shared {
+                classes: (0..SIZE_CLASSES).map(|_| Mutex::new(Vec::new())).collect(),
+                max_idle,
+                hits: AtomicU64::new(0),
+                misses: AtomicU64::new(0),
+                returned: AtomicU64::new(0),
+                discarded: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Takes an empty buffer of at least `capacity` bytes, returned to the
+    /// pool when dropped.
+    pub fn get(&self, capacity: usize) -> PooledBuffer {
+        PooledBuffer {
+            buffer: self.take(capacity),
+            pool: self.clone(),
+        }
+    }
+
+    /// Takes an empty buffer of at least `capacity` bytes, to give back
+    /// with `put` once done with.
+    pub fn take(&self, capacity: usize) -> Vec<u8> {
+        let Some(class) = request_class(capacity) else {
+            self.shared.misses.fetch_add(1, Ordering::Relaxed);
+            return Vec::with_capacity(capacity);
+        };
+        // A buffer that grew past its request is kept in a larger class
+        if let Some(buffer) = (class..SIZE_CLASSES).find_map(|class| self.class(class).pop()) {
+            self.shared.hits.fetch_add(1, Ordering::Relaxed);
+            return buffer;
+        }
+        self.shared.misses.fetch_add(1, Ordering::Relaxed);
+        Vec::with_capacity(MIN_POOLED_CAPACITY << class)
+    }
+
+    /// Gives `buffer` back to the pool, emptied, to be handed out again. It
+    /// is freed instead if its size class is full.
+    pub fn put(&self, mut buffer: Vec<u8>) {
+        let kept = match return_class(buffer.capacity()) {
+            Some(class) => {
+                let mut idle = self.class(class);
+                if idle.len() < self.shared.max_idle {
+                    buffer.clear();
+                    idle.push(buffer);
+                    true
+                } else {
+                    false
+                }
+            }
+            None => false,
+        };
+        let counter = match kept {
+            true => &self.shared.returned,
+            false => &self.shared.discarded,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Encodes `document` into a buffer from the pool, as `to_bytes` does
+    /// into a new one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the document contains a value that cannot be
+    /// encoded.
+    pub fn encode(&self, document: &Document) -> Result<PooledBuffer, SerializeError> {
+        let mut buffer = self.get(0);
+        let mut serializer = BsonSerializer::new(Cursor::new(std::mem::take(&mut buffer.buffer)));
+        let result = serializer.serialize_document(document);
+        buffer.buffer = serializer.into_inner().into_inner();
+        result?;
+        Ok(buffer)
+    }
+
+    /// Returns what the pool has done so far, and what it holds.
+    pub fn stats(&self) -> BufferPoolStats {
+        let (idle, idle_bytes) = self
+            .shared
+            .classes
+            .iter()
+            .fold((0, 0), |(idle, bytes), class| {
+                let class = class.lock().unwrap_or_else(|e| e.into_inner());
+                let class_bytes: usize = class.iter().map(Vec::capacity).sum();
+                (idle + class.len(), bytes + class_bytes)
+            });
+        BufferPoolStats {
+            hits: self.shared.hits.load(Ordering::Relaxed),
+            misses: self.shared.misses.load(Ordering::Relaxed),
+            returned: self.shared.returned.load(Ordering::Relaxed),
+            discarded: self.shared.discarded.load(Ordering::Relaxed),
+            idle,
+            idle_bytes,
+        }
+    }
+
+    fn class(&self, class: usize) -> MutexGuard<'_, Vec<Vec<u8>>> {
+        self.shared.classes[class]
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for BufferPool {
+    fn default() -> Self {
+        BufferPool::new(DEFAULT_MAX_IDLE)
+    }
+}
+
+/// Returns the size class whose buffers all hold `capacity` bytes, if any.
+fn request_class(capacity: usize) -> Option<usize> {
+    let size = capacity
+        .max(MIN_POOLED_CAPACITY)
+        .checked_next_power_of_two()?;
+    let class = (size.trailing_zeros() - MIN_POOLED_CAPACITY.trailing_zeros()) as usize;
+    (class < SIZE_CLASSES).then_some(class)
+}
+
+/// Returns the largest size class a buffer of `capacity` bytes can serve.
+fn return_class(capacity: usize) -> Option<usize> {
+    if capacity < MIN_POOLED_CAPACITY {
+        return None;
+    }
+    let class = (capacity.ilog2() - MIN_POOLED_CAPACITY.trailing_zeros()) as usize;
+    (class < SIZE_CLASSES).then_some(class)
+}
+
+/// A buffer from a `BufferPool`, given back to it when dropped. It
+/// dereferences to the `Vec<u8>` it wraps.
+#[derive(Debug)]
+pub struct PooledBuffer {
+    buffer: Vec<u8>,
+    pool: BufferPool,
+}
+
+impl PooledBuffer {
+    /// Keeps the buffer instead of giving it back to the pool.
+    pub fn into_vec(mut self) -> Vec<u8> {
+        std::mem::take(&mut self.buffer)
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.buffer
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.buffer
+    }
+}
+
+impl AsRef<[u8]> for PooledBuffer {
+    fn as_ref(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        // A buffer taken by `into_vec` leaves an empty one behind
+        if self.buffer.capacity() > 0 {
+            self.pool.put(std::mem::take(&mut self.buffer));
+        }
+    }
+}
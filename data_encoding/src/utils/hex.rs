@@ -0,0 +1,144 @@
+// src/utils/hex.rs
+
+const DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+/// Encodes bytes as lowercase hex.
+///
+/// Uses SSSE3 when the CPU has it, 16 bytes at a time, so that even an
+/// ObjectId's 12 bytes take one pass.
+pub fn encode(bytes: &[u8]) -> String {
+    let mut output = vec![0; bytes.len() * 2];
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("ssse3") {
+        // SAFETY: the CPU was just checked for SSSE3.
+        unsafe { x86::encode(bytes, &mut output) };
+        // SAFETY: every byte written is an ASCII hex digit.
+        return unsafe { String::from_utf8_unchecked(output) };
+    }
+    encode_scalar(bytes, &mut output);
+    // SAFETY: every byte written is an ASCII hex digit.
+    unsafe { String::from_utf8_unchecked(output) }
+}
+
+/// Decodes hex, upper or lower case.
+///
+/// Returns `None` if the input has an odd length or a character that is
+/// not a hex digit.
+pub fn decode(text: &str) -> Option<Vec<u8>> {
+    let text = text.as_bytes();
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    let mut output = vec![0; text.len() / 2];
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("ssse3") {
+        // SAFETY: the CPU was just checked for SSSE3.
+        return unsafe { x86::decode(text, &mut output) }.then_some(output);
+    }
+    decode_scalar(text, &mut output).then_some(output)
+}
+
+fn encode_scalar(bytes: &[u8], output: &mut [u8]) {
+    for (byte, pair) in bytes.iter().zip(output.chunks_exact_mut(2)) {
+        pair[0] = DIGITS[(byte >> 4) as usize];
+        pair[1] = DIGITS[(byte & 0x0F) as usize];
+    }
+}
+
+fn decode_scalar(text: &[u8], output: &mut [u8]) -> bool {
+    for (pair, byte) in text.chunks_exact(2).zip(output.iter_mut()) {
+        match (nibble(pair[0]), nibble(pair[1])) {
+            (Some(high), Some(low)) => *byte = high << 4 | low,
+            _ => return false,
+        }
+    }
+    true
+}
+
+fn nibble(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    use super::DIGITS;
+
+    /// Encodes `bytes` into `output`, twice as long, 16 bytes at a time; a
+    /// shorter last block is padded on the stack.
+    #[target_feature(enable = "ssse3")]
+    pub(super) unsafe fn encode(bytes: &[u8], output: &mut [u8]) {
+        let digits = _mm_loadu_si128(DIGITS.as_ptr().cast());
+        let low_nibbles = _mm_set1_epi8(0x0F);
+        for (block, out) in bytes.chunks(16).zip(output.chunks_mut(32)) {
+            let mut padded = [0u8; 16];
+            padded[..block.len()].copy_from_slice(block);
+            let input = _mm_loadu_si128(padded.as_ptr().cast());
+            let high = _mm_and_si128(_mm_srli_epi16(input, 4), low_nibbles);
+            let low = _mm_and_si128(input, low_nibbles);
+            let high = _mm_shuffle_epi8(digits, high);
+            let low = _mm_shuffle_epi8(digits, low);
+            let mut encoded = [0u8; 32];
+            _mm_storeu_si128(encoded.as_mut_ptr().cast(), _mm_unpacklo_epi8(high, low));
+            _mm_storeu_si128(
+                encoded[16..].as_mut_ptr().cast(),
+                _mm_unpackhi_epi8(high, low),
+            );
+            out.copy_from_slice(&encoded[..out.len()]);
+        }
+    }
+
+    /// Decodes `text` into `output`, half as long, 32 digits at a time; a
+    /// shorter last block is padded with `0` digits. Returns `false` if a
+    /// character is not a hex digit.
+    #[target_feature(enable = "ssse3")]
+    pub(super) unsafe fn decode(text: &[u8], output: &mut [u8]) -> bool {
+        for (block, out) in text.chunks(32).zip(output.chunks_mut(16)) {
+            let mut padded = [b'0'; 32];
+            padded[..block.len()].copy_from_slice(block);
+            let (first, first_valid) = nibbles(_mm_loadu_si128(padded.as_ptr().cast()));
+            let (second, second_valid) = nibbles(_mm_loadu_si128(padded[16..].as_ptr().cast()));
+            if _mm_movemask_epi8(_mm_and_si128(first_valid, second_valid)) != 0xFFFF {
+                return false;
+            }
+            // Each pair of nibbles becomes high * 16 + low
+            let weights = _mm_set1_epi16(0x0110);
+            let decoded = _mm_packus_epi16(
+                _mm_maddubs_epi16(first, weights),
+                _mm_maddubs_epi16(second, weights),
+            );
+            let mut bytes = [0u8; 16];
+            _mm_storeu_si128(bytes.as_mut_ptr().cast(), decoded);
+            out.copy_from_slice(&bytes[..out.len()]);
+        }
+        true
+    }
+
+    /// Returns the value of each hex digit in `chars`, and a mask of the
+    /// lanes that held one.
+    #[target_feature(enable = "ssse3")]
+    unsafe fn nibbles(chars: __m128i) -> (__m128i, __m128i) {
+        let below = |value: __m128i, bound: i8| {
+            _mm_cmpeq_epi8(_mm_min_epu8(value, _mm_set1_epi8(bound)), value)
+        };
+        let digit = _mm_sub_epi8(chars, _mm_set1_epi8(b'0' as i8));
+        let is_digit = below(digit, 9);
+        // Setting bit 5 folds 'A'..='F' onto 'a'..='f'
+        let letter = _mm_sub_epi8(
+            _mm_or_si128(chars, _mm_set1_epi8(0x20)),
+            _mm_set1_epi8(b'a' as i8),
+        );
+        let is_letter = below(letter, 5);
+        let values = _mm_or_si128(
+            _mm_and_si128(is_digit, digit),
+            _mm_and_si128(is_letter, _mm_add_epi8(letter, _mm_set1_epi8(10))),
+        );
+        (values, _mm_or_si128(is_digit, is_letter))
+    }
+}
@@ -0,0 +1,8 @@
+// src/utils/mod.rs
+
+pub mod base64;
+pub mod buffer_pool;
+pub mod hex;
+pub(crate) mod scan;
+pub mod validator;
+mod test;
@@ -0,0 +1,32 @@
+// src/utils/scan.rs
+
+use std::str::Utf8Error;
+use std::string::FromUtf8Error;
+
+/// Returns the length of the null-terminated string at the start of `bytes`,
+/// excluding the terminator, or `None` if it is not terminated.
+///
+/// Searches a vector register's width at a time where the CPU allows.
+pub(crate) fn cstring_len(bytes: &[u8]) -> Option<usize> {
+    memchr::memchr(0, bytes)
+}
+
+/// Checks that `bytes` are UTF-8, like `std::str::from_utf8`, with SIMD
+/// where the CPU allows. Valid input, the common case, is checked once;
+/// invalid input is checked again by `std` for its detailed error.
+pub(crate) fn str_from_utf8(bytes: &[u8]) -> Result<&str, Utf8Error> {
+    match simdutf8::basic::from_utf8(bytes) {
+        Ok(text) => Ok(text),
+        Err(_) => std::str::from_utf8(bytes),
+    }
+}
+
+/// Converts `bytes` to a string like `String::from_utf8`, checking them as
+/// `str_from_utf8` does.
+pub(crate) fn string_from_utf8(bytes: Vec<u8>) -> Result<String, FromUtf8Error> {
+    match simdutf8::basic::from_utf8(&bytes) {
+        // SAFETY: the bytes were just checked to be UTF-8.
+        Ok(_) => Ok(unsafe { String::from_utf8_unchecked(bytes) }),
+        Err(_) => String::from_utf8(bytes),
+    }
+}
@@ -0,0 +1,216 @@
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use crate::deser::Decoder;
+    use crate::ser::{to_bytes, Encoder};
+    use crate::types::{Array, Document, ObjectId, Value};
+    use crate::utils::buffer_pool::BufferPool;
+    use crate::utils::hex;
+    use crate::utils::validator::{validate, IssueKind};
+
+    /// Wraps encoded elements in a length prefix and terminator.
+    fn document(elements: &[u8]) -> Vec<u8> {
+        let mut bytes = ((elements.len() + 5) as i32).to_le_bytes().to_vec();
+        bytes.extend_from_slice(elements);
+        bytes.push(0);
+        bytes
+    }
+
+    fn kinds(bytes: &[u8]) -> Vec<(usize, IssueKind)> {
+        validate(bytes)
+            .into_issues()
+            .into_iter()
+            .map(|issue| (issue.offset, issue.kind))
+            .collect()
+    }
+
+    // -------------------------------------
+    //          Validator Tests
+    // -------------------------------------
+
+    #[test]
+    fn test_validate_well_formed() {
+        let mut inner = Document::new();
+        inner.insert("id", ObjectId::from_bytes([7; 12]));
+        let mut document = Document::new();
+        document.insert("s", "text");
+        document.insert("d", inner);
+        document.insert("a", Array::from(vec![Value::Int32(1), Value::Null, Value::Double(0.5)]));
+        document.insert("b", Value::Binary(vec![1, 2, 3]));
+        document.insert(
+            "r",
+            Value::RegularExpression {
+                pattern: "^a".to_string(),
+                options: "i".to_string(),
+            },
+        );
+        document.insert("t", Value::Timestamp(9));
+        assert!(validate(&to_bytes(&document).unwrap()).is_valid());
+    }
+
+    #[test]
+    fn test_validate_reports_every_issue() {
+        // {"a": true-ish 2, "\xff": "ok\xfe"}
+        let mut elements = vec![0x08, b'a', 0, 2];
+        elements.extend_from_slice(&[0x02, 0xFF, 0, 4, 0, 0, 0, b'o', b'k', 0xFE, 0]);
+        let bytes = document(&elements);
+        assert_eq!(
+            kinds(&bytes),
+            [
+                (7, IssueKind::InvalidBoolean(2)),
+                (9, IssueKind::InvalidUtf8),
+                (15, IssueKind::InvalidUtf8),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_skips_damaged_embedded_document() {
+        // {"d": {"x": <type 0x42> ...}, "b": 2}: the rest of "d" is skipped, "b" is still checked
+        let inner = document(&[0x42, b'x', 0, 1, 2, 3]);
+        let mut elements = vec![0x03, b'd', 0];
+        elements.extend_from_slice(&inner);
+        elements.extend_from_slice(&[0x08, b'b', 0, 2]);
+        let bytes = document(&elements);
+        assert_eq!(
+            kinds(&bytes),
+            [
+                (11, IssueKind::UnknownElementType(0x42)),
+                (bytes.len() - 2, IssueKind::InvalidBoolean(2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_framing_issues() {
+        let bytes = document(&[0x10, b'n', 0, 1, 0, 0, 0]);
+
+        assert_eq!(kinds(&bytes[..3]), [(0, IssueKind::Truncated)]);
+        assert_eq!(
+            kinds(&bytes[..bytes.len() - 1]),
+            [(0, IssueKind::LengthOverrun { declared: 12, available: 11 })]
+        );
+        assert_eq!(
+            kinds(&[bytes.clone(), vec![0, 0]].concat()),
+            [(12, IssueKind::TrailingBytes(2))]
+        );
+
+        let mut unterminated = bytes.clone();
+        *unterminated.last_mut().unwrap() = 1;
+        assert_eq!(kinds(&unterminated), [(11, IssueKind::MissingTerminator)]);
+
+        let mut overrun = bytes;
+        overrun[4] = 0x02; // the int32 now reads as a string length of 1 with no room for it
+        assert_eq!(
+            kinds(&overrun),
+            [(7, IssueKind::LengthOverrun { declared: 1, available: 0 })]
+        );
+    }
+
+    #[test]
+    fn test_validate_array_indexes() {
+        let array = document(&[0x10, b'0', 0, 1, 0, 0, 0, 0x10, b'5', 0, 2, 0, 0, 0]);
+        let mut elements = vec![0x04, b'a', 0];
+        elements.extend_from_slice(&array);
+        let bytes = document(&elements);
+        assert_eq!(kinds(&bytes), [(19, IssueKind::InvalidArrayIndex { expected: 1 })]);
+    }
+
+    // -------------------------------------
+    //          Hex Tests
+    // -------------------------------------
+
+    #[test]
+    fn test_hex_round_trip() {
+        // Lengths on both sides of the 16-byte blocks the SIMD path takes
+        for len in 0..50 {
+            let bytes: Vec<u8> = (0..len).map(|i| (i * 37 + 11) as u8).collect();
+            let text = hex::encode(&bytes);
+            assert_eq!(text, ::hex::encode(&bytes));
+            assert_eq!(hex::decode(&text), Some(bytes.clone()));
+            assert_eq!(hex::decode(&text.to_uppercase()), Some(bytes));
+        }
+    }
+
+    #[test]
+    fn test_hex_decode_invalid() {
+        assert_eq!(hex::decode("abc"), None);
+        let text = "0123456789abcdefABCDEF0123456789abcdef";
+        for i in 0..text.len() {
+            for bad in ['g', 'G', '/', ':', '@', '`', ' '] {
+                let mut invalid = text.to_string();
+                invalid.replace_range(i..i + 1, &bad.to_string());
+                assert_eq!(hex::decode(&invalid), None, "{}", invalid);
+            }
+        }
+    }
+
+    // -------------------------------------
+    //          Buffer Pool Tests
+    // -------------------------------------
+
+    #[test]
+    fn test_buffer_pool_size_classes() {
+        let pool = BufferPool::new(2);
+        let small = pool.get(10);
+        assert_eq!(small.capacity(), 256);
+        let large = pool.get(1000);
+        assert_eq!(large.capacity(), 1024);
+        drop(small);
+        drop(large);
+
+        // A small request is not handed the large buffer
+        assert_eq!(pool.get(100).capacity(), 256);
+        assert_eq!(pool.get(600).capacity(), 1024);
+        let stats = pool.stats();
+        assert_eq!((stats.hits, stats.misses, stats.returned), (2, 2, 4));
+        assert_eq!((stats.idle, stats.idle_bytes), (2, 1280));
+
+        // Full classes and tiny buffers are freed
+        let buffers: Vec<_> = (0..3).map(|_| pool.take(1024)).collect();
+        buffers.into_iter().for_each(|buffer| pool.put(buffer));
+        pool.put(Vec::with_capacity(8));
+        assert_eq!(pool.stats().discarded, 2);
+        assert_eq!(pool.stats().idle, 3);
+    }
+
+    #[test]
+    fn test_buffer_pool_shared_across_threads() {
+        let pool = BufferPool::default();
+        let mut document = Document::new();
+        document.insert("a", "b".repeat(300));
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let (pool, document) = (pool.clone(), document.clone());
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        let bytes = pool.encode(&document).unwrap();
+                        assert_eq!(*bytes, to_bytes(&document).unwrap());
+                    }
+                })
+            })
+            .collect();
+        handles.into_iter().for_each(|handle| handle.join().unwrap());
+        let stats = pool.stats();
+        assert_eq!(stats.hits + stats.misses, 400);
+        assert!(stats.misses <= 4);
+    }
+
+    #[test]
+    fn test_encoder_and_decoder_with_pool() {
+        let pool = BufferPool::default();
+        let mut document = Document::new();
+        document.insert("text", "x".repeat(300));
+        document.insert("n", 1);
+
+        let mut encoder = Encoder::with_pool(&pool);
+        let bytes = encoder.encode(&document).unwrap().to_vec();
+        drop(encoder);
+        assert_eq!(pool.stats().idle, 1);
+
+        let mut decoder = Decoder::new(bytes.as_slice()).with_pool(&pool);
+        assert_eq!(decoder.decode_document().unwrap(), document);
+        assert_eq!(pool.stats().hits, 1);
+    }
+}
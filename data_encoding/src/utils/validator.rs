@@ -0,0 +1,346 @@
+// src/utils/validator.rs
+
+use std::fmt;
+
+use crate::deser::{MAX_DOCUMENT_LEN, MAX_NESTING_DEPTH};
+use crate::utils::scan::{cstring_len, str_from_utf8};
+
+/// The kind of problem found by `validate`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IssueKind {
+    /// A document or value length prefix is negative or too small.
+    InvalidLength(i64),
+    /// A top-level document is larger than `MAX_DOCUMENT_LEN`.
+    DocumentTooLarge(usize),
+    /// A length prefix points past the end of its enclosing document or the input.
+    LengthOverrun { declared: usize, available: usize },
+    /// An element runs past the end of its document.
+    Truncated,
+    /// A document does not end with a null byte.
+    MissingTerminator,
+    /// A null byte appears where an element type was expected, before the end of the document.
+    UnexpectedTerminator,
+    /// A key or regex cstring has no null terminator.
+    UnterminatedCString,
+    /// A length-prefixed string does not end with a null byte.
+    UnterminatedString,
+    /// A key or string is not valid UTF-8.
+    InvalidUtf8,
+    /// An element has a type byte this crate does not know.
+    UnknownElementType(u8),
+    /// A boolean is neither `0x00` nor `0x01`.
+    InvalidBoolean(u8),
+    /// An array key is not the next index.
+    InvalidArrayIndex { expected: usize },
+    /// A code-with-scope length does not match its contents.
+    CodeWithScopeLengthMismatch,
+    /// Documents are nested deeper than `MAX_NESTING_DEPTH`.
+    NestingTooDeep,
+    /// Bytes follow the end of the top-level document.
+    TrailingBytes(usize),
+}
+
+impl fmt::Display for IssueKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IssueKind::InvalidLength(length) => write!(f, "invalid length {}", length),
+            IssueKind::DocumentTooLarge(length) => {
+                write!(
+                    f,
+                    "document length {} exceeds maximum of {}",
+                    length, MAX_DOCUMENT_LEN
+                )
+            }
+            IssueKind::LengthOverrun {
+                declared,
+                available,
+            } => write!(
+                f,
+                "length {} overruns the {} available bytes",
+                declared, available
+            ),
+            IssueKind::Truncated => write!(f, "element is truncated"),
+            IssueKind::MissingTerminator => write!(f, "document is not null-terminated"),
+            IssueKind::UnexpectedTerminator => {
+                write!(f, "terminator before the end of the document")
+            }
+            IssueKind::UnterminatedCString => write!(f, "unterminated cstring"),
+            IssueKind::UnterminatedString => write!(f, "string is missing its null terminator"),
+            IssueKind::InvalidUtf8 => write!(f, "invalid UTF-8"),
+            IssueKind::UnknownElementType(t) => write!(f, "unknown element type {:#04x}", t),
+            IssueKind::InvalidBoolean(b) => write!(f, "invalid boolean byte {:#04x}", b),
+            IssueKind::InvalidArrayIndex { expected } => {
+                write!(f, "array key is not the expected index {}", expected)
+            }
+            IssueKind::CodeWithScopeLengthMismatch => write!(f, "code with scope length mismatch"),
+            IssueKind::NestingTooDeep => write!(f, "maximum nesting depth exceeded"),
+            IssueKind::TrailingBytes(count) => write!(f, "{} trailing bytes after document", count),
+        }
+    }
+}
+
+/// A problem found by `validate`, with the byte offset it was found at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationIssue {
+    pub offset: usize,
+    pub kind: IssueKind,
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "offset {}: {}", self.offset, self.kind)
+    }
+}
+
+/// The result of `validate`: every problem found, in input order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    /// Returns `true` if no problems were found.
+    pub fn is_valid(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// Returns the problems found, in input order.
+    pub fn issues(&self) -> &[ValidationIssue] {
+        &self.issues
+    }
+
+    /// Consumes the report, returning the problems found.
+    pub fn into_issues(self) -> Vec<ValidationIssue> {
+        self.issues
+    }
+}
+
+/// Checks that `bytes` is exactly one well-formed BSON document without
+/// building any values.
+///
+/// Length prefixes, terminators, cstrings, UTF-8 and type bytes are all
+/// checked, and every problem is reported rather than just the first.
+/// When an element is too damaged to find where it ends, the rest of its
+/// document is skipped using the document's length prefix, and checking
+/// carries on after it.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb_data_encoding::{validate, IssueKind};
+/// let bytes = [12, 0, 0, 0, 0x08, b'a', 0, 2, 0, 0, 0, 0];
+/// let report = validate(&bytes);
+/// assert!(!report.is_valid());
+/// assert_eq!(report.issues()[0].offset, 7);
+/// assert_eq!(report.issues()[0].kind, IssueKind::InvalidBoolean(2));
+/// ```
+pub fn validate(bytes: &[u8]) -> ValidationReport {
+    let mut validator = Validator {
+        bytes,
+        issues: Vec::new(),
+    };
+    if let Some(end) = validator.container(0, bytes.len(), 0, false) {
+        if end < bytes.len() {
+            validator.issue(end, IssueKind::TrailingBytes(bytes.len() - end));
+        }
+    }
+    ValidationReport {
+        issues: validator.issues,
+    }
+}
+
+struct Validator<'a> {
+    bytes: &'a [u8],
+    issues: Vec<ValidationIssue>,
+}
+
+impl Validator<'_> {
+    fn issue(&mut self, offset: usize, kind: IssueKind) {
+        self.issues.push(ValidationIssue { offset, kind });
+    }
+
+    /// Reads a length prefix at `offset`, which must lie before `limit`.
+    fn read_i32(&mut self, offset: usize, limit: usize) -> Option<i32> {
+        match self.bytes.get(offset..offset + 4) {
+            Some(prefix) if offset + 4 <= limit => {
+                Some(i32::from_le_bytes(prefix.try_into().unwrap()))
+            }
+            _ => {
+                self.issue(offset, IssueKind::Truncated);
+                None
+            }
+        }
+    }
+
+    /// Validates the document or array at `start`, which must end by `limit`.
+    /// Returns the offset just past it, or `None` if its length is unusable.
+    fn container(
+        &mut self,
+        start: usize,
+        limit: usize,
+        depth: usize,
+        is_array: bool,
+    ) -> Option<usize> {
+        let length = self.read_i32(start, limit)?;
+        if length < 5 {
+            self.issue(start, IssueKind::InvalidLength(length as i64));
+            return None;
+        }
+        let length = length as usize;
+        if depth == 0 && length > MAX_DOCUMENT_LEN {
+            self.issue(start, IssueKind::DocumentTooLarge(length));
+        }
+        let end = match start.checked_add(length) {
+            Some(end) if end <= limit => end,
+            _ => {
+                self.issue(
+                    start,
+                    IssueKind::LengthOverrun {
+                        declared: length,
+                        available: limit - start,
+                    },
+                );
+                return None;
+            }
+        };
+        if depth >= MAX_NESTING_DEPTH {
+            self.issue(start, IssueKind::NestingTooDeep);
+            return Some(end);
+        }
+
+        self.elements(start + 4, end - 1, depth, is_array);
+        if self.bytes[end - 1] != 0 {
+            self.issue(end - 1, IssueKind::MissingTerminator);
+        }
+        Some(end)
+    }
+
+    /// Validates the elements between `position` and the terminator at `end`.
+    fn elements(&mut self, mut position: usize, end: usize, depth: usize, is_array: bool) {
+        let mut index = 0;
+        while position < end {
+            let element_type = self.bytes[position];
+            if element_type == 0 {
+                self.issue(position, IssueKind::UnexpectedTerminator);
+                return;
+            }
+
+            let key_start = position + 1;
+            let Some(key_end) = self.cstring(key_start, end) else {
+                return;
+            };
+            if is_array && self.bytes[key_start..key_end] != *index.to_string().as_bytes() {
+                self.issue(key_start, IssueKind::InvalidArrayIndex { expected: index });
+            }
+            index += 1;
+
+            match self.value(position, element_type, key_end + 1, end, depth) {
+                Some(next) => position = next,
+                None => return,
+            }
+        }
+    }
+
+    /// Validates the value at `start` of the element whose type byte is at
+    /// `offset`. Returns the offset just past the value, or `None` if the
+    /// rest of the document cannot be framed.
+    fn value(
+        &mut self,
+        offset: usize,
+        element_type: u8,
+        start: usize,
+        end: usize,
+        depth: usize,
+    ) -> Option<usize> {
+        let fixed = |len: usize| Some(start + len);
+        let next = match element_type {
+            0x01 | 0x09 | 0x11 | 0x12 | 0x13 => fixed(8),
+            0x07 => fixed(12),
+            0x10 => fixed(4),
+            0x06 | 0x0A | 0x7F | 0xFF => fixed(0),
+            0x08 => {
+                if let Some(byte) = self.bytes.get(start).filter(|_| start < end) {
+                    if *byte > 1 {
+                        self.issue(start, IssueKind::InvalidBoolean(*byte));
+                    }
+                }
+                fixed(1)
+            }
+            0x02 | 0x0D | 0x0E => self.string(start, end),
+            0x03 => self.container(start, end, depth + 1, false),
+            0x04 => self.container(start, end, depth + 1, true),
+            0x05 => {
+                let length = self.read_i32(start, end)?;
+                if length < 0 {
+                    self.issue(start, IssueKind::InvalidLength(length as i64));
+                    return None;
+                }
+                fixed(5 + length as usize)
+            }
+            0x0B => {
+                let pattern_end = self.cstring(start, end)?;
+                Some(self.cstring(pattern_end + 1, end)? + 1)
+            }
+            0x0C => Some(self.string(start, end)? + 12),
+            0x0F => {
+                let length = self.read_i32(start, end)?;
+                let code_end = self.string(start + 4, end)?;
+                let scope_end = self.container(code_end, end, depth + 1, false)?;
+                if length < 0 || scope_end - start != length as usize {
+                    self.issue(start, IssueKind::CodeWithScopeLengthMismatch);
+                }
+                Some(scope_end)
+            }
+            other => {
+                self.issue(offset, IssueKind::UnknownElementType(other));
+                return None;
+            }
+        }?;
+
+        if next > end {
+            self.issue(start, IssueKind::Truncated);
+            return None;
+        }
+        Some(next)
+    }
+
+    /// Validates a length-prefixed string. Returns the offset just past it.
+    fn string(&mut self, start: usize, end: usize) -> Option<usize> {
+        let length = self.read_i32(start, end)?;
+        if length < 1 {
+            self.issue(start, IssueKind::InvalidLength(length as i64));
+            return None;
+        }
+        let content = start + 4;
+        let string_end = content + length as usize;
+        if string_end > end {
+            self.issue(
+                start,
+                IssueKind::LengthOverrun {
+                    declared: length as usize,
+                    available: end - content,
+                },
+            );
+            return None;
+        }
+        if self.bytes[string_end - 1] != 0 {
+            self.issue(string_end - 1, IssueKind::UnterminatedString);
+        }
+        if str_from_utf8(&self.bytes[content..string_end - 1]).is_err() {
+            self.issue(content, IssueKind::InvalidUtf8);
+        }
+        Some(string_end)
+    }
+
+    /// Validates a cstring starting at `start`. Returns the offset of its null terminator.
+    fn cstring(&mut self, start: usize, end: usize) -> Option<usize> {
+        let Some(len) = cstring_len(&self.bytes[start.min(end)..end]) else {
+            self.issue(start, IssueKind::UnterminatedCString);
+            return None;
+        };
+        if str_from_utf8(&self.bytes[start..start + len]).is_err() {
+            self.issue(start, IssueKind::InvalidUtf8);
+        }
+        Some(start + len)
+    }
+}
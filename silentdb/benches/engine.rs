@@ -0,0 +1,182 @@
+// benches/engine.rs
+
+use std::fs;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use silentdb::query::Matcher;
+use silentdb::storage::{encode_key, BTree, Pager};
+use silentdb_data_encoding::{from_json_str, to_bytes, Document, RawDocument, Value};
+
+/// Keys in the B+trees benchmarked.
+const KEYS: i64 = 10_000;
+
+/// Returns a scratch path unique to this process and `name`, with nothing
+/// at it.
+fn scratch_file(name: &str) -> PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("silentdb-bench-{}-{}.db", name, std::process::id()));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+fn user(i: i32) -> Document {
+    from_json_str(&format!(
+        r#"{{"_id": {i}, "name": "user {i}", "age": {age}, "score": {score},
+            "address": {{"city": "{city}", "zip": "0{i}"}},
+            "tags": ["t{t0}", "t{t1}", "t{t2}"],
+            "orders": [{{"item": "pen", "qty": {q0}}}, {{"item": "ink", "qty": {q1}}}]}}"#,
+        i = i,
+        age = 18 + i % 60,
+        score = i as f64 / 3.0,
+        city = ["Oslo", "Paris", "Lima", "Pune"][i as usize % 4],
+        t0 = i % 5,
+        t1 = i % 7,
+        t2 = i % 11,
+        q0 = i % 3,
+        q1 = i % 13,
+    ))
+    .unwrap()
+}
+
+fn matcher(c: &mut Criterion) {
+    let users: Vec<Document> = (0..1000).map(user).collect();
+    let encoded: Vec<Vec<u8>> = users.iter().map(|user| to_bytes(user).unwrap()).collect();
+    let filters = [
+        ("equality", r#"{"address.city": "Lima"}"#),
+        (
+            "range",
+            r#"{"age": {"$gte": 30, "$lt": 40}, "tags": {"$in": ["t1", "t3"]}}"#,
+        ),
+        (
+            "regex",
+            r#"{"name": {"$regex": "^user 9", "$options": "i"}}"#,
+        ),
+        (
+            "elem_match",
+            r#"{"orders": {"$elemMatch": {"item": "ink", "qty": {"$gt": 6}}}}"#,
+        ),
+        (
+            "or",
+            r#"{"$or": [{"score": {"$lt": 10}}, {"address.zip": "0500"}, {"tags": "t4"}]}"#,
+        ),
+    ];
+    for (name, filter) in filters {
+        let matcher = Matcher::new(&from_json_str(filter).unwrap()).unwrap();
+        let mut group = c.benchmark_group(format!("matcher/{}", name));
+        group.throughput(Throughput::Elements(users.len() as u64));
+
+        group.bench_function("matches", |b| {
+            b.iter(|| {
+                users
+                    .iter()
+                    .filter(|user| matcher.matches(black_box(user)))
+                    .count()
+            })
+        });
+        group.bench_function("matches_raw", |b| {
+            b.iter(|| {
+                encoded
+                    .iter()
+                    .filter(|bytes| {
+                        let raw = RawDocument::from_bytes(black_box(bytes)).unwrap();
+                        matcher.matches_raw(raw).unwrap()
+                    })
+                    .count()
+            })
+        });
+        group.finish();
+    }
+}
+
+/// Opens a new tree at `path`, holding `keys` keys with 100-byte values.
+fn tree(path: &Path, keys: i64) -> (Pager, BTree) {
+    let _ = fs::remove_file(path);
+    let mut pager = Pager::open(path).unwrap();
+    let tree = BTree::create(&mut pager).unwrap();
+    for i in 0..keys {
+        tree.insert(&mut pager, &key(i), &[7; 100]).unwrap();
+    }
+    (pager, tree)
+}
+
+fn key(i: i64) -> Vec<u8> {
+    encode_key(&Value::Int64(i))
+}
+
+/// Scatters `0..KEYS` so that successive keys land on different leaves.
+fn scrambled(i: i64) -> i64 {
+    i * 7919 % KEYS
+}
+
+fn btree(c: &mut Criterion) {
+    let path = scratch_file("btree");
+    let mut group = c.benchmark_group("btree");
+
+    group.throughput(Throughput::Elements(KEYS as u64));
+    group.bench_function("insert_sequential", |b| {
+        b.iter_batched(
+            || tree(&path, 0),
+            |(mut pager, tree)| {
+                for i in 0..KEYS {
+                    tree.insert(&mut pager, &key(i), &[7; 100]).unwrap();
+                }
+                pager
+            },
+            BatchSize::PerIteration,
+        )
+    });
+    group.bench_function("insert_random", |b| {
+        b.iter_batched(
+            || tree(&path, 0),
+            |(mut pager, tree)| {
+                for i in 0..KEYS {
+                    tree.insert(&mut pager, &key(scrambled(i)), &[7; 100])
+                        .unwrap();
+                }
+                pager
+            },
+            BatchSize::PerIteration,
+        )
+    });
+
+    let (mut pager, full) = tree(&path, KEYS);
+    group.throughput(Throughput::Elements(1));
+    let mut i = 0;
+    group.bench_function("get", |b| {
+        b.iter(|| {
+            i = (i + 1) % KEYS;
+            full.get(&pager, black_box(&key(scrambled(i)))).unwrap()
+        })
+    });
+    group.throughput(Throughput::Elements(100));
+    group.bench_function("range_100", |b| {
+        b.iter(|| {
+            i = (i + 1) % (KEYS - 100);
+            let (start, end) = (key(i), key(i + 100));
+            full.range(
+                &pager,
+                Bound::Included(&start[..]),
+                Bound::Excluded(&end[..]),
+            )
+            .unwrap()
+            .count()
+        })
+    });
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("remove_and_insert", |b| {
+        b.iter(|| {
+            i = (i + 1) % KEYS;
+            let key = key(scrambled(i));
+            let value = full.remove(&mut pager, &key).unwrap().unwrap();
+            full.insert(&mut pager, &key, &value).unwrap()
+        })
+    });
+    group.finish();
+    drop(pager);
+    let _ = fs::remove_file(&path);
+}
+
+criterion_group!(benches, matcher, btree);
+criterion_main!(benches);
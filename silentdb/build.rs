@@ -0,0 +1,8 @@
+// build.rs
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Generates the gRPC service and messages the grpc module includes
+    #[cfg(feature = "grpc")]
+    tonic_build::compile_protos("proto/silentdb.proto")?;
+    Ok(())
+}
@@ -0,0 +1,265 @@
+// src/accounting/mod.rs
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// What the database was doing when it allocated memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MemoryCategory {
+    /// Decoding documents read from storage.
+    Decode,
+    /// Filling the storage engine's page cache.
+    Cache,
+    /// Buffering documents for a sort.
+    Sort,
+    /// Buffering the batches cursors fetch.
+    Cursor,
+    /// Anything else, including allocations outside the database.
+    Other,
+}
+
+impl MemoryCategory {
+    pub(crate) const ALL: [MemoryCategory; 5] = [
+        MemoryCategory::Decode,
+        MemoryCategory::Cache,
+        MemoryCategory::Sort,
+        MemoryCategory::Cursor,
+        MemoryCategory::Other,
+    ];
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            MemoryCategory::Decode => "decode",
+            MemoryCategory::Cache => "cache",
+            MemoryCategory::Sort => "sort",
+            MemoryCategory::Cursor => "cursor",
+            MemoryCategory::Other => "other",
+        }
+    }
+}
+
+thread_local! {
+    /// The category this thread's allocations are attributed to.
+    static CURRENT: Cell<MemoryCategory> = const { Cell::new(MemoryCategory::Other) };
+}
+
+/// The counters of one category. Zero until a `TrackingAllocator` is the
+/// global allocator.
+struct Counters {
+    allocations: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl Counters {
+    const fn new() -> Self {
+        Counters {
+            allocations: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+        }
+    }
+}
+
+static COUNTERS: [Counters; MemoryCategory::ALL.len()] = [
+    Counters::new(),
+    Counters::new(),
+    Counters::new(),
+    Counters::new(),
+    Counters::new(),
+];
+static LIVE: AtomicU64 = AtomicU64::new(0);
+static PEAK: AtomicU64 = AtomicU64::new(0);
+static TRACKING: AtomicBool = AtomicBool::new(false);
+
+/// Attributes the allocations a thread makes to `category` until dropped,
+/// when the category it replaced applies again.
+///
+/// Scopes nest, so an operation can attribute its own allocations and
+/// still leave those of a step within it, such as filling the page cache,
+/// to that step.
+///
+/// # Examples
+///
+/// ```
+/// use silentdb::{MemoryCategory, MemoryScope};
+///
+/// let _scope = MemoryScope::enter(MemoryCategory::Sort);
+/// // Counted as sorting
+/// let buffer: Vec<u8> = Vec::with_capacity(1 << 10);
+/// ```
+#[derive(Debug)]
+pub struct MemoryScope {
+    previous: MemoryCategory,
+}
+
+impl MemoryScope {
+    /// Attributes the thread's allocations to `category` until the scope
+    /// is dropped.
+    pub fn enter(category: MemoryCategory) -> Self {
+        let previous = CURRENT.with(|current| current.replace(category));
+        MemoryScope { previous }
+    }
+}
+
+impl Drop for MemoryScope {
+    fn drop(&mut self) {
+        CURRENT.with(|current| current.set(self.previous));
+    }
+}
+
+/// Records an allocation of `size` bytes against the thread's category.
+fn allocated(size: usize) {
+    // Threads being torn down have no category left; count them as other
+    let category = CURRENT.try_with(Cell::get).unwrap_or(MemoryCategory::Other);
+    let counters = &COUNTERS[category as usize];
+    TRACKING.store(true, Ordering::Relaxed);
+    counters.allocations.fetch_add(1, Ordering::Relaxed);
+    counters.bytes.fetch_add(size as u64, Ordering::Relaxed);
+    let live = LIVE.fetch_add(size as u64, Ordering::Relaxed) + size as u64;
+    PEAK.fetch_max(live, Ordering::Relaxed);
+}
+
+fn freed(size: usize) {
+    LIVE.fetch_sub(size as u64, Ordering::Relaxed);
+}
+
+/// A global allocator counting what the database allocates, by category,
+/// on top of another allocator.
+///
+/// Accounting is off unless a `TrackingAllocator` is installed as the
+/// global allocator, so programs that do not need it pay nothing. The
+/// allocator it wraps is pluggable: `System`, or any other global
+/// allocator. Each allocation costs a few relaxed atomic additions.
+///
+/// # Examples
+///
+/// ```
+/// use std::alloc::System;
+/// use silentdb::TrackingAllocator;
+///
+/// #[global_allocator]
+/// static ALLOCATOR: TrackingAllocator<System> = TrackingAllocator::new(System);
+///
+/// fn main() {
+///     let stats = silentdb::memory_stats();
+///     assert!(stats.tracking);
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct TrackingAllocator<A = System> {
+    inner: A,
+}
+
+impl<A> TrackingAllocator<A> {
+    /// Creates an allocator counting what `inner` allocates. It is `const`
+    /// so it can initialize the `#[global_allocator]` static.
+    pub const fn new(inner: A) -> Self {
+        TrackingAllocator { inner }
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for TrackingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc(layout);
+        if !ptr.is_null() {
+            allocated(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            allocated(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.dealloc(ptr, layout);
+        freed(layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new = self.inner.realloc(ptr, layout, new_size);
+        if !new.is_null() {
+            // Growth counts as an allocation of the extra bytes
+            match new_size.checked_sub(layout.size()) {
+                Some(grown) => allocated(grown),
+                None => freed(layout.size() - new_size),
+            }
+        }
+        new
+    }
+}
+
+/// The bytes one category has allocated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CategoryStats {
+    /// Allocations and reallocations that grew a block.
+    pub allocations: u64,
+    /// Bytes allocated, counting those since freed.
+    pub bytes: u64,
+}
+
+/// The memory the process has allocated, as returned by `memory_stats`.
+///
+/// Freed memory is not attributed: `live` and `peak` cover every category
+/// together.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    /// Whether a `TrackingAllocator` is the global allocator. Every other
+    /// counter is zero if not.
+    pub tracking: bool,
+    /// Bytes allocated and not yet freed.
+    pub live: u64,
+    /// The most bytes live at once.
+    pub peak: u64,
+    /// Allocations made decoding documents read from storage.
+    pub decode: CategoryStats,
+    /// Allocations made filling the page cache.
+    pub cache: CategoryStats,
+    /// Allocations made buffering documents for sorts.
+    pub sort: CategoryStats,
+    /// Allocations made buffering cursor batches.
+    pub cursor: CategoryStats,
+    /// Every other allocation, including those outside the database.
+    pub other: CategoryStats,
+}
+
+impl MemoryStats {
+    /// Returns the counters of `category`.
+    pub fn category(&self, category: MemoryCategory) -> CategoryStats {
+        match category {
+            MemoryCategory::Decode => self.decode,
+            MemoryCategory::Cache => self.cache,
+            MemoryCategory::Sort => self.sort,
+            MemoryCategory::Cursor => self.cursor,
+            MemoryCategory::Other => self.other,
+        }
+    }
+}
+
+/// Returns the memory the process has allocated through a
+/// `TrackingAllocator`, by category, since it started.
+///
+/// The counters are the process's, shared by every database it opens.
+pub fn memory_stats() -> MemoryStats {
+    let category = |category: MemoryCategory| {
+        let counters = &COUNTERS[category as usize];
+        CategoryStats {
+            allocations: counters.allocations.load(Ordering::Relaxed),
+            bytes: counters.bytes.load(Ordering::Relaxed),
+        }
+    };
+    MemoryStats {
+        tracking: TRACKING.load(Ordering::Relaxed),
+        live: LIVE.load(Ordering::Relaxed),
+        peak: PEAK.load(Ordering::Relaxed),
+        decode: category(MemoryCategory::Decode),
+        cache: category(MemoryCategory::Cache),
+        sort: category(MemoryCategory::Sort),
+        cursor: category(MemoryCategory::Cursor),
+        other: category(MemoryCategory::Other),
+    }
+}
@@ -0,0 +1,429 @@
+// src/db/backup.rs
+
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write as _};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use silentdb_data_encoding::{
+    from_reader, to_writer, Array, Document, DocumentFileIterator, Value,
+};
+
+use super::catalog;
+use super::collection::Collection;
+use super::concern::WriteConcern;
+use super::database::{record_writes, DatabaseInner};
+use super::error::DatabaseError;
+use super::index::IndexInfo;
+use super::replication::{is_local, OpTime};
+use super::transaction::Transaction;
+use crate::storage::{StorageError, WalRecord};
+
+/// Name of the file listing a backup's collections and indexes.
+const MANIFEST: &str = "manifest.bson";
+/// Version of the backup layout written to the manifest.
+const BACKUP_FORMAT: i32 = 1;
+/// Documents inserted per write while restoring.
+const RESTORE_BATCH: usize = 1000;
+/// Version of the streamed backup layout written to its header.
+const STREAM_FORMAT: i32 = 1;
+
+/// What `Database::backup` or `Database::restore` copied.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BackupStats {
+    pub collections: u64,
+    pub documents: u64,
+    pub indexes: u64,
+    /// The last oplog entry the backup includes, or `None` if the database
+    /// kept no oplog when it was written. Secondaries synced from the
+    /// backup carry on after it.
+    pub optime: Option<OpTime>,
+    /// The sequence number of the first log record committed after the
+    /// backup's snapshot, from which `Database::recover` replays archived
+    /// records, or `None` for backups written before it was recorded.
+    pub lsn: Option<u64>,
+}
+
+/// Writes a snapshot of every collection in the database to `dir`.
+///
+/// The snapshot is read through a transaction, so it reflects a single
+/// point in time however long it takes, and writes carry on meanwhile:
+/// the lock is only held while each batch is read. Each collection goes to
+/// its own file of back-to-back BSON documents, and `manifest.bson` lists
+/// the collections, their files and their indexes. The manifest is written
+/// last, so a backup without one is incomplete.
+pub(crate) fn backup(
+    inner: &Arc<Mutex<DatabaseInner>>,
+    dir: &Path,
+) -> Result<BackupStats, DatabaseError> {
+    let (txn, optime, lsn) = {
+        let mut locked = DatabaseInner::lock(inner);
+        let id = locked.transactions.begin();
+        let optime = locked.replication.last();
+        (
+            Transaction::new(id, Arc::clone(inner)),
+            optime,
+            locked.next_lsn(),
+        )
+    };
+    let names = catalog::list(&DatabaseInner::lock(inner))?;
+    fs::create_dir_all(dir).map_err(StorageError::from)?;
+
+    let mut stats = BackupStats {
+        optime,
+        lsn: Some(lsn),
+        ..BackupStats::default()
+    };
+    let mut entries = Vec::new();
+    for (i, name) in names.iter().enumerate() {
+        let collection = txn.collection(name);
+        let file_name = format!("collection-{}.bson", i);
+        let mut file =
+            BufWriter::new(File::create(dir.join(&file_name)).map_err(StorageError::from)?);
+        let mut documents = 0;
+        for document in collection.find(&Document::new())? {
+            to_writer(&mut file, &document?)?;
+            documents += 1;
+        }
+        sync(file)?;
+        let indexes = collection.list_indexes()?;
+
+        let mut entry = Document::new();
+        entry.insert("name", name.as_str());
+        entry.insert("file", file_name.as_str());
+        entry.insert("documents", documents as i64);
+        let indexes_array = indexes
+            .iter()
+            .map(|index| Value::Document(index.to_document()))
+            .collect();
+        entry.insert("indexes", Array::from_vec(indexes_array));
+        entries.push(Value::Document(entry));
+        stats.collections += 1;
+        stats.documents += documents;
+        stats.indexes += indexes.len() as u64;
+    }
+    txn.abort();
+
+    let created = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64);
+    let mut manifest = Document::new();
+    manifest.insert("format", BACKUP_FORMAT);
+    manifest.insert("created", Value::UTCDateTime(created));
+    manifest.insert("collections", Array::from_vec(entries));
+    if let Some(optime) = optime {
+        manifest.insert("optime", optime.to_value());
+    }
+    manifest.insert("lsn", lsn as i64);
+    let partial = dir.join(format!("{}.partial", MANIFEST));
+    let mut file = BufWriter::new(File::create(&partial).map_err(StorageError::from)?);
+    to_writer(&mut file, &manifest)?;
+    sync(file)?;
+    fs::rename(&partial, dir.join(MANIFEST)).map_err(StorageError::from)?;
+    Ok(stats)
+}
+
+/// Loads the backup in `dir` into the database: creates each collection's
+/// indexes, then inserts its documents in batches.
+///
+/// Documents whose `_id` is already taken fail the restore with
+/// `DuplicateKey`, leaving the batches before it in place, so backups are
+/// best restored into an empty database.
+pub(crate) fn restore(
+    inner: &Arc<Mutex<DatabaseInner>>,
+    dir: &Path,
+) -> Result<BackupStats, DatabaseError> {
+    let manifest_path = dir.join(MANIFEST);
+    let bad = |message: &str| StorageError::corrupt(manifest_path.display(), message);
+    let manifest = read_manifest(dir)?;
+    let Some(Value::Array(entries)) = manifest.get("collections") else {
+        return Err(bad("no collection list").into());
+    };
+
+    let mut stats = BackupStats {
+        lsn: manifest_lsn(&manifest).map_err(|_| bad("bad lsn"))?,
+        ..BackupStats::default()
+    };
+    if let Some(optime) = manifest.get("optime") {
+        stats.optime = Some(OpTime::from_value(optime).ok_or_else(|| bad("bad optime"))?);
+    }
+    for entry in entries.iter() {
+        let entry = entry
+            .as_document()
+            .ok_or_else(|| bad("bad collection entry"))?;
+        let (
+            Some(Value::String(name)),
+            Some(Value::String(file_name)),
+            Some(Value::Array(indexes)),
+        ) = (entry.get("name"), entry.get("file"), entry.get("indexes"))
+        else {
+            return Err(bad("bad collection entry").into());
+        };
+        if file_name.contains(['/', '\\']) {
+            return Err(bad("collection file outside the backup").into());
+        }
+        // Collections holding nothing are only restored by their entry
+        catalog::register(&mut DatabaseInner::lock(inner), name)?;
+        let collection = Collection::new(name, Arc::clone(inner), None);
+        for index in indexes.iter() {
+            let index = index
+                .as_document()
+                .and_then(IndexInfo::from_document)
+                .ok_or_else(|| bad("bad index entry"))?;
+            collection.create_index_with(index.to_options())?;
+            stats.indexes += 1;
+        }
+
+        let file = File::open(dir.join(file_name)).map_err(StorageError::from)?;
+        let mut batch = Vec::with_capacity(RESTORE_BATCH);
+        for document in DocumentFileIterator::new(BufReader::new(file)) {
+            batch.push(document?);
+            if batch.len() == RESTORE_BATCH {
+                stats.documents += batch.len() as u64;
+                collection.insert_many(std::mem::take(&mut batch))?;
+            }
+        }
+        stats.documents += batch.len() as u64;
+        if !batch.is_empty() {
+            collection.insert_many(batch)?;
+        }
+        stats.collections += 1;
+    }
+    Ok(stats)
+}
+
+/// Writes a snapshot of every collection in the database to `out` as one
+/// stream of BSON documents, followed by the log records committed while
+/// it was written, and returns what was written.
+///
+/// The stream is a sequence of frames:
+///
+/// ```text
+/// {"format": 1, "lsn": <first record after the snapshot>, "optime": ...}
+/// {"collection": "users", "indexes": [...]}
+/// {"document": {...}}                      one per document of "users"
+/// ...
+/// {"lsn": 42, "writes": [...]}              one per record of the tail
+/// {"end": {"collections": 2, "documents": 10, "indexes": 3, "lsn": 43}}
+/// ```
+///
+/// The snapshot is read through a transaction as `backup` reads it, so
+/// writes carry on while the stream is written, and the log records from
+/// the snapshot on are kept from checkpoints until the tail is written.
+/// Restoring the stream gives the database as of the last record of the
+/// tail. The end frame is written last, so a stream without one is
+/// incomplete.
+pub(crate) fn stream_backup<W: std::io::Write>(
+    inner: &Arc<Mutex<DatabaseInner>>,
+    out: W,
+) -> Result<BackupStats, DatabaseError> {
+    let (txn, optime, lsn) = {
+        let mut locked = DatabaseInner::lock(inner);
+        let id = locked.transactions.begin();
+        let optime = locked.replication.last();
+        let lsn = locked.next_lsn();
+        locked.checkpoints.pin(lsn);
+        (Transaction::new(id, Arc::clone(inner)), optime, lsn)
+    };
+    let result = write_stream(inner, &txn, optime, lsn, out);
+    txn.abort();
+    DatabaseInner::lock(inner).checkpoints.unpin(lsn);
+    result
+}
+
+fn write_stream<W: std::io::Write>(
+    inner: &Arc<Mutex<DatabaseInner>>,
+    txn: &Transaction,
+    optime: Option<OpTime>,
+    lsn: u64,
+    out: W,
+) -> Result<BackupStats, DatabaseError> {
+    let mut out = BufWriter::new(out);
+    let mut header = Document::new();
+    header.insert("format", STREAM_FORMAT);
+    header.insert("lsn", lsn as i64);
+    if let Some(optime) = optime {
+        header.insert("optime", optime.to_value());
+    }
+    to_writer(&mut out, &header)?;
+
+    let mut stats = BackupStats {
+        optime,
+        ..BackupStats::default()
+    };
+    let names = catalog::list(&DatabaseInner::lock(inner))?;
+    for name in names {
+        let collection = txn.collection(&name);
+        let indexes = collection.list_indexes()?;
+        let mut frame = Document::new();
+        frame.insert("collection", name.as_str());
+        let indexes_array = indexes
+            .iter()
+            .map(|index| Value::Document(index.to_document()))
+            .collect();
+        frame.insert("indexes", Array::from_vec(indexes_array));
+        to_writer(&mut out, &frame)?;
+        for document in collection.find(&Document::new())? {
+            let mut frame = Document::new();
+            frame.insert("document", document?);
+            to_writer(&mut out, &frame)?;
+            stats.documents += 1;
+        }
+        stats.collections += 1;
+        stats.indexes += indexes.len() as u64;
+    }
+
+    // The tail runs up to the last commit before it is read
+    let (records, end) = {
+        let locked = DatabaseInner::lock(inner);
+        (locked.log_records(lsn)?, locked.next_lsn())
+    };
+    for record in records {
+        let record = record?;
+        if record.lsn >= end {
+            break;
+        }
+        let mut frame = Document::new();
+        frame.insert("lsn", record.lsn as i64);
+        let writes = record
+            .payload
+            .get("writes")
+            .cloned()
+            .unwrap_or_else(|| Value::Array(Array::new()));
+        frame.insert("writes", writes);
+        to_writer(&mut out, &frame)?;
+    }
+
+    stats.lsn = Some(end);
+    let mut summary = Document::new();
+    summary.insert("collections", stats.collections as i64);
+    summary.insert("documents", stats.documents as i64);
+    summary.insert("indexes", stats.indexes as i64);
+    summary.insert("lsn", end as i64);
+    let mut frame = Document::new();
+    frame.insert("end", summary);
+    to_writer(&mut out, &frame)?;
+    out.flush().map_err(StorageError::from)?;
+    Ok(stats)
+}
+
+/// Loads a backup streamed by `stream_backup` from `input`: creates each
+/// collection's indexes, inserts its documents in batches, then commits
+/// the writes of each record of the tail.
+///
+/// As with `restore`, streams are best restored into an empty database.
+/// A stream cut short fails once its end is reached, leaving what was
+/// loaded before in place.
+pub(crate) fn restore_stream<R: Read>(
+    inner: &Arc<Mutex<DatabaseInner>>,
+    input: R,
+) -> Result<BackupStats, DatabaseError> {
+    let bad = |message: &str| StorageError::corrupt("backup stream", message);
+    let mut frames = DocumentFileIterator::new(BufReader::new(input));
+    let header = frames.next().ok_or_else(|| bad("empty stream"))??;
+    if header.get("format") != Some(&Value::Int32(STREAM_FORMAT)) {
+        return Err(bad("unknown backup format").into());
+    }
+    let mut stats = BackupStats::default();
+    if let Some(optime) = header.get("optime") {
+        stats.optime = Some(OpTime::from_value(optime).ok_or_else(|| bad("bad optime"))?);
+    }
+
+    let mut collection: Option<Collection> = None;
+    let mut batch = Vec::with_capacity(RESTORE_BATCH);
+    for frame in frames {
+        let frame = frame?;
+        if let Some(Value::Document(document)) = frame.get("document") {
+            let Some(collection) = &collection else {
+                return Err(bad("document outside a collection").into());
+            };
+            batch.push(document.clone());
+            stats.documents += 1;
+            if batch.len() == RESTORE_BATCH {
+                collection.insert_many(std::mem::take(&mut batch))?;
+            }
+            continue;
+        }
+        if let Some(collection) = &collection {
+            if !batch.is_empty() {
+                collection.insert_many(std::mem::take(&mut batch))?;
+            }
+        }
+
+        if let Some(Value::String(name)) = frame.get("collection") {
+            let Some(Value::Array(indexes)) = frame.get("indexes") else {
+                return Err(bad("bad collection frame").into());
+            };
+            catalog::register(&mut DatabaseInner::lock(inner), name)?;
+            let created = Collection::new(name, Arc::clone(inner), None);
+            for index in indexes.iter() {
+                let index = index
+                    .as_document()
+                    .and_then(IndexInfo::from_document)
+                    .ok_or_else(|| bad("bad index entry"))?;
+                created.create_index_with(index.to_options())?;
+                stats.indexes += 1;
+            }
+            collection = Some(created);
+            stats.collections += 1;
+        } else if let (Some(Value::Int64(lsn)), Some(Value::Array(_))) =
+            (frame.get("lsn"), frame.get("writes"))
+        {
+            let record = WalRecord {
+                lsn: *lsn as u64,
+                payload: frame.clone(),
+            };
+            let writes = record_writes(&record)?
+                .into_iter()
+                .filter(|write| !is_local(write.target().0))
+                .collect();
+            DatabaseInner::lock(inner).commit(writes, &BTreeSet::new(), WriteConcern::Logged)?;
+        } else if let Some(Value::Document(end)) = frame.get("end") {
+            if let Some(Value::Int64(lsn)) = end.get("lsn") {
+                stats.lsn = Some(*lsn as u64);
+            }
+            return Ok(stats);
+        } else {
+            return Err(bad("unknown frame").into());
+        }
+    }
+    Err(bad("the stream ends before its end frame").into())
+}
+
+/// Returns the sequence number of the first log record committed after
+/// the backup in `dir`, if its manifest records one.
+pub(crate) fn backup_lsn(dir: &Path) -> Result<Option<u64>, DatabaseError> {
+    let manifest = read_manifest(dir)?;
+    manifest_lsn(&manifest)
+        .map_err(|_| StorageError::corrupt(dir.join(MANIFEST).display(), "bad lsn").into())
+}
+
+/// Reads the manifest of the backup in `dir`, checking its format.
+fn read_manifest(dir: &Path) -> Result<Document, DatabaseError> {
+    let manifest_path = dir.join(MANIFEST);
+    let file = File::open(&manifest_path).map_err(StorageError::from)?;
+    let manifest = from_reader(BufReader::new(file))?;
+    if manifest.get("format") != Some(&Value::Int32(BACKUP_FORMAT)) {
+        return Err(StorageError::corrupt(manifest_path.display(), "unknown backup format").into());
+    }
+    Ok(manifest)
+}
+
+fn manifest_lsn(manifest: &Document) -> Result<Option<u64>, ()> {
+    match manifest.get("lsn") {
+        Some(Value::Int64(lsn)) if *lsn > 0 => Ok(Some(*lsn as u64)),
+        Some(_) => Err(()),
+        None => Ok(None),
+    }
+}
+
+fn sync(file: BufWriter<File>) -> Result<(), DatabaseError> {
+    let mut file = file
+        .into_inner()
+        .map_err(|e| StorageError::from(e.into_error()))?;
+    file.flush().map_err(StorageError::from)?;
+    file.sync_all().map_err(StorageError::from)?;
+    Ok(())
+}
@@ -0,0 +1,378 @@
+// src/db/catalog.rs
+
+use std::collections::BTreeSet;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use silentdb_data_encoding::{from_bytes, to_bytes, Document, Value};
+
+use super::collation::{collation_write, load_collation};
+use super::concern::WriteConcern;
+use super::database::{DatabaseInner, Write};
+use super::error::DatabaseError;
+use super::index::{load_indexes, IndexInfo};
+use super::validation::{load_validator, validator_write, Validator};
+use super::version::{is_versioned, versioned_write};
+use crate::query::Collation;
+use crate::storage::KeyRange;
+
+/// Namespace holding a meta-document for each collection, keyed by name.
+pub(crate) const COLLECTIONS_NAMESPACE: &str = "$system.$collections";
+
+/// Options for `Database::create_collection_with`.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb::{CollectionOptions, Validator};
+/// # use silentdb_data_encoding::Document;
+/// let mut rules = Document::new();
+/// rules.insert("name", Document::new());
+/// let options = CollectionOptions::new()
+///     .validator(Validator::new(rules))
+///     .versioned(true);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CollectionOptions {
+    pub(crate) validator: Option<Validator>,
+    pub(crate) collation: Option<Collation>,
+    pub(crate) versioned: bool,
+}
+
+impl CollectionOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the validator documents must pass. See
+    /// `Collection::set_validator`.
+    pub fn validator(mut self, validator: Validator) -> Self {
+        self.validator = Some(validator);
+        self
+    }
+
+    /// Sets the default collation. See `Collection::set_collation`.
+    pub fn collation(mut self, collation: Collation) -> Self {
+        self.collation = Some(collation);
+        self
+    }
+
+    /// Sets whether documents carry versions. See
+    /// `Collection::set_versioned`. Off by default.
+    pub fn versioned(mut self, versioned: bool) -> Self {
+        self.versioned = versioned;
+        self
+    }
+}
+
+/// A collection's entry in the catalog, as returned by
+/// `Database::collection_info`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CollectionInfo {
+    /// The collection's name.
+    pub name: String,
+    /// When the collection was created, in milliseconds since the Unix
+    /// epoch, or `None` if it was created before the catalog tracked it.
+    pub created: Option<i64>,
+    /// The collection's secondary indexes.
+    pub indexes: Vec<IndexInfo>,
+    /// The validator documents must pass, if any.
+    pub validator: Option<Validator>,
+    /// The default collation, if any.
+    pub collation: Option<Collation>,
+    /// Whether documents carry versions.
+    pub versioned: bool,
+}
+
+/// Returns the names of the collections the catalog holds an entry for.
+pub(crate) fn load_registered(inner: &DatabaseInner) -> Result<BTreeSet<String>, DatabaseError> {
+    let entries = inner.scan(None, COLLECTIONS_NAMESPACE, &KeyRange::all(), usize::MAX)?;
+    entries
+        .into_iter()
+        .map(|(key, _)| {
+            String::from_utf8(key)
+                .map_err(|_| DatabaseError::InvalidCollectionName("bad catalog entry".to_string()))
+        })
+        .collect()
+}
+
+/// Returns the collection whose documents or meta-documents `namespace`
+/// holds, or `None` for the catalog and other system namespaces, whose
+/// names start with `$`.
+pub(crate) fn collection_of(namespace: &str) -> Option<&str> {
+    if namespace.starts_with('$') {
+        return None;
+    }
+    namespace.split(".$").next()
+}
+
+/// Returns the writes adding catalog entries for the collections `writes`
+/// put something in that have none, as seen by transaction `txn` if given,
+/// so collections created by their first write are listed too.
+pub(crate) fn registrations(
+    inner: &DatabaseInner,
+    txn: Option<u64>,
+    writes: &[Write],
+) -> Result<Vec<Write>, DatabaseError> {
+    let mut added = BTreeSet::new();
+    let entries: BTreeSet<&[u8]> = writes
+        .iter()
+        .map(Write::target)
+        .filter(|(namespace, _)| *namespace == COLLECTIONS_NAMESPACE)
+        .map(|(_, key)| key)
+        .collect();
+    for write in writes {
+        let Write::Put { namespace, .. } = write else {
+            continue;
+        };
+        let Some(collection) = collection_of(namespace) else {
+            continue;
+        };
+        if inner.collections.contains(collection)
+            || added.contains(collection)
+            || entries.contains(collection.as_bytes())
+        {
+            continue;
+        }
+        if inner
+            .get(txn, COLLECTIONS_NAMESPACE, collection.as_bytes())?
+            .is_none()
+        {
+            added.insert(collection.to_string());
+        }
+    }
+    added.iter().map(|name| entry_write(name, now())).collect()
+}
+
+/// Returns the names of every collection, in order: those in the catalog
+/// and any older ones holding documents or meta-documents.
+pub(crate) fn list(inner: &DatabaseInner) -> Result<Vec<String>, DatabaseError> {
+    let mut names: BTreeSet<String> = inner.collections.clone();
+    for namespace in inner.namespaces()? {
+        if let Some(collection) = collection_of(&namespace) {
+            if !names.contains(collection) && !is_empty(inner, &namespace)? {
+                names.insert(collection.to_string());
+            }
+        }
+    }
+    Ok(names.into_iter().collect())
+}
+
+/// Returns the catalog entry of `name`, or `None` if there is no such
+/// collection.
+pub(crate) fn info(
+    inner: &DatabaseInner,
+    name: &str,
+) -> Result<Option<CollectionInfo>, DatabaseError> {
+    let entry = inner.get(None, COLLECTIONS_NAMESPACE, name.as_bytes())?;
+    if entry.is_none() && namespaces_of(inner, name)?.is_empty() {
+        return Ok(None);
+    }
+    let created = match entry {
+        Some(bytes) => match from_bytes(&bytes)?.get("created") {
+            Some(Value::UTCDateTime(created)) => Some(*created),
+            _ => return Err(bad_entry(name)),
+        },
+        None => None,
+    };
+    Ok(Some(CollectionInfo {
+        name: name.to_string(),
+        created,
+        indexes: load_indexes(inner, None, name)?,
+        validator: load_validator(inner, None, name)?,
+        collation: load_collation(inner, None, name)?,
+        versioned: is_versioned(inner, None, name)?,
+    }))
+}
+
+/// Creates the collection `name` with `options`, in one commit.
+///
+/// # Errors
+///
+/// Returns `InvalidCollectionName` if `name` cannot name a collection, or
+/// `CollectionExists` if there is one already.
+pub(crate) fn create(
+    inner: &mut DatabaseInner,
+    name: &str,
+    options: &CollectionOptions,
+) -> Result<(), DatabaseError> {
+    check_name(name)?;
+    if exists(inner, name)? {
+        return Err(DatabaseError::CollectionExists(name.to_string()));
+    }
+    let mut writes = vec![entry_write(name, now())?];
+    if let Some(validator) = &options.validator {
+        writes.push(validator_write(name, Some(validator))?);
+    }
+    if let Some(collation) = &options.collation {
+        writes.push(collation_write(name, Some(collation))?);
+    }
+    if options.versioned {
+        writes.push(versioned_write(name, true));
+    }
+    inner.write(None, writes, BTreeSet::new(), WriteConcern::default())
+}
+
+/// Adds a catalog entry for `name` if it has none.
+pub(crate) fn register(inner: &mut DatabaseInner, name: &str) -> Result<(), DatabaseError> {
+    if inner.collections.contains(name) {
+        return Ok(());
+    }
+    inner.write(
+        None,
+        vec![entry_write(name, now())?],
+        BTreeSet::new(),
+        WriteConcern::default(),
+    )
+}
+
+/// Removes the collection `name` with its documents, indexes and settings,
+/// in one commit. Returns `false` if there is no such collection.
+pub(crate) fn drop(inner: &mut DatabaseInner, name: &str) -> Result<bool, DatabaseError> {
+    let namespaces = namespaces_of(inner, name)?;
+    let registered = inner
+        .get(None, COLLECTIONS_NAMESPACE, name.as_bytes())?
+        .is_some();
+    if !registered && namespaces.is_empty() {
+        return Ok(false);
+    }
+    let mut writes = Vec::new();
+    for namespace in &namespaces {
+        for (key, _) in inner.scan(None, namespace, &KeyRange::all(), usize::MAX)? {
+            writes.push(Write::Delete {
+                namespace: namespace.clone(),
+                key,
+            });
+        }
+    }
+    writes.push(Write::Delete {
+        namespace: COLLECTIONS_NAMESPACE.to_string(),
+        key: name.as_bytes().to_vec(),
+    });
+    inner.write(None, writes, BTreeSet::new(), WriteConcern::default())?;
+    for namespace in &namespaces {
+        inner.drop_namespace(namespace)?;
+    }
+    inner.collection_stats.remove(name);
+    Ok(true)
+}
+
+/// Moves the collection `from`, with its documents, indexes and settings,
+/// to the name `to`, in one commit.
+///
+/// # Errors
+///
+/// Returns `InvalidCollectionName` if `to` cannot name a collection,
+/// `CollectionNotFound` if there is no collection `from`, or
+/// `CollectionExists` if there is one named `to`.
+pub(crate) fn rename(inner: &mut DatabaseInner, from: &str, to: &str) -> Result<(), DatabaseError> {
+    check_name(to)?;
+    let Some(info) = info(inner, from)? else {
+        return Err(DatabaseError::CollectionNotFound(from.to_string()));
+    };
+    if exists(inner, to)? {
+        return Err(DatabaseError::CollectionExists(to.to_string()));
+    }
+    let namespaces = namespaces_of(inner, from)?;
+    let mut writes = Vec::new();
+    for namespace in &namespaces {
+        let renamed = format!("{}{}", to, &namespace[from.len()..]);
+        for (key, value) in inner.scan(None, namespace, &KeyRange::all(), usize::MAX)? {
+            writes.push(Write::Delete {
+                namespace: namespace.clone(),
+                key: key.clone(),
+            });
+            writes.push(Write::Put {
+                namespace: renamed.clone(),
+                key,
+                value,
+            });
+        }
+    }
+    writes.push(Write::Delete {
+        namespace: COLLECTIONS_NAMESPACE.to_string(),
+        key: from.as_bytes().to_vec(),
+    });
+    writes.push(entry_write(to, info.created.unwrap_or_else(now))?);
+    inner.write(None, writes, BTreeSet::new(), WriteConcern::default())?;
+    for namespace in &namespaces {
+        inner.drop_namespace(namespace)?;
+    }
+    if let Some(stats) = inner.collection_stats.remove(from) {
+        inner.collection_stats.insert(to.to_string(), stats);
+    }
+    Ok(())
+}
+
+/// Applies the catalog writes among `writes`, just committed, to the
+/// in-memory set of registered collections.
+pub(crate) fn apply_writes(collections: &mut BTreeSet<String>, writes: &[Write]) {
+    for write in writes {
+        let (namespace, key) = write.target();
+        if namespace != COLLECTIONS_NAMESPACE {
+            continue;
+        }
+        let name = String::from_utf8_lossy(key).into_owned();
+        match write {
+            Write::Put { .. } => collections.insert(name),
+            Write::Delete { .. } => collections.remove(&name),
+        };
+    }
+}
+
+/// Returns whether there is a collection `name`, registered or not.
+fn exists(inner: &DatabaseInner, name: &str) -> Result<bool, DatabaseError> {
+    Ok(inner.collections.contains(name) || !namespaces_of(inner, name)?.is_empty())
+}
+
+/// Returns the namespaces holding the documents and meta-documents of
+/// `collection`, leaving out empty ones.
+fn namespaces_of(inner: &DatabaseInner, collection: &str) -> Result<Vec<String>, DatabaseError> {
+    let mut namespaces = Vec::new();
+    for namespace in inner.namespaces()? {
+        if collection_of(&namespace) == Some(collection) && !is_empty(inner, &namespace)? {
+            namespaces.push(namespace);
+        }
+    }
+    Ok(namespaces)
+}
+
+/// Returns whether `namespace` has no entries. Engines may keep a namespace
+/// whose entries were all deleted, such as one dropped before a crash and
+/// emptied again by replaying the log.
+fn is_empty(inner: &DatabaseInner, namespace: &str) -> Result<bool, DatabaseError> {
+    Ok(inner.scan(None, namespace, &KeyRange::all(), 1)?.is_empty())
+}
+
+/// Returns an error if `name` cannot name a collection: it must be
+/// non-empty, and neither start with `$` nor contain `.$`, which are kept
+/// for the catalog and meta-documents.
+fn check_name(name: &str) -> Result<(), DatabaseError> {
+    if name.is_empty() || name.starts_with('$') || name.contains(".$") {
+        return Err(DatabaseError::InvalidCollectionName(name.to_string()));
+    }
+    Ok(())
+}
+
+/// Returns the write recording `name` in the catalog as created at
+/// `created`.
+fn entry_write(name: &str, created: i64) -> Result<Write, DatabaseError> {
+    let mut entry = Document::new();
+    entry.insert("name", name);
+    entry.insert("created", Value::UTCDateTime(created));
+    Ok(Write::Put {
+        namespace: COLLECTIONS_NAMESPACE.to_string(),
+        key: name.as_bytes().to_vec(),
+        value: to_bytes(&entry)?,
+    })
+}
+
+fn bad_entry(name: &str) -> DatabaseError {
+    DatabaseError::InvalidCollectionName(format!("bad catalog entry for {}", name))
+}
+
+/// Returns the current time in milliseconds since the Unix epoch.
+pub(crate) fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_millis() as i64)
+}
@@ -0,0 +1,436 @@
+// src/db/change.rs
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use silentdb_data_encoding::{
+    from_bytes, raw_diff, Array, Document, RawChangeKind, RawDocument, Value,
+};
+
+use super::database::DatabaseInner;
+use super::error::DatabaseError;
+use super::path::get_path;
+use crate::query::Matcher;
+use crate::storage::{StorageError, WalRecord};
+
+/// What a change did to a document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChangeKind {
+    Insert,
+    /// Changed by `update_one` or `update_many`.
+    Update,
+    /// Replaced whole by `replace_one`.
+    Replace,
+    Delete,
+}
+
+impl ChangeKind {
+    /// Returns the name of the kind in change event documents, such as
+    /// `insert`.
+    pub fn name(self) -> &'static str {
+        match self {
+            ChangeKind::Insert => "insert",
+            ChangeKind::Update => "update",
+            ChangeKind::Replace => "replace",
+            ChangeKind::Delete => "delete",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<ChangeKind> {
+        match name {
+            "insert" => Some(ChangeKind::Insert),
+            "update" => Some(ChangeKind::Update),
+            "replace" => Some(ChangeKind::Replace),
+            "delete" => Some(ChangeKind::Delete),
+            _ => None,
+        }
+    }
+}
+
+/// The fields an update changed, by dotted path.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct UpdateDescription {
+    /// The new value of each field the update set.
+    pub updated_fields: Document,
+    /// The fields the update removed.
+    pub removed_fields: Vec<String>,
+}
+
+/// The position of a change in the database's history, from which a
+/// change stream can resume.
+///
+/// Tokens order changes as they were committed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ResumeToken {
+    /// The write-ahead log record holding the change.
+    lsn: u64,
+    /// The change's position in the record, or `u32::MAX` for the end of
+    /// the record.
+    index: u32,
+}
+
+impl ResumeToken {
+    /// Returns the token as bytes, for saving alongside whatever the
+    /// changes were applied to.
+    pub fn to_bytes(&self) -> [u8; 12] {
+        let mut bytes = [0; 12];
+        bytes[..8].copy_from_slice(&self.lsn.to_be_bytes());
+        bytes[8..].copy_from_slice(&self.index.to_be_bytes());
+        bytes
+    }
+
+    /// Reads a token written by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Option<ResumeToken> {
+        let bytes: &[u8; 12] = bytes.try_into().ok()?;
+        Some(ResumeToken {
+            lsn: u64::from_be_bytes(bytes[..8].try_into().ok()?),
+            index: u32::from_be_bytes(bytes[8..].try_into().ok()?),
+        })
+    }
+
+    /// Returns the token of the end of record `lsn`.
+    fn end_of(lsn: u64) -> ResumeToken {
+        ResumeToken {
+            lsn,
+            index: u32::MAX,
+        }
+    }
+}
+
+/// A committed change to one document, as reported by a `ChangeStream`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangeEvent {
+    /// Where the stream can resume after this change.
+    pub resume_token: ResumeToken,
+    pub kind: ChangeKind,
+    pub collection: String,
+    /// The document's `_id`.
+    pub document_key: Value,
+    /// The document after the change, except for deletes.
+    pub full_document: Option<Document>,
+    /// The fields changed, for updates.
+    pub update_description: Option<UpdateDescription>,
+}
+
+impl ChangeEvent {
+    /// Returns the event as the document change stream filters are
+    /// matched against:
+    ///
+    /// ```text
+    /// {"operationType": "update", "ns": {"coll": "users"},
+    ///  "documentKey": {"_id": 1}, "fullDocument": {...},
+    ///  "updateDescription": {"updatedFields": {...}, "removedFields": [...]}}
+    /// ```
+    pub fn to_document(&self) -> Document {
+        let mut document = Document::new();
+        document.insert("operationType", self.kind.name());
+        let mut ns = Document::new();
+        ns.insert("coll", self.collection.as_str());
+        document.insert("ns", ns);
+        let mut key = Document::new();
+        key.insert("_id", self.document_key.clone());
+        document.insert("documentKey", key);
+        if let Some(full_document) = &self.full_document {
+            document.insert("fullDocument", full_document.clone());
+        }
+        if let Some(description) = &self.update_description {
+            let mut update = Document::new();
+            update.insert("updatedFields", description.updated_fields.clone());
+            let removed = description
+                .removed_fields
+                .iter()
+                .map(|path| Value::from(path.as_str()))
+                .collect();
+            update.insert("removedFields", Array::from_vec(removed));
+            document.insert("updateDescription", update);
+        }
+        document
+    }
+}
+
+/// The changes committed to a collection, in commit order, as made by
+/// `Collection::watch`.
+///
+/// A stream reads the changes from the write-ahead log, where each commit
+/// records what it did to each document alongside its writes, so a stream
+/// sees every committed change exactly once, survives restarts through its
+/// resume token, and holds no state in the database. Changes made by a
+/// transaction appear when it commits.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use silentdb::Database;
+/// # use silentdb_data_encoding::Document;
+/// let users = Database::open("data").unwrap().collection("users");
+/// let mut inserts = Document::new();
+/// inserts.insert("operationType", "insert");
+/// let mut stream = users.watch(&inserts).unwrap();
+///
+/// while let Some(event) = stream.next_timeout(Duration::from_secs(1)).unwrap() {
+///     println!("{:?} inserted", event.document_key);
+/// }
+/// let token = stream.resume_token().to_bytes();
+/// ```
+#[derive(Debug)]
+pub struct ChangeStream {
+    inner: Arc<Mutex<DatabaseInner>>,
+    collection: String,
+    matcher: Matcher,
+    /// The last change read from the log, matching or not.
+    read: ResumeToken,
+    /// The last change returned.
+    returned: ResumeToken,
+    buffer: VecDeque<ChangeEvent>,
+}
+
+impl ChangeStream {
+    /// Starts a stream of the changes to `collection` matching `matcher`
+    /// committed after `after`.
+    pub(crate) fn new(
+        inner: Arc<Mutex<DatabaseInner>>,
+        collection: &str,
+        matcher: Matcher,
+        after: ResumeToken,
+    ) -> Self {
+        ChangeStream {
+            inner,
+            collection: collection.to_string(),
+            matcher,
+            read: after,
+            returned: after,
+            buffer: VecDeque::new(),
+        }
+    }
+
+    /// Returns the token of the end of the log, after every change
+    /// committed so far.
+    pub(crate) fn end_of_log(inner: &DatabaseInner) -> ResumeToken {
+        ResumeToken::end_of(inner.next_lsn() - 1)
+    }
+
+    /// Returns the next matching change, or `None` if there is none yet.
+    ///
+    /// # Errors
+    ///
+    /// Returns `HistoryLost` if a checkpoint removed changes the stream
+    /// had not read yet, or an error if the log cannot be read.
+    pub fn try_next(&mut self) -> Result<Option<ChangeEvent>, DatabaseError> {
+        let inner = Arc::clone(&self.inner);
+        let inner = DatabaseInner::lock(&inner);
+        self.next_from(&inner)
+    }
+
+    /// Returns the next matching change, waiting up to `timeout` for one
+    /// to be committed.
+    ///
+    /// # Errors
+    ///
+    /// Returns `HistoryLost` if a checkpoint removed changes the stream
+    /// had not read yet, or an error if the log cannot be read.
+    pub fn next_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<Option<ChangeEvent>, DatabaseError> {
+        let deadline = Instant::now() + timeout;
+        let shared = Arc::clone(&self.inner);
+        let mut inner = DatabaseInner::lock(&shared);
+        loop {
+            if let Some(event) = self.next_from(&inner)? {
+                return Ok(Some(event));
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            let committed = Arc::clone(&inner.committed);
+            inner = committed
+                .wait_timeout(inner, deadline - now)
+                .expect("database lock poisoned")
+                .0;
+        }
+    }
+
+    /// Returns the token to resume the stream from with
+    /// `Collection::watch_after`, skipping every change returned so far.
+    pub fn resume_token(&self) -> ResumeToken {
+        match self.buffer.is_empty() {
+            true => self.read,
+            false => self.returned,
+        }
+    }
+
+    fn next_from(&mut self, inner: &DatabaseInner) -> Result<Option<ChangeEvent>, DatabaseError> {
+        if self.buffer.is_empty() {
+            self.fill(inner)?;
+        }
+        let event = self.buffer.pop_front();
+        if let Some(event) = &event {
+            self.returned = event.resume_token;
+        }
+        Ok(event)
+    }
+
+    /// Reads the matching changes committed since the last read.
+    fn fill(&mut self, inner: &DatabaseInner) -> Result<(), DatabaseError> {
+        let from = match self.read.index {
+            u32::MAX => self.read.lsn + 1,
+            _ => self.read.lsn,
+        };
+        if from >= inner.next_lsn() {
+            return Ok(());
+        }
+        for record in inner.log_records(from)? {
+            let record = record?;
+            for event in events(&record)? {
+                if event.resume_token <= self.read || event.collection != self.collection {
+                    continue;
+                }
+                if self.matcher.matches(&event.to_document()) {
+                    self.buffer.push_back(event);
+                }
+            }
+            self.read = ResumeToken::end_of(record.lsn);
+        }
+        Ok(())
+    }
+}
+
+/// Returns whether `namespace` holds a collection's documents, rather than
+/// index entries or the index catalog.
+pub(crate) fn is_collection(namespace: &str) -> bool {
+    !namespace.contains(".$")
+}
+
+/// Describes the change a commit makes to the document with key `key` in
+/// `collection`, from its encoding `before` to `after`, where `None` means
+/// no document, for the `changes` of the commit's log record.
+pub(crate) fn describe(
+    collection: &str,
+    key: &[u8],
+    before: Option<&[u8]>,
+    after: Option<&[u8]>,
+    replaced: bool,
+) -> Result<Document, DatabaseError> {
+    let mut change = Document::new();
+    let (kind, id) = match (before, after) {
+        (_, None) => (ChangeKind::Delete, before),
+        (None, Some(_)) => (ChangeKind::Insert, after),
+        (Some(_), Some(_)) if replaced => (ChangeKind::Replace, after),
+        (Some(_), Some(_)) => (ChangeKind::Update, after),
+    };
+    change.insert("op", kind.name());
+    change.insert("ns", collection);
+    change.insert("key", Value::Binary(key.to_vec()));
+    let id = match id {
+        Some(bytes) => RawDocument::from_bytes(bytes)?
+            .get("_id")?
+            .map(|element| element.value())
+            .transpose()?,
+        None => None,
+    };
+    change.insert("_id", id.unwrap_or(Value::Null));
+    if let (ChangeKind::Update, Some(before), Some(after)) = (kind, before, after) {
+        let document: Document = from_bytes(after)?;
+        let mut updated = Document::new();
+        let mut removed = Vec::new();
+        for diff in raw_diff(before, after)? {
+            match diff.kind {
+                RawChangeKind::Removed => removed.push(Value::from(diff.path.as_str())),
+                _ => {
+                    let value = get_path(&document, &diff.path).cloned();
+                    updated.insert(diff.path.as_str(), value.unwrap_or(Value::Null));
+                }
+            }
+        }
+        change.insert("updated", updated);
+        change.insert("removed", Array::from_vec(removed));
+    }
+    Ok(change)
+}
+
+/// Returns the change events recorded in `record`.
+fn events(record: &WalRecord) -> Result<Vec<ChangeEvent>, DatabaseError> {
+    let Some(Value::Array(changes)) = record.payload.get("changes") else {
+        return Ok(Vec::new());
+    };
+    let bad = || {
+        StorageError::corrupt(
+            format!("write-ahead log record {}", record.lsn),
+            "bad change record",
+        )
+    };
+    let mut events = Vec::with_capacity(changes.len());
+    for (index, change) in changes.iter().enumerate() {
+        let change = change.as_document().ok_or_else(bad)?;
+        let (
+            Some(Value::String(op)),
+            Some(Value::String(collection)),
+            Some(Value::Binary(key)),
+            Some(id),
+        ) = (
+            change.get("op"),
+            change.get("ns"),
+            change.get("key"),
+            change.get("_id"),
+        )
+        else {
+            return Err(bad().into());
+        };
+        let kind = ChangeKind::from_name(op).ok_or_else(bad)?;
+        let full_document = match kind {
+            ChangeKind::Delete => None,
+            _ => Some(from_bytes(
+                written(record, collection, key).ok_or_else(bad)?,
+            )?),
+        };
+        let update_description = match (change.get("updated"), change.get("removed")) {
+            (Some(Value::Document(updated)), Some(Value::Array(removed))) => {
+                Some(UpdateDescription {
+                    updated_fields: updated.clone(),
+                    removed_fields: removed
+                        .iter()
+                        .map(|path| match path {
+                            Value::String(path) => Some(path.clone()),
+                            _ => None,
+                        })
+                        .collect::<Option<_>>()
+                        .ok_or_else(bad)?,
+                })
+            }
+            _ => None,
+        };
+        events.push(ChangeEvent {
+            resume_token: ResumeToken {
+                lsn: record.lsn,
+                index: index as u32,
+            },
+            kind,
+            collection: collection.clone(),
+            document_key: id.clone(),
+            full_document,
+            update_description,
+        });
+    }
+    Ok(events)
+}
+
+/// Returns the value `record` writes to `key` in `namespace`.
+fn written<'a>(record: &'a WalRecord, namespace: &str, key: &[u8]) -> Option<&'a [u8]> {
+    let Some(Value::Array(writes)) = record.payload.get("writes") else {
+        return None;
+    };
+    writes.iter().find_map(|write| {
+        let write = write.as_document()?;
+        match (write.get("put"), write.get("key"), write.get("value")) {
+            (
+                Some(Value::String(put)),
+                Some(Value::Binary(put_key)),
+                Some(Value::Binary(value)),
+            ) if put == namespace && put_key == key => Some(value.as_slice()),
+            _ => None,
+        }
+    })
+}
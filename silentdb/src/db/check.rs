@@ -0,0 +1,356 @@
+// src/db/check.rs
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt;
+
+use silentdb_data_encoding::validate;
+
+use super::catalog;
+use super::concern::WriteConcern;
+use super::database::{DatabaseInner, Write};
+use super::error::DatabaseError;
+use super::index::{index_writes, load_indexes, CATALOG_SUFFIX};
+use crate::storage::{KeyRange, StorageIssue};
+
+/// Entries read from the engine at a time.
+const SCAN_BATCH: usize = 256;
+/// The most rounds of repairs `repair` makes, since fixing one issue may
+/// only bring the next to light, such as the index entries of a damaged
+/// document once it is gone.
+const MAX_REPAIR_ROUNDS: usize = 4;
+
+/// How much `Database::check` looks at, each level checking everything the
+/// ones before it do.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CheckLevel {
+    /// The storage engine's files: page and record checksums, the trees
+    /// linking pages together, and the list of free pages.
+    Storage,
+    /// Also the encoding of every document, with the byte-level validator.
+    Documents,
+    /// Also that every index holds exactly the entries its collection's
+    /// documents call for.
+    #[default]
+    Full,
+}
+
+/// A problem found by `Database::check`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckIssue {
+    /// Damage found in the storage engine's files.
+    Storage(StorageIssue),
+    /// The document under `key` in `collection` is not validly encoded.
+    InvalidDocument {
+        collection: String,
+        key: Vec<u8>,
+        message: String,
+    },
+    /// An entry of index `index` of `collection` that no document calls
+    /// for.
+    DanglingEntry {
+        collection: String,
+        index: String,
+        key: Vec<u8>,
+    },
+    /// An entry of index `index` of `collection` that a document calls for
+    /// and is missing, or held with the wrong value.
+    MissingEntry {
+        collection: String,
+        index: String,
+        key: Vec<u8>,
+    },
+}
+
+impl fmt::Display for CheckIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckIssue::Storage(issue) => write!(f, "{}", issue),
+            CheckIssue::InvalidDocument {
+                collection,
+                key,
+                message,
+            } => write!(
+                f,
+                "{} document {}: {}",
+                collection,
+                hex::encode(key),
+                message
+            ),
+            CheckIssue::DanglingEntry {
+                collection,
+                index,
+                key,
+            } => write!(
+                f,
+                "{} index {} entry {} has no document",
+                collection,
+                index,
+                hex::encode(key)
+            ),
+            CheckIssue::MissingEntry {
+                collection,
+                index,
+                key,
+            } => write!(
+                f,
+                "{} index {} entry {} is missing",
+                collection,
+                index,
+                hex::encode(key)
+            ),
+        }
+    }
+}
+
+/// A change that fixes issues found by `Database::check`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Repair {
+    /// Rebuild what the storage engine can rebuild from the rest of its
+    /// files, such as its list of free pages. See `StorageEngine::repair`.
+    RebuildStorage,
+    /// Drop the damaged record or invalid document under `key` in
+    /// `namespace`. Its index entries are left to a later round.
+    DropRecord { namespace: String, key: Vec<u8> },
+    /// Delete an index entry no document calls for.
+    DeleteEntry { namespace: String, key: Vec<u8> },
+    /// Put an index entry a document calls for.
+    PutEntry {
+        namespace: String,
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+}
+
+/// What `Database::check` or `Database::repair` found.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CheckReport {
+    /// The level checked at.
+    pub level: CheckLevel,
+    /// Documents checked.
+    pub documents: u64,
+    /// Index entries checked.
+    pub index_entries: u64,
+    /// The problems found, storage issues first.
+    pub issues: Vec<CheckIssue>,
+    /// The repairs that would fix the issues found. Issues no repair fixes,
+    /// such as a damaged tree, need a backup restored.
+    pub plan: Vec<Repair>,
+    /// The repairs made before the issues above were found, by
+    /// `Database::repair`.
+    pub repaired: Vec<Repair>,
+}
+
+impl CheckReport {
+    /// Returns `true` if no problems were found.
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// Checks `inner` at `level` and plans the repairs for what it finds.
+///
+/// The documents and indexes of a collection whose namespaces the storage
+/// engine found damaged are not checked, since they cannot be read; the
+/// repairs for the damage bring them back within reach.
+pub(crate) fn check(
+    inner: &mut DatabaseInner,
+    level: CheckLevel,
+) -> Result<CheckReport, DatabaseError> {
+    let mut report = CheckReport {
+        level,
+        ..CheckReport::default()
+    };
+    let mut damaged = HashSet::new();
+    let mut rebuild = false;
+    for issue in inner.verify_storage()? {
+        match &issue {
+            StorageIssue::BadTree {
+                namespace: Some(namespace),
+                ..
+            } => {
+                damaged.insert(namespace.clone());
+            }
+            StorageIssue::BadRecord { namespace, key, .. } => {
+                damaged.insert(namespace.clone());
+                report.plan.push(Repair::DropRecord {
+                    namespace: namespace.clone(),
+                    key: key.clone(),
+                });
+            }
+            issue if issue.is_repairable() => rebuild = true,
+            _ => {}
+        }
+        report.issues.push(CheckIssue::Storage(issue));
+    }
+    if rebuild {
+        report.plan.insert(0, Repair::RebuildStorage);
+    }
+    if level == CheckLevel::Storage {
+        return Ok(report);
+    }
+
+    for collection in catalog::list(inner)? {
+        if damaged.contains(&collection) {
+            continue;
+        }
+        let full = level == CheckLevel::Full
+            && !damaged.contains(&format!("{}{}", collection, CATALOG_SUFFIX));
+        let mut indexes = match full {
+            true => load_indexes(inner, None, &collection)?,
+            false => Vec::new(),
+        };
+        indexes.retain(|index| !damaged.contains(&index.namespace(&collection)));
+        let mut expected: BTreeMap<String, BTreeMap<Vec<u8>, Vec<u8>>> = indexes
+            .iter()
+            .map(|index| (index.namespace(&collection), BTreeMap::new()))
+            .collect();
+
+        let mut range = KeyRange::all();
+        loop {
+            let batch = inner.scan(None, &collection, &range, SCAN_BATCH)?;
+            let Some((last, _)) = batch.last() else {
+                break;
+            };
+            range = range.after(last);
+            for (key, bytes) in batch {
+                report.documents += 1;
+                let validation = validate(&bytes);
+                let writes = match validation.is_valid() {
+                    true => index_writes(&collection, &mut indexes, &key, None, Some(&bytes))
+                        .map_err(|e| e.to_string()),
+                    false => Err(validation
+                        .issues()
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join("; ")),
+                };
+                let writes = match writes {
+                    Ok(writes) => writes,
+                    Err(message) => {
+                        report.plan.push(Repair::DropRecord {
+                            namespace: collection.clone(),
+                            key: key.clone(),
+                        });
+                        report.issues.push(CheckIssue::InvalidDocument {
+                            collection: collection.clone(),
+                            key,
+                            message,
+                        });
+                        continue;
+                    }
+                };
+                // Writes to the catalog, clearing a covering flag, are not
+                // entries
+                for write in writes {
+                    if let Write::Put {
+                        namespace,
+                        key,
+                        value,
+                    } = write
+                    {
+                        if let Some(entries) = expected.get_mut(&namespace) {
+                            entries.insert(key, value);
+                        }
+                    }
+                }
+            }
+        }
+
+        for index in &indexes {
+            let namespace = index.namespace(&collection);
+            let mut expected = expected.remove(&namespace).unwrap_or_default();
+            let mut range = KeyRange::all();
+            loop {
+                let batch = inner.scan(None, &namespace, &range, SCAN_BATCH)?;
+                let Some((last, _)) = batch.last() else {
+                    break;
+                };
+                range = range.after(last);
+                for (key, value) in batch {
+                    report.index_entries += 1;
+                    match expected.remove(&key) {
+                        Some(called_for) if called_for == value => {}
+                        Some(called_for) => {
+                            expected.insert(key, called_for);
+                        }
+                        None => {
+                            report.plan.push(Repair::DeleteEntry {
+                                namespace: namespace.clone(),
+                                key: key.clone(),
+                            });
+                            report.issues.push(CheckIssue::DanglingEntry {
+                                collection: collection.clone(),
+                                index: index.name.clone(),
+                                key,
+                            });
+                        }
+                    }
+                }
+            }
+            for (key, value) in expected {
+                report.issues.push(CheckIssue::MissingEntry {
+                    collection: collection.clone(),
+                    index: index.name.clone(),
+                    key: key.clone(),
+                });
+                report.plan.push(Repair::PutEntry {
+                    namespace: namespace.clone(),
+                    key,
+                    value,
+                });
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Checks `inner` at `level` and makes the repairs planned, round after
+/// round until a check plans none, then checkpoints, and returns the last
+/// check's report along with every repair made.
+pub(crate) fn repair(
+    inner: &mut DatabaseInner,
+    level: CheckLevel,
+) -> Result<CheckReport, DatabaseError> {
+    let mut repaired = Vec::new();
+    let mut report = check(inner, level)?;
+    for _ in 0..MAX_REPAIR_ROUNDS {
+        if report.plan.is_empty() {
+            break;
+        }
+        apply(inner, &report.plan)?;
+        repaired.append(&mut report.plan);
+        report = check(inner, level)?;
+    }
+    if !repaired.is_empty() {
+        inner.checkpoint()?;
+    }
+    report.repaired = repaired;
+    Ok(report)
+}
+
+/// Makes the repairs in `plan`: the records first, straight in the engine,
+/// then the index entries, through the log like any other write.
+fn apply(inner: &mut DatabaseInner, plan: &[Repair]) -> Result<(), DatabaseError> {
+    let mut records = Vec::new();
+    let mut writes = Vec::new();
+    let mut rebuild = false;
+    for repair in plan {
+        match repair.clone() {
+            Repair::RebuildStorage => rebuild = true,
+            Repair::DropRecord { namespace, key } => records.push((namespace, key)),
+            Repair::DeleteEntry { namespace, key } => writes.push(Write::Delete { namespace, key }),
+            Repair::PutEntry {
+                namespace,
+                key,
+                value,
+            } => writes.push(Write::Put {
+                namespace,
+                key,
+                value,
+            }),
+        }
+    }
+    inner.repair_storage(&records, rebuild)?;
+    inner.write(None, writes, BTreeSet::new(), WriteConcern::default())
+}
@@ -0,0 +1,80 @@
+// src/db/checkpoint.rs
+
+use std::collections::BTreeMap;
+
+/// How large the write-ahead log may grow before a commit checkpoints it,
+/// unless told otherwise.
+const DEFAULT_CHECKPOINT_WAL_SIZE: u64 = 64 * 1024 * 1024;
+
+/// What checkpointing has done since the database was opened, as returned
+/// by `Database::checkpoint_stats`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CheckpointStats {
+    /// The number of checkpoints taken.
+    pub checkpoints: u64,
+    /// The sequence number of the last log record the latest checkpoint
+    /// covers, or 0 if none has been taken.
+    pub lsn: u64,
+    /// Bytes of write-ahead log removed by checkpoints.
+    pub reclaimed: u64,
+}
+
+/// The checkpoint settings and counters, kept with the database they
+/// serve. Periodic checkpoints are a task of the database's scheduler.
+#[derive(Debug)]
+pub(crate) struct CheckpointState {
+    wal_size: u64,
+    stats: CheckpointStats,
+    /// How many readers need the log records from each sequence number on,
+    /// which checkpoints must keep.
+    pins: BTreeMap<u64, usize>,
+}
+
+impl CheckpointState {
+    pub(crate) fn new() -> Self {
+        CheckpointState {
+            wal_size: DEFAULT_CHECKPOINT_WAL_SIZE,
+            stats: CheckpointStats::default(),
+            pins: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn wal_size(&self) -> u64 {
+        self.wal_size
+    }
+
+    pub(crate) fn set_wal_size(&mut self, bytes: u64) {
+        self.wal_size = bytes;
+    }
+
+    pub(crate) fn stats(&self) -> CheckpointStats {
+        self.stats.clone()
+    }
+
+    /// Keeps checkpoints from removing the log records from `lsn` on until
+    /// a matching `unpin`.
+    pub(crate) fn pin(&mut self, lsn: u64) {
+        *self.pins.entry(lsn).or_default() += 1;
+    }
+
+    pub(crate) fn unpin(&mut self, lsn: u64) {
+        if let Some(count) = self.pins.get_mut(&lsn) {
+            *count -= 1;
+            if *count == 0 {
+                self.pins.remove(&lsn);
+            }
+        }
+    }
+
+    /// Returns the earliest pinned sequence number, if any.
+    pub(crate) fn pinned(&self) -> Option<u64> {
+        self.pins.keys().next().copied()
+    }
+
+    /// Counts a checkpoint at `lsn` that removed `reclaimed` bytes of log.
+    pub(crate) fn record(&mut self, lsn: u64, reclaimed: u64) {
+        self.stats.checkpoints += 1;
+        self.stats.lsn = lsn;
+        self.stats.reclaimed += reclaimed;
+    }
+}
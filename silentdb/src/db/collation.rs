@@ -0,0 +1,50 @@
+// src/db/collation.rs
+
+use silentdb_data_encoding::{from_bytes, to_bytes};
+
+use super::database::{DatabaseInner, Write};
+use super::error::DatabaseError;
+use crate::query::Collation;
+
+/// Suffix of the namespace holding a collection's default collation.
+pub(crate) const COLLATION_SUFFIX: &str = ".$collation";
+/// Key of the collation in its namespace.
+const COLLATION_KEY: &[u8] = b"collation";
+
+/// Returns the default collation of `collection`, as seen by transaction
+/// `txn` if given.
+pub(crate) fn load_collation(
+    inner: &DatabaseInner,
+    txn: Option<u64>,
+    collection: &str,
+) -> Result<Option<Collation>, DatabaseError> {
+    let Some(bytes) = inner.get(txn, &collation_namespace(collection), COLLATION_KEY)? else {
+        return Ok(None);
+    };
+    let collation = Collation::from_document(&from_bytes(&bytes)?).ok_or_else(|| {
+        DatabaseError::InvalidCollation(format!("bad collation for {}", collection))
+    })?;
+    Ok(Some(collation))
+}
+
+/// Returns the write setting the default collation of `collection`, or
+/// removing it if `collation` is `None`.
+pub(crate) fn collation_write(
+    collection: &str,
+    collation: Option<&Collation>,
+) -> Result<Write, DatabaseError> {
+    let namespace = collation_namespace(collection);
+    let key = COLLATION_KEY.to_vec();
+    Ok(match collation {
+        Some(collation) => Write::Put {
+            namespace,
+            key,
+            value: to_bytes(&collation.to_document())?,
+        },
+        None => Write::Delete { namespace, key },
+    })
+}
+
+fn collation_namespace(collection: &str) -> String {
+    format!("{}{}", collection, COLLATION_SUFFIX)
+}
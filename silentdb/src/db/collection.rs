@@ -0,0 +1,1748 @@
+// src/db/collection.rs
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use silentdb_data_encoding::{from_bytes, to_bytes, Array, Document, FromValue, IntoValue, Value};
+
+use super::change::{ChangeStream, ResumeToken};
+use super::collation::{collation_write, load_collation};
+use super::concern::{ReadConcern, WriteConcern};
+use super::cursor::{Cursor, CursorState};
+use super::database::{DatabaseInner, Write};
+use super::error::DatabaseError;
+use super::id::generate_object_id;
+use super::index::{catalog_write, index_writes, load_indexes, IndexInfo, IndexOptions};
+use super::lock::{LockMode, LockResource};
+use super::metrics::{Op, OpTimer};
+#[cfg(feature = "parquet")]
+use super::parquet_export::{self, ParquetOptions, ParquetStats};
+use super::path::distinct_values;
+use super::planner::{
+    choose, estimate, plan_geo, plan_text, split_text, Candidate, QueryScan, Stored,
+};
+use super::projection::Projection;
+use super::schema::{
+    rollback_namespace, ProgressHook, SchemaMigrationOptions, SchemaMigrationStats,
+};
+use super::sort::{ExternalSort, SortSpec, DEFAULT_SORT_MEMORY};
+use super::stats::{gather, CollectionStats};
+use super::transaction::lock;
+use super::transfer::{self, Format, ImportOptions, ImportStats};
+use super::typed::TypedCollection;
+use super::update::{apply_update, upsert_base};
+use super::validation::{load_rules, load_validator, validator_write, Validator};
+use super::version::{
+    is_versioned, keep_version, stamp, version_of, versioned_write, VERSION_FIELD,
+};
+use crate::query::{Collation, Matcher};
+use crate::storage::{encode_key, KeyRange};
+
+/// Documents read per scan while building an index.
+const SCAN_BATCH: usize = 256;
+
+/// The result of `Collection::insert_one`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InsertOneResult {
+    /// The `_id` of the inserted document.
+    pub inserted_id: Value,
+}
+
+/// The result of `Collection::insert_many`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InsertManyResult {
+    /// The `_id`s of the inserted documents, in insertion order.
+    pub inserted_ids: Vec<Value>,
+}
+
+/// The result of `Collection::update_one`, `Collection::update_many` and
+/// `Collection::replace_one`, and of their `_with` variants.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpdateResult {
+    /// The number of documents that matched the filter.
+    pub matched_count: u64,
+    /// The number of matched documents the update actually changed.
+    pub modified_count: u64,
+    /// The `_id` of the document inserted by an upsert, if any.
+    pub upserted_id: Option<Value>,
+}
+
+/// The result of `Collection::delete_one` and `Collection::delete_many`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeleteResult {
+    /// The number of documents deleted.
+    pub deleted_count: u64,
+}
+
+/// Options for `Collection::update_one_with`, `Collection::update_many_with`
+/// and `Collection::replace_one_with`.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb::UpdateOptions;
+/// let options = UpdateOptions::new().upsert(true);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UpdateOptions {
+    pub(crate) upsert: bool,
+}
+
+impl UpdateOptions {
+    /// Creates options that leave a filter matching nothing alone.
+    pub fn new() -> Self {
+        UpdateOptions::default()
+    }
+
+    /// Sets whether a filter matching no documents inserts one instead.
+    pub fn upsert(mut self, upsert: bool) -> Self {
+        self.upsert = upsert;
+        self
+    }
+}
+
+/// Options for `Collection::find_with` and `Collection::explain_with`.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb::FindOptions;
+/// # use silentdb_data_encoding::Document;
+/// let mut projection = Document::new();
+/// projection.insert("email", 1);
+/// projection.insert("_id", 0);
+/// let options = FindOptions::new().projection(projection);
+///
+/// let mut sort = Document::new();
+/// sort.insert("age", -1);
+/// let options = FindOptions::new().sort(sort).sort_memory(1024 * 1024);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FindOptions {
+    pub(crate) projection: Option<Document>,
+    pub(crate) sort: Option<Document>,
+    pub(crate) sort_memory: Option<usize>,
+    pub(crate) max_time: Option<Duration>,
+}
+
+impl FindOptions {
+    /// Creates options returning whole documents.
+    pub fn new() -> Self {
+        FindOptions::default()
+    }
+
+    /// Returns only the fields `projection` includes of each document,
+    /// such as `{"email": 1, "_id": 0}`: each path set to `1` or `true`,
+    /// and `_id` unless set to `0` or `false`.
+    pub fn projection(mut self, projection: Document) -> Self {
+        self.projection = Some(projection);
+        self
+    }
+
+    /// Returns documents in the order `sort` asks for, such as
+    /// `{"age": -1, "name": 1}`: by each path in turn, `1` for ascending
+    /// and `-1` for descending, comparing values as indexes do. Documents
+    /// sorting equal come in `_id` order.
+    ///
+    /// Every match is read and sorted before the first is returned, with
+    /// those past the memory budget spilled to temporary files.
+    pub fn sort(mut self, sort: Document) -> Self {
+        self.sort = Some(sort);
+        self
+    }
+
+    /// Sets how many bytes of documents a sort may hold in memory before
+    /// spilling sorted runs of them to disk. The default is 32 MiB.
+    pub fn sort_memory(mut self, bytes: usize) -> Self {
+        self.sort_memory = Some(bytes);
+        self
+    }
+
+    /// Fails the cursor with `MaxTimeExpired` once fetching its documents
+    /// has taken longer than `max_time` in all. Time the cursor sits idle
+    /// between batches is not counted.
+    pub fn max_time(mut self, max_time: Duration) -> Self {
+        self.max_time = Some(max_time);
+        self
+    }
+}
+
+/// Options for `Collection::count_with`.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb::CountOptions;
+/// # use std::time::Duration;
+/// let options = CountOptions::new().max_time(Duration::from_secs(5));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CountOptions {
+    pub(crate) max_time: Option<Duration>,
+}
+
+impl CountOptions {
+    /// Creates options counting for as long as it takes.
+    pub fn new() -> Self {
+        CountOptions::default()
+    }
+
+    /// Fails the count with `MaxTimeExpired` once it has taken longer than
+    /// `max_time`.
+    pub fn max_time(mut self, max_time: Duration) -> Self {
+        self.max_time = Some(max_time);
+        self
+    }
+}
+
+/// Which document `Collection::find_one_and_update` and
+/// `Collection::find_one_and_replace` return.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReturnDocument {
+    /// The document as it was before the change.
+    #[default]
+    Before,
+    /// The document as the change left it.
+    After,
+}
+
+/// Options for `Collection::find_one_and_update` and
+/// `Collection::find_one_and_replace`.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb::{FindOneAndModifyOptions, ReturnDocument};
+/// let options = FindOneAndModifyOptions::new()
+///     .upsert(true)
+///     .return_document(ReturnDocument::After);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FindOneAndModifyOptions {
+    pub(crate) upsert: bool,
+    pub(crate) return_document: ReturnDocument,
+}
+
+impl FindOneAndModifyOptions {
+    /// Creates options that return the document from before the change
+    /// and leave a filter matching nothing alone.
+    pub fn new() -> Self {
+        FindOneAndModifyOptions::default()
+    }
+
+    /// Sets whether a filter matching no documents inserts one instead.
+    pub fn upsert(mut self, upsert: bool) -> Self {
+        self.upsert = upsert;
+        self
+    }
+
+    /// Sets which document is returned.
+    pub fn return_document(mut self, return_document: ReturnDocument) -> Self {
+        self.return_document = return_document;
+        self
+    }
+}
+
+/// A change to the documents matching a filter.
+#[derive(Debug, Clone, Copy)]
+enum Modification<'a> {
+    /// An update document of update operators.
+    Update(&'a Document),
+    /// A replacement document.
+    Replace(&'a Document),
+}
+
+/// What `Collection::modify` did, with the first document it matched or
+/// inserted from before and after the change.
+#[derive(Debug)]
+struct Modified {
+    result: UpdateResult,
+    before: Option<Document>,
+    after: Option<Document>,
+}
+
+impl Modified {
+    fn image(self, return_document: ReturnDocument) -> Option<Document> {
+        match return_document {
+            ReturnDocument::Before => self.before,
+            ReturnDocument::After => self.after,
+        }
+    }
+}
+
+/// A named set of documents in a `Database`, each identified by its `_id`.
+///
+/// Documents are stored in the namespace named after the collection, keyed
+/// by the order-preserving encoding of their `_id`. That namespace is the
+/// collection's primary index: it keeps documents in `_id` order, finds
+/// them by `_id` without a scan, and rejects a second document with an
+/// `_id` already in use.
+///
+/// Filters are query documents as described for `Matcher`, such as
+/// `{"age": {"$gte": 21}, "name": "alice"}`. An empty filter matches every
+/// document.
+///
+/// Secondary indexes made with `create_index` map the values at a path to
+/// the documents holding them. A query with an equality or range predicate
+/// on `_id` or an indexed path reads only the matching range of that index.
+/// An index over several paths serves queries on any prefix of them.
+/// Results come in `_id` order, or in index order when a query uses a
+/// secondary index.
+///
+/// A text index splits the strings at its paths into terms, and serves the
+/// top-level `$text` operator: `{"$text": {"$search": "quick fox"}}`
+/// matches the documents holding any term of the search string, best
+/// first by BM25 relevance, and `Cursor::text_score` adds each document's
+/// score to it.
+///
+/// A geo index over GeoJSON geometries serves `$geoWithin` and
+/// `$geoIntersects`, and is required by `$near`, which returns documents
+/// nearest first.
+///
+/// A query whose filter and projection only use `_id` and the fields of
+/// an index is covered by it: `find_with` answers it from the index's
+/// entries without reading the documents, as long as no document has held
+/// an array or a missing value at an indexed path.
+///
+/// `watch` streams the changes committed to the collection, read from the
+/// write-ahead log, for keeping caches and downstream copies in sync.
+///
+/// Handles from `Database::collection` apply each operation on its own,
+/// while those from `Transaction::collection` read and write within the
+/// transaction.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use silentdb::Database;
+/// # use silentdb_data_encoding::{Document, Value};
+/// let users = Database::open("data").unwrap().collection("users");
+///
+/// let mut filter = Document::new();
+/// filter.insert("name", "alice");
+/// let mut set = Document::new();
+/// set.insert("active", true);
+/// let mut update = Document::new();
+/// update.insert("$set", set);
+///
+/// let result = users.update_many(&filter, &update).unwrap();
+/// println!("updated {} of {}", result.modified_count, result.matched_count);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Collection {
+    name: String,
+    inner: Arc<Mutex<DatabaseInner>>,
+    /// The transaction the collection's operations run in, if any.
+    txn: Option<u64>,
+    write_concern: WriteConcern,
+    read_concern: ReadConcern,
+}
+
+impl Collection {
+    pub(crate) fn new(name: &str, inner: Arc<Mutex<DatabaseInner>>, txn: Option<u64>) -> Self {
+        Collection {
+            name: name.to_string(),
+            inner,
+            txn,
+            write_concern: WriteConcern::default(),
+            read_concern: ReadConcern::default(),
+        }
+    }
+
+    /// Returns the collection's name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the collection as one of values of `T`, which are stored as
+    /// the documents they convert into. See `TypedCollection`.
+    pub fn typed<T: IntoValue + FromValue>(self) -> TypedCollection<T> {
+        TypedCollection::new(self)
+    }
+
+    /// Sets how durable the handle's writes must be before they return.
+    /// Within a transaction, writes are made durable by its commit instead;
+    /// see `Transaction::commit_with`.
+    pub fn write_concern(mut self, concern: WriteConcern) -> Self {
+        self.write_concern = concern;
+        self
+    }
+
+    /// Sets what the cursors the handle opens read between their batches.
+    pub fn read_concern(mut self, concern: ReadConcern) -> Self {
+        self.read_concern = concern;
+        self
+    }
+
+    /// Inserts `document`, first giving it a generated `ObjectId` as its
+    /// `_id` if it has none.
+    ///
+    /// # Errors
+    ///
+    /// Returns `DuplicateKey` if a document with the same `_id` already
+    /// exists, or an error if the document cannot be encoded or stored.
+    pub fn insert_one(&self, document: Document) -> Result<InsertOneResult, DatabaseError> {
+        let mut result = self.insert_many([document])?;
+        Ok(InsertOneResult {
+            inserted_id: result.inserted_ids.remove(0),
+        })
+    }
+
+    /// Inserts every document in `documents` as one atomic write, giving
+    /// each one without an `_id` a generated `ObjectId`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `DuplicateKey` if any `_id` is already in the collection or
+    /// appears twice in `documents`, or `ValidationFailed` if a document
+    /// is rejected by the collection's validator, in which case nothing is
+    /// inserted.
+    pub fn insert_many<I>(&self, documents: I) -> Result<InsertManyResult, DatabaseError>
+    where
+        I: IntoIterator<Item = Document>,
+    {
+        let _timer = OpTimer::start(&self.inner, Op::Insert);
+        let mut entries = Vec::new();
+        let mut inserted_ids = Vec::new();
+        let mut keys = HashSet::new();
+        for mut document in documents {
+            let id = match document.get("_id") {
+                Some(id) => id.clone(),
+                None => {
+                    let id = Value::ObjectId(generate_object_id());
+                    document.insert("_id", id.clone());
+                    id
+                }
+            };
+            let key = encode_key(&id);
+            if !keys.insert(key.clone()) {
+                return Err(DatabaseError::DuplicateKey(id));
+            }
+            entries.push((key, to_bytes(&document)?, document));
+            inserted_ids.push(id);
+        }
+
+        self.lock_collection(LockMode::IntentExclusive)?;
+        let mut inner = DatabaseInner::lock(&self.inner);
+        for ((key, _, _), id) in entries.iter().zip(&inserted_ids) {
+            if inner.get(self.txn, &self.name, key)?.is_some() {
+                return Err(DatabaseError::DuplicateKey(id.clone()));
+            }
+        }
+        if is_versioned(&inner, self.txn, &self.name)? {
+            for (_, bytes, document) in &mut entries {
+                stamp(document, None);
+                *bytes = to_bytes(document)?;
+            }
+        }
+        for (_, bytes, document) in &mut entries {
+            if inner.migrations.stamp(&self.name, document) {
+                *bytes = to_bytes(document)?;
+            }
+        }
+        if let Some(rules) = load_rules(&inner, self.txn, &self.name)? {
+            for (_, _, document) in &entries {
+                rules.check(&mut inner, None, document)?;
+            }
+        }
+        let mut indexes = load_indexes(&inner, self.txn, &self.name)?;
+        let mut writes = Vec::new();
+        for (key, value, _) in entries {
+            writes.extend(index_writes(
+                &self.name,
+                &mut indexes,
+                &key,
+                None,
+                Some(&value),
+            )?);
+            writes.push(Write::Put {
+                namespace: self.name.clone(),
+                key,
+                value,
+            });
+        }
+        inner.write(self.txn, writes, BTreeSet::new(), self.write_concern)?;
+        Ok(InsertManyResult { inserted_ids })
+    }
+
+    /// Returns a cursor over the documents matching `filter`. No documents
+    /// are read until the cursor's first batch is fetched.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Query` if `filter` is invalid.
+    pub fn find(&self, filter: &Document) -> Result<Cursor, DatabaseError> {
+        self.find_with(filter, &FindOptions::new())
+    }
+
+    /// Returns a cursor over the documents matching `filter`, as `find`
+    /// does, in the order `options` sort them, with only the fields they
+    /// project of each. A covered query's results hold the indexed values
+    /// in index field order, with numbers of the narrowest type holding
+    /// them.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Query` if `filter`, the projection or the sort is invalid.
+    pub fn find_with(
+        &self,
+        filter: &Document,
+        options: &FindOptions,
+    ) -> Result<Cursor, DatabaseError> {
+        let projection = options
+            .projection
+            .as_ref()
+            .map(Projection::new)
+            .transpose()?;
+        if let Some(sort) = &options.sort {
+            // Documents are projected once their sort key is taken
+            let sort = ExternalSort::new(
+                SortSpec::new(sort)?,
+                projection,
+                options.sort_memory.unwrap_or(DEFAULT_SORT_MEMORY),
+            );
+            let unsorted = FindOptions {
+                max_time: options.max_time,
+                ..FindOptions::default()
+            };
+            return Ok(self.find_with(filter, &unsorted)?.sorted(sort));
+        }
+        let _timer = OpTimer::start(&self.inner, Op::Find);
+        let mut inner = DatabaseInner::lock(&self.inner);
+        let (matcher, mut candidates) =
+            self.candidates(&inner, filter, None, projection.as_ref())?;
+        let chosen = candidates.swap_remove(0);
+        let mut scan = QueryScan::new(&self.name, self.txn, matcher, chosen.plan);
+        if let Some(projection) = projection {
+            scan = scan.project(projection, chosen.covered);
+        }
+        if let Some(max_time) = options.max_time {
+            scan = scan.max_time(max_time);
+        }
+        Ok(self.open_cursor(&mut inner, scan))
+    }
+
+    /// Returns the distinct values at the dotted `path` among the
+    /// documents matching `filter`, in key order, with each element of an
+    /// array counted as a value of its own. Values that compare equal,
+    /// such as `1` and `1.0`, count once.
+    ///
+    /// Only `path` of each document is read, so an index on it that covers
+    /// `filter` answers the query without reading the documents.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Query` if `filter` is invalid or `path` is not a valid
+    /// path.
+    pub fn distinct(&self, path: &str, filter: &Document) -> Result<Vec<Value>, DatabaseError> {
+        let mut projection = Document::new();
+        projection.insert(path, 1);
+        if path != "_id" {
+            projection.insert("_id", 0);
+        }
+        let options = FindOptions::new().projection(projection);
+        let mut values = BTreeMap::new();
+        for document in self.find_with(filter, &options)? {
+            for value in distinct_values(&document?, path) {
+                values
+                    .entry(encode_key(value))
+                    .or_insert_with(|| value.clone());
+            }
+        }
+        Ok(values.into_values().collect())
+    }
+
+    /// Returns a cursor over the documents matching `filter` with strings
+    /// compared under `collation` rather than the collection's, as `find`
+    /// does.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Query` if `filter` is invalid.
+    pub fn find_collated(
+        &self,
+        filter: &Document,
+        collation: &Collation,
+    ) -> Result<Cursor, DatabaseError> {
+        let mut inner = DatabaseInner::lock(&self.inner);
+        let (matcher, mut candidates) = self.candidates(&inner, filter, Some(collation), None)?;
+        let plan = candidates.swap_remove(0).plan;
+        let scan = QueryScan::new(&self.name, self.txn, matcher, plan);
+        Ok(self.open_cursor(&mut inner, scan))
+    }
+
+    /// Sets how the collection's queries compare strings, and the
+    /// collation indexes created afterwards take by default. Existing
+    /// indexes keep theirs, and queries only use those whose collation
+    /// matches.
+    pub fn set_collation(&self, collation: Collation) -> Result<(), DatabaseError> {
+        let write = collation_write(&self.name, Some(&collation))?;
+        self.lock_collection(LockMode::Exclusive)?;
+        let mut inner = DatabaseInner::lock(&self.inner);
+        inner.write(self.txn, vec![write], BTreeSet::new(), self.write_concern)
+    }
+
+    /// Removes the collection's collation, so that strings compare by code
+    /// point again.
+    pub fn remove_collation(&self) -> Result<(), DatabaseError> {
+        let write = collation_write(&self.name, None)?;
+        self.lock_collection(LockMode::Exclusive)?;
+        let mut inner = DatabaseInner::lock(&self.inner);
+        inner.write(self.txn, vec![write], BTreeSet::new(), self.write_concern)
+    }
+
+    /// Returns the collection's collation, if it has one.
+    pub fn collation(&self) -> Result<Option<Collation>, DatabaseError> {
+        let inner = DatabaseInner::lock(&self.inner);
+        load_collation(&inner, self.txn, &self.name)
+    }
+
+    /// Returns the first document matching `filter`.
+    pub fn find_one(&self, filter: &Document) -> Result<Option<Document>, DatabaseError> {
+        let _timer = OpTimer::start(&self.inner, Op::Find);
+        let inner = DatabaseInner::lock(&self.inner);
+        let matches = self.matching(&inner, filter, 1)?;
+        Ok(matches.into_iter().next().map(|stored| stored.document))
+    }
+
+    /// Creates an ascending index on the dotted `path`, unless one exists,
+    /// and returns its name.
+    ///
+    /// The index is built from the documents already in the collection and
+    /// kept up to date by every later write. Queries with an equality or
+    /// range predicate on `path` use it to find candidate documents.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidIndex` if `path` is empty or names an operator.
+    pub fn create_index(&self, path: &str) -> Result<String, DatabaseError> {
+        self.create_index_with(IndexOptions::new().ascending(path))
+    }
+
+    /// Creates the index described by `options`, unless one exists, and
+    /// returns its name.
+    ///
+    /// A compound index orders documents by its first field, then by the
+    /// second among equal values of the first, and so on. Besides filters
+    /// on all its fields, it serves filters on any prefix of them, such as
+    /// an index on `(a, b)` serving a filter on `a` alone.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidIndex` if there are no fields, a path is empty,
+    /// names an operator or appears twice, a TTL index has more than one
+    /// field or is a text index, a text index is partial or the collection
+    /// already has one, a geo index has more than one field or is a text or
+    /// TTL index, or an index with the same name but different
+    /// options exists, and `Query` if the partial filter is malformed.
+    pub fn create_index_with(&self, options: IndexOptions) -> Result<String, DatabaseError> {
+        if options.fields.is_empty() {
+            return Err(DatabaseError::InvalidIndex(
+                "an index needs at least one field".to_string(),
+            ));
+        }
+        for (i, (path, _)) in options.fields.iter().enumerate() {
+            if path.is_empty()
+                || path
+                    .split('.')
+                    .any(|segment| segment.is_empty() || segment.starts_with('$'))
+            {
+                return Err(DatabaseError::InvalidIndex(format!(
+                    "bad index path {:?}",
+                    path
+                )));
+            }
+            if options.fields[..i].iter().any(|(other, _)| other == path) {
+                return Err(DatabaseError::InvalidIndex(format!(
+                    "path {:?} indexed twice",
+                    path
+                )));
+            }
+        }
+        if options.expire_after.is_some() && options.fields.len() != 1 {
+            return Err(DatabaseError::InvalidIndex(
+                "a TTL index needs exactly one field".to_string(),
+            ));
+        }
+        if options.text.is_some()
+            && (options.expire_after.is_some() || options.partial_filter.is_some())
+        {
+            return Err(DatabaseError::InvalidIndex(
+                "a text index cannot be a TTL or partial index".to_string(),
+            ));
+        }
+        if options.geo
+            && (options.fields.len() != 1
+                || options.text.is_some()
+                || options.expire_after.is_some())
+        {
+            return Err(DatabaseError::InvalidIndex(
+                "a geo index needs exactly one field and cannot be a text or TTL index".to_string(),
+            ));
+        }
+        if let Some(filter) = &options.partial_filter {
+            Matcher::new(filter)?;
+        }
+        let mut index = IndexInfo::from_options(options);
+        self.lock_collection(LockMode::Exclusive)?;
+        let mut inner = DatabaseInner::lock(&self.inner);
+        if index.collation.is_none() && index.text.is_none() && !index.geo {
+            index.collation = load_collation(&inner, self.txn, &self.name)?;
+        }
+        let existing = load_indexes(&inner, self.txn, &self.name)?;
+        if let Some(other) = existing.iter().find(|other| other.name == index.name) {
+            if !other.same_options(&index) {
+                return Err(DatabaseError::InvalidIndex(format!(
+                    "index {} exists with different options",
+                    index.name
+                )));
+            }
+            return Ok(index.name);
+        }
+        if index.text.is_some() {
+            if let Some(other) = existing.iter().find(|other| other.text.is_some()) {
+                return Err(DatabaseError::InvalidIndex(format!(
+                    "{} already has text index {}",
+                    self.name, other.name
+                )));
+            }
+        }
+
+        let mut writes = vec![catalog_write(&self.name, &index)?];
+        let mut range = KeyRange::all();
+        loop {
+            let batch = inner.scan(self.txn, &self.name, &range, SCAN_BATCH)?;
+            let Some((last, _)) = batch.last() else {
+                break;
+            };
+            range = range.after(last);
+            for (key, bytes) in &batch {
+                let index = std::slice::from_mut(&mut index);
+                writes.extend(index_writes(&self.name, index, key, None, Some(bytes))?);
+            }
+        }
+        inner.write(self.txn, writes, BTreeSet::new(), self.write_concern)?;
+        Ok(index.name)
+    }
+
+    /// Returns the collection's secondary indexes.
+    pub fn list_indexes(&self) -> Result<Vec<IndexInfo>, DatabaseError> {
+        let inner = DatabaseInner::lock(&self.inner);
+        load_indexes(&inner, self.txn, &self.name)
+    }
+
+    /// Sets the rules inserts, updates and replacements in the collection
+    /// are checked against, replacing any it had. Documents already in the
+    /// collection are not checked; see `revalidate`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Query` if the validator's rules are not a valid filter.
+    pub fn set_validator(&self, validator: Validator) -> Result<(), DatabaseError> {
+        let write = validator_write(&self.name, Some(&validator))?;
+        self.lock_collection(LockMode::Exclusive)?;
+        let mut inner = DatabaseInner::lock(&self.inner);
+        inner.write(self.txn, vec![write], BTreeSet::new(), self.write_concern)
+    }
+
+    /// Removes the collection's validator, if it has one.
+    pub fn remove_validator(&self) -> Result<(), DatabaseError> {
+        let write = validator_write(&self.name, None)?;
+        self.lock_collection(LockMode::Exclusive)?;
+        let mut inner = DatabaseInner::lock(&self.inner);
+        inner.write(self.txn, vec![write], BTreeSet::new(), self.write_concern)
+    }
+
+    /// Returns the collection's validator, if it has one.
+    pub fn validator(&self) -> Result<Option<Validator>, DatabaseError> {
+        let inner = DatabaseInner::lock(&self.inner);
+        load_validator(&inner, self.txn, &self.name)
+    }
+
+    /// Checks every document in the collection against its validator and
+    /// returns the `_id`s of those that fail it, in key order. Nothing is
+    /// changed, whatever the validator's action.
+    pub fn revalidate(&self) -> Result<Vec<Value>, DatabaseError> {
+        let inner = DatabaseInner::lock(&self.inner);
+        let Some(rules) = load_rules(&inner, self.txn, &self.name)? else {
+            return Ok(Vec::new());
+        };
+        let invalid = self
+            .matching(&inner, &Document::new(), usize::MAX)?
+            .into_iter()
+            .filter(|stored| !rules.passes(&stored.document))
+            .map(|stored| stored.document.get("_id").cloned().unwrap_or(Value::Null))
+            .collect();
+        Ok(invalid)
+    }
+
+    /// Applies `update` to the first document matching `filter`.
+    ///
+    /// `update` is a document of update operators: `$set` and `$unset`
+    /// take dotted paths to set or remove, and `$inc` adds to numeric
+    /// fields, treating missing ones as zero.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidUpdate` if `update` uses an unknown operator, would
+    /// change `_id`, or increments a non-numeric field, or
+    /// `ValidationFailed` if the collection's validator rejects the result.
+    pub fn update_one(
+        &self,
+        filter: &Document,
+        update: &Document,
+    ) -> Result<UpdateResult, DatabaseError> {
+        self.update(filter, update, 1)
+    }
+
+    /// Applies `update` to every document matching `filter` as one atomic
+    /// write. See `update_one` for the update operators.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidUpdate` if `update` is invalid for any matched
+    /// document, or `ValidationFailed` if the collection's validator
+    /// rejects any result, in which case nothing is changed.
+    pub fn update_many(
+        &self,
+        filter: &Document,
+        update: &Document,
+    ) -> Result<UpdateResult, DatabaseError> {
+        self.update(filter, update, usize::MAX)
+    }
+
+    /// Replaces the first document matching `filter` with `replacement`,
+    /// keeping its `_id`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidUpdate` if `replacement` holds update operators or
+    /// a different `_id`, or `ValidationFailed` if the collection's
+    /// validator rejects it.
+    pub fn replace_one(
+        &self,
+        filter: &Document,
+        replacement: &Document,
+    ) -> Result<UpdateResult, DatabaseError> {
+        self.replace_one_with(filter, replacement, &UpdateOptions::new())
+    }
+
+    /// Applies `update` to the first document matching `filter`, as
+    /// `update_one` does, or with `upsert` set inserts a document made of
+    /// the filter's equality fields with `update` applied if none match.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of `update_one`, or `DuplicateKey` if the
+    /// inserted document's `_id` is already in use.
+    pub fn update_one_with(
+        &self,
+        filter: &Document,
+        update: &Document,
+        options: &UpdateOptions,
+    ) -> Result<UpdateResult, DatabaseError> {
+        let modified = self.modify(filter, Modification::Update(update), 1, options.upsert)?;
+        Ok(modified.result)
+    }
+
+    /// Applies `update` to every document matching `filter`, as
+    /// `update_many` does, upserting as `update_one_with` does.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of `update_many`, or `DuplicateKey` if an
+    /// inserted document's `_id` is already in use.
+    pub fn update_many_with(
+        &self,
+        filter: &Document,
+        update: &Document,
+        options: &UpdateOptions,
+    ) -> Result<UpdateResult, DatabaseError> {
+        let modified = self.modify(
+            filter,
+            Modification::Update(update),
+            usize::MAX,
+            options.upsert,
+        )?;
+        Ok(modified.result)
+    }
+
+    /// Replaces the first document matching `filter`, as `replace_one`
+    /// does, or with `upsert` set inserts `replacement` if none match,
+    /// with the `_id` the filter fixes if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of `replace_one`, or `DuplicateKey` if the
+    /// inserted document's `_id` is already in use.
+    pub fn replace_one_with(
+        &self,
+        filter: &Document,
+        replacement: &Document,
+        options: &UpdateOptions,
+    ) -> Result<UpdateResult, DatabaseError> {
+        let modification = Modification::Replace(replacement);
+        Ok(self.modify(filter, modification, 1, options.upsert)?.result)
+    }
+
+    /// Applies `update` to the first document matching `filter`, as
+    /// `update_one_with` does, and returns the document from before or
+    /// after the update in the same atomic write. Returns `None` if no
+    /// document matched and none was upserted, or if the document from
+    /// before an upsert is asked for.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of `update_one_with`.
+    pub fn find_one_and_update(
+        &self,
+        filter: &Document,
+        update: &Document,
+        options: &FindOneAndModifyOptions,
+    ) -> Result<Option<Document>, DatabaseError> {
+        let modified = self.modify(filter, Modification::Update(update), 1, options.upsert)?;
+        Ok(modified.image(options.return_document))
+    }
+
+    /// Replaces the first document matching `filter`, as
+    /// `replace_one_with` does, and returns the document from before or
+    /// after the replacement as `find_one_and_update` does.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of `replace_one_with`.
+    pub fn find_one_and_replace(
+        &self,
+        filter: &Document,
+        replacement: &Document,
+        options: &FindOneAndModifyOptions,
+    ) -> Result<Option<Document>, DatabaseError> {
+        let modification = Modification::Replace(replacement);
+        let modified = self.modify(filter, modification, 1, options.upsert)?;
+        Ok(modified.image(options.return_document))
+    }
+
+    /// Replaces the document with `_id` `id` with `replacement`, as
+    /// `replace_one` does, only if it is still at `expected_version`, for
+    /// optimistic concurrency without a transaction: read a document, make
+    /// the change, and retry from the read on `VersionConflict`.
+    ///
+    /// The collection must be versioned; see `set_versioned`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `VersionConflict` if the document has another version, in
+    /// which case nothing is changed, or the errors of `replace_one`.
+    pub fn replace_one_if_version(
+        &self,
+        id: &Value,
+        expected_version: i64,
+        replacement: &Document,
+    ) -> Result<UpdateResult, DatabaseError> {
+        let mut filter = filter_id(id);
+        match expected_version {
+            0 => {
+                let mut missing = Document::new();
+                missing.insert("$exists", false);
+                filter.insert(VERSION_FIELD, missing)
+            }
+            version => filter.insert(VERSION_FIELD, version),
+        };
+        let result = self.replace_one(&filter, replacement)?;
+        if result.matched_count == 0 {
+            if let Some(current) = self.find_one(&filter_id(id))? {
+                return Err(DatabaseError::VersionConflict {
+                    id: id.clone(),
+                    expected: expected_version,
+                    actual: version_of(&current),
+                });
+            }
+        }
+        Ok(result)
+    }
+
+    /// Sets whether the collection keeps a version in every document's
+    /// `_version` field: 1 when it is inserted, and one more each time a
+    /// write changes it. Documents written while the collection was not
+    /// versioned are at version 0.
+    pub fn set_versioned(&self, versioned: bool) -> Result<(), DatabaseError> {
+        let write = versioned_write(&self.name, versioned);
+        self.lock_collection(LockMode::Exclusive)?;
+        let mut inner = DatabaseInner::lock(&self.inner);
+        inner.write(self.txn, vec![write], BTreeSet::new(), self.write_concern)
+    }
+
+    /// Returns whether the collection keeps document versions.
+    pub fn is_versioned(&self) -> Result<bool, DatabaseError> {
+        let inner = DatabaseInner::lock(&self.inner);
+        is_versioned(&inner, self.txn, &self.name)
+    }
+
+    /// Registers `migration` as the function upgrading the collection's
+    /// documents from schema version `version - 1` to `version`, replacing
+    /// any registered for that version.
+    ///
+    /// Once the collection has migrations, documents written to it without
+    /// a `_schema` field are given the latest version in it, and documents
+    /// read at an older one are matched and returned as the migrations
+    /// after theirs upgrade them, without being written back until
+    /// `migrate_schema` or an update rewrites them. Documents without the
+    /// field are at version 0. Indexes hold documents as stored, so a query
+    /// answered from one may miss outdated documents that match once
+    /// upgraded.
+    ///
+    /// Migrations are kept only while the database is open: they must be
+    /// registered again, in order, each time it is opened.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidMigration` if `version` is 0 or more than one past
+    /// `schema_version`. Reads fail with `InvalidMigration` if a migration
+    /// changes a document's `_id`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use silentdb::Database;
+    /// let users = Database::open("data").unwrap().collection("users");
+    /// users
+    ///     .register_migration(1, |mut user| {
+    ///         if let Some(name) = user.remove("name") {
+    ///             user.insert("full_name", name);
+    ///         }
+    ///         user
+    ///     })
+    ///     .unwrap();
+    /// assert_eq!(users.schema_version(), 1);
+    /// ```
+    pub fn register_migration<F>(&self, version: u32, migration: F) -> Result<(), DatabaseError>
+    where
+        F: Fn(Document) -> Document + Send + Sync + 'static,
+    {
+        let mut inner = DatabaseInner::lock(&self.inner);
+        inner
+            .migrations
+            .register(&self.name, version, Arc::new(migration))
+    }
+
+    /// Returns the schema version the collection's migrations upgrade
+    /// documents to, or 0 if it has none.
+    pub fn schema_version(&self) -> u32 {
+        DatabaseInner::lock(&self.inner)
+            .migrations
+            .latest(&self.name)
+    }
+
+    /// Upgrades every outdated document in the collection to the latest
+    /// schema version and writes it back, in batches of one write each,
+    /// and returns what was done.
+    ///
+    /// Unless `options` turn it off, the first migration of each document
+    /// keeps what it replaced, for `rollback_schema`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidMigration` if a migration changes a document's
+    /// `_id`, or an error if a document cannot be read or stored. The
+    /// batches before it stay migrated.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use silentdb::{Database, SchemaMigrationOptions};
+    /// let users = Database::open("data").unwrap().collection("users");
+    /// let options = SchemaMigrationOptions::new()
+    ///     .progress(|stats| println!("{} of {} migrated", stats.migrated, stats.scanned));
+    /// let stats = users.migrate_schema(&options).unwrap();
+    /// println!("{} documents now at version {}", stats.migrated, stats.version);
+    /// ```
+    pub fn migrate_schema(
+        &self,
+        options: &SchemaMigrationOptions,
+    ) -> Result<SchemaMigrationStats, DatabaseError> {
+        self.lock_collection(LockMode::IntentExclusive)?;
+        let rollback = rollback_namespace(&self.name);
+        let mut stats = SchemaMigrationStats::default();
+        let mut range = KeyRange::all();
+        loop {
+            let mut inner = DatabaseInner::lock(&self.inner);
+            stats.version = inner.migrations.latest(&self.name);
+            let batch = inner.scan(self.txn, &self.name, &range, options.batch_size)?;
+            let Some((last, _)) = batch.last() else {
+                return Ok(stats);
+            };
+            range = range.after(last);
+            let mut indexes = load_indexes(&inner, self.txn, &self.name)?;
+            let mut writes = Vec::new();
+            for (key, bytes) in batch {
+                stats.scanned += 1;
+                let Some(upgraded) = inner.migrations.upgrade_bytes(&self.name, &bytes)? else {
+                    continue;
+                };
+                writes.extend(index_writes(
+                    &self.name,
+                    &mut indexes,
+                    &key,
+                    Some(&bytes),
+                    Some(&upgraded),
+                )?);
+                if options.rollback && inner.get(self.txn, &rollback, &key)?.is_none() {
+                    writes.push(Write::Put {
+                        namespace: rollback.clone(),
+                        key: key.clone(),
+                        value: bytes,
+                    });
+                }
+                writes.push(Write::Put {
+                    namespace: self.name.clone(),
+                    key,
+                    value: upgraded,
+                });
+                stats.migrated += 1;
+            }
+            if !writes.is_empty() {
+                inner.write(self.txn, writes, BTreeSet::new(), self.write_concern)?;
+                stats.batches += 1;
+            }
+            drop(inner);
+            if let Some(ProgressHook(progress)) = &options.progress {
+                progress(&stats);
+            }
+        }
+    }
+
+    /// Restores the documents `migrate_schema` kept rollback records for
+    /// to what they were before it first migrated them, as one atomic
+    /// write, removes the records, and returns how many were restored.
+    ///
+    /// Documents deleted since are not restored, and changes made since to
+    /// the others are lost. While the collection has migrations, restored
+    /// documents are still upgraded as they are read: register corrected
+    /// migrations for the versions at fault before migrating again.
+    pub fn rollback_schema(&self) -> Result<u64, DatabaseError> {
+        self.lock_collection(LockMode::Exclusive)?;
+        let mut inner = DatabaseInner::lock(&self.inner);
+        let namespace = rollback_namespace(&self.name);
+        let records = inner.scan(self.txn, &namespace, &KeyRange::all(), usize::MAX)?;
+        let mut indexes = load_indexes(&inner, self.txn, &self.name)?;
+        let mut writes = Vec::new();
+        let mut restored = 0;
+        for (key, value) in records {
+            if let Some(bytes) = inner.get(self.txn, &self.name, &key)? {
+                writes.extend(index_writes(
+                    &self.name,
+                    &mut indexes,
+                    &key,
+                    Some(&bytes),
+                    Some(&value),
+                )?);
+                writes.push(Write::Put {
+                    namespace: self.name.clone(),
+                    key: key.clone(),
+                    value,
+                });
+                restored += 1;
+            }
+            writes.push(Write::Delete {
+                namespace: namespace.clone(),
+                key,
+            });
+        }
+        inner.write(self.txn, writes, BTreeSet::new(), self.write_concern)?;
+        Ok(restored)
+    }
+
+    /// Deletes the first document matching `filter` and returns it, or
+    /// `None` if none matched.
+    pub fn find_one_and_delete(
+        &self,
+        filter: &Document,
+    ) -> Result<Option<Document>, DatabaseError> {
+        Ok(self.remove(filter, 1)?.pop().map(|stored| stored.document))
+    }
+
+    /// Deletes the first document matching `filter`.
+    pub fn delete_one(&self, filter: &Document) -> Result<DeleteResult, DatabaseError> {
+        self.delete(filter, 1)
+    }
+
+    /// Deletes every document matching `filter` as one atomic write.
+    pub fn delete_many(&self, filter: &Document) -> Result<DeleteResult, DatabaseError> {
+        self.delete(filter, usize::MAX)
+    }
+
+    /// Returns a stream of the changes committed to the collection from
+    /// now on whose change event documents, as described for
+    /// `ChangeEvent::to_document`, match `filter`. Changes made through a
+    /// transaction's handle appear when it commits.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Query` if `filter` is invalid.
+    pub fn watch(&self, filter: &Document) -> Result<ChangeStream, DatabaseError> {
+        let after = ChangeStream::end_of_log(&DatabaseInner::lock(&self.inner));
+        self.watch_after(filter, after)
+    }
+
+    /// Returns a stream of the changes matching `filter` committed after
+    /// the one `token` was taken at, as `watch` does.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Query` if `filter` is invalid.
+    pub fn watch_after(
+        &self,
+        filter: &Document,
+        token: ResumeToken,
+    ) -> Result<ChangeStream, DatabaseError> {
+        let matcher = Matcher::new(filter)?;
+        Ok(ChangeStream::new(
+            Arc::clone(&self.inner),
+            &self.name,
+            matcher,
+            token,
+        ))
+    }
+
+    /// Returns a document describing how `find` runs `filter`, after
+    /// running it to completion.
+    ///
+    /// `plan` describes the chosen plan by its `stage`: `collection_scan`,
+    /// `id_scan`, `index_scan`, `index_intersection`, `text`, `geo` or
+    /// `near`, along with the indexes it reads. `covered` is whether the
+    /// plan answers the query from index entries alone. `estimated_rows` is
+    /// the number of documents the plan was expected to read, `examined`
+    /// the number it read and `actual_rows` the number that matched, while
+    /// `candidates` lists every plan considered with its `estimated_rows`,
+    /// `cost` and `covered`. Estimates the planner could not make are
+    /// `null`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Query` if `filter` is invalid.
+    pub fn explain(&self, filter: &Document) -> Result<Document, DatabaseError> {
+        self.explain_with(filter, &FindOptions::new())
+    }
+
+    /// Returns a document describing how `find_with` runs `filter` with
+    /// `options`, as `explain` does.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Query` if `filter` or the projection is invalid.
+    pub fn explain_with(
+        &self,
+        filter: &Document,
+        options: &FindOptions,
+    ) -> Result<Document, DatabaseError> {
+        let projection = options
+            .projection
+            .as_ref()
+            .map(Projection::new)
+            .transpose()?;
+        let inner = DatabaseInner::lock(&self.inner);
+        let (matcher, mut candidates) =
+            self.candidates(&inner, filter, None, projection.as_ref())?;
+        if candidates[0].estimate.is_none() {
+            candidates[0].estimate = estimate(&inner, self.txn, &self.name, &candidates[0].plan)?;
+        }
+        let chosen = candidates[0].clone();
+        let mut scan = QueryScan::new(&self.name, self.txn, matcher, chosen.plan.clone());
+        if let Some(projection) = projection {
+            scan = scan.project(projection, chosen.covered);
+        }
+        let mut actual_rows = 0;
+        loop {
+            let batch = scan.next(&inner, SCAN_BATCH)?;
+            actual_rows += batch.len() as i64;
+            if batch.len() < SCAN_BATCH {
+                break;
+            }
+        }
+        let count = |count: Option<u64>| count.map_or(Value::Null, |count| (count as i64).into());
+
+        let mut explain = Document::new();
+        explain.insert("plan", chosen.plan.to_document());
+        explain.insert("covered", chosen.covered);
+        explain.insert("estimated_rows", count(chosen.estimate));
+        explain.insert("examined", scan.examined() as i64);
+        explain.insert("actual_rows", actual_rows);
+        let candidates = candidates
+            .iter()
+            .map(|candidate| {
+                let mut document = Document::new();
+                document.insert("plan", candidate.plan.to_document());
+                document.insert("estimated_rows", count(candidate.estimate));
+                document.insert("cost", count(candidate.cost));
+                document.insert("covered", candidate.covered);
+                Value::Document(document)
+            })
+            .collect();
+        explain.insert("candidates", Array::from_vec(candidates));
+        Ok(explain)
+    }
+
+    /// Returns statistics about the collection and its indexes, gathered
+    /// by reading all of them.
+    ///
+    /// Outside a transaction, the statistics are kept for the query
+    /// planner, which estimates from them how many documents a plan reads
+    /// when there are too many to count, until they are next gathered.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the collection or an index cannot be read.
+    pub fn stats(&self) -> Result<CollectionStats, DatabaseError> {
+        let mut inner = DatabaseInner::lock(&self.inner);
+        let indexes = load_indexes(&inner, self.txn, &self.name)?;
+        let stats = gather(&inner, self.txn, &self.name, &indexes)?;
+        if self.txn.is_none() {
+            inner
+                .collection_stats
+                .insert(self.name.clone(), stats.clone());
+        }
+        Ok(stats)
+    }
+
+    /// Returns the number of documents matching `filter`.
+    pub fn count(&self, filter: &Document) -> Result<u64, DatabaseError> {
+        self.count_with(filter, &CountOptions::new())
+    }
+
+    /// Returns the number of documents matching `filter`, as `count`
+    /// does, within the time `options` allow.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Query` if `filter` is invalid, or `MaxTimeExpired` if
+    /// counting takes longer than the options' `max_time`.
+    pub fn count_with(
+        &self,
+        filter: &Document,
+        options: &CountOptions,
+    ) -> Result<u64, DatabaseError> {
+        let _timer = OpTimer::start(&self.inner, Op::Count);
+        let inner = DatabaseInner::lock(&self.inner);
+        let mut scan = self.scan(&inner, filter)?;
+        if let Some(max_time) = options.max_time {
+            scan = scan.max_time(max_time);
+        }
+        Ok(scan.next(&inner, usize::MAX)?.len() as u64)
+    }
+
+    /// Inserts the documents `reader` holds in `format`, a thousand at a
+    /// time, stopping at the first record that cannot be read or inserted.
+    /// See `import_with`.
+    ///
+    /// # Errors
+    ///
+    /// As `import_with`.
+    pub fn import<R: Read>(&self, reader: R, format: Format) -> Result<ImportStats, DatabaseError> {
+        self.import_with(reader, format, &ImportOptions::new())
+    }
+
+    /// Inserts the documents `reader` holds in `format` in batches, each
+    /// as one write, and returns what was done. A batch that fails is
+    /// inserted again a document at a time, so that only the records at
+    /// fault are skipped or reported.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ImportFailed` with the number of the first record that
+    /// cannot be read or inserted, counting from 1, unless `options` skip
+    /// such records. The batches before it stay inserted.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use std::fs::File;
+    /// # use silentdb::{Database, ErrorPolicy, Format, ImportOptions};
+    /// let db = Database::open("data").unwrap();
+    /// let file = File::open("users.ndjson").unwrap();
+    /// let options = ImportOptions::new().on_error(ErrorPolicy::Skip);
+    /// let stats = db
+    ///     .collection("users")
+    ///     .import_with(file, Format::Ndjson, &options)
+    ///     .unwrap();
+    /// println!("imported {}, skipped {}", stats.inserted, stats.skipped);
+    /// ```
+    pub fn import_with<R: Read>(
+        &self,
+        reader: R,
+        format: Format,
+        options: &ImportOptions,
+    ) -> Result<ImportStats, DatabaseError> {
+        transfer::import(self, reader, format, options)
+    }
+
+    /// Writes the documents matching `filter` to `writer` in `format`,
+    /// with only the fields `projection` selects of each, and returns how
+    /// many were written. CSV columns are the paths `projection` includes,
+    /// or else the fields of the first document.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Query` if `filter` or `projection` is invalid, or an error
+    /// if a document cannot be read or written.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use std::fs::File;
+    /// # use silentdb::{Database, Format};
+    /// # use silentdb_data_encoding::Document;
+    /// let db = Database::open("data").unwrap();
+    /// let mut projection = Document::new();
+    /// projection.insert("name", 1);
+    /// projection.insert("age", 1);
+    /// let file = File::create("users.csv").unwrap();
+    /// db.collection("users")
+    ///     .export(file, Format::Csv, &Document::new(), Some(&projection))
+    ///     .unwrap();
+    /// ```
+    pub fn export<W: std::io::Write>(
+        &self,
+        writer: W,
+        format: Format,
+        filter: &Document,
+        projection: Option<&Document>,
+    ) -> Result<u64, DatabaseError> {
+        transfer::export(self, writer, format, filter, projection)
+    }
+
+    /// Writes the documents `options` select to a Parquet file at `path`,
+    /// for DuckDB, Spark and the like to query, and returns what was
+    /// written.
+    ///
+    /// The schema is inferred from the documents. Each column's type is the
+    /// narrowest that holds all its values: 32-bit integers widen to 64-bit,
+    /// and integers mixed with doubles become doubles. Strings and
+    /// ObjectIds, as hex, are text, dates are UTC millisecond timestamps,
+    /// and binaries are binary. Arrays, the other BSON types, and columns
+    /// mixing types none of these hold are written as relaxed Extended JSON
+    /// text. Every column is nullable, and a missing field is null.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Query` if the filter or projection is invalid, or an error
+    /// if a document cannot be read or the file cannot be written.
+    #[cfg(feature = "parquet")]
+    pub fn export_parquet(
+        &self,
+        path: impl AsRef<std::path::Path>,
+        options: &ParquetOptions,
+    ) -> Result<ParquetStats, DatabaseError> {
+        parquet_export::export(self, path.as_ref(), options)
+    }
+
+    /// Locks the collection in `mode` for the handle's transaction, if it
+    /// has one, waiting for other transactions in the way.
+    fn lock_collection(&self, mode: LockMode) -> Result<(), DatabaseError> {
+        match self.txn {
+            Some(id) => lock(
+                &self.inner,
+                id,
+                LockResource::Collection(self.name.clone()),
+                mode,
+            ),
+            None => Ok(()),
+        }
+    }
+
+    fn update(
+        &self,
+        filter: &Document,
+        update: &Document,
+        limit: usize,
+    ) -> Result<UpdateResult, DatabaseError> {
+        let modified = self.modify(filter, Modification::Update(update), limit, false)?;
+        Ok(modified.result)
+    }
+
+    /// Applies `modification` to up to `limit` documents matching
+    /// `filter` as one atomic write, or if none match and `upsert` is set,
+    /// inserts a document built from the filter instead.
+    fn modify(
+        &self,
+        filter: &Document,
+        modification: Modification<'_>,
+        limit: usize,
+        upsert: bool,
+    ) -> Result<Modified, DatabaseError> {
+        let _timer = OpTimer::start(&self.inner, Op::Update);
+        if let Modification::Replace(replacement) = modification {
+            if let Some((key, _)) = replacement.iter().find(|(key, _)| key.starts_with('$')) {
+                return Err(DatabaseError::InvalidUpdate(format!(
+                    "a replacement cannot hold the operator {}",
+                    key
+                )));
+            }
+        }
+        self.lock_collection(LockMode::IntentExclusive)?;
+        let mut inner = DatabaseInner::lock(&self.inner);
+        let matches = self.matching(&inner, filter, limit)?;
+        if matches.is_empty() && upsert {
+            return self.upsert(&mut inner, filter, modification);
+        }
+        let mut indexes = load_indexes(&inner, self.txn, &self.name)?;
+        let rules = load_rules(&inner, self.txn, &self.name)?;
+        let versioned = is_versioned(&inner, self.txn, &self.name)?;
+
+        let mut writes = Vec::new();
+        let mut replaced = BTreeSet::new();
+        let mut modified_count = 0;
+        let mut images = None;
+        for stored in &matches {
+            let (mut bytes, mut document) = match modification {
+                Modification::Update(update) => {
+                    let current = stored.upgraded.as_ref().unwrap_or(&stored.bytes);
+                    let bytes = apply_update(current, &stored.document, update)?;
+                    let document = from_bytes(&bytes)?;
+                    (bytes, document)
+                }
+                Modification::Replace(replacement) => {
+                    let id = stored.document.get("_id").cloned().unwrap_or(Value::Null);
+                    let mut document = replacement_document(id, replacement)?;
+                    inner.migrations.stamp(&self.name, &mut document);
+                    (to_bytes(&document)?, document)
+                }
+            };
+            if versioned {
+                keep_version(&mut document, &stored.document);
+            }
+            if bytes == stored.bytes || document == stored.document {
+                images.get_or_insert_with(|| (stored.document.clone(), document));
+                continue;
+            }
+            if versioned {
+                stamp(&mut document, Some(&stored.document));
+                bytes = to_bytes(&document)?;
+            }
+            images.get_or_insert_with(|| (stored.document.clone(), document.clone()));
+            if let Some(rules) = &rules {
+                rules.check(&mut inner, Some(&stored.document), &document)?;
+            }
+            modified_count += 1;
+            writes.extend(index_writes(
+                &self.name,
+                &mut indexes,
+                &stored.key,
+                Some(&stored.bytes),
+                Some(&bytes),
+            )?);
+            if let Modification::Replace(_) = modification {
+                replaced.insert((self.name.clone(), stored.key.clone()));
+            }
+            writes.push(Write::Put {
+                namespace: self.name.clone(),
+                key: stored.key.clone(),
+                value: bytes,
+            });
+        }
+        inner.write(self.txn, writes, replaced, self.write_concern)?;
+        let (before, after) = images.unzip();
+        Ok(Modified {
+            result: UpdateResult {
+                matched_count: matches.len() as u64,
+                modified_count,
+                upserted_id: None,
+            },
+            before,
+            after,
+        })
+    }
+
+    /// Inserts the document an upsert with `filter` and `modification`
+    /// builds, for `modify`.
+    fn upsert(
+        &self,
+        inner: &mut DatabaseInner,
+        filter: &Document,
+        modification: Modification<'_>,
+    ) -> Result<Modified, DatabaseError> {
+        let base = upsert_base(filter)?;
+        let mut document = match modification {
+            Modification::Update(update) => {
+                from_bytes(&apply_update(&to_bytes(&base)?, &base, update)?)?
+            }
+            Modification::Replace(replacement) => {
+                let id = base.get("_id").or(replacement.get("_id")).cloned();
+                let id = id.unwrap_or_else(|| Value::ObjectId(generate_object_id()));
+                replacement_document(id, replacement)?
+            }
+        };
+        let id = match document.get("_id") {
+            Some(id) => id.clone(),
+            None => {
+                let id = Value::ObjectId(generate_object_id());
+                document.insert("_id", id.clone());
+                id
+            }
+        };
+        let key = encode_key(&id);
+        if inner.get(self.txn, &self.name, &key)?.is_some() {
+            return Err(DatabaseError::DuplicateKey(id));
+        }
+        if is_versioned(inner, self.txn, &self.name)? {
+            stamp(&mut document, None);
+        }
+        inner.migrations.stamp(&self.name, &mut document);
+        if let Some(rules) = load_rules(inner, self.txn, &self.name)? {
+            rules.check(inner, None, &document)?;
+        }
+
+        let bytes = to_bytes(&document)?;
+        let mut indexes = load_indexes(inner, self.txn, &self.name)?;
+        let mut writes = index_writes(&self.name, &mut indexes, &key, None, Some(&bytes))?;
+        writes.push(Write::Put {
+            namespace: self.name.clone(),
+            key,
+            value: bytes,
+        });
+        inner.write(self.txn, writes, BTreeSet::new(), self.write_concern)?;
+        Ok(Modified {
+            result: UpdateResult {
+                matched_count: 0,
+                modified_count: 0,
+                upserted_id: Some(id),
+            },
+            before: None,
+            after: Some(document),
+        })
+    }
+
+    pub(crate) fn delete(
+        &self,
+        filter: &Document,
+        limit: usize,
+    ) -> Result<DeleteResult, DatabaseError> {
+        let deleted_count = self.remove(filter, limit)?.len() as u64;
+        Ok(DeleteResult { deleted_count })
+    }
+
+    /// Deletes up to `limit` documents matching `filter` as one atomic
+    /// write and returns them.
+    fn remove(&self, filter: &Document, limit: usize) -> Result<Vec<Stored>, DatabaseError> {
+        let _timer = OpTimer::start(&self.inner, Op::Delete);
+        self.lock_collection(LockMode::IntentExclusive)?;
+        let mut inner = DatabaseInner::lock(&self.inner);
+        let matches = self.matching(&inner, filter, limit)?;
+        let mut indexes = load_indexes(&inner, self.txn, &self.name)?;
+
+        let mut writes = Vec::new();
+        for stored in &matches {
+            writes.extend(index_writes(
+                &self.name,
+                &mut indexes,
+                &stored.key,
+                Some(&stored.bytes),
+                None,
+            )?);
+            writes.push(Write::Delete {
+                namespace: self.name.clone(),
+                key: stored.key.clone(),
+            });
+        }
+        inner.write(self.txn, writes, BTreeSet::new(), self.write_concern)?;
+        Ok(matches)
+    }
+
+    /// Plans a scan for the documents matching `filter`.
+    fn scan(&self, inner: &DatabaseInner, filter: &Document) -> Result<QueryScan, DatabaseError> {
+        let (matcher, mut candidates) = self.candidates(inner, filter, None, None)?;
+        let plan = candidates.swap_remove(0).plan;
+        Ok(QueryScan::new(&self.name, self.txn, matcher, plan))
+    }
+
+    /// Opens a cursor running `scan`. Outside a transaction, the `Snapshot`
+    /// read concern gives the cursor a snapshot of its own.
+    fn open_cursor(&self, inner: &mut DatabaseInner, scan: QueryScan) -> Cursor {
+        let snapshot = match (self.txn, self.read_concern) {
+            (None, ReadConcern::Snapshot) => Some(inner.transactions.begin()),
+            _ => None,
+        };
+        let scan = scan.in_snapshot(snapshot);
+        let id = inner.open_cursor(CursorState::new(scan, snapshot));
+        Cursor::new(id, Arc::clone(&self.inner))
+    }
+
+    /// Returns the matcher for `filter` and the plans considered for it,
+    /// the chosen one first, comparing strings under `collation` or else
+    /// the collection's, and returning the fields `projection` selects.
+    fn candidates(
+        &self,
+        inner: &DatabaseInner,
+        filter: &Document,
+        collation: Option<&Collation>,
+        projection: Option<&Projection>,
+    ) -> Result<(Matcher, Vec<Candidate>), DatabaseError> {
+        let (search, filter) = split_text(filter)?;
+        let collation = match collation {
+            Some(collation) => Some(collation.clone()),
+            None => load_collation(inner, self.txn, &self.name)?,
+        };
+        let matcher = match &collation {
+            Some(collation) => Matcher::with_collation(&filter, collation)?,
+            None => Matcher::new(&filter)?,
+        };
+        let indexes = load_indexes(inner, self.txn, &self.name)?;
+        let candidates = match search {
+            Some(search) => vec![Candidate::new(plan_text(&self.name, &search, &indexes)?)],
+            None => {
+                let mut candidates = choose(
+                    inner,
+                    self.txn,
+                    &self.name,
+                    &filter,
+                    &indexes,
+                    collation.as_ref(),
+                    projection,
+                )?;
+                let chosen = &candidates[0].plan;
+                let plan = plan_geo(&self.name, &filter, &indexes, chosen.clone())?;
+                if plan != *chosen {
+                    candidates.insert(0, Candidate::new(plan));
+                }
+                candidates
+            }
+        };
+        Ok((matcher, candidates))
+    }
+
+    /// Returns up to `limit` documents matching `filter`.
+    fn matching(
+        &self,
+        inner: &DatabaseInner,
+        filter: &Document,
+        limit: usize,
+    ) -> Result<Vec<Stored>, DatabaseError> {
+        self.scan(inner, filter)?.next(inner, limit)
+    }
+}
+
+/// Returns `replacement` with `_id` set to `id`.
+///
+/// # Errors
+///
+/// Returns `InvalidUpdate` if `replacement` holds a different `_id`.
+fn replacement_document(id: Value, replacement: &Document) -> Result<Document, DatabaseError> {
+    if replacement.get("_id").is_some_and(|other| *other != id) {
+        return Err(DatabaseError::InvalidUpdate(
+            "the _id field cannot be changed".into(),
+        ));
+    }
+    let mut document = Document::new();
+    document.insert("_id", id);
+    for (key, value) in replacement.iter().filter(|(key, _)| *key != "_id") {
+        document.insert(key.as_str(), value.clone());
+    }
+    Ok(document)
+}
+
+fn filter_id(id: &Value) -> Document {
+    let mut filter = Document::new();
+    filter.insert("_id", id.clone());
+    filter
+}
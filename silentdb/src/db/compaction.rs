@@ -0,0 +1,114 @@
+// src/db/compaction.rs
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::database::DatabaseInner;
+use super::error::DatabaseError;
+
+/// Bytes of live data a compaction step goes through unless told otherwise.
+const DEFAULT_STEP_SIZE: u64 = 1 << 20;
+/// Bytes per second compaction is held to unless told otherwise.
+const DEFAULT_RATE_LIMIT: u64 = 32 << 20;
+
+/// Options for `Database::compact`.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb::CompactionOptions;
+/// let options = CompactionOptions::new()
+///     .step_size(256 * 1024)
+///     .rate_limit(8 * 1024 * 1024);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompactionOptions {
+    pub(crate) step_size: u64,
+    pub(crate) rate_limit: Option<u64>,
+}
+
+impl CompactionOptions {
+    /// Creates options with 1 MiB steps held to 32 MiB per second.
+    pub fn new() -> Self {
+        CompactionOptions {
+            step_size: DEFAULT_STEP_SIZE,
+            rate_limit: Some(DEFAULT_RATE_LIMIT),
+        }
+    }
+
+    /// Sets how many bytes of live data each step goes through while
+    /// holding the database lock. Smaller steps keep the pauses seen by
+    /// other operations shorter.
+    pub fn step_size(mut self, bytes: u64) -> Self {
+        self.step_size = bytes;
+        self
+    }
+
+    /// Holds compaction to about `bytes_per_second` of work, sleeping
+    /// between steps when it gets ahead.
+    pub fn rate_limit(mut self, bytes_per_second: u64) -> Self {
+        self.rate_limit = Some(bytes_per_second.max(1));
+        self
+    }
+
+    /// Runs steps back to back, only yielding the lock between them.
+    pub fn unthrottled(mut self) -> Self {
+        self.rate_limit = None;
+        self
+    }
+}
+
+impl Default for CompactionOptions {
+    fn default() -> Self {
+        CompactionOptions::new()
+    }
+}
+
+/// What a call to `Database::compact` did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompactionStats {
+    /// The number of steps run.
+    pub steps: u64,
+    /// Bytes of live data read or rewritten.
+    pub work: u64,
+    /// Bytes of disk space freed.
+    pub reclaimed: u64,
+    /// How long compaction slept to stay under its rate limit.
+    pub throttled: Duration,
+}
+
+/// Compacts the engine of `inner` a step at a time until it is done.
+///
+/// The lock is only held for a step, so reads and writes carry on between
+/// them, and after each step the thread sleeps for as long as it is ahead
+/// of the rate limit.
+pub(crate) fn compact(
+    inner: &Arc<Mutex<DatabaseInner>>,
+    options: &CompactionOptions,
+) -> Result<CompactionStats, DatabaseError> {
+    let started = Instant::now();
+    let mut stats = CompactionStats::default();
+    loop {
+        let step = DatabaseInner::lock(inner).compact_step(options.step_size)?;
+        stats.steps += 1;
+        stats.work += step.work;
+        stats.reclaimed += step.reclaimed;
+        if step.done {
+            return Ok(stats);
+        }
+
+        let Some(rate) = options.rate_limit else {
+            thread::yield_now();
+            continue;
+        };
+        let due = Duration::from_secs_f64(stats.work as f64 / rate as f64);
+        match due.checked_sub(started.elapsed()) {
+            Some(ahead) if !ahead.is_zero() => {
+                thread::sleep(ahead);
+                stats.throttled += ahead;
+            }
+            _ => thread::yield_now(),
+        }
+    }
+}
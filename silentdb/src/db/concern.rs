@@ -0,0 +1,49 @@
+// src/db/concern.rs
+
+/// How durable a write must be before the operation making it returns.
+///
+/// Every write is logged to the write-ahead log and applied to the storage
+/// engine before it returns; the concern only decides what is forced to
+/// stable storage first. Writes are applied in the order they are made
+/// whatever their concern, so a write is as durable as the most durable
+/// one made after it.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use silentdb::{Database, WriteConcern};
+/// let db = Database::open("data").unwrap();
+/// let events = db.collection("events").write_concern(WriteConcern::Memory);
+/// let orders = db.collection("orders").write_concern(WriteConcern::Journaled);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WriteConcern {
+    /// Return once the write is applied to the engine. Its log record is
+    /// synced by a later write or checkpoint, so a crash before then may
+    /// lose it.
+    Memory,
+    /// Return once the log record is committed under the log's
+    /// `SyncPolicy`: synced at once unless the policy is an interval.
+    #[default]
+    Logged,
+    /// Return once the log record is synced, whatever the log's policy.
+    Journaled,
+    /// Return once a checkpoint has flushed the write to the engine's
+    /// files.
+    Checkpointed,
+}
+
+/// What a cursor reads between its batches.
+///
+/// Within a transaction, reads always see the transaction's snapshot.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReadConcern {
+    /// Read the latest committed data as each batch is fetched, so writes
+    /// committed after a cursor is opened may show up in later batches.
+    #[default]
+    Local,
+    /// Read a snapshot taken when the cursor is opened, so every batch
+    /// sees the same data. The snapshot keeps the values later writes
+    /// replace until the cursor is exhausted, killed or times out.
+    Snapshot,
+}
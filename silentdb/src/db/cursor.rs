@@ -0,0 +1,305 @@
+// src/db/cursor.rs
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use silentdb_data_encoding::Document;
+
+use super::database::DatabaseInner;
+use super::error::DatabaseError;
+use super::metrics::Op;
+use super::planner::{QueryScan, Stored};
+use super::sort::{ExternalSort, Sorted};
+use crate::accounting::{MemoryCategory, MemoryScope};
+
+/// Documents a cursor fetches per batch unless told otherwise.
+const DEFAULT_BATCH_SIZE: usize = 101;
+/// How long a cursor may sit idle before it is killed.
+const DEFAULT_CURSOR_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// A handle to the results of a query, fetched from storage in batches.
+///
+/// A cursor holds one batch of documents at a time and fetches the next one
+/// only once the current one is used up. Between batches, where the query
+/// left off is kept by the database under the cursor's id, so the cursor
+/// holds no lock and writes made in the meantime may show up in later
+/// batches, unless the cursor was opened with the `Snapshot` read concern.
+///
+/// A sorted query's cursor reads every match into an external sort when
+/// its first document is asked for, then returns them in order.
+///
+/// A cursor left idle longer than the database's cursor timeout, or killed
+/// with `Database::kill_cursors`, is forgotten, and fetching from it fails
+/// with `CursorNotFound`. Dropping a cursor kills it.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use silentdb::Database;
+/// # use silentdb_data_encoding::Document;
+/// let users = Database::open("data").unwrap().collection("users");
+///
+/// let cursor = users.find(&Document::new()).unwrap().batch_size(500);
+/// for user in cursor {
+///     println!("{:?}", user.unwrap());
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Cursor {
+    id: u64,
+    inner: Arc<Mutex<DatabaseInner>>,
+    batch_size: usize,
+    score_field: Option<String>,
+    buffer: VecDeque<Document>,
+    exhausted: bool,
+    sort: Option<SortState>,
+}
+
+/// Where a sorted cursor's sort is at.
+#[derive(Debug)]
+enum SortState {
+    /// Waiting for the query's matches.
+    Pending(ExternalSort),
+    Sorted(Sorted),
+}
+
+impl Cursor {
+    pub(crate) fn new(id: u64, inner: Arc<Mutex<DatabaseInner>>) -> Self {
+        Cursor {
+            id,
+            inner,
+            batch_size: DEFAULT_BATCH_SIZE,
+            score_field: None,
+            buffer: VecDeque::new(),
+            exhausted: false,
+            sort: None,
+        }
+    }
+
+    /// Returns the documents in the order `sort` puts them in instead.
+    pub(crate) fn sorted(mut self, sort: ExternalSort) -> Self {
+        self.sort = Some(SortState::Pending(sort));
+        self
+    }
+
+    /// Returns the id the database keeps the cursor's position under.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Sets how many documents to fetch per batch. Zero is treated as one.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Adds each document's relevance to the `$text` query that made the
+    /// cursor to the document as a double under `field`. Has no effect on
+    /// other queries.
+    pub fn text_score(mut self, field: &str) -> Self {
+        self.score_field = Some(field.to_string());
+        self
+    }
+
+    /// Returns the remaining documents.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error fetching a batch.
+    pub fn try_collect(self) -> Result<Vec<Document>, DatabaseError> {
+        self.collect()
+    }
+
+    /// Kills the cursor, releasing its position in the database.
+    pub fn kill(self) {}
+
+    /// Reads every remaining match into `sort` and returns them sorted.
+    fn sort(&mut self, mut sort: ExternalSort) -> Result<Sorted, DatabaseError> {
+        loop {
+            for document in self.buffer.drain(..) {
+                sort.push(document)?;
+            }
+            if self.exhausted {
+                return sort.finish();
+            }
+            self.fetch()?;
+        }
+    }
+
+    fn fetch(&mut self) -> Result<(), DatabaseError> {
+        let _scope = MemoryScope::enter(MemoryCategory::Cursor);
+        let started = Instant::now();
+        let mut inner = DatabaseInner::lock(&self.inner);
+        let batch = inner.get_more(self.id, self.batch_size);
+        inner.metrics.record(Op::GetMore, started.elapsed());
+        let batch = batch?;
+        self.exhausted = batch.len() < self.batch_size;
+        for stored in batch {
+            let mut document = stored.document;
+            if let (Some(field), Some(score)) = (&self.score_field, stored.score) {
+                document.insert(field.as_str(), score);
+            }
+            self.buffer.push_back(document);
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for Cursor {
+    type Item = Result<Document, DatabaseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.sort.take() {
+            Some(SortState::Pending(sort)) => match self.sort(sort) {
+                Ok(sorted) => self.sort = Some(SortState::Sorted(sorted)),
+                Err(e) => {
+                    if let Ok(mut inner) = self.inner.lock() {
+                        inner.kill_cursor(self.id);
+                    }
+                    self.exhausted = true;
+                    return Some(Err(e));
+                }
+            },
+            Some(state) => self.sort = Some(state),
+            None => {}
+        }
+        if let Some(SortState::Sorted(sorted)) = &mut self.sort {
+            return sorted.next();
+        }
+        if self.buffer.is_empty() && !self.exhausted {
+            if let Err(e) = self.fetch() {
+                self.exhausted = true;
+                return Some(Err(e));
+            }
+        }
+        self.buffer.pop_front().map(Ok)
+    }
+}
+
+impl Drop for Cursor {
+    fn drop(&mut self) {
+        if !self.exhausted {
+            if let Ok(mut inner) = self.inner.lock() {
+                inner.kill_cursor(self.id);
+            }
+        }
+    }
+}
+
+/// Where an open cursor's query left off.
+#[derive(Debug)]
+pub(crate) struct CursorState {
+    scan: QueryScan,
+    /// The transaction holding the snapshot the cursor reads, if it has
+    /// one of its own, which ends with the cursor.
+    snapshot: Option<u64>,
+    last_used: Instant,
+}
+
+impl CursorState {
+    pub(crate) fn new(scan: QueryScan, snapshot: Option<u64>) -> Self {
+        CursorState {
+            scan,
+            snapshot,
+            last_used: Instant::now(),
+        }
+    }
+}
+
+/// The open cursors of a database, by id.
+#[derive(Debug)]
+pub(crate) struct CursorTable {
+    open: HashMap<u64, CursorState>,
+    next_id: u64,
+    timeout: Duration,
+}
+
+impl CursorTable {
+    pub(crate) fn new() -> Self {
+        CursorTable {
+            open: HashMap::new(),
+            next_id: 1,
+            timeout: DEFAULT_CURSOR_TIMEOUT,
+        }
+    }
+
+    pub(crate) fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.open.len()
+    }
+
+    /// Returns the ids of the cursors idle for longer than the timeout.
+    fn expired(&self) -> Vec<u64> {
+        self.open
+            .iter()
+            .filter(|(_, state)| state.last_used.elapsed() > self.timeout)
+            .map(|(id, _)| *id)
+            .collect()
+    }
+}
+
+impl DatabaseInner {
+    /// Registers a cursor and returns its id.
+    pub(crate) fn open_cursor(&mut self, state: CursorState) -> u64 {
+        self.reap_cursors();
+        let id = self.cursors.next_id;
+        self.cursors.next_id += 1;
+        self.cursors.open.insert(id, state);
+        self.metrics.cursor_opened();
+        id
+    }
+
+    /// Forgets the cursor `id`, returning `false` if it was not open.
+    pub(crate) fn kill_cursor(&mut self, id: u64) -> bool {
+        match self.cursors.open.remove(&id) {
+            Some(state) => {
+                self.release_cursor(state);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Fetches the next `limit` documents of the cursor `id`, forgetting
+    /// the cursor once it runs out.
+    pub(crate) fn get_more(&mut self, id: u64, limit: usize) -> Result<Vec<Stored>, DatabaseError> {
+        self.reap_cursors();
+        let mut state = self
+            .cursors
+            .open
+            .remove(&id)
+            .ok_or(DatabaseError::CursorNotFound(id))?;
+        let batch = match state.scan.next(self, limit) {
+            Ok(batch) => batch,
+            Err(e) => {
+                self.release_cursor(state);
+                return Err(e);
+            }
+        };
+        if batch.len() == limit {
+            state.last_used = Instant::now();
+            self.cursors.open.insert(id, state);
+        } else {
+            self.release_cursor(state);
+        }
+        Ok(batch)
+    }
+
+    /// Forgets every cursor idle for longer than the timeout.
+    fn reap_cursors(&mut self) {
+        for id in self.cursors.expired() {
+            self.kill_cursor(id);
+        }
+    }
+
+    /// Ends the snapshot of a forgotten cursor, if it has one.
+    fn release_cursor(&mut self, state: CursorState) {
+        if let Some(snapshot) = state.snapshot {
+            self.transactions.release(snapshot);
+        }
+    }
+}
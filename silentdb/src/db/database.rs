@@ -0,0 +1,1156 @@
+// src/db/database.rs
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+
+use silentdb_data_encoding::{Array, Document, Value};
+
+use super::backup::{backup, restore, restore_stream, stream_backup, BackupStats};
+use super::catalog::{self, CollectionInfo, CollectionOptions};
+use super::change::{describe, is_collection};
+use super::check::{check, repair, CheckLevel, CheckReport};
+use super::checkpoint::{CheckpointState, CheckpointStats};
+use super::collection::{Collection, FindOptions};
+use super::compaction::{compact, CompactionOptions, CompactionStats};
+use super::concern::WriteConcern;
+use super::cursor::CursorTable;
+use super::error::DatabaseError;
+use super::lock::LockStats;
+use super::metrics::{Metrics, MetricsSnapshot};
+use super::recovery::{recover, RecoveryStats, RecoveryTarget};
+use super::replication::{
+    enable_oplog, load_replication, oplog_after, OpTime, OplogEntry, ReplicationState,
+};
+use super::scheduler::{
+    run_task, scheduler_state, spawn_scheduler, MaintenanceTask, SchedulerState,
+};
+use super::schema::Migrations;
+use super::stats::CollectionStats;
+use super::transaction::{Target, Transaction, TransactionTable};
+use super::ttl::{expire, TtlState, TtlStats};
+use super::users::{self, Role, UserInfo, UserRecord};
+use super::validation::ValidationStats;
+use crate::accounting::memory_stats;
+use crate::query::SqlQuery;
+use crate::storage::{
+    BTreeEngine, BTreeOptions, CompactionStep, Encryption, Entry, KeyRange, SegmentArchive,
+    StorageEngine, StorageError, StorageIssue, Wal, WalOptions, WalRecord, WalReplay,
+};
+
+/// Name of the directory holding the storage engine's files.
+const DATA_DIR: &str = "data";
+/// Name of the directory holding the write-ahead log.
+const WAL_DIR: &str = "wal";
+
+/// A database: a set of named collections over one storage engine.
+///
+/// Every change is logged to the write-ahead log as a single record before
+/// it is applied to the engine, and the log is replayed when the database
+/// is opened, so an operation that touches several keys is applied in full
+/// or not at all after a crash.
+///
+/// Checkpoints keep the log short: every minute, and whenever the log
+/// outgrows its size limit, the engine is flushed and the log records it
+/// now holds are removed, so only later records are replayed on opening.
+///
+/// `Database` is a cheap handle; clones and the collections they return
+/// share the same engine and log.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use silentdb::Database;
+/// # use silentdb_data_encoding::Document;
+/// let db = Database::open("data").unwrap();
+/// let users = db.collection("users");
+///
+/// let mut user = Document::new();
+/// user.insert("_id", 1);
+/// user.insert("name", "alice");
+/// users.insert_one(user).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct Database {
+    pub(crate) inner: Arc<Mutex<DatabaseInner>>,
+}
+
+impl Database {
+    /// Opens the database in `dir` on a `BTreeEngine`, creating it if needed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the engine or log cannot be opened or the log
+    /// cannot be replayed.
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Database, DatabaseError> {
+        let dir = dir.as_ref();
+        let engine = BTreeEngine::open(dir.join(DATA_DIR))?;
+        let wal = Wal::open(dir.join(WAL_DIR), WalOptions::new())?;
+        Database::with_engine(Box::new(engine), wal)
+    }
+
+    /// Opens the database in `dir` on a `BTreeEngine`, creating it if
+    /// needed, with its data files and log encrypted by `encryption`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the engine or log cannot be opened, including
+    /// when they were encrypted with keys that are unavailable or were not
+    /// encrypted at all, or the log cannot be replayed.
+    pub fn open_encrypted<P: AsRef<Path>>(
+        dir: P,
+        encryption: Encryption,
+    ) -> Result<Database, DatabaseError> {
+        let dir = dir.as_ref();
+        let options = BTreeOptions::new().encryption(encryption.clone());
+        let engine = BTreeEngine::open_with(dir.join(DATA_DIR), options)?;
+        let wal = Wal::open(dir.join(WAL_DIR), WalOptions::new().encryption(encryption))?;
+        Database::with_engine(Box::new(engine), wal)
+    }
+
+    /// Opens the database in `dir` on a `BTreeEngine`, creating it if
+    /// needed, with its log segments handed to `archive` as they are
+    /// sealed. See `Database::recover`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the engine or log cannot be opened or the log
+    /// cannot be replayed.
+    pub fn open_archived<P, A>(dir: P, archive: A) -> Result<Database, DatabaseError>
+    where
+        P: AsRef<Path>,
+        A: SegmentArchive + 'static,
+    {
+        let dir = dir.as_ref();
+        let engine = BTreeEngine::open(dir.join(DATA_DIR))?;
+        let wal = Wal::open(dir.join(WAL_DIR), WalOptions::new().archive(archive))?;
+        Database::with_engine(Box::new(engine), wal)
+    }
+
+    /// Opens a database over `engine`, first replaying `wal` into it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a log record is damaged or cannot be applied.
+    pub fn with_engine(
+        engine: Box<dyn StorageEngine>,
+        wal: Wal,
+    ) -> Result<Database, DatabaseError> {
+        let (scheduler, woken) = scheduler_state();
+        let mut inner = DatabaseInner {
+            engine,
+            wal,
+            cursors: CursorTable::new(),
+            transactions: TransactionTable::new(),
+            ttl: TtlState::default(),
+            checkpoints: CheckpointState::new(),
+            scheduler,
+            validation: ValidationStats::default(),
+            migrations: Migrations::default(),
+            metrics: Metrics::default(),
+            collection_stats: HashMap::new(),
+            collections: BTreeSet::new(),
+            replication: ReplicationState::default(),
+            committed: Arc::new(Condvar::new()),
+        };
+        inner.replay()?;
+        inner.collections = catalog::load_registered(&inner)?;
+        inner.replication = load_replication(&inner)?;
+        let inner = Arc::new(Mutex::new(inner));
+        spawn_scheduler(&inner, woken);
+        Ok(Database { inner })
+    }
+
+    /// Returns a handle to the collection `name`.
+    ///
+    /// Collections are created by their first write, or explicitly by
+    /// `create_collection`.
+    pub fn collection(&self, name: &str) -> Collection {
+        Collection::new(name, Arc::clone(&self.inner), None)
+    }
+
+    /// Creates the collection `name` and returns a handle to it.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidCollectionName` if `name` is empty, starts with `$`
+    /// or contains `.$`, or `CollectionExists` if there is one already.
+    pub fn create_collection(&self, name: &str) -> Result<Collection, DatabaseError> {
+        self.create_collection_with(name, CollectionOptions::new())
+    }
+
+    /// Creates the collection `name` with `options`, in one commit, and
+    /// returns a handle to it.
+    ///
+    /// # Errors
+    ///
+    /// As `create_collection`.
+    pub fn create_collection_with(
+        &self,
+        name: &str,
+        options: CollectionOptions,
+    ) -> Result<Collection, DatabaseError> {
+        catalog::create(&mut DatabaseInner::lock(&self.inner), name, &options)?;
+        Ok(self.collection(name))
+    }
+
+    /// Removes the collection `name` with its documents, indexes and
+    /// settings, in one commit. Returns `false` if there is no such
+    /// collection.
+    ///
+    /// Change streams see each document deleted.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the collection cannot be read, or `WriteConflict`
+    /// if an open transaction has written to it.
+    pub fn drop_collection(&self, name: &str) -> Result<bool, DatabaseError> {
+        catalog::drop(&mut DatabaseInner::lock(&self.inner), name)
+    }
+
+    /// Moves the collection `from`, with its documents, indexes and
+    /// settings, to the name `to`, in one commit.
+    ///
+    /// The collection is copied in a single log record, so renaming a large
+    /// one takes memory in proportion to its size. Change streams see each
+    /// document deleted from `from` and inserted into `to`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidCollectionName` if `to` cannot name a collection,
+    /// `CollectionNotFound` if there is no collection `from`,
+    /// `CollectionExists` if there is one named `to`, or `WriteConflict` if
+    /// an open transaction has written to `from`.
+    pub fn rename_collection(&self, from: &str, to: &str) -> Result<(), DatabaseError> {
+        catalog::rename(&mut DatabaseInner::lock(&self.inner), from, to)
+    }
+
+    /// Returns the catalog entry of the collection `name`, with its
+    /// indexes and settings, or `None` if there is no such collection.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the catalog cannot be read.
+    pub fn collection_info(&self, name: &str) -> Result<Option<CollectionInfo>, DatabaseError> {
+        catalog::info(&DatabaseInner::lock(&self.inner), name)
+    }
+
+    /// Runs the SQL `SELECT` statement `sql` as the find `SqlQuery`
+    /// translates it to, planned and matched as any other, and returns an
+    /// iterator over its rows.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Query` if `sql` is not a statement `SqlQuery` understands.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use silentdb::Database;
+    /// let db = Database::open("data").unwrap();
+    /// let rows = db
+    ///     .sql("SELECT name FROM users WHERE age BETWEEN 20 AND 29 ORDER BY name LIMIT 5")
+    ///     .unwrap();
+    /// for row in rows {
+    ///     println!("{}", row.unwrap());
+    /// }
+    /// ```
+    pub fn sql(
+        &self,
+        sql: &str,
+    ) -> Result<impl Iterator<Item = Result<Document, DatabaseError>>, DatabaseError> {
+        let query = SqlQuery::parse(sql)?;
+        let mut options = FindOptions::new();
+        if let Some(sort) = query.sort {
+            options = options.sort(sort);
+        }
+        if let Some(projection) = query.projection {
+            options = options.projection(projection);
+        }
+        let skip = usize::try_from(query.skip).unwrap_or(usize::MAX);
+        let limit = query.limit.map_or(usize::MAX, |limit| {
+            usize::try_from(limit).unwrap_or(usize::MAX)
+        });
+        let cursor = self
+            .collection(&query.collection)
+            .find_with(&query.filter, &options)?;
+        Ok(cursor.skip(skip).take(limit))
+    }
+
+    /// Creates the user `name`, who authenticates with `password` and may do
+    /// what `roles` allow, in the `$system.$users` namespace. The password
+    /// is kept only as a SCRAM-SHA-256 credential salted with random bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidUserName` if `name` is empty, or `UserExists` if there
+    /// is a user `name` already.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use silentdb::{Database, Role};
+    /// let db = Database::open("data").unwrap();
+    /// db.create_user("reporting", "s3cret", &[Role::Read]).unwrap();
+    /// ```
+    pub fn create_user(
+        &self,
+        name: &str,
+        password: &str,
+        roles: &[Role],
+    ) -> Result<(), DatabaseError> {
+        users::create(&mut DatabaseInner::lock(&self.inner), name, password, roles)
+    }
+
+    /// Removes the user `name`. Returns `false` if there is no such user.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the users cannot be read or written.
+    pub fn drop_user(&self, name: &str) -> Result<bool, DatabaseError> {
+        users::drop(&mut DatabaseInner::lock(&self.inner), name)
+    }
+
+    /// Replaces the roles of the user `name` with `roles`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `UserNotFound` if there is no user `name`.
+    pub fn set_user_roles(&self, name: &str, roles: &[Role]) -> Result<(), DatabaseError> {
+        users::set_roles(&mut DatabaseInner::lock(&self.inner), name, roles)
+    }
+
+    /// Sets the password of the user `name` to `password`, with a new salt.
+    ///
+    /// # Errors
+    ///
+    /// Returns `UserNotFound` if there is no user `name`.
+    pub fn set_user_password(&self, name: &str, password: &str) -> Result<(), DatabaseError> {
+        users::set_password(&mut DatabaseInner::lock(&self.inner), name, password)
+    }
+
+    /// Returns every user with their roles, in name order.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the users cannot be read.
+    pub fn users(&self) -> Result<Vec<UserInfo>, DatabaseError> {
+        users::list(&DatabaseInner::lock(&self.inner))
+    }
+
+    /// Returns the stored roles and credential of the user `name`, if there
+    /// is one.
+    pub(crate) fn user(&self, name: &str) -> Result<Option<UserRecord>, DatabaseError> {
+        users::record(&DatabaseInner::lock(&self.inner), name)
+    }
+
+    /// Begins a transaction. See `Transaction` for its guarantees.
+    pub fn begin(&self) -> Transaction {
+        let id = DatabaseInner::lock(&self.inner).transactions.begin();
+        Transaction::new(id, Arc::clone(&self.inner))
+    }
+
+    /// Sets how long a transaction waits for a lock held by another before
+    /// failing with `LockTimeout`. The default is five seconds.
+    pub fn set_lock_timeout(&self, timeout: Duration) {
+        DatabaseInner::lock(&self.inner)
+            .transactions
+            .locks
+            .set_timeout(timeout);
+    }
+
+    /// Returns how often transactions have waited for locks, deadlocked
+    /// and timed out since the database was opened.
+    pub fn lock_stats(&self) -> LockStats {
+        DatabaseInner::lock(&self.inner).transactions.locks.stats()
+    }
+
+    /// Sets how long a cursor may sit idle between batches before it is
+    /// killed. The default is ten minutes.
+    pub fn set_cursor_timeout(&self, timeout: Duration) {
+        DatabaseInner::lock(&self.inner)
+            .cursors
+            .set_timeout(timeout);
+    }
+
+    /// Kills the cursors with the given ids and returns how many were
+    /// open. Fetching from a killed cursor fails with `CursorNotFound`.
+    pub fn kill_cursors(&self, ids: &[u64]) -> usize {
+        let mut inner = DatabaseInner::lock(&self.inner);
+        ids.iter().filter(|id| inner.kill_cursor(**id)).count()
+    }
+
+    /// Sets how long the TTL reaper waits between expiration passes and
+    /// runs a pass at once. The default is one minute.
+    pub fn set_ttl_interval(&self, interval: Duration) {
+        self.set_task_interval(MaintenanceTask::TtlReaper, interval);
+    }
+
+    /// Deletes every expired document now, without waiting for the TTL
+    /// reaper, and returns how many were deleted. See
+    /// `IndexOptions::expire_after` for when a document expires.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the indexes cannot be read or a delete fails.
+    pub fn expire_documents(&self) -> Result<u64, DatabaseError> {
+        expire(&self.inner)
+    }
+
+    /// Returns what the TTL reaper has done since the database was opened.
+    pub fn ttl_stats(&self) -> TtlStats {
+        DatabaseInner::lock(&self.inner).ttl.stats()
+    }
+
+    /// Compacts the storage engine, reclaiming the space held by replaced
+    /// and deleted documents, and returns what was done.
+    ///
+    /// Compaction runs in steps with the database unlocked between them,
+    /// so other handles can keep reading and writing; run it on its own
+    /// thread to carry on meanwhile. The rate limit in `options` keeps it
+    /// from crowding out that traffic.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the engine fails to read or write its files.
+    /// A compaction cut short by an error carries on from where it was
+    /// the next time this is called.
+    pub fn compact(&self, options: CompactionOptions) -> Result<CompactionStats, DatabaseError> {
+        compact(&self.inner, &options)
+    }
+
+    /// Checks the database for damage at `level` and returns what was
+    /// found, along with the repairs `repair` would make for it. Nothing
+    /// is changed, but the storage engine is flushed first.
+    ///
+    /// The database is locked while the check runs.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the engine fails to read its files, or the keys
+    /// they are encrypted with are unavailable. Damage is reported, not
+    /// returned as an error.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use silentdb::{CheckLevel, Database};
+    /// let db = Database::open("data").unwrap();
+    /// let report = db.check(CheckLevel::Full).unwrap();
+    /// for issue in &report.issues {
+    ///     println!("{}", issue);
+    /// }
+    /// if !report.plan.is_empty() {
+    ///     db.repair(CheckLevel::Full).unwrap();
+    /// }
+    /// ```
+    pub fn check(&self, level: CheckLevel) -> Result<CheckReport, DatabaseError> {
+        check(&mut DatabaseInner::lock(&self.inner), level)
+    }
+
+    /// Checks the database at `level` and makes the repairs planned, round
+    /// after round until none are left, then takes a checkpoint. Returns
+    /// the last check's report, holding only the issues no repair fixes,
+    /// with `repaired` listing what was done.
+    ///
+    /// Damaged records and invalid documents are dropped, so their
+    /// documents are lost; index entries are put back or deleted to match
+    /// the documents left. Run it with no transactions open, since the
+    /// records dropped are not kept for their snapshots.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors `check` does, and an error if a repair fails.
+    pub fn repair(&self, level: CheckLevel) -> Result<CheckReport, DatabaseError> {
+        repair(&mut DatabaseInner::lock(&self.inner), level)
+    }
+
+    /// Takes a checkpoint now, without waiting for the checkpointer, and
+    /// returns the sequence number of the last log record it covers.
+    ///
+    /// A checkpoint flushes the storage engine, records the log position
+    /// it is up to date with, and removes the log segments before it, so
+    /// opening the database only replays the records after it. Change
+    /// streams that have not read past the removed records fail with
+    /// `HistoryLost`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the engine cannot be flushed or the log cannot
+    /// be checkpointed.
+    pub fn checkpoint(&self) -> Result<u64, DatabaseError> {
+        DatabaseInner::lock(&self.inner).checkpoint()
+    }
+
+    /// Sets how long the checkpointer waits between checkpoints and takes
+    /// one at once. The default is one minute.
+    pub fn set_checkpoint_interval(&self, interval: Duration) {
+        self.set_task_interval(MaintenanceTask::Checkpoint, interval);
+    }
+
+    /// Turns the scheduled runs of `task` on or off. A run under way is
+    /// left to finish.
+    pub fn set_task_enabled(&self, task: MaintenanceTask, enabled: bool) {
+        DatabaseInner::lock(&self.inner)
+            .scheduler
+            .set_enabled(task, enabled);
+    }
+
+    /// Sets how long the scheduler waits between runs of `task`, and runs
+    /// it at once if it is enabled.
+    pub fn set_task_interval(&self, task: MaintenanceTask, interval: Duration) {
+        DatabaseInner::lock(&self.inner)
+            .scheduler
+            .set_interval(task, interval);
+    }
+
+    /// Sets how far each wait between runs of `task` is randomly stretched
+    /// or shrunk, as a fraction of it from 0 to 1, so tasks of databases
+    /// opened together do not all run at once. The default is 0.1.
+    pub fn set_task_jitter(&self, task: MaintenanceTask, jitter: f64) {
+        DatabaseInner::lock(&self.inner)
+            .scheduler
+            .set_jitter(task, jitter);
+    }
+
+    /// Runs `task` now, whether or not it is enabled, and returns what it
+    /// did, as its latest run in `task_status` also shows.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error the task failed with, which puts off its next
+    /// scheduled run as a scheduled failure would.
+    pub fn run_task(&self, task: MaintenanceTask) -> Result<Document, DatabaseError> {
+        run_task(&self.inner, task)
+    }
+
+    /// Describes each maintenance task's schedule and latest run, as
+    /// documents of the form:
+    ///
+    /// ```text
+    /// {"task": "ttl", "enabled": true, "intervalMillis": 60000,
+    ///  "jitter": 0.1, "running": false, "runs": 3, "failures": 0,
+    ///  "nextRunMillis": 41250,
+    ///  "lastRun": {"started": <date>, "tookMillis": 2, "ok": true,
+    ///              "result": {"expired": 12}}}
+    /// ```
+    ///
+    /// A failed run has `"ok": false` and an `error` message instead of a
+    /// `result`. Scheduled runs back off after failures, waiting twice as
+    /// long after each one in a row, up to an hour.
+    pub fn task_status(&self) -> Vec<Document> {
+        DatabaseInner::lock(&self.inner).scheduler.status()
+    }
+
+    /// Sets how large the write-ahead log may grow, in bytes, before the
+    /// commit that takes it past that size checkpoints it. The default is
+    /// 64 MiB.
+    pub fn set_checkpoint_wal_size(&self, bytes: u64) {
+        DatabaseInner::lock(&self.inner)
+            .checkpoints
+            .set_wal_size(bytes);
+    }
+
+    /// Returns what checkpointing has done since the database was opened.
+    pub fn checkpoint_stats(&self) -> CheckpointStats {
+        DatabaseInner::lock(&self.inner).checkpoints.stats()
+    }
+
+    /// Returns how many writes collection validators have rejected or
+    /// warned about since the database was opened.
+    pub fn validation_stats(&self) -> ValidationStats {
+        DatabaseInner::lock(&self.inner).validation.clone()
+    }
+
+    /// Returns the database's metrics as a document: how many operations
+    /// of each kind have run since it was opened and how long they took,
+    /// as cumulative latency buckets; the open cursors; the page cache's
+    /// hits and misses; the write-ahead log's syncs and size; the bytes
+    /// compaction would reclaim; and, with a `TrackingAllocator` installed,
+    /// the memory allocated for decoding, caching, sorting and cursors.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use silentdb::Database;
+    /// let db = Database::open("data").unwrap();
+    /// let metrics = db.metrics();
+    /// println!("{:?}", metrics.get("ops"));
+    /// ```
+    pub fn metrics(&self) -> Document {
+        DatabaseInner::lock(&self.inner)
+            .metrics_snapshot()
+            .to_document()
+    }
+
+    /// Returns the same metrics as `metrics`, in the Prometheus text
+    /// exposition format.
+    pub fn prometheus_metrics(&self) -> String {
+        DatabaseInner::lock(&self.inner)
+            .metrics_snapshot()
+            .to_prometheus()
+    }
+
+    /// Returns the names of the collections, in order: those in the
+    /// catalog, along with any created before it that hold documents or
+    /// indexes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage engine cannot list its namespaces.
+    pub fn list_collections(&self) -> Result<Vec<String>, DatabaseError> {
+        catalog::list(&DatabaseInner::lock(&self.inner))
+    }
+
+    /// Writes a consistent snapshot of the database to the directory
+    /// `path`, creating it if needed, and returns what was copied.
+    ///
+    /// The snapshot is read through a transaction, so writes may carry on
+    /// while it runs without any of them showing up half applied. Each
+    /// collection is written to its own file of BSON documents, and a
+    /// `manifest.bson` written last lists the collections and their indexes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a collection cannot be read or a file cannot be
+    /// written.
+    pub fn backup<P: AsRef<Path>>(&self, path: P) -> Result<BackupStats, DatabaseError> {
+        backup(&self.inner, path.as_ref())
+    }
+
+    /// Loads a backup written by `Database::backup` from the directory
+    /// `path`, recreating its indexes and documents, and returns what was
+    /// loaded. Backups are meant to be restored into an empty database.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the backup is missing or malformed, an index
+    /// cannot be created, or a document's `_id` is already taken.
+    pub fn restore<P: AsRef<Path>>(&self, path: P) -> Result<BackupStats, DatabaseError> {
+        restore(&self.inner, path.as_ref())
+    }
+
+    /// Streams a consistent snapshot of the database to `out`, followed by
+    /// the log records committed while it was written, and returns what
+    /// was streamed.
+    ///
+    /// Writes carry on meanwhile, as with `backup`, so the stream can be
+    /// piped somewhere slow, such as over SSH or into object storage,
+    /// without pausing the database. It is a sequence of BSON documents,
+    /// readable back with `restore_stream`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a collection or the log cannot be read or `out`
+    /// cannot be written.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use silentdb::Database;
+    /// let db = Database::open("data").unwrap();
+    /// let stats = db.stream_backup(std::io::stdout().lock()).unwrap();
+    /// eprintln!("streamed {} documents", stats.documents);
+    /// ```
+    pub fn stream_backup<W: std::io::Write>(&self, out: W) -> Result<BackupStats, DatabaseError> {
+        stream_backup(&self.inner, out)
+    }
+
+    /// Loads a backup written by `stream_backup` from `input`, and returns
+    /// what was loaded. Like `restore`, streams are meant to be restored
+    /// into an empty database.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stream is malformed or cut short, an index
+    /// cannot be created, or a document's `_id` is already taken.
+    pub fn restore_stream<R: std::io::Read>(&self, input: R) -> Result<BackupStats, DatabaseError> {
+        restore_stream(&self.inner, input)
+    }
+
+    /// Seals the log's current segment and archives every segment not
+    /// archived yet, so the archive holds every commit made so far. Does
+    /// nothing more than start a new segment if the log has no archive.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a segment cannot be synced or archived.
+    pub fn seal_log(&self) -> Result<(), DatabaseError> {
+        Ok(DatabaseInner::lock(&self.inner).wal.seal()?)
+    }
+
+    /// Recovers the database to a point in time: loads the backup written
+    /// by `Database::backup` in `backup`, then replays the log records
+    /// archived in `archive` by a `DirArchive` from the first one the
+    /// backup does not include up to `target`, and returns what was done.
+    ///
+    /// This undoes mistakes such as a bad bulk delete: restore the last
+    /// backup taken before it into an empty database, up to a time or
+    /// sequence number just before it. Archived records are read with the
+    /// encryption the database's log is opened with, and each is committed
+    /// as a record of the database's own log.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of `restore`, `LogGap` if a record between the
+    /// backup and the target is missing from the archive, or an error if
+    /// an archived record is damaged.
+    pub fn recover<P, Q>(
+        &self,
+        backup: P,
+        archive: Q,
+        target: RecoveryTarget,
+    ) -> Result<RecoveryStats, DatabaseError>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        recover(&self.inner, backup.as_ref(), archive.as_ref(), target)
+    }
+
+    /// Starts keeping an oplog of at most `size` bytes, or resizes the one
+    /// kept, for secondaries to follow. See `Secondary`.
+    ///
+    /// Each commit from then on adds an entry recording its writes to the
+    /// oplog, in the same log record, and removes the oldest entries once
+    /// they outgrow `size`. A secondary that falls further behind than the
+    /// oplog reaches must be synced anew.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the setting cannot be committed.
+    pub fn enable_oplog(&self, size: u64) -> Result<(), DatabaseError> {
+        enable_oplog(&mut DatabaseInner::lock(&self.inner), size)
+    }
+
+    /// Returns the time of the newest oplog entry, or `None` if the
+    /// database keeps no oplog.
+    pub fn last_optime(&self) -> Option<OpTime> {
+        DatabaseInner::lock(&self.inner).replication.last()
+    }
+
+    /// Returns up to `limit` oplog entries committed after `after`, or from
+    /// the oldest one kept if `None`, waiting up to `wait` for one to be
+    /// committed if there are none yet.
+    ///
+    /// # Errors
+    ///
+    /// Returns `OplogDisabled` if the database keeps no oplog, or
+    /// `OplogTruncated` if the entry `after` has been removed.
+    pub fn oplog_after(
+        &self,
+        after: Option<OpTime>,
+        limit: usize,
+        wait: Duration,
+    ) -> Result<Vec<OplogEntry>, DatabaseError> {
+        oplog_after(&self.inner, after, limit, wait)
+    }
+}
+
+/// The engine, log, open cursors, open transactions, TTL reaper,
+/// checkpoint, scheduler and oplog state shared by a database's handles,
+/// and the condition change streams and secondaries wait on for commits.
+pub(crate) struct DatabaseInner {
+    engine: Box<dyn StorageEngine>,
+    wal: Wal,
+    pub(crate) cursors: CursorTable,
+    pub(crate) transactions: TransactionTable,
+    pub(crate) ttl: TtlState,
+    pub(crate) checkpoints: CheckpointState,
+    pub(crate) scheduler: SchedulerState,
+    pub(crate) validation: ValidationStats,
+    /// The schema migrations registered since the database was opened.
+    pub(crate) migrations: Migrations,
+    pub(crate) metrics: Metrics,
+    /// The statistics last gathered for each collection, outside any
+    /// transaction.
+    pub(crate) collection_stats: HashMap<String, CollectionStats>,
+    /// The collections the catalog holds an entry for, as committed.
+    pub(crate) collections: BTreeSet<String>,
+    pub(crate) replication: ReplicationState,
+    pub(crate) committed: Arc<Condvar>,
+}
+
+impl fmt::Debug for DatabaseInner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DatabaseInner")
+            .field("wal", &self.wal)
+            .field("cursors", &self.cursors)
+            .field("transactions", &self.transactions)
+            .field("ttl", &self.ttl)
+            .field("checkpoints", &self.checkpoints)
+            .field("scheduler", &self.scheduler)
+            .field("validation", &self.validation)
+            .field("migrations", &self.migrations)
+            .field("metrics", &self.metrics)
+            .finish_non_exhaustive()
+    }
+}
+
+impl DatabaseInner {
+    /// Locks the shared state of a database handle.
+    pub(crate) fn lock(inner: &Mutex<DatabaseInner>) -> MutexGuard<'_, DatabaseInner> {
+        inner.lock().expect("database lock poisoned")
+    }
+
+    /// Gathers the database's metrics with the gauges as they are now.
+    pub(crate) fn metrics_snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            metrics: self.metrics.clone(),
+            open_cursors: self.cursors.len(),
+            engine: self.engine.stats(),
+            wal_syncs: self.wal.syncs(),
+            wal_size: self.wal.size(),
+            memory: memory_stats(),
+        }
+    }
+
+    /// Runs one step of the engine's compaction. See
+    /// `StorageEngine::compact_step`.
+    pub(crate) fn compact_step(&mut self, budget: u64) -> Result<CompactionStep, DatabaseError> {
+        Ok(self.engine.compact_step(budget)?)
+    }
+
+    /// Checks the engine's files for damage. See `StorageEngine::verify`.
+    pub(crate) fn verify_storage(&mut self) -> Result<Vec<StorageIssue>, DatabaseError> {
+        Ok(self.engine.verify()?)
+    }
+
+    /// Deletes `records` from the engine, then rebuilds its structures if
+    /// `rebuild` says so. See `StorageEngine::repair`.
+    ///
+    /// The deletes bypass the log, since the values they remove cannot be
+    /// read for it, so callers checkpoint once they are done to keep the
+    /// log from putting back older values.
+    pub(crate) fn repair_storage(
+        &mut self,
+        records: &[(String, Vec<u8>)],
+        rebuild: bool,
+    ) -> Result<(), DatabaseError> {
+        for (namespace, key) in records {
+            self.engine.delete(namespace, key)?;
+        }
+        if rebuild {
+            self.engine.repair()?;
+        }
+        Ok(self.engine.flush()?)
+    }
+
+    /// Returns the names of the engine's namespaces.
+    pub(crate) fn namespaces(&self) -> Result<Vec<String>, DatabaseError> {
+        Ok(self.engine.namespaces()?)
+    }
+
+    /// Removes `namespace` from the engine, once its entries are deleted.
+    pub(crate) fn drop_namespace(&mut self, namespace: &str) -> Result<bool, DatabaseError> {
+        Ok(self.engine.drop_namespace(namespace)?)
+    }
+
+    /// Reads `key` in `namespace`, as seen by transaction `txn` if given.
+    pub(crate) fn get(
+        &self,
+        txn: Option<u64>,
+        namespace: &str,
+        key: &[u8],
+    ) -> Result<Option<Vec<u8>>, DatabaseError> {
+        if let Some(id) = txn {
+            let snapshot = self.transactions.snapshot(id)?;
+            if let Some(value) = snapshot.visible(namespace, key) {
+                return Ok(value);
+            }
+        }
+        Ok(self.engine.get(namespace, key)?)
+    }
+
+    /// Returns up to `limit` entries of `namespace` in `range`, as seen by
+    /// transaction `txn` if given.
+    pub(crate) fn scan(
+        &self,
+        txn: Option<u64>,
+        namespace: &str,
+        range: &KeyRange,
+        limit: usize,
+    ) -> Result<Vec<Entry>, DatabaseError> {
+        let Some(id) = txn else {
+            return Ok(self.engine.scan(namespace, range, limit)?);
+        };
+        let snapshot = self.transactions.snapshot(id)?;
+
+        // Merge each batch from the engine with the keys the transaction
+        // sees differently, up to the last key of the batch
+        let mut entries = Vec::new();
+        let mut range = range.clone();
+        loop {
+            let batch = self.engine.scan(namespace, &range, limit)?;
+            let last = match batch.last() {
+                Some((key, _)) if batch.len() == limit => Some(key.clone()),
+                _ => None,
+            };
+            let mut merged: BTreeMap<Vec<u8>, Option<Vec<u8>>> = batch
+                .into_iter()
+                .map(|(key, value)| (key, Some(value)))
+                .collect();
+            for key in snapshot.changed_keys(namespace, &range) {
+                if last.as_ref().is_none_or(|last| key <= *last) {
+                    merged.entry(key).or_insert(None);
+                }
+            }
+            for (key, value) in merged {
+                let value = snapshot.visible(namespace, &key).unwrap_or(value);
+                if let Some(value) = value {
+                    entries.push((key, value));
+                    if entries.len() == limit {
+                        return Ok(entries);
+                    }
+                }
+            }
+            match last {
+                Some(last) => range = range.after(&last),
+                None => return Ok(entries),
+            }
+        }
+    }
+
+    /// Returns the sequence number the next log record will get.
+    pub(crate) fn next_lsn(&self) -> u64 {
+        self.wal.next_lsn()
+    }
+
+    /// Returns the records archived in `dir` from sequence number `from`
+    /// on, read with the options of the database's log.
+    pub(crate) fn read_archive(&self, dir: &Path, from: u64) -> Result<WalReplay, DatabaseError> {
+        Ok(Wal::read_archive(dir, from, self.wal.options())?)
+    }
+
+    /// Returns the log records from sequence number `from` on.
+    ///
+    /// # Errors
+    ///
+    /// Returns `HistoryLost` if a checkpoint has removed the record `from`.
+    pub(crate) fn log_records(&self, from: u64) -> Result<WalReplay, DatabaseError> {
+        if from < self.wal.first_lsn() {
+            return Err(DatabaseError::HistoryLost(self.wal.first_lsn()));
+        }
+        Ok(self.wal.replay(from)?)
+    }
+
+    /// Flushes the engine and checkpoints the log at its last record,
+    /// unless nothing has been logged since the last checkpoint. Returns
+    /// the sequence number of the checkpoint.
+    ///
+    /// Pinned records are kept: the checkpoint then stops before the
+    /// earliest of them, though the engine is flushed all the same.
+    pub(crate) fn checkpoint(&mut self) -> Result<u64, DatabaseError> {
+        let last = self.wal.next_lsn() - 1;
+        if last == self.wal.checkpoint_lsn() {
+            return Ok(last);
+        }
+        self.engine.flush()?;
+        let lsn = match self.checkpoints.pinned() {
+            Some(pinned) => last.min(pinned - 1),
+            None => last,
+        };
+        if lsn <= self.wal.checkpoint_lsn() {
+            return Ok(self.wal.checkpoint_lsn());
+        }
+        let reclaimed = self.wal.checkpoint(lsn)?;
+        self.checkpoints.record(lsn, reclaimed);
+        Ok(lsn)
+    }
+
+    /// Logs `writes` as one record and applies them to the engine, along
+    /// with the oplog entry recording them if the database keeps an oplog.
+    ///
+    /// The record also describes the change to each document written, for
+    /// change streams, with the documents at `replaced` reported as
+    /// replaced rather than updated. The record is made as durable as
+    /// `concern` asks before this returns.
+    pub(crate) fn commit(
+        &mut self,
+        mut writes: Vec<Write>,
+        replaced: &BTreeSet<Target>,
+        concern: WriteConcern,
+    ) -> Result<(), DatabaseError> {
+        if writes.is_empty() {
+            return Ok(());
+        }
+        let appended = self.replication.append(&mut writes)?;
+        let mut changes = Vec::new();
+        for write in &writes {
+            let (namespace, key) = write.target();
+            if !is_collection(namespace) {
+                continue;
+            }
+            let before = self.engine.get(namespace, key)?;
+            let after = match write {
+                Write::Put { value, .. } => Some(value.as_slice()),
+                Write::Delete { .. } => None,
+            };
+            if before.is_none() && after.is_none() {
+                continue;
+            }
+            let target = (namespace.to_string(), key.to_vec());
+            changes.push(Value::Document(describe(
+                namespace,
+                key,
+                before.as_deref(),
+                after,
+                replaced.contains(&target),
+            )?));
+        }
+        let mut record = Document::new();
+        record.insert(
+            "writes",
+            Array::from_vec(
+                writes
+                    .iter()
+                    .map(|w| Value::Document(w.to_document()))
+                    .collect(),
+            ),
+        );
+        if !changes.is_empty() {
+            record.insert("changes", Array::from_vec(changes));
+        }
+        record.insert("time", Value::UTCDateTime(catalog::now()));
+        self.transactions.preserve(self.engine.as_ref(), &writes)?;
+        self.wal.append_deferred(&record)?;
+        match concern {
+            WriteConcern::Memory | WriteConcern::Checkpointed => {}
+            WriteConcern::Logged => self.wal.commit()?,
+            WriteConcern::Journaled => self.wal.sync()?,
+        }
+        for write in &writes {
+            write.apply(self.engine.as_mut())?;
+        }
+        catalog::apply_writes(&mut self.collections, &writes);
+        if let Some(appended) = appended {
+            self.replication.appended(appended);
+        }
+        self.committed.notify_all();
+        if concern == WriteConcern::Checkpointed {
+            self.checkpoint()?;
+        } else if self.wal.size() >= self.checkpoints.wal_size() {
+            // The commit is durable either way, so a failed checkpoint is
+            // left for the next commit or interval to retry
+            let _ = self.checkpoint();
+        }
+        Ok(())
+    }
+
+    /// Re-applies every write logged since the last checkpoint. Writes are
+    /// idempotent, so records the engine already holds are harmless to
+    /// apply again.
+    fn replay(&mut self) -> Result<(), DatabaseError> {
+        for record in self.wal.replay(self.wal.checkpoint_lsn() + 1)? {
+            for write in record_writes(&record?)? {
+                write.apply(self.engine.as_mut())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads the writes logged in `record`.
+pub(crate) fn record_writes(record: &WalRecord) -> Result<Vec<Write>, StorageError> {
+    let Some(Value::Array(writes)) = record.payload.get("writes") else {
+        return Err(bad_record(record.lsn));
+    };
+    writes
+        .iter()
+        .map(|write| {
+            write
+                .as_document()
+                .and_then(Write::from_document)
+                .ok_or_else(|| bad_record(record.lsn))
+        })
+        .collect()
+}
+
+fn bad_record(lsn: u64) -> StorageError {
+    StorageError::corrupt(
+        format!("write-ahead log record {}", lsn),
+        "bad write record",
+    )
+}
+
+/// A single change to the storage engine, as logged to the write-ahead log.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Write {
+    Put {
+        namespace: String,
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    Delete {
+        namespace: String,
+        key: Vec<u8>,
+    },
+}
+
+impl Write {
+    /// Returns the namespace and key the write changes.
+    pub(crate) fn target(&self) -> (&str, &[u8]) {
+        match self {
+            Write::Put { namespace, key, .. } | Write::Delete { namespace, key } => {
+                (namespace, key)
+            }
+        }
+    }
+
+    fn apply(&self, engine: &mut dyn StorageEngine) -> Result<(), StorageError> {
+        match self {
+            Write::Put {
+                namespace,
+                key,
+                value,
+            } => engine.put(namespace, key, value),
+            Write::Delete { namespace, key } => engine.delete(namespace, key).map(|_| ()),
+        }
+    }
+
+    pub(crate) fn to_document(&self) -> Document {
+        let mut document = Document::new();
+        match self {
+            Write::Put {
+                namespace,
+                key,
+                value,
+            } => {
+                document.insert("put", namespace.as_str());
+                document.insert("key", Value::Binary(key.clone()));
+                document.insert("value", Value::Binary(value.clone()));
+            }
+            Write::Delete { namespace, key } => {
+                document.insert("delete", namespace.as_str());
+                document.insert("key", Value::Binary(key.clone()));
+            }
+        }
+        document
+    }
+
+    pub(crate) fn from_document(document: &Document) -> Option<Write> {
+        let Some(Value::Binary(key)) = document.get("key") else {
+            return None;
+        };
+        match (
+            document.get("put"),
+            document.get("delete"),
+            document.get("value"),
+        ) {
+            (Some(Value::String(namespace)), None, Some(Value::Binary(value))) => {
+                Some(Write::Put {
+                    namespace: namespace.clone(),
+                    key: key.clone(),
+                    value: value.clone(),
+                })
+            }
+            (None, Some(Value::String(namespace)), None) => Some(Write::Delete {
+                namespace: namespace.clone(),
+                key: key.clone(),
+            }),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,142 @@
+// src/db/error.rs
+
+use std::time::Duration;
+
+use silentdb_data_encoding::{
+    DeserializeError, PatchError, SerializeError, Value, ValueConversionError,
+};
+
+use super::replication::OpTime;
+use crate::query::QueryError;
+use crate::storage::StorageError;
+
+/// Represents errors that can occur in database and collection operations.
+#[derive(Debug, thiserror::Error)]
+pub enum DatabaseError {
+    #[error("Storage error: {0}")]
+    Storage(#[from] StorageError),
+    #[error("Serialization error: {0}")]
+    Serialize(#[from] SerializeError),
+    #[error("Deserialization error: {0}")]
+    Deserialize(#[from] DeserializeError),
+    #[error("Query error: {0}")]
+    Query(#[from] QueryError),
+    #[error("Patch error: {0}")]
+    Patch(#[from] PatchError),
+    #[error("Conversion error: {0}")]
+    Conversion(#[from] ValueConversionError),
+    #[error("Duplicate _id {0:?}")]
+    DuplicateKey(Value),
+    #[error("Invalid update: {0}")]
+    InvalidUpdate(String),
+    #[error("Invalid index: {0}")]
+    InvalidIndex(String),
+    #[error("Invalid validator: {0}")]
+    InvalidValidator(String),
+    #[error("Invalid migration: {0}")]
+    InvalidMigration(String),
+    #[error("Invalid collection name: {0:?}")]
+    InvalidCollectionName(String),
+    #[error("Collection {0} already exists")]
+    CollectionExists(String),
+    #[error("Collection {0} not found")]
+    CollectionNotFound(String),
+    #[error("Invalid collation: {0}")]
+    InvalidCollation(String),
+    #[error("Document {0:?} failed its collection's validator")]
+    ValidationFailed(Value),
+    #[error("Cursor {0} not found")]
+    CursorNotFound(u64),
+    #[error("Write conflict on {0}")]
+    WriteConflict(String),
+    #[error("Transaction {0} is not active")]
+    NoSuchTransaction(u64),
+    #[error("Deadlock among transactions {cycle:?}; transaction {victim} was aborted")]
+    Deadlock { victim: u64, cycle: Vec<u64> },
+    #[error("Timed out waiting for a lock on {0}")]
+    LockTimeout(String),
+    #[error("Log records before {0} were removed by a checkpoint")]
+    HistoryLost(u64),
+    #[error("The database keeps no oplog")]
+    OplogDisabled,
+    #[error("Oplog entries after {0} were removed")]
+    OplogTruncated(OpTime),
+    #[error("Not the Raft leader; the last known leader is {0:?}")]
+    NotLeader(Option<u64>),
+    #[error(
+        "Raft log entries from {0} on were applied but not committed; the node must be synced anew"
+    )]
+    RaftDiverged(u64),
+    #[error("Log record {0} is missing from the archive")]
+    LogGap(u64),
+    #[error("Import failed at record {record}: {source}")]
+    ImportFailed {
+        record: u64,
+        source: Box<DatabaseError>,
+    },
+    #[error("Collection {0} is not sharded")]
+    NotSharded(String),
+    #[error("Shard {0:?} not found")]
+    ShardNotFound(String),
+    #[error("Document {id:?} is at version {actual}, not {expected}")]
+    VersionConflict {
+        id: Value,
+        expected: i64,
+        actual: i64,
+    },
+    #[error("Invalid user name: {0:?}")]
+    InvalidUserName(String),
+    #[error("User {0} already exists")]
+    UserExists(String),
+    #[error("User {0} not found")]
+    UserNotFound(String),
+    #[error("Operation exceeded its time limit of {0:?}")]
+    MaxTimeExpired(Duration),
+    #[cfg(feature = "parquet")]
+    #[error("Parquet error: {0}")]
+    Parquet(#[from] parquet::errors::ParquetError),
+}
+
+impl DatabaseError {
+    /// Returns the code of the error, one of the codes MongoDB uses for
+    /// the same failure where there is one, and 1 otherwise.
+    pub fn code(&self) -> i32 {
+        match self {
+            DatabaseError::Query(_) | DatabaseError::InvalidUpdate(_) => 2,
+            DatabaseError::DuplicateKey(_) => 11000,
+            DatabaseError::InvalidIndex(_) => 67,
+            DatabaseError::InvalidCollectionName(_) => 73,
+            DatabaseError::CollectionExists(_) => 48,
+            DatabaseError::CollectionNotFound(_) => 26,
+            DatabaseError::ValidationFailed(_) => 121,
+            DatabaseError::CursorNotFound(_) => 43,
+            DatabaseError::WriteConflict(_)
+            | DatabaseError::Deadlock { .. }
+            | DatabaseError::VersionConflict { .. } => 112,
+            DatabaseError::LockTimeout(_) => 24,
+            DatabaseError::NotLeader(_) => 10107,
+            DatabaseError::InvalidUserName(_) => 2,
+            DatabaseError::UserNotFound(_) => 11,
+            DatabaseError::UserExists(_) => 51003,
+            DatabaseError::MaxTimeExpired(_) => 50,
+            _ => 1,
+        }
+    }
+
+    /// Returns `true` if the operation may succeed if run again unchanged:
+    /// it lost a race with another transaction, waited too long for a
+    /// lock, or was sent to a node that is not the leader.
+    ///
+    /// A `VersionConflict` is not retryable, since the document must be
+    /// read again for its new version first.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            DatabaseError::WriteConflict(_)
+            | DatabaseError::Deadlock { .. }
+            | DatabaseError::LockTimeout(_)
+            | DatabaseError::NotLeader(_) => true,
+            DatabaseError::Storage(e) => e.is_retryable(),
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,35 @@
+// src/db/id.rs
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rand::Rng;
+use silentdb_data_encoding::ObjectId;
+
+/// Random bytes identifying this process in generated ids.
+static PROCESS_ID: OnceLock<[u8; 5]> = OnceLock::new();
+/// Counter distinguishing ids generated in the same second.
+static COUNTER: OnceLock<AtomicU32> = OnceLock::new();
+
+/// Generates a new `ObjectId` for a document inserted without an `_id`.
+///
+/// Ids follow the usual layout of a 4-byte big-endian timestamp in
+/// seconds, 5 random bytes fixed per process and a 3-byte counter starting
+/// at a random value, so ids generated by one process sort in the order
+/// they were generated and inserts append to the end of the primary index.
+pub(crate) fn generate_object_id() -> ObjectId {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as u32);
+    let process = PROCESS_ID.get_or_init(|| rand::thread_rng().gen());
+    let count = COUNTER
+        .get_or_init(|| AtomicU32::new(rand::thread_rng().gen()))
+        .fetch_add(1, Ordering::Relaxed);
+
+    let mut bytes = [0; 12];
+    bytes[..4].copy_from_slice(&seconds.to_be_bytes());
+    bytes[4..9].copy_from_slice(process);
+    bytes[9..].copy_from_slice(&count.to_be_bytes()[1..]);
+    ObjectId::from_bytes(bytes)
+}
@@ -0,0 +1,681 @@
+// src/db/index.rs
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+
+use silentdb_data_encoding::{
+    apply_encoded_patch, from_bytes, raw_diff, to_bytes, Array, Document, Patch, RawDocument, Value,
+};
+
+use super::database::{DatabaseInner, Write};
+use super::error::DatabaseError;
+use crate::geo::{self, Geometry};
+use crate::query::{path_values, Collation, Matcher};
+use crate::storage::{decode_key, encode_key, KeyRange};
+use crate::text::TextOptions;
+
+/// The direction an index orders a field's values in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    Ascending,
+    Descending,
+}
+
+impl SortOrder {
+    /// Returns the number that stands for the order in index names and
+    /// catalog entries: `1` or `-1`.
+    fn number(self) -> i32 {
+        match self {
+            SortOrder::Ascending => 1,
+            SortOrder::Descending => -1,
+        }
+    }
+}
+
+/// Options for `Collection::create_index_with`.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb::IndexOptions;
+/// let options = IndexOptions::new()
+///     .ascending("user.country")
+///     .descending("created")
+///     .name("recent_by_country");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct IndexOptions {
+    pub(crate) fields: Vec<(String, SortOrder)>,
+    pub(crate) name: Option<String>,
+    pub(crate) sparse: bool,
+    pub(crate) partial_filter: Option<Document>,
+    pub(crate) expire_after: Option<Duration>,
+    pub(crate) text: Option<TextOptions>,
+    pub(crate) geo: bool,
+    pub(crate) collation: Option<Collation>,
+}
+
+impl IndexOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `path` as the index's next field, in ascending order.
+    pub fn ascending(mut self, path: &str) -> Self {
+        self.fields.push((path.to_string(), SortOrder::Ascending));
+        self
+    }
+
+    /// Adds `path` as the index's next field, in descending order.
+    pub fn descending(mut self, path: &str) -> Self {
+        self.fields.push((path.to_string(), SortOrder::Descending));
+        self
+    }
+
+    /// Names the index. By default the name joins each field's path and
+    /// order, such as `a_1_b_-1`.
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    /// Sets whether the index skips documents missing every indexed path,
+    /// rather than indexing them under `null`. Off by default.
+    ///
+    /// Queries that may match documents with a missing path, such as
+    /// equality with `null`, cannot use a sparse index.
+    pub fn sparse(mut self, sparse: bool) -> Self {
+        self.sparse = sparse;
+        self
+    }
+
+    /// Only indexes documents matching `filter`, a query document as
+    /// described for `Matcher`.
+    ///
+    /// Queries can use a partial index only if every predicate of `filter`
+    /// also appears in the query's filter, at its top level or in `$and`.
+    pub fn partial_filter(mut self, filter: Document) -> Self {
+        self.partial_filter = Some(filter);
+        self
+    }
+
+    /// Makes the index a TTL index: a document expires `expire_after`,
+    /// counted in whole seconds, after the earliest `UTCDateTime` at the
+    /// indexed path, and is deleted by the database's TTL reaper. A TTL
+    /// index has a single field.
+    pub fn expire_after(mut self, expire_after: Duration) -> Self {
+        self.expire_after = Some(expire_after);
+        self
+    }
+
+    /// Adds `path` as a field of a text index, which indexes the terms of
+    /// the strings at its paths for `$text` queries. Every field of a text
+    /// index is indexed as text, and a collection has at most one.
+    pub fn text(mut self, path: &str) -> Self {
+        self.fields.push((path.to_string(), SortOrder::Ascending));
+        self.text.get_or_insert_with(TextOptions::new);
+        self
+    }
+
+    /// Sets how a text index splits strings into terms. The default is
+    /// `TextOptions::new()`.
+    pub fn text_options(mut self, options: TextOptions) -> Self {
+        self.text = Some(options);
+        self
+    }
+
+    /// Adds `path` as the field of a geo index, which indexes the GeoJSON
+    /// points, line strings and polygons at the path for `$near`,
+    /// `$geoWithin` and `$geoIntersects` queries. A geo index has a single
+    /// field, and leaves out documents without a geometry at its path.
+    pub fn geo(mut self, path: &str) -> Self {
+        self.fields.push((path.to_string(), SortOrder::Ascending));
+        self.geo = true;
+        self
+    }
+
+    /// Sets how the index orders and compares strings. Only queries with
+    /// the same collation can use the index. By default an index takes the
+    /// collation of its collection.
+    pub fn collation(mut self, collation: Collation) -> Self {
+        self.collation = Some(collation);
+        self
+    }
+}
+
+/// A secondary index on one or more document paths, as returned by
+/// `Collection::list_indexes`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexInfo {
+    /// The index's name, such as `user.email_1`.
+    pub name: String,
+    /// The dotted paths of the indexed fields, most significant first, and
+    /// the order of each.
+    pub fields: Vec<(String, SortOrder)>,
+    /// Whether documents missing every indexed path are left out.
+    pub sparse: bool,
+    /// The filter documents must match to be indexed, if any.
+    pub partial_filter: Option<Document>,
+    /// How long after the date at the indexed path documents expire, for
+    /// a TTL index.
+    pub expire_after: Option<Duration>,
+    /// How strings are split into terms, for a text index.
+    pub text: Option<TextOptions>,
+    /// Whether this is a geo index.
+    pub geo: bool,
+    /// How the index compares strings, if not by code point.
+    pub collation: Option<Collation>,
+    /// Whether every document indexed has held exactly one value at each
+    /// indexed path and at `_id`, one its key encoding gives back, so that
+    /// queries can be answered from the entries alone. Cleared for good by
+    /// the first document that does not, such as one holding an array.
+    pub covering: bool,
+}
+
+impl IndexInfo {
+    /// Describes the index `options` ask for, named after its fields unless
+    /// given a name.
+    pub(crate) fn from_options(options: IndexOptions) -> Self {
+        let name = options.name.unwrap_or_else(|| {
+            options
+                .fields
+                .iter()
+                .map(|(path, order)| match (&options.text, options.geo) {
+                    (Some(_), _) => format!("{}_text", path),
+                    (None, true) => format!("{}_2dsphere", path),
+                    (None, false) => format!("{}_{}", path, order.number()),
+                })
+                .collect::<Vec<_>>()
+                .join("_")
+        });
+        IndexInfo {
+            name,
+            fields: options.fields,
+            sparse: options.sparse,
+            partial_filter: options.partial_filter,
+            expire_after: options
+                .expire_after
+                .map(|expire_after| Duration::from_secs(expire_after.as_secs())),
+            covering: options.text.is_none() && !options.geo,
+            text: options.text,
+            geo: options.geo,
+            collation: options.collation,
+        }
+    }
+
+    /// Returns options that create the index again.
+    pub(crate) fn to_options(&self) -> IndexOptions {
+        IndexOptions {
+            fields: self.fields.clone(),
+            name: Some(self.name.clone()),
+            sparse: self.sparse,
+            partial_filter: self.partial_filter.clone(),
+            expire_after: self.expire_after,
+            text: self.text.clone(),
+            geo: self.geo,
+            collation: self.collation.clone(),
+        }
+    }
+
+    /// Returns the namespace holding the index's entries for `collection`.
+    ///
+    /// Each entry's key is the key encoding of the indexed values, one per
+    /// field with descending fields complemented, followed by the primary
+    /// key of the document holding them, and its value is that primary
+    /// key, so the entries sort in index order and then by `_id`.
+    ///
+    /// A text index instead holds a posting per term of each document,
+    /// keyed by the term's key encoding followed by the primary key, with
+    /// the number of times the document holds the term as its value; and
+    /// under `length_key`, the number of terms each document holds.
+    ///
+    /// A geo index keys each entry by a cell of a grid over the Earth, as
+    /// eight big-endian bytes, followed by the primary key: the finest cell
+    /// holding a point, or the few cells covering another geometry's
+    /// bounds. Queries read the cells covering their area, and check each
+    /// candidate's geometry.
+    pub(crate) fn namespace(&self, collection: &str) -> String {
+        format!("{}.$index.{}", collection, self.name)
+    }
+
+    pub(crate) fn to_document(&self) -> Document {
+        let fields = self
+            .fields
+            .iter()
+            .map(|(path, order)| {
+                let mut field = Document::new();
+                field.insert("path", path.as_str());
+                field.insert("order", order.number());
+                Value::Document(field)
+            })
+            .collect();
+        let mut document = Document::new();
+        document.insert("name", self.name.as_str());
+        document.insert("fields", Array::from_vec(fields));
+        document.insert("sparse", self.sparse);
+        if let Some(filter) = &self.partial_filter {
+            document.insert("partial_filter", filter.clone());
+        }
+        if let Some(expire_after) = self.expire_after {
+            document.insert("expire_after_secs", expire_after.as_secs() as i64);
+        }
+        if let Some(text) = &self.text {
+            document.insert("text", text.to_document());
+        }
+        if self.geo {
+            document.insert("geo", true);
+        }
+        if let Some(collation) = &self.collation {
+            document.insert("collation", collation.to_document());
+        }
+        if self.covering {
+            document.insert("covering", true);
+        }
+        document
+    }
+
+    pub(crate) fn from_document(document: &Document) -> Option<IndexInfo> {
+        let (Some(Value::String(name)), Some(Value::Array(fields))) =
+            (document.get("name"), document.get("fields"))
+        else {
+            return None;
+        };
+        let fields = fields
+            .iter()
+            .map(|field| {
+                let Value::Document(field) = field else {
+                    return None;
+                };
+                let order = match field.get("order") {
+                    Some(Value::Int32(1)) => SortOrder::Ascending,
+                    Some(Value::Int32(-1)) => SortOrder::Descending,
+                    _ => return None,
+                };
+                match field.get("path") {
+                    Some(Value::String(path)) => Some((path.clone(), order)),
+                    _ => None,
+                }
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let sparse = match document.get("sparse") {
+            Some(Value::Boolean(sparse)) => *sparse,
+            None => false,
+            _ => return None,
+        };
+        let partial_filter = match document.get("partial_filter") {
+            Some(Value::Document(filter)) => Some(filter.clone()),
+            None => None,
+            _ => return None,
+        };
+        let expire_after = match document.get("expire_after_secs") {
+            Some(Value::Int64(secs)) => Some(Duration::from_secs(u64::try_from(*secs).ok()?)),
+            None => None,
+            _ => return None,
+        };
+        let text = match document.get("text") {
+            Some(Value::Document(text)) => Some(TextOptions::from_document(text)?),
+            None => None,
+            _ => return None,
+        };
+        let geo = match document.get("geo") {
+            Some(Value::Boolean(geo)) => *geo,
+            None => false,
+            _ => return None,
+        };
+        let collation = match document.get("collation") {
+            Some(Value::Document(collation)) => Some(Collation::from_document(collation)?),
+            None => None,
+            _ => return None,
+        };
+        // Entries written before the flag was kept may not cover
+        let covering = match document.get("covering") {
+            Some(Value::Boolean(covering)) => *covering,
+            None => false,
+            _ => return None,
+        };
+        Some(IndexInfo {
+            name: name.clone(),
+            fields,
+            sparse,
+            partial_filter,
+            expire_after,
+            text,
+            geo,
+            collation,
+            covering,
+        })
+    }
+
+    /// Returns `true` if the index has the same options as `other`, which
+    /// may differ in whether it still covers queries.
+    pub(crate) fn same_options(&self, other: &IndexInfo) -> bool {
+        *self
+            == IndexInfo {
+                covering: self.covering,
+                ..other.clone()
+            }
+    }
+
+    /// Returns the document an entry with key `key` and primary key
+    /// `primary` stands for, holding `_id` and the indexed values, for a
+    /// query the index covers.
+    pub(crate) fn entry_document(
+        &self,
+        key: &[u8],
+        primary: &[u8],
+    ) -> Result<Document, DatabaseError> {
+        let mut document = Document::new();
+        document.insert("_id", decode_key(primary)?.0);
+        let mut patch = Patch::new();
+        let mut position = 0;
+        for (path, order) in &self.fields {
+            let (value, used) = match order {
+                SortOrder::Ascending => decode_key(&key[position..])?,
+                SortOrder::Descending => decode_key(&complement(&key[position..]))?,
+            };
+            position += used;
+            patch = patch.set(path, value);
+        }
+        Ok(from_bytes(&apply_encoded_patch(
+            &to_bytes(&document)?,
+            &patch,
+        )?)?)
+    }
+
+    /// Returns `true` if the encoded `document` keeps the index covering:
+    /// `_id` and each indexed path lead through embedded documents alone to
+    /// a value that is not an array and decodes from its key encoding
+    /// unchanged.
+    fn covers(&self, document: RawDocument<'_>) -> Result<bool, DatabaseError> {
+        let paths = std::iter::once("_id").chain(self.fields.iter().map(|(path, _)| path.as_str()));
+        for path in paths {
+            let mut segments = path.split('.');
+            let mut element = document.get(segments.next().unwrap_or_default())?;
+            for segment in segments {
+                element = match element.and_then(|element| element.as_document()) {
+                    Some(embedded) => embedded.get(segment)?,
+                    None => None,
+                };
+            }
+            let Some(element) = element.filter(|element| element.as_array().is_none()) else {
+                return Ok(false);
+            };
+            let value = element.value()?;
+            if decode_key(&encode_key(&value))?.0 != value {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Returns the entries for the document with primary key `primary` and
+    /// encoding `bytes`, by key.
+    ///
+    /// Documents failing the partial filter have no entries.
+    fn entries(
+        &self,
+        primary: &[u8],
+        bytes: &[u8],
+    ) -> Result<BTreeMap<Vec<u8>, Vec<u8>>, DatabaseError> {
+        let document = RawDocument::from_bytes(bytes)?;
+        if let Some(filter) = &self.partial_filter {
+            if !Matcher::new(filter)?.matches_raw(document)? {
+                return Ok(BTreeMap::new());
+            }
+        }
+        if let Some(text) = &self.text {
+            return self.text_entries(text, primary, document);
+        }
+        if self.geo {
+            return self.geo_entries(primary, document);
+        }
+        Ok(self
+            .entry_keys(primary, document)?
+            .into_iter()
+            .map(|key| (key, primary.to_vec()))
+            .collect())
+    }
+
+    /// Returns the entry keys for the document with primary key
+    /// `primary`: one per combination of the values a
+    /// filter on each field is tested against, with `null` standing in for
+    /// a missing path so that equality with `null` can use the index too.
+    ///
+    /// Documents missing every path of a sparse index have no entries.
+    fn entry_keys(
+        &self,
+        primary: &[u8],
+        document: RawDocument<'_>,
+    ) -> Result<BTreeSet<Vec<u8>>, DatabaseError> {
+        let mut missing = 0;
+        let mut keys = BTreeSet::from([Vec::new()]);
+        for (path, order) in &self.fields {
+            let mut values = path_values(document, path)?;
+            if values.is_empty() {
+                missing += 1;
+                values.push(Value::Null);
+            }
+            if let Some(collation) = &self.collation {
+                values = values.iter().map(|value| collation.key(value)).collect();
+            }
+            let encodings: BTreeSet<Vec<u8>> = values
+                .iter()
+                .map(|value| match order {
+                    SortOrder::Ascending => encode_key(value),
+                    SortOrder::Descending => complement(&encode_key(value)),
+                })
+                .collect();
+            keys = keys
+                .iter()
+                .flat_map(|prefix| {
+                    encodings
+                        .iter()
+                        .map(move |encoding| [prefix.as_slice(), encoding].concat())
+                })
+                .collect();
+        }
+        if self.sparse && missing == self.fields.len() {
+            return Ok(BTreeSet::new());
+        }
+        Ok(keys
+            .into_iter()
+            .map(|mut key| {
+                key.extend_from_slice(primary);
+                key
+            })
+            .collect())
+    }
+
+    /// Returns the text index entries for the document with primary key
+    /// `primary`: a posting per term of the strings at the indexed paths,
+    /// and its length, or none if it has no terms.
+    fn text_entries(
+        &self,
+        text: &TextOptions,
+        primary: &[u8],
+        document: RawDocument<'_>,
+    ) -> Result<BTreeMap<Vec<u8>, Vec<u8>>, DatabaseError> {
+        let mut frequencies: BTreeMap<String, u32> = BTreeMap::new();
+        for (path, _) in &self.fields {
+            for value in path_values(document, path)? {
+                if let Value::String(string) = value {
+                    for term in text.terms(&string) {
+                        *frequencies.entry(term).or_default() += 1;
+                    }
+                }
+            }
+        }
+        let length: u32 = frequencies.values().sum();
+        if length == 0 {
+            return Ok(BTreeMap::new());
+        }
+        let mut entries: BTreeMap<Vec<u8>, Vec<u8>> = frequencies
+            .into_iter()
+            .map(|(term, frequency)| {
+                (
+                    posting_key(&term, primary),
+                    frequency.to_be_bytes().to_vec(),
+                )
+            })
+            .collect();
+        entries.insert(length_key(primary), length.to_be_bytes().to_vec());
+        Ok(entries)
+    }
+
+    /// Returns the geo index entries for the document with primary key
+    /// `primary`, under the cells of each geometry at the indexed path.
+    /// Values that are not GeoJSON geometries are left out.
+    fn geo_entries(
+        &self,
+        primary: &[u8],
+        document: RawDocument<'_>,
+    ) -> Result<BTreeMap<Vec<u8>, Vec<u8>>, DatabaseError> {
+        let mut entries = BTreeMap::new();
+        for (path, _) in &self.fields {
+            for value in path_values(document, path)? {
+                let Some(geometry) = Geometry::from_value(&value) else {
+                    continue;
+                };
+                for cell in geo::index_keys(&geometry) {
+                    entries.insert([&cell[..], primary].concat(), primary.to_vec());
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Returns `true` if a change at `changed` may change the values at any
+    /// indexed path.
+    fn affected_by(&self, changed: &str) -> bool {
+        let within = |inner: &str, outer: &str| {
+            inner == outer
+                || inner
+                    .strip_prefix(outer)
+                    .is_some_and(|rest| rest.starts_with('.'))
+        };
+        self.fields
+            .iter()
+            .any(|(path, _)| within(path, changed) || within(changed, path))
+    }
+}
+
+/// Returns the key of the posting of `term` for the document with primary
+/// key `primary` in a text index.
+pub(crate) fn posting_key(term: &str, primary: &[u8]) -> Vec<u8> {
+    let mut key = encode_key(&Value::from(term));
+    key.extend_from_slice(primary);
+    key
+}
+
+/// Returns the key of the length of the document with primary key
+/// `primary` in a text index. These keys sort before every posting, since
+/// no key encoding starts with a zero byte.
+pub(crate) fn length_key(primary: &[u8]) -> Vec<u8> {
+    [&[0], primary].concat()
+}
+
+/// Returns `bytes` with every byte inverted, which reverses the order of
+/// key encodings: since they are self-delimiting, no complemented encoding
+/// is a prefix of another.
+pub(crate) fn complement(bytes: &[u8]) -> Vec<u8> {
+    bytes.iter().map(|byte| !byte).collect()
+}
+
+/// Suffix of the namespace listing a collection's indexes.
+pub(crate) const CATALOG_SUFFIX: &str = ".$indexes";
+
+/// Returns the namespace listing the indexes of `collection`.
+fn catalog_namespace(collection: &str) -> String {
+    format!("{}{}", collection, CATALOG_SUFFIX)
+}
+
+/// Returns the indexes of `collection`, as seen by transaction `txn` if
+/// given.
+pub(crate) fn load_indexes(
+    inner: &DatabaseInner,
+    txn: Option<u64>,
+    collection: &str,
+) -> Result<Vec<IndexInfo>, DatabaseError> {
+    let entries = inner.scan(
+        txn,
+        &catalog_namespace(collection),
+        &KeyRange::all(),
+        usize::MAX,
+    )?;
+    entries
+        .iter()
+        .map(|(_, bytes)| {
+            IndexInfo::from_document(&from_bytes(bytes)?).ok_or_else(|| {
+                DatabaseError::InvalidIndex(format!("bad catalog entry in {}", collection))
+            })
+        })
+        .collect()
+}
+
+/// Returns the write adding `index` to the catalog of `collection`.
+pub(crate) fn catalog_write(collection: &str, index: &IndexInfo) -> Result<Write, DatabaseError> {
+    Ok(Write::Put {
+        namespace: catalog_namespace(collection),
+        key: encode_key(&Value::from(index.name.as_str())),
+        value: to_bytes(&index.to_document())?,
+    })
+}
+
+/// Returns the writes that bring `indexes` of `collection` up to date after
+/// the document with primary key `primary` changes from `old` to `new`,
+/// where `None` means no document.
+///
+/// When both encodings are given, only indexes on paths the change touched,
+/// and partial indexes, are updated. A covering index the new document
+/// stops covering is cleared of the flag, in `indexes` and in the catalog.
+pub(crate) fn index_writes(
+    collection: &str,
+    indexes: &mut [IndexInfo],
+    primary: &[u8],
+    old: Option<&[u8]>,
+    new: Option<&[u8]>,
+) -> Result<Vec<Write>, DatabaseError> {
+    let changed = match (old, new) {
+        (Some(old), Some(new)) => Some(raw_diff(old, new)?),
+        _ => None,
+    };
+    let mut writes = Vec::new();
+    for index in indexes {
+        // Any change may decide whether a document passes a partial filter
+        if let (Some(changed), None) = (&changed, &index.partial_filter) {
+            if !changed.iter().any(|change| index.affected_by(&change.path)) {
+                continue;
+            }
+        }
+        let entries = |bytes: Option<&[u8]>| match bytes {
+            Some(bytes) => index.entries(primary, bytes),
+            None => Ok(BTreeMap::new()),
+        };
+        let (old_entries, new_entries) = (entries(old)?, entries(new)?);
+        if let Some(new) = new.filter(|_| index.covering && !new_entries.is_empty()) {
+            if !index.covers(RawDocument::from_bytes(new)?)? {
+                index.covering = false;
+                writes.push(catalog_write(collection, index)?);
+            }
+        }
+        let namespace = index.namespace(collection);
+        for key in old_entries.keys() {
+            if !new_entries.contains_key(key) {
+                writes.push(Write::Delete {
+                    namespace: namespace.clone(),
+                    key: key.clone(),
+                });
+            }
+        }
+        for (key, value) in new_entries {
+            if old_entries.get(&key) != Some(&value) {
+                writes.push(Write::Put {
+                    namespace: namespace.clone(),
+                    key,
+                    value,
+                });
+            }
+        }
+    }
+    Ok(writes)
+}
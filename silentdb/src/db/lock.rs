@@ -0,0 +1,377 @@
+// src/db/lock.rs
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use super::catalog::collection_of;
+use super::error::DatabaseError;
+
+/// How long a transaction waits for a lock before giving up, unless told
+/// otherwise.
+const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The mode a transaction holds a lock in.
+///
+/// Locks form a hierarchy of the database, its collections and their
+/// documents. Before locking a resource, a transaction takes an intent
+/// lock on each resource above it, so a lock on a collection conflicts
+/// with locks on its documents without checking every one of them.
+///
+/// | held \ asked      | `IntentShared` | `IntentExclusive` | `Shared` | `Exclusive` |
+/// |-------------------|----------------|-------------------|----------|-------------|
+/// | `IntentShared`    | yes            | yes               | yes      | no          |
+/// | `IntentExclusive` | yes            | yes               | no       | no          |
+/// | `Shared`          | yes            | no                | yes      | no          |
+/// | `Exclusive`       | no             | no                | no       | no          |
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LockMode {
+    /// Reads something below the resource.
+    IntentShared,
+    /// Writes something below the resource.
+    IntentExclusive,
+    /// Reads the whole resource: no one else may write any of it.
+    Shared,
+    /// Writes the whole resource: no one else may lock any of it.
+    Exclusive,
+}
+
+impl LockMode {
+    /// Returns whether two transactions may hold a resource in `self` and
+    /// `other` at once.
+    fn compatible(self, other: LockMode) -> bool {
+        use LockMode::*;
+        match (self, other) {
+            (Exclusive, _) | (_, Exclusive) => false,
+            (IntentShared, _) | (_, IntentShared) => true,
+            (a, b) => a == b,
+        }
+    }
+
+    /// Returns the weakest mode that grants what both `self` and `other`
+    /// do. Holding a resource both shared and with intent to write below
+    /// it is taken as holding it exclusively.
+    fn join(self, other: LockMode) -> LockMode {
+        use LockMode::*;
+        match (self, other) {
+            (a, b) if a == b => a,
+            (IntentShared, mode) | (mode, IntentShared) => mode,
+            _ => Exclusive,
+        }
+    }
+
+    /// Returns the intent lock a lock in this mode needs on each resource
+    /// above it.
+    fn intent(self) -> LockMode {
+        match self {
+            LockMode::IntentShared | LockMode::Shared => LockMode::IntentShared,
+            LockMode::IntentExclusive | LockMode::Exclusive => LockMode::IntentExclusive,
+        }
+    }
+}
+
+/// Something a transaction may lock.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum LockResource {
+    Database,
+    Collection(String),
+    /// A key of a namespace, such as a document or an index entry, under
+    /// the collection the namespace belongs to, or directly under the
+    /// database for the system namespaces.
+    Key(String, Vec<u8>),
+}
+
+impl LockResource {
+    fn parent(&self) -> Option<LockResource> {
+        match self {
+            LockResource::Database => None,
+            LockResource::Collection(_) => Some(LockResource::Database),
+            LockResource::Key(namespace, _) => Some(match collection_of(namespace) {
+                Some(collection) => LockResource::Collection(collection.to_string()),
+                None => LockResource::Database,
+            }),
+        }
+    }
+
+    /// Returns the locks taking this resource in `mode` needs: intent locks
+    /// on the resources above it, from the database down, then the
+    /// resource itself.
+    fn requests(self, mode: LockMode) -> Vec<(LockResource, LockMode)> {
+        let mut requests = vec![(self, mode)];
+        while let Some(parent) = requests.last().and_then(|(resource, _)| resource.parent()) {
+            requests.push((parent, mode.intent()));
+        }
+        requests.reverse();
+        requests
+    }
+}
+
+impl fmt::Display for LockResource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LockResource::Database => f.write_str("the database"),
+            LockResource::Collection(name) => write!(f, "collection {}", name),
+            LockResource::Key(namespace, key) => {
+                write!(f, "{} key {}", namespace, hex::encode(key))
+            }
+        }
+    }
+}
+
+/// What the lock manager has done since the database was opened, as
+/// returned by `Database::lock_stats`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LockStats {
+    /// The number of lock requests that had to wait.
+    pub waits: u64,
+    /// The number of deadlocks found, each of which aborted a transaction.
+    pub deadlocks: u64,
+    /// The number of lock requests that gave up waiting.
+    pub timeouts: u64,
+}
+
+/// The locks held and waited for, guarded by the lock manager.
+#[derive(Debug)]
+struct LockTable {
+    /// The transactions holding each resource, and the mode each holds it in.
+    granted: HashMap<LockResource, BTreeMap<u64, LockMode>>,
+    /// The resources each transaction holds.
+    held: HashMap<u64, Vec<LockResource>>,
+    /// The lock each waiting transaction waits for.
+    waiting: BTreeMap<u64, (LockResource, LockMode)>,
+    /// Waiting transactions chosen to break a deadlock that have yet to
+    /// find out, and the cycle of waits they were in.
+    victims: HashMap<u64, Vec<u64>>,
+    timeout: Duration,
+    stats: LockStats,
+}
+
+impl LockTable {
+    /// Returns the transactions other than `id` holding `resource` in a
+    /// mode that keeps it from being granted in `mode`.
+    fn blockers(&self, id: Option<u64>, resource: &LockResource, mode: LockMode) -> Vec<u64> {
+        self.granted.get(resource).map_or_else(Vec::new, |holders| {
+            holders
+                .iter()
+                .filter(|(holder, held)| Some(**holder) != id && !held.compatible(mode))
+                .map(|(holder, _)| *holder)
+                .collect()
+        })
+    }
+
+    fn grant(&mut self, id: u64, resource: LockResource, mode: LockMode) {
+        let holders = self.granted.entry(resource.clone()).or_default();
+        match holders.get_mut(&id) {
+            Some(held) => *held = held.join(mode),
+            None => {
+                holders.insert(id, mode);
+                self.held.entry(id).or_default().push(resource);
+            }
+        }
+    }
+
+    /// Returns the transactions in a cycle of waits through `id`, starting
+    /// with it, if there is one.
+    ///
+    /// Waiting transactions look for cycles through themselves as they
+    /// start waiting and whenever they wake, so each deadlock is found by
+    /// one of its members as it forms.
+    fn cycle(&self, id: u64) -> Option<Vec<u64>> {
+        let mut path = vec![id];
+        let mut visited = HashSet::new();
+        self.extend_cycle(&mut path, &mut visited).then_some(path)
+    }
+
+    /// Follows the waits from the last transaction of `path`, returning
+    /// whether they lead back to the first.
+    fn extend_cycle(&self, path: &mut Vec<u64>, visited: &mut HashSet<u64>) -> bool {
+        let from = *path.last().expect("the path starts with a transaction");
+        let Some((resource, mode)) = self.waiting.get(&from) else {
+            return false;
+        };
+        for next in self.blockers(Some(from), resource, *mode) {
+            if next == path[0] {
+                return true;
+            }
+            if visited.insert(next) {
+                path.push(next);
+                if self.extend_cycle(path, visited) {
+                    return true;
+                }
+                path.pop();
+            }
+        }
+        false
+    }
+}
+
+/// The locks of a database's transactions.
+///
+/// The table has a mutex of its own, apart from the database's, so a
+/// transaction can wait for a lock without keeping everyone else out of
+/// the database. Locks are held until their transaction ends.
+///
+/// A transaction that would wait for a lock held by one waiting on it,
+/// directly or through others, is deadlocked with them. The youngest
+/// transaction of the cycle, the one begun last, is aborted with
+/// `Deadlock` so the others can go on.
+#[derive(Debug)]
+pub(crate) struct LockManager {
+    table: Mutex<LockTable>,
+    /// Signalled when locks are released or a waiting transaction is
+    /// chosen as a deadlock's victim.
+    changed: Condvar,
+}
+
+impl LockManager {
+    pub(crate) fn new() -> Self {
+        LockManager {
+            table: Mutex::new(LockTable {
+                granted: HashMap::new(),
+                held: HashMap::new(),
+                waiting: BTreeMap::new(),
+                victims: HashMap::new(),
+                timeout: DEFAULT_LOCK_TIMEOUT,
+                stats: LockStats::default(),
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    fn table(&self) -> MutexGuard<'_, LockTable> {
+        self.table.lock().expect("lock table poisoned")
+    }
+
+    pub(crate) fn set_timeout(&self, timeout: Duration) {
+        self.table().timeout = timeout;
+    }
+
+    pub(crate) fn stats(&self) -> LockStats {
+        self.table().stats.clone()
+    }
+
+    /// Takes `resource` in `mode` for transaction `id`, along with the
+    /// intent locks above it, if no other transaction is in the way. With
+    /// no transaction, only checks that nothing is in the way of a write
+    /// made and committed at once.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first resource held by another transaction in the way,
+    /// having taken none of the locks.
+    pub(crate) fn try_lock(
+        &self,
+        id: Option<u64>,
+        resource: LockResource,
+        mode: LockMode,
+    ) -> Result<(), LockResource> {
+        let mut table = self.table();
+        let requests = resource.requests(mode);
+        if let Some((resource, _)) = requests
+            .iter()
+            .find(|(resource, mode)| !table.blockers(id, resource, *mode).is_empty())
+        {
+            return Err(resource.clone());
+        }
+        if let Some(id) = id {
+            for (resource, mode) in requests {
+                table.grant(id, resource, mode);
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes `resource` in `mode` for transaction `id`, along with the
+    /// intent locks above it, waiting up to the lock timeout for the other
+    /// transactions in the way to end.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Deadlock` if the transaction is chosen to break a deadlock,
+    /// or `LockTimeout` if the lock is not granted in time. Locks already
+    /// taken are kept either way, until the transaction ends.
+    pub(crate) fn lock(
+        &self,
+        id: u64,
+        resource: LockResource,
+        mode: LockMode,
+    ) -> Result<(), DatabaseError> {
+        let mut table = self.table();
+        let deadline = Instant::now() + table.timeout;
+        for (resource, mode) in resource.requests(mode) {
+            table = self.wait(table, id, resource, mode, deadline)?;
+        }
+        Ok(())
+    }
+
+    /// Waits until `resource` can be granted to `id` in `mode`, then grants
+    /// it.
+    fn wait<'a>(
+        &'a self,
+        mut table: MutexGuard<'a, LockTable>,
+        id: u64,
+        resource: LockResource,
+        mode: LockMode,
+        deadline: Instant,
+    ) -> Result<MutexGuard<'a, LockTable>, DatabaseError> {
+        let mut waited = false;
+        loop {
+            if let Some(cycle) = table.victims.remove(&id) {
+                return Err(DatabaseError::Deadlock { victim: id, cycle });
+            }
+            if table.blockers(Some(id), &resource, mode).is_empty() {
+                table.waiting.remove(&id);
+                table.grant(id, resource, mode);
+                return Ok(table);
+            }
+            if !waited {
+                waited = true;
+                table.stats.waits += 1;
+                table.waiting.insert(id, (resource.clone(), mode));
+            }
+            // Checked again on each wake, as who is in the way may change
+            if let Some(cycle) = table.cycle(id) {
+                let victim = *cycle.iter().max().expect("a cycle has members");
+                table.waiting.remove(&victim);
+                table.stats.deadlocks += 1;
+                if victim == id {
+                    return Err(DatabaseError::Deadlock { victim, cycle });
+                }
+                table.victims.insert(victim, cycle);
+                self.changed.notify_all();
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                table.waiting.remove(&id);
+                table.stats.timeouts += 1;
+                return Err(DatabaseError::LockTimeout(resource.to_string()));
+            }
+            table = self
+                .changed
+                .wait_timeout(table, deadline - now)
+                .expect("lock table poisoned")
+                .0;
+        }
+    }
+
+    /// Releases every lock of transaction `id`, which has ended.
+    pub(crate) fn release_all(&self, id: u64) {
+        let mut table = self.table();
+        table.waiting.remove(&id);
+        table.victims.remove(&id);
+        let Some(resources) = table.held.remove(&id) else {
+            return;
+        };
+        for resource in resources {
+            if let Some(holders) = table.granted.get_mut(&resource) {
+                holders.remove(&id);
+                if holders.is_empty() {
+                    table.granted.remove(&resource);
+                }
+            }
+        }
+        self.changed.notify_all();
+    }
+}
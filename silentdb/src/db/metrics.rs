@@ -0,0 +1,355 @@
+// src/db/metrics.rs
+
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use silentdb_data_encoding::{Array, Document, Value};
+
+use super::database::DatabaseInner;
+use crate::accounting::{MemoryCategory, MemoryStats};
+use crate::storage::EngineStats;
+
+/// Upper bounds of the operation latency buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.0,
+];
+
+/// A kind of operation whose latency is recorded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Op {
+    Insert,
+    Find,
+    /// A batch fetched by a cursor.
+    GetMore,
+    Update,
+    Delete,
+    Count,
+}
+
+impl Op {
+    const ALL: [Op; 6] = [
+        Op::Insert,
+        Op::Find,
+        Op::GetMore,
+        Op::Update,
+        Op::Delete,
+        Op::Count,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Op::Insert => "insert",
+            Op::Find => "find",
+            Op::GetMore => "getmore",
+            Op::Update => "update",
+            Op::Delete => "delete",
+            Op::Count => "count",
+        }
+    }
+}
+
+/// How long the operations of one kind took.
+#[derive(Debug, Clone, Copy, Default)]
+struct Histogram {
+    /// The operations falling in each latency bucket but not the one
+    /// before it, the last counting those slower than every bound.
+    buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    sum: Duration,
+    count: u64,
+}
+
+impl Histogram {
+    fn record(&mut self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[bucket] += 1;
+        self.sum += elapsed;
+        self.count += 1;
+    }
+
+    /// Returns each bucket's bound, ending with infinity, and the
+    /// operations within it.
+    fn cumulative(&self) -> impl Iterator<Item = (f64, u64)> + '_ {
+        LATENCY_BUCKETS
+            .iter()
+            .copied()
+            .chain([f64::INFINITY])
+            .zip(self.buckets.iter().scan(0, |total, count| {
+                *total += count;
+                Some(*total)
+            }))
+    }
+}
+
+/// The counters of a database's operations, kept with the database.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Metrics {
+    ops: [Histogram; Op::ALL.len()],
+    cursors_opened: u64,
+}
+
+impl Metrics {
+    pub(crate) fn record(&mut self, op: Op, elapsed: Duration) {
+        self.ops[op as usize].record(elapsed);
+    }
+
+    pub(crate) fn cursor_opened(&mut self) {
+        self.cursors_opened += 1;
+    }
+}
+
+/// Records how long an operation took once dropped, so that operations
+/// returning early with an error are recorded too.
+///
+/// The timer locks the database as it is dropped, so it must be created
+/// before any guard of the operation is taken.
+pub(crate) struct OpTimer<'a> {
+    inner: &'a Mutex<DatabaseInner>,
+    op: Op,
+    started: Instant,
+}
+
+impl<'a> OpTimer<'a> {
+    pub(crate) fn start(inner: &'a Mutex<DatabaseInner>, op: Op) -> Self {
+        OpTimer {
+            inner,
+            op,
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Drop for OpTimer<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.metrics.record(self.op, self.started.elapsed());
+        }
+    }
+}
+
+/// The metrics of a database at one moment, gathered under its lock.
+#[derive(Debug, Clone)]
+pub(crate) struct MetricsSnapshot {
+    pub(crate) metrics: Metrics,
+    pub(crate) open_cursors: usize,
+    pub(crate) engine: EngineStats,
+    pub(crate) wal_syncs: u64,
+    pub(crate) wal_size: u64,
+    pub(crate) memory: MemoryStats,
+}
+
+impl MetricsSnapshot {
+    /// Returns the metrics as a document, grouped by subsystem.
+    pub(crate) fn to_document(&self) -> Document {
+        let mut ops = Document::new();
+        for op in Op::ALL {
+            let histogram = &self.metrics.ops[op as usize];
+            let buckets: Vec<Value> = histogram
+                .cumulative()
+                .map(|(bound, count)| {
+                    let mut bucket = Document::new();
+                    bucket.insert("le", bound);
+                    bucket.insert("count", count as i64);
+                    Value::Document(bucket)
+                })
+                .collect();
+            let mut latency = Document::new();
+            latency.insert("count", histogram.count as i64);
+            latency.insert("seconds", histogram.sum.as_secs_f64());
+            latency.insert("buckets", Value::Array(Array::from(buckets)));
+            ops.insert(op.name(), latency);
+        }
+
+        let mut cursors = Document::new();
+        cursors.insert("open", self.open_cursors as i64);
+        cursors.insert("opened", self.metrics.cursors_opened as i64);
+
+        let mut wal = Document::new();
+        wal.insert("syncs", self.wal_syncs as i64);
+        wal.insert("bytes", self.wal_size as i64);
+
+        let mut compaction = Document::new();
+        compaction.insert("debt", self.engine.compaction_debt as i64);
+
+        let mut document = Document::new();
+        document.insert("ops", ops);
+        document.insert("cursors", cursors);
+        if let Some(cache) = &self.engine.cache {
+            let mut stats = Document::new();
+            stats.insert("hits", cache.hits as i64);
+            stats.insert("misses", cache.misses as i64);
+            stats.insert("hitRatio", hit_ratio(cache.hits, cache.misses));
+            stats.insert("evictions", cache.evictions as i64);
+            document.insert("cache", stats);
+        }
+        document.insert("wal", wal);
+        document.insert("compaction", compaction);
+        if self.memory.tracking {
+            let mut memory = Document::new();
+            memory.insert("live", self.memory.live as i64);
+            memory.insert("peak", self.memory.peak as i64);
+            for category in MemoryCategory::ALL {
+                let stats = self.memory.category(category);
+                let mut allocated = Document::new();
+                allocated.insert("allocations", stats.allocations as i64);
+                allocated.insert("bytes", stats.bytes as i64);
+                memory.insert(category.name(), allocated);
+            }
+            document.insert("memory", memory);
+        }
+        document
+    }
+
+    /// Returns the metrics in the Prometheus text exposition format.
+    pub(crate) fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        header(
+            &mut out,
+            "silentdb_op_duration_seconds",
+            "histogram",
+            "How long operations took, by kind.",
+        );
+        for op in Op::ALL {
+            let histogram = &self.metrics.ops[op as usize];
+            let name = op.name();
+            for (bound, count) in histogram.cumulative() {
+                let le = match bound.is_finite() {
+                    true => bound.to_string(),
+                    false => "+Inf".to_string(),
+                };
+                let _ = writeln!(
+                    out,
+                    "silentdb_op_duration_seconds_bucket{{op=\"{}\",le=\"{}\"}} {}",
+                    name, le, count
+                );
+            }
+            let _ = writeln!(
+                out,
+                "silentdb_op_duration_seconds_sum{{op=\"{}\"}} {}",
+                name,
+                histogram.sum.as_secs_f64()
+            );
+            let _ = writeln!(
+                out,
+                "silentdb_op_duration_seconds_count{{op=\"{}\"}} {}",
+                name, histogram.count
+            );
+        }
+
+        let mut sample = |name: &str, kind: &str, help: &str, value: String| {
+            header(&mut out, name, kind, help);
+            let _ = writeln!(out, "{} {}", name, value);
+        };
+        sample(
+            "silentdb_cursors_open",
+            "gauge",
+            "Cursors open.",
+            self.open_cursors.to_string(),
+        );
+        sample(
+            "silentdb_cursors_opened_total",
+            "counter",
+            "Cursors opened.",
+            self.metrics.cursors_opened.to_string(),
+        );
+        if let Some(cache) = &self.engine.cache {
+            sample(
+                "silentdb_cache_hits_total",
+                "counter",
+                "Page reads answered from the cache.",
+                cache.hits.to_string(),
+            );
+            sample(
+                "silentdb_cache_misses_total",
+                "counter",
+                "Page reads that went to disk.",
+                cache.misses.to_string(),
+            );
+            sample(
+                "silentdb_cache_hit_ratio",
+                "gauge",
+                "The share of page reads answered from the cache.",
+                hit_ratio(cache.hits, cache.misses).to_string(),
+            );
+        }
+        sample(
+            "silentdb_wal_syncs_total",
+            "counter",
+            "Times the write-ahead log was synced to disk.",
+            self.wal_syncs.to_string(),
+        );
+        sample(
+            "silentdb_wal_bytes",
+            "gauge",
+            "Size of the write-ahead log.",
+            self.wal_size.to_string(),
+        );
+        sample(
+            "silentdb_compaction_debt_bytes",
+            "gauge",
+            "Bytes held by replaced and deleted documents that compaction would reclaim.",
+            self.engine.compaction_debt.to_string(),
+        );
+        if self.memory.tracking {
+            sample(
+                "silentdb_memory_live_bytes",
+                "gauge",
+                "Bytes allocated and not yet freed.",
+                self.memory.live.to_string(),
+            );
+            sample(
+                "silentdb_memory_peak_bytes",
+                "gauge",
+                "The most bytes allocated at once.",
+                self.memory.peak.to_string(),
+            );
+            header(
+                &mut out,
+                "silentdb_memory_allocated_bytes_total",
+                "counter",
+                "Bytes allocated, by what they were allocated for.",
+            );
+            for category in MemoryCategory::ALL {
+                let _ = writeln!(
+                    out,
+                    "silentdb_memory_allocated_bytes_total{{category=\"{}\"}} {}",
+                    category.name(),
+                    self.memory.category(category).bytes
+                );
+            }
+            header(
+                &mut out,
+                "silentdb_memory_allocations_total",
+                "counter",
+                "Allocations, by what they were for.",
+            );
+            for category in MemoryCategory::ALL {
+                let _ = writeln!(
+                    out,
+                    "silentdb_memory_allocations_total{{category=\"{}\"}} {}",
+                    category.name(),
+                    self.memory.category(category).allocations
+                );
+            }
+        }
+        out
+    }
+}
+
+/// Writes the `HELP` and `TYPE` lines introducing the metric `name`.
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Returns the share of reads that were hits, or 0 before any read.
+fn hit_ratio(hits: u64, misses: u64) -> f64 {
+    match hits + misses {
+        0 => 0.0,
+        reads => hits as f64 / reads as f64,
+    }
+}
@@ -0,0 +1,74 @@
+// src/db/mod.rs
+
+mod backup;
+mod catalog;
+mod change;
+mod check;
+mod checkpoint;
+mod collation;
+mod collection;
+mod compaction;
+mod concern;
+mod cursor;
+mod database;
+mod error;
+mod id;
+mod index;
+mod lock;
+mod metrics;
+#[cfg(feature = "parquet")]
+mod parquet_export;
+mod path;
+mod planner;
+mod projection;
+mod raft;
+mod recovery;
+mod replication;
+mod scheduler;
+mod schema;
+mod sharding;
+mod sort;
+mod stats;
+mod test;
+mod transaction;
+mod transfer;
+mod ttl;
+mod typed;
+mod update;
+mod users;
+mod validation;
+mod version;
+
+pub use backup::BackupStats;
+pub use catalog::{CollectionInfo, CollectionOptions};
+pub use change::{ChangeEvent, ChangeKind, ChangeStream, ResumeToken, UpdateDescription};
+pub use check::{CheckIssue, CheckLevel, CheckReport, Repair};
+pub use checkpoint::CheckpointStats;
+pub use collection::{
+    Collection, CountOptions, DeleteResult, FindOneAndModifyOptions, FindOptions, InsertManyResult,
+    InsertOneResult, ReturnDocument, UpdateOptions, UpdateResult,
+};
+pub use compaction::{CompactionOptions, CompactionStats};
+pub use concern::{ReadConcern, WriteConcern};
+pub use cursor::Cursor;
+pub use database::Database;
+pub use error::DatabaseError;
+pub use index::{IndexInfo, IndexOptions, SortOrder};
+pub use lock::{LockMode, LockStats};
+#[cfg(feature = "parquet")]
+pub use parquet_export::{NestedPolicy, ParquetOptions, ParquetStats};
+pub use raft::{RaftConfig, RaftEntry, RaftMessage, RaftNode, RaftRole};
+pub use recovery::{RecoveryStats, RecoveryTarget};
+pub use replication::{OpTime, OplogEntry, Secondary, SyncSource};
+pub use scheduler::MaintenanceTask;
+pub use schema::{SchemaMigrationOptions, SchemaMigrationStats};
+pub use sharding::{Chunk, MigrationStats, Router, ShardKey, ShardedCollection, ShardedCursor};
+pub use stats::{CollectionStats, IndexStats};
+pub use transaction::Transaction;
+pub use transfer::{ErrorPolicy, Format, ImportOptions, ImportStats};
+pub use ttl::TtlStats;
+pub use typed::{Field, Filter, TypedCollection, TypedCursor, Update};
+pub use users::{Action, Role, UserInfo};
+pub use validation::{ValidationAction, ValidationLevel, ValidationStats, Validator};
+
+pub(crate) use users::Credential;
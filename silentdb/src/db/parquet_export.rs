@@ -0,0 +1,479 @@
+// src/db/parquet_export.rs
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+use arrow_array::builder::{
+    BinaryBuilder, BooleanBuilder, Float64Builder, Int32Builder, Int64Builder, StringBuilder,
+    TimestampMillisecondBuilder, UInt64Builder,
+};
+use arrow_array::{ArrayRef, RecordBatch, RecordBatchOptions};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use parquet::arrow::ArrowWriter;
+use parquet::errors::ParquetError;
+use parquet::file::properties::WriterProperties;
+use silentdb_data_encoding::{Document, JsonSerializer, Value};
+
+use super::collection::{Collection, FindOptions};
+use super::error::DatabaseError;
+use crate::storage::StorageError;
+
+/// Rows per row group unless told otherwise.
+const DEFAULT_ROW_GROUP_SIZE: usize = 64 * 1024;
+
+/// How `Collection::export_parquet` writes embedded documents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NestedPolicy {
+    /// Gives each field of an embedded document a column of its own, named
+    /// by its dotted path, such as `address.city`, at any depth.
+    #[default]
+    Flatten,
+    /// Writes each embedded document whole, as relaxed Extended JSON text.
+    Json,
+}
+
+/// Options for `Collection::export_parquet`.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use silentdb::{Database, NestedPolicy, ParquetOptions};
+/// # use silentdb_data_encoding::Document;
+/// let db = Database::open("data").unwrap();
+/// let mut filter = Document::new();
+/// filter.insert("active", true);
+/// let options = ParquetOptions::new()
+///     .filter(filter)
+///     .nested(NestedPolicy::Json)
+///     .row_group_size(10_000);
+/// let stats = db.collection("users").export_parquet("users.parquet", &options).unwrap();
+/// println!("{} rows in {} row groups", stats.documents, stats.row_groups);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParquetOptions {
+    pub(crate) filter: Document,
+    pub(crate) projection: Option<Document>,
+    pub(crate) nested: NestedPolicy,
+    pub(crate) row_group_size: usize,
+    pub(crate) sample_size: Option<usize>,
+}
+
+impl ParquetOptions {
+    /// Creates options exporting every document, flattening embedded
+    /// documents, in row groups of 65536 rows, with a schema inferred from
+    /// all of them.
+    pub fn new() -> Self {
+        ParquetOptions {
+            filter: Document::new(),
+            projection: None,
+            nested: NestedPolicy::Flatten,
+            row_group_size: DEFAULT_ROW_GROUP_SIZE,
+            sample_size: None,
+        }
+    }
+
+    /// Exports only the documents matching `filter`.
+    pub fn filter(mut self, filter: Document) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Exports only the fields `projection` selects of each document.
+    pub fn projection(mut self, projection: Document) -> Self {
+        self.projection = Some(projection);
+        self
+    }
+
+    /// Sets how embedded documents are written.
+    pub fn nested(mut self, policy: NestedPolicy) -> Self {
+        self.nested = policy;
+        self
+    }
+
+    /// Writes at most `rows` rows per row group, and holds no more than
+    /// that many rows in memory. Readers skip whole row groups by their
+    /// column statistics, so smaller groups suit selective queries, and
+    /// larger ones full scans.
+    pub fn row_group_size(mut self, rows: usize) -> Self {
+        self.row_group_size = rows.max(1);
+        self
+    }
+
+    /// Infers the schema from the first `documents` exported, rather than
+    /// reading them all twice. Fields the sample lacks are left out, and a
+    /// value that does not fit its column's type is written as null and
+    /// counted in `ParquetStats::mismatched`.
+    pub fn sample_size(mut self, documents: usize) -> Self {
+        self.sample_size = Some(documents);
+        self
+    }
+}
+
+impl Default for ParquetOptions {
+    fn default() -> Self {
+        ParquetOptions::new()
+    }
+}
+
+/// What `Collection::export_parquet` wrote.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParquetStats {
+    /// The number of documents written, one per row.
+    pub documents: u64,
+    /// The number of row groups written.
+    pub row_groups: u64,
+    /// The columns of the inferred schema, in the order their fields were
+    /// first seen.
+    pub columns: Vec<String>,
+    /// The number of values written as null because they did not fit
+    /// their column's type, which only a sampled schema leaves.
+    pub mismatched: u64,
+}
+
+/// Writes the documents of `collection` that `options` select to a Parquet
+/// file at `path`, and returns what was written: one pass over them infers
+/// the schema, or over the sample `options` set, and another writes them,
+/// a row group at a time.
+pub(crate) fn export(
+    collection: &Collection,
+    path: &Path,
+    options: &ParquetOptions,
+) -> Result<ParquetStats, DatabaseError> {
+    let mut find = FindOptions::new();
+    if let Some(projection) = &options.projection {
+        find = find.projection(projection.clone());
+    }
+
+    let mut columns = Columns::default();
+    let sample = options.sample_size.unwrap_or(usize::MAX);
+    for document in collection.find_with(&options.filter, &find)?.take(sample) {
+        columns.infer(&document?, options.nested);
+    }
+
+    let schema: SchemaRef = Arc::new(Schema::new(
+        columns
+            .types
+            .iter()
+            .zip(&columns.names)
+            .map(|(column_type, name)| Field::new(name, column_type.data_type(), true))
+            .collect::<Vec<_>>(),
+    ));
+    let file = File::create(path).map_err(StorageError::from)?;
+    let properties = WriterProperties::builder()
+        .set_max_row_group_size(options.row_group_size)
+        .build();
+    let mut writer = ArrowWriter::try_new(file, Arc::clone(&schema), Some(properties))?;
+    let mut builders: Vec<ColumnBuilder> = columns
+        .types
+        .iter()
+        .map(|column_type| ColumnBuilder::new(*column_type))
+        .collect();
+
+    let mut stats = ParquetStats::default();
+    let mut rows = 0;
+    for document in collection.find_with(&options.filter, &find)? {
+        let document = document?;
+        let mut values = HashMap::new();
+        visit_columns(&document, options.nested, "", &mut |path, value| {
+            values.insert(path, value);
+        });
+        for (name, builder) in columns.names.iter().zip(&mut builders) {
+            if !builder.append(values.get(name).copied())? {
+                stats.mismatched += 1;
+            }
+        }
+        stats.documents += 1;
+        rows += 1;
+        if rows == options.row_group_size {
+            write_batch(&mut writer, &schema, &mut builders, rows)?;
+            rows = 0;
+        }
+    }
+    if rows > 0 {
+        write_batch(&mut writer, &schema, &mut builders, rows)?;
+    }
+    let metadata = writer.close()?;
+    stats.row_groups = metadata.row_groups.len() as u64;
+    stats.columns = columns.names;
+    Ok(stats)
+}
+
+/// Writes the rows the builders hold as one batch, emptying them.
+fn write_batch(
+    writer: &mut ArrowWriter<File>,
+    schema: &SchemaRef,
+    builders: &mut [ColumnBuilder],
+    rows: usize,
+) -> Result<(), DatabaseError> {
+    let arrays: Vec<ArrayRef> = builders.iter_mut().map(ColumnBuilder::finish).collect();
+    // A batch without columns still has its rows
+    let batch = RecordBatch::try_new_with_options(
+        Arc::clone(schema),
+        arrays,
+        &RecordBatchOptions::new().with_row_count(Some(rows)),
+    )
+    .map_err(ParquetError::from)?;
+    writer.write(&batch)?;
+    Ok(())
+}
+
+/// Calls `visit` with the column path and value of each field of
+/// `document`, descending into embedded documents if `nested` flattens
+/// them.
+fn visit_columns<'a>(
+    document: &'a Document,
+    nested: NestedPolicy,
+    prefix: &str,
+    visit: &mut impl FnMut(String, &'a Value),
+) {
+    for (key, value) in document.iter() {
+        let path = match prefix {
+            "" => key.to_string(),
+            prefix => format!("{}.{}", prefix, key),
+        };
+        match (nested, value) {
+            (NestedPolicy::Flatten, Value::Document(inner)) => {
+                visit_columns(inner, nested, &path, visit)
+            }
+            _ => visit(path, value),
+        }
+    }
+}
+
+/// The columns inferred so far, in the order their fields were first seen.
+#[derive(Default)]
+struct Columns {
+    names: Vec<String>,
+    types: Vec<ColumnType>,
+    index: HashMap<String, usize>,
+}
+
+impl Columns {
+    /// Widens the columns' types to hold the values of `document`, adding
+    /// columns for the fields not seen before.
+    fn infer(&mut self, document: &Document, nested: NestedPolicy) {
+        visit_columns(document, nested, "", &mut |path, value| {
+            let column_type = ColumnType::of(value);
+            match self.index.get(&path) {
+                Some(&column) => self.types[column] = self.types[column].unify(column_type),
+                None => {
+                    self.index.insert(path.clone(), self.names.len());
+                    self.names.push(path);
+                    self.types.push(column_type);
+                }
+            }
+        });
+    }
+}
+
+/// The type of a column, as inferred from its values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColumnType {
+    /// Only nulls so far; written as text.
+    Null,
+    Boolean,
+    Int32,
+    Int64,
+    UInt64,
+    Double,
+    /// Strings and ObjectIds.
+    Text,
+    Binary,
+    Date,
+    /// Anything else, as Extended JSON text.
+    Json,
+}
+
+impl ColumnType {
+    fn of(value: &Value) -> ColumnType {
+        match value {
+            Value::Null => ColumnType::Null,
+            Value::Boolean(_) => ColumnType::Boolean,
+            Value::Int32(_) => ColumnType::Int32,
+            Value::Int64(_) => ColumnType::Int64,
+            Value::UInt64(_) => ColumnType::UInt64,
+            Value::Double(_) => ColumnType::Double,
+            Value::String(_) | Value::ObjectId(_) => ColumnType::Text,
+            Value::Binary(_) => ColumnType::Binary,
+            Value::UTCDateTime(_) => ColumnType::Date,
+            _ => ColumnType::Json,
+        }
+    }
+
+    /// Returns the narrowest type holding the values of both.
+    fn unify(self, other: ColumnType) -> ColumnType {
+        use ColumnType::*;
+        match (self, other) {
+            (a, b) if a == b => a,
+            (Null, other) | (other, Null) => other,
+            (Int32 | Int64 | UInt64, Int32 | Int64 | UInt64) => Int64,
+            (Int32 | Int64 | UInt64 | Double, Int32 | Int64 | UInt64 | Double) => Double,
+            _ => Json,
+        }
+    }
+
+    fn data_type(self) -> DataType {
+        match self {
+            ColumnType::Null | ColumnType::Text | ColumnType::Json => DataType::Utf8,
+            ColumnType::Boolean => DataType::Boolean,
+            ColumnType::Int32 => DataType::Int32,
+            ColumnType::Int64 => DataType::Int64,
+            ColumnType::UInt64 => DataType::UInt64,
+            ColumnType::Double => DataType::Float64,
+            ColumnType::Binary => DataType::Binary,
+            ColumnType::Date => DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+        }
+    }
+}
+
+/// Collects the values of one column for the next batch.
+enum ColumnBuilder {
+    Boolean(BooleanBuilder),
+    Int32(Int32Builder),
+    Int64(Int64Builder),
+    UInt64(UInt64Builder),
+    Double(Float64Builder),
+    Text(StringBuilder),
+    Binary(BinaryBuilder),
+    Date(TimestampMillisecondBuilder),
+    Json(StringBuilder),
+}
+
+impl ColumnBuilder {
+    fn new(column_type: ColumnType) -> Self {
+        match column_type {
+            ColumnType::Null | ColumnType::Text => ColumnBuilder::Text(StringBuilder::new()),
+            ColumnType::Boolean => ColumnBuilder::Boolean(BooleanBuilder::new()),
+            ColumnType::Int32 => ColumnBuilder::Int32(Int32Builder::new()),
+            ColumnType::Int64 => ColumnBuilder::Int64(Int64Builder::new()),
+            ColumnType::UInt64 => ColumnBuilder::UInt64(UInt64Builder::new()),
+            ColumnType::Double => ColumnBuilder::Double(Float64Builder::new()),
+            ColumnType::Binary => ColumnBuilder::Binary(BinaryBuilder::new()),
+            ColumnType::Date => ColumnBuilder::Date(TimestampMillisecondBuilder::new()),
+            ColumnType::Json => ColumnBuilder::Json(StringBuilder::new()),
+        }
+    }
+
+    /// Appends `value`, or null if it is missing or null. Returns whether
+    /// it fit the column's type; one that does not is appended as null.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value cannot be written as JSON.
+    fn append(&mut self, value: Option<&Value>) -> Result<bool, DatabaseError> {
+        let Some(value) = value.filter(|value| !matches!(value, Value::Null)) else {
+            self.append_null();
+            return Ok(true);
+        };
+        let fits = match (&mut *self, value) {
+            (ColumnBuilder::Boolean(builder), Value::Boolean(value)) => {
+                builder.append_value(*value);
+                true
+            }
+            (ColumnBuilder::Int32(builder), Value::Int32(value)) => {
+                builder.append_value(*value);
+                true
+            }
+            (ColumnBuilder::Int64(builder), value) => match as_i64(value) {
+                Some(value) => {
+                    builder.append_value(value);
+                    true
+                }
+                None => false,
+            },
+            (ColumnBuilder::UInt64(builder), Value::UInt64(value)) => {
+                builder.append_value(*value);
+                true
+            }
+            (ColumnBuilder::Double(builder), value) => match as_f64(value) {
+                Some(value) => {
+                    builder.append_value(value);
+                    true
+                }
+                None => false,
+            },
+            (ColumnBuilder::Text(builder), Value::String(value)) => {
+                builder.append_value(value);
+                true
+            }
+            (ColumnBuilder::Text(builder), Value::ObjectId(id)) => {
+                builder.append_value(id.to_string());
+                true
+            }
+            (ColumnBuilder::Binary(builder), Value::Binary(bytes)) => {
+                builder.append_value(bytes);
+                true
+            }
+            (ColumnBuilder::Date(builder), Value::UTCDateTime(secs)) => {
+                builder.append_value(secs.saturating_mul(1000));
+                true
+            }
+            (ColumnBuilder::Json(builder), value) => {
+                builder.append_value(json_text(value)?);
+                true
+            }
+            _ => false,
+        };
+        if !fits {
+            self.append_null();
+        }
+        Ok(fits)
+    }
+
+    fn append_null(&mut self) {
+        match self {
+            ColumnBuilder::Boolean(builder) => builder.append_null(),
+            ColumnBuilder::Int32(builder) => builder.append_null(),
+            ColumnBuilder::Int64(builder) => builder.append_null(),
+            ColumnBuilder::UInt64(builder) => builder.append_null(),
+            ColumnBuilder::Double(builder) => builder.append_null(),
+            ColumnBuilder::Text(builder) | ColumnBuilder::Json(builder) => builder.append_null(),
+            ColumnBuilder::Binary(builder) => builder.append_null(),
+            ColumnBuilder::Date(builder) => builder.append_null(),
+        }
+    }
+
+    /// Returns the values appended since the last call as an array.
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            ColumnBuilder::Boolean(builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Int32(builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Int64(builder) => Arc::new(builder.finish()),
+            ColumnBuilder::UInt64(builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Double(builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Text(builder) | ColumnBuilder::Json(builder) => {
+                Arc::new(builder.finish())
+            }
+            ColumnBuilder::Binary(builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Date(builder) => Arc::new(builder.finish().with_timezone("UTC")),
+        }
+    }
+}
+
+fn as_i64(value: &Value) -> Option<i64> {
+    match value {
+        Value::Int32(value) => Some(*value as i64),
+        Value::Int64(value) => Some(*value),
+        Value::UInt64(value) => i64::try_from(*value).ok(),
+        _ => None,
+    }
+}
+
+fn as_f64(value: &Value) -> Option<f64> {
+    match value {
+        Value::Double(value) => Some(*value),
+        Value::Int32(value) => Some(*value as f64),
+        Value::Int64(value) => Some(*value as f64),
+        Value::UInt64(value) => Some(*value as f64),
+        _ => None,
+    }
+}
+
+/// Returns `value` as relaxed Extended JSON.
+fn json_text(value: &Value) -> Result<String, DatabaseError> {
+    let mut serializer = JsonSerializer::new(Vec::new(), false);
+    value.serialize(&mut serializer)?;
+    Ok(String::from_utf8_lossy(&serializer.into_inner()).into_owned())
+}
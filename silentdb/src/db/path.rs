@@ -0,0 +1,51 @@
+// src/db/path.rs
+
+use silentdb_data_encoding::{Document, Value};
+
+/// Returns the value at a dotted path, where a numeric segment indexes
+/// into an array.
+pub(crate) fn get_path<'a>(document: &'a Document, path: &str) -> Option<&'a Value> {
+    let mut segments = path.split('.');
+    let mut current = document.get(segments.next()?)?;
+    for segment in segments {
+        current = match current {
+            Value::Document(document) => document.get(segment)?,
+            Value::Array(array) => array.get(segment.parse().ok()?)?,
+            _ => return None,
+        };
+    }
+    Some(current)
+}
+
+/// Returns the values at a dotted path for `Collection::distinct`: those
+/// found through embedded documents and arrays of them, with an array at
+/// the end of the path giving its elements instead.
+pub(crate) fn distinct_values<'a>(document: &'a Document, path: &str) -> Vec<&'a Value> {
+    let segments: Vec<&str> = path.split('.').collect();
+    let mut values = Vec::new();
+    collect(document, &segments, &mut values);
+    values
+}
+
+fn collect<'a>(document: &'a Document, segments: &[&str], out: &mut Vec<&'a Value>) {
+    let Some(value) = document.get(segments[0]) else {
+        return;
+    };
+    collect_value(value, &segments[1..], out);
+}
+
+fn collect_value<'a>(value: &'a Value, rest: &[&str], out: &mut Vec<&'a Value>) {
+    match (value, rest.is_empty()) {
+        (Value::Array(array), true) => out.extend(array.iter()),
+        (value, true) => out.push(value),
+        (Value::Document(document), false) => collect(document, rest, out),
+        (Value::Array(array), false) => {
+            for element in array.iter() {
+                if let Value::Document(document) = element {
+                    collect(document, rest, out);
+                }
+            }
+        }
+        _ => {}
+    }
+}
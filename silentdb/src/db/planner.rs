@@ -0,0 +1,1144 @@
+// src/db/planner.rs
+
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::ops::Bound;
+use std::time::{Duration, Instant};
+
+use silentdb_data_encoding::{from_bytes, to_bytes, Array, Document, RawDocument, Value};
+
+use super::database::DatabaseInner;
+use super::error::DatabaseError;
+use super::index::{complement, length_key, IndexInfo, SortOrder};
+use super::projection::Projection;
+use crate::accounting::{MemoryCategory, MemoryScope};
+use crate::geo::{self, Geometry, Near, Region};
+use crate::query::{path_values, Collation, Matcher, QueryError};
+use crate::storage::{encode_key, KeyRange};
+use crate::text::Corpus;
+
+/// Entries read from storage per scan while looking for matches.
+const SCAN_BATCH: usize = 256;
+/// Entries counted at most per candidate plan when estimating its cost.
+const ESTIMATE_LIMIT: usize = 1000;
+/// The cost of reading a document and checking it against a filter, in
+/// index entries read.
+const FETCH_COST: u64 = 4;
+
+/// A matched document along with its storage key and encoding.
+pub(crate) struct Stored {
+    pub(crate) key: Vec<u8>,
+    pub(crate) bytes: Vec<u8>,
+    /// The encoding of the document upgraded by the collection's schema
+    /// migrations, if `bytes` is at an older version.
+    pub(crate) upgraded: Option<Vec<u8>>,
+    pub(crate) document: Document,
+    /// The document's relevance to a `$text` query.
+    pub(crate) score: Option<f64>,
+}
+
+/// Where a query reads its candidate documents from.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Plan {
+    /// A range of `_id` keys in the collection itself.
+    Primary(KeyRange),
+    /// A range of entries in a secondary index.
+    Index { index: IndexInfo, range: KeyRange },
+    /// The documents of a text index holding any of `terms`, by relevance.
+    Text {
+        index: IndexInfo,
+        terms: Vec<String>,
+    },
+    /// The entries of a geo index in `ranges`, read in turn.
+    Geo {
+        index: IndexInfo,
+        ranges: VecDeque<KeyRange>,
+    },
+    /// The documents of a geo index with a geometry in range of `near`,
+    /// nearest first, found in `ranges`.
+    Near {
+        index: IndexInfo,
+        near: Near,
+        ranges: Vec<KeyRange>,
+    },
+    /// The documents with entries in the range of every one of several
+    /// secondary indexes, in `_id` order.
+    Intersect(Vec<(IndexInfo, KeyRange)>),
+}
+
+impl Plan {
+    /// Describes the plan for `Collection::explain`.
+    pub(crate) fn to_document(&self) -> Document {
+        let mut document = Document::new();
+        match self {
+            Plan::Primary(range) if *range == KeyRange::all() => {
+                document.insert("stage", "collection_scan");
+            }
+            Plan::Primary(_) => {
+                document.insert("stage", "id_scan");
+            }
+            Plan::Index { index, .. } => {
+                document.insert("stage", "index_scan");
+                document.insert("index", index.name.as_str());
+            }
+            Plan::Text { index, terms } => {
+                document.insert("stage", "text");
+                document.insert("index", index.name.as_str());
+                let terms = terms.iter().map(|term| term.as_str().into()).collect();
+                document.insert("terms", Array::from_vec(terms));
+            }
+            Plan::Geo { index, .. } => {
+                document.insert("stage", "geo");
+                document.insert("index", index.name.as_str());
+            }
+            Plan::Near { index, .. } => {
+                document.insert("stage", "near");
+                document.insert("index", index.name.as_str());
+            }
+            Plan::Intersect(scans) => {
+                document.insert("stage", "index_intersection");
+                let names = scans
+                    .iter()
+                    .map(|(index, _)| index.name.as_str().into())
+                    .collect();
+                document.insert("indexes", Array::from_vec(names));
+            }
+        }
+        document
+    }
+}
+
+/// A plan considered for a query, as returned by `choose`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Candidate {
+    pub(crate) plan: Plan,
+    /// The number of documents the plan is expected to read, if known.
+    pub(crate) estimate: Option<u64>,
+    /// The estimated cost of the plan, in entries read in order, if known.
+    pub(crate) cost: Option<u64>,
+    /// Whether the plan answers the query from index entries alone,
+    /// without reading documents.
+    pub(crate) covered: bool,
+}
+
+impl Candidate {
+    pub(crate) fn new(plan: Plan) -> Self {
+        Candidate {
+            plan,
+            estimate: None,
+            cost: None,
+            covered: false,
+        }
+    }
+}
+
+/// The values the predicates on one field can match, as bounds on their
+/// key encodings.
+///
+/// An inclusive bound takes in every key starting with it, so a bound of a
+/// single type tag byte covers the whole type bracket.
+#[derive(Debug, Clone, Default)]
+struct FieldBounds {
+    lower: Option<(Vec<u8>, bool)>,
+    upper: Option<(Vec<u8>, bool)>,
+}
+
+impl FieldBounds {
+    /// Returns the bounds each predicate in `value` puts on a field, one
+    /// per operator that narrows them.
+    fn of(value: &Value) -> Vec<FieldBounds> {
+        let bounds = |lower: Option<(Vec<u8>, bool)>, upper: Option<(Vec<u8>, bool)>| FieldBounds {
+            lower,
+            upper,
+        };
+        let point = |value: &Value| {
+            let key = encode_key(value);
+            bounds(Some((key.clone(), true)), Some((key, true)))
+        };
+        let operators = match value {
+            Value::RegularExpression { .. } => return Vec::new(),
+            Value::Document(operators) if operators.iter().any(|(key, _)| key.starts_with('$')) => {
+                operators
+            }
+            value => return vec![point(value)],
+        };
+        operators
+            .iter()
+            .filter_map(|(operator, operand)| match operator.as_str() {
+                "$eq" if !matches!(operand, Value::RegularExpression { .. }) => {
+                    Some(point(operand))
+                }
+                "$gt" => Some(bounds(Some((encode_key(operand), false)), None)),
+                "$gte" => Some(bounds(Some((encode_key(operand), true)), None)),
+                "$lt" => Some(bounds(None, Some((encode_key(operand), false)))),
+                "$lte" => Some(bounds(None, Some((encode_key(operand), true)))),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Narrows the bounds to the values both `self` and `other` allow.
+    ///
+    /// Only valid for fields holding a single value: an array field can
+    /// match each predicate with a different element.
+    fn intersect(mut self, other: &FieldBounds) -> FieldBounds {
+        if let Some((key, inclusive)) = &other.lower {
+            let tighter = match &self.lower {
+                Some((lower, lower_inclusive)) => {
+                    key > lower || (key == lower && *lower_inclusive && !inclusive)
+                }
+                None => true,
+            };
+            if tighter {
+                self.lower = other.lower.clone();
+            }
+        }
+        if let Some((key, inclusive)) = &other.upper {
+            let tighter = match &self.upper {
+                Some((upper, upper_inclusive)) => {
+                    key < upper || (key == upper && *upper_inclusive && !inclusive)
+                }
+                None => true,
+            };
+            if tighter {
+                self.upper = other.upper.clone();
+            }
+        }
+        self
+    }
+
+    /// Returns `true` if the bounds allow `null`, which a missing path is
+    /// compared as.
+    fn allows_null(&self) -> bool {
+        let null = encode_key(&Value::Null);
+        [&self.lower, &self.upper]
+            .into_iter()
+            .any(|bound| matches!(bound, Some((key, true)) if *key == null))
+    }
+
+    /// Returns `true` if the bounds allow a single value.
+    fn is_point(&self) -> bool {
+        matches!(
+            (&self.lower, &self.upper),
+            (Some((lower, true)), Some((upper, true))) if lower == upper
+        )
+    }
+
+    /// Returns the start (inclusive) and end (exclusive) of the keys the
+    /// bounds allow in a field sorted in `order`.
+    ///
+    /// A missing bound is taken from the type bracket of the other one, so
+    /// that range predicates stay within the bracket of their operand as
+    /// the matcher does.
+    fn key_range(&self, order: SortOrder) -> (Vec<u8>, Vec<u8>) {
+        let bracket =
+            |bound: &Option<(Vec<u8>, bool)>| bound.as_ref().map(|(key, _)| (vec![key[0]], true));
+        let lower = self.lower.clone().or_else(|| bracket(&self.upper));
+        let upper = self.upper.clone().or_else(|| bracket(&self.lower));
+        let (Some((lower, lower_inclusive)), Some((upper, upper_inclusive))) = (lower, upper)
+        else {
+            unreachable!("field bounds without a bound");
+        };
+        if lower[0] != upper[0] {
+            // Bounds in different brackets match nothing
+            return (lower.clone(), lower);
+        }
+
+        let (start, end) = match order {
+            SortOrder::Ascending => (
+                if lower_inclusive {
+                    lower.clone()
+                } else {
+                    past(&lower)
+                },
+                if upper_inclusive { past(&upper) } else { upper },
+            ),
+            SortOrder::Descending => {
+                let (lower, upper) = (complement(&lower), complement(&upper));
+                (
+                    if upper_inclusive {
+                        upper.clone()
+                    } else {
+                        past(&upper)
+                    },
+                    if lower_inclusive { past(&lower) } else { lower },
+                )
+            }
+        };
+        if start > end {
+            return (start.clone(), start);
+        }
+        (start, end)
+    }
+}
+
+/// Returns the best ranked of the plans for `filter`, or a scan of the
+/// whole collection if there are none, without looking at the data.
+#[cfg(test)]
+pub(crate) fn plan(filter: &Document, indexes: &[IndexInfo]) -> Plan {
+    ranked_plans(filter, indexes, None)
+        .into_iter()
+        .next()
+        .map_or(Plan::Primary(KeyRange::all()), |(_, plan)| plan)
+}
+
+/// Returns the plans that could find the documents matching `filter`,
+/// given the collection's `indexes`, and their ranks, best first.
+///
+/// Equality and range predicates on `_id` or indexed paths, at the top
+/// level of the filter or inside `$and`, narrow the scan to a key range.
+/// Predicates are combined for `_id`, but an indexed path may hold an
+/// array whose elements satisfy different predicates, so only one of its
+/// predicates is used. A compound index is usable for equality on a prefix
+/// of its fields, optionally followed by a range on the next one. A sparse
+/// index is only usable if its first field's bounds exclude `null`, and a
+/// partial index only if every predicate of its filter also appears in
+/// `filter`, since they leave documents out. Equality on `_id` ranks
+/// first, then the index with equality on the most fields, then a range on
+/// `_id`, then a range on an index. A scan only narrows the candidates, and
+/// every one is still checked against the full filter.
+///
+/// Under a `collation` that is not simple, indexes are only usable if they
+/// share it, and string predicates on `_id` cannot narrow the scan, since
+/// the primary index orders strings by code point.
+/// Returns how many leading index fields a plan ranked `rank` by
+/// `ranked_plans` fixes to one value, which index scans with equality on
+/// some fields are ranked by.
+fn fixed_fields(rank: (u8, usize)) -> usize {
+    match rank {
+        (1, rank) => usize::MAX - rank,
+        _ => 0,
+    }
+}
+
+fn ranked_plans(
+    filter: &Document,
+    indexes: &[IndexInfo],
+    collation: Option<&Collation>,
+) -> Vec<((u8, usize), Plan)> {
+    let collation = collation.filter(|collation| !collation.is_simple());
+    let mut fields: HashMap<String, Vec<FieldBounds>> = HashMap::new();
+    let mut predicates = Vec::new();
+    visit_fields(filter, &mut |path, value| {
+        predicates.push((path.to_string(), value.clone()));
+        let value = match collation {
+            Some(_) if path == "_id" && has_strings(value) => return,
+            Some(collation) => collation.key(value),
+            None => value.clone(),
+        };
+        let bounds = FieldBounds::of(&value);
+        if !bounds.is_empty() {
+            fields.entry(path.to_string()).or_default().extend(bounds);
+        }
+    });
+    // An _id holds a single value, so all its predicates can be combined,
+    // while an indexed path may be an array and can only use one
+    let bounds = |path: &str| -> Option<FieldBounds> {
+        let bounds = fields.get(path)?;
+        if path == "_id" {
+            let (first, rest) = bounds.split_first()?;
+            return Some(rest.iter().fold(first.clone(), FieldBounds::intersect));
+        }
+        bounds
+            .iter()
+            .find(|bounds| bounds.is_point())
+            .or(bounds.first())
+            .cloned()
+    };
+
+    // Lower ranks are better
+    let mut ranked: Vec<((u8, usize), Plan)> = Vec::new();
+    let mut consider = |rank: (u8, usize), plan: Plan| ranked.push((rank, plan));
+    if let Some(bounds) = bounds("_id") {
+        let (start, end) = bounds.key_range(SortOrder::Ascending);
+        let tier = if bounds.is_point() { 0 } else { 2 };
+        consider((tier, 0), Plan::Primary(range(start, end)));
+    }
+    for index in indexes.iter().filter(|index| {
+        let index_collation = index.collation.as_ref().filter(|c| !c.is_simple());
+        index.text.is_none() && !index.geo && index_collation == collation
+    }) {
+        if let Some(partial) = &index.partial_filter {
+            if !implies(&predicates, partial) {
+                continue;
+            }
+        }
+        if index.sparse {
+            let first = index.fields.first().and_then(|(path, _)| bounds(path));
+            if first.is_none_or(|bounds| bounds.allows_null()) {
+                continue;
+            }
+        }
+        let mut prefix = Vec::new();
+        let mut equal = 0;
+        let mut last = None;
+        for (path, order) in &index.fields {
+            let Some(bounds) = bounds(path) else {
+                break;
+            };
+            let (start, end) = bounds.key_range(*order);
+            if bounds.is_point() {
+                prefix.extend_from_slice(&start);
+                equal += 1;
+            } else {
+                last = Some((start, end));
+                break;
+            }
+        }
+        let range = match last {
+            Some((start, end)) => {
+                range([&prefix, &start[..]].concat(), [&prefix, &end[..]].concat())
+            }
+            None if equal > 0 => KeyRange::prefix(&prefix),
+            None => continue,
+        };
+        let tier = if equal > 0 { 1 } else { 3 };
+        let plan = Plan::Index {
+            index: index.clone(),
+            range,
+        };
+        consider((tier, usize::MAX - equal), plan);
+    }
+    ranked.sort_by_key(|(rank, _)| *rank);
+    ranked
+}
+
+/// Chooses how to find the documents matching `filter` by estimating the
+/// cost of every plan `ranked_plans` returns, of a scan of the whole
+/// collection, and of the intersection of the two most selective index
+/// scans, for a query under `collation`. With a `projection`, every
+/// covering index that `covers` the query is also considered for a scan
+/// of all its entries. Returns the candidates, the chosen one first.
+///
+/// Estimates count the entries in each plan's range, each document read
+/// costing `FETCH_COST` index entries, which covered plans do not read;
+/// the size of an intersection is estimated assuming its predicates are
+/// independent. A range holding more than `ESTIMATE_LIMIT` entries is
+/// estimated from the statistics `Collection::stats` last gathered, if
+/// they can tell: the document count for a scan of the whole collection,
+/// the entry count for a scan of a whole index, or the entries per
+/// distinct value of the fixed fields for an index scan. Otherwise it is
+/// not costed, and if no candidate is, the best ranked one is chosen.
+/// Nothing is estimated if `filter` allows no plan but a collection scan,
+/// or fixes `_id`, since no plan reads less than that lookup.
+pub(crate) fn choose(
+    inner: &DatabaseInner,
+    txn: Option<u64>,
+    collection: &str,
+    filter: &Document,
+    indexes: &[IndexInfo],
+    collation: Option<&Collation>,
+    projection: Option<&Projection>,
+) -> Result<Vec<Candidate>, DatabaseError> {
+    let ranked = ranked_plans(filter, indexes, collation);
+    if let Some(((0, _), plan)) = ranked.first() {
+        return Ok(vec![Candidate::new(plan.clone())]);
+    }
+    let mut fixed: Vec<usize> = ranked.iter().map(|(rank, _)| fixed_fields(*rank)).collect();
+    let mut candidates: Vec<Candidate> = ranked
+        .into_iter()
+        .map(|(_, plan)| Candidate::new(plan))
+        .collect();
+    let collection_scan = Plan::Primary(KeyRange::all());
+    candidates.push(Candidate::new(collection_scan.clone()));
+    fixed.push(0);
+    // Index entries hold documents as stored, not as their migrations
+    // upgrade them
+    let projection = projection.filter(|_| inner.migrations.latest(collection) == 0);
+    if let Some(projection) = projection {
+        // A sparse or partial index leaves out documents the query may match
+        for index in indexes
+            .iter()
+            .filter(|index| !index.sparse && index.partial_filter.is_none())
+        {
+            let plan = Plan::Index {
+                index: index.clone(),
+                range: KeyRange::all(),
+            };
+            if covers(&plan, filter, projection) && !candidates.iter().any(|c| c.plan == plan) {
+                candidates.push(Candidate::new(plan));
+                fixed.push(0);
+            }
+        }
+    }
+    if candidates.len() == 1 {
+        return Ok(candidates);
+    }
+    let stats = inner.collection_stats.get(collection);
+    for (candidate, fixed) in candidates.iter_mut().zip(fixed) {
+        candidate.estimate = match estimate(inner, txn, collection, &candidate.plan)? {
+            Some(estimate) => Some(estimate),
+            None => stats.and_then(|stats| stats.estimate(&candidate.plan, fixed)),
+        };
+        candidate.covered =
+            projection.is_some_and(|projection| covers(&candidate.plan, filter, projection));
+        candidate.cost = candidate.estimate.map(|estimate| match candidate.plan {
+            Plan::Primary(_) => estimate * FETCH_COST,
+            _ if candidate.covered => estimate,
+            _ => estimate * (1 + FETCH_COST),
+        });
+    }
+
+    let size = candidates
+        .iter()
+        .find(|candidate| candidate.plan == collection_scan)
+        .and_then(|candidate| candidate.estimate)
+        .unwrap_or(ESTIMATE_LIMIT as u64)
+        .max(1);
+    let mut scans: Vec<(u64, &IndexInfo, &KeyRange)> = Vec::new();
+    for candidate in &candidates {
+        if let (Plan::Index { index, range }, Some(estimate)) =
+            (&candidate.plan, candidate.estimate)
+        {
+            // Every document has an entry in an index scanned in full
+            if *range == KeyRange::all() {
+                continue;
+            }
+            if scans.iter().all(|(_, other, _)| other.name != index.name) {
+                scans.push((estimate, index, range));
+            }
+        }
+    }
+    scans.sort_by_key(|(estimate, _, _)| *estimate);
+    if let [(a, first, first_range), (b, second, second_range), ..] = scans[..] {
+        let estimate = (a * b).div_ceil(size);
+        candidates.push(Candidate {
+            plan: Plan::Intersect(vec![
+                (first.clone(), first_range.clone()),
+                (second.clone(), second_range.clone()),
+            ]),
+            estimate: Some(estimate),
+            cost: Some(a + b + estimate * FETCH_COST),
+            covered: false,
+        });
+    }
+
+    let chosen = (0..candidates.len())
+        .min_by_key(|&i| (candidates[i].cost.is_none(), candidates[i].cost, i))
+        .expect("a collection scan is always a candidate");
+    let chosen = candidates.remove(chosen);
+    candidates.insert(0, chosen);
+    Ok(candidates)
+}
+
+/// Returns how many documents `plan` reads from `collection`, counted
+/// from its index entries, or `None` if that is more than
+/// `ESTIMATE_LIMIT` or the plan is ranked rather than scanned.
+pub(crate) fn estimate(
+    inner: &DatabaseInner,
+    txn: Option<u64>,
+    collection: &str,
+    plan: &Plan,
+) -> Result<Option<u64>, DatabaseError> {
+    let (namespace, range) = match plan {
+        Plan::Primary(range) => (collection.to_string(), range),
+        Plan::Index { index, range } => (index.namespace(collection), range),
+        _ => return Ok(None),
+    };
+    let count = inner
+        .scan(txn, &namespace, range, ESTIMATE_LIMIT + 1)?
+        .len();
+    Ok((count <= ESTIMATE_LIMIT).then_some(count as u64))
+}
+
+/// Returns `true` if `plan` can answer a query with `filter` and
+/// `projection` from the entries of its index, which must be covering and
+/// compare strings by code point: every path the filter tests and the
+/// projection reads must be `_id` or an indexed path, and the filter may
+/// use no top-level operator but `$and`.
+fn covers(plan: &Plan, filter: &Document, projection: &Projection) -> bool {
+    let Plan::Index { index, .. } = plan else {
+        return false;
+    };
+    if !index.covering || index.collation.as_ref().is_some_and(|c| !c.is_simple()) {
+        return false;
+    }
+    let indexed = |path: &str| path == "_id" || index.fields.iter().any(|(field, _)| field == path);
+    let Some(mut paths) = projection.paths() else {
+        return false;
+    };
+    let mut covered = paths.all(indexed);
+    visit_clauses(filter, &mut |path, _| covered &= path.is_some_and(indexed));
+    covered
+}
+
+/// Splits the top-level `$text` query off `filter`, returning its search
+/// string, if any, and the rest of the filter.
+///
+/// # Errors
+///
+/// Returns `Query` if `$text` is not a document holding just a `$search`
+/// string.
+pub(crate) fn split_text(filter: &Document) -> Result<(Option<String>, Document), DatabaseError> {
+    let mut search = None;
+    let mut rest = Document::new();
+    for (key, value) in filter.iter() {
+        if key != "$text" {
+            rest.insert(key.as_str(), value.clone());
+            continue;
+        }
+        let Value::Document(text) = value else {
+            return Err(bad_text("$text needs a document"));
+        };
+        for (operator, operand) in text.iter() {
+            match (operator.as_str(), operand) {
+                ("$search", Value::String(string)) => search = Some(string.clone()),
+                ("$search", _) => return Err(bad_text("$search needs a string")),
+                (operator, _) => {
+                    return Err(bad_text(&format!("unknown $text option {}", operator)))
+                }
+            }
+        }
+        if search.is_none() {
+            return Err(bad_text("$text needs $search"));
+        }
+    }
+    Ok((search, rest))
+}
+
+fn bad_text(message: &str) -> DatabaseError {
+    QueryError::InvalidFilter(message.to_string()).into()
+}
+
+/// Plans a `$text` query for `search` over the collection's text index.
+///
+/// # Errors
+///
+/// Returns `Query` if `collection` has no text index.
+pub(crate) fn plan_text(
+    collection: &str,
+    search: &str,
+    indexes: &[IndexInfo],
+) -> Result<Plan, DatabaseError> {
+    let Some(index) = indexes.iter().find(|index| index.text.is_some()) else {
+        return Err(bad_text(&format!(
+            "$text needs a text index on {}",
+            collection
+        )));
+    };
+    let options = index.text.as_ref().expect("found by its text options");
+    let mut terms = options.terms(search);
+    terms.sort();
+    terms.dedup();
+    Ok(Plan::Text {
+        index: index.clone(),
+        terms,
+    })
+}
+
+/// Plans a query with geo operators on a path with a geo index, unless
+/// `fallback`, the plan for the rest of the filter, already narrows the
+/// scan.
+///
+/// `$near` always uses the index, returning documents nearest first, while
+/// `$geoWithin` and `$geoIntersects` read the index entries near their
+/// shape.
+///
+/// # Errors
+///
+/// Returns `Query` if `$near` is on a path without a geo index.
+pub(crate) fn plan_geo(
+    collection: &str,
+    filter: &Document,
+    indexes: &[IndexInfo],
+    fallback: Plan,
+) -> Result<Plan, DatabaseError> {
+    let geo_index = |path: &str| {
+        indexes
+            .iter()
+            .find(|index| index.geo && index.fields[0].0 == path)
+    };
+    let mut near = None;
+    let mut within = None;
+    visit_fields(filter, &mut |path, value| {
+        let Value::Document(operators) = value else {
+            return;
+        };
+        for (operator, operand) in operators.iter() {
+            let area = match operator.as_str() {
+                "$near" => {
+                    if near.is_none() {
+                        near = Some((path.to_string(), Near::from_operand(operand).ok()));
+                    }
+                    continue;
+                }
+                "$geoWithin" => Region::from_operand(operand).map(|region| region.bounds()),
+                "$geoIntersects" => Geometry::from_operand(operand).map(|shape| shape.bounds()),
+                _ => continue,
+            };
+            if let (Some(index), Ok(area)) = (geo_index(path), area) {
+                within.get_or_insert((index, area));
+            }
+        }
+    });
+
+    if let Some((path, near)) = near {
+        let Some(index) = geo_index(&path) else {
+            return Err(QueryError::InvalidFilter(format!(
+                "$near needs a geo index on {}.{}",
+                collection, path
+            ))
+            .into());
+        };
+        let near = near.expect("checked by the matcher");
+        let ranges = match near.max.is_finite() {
+            true => geo::search_ranges(&geo::Rect::around(&near.center, near.max)),
+            false => vec![KeyRange::all()],
+        };
+        return Ok(Plan::Near {
+            index: index.clone(),
+            near,
+            ranges,
+        });
+    }
+    match within {
+        Some((index, area)) if fallback == Plan::Primary(KeyRange::all()) => Ok(Plan::Geo {
+            index: index.clone(),
+            ranges: geo::search_ranges(&area).into(),
+        }),
+        _ => Ok(fallback),
+    }
+}
+
+/// Calls `visit` for every field predicate that all matches must satisfy.
+pub(crate) fn visit_fields(filter: &Document, visit: &mut impl FnMut(&str, &Value)) {
+    visit_clauses(filter, &mut |path, value| {
+        if let Some(path) = path {
+            visit(path, value);
+        }
+    });
+}
+
+/// Returns `true` if `value` is or holds a string.
+fn has_strings(value: &Value) -> bool {
+    match value {
+        Value::String(_) => true,
+        Value::Array(array) => array.iter().any(has_strings),
+        Value::Document(document) => document.iter().any(|(_, value)| has_strings(value)),
+        _ => false,
+    }
+}
+
+/// Returns `true` if a filter with field `predicates` only matches
+/// documents that match `partial`, judged by `partial`'s predicates each
+/// appearing among them.
+fn implies(predicates: &[(String, Value)], partial: &Document) -> bool {
+    let mut required = Vec::new();
+    let mut understood = true;
+    visit_clauses(partial, &mut |path, value| match path {
+        Some(path) => required.push((path.to_string(), value.clone())),
+        None => understood = false,
+    });
+    understood
+        && required
+            .iter()
+            .all(|predicate| predicates.contains(predicate))
+}
+
+/// Calls `visit` for every clause that all matches must satisfy, with the
+/// path of field predicates and `None` for other operators.
+fn visit_clauses(filter: &Document, visit: &mut impl FnMut(Option<&str>, &Value)) {
+    for (key, value) in filter.iter() {
+        match (key.as_str(), value) {
+            ("$and", Value::Array(filters)) => {
+                for filter in filters.iter() {
+                    match filter {
+                        Value::Document(filter) => visit_clauses(filter, visit),
+                        filter => visit(None, filter),
+                    }
+                }
+            }
+            (key, value) if key.starts_with('$') => visit(None, value),
+            (path, value) => visit(Some(path), value),
+        }
+    }
+}
+
+fn range(start: Vec<u8>, end: Vec<u8>) -> KeyRange {
+    KeyRange::new(Bound::Included(start), Bound::Excluded(end))
+}
+
+/// Returns the first key past every key starting with `key`.
+fn past(key: &[u8]) -> Vec<u8> {
+    match KeyRange::prefix(key).end {
+        Bound::Excluded(end) => end,
+        // Key encodings never consist only of 0xFF bytes
+        _ => unreachable!("key encoding without an upper bound"),
+    }
+}
+
+/// A query in progress: where it reads from and how far it has got.
+#[derive(Debug)]
+pub(crate) struct QueryScan {
+    collection: String,
+    txn: Option<u64>,
+    matcher: Matcher,
+    plan: Plan,
+    /// Primary keys already returned from a secondary index, which may
+    /// hold several entries for one document.
+    seen: HashSet<Vec<u8>>,
+    /// For a text, near or intersection plan, the primary keys of the
+    /// documents left to return and their scores or distances, once ranked
+    /// by the first batch.
+    ranked: Option<VecDeque<(Vec<u8>, f64)>>,
+    /// The number of documents read and checked against the filter.
+    examined: u64,
+    /// The fields returned of each match, if not all of them.
+    projection: Option<Projection>,
+    /// Whether matches are made from index entries rather than read.
+    covered: bool,
+    /// How long the scan may spend fetching matches, across batches.
+    max_time: Option<Duration>,
+    /// How long it has spent so far.
+    spent: Duration,
+}
+
+impl QueryScan {
+    /// Starts a scan of `collection` for documents matching `matcher`,
+    /// as seen by transaction `txn` if given.
+    pub(crate) fn new(collection: &str, txn: Option<u64>, matcher: Matcher, plan: Plan) -> Self {
+        QueryScan {
+            collection: collection.to_string(),
+            txn,
+            matcher,
+            plan,
+            seen: HashSet::new(),
+            ranked: None,
+            examined: 0,
+            projection: None,
+            covered: false,
+            max_time: None,
+            spent: Duration::ZERO,
+        }
+    }
+
+    /// Returns only the fields `projection` selects of each match, made
+    /// from the entries of the plan's index if `covered`, as decided by
+    /// `covers`.
+    pub(crate) fn project(mut self, projection: Projection, covered: bool) -> Self {
+        self.projection = Some(projection);
+        self.covered = covered;
+        self
+    }
+
+    /// Fails with `MaxTimeExpired` once fetching matches has taken longer
+    /// than `max_time` in all. Time between batches is not counted.
+    pub(crate) fn max_time(mut self, max_time: Duration) -> Self {
+        self.max_time = Some(max_time);
+        self
+    }
+
+    /// Reads as of the snapshot of transaction `snapshot` instead, if
+    /// given.
+    pub(crate) fn in_snapshot(mut self, snapshot: Option<u64>) -> Self {
+        self.txn = snapshot.or(self.txn);
+        self
+    }
+
+    /// Returns the number of documents read and checked against the
+    /// filter so far, which a covered scan reads none of.
+    pub(crate) fn examined(&self) -> u64 {
+        self.examined
+    }
+
+    /// Returns the next `limit` matches, in `_id` order for a primary
+    /// or intersection scan, by descending score and then `_id` for a text
+    /// search, by distance and then `_id` for `$near`, and in index order
+    /// otherwise.
+    ///
+    /// Stored documents are matched in their encoded form, and only the
+    /// matches are decoded, or just their projected fields. Fewer than
+    /// `limit` matches means the scan has reached its end.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MaxTimeExpired` if the scan runs out of time, as well as
+    /// any error reading or matching documents.
+    pub(crate) fn next(
+        &mut self,
+        inner: &DatabaseInner,
+        limit: usize,
+    ) -> Result<Vec<Stored>, DatabaseError> {
+        let started = Instant::now();
+        let matches = self.next_matches(inner, limit, started);
+        self.spent += started.elapsed();
+        matches
+    }
+
+    /// Fails with `MaxTimeExpired` if the scan is out of time, counting
+    /// the batch `started` then.
+    fn check_time(&self, started: Instant) -> Result<(), DatabaseError> {
+        match self.max_time {
+            Some(max_time) if self.spent + started.elapsed() > max_time => {
+                Err(DatabaseError::MaxTimeExpired(max_time))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn next_matches(
+        &mut self,
+        inner: &DatabaseInner,
+        limit: usize,
+        started: Instant,
+    ) -> Result<Vec<Stored>, DatabaseError> {
+        self.check_time(started)?;
+        let mut matches = Vec::new();
+        if limit == 0 {
+            return Ok(matches);
+        }
+        let namespace = match &self.plan {
+            Plan::Primary(_) => self.collection.clone(),
+            Plan::Index { index, .. } | Plan::Geo { index, .. } => {
+                index.namespace(&self.collection)
+            }
+            Plan::Text { .. } | Plan::Near { .. } | Plan::Intersect(_) => {
+                return self.next_ranked(inner, limit)
+            }
+        };
+        loop {
+            let range = match &self.plan {
+                Plan::Primary(range) | Plan::Index { range, .. } => range.clone(),
+                Plan::Geo { ranges, .. } => match ranges.front() {
+                    Some(range) => range.clone(),
+                    None => return Ok(matches),
+                },
+                Plan::Text { .. } | Plan::Near { .. } | Plan::Intersect(_) => {
+                    unreachable!("ranked plans are not scanned by range")
+                }
+            };
+            self.check_time(started)?;
+            let batch = inner.scan(self.txn, &namespace, &range, SCAN_BATCH)?;
+            let Some((last, _)) = batch.last() else {
+                if let Plan::Geo { ranges, .. } = &mut self.plan {
+                    ranges.pop_front();
+                    continue;
+                }
+                return Ok(matches);
+            };
+            let next = range.after(last);
+            for (key, value) in batch {
+                if let Some(stored) = self.candidate(inner, &key, value)? {
+                    matches.push(stored);
+                    if matches.len() == limit {
+                        self.resume_after(&key);
+                        return Ok(matches);
+                    }
+                }
+            }
+            self.set_range(next);
+        }
+    }
+
+    /// Returns the document an entry of the scanned namespace refers to,
+    /// if it matches.
+    fn candidate(
+        &mut self,
+        inner: &DatabaseInner,
+        key: &[u8],
+        value: Vec<u8>,
+    ) -> Result<Option<Stored>, DatabaseError> {
+        let (key, bytes) = match &self.plan {
+            Plan::Primary(_) => (key.to_vec(), value),
+            Plan::Index { index, .. } if self.covered => {
+                let document = index.entry_document(key, &value)?;
+                (value, to_bytes(&document)?)
+            }
+            Plan::Index { .. }
+            | Plan::Text { .. }
+            | Plan::Geo { .. }
+            | Plan::Near { .. }
+            | Plan::Intersect(_) => {
+                if !self.seen.insert(value.clone()) {
+                    return Ok(None);
+                }
+                match inner.get(self.txn, &self.collection, &value)? {
+                    Some(bytes) => (value, bytes),
+                    None => return Ok(None),
+                }
+            }
+        };
+        let mut upgraded = None;
+        if !self.covered {
+            self.examined += 1;
+            upgraded = inner.migrations.upgrade_bytes(&self.collection, &bytes)?;
+        }
+        // Outdated documents are matched and returned as upgraded
+        let current = upgraded.as_deref().unwrap_or(&bytes);
+        if !self
+            .matcher
+            .matches_raw(RawDocument::from_bytes(current)?)?
+        {
+            return Ok(None);
+        }
+        let scope = MemoryScope::enter(MemoryCategory::Decode);
+        let document = match &self.projection {
+            Some(projection) => projection.apply(current)?,
+            None => from_bytes(current)?,
+        };
+        drop(scope);
+        Ok(Some(Stored {
+            key,
+            bytes,
+            upgraded,
+            document,
+            score: None,
+        }))
+    }
+
+    /// Returns the next `limit` matches of a text, near or intersection
+    /// plan, ranking the candidates on the first call.
+    fn next_ranked(
+        &mut self,
+        inner: &DatabaseInner,
+        limit: usize,
+    ) -> Result<Vec<Stored>, DatabaseError> {
+        if self.ranked.is_none() {
+            self.ranked = Some(match self.plan {
+                Plan::Near { .. } => self.rank_near(inner)?,
+                Plan::Intersect(_) => self.rank_intersect(inner)?,
+                _ => self.rank(inner)?,
+            });
+        }
+        let mut matches = Vec::new();
+        while let Some((primary, score)) = self.ranked.as_mut().and_then(VecDeque::pop_front) {
+            if let Some(mut stored) = self.candidate(inner, &primary, primary.clone())? {
+                stored.score = matches!(self.plan, Plan::Text { .. }).then_some(score);
+                matches.push(stored);
+                if matches.len() == limit {
+                    break;
+                }
+            }
+        }
+        Ok(matches)
+    }
+
+    /// Scores every document holding a term of the text plan with BM25,
+    /// returning their primary keys, best first.
+    fn rank(&self, inner: &DatabaseInner) -> Result<VecDeque<(Vec<u8>, f64)>, DatabaseError> {
+        let Plan::Text { index, terms } = &self.plan else {
+            unreachable!("only text plans are ranked");
+        };
+        let namespace = index.namespace(&self.collection);
+        let count = |bytes: &[u8]| bytes.try_into().map_or(0, u32::from_be_bytes);
+
+        let lengths: HashMap<Vec<u8>, u32> = inner
+            .scan(
+                self.txn,
+                &namespace,
+                &KeyRange::prefix(&length_key(&[])),
+                usize::MAX,
+            )?
+            .into_iter()
+            .map(|(key, value)| (key[1..].to_vec(), count(&value)))
+            .collect();
+        let corpus = Corpus {
+            documents: lengths.len() as u64,
+            terms: lengths.values().map(|&length| length as u64).sum(),
+        };
+        let mut scores: HashMap<Vec<u8>, f64> = HashMap::new();
+        for term in terms {
+            let prefix = encode_key(&Value::from(term.as_str()));
+            let postings =
+                inner.scan(self.txn, &namespace, &KeyRange::prefix(&prefix), usize::MAX)?;
+            let containing = postings.len() as u64;
+            for (key, value) in postings {
+                let primary = key[prefix.len()..].to_vec();
+                let length = lengths.get(&primary).copied().unwrap_or(1);
+                *scores.entry(primary).or_default() +=
+                    corpus.score(count(&value), length, containing);
+            }
+        }
+        let mut ranked: Vec<(Vec<u8>, f64)> = scores.into_iter().collect();
+        ranked.sort_by(|(a, a_score), (b, b_score)| b_score.total_cmp(a_score).then(a.cmp(b)));
+        Ok(ranked.into())
+    }
+
+    /// Finds every document of the near plan with a geometry in range,
+    /// returning their primary keys and distances, nearest first.
+    fn rank_near(&self, inner: &DatabaseInner) -> Result<VecDeque<(Vec<u8>, f64)>, DatabaseError> {
+        let Plan::Near {
+            index,
+            near,
+            ranges,
+        } = &self.plan
+        else {
+            unreachable!("only near plans are ranked by distance");
+        };
+        let namespace = index.namespace(&self.collection);
+        let mut primaries = BTreeSet::new();
+        for range in ranges {
+            for (_, primary) in inner.scan(self.txn, &namespace, range, usize::MAX)? {
+                primaries.insert(primary);
+            }
+        }
+        let mut ranked = Vec::new();
+        for primary in primaries {
+            let Some(bytes) = inner.get(self.txn, &self.collection, &primary)? else {
+                continue;
+            };
+            let distance = path_values(RawDocument::from_bytes(&bytes)?, &index.fields[0].0)?
+                .iter()
+                .filter_map(Geometry::from_value)
+                .filter_map(|geometry| near.distance(&geometry))
+                .reduce(f64::min);
+            if let Some(distance) = distance {
+                ranked.push((primary, distance));
+            }
+        }
+        ranked.sort_by(|(a, a_distance), (b, b_distance)| {
+            a_distance.total_cmp(b_distance).then(a.cmp(b))
+        });
+        Ok(ranked.into())
+    }
+
+    /// Finds the documents with entries in the range of every index of the
+    /// intersection plan, returning their primary keys in order.
+    fn rank_intersect(
+        &self,
+        inner: &DatabaseInner,
+    ) -> Result<VecDeque<(Vec<u8>, f64)>, DatabaseError> {
+        let Plan::Intersect(scans) = &self.plan else {
+            unreachable!("only intersection plans are intersected");
+        };
+        let mut primaries: Option<BTreeSet<Vec<u8>>> = None;
+        for (index, range) in scans {
+            let namespace = index.namespace(&self.collection);
+            let found: BTreeSet<Vec<u8>> = inner
+                .scan(self.txn, &namespace, range, usize::MAX)?
+                .into_iter()
+                .map(|(_, primary)| primary)
+                .filter(|primary| primaries.as_ref().is_none_or(|set| set.contains(primary)))
+                .collect();
+            primaries = Some(found);
+        }
+        Ok(primaries
+            .unwrap_or_default()
+            .into_iter()
+            .map(|primary| (primary, 0.0))
+            .collect())
+    }
+
+    fn resume_after(&mut self, key: &[u8]) {
+        let range = match &self.plan {
+            Plan::Primary(range) | Plan::Index { range, .. } => range.after(key),
+            Plan::Geo { ranges, .. } => match ranges.front() {
+                Some(range) => range.after(key),
+                None => return,
+            },
+            Plan::Text { .. } | Plan::Near { .. } | Plan::Intersect(_) => return,
+        };
+        self.set_range(range);
+    }
+
+    fn set_range(&mut self, next: KeyRange) {
+        match &mut self.plan {
+            Plan::Primary(range) | Plan::Index { range, .. } => *range = next,
+            Plan::Geo { ranges, .. } => {
+                if let Some(range) = ranges.front_mut() {
+                    *range = next;
+                }
+            }
+            Plan::Text { .. } | Plan::Near { .. } | Plan::Intersect(_) => {}
+        }
+    }
+}
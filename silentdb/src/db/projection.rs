@@ -0,0 +1,84 @@
+// src/db/projection.rs
+
+use silentdb_data_encoding::{decode_projected, from_bytes, Document, Projection as Paths, Value};
+
+use super::error::DatabaseError;
+use crate::query::QueryError;
+
+/// The fields a query returns of each document it matches, parsed from a
+/// projection document such as `{"name": 1, "address.city": 1, "_id": 0}`.
+///
+/// Each path is included with `1` or `true`. `_id` is included unless
+/// excluded with `0` or `false`, the only path that can be. A projection
+/// including no path returns whole documents, less `_id` if excluded.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Projection {
+    paths: Vec<String>,
+    id: bool,
+}
+
+impl Projection {
+    /// Parses `projection`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Query` if a path is empty or names an operator, is set to
+    /// something other than a number or boolean, or is excluded without
+    /// being `_id`.
+    pub(crate) fn new(projection: &Document) -> Result<Self, DatabaseError> {
+        let invalid =
+            |message: String| -> DatabaseError { QueryError::InvalidProjection(message).into() };
+        let mut parsed = Projection {
+            paths: Vec::new(),
+            id: true,
+        };
+        for (path, value) in projection.iter() {
+            if path
+                .split('.')
+                .any(|segment| segment.is_empty() || segment.starts_with('$'))
+            {
+                return Err(invalid(format!("bad path {:?}", path)));
+            }
+            let included = match value {
+                Value::Boolean(included) => *included,
+                Value::Int32(n) => *n != 0,
+                Value::Int64(n) => *n != 0,
+                Value::Double(n) => *n != 0.0,
+                _ => return Err(invalid(format!("{} must be a number or boolean", path))),
+            };
+            match (path.as_str(), included) {
+                ("_id", included) => parsed.id = included,
+                (path, true) => parsed.paths.push(path.to_string()),
+                (path, false) => {
+                    return Err(invalid(format!("only _id can be excluded, not {}", path)))
+                }
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Returns the paths the projection reads: the included ones and
+    /// `_id`, or `None` if it returns whole documents.
+    pub(crate) fn paths(&self) -> Option<impl Iterator<Item = &str>> {
+        if self.paths.is_empty() {
+            return None;
+        }
+        let id = self.id.then_some("_id");
+        Some(id.into_iter().chain(self.paths.iter().map(String::as_str)))
+    }
+
+    /// Decodes the projected fields of the encoded document `bytes`,
+    /// leaving the rest undecoded.
+    pub(crate) fn apply(&self, bytes: &[u8]) -> Result<Document, DatabaseError> {
+        match self.paths() {
+            Some(paths) => Ok(decode_projected(bytes, &Paths::new(paths))?),
+            None => {
+                let mut document: Document = from_bytes(bytes)?;
+                if !self.id {
+                    document.remove("_id");
+                }
+                Ok(document)
+            }
+        }
+    }
+}
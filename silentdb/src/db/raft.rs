@@ -0,0 +1,796 @@
+// src/db/raft.rs
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Bound;
+
+use rand::Rng;
+use silentdb_data_encoding::{from_bytes, to_bytes, Document, Value};
+
+use super::concern::WriteConcern;
+use super::database::{Database, DatabaseInner, Write};
+use super::error::DatabaseError;
+use super::replication::OplogEntry;
+use crate::storage::{KeyRange, StorageError};
+
+/// Namespace holding a node's term, vote and last applied entry.
+pub(crate) const RAFT_NAMESPACE: &str = "$system.$raft";
+/// Namespace holding the Raft log's entries, keyed by index.
+pub(crate) const RAFT_LOG_NAMESPACE: &str = "$system.$raftlog";
+/// Key of the term and vote in their namespace.
+const STATE_KEY: &[u8] = b"state";
+/// Key of the last applied entry's index in its namespace.
+const APPLIED_KEY: &[u8] = b"applied";
+/// Ticks without hearing from a leader before a follower stands for
+/// election, unless told otherwise; each election waits up to twice this.
+const DEFAULT_ELECTION_TICKS: u32 = 10;
+/// Ticks between a leader's heartbeats unless told otherwise.
+const DEFAULT_HEARTBEAT_TICKS: u32 = 2;
+/// Entries sent per append message unless told otherwise.
+const DEFAULT_APPEND_BATCH: usize = 256;
+
+/// Settings for a `RaftNode`.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb::RaftConfig;
+/// let config = RaftConfig::new(1, [1, 2, 3])
+///     .election_ticks(20)
+///     .heartbeat_ticks(4);
+/// ```
+#[derive(Debug, Clone)]
+pub struct RaftConfig {
+    pub(crate) id: u64,
+    pub(crate) peers: Vec<u64>,
+    pub(crate) election_ticks: u32,
+    pub(crate) heartbeat_ticks: u32,
+    pub(crate) batch_size: usize,
+}
+
+impl RaftConfig {
+    /// Creates the settings of node `id` in the cluster of `nodes`, which
+    /// may list `id` itself. Every node of a cluster must list the same
+    /// nodes.
+    pub fn new(id: u64, nodes: impl IntoIterator<Item = u64>) -> Self {
+        let mut peers: Vec<u64> = nodes.into_iter().filter(|&node| node != id).collect();
+        peers.sort_unstable();
+        peers.dedup();
+        RaftConfig {
+            id,
+            peers,
+            election_ticks: DEFAULT_ELECTION_TICKS,
+            heartbeat_ticks: DEFAULT_HEARTBEAT_TICKS,
+            batch_size: DEFAULT_APPEND_BATCH,
+        }
+    }
+
+    /// Sets how many ticks a follower waits to hear from a leader before
+    /// standing for election. Each wait is drawn at random between this and
+    /// twice this, so nodes rarely stand at once. Defaults to 10.
+    pub fn election_ticks(mut self, ticks: u32) -> Self {
+        self.election_ticks = ticks.max(1);
+        self
+    }
+
+    /// Sets how many ticks a leader waits between heartbeats, which should
+    /// be well under the election ticks. Defaults to 2.
+    pub fn heartbeat_ticks(mut self, ticks: u32) -> Self {
+        self.heartbeat_ticks = ticks.max(1);
+        self
+    }
+
+    /// Sets how many entries each append message carries at most.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+}
+
+/// What a `RaftNode` is doing in its cluster.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RaftRole {
+    /// Applying the entries its leader sends.
+    Follower,
+    /// Standing for election.
+    Candidate,
+    /// Taking writes and sending them to the other nodes.
+    Leader,
+}
+
+/// An entry of the Raft log: the oplog entry of one commit on the leader
+/// of `term`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RaftEntry {
+    pub index: u64,
+    pub term: u64,
+    pub entry: OplogEntry,
+}
+
+/// A message between the nodes of a Raft cluster, passed on by whatever
+/// connects them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RaftMessage {
+    /// Asks for a vote for the sender, whose log ends with the entry at
+    /// `last_index` from `last_term`.
+    RequestVote {
+        term: u64,
+        last_index: u64,
+        last_term: u64,
+    },
+    /// Answers `RequestVote`.
+    Vote { term: u64, granted: bool },
+    /// Sends the entries following the one at `prev_index` from
+    /// `prev_term`, or none as a heartbeat, with the leader's commit index.
+    Append {
+        term: u64,
+        prev_index: u64,
+        prev_term: u64,
+        entries: Vec<RaftEntry>,
+        commit: u64,
+    },
+    /// Answers `Append`: on success, `last_index` is the last entry the
+    /// follower now shares with the leader; otherwise it is the last entry
+    /// of the follower's log, which the leader goes back from.
+    Appended {
+        term: u64,
+        success: bool,
+        last_index: u64,
+    },
+}
+
+impl RaftMessage {
+    /// Returns the sender's term.
+    pub fn term(&self) -> u64 {
+        match self {
+            RaftMessage::RequestVote { term, .. }
+            | RaftMessage::Vote { term, .. }
+            | RaftMessage::Append { term, .. }
+            | RaftMessage::Appended { term, .. } => *term,
+        }
+    }
+}
+
+/// The Raft log's end and the node's standing, kept with the database so
+/// that commits on the leader add their entries to the log, and writes on
+/// other nodes are refused.
+#[derive(Debug)]
+pub(crate) struct RaftLog {
+    term: u64,
+    pub(crate) leader: bool,
+    /// The node last known to lead.
+    pub(crate) leader_id: Option<u64>,
+    /// Whether the node is applying committed entries, which it does
+    /// whether it leads or not.
+    pub(crate) applying: bool,
+    last_index: u64,
+    last_term: u64,
+}
+
+impl RaftLog {
+    /// Returns the writes adding `entry` to the end of the log in the
+    /// current term, as applied by the commit it records.
+    pub(crate) fn append_writes(&self, entry: &OplogEntry) -> Result<Vec<Write>, DatabaseError> {
+        let index = self.last_index + 1;
+        Ok(vec![
+            entry_write(index, self.term, entry)?,
+            applied_write(index),
+        ])
+    }
+
+    /// Counts an entry added by `append_writes` once its commit is applied.
+    pub(crate) fn appended(&mut self) {
+        self.last_index += 1;
+        self.last_term = self.term;
+    }
+}
+
+/// A database run as one node of a Raft cluster, as an alternative to
+/// following a primary with `Secondary`.
+///
+/// The cluster elects a leader, which alone takes writes: every commit on
+/// it adds its oplog entry to the Raft log in the same log record, and the
+/// leader sends the entries on to the other nodes, which apply them once a
+/// majority of the cluster holds them. If the leader fails, a node holding
+/// every committed entry takes over. Writes to a node that does not lead
+/// fail with `NotLeader`.
+///
+/// A write is committed, and survives the loss of any minority of the
+/// cluster, once `commit_index` reaches the `last_index` the leader had
+/// when the write returned. A leader that loses its place before its
+/// writes commit has applied writes the cluster may drop; it then fails
+/// with `RaftDiverged` and must be synced anew.
+///
+/// The node is driven from outside: `tick` advances its timers, `step`
+/// hands it a message from another node, and `take_messages` returns the
+/// messages it wants sent.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use silentdb::{Database, RaftConfig, RaftNode};
+/// let db = Database::open("node-1").unwrap();
+/// let mut node = RaftNode::new(db, RaftConfig::new(1, [1, 2, 3])).unwrap();
+/// loop {
+///     node.tick().unwrap();
+///     for (to, message) in node.take_messages() {
+///         // send `message` to node `to`, and `step` the replies
+///     }
+///     # break;
+/// }
+/// ```
+#[derive(Debug)]
+pub struct RaftNode {
+    db: Database,
+    config: RaftConfig,
+    role: RaftRole,
+    term: u64,
+    voted_for: Option<u64>,
+    leader: Option<u64>,
+    votes: BTreeSet<u64>,
+    /// The next entry to send each peer, while leading.
+    next_index: BTreeMap<u64, u64>,
+    /// The last entry each peer is known to share, while leading.
+    match_index: BTreeMap<u64, u64>,
+    commit_index: u64,
+    applied: u64,
+    elapsed: u32,
+    timeout: u32,
+    outbox: Vec<(u64, RaftMessage)>,
+}
+
+impl RaftNode {
+    /// Starts `db` as a node of the cluster `config` describes, as a
+    /// follower, picking up the term, vote and log it had when last run.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the node's state cannot be read.
+    pub fn new(db: Database, config: RaftConfig) -> Result<RaftNode, DatabaseError> {
+        let (term, voted_for, applied) = {
+            let mut inner = DatabaseInner::lock(&db.inner);
+            let (term, voted_for) = match inner.get(None, RAFT_NAMESPACE, STATE_KEY)? {
+                Some(bytes) => {
+                    let state = from_bytes(&bytes)?;
+                    match (state.get("term"), state.get("votedFor")) {
+                        (Some(Value::Int64(term)), Some(Value::Int64(voted))) => {
+                            (*term as u64, Some(*voted as u64))
+                        }
+                        (Some(Value::Int64(term)), Some(Value::Null)) => (*term as u64, None),
+                        _ => return Err(bad_log("bad state").into()),
+                    }
+                }
+                None => (0, None),
+            };
+            let applied = match inner.get(None, RAFT_NAMESPACE, APPLIED_KEY)? {
+                Some(bytes) => index_of(&bytes)?,
+                None => 0,
+            };
+            // Entries past the last applied are the only ones read
+            let mut last = (applied, 0);
+            let tail = read_entries(&inner, applied.max(1), usize::MAX)?;
+            for entry in &tail {
+                inner.replication.observe(entry.entry.ts);
+                last = (entry.index, entry.term);
+            }
+            inner.replication.raft = Some(RaftLog {
+                term,
+                leader: false,
+                leader_id: None,
+                applying: false,
+                last_index: last.0,
+                last_term: last.1,
+            });
+            (term, voted_for, applied)
+        };
+        let mut node = RaftNode {
+            db,
+            config,
+            role: RaftRole::Follower,
+            term,
+            voted_for,
+            leader: None,
+            votes: BTreeSet::new(),
+            next_index: BTreeMap::new(),
+            match_index: BTreeMap::new(),
+            commit_index: applied,
+            applied,
+            elapsed: 0,
+            timeout: 0,
+            outbox: Vec::new(),
+        };
+        node.reset_timer();
+        Ok(node)
+    }
+
+    /// Returns the node's database, which takes writes while the node
+    /// leads.
+    pub fn database(&self) -> &Database {
+        &self.db
+    }
+
+    pub fn id(&self) -> u64 {
+        self.config.id
+    }
+
+    pub fn role(&self) -> RaftRole {
+        self.role
+    }
+
+    pub fn term(&self) -> u64 {
+        self.term
+    }
+
+    /// Returns the node last known to lead, if any.
+    pub fn leader(&self) -> Option<u64> {
+        self.leader
+    }
+
+    /// Returns the index of the last entry a majority of the cluster is
+    /// known to hold.
+    pub fn commit_index(&self) -> u64 {
+        self.commit_index
+    }
+
+    /// Returns the index of the last entry in the node's log.
+    pub fn last_index(&self) -> u64 {
+        self.last().0
+    }
+
+    /// Returns the messages the node wants sent since the last call, with
+    /// the node each goes to.
+    pub fn take_messages(&mut self) -> Vec<(u64, RaftMessage)> {
+        std::mem::take(&mut self.outbox)
+    }
+
+    /// Advances the node's timers by one tick: a leader sends heartbeats
+    /// carrying any new entries every few ticks, and any other node stands
+    /// for election once it has not heard from a leader for long enough.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the node's state cannot be read or written.
+    pub fn tick(&mut self) -> Result<(), DatabaseError> {
+        self.elapsed += 1;
+        match self.role {
+            RaftRole::Leader => {
+                self.advance_commit()?;
+                if self.elapsed >= self.config.heartbeat_ticks {
+                    self.elapsed = 0;
+                    for peer in self.config.peers.clone() {
+                        self.send_append(peer)?;
+                    }
+                }
+            }
+            _ if self.elapsed >= self.timeout => self.campaign()?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Handles `message` from the node `from`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `RaftDiverged` if the leader's log drops entries this node
+    /// has applied, or an error if the node's state or log cannot be read
+    /// or written, or an entry cannot be applied; such entries are applied
+    /// again when the leader next sends.
+    pub fn step(&mut self, from: u64, message: RaftMessage) -> Result<(), DatabaseError> {
+        if message.term() > self.term {
+            let leader = match message {
+                RaftMessage::Append { .. } => Some(from),
+                _ => None,
+            };
+            self.become_follower(message.term(), leader)?;
+        }
+        match message {
+            RaftMessage::RequestVote {
+                term,
+                last_index,
+                last_term,
+            } => {
+                let (our_index, our_term) = self.last();
+                let granted = term == self.term
+                    && self.voted_for.is_none_or(|voted| voted == from)
+                    && (last_term, last_index) >= (our_term, our_index);
+                if granted {
+                    if self.voted_for.is_none() {
+                        self.voted_for = Some(from);
+                        self.save_state()?;
+                    }
+                    self.reset_timer();
+                }
+                let term = self.term;
+                self.outbox
+                    .push((from, RaftMessage::Vote { term, granted }));
+            }
+            RaftMessage::Vote { term, granted } => {
+                if self.role == RaftRole::Candidate && term == self.term && granted {
+                    self.votes.insert(from);
+                    if self.has_quorum(self.votes.len()) {
+                        self.become_leader()?;
+                    }
+                }
+            }
+            RaftMessage::Append {
+                term,
+                prev_index,
+                prev_term,
+                entries,
+                commit,
+            } => {
+                if term < self.term {
+                    let reply = RaftMessage::Appended {
+                        term: self.term,
+                        success: false,
+                        last_index: self.last().0,
+                    };
+                    self.outbox.push((from, reply));
+                    return Ok(());
+                }
+                if self.role != RaftRole::Follower || self.leader != Some(from) {
+                    self.become_follower(term, Some(from))?;
+                }
+                self.reset_timer();
+                let reply = match self.append_entries(prev_index, prev_term, entries)? {
+                    Some(last) => {
+                        self.commit_index = self.commit_index.max(commit.min(last));
+                        self.apply(self.commit_index)?;
+                        RaftMessage::Appended {
+                            term: self.term,
+                            success: true,
+                            last_index: last,
+                        }
+                    }
+                    None => RaftMessage::Appended {
+                        term: self.term,
+                        success: false,
+                        last_index: self.last().0,
+                    },
+                };
+                self.outbox.push((from, reply));
+            }
+            RaftMessage::Appended {
+                term,
+                success,
+                last_index,
+            } => {
+                if self.role != RaftRole::Leader || term != self.term {
+                    return Ok(());
+                }
+                let next = self.next_index.get(&from).copied().unwrap_or(1);
+                if success {
+                    let matched = self.match_index.entry(from).or_insert(0);
+                    *matched = (*matched).max(last_index);
+                    self.next_index.insert(from, next.max(last_index + 1));
+                    self.advance_commit()?;
+                    if last_index < self.last().0 {
+                        self.send_append(from)?;
+                    }
+                } else {
+                    let next = (last_index + 1).min(next.saturating_sub(1)).max(1);
+                    self.next_index.insert(from, next);
+                    self.send_append(from)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the index and term of the last entry in the node's log.
+    fn last(&self) -> (u64, u64) {
+        let inner = DatabaseInner::lock(&self.db.inner);
+        let log = inner.replication.raft.as_ref().expect("raft node started");
+        (log.last_index, log.last_term)
+    }
+
+    fn has_quorum(&self, nodes: usize) -> bool {
+        nodes > self.config.peers.len().div_ceil(2)
+    }
+
+    fn reset_timer(&mut self) {
+        let ticks = self.config.election_ticks;
+        self.elapsed = 0;
+        self.timeout = ticks + rand::thread_rng().gen_range(0..ticks);
+    }
+
+    /// Stands for election in the next term.
+    fn campaign(&mut self) -> Result<(), DatabaseError> {
+        self.term += 1;
+        self.voted_for = Some(self.config.id);
+        self.save_state()?;
+        self.role = RaftRole::Candidate;
+        self.leader = None;
+        self.publish(false);
+        self.votes = BTreeSet::from([self.config.id]);
+        self.reset_timer();
+        if self.has_quorum(self.votes.len()) {
+            return self.become_leader();
+        }
+        let (last_index, last_term) = self.last();
+        for &peer in &self.config.peers {
+            let request = RaftMessage::RequestVote {
+                term: self.term,
+                last_index,
+                last_term,
+            };
+            self.outbox.push((peer, request));
+        }
+        Ok(())
+    }
+
+    /// Takes the lead: applies the entries of earlier terms its log holds,
+    /// which the cluster will keep, then adds an empty entry of its own
+    /// term, whose commit commits them.
+    fn become_leader(&mut self) -> Result<(), DatabaseError> {
+        let (last_index, _) = self.last();
+        self.apply(last_index)?;
+        self.role = RaftRole::Leader;
+        self.leader = Some(self.config.id);
+        for &peer in &self.config.peers {
+            self.next_index.insert(peer, last_index + 1);
+            self.match_index.insert(peer, 0);
+        }
+        {
+            let mut inner = DatabaseInner::lock(&self.db.inner);
+            let entry = OplogEntry {
+                ts: inner.replication.next_time(),
+                ops: Vec::new(),
+            };
+            let writes = log_mut(&mut inner).append_writes(&entry)?;
+            inner.write(None, writes, BTreeSet::new(), WriteConcern::Journaled)?;
+            inner.replication.observe(entry.ts);
+            log_mut(&mut inner).appended();
+        }
+        self.publish(true);
+        self.advance_commit()?;
+        self.elapsed = 0;
+        for peer in self.config.peers.clone() {
+            self.send_append(peer)?;
+        }
+        Ok(())
+    }
+
+    /// Follows `leader` in `term`, which starts without a vote if it is a
+    /// new term.
+    fn become_follower(&mut self, term: u64, leader: Option<u64>) -> Result<(), DatabaseError> {
+        if term != self.term {
+            self.term = term;
+            self.voted_for = None;
+            self.save_state()?;
+        }
+        if self.role == RaftRole::Leader {
+            // The leader applied each entry as it was committed locally
+            self.applied = self.last().0;
+        }
+        self.role = RaftRole::Follower;
+        self.leader = leader;
+        self.publish(false);
+        Ok(())
+    }
+
+    /// Shows the node's term and whether it leads to the database's
+    /// commits.
+    fn publish(&self, leader: bool) {
+        let mut inner = DatabaseInner::lock(&self.db.inner);
+        let log = log_mut(&mut inner);
+        log.term = self.term;
+        log.leader = leader;
+        log.leader_id = self.leader;
+    }
+
+    /// Writes the node's term and vote, synced before any message that
+    /// relies on them is sent.
+    fn save_state(&self) -> Result<(), DatabaseError> {
+        let mut state = Document::new();
+        state.insert("term", self.term as i64);
+        match self.voted_for {
+            Some(voted) => state.insert("votedFor", voted as i64),
+            None => state.insert("votedFor", Value::Null),
+        };
+        let write = Write::Put {
+            namespace: RAFT_NAMESPACE.to_string(),
+            key: STATE_KEY.to_vec(),
+            value: to_bytes(&state)?,
+        };
+        let mut inner = DatabaseInner::lock(&self.db.inner);
+        inner.write(None, vec![write], BTreeSet::new(), WriteConcern::Journaled)
+    }
+
+    /// Adds `entries`, which follow the entry at `prev_index` from
+    /// `prev_term` in the leader's log, to the node's log, replacing any
+    /// that differ from them. Returns the index of the last of them, or
+    /// `None` if the node's log does not hold the entry they follow.
+    fn append_entries(
+        &mut self,
+        prev_index: u64,
+        prev_term: u64,
+        entries: Vec<RaftEntry>,
+    ) -> Result<Option<u64>, DatabaseError> {
+        let mut inner = DatabaseInner::lock(&self.db.inner);
+        if term_at(&inner, prev_index)? != Some(prev_term) {
+            return Ok(None);
+        }
+        let last = prev_index + entries.len() as u64;
+        let (log_last, _) = {
+            let log = log_mut(&mut inner);
+            (log.last_index, log.last_term)
+        };
+        let mut truncate_from = None;
+        let mut puts = Vec::new();
+        let mut newest = None;
+        for entry in entries {
+            if entry.index <= log_last && truncate_from.is_none() {
+                if term_at(&inner, entry.index)? == Some(entry.term) {
+                    continue;
+                }
+                truncate_from = Some(entry.index);
+            }
+            puts.push(entry_write(entry.index, entry.term, &entry.entry)?);
+            newest = Some((entry.term, entry.entry.ts));
+        }
+        let Some((newest_term, newest_ts)) = newest else {
+            return Ok(Some(last));
+        };
+
+        let mut writes = Vec::new();
+        if let Some(from) = truncate_from {
+            if from <= self.applied {
+                return Err(DatabaseError::RaftDiverged(from));
+            }
+            let range = KeyRange::new(Bound::Included(index_key(from)), Bound::Unbounded);
+            for (key, _) in inner.scan(None, RAFT_LOG_NAMESPACE, &range, usize::MAX)? {
+                writes.push(Write::Delete {
+                    namespace: RAFT_LOG_NAMESPACE.to_string(),
+                    key,
+                });
+            }
+        }
+        writes.extend(puts);
+        inner.write(None, writes, BTreeSet::new(), WriteConcern::Journaled)?;
+        inner.replication.observe(newest_ts);
+        let log = log_mut(&mut inner);
+        if truncate_from.is_some() || last > log_last {
+            log.last_index = last;
+            log.last_term = newest_term;
+        }
+        Ok(Some(last))
+    }
+
+    /// Applies the entries of the node's log up to `index` that it has not
+    /// applied yet, each in a commit of its own that records it as applied.
+    fn apply(&mut self, index: u64) -> Result<(), DatabaseError> {
+        while self.applied < index {
+            let mut inner = DatabaseInner::lock(&self.db.inner);
+            let batch = (index - self.applied).min(self.config.batch_size as u64);
+            let entries = read_entries(&inner, self.applied + 1, batch as usize)?;
+            if entries.is_empty() {
+                return Err(bad_log("missing entry").into());
+            }
+            for entry in entries {
+                let mut writes = entry.entry.ops;
+                writes.push(applied_write(entry.index));
+                log_mut(&mut inner).applying = true;
+                let result = inner.write(None, writes, BTreeSet::new(), WriteConcern::default());
+                log_mut(&mut inner).applying = false;
+                result?;
+                self.applied = entry.index;
+            }
+        }
+        Ok(())
+    }
+
+    /// Commits the last entry of the leader's term a majority of the
+    /// cluster holds, with every entry before it.
+    fn advance_commit(&mut self) -> Result<(), DatabaseError> {
+        let (last_index, _) = self.last();
+        let mut held: Vec<u64> = self.match_index.values().copied().collect();
+        held.push(last_index);
+        held.sort_unstable_by(|a, b| b.cmp(a));
+        let majority = held[held.len() / 2];
+        if majority > self.commit_index {
+            let inner = DatabaseInner::lock(&self.db.inner);
+            if term_at(&inner, majority)? == Some(self.term) {
+                self.commit_index = majority;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends `peer` the entries from the next one it needs, up to a batch.
+    fn send_append(&mut self, peer: u64) -> Result<(), DatabaseError> {
+        let next = self.next_index.get(&peer).copied().unwrap_or(1);
+        let (prev_term, entries) = {
+            let inner = DatabaseInner::lock(&self.db.inner);
+            let prev_term = term_at(&inner, next - 1)?.ok_or_else(|| bad_log("missing entry"))?;
+            (
+                prev_term,
+                read_entries(&inner, next, self.config.batch_size)?,
+            )
+        };
+        let append = RaftMessage::Append {
+            term: self.term,
+            prev_index: next - 1,
+            prev_term,
+            entries,
+            commit: self.commit_index,
+        };
+        self.outbox.push((peer, append));
+        Ok(())
+    }
+}
+
+fn log_mut(inner: &mut DatabaseInner) -> &mut RaftLog {
+    inner.replication.raft.as_mut().expect("raft node started")
+}
+
+fn index_key(index: u64) -> Vec<u8> {
+    index.to_be_bytes().to_vec()
+}
+
+fn index_of(bytes: &[u8]) -> Result<u64, StorageError> {
+    let bytes = bytes.try_into().map_err(|_| bad_log("bad index"))?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+/// Returns the write putting `entry` at `index` in the log, from `term`.
+fn entry_write(index: u64, term: u64, entry: &OplogEntry) -> Result<Write, DatabaseError> {
+    let mut document = Document::new();
+    document.insert("term", term as i64);
+    document.insert("entry", entry.to_document());
+    Ok(Write::Put {
+        namespace: RAFT_LOG_NAMESPACE.to_string(),
+        key: index_key(index),
+        value: to_bytes(&document)?,
+    })
+}
+
+/// Returns the write recording the entry at `index` as applied.
+fn applied_write(index: u64) -> Write {
+    Write::Put {
+        namespace: RAFT_NAMESPACE.to_string(),
+        key: APPLIED_KEY.to_vec(),
+        value: index_key(index),
+    }
+}
+
+/// Returns up to `limit` entries of the log from `from` on.
+fn read_entries(
+    inner: &DatabaseInner,
+    from: u64,
+    limit: usize,
+) -> Result<Vec<RaftEntry>, DatabaseError> {
+    let range = KeyRange::new(Bound::Included(index_key(from)), Bound::Unbounded);
+    let mut entries = Vec::new();
+    for (key, value) in inner.scan(None, RAFT_LOG_NAMESPACE, &range, limit)? {
+        let document = from_bytes(&value)?;
+        let (Some(Value::Int64(term)), Some(Value::Document(entry))) =
+            (document.get("term"), document.get("entry"))
+        else {
+            return Err(bad_log("bad entry").into());
+        };
+        entries.push(RaftEntry {
+            index: index_of(&key)?,
+            term: *term as u64,
+            entry: OplogEntry::from_document(entry).ok_or_else(|| bad_log("bad entry"))?,
+        });
+    }
+    Ok(entries)
+}
+
+/// Returns the term of the entry at `index`, where index 0 comes before
+/// the first entry, or `None` if the log holds no such entry.
+fn term_at(inner: &DatabaseInner, index: u64) -> Result<Option<u64>, DatabaseError> {
+    if index == 0 {
+        return Ok(Some(0));
+    }
+    let entries = read_entries(inner, index, 1)?;
+    Ok(entries
+        .first()
+        .filter(|entry| entry.index == index)
+        .map(|entry| entry.term))
+}
+
+fn bad_log(message: &str) -> StorageError {
+    StorageError::corrupt(RAFT_LOG_NAMESPACE, message)
+}
@@ -0,0 +1,102 @@
+// src/db/recovery.rs
+
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use silentdb_data_encoding::Value;
+
+use super::backup::{backup_lsn, restore, BackupStats};
+use super::concern::WriteConcern;
+use super::database::{record_writes, DatabaseInner};
+use super::error::DatabaseError;
+use super::replication::is_local;
+use crate::storage::{Lsn, StorageError, WalRecord};
+
+/// How far `Database::recover` replays the archived log on top of a
+/// backup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryTarget {
+    /// Up to and including the record with this sequence number.
+    Lsn(Lsn),
+    /// Up to and including the last record committed no later than this
+    /// time, to the millisecond.
+    Time(SystemTime),
+    /// Every record in the archive.
+    Latest,
+}
+
+impl RecoveryTarget {
+    /// Returns whether `record` comes after the target. Records logged
+    /// before commit times were recorded are taken to come before any time.
+    fn passed_by(&self, record: &WalRecord) -> bool {
+        match self {
+            RecoveryTarget::Lsn(lsn) => record.lsn > *lsn,
+            RecoveryTarget::Time(time) => {
+                let cutoff = time
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |since| since.as_millis() as i64);
+                matches!(record.payload.get("time"), Some(Value::UTCDateTime(time)) if *time > cutoff)
+            }
+            RecoveryTarget::Latest => false,
+        }
+    }
+}
+
+/// What `Database::recover` restored and replayed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecoveryStats {
+    /// What was loaded from the base backup.
+    pub backup: BackupStats,
+    /// The number of archived log records replayed on top of it.
+    pub records: u64,
+    /// The sequence number of the last record replayed, or `None` if the
+    /// backup was already at the target.
+    pub lsn: Option<Lsn>,
+}
+
+/// Restores the backup in `backup_dir`, then commits the writes of each
+/// log record archived in `archive_dir` from the one the backup stops
+/// before up to `target`, in order.
+///
+/// Writes to the oplog and Raft state are left out: they describe the
+/// node the records were archived from, not the one recovering.
+pub(crate) fn recover(
+    inner: &Arc<Mutex<DatabaseInner>>,
+    backup_dir: &Path,
+    archive_dir: &Path,
+    target: RecoveryTarget,
+) -> Result<RecoveryStats, DatabaseError> {
+    let from = backup_lsn(backup_dir)?.ok_or_else(|| {
+        StorageError::corrupt(backup_dir.display(), "the backup records no log position")
+    })?;
+    let records = DatabaseInner::lock(inner).read_archive(archive_dir, from)?;
+    let mut stats = RecoveryStats {
+        backup: restore(inner, backup_dir)?,
+        ..RecoveryStats::default()
+    };
+
+    let mut next = from;
+    for record in records {
+        let record = record?;
+        if record.lsn != next {
+            return Err(DatabaseError::LogGap(next));
+        }
+        if target.passed_by(&record) {
+            break;
+        }
+        let writes = record_writes(&record)?
+            .into_iter()
+            .filter(|write| !is_local(write.target().0))
+            .collect();
+        DatabaseInner::lock(inner).commit(writes, &BTreeSet::new(), WriteConcern::Logged)?;
+        stats.records += 1;
+        stats.lsn = Some(record.lsn);
+        next += 1;
+    }
+    match target {
+        RecoveryTarget::Lsn(lsn) if next <= lsn => Err(DatabaseError::LogGap(next)),
+        _ => Ok(stats),
+    }
+}
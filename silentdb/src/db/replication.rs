@@ -0,0 +1,568 @@
+// src/db/replication.rs
+
+use std::collections::{BTreeSet, VecDeque};
+use std::fmt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use silentdb_data_encoding::{from_bytes, to_bytes, Array, Document, Value};
+
+use super::backup::BackupStats;
+use super::concern::WriteConcern;
+use super::database::{Database, DatabaseInner, Write};
+use super::error::DatabaseError;
+use super::raft::{RaftLog, RAFT_LOG_NAMESPACE, RAFT_NAMESPACE};
+use crate::storage::{KeyRange, StorageError};
+
+/// Namespace holding the oplog's entries, keyed by their timestamps.
+pub(crate) const OPLOG_NAMESPACE: &str = "$system.$oplog";
+/// Namespace holding the oplog's size and a secondary's progress, which
+/// are kept out of the oplog.
+const REPLICATION_NAMESPACE: &str = "$system.$replication";
+/// Key of the oplog's size in its namespace.
+const OPLOG_SIZE_KEY: &[u8] = b"oplogSize";
+/// Key of the last entry a secondary applied in its namespace.
+const APPLIED_KEY: &[u8] = b"applied";
+/// Entries a secondary fetches per batch unless told otherwise.
+const DEFAULT_SYNC_BATCH: usize = 1000;
+
+/// The position of an entry in the oplog: the second it was committed in,
+/// and its order among the entries committed in that second.
+///
+/// Entries are stored as BSON timestamps, with `secs` in the high 32 bits
+/// and `increment` in the low ones, and times order entries as they were
+/// committed even if the clock goes back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct OpTime {
+    /// Seconds since the Unix epoch.
+    pub secs: u32,
+    pub increment: u32,
+}
+
+impl OpTime {
+    /// Returns the time as a BSON timestamp.
+    pub fn to_value(self) -> Value {
+        Value::Timestamp(self.to_u64() as i64)
+    }
+
+    /// Reads a time from a BSON timestamp.
+    pub fn from_value(value: &Value) -> Option<OpTime> {
+        match value {
+            Value::Timestamp(packed) => Some(OpTime::from_u64(*packed as u64)),
+            _ => None,
+        }
+    }
+
+    /// Returns the time as bytes that sort in time order, as oplog keys.
+    pub fn to_bytes(self) -> [u8; 8] {
+        self.to_u64().to_be_bytes()
+    }
+
+    /// Reads a time written by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Option<OpTime> {
+        Some(OpTime::from_u64(u64::from_be_bytes(bytes.try_into().ok()?)))
+    }
+
+    fn to_u64(self) -> u64 {
+        (u64::from(self.secs) << 32) | u64::from(self.increment)
+    }
+
+    fn from_u64(packed: u64) -> OpTime {
+        OpTime {
+            secs: (packed >> 32) as u32,
+            increment: packed as u32,
+        }
+    }
+
+    /// Returns the time of an entry committed after one at `last`, if any.
+    fn next(last: Option<OpTime>) -> OpTime {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_secs() as u32);
+        match last {
+            Some(last) if last.secs >= now => OpTime {
+                secs: last.secs,
+                increment: last.increment + 1,
+            },
+            _ => OpTime {
+                secs: now,
+                increment: 1,
+            },
+        }
+    }
+}
+
+impl fmt::Display for OpTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Timestamp({}, {})", self.secs, self.increment)
+    }
+}
+
+/// One commit as recorded in the oplog: every storage write it made, less
+/// those to the oplog itself and to replication settings.
+///
+/// Each operation puts a value at a key or deletes one, so applying an
+/// entry twice, or applying it over a snapshot that already holds it,
+/// leaves the same data as applying it once.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OplogEntry {
+    pub ts: OpTime,
+    pub(crate) ops: Vec<Write>,
+}
+
+impl OplogEntry {
+    /// Returns the number of operations in the entry.
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    /// Returns whether the entry holds no operation, as when its commit
+    /// only started the oplog.
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Returns the entry as stored in the oplog, for sending to a
+    /// secondary:
+    ///
+    /// ```text
+    /// {"ts": Timestamp(...), "ops": [{"put": "users", "key": ..., "value": ...}, ...]}
+    /// ```
+    pub fn to_document(&self) -> Document {
+        let mut document = Document::new();
+        document.insert("ts", self.ts.to_value());
+        let ops = self
+            .ops
+            .iter()
+            .map(|op| Value::Document(op.to_document()))
+            .collect();
+        document.insert("ops", Array::from_vec(ops));
+        document
+    }
+
+    /// Reads an entry written by `to_document`.
+    pub fn from_document(document: &Document) -> Option<OplogEntry> {
+        let (Some(ts), Some(Value::Array(ops))) = (document.get("ts"), document.get("ops")) else {
+            return None;
+        };
+        Some(OplogEntry {
+            ts: OpTime::from_value(ts)?,
+            ops: ops
+                .iter()
+                .map(|op| op.as_document().and_then(Write::from_document))
+                .collect::<Option<_>>()?,
+        })
+    }
+}
+
+/// Where a secondary reads the oplog and initial snapshot of the primary
+/// it follows. `Database` is a sync source for secondaries in the same
+/// process; other transports implement it by forwarding the calls.
+pub trait SyncSource {
+    /// Returns up to `limit` oplog entries committed after `after`, or from
+    /// the oldest one kept if `None`, waiting up to `wait` for one to be
+    /// committed if there are none yet.
+    ///
+    /// # Errors
+    ///
+    /// Returns `OplogDisabled` if the source keeps no oplog, or
+    /// `OplogTruncated` if the entry `after` has been removed, so entries
+    /// the secondary has not applied may have been too.
+    fn oplog_after(
+        &self,
+        after: Option<OpTime>,
+        limit: usize,
+        wait: Duration,
+    ) -> Result<Vec<OplogEntry>, DatabaseError>;
+
+    /// Writes a backup of the source to `dir`, as `Database::backup` does,
+    /// whose `optime` is the last oplog entry it includes.
+    fn snapshot(&self, dir: &Path) -> Result<BackupStats, DatabaseError>;
+}
+
+impl SyncSource for Database {
+    fn oplog_after(
+        &self,
+        after: Option<OpTime>,
+        limit: usize,
+        wait: Duration,
+    ) -> Result<Vec<OplogEntry>, DatabaseError> {
+        Database::oplog_after(self, after, limit, wait)
+    }
+
+    fn snapshot(&self, dir: &Path) -> Result<BackupStats, DatabaseError> {
+        self.backup(dir)
+    }
+}
+
+/// The oplog's size limit and the entries it holds, and the Raft log's
+/// state if a `RaftNode` runs the database, kept with the database they
+/// record.
+#[derive(Debug, Default)]
+pub(crate) struct ReplicationState {
+    /// The most bytes of entries the oplog keeps, or `None` if the
+    /// database keeps no oplog.
+    size: Option<u64>,
+    /// The time and encoded size of each entry, oldest first.
+    entries: VecDeque<(OpTime, u64)>,
+    bytes: u64,
+    /// The time of the last entry recorded, in the oplog or the Raft log.
+    last: Option<OpTime>,
+    pub(crate) raft: Option<RaftLog>,
+}
+
+/// An entry added to a commit's writes by `ReplicationState::append`,
+/// counted once the commit is applied.
+pub(crate) struct Appended {
+    ts: OpTime,
+    /// The entry's size if it went to the oplog.
+    size: Option<u64>,
+    removed: usize,
+    /// Whether the entry went to the Raft log too.
+    raft: bool,
+}
+
+impl ReplicationState {
+    /// Returns the time of the last entry in the oplog, if any.
+    pub(crate) fn last(&self) -> Option<OpTime> {
+        self.entries.back().map(|&(ts, _)| ts)
+    }
+
+    /// Adds the entry recording `writes` to them: in the oplog, with the
+    /// deletes of the oldest entries that no longer fit under the size
+    /// limit, and in the Raft log if this node leads. The newest oplog
+    /// entry is always kept.
+    ///
+    /// Commits writing nothing but replication state add no entry, except
+    /// the one starting the oplog, so the oplog is never empty once
+    /// started.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotLeader` if a Raft node that does not lead is asked to
+    /// commit writes other than those it replicates.
+    pub(crate) fn append(
+        &self,
+        writes: &mut Vec<Write>,
+    ) -> Result<Option<Appended>, DatabaseError> {
+        let ops: Vec<Write> = writes
+            .iter()
+            .filter(|write| !is_local(write.target().0))
+            .cloned()
+            .collect();
+        let raft = match &self.raft {
+            Some(raft) if !raft.leader && !raft.applying && !ops.is_empty() => {
+                return Err(DatabaseError::NotLeader(raft.leader_id));
+            }
+            Some(raft) => raft.leader && !ops.is_empty(),
+            None => false,
+        };
+        let starts_oplog = writes
+            .iter()
+            .any(|write| write.target() == (REPLICATION_NAMESPACE, OPLOG_SIZE_KEY));
+        let oplog = self.size.is_some() && (!ops.is_empty() || starts_oplog);
+        if !oplog && !raft {
+            return Ok(None);
+        }
+        let entry = OplogEntry {
+            ts: self.next_time(),
+            ops,
+        };
+        if let (true, Some(raft)) = (raft, &self.raft) {
+            writes.extend(raft.append_writes(&entry)?);
+        }
+        let mut appended = Appended {
+            ts: entry.ts,
+            size: None,
+            removed: 0,
+            raft,
+        };
+        if let (true, Some(limit)) = (oplog, self.size) {
+            let value = to_bytes(&entry.to_document())?;
+            let size = value.len() as u64;
+            let mut bytes = self.bytes + size;
+            for &(ts, entry_size) in &self.entries {
+                if bytes <= limit {
+                    break;
+                }
+                bytes -= entry_size;
+                appended.removed += 1;
+                writes.push(Write::Delete {
+                    namespace: OPLOG_NAMESPACE.to_string(),
+                    key: ts.to_bytes().to_vec(),
+                });
+            }
+            writes.push(Write::Put {
+                namespace: OPLOG_NAMESPACE.to_string(),
+                key: entry.ts.to_bytes().to_vec(),
+                value,
+            });
+            appended.size = Some(size);
+        }
+        Ok(Some(appended))
+    }
+
+    /// Counts an entry added by `append` once its commit is applied.
+    pub(crate) fn appended(&mut self, appended: Appended) {
+        self.last = Some(appended.ts);
+        if let Some(size) = appended.size {
+            for (_, size) in self.entries.drain(..appended.removed) {
+                self.bytes -= size;
+            }
+            self.entries.push_back((appended.ts, size));
+            self.bytes += size;
+        }
+        if let (true, Some(raft)) = (appended.raft, &mut self.raft) {
+            raft.appended();
+        }
+    }
+
+    /// Returns the time for an entry recorded now.
+    pub(crate) fn next_time(&self) -> OpTime {
+        OpTime::next(self.last)
+    }
+
+    /// Notes `ts` as the time of an entry recorded by the Raft log, so
+    /// later entries come after it.
+    pub(crate) fn observe(&mut self, ts: OpTime) {
+        self.last = self.last.max(Some(ts));
+    }
+}
+
+/// Reads the oplog's size limit and the sizes of its entries from storage,
+/// when opening a database.
+pub(crate) fn load_replication(inner: &DatabaseInner) -> Result<ReplicationState, DatabaseError> {
+    let mut state = ReplicationState::default();
+    let Some(settings) = inner.get(None, REPLICATION_NAMESPACE, OPLOG_SIZE_KEY)? else {
+        return Ok(state);
+    };
+    let settings = from_bytes(&settings)?;
+    let Some(Value::Int64(size)) = settings.get("size") else {
+        return Err(bad_oplog("bad oplog size").into());
+    };
+    state.size = Some(*size as u64);
+    for (key, value) in inner.scan(None, OPLOG_NAMESPACE, &KeyRange::all(), usize::MAX)? {
+        let ts = OpTime::from_bytes(&key).ok_or_else(|| bad_oplog("bad entry key"))?;
+        state.entries.push_back((ts, value.len() as u64));
+        state.bytes += value.len() as u64;
+    }
+    state.last = state.last();
+    Ok(state)
+}
+
+/// Starts or resizes the oplog of the database, in a commit that is the
+/// oplog's newest entry.
+pub(crate) fn enable_oplog(inner: &mut DatabaseInner, size: u64) -> Result<(), DatabaseError> {
+    let mut settings = Document::new();
+    settings.insert("size", size as i64);
+    let write = Write::Put {
+        namespace: REPLICATION_NAMESPACE.to_string(),
+        key: OPLOG_SIZE_KEY.to_vec(),
+        value: to_bytes(&settings)?,
+    };
+    let previous = inner.replication.size.replace(size);
+    let result = inner.write(None, vec![write], BTreeSet::new(), WriteConcern::default());
+    if result.is_err() {
+        inner.replication.size = previous;
+    }
+    result
+}
+
+/// Returns up to `limit` oplog entries after `after`, waiting up to `wait`
+/// for a commit if there are none. See `SyncSource::oplog_after`.
+pub(crate) fn oplog_after(
+    inner: &Arc<Mutex<DatabaseInner>>,
+    after: Option<OpTime>,
+    limit: usize,
+    wait: Duration,
+) -> Result<Vec<OplogEntry>, DatabaseError> {
+    let deadline = Instant::now() + wait;
+    let mut locked = DatabaseInner::lock(inner);
+    loop {
+        let state = &locked.replication;
+        if state.size.is_none() {
+            return Err(DatabaseError::OplogDisabled);
+        }
+        let range = match after {
+            Some(after) => {
+                if state
+                    .entries
+                    .front()
+                    .is_none_or(|&(first, _)| after < first)
+                {
+                    return Err(DatabaseError::OplogTruncated(after));
+                }
+                KeyRange::all().after(&after.to_bytes())
+            }
+            None => KeyRange::all(),
+        };
+        let entries = locked.scan(None, OPLOG_NAMESPACE, &range, limit)?;
+        let now = Instant::now();
+        if !entries.is_empty() || now >= deadline {
+            return entries
+                .iter()
+                .map(|(_, value)| decode_entry(value))
+                .collect();
+        }
+        let committed = Arc::clone(&locked.committed);
+        locked = committed
+            .wait_timeout(locked, deadline - now)
+            .expect("database lock poisoned")
+            .0;
+    }
+}
+
+/// A database following a primary through a `SyncSource`, applying each
+/// entry of the primary's oplog in a commit of its own.
+///
+/// A secondary starts from a copy of the primary made by `initial_sync`,
+/// or from an empty database if the primary kept an oplog from the start,
+/// and `sync` then applies the entries committed since. The last entry
+/// applied is committed with it, so a secondary reopened after a crash
+/// picks up where it left off.
+///
+/// The secondary's database can be read as usual. Writes made to it
+/// directly are not sent to the primary, and may be overwritten by it.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use silentdb::{Database, Secondary};
+/// let primary = Database::open("primary").unwrap();
+/// primary.enable_oplog(64 * 1024 * 1024).unwrap();
+///
+/// let secondary = Secondary::new(Database::open("secondary").unwrap(), primary);
+/// secondary.initial_sync("snapshot").unwrap();
+/// loop {
+///     secondary.sync(Duration::from_secs(1)).unwrap();
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Secondary<S: SyncSource> {
+    db: Database,
+    source: S,
+    batch_size: usize,
+}
+
+impl<S: SyncSource> Secondary<S> {
+    /// Makes `db` a secondary of `source`.
+    pub fn new(db: Database, source: S) -> Self {
+        Secondary {
+            db,
+            source,
+            batch_size: DEFAULT_SYNC_BATCH,
+        }
+    }
+
+    /// Sets how many entries each fetch from the source asks for.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Returns the secondary's database.
+    pub fn database(&self) -> &Database {
+        &self.db
+    }
+
+    /// Returns the last oplog entry the secondary applied, or `None` if it
+    /// has applied none.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the progress record cannot be read.
+    pub fn applied(&self) -> Result<Option<OpTime>, DatabaseError> {
+        let inner = DatabaseInner::lock(&self.db.inner);
+        let Some(bytes) = inner.get(None, REPLICATION_NAMESPACE, APPLIED_KEY)? else {
+            return Ok(None);
+        };
+        let ts = from_bytes(&bytes)?
+            .get("ts")
+            .and_then(OpTime::from_value)
+            .ok_or_else(|| bad_oplog("bad applied entry"))?;
+        Ok(Some(ts))
+    }
+
+    /// Copies the source into the secondary's database through a backup
+    /// in `dir`, and records the backup's last entry as applied, so `sync`
+    /// carries on from it. The database is meant to be empty.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the backup cannot be written or restored, as
+    /// `Database::restore` does.
+    pub fn initial_sync<P: AsRef<Path>>(&self, dir: P) -> Result<BackupStats, DatabaseError> {
+        let dir = dir.as_ref();
+        self.source.snapshot(dir)?;
+        let stats = self.db.restore(dir)?;
+        if let Some(ts) = stats.optime {
+            let mut inner = DatabaseInner::lock(&self.db.inner);
+            inner.write(
+                None,
+                vec![applied_write(ts)?],
+                BTreeSet::new(),
+                WriteConcern::default(),
+            )?;
+        }
+        Ok(stats)
+    }
+
+    /// Applies one batch of entries the source committed after the last
+    /// one applied, waiting up to `wait` for one if there are none yet.
+    /// Returns the number of entries applied.
+    ///
+    /// # Errors
+    ///
+    /// Returns `OplogTruncated` if the source has removed entries the
+    /// secondary has not applied, so it must be synced anew, or
+    /// `WriteConflict` if an open transaction on the secondary wrote to a
+    /// document an entry changes, in which case the entry is retried by
+    /// the next call.
+    pub fn sync(&self, wait: Duration) -> Result<usize, DatabaseError> {
+        let entries = self
+            .source
+            .oplog_after(self.applied()?, self.batch_size, wait)?;
+        let mut inner = DatabaseInner::lock(&self.db.inner);
+        for entry in &entries {
+            let mut writes = entry.ops.clone();
+            writes.push(applied_write(entry.ts)?);
+            inner.write(None, writes, BTreeSet::new(), WriteConcern::default())?;
+        }
+        Ok(entries.len())
+    }
+}
+
+/// Returns whether writes to `namespace` are kept out of the oplog and
+/// the Raft log, as state of the node rather than of its data.
+pub(crate) fn is_local(namespace: &str) -> bool {
+    [
+        OPLOG_NAMESPACE,
+        REPLICATION_NAMESPACE,
+        RAFT_NAMESPACE,
+        RAFT_LOG_NAMESPACE,
+    ]
+    .contains(&namespace)
+}
+
+/// Returns the write recording `ts` as the last entry a secondary applied.
+fn applied_write(ts: OpTime) -> Result<Write, DatabaseError> {
+    let mut applied = Document::new();
+    applied.insert("ts", ts.to_value());
+    Ok(Write::Put {
+        namespace: REPLICATION_NAMESPACE.to_string(),
+        key: APPLIED_KEY.to_vec(),
+        value: to_bytes(&applied)?,
+    })
+}
+
+/// Reads an oplog entry stored as `value`.
+fn decode_entry(value: &[u8]) -> Result<OplogEntry, DatabaseError> {
+    let entry = OplogEntry::from_document(&from_bytes(value)?);
+    Ok(entry.ok_or_else(|| bad_oplog("bad entry"))?)
+}
+
+fn bad_oplog(message: &str) -> StorageError {
+    StorageError::corrupt(OPLOG_NAMESPACE, message)
+}
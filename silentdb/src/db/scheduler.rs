@@ -0,0 +1,361 @@
+// src/db/scheduler.rs
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use rand::Rng;
+use silentdb_data_encoding::{Document, Value};
+
+use super::catalog;
+use super::collection::Collection;
+use super::compaction::{compact, CompactionOptions};
+use super::database::DatabaseInner;
+use super::error::DatabaseError;
+use super::ttl::expire;
+
+/// The longest a failing task's retries are put off for.
+const MAX_BACKOFF: Duration = Duration::from_secs(60 * 60);
+/// How far each wait is randomly stretched or shrunk unless told
+/// otherwise, as a fraction of it.
+const DEFAULT_JITTER: f64 = 0.1;
+
+/// A recurring maintenance task run by a database's scheduler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MaintenanceTask {
+    /// Deletes expired documents. See `Database::expire_documents`.
+    /// Enabled by default, every minute.
+    TtlReaper,
+    /// Takes a checkpoint. See `Database::checkpoint`. Enabled by
+    /// default, every minute.
+    Checkpoint,
+    /// Compacts the storage engine with the default options. See
+    /// `Database::compact`. Disabled by default, every hour once enabled.
+    Compaction,
+    /// Gathers the statistics the query planner uses for each collection.
+    /// See `Collection::stats`. Disabled by default, every ten minutes
+    /// once enabled.
+    StatsRefresh,
+}
+
+impl MaintenanceTask {
+    /// Every task, in the order they are reported.
+    pub const ALL: [MaintenanceTask; 4] = [
+        MaintenanceTask::TtlReaper,
+        MaintenanceTask::Checkpoint,
+        MaintenanceTask::Compaction,
+        MaintenanceTask::StatsRefresh,
+    ];
+
+    /// Returns the name the task is reported under.
+    pub fn name(self) -> &'static str {
+        match self {
+            MaintenanceTask::TtlReaper => "ttl",
+            MaintenanceTask::Checkpoint => "checkpoint",
+            MaintenanceTask::Compaction => "compaction",
+            MaintenanceTask::StatsRefresh => "stats",
+        }
+    }
+
+    fn default_schedule(self) -> (bool, Duration) {
+        match self {
+            MaintenanceTask::TtlReaper => (true, Duration::from_secs(60)),
+            MaintenanceTask::Checkpoint => (true, Duration::from_secs(60)),
+            MaintenanceTask::Compaction => (false, Duration::from_secs(60 * 60)),
+            MaintenanceTask::StatsRefresh => (false, Duration::from_secs(10 * 60)),
+        }
+    }
+
+    /// Runs the task once over `inner` and describes what it did.
+    fn run(self, inner: &Arc<Mutex<DatabaseInner>>) -> Result<Document, DatabaseError> {
+        let mut result = Document::new();
+        match self {
+            MaintenanceTask::TtlReaper => {
+                result.insert("expired", expire(inner)? as i64);
+            }
+            MaintenanceTask::Checkpoint => {
+                let lsn = DatabaseInner::lock(inner).checkpoint()?;
+                result.insert("lsn", lsn as i64);
+            }
+            MaintenanceTask::Compaction => {
+                let stats = compact(inner, &CompactionOptions::new())?;
+                result.insert("steps", stats.steps as i64);
+                result.insert("work", stats.work as i64);
+                result.insert("reclaimed", stats.reclaimed as i64);
+            }
+            MaintenanceTask::StatsRefresh => {
+                let names = catalog::list(&DatabaseInner::lock(inner))?;
+                for name in &names {
+                    Collection::new(name, Arc::clone(inner), None).stats()?;
+                }
+                result.insert("collections", names.len() as i64);
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl fmt::Display for MaintenanceTask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The outcome of a task's run, kept for introspection.
+#[derive(Debug, Clone)]
+struct TaskRun {
+    /// When the run started, in milliseconds since the Unix epoch.
+    started: i64,
+    took: Duration,
+    result: Result<Document, String>,
+}
+
+/// A task's schedule and latest run.
+#[derive(Debug, Clone)]
+struct TaskState {
+    enabled: bool,
+    interval: Duration,
+    jitter: f64,
+    next_run: Instant,
+    /// Whether the scheduler is running the task now.
+    running: bool,
+    /// Runs failed in a row, which put off the next one further each.
+    failures: u32,
+    runs: u64,
+    last: Option<TaskRun>,
+}
+
+impl TaskState {
+    /// Returns how long to wait for the next run: the interval, doubled
+    /// for each failure in a row up to `MAX_BACKOFF`, stretched or shrunk
+    /// by up to the jitter.
+    fn delay(&self) -> Duration {
+        let backoff = 2u32.saturating_pow(self.failures.min(16));
+        let delay = match self.failures {
+            0 => self.interval,
+            _ => (self.interval * backoff)
+                .min(MAX_BACKOFF)
+                .max(self.interval),
+        };
+        if self.jitter <= 0.0 {
+            return delay;
+        }
+        let factor = rand::thread_rng().gen_range(-self.jitter..=self.jitter);
+        delay.mul_f64((1.0 + factor).max(0.0))
+    }
+
+    fn to_document(&self, task: MaintenanceTask) -> Document {
+        let mut document = Document::new();
+        document.insert("task", task.name());
+        document.insert("enabled", self.enabled);
+        document.insert("intervalMillis", self.interval.as_millis() as i64);
+        document.insert("jitter", self.jitter);
+        document.insert("running", self.running);
+        document.insert("runs", self.runs as i64);
+        document.insert("failures", self.failures as i64);
+        if self.enabled && !self.running {
+            let due = self.next_run.saturating_duration_since(Instant::now());
+            document.insert("nextRunMillis", due.as_millis() as i64);
+        }
+        if let Some(last) = &self.last {
+            let mut run = Document::new();
+            run.insert("started", Value::UTCDateTime(last.started));
+            run.insert("tookMillis", last.took.as_millis() as i64);
+            match &last.result {
+                Ok(result) => {
+                    run.insert("ok", true);
+                    run.insert("result", result.clone());
+                }
+                Err(error) => {
+                    run.insert("ok", false);
+                    run.insert("error", error.as_str());
+                }
+            }
+            document.insert("lastRun", run);
+        }
+        document
+    }
+}
+
+/// The schedules of a database's maintenance tasks, kept with the database
+/// they serve.
+#[derive(Debug)]
+pub(crate) struct SchedulerState {
+    tasks: BTreeMap<MaintenanceTask, TaskState>,
+    /// Wakes the scheduler early; dropping it stops the scheduler.
+    wake: Sender<()>,
+}
+
+impl SchedulerState {
+    pub(crate) fn set_enabled(&mut self, task: MaintenanceTask, enabled: bool) {
+        let state = self.task(task);
+        if enabled && !state.enabled {
+            state.next_run = Instant::now() + state.delay();
+        }
+        state.enabled = enabled;
+        let _ = self.wake.send(());
+    }
+
+    /// Sets how long `task` waits between runs, and runs it at once if it
+    /// is enabled.
+    pub(crate) fn set_interval(&mut self, task: MaintenanceTask, interval: Duration) {
+        let state = self.task(task);
+        state.interval = interval;
+        state.next_run = Instant::now();
+        let _ = self.wake.send(());
+    }
+
+    pub(crate) fn set_jitter(&mut self, task: MaintenanceTask, jitter: f64) {
+        self.task(task).jitter = jitter.clamp(0.0, 1.0);
+    }
+
+    /// Describes each task's schedule and latest run.
+    pub(crate) fn status(&self) -> Vec<Document> {
+        self.tasks
+            .iter()
+            .map(|(task, state)| state.to_document(*task))
+            .collect()
+    }
+
+    fn task(&mut self, task: MaintenanceTask) -> &mut TaskState {
+        self.tasks.get_mut(&task).expect("every task is scheduled")
+    }
+
+    /// Returns the enabled task due first, if it is due by now, or else
+    /// how long until it is.
+    fn due(&self) -> Result<MaintenanceTask, Option<Duration>> {
+        let now = Instant::now();
+        let next = self
+            .tasks
+            .iter()
+            .filter(|(_, state)| state.enabled && !state.running)
+            .min_by_key(|(_, state)| state.next_run);
+        match next {
+            Some((task, state)) if state.next_run <= now => Ok(*task),
+            Some((_, state)) => Err(Some(state.next_run - now)),
+            None => Err(None),
+        }
+    }
+
+    /// Records a run of `task` that started at `started` and took `took`,
+    /// and schedules the next one.
+    fn record(
+        &mut self,
+        task: MaintenanceTask,
+        started: SystemTime,
+        took: Duration,
+        result: &Result<Document, DatabaseError>,
+    ) {
+        let state = self.task(task);
+        state.running = false;
+        state.runs += 1;
+        match result {
+            Ok(_) => state.failures = 0,
+            Err(_) => state.failures = state.failures.saturating_add(1),
+        }
+        state.next_run = Instant::now() + state.delay();
+        state.last = Some(TaskRun {
+            started: started
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_millis() as i64),
+            took,
+            result: match result {
+                Ok(result) => Ok(result.clone()),
+                Err(e) => Err(e.to_string()),
+            },
+        });
+    }
+}
+
+/// Returns the scheduler state for a new database, with every task on its
+/// default schedule, and the receiving end of its wake channel, to pass to
+/// `spawn_scheduler` once the database is shared.
+pub(crate) fn scheduler_state() -> (SchedulerState, Receiver<()>) {
+    let (wake, woken) = mpsc::channel();
+    let tasks = MaintenanceTask::ALL
+        .into_iter()
+        .map(|task| {
+            let (enabled, interval) = task.default_schedule();
+            let mut state = TaskState {
+                enabled,
+                interval,
+                jitter: DEFAULT_JITTER,
+                next_run: Instant::now(),
+                running: false,
+                failures: 0,
+                runs: 0,
+                last: None,
+            };
+            state.next_run += state.delay();
+            (task, state)
+        })
+        .collect();
+    (SchedulerState { tasks, wake }, woken)
+}
+
+/// Runs `task` over `inner` now, recording the run as the scheduler would,
+/// and returns what it did.
+pub(crate) fn run_task(
+    inner: &Arc<Mutex<DatabaseInner>>,
+    task: MaintenanceTask,
+) -> Result<Document, DatabaseError> {
+    let started = SystemTime::now();
+    let clock = Instant::now();
+    let result = task.run(inner);
+    DatabaseInner::lock(inner)
+        .scheduler
+        .record(task, started, clock.elapsed(), &result);
+    result
+}
+
+/// Starts the background thread that runs the maintenance tasks of `inner`
+/// as they fall due, one at a time.
+///
+/// The thread holds no reference that keeps the database open, and exits
+/// once the database is dropped.
+pub(crate) fn spawn_scheduler(inner: &Arc<Mutex<DatabaseInner>>, woken: Receiver<()>) {
+    let inner = Arc::downgrade(inner);
+    let scheduler = move || loop {
+        let Some(due) = upgrade(&inner, |inner| {
+            let mut locked = DatabaseInner::lock(inner);
+            let due = locked.scheduler.due();
+            if let Ok(task) = due {
+                locked.scheduler.task(task).running = true;
+            }
+            due
+        }) else {
+            return;
+        };
+        match due {
+            Ok(task) => {
+                // Failures are recorded, and put off the task's next run
+                if upgrade(&inner, |inner| run_task(inner, task)).is_none() {
+                    return;
+                }
+            }
+            Err(wait) => {
+                let woken = match wait {
+                    Some(wait) => woken.recv_timeout(wait),
+                    None => woken.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                };
+                if let Err(RecvTimeoutError::Disconnected) = woken {
+                    return;
+                }
+            }
+        }
+    };
+    thread::Builder::new()
+        .name("silentdb-scheduler".to_string())
+        .spawn(scheduler)
+        .expect("failed to spawn scheduler");
+}
+
+fn upgrade<T>(
+    inner: &Weak<Mutex<DatabaseInner>>,
+    f: impl FnOnce(&Arc<Mutex<DatabaseInner>>) -> T,
+) -> Option<T> {
+    inner.upgrade().map(|inner| f(&inner))
+}
@@ -0,0 +1,269 @@
+// src/db/schema.rs
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use silentdb_data_encoding::{from_bytes, to_bytes, Document, RawDocument, Value};
+
+use super::error::DatabaseError;
+
+/// The field holding a document's schema version in a collection with
+/// migrations.
+const SCHEMA_FIELD: &str = "_schema";
+/// Suffix of the namespace holding the documents a schema migration
+/// replaced, by key.
+const SCHEMA_ROLLBACK_SUFFIX: &str = ".$schemaRollback";
+/// Documents migrated per write by `Collection::migrate_schema` unless told
+/// otherwise.
+const DEFAULT_BATCH_SIZE: usize = 500;
+
+/// A function upgrading a document to the schema version it was registered
+/// for, from the one before.
+pub(crate) type MigrationFn = Arc<dyn Fn(Document) -> Document + Send + Sync>;
+
+/// The migrations registered for each collection, as held by the database
+/// while it is open.
+///
+/// The migration at position `i` of a collection's list upgrades documents
+/// from version `i` to version `i + 1`, so documents without a version,
+/// written before the collection had migrations, are at version 0.
+#[derive(Default)]
+pub(crate) struct Migrations {
+    steps: HashMap<String, Vec<MigrationFn>>,
+}
+
+impl fmt::Debug for Migrations {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let latest: HashMap<&str, usize> = self
+            .steps
+            .iter()
+            .map(|(collection, steps)| (collection.as_str(), steps.len()))
+            .collect();
+        f.debug_struct("Migrations")
+            .field("latest", &latest)
+            .finish()
+    }
+}
+
+impl Migrations {
+    /// Registers `migration` as the one upgrading documents of
+    /// `collection` to `version`, replacing any registered for it.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidMigration` if `version` is 0 or more than one past
+    /// the latest registered.
+    pub(crate) fn register(
+        &mut self,
+        collection: &str,
+        version: u32,
+        migration: MigrationFn,
+    ) -> Result<(), DatabaseError> {
+        let steps = self.steps.entry(collection.to_string()).or_default();
+        match version as usize {
+            0 => Err(DatabaseError::InvalidMigration(
+                "schema versions start at 1".into(),
+            )),
+            version if version <= steps.len() => {
+                steps[version - 1] = migration;
+                Ok(())
+            }
+            version if version == steps.len() + 1 => {
+                steps.push(migration);
+                Ok(())
+            }
+            version => Err(DatabaseError::InvalidMigration(format!(
+                "{} has no migration to version {}",
+                collection,
+                version - 1
+            ))),
+        }
+    }
+
+    /// Returns the version documents of `collection` are upgraded to, or 0
+    /// if it has no migrations.
+    pub(crate) fn latest(&self, collection: &str) -> u32 {
+        self.steps
+            .get(collection)
+            .map_or(0, |steps| steps.len() as u32)
+    }
+
+    /// Gives `document`, about to be written to `collection`, the latest
+    /// version, unless it has a version already or the collection has no
+    /// migrations. Returns whether it was changed.
+    pub(crate) fn stamp(&self, collection: &str, document: &mut Document) -> bool {
+        let latest = self.latest(collection);
+        if latest == 0 || document.get(SCHEMA_FIELD).is_some() {
+            return false;
+        }
+        document.insert(SCHEMA_FIELD, latest as i64);
+        true
+    }
+
+    /// Returns whether the document `bytes` of `collection` is at a version
+    /// older than the latest.
+    pub(crate) fn is_outdated(
+        &self,
+        collection: &str,
+        bytes: &[u8],
+    ) -> Result<bool, DatabaseError> {
+        let latest = self.latest(collection);
+        if latest == 0 {
+            return Ok(false);
+        }
+        let version = match RawDocument::from_bytes(bytes)?.get(SCHEMA_FIELD)? {
+            Some(element) => schema_version(&element.value()?),
+            None => 0,
+        };
+        Ok(version < latest as i64)
+    }
+
+    /// Returns `document` of `collection` upgraded to the latest version by
+    /// the migrations after its own.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidMigration` if a migration changes the document's
+    /// `_id`.
+    pub(crate) fn upgrade(
+        &self,
+        collection: &str,
+        mut document: Document,
+    ) -> Result<Document, DatabaseError> {
+        let Some(steps) = self.steps.get(collection) else {
+            return Ok(document);
+        };
+        let version = document.get(SCHEMA_FIELD).map_or(0, schema_version);
+        let id = document.get("_id").cloned();
+        for (step, migration) in steps.iter().enumerate().skip(version.max(0) as usize) {
+            document = migration(document);
+            if document.get("_id") != id.as_ref() {
+                return Err(DatabaseError::InvalidMigration(format!(
+                    "the migration of {} to version {} changed _id",
+                    collection,
+                    step + 1
+                )));
+            }
+        }
+        document.insert(SCHEMA_FIELD, steps.len() as i64);
+        Ok(document)
+    }
+
+    /// Returns the encoding of the document `bytes` of `collection`
+    /// upgraded to the latest version, or `None` if it is up to date.
+    pub(crate) fn upgrade_bytes(
+        &self,
+        collection: &str,
+        bytes: &[u8],
+    ) -> Result<Option<Vec<u8>>, DatabaseError> {
+        if !self.is_outdated(collection, bytes)? {
+            return Ok(None);
+        }
+        let document = self.upgrade(collection, from_bytes(bytes)?)?;
+        Ok(Some(to_bytes(&document)?))
+    }
+}
+
+/// Returns the version a `_schema` field holding `value` stands for, where
+/// anything but an integer is version 0.
+fn schema_version(value: &Value) -> i64 {
+    match value {
+        Value::Int32(version) => *version as i64,
+        Value::Int64(version) => *version,
+        _ => 0,
+    }
+}
+
+pub(crate) fn rollback_namespace(collection: &str) -> String {
+    format!("{}{}", collection, SCHEMA_ROLLBACK_SUFFIX)
+}
+
+/// What a schema migration has done, as returned by
+/// `Collection::migrate_schema` and passed to its progress callback after
+/// each batch.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SchemaMigrationStats {
+    /// The version documents were upgraded to.
+    pub version: u32,
+    /// The number of documents read.
+    pub scanned: u64,
+    /// The number of documents upgraded and written back.
+    pub migrated: u64,
+    /// The number of writes made.
+    pub batches: u64,
+}
+
+/// The progress callback of a schema migration, compared by identity.
+#[derive(Clone)]
+pub(crate) struct ProgressHook(pub(crate) Arc<dyn Fn(&SchemaMigrationStats) + Send + Sync>);
+
+impl fmt::Debug for ProgressHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ProgressHook")
+    }
+}
+
+impl PartialEq for ProgressHook {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// Options for `Collection::migrate_schema`.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb::SchemaMigrationOptions;
+/// let options = SchemaMigrationOptions::new()
+///     .batch_size(100)
+///     .progress(|stats| println!("{} migrated", stats.migrated));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaMigrationOptions {
+    pub(crate) batch_size: usize,
+    pub(crate) rollback: bool,
+    pub(crate) progress: Option<ProgressHook>,
+}
+
+impl SchemaMigrationOptions {
+    /// Creates options migrating 500 documents per write and keeping
+    /// rollback records.
+    pub fn new() -> Self {
+        SchemaMigrationOptions {
+            batch_size: DEFAULT_BATCH_SIZE,
+            rollback: true,
+            progress: None,
+        }
+    }
+
+    /// Sets how many documents are read and migrated per write.
+    pub fn batch_size(mut self, documents: usize) -> Self {
+        self.batch_size = documents.max(1);
+        self
+    }
+
+    /// Sets whether the documents replaced are kept, so that
+    /// `Collection::rollback_schema` can restore them.
+    pub fn rollback(mut self, rollback: bool) -> Self {
+        self.rollback = rollback;
+        self
+    }
+
+    /// Calls `progress` with what the migration has done after each
+    /// batch.
+    pub fn progress(
+        mut self,
+        progress: impl Fn(&SchemaMigrationStats) + Send + Sync + 'static,
+    ) -> Self {
+        self.progress = Some(ProgressHook(Arc::new(progress)));
+        self
+    }
+}
+
+impl Default for SchemaMigrationOptions {
+    fn default() -> Self {
+        SchemaMigrationOptions::new()
+    }
+}
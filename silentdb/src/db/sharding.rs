@@ -0,0 +1,781 @@
+// src/db/sharding.rs
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::ops::Bound;
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+
+use silentdb_data_encoding::{from_bytes, to_bytes, Array, Document, Value};
+
+use super::change::ChangeKind;
+use super::collection::{Collection, DeleteResult, InsertManyResult, InsertOneResult};
+use super::collection::{UpdateOptions, UpdateResult};
+use super::concern::WriteConcern;
+use super::cursor::Cursor;
+use super::database::{Database, DatabaseInner, Write};
+use super::error::DatabaseError;
+use super::path::get_path;
+use crate::storage::{encode_key, hash64, StorageError};
+
+/// Namespace in the config database holding each sharded collection's
+/// shard key and chunks, keyed by collection name.
+const SHARDING_NAMESPACE: &str = "$system.$sharding";
+/// Documents copied or deleted per write while migrating a chunk.
+const MIGRATION_BATCH: usize = 1000;
+
+/// The paths a sharded collection's documents are partitioned by.
+///
+/// A document's shard key hash is the hash of the values it holds at the
+/// paths, in order, with missing ones taken as null. Numbers hash by
+/// value, so `1` and `1.0` land in the same chunk.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb::ShardKey;
+/// # use silentdb_data_encoding::Document;
+/// let key = ShardKey::new(["tenant", "user.id"]);
+/// let mut document = Document::new();
+/// document.insert("tenant", "acme");
+/// assert_eq!(key.hash(&document), key.hash(&document.clone()));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShardKey {
+    paths: Vec<String>,
+}
+
+impl ShardKey {
+    /// Creates a shard key over the dotted `paths`.
+    pub fn new<I, S>(paths: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        ShardKey {
+            paths: paths.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    pub fn paths(&self) -> &[String] {
+        &self.paths
+    }
+
+    /// Returns the shard key hash of `document`.
+    pub fn hash(&self, document: &Document) -> u64 {
+        let values = self
+            .paths
+            .iter()
+            .map(|path| get_path(document, path).unwrap_or(&Value::Null));
+        hash_values(values)
+    }
+
+    /// Returns the hash of the documents `filter` can match, if it fixes
+    /// every path of the key to one value that is not an array.
+    fn target(&self, filter: &Document) -> Option<u64> {
+        let mut values = Vec::with_capacity(self.paths.len());
+        for path in &self.paths {
+            let value = match filter.get(path)? {
+                Value::Document(operators) if is_operators(operators) => {
+                    match (operators.len(), operators.get("$eq")) {
+                        (1, Some(value)) => value,
+                        _ => return None,
+                    }
+                }
+                value => value,
+            };
+            if matches!(value, Value::Array(_)) {
+                return None;
+            }
+            values.push(value);
+        }
+        Some(hash_values(values))
+    }
+
+    /// Returns whether `update` changes a path of the key.
+    fn touched_by(&self, update: &Document) -> bool {
+        let overlaps = |a: &str, b: &str| {
+            a == b
+                || a.strip_prefix(b).is_some_and(|rest| rest.starts_with('.'))
+                || b.strip_prefix(a).is_some_and(|rest| rest.starts_with('.'))
+        };
+        update.iter().any(|(_, fields)| match fields {
+            Value::Document(fields) => fields
+                .iter()
+                .any(|(field, _)| self.paths.iter().any(|path| overlaps(field, path))),
+            _ => false,
+        })
+    }
+
+    fn to_value(&self) -> Value {
+        let paths = self
+            .paths
+            .iter()
+            .map(|path| Value::from(path.as_str()))
+            .collect();
+        Value::Array(Array::from_vec(paths))
+    }
+}
+
+/// A range of shard key hashes and the shard holding the documents in it:
+/// from `start` up to the next chunk's start, or the end of the hash
+/// space for the last chunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    pub start: u64,
+    pub shard: String,
+}
+
+/// What `Router::move_chunk` moved.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MigrationStats {
+    /// Documents copied to the new shard before the chunk changed hands.
+    pub copied: u64,
+    /// Changes to the chunk made during the copy and replayed after it.
+    pub replayed: u64,
+    /// Documents removed from the old shard.
+    pub deleted: u64,
+}
+
+/// A sharded collection's layout, as kept in the config database.
+#[derive(Debug, Clone)]
+struct Layout {
+    key: ShardKey,
+    /// Each chunk's shard, by start.
+    chunks: BTreeMap<u64, String>,
+}
+
+impl Layout {
+    fn shard_of(&self, hash: u64) -> &str {
+        self.chunks
+            .range(..=hash)
+            .next_back()
+            .map(|(_, shard)| shard.as_str())
+            .expect("the first chunk starts at 0")
+    }
+
+    /// Returns whether `hash` falls in the chunk starting at `start`.
+    fn in_chunk(&self, start: u64, hash: u64) -> bool {
+        let after = (Bound::Excluded(start), Bound::Unbounded);
+        let end = self.chunks.range(after).next().map(|(&end, _)| end);
+        hash >= start && end.is_none_or(|end| hash < end)
+    }
+
+    fn to_document(&self) -> Document {
+        let mut document = Document::new();
+        document.insert("key", self.key.to_value());
+        let chunks = self
+            .chunks
+            .iter()
+            .map(|(&start, shard)| {
+                let mut chunk = Document::new();
+                chunk.insert("start", start as i64);
+                chunk.insert("shard", shard.as_str());
+                Value::Document(chunk)
+            })
+            .collect();
+        document.insert("chunks", Array::from_vec(chunks));
+        document
+    }
+
+    fn from_document(document: &Document) -> Option<Layout> {
+        let (Some(Value::Array(paths)), Some(Value::Array(chunks))) =
+            (document.get("key"), document.get("chunks"))
+        else {
+            return None;
+        };
+        let paths = paths
+            .iter()
+            .map(|path| match path {
+                Value::String(path) => Some(path.clone()),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let chunks = chunks
+            .iter()
+            .map(|chunk| {
+                let chunk = chunk.as_document()?;
+                match (chunk.get("start"), chunk.get("shard")) {
+                    (Some(Value::Int64(start)), Some(Value::String(shard))) => {
+                        Some((*start as u64, shard.clone()))
+                    }
+                    _ => None,
+                }
+            })
+            .collect::<Option<BTreeMap<_, _>>>()?;
+        if !chunks.contains_key(&0) {
+            return None;
+        }
+        Some(Layout {
+            key: ShardKey { paths },
+            chunks,
+        })
+    }
+}
+
+/// Routes the operations on sharded collections to the databases, or
+/// shards, holding their documents.
+///
+/// A sharded collection's documents are spread over the shards by the
+/// hash of their shard key: the hash space is cut into chunks, each held
+/// by one shard. Writes go to the shard holding the document's chunk,
+/// queries that fix the shard key go to that shard alone, and other
+/// queries go to every shard, with their cursors merged. Chunks move
+/// between shards with `move_chunk`. The layout is kept in a config
+/// database, so routers over the same config and shards see the same
+/// layout, though only operations through one router are held off while
+/// it moves a chunk.
+///
+/// Each document lives on one shard, so `_id`s are only checked for
+/// uniqueness within a shard, and operations touching documents on
+/// several shards are not atomic across them.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use silentdb::{Database, Router, ShardKey};
+/// # use silentdb_data_encoding::Document;
+/// let router = Router::new(
+///     Database::open("config").unwrap(),
+///     [
+///         ("a".to_string(), Database::open("shard-a").unwrap()),
+///         ("b".to_string(), Database::open("shard-b").unwrap()),
+///     ],
+/// );
+/// router.shard_collection("events", ShardKey::new(["device"]), 8).unwrap();
+///
+/// let events = router.collection("events");
+/// let mut event = Document::new();
+/// event.insert("device", "sensor-1");
+/// events.insert_one(event).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct Router {
+    config: Database,
+    shards: Arc<BTreeMap<String, Database>>,
+    /// Held shared by operations and exclusively while a migration
+    /// hands a chunk over.
+    migration: Arc<RwLock<()>>,
+}
+
+impl Router {
+    /// Creates a router over `shards`, named by the first of each pair,
+    /// keeping the layout of sharded collections in `config`.
+    pub fn new(config: Database, shards: impl IntoIterator<Item = (String, Database)>) -> Self {
+        Router {
+            config,
+            shards: Arc::new(shards.into_iter().collect()),
+            migration: Arc::new(RwLock::new(())),
+        }
+    }
+
+    /// Returns the names of the shards.
+    pub fn shards(&self) -> Vec<String> {
+        self.shards.keys().cloned().collect()
+    }
+
+    /// Shards the collection `name` by `key`, cutting the hash space into
+    /// `chunks` chunks of equal size dealt out to the shards in turn.
+    ///
+    /// Documents already in a shard's collection `name` are not moved, so
+    /// collections are best sharded before they hold any.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CollectionExists` if the collection is sharded already,
+    /// `ShardNotFound` if the router has no shards, or `InvalidIndex` if
+    /// `key` names no path.
+    pub fn shard_collection(
+        &self,
+        name: &str,
+        key: ShardKey,
+        chunks: usize,
+    ) -> Result<(), DatabaseError> {
+        if key.paths.is_empty() {
+            return Err(DatabaseError::InvalidIndex(
+                "a shard key needs a path".to_string(),
+            ));
+        }
+        let shards: Vec<&String> = self.shards.keys().collect();
+        if shards.is_empty() {
+            return Err(DatabaseError::ShardNotFound(String::new()));
+        }
+        let mut inner = DatabaseInner::lock(&self.config.inner);
+        if inner
+            .get(None, SHARDING_NAMESPACE, name.as_bytes())?
+            .is_some()
+        {
+            return Err(DatabaseError::CollectionExists(name.to_string()));
+        }
+        let count = chunks.max(1) as u128;
+        let layout = Layout {
+            key,
+            chunks: (0..count)
+                .map(|i| {
+                    let start = (i << 64) / count;
+                    (start as u64, shards[i as usize % shards.len()].clone())
+                })
+                .collect(),
+        };
+        inner.write(
+            None,
+            vec![layout_write(name, &layout)?],
+            BTreeSet::new(),
+            WriteConcern::Journaled,
+        )
+    }
+
+    /// Returns the chunks of the sharded collection `name`, in hash order.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotSharded` if the collection is not sharded.
+    pub fn chunks(&self, name: &str) -> Result<Vec<Chunk>, DatabaseError> {
+        let layout = self.layout(name)?;
+        Ok(layout
+            .chunks
+            .into_iter()
+            .map(|(start, shard)| Chunk { start, shard })
+            .collect())
+    }
+
+    /// Returns a handle to the sharded collection `name`.
+    pub fn collection(&self, name: &str) -> ShardedCollection {
+        ShardedCollection {
+            name: name.to_string(),
+            router: self.clone(),
+        }
+    }
+
+    /// Moves the chunk of `name` starting at `start` to the shard `to`, and
+    /// returns what was moved.
+    ///
+    /// The chunk's documents are copied while operations carry on, with a
+    /// change stream on the old shard catching the changes made meanwhile.
+    /// Operations through this router are then held off while the last
+    /// changes are replayed and the chunk changes hands, after which its
+    /// documents are removed from the old shard.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotSharded` if the collection is not sharded,
+    /// `ShardNotFound` if there is no chunk starting at `start` or no shard
+    /// `to`, or an error if a shard cannot be read or written, in which
+    /// case the chunk stays where it was.
+    pub fn move_chunk(
+        &self,
+        name: &str,
+        start: u64,
+        to: &str,
+    ) -> Result<MigrationStats, DatabaseError> {
+        let layout = self.layout(name)?;
+        let from = layout
+            .chunks
+            .get(&start)
+            .ok_or_else(|| DatabaseError::ShardNotFound(format!("chunk {}", start)))?
+            .clone();
+        let source = self.shard(&from)?.collection(name);
+        let target = self.shard(to)?.collection(name);
+        let mut stats = MigrationStats::default();
+        if from == to {
+            return Ok(stats);
+        }
+        for index in source.list_indexes()? {
+            if !target
+                .list_indexes()?
+                .iter()
+                .any(|i| i.same_options(&index))
+            {
+                target.create_index_with(index.to_options())?;
+            }
+        }
+
+        // Copy what the chunk holds now, then replay what changed meanwhile
+        let mut changes = source.watch(&Document::new())?;
+        let mut moved = HashSet::new();
+        let mut batch = Vec::new();
+        for document in source.find(&Document::new())? {
+            let document = document?;
+            if !layout.in_chunk(start, layout.key.hash(&document)) {
+                continue;
+            }
+            if let Some(id) = document.get("_id") {
+                moved.insert(encode_key(id));
+            }
+            batch.push(document);
+            if batch.len() == MIGRATION_BATCH {
+                stats.copied += batch.len() as u64;
+                target.insert_many(std::mem::take(&mut batch))?;
+            }
+        }
+        stats.copied += batch.len() as u64;
+        if !batch.is_empty() {
+            target.insert_many(batch)?;
+        }
+        let mut replay = |moved: &mut HashSet<Vec<u8>>, stats: &mut MigrationStats| {
+            while let Some(event) = changes.try_next()? {
+                let key = encode_key(&event.document_key);
+                let filter = id_filter(event.document_key.clone());
+                match (event.kind, event.full_document) {
+                    (ChangeKind::Delete, _) => {
+                        if moved.remove(&key) {
+                            target.delete_one(&filter)?;
+                            stats.replayed += 1;
+                        }
+                    }
+                    (_, Some(document)) => {
+                        if layout.in_chunk(start, layout.key.hash(&document)) {
+                            let upsert = UpdateOptions::new().upsert(true);
+                            target.replace_one_with(&filter, &document, &upsert)?;
+                            moved.insert(key);
+                            stats.replayed += 1;
+                        }
+                    }
+                    (_, None) => {}
+                }
+            }
+            Ok::<_, DatabaseError>(())
+        };
+        replay(&mut moved, &mut stats)?;
+
+        let _handover = self.migration.write().expect("migration lock poisoned");
+        replay(&mut moved, &mut stats)?;
+        let mut layout = self.layout(name)?;
+        layout.chunks.insert(start, to.to_string());
+        DatabaseInner::lock(&self.config.inner).write(
+            None,
+            vec![layout_write(name, &layout)?],
+            BTreeSet::new(),
+            WriteConcern::Journaled,
+        )?;
+
+        let mut ids = Vec::new();
+        for document in source.find(&Document::new())? {
+            let document = document?;
+            if layout.in_chunk(start, layout.key.hash(&document)) {
+                ids.push(document.get("_id").cloned().unwrap_or(Value::Null));
+            }
+        }
+        for ids in ids.chunks(MIGRATION_BATCH) {
+            let mut any = Document::new();
+            any.insert("$in", Array::from_vec(ids.to_vec()));
+            let filter = id_filter(Value::Document(any));
+            stats.deleted += source.delete_many(&filter)?.deleted_count;
+        }
+        Ok(stats)
+    }
+
+    fn layout(&self, name: &str) -> Result<Layout, DatabaseError> {
+        let inner = DatabaseInner::lock(&self.config.inner);
+        let Some(bytes) = inner.get(None, SHARDING_NAMESPACE, name.as_bytes())? else {
+            return Err(DatabaseError::NotSharded(name.to_string()));
+        };
+        Layout::from_document(&from_bytes(&bytes)?).ok_or_else(|| {
+            StorageError::corrupt(SHARDING_NAMESPACE, format!("bad layout of {}", name)).into()
+        })
+    }
+
+    /// Holds off chunk handovers while an operation runs.
+    fn operation(&self) -> RwLockReadGuard<'_, ()> {
+        self.migration.read().expect("migration lock poisoned")
+    }
+
+    fn shard(&self, name: &str) -> Result<&Database, DatabaseError> {
+        self.shards
+            .get(name)
+            .ok_or_else(|| DatabaseError::ShardNotFound(name.to_string()))
+    }
+}
+
+/// A handle to a sharded collection, made by `Router::collection`.
+///
+/// Operations fixing every path of the shard key in their filter, or
+/// inserting documents, go to the one shard holding the documents they
+/// touch; the others go to every shard.
+#[derive(Debug, Clone)]
+pub struct ShardedCollection {
+    name: String,
+    router: Router,
+}
+
+impl ShardedCollection {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Inserts `document` into the shard holding its chunk.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotSharded` if the collection is not sharded, or the
+    /// errors of `Collection::insert_one`.
+    pub fn insert_one(&self, document: Document) -> Result<InsertOneResult, DatabaseError> {
+        let mut result = self.insert_many([document])?;
+        Ok(InsertOneResult {
+            inserted_id: result.inserted_ids.remove(0),
+        })
+    }
+
+    /// Inserts `documents`, in one write per shard they go to. Each
+    /// shard's write is atomic, but not the insert as a whole.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotSharded` if the collection is not sharded, or the
+    /// errors of `Collection::insert_many` from the first shard that
+    /// fails.
+    pub fn insert_many<I>(&self, documents: I) -> Result<InsertManyResult, DatabaseError>
+    where
+        I: IntoIterator<Item = Document>,
+    {
+        let _operation = self.router.operation();
+        let layout = self.router.layout(&self.name)?;
+        let mut by_shard: BTreeMap<&str, Vec<(usize, Document)>> = BTreeMap::new();
+        let mut count = 0;
+        for (i, document) in documents.into_iter().enumerate() {
+            let shard = layout.shard_of(layout.key.hash(&document));
+            by_shard.entry(shard).or_default().push((i, document));
+            count += 1;
+        }
+        let mut inserted_ids = vec![Value::Null; count];
+        for (shard, documents) in by_shard {
+            let (positions, documents): (Vec<usize>, Vec<Document>) = documents.into_iter().unzip();
+            let result = self.on(shard)?.insert_many(documents)?;
+            for (position, id) in positions.into_iter().zip(result.inserted_ids) {
+                inserted_ids[position] = id;
+            }
+        }
+        Ok(InsertManyResult { inserted_ids })
+    }
+
+    /// Returns a cursor over the documents matching `filter` on the shards
+    /// that may hold them. Documents come in `_id` order when each shard
+    /// returns them so, as a collection scan does.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotSharded` if the collection is not sharded, or `Query`
+    /// if `filter` is invalid.
+    pub fn find(&self, filter: &Document) -> Result<ShardedCursor, DatabaseError> {
+        let _operation = self.router.operation();
+        let cursors = self
+            .targets(filter)?
+            .iter()
+            .map(|collection| collection.find(filter))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(ShardedCursor::new(cursors))
+    }
+
+    /// Returns the first document matching `filter` on the shards that may
+    /// hold it, taken in shard order.
+    pub fn find_one(&self, filter: &Document) -> Result<Option<Document>, DatabaseError> {
+        let _operation = self.router.operation();
+        for collection in self.targets(filter)? {
+            if let Some(document) = collection.find_one(filter)? {
+                return Ok(Some(document));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the number of documents matching `filter` across the shards.
+    pub fn count(&self, filter: &Document) -> Result<u64, DatabaseError> {
+        let _operation = self.router.operation();
+        let mut count = 0;
+        for collection in self.targets(filter)? {
+            count += collection.count(filter)?;
+        }
+        Ok(count)
+    }
+
+    /// Applies `update` to the first document matching `filter`, on the
+    /// first shard in order holding one.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidUpdate` if `update` changes a path of the shard
+    /// key, which would leave the document on the wrong shard, or the
+    /// errors of `Collection::update_one`.
+    pub fn update_one(
+        &self,
+        filter: &Document,
+        update: &Document,
+    ) -> Result<UpdateResult, DatabaseError> {
+        self.update(filter, update, true)
+    }
+
+    /// Applies `update` to every document matching `filter`, in one write
+    /// per shard. See `update_one`.
+    pub fn update_many(
+        &self,
+        filter: &Document,
+        update: &Document,
+    ) -> Result<UpdateResult, DatabaseError> {
+        self.update(filter, update, false)
+    }
+
+    /// Deletes the first document matching `filter`, on the first shard in
+    /// order holding one.
+    pub fn delete_one(&self, filter: &Document) -> Result<DeleteResult, DatabaseError> {
+        let _operation = self.router.operation();
+        for collection in self.targets(filter)? {
+            let result = collection.delete_one(filter)?;
+            if result.deleted_count > 0 {
+                return Ok(result);
+            }
+        }
+        Ok(DeleteResult { deleted_count: 0 })
+    }
+
+    /// Deletes every document matching `filter`, in one write per shard.
+    pub fn delete_many(&self, filter: &Document) -> Result<DeleteResult, DatabaseError> {
+        let _operation = self.router.operation();
+        let mut deleted_count = 0;
+        for collection in self.targets(filter)? {
+            deleted_count += collection.delete_many(filter)?.deleted_count;
+        }
+        Ok(DeleteResult { deleted_count })
+    }
+
+    fn update(
+        &self,
+        filter: &Document,
+        update: &Document,
+        one: bool,
+    ) -> Result<UpdateResult, DatabaseError> {
+        let _operation = self.router.operation();
+        let layout = self.router.layout(&self.name)?;
+        if layout.key.touched_by(update) {
+            return Err(DatabaseError::InvalidUpdate(
+                "cannot change the shard key".to_string(),
+            ));
+        }
+        let mut total = UpdateResult {
+            matched_count: 0,
+            modified_count: 0,
+            upserted_id: None,
+        };
+        for collection in self.targets(filter)? {
+            let result = match one {
+                true => collection.update_one(filter, update)?,
+                false => collection.update_many(filter, update)?,
+            };
+            total.matched_count += result.matched_count;
+            total.modified_count += result.modified_count;
+            if one && result.matched_count > 0 {
+                break;
+            }
+        }
+        Ok(total)
+    }
+
+    /// Returns the collection on each shard that may hold documents
+    /// matching `filter`.
+    fn targets(&self, filter: &Document) -> Result<Vec<Collection>, DatabaseError> {
+        let layout = self.router.layout(&self.name)?;
+        let shards: BTreeSet<&str> = match layout.key.target(filter) {
+            Some(hash) => BTreeSet::from([layout.shard_of(hash)]),
+            None => layout.chunks.values().map(String::as_str).collect(),
+        };
+        shards.into_iter().map(|shard| self.on(shard)).collect()
+    }
+
+    fn on(&self, shard: &str) -> Result<Collection, DatabaseError> {
+        Ok(self.router.shard(shard)?.collection(&self.name))
+    }
+}
+
+/// The results of a query on a sharded collection, merged from a cursor
+/// on each shard queried by `_id`.
+///
+/// The shards are chosen when the query starts, so documents of a chunk
+/// moved while the cursor is read may be missed.
+#[derive(Debug)]
+pub struct ShardedCursor {
+    cursors: Vec<Cursor>,
+    /// The next document of each cursor with its encoded `_id`, once read.
+    heads: Vec<Option<(Vec<u8>, Document)>>,
+    started: bool,
+}
+
+impl ShardedCursor {
+    fn new(cursors: Vec<Cursor>) -> Self {
+        let heads = cursors.iter().map(|_| None).collect();
+        ShardedCursor {
+            cursors,
+            heads,
+            started: false,
+        }
+    }
+
+    /// Collects the remaining documents, stopping at the first error.
+    pub fn try_collect(self) -> Result<Vec<Document>, DatabaseError> {
+        self.collect()
+    }
+
+    /// Reads the next document of cursor `i` into its head.
+    fn advance(&mut self, i: usize) -> Result<(), DatabaseError> {
+        self.heads[i] = match self.cursors[i].next().transpose()? {
+            Some(document) => {
+                let id = document.get("_id").map(encode_key).unwrap_or_default();
+                Some((id, document))
+            }
+            None => None,
+        };
+        Ok(())
+    }
+}
+
+impl Iterator for ShardedCursor {
+    type Item = Result<Document, DatabaseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.started {
+            self.started = true;
+            for i in 0..self.cursors.len() {
+                if let Err(e) = self.advance(i) {
+                    return Some(Err(e));
+                }
+            }
+        }
+        let next = self
+            .heads
+            .iter()
+            .enumerate()
+            .filter_map(|(i, head)| head.as_ref().map(|(id, _)| (i, id)))
+            .min_by(|(_, a), (_, b)| a.cmp(b))
+            .map(|(i, _)| i)?;
+        let (_, document) = self.heads[next].take()?;
+        match self.advance(next) {
+            Ok(()) => Some(Ok(document)),
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Returns the shard key hash of `values`.
+fn hash_values<'a>(values: impl IntoIterator<Item = &'a Value>) -> u64 {
+    let mut bytes = Vec::new();
+    for value in values {
+        bytes.extend(encode_key(value));
+    }
+    hash64(&bytes)
+}
+
+/// Returns whether `document` is a set of query operators, such as
+/// `{"$in": [...]}`, rather than a value to match whole.
+fn is_operators(document: &Document) -> bool {
+    document.iter().any(|(key, _)| key.starts_with('$'))
+}
+
+fn id_filter(id: Value) -> Document {
+    let mut filter = Document::new();
+    filter.insert("_id", id);
+    filter
+}
+
+fn layout_write(name: &str, layout: &Layout) -> Result<Write, DatabaseError> {
+    Ok(Write::Put {
+        namespace: SHARDING_NAMESPACE.to_string(),
+        key: name.as_bytes().to_vec(),
+        value: to_bytes(&layout.to_document())?,
+    })
+}
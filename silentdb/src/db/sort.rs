@@ -0,0 +1,315 @@
+// src/db/sort.rs
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write as _};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use silentdb_data_encoding::{to_bytes, to_writer, Document, DocumentFileIterator, Value};
+
+use super::error::DatabaseError;
+use super::index::{complement, SortOrder};
+use super::path::get_path;
+use super::projection::Projection;
+use crate::accounting::{MemoryCategory, MemoryScope};
+use crate::query::QueryError;
+use crate::storage::{encode_key, StorageError};
+
+/// Bytes of documents a sort holds in memory before spilling them to disk,
+/// unless told otherwise.
+pub(crate) const DEFAULT_SORT_MEMORY: usize = 32 * 1024 * 1024;
+
+/// Numbers the spill files of this process, to keep their names apart.
+static NEXT_RUN: AtomicU64 = AtomicU64::new(0);
+
+/// The order a sort document such as `{"age": -1, "name": 1}` asks for:
+/// by each path in turn, `1` for ascending and `-1` for descending.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SortSpec {
+    fields: Vec<(String, SortOrder)>,
+}
+
+impl SortSpec {
+    /// Parses `sort`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Query` if `sort` is empty, or a path is empty, names an
+    /// operator or is set to something other than `1` or `-1`.
+    pub(crate) fn new(sort: &Document) -> Result<Self, DatabaseError> {
+        let invalid =
+            |message: String| -> DatabaseError { QueryError::InvalidSort(message).into() };
+        if sort.is_empty() {
+            return Err(invalid("no field to sort by".to_string()));
+        }
+        let mut fields = Vec::new();
+        for (path, value) in sort.iter() {
+            if path
+                .split('.')
+                .any(|segment| segment.is_empty() || segment.starts_with('$'))
+            {
+                return Err(invalid(format!("bad path {:?}", path)));
+            }
+            let order = match value {
+                Value::Int32(1) | Value::Int64(1) => SortOrder::Ascending,
+                Value::Int32(-1) | Value::Int64(-1) => SortOrder::Descending,
+                Value::Double(n) if *n == 1.0 => SortOrder::Ascending,
+                Value::Double(n) if *n == -1.0 => SortOrder::Descending,
+                _ => return Err(invalid(format!("{} must be 1 or -1", path))),
+            };
+            fields.push((path.to_string(), order));
+        }
+        Ok(SortSpec { fields })
+    }
+
+    /// Returns the key `document` sorts by: the key encodings of its
+    /// values at each path, complemented for descending paths, so keys
+    /// compare byte by byte in the order asked for.
+    ///
+    /// A missing path sorts as null. An array sorts by its least element
+    /// ascending and by its greatest descending, and an empty one as
+    /// itself, before any other.
+    fn key(&self, document: &Document) -> Vec<u8> {
+        let mut key = Vec::new();
+        for (path, order) in &self.fields {
+            let value = get_path(document, path).unwrap_or(&Value::Null);
+            let mut encoded = match value {
+                Value::Array(elements) if !elements.is_empty() => {
+                    let encoded = elements.iter().map(encode_key);
+                    let chosen = match order {
+                        SortOrder::Ascending => encoded.min(),
+                        SortOrder::Descending => encoded.max(),
+                    };
+                    chosen.expect("array is not empty")
+                }
+                value => encode_key(value),
+            };
+            if *order == SortOrder::Descending {
+                encoded = complement(&encoded);
+            }
+            key.extend(encoded);
+        }
+        key
+    }
+}
+
+/// An external merge sort of a query's results.
+///
+/// Documents are buffered until they outgrow the memory budget, then
+/// sorted and written to a temporary file as a run, in the document
+/// sequence format. Once every document is in, the runs are merged,
+/// reading one document of each at a time. Documents with equal keys
+/// keep the order they came in.
+///
+/// Documents are projected as they come in, after their key is taken, so
+/// a sort may be by fields the query does not return.
+#[derive(Debug)]
+pub(crate) struct ExternalSort {
+    spec: SortSpec,
+    projection: Option<Projection>,
+    budget: usize,
+    buffer: Vec<(Vec<u8>, Document)>,
+    /// The encoded size of the buffered documents and their keys.
+    buffered: usize,
+    runs: Vec<Run>,
+}
+
+impl ExternalSort {
+    /// Creates a sort by `spec` holding at most about `budget` bytes of
+    /// documents in memory, and returning only the fields `projection`
+    /// selects of each.
+    pub(crate) fn new(spec: SortSpec, projection: Option<Projection>, budget: usize) -> Self {
+        ExternalSort {
+            spec,
+            projection,
+            budget,
+            buffer: Vec::new(),
+            buffered: 0,
+            runs: Vec::new(),
+        }
+    }
+
+    /// Returns the number of runs spilled to disk so far.
+    #[cfg(test)]
+    pub(crate) fn runs(&self) -> usize {
+        self.runs.len()
+    }
+
+    /// Adds `document` to the sort, spilling the buffer to a run if it
+    /// grows past the budget.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a run cannot be written.
+    pub(crate) fn push(&mut self, document: Document) -> Result<(), DatabaseError> {
+        let _scope = MemoryScope::enter(MemoryCategory::Sort);
+        let key = self.spec.key(&document);
+        let bytes = to_bytes(&document)?;
+        let document = match &self.projection {
+            Some(projection) => projection.apply(&bytes)?,
+            None => document,
+        };
+        self.buffered += bytes.len() + key.len();
+        self.buffer.push((key, document));
+        if self.buffered > self.budget {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Returns the documents in sorted order.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the last run cannot be written or a run cannot
+    /// be opened.
+    pub(crate) fn finish(mut self) -> Result<Sorted, DatabaseError> {
+        let _scope = MemoryScope::enter(MemoryCategory::Sort);
+        if self.runs.is_empty() {
+            self.buffer.sort_by(|(a, _), (b, _)| a.cmp(b));
+            return Ok(Sorted::Memory(std::mem::take(&mut self.buffer).into_iter()));
+        }
+        if !self.buffer.is_empty() {
+            self.spill()?;
+        }
+        let mut merge = Merge {
+            readers: Vec::new(),
+            heads: Vec::new(),
+            heap: BinaryHeap::new(),
+            runs: std::mem::take(&mut self.runs),
+        };
+        for i in 0..merge.runs.len() {
+            let file = File::open(&merge.runs[i].path).map_err(StorageError::from)?;
+            merge
+                .readers
+                .push(DocumentFileIterator::new(BufReader::new(file)));
+            merge.heads.push(None);
+            merge.advance(i)?;
+        }
+        Ok(Sorted::Merge(merge))
+    }
+
+    /// Sorts the buffer and writes it to a new run as `{k, d}` documents
+    /// holding each key and document.
+    fn spill(&mut self) -> Result<(), DatabaseError> {
+        // Stable, so equal keys keep their order within the run
+        self.buffer.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let run = Run::new();
+        let file = File::create(&run.path).map_err(StorageError::from)?;
+        let mut out = BufWriter::new(file);
+        for (key, document) in self.buffer.drain(..) {
+            let mut entry = Document::new_with_capacity(2);
+            entry.insert("k", key);
+            entry.insert("d", document);
+            to_writer(&mut out, &entry)?;
+        }
+        out.flush().map_err(StorageError::from)?;
+        self.runs.push(run);
+        self.buffered = 0;
+        Ok(())
+    }
+}
+
+/// A sorted run on disk, removed when dropped.
+#[derive(Debug)]
+struct Run {
+    path: PathBuf,
+}
+
+impl Run {
+    /// Names a new run in the system's temporary directory.
+    fn new() -> Run {
+        let name = format!(
+            "silentdb-sort-{}-{}.bson",
+            std::process::id(),
+            NEXT_RUN.fetch_add(1, Ordering::Relaxed)
+        );
+        Run {
+            path: std::env::temp_dir().join(name),
+        }
+    }
+}
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// The documents of an `ExternalSort`, in order.
+#[derive(Debug)]
+pub(crate) enum Sorted {
+    /// Everything fit in memory.
+    Memory(std::vec::IntoIter<(Vec<u8>, Document)>),
+    /// Runs were spilled, and are merged.
+    Merge(Merge),
+}
+
+impl Iterator for Sorted {
+    type Item = Result<Document, DatabaseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Sorted::Memory(documents) => documents.next().map(|(_, document)| Ok(document)),
+            Sorted::Merge(merge) => merge.next(),
+        }
+    }
+}
+
+/// A k-way merge of sorted runs.
+pub(crate) struct Merge {
+    readers: Vec<DocumentFileIterator<BufReader<File>>>,
+    /// The next document of each run, once read.
+    heads: Vec<Option<Document>>,
+    /// The key of each run's head and the run, least first; ties go to the
+    /// earlier run, which holds the earlier documents.
+    heap: BinaryHeap<Reverse<(Vec<u8>, usize)>>,
+    /// Kept for their files, which go with them.
+    runs: Vec<Run>,
+}
+
+impl Merge {
+    /// Reads the next entry of run `i` into its head.
+    fn advance(&mut self, i: usize) -> Result<(), DatabaseError> {
+        let Some(entry) = self.readers[i].next() else {
+            return Ok(());
+        };
+        let mut entry = entry?;
+        let bad = || StorageError::corrupt(self.runs[i].path.display(), "bad sort entry");
+        let (Some(Value::Binary(key)), Some(Value::Document(document))) =
+            (entry.remove("k"), entry.remove("d"))
+        else {
+            return Err(bad().into());
+        };
+        self.heads[i] = Some(document);
+        self.heap.push(Reverse((key, i)));
+        Ok(())
+    }
+}
+
+impl Iterator for Merge {
+    type Item = Result<Document, DatabaseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse((_, i)) = self.heap.pop()?;
+        let document = self.heads[i].take()?;
+        match self.advance(i) {
+            Ok(()) => Some(Ok(document)),
+            Err(e) => {
+                self.heap.clear();
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Merge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Merge")
+            .field("runs", &self.runs)
+            .finish_non_exhaustive()
+    }
+}
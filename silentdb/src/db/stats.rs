@@ -0,0 +1,217 @@
+// src/db/stats.rs
+
+use super::database::DatabaseInner;
+use super::error::DatabaseError;
+use super::index::{complement, IndexInfo, SortOrder};
+use super::planner::Plan;
+use crate::storage::{decode_key, KeyRange};
+
+/// Entries read per scan while gathering statistics.
+const SCAN_BATCH: usize = 256;
+/// Bits of each hash choosing a HyperLogLog register, for 4096 registers
+/// and a standard error of about 1.6%.
+const PRECISION: u32 = 12;
+
+/// Statistics about a collection and its indexes, as returned by
+/// `Collection::stats`.
+///
+/// Sizes count the bytes of keys and encoded documents the collection
+/// stores, before any overhead of the storage engine.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CollectionStats {
+    /// The number of documents.
+    pub count: u64,
+    /// The average size of a document's encoding, in bytes.
+    pub avg_size: u64,
+    /// The size of the largest document's encoding, in bytes.
+    pub max_size: u64,
+    /// The bytes the documents take, keys included.
+    pub storage_size: u64,
+    /// Statistics for each secondary index.
+    pub indexes: Vec<IndexStats>,
+}
+
+impl CollectionStats {
+    /// Returns the statistics of the index `name`, if it exists.
+    pub fn index(&self, name: &str) -> Option<&IndexStats> {
+        self.indexes.iter().find(|index| index.name == name)
+    }
+
+    /// Estimates how many documents `plan` reads, where `fixed` is how many
+    /// leading fields of an index scan's range are fixed to one value, or
+    /// returns `None` if the statistics cannot tell.
+    pub(crate) fn estimate(&self, plan: &Plan, fixed: usize) -> Option<u64> {
+        match plan {
+            Plan::Primary(range) if *range == KeyRange::all() => Some(self.count),
+            Plan::Index { index, range } if *range == KeyRange::all() => {
+                Some(self.index(&index.name)?.entries)
+            }
+            Plan::Index { index, .. } if fixed > 0 => {
+                let stats = self.index(&index.name)?;
+                if stats.cardinality.len() != index.fields.len() {
+                    return None;
+                }
+                let distinct = stats.cardinality[fixed.min(index.fields.len()) - 1];
+                Some(stats.entries.div_ceil(distinct.max(1)))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Statistics about a secondary index.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IndexStats {
+    /// The index's name.
+    pub name: String,
+    /// The number of entries, which may be more than one per document for
+    /// arrays, or fewer for sparse and partial indexes.
+    pub entries: u64,
+    /// The bytes the entries take.
+    pub size: u64,
+    /// The estimated number of distinct values of the index's first field,
+    /// of its first two fields, and so on. Empty for text and geo indexes.
+    pub cardinality: Vec<u64>,
+}
+
+/// Gathers the statistics of `collection` and its `indexes`, as seen by
+/// transaction `txn` if any.
+pub(crate) fn gather(
+    inner: &DatabaseInner,
+    txn: Option<u64>,
+    collection: &str,
+    indexes: &[IndexInfo],
+) -> Result<CollectionStats, DatabaseError> {
+    let mut stats = CollectionStats::default();
+    for_each_entry(inner, txn, collection, |key, value| {
+        stats.count += 1;
+        stats.max_size = stats.max_size.max(value.len() as u64);
+        stats.storage_size += (key.len() + value.len()) as u64;
+        stats.avg_size += value.len() as u64;
+    })?;
+    stats.avg_size = stats.avg_size.checked_div(stats.count).unwrap_or(0);
+
+    for index in indexes {
+        let counted = index.text.is_none() && !index.geo;
+        let mut sketches = vec![HyperLogLog::new(); if counted { index.fields.len() } else { 0 }];
+        let mut index_stats = IndexStats {
+            name: index.name.clone(),
+            ..IndexStats::default()
+        };
+        for_each_entry(inner, txn, &index.namespace(collection), |key, value| {
+            index_stats.entries += 1;
+            index_stats.size += (key.len() + value.len()) as u64;
+            // Entries of other indexes end with the primary key they hold
+            if counted && key.ends_with(value) {
+                let values = &key[..key.len() - value.len()];
+                for (sketch, end) in sketches.iter_mut().zip(field_ends(index, values)) {
+                    sketch.add(&values[..end]);
+                }
+            }
+        })?;
+        index_stats.cardinality = sketches.iter().map(HyperLogLog::estimate).collect();
+        stats.indexes.push(index_stats);
+    }
+    Ok(stats)
+}
+
+/// Calls `f` with every entry of `namespace`, a batch at a time.
+fn for_each_entry(
+    inner: &DatabaseInner,
+    txn: Option<u64>,
+    namespace: &str,
+    mut f: impl FnMut(&[u8], &[u8]),
+) -> Result<(), DatabaseError> {
+    let mut range = KeyRange::all();
+    loop {
+        let batch = inner.scan(txn, namespace, &range, SCAN_BATCH)?;
+        for (key, value) in &batch {
+            f(key, value);
+        }
+        match batch.last() {
+            Some((key, _)) if batch.len() == SCAN_BATCH => range = range.after(key),
+            _ => return Ok(()),
+        }
+    }
+}
+
+/// Returns where the encoding of each of `index`'s fields ends in the
+/// indexed values of an entry, stopping at any that cannot be decoded.
+fn field_ends(index: &IndexInfo, values: &[u8]) -> Vec<usize> {
+    let mut ends = Vec::with_capacity(index.fields.len());
+    let mut end = 0;
+    for (_, order) in &index.fields {
+        let decoded = match order {
+            SortOrder::Ascending => decode_key(&values[end..]),
+            SortOrder::Descending => decode_key(&complement(&values[end..])),
+        };
+        let Ok((_, len)) = decoded else {
+            break;
+        };
+        end += len;
+        ends.push(end);
+    }
+    ends
+}
+
+/// A HyperLogLog sketch, estimating how many distinct byte strings it has
+/// been given in a few kilobytes.
+///
+/// Each string is hashed; the first bits of the hash pick a register, which
+/// keeps the longest run of leading zeros seen in the rest. Long runs are
+/// rare, so the runs across registers tell how many distinct hashes there
+/// were.
+#[derive(Debug, Clone)]
+pub(crate) struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    pub(crate) fn new() -> Self {
+        HyperLogLog {
+            registers: vec![0; 1 << PRECISION],
+        }
+    }
+
+    pub(crate) fn add(&mut self, bytes: &[u8]) {
+        let hash = hash(bytes);
+        let register = (hash >> (64 - PRECISION)) as usize;
+        let run = ((hash << PRECISION) | (1 << (PRECISION - 1))).leading_zeros() as u8 + 1;
+        self.registers[register] = self.registers[register].max(run);
+    }
+
+    pub(crate) fn estimate(&self) -> u64 {
+        let m = self.registers.len() as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|&run| 2f64.powi(-i32::from(run)))
+            .sum();
+        let raw = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|&&run| run == 0).count();
+        // Small counts leave registers empty, and are better estimated
+        // from how many are
+        let estimate = if raw <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            raw
+        };
+        estimate.round() as u64
+    }
+}
+
+/// Hashes `bytes` with 64-bit FNV-1a, then mixes the result so every bit
+/// depends on every input byte.
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in bytes {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash ^= hash >> 30;
+    hash = hash.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash ^= hash >> 27;
+    hash = hash.wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
@@ -0,0 +1,3757 @@
+// src/db/test.rs
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use silentdb_data_encoding::{
+        Array, Document, FromValue, IntoValue, Value, ValueConversionError,
+    };
+
+    use crate::db::planner::{plan, Plan};
+    use crate::db::sort::{ExternalSort, SortSpec};
+    use crate::db::{
+        ChangeKind, CheckIssue, CheckLevel, CollectionOptions, CompactionOptions, CountOptions,
+        Cursor, Database, DatabaseError, DeleteResult, ErrorPolicy, Field, Filter,
+        FindOneAndModifyOptions, FindOptions, Format, ImportOptions, IndexInfo, IndexOptions,
+        LockMode, MaintenanceTask, RaftConfig, RaftNode, RaftRole, ReadConcern, RecoveryTarget,
+        ResumeToken, ReturnDocument, Role, Router, SchemaMigrationOptions, SchemaMigrationStats,
+        Secondary, ShardKey, SortOrder, UpdateOptions, UpdateResult, ValidationAction,
+        ValidationLevel, ValidationStats, Validator, WriteConcern,
+    };
+    use crate::query::{Collation, QueryError};
+    use crate::storage::{
+        encode_key, BTreeEngine, DirArchive, Encryption, KeyRange, KeyRing, LsmEngine, LsmOptions,
+        MemoryEngine, StorageEngine, StorageError, Wal, WalOptions,
+    };
+
+    /// Returns an empty scratch directory unique to this process and `name`.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("silentdb-db-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn user(id: i32, name: &str, age: i32) -> Document {
+        let mut document = Document::new();
+        document.insert("_id", id);
+        document.insert("name", name);
+        document.insert("age", age);
+        document
+    }
+
+    fn doc(key: &str, value: impl Into<Value>) -> Document {
+        let mut document = Document::new();
+        document.insert(key, value);
+        document
+    }
+
+    fn ids(documents: &[Document]) -> Vec<Value> {
+        documents
+            .iter()
+            .map(|document| document.get("_id").unwrap().clone())
+            .collect()
+    }
+
+    // -------------------------------------
+    //          Collection CRUD Tests
+    // -------------------------------------
+
+    #[test]
+    fn test_insert_and_find() {
+        let db = Database::open(scratch_dir("insert-find")).unwrap();
+        let users = db.collection("users");
+        let result = users.insert_one(user(2, "bob", 30)).unwrap();
+        assert_eq!(result.inserted_id, Value::Int32(2));
+        let result = users
+            .insert_many([user(1, "alice", 30), user(3, "carol", 40)])
+            .unwrap();
+        assert_eq!(result.inserted_ids, vec![Value::Int32(1), Value::Int32(3)]);
+
+        let all = users.find(&Document::new()).unwrap().try_collect().unwrap();
+        assert_eq!(
+            ids(&all),
+            vec![Value::Int32(1), Value::Int32(2), Value::Int32(3)]
+        );
+        assert_eq!(all[0], user(1, "alice", 30));
+
+        let thirty = users
+            .find(&doc("age", 30.0))
+            .unwrap()
+            .try_collect()
+            .unwrap();
+        assert_eq!(ids(&thirty), vec![Value::Int32(1), Value::Int32(2)]);
+        assert_eq!(
+            users.find_one(&doc("_id", 3i64)).unwrap(),
+            Some(user(3, "carol", 40))
+        );
+        assert_eq!(users.find_one(&doc("name", "dave")).unwrap(), None);
+        assert_eq!(users.count(&doc("age", 30)).unwrap(), 2);
+        assert_eq!(db.collection("other").count(&Document::new()).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_insert_generates_ids() {
+        let db = Database::open(scratch_dir("generated-id")).unwrap();
+        let users = db.collection("users");
+        let result = users
+            .insert_many([
+                doc("name", "alice"),
+                user(7, "bob", 30),
+                doc("name", "carol"),
+            ])
+            .unwrap();
+        let ids = result.inserted_ids;
+        assert!(matches!(ids[0], Value::ObjectId(_)));
+        assert_eq!(ids[1], Value::Int32(7));
+        assert!(matches!(ids[2], Value::ObjectId(_)));
+        assert_ne!(ids[0], ids[2]);
+
+        // Generated ids sort in the order they were generated
+        let stored = users.find(&Document::new()).unwrap().try_collect().unwrap();
+        assert_eq!(stored.len(), 3);
+        assert_eq!(stored[1].get("_id"), Some(&ids[0]));
+        assert_eq!(stored[2].get("_id"), Some(&ids[2]));
+        assert_eq!(stored[2].get("name"), Some(&Value::from("carol")));
+
+        let id = users.insert_one(doc("name", "dave")).unwrap().inserted_id;
+        assert_eq!(
+            users
+                .find_one(&doc("_id", id))
+                .unwrap()
+                .unwrap()
+                .get("name"),
+            Some(&Value::from("dave"))
+        );
+    }
+
+    #[test]
+    fn test_duplicate_ids_are_rejected() {
+        let db = Database::open(scratch_dir("duplicate-id")).unwrap();
+        let users = db.collection("users");
+        users.insert_one(user(1, "alice", 30)).unwrap();
+
+        let result = users.insert_one(user(1, "impostor", 99));
+        assert!(matches!(
+            result,
+            Err(DatabaseError::DuplicateKey(Value::Int32(1)))
+        ));
+        // Equal ids of different numeric types are the same key
+        let result = users.insert_many([user(2, "bob", 30), doc("_id", 1.0)]);
+        assert!(matches!(result, Err(DatabaseError::DuplicateKey(_))));
+        let result = users.insert_many([user(3, "carol", 40), user(3, "carol", 40)]);
+        assert!(matches!(
+            result,
+            Err(DatabaseError::DuplicateKey(Value::Int32(3)))
+        ));
+
+        assert_eq!(
+            users.find(&Document::new()).unwrap().try_collect().unwrap(),
+            vec![user(1, "alice", 30)]
+        );
+        // The same _id may be used in another collection
+        db.collection("admins")
+            .insert_one(user(1, "alice", 30))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_find_by_nested_path() {
+        let db = Database::open(scratch_dir("nested")).unwrap();
+        let places = db.collection("places");
+        let mut place = doc("_id", 1);
+        place.insert("address", doc("city", "Oslo"));
+        places.insert_one(place.clone()).unwrap();
+
+        assert_eq!(
+            places
+                .find(&doc("address.city", "Oslo"))
+                .unwrap()
+                .try_collect()
+                .unwrap(),
+            vec![place]
+        );
+        assert!(places
+            .find(&doc("address.city", "Bergen"))
+            .unwrap()
+            .try_collect()
+            .unwrap()
+            .is_empty());
+        assert!(places
+            .find(&doc("address.city.name", "Oslo"))
+            .unwrap()
+            .try_collect()
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_find_with_query_operators() {
+        let db = Database::open(scratch_dir("query")).unwrap();
+        let users = db.collection("users");
+        users
+            .insert_many([
+                user(1, "alice", 30),
+                user(2, "bob", 25),
+                user(3, "carol", 40),
+            ])
+            .unwrap();
+
+        let mut range = doc("$gte", 26);
+        range.insert("$lt", 40);
+        let mut filter = doc("age", range);
+        filter.insert("name", doc("$regex", "^a"));
+        assert_eq!(
+            users.find(&filter).unwrap().try_collect().unwrap(),
+            vec![user(1, "alice", 30)]
+        );
+
+        let ids_in = doc(
+            "$in",
+            Array::from_vec(vec![Value::Int32(2), Value::Int32(3)]),
+        );
+        assert_eq!(users.count(&doc("_id", ids_in)).unwrap(), 2);
+        let result = users
+            .update_many(&doc("age", doc("$lt", 35)), &doc("$inc", doc("age", 1)))
+            .unwrap();
+        assert_eq!(result.modified_count, 2);
+        assert_eq!(
+            users.delete_many(&doc("age", doc("$gt", 30))).unwrap(),
+            DeleteResult { deleted_count: 2 }
+        );
+        assert!(matches!(
+            users.find(&doc("age", doc("$near", 1))),
+            Err(DatabaseError::Query(_))
+        ));
+    }
+
+    #[test]
+    fn test_update_operators() {
+        let db = Database::open(scratch_dir("update")).unwrap();
+        let users = db.collection("users");
+        users
+            .insert_many([
+                user(1, "alice", 30),
+                user(2, "bob", 30),
+                user(3, "carol", 40),
+            ])
+            .unwrap();
+
+        let mut update = doc("$inc", doc("age", 1));
+        update.insert("$set", doc("profile.active", true));
+        let result = users.update_many(&doc("age", 30), &update).unwrap();
+        assert_eq!(
+            result,
+            UpdateResult {
+                matched_count: 2,
+                modified_count: 2,
+                upserted_id: None
+            }
+        );
+        let alice = users.find_one(&doc("_id", 1)).unwrap().unwrap();
+        assert_eq!(alice.get("age"), Some(&Value::Int32(31)));
+        assert_eq!(
+            alice.get("profile"),
+            Some(&Value::Document(doc("active", true)))
+        );
+
+        let result = users
+            .update_one(&doc("_id", 3), &doc("$unset", doc("name", "")))
+            .unwrap();
+        assert_eq!(result.modified_count, 1);
+        assert!(!users
+            .find_one(&doc("_id", 3))
+            .unwrap()
+            .unwrap()
+            .contains_key("name"));
+
+        // Setting a field to its current value matches but does not modify
+        let result = users
+            .update_one(&doc("_id", 3), &doc("$set", doc("age", 40)))
+            .unwrap();
+        assert_eq!(
+            result,
+            UpdateResult {
+                matched_count: 1,
+                modified_count: 0,
+                upserted_id: None
+            }
+        );
+
+        let result = users
+            .update_one(&doc("_id", 1), &doc("$inc", doc("age", 0.5)))
+            .unwrap();
+        assert_eq!(result.modified_count, 1);
+        assert_eq!(
+            users.find_one(&doc("_id", 1)).unwrap().unwrap().get("age"),
+            Some(&Value::Double(31.5))
+        );
+    }
+
+    #[test]
+    fn test_invalid_updates_change_nothing() {
+        let db = Database::open(scratch_dir("bad-update")).unwrap();
+        let users = db.collection("users");
+        users
+            .insert_many([user(1, "alice", 30), user(2, "bob", 30)])
+            .unwrap();
+
+        for update in [
+            Document::new(),
+            doc("$rename", doc("age", "years")),
+            doc("$set", doc("_id", 5)),
+            doc("$inc", doc("name", 1)),
+            doc("age", 31),
+        ] {
+            let result = users.update_many(&Document::new(), &update);
+            assert!(
+                matches!(result, Err(DatabaseError::InvalidUpdate(_))),
+                "{:?}",
+                result
+            );
+        }
+        assert_eq!(users.count(&doc("age", 30)).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_upserts() {
+        let db = Database::open(scratch_dir("upsert")).unwrap();
+        let users = db.collection("users");
+        let upsert = UpdateOptions::new().upsert(true);
+
+        // The new document holds the filter's equality fields and the update
+        let mut filter = doc("name", "alice");
+        filter.insert("age", doc("$gte", 18));
+        filter.insert("profile.city", doc("$eq", "oslo"));
+        let result = users
+            .update_one_with(&filter, &doc("$inc", doc("visits", 1)), &upsert)
+            .unwrap();
+        assert_eq!((result.matched_count, result.modified_count), (0, 0));
+        let Some(Value::ObjectId(_)) = result.upserted_id else {
+            panic!("no generated _id in {:?}", result);
+        };
+        let alice = users.find_one(&doc("name", "alice")).unwrap().unwrap();
+        assert_eq!(alice.get("visits"), Some(&Value::Int32(1)));
+        assert_eq!(alice.get("age"), None);
+        assert_eq!(
+            alice.get("profile"),
+            Some(&Value::Document(doc("city", "oslo")))
+        );
+
+        // A filter that matches updates instead
+        let result = users
+            .update_many_with(
+                &doc("name", "alice"),
+                &doc("$inc", doc("visits", 1)),
+                &upsert,
+            )
+            .unwrap();
+        assert_eq!((result.matched_count, result.upserted_id), (1, None));
+        assert_eq!(users.count(&doc("visits", 2)).unwrap(), 1);
+
+        // Replacements keep the _id the filter fixes
+        let result = users
+            .replace_one_with(&doc("_id", 7), &doc("name", "bob"), &upsert)
+            .unwrap();
+        assert_eq!(result.upserted_id, Some(Value::Int32(7)));
+        assert_eq!(
+            users.find_one(&doc("_id", 7)).unwrap(),
+            Some({
+                let mut bob = doc("_id", 7);
+                bob.insert("name", "bob");
+                bob
+            })
+        );
+        let mut filter = doc("_id", 7);
+        filter.insert("name", "carol");
+        assert!(matches!(
+            users.update_one_with(&filter, &doc("$set", doc("a", 1)), &upsert),
+            Err(DatabaseError::DuplicateKey(_))
+        ));
+        assert_eq!(users.count(&Document::new()).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_find_one_and_modify() {
+        let db = Database::open(scratch_dir("find-and-modify")).unwrap();
+        let counters = db.collection("counters");
+        counters.insert_one(doc("_id", "a")).unwrap();
+        let inc = doc("$inc", doc("n", 1));
+        let counter = |n: i32| {
+            let mut counter = doc("_id", "a");
+            counter.insert("n", n);
+            counter
+        };
+
+        let before = FindOneAndModifyOptions::new();
+        let after = FindOneAndModifyOptions::new().return_document(ReturnDocument::After);
+        assert_eq!(
+            counters
+                .find_one_and_update(&doc("_id", "a"), &inc, &before)
+                .unwrap(),
+            Some(doc("_id", "a"))
+        );
+        assert_eq!(
+            counters
+                .find_one_and_update(&doc("_id", "a"), &inc, &after)
+                .unwrap(),
+            Some(counter(2))
+        );
+        assert_eq!(
+            counters
+                .find_one_and_update(&doc("_id", "b"), &inc, &after)
+                .unwrap(),
+            None
+        );
+
+        // Upserts return nothing from before, and the new document after
+        let upsert = after.clone().upsert(true);
+        let mut b = doc("_id", "b");
+        b.insert("n", 1);
+        assert_eq!(
+            counters
+                .find_one_and_update(&doc("_id", "b"), &inc, &upsert)
+                .unwrap(),
+            Some(b)
+        );
+        assert_eq!(
+            counters
+                .find_one_and_replace(&doc("_id", "c"), &doc("n", 5), &before.upsert(true))
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            counters
+                .find_one_and_replace(&doc("_id", "a"), &doc("n", 9), &after)
+                .unwrap(),
+            Some(counter(9))
+        );
+
+        assert_eq!(
+            counters.find_one_and_delete(&doc("_id", "a")).unwrap(),
+            Some(counter(9))
+        );
+        assert_eq!(
+            counters.find_one_and_delete(&doc("_id", "a")).unwrap(),
+            None
+        );
+        assert_eq!(counters.count(&Document::new()).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_document_versions() {
+        let db = Database::open(scratch_dir("versions")).unwrap();
+        let counters = db.collection("counters");
+        counters.insert_one(doc("_id", "old")).unwrap();
+        counters.set_versioned(true).unwrap();
+        assert!(counters.is_versioned().unwrap());
+        counters.insert_one(doc("_id", "a")).unwrap();
+        let version = |id: &str| {
+            let counter = counters.find_one(&doc("_id", id)).unwrap().unwrap();
+            counter.get("_version").cloned()
+        };
+        assert_eq!(version("a"), Some(Value::Int64(1)));
+        assert_eq!(version("old"), None);
+
+        // Changes bump the version, while writes changing nothing do not
+        counters
+            .update_one(&doc("_id", "a"), &doc("$set", doc("n", 1)))
+            .unwrap();
+        counters
+            .update_one(&doc("_id", "a"), &doc("$set", doc("n", 1)))
+            .unwrap();
+        counters
+            .update_one(&doc("_id", "a"), &doc("$set", doc("_version", 10)))
+            .unwrap();
+        assert_eq!(version("a"), Some(Value::Int64(2)));
+
+        // Conditional replacements check the version first
+        let result = counters
+            .replace_one_if_version(&Value::from("a"), 2, &doc("n", 2))
+            .unwrap();
+        assert_eq!(result.modified_count, 1);
+        assert!(matches!(
+            counters.replace_one_if_version(&Value::from("a"), 2, &doc("n", 3)),
+            Err(DatabaseError::VersionConflict {
+                expected: 2,
+                actual: 3,
+                ..
+            })
+        ));
+        counters
+            .replace_one_if_version(&Value::from("old"), 0, &doc("n", 1))
+            .unwrap();
+        assert_eq!(version("old"), Some(Value::Int64(1)));
+        let result = counters
+            .replace_one_if_version(&Value::from("none"), 1, &doc("n", 1))
+            .unwrap();
+        assert_eq!(result.matched_count, 0);
+
+        // Concurrent writers retrying on conflicts lose no increments
+        let writers: Vec<_> = (0..4)
+            .map(|_| {
+                let counters = counters.clone();
+                thread::spawn(move || {
+                    for _ in 0..25 {
+                        loop {
+                            let counter = counters.find_one(&doc("_id", "a")).unwrap().unwrap();
+                            let Some(Value::Int64(version)) = counter.get("_version") else {
+                                panic!("no version in {:?}", counter);
+                            };
+                            let Some(Value::Int32(n)) = counter.get("n") else {
+                                panic!("no count in {:?}", counter);
+                            };
+                            match counters.replace_one_if_version(
+                                &Value::from("a"),
+                                *version,
+                                &doc("n", n + 1),
+                            ) {
+                                Ok(_) => break,
+                                Err(DatabaseError::VersionConflict { .. }) => continue,
+                                Err(e) => panic!("{}", e),
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        let counter = counters.find_one(&doc("_id", "a")).unwrap().unwrap();
+        assert_eq!(counter.get("n"), Some(&Value::Int32(102)));
+        assert_eq!(counter.get("_version"), Some(&Value::Int64(103)));
+
+        counters.set_versioned(false).unwrap();
+        counters
+            .update_one(&doc("_id", "a"), &doc("$set", doc("n", 0)))
+            .unwrap();
+        assert_eq!(version("a"), Some(Value::Int64(103)));
+    }
+
+    #[test]
+    fn test_schema_migration() {
+        let dir = scratch_dir("schema");
+        let db = Database::open(&dir).unwrap();
+        let users = db.collection("users");
+        users
+            .insert_many([user(1, "alice", 30), user(2, "bob", 40)])
+            .unwrap();
+        let rename = |mut user: Document| {
+            let name = user.remove("name").unwrap_or(Value::Null);
+            user.insert("full_name", name);
+            user
+        };
+        users.register_migration(1, rename).unwrap();
+        assert_eq!(users.schema_version(), 1);
+        for version in [0, 3] {
+            assert!(matches!(
+                users.register_migration(version, |user| user),
+                Err(DatabaseError::InvalidMigration(_))
+            ));
+        }
+
+        // Outdated documents are read upgraded, and new ones are stamped
+        let alice = users.find_one(&doc("full_name", "alice")).unwrap().unwrap();
+        assert_eq!(alice.get("name"), None);
+        assert_eq!(alice.get("_schema"), Some(&Value::Int64(1)));
+        users.insert_one(doc("_id", 3)).unwrap();
+        let carol = users.find_one(&doc("_id", 3)).unwrap().unwrap();
+        assert_eq!(carol.get("_schema"), Some(&Value::Int64(1)));
+
+        // Updates apply to the upgraded document and write it back
+        users
+            .update_one(&doc("_id", 2), &doc("$set", doc("age", 41)))
+            .unwrap();
+        let progress = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&progress);
+        let options = SchemaMigrationOptions::new()
+            .batch_size(1)
+            .progress(move |stats| seen.lock().unwrap().push(stats.scanned));
+        let stats = users.migrate_schema(&options).unwrap();
+        assert_eq!(
+            stats,
+            SchemaMigrationStats {
+                version: 1,
+                scanned: 3,
+                migrated: 1,
+                batches: 1,
+            }
+        );
+        assert_eq!(*progress.lock().unwrap(), vec![1, 2, 3]);
+
+        // Migrations may not change _id, and may be replaced
+        users
+            .register_migration(2, |mut user| {
+                user.insert("_id", 0);
+                user
+            })
+            .unwrap();
+        assert!(matches!(
+            users.find_one(&doc("_id", 1)),
+            Err(DatabaseError::InvalidMigration(_))
+        ));
+        users
+            .register_migration(2, |mut user| {
+                user.insert("active", true);
+                user
+            })
+            .unwrap();
+        let stats = users
+            .migrate_schema(&SchemaMigrationOptions::new())
+            .unwrap();
+        assert_eq!(stats.migrated, 3);
+        assert_eq!(users.count(&doc("active", true)).unwrap(), 3);
+
+        // Rolling back restores each document as it was before it was
+        // first migrated
+        assert_eq!(users.rollback_schema().unwrap(), 3);
+        assert_eq!(users.rollback_schema().unwrap(), 0);
+        drop(users);
+        drop(db);
+        let db = Database::open(&dir).unwrap();
+        let users = db.collection("users");
+        assert_eq!(users.schema_version(), 0);
+        assert_eq!(
+            users.find_one(&doc("_id", 1)).unwrap(),
+            Some(user(1, "alice", 30))
+        );
+        let bob = users.find_one(&doc("_id", 2)).unwrap().unwrap();
+        assert_eq!(bob.get("full_name"), Some(&Value::from("bob")));
+        assert_eq!(bob.get("active"), None);
+    }
+
+    #[test]
+    fn test_delete() {
+        let db = Database::open(scratch_dir("delete")).unwrap();
+        let users = db.collection("users");
+        users
+            .insert_many([
+                user(1, "alice", 30),
+                user(2, "bob", 30),
+                user(3, "carol", 40),
+            ])
+            .unwrap();
+
+        assert_eq!(
+            users.delete_one(&doc("age", 30)).unwrap(),
+            DeleteResult { deleted_count: 1 }
+        );
+        assert_eq!(
+            ids(&users.find(&Document::new()).unwrap().try_collect().unwrap()),
+            vec![Value::Int32(2), Value::Int32(3)]
+        );
+        assert_eq!(
+            users.delete_many(&Document::new()).unwrap().deleted_count,
+            2
+        );
+        assert_eq!(
+            users.delete_many(&Document::new()).unwrap().deleted_count,
+            0
+        );
+    }
+
+    #[test]
+    fn test_reopen_replays_log() {
+        let dir = scratch_dir("reopen");
+        {
+            let db = Database::open(&dir).unwrap();
+            let users = db.collection("users");
+            users
+                .insert_many([user(1, "alice", 30), user(2, "bob", 30)])
+                .unwrap();
+            users.delete_one(&doc("_id", 2)).unwrap();
+        }
+        let db = Database::open(&dir).unwrap();
+        assert_eq!(
+            db.collection("users")
+                .find(&Document::new())
+                .unwrap()
+                .try_collect()
+                .unwrap(),
+            vec![user(1, "alice", 30)]
+        );
+
+        drop(db);
+
+        // The log alone is enough to rebuild the collection on another engine
+        let engine = LsmEngine::open(dir.join("lsm"), LsmOptions::new()).unwrap();
+        let wal = Wal::open(dir.join("wal"), WalOptions::new()).unwrap();
+        let db = Database::with_engine(Box::new(engine), wal).unwrap();
+        assert_eq!(
+            db.collection("users")
+                .find(&Document::new())
+                .unwrap()
+                .try_collect()
+                .unwrap(),
+            vec![user(1, "alice", 30)]
+        );
+        drop(db);
+
+        // ... including one that keeps nothing on disk
+        let wal = Wal::open(dir.join("wal"), WalOptions::new()).unwrap();
+        let db = Database::with_engine(Box::new(MemoryEngine::new()), wal).unwrap();
+        let users = db.collection("users");
+        assert_eq!(users.count(&Document::new()).unwrap(), 1);
+        users.insert_one(user(2, "bob", 25)).unwrap();
+        assert_eq!(users.count(&doc("age", doc("$lt", 40))).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_open_encrypted() {
+        let dir = scratch_dir("encrypted");
+        let keys = KeyRing::new(1, [9; 32]);
+        let secret = "x".repeat(100) + "top secret";
+        {
+            let db = Database::open_encrypted(&dir, Encryption::new(keys.clone())).unwrap();
+            let users = db.collection("users");
+            users.insert_one(user(1, &secret, 30)).unwrap();
+            db.checkpoint().unwrap();
+            users.insert_one(user(2, "bob", 30)).unwrap();
+        }
+        for entry in fs::read_dir(dir.join("data")).unwrap() {
+            let bytes = fs::read(entry.unwrap().path()).unwrap();
+            assert!(!bytes.windows(10).any(|window| window == b"top secret"));
+        }
+
+        let db = Database::open_encrypted(&dir, Encryption::new(keys)).unwrap();
+        let users = db.collection("users");
+        assert_eq!(users.count(&Document::new()).unwrap(), 2);
+        assert_eq!(
+            users.find_one(&doc("_id", 1)).unwrap(),
+            Some(user(1, &secret, 30))
+        );
+        drop((users, db));
+
+        // Without the key the database stays shut
+        assert!(matches!(
+            Database::open(&dir),
+            Err(DatabaseError::Storage(StorageError::Encrypted { .. }))
+        ));
+        let other = Encryption::new(KeyRing::new(2, [9; 32]));
+        assert!(matches!(
+            Database::open_encrypted(&dir, other),
+            Err(DatabaseError::Storage(StorageError::KeyUnavailable {
+                id: 1,
+                ..
+            }))
+        ));
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Person {
+        name: String,
+        age: i32,
+        city: Option<String>,
+    }
+
+    impl Person {
+        const NAME: Field<Person, String> = Field::new("name");
+        const AGE: Field<Person, i32> = Field::new("age");
+        const CITY: Field<Person, Option<String>> = Field::new("city");
+
+        fn new(name: &str, age: i32, city: Option<&str>) -> Self {
+            Person {
+                name: name.to_string(),
+                age,
+                city: city.map(str::to_string),
+            }
+        }
+    }
+
+    impl IntoValue for Person {
+        fn into_value(self) -> Value {
+            let mut document = Document::new();
+            document.insert("name", self.name);
+            document.insert("age", self.age);
+            document.insert("city", self.city.into_value());
+            Value::Document(document)
+        }
+    }
+
+    impl FromValue for Person {
+        fn from_value(value: &Value) -> Result<Self, ValueConversionError> {
+            Self::from_document(&Document::from_value(value)?)
+        }
+
+        fn from_document(document: &Document) -> Result<Self, ValueConversionError> {
+            Ok(Person {
+                name: document.get_as("name")?,
+                age: document.get_as("age")?,
+                city: document.get_as("city")?,
+            })
+        }
+    }
+
+    #[test]
+    fn test_typed_collection() {
+        let db = Database::open(scratch_dir("typed")).unwrap();
+        let people = db.collection("people").typed::<Person>();
+        people
+            .insert_many([
+                Person::new("ann", 30, Some("Oslo")),
+                Person::new("bob", 25, None),
+                Person::new("cat", 41, Some("Lima")),
+            ])
+            .unwrap();
+        people
+            .insert_one(Person::new("dan", 35, Some("Oslo")))
+            .unwrap();
+
+        let names = |filter: &Filter<Person>| {
+            let mut names: Vec<String> = people
+                .find(filter)
+                .unwrap()
+                .map(|person| person.unwrap().name)
+                .collect();
+            names.sort();
+            names
+        };
+        assert_eq!(names(&Filter::new()).len(), 4);
+        assert_eq!(names(&Person::AGE.gte(30)), ["ann", "cat", "dan"]);
+        let filter = Person::AGE
+            .gt(26)
+            .and(Person::CITY.eq(Some("Oslo".into())))
+            .and(Person::NAME.ne("dan".into()));
+        assert_eq!(filter.as_document().len(), 1);
+        assert_eq!(names(&filter), ["ann"]);
+        let filter = Person::CITY
+            .is_in([Some("Lima".into()), None])
+            .or(Person::AGE.lt(26));
+        assert_eq!(names(&filter), ["bob", "cat"]);
+        assert_eq!(names(&Person::CITY.exists(true).or(Filter::new())).len(), 4);
+
+        let result = people
+            .update_many(
+                &Person::CITY.eq(Some("Oslo".into())),
+                &Person::AGE
+                    .inc(1)
+                    .and(Person::CITY.set(Some("Bergen".into()))),
+            )
+            .unwrap();
+        assert_eq!(result.modified_count, 2);
+        assert_eq!(
+            people.find_one(&Person::NAME.eq("ann".into())).unwrap(),
+            Some(Person::new("ann", 31, Some("Bergen")))
+        );
+        people
+            .replace_one(&Person::NAME.eq("bob".into()), Person::new("bob", 26, None))
+            .unwrap();
+        people
+            .update_one(&Person::NAME.eq("cat".into()), &Person::CITY.unset())
+            .unwrap();
+        assert_eq!(
+            people
+                .find(&Person::AGE.lt(30))
+                .unwrap()
+                .try_collect()
+                .unwrap(),
+            [Person::new("bob", 26, None)]
+        );
+        assert_eq!(people.count(&Person::CITY.exists(false)).unwrap(), 1);
+        assert_eq!(
+            people
+                .delete_many(&Person::AGE.lte(31))
+                .unwrap()
+                .deleted_count,
+            2
+        );
+
+        // Documents that do not convert fail as they are read
+        people.untyped().insert_one(doc("name", 7)).unwrap();
+        assert!(matches!(
+            people.find_one(&Person::NAME.eq("cat".into())).unwrap(),
+            Some(person) if person.city.is_none()
+        ));
+        assert!(matches!(
+            people.find(&Person::AGE.exists(false)).unwrap().next(),
+            Some(Err(DatabaseError::Conversion(_)))
+        ));
+    }
+
+    // -------------------------------------
+    //          Cursor Tests
+    // -------------------------------------
+
+    #[test]
+    fn test_cursor_batches() {
+        let db = Database::open(scratch_dir("cursor")).unwrap();
+        let users = db.collection("users");
+        let mut cursor = users
+            .find(&doc("age", doc("$gte", 20)))
+            .unwrap()
+            .batch_size(3);
+        let other = users.find(&Document::new()).unwrap();
+        assert_ne!(cursor.id(), other.id());
+
+        // Nothing is read until the first batch is fetched
+        users
+            .insert_many((1..=10).map(|i| user(i, "user", i * 3)))
+            .unwrap();
+        assert_eq!(cursor.next().unwrap().unwrap(), user(7, "user", 21));
+
+        // Writes after the cursor's position show up in later batches
+        users.insert_one(user(11, "late", 50)).unwrap();
+        users.delete_one(&doc("_id", 10)).unwrap();
+        let rest = cursor.try_collect().unwrap();
+        assert_eq!(
+            ids(&rest),
+            vec![8, 9, 11]
+                .into_iter()
+                .map(Value::Int32)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(other.try_collect().unwrap().len(), 10);
+
+        let none = users.find(&doc("name", "nobody")).unwrap();
+        assert!(none.try_collect().unwrap().is_empty());
+        let one = users.find(&doc("_id", 3)).unwrap().try_collect().unwrap();
+        assert_eq!(one, vec![user(3, "user", 9)]);
+    }
+
+    #[test]
+    fn test_cursor_kill_and_timeout() {
+        let db = Database::open(scratch_dir("cursor-kill")).unwrap();
+        let users = db.collection("users");
+        users
+            .insert_many((1..=5).map(|i| user(i, "user", 30)))
+            .unwrap();
+
+        // A killed cursor still returns the batch it holds, then fails
+        let mut cursor = users.find(&Document::new()).unwrap().batch_size(2);
+        let id = cursor.id();
+        assert!(cursor.next().unwrap().is_ok());
+        assert_eq!(db.kill_cursors(&[id, 999]), 1);
+        assert!(cursor.next().unwrap().is_ok());
+        assert!(matches!(
+            cursor.next(),
+            Some(Err(DatabaseError::CursorNotFound(killed))) if killed == id
+        ));
+        assert!(cursor.next().is_none());
+
+        // Exhausted and dropped cursors are already gone
+        let cursor = users.find(&Document::new()).unwrap();
+        let exhausted = cursor.id();
+        assert_eq!(cursor.try_collect().unwrap().len(), 5);
+        let dropped = users.find(&Document::new()).unwrap().id();
+        assert_eq!(db.kill_cursors(&[exhausted, dropped]), 0);
+
+        db.set_cursor_timeout(Duration::from_millis(1));
+        let mut cursor = users.find(&Document::new()).unwrap().batch_size(2);
+        thread::sleep(Duration::from_millis(20));
+        assert!(matches!(
+            cursor.next(),
+            Some(Err(DatabaseError::CursorNotFound(_)))
+        ));
+        db.set_cursor_timeout(Duration::from_secs(600));
+
+        // Only time spent fetching counts against a cursor's time limit
+        let options = FindOptions::new().max_time(Duration::from_secs(60));
+        let mut cursor = users
+            .find_with(&Document::new(), &options)
+            .unwrap()
+            .batch_size(2);
+        assert!(cursor.next().unwrap().is_ok());
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(cursor.try_collect().unwrap().len(), 4);
+        let options = FindOptions::new().max_time(Duration::from_nanos(1));
+        let cursor = users.find_with(&Document::new(), &options).unwrap();
+        assert!(matches!(
+            cursor.try_collect(),
+            Err(DatabaseError::MaxTimeExpired(_))
+        ));
+
+        // Counts keep to a time limit too
+        let options = CountOptions::new().max_time(Duration::from_secs(60));
+        assert_eq!(users.count_with(&Document::new(), &options).unwrap(), 5);
+        let options = CountOptions::new().max_time(Duration::from_nanos(1));
+        assert!(matches!(
+            users.count_with(&Document::new(), &options),
+            Err(DatabaseError::MaxTimeExpired(_))
+        ));
+    }
+
+    #[test]
+    fn test_cursor_read_concerns() {
+        let db = Database::open(scratch_dir("read-concern")).unwrap();
+        let users = db.collection("users");
+        users
+            .insert_many((1..=5).map(|i| user(i, "user", 30)))
+            .unwrap();
+        let snapshot = users.clone().read_concern(ReadConcern::Snapshot);
+        let mut local = users.find(&Document::new()).unwrap().batch_size(2);
+        let mut cursor = snapshot.find(&Document::new()).unwrap().batch_size(2);
+        assert_eq!(local.next().unwrap().unwrap(), user(1, "user", 30));
+        assert_eq!(cursor.next().unwrap().unwrap(), user(1, "user", 30));
+
+        // Later batches of a snapshot cursor miss writes made since it
+        // was opened
+        users.insert_one(user(6, "late", 40)).unwrap();
+        users.delete_one(&doc("_id", 5)).unwrap();
+        users
+            .update_one(&doc("_id", 4), &doc("$set", doc("age", 31)))
+            .unwrap();
+        let ids_of = |cursor: Cursor| ids(&cursor.try_collect().unwrap());
+        assert_eq!(
+            ids_of(local),
+            (2..=6)
+                .filter(|&i| i != 5)
+                .map(Value::Int32)
+                .collect::<Vec<_>>()
+        );
+        let rest = cursor.try_collect().unwrap();
+        assert_eq!(rest.len(), 4);
+        assert_eq!(rest[2], user(4, "user", 30));
+        assert_eq!(ids(&rest).last(), Some(&Value::Int32(5)));
+
+        // Killed snapshot cursors end their snapshot too
+        let mut killed = snapshot.find(&Document::new()).unwrap().batch_size(1);
+        assert!(killed.next().unwrap().is_ok());
+        assert_eq!(db.kill_cursors(&[killed.id()]), 1);
+        assert_eq!(snapshot.count(&Document::new()).unwrap(), 5);
+    }
+
+    // -------------------------------------
+    //          Transaction Tests
+    // -------------------------------------
+
+    #[test]
+    fn test_transaction_snapshot_isolation() {
+        let dir = scratch_dir("txn-snapshot");
+        let db = Database::open(&dir).unwrap();
+        let users = db.collection("users");
+        users
+            .insert_many([user(1, "alice", 30), user(2, "bob", 30)])
+            .unwrap();
+
+        let txn = db.begin();
+        let in_txn = txn.collection("users");
+        // Commits after the snapshot are invisible to the transaction
+        users.insert_one(user(3, "carol", 40)).unwrap();
+        users.delete_one(&doc("_id", 2)).unwrap();
+        assert_eq!(
+            ids(&in_txn
+                .find(&Document::new())
+                .unwrap()
+                .try_collect()
+                .unwrap()),
+            vec![Value::Int32(1), Value::Int32(2)]
+        );
+        assert_eq!(
+            in_txn.find_one(&doc("_id", 2)).unwrap(),
+            Some(user(2, "bob", 30))
+        );
+
+        // The transaction sees its own writes, and no one else does
+        in_txn.insert_one(user(4, "dave", 20)).unwrap();
+        in_txn
+            .update_one(&doc("_id", 1), &doc("$set", doc("age", 31)))
+            .unwrap();
+        assert_eq!(in_txn.count(&doc("age", doc("$lt", 35))).unwrap(), 3);
+        assert_eq!(users.count(&doc("age", doc("$lt", 35))).unwrap(), 1);
+        txn.commit().unwrap();
+        assert_eq!(users.count(&doc("age", doc("$lt", 35))).unwrap(), 2);
+        assert!(matches!(
+            in_txn.count(&Document::new()),
+            Err(DatabaseError::NoSuchTransaction(_))
+        ));
+
+        // Aborted writes are discarded
+        let txn = db.begin();
+        txn.collection("users")
+            .delete_many(&Document::new())
+            .unwrap();
+        txn.abort();
+        assert_eq!(users.count(&Document::new()).unwrap(), 3);
+
+        // Committed transactions survive a reopen
+        drop(users);
+        drop(db);
+        let users = Database::open(&dir).unwrap().collection("users");
+        assert_eq!(
+            users.find_one(&doc("_id", 4)).unwrap(),
+            Some(user(4, "dave", 20))
+        );
+        assert_eq!(
+            users.find_one(&doc("_id", 1)).unwrap().unwrap().get("age"),
+            Some(&Value::Int32(31))
+        );
+    }
+
+    #[test]
+    fn test_transaction_write_conflicts() {
+        let db = Database::open(scratch_dir("txn-conflict")).unwrap();
+        let users = db.collection("users");
+        users
+            .insert_many([user(1, "alice", 30), user(2, "bob", 30)])
+            .unwrap();
+        let birthday = doc("$inc", doc("age", 1));
+
+        // The first writer of a document wins
+        let first = db.begin();
+        let second = db.begin();
+        first
+            .collection("users")
+            .update_one(&doc("_id", 1), &birthday)
+            .unwrap();
+        let result = second
+            .collection("users")
+            .update_one(&doc("_id", 1), &birthday);
+        assert!(matches!(result, Err(DatabaseError::WriteConflict(_))));
+        // ... and the loser is aborted
+        assert!(matches!(
+            second.collection("users").count(&Document::new()),
+            Err(DatabaseError::NoSuchTransaction(_))
+        ));
+        assert!(matches!(
+            second.commit(),
+            Err(DatabaseError::NoSuchTransaction(_))
+        ));
+        let result = users.update_one(&doc("_id", 1), &birthday);
+        assert!(matches!(result, Err(DatabaseError::WriteConflict(_))));
+        users.update_one(&doc("_id", 2), &birthday).unwrap();
+        first.commit().unwrap();
+        users.update_one(&doc("_id", 1), &birthday).unwrap();
+
+        // Documents changed since the snapshot cannot be written
+        let txn = db.begin();
+        users.insert_one(user(3, "carol", 40)).unwrap();
+        let result = txn.collection("users").insert_one(user(3, "carol", 41));
+        assert!(matches!(result, Err(DatabaseError::WriteConflict(_))));
+
+        let ages: Vec<_> = users
+            .find(&Document::new())
+            .unwrap()
+            .map(|user| user.unwrap().get("age").cloned().unwrap())
+            .collect();
+        assert_eq!(
+            ages,
+            vec![Value::Int32(32), Value::Int32(31), Value::Int32(40)]
+        );
+    }
+
+    // -------------------------------------
+    //          Index Tests
+    // -------------------------------------
+
+    fn profile(id: i32, email: &str, tags: Vec<Value>) -> Document {
+        let mut document = doc("_id", id);
+        document.insert("user", doc("email", email));
+        document.insert("tags", Array::from_vec(tags));
+        document
+    }
+
+    #[test]
+    fn test_query_planner() {
+        let email = IndexInfo {
+            name: "user.email_1".to_string(),
+            fields: vec![("user.email".to_string(), SortOrder::Ascending)],
+            sparse: false,
+            partial_filter: None,
+            expire_after: None,
+            text: None,
+            geo: false,
+            collation: None,
+            covering: false,
+        };
+        let indexes = [email.clone()];
+        let uses_index = |filter: &Document| matches!(plan(filter, &indexes), Plan::Index { .. });
+
+        assert_eq!(
+            plan(&Document::new(), &indexes),
+            Plan::Primary(KeyRange::all())
+        );
+        assert!(uses_index(&doc("user.email", "a@x")));
+        assert!(uses_index(&doc("user.email", doc("$gt", "a"))));
+        assert!(uses_index(&doc(
+            "$and",
+            Array::from_vec(vec![Value::Document(doc("user.email", "a@x"))])
+        )));
+        assert!(!uses_index(&doc("user.name", "a")));
+        assert!(!uses_index(&doc("user.email", doc("$ne", "a"))));
+        assert!(!uses_index(&doc(
+            "user.email",
+            Value::RegularExpression {
+                pattern: "^a".to_string(),
+                options: String::new(),
+            }
+        )));
+        assert!(!uses_index(&doc(
+            "$or",
+            Array::from_vec(vec![Value::Document(doc("user.email", "a@x"))])
+        )));
+
+        // _id equality wins, then equality on an index, then ranges
+        let mut filter = doc("user.email", "a@x");
+        filter.insert("_id", doc("$gte", 3));
+        assert!(uses_index(&filter));
+        filter.insert("_id", 3);
+        assert!(matches!(plan(&filter, &indexes), Plan::Primary(_)));
+        let mut filter = doc("user.email", doc("$gt", "a"));
+        filter.insert("_id", doc("$gte", 3));
+        assert!(matches!(plan(&filter, &indexes), Plan::Primary(_)));
+
+        // A compound index serves its prefixes, preferring more equalities
+        let compound = IndexInfo {
+            name: "a_1_b_-1".to_string(),
+            fields: vec![
+                ("a".to_string(), SortOrder::Ascending),
+                ("b".to_string(), SortOrder::Descending),
+            ],
+            sparse: false,
+            partial_filter: None,
+            expire_after: None,
+            text: None,
+            geo: false,
+            collation: None,
+            covering: false,
+        };
+        let single = IndexInfo {
+            name: "b_1".to_string(),
+            fields: vec![("b".to_string(), SortOrder::Ascending)],
+            sparse: false,
+            partial_filter: None,
+            expire_after: None,
+            text: None,
+            geo: false,
+            collation: None,
+            covering: false,
+        };
+        let indexes = [single, compound];
+        let index_used = |filter: &Document| match plan(filter, &indexes) {
+            Plan::Index { index, .. } => Some(index.name),
+            _ => None,
+        };
+        assert_eq!(index_used(&doc("a", 1)).as_deref(), Some("a_1_b_-1"));
+        assert_eq!(index_used(&doc("b", 1)).as_deref(), Some("b_1"));
+        let mut filter = doc("a", 1);
+        filter.insert("b", 2);
+        assert_eq!(index_used(&filter).as_deref(), Some("a_1_b_-1"));
+        assert_eq!(index_used(&doc("c", 1)), None);
+
+        // Sparse and partial indexes are only used when no document they
+        // leave out can match
+        let sparse = IndexInfo {
+            name: "c_1".to_string(),
+            fields: vec![("c".to_string(), SortOrder::Ascending)],
+            sparse: true,
+            partial_filter: None,
+            expire_after: None,
+            text: None,
+            geo: false,
+            collation: None,
+            covering: false,
+        };
+        let partial = IndexInfo {
+            name: "d_1".to_string(),
+            fields: vec![("d".to_string(), SortOrder::Ascending)],
+            sparse: false,
+            partial_filter: Some(doc("active", true)),
+            expire_after: None,
+            text: None,
+            geo: false,
+            collation: None,
+            covering: false,
+        };
+        let indexes = [sparse, partial];
+        let uses_index = |filter: &Document| matches!(plan(filter, &indexes), Plan::Index { .. });
+        assert!(uses_index(&doc("c", 1)));
+        assert!(uses_index(&doc("c", doc("$lt", 1))));
+        assert!(!uses_index(&doc("c", Value::Null)));
+        assert!(!uses_index(&doc("c", doc("$lte", Value::Null))));
+        assert!(!uses_index(&doc("d", 1)));
+        let mut filter = doc("d", 1);
+        filter.insert("active", true);
+        assert!(uses_index(&filter));
+        assert!(uses_index(&doc(
+            "$and",
+            Array::from_vec(vec![
+                Value::Document(doc("active", true)),
+                Value::Document(doc("d", doc("$gt", 1))),
+            ])
+        )));
+        filter.insert("active", false);
+        assert!(!uses_index(&filter));
+    }
+
+    #[test]
+    fn test_explain_chooses_by_cost() {
+        let db = Database::open(scratch_dir("explain")).unwrap();
+        let items = db.collection("items");
+        items
+            .insert_many((0..200).map(|i| {
+                let mut item = doc("_id", i);
+                item.insert("a", i % 10);
+                item.insert("b", i % 7);
+                item.insert("active", true);
+                item
+            }))
+            .unwrap();
+        for path in ["a", "b", "active"] {
+            items.create_index(path).unwrap();
+        }
+        let stage = |explain: &Document| match explain.get("plan") {
+            Some(Value::Document(plan)) => plan.get("stage").cloned().unwrap(),
+            plan => panic!("no plan in {:?}", plan),
+        };
+        let count = |explain: &Document, field: &str| explain.get(field).cloned().unwrap();
+
+        let explain = items.explain(&Document::new()).unwrap();
+        assert_eq!(stage(&explain), Value::from("collection_scan"));
+        assert_eq!(count(&explain, "estimated_rows"), Value::Int64(200));
+        assert_eq!(count(&explain, "actual_rows"), Value::Int64(200));
+
+        let explain = items.explain(&doc("_id", 5)).unwrap();
+        assert_eq!(stage(&explain), Value::from("id_scan"));
+        assert_eq!(count(&explain, "examined"), Value::Int64(1));
+
+        // An index matching every document costs more than scanning
+        let explain = items.explain(&doc("active", true)).unwrap();
+        assert_eq!(stage(&explain), Value::from("collection_scan"));
+        let Some(Value::Array(candidates)) = explain.get("candidates") else {
+            panic!("no candidates in {:?}", explain);
+        };
+        assert_eq!(candidates.len(), 2);
+
+        // Two selective predicates are answered by intersecting indexes
+        let mut filter = doc("a", 3);
+        filter.insert("b", 2);
+        let explain = items.explain(&filter).unwrap();
+        assert_eq!(stage(&explain), Value::from("index_intersection"));
+        assert_eq!(count(&explain, "actual_rows"), Value::Int64(3));
+        assert_eq!(count(&explain, "examined"), Value::Int64(3));
+        assert_eq!(
+            ids(&items.find(&filter).unwrap().try_collect().unwrap()),
+            vec![Value::Int32(23), Value::Int32(93), Value::Int32(163)]
+        );
+        filter.insert("active", false);
+        assert_eq!(items.count(&filter).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_distinct_and_covered_queries() {
+        let db = Database::open(scratch_dir("covered")).unwrap();
+        let items = db.collection("items");
+        items
+            .insert_many((0..50).map(|i| {
+                let mut item = doc("_id", i);
+                item.insert("a", i % 5);
+                item.insert("b", format!("b{}", i));
+                item
+            }))
+            .unwrap();
+        items.create_index("a").unwrap();
+        let field = |explain: &Document, field: &str| explain.get(field).cloned().unwrap();
+        let only_a = FindOptions::new().projection({
+            let mut projection = doc("a", 1);
+            projection.insert("_id", 0);
+            projection
+        });
+
+        // A projection of indexed fields is answered from the index alone
+        let filter = doc("a", doc("$gte", 3));
+        let found = items
+            .find_with(&filter, &only_a)
+            .unwrap()
+            .try_collect()
+            .unwrap();
+        assert_eq!(found.len(), 20);
+        assert!(found.iter().all(|item| item.len() == 1));
+        let explain = items.explain_with(&filter, &only_a).unwrap();
+        assert_eq!(field(&explain, "covered"), Value::Boolean(true));
+        assert_eq!(field(&explain, "examined"), Value::Int64(0));
+        assert_eq!(field(&explain, "actual_rows"), Value::Int64(20));
+        let explain = items.explain_with(&Document::new(), &only_a).unwrap();
+        assert_eq!(field(&explain, "covered"), Value::Boolean(true));
+
+        // Reading another field needs the documents
+        let with_b = FindOptions::new().projection(doc("b", true));
+        let found = items
+            .find_with(&doc("a", 4), &with_b)
+            .unwrap()
+            .try_collect()
+            .unwrap();
+        assert_eq!(found[0], {
+            let mut item = doc("_id", 4);
+            item.insert("b", "b4");
+            item
+        });
+        let explain = items.explain_with(&doc("a", 4), &with_b).unwrap();
+        assert_eq!(field(&explain, "covered"), Value::Boolean(false));
+        assert_eq!(field(&explain, "examined"), Value::Int64(10));
+
+        let values: Vec<Value> = (0..5).map(Value::Int32).collect();
+        assert_eq!(items.distinct("a", &Document::new()).unwrap(), values);
+        assert_eq!(
+            items.distinct("a", &doc("b", "b7")).unwrap(),
+            vec![Value::Int32(2)]
+        );
+
+        // An array at the indexed path stops the index covering for good
+        let mut item = doc("_id", 50);
+        item.insert("a", Array::from_vec(vec![Value::Int32(7), Value::Int32(1)]));
+        items.insert_one(item).unwrap();
+        assert!(!items.list_indexes().unwrap()[0].covering);
+        let explain = items.explain_with(&filter, &only_a).unwrap();
+        assert_eq!(field(&explain, "covered"), Value::Boolean(false));
+        let mut values = values;
+        values.push(Value::Int32(7));
+        assert_eq!(items.distinct("a", &Document::new()).unwrap(), values);
+
+        assert!(matches!(
+            items.find_with(&filter, &FindOptions::new().projection(doc("b", 0))),
+            Err(DatabaseError::Query(QueryError::InvalidProjection(_)))
+        ));
+    }
+
+    #[test]
+    fn test_collection_stats() {
+        let db = Database::open(scratch_dir("stats")).unwrap();
+        let items = db.collection("items");
+        items
+            .insert_many((0..3000).map(|i| {
+                let mut item = doc("_id", i);
+                item.insert("kind", if i % 2 == 0 { "a" } else { "b" });
+                item.insert("n", i);
+                item
+            }))
+            .unwrap();
+        items.create_index("kind").unwrap();
+        items.create_index("n").unwrap();
+        let options = IndexOptions::new().ascending("kind").descending("n");
+        let compound = items.create_index_with(options).unwrap();
+
+        let mut filter = doc("kind", "a");
+        filter.insert("n", doc("$gte", 0));
+        let estimates = || -> Vec<(Value, Value)> {
+            let explain = items.explain(&filter).unwrap();
+            let Some(Value::Array(candidates)) = explain.get("candidates") else {
+                panic!("no candidates in {:?}", explain);
+            };
+            candidates
+                .iter()
+                .map(|candidate| {
+                    let Value::Document(candidate) = candidate else {
+                        panic!("bad candidate {:?}", candidate);
+                    };
+                    let Some(Value::Document(plan)) = candidate.get("plan") else {
+                        panic!("no plan in {:?}", candidate);
+                    };
+                    let name = plan.get("index").or(plan.get("stage")).cloned().unwrap();
+                    (name, candidate.get("estimated_rows").cloned().unwrap())
+                })
+                .collect::<Vec<_>>()
+        };
+        let estimate = |estimates: &[(Value, Value)], name: &str| {
+            let found = estimates
+                .iter()
+                .find(|(plan, _)| *plan == Value::from(name));
+            found.map(|(_, estimate)| estimate.clone()).unwrap()
+        };
+        // Too many rows to count, so nothing is estimated until gathered
+        let before = estimates();
+        assert_eq!(estimate(&before, "kind_1"), Value::Null);
+        assert_eq!(estimate(&before, "collection_scan"), Value::Null);
+
+        let stats = items.stats().unwrap();
+        assert_eq!(stats.count, 3000);
+        assert!(stats.avg_size > 0 && stats.max_size >= stats.avg_size);
+        assert!(stats.storage_size >= stats.count * stats.avg_size);
+        assert_eq!(stats.indexes.len(), 3);
+        let near = |estimate: u64, expected: u64| estimate.abs_diff(expected) * 20 <= expected;
+        let kind = stats.index("kind_1").unwrap();
+        assert_eq!(kind.entries, 3000);
+        assert_eq!(kind.cardinality, vec![2]);
+        let n = stats.index("n_1").unwrap();
+        assert!(near(n.cardinality[0], 3000), "{:?}", n);
+        let compound = stats.index(&compound).unwrap();
+        assert_eq!(compound.cardinality[0], 2);
+        assert!(near(compound.cardinality[1], 3000), "{:?}", compound);
+        assert!(stats.index("missing").is_none());
+
+        let after = estimates();
+        assert_eq!(estimate(&after, "kind_1"), Value::Int64(1500));
+        assert_eq!(estimate(&after, "kind_1_n_-1"), Value::Int64(1500));
+        assert_eq!(estimate(&after, "n_1"), Value::Null);
+        assert_eq!(estimate(&after, "collection_scan"), Value::Int64(3000));
+    }
+
+    #[test]
+    fn test_collations() {
+        let db = Database::open(scratch_dir("collation")).unwrap();
+        let items = db.collection("items");
+        let collation = Collation::new("en")
+            .case_sensitive(false)
+            .numeric_ordering(true);
+        items.set_collation(collation.clone()).unwrap();
+        assert_eq!(items.collation().unwrap(), Some(collation.clone()));
+        for (id, name) in [
+            ("A", "Item 10"),
+            ("b", "item 2"),
+            ("c", "ITEM 1"),
+            ("d", "other"),
+        ] {
+            let mut item = doc("_id", id);
+            item.insert("name", name);
+            items.insert_one(item).unwrap();
+        }
+        let found = |cursor: crate::db::Cursor| ids(&cursor.try_collect().unwrap());
+        let stage = |filter: &Document| {
+            let explain = items.explain(filter).unwrap();
+            let Some(Value::Document(plan)) = explain.get("plan") else {
+                panic!("no plan in {:?}", explain);
+            };
+            plan.get("stage").cloned().unwrap()
+        };
+
+        // Queries use the collection's collation, on _id too
+        assert_eq!(
+            found(items.find(&doc("name", "item 02")).unwrap()),
+            vec![Value::from("b")]
+        );
+        assert_eq!(
+            found(items.find(&doc("_id", "a")).unwrap()),
+            vec![Value::from("A")]
+        );
+        assert_eq!(
+            found(items.find(&doc("name", doc("$gt", "item 1"))).unwrap()),
+            vec![Value::from("A"), Value::from("b"), Value::from("d")]
+        );
+
+        // New indexes take it, and keep strings in its order
+        items.create_index("name").unwrap();
+        let index = &items.list_indexes().unwrap()[0];
+        assert_eq!(index.collation, Some(collation.clone()));
+        let prefix = doc("name", doc("$gte", "ITEM 2"));
+        assert_eq!(stage(&prefix), Value::from("index_scan"));
+        assert_eq!(
+            found(items.find(&prefix).unwrap()),
+            vec![Value::from("b"), Value::from("A"), Value::from("d")]
+        );
+
+        // Other collations compare their own way, without the index
+        let simple = Collation::new("simple");
+        assert_eq!(
+            found(
+                items
+                    .find_collated(&doc("name", "item 2"), &simple)
+                    .unwrap()
+            ),
+            vec![Value::from("b")]
+        );
+        assert!(found(
+            items
+                .find_collated(&doc("name", "ITEM 2"), &simple)
+                .unwrap()
+        )
+        .is_empty());
+        items.remove_collation().unwrap();
+        assert_eq!(
+            stage(&doc("name", "item 2")),
+            Value::from("collection_scan")
+        );
+        assert!(found(items.find(&doc("name", "Item 2")).unwrap()).is_empty());
+    }
+
+    #[test]
+    fn test_indexes_match_collection_scans() {
+        let db = Database::open(scratch_dir("index")).unwrap();
+        let indexed = db.collection("indexed");
+        let plain = db.collection("plain");
+        let tags = |names: &[&str]| names.iter().map(|&name| Value::from(name)).collect();
+        let documents = vec![
+            profile(1, "carol@x", tags(&["admin", "ops"])),
+            profile(2, "alice@x", tags(&["ops"])),
+            profile(3, "bob@x", tags(&[])),
+            doc("_id", 4),
+        ];
+        indexed.insert_many(documents.clone()).unwrap();
+        plain.insert_many(documents).unwrap();
+
+        // Indexes are built from existing documents and kept up to date
+        assert_eq!(indexed.create_index("user.email").unwrap(), "user.email_1");
+        assert_eq!(indexed.create_index("tags").unwrap(), "tags_1");
+        assert_eq!(indexed.create_index("tags").unwrap(), "tags_1");
+        assert_eq!(indexed.list_indexes().unwrap().len(), 2);
+        assert!(matches!(
+            indexed.create_index("a..b"),
+            Err(DatabaseError::InvalidIndex(_))
+        ));
+        for collection in [&indexed, &plain] {
+            collection
+                .insert_one(profile(5, "dave@x", tags(&["ops", "dev"])))
+                .unwrap();
+            collection
+                .update_one(&doc("_id", 1), &doc("$set", doc("user.email", "zed@x")))
+                .unwrap();
+            collection
+                .update_one(&doc("_id", 2), &doc("$set", doc("tags.0", "dev")))
+                .unwrap();
+            collection
+                .update_one(&doc("_id", 3), &doc("$set", doc("age", 30)))
+                .unwrap();
+            collection.delete_one(&doc("user.email", "dave@x")).unwrap();
+        }
+
+        let sorted = |collection: &crate::db::Collection, filter: &Document| {
+            let mut found = ids(&collection.find(filter).unwrap().try_collect().unwrap());
+            found.sort_by(|a, b| a.bson_cmp(b));
+            found
+        };
+        for filter in [
+            doc("user.email", "alice@x"),
+            doc("user.email", "carol@x"),
+            doc("user.email", "zed@x"),
+            doc("user.email", Value::Null),
+            doc("user.email", doc("$gte", "b")),
+            doc("user.email", doc("$lt", "c")),
+            doc("tags", "dev"),
+            doc("tags", "ops"),
+            doc("tags", doc("$gt", "b")),
+            doc("tags", Array::from_vec(vec![])),
+            doc("tags", Array::from_vec(tags(&["dev"]))),
+        ] {
+            assert!(matches!(
+                plan(&filter, &indexed.list_indexes().unwrap()),
+                Plan::Index { .. }
+            ));
+            assert_eq!(
+                sorted(&indexed, &filter),
+                sorted(&plain, &filter),
+                "{:?}",
+                filter
+            );
+        }
+        assert_eq!(
+            ids(&indexed
+                .find(&doc("tags", "dev"))
+                .unwrap()
+                .try_collect()
+                .unwrap()),
+            vec![Value::Int32(2)]
+        );
+        assert_eq!(indexed.count(&doc("user.email", doc("$gt", 1))).unwrap(), 0);
+
+        // Indexed values are returned in index order
+        assert_eq!(
+            ids(&indexed
+                .find(&doc("user.email", doc("$gte", "a")))
+                .unwrap()
+                .batch_size(1)
+                .try_collect()
+                .unwrap()),
+            vec![Value::Int32(2), Value::Int32(3), Value::Int32(1)]
+        );
+    }
+
+    #[test]
+    fn test_compound_indexes() {
+        let db = Database::open(scratch_dir("compound_index")).unwrap();
+        let indexed = db.collection("indexed");
+        let plain = db.collection("plain");
+        let item = |id: i32, kind: &str, price: Value| {
+            let mut document = doc("_id", id);
+            document.insert("kind", kind);
+            document.insert("price", price);
+            document
+        };
+        let documents = vec![
+            item(1, "book", Value::Int32(12)),
+            item(2, "pen", Value::Int32(2)),
+            item(3, "book", Value::Double(7.5)),
+            item(4, "book", Value::Int32(30)),
+            item(5, "pen", Value::Null),
+            doc("_id", 6),
+            item(7, "book", Value::from("n/a")),
+        ];
+        indexed.insert_many(documents.clone()).unwrap();
+        plain.insert_many(documents).unwrap();
+
+        let options = IndexOptions::new().ascending("kind").descending("price");
+        assert_eq!(
+            indexed.create_index_with(options.clone()).unwrap(),
+            "kind_1_price_-1"
+        );
+        assert_eq!(
+            indexed.create_index_with(options).unwrap(),
+            "kind_1_price_-1"
+        );
+        assert!(matches!(
+            indexed.create_index_with(IndexOptions::new()),
+            Err(DatabaseError::InvalidIndex(_))
+        ));
+        assert!(matches!(
+            indexed.create_index_with(IndexOptions::new().ascending("a").descending("a")),
+            Err(DatabaseError::InvalidIndex(_))
+        ));
+        assert!(matches!(
+            indexed.create_index_with(
+                IndexOptions::new()
+                    .ascending("kind")
+                    .name("kind_1_price_-1")
+            ),
+            Err(DatabaseError::InvalidIndex(_))
+        ));
+        assert_eq!(
+            indexed.list_indexes().unwrap()[0].fields,
+            vec![
+                ("kind".to_string(), SortOrder::Ascending),
+                ("price".to_string(), SortOrder::Descending),
+            ]
+        );
+        for collection in [&indexed, &plain] {
+            collection
+                .update_one(&doc("_id", 2), &doc("$set", doc("kind", "book")))
+                .unwrap();
+        }
+
+        let found = |collection: &crate::db::Collection, filter: &Document| {
+            ids(&collection
+                .find(filter)
+                .unwrap()
+                .batch_size(2)
+                .try_collect()
+                .unwrap())
+        };
+        let both = |kind: &str, price: Document| {
+            let mut filter = doc("kind", kind);
+            filter.insert("price", price);
+            filter
+        };
+        // Equal kinds come out by descending price, with types ordered too
+        assert_eq!(
+            found(&indexed, &doc("kind", "book")),
+            vec![
+                Value::Int32(7),
+                Value::Int32(4),
+                Value::Int32(1),
+                Value::Int32(3),
+                Value::Int32(2),
+            ]
+        );
+        assert_eq!(
+            found(&indexed, &both("book", doc("$lt", 12))),
+            vec![Value::Int32(3), Value::Int32(2)]
+        );
+        assert_eq!(
+            found(&indexed, &both("book", doc("$gte", 12))),
+            vec![Value::Int32(4), Value::Int32(1)]
+        );
+        for filter in [
+            doc("kind", "pen"),
+            doc("kind", Value::Null),
+            doc("kind", doc("$gt", "c")),
+            both("book", doc("$gt", 7.5)),
+            both("pen", doc("$lte", Value::Null)),
+        ] {
+            assert!(matches!(
+                plan(&filter, &indexed.list_indexes().unwrap()),
+                Plan::Index { .. }
+            ));
+            let mut expected = found(&plain, &filter);
+            let mut actual = found(&indexed, &filter);
+            expected.sort_by(|a, b| a.bson_cmp(b));
+            actual.sort_by(|a, b| a.bson_cmp(b));
+            assert_eq!(actual, expected, "{:?}", filter);
+        }
+    }
+
+    #[test]
+    fn test_partial_and_sparse_indexes() {
+        let db = Database::open(scratch_dir("partial_index")).unwrap();
+        let indexed = db.collection("indexed");
+        let plain = db.collection("plain");
+        let account = |id: i32, email: Option<&str>, active: bool| {
+            let mut document = doc("_id", id);
+            if let Some(email) = email {
+                document.insert("email", email);
+            }
+            document.insert("active", active);
+            document
+        };
+        let documents = vec![
+            account(1, Some("a@x"), true),
+            account(2, None, true),
+            account(3, Some("c@x"), false),
+            account(4, None, false),
+        ];
+        indexed.insert_many(documents.clone()).unwrap();
+        plain.insert_many(documents).unwrap();
+
+        let sparse = IndexOptions::new().ascending("email").sparse(true);
+        assert_eq!(indexed.create_index_with(sparse).unwrap(), "email_1");
+        let partial = IndexOptions::new()
+            .ascending("email")
+            .name("active_email")
+            .partial_filter(doc("active", true));
+        indexed.create_index_with(partial).unwrap();
+        assert!(matches!(
+            indexed.create_index_with(IndexOptions::new().ascending("email")),
+            Err(DatabaseError::InvalidIndex(_))
+        ));
+        assert!(matches!(
+            indexed.create_index_with(
+                IndexOptions::new()
+                    .ascending("x")
+                    .partial_filter(doc("$bogus", 1))
+            ),
+            Err(DatabaseError::Query(_))
+        ));
+        let indexes = indexed.list_indexes().unwrap();
+        assert!(indexes[1].sparse);
+        assert_eq!(indexes[0].partial_filter, Some(doc("active", true)));
+
+        for collection in [&indexed, &plain] {
+            collection
+                .update_one(&doc("_id", 3), &doc("$set", doc("active", true)))
+                .unwrap();
+            collection
+                .update_one(&doc("_id", 1), &doc("$set", doc("active", false)))
+                .unwrap();
+            collection
+                .update_one(&doc("_id", 2), &doc("$set", doc("email", "b@x")))
+                .unwrap();
+            collection
+                .update_one(&doc("_id", 3), &doc("$unset", doc("email", "")))
+                .unwrap();
+        }
+
+        // Documents left out never go missing from results
+        let both = |email: Value, active: bool| {
+            let mut filter = doc("email", email);
+            filter.insert("active", active);
+            filter
+        };
+        for filter in [
+            doc("email", "a@x"),
+            doc("email", "b@x"),
+            doc("email", doc("$gte", "a")),
+            doc("email", Value::Null),
+            both(Value::from("b@x"), true),
+            both(Value::from("a@x"), false),
+            both(Value::Null, true),
+        ] {
+            let found = |collection: &crate::db::Collection| {
+                let mut found = ids(&collection.find(&filter).unwrap().try_collect().unwrap());
+                found.sort_by(|a, b| a.bson_cmp(b));
+                found
+            };
+            assert_eq!(found(&indexed), found(&plain), "{:?}", filter);
+        }
+        assert!(matches!(
+            plan(&doc("email", Value::Null), &indexes),
+            Plan::Primary(_)
+        ));
+    }
+
+    #[test]
+    fn test_ttl_indexes() {
+        let db = Database::open(scratch_dir("ttl")).unwrap();
+        let sessions = db.collection("sessions");
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let session = |id: i32, seen: Value| {
+            let mut document = doc("_id", id);
+            document.insert("seen", seen);
+            document
+        };
+        sessions
+            .insert_many(vec![
+                session(1, Value::UTCDateTime(now - 1000)),
+                session(2, Value::UTCDateTime(now)),
+                session(
+                    3,
+                    Value::from(Array::from_vec(vec![
+                        Value::UTCDateTime(now),
+                        Value::UTCDateTime(now - 500),
+                    ])),
+                ),
+                session(4, Value::Int64(now - 1000)),
+                doc("_id", 5),
+            ])
+            .unwrap();
+
+        let options = IndexOptions::new()
+            .ascending("seen")
+            .expire_after(Duration::from_secs(100));
+        sessions.create_index_with(options).unwrap();
+        assert_eq!(
+            sessions.list_indexes().unwrap()[0].expire_after,
+            Some(Duration::from_secs(100))
+        );
+        assert!(matches!(
+            sessions.create_index_with(
+                IndexOptions::new()
+                    .ascending("a")
+                    .ascending("b")
+                    .expire_after(Duration::from_secs(1))
+            ),
+            Err(DatabaseError::InvalidIndex(_))
+        ));
+
+        // Only dates past their TTL expire, the earliest one for arrays
+        assert_eq!(db.expire_documents().unwrap(), 2);
+        assert_eq!(
+            ids(&sessions
+                .find(&Document::new())
+                .unwrap()
+                .try_collect()
+                .unwrap()),
+            vec![Value::Int32(2), Value::Int32(4), Value::Int32(5)]
+        );
+        let stats = db.ttl_stats();
+        assert_eq!((stats.passes, stats.expired), (1, 2));
+        assert!(stats.lag >= Duration::from_secs(900));
+        assert_eq!(db.expire_documents().unwrap(), 0);
+        assert_eq!(db.ttl_stats().lag, Duration::ZERO);
+
+        // Large passes delete in batches, and the reaper runs on its own
+        let expired: Vec<Document> = (10..600)
+            .map(|id| session(id, Value::UTCDateTime(now - 200)))
+            .collect();
+        sessions.insert_many(expired).unwrap();
+        db.set_ttl_interval(Duration::from_millis(10));
+        for _ in 0..200 {
+            if db.ttl_stats().expired == 592 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(db.ttl_stats().expired, 592);
+        assert_eq!(sessions.count(&Document::new()).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_text_indexes() {
+        let db = Database::open(scratch_dir("text")).unwrap();
+        let posts = db.collection("posts");
+        let post = |id: i32, title: &str, body: &str| {
+            let mut document = doc("_id", id);
+            document.insert("title", title);
+            document.insert("body", body);
+            document
+        };
+        posts
+            .insert_many(vec![
+                post(
+                    1,
+                    "Running shoes",
+                    "A review of shoes for running on trails",
+                ),
+                post(2, "Cooking", "Slow cooking beans"),
+                post(3, "Marathon", "Ran my first marathon, running all the way"),
+                doc("_id", 4),
+            ])
+            .unwrap();
+        let search = |text: &str| doc("$text", doc("$search", text));
+
+        assert!(matches!(
+            posts.find(&search("running")),
+            Err(DatabaseError::Query(_))
+        ));
+        let options = IndexOptions::new().text("title").text("body");
+        assert_eq!(
+            posts.create_index_with(options).unwrap(),
+            "title_text_body_text"
+        );
+        assert!(matches!(
+            posts.create_index_with(IndexOptions::new().text("body").name("other")),
+            Err(DatabaseError::InvalidIndex(_))
+        ));
+        assert!(matches!(
+            posts.find(&doc("$text", doc("$search", 1))),
+            Err(DatabaseError::Query(_))
+        ));
+        assert!(posts.list_indexes().unwrap()[0].text.is_some());
+
+        // Matches are stemmed and ranked, best first
+        let found = |filter: &Document| {
+            ids(&posts
+                .find(filter)
+                .unwrap()
+                .batch_size(1)
+                .try_collect()
+                .unwrap())
+        };
+        assert_eq!(
+            found(&search("runs")),
+            vec![Value::Int32(1), Value::Int32(3)]
+        );
+        assert_eq!(
+            found(&search("marathon running")),
+            vec![Value::Int32(3), Value::Int32(1)]
+        );
+        assert_eq!(found(&search("the")), Vec::<Value>::new());
+        let mut filter = search("running");
+        filter.insert("_id", doc("$gt", 1));
+        assert_eq!(found(&filter), vec![Value::Int32(3)]);
+        assert_eq!(posts.count(&search("cook trail")).unwrap(), 2);
+
+        let scored = posts
+            .find(&search("shoes"))
+            .unwrap()
+            .text_score("score")
+            .try_collect()
+            .unwrap();
+        assert!(matches!(scored[0].get("score"), Some(Value::Double(score)) if *score > 0.0));
+
+        // Writes keep the index up to date
+        posts
+            .update_one(
+                &doc("_id", 2),
+                &doc("$set", doc("body", "Shoes for cooking")),
+            )
+            .unwrap();
+        posts.delete_one(&doc("_id", 1)).unwrap();
+        assert_eq!(found(&search("shoe")), vec![Value::Int32(2)]);
+        assert_eq!(found(&search("beans")), Vec::<Value>::new());
+        let txn = db.begin();
+        txn.collection("posts")
+            .insert_one(post(5, "Beans", "More beans"))
+            .unwrap();
+        assert_eq!(
+            ids(&txn
+                .collection("posts")
+                .find(&search("bean"))
+                .unwrap()
+                .try_collect()
+                .unwrap()),
+            vec![Value::Int32(5)]
+        );
+        assert_eq!(found(&search("bean")), Vec::<Value>::new());
+    }
+
+    fn place(id: i32, kind: &str, coordinates: Value) -> Document {
+        let mut location = doc("type", kind);
+        location.insert("coordinates", coordinates);
+        let mut document = doc("_id", id);
+        document.insert("location", location);
+        document
+    }
+
+    fn lng_lat(lng: f64, lat: f64) -> Value {
+        Value::Array(Array::from_vec(vec![lng.into(), lat.into()]))
+    }
+
+    #[test]
+    fn test_geo_indexes() {
+        let db = Database::open(scratch_dir("geo")).unwrap();
+        let places = db.collection("places");
+        let ring = |points: &[(f64, f64)]| {
+            let ring = points.iter().map(|&(lng, lat)| lng_lat(lng, lat));
+            Value::Array(Array::from_vec(vec![Value::Array(Array::from_vec(
+                ring.collect(),
+            ))]))
+        };
+        let park = [
+            (2.2, 48.8),
+            (2.3, 48.8),
+            (2.3, 48.9),
+            (2.2, 48.9),
+            (2.2, 48.8),
+        ];
+        places
+            .insert_many(vec![
+                place(1, "Point", lng_lat(2.3522, 48.8566)),
+                place(2, "Point", lng_lat(-0.1276, 51.5072)),
+                place(3, "Point", lng_lat(13.405, 52.52)),
+                place(4, "Point", lng_lat(-74.006, 40.7128)),
+                place(5, "Polygon", ring(&park)),
+                doc("_id", 6),
+                doc("location", "nowhere"),
+            ])
+            .unwrap();
+        let france = ring(&[
+            (-5.0, 42.0),
+            (8.0, 42.0),
+            (8.0, 51.0),
+            (-5.0, 51.0),
+            (-5.0, 42.0),
+        ]);
+        let mut polygon = doc("type", "Polygon");
+        polygon.insert("coordinates", france);
+        let within_france = doc("location", doc("$geoWithin", doc("$geometry", polygon)));
+        let europe = doc(
+            "location",
+            doc(
+                "$geoWithin",
+                doc(
+                    "$centerSphere",
+                    Array::from_vec(vec![lng_lat(5.0, 50.0), 0.1.into()]),
+                ),
+            ),
+        );
+        let mut london = doc("type", "Point");
+        london.insert("coordinates", lng_lat(-0.1276, 51.5072));
+        let intersects = doc(
+            "location",
+            doc("$geoIntersects", doc("$geometry", london.clone())),
+        );
+        let near = |max: Option<f64>| {
+            let mut near = doc("$geometry", london.clone());
+            if let Some(max) = max {
+                near.insert("$maxDistance", max);
+            }
+            doc("location", doc("$near", near))
+        };
+        let found = |filter: &Document| {
+            ids(&places
+                .find(filter)
+                .unwrap()
+                .batch_size(1)
+                .try_collect()
+                .unwrap())
+        };
+
+        // Malformed shapes are rejected, and $near needs a geo index
+        let open = doc(
+            "location",
+            doc("$geoWithin", doc("$geometry", doc("type", "Polygon"))),
+        );
+        assert!(matches!(places.find(&open), Err(DatabaseError::Query(_))));
+        assert!(matches!(
+            places.find(&near(None)),
+            Err(DatabaseError::Query(_))
+        ));
+        let sorted = |filter: &Document| {
+            let mut ids = found(filter);
+            ids.sort_by(|a, b| a.bson_cmp(b));
+            ids
+        };
+        let scanned: Vec<Vec<Value>> = [&within_france, &europe, &intersects]
+            .into_iter()
+            .map(sorted)
+            .collect();
+        assert_eq!(
+            scanned,
+            vec![
+                vec![Value::Int32(1), Value::Int32(5)],
+                vec![Value::Int32(1), Value::Int32(2), Value::Int32(5)],
+                vec![Value::Int32(2)],
+            ]
+        );
+
+        assert_eq!(
+            places
+                .create_index_with(IndexOptions::new().geo("location"))
+                .unwrap(),
+            "location_2dsphere"
+        );
+        assert!(places.list_indexes().unwrap()[0].geo);
+        assert!(matches!(
+            places.create_index_with(IndexOptions::new().geo("a").ascending("b")),
+            Err(DatabaseError::InvalidIndex(_))
+        ));
+
+        // The index finds the same documents as a scan
+        let indexed: Vec<Vec<Value>> = [&within_france, &europe, &intersects]
+            .into_iter()
+            .map(sorted)
+            .collect();
+        assert_eq!(indexed, scanned);
+
+        // $near returns documents nearest first, within $maxDistance
+        let ids_of = |ids: &[i32]| ids.iter().map(|&id| Value::Int32(id)).collect::<Vec<_>>();
+        assert_eq!(found(&near(None)), ids_of(&[2, 5, 1, 3, 4]));
+        assert_eq!(found(&near(Some(400_000.0))), ids_of(&[2, 5, 1]));
+        let mut filter = near(Some(1_000_000.0));
+        filter.insert("_id", doc("$ne", 5));
+        assert_eq!(found(&filter), ids_of(&[2, 1, 3]));
+
+        // Writes keep the index up to date
+        places
+            .update_one(
+                &doc("_id", 4),
+                &doc("$set", doc("location.coordinates", lng_lat(-0.1, 51.5))),
+            )
+            .unwrap();
+        places.delete_one(&doc("_id", 2)).unwrap();
+        assert_eq!(found(&near(Some(10_000.0))), ids_of(&[4]));
+    }
+
+    // -------------------------------------
+    //          Change Stream Tests
+    // -------------------------------------
+
+    #[test]
+    fn test_change_streams() {
+        let dir = scratch_dir("changes");
+        let db = Database::open(&dir).unwrap();
+        let users = db.collection("users");
+        users.insert_one(user(1, "alice", 30)).unwrap();
+        let mut stream = users.watch(&Document::new()).unwrap();
+        let mut deletes = users.watch(&doc("operationType", "delete")).unwrap();
+        assert_eq!(stream.try_next().unwrap(), None);
+
+        users.insert_one(user(2, "bob", 25)).unwrap();
+        db.collection("other").insert_one(doc("_id", 1)).unwrap();
+        let mut update = doc("$set", doc("age", 31));
+        update.insert("$unset", doc("name", ""));
+        users.update_one(&doc("_id", 1), &update).unwrap();
+        assert!(matches!(
+            users.replace_one(&doc("_id", 2), &doc("$set", doc("a", 1))),
+            Err(DatabaseError::InvalidUpdate(_))
+        ));
+        assert!(matches!(
+            users.replace_one(&doc("_id", 2), &doc("_id", 3)),
+            Err(DatabaseError::InvalidUpdate(_))
+        ));
+        assert_eq!(
+            users
+                .replace_one(&doc("_id", 2), &doc("name", "robert"))
+                .unwrap(),
+            UpdateResult {
+                matched_count: 1,
+                modified_count: 1,
+                upserted_id: None,
+            }
+        );
+        assert_eq!(users.find_one(&doc("_id", 2)).unwrap(), {
+            let mut robert = doc("_id", 2);
+            robert.insert("name", "robert");
+            Some(robert)
+        });
+        users.delete_one(&doc("_id", 1)).unwrap();
+
+        let mut events = Vec::new();
+        while let Some(event) = stream.try_next().unwrap() {
+            events.push(event);
+        }
+        let kinds: Vec<ChangeKind> = events.iter().map(|event| event.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ChangeKind::Insert,
+                ChangeKind::Update,
+                ChangeKind::Replace,
+                ChangeKind::Delete
+            ]
+        );
+        assert!(events.iter().all(|event| event.collection == "users"));
+        assert_eq!(events[0].full_document, Some(user(2, "bob", 25)));
+        let description = events[1].update_description.as_ref().unwrap();
+        assert_eq!(description.updated_fields, doc("age", 31));
+        assert_eq!(description.removed_fields, vec!["name".to_string()]);
+        assert_eq!(events[3].document_key, Value::Int32(1));
+        assert_eq!(events[3].full_document, None);
+        assert!(events
+            .windows(2)
+            .all(|pair| pair[0].resume_token < pair[1].resume_token));
+
+        // Filters match the event documents
+        let deleted = deletes.try_next().unwrap().unwrap();
+        assert_eq!(deleted.kind, ChangeKind::Delete);
+        assert_eq!(deletes.try_next().unwrap(), None);
+
+        // Transactions' changes appear when they commit
+        let txn = db.begin();
+        txn.collection("users")
+            .insert_one(user(4, "dan", 40))
+            .unwrap();
+        assert_eq!(stream.try_next().unwrap(), None);
+        txn.commit().unwrap();
+        let event = stream.try_next().unwrap().unwrap();
+        assert_eq!(
+            (event.kind, event.document_key),
+            (ChangeKind::Insert, Value::Int32(4))
+        );
+
+        // A stream waits for commits
+        let writer = {
+            let users = users.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                users.insert_one(user(5, "eve", 50)).unwrap();
+            })
+        };
+        let event = stream
+            .next_timeout(Duration::from_secs(10))
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.document_key, Value::Int32(5));
+        writer.join().unwrap();
+        assert_eq!(
+            stream.next_timeout(Duration::from_millis(10)).unwrap(),
+            None
+        );
+
+        // Streams resume after a token, across restarts
+        let token = events[1].resume_token.to_bytes();
+        drop((stream, deletes, users, db));
+        let db = Database::open(&dir).unwrap();
+        let token = ResumeToken::from_bytes(&token).unwrap();
+        let mut resumed = db
+            .collection("users")
+            .watch_after(&Document::new(), token)
+            .unwrap();
+        let mut keys = Vec::new();
+        while let Some(event) = resumed.try_next().unwrap() {
+            keys.push(event.document_key);
+        }
+        assert_eq!(
+            keys,
+            vec![
+                Value::Int32(2),
+                Value::Int32(1),
+                Value::Int32(4),
+                Value::Int32(5)
+            ]
+        );
+        assert_eq!(ResumeToken::from_bytes(&[0; 3]), None);
+    }
+
+    // -------------------------------------
+    //          Backup Tests
+    // -------------------------------------
+
+    fn account(id: i32, balance: i32) -> Document {
+        let mut account = doc("_id", id);
+        account.insert("balance", balance);
+        account
+    }
+
+    #[test]
+    fn test_backup_and_restore() {
+        let dir = scratch_dir("backup");
+        let db = Database::open(dir.join("source")).unwrap();
+        let users = db.collection("users");
+        users
+            .insert_many((0..2500).map(|i| user(i, &format!("user{}", i), i % 90)))
+            .unwrap();
+        users
+            .create_index_with(IndexOptions::new().ascending("age").name("by_age"))
+            .unwrap();
+        db.collection("empty")
+            .create_index_with(IndexOptions::new().ascending("x").sparse(true))
+            .unwrap();
+        let accounts = db.collection("accounts");
+        accounts
+            .insert_many([account(1, 500), account(2, 500)])
+            .unwrap();
+        db.create_collection("created").unwrap();
+        assert_eq!(
+            db.list_collections().unwrap(),
+            vec!["accounts", "created", "empty", "users"]
+        );
+
+        // Transfers commit throughout the backup, which sees none of them
+        // half done
+        let writer = {
+            let db = db.clone();
+            thread::spawn(move || {
+                for i in 0..200 {
+                    let (from, to) = if i % 2 == 0 { (1, 2) } else { (2, 1) };
+                    let txn = db.begin();
+                    let accounts = txn.collection("accounts");
+                    let moved = accounts
+                        .update_one(&doc("_id", from), &doc("$inc", doc("balance", -7)))
+                        .and_then(|_| {
+                            accounts.update_one(&doc("_id", to), &doc("$inc", doc("balance", 7)))
+                        });
+                    match moved {
+                        Ok(_) => txn.commit().unwrap(),
+                        Err(_) => txn.abort(),
+                    }
+                }
+            })
+        };
+        let stats = db.backup(dir.join("backup")).unwrap();
+        writer.join().unwrap();
+        assert_eq!(stats.collections, 4);
+        assert_eq!(stats.documents, 2502);
+        assert_eq!(stats.indexes, 2);
+
+        let copy = Database::open(dir.join("copy")).unwrap();
+        assert_eq!(copy.restore(dir.join("backup")).unwrap(), stats);
+        let balances: Vec<i32> = copy
+            .collection("accounts")
+            .find(&Document::new())
+            .unwrap()
+            .map(|account| match account.unwrap().get("balance") {
+                Some(Value::Int32(balance)) => *balance,
+                other => panic!("{:?}", other),
+            })
+            .collect();
+        assert_eq!(balances.iter().sum::<i32>(), 1000);
+
+        let restored = copy.collection("users");
+        assert_eq!(restored.count(&Document::new()).unwrap(), 2500);
+        assert_eq!(
+            restored.find_one(&doc("_id", 1234)).unwrap(),
+            users.find_one(&doc("_id", 1234)).unwrap()
+        );
+        assert_eq!(
+            restored.list_indexes().unwrap(),
+            users.list_indexes().unwrap()
+        );
+        assert_eq!(
+            copy.collection("empty").list_indexes().unwrap(),
+            db.collection("empty").list_indexes().unwrap()
+        );
+        assert_eq!(
+            copy.list_collections().unwrap(),
+            db.list_collections().unwrap()
+        );
+
+        // Restoring over existing documents fails, as does a missing backup
+        assert!(matches!(
+            copy.restore(dir.join("backup")),
+            Err(DatabaseError::DuplicateKey(_) | DatabaseError::InvalidIndex(_))
+        ));
+        assert!(matches!(
+            copy.restore(dir.join("missing")),
+            Err(DatabaseError::Storage(_))
+        ));
+    }
+
+    #[test]
+    fn test_replication() {
+        let dir = scratch_dir("replication");
+        let primary = Database::open(dir.join("primary")).unwrap();
+        let users = primary.collection("users");
+        users
+            .insert_many((0..100).map(|i| user(i, &format!("user{}", i), i % 40)))
+            .unwrap();
+        assert!(matches!(
+            primary.oplog_after(None, 10, Duration::ZERO),
+            Err(DatabaseError::OplogDisabled)
+        ));
+        primary.enable_oplog(1024 * 1024).unwrap();
+        users
+            .create_index_with(IndexOptions::new().ascending("age").name("by_age"))
+            .unwrap();
+
+        // The secondary starts from a backup and carries on from its optime
+        let secondary = Secondary::new(
+            Database::open(dir.join("secondary")).unwrap(),
+            primary.clone(),
+        )
+        .batch_size(2);
+        let stats = secondary.initial_sync(dir.join("snapshot")).unwrap();
+        assert_eq!(stats.documents, 100);
+        assert_eq!(stats.optime, primary.last_optime());
+        assert_eq!(secondary.applied().unwrap(), stats.optime);
+
+        users
+            .update_one(&doc("_id", 1), &doc("$set", doc("age", 99)))
+            .unwrap();
+        users.delete_one(&doc("_id", 2)).unwrap();
+        users.insert_one(user(100, "late", 7)).unwrap();
+        primary
+            .collection("logs")
+            .insert_one(doc("_id", "a"))
+            .unwrap();
+        while secondary.sync(Duration::ZERO).unwrap() > 0 {}
+        assert_eq!(secondary.applied().unwrap(), primary.last_optime());
+
+        let replica = secondary.database().collection("users");
+        assert_eq!(replica.count(&Document::new()).unwrap(), 100);
+        assert_eq!(
+            replica.find_one(&doc("_id", 1)).unwrap(),
+            Some(user(1, "user1", 99))
+        );
+        assert_eq!(replica.find_one(&doc("_id", 2)).unwrap(), None);
+        let aged = replica
+            .find(&doc("age", 99))
+            .unwrap()
+            .try_collect()
+            .unwrap();
+        assert_eq!(ids(&aged), vec![Value::Int32(1)]);
+        assert_eq!(
+            secondary.database().list_collections().unwrap(),
+            vec!["logs", "users"]
+        );
+
+        // Entries are in commit order, and a secondary synced from a newer
+        // snapshot has nothing to catch up on
+        let entries = primary
+            .oplog_after(None, usize::MAX, Duration::ZERO)
+            .unwrap();
+        assert!(entries.windows(2).all(|pair| pair[0].ts < pair[1].ts));
+        let copy = Secondary::new(Database::open(dir.join("copy")).unwrap(), primary.clone());
+        copy.initial_sync(dir.join("snapshot-copy")).unwrap();
+        assert_eq!(copy.sync(Duration::ZERO).unwrap(), 0);
+
+        // A waiting secondary gets a commit as soon as it is made
+        let writer = {
+            let users = users.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                users.insert_one(user(101, "waited", 1)).unwrap();
+            })
+        };
+        assert_eq!(secondary.sync(Duration::from_secs(5)).unwrap(), 1);
+        writer.join().unwrap();
+        assert!(replica.find_one(&doc("_id", 101)).unwrap().is_some());
+
+        // Progress survives reopening, and the oplog is capped, so a
+        // secondary left too far behind must be synced anew
+        drop(secondary);
+        drop(replica);
+        let secondary = Secondary::new(
+            Database::open(dir.join("secondary")).unwrap(),
+            primary.clone(),
+        );
+        assert_eq!(secondary.applied().unwrap(), primary.last_optime());
+        primary.enable_oplog(4096).unwrap();
+        for i in 200..300 {
+            users.insert_one(user(i, "filler", 1)).unwrap();
+        }
+        assert!(matches!(
+            secondary.sync(Duration::ZERO),
+            Err(DatabaseError::OplogTruncated(_))
+        ));
+        let kept = primary
+            .oplog_after(None, usize::MAX, Duration::ZERO)
+            .unwrap();
+        assert!(!kept.is_empty() && kept.len() < 100);
+        assert_eq!(kept.last().map(|entry| entry.ts), primary.last_optime());
+    }
+
+    /// Passes the messages of `nodes` between them until none are left,
+    /// dropping those to or from the nodes in `down`.
+    fn deliver(nodes: &mut [RaftNode], down: &[u64]) {
+        loop {
+            let mut messages = Vec::new();
+            for node in nodes.iter_mut() {
+                let from = node.id();
+                for (to, message) in node.take_messages() {
+                    if !down.contains(&from) && !down.contains(&to) {
+                        messages.push((from, to, message));
+                    }
+                }
+            }
+            if messages.is_empty() {
+                return;
+            }
+            for (from, to, message) in messages {
+                let node = nodes.iter_mut().find(|node| node.id() == to).unwrap();
+                node.step(from, message).unwrap();
+            }
+        }
+    }
+
+    /// Ticks `nodes` until one of those not in `down` leads, and returns
+    /// its id.
+    fn elect(nodes: &mut [RaftNode], down: &[u64]) -> u64 {
+        for _ in 0..1000 {
+            for node in nodes.iter_mut() {
+                if !down.contains(&node.id()) {
+                    node.tick().unwrap();
+                }
+            }
+            deliver(nodes, down);
+            let leader = nodes
+                .iter()
+                .find(|node| node.role() == RaftRole::Leader && !down.contains(&node.id()));
+            if let Some(leader) = leader {
+                return leader.id();
+            }
+        }
+        panic!("no leader elected");
+    }
+
+    fn node(nodes: &[RaftNode], id: u64) -> &RaftNode {
+        nodes.iter().find(|node| node.id() == id).unwrap()
+    }
+
+    #[test]
+    fn test_raft_replication() {
+        let dir = scratch_dir("raft");
+        let mut nodes: Vec<RaftNode> = (1..=3)
+            .map(|id| {
+                let db = Database::open(dir.join(format!("node-{}", id))).unwrap();
+                RaftNode::new(db, RaftConfig::new(id, [1, 2, 3])).unwrap()
+            })
+            .collect();
+        let first = elect(&mut nodes, &[]);
+        let follower = (1..=3).find(|&id| id != first).unwrap();
+        assert!(nodes.iter().all(|node| node.leader() == Some(first)));
+
+        // Only the leader takes writes, which commit once sent to the others
+        let users = node(&nodes, first).database().collection("users");
+        users.insert_one(user(1, "alice", 30)).unwrap();
+        users
+            .create_index_with(IndexOptions::new().ascending("age"))
+            .unwrap();
+        let written = node(&nodes, first).last_index();
+        assert!(node(&nodes, first).commit_index() < written);
+        assert!(matches!(
+            node(&nodes, follower)
+                .database()
+                .collection("users")
+                .insert_one(user(2, "bob", 25)),
+            Err(DatabaseError::NotLeader(Some(leader))) if leader == first
+        ));
+        for _ in 0..5 {
+            nodes.iter_mut().for_each(|node| node.tick().unwrap());
+            deliver(&mut nodes, &[]);
+        }
+        for node in &nodes {
+            assert!(node.commit_index() >= written);
+            assert_eq!(
+                node.database()
+                    .collection("users")
+                    .find_one(&doc("age", 30))
+                    .unwrap(),
+                Some(user(1, "alice", 30))
+            );
+        }
+
+        // Without its leader, the rest of the cluster elects another, and
+        // the old leader catches up once back
+        let second = elect(&mut nodes, &[first]);
+        assert_ne!(second, first);
+        assert!(node(&nodes, second).term() > node(&nodes, first).term());
+        let users = node(&nodes, second).database().collection("users");
+        users.insert_one(user(2, "bob", 25)).unwrap();
+        for _ in 0..5 {
+            nodes.iter_mut().for_each(|node| node.tick().unwrap());
+            deliver(&mut nodes, &[]);
+        }
+        assert_eq!(node(&nodes, first).role(), RaftRole::Follower);
+        assert_eq!(node(&nodes, first).leader(), Some(second));
+        let old = node(&nodes, first).database().collection("users");
+        assert_eq!(old.count(&Document::new()).unwrap(), 2);
+
+        // A leader cut off from the rest commits nothing, and writes it took
+        // meanwhile leave it diverged once the others move on
+        let stale = node(&nodes, second).database().collection("users");
+        stale.insert_one(user(3, "carol", 40)).unwrap();
+        let next = elect(&mut nodes, &[second]);
+        assert_ne!(next, second);
+        node(&nodes, next)
+            .database()
+            .collection("users")
+            .insert_one(user(4, "dave", 35))
+            .unwrap();
+        let mut diverged = false;
+        for _ in 0..20 {
+            for node in nodes.iter_mut() {
+                node.tick().unwrap();
+            }
+            let messages: Vec<_> = nodes
+                .iter_mut()
+                .flat_map(|node| {
+                    let from = node.id();
+                    node.take_messages()
+                        .into_iter()
+                        .map(move |(to, message)| (from, to, message))
+                })
+                .collect();
+            for (from, to, message) in messages {
+                let node = nodes.iter_mut().find(|node| node.id() == to).unwrap();
+                match node.step(from, message) {
+                    Ok(()) => {}
+                    Err(DatabaseError::RaftDiverged(_)) => diverged = true,
+                    Err(e) => panic!("{}", e),
+                }
+            }
+        }
+        assert!(diverged);
+        let users = node(&nodes, next).database().collection("users");
+        assert_eq!(users.find_one(&doc("_id", 3)).unwrap(), None);
+        assert!(users.find_one(&doc("_id", 4)).unwrap().is_some());
+    }
+
+    // -------------------------------------
+    //          Compaction Tests
+    // -------------------------------------
+
+    #[test]
+    fn test_compaction_while_writing() {
+        let db = Database::open(scratch_dir("compact")).unwrap();
+        let users = db.collection("users");
+        users
+            .insert_many((0..300).map(|i| user(i, &"x".repeat(100), 0)))
+            .unwrap();
+        for age in 1..5 {
+            users
+                .update_many(&Document::new(), &doc("$set", doc("age", age)))
+                .unwrap();
+        }
+        users.delete_many(&doc("_id", doc("$gte", 200))).unwrap();
+
+        // Updates keep committing while compaction runs
+        let writer = {
+            let users = db.collection("users");
+            thread::spawn(move || {
+                for i in 0..100 {
+                    users
+                        .update_one(&doc("_id", i), &doc("$set", doc("age", 99)))
+                        .unwrap();
+                }
+            })
+        };
+        let stats = db
+            .compact(CompactionOptions::new().step_size(4096).rate_limit(4 << 20))
+            .unwrap();
+        writer.join().unwrap();
+        assert!(stats.steps > 1, "{:?}", stats);
+        assert!(stats.reclaimed > 0, "{:?}", stats);
+        assert!(stats.work > 0);
+
+        assert_eq!(users.count(&Document::new()).unwrap(), 200);
+        assert_eq!(users.count(&doc("age", 99)).unwrap(), 100);
+        assert_eq!(users.count(&doc("age", 4)).unwrap(), 100);
+
+        // There is less to reclaim the second time
+        let again = db.compact(CompactionOptions::new().unthrottled()).unwrap();
+        assert!(again.reclaimed < stats.reclaimed, "{:?}", again);
+        assert_eq!(again.throttled, Duration::ZERO);
+    }
+
+    // -------------------------------------
+    //          Integrity Check Tests
+    // -------------------------------------
+
+    #[test]
+    fn test_check_and_repair() {
+        let dir = scratch_dir("check");
+        {
+            let db = Database::open(&dir).unwrap();
+            let users = db.collection("users");
+            users.create_index("name").unwrap();
+            users
+                .insert_many([
+                    user(1, "alice", 30),
+                    user(2, "bob", 25),
+                    user(3, "carol", 40),
+                ])
+                .unwrap();
+            let report = db.check(CheckLevel::Full).unwrap();
+            assert!(report.is_ok(), "{:?}", report.issues);
+            assert_eq!((report.documents, report.index_entries), (3, 3));
+            db.checkpoint().unwrap();
+        }
+
+        // Lose an index entry and store a document that is not one, behind
+        // the database's back
+        {
+            let mut engine = BTreeEngine::open(dir.join("data")).unwrap();
+            let namespace = "users.$index.name_1";
+            let (key, _) = engine
+                .scan(namespace, &KeyRange::all(), 1)
+                .unwrap()
+                .remove(0);
+            engine.delete(namespace, &key).unwrap();
+            engine
+                .put("users", &encode_key(&Value::Int32(9)), b"not a document")
+                .unwrap();
+            engine.flush().unwrap();
+        }
+
+        let db = Database::open(&dir).unwrap();
+        assert!(db.check(CheckLevel::Storage).unwrap().is_ok());
+        let report = db.check(CheckLevel::Full).unwrap();
+        assert_eq!(report.issues.len(), 2, "{:?}", report.issues);
+        assert!(matches!(
+            &report.issues[0],
+            CheckIssue::InvalidDocument { collection, .. } if collection == "users"
+        ));
+        assert!(matches!(
+            &report.issues[1],
+            CheckIssue::MissingEntry { index, .. } if index == "name_1"
+        ));
+        assert_eq!(report.plan.len(), 2);
+        assert!(report.repaired.is_empty());
+
+        let report = db.repair(CheckLevel::Full).unwrap();
+        assert!(report.is_ok(), "{:?}", report.issues);
+        assert_eq!(report.repaired.len(), 2);
+        assert_eq!((report.documents, report.index_entries), (3, 3));
+        let users = db.collection("users");
+        assert_eq!(users.count(&Document::new()).unwrap(), 3);
+        assert_eq!(users.count(&doc("name", "alice")).unwrap(), 1);
+    }
+
+    // -------------------------------------
+    //          Checkpoint Tests
+    // -------------------------------------
+
+    #[test]
+    fn test_checkpoints_truncate_the_log() {
+        let dir = scratch_dir("checkpoint");
+        let wal_size = |dir: &PathBuf| -> u64 {
+            fs::read_dir(dir.join("wal"))
+                .unwrap()
+                .map(|entry| entry.unwrap().metadata().unwrap().len())
+                .sum()
+        };
+        {
+            let db = Database::open(&dir).unwrap();
+            let users = db.collection("users");
+            let mut stream = users.watch(&Document::new()).unwrap();
+            users.insert_one(user(1, "alice", 30)).unwrap();
+            users.insert_one(user(2, "bob", 25)).unwrap();
+
+            let lsn = db.checkpoint().unwrap();
+            assert_eq!(lsn, 2);
+            assert_eq!(db.checkpoint().unwrap(), lsn);
+            assert_eq!(db.checkpoint_stats().checkpoints, 1);
+            assert!(db.checkpoint_stats().reclaimed > 0);
+            // The stream had not read the removed changes
+            assert!(matches!(
+                stream.try_next(),
+                Err(DatabaseError::HistoryLost(3))
+            ));
+            let mut stream = users.watch(&Document::new()).unwrap();
+
+            // Commits checkpoint the log once it outgrows its limit
+            db.set_checkpoint_wal_size(2048);
+            for i in 3..50 {
+                users.insert_one(user(i, "user", i)).unwrap();
+            }
+            let stats = db.checkpoint_stats();
+            assert!(stats.checkpoints > 2, "{:?}", stats);
+            assert!(wal_size(&dir) < 4096);
+            assert!(stream.try_next().is_err());
+
+            // ... and so does the checkpointer, at its interval
+            db.set_checkpoint_wal_size(u64::MAX);
+            users.delete_one(&doc("_id", 1)).unwrap();
+            let before = db.checkpoint_stats().checkpoints;
+            db.set_checkpoint_interval(Duration::from_millis(20));
+            let deadline = SystemTime::now() + Duration::from_secs(5);
+            while db.checkpoint_stats().checkpoints == before {
+                assert!(SystemTime::now() < deadline, "no checkpoint was taken");
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+
+        let db = Database::open(&dir).unwrap();
+        let users = db.collection("users");
+        assert_eq!(users.count(&Document::new()).unwrap(), 48);
+        assert_eq!(users.find_one(&doc("_id", 1)).unwrap(), None);
+        assert_eq!(
+            users.find_one(&doc("_id", 2)).unwrap(),
+            Some(user(2, "bob", 25))
+        );
+    }
+
+    #[test]
+    fn test_write_concerns() {
+        let dir = scratch_dir("write-concern");
+        {
+            let db = Database::open(&dir).unwrap();
+            let users = db.collection("users");
+            users
+                .clone()
+                .write_concern(WriteConcern::Checkpointed)
+                .insert_one(user(1, "alice", 30))
+                .unwrap();
+            let stats = db.checkpoint_stats();
+            assert_eq!((stats.checkpoints, stats.lsn), (1, 1));
+
+            for (i, concern) in [WriteConcern::Memory, WriteConcern::Journaled]
+                .into_iter()
+                .enumerate()
+            {
+                let users = users.clone().write_concern(concern);
+                users.insert_one(user(i as i32 + 2, "user", 30)).unwrap();
+            }
+            let txn = db.begin();
+            txn.collection("users")
+                .insert_one(user(4, "dave", 40))
+                .unwrap();
+            txn.commit_with(WriteConcern::Journaled).unwrap();
+            assert_eq!(db.checkpoint_stats().checkpoints, 1);
+        }
+        let db = Database::open(&dir).unwrap();
+        assert_eq!(db.collection("users").count(&Document::new()).unwrap(), 4);
+    }
+
+    // -------------------------------------
+    //          Validator Tests
+    // -------------------------------------
+
+    #[test]
+    fn test_schema_validators() {
+        let db = Database::open(scratch_dir("validators")).unwrap();
+        let users = db.collection("users");
+        users.insert_one(user(1, "old", -5)).unwrap();
+
+        let mut rules = doc("age", doc("$gte", 0));
+        rules.insert("name", doc("$exists", true));
+        users.set_validator(Validator::new(rules.clone())).unwrap();
+        assert_eq!(
+            users.validator().unwrap(),
+            Some(Validator::new(rules.clone()))
+        );
+        assert!(users
+            .set_validator(Validator::new(doc("age", doc("$bad", 1))))
+            .is_err());
+
+        // Strict validators check inserts, updates and replacements
+        users.insert_one(user(2, "alice", 30)).unwrap();
+        assert!(matches!(
+            users.insert_many([user(3, "bob", 25), doc("_id", 4)]),
+            Err(DatabaseError::ValidationFailed(Value::Int32(4)))
+        ));
+        assert!(matches!(
+            users.update_one(&doc("_id", 2), &doc("$inc", doc("age", -40))),
+            Err(DatabaseError::ValidationFailed(_))
+        ));
+        assert!(matches!(
+            users.replace_one(&doc("_id", 2), &doc("age", 3)),
+            Err(DatabaseError::ValidationFailed(_))
+        ));
+        assert!(matches!(
+            users.update_one(&doc("_id", 1), &doc("$set", doc("name", "older"))),
+            Err(DatabaseError::ValidationFailed(_))
+        ));
+        assert_eq!(users.count(&Document::new()).unwrap(), 2);
+        assert_eq!(users.revalidate().unwrap(), vec![Value::Int32(1)]);
+
+        // Moderate validators leave invalid documents alone
+        let moderate = Validator::new(rules.clone()).level(ValidationLevel::Moderate);
+        users.set_validator(moderate).unwrap();
+        users
+            .update_one(&doc("_id", 1), &doc("$set", doc("name", "older")))
+            .unwrap();
+        assert!(users
+            .update_one(&doc("_id", 2), &doc("$unset", doc("name", "")))
+            .is_err());
+
+        // Warning validators let writes through and count them
+        users
+            .set_validator(Validator::new(rules).action(ValidationAction::Warn))
+            .unwrap();
+        users.insert_one(doc("_id", 4)).unwrap();
+        assert_eq!(
+            db.validation_stats(),
+            ValidationStats {
+                rejected: 5,
+                warned: 1
+            }
+        );
+        assert_eq!(
+            users.revalidate().unwrap(),
+            vec![Value::Int32(1), Value::Int32(4)]
+        );
+
+        users.remove_validator().unwrap();
+        assert_eq!(users.validator().unwrap(), None);
+        assert!(users.revalidate().unwrap().is_empty());
+        users.insert_one(doc("_id", 5)).unwrap();
+    }
+
+    // -------------------------------------
+    //          Catalog Tests
+    // -------------------------------------
+
+    #[test]
+    fn test_collection_catalog() {
+        let dir = scratch_dir("catalog");
+        let db = Database::open(&dir).unwrap();
+        let validator = Validator::new(doc("name", doc("$exists", true)));
+        let options = CollectionOptions::new()
+            .validator(validator.clone())
+            .versioned(true);
+        let users = db.create_collection_with("users", options).unwrap();
+        assert!(db.collection_info("missing").unwrap().is_none());
+        let info = db.collection_info("users").unwrap().unwrap();
+        assert!(info.created.is_some());
+        assert_eq!(info.validator, Some(validator.clone()));
+        assert!(info.versioned);
+        assert!(matches!(
+            db.create_collection("users"),
+            Err(DatabaseError::CollectionExists(_))
+        ));
+        for bad in ["", "$users", "a.$b"] {
+            assert!(matches!(
+                db.create_collection(bad),
+                Err(DatabaseError::InvalidCollectionName(_))
+            ));
+        }
+
+        // Collections written to are registered with the write, or with
+        // the transaction's commit
+        db.collection("logs").insert_one(doc("_id", 1)).unwrap();
+        let txn = db.begin();
+        txn.collection("pending").insert_one(doc("_id", 1)).unwrap();
+        txn.collection("aborted").insert_one(doc("_id", 1)).unwrap();
+        assert_eq!(db.list_collections().unwrap(), vec!["logs", "users"]);
+        // Both would create the collection, so the later write conflicts
+        let txn2 = db.begin();
+        assert!(matches!(
+            txn2.collection("pending").insert_one(doc("_id", 2)),
+            Err(DatabaseError::WriteConflict(_))
+        ));
+        txn.commit().unwrap();
+        assert_eq!(
+            db.list_collections().unwrap(),
+            vec!["aborted", "logs", "pending", "users"]
+        );
+        assert!(db
+            .collection_info("logs")
+            .unwrap()
+            .unwrap()
+            .created
+            .is_some());
+
+        users.create_index("name").unwrap();
+        users
+            .insert_many((0..5).map(|i| user(i, &format!("user{}", i), 20 + i)))
+            .unwrap();
+        assert!(matches!(
+            db.rename_collection("users", "logs"),
+            Err(DatabaseError::CollectionExists(_))
+        ));
+        assert!(matches!(
+            db.rename_collection("missing", "other"),
+            Err(DatabaseError::CollectionNotFound(_))
+        ));
+        db.rename_collection("users", "people").unwrap();
+        let people = db.collection("people");
+        assert_eq!(people.count(&Document::new()).unwrap(), 5);
+        assert_eq!(people.count(&doc("name", "user3")).unwrap(), 1);
+        let renamed = db.collection_info("people").unwrap().unwrap();
+        assert_eq!(
+            (renamed.created, &renamed.validator),
+            (info.created, &info.validator)
+        );
+        assert_eq!(renamed.indexes.len(), 1);
+        assert!(db.collection_info("users").unwrap().is_none());
+        assert_eq!(users.count(&Document::new()).unwrap(), 0);
+
+        assert!(db.drop_collection("people").unwrap());
+        assert!(!db.drop_collection("people").unwrap());
+        assert!(db.drop_collection("aborted").unwrap());
+        assert_eq!(people.count(&Document::new()).unwrap(), 0);
+        assert!(people.list_indexes().unwrap().is_empty());
+        assert_eq!(people.validator().unwrap(), None);
+        drop((users, people, db));
+
+        let db = Database::open(&dir).unwrap();
+        assert_eq!(db.list_collections().unwrap(), vec!["logs", "pending"]);
+        db.create_collection("people").unwrap();
+    }
+
+    #[test]
+    fn test_sharding() {
+        let dir = scratch_dir("sharding");
+        let a = Database::open(dir.join("a")).unwrap();
+        let b = Database::open(dir.join("b")).unwrap();
+        let router = Router::new(
+            Database::open(dir.join("config")).unwrap(),
+            [("a".to_string(), a.clone()), ("b".to_string(), b.clone())],
+        );
+        assert!(matches!(
+            router.chunks("users"),
+            Err(DatabaseError::NotSharded(_))
+        ));
+        router
+            .shard_collection("users", ShardKey::new(["name"]), 4)
+            .unwrap();
+        assert!(matches!(
+            router.shard_collection("users", ShardKey::new(["age"]), 4),
+            Err(DatabaseError::CollectionExists(_))
+        ));
+        let chunks = router.chunks("users").unwrap();
+        assert_eq!(chunks.len(), 4);
+        assert_eq!(chunks[0].start, 0);
+        assert_eq!(chunks[1].start, 1 << 62);
+        assert_eq!(chunks[1].shard, "b");
+
+        // Documents spread over both shards and come back merged by _id
+        let users = router.collection("users");
+        users
+            .insert_many((0..200).map(|i| user(i, &format!("user{}", i), i % 40)))
+            .unwrap();
+        let held = a.collection("users").count(&Document::new()).unwrap();
+        assert!(held > 0 && held < 200);
+        let ids: Vec<Value> = users
+            .find(&Document::new())
+            .unwrap()
+            .try_collect()
+            .unwrap()
+            .iter()
+            .map(|user| user.get("_id").unwrap().clone())
+            .collect();
+        assert_eq!(ids, (0..200).map(Value::from).collect::<Vec<_>>());
+        assert_eq!(users.count(&doc("age", 3)).unwrap(), 5);
+        assert_eq!(
+            users.find_one(&doc("name", "user7")).unwrap().unwrap(),
+            user(7, "user7", 7)
+        );
+
+        // Updates reach every shard but may not move documents
+        let result = users
+            .update_many(&doc("age", 3), &doc("$set", doc("age", 4)))
+            .unwrap();
+        assert_eq!(result.modified_count, 5);
+        assert!(matches!(
+            users.update_one(&doc("_id", 1), &doc("$set", doc("name", "x"))),
+            Err(DatabaseError::InvalidUpdate(_))
+        ));
+        assert_eq!(users.delete_many(&doc("age", 4)).unwrap().deleted_count, 10);
+
+        // Moving a chunk carries its documents and indexes with it
+        a.collection("users")
+            .create_index_with(IndexOptions::new().ascending("age").name("by_age"))
+            .unwrap();
+        let stats = router.move_chunk("users", 0, "b").unwrap();
+        assert_eq!(stats.copied, stats.deleted);
+        assert!(stats.copied > 0);
+        assert_eq!(router.chunks("users").unwrap()[0].shard, "b");
+        assert_eq!(users.count(&Document::new()).unwrap(), 190);
+        assert_eq!(
+            users.find_one(&doc("name", "user7")).unwrap().unwrap(),
+            user(7, "user7", 7)
+        );
+        assert!(b
+            .collection("users")
+            .list_indexes()
+            .unwrap()
+            .iter()
+            .any(|i| i.name == "by_age"));
+        assert!(matches!(
+            router.move_chunk("users", 1, "c"),
+            Err(DatabaseError::ShardNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_point_in_time_recovery() {
+        let dir = scratch_dir("recovery");
+        let archive = dir.join("archive");
+        let db = Database::open_archived(dir.join("live"), DirArchive::new(&archive)).unwrap();
+        let users = db.collection("users");
+        users
+            .create_index_with(IndexOptions::new().ascending("age").name("by_age"))
+            .unwrap();
+        users
+            .insert_many((0..10).map(|i| user(i, &format!("user{}", i), i)))
+            .unwrap();
+        let backup = db.backup(dir.join("backup")).unwrap();
+        assert!(backup.lsn.is_some());
+
+        users
+            .insert_many((10..20).map(|i| user(i, &format!("user{}", i), i)))
+            .unwrap();
+        users
+            .update_one(&doc("_id", 3), &doc("$set", doc("age", 30)))
+            .unwrap();
+        let before_mistake = SystemTime::now();
+        thread::sleep(Duration::from_millis(20));
+        users.delete_many(&Document::new()).unwrap();
+        db.checkpoint().unwrap();
+        db.seal_log().unwrap();
+
+        // Recovering to just before the bad delete brings every user back
+        let restored = Database::open(dir.join("by-time")).unwrap();
+        let stats = restored
+            .recover(
+                dir.join("backup"),
+                &archive,
+                RecoveryTarget::Time(before_mistake),
+            )
+            .unwrap();
+        assert_eq!(stats.backup.documents, 10);
+        assert_eq!(stats.records, 2);
+        let users = restored.collection("users");
+        assert_eq!(users.count(&Document::new()).unwrap(), 20);
+        assert_eq!(
+            users.find_one(&doc("age", 30)).unwrap().unwrap(),
+            user(3, "user3", 30)
+        );
+        assert!(users
+            .list_indexes()
+            .unwrap()
+            .iter()
+            .any(|i| i.name == "by_age"));
+
+        // A sequence number stops at that record, and one missing from the
+        // archive is an error
+        let restored = Database::open(dir.join("by-lsn")).unwrap();
+        let first = backup.lsn.unwrap();
+        let stats = restored
+            .recover(dir.join("backup"), &archive, RecoveryTarget::Lsn(first))
+            .unwrap();
+        assert_eq!((stats.records, stats.lsn), (1, Some(first)));
+        assert_eq!(
+            restored
+                .collection("users")
+                .count(&Document::new())
+                .unwrap(),
+            20
+        );
+        let restored = Database::open(dir.join("gap")).unwrap();
+        fs::create_dir_all(dir.join("empty")).unwrap();
+        assert!(matches!(
+            restored.recover(
+                dir.join("backup"),
+                dir.join("empty"),
+                RecoveryTarget::Lsn(first + 10)
+            ),
+            Err(DatabaseError::LogGap(lsn)) if lsn == first
+        ));
+    }
+
+    /// A writer that commits changes to `db` the first time it is written
+    /// to, as if they raced a streamed backup.
+    struct RacingWriter {
+        db: Database,
+        out: Vec<u8>,
+        raced: bool,
+    }
+
+    impl std::io::Write for RacingWriter {
+        fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+            if !self.raced {
+                self.raced = true;
+                let users = self.db.collection("users");
+                users.insert_one(user(1000, "late", 1)).unwrap();
+                users.delete_one(&doc("_id", 0)).unwrap();
+                self.db.checkpoint().unwrap();
+            }
+            self.out.write(bytes)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_stream_backup() {
+        let dir = scratch_dir("stream-backup");
+        let db = Database::open(dir.join("source")).unwrap();
+        let users = db.collection("users");
+        users
+            .create_index_with(IndexOptions::new().ascending("age").name("by_age"))
+            .unwrap();
+        users
+            .insert_many((0..500).map(|i| user(i, &format!("user{}", i), i % 40)))
+            .unwrap();
+
+        // Changes made while the snapshot is written arrive in the tail
+        let mut out = RacingWriter {
+            db: db.clone(),
+            out: Vec::new(),
+            raced: false,
+        };
+        let stats = db.stream_backup(&mut out).unwrap();
+        assert!(out.raced);
+        assert_eq!((stats.collections, stats.documents), (1, 500));
+
+        let restored = Database::open(dir.join("restored")).unwrap();
+        let loaded = restored.restore_stream(out.out.as_slice()).unwrap();
+        assert_eq!(loaded.documents, 500);
+        assert_eq!(loaded.lsn, stats.lsn);
+        let users = restored.collection("users");
+        assert_eq!(users.count(&Document::new()).unwrap(), 500);
+        assert!(users.find_one(&doc("_id", 0)).unwrap().is_none());
+        assert_eq!(
+            users.find_one(&doc("name", "late")).unwrap().unwrap(),
+            user(1000, "late", 1)
+        );
+        assert!(users
+            .list_indexes()
+            .unwrap()
+            .iter()
+            .any(|i| i.name == "by_age"));
+
+        // A stream cut short is reported once its end is reached
+        let cut = &out.out[..out.out.len() / 2];
+        let partial = Database::open(dir.join("partial")).unwrap();
+        assert!(partial.restore_stream(cut).is_err());
+    }
+
+    #[test]
+    fn test_external_sort() {
+        let dir = scratch_dir("sort");
+        let db = Database::open(&dir).unwrap();
+        let users = db.collection("users");
+        users
+            .insert_many((0..300).map(|i| user(i, &format!("user{}", (i * 7) % 300), i % 13)))
+            .unwrap();
+        let mut expected: Vec<(i32, String)> = (0..300)
+            .map(|i| (i % 13, format!("user{}", (i * 7) % 300)))
+            .collect();
+        expected.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+
+        // A budget of a few documents spills many runs, merged in order
+        let mut sort = Document::new();
+        sort.insert("age", -1);
+        sort.insert("name", 1);
+        let mut projection = doc("name", 1);
+        projection.insert("_id", 0);
+        let options = FindOptions::new()
+            .sort(sort.clone())
+            .projection(projection)
+            .sort_memory(500);
+        let names: Vec<Document> = users
+            .find_with(&Document::new(), &options)
+            .unwrap()
+            .try_collect()
+            .unwrap();
+        let expected_names: Vec<Document> = expected
+            .iter()
+            .map(|(_, name)| doc("name", name.as_str()))
+            .collect();
+        assert_eq!(names, expected_names);
+
+        let mut external = ExternalSort::new(SortSpec::new(&sort).unwrap(), None, 500);
+        for document in users.find(&Document::new()).unwrap() {
+            external.push(document.unwrap()).unwrap();
+        }
+        assert!(external.runs() > 10);
+        let sorted: Vec<Document> = external.finish().unwrap().map(Result::unwrap).collect();
+        assert_eq!(sorted.len(), 300);
+
+        // Filters apply before sorting, and ties keep _id order
+        let options = FindOptions::new().sort(doc("age", 1));
+        let ids: Vec<Value> = users
+            .find_with(&doc("age", 12), &options)
+            .unwrap()
+            .map(|user| user.unwrap().get("_id").unwrap().clone())
+            .collect();
+        let expected_ids: Vec<Value> = (0..300).filter(|i| i % 13 == 12).map(Value::from).collect();
+        assert_eq!(ids, expected_ids);
+
+        assert!(matches!(
+            users.find_with(&Document::new(), &FindOptions::new().sort(doc("age", 2))),
+            Err(DatabaseError::Query(QueryError::InvalidSort(_)))
+        ));
+    }
+
+    #[test]
+    fn test_scheduler() {
+        let db = Database::open(scratch_dir("scheduler")).unwrap();
+        let status = |task: MaintenanceTask| {
+            db.task_status()
+                .into_iter()
+                .find(|status| status.get("task") == Some(&Value::from(task.name())))
+                .unwrap()
+        };
+        assert_eq!(db.task_status().len(), MaintenanceTask::ALL.len());
+        assert_eq!(
+            status(MaintenanceTask::Compaction).get("enabled"),
+            Some(&Value::Boolean(false))
+        );
+        assert!(status(MaintenanceTask::Checkpoint).get("lastRun").is_none());
+
+        // Runs on demand are recorded with what they did
+        db.collection("users")
+            .insert_one(user(1, "ann", 30))
+            .unwrap();
+        let result = db.run_task(MaintenanceTask::StatsRefresh).unwrap();
+        assert_eq!(result, doc("collections", 1i64));
+        let last_run = status(MaintenanceTask::StatsRefresh);
+        let last_run = last_run.get("lastRun").unwrap().as_document().unwrap();
+        assert_eq!(last_run.get("ok"), Some(&Value::Boolean(true)));
+        assert_eq!(last_run.get("result"), Some(&Value::Document(result)));
+
+        // Disabled tasks wait, whatever their interval, until enabled
+        db.set_task_enabled(MaintenanceTask::Checkpoint, false);
+        db.set_task_jitter(MaintenanceTask::Checkpoint, 0.0);
+        db.set_checkpoint_interval(Duration::from_millis(5));
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(db.checkpoint_stats().checkpoints, 0);
+        db.set_task_enabled(MaintenanceTask::Checkpoint, true);
+        for _ in 0..200 {
+            if db.checkpoint_stats().checkpoints > 0 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(db.checkpoint_stats().checkpoints > 0);
+        let runs = status(MaintenanceTask::Checkpoint);
+        assert!(matches!(runs.get("runs"), Some(Value::Int64(runs)) if *runs > 0));
+        assert_eq!(runs.get("failures"), Some(&Value::Int64(0)));
+    }
+
+    #[test]
+    fn test_deadlock_detection() {
+        let db = Database::open(scratch_dir("deadlock")).unwrap();
+        db.collection("users")
+            .insert_one(user(1, "ann", 30))
+            .unwrap();
+        db.collection("orders").insert_one(doc("_id", 1)).unwrap();
+
+        let older = db.begin();
+        let younger = db.begin();
+        older.lock_collection("users", LockMode::Shared).unwrap();
+        younger.lock_collection("orders", LockMode::Shared).unwrap();
+        // Shared locks keep out writes made outside any transaction
+        let result = db.collection("users").insert_one(user(2, "bob", 25));
+        assert!(matches!(result, Err(DatabaseError::WriteConflict(_))));
+
+        let (older_id, younger_id) = (older.id(), younger.id());
+        let waiter = thread::spawn(move || {
+            let result = younger.collection("users").insert_one(user(3, "cat", 41));
+            (result, younger)
+        });
+        while db.lock_stats().waits == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        // Closing the cycle aborts its youngest transaction, which was waiting
+        older
+            .collection("orders")
+            .insert_one(doc("_id", 2))
+            .unwrap();
+        let (result, younger) = waiter.join().unwrap();
+        match result {
+            Err(DatabaseError::Deadlock { victim, cycle }) => {
+                assert_eq!(victim, younger_id);
+                assert_eq!(cycle, vec![older_id, younger_id]);
+            }
+            other => panic!("expected a deadlock, got {:?}", other),
+        }
+        assert!(matches!(
+            younger.commit(),
+            Err(DatabaseError::NoSuchTransaction(_))
+        ));
+        older.commit().unwrap();
+        assert_eq!(db.collection("orders").count(&Document::new()).unwrap(), 2);
+        assert_eq!(db.lock_stats().deadlocks, 1);
+
+        // Waits give up after the lock timeout; reads take no locks
+        db.set_lock_timeout(Duration::from_millis(20));
+        let holder = db.begin();
+        holder
+            .lock_collection("users", LockMode::Exclusive)
+            .unwrap();
+        let other = db.begin();
+        let users = other.collection("users");
+        assert!(users.find_one(&Document::new()).unwrap().is_some());
+        let result = users.insert_one(user(4, "dan", 19));
+        assert!(matches!(result, Err(DatabaseError::LockTimeout(_))));
+        assert!(matches!(
+            other.commit(),
+            Err(DatabaseError::NoSuchTransaction(_))
+        ));
+        assert_eq!(db.lock_stats().timeouts, 1);
+    }
+
+    #[test]
+    fn test_import_export() {
+        let db = Database::open(scratch_dir("import-export")).unwrap();
+        let users = db.collection("users");
+        let mut ann = user(1, "ann", 30);
+        ann.insert(
+            "tags",
+            Array::from(vec![Value::from("a"), Value::from("b")]),
+        );
+        users
+            .insert_many([ann, user(2, "bob", 25), user(3, "cat", 41)])
+            .unwrap();
+        let all = |name: &str| {
+            db.collection(name)
+                .find(&Document::new())
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap()
+        };
+
+        // JSON and BSON round trip every document whole
+        for (format, copy) in [(Format::Ndjson, "json"), (Format::BsonSeq, "bson")] {
+            let mut out = Vec::new();
+            let exported = users
+                .export(&mut out, format, &Document::new(), None)
+                .unwrap();
+            assert_eq!(exported, 3);
+            let stats = db.collection(copy).import(out.as_slice(), format).unwrap();
+            assert_eq!((stats.records, stats.inserted), (3, 3));
+            assert_eq!(all(copy), all("users"));
+        }
+
+        let mut projection = Document::new();
+        projection.insert("name", 1);
+        projection.insert("age", 1);
+        let mut out = Vec::new();
+        users
+            .export(&mut out, Format::Csv, &doc("age", 30), Some(&projection))
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "_id,name,age\n1,\"ann\",30\n"
+        );
+
+        // The third record has a field too few, the fifth a taken _id
+        let csv = "_id,name,address.city\n\
+                   1,\"ann\",Paris\n\
+                   2,bob,\"Oslo, Norway\"\n\
+                   4,\"dan\"\n\
+                   3,\"007\",\n\
+                   1,\"dup\",Rome\n";
+        let progress = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&progress);
+        let options = ImportOptions::new()
+            .batch_size(2)
+            .on_error(ErrorPolicy::Skip)
+            .progress(move |stats| seen.lock().unwrap().push(stats.inserted));
+        let people = db.collection("people");
+        let stats = people
+            .import_with(csv.as_bytes(), Format::Csv, &options)
+            .unwrap();
+        assert_eq!((stats.records, stats.inserted, stats.skipped), (5, 3, 2));
+        let skipped: Vec<u64> = stats.errors.iter().map(|(record, _)| *record).collect();
+        assert_eq!(skipped, vec![3, 5]);
+        assert_eq!(*progress.lock().unwrap(), vec![2, 3]);
+        let bob = people.find_one(&doc("_id", 2)).unwrap().unwrap();
+        assert_eq!(bob.get("name"), Some(&Value::from("bob")));
+        assert_eq!(
+            bob.get("address"),
+            Some(&Value::Document(doc("city", "Oslo, Norway")))
+        );
+        // Quoted cells stay strings, and empty ones leave the field out
+        let cat = people.find_one(&doc("_id", 3)).unwrap().unwrap();
+        assert_eq!(cat.get("name"), Some(&Value::from("007")));
+        assert!(cat.get("address").is_none());
+
+        let options = ImportOptions::new().batch_size(2);
+        let result = db
+            .collection("strict")
+            .import_with(csv.as_bytes(), Format::Csv, &options);
+        assert!(matches!(
+            result,
+            Err(DatabaseError::ImportFailed { record: 3, .. })
+        ));
+        assert_eq!(db.collection("strict").count(&Document::new()).unwrap(), 2);
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_export_parquet() {
+        use arrow_array::cast::AsArray;
+        use arrow_array::types::{Float64Type, Int32Type};
+        use arrow_schema::DataType;
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+        use crate::db::{NestedPolicy, ParquetOptions};
+
+        let dir = scratch_dir("parquet");
+        let db = Database::open(dir.join("db")).unwrap();
+        let users = db.collection("users");
+        let mut ann = user(1, "ann", 30);
+        ann.insert("address", doc("city", "Paris"));
+        let mut bob = user(2, "bob", 25);
+        bob.insert("score", 2.5);
+        let mut cat = user(3, "cat", 41);
+        cat.insert("score", 4);
+        cat.insert("address", doc("city", "Oslo"));
+        users.insert_many([ann, bob, cat]).unwrap();
+
+        // Ints widen to doubles beside them, and missing fields are null
+        let path = dir.join("users.parquet");
+        let options = ParquetOptions::new().row_group_size(2);
+        let stats = users.export_parquet(&path, &options).unwrap();
+        assert_eq!((stats.documents, stats.row_groups), (3, 2));
+        assert_eq!(
+            stats.columns,
+            ["_id", "name", "age", "address.city", "score"]
+        );
+        let read = |path: &PathBuf| {
+            ParquetRecordBatchReaderBuilder::try_new(fs::File::open(path).unwrap())
+                .unwrap()
+                .build()
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap()
+        };
+        let batches = read(&path);
+        let schema = batches[0].schema();
+        assert_eq!(schema.field(0).data_type(), &DataType::Int32);
+        assert_eq!(schema.field(4).data_type(), &DataType::Float64);
+        let mut ages = Vec::new();
+        let mut scores = Vec::new();
+        let mut cities = Vec::new();
+        for batch in &batches {
+            ages.extend(batch.column(2).as_primitive::<Int32Type>().iter());
+            scores.extend(batch.column(4).as_primitive::<Float64Type>().iter());
+            cities.extend(
+                batch
+                    .column(3)
+                    .as_string::<i32>()
+                    .iter()
+                    .map(|city| city.map(String::from)),
+            );
+        }
+        assert_eq!(ages, [Some(30), Some(25), Some(41)]);
+        assert_eq!(scores, [None, Some(2.5), Some(4.0)]);
+        assert_eq!(
+            cities,
+            [Some("Paris".to_string()), None, Some("Oslo".to_string())]
+        );
+
+        // Embedded documents kept whole become JSON text
+        let options = ParquetOptions::new()
+            .filter(doc("name", "ann"))
+            .nested(NestedPolicy::Json);
+        let stats = users.export_parquet(&path, &options).unwrap();
+        assert_eq!(stats.columns, ["_id", "name", "age", "address"]);
+        let batches = read(&path);
+        let batch = &batches[0];
+        assert_eq!(batch.num_rows(), 1);
+        assert_eq!(
+            batch.column(3).as_string::<i32>().value(0),
+            r#"{"city":"Paris"}"#
+        );
+    }
+
+    #[test]
+    fn test_users() {
+        let dir = scratch_dir("users");
+        let db = Database::open(&dir).unwrap();
+        db.create_user(
+            "alice",
+            "wonderland",
+            &[Role::ReadWrite, Role::Read, Role::Read],
+        )
+        .unwrap();
+        db.create_user("bob", "builder", &[Role::Read]).unwrap();
+        assert!(matches!(
+            db.create_user("alice", "again", &[]),
+            Err(DatabaseError::UserExists(_))
+        ));
+        assert!(matches!(
+            db.create_user("", "nobody", &[]),
+            Err(DatabaseError::InvalidUserName(_))
+        ));
+        db.set_user_roles("bob", &[Role::DbAdmin]).unwrap();
+        assert!(matches!(
+            db.set_user_roles("carol", &[Role::Read]),
+            Err(DatabaseError::UserNotFound(_))
+        ));
+        // Users are kept out of the collections
+        assert!(db.list_collections().unwrap().is_empty());
+        drop(db);
+
+        let db = Database::open(&dir).unwrap();
+        let users = db.users().unwrap();
+        let names: Vec<&str> = users.iter().map(|user| user.name.as_str()).collect();
+        assert_eq!(names, vec!["alice", "bob"]);
+        assert_eq!(users[0].roles, vec![Role::Read, Role::ReadWrite]);
+        assert_eq!(users[1].roles, vec![Role::DbAdmin]);
+        let credential = db.user("alice").unwrap().unwrap().credential;
+        assert_ne!(
+            credential.salt,
+            db.user("bob").unwrap().unwrap().credential.salt
+        );
+        assert!(db.drop_user("bob").unwrap());
+        assert!(!db.drop_user("bob").unwrap());
+        assert_eq!(db.users().unwrap().len(), 1);
+        assert!(Role::ReadWrite.allows(crate::db::Action::Write));
+        assert!(!Role::Read.allows(crate::db::Action::Write));
+    }
+
+    #[test]
+    fn test_metrics() {
+        let db = Database::open(scratch_dir("metrics")).unwrap();
+        let users = db.collection("users");
+        users
+            .insert_many((0..10).map(|i| user(i, &"x".repeat(100), 20)))
+            .unwrap();
+        users.insert_one(user(10, "y", 30)).unwrap();
+        users
+            .update_many(&Document::new(), &doc("$inc", doc("age", 1)))
+            .unwrap();
+        users.delete_one(&doc("_id", 10)).unwrap();
+        assert_eq!(users.count(&Document::new()).unwrap(), 10);
+        let mut cursor = users.find(&Document::new()).unwrap().batch_size(4);
+        cursor.next().unwrap().unwrap();
+
+        let metrics = db.metrics();
+        let section = |name: &str| match metrics.get(name) {
+            Some(Value::Document(section)) => section.clone(),
+            other => panic!("bad {}: {:?}", name, other),
+        };
+        let ops = section("ops");
+        let op_count = |op: &str| match ops.get(op) {
+            Some(Value::Document(op)) => op.get("count").cloned(),
+            other => panic!("bad {}: {:?}", op, other),
+        };
+        assert_eq!(op_count("insert"), Some(Value::Int64(2)));
+        assert_eq!(op_count("update"), Some(Value::Int64(1)));
+        assert_eq!(op_count("delete"), Some(Value::Int64(1)));
+        assert_eq!(op_count("count"), Some(Value::Int64(1)));
+        assert_eq!(op_count("find"), Some(Value::Int64(1)));
+        assert_eq!(op_count("getmore"), Some(Value::Int64(1)));
+        let Some(Value::Document(insert)) = ops.get("insert") else {
+            unreachable!();
+        };
+        let Some(Value::Array(buckets)) = insert.get("buckets") else {
+            panic!("no buckets in {:?}", insert);
+        };
+        let Some(Value::Document(last)) = buckets.iter().last() else {
+            panic!("no last bucket in {:?}", buckets);
+        };
+        assert_eq!(last.get("le"), Some(&Value::Double(f64::INFINITY)));
+        assert_eq!(last.get("count"), Some(&Value::Int64(2)));
+        assert_eq!(section("cursors").get("open"), Some(&Value::Int64(1)));
+        assert!(section("cache").contains_key("hitRatio"));
+        assert!(matches!(section("wal").get("syncs"), Some(Value::Int64(n)) if *n > 0));
+        // Without a TrackingAllocator there is no memory to report
+        assert!(!metrics.contains_key("memory"));
+        // Replaced and deleted documents too large to keep inline are debt
+        // until compacted
+        let debt = |db: &Database| match db.metrics().get("compaction") {
+            Some(Value::Document(compaction)) => compaction.get("debt").cloned(),
+            other => panic!("bad compaction: {:?}", other),
+        };
+        assert!(matches!(debt(&db), Some(Value::Int64(n)) if n > 0));
+        drop(cursor);
+        db.compact(CompactionOptions::new()).unwrap();
+        assert_eq!(debt(&db), Some(Value::Int64(0)));
+
+        let text = db.prometheus_metrics();
+        assert!(text.contains("# TYPE silentdb_op_duration_seconds histogram\n"));
+        assert!(text.contains("silentdb_op_duration_seconds_bucket{op=\"insert\",le=\"+Inf\"} 2\n"));
+        assert!(text.contains("silentdb_op_duration_seconds_count{op=\"update\"} 1\n"));
+        assert!(text.contains("silentdb_cursors_open 0\n"));
+        assert!(text.contains("silentdb_compaction_debt_bytes 0\n"));
+        assert!(!text.contains("silentdb_memory_"));
+        assert!(text
+            .lines()
+            .all(|line| line.starts_with('#') || line.starts_with("silentdb_")));
+    }
+
+    #[test]
+    fn test_sql() {
+        let db = Database::open(scratch_dir("sql")).unwrap();
+        let users = db.collection("users");
+        let mut ann = user(1, "ann", 30);
+        ann.insert("address", doc("city", "Paris"));
+        users
+            .insert_many([
+                ann,
+                user(2, "bob", 25),
+                user(3, "cat", 41),
+                user(4, "dan", 35),
+            ])
+            .unwrap();
+        users.create_index("age").unwrap();
+
+        let rows = db
+            .sql("SELECT name FROM users WHERE age >= 30 ORDER BY age DESC LIMIT 2 OFFSET 1")
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(rows, vec![doc("name", "dan"), doc("name", "ann")]);
+        let rows = db
+            .sql(
+                "SELECT _id, address.city FROM users WHERE name LIKE 'a%' OR age < 26 ORDER BY _id",
+            )
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let mut ann = doc("_id", 1);
+        ann.insert("address", doc("city", "Paris"));
+        assert_eq!(rows, vec![ann, doc("_id", 2)]);
+
+        assert!(matches!(
+            db.sql("DELETE FROM users"),
+            Err(DatabaseError::Query(QueryError::InvalidSql(_)))
+        ));
+    }
+}
@@ -0,0 +1,469 @@
+// src/db/transaction.rs
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+
+use super::catalog::registrations;
+use super::collection::Collection;
+use super::concern::WriteConcern;
+use super::database::{DatabaseInner, Write};
+use super::error::DatabaseError;
+use super::lock::{LockManager, LockMode, LockResource};
+use crate::storage::{KeyRange, StorageEngine, StorageError};
+
+/// A namespace and a key in it.
+pub(crate) type Target = (String, Vec<u8>);
+
+/// A multi-document transaction.
+///
+/// A transaction reads from a snapshot of the database taken when it began,
+/// along with its own writes, and its writes stay invisible to everyone
+/// else until it commits. Commit logs every write as one record in the
+/// write-ahead log, so a transaction is applied in full or not at all, even
+/// across a crash.
+///
+/// Conflicts are first-writer-wins: writing a document that another open
+/// transaction has written, or that was changed by a commit since this
+/// transaction's snapshot, fails with `WriteConflict` and aborts this
+/// transaction. Writes outside any transaction to a document an open
+/// transaction has written fail the same way.
+///
+/// Writes also lock the collection they go to with intent to write, which
+/// only conflicts with whole-collection locks: those taken by
+/// `lock_collection` or `lock_database`, and by changes to a collection's
+/// indexes, validator, collation or versioning. A transaction waits for
+/// such a lock held by another, up to the database's lock timeout, then
+/// fails with `LockTimeout`. If it would wait for a transaction waiting on
+/// it, directly or through others, the youngest transaction of the cycle
+/// fails with `Deadlock` instead. Either failure aborts the transaction.
+/// Writes outside any transaction never wait; they fail with
+/// `WriteConflict`.
+///
+/// Dropping a transaction without committing it aborts it.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use silentdb::Database;
+/// # use silentdb_data_encoding::Document;
+/// let db = Database::open("data").unwrap();
+/// let txn = db.begin();
+/// let accounts = txn.collection("accounts");
+///
+/// let mut from = Document::new();
+/// from.insert("_id", "alice");
+/// let mut debit = Document::new();
+/// debit.insert("$inc", {
+///     let mut balance = Document::new();
+///     balance.insert("balance", -10);
+///     balance
+/// });
+/// accounts.update_one(&from, &debit).unwrap();
+/// txn.commit().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct Transaction {
+    id: u64,
+    inner: Arc<Mutex<DatabaseInner>>,
+    finished: bool,
+}
+
+impl Transaction {
+    pub(crate) fn new(id: u64, inner: Arc<Mutex<DatabaseInner>>) -> Self {
+        Transaction {
+            id,
+            inner,
+            finished: false,
+        }
+    }
+
+    /// Returns the transaction's id.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Returns a handle to the collection `name` that reads and writes
+    /// within this transaction.
+    ///
+    /// Once the transaction ends, operations on the handle fail with
+    /// `NoSuchTransaction`.
+    pub fn collection(&self, name: &str) -> Collection {
+        Collection::new(name, Arc::clone(&self.inner), Some(self.id))
+    }
+
+    /// Locks the collection `name` in `mode` until the transaction ends.
+    ///
+    /// A shared lock keeps other transactions and writes outside any
+    /// transaction from writing to the collection, while an exclusive one
+    /// also keeps other transactions from locking it at all.
+    ///
+    /// # Errors
+    ///
+    /// Returns `LockTimeout` if another transaction keeps the lock for
+    /// longer than the database's lock timeout, `Deadlock` if this
+    /// transaction is chosen to break a deadlock, or `NoSuchTransaction` if
+    /// the transaction was already aborted. The first two abort it.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use silentdb::{Database, LockMode};
+    /// # use silentdb_data_encoding::Document;
+    /// let db = Database::open("data").unwrap();
+    /// let txn = db.begin();
+    /// // Nothing else writes to accounts until the transaction ends
+    /// txn.lock_collection("accounts", LockMode::Shared).unwrap();
+    /// let count = txn.collection("accounts").count(&Document::new()).unwrap();
+    /// println!("{} accounts", count);
+    /// txn.commit().unwrap();
+    /// ```
+    pub fn lock_collection(&self, name: &str, mode: LockMode) -> Result<(), DatabaseError> {
+        lock(
+            &self.inner,
+            self.id,
+            LockResource::Collection(name.to_string()),
+            mode,
+        )
+    }
+
+    /// Locks the whole database in `mode` until the transaction ends. See
+    /// `lock_collection`.
+    ///
+    /// # Errors
+    ///
+    /// As `lock_collection`.
+    pub fn lock_database(&self, mode: LockMode) -> Result<(), DatabaseError> {
+        lock(&self.inner, self.id, LockResource::Database, mode)
+    }
+
+    /// Commits the transaction's writes atomically.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NoSuchTransaction` if the transaction was already aborted
+    /// by a write conflict, or an error if the writes cannot be logged or
+    /// applied.
+    pub fn commit(self) -> Result<(), DatabaseError> {
+        self.commit_with(WriteConcern::default())
+    }
+
+    /// Commits the transaction's writes atomically, returning once they
+    /// are as durable as `concern` asks.
+    ///
+    /// # Errors
+    ///
+    /// As `commit`.
+    pub fn commit_with(mut self, concern: WriteConcern) -> Result<(), DatabaseError> {
+        self.finished = true;
+        DatabaseInner::lock(&self.inner).commit_transaction(self.id, concern)
+    }
+
+    /// Aborts the transaction, discarding its writes.
+    pub fn abort(self) {}
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        if !self.finished {
+            if let Ok(mut inner) = self.inner.lock() {
+                inner.transactions.end(self.id);
+            }
+        }
+    }
+}
+
+/// A value a key held before a commit replaced it.
+#[derive(Debug)]
+struct Version {
+    /// The commit that replaced the value.
+    seq: u64,
+    /// The value, or `None` if the key did not exist.
+    before: Option<Vec<u8>>,
+}
+
+/// An open transaction's snapshot and buffered writes.
+#[derive(Debug)]
+struct TransactionState {
+    /// The last commit visible to the transaction.
+    snapshot: u64,
+    /// Buffered writes, with `None` for deletes.
+    writes: BTreeMap<Target, Option<Vec<u8>>>,
+    /// The documents written by `replace_one`.
+    replaced: BTreeSet<Target>,
+}
+
+/// The open transactions of a database, the old versions they may still
+/// read and the locks they hold.
+///
+/// Commits are numbered in order. While any transaction is open, each
+/// commit saves the values it replaces, forming a chain of versions per
+/// key that lets a transaction see every key as of its snapshot. Versions
+/// older than every open snapshot are dropped.
+///
+/// A transaction locks each key it writes exclusively. Those locks are
+/// never waited for: a transaction that waited for another's write would
+/// conflict with it once it committed.
+#[derive(Debug)]
+pub(crate) struct TransactionTable {
+    open: HashMap<u64, TransactionState>,
+    next_id: u64,
+    /// The number of the last commit.
+    committed: u64,
+    /// Saved versions per key, oldest first.
+    versions: BTreeMap<Target, Vec<Version>>,
+    /// Shared with transactions waiting for locks outside the database's
+    /// lock.
+    pub(crate) locks: Arc<LockManager>,
+}
+
+impl TransactionTable {
+    pub(crate) fn new() -> Self {
+        TransactionTable {
+            open: HashMap::new(),
+            next_id: 1,
+            committed: 0,
+            versions: BTreeMap::new(),
+            locks: Arc::new(LockManager::new()),
+        }
+    }
+
+    /// Opens a transaction over the current snapshot and returns its id.
+    pub(crate) fn begin(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.open.insert(
+            id,
+            TransactionState {
+                snapshot: self.committed,
+                writes: BTreeMap::new(),
+                replaced: BTreeSet::new(),
+            },
+        );
+        id
+    }
+
+    /// Numbers a commit of `writes`, first saving the values they replace
+    /// if any transaction may still need them.
+    pub(crate) fn preserve(
+        &mut self,
+        engine: &dyn StorageEngine,
+        writes: &[Write],
+    ) -> Result<(), StorageError> {
+        self.committed += 1;
+        if self.open.is_empty() {
+            return Ok(());
+        }
+        for write in writes {
+            let (namespace, key) = write.target();
+            let chain = self
+                .versions
+                .entry((namespace.to_string(), key.to_vec()))
+                .or_default();
+            if chain
+                .last()
+                .is_none_or(|version| version.seq != self.committed)
+            {
+                chain.push(Version {
+                    seq: self.committed,
+                    before: engine.get(namespace, key)?,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Ends the transaction `id`, which only read, such as one holding a
+    /// cursor's snapshot.
+    pub(crate) fn release(&mut self, id: u64) {
+        self.end(id);
+    }
+
+    /// Aborts the transaction `id`, discarding its writes.
+    pub(crate) fn abort(&mut self, id: u64) {
+        self.end(id);
+    }
+
+    /// Ends the transaction `id`, discarding its writes and releasing its
+    /// locks, and returns its state if it was open.
+    fn end(&mut self, id: u64) -> Option<TransactionState> {
+        let state = self.open.remove(&id)?;
+        self.locks.release_all(id);
+        // Versions replaced at or before every open snapshot are unreadable
+        match self.open.values().map(|state| state.snapshot).min() {
+            None => self.versions.clear(),
+            Some(oldest) => self.versions.retain(|_, chain| {
+                chain.retain(|version| version.seq > oldest);
+                !chain.is_empty()
+            }),
+        }
+        Some(state)
+    }
+
+    /// Checks that nothing but transaction `id`, if any, may write
+    /// `target`, and locks it for the transaction.
+    fn check_write(&self, id: Option<u64>, target: &Target) -> Result<(), DatabaseError> {
+        let resource = LockResource::Key(target.0.clone(), target.1.clone());
+        self.locks
+            .try_lock(id, resource.clone(), LockMode::Exclusive)
+            .map_err(|held| DatabaseError::WriteConflict(held.to_string()))?;
+        if let Some(snapshot) = id.and_then(|id| self.open.get(&id)).map(|s| s.snapshot) {
+            let changed = self
+                .versions
+                .get(target)
+                .is_some_and(|chain| chain.iter().any(|version| version.seq > snapshot));
+            if changed {
+                return Err(DatabaseError::WriteConflict(resource.to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the view of transaction `id`.
+    pub(crate) fn snapshot(&self, id: u64) -> Result<Snapshot<'_>, DatabaseError> {
+        let state = self
+            .open
+            .get(&id)
+            .ok_or(DatabaseError::NoSuchTransaction(id))?;
+        Ok(Snapshot { table: self, state })
+    }
+}
+
+/// The database as seen by an open transaction.
+pub(crate) struct Snapshot<'a> {
+    table: &'a TransactionTable,
+    state: &'a TransactionState,
+}
+
+impl Snapshot<'_> {
+    /// Returns what the transaction sees for `key` in place of the engine's
+    /// current value: its own write, or the value as of its snapshot, with
+    /// `None` for a missing key. Returns `None` if the engine's value is
+    /// what it sees.
+    pub(crate) fn visible(&self, namespace: &str, key: &[u8]) -> Option<Option<Vec<u8>>> {
+        let target = (namespace.to_string(), key.to_vec());
+        if let Some(write) = self.state.writes.get(&target) {
+            return Some(write.clone());
+        }
+        self.table
+            .versions
+            .get(&target)?
+            .iter()
+            .find(|version| version.seq > self.state.snapshot)
+            .map(|version| version.before.clone())
+    }
+
+    /// Returns the keys of `namespace` in `range` that the transaction may
+    /// see differently from the engine, in no particular order.
+    pub(crate) fn changed_keys(&self, namespace: &str, range: &KeyRange) -> Vec<Vec<u8>> {
+        let mut keys = keys_in(&self.table.versions, namespace, range);
+        keys.extend(keys_in(&self.state.writes, namespace, range));
+        keys
+    }
+}
+
+fn keys_in<V>(map: &BTreeMap<Target, V>, namespace: &str, range: &KeyRange) -> Vec<Vec<u8>> {
+    map.range((namespace.to_string(), Vec::new())..)
+        .take_while(|((n, _), _)| n == namespace)
+        .map(|((_, key), _)| key)
+        .filter(|key| range.contains(key))
+        .cloned()
+        .collect()
+}
+
+/// Takes `resource` in `mode` for the open transaction `id`, waiting for
+/// it without holding the database's lock, and aborts the transaction if
+/// it cannot be had.
+pub(crate) fn lock(
+    inner: &Mutex<DatabaseInner>,
+    id: u64,
+    resource: LockResource,
+    mode: LockMode,
+) -> Result<(), DatabaseError> {
+    let locks = {
+        let inner = DatabaseInner::lock(inner);
+        inner.transactions.snapshot(id)?;
+        Arc::clone(&inner.transactions.locks)
+    };
+    let locked = locks.lock(id, resource, mode);
+    if locked.is_err() {
+        DatabaseInner::lock(inner).transactions.abort(id);
+    }
+    locked
+}
+
+impl DatabaseInner {
+    /// Writes `writes` as part of transaction `txn`, or commits them at
+    /// once outside of any transaction. The documents at `replaced` are
+    /// reported to change streams as replaced, and writes committed at
+    /// once are made as durable as `concern` asks.
+    ///
+    /// A write conflict aborts the transaction.
+    pub(crate) fn write(
+        &mut self,
+        txn: Option<u64>,
+        mut writes: Vec<Write>,
+        replaced: BTreeSet<Target>,
+        concern: WriteConcern,
+    ) -> Result<(), DatabaseError> {
+        writes.extend(registrations(self, txn, &writes)?);
+        let targets: Vec<Target> = writes
+            .iter()
+            .map(|write| {
+                let (namespace, key) = write.target();
+                (namespace.to_string(), key.to_vec())
+            })
+            .collect();
+        if let Some(id) = txn {
+            self.transactions.snapshot(id)?;
+        }
+        for target in &targets {
+            if let Err(e) = self.transactions.check_write(txn, target) {
+                if let Some(id) = txn {
+                    self.transactions.end(id);
+                }
+                return Err(e);
+            }
+        }
+
+        let Some(id) = txn else {
+            return self.commit(writes, &replaced, concern);
+        };
+        let table = &mut self.transactions;
+        let state = table.open.get_mut(&id).expect("transaction checked above");
+        state.replaced.extend(replaced);
+        for (write, target) in writes.into_iter().zip(targets) {
+            let value = match write {
+                Write::Put { value, .. } => Some(value),
+                Write::Delete { .. } => None,
+            };
+            state.writes.insert(target, value);
+        }
+        Ok(())
+    }
+
+    /// Commits the writes of transaction `id` as one logged record, made as
+    /// durable as `concern` asks.
+    pub(crate) fn commit_transaction(
+        &mut self,
+        id: u64,
+        concern: WriteConcern,
+    ) -> Result<(), DatabaseError> {
+        let state = self
+            .transactions
+            .end(id)
+            .ok_or(DatabaseError::NoSuchTransaction(id))?;
+        let writes = state
+            .writes
+            .into_iter()
+            .map(|((namespace, key), value)| match value {
+                Some(value) => Write::Put {
+                    namespace,
+                    key,
+                    value,
+                },
+                None => Write::Delete { namespace, key },
+            })
+            .collect();
+        self.commit(writes, &state.replaced, concern)
+    }
+}
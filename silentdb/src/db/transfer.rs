@@ -0,0 +1,572 @@
+// src/db/transfer.rs
+
+use std::fmt;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::sync::Arc;
+
+use silentdb_data_encoding::{
+    to_writer, DeserializeError, Document, DocumentFileIterator, JsonSerializer, NdjsonReader,
+    Serializer, Value,
+};
+
+use super::collection::{Collection, FindOptions};
+use super::error::DatabaseError;
+use super::path::get_path;
+use super::projection::Projection;
+use crate::storage::StorageError;
+
+/// Documents inserted per batch by an import unless told otherwise.
+const DEFAULT_BATCH_SIZE: usize = 1000;
+/// Skipped records whose errors an import keeps.
+const MAX_REPORTED_ERRORS: usize = 100;
+
+/// A format documents are imported from and exported to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// One canonical Extended JSON document per line. Blank lines are
+    /// skipped on import.
+    Ndjson,
+    /// Encoded documents back to back, as `to_writer` writes them.
+    BsonSeq,
+    /// Comma-separated values, with a header row naming a dotted path for
+    /// each column.
+    ///
+    /// Strings are written quoted and read back as strings. Other cells
+    /// are read by their text: empty cells leave the field out, `true` and
+    /// `false` are booleans, and numbers are 32-bit integers if they fit,
+    /// else 64-bit integers, else doubles. Numbers and booleans are
+    /// written as such, missing fields and nulls as empty cells, and other
+    /// values as Extended JSON text, which is read back as a string.
+    Csv,
+}
+
+/// What an import does with a record that cannot be read or inserted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ErrorPolicy {
+    /// Stops the import with the record's error. The records before it
+    /// stay inserted.
+    #[default]
+    Abort,
+    /// Leaves the record out, counting it in `ImportStats::skipped`, and
+    /// carries on.
+    Skip,
+}
+
+/// What an import has done, as returned by `Collection::import` and passed
+/// to its progress callback after each batch.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportStats {
+    /// The number of records read.
+    pub records: u64,
+    /// The number of documents inserted.
+    pub inserted: u64,
+    /// The number of records skipped because of an error.
+    pub skipped: u64,
+    /// The number and error of each skipped record, counting records from
+    /// 1, up to the first 100.
+    pub errors: Vec<(u64, String)>,
+}
+
+/// The progress callback of an import, compared by identity.
+#[derive(Clone)]
+pub(crate) struct ProgressHook(Arc<dyn Fn(&ImportStats) + Send + Sync>);
+
+impl fmt::Debug for ProgressHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ProgressHook")
+    }
+}
+
+impl PartialEq for ProgressHook {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// Options for `Collection::import_with`.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb::{ErrorPolicy, ImportOptions};
+/// let options = ImportOptions::new()
+///     .batch_size(500)
+///     .on_error(ErrorPolicy::Skip)
+///     .progress(|stats| println!("{} imported", stats.inserted));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportOptions {
+    pub(crate) batch_size: usize,
+    pub(crate) on_error: ErrorPolicy,
+    pub(crate) progress: Option<ProgressHook>,
+}
+
+impl ImportOptions {
+    /// Creates options inserting 1000 documents per batch that stop at the
+    /// first bad record.
+    pub fn new() -> Self {
+        ImportOptions {
+            batch_size: DEFAULT_BATCH_SIZE,
+            on_error: ErrorPolicy::default(),
+            progress: None,
+        }
+    }
+
+    /// Sets how many documents are inserted per write.
+    pub fn batch_size(mut self, documents: usize) -> Self {
+        self.batch_size = documents.max(1);
+        self
+    }
+
+    /// Sets what is done with records that cannot be read or inserted.
+    pub fn on_error(mut self, policy: ErrorPolicy) -> Self {
+        self.on_error = policy;
+        self
+    }
+
+    /// Calls `progress` with what the import has done after each batch.
+    pub fn progress(mut self, progress: impl Fn(&ImportStats) + Send + Sync + 'static) -> Self {
+        self.progress = Some(ProgressHook(Arc::new(progress)));
+        self
+    }
+
+    /// Records the failure of record `record` with `error` in `stats`, or
+    /// returns it if the import stops at errors.
+    fn fail(
+        &self,
+        stats: &mut ImportStats,
+        record: u64,
+        error: DatabaseError,
+    ) -> Result<(), DatabaseError> {
+        match self.on_error {
+            ErrorPolicy::Abort => Err(DatabaseError::ImportFailed {
+                record,
+                source: Box::new(error),
+            }),
+            ErrorPolicy::Skip => {
+                stats.skipped += 1;
+                if stats.errors.len() < MAX_REPORTED_ERRORS {
+                    stats.errors.push((record, error.to_string()));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        ImportOptions::new()
+    }
+}
+
+/// Inserts the documents `reader` holds in `format` into `collection`.
+///
+/// A malformed document in a BSON sequence ends the input, as the
+/// documents after it cannot be found.
+pub(crate) fn import<R: Read>(
+    collection: &Collection,
+    reader: R,
+    format: Format,
+    options: &ImportOptions,
+) -> Result<ImportStats, DatabaseError> {
+    let reader = BufReader::new(reader);
+    match format {
+        Format::Ndjson => insert_all(
+            collection,
+            NdjsonReader::new(reader).map(|record| record.map_err(DatabaseError::from)),
+            options,
+        ),
+        Format::BsonSeq => insert_all(
+            collection,
+            DocumentFileIterator::new(reader).map(|record| record.map_err(DatabaseError::from)),
+            options,
+        ),
+        Format::Csv => insert_all(collection, CsvDocuments::new(reader), options),
+    }
+}
+
+fn insert_all(
+    collection: &Collection,
+    records: impl Iterator<Item = Result<Document, DatabaseError>>,
+    options: &ImportOptions,
+) -> Result<ImportStats, DatabaseError> {
+    let mut stats = ImportStats::default();
+    let mut batch = Vec::new();
+    for record in records {
+        stats.records += 1;
+        let number = stats.records;
+        match record {
+            Ok(document) => batch.push((number, document)),
+            Err(e) => options.fail(&mut stats, number, e)?,
+        }
+        if batch.len() >= options.batch_size {
+            insert_batch(collection, &mut batch, options, &mut stats)?;
+        }
+    }
+    if !batch.is_empty() {
+        insert_batch(collection, &mut batch, options, &mut stats)?;
+    }
+    Ok(stats)
+}
+
+/// Inserts `batch` as one write, or if that fails, each document in turn
+/// to find the ones at fault.
+fn insert_batch(
+    collection: &Collection,
+    batch: &mut Vec<(u64, Document)>,
+    options: &ImportOptions,
+    stats: &mut ImportStats,
+) -> Result<(), DatabaseError> {
+    let documents = batch.iter().map(|(_, document)| document.clone());
+    match collection.insert_many(documents) {
+        Ok(result) => {
+            stats.inserted += result.inserted_ids.len() as u64;
+            batch.clear();
+        }
+        Err(_) => {
+            for (record, document) in batch.drain(..) {
+                match collection.insert_one(document) {
+                    Ok(_) => stats.inserted += 1,
+                    Err(e) => options.fail(stats, record, e)?,
+                }
+            }
+        }
+    }
+    if let Some(ProgressHook(progress)) = &options.progress {
+        progress(stats);
+    }
+    Ok(())
+}
+
+/// Writes the documents of `collection` matching `filter` to `writer` in
+/// `format`, with only the fields `projection` selects of each, and
+/// returns how many were written.
+///
+/// CSV columns are the paths `projection` includes, or else the fields of
+/// the first document; other fields are left out.
+pub(crate) fn export<W: Write>(
+    collection: &Collection,
+    writer: W,
+    format: Format,
+    filter: &Document,
+    projection: Option<&Document>,
+) -> Result<u64, DatabaseError> {
+    let mut options = FindOptions::new();
+    let mut columns = None;
+    if let Some(projection) = projection {
+        options = options.projection(projection.clone());
+        if format == Format::Csv {
+            columns = Projection::new(projection)?
+                .paths()
+                .map(|paths| paths.map(str::to_string).collect::<Vec<_>>());
+        }
+    }
+    let cursor = collection.find_with(filter, &options)?;
+
+    let mut out = BufWriter::new(writer);
+    if let Some(columns) = &columns {
+        write_header(&mut out, columns)?;
+    }
+    let mut exported = 0;
+    for document in cursor {
+        let document = document?;
+        match format {
+            Format::Ndjson => {
+                JsonSerializer::canonical(&mut out, false).serialize_document(&document)?;
+                out.write_all(b"\n").map_err(StorageError::from)?;
+            }
+            Format::BsonSeq => to_writer(&mut out, &document)?,
+            Format::Csv => {
+                if columns.is_none() {
+                    let fields: Vec<String> = document
+                        .iter()
+                        .map(|(field, _)| field.to_string())
+                        .collect();
+                    write_header(&mut out, &fields)?;
+                    columns = Some(fields);
+                }
+                write_row(&mut out, columns.as_deref().unwrap_or_default(), &document)?;
+            }
+        }
+        exported += 1;
+    }
+    out.flush().map_err(StorageError::from)?;
+    Ok(exported)
+}
+
+fn write_header(out: &mut impl Write, columns: &[String]) -> Result<(), DatabaseError> {
+    let cells: Vec<String> = columns
+        .iter()
+        .map(|column| {
+            if column.contains([',', '"', '\r', '\n']) {
+                quote(column)
+            } else {
+                column.clone()
+            }
+        })
+        .collect();
+    writeln!(out, "{}", cells.join(",")).map_err(StorageError::from)?;
+    Ok(())
+}
+
+fn write_row(
+    out: &mut impl Write,
+    columns: &[String],
+    document: &Document,
+) -> Result<(), DatabaseError> {
+    let mut cells = Vec::with_capacity(columns.len());
+    for column in columns {
+        let cell = match get_path(document, column) {
+            None | Some(Value::Null) => String::new(),
+            Some(Value::String(text)) => quote(text),
+            Some(Value::Boolean(b)) => b.to_string(),
+            Some(Value::Int32(n)) => n.to_string(),
+            Some(Value::Int64(n)) => n.to_string(),
+            Some(Value::UInt64(n)) => n.to_string(),
+            // Debug keeps the fraction of whole numbers, so they read back
+            // as doubles
+            Some(Value::Double(n)) if n.is_finite() => format!("{:?}", n),
+            Some(value) => {
+                let mut json = JsonSerializer::new(Vec::new(), false);
+                value.serialize(&mut json)?;
+                quote(&String::from_utf8_lossy(&json.into_inner()))
+            }
+        };
+        cells.push(cell);
+    }
+    writeln!(out, "{}", cells.join(",")).map_err(StorageError::from)?;
+    Ok(())
+}
+
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('"', "\"\""))
+}
+
+/// A cell of a CSV record.
+#[derive(Debug)]
+struct Cell {
+    text: String,
+    quoted: bool,
+}
+
+impl Cell {
+    /// Returns the value the cell holds, or `None` if it is empty.
+    fn value(self) -> Option<Value> {
+        if self.quoted {
+            return Some(Value::String(self.text));
+        }
+        let text = self.text.trim();
+        if text.is_empty() {
+            return None;
+        }
+        let numeric = text.starts_with(|c: char| c.is_ascii_digit() || "+-.".contains(c));
+        Some(match text {
+            "true" => Value::Boolean(true),
+            "false" => Value::Boolean(false),
+            _ if !numeric => Value::String(text.to_string()),
+            _ => {
+                if let Ok(n) = text.parse::<i32>() {
+                    Value::Int32(n)
+                } else if let Ok(n) = text.parse::<i64>() {
+                    Value::Int64(n)
+                } else if let Ok(n) = text.parse::<f64>() {
+                    Value::Double(n)
+                } else {
+                    Value::String(text.to_string())
+                }
+            }
+        })
+    }
+}
+
+/// Reads the records of a CSV file as documents, taking the field paths
+/// from its first record.
+struct CsvDocuments<R: BufRead> {
+    reader: R,
+    header: Option<Vec<String>>,
+    /// The number of the last line read, counting from 1.
+    line: usize,
+    done: bool,
+}
+
+impl<R: BufRead> CsvDocuments<R> {
+    fn new(reader: R) -> Self {
+        CsvDocuments {
+            reader,
+            header: None,
+            line: 0,
+            done: false,
+        }
+    }
+
+    /// Returns the text of the next record, which spans several lines if
+    /// a quoted cell holds line breaks, or `None` at the end of the input.
+    fn read_record(&mut self) -> Option<Result<String, DatabaseError>> {
+        let mut record = String::new();
+        loop {
+            match self.reader.read_line(&mut record) {
+                Ok(0) => return (!record.is_empty()).then_some(Ok(record)),
+                Ok(_) => self.line += 1,
+                Err(e) => return Some(Err(self.invalid(e.into()))),
+            }
+            // A record ends at a line break outside quotes
+            if record.matches('"').count().is_multiple_of(2) {
+                return Some(Ok(record));
+            }
+        }
+    }
+
+    fn invalid(&self, error: DeserializeError) -> DatabaseError {
+        DeserializeError::Line {
+            line: self.line,
+            source: Box::new(error),
+        }
+        .into()
+    }
+
+    fn malformed(&self, message: impl Into<String>) -> DatabaseError {
+        self.invalid(DeserializeError::InvalidDocument(message.into()))
+    }
+
+    /// Checks the header's paths: none may be empty, name an operator,
+    /// repeat or lead to another.
+    fn parse_header(&self, cells: Vec<Cell>) -> Result<Vec<String>, DatabaseError> {
+        let paths: Vec<String> = cells
+            .into_iter()
+            .map(|cell| cell.text.trim().to_string())
+            .collect();
+        for (i, path) in paths.iter().enumerate() {
+            if path
+                .split('.')
+                .any(|segment| segment.is_empty() || segment.starts_with('$'))
+            {
+                return Err(self.malformed(format!("bad column name {:?}", path)));
+            }
+            for other in &paths[..i] {
+                let nested = |outer: &str, inner: &str| {
+                    inner
+                        .strip_prefix(outer)
+                        .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+                };
+                if nested(path, other) || nested(other, path) {
+                    return Err(self.malformed(format!("columns {} and {} overlap", other, path)));
+                }
+            }
+        }
+        Ok(paths)
+    }
+}
+
+impl<R: BufRead> Iterator for CsvDocuments<R> {
+    type Item = Result<Document, DatabaseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            let record = match self.read_record()? {
+                Ok(record) => record,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            };
+            let mut text = record.trim_end_matches(['\r', '\n']);
+            if self.header.is_none() {
+                text = text.trim_start_matches('\u{FEFF}');
+            }
+            if text.trim().is_empty() {
+                continue;
+            }
+            let cells = match parse_record(text) {
+                Ok(cells) => cells,
+                Err(message) => return Some(Err(self.malformed(message))),
+            };
+            let Some(header) = &self.header else {
+                // Without a header no record can be read
+                match self.parse_header(cells) {
+                    Ok(header) => self.header = Some(header),
+                    Err(e) => {
+                        self.done = true;
+                        return Some(Err(e));
+                    }
+                }
+                continue;
+            };
+            if cells.len() != header.len() {
+                return Some(Err(self.malformed(format!(
+                    "expected {} fields, found {}",
+                    header.len(),
+                    cells.len()
+                ))));
+            }
+            let mut document = Document::new();
+            for (path, cell) in header.iter().zip(cells) {
+                if let Some(value) = cell.value() {
+                    set_path(&mut document, path, value);
+                }
+            }
+            return Some(Ok(document));
+        }
+        None
+    }
+}
+
+/// Splits the text of a CSV record into its cells.
+fn parse_record(text: &str) -> Result<Vec<Cell>, String> {
+    let mut cells = Vec::new();
+    let mut chars = text.chars().peekable();
+    loop {
+        let mut cell = Cell {
+            text: String::new(),
+            quoted: chars.peek() == Some(&'"'),
+        };
+        if cell.quoted {
+            chars.next();
+            loop {
+                match chars.next() {
+                    None => return Err("unterminated quoted field".to_string()),
+                    Some('"') if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        cell.text.push('"');
+                    }
+                    Some('"') => break,
+                    Some(c) => cell.text.push(c),
+                }
+            }
+            cells.push(cell);
+            match chars.next() {
+                None => return Ok(cells),
+                Some(',') => {}
+                Some(c) => return Err(format!("unexpected {:?} after a quoted field", c)),
+            }
+        } else {
+            loop {
+                match chars.next() {
+                    None => {
+                        cells.push(cell);
+                        return Ok(cells);
+                    }
+                    Some(',') => break,
+                    Some('"') => return Err("quote inside an unquoted field".to_string()),
+                    Some(c) => cell.text.push(c),
+                }
+            }
+            cells.push(cell);
+        }
+    }
+}
+
+/// Sets the dotted `path` of `document` to `value`, creating the
+/// documents along it.
+fn set_path(document: &mut Document, path: &str, value: Value) {
+    let Some((head, rest)) = path.split_once('.') else {
+        document.insert(path, value);
+        return;
+    };
+    if !matches!(document.get(head), Some(Value::Document(_))) {
+        document.insert(head, Document::new());
+    }
+    if let Some(Value::Document(child)) = document.get_mut(head) {
+        set_path(child, rest, value);
+    }
+}
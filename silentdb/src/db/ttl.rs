@@ -0,0 +1,123 @@
+// src/db/ttl.rs
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use silentdb_data_encoding::{Array, Document, Value};
+
+use super::collection::Collection;
+use super::database::DatabaseInner;
+use super::error::DatabaseError;
+use super::path::get_path;
+
+/// Documents an expiration pass deletes per write.
+const TTL_BATCH: usize = 256;
+
+/// What the TTL reaper has done since the database was opened, as returned
+/// by `Database::ttl_stats`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TtlStats {
+    /// The number of expiration passes run.
+    pub passes: u64,
+    /// The number of documents deleted because they expired.
+    pub expired: u64,
+    /// How long past its expiry the oldest document deleted by the last
+    /// pass was, or zero if it deleted none.
+    pub lag: Duration,
+}
+
+/// The reaper's counters, kept with the database it serves. The reaper
+/// itself is a task of the database's scheduler.
+#[derive(Debug, Default)]
+pub(crate) struct TtlState {
+    stats: TtlStats,
+}
+
+impl TtlState {
+    pub(crate) fn stats(&self) -> TtlStats {
+        self.stats.clone()
+    }
+}
+
+/// Deletes every document of `inner` whose TTL has passed and returns how
+/// many were deleted.
+///
+/// A document expires once the earliest date at the path of a TTL index,
+/// plus the index's `expire_after`, is in the past; documents without a
+/// date there, or failing a partial index's filter, never expire. Each
+/// batch of deletes is written and unlocked on its own, so other operations
+/// are not held up by a large pass. Documents written by an open
+/// transaction are left for a later pass.
+pub(crate) fn expire(inner: &Arc<Mutex<DatabaseInner>>) -> Result<u64, DatabaseError> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs() as i64);
+    let collections: Vec<String> = DatabaseInner::lock(inner)
+        .namespaces()?
+        .iter()
+        .filter_map(|namespace| namespace.strip_suffix(".$indexes"))
+        .map(str::to_string)
+        .collect();
+
+    let mut expired = 0;
+    let mut lag = 0;
+    for name in collections {
+        let collection = Collection::new(&name, Arc::clone(inner), None);
+        for index in collection.list_indexes()? {
+            let (Some(expire_after), Some((path, _))) = (index.expire_after, index.fields.first())
+            else {
+                continue;
+            };
+            let cutoff = now.saturating_sub(expire_after.as_secs() as i64);
+            let mut clauses = vec![Value::Document(due(path, cutoff))];
+            clauses.extend(index.partial_filter.map(Value::Document));
+            let mut filter = Document::new();
+            filter.insert("$and", Array::from_vec(clauses));
+
+            if let Some(oldest) = collection.find_one(&filter)? {
+                if let Some(date) = get_path(&oldest, path).and_then(earliest_date) {
+                    lag = lag.max(cutoff - date);
+                }
+            }
+            loop {
+                let deleted = match collection.delete(&filter, TTL_BATCH) {
+                    Ok(result) => result.deleted_count,
+                    Err(DatabaseError::WriteConflict(_)) => break,
+                    Err(e) => return Err(e),
+                };
+                expired += deleted;
+                if deleted < TTL_BATCH as u64 {
+                    break;
+                }
+            }
+        }
+    }
+
+    let stats = &mut DatabaseInner::lock(inner).ttl.stats;
+    stats.passes += 1;
+    stats.expired += expired;
+    stats.lag = Duration::from_secs(lag.max(0) as u64);
+    Ok(expired)
+}
+
+/// Returns the filter for documents with a date at `path` no later than
+/// `cutoff`.
+fn due(path: &str, cutoff: i64) -> Document {
+    let mut bound = Document::new();
+    bound.insert("$lte", Value::UTCDateTime(cutoff));
+    let mut filter = Document::new();
+    filter.insert(path, bound);
+    filter
+}
+
+/// Returns the earliest date in `value`, itself or among its elements.
+fn earliest_date(value: &Value) -> Option<i64> {
+    let date = |value: &Value| match value {
+        Value::UTCDateTime(date) => Some(*date),
+        _ => None,
+    };
+    match value {
+        Value::Array(array) => array.iter().filter_map(date).min(),
+        value => date(value),
+    }
+}
@@ -0,0 +1,562 @@
+// src/db/typed.rs
+
+use std::fmt;
+use std::marker::PhantomData;
+
+use silentdb_data_encoding::{Array, Document, FromValue, IntoValue, Value};
+
+use super::collection::{
+    Collection, DeleteResult, FindOptions, InsertManyResult, InsertOneResult, UpdateResult,
+};
+use super::cursor::Cursor;
+use super::error::DatabaseError;
+
+/// A collection whose documents are values of `T`, as returned by
+/// `Collection::typed`.
+///
+/// Values are stored as the documents `IntoValue` converts them into and
+/// read back with `FromValue`, and filters and updates are built from
+/// `Field`s of `T`, so application code need not handle documents.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use silentdb::{Database, Field};
+/// # use silentdb_data_encoding::{Document, FromValue, IntoValue, Value, ValueConversionError};
+/// struct User {
+///     name: String,
+///     age: i32,
+/// }
+///
+/// impl User {
+///     const NAME: Field<User, String> = Field::new("name");
+///     const AGE: Field<User, i32> = Field::new("age");
+/// }
+///
+/// impl IntoValue for User {
+///     fn into_value(self) -> Value {
+///         let mut document = Document::new();
+///         document.insert("name", self.name);
+///         document.insert("age", self.age);
+///         Value::Document(document)
+///     }
+/// }
+///
+/// impl FromValue for User {
+///     fn from_value(value: &Value) -> Result<Self, ValueConversionError> {
+///         Self::from_document(&Document::from_value(value)?)
+///     }
+///
+///     fn from_document(document: &Document) -> Result<Self, ValueConversionError> {
+///         Ok(User {
+///             name: document.get_as("name")?,
+///             age: document.get_as("age")?,
+///         })
+///     }
+/// }
+///
+/// let db = Database::open("data").unwrap();
+/// let users = db.collection("users").typed::<User>();
+/// users.insert_one(User { name: "ada".into(), age: 36 }).unwrap();
+/// users
+///     .update_one(&User::NAME.eq("ada".into()), &User::AGE.inc(1))
+///     .unwrap();
+/// for user in users.find(&User::AGE.gte(18)).unwrap() {
+///     let user = user.unwrap();
+///     println!("{} is {}", user.name, user.age);
+/// }
+/// ```
+pub struct TypedCollection<T> {
+    collection: Collection,
+    marker: PhantomData<fn(T) -> T>,
+}
+
+impl<T> TypedCollection<T> {
+    pub(crate) fn new(collection: Collection) -> Self {
+        TypedCollection {
+            collection,
+            marker: PhantomData,
+        }
+    }
+
+    /// Returns the untyped collection, for the operations that take or
+    /// return documents.
+    pub fn untyped(&self) -> &Collection {
+        &self.collection
+    }
+
+    /// Returns the collection's name.
+    pub fn name(&self) -> &str {
+        self.collection.name()
+    }
+}
+
+impl<T: IntoValue + FromValue> TypedCollection<T> {
+    /// Inserts `value`, as `Collection::insert_one` does.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Conversion` if `value` does not convert into a document,
+    /// or the errors of `Collection::insert_one`.
+    pub fn insert_one(&self, value: T) -> Result<InsertOneResult, DatabaseError> {
+        self.collection.insert_one(to_document(value)?)
+    }
+
+    /// Inserts every value in `values` as one atomic write, as
+    /// `Collection::insert_many` does.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Conversion` if a value does not convert into a document,
+    /// in which case nothing is inserted, or the errors of
+    /// `Collection::insert_many`.
+    pub fn insert_many<I>(&self, values: I) -> Result<InsertManyResult, DatabaseError>
+    where
+        I: IntoIterator<Item = T>,
+    {
+        let documents = values
+            .into_iter()
+            .map(to_document)
+            .collect::<Result<Vec<_>, _>>()?;
+        self.collection.insert_many(documents)
+    }
+
+    /// Returns a cursor over the values matching `filter`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Query` if `filter` is invalid.
+    pub fn find(&self, filter: &Filter<T>) -> Result<TypedCursor<T>, DatabaseError> {
+        self.find_with(filter, &FindOptions::new())
+    }
+
+    /// Returns a cursor over the values matching `filter`, sorted as
+    /// `options` sort them. A projection must keep every field `T` needs.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Query` if `filter`, the projection or the sort is invalid.
+    pub fn find_with(
+        &self,
+        filter: &Filter<T>,
+        options: &FindOptions,
+    ) -> Result<TypedCursor<T>, DatabaseError> {
+        let cursor = self.collection.find_with(filter.as_document(), options)?;
+        Ok(TypedCursor::new(cursor))
+    }
+
+    /// Returns the first value matching `filter`, or `None` if none do.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Conversion` if the document found does not convert into a
+    /// `T`, or the errors of `Collection::find_one`.
+    pub fn find_one(&self, filter: &Filter<T>) -> Result<Option<T>, DatabaseError> {
+        match self.collection.find_one(filter.as_document())? {
+            Some(document) => Ok(Some(T::from_document(&document)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Replaces the first document matching `filter` with `replacement`,
+    /// keeping its `_id`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Conversion` if `replacement` does not convert into a
+    /// document, or the errors of `Collection::replace_one`.
+    pub fn replace_one(
+        &self,
+        filter: &Filter<T>,
+        replacement: T,
+    ) -> Result<UpdateResult, DatabaseError> {
+        let replacement = to_document(replacement)?;
+        self.collection
+            .replace_one(filter.as_document(), &replacement)
+    }
+
+    /// Applies `update` to the first document matching `filter`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of `Collection::update_one`.
+    pub fn update_one(
+        &self,
+        filter: &Filter<T>,
+        update: &Update<T>,
+    ) -> Result<UpdateResult, DatabaseError> {
+        self.collection
+            .update_one(filter.as_document(), update.as_document())
+    }
+
+    /// Applies `update` to every document matching `filter` as one atomic
+    /// write.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of `Collection::update_many`.
+    pub fn update_many(
+        &self,
+        filter: &Filter<T>,
+        update: &Update<T>,
+    ) -> Result<UpdateResult, DatabaseError> {
+        self.collection
+            .update_many(filter.as_document(), update.as_document())
+    }
+
+    /// Deletes the first document matching `filter`.
+    pub fn delete_one(&self, filter: &Filter<T>) -> Result<DeleteResult, DatabaseError> {
+        self.collection.delete_one(filter.as_document())
+    }
+
+    /// Deletes every document matching `filter` as one atomic write.
+    pub fn delete_many(&self, filter: &Filter<T>) -> Result<DeleteResult, DatabaseError> {
+        self.collection.delete_many(filter.as_document())
+    }
+
+    /// Returns the number of documents matching `filter`.
+    pub fn count(&self, filter: &Filter<T>) -> Result<u64, DatabaseError> {
+        self.collection.count(filter.as_document())
+    }
+}
+
+impl<T> Clone for TypedCollection<T> {
+    fn clone(&self) -> Self {
+        TypedCollection::new(self.collection.clone())
+    }
+}
+
+impl<T> fmt::Debug for TypedCollection<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TypedCollection")
+            .field("collection", &self.collection)
+            .finish()
+    }
+}
+
+/// Converts `value` into the document it is stored as.
+fn to_document<T: IntoValue>(value: T) -> Result<Document, DatabaseError> {
+    match value.into_value() {
+        Value::Document(document) => Ok(document),
+        // Fails, naming the type found instead
+        other => Ok(Document::from_value(&other)?),
+    }
+}
+
+/// A cursor over the values of a `TypedCollection`, converting each
+/// document as it is read.
+#[derive(Debug)]
+pub struct TypedCursor<T> {
+    cursor: Cursor,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T: FromValue> TypedCursor<T> {
+    fn new(cursor: Cursor) -> Self {
+        TypedCursor {
+            cursor,
+            marker: PhantomData,
+        }
+    }
+
+    /// Sets how many documents each batch fetches. See
+    /// `Cursor::batch_size`.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.cursor = self.cursor.batch_size(batch_size);
+        self
+    }
+
+    /// Reads every remaining value.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error a batch or a conversion fails with.
+    pub fn try_collect(self) -> Result<Vec<T>, DatabaseError> {
+        self.collect()
+    }
+}
+
+impl<T: FromValue> Iterator for TypedCursor<T> {
+    type Item = Result<T, DatabaseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let document = match self.cursor.next()? {
+            Ok(document) => document,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(T::from_document(&document).map_err(DatabaseError::from))
+    }
+}
+
+/// A field of the values of a `TypedCollection<T>`, holding values of `V`,
+/// from which filters and updates are built.
+///
+/// Fields are named by their dotted path in the documents `T` converts
+/// into, and usually declared as constants of `T`. Their operators take
+/// `V`, so a filter cannot compare a field with a value of another type.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb::{Field, Filter};
+/// # struct User;
+/// const AGE: Field<User, i32> = Field::new("age");
+/// const CITY: Field<User, String> = Field::new("address.city");
+///
+/// let filter: Filter<User> = AGE.gte(18).and(CITY.is_in(["Oslo".into(), "Lima".into()]));
+/// assert!(filter.as_document().contains_key("$and"));
+/// ```
+pub struct Field<T, V> {
+    path: &'static str,
+    marker: PhantomData<fn(&T) -> V>,
+}
+
+impl<T, V> Field<T, V> {
+    pub const fn new(path: &'static str) -> Self {
+        Field {
+            path,
+            marker: PhantomData,
+        }
+    }
+
+    /// Returns the field's dotted path.
+    pub fn path(&self) -> &'static str {
+        self.path
+    }
+
+    /// Matches values whose field is present, or absent if `exists` is
+    /// `false`.
+    pub fn exists(self, exists: bool) -> Filter<T> {
+        self.operator("$exists", Value::Boolean(exists))
+    }
+
+    /// Removes the field.
+    pub fn unset(self) -> Update<T> {
+        Update::operator("$unset", self.path, Value::String(String::new()))
+    }
+
+    fn operator(self, operator: &str, operand: Value) -> Filter<T> {
+        let mut operators = Document::new();
+        operators.insert(operator, operand);
+        Filter::field(self.path, Value::Document(operators))
+    }
+}
+
+impl<T, V: IntoValue> Field<T, V> {
+    /// Matches values whose field equals `value`.
+    pub fn eq(self, value: V) -> Filter<T> {
+        Filter::field(self.path, value.into_value())
+    }
+
+    /// Matches values whose field does not equal `value`.
+    pub fn ne(self, value: V) -> Filter<T> {
+        self.operator("$ne", value.into_value())
+    }
+
+    /// Matches values whose field is greater than `value`.
+    pub fn gt(self, value: V) -> Filter<T> {
+        self.operator("$gt", value.into_value())
+    }
+
+    /// Matches values whose field is at least `value`.
+    pub fn gte(self, value: V) -> Filter<T> {
+        self.operator("$gte", value.into_value())
+    }
+
+    /// Matches values whose field is less than `value`.
+    pub fn lt(self, value: V) -> Filter<T> {
+        self.operator("$lt", value.into_value())
+    }
+
+    /// Matches values whose field is at most `value`.
+    pub fn lte(self, value: V) -> Filter<T> {
+        self.operator("$lte", value.into_value())
+    }
+
+    /// Matches values whose field equals any of `values`.
+    pub fn is_in<I>(self, values: I) -> Filter<T>
+    where
+        I: IntoIterator<Item = V>,
+    {
+        let values = values.into_iter().map(IntoValue::into_value).collect();
+        self.operator("$in", Value::Array(Array::from_vec(values)))
+    }
+
+    /// Sets the field to `value`.
+    pub fn set(self, value: V) -> Update<T> {
+        Update::operator("$set", self.path, value.into_value())
+    }
+
+    /// Adds `value` to the field, treating it as zero if missing.
+    pub fn inc(self, value: V) -> Update<T> {
+        Update::operator("$inc", self.path, value.into_value())
+    }
+}
+
+impl<T, V> Clone for Field<T, V> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T, V> Copy for Field<T, V> {}
+
+impl<T, V> fmt::Debug for Field<T, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Field").field(&self.path).finish()
+    }
+}
+
+/// A filter on the values of a `TypedCollection<T>`, built from its
+/// `Field`s.
+///
+/// `Filter::new` matches every value, and a filter document written by
+/// hand converts into one with `From`.
+pub struct Filter<T> {
+    document: Document,
+    marker: PhantomData<fn(&T)>,
+}
+
+impl<T> Filter<T> {
+    /// Creates a filter matching every value.
+    pub fn new() -> Self {
+        Filter::from(Document::new())
+    }
+
+    fn field(path: &str, condition: Value) -> Self {
+        let mut document = Document::new();
+        document.insert(path, condition);
+        Filter::from(document)
+    }
+
+    /// Matches values both filters match.
+    pub fn and(self, other: Filter<T>) -> Self {
+        self.combine("$and", other)
+    }
+
+    /// Matches values either filter matches.
+    pub fn or(self, other: Filter<T>) -> Self {
+        self.combine("$or", other)
+    }
+
+    /// Joins two filters with `operator`, extending a filter that already
+    /// joins others with it rather than nesting.
+    fn combine(self, operator: &str, other: Filter<T>) -> Self {
+        // An empty filter matches everything, so `$or` with one does too,
+        // and `$and` with one is the other
+        if self.document.is_empty() || other.document.is_empty() {
+            if operator == "$or" {
+                return Filter::new();
+            }
+            return match self.document.is_empty() {
+                true => other,
+                false => self,
+            };
+        }
+        let mut operands = Vec::new();
+        for filter in [self.document, other.document] {
+            match filter.get(operator) {
+                Some(Value::Array(joined)) if filter.len() == 1 => {
+                    operands.extend(joined.iter().cloned())
+                }
+                _ => operands.push(Value::Document(filter)),
+            }
+        }
+        Filter::field(operator, Value::Array(Array::from_vec(operands)))
+    }
+
+    /// Returns the filter document.
+    pub fn as_document(&self) -> &Document {
+        &self.document
+    }
+
+    /// Returns the filter document, consuming the filter.
+    pub fn into_document(self) -> Document {
+        self.document
+    }
+}
+
+impl<T> Default for Filter<T> {
+    fn default() -> Self {
+        Filter::new()
+    }
+}
+
+impl<T> From<Document> for Filter<T> {
+    fn from(document: Document) -> Self {
+        Filter {
+            document,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T> Clone for Filter<T> {
+    fn clone(&self) -> Self {
+        Filter::from(self.document.clone())
+    }
+}
+
+impl<T> fmt::Debug for Filter<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Filter").field(&self.document).finish()
+    }
+}
+
+/// An update of the values of a `TypedCollection<T>`, built from its
+/// `Field`s and combined with `and`.
+pub struct Update<T> {
+    document: Document,
+    marker: PhantomData<fn(&T)>,
+}
+
+impl<T> Update<T> {
+    fn operator(operator: &str, path: &str, operand: Value) -> Self {
+        let mut fields = Document::new();
+        fields.insert(path, operand);
+        let mut document = Document::new();
+        document.insert(operator, fields);
+        Update {
+            document,
+            marker: PhantomData,
+        }
+    }
+
+    /// Applies both updates. If both change one field, `other`'s change
+    /// is the one applied.
+    pub fn and(mut self, other: Update<T>) -> Self {
+        for (operator, fields) in other.document.iter() {
+            match (self.document.get_mut(operator), fields) {
+                (Some(Value::Document(mine)), Value::Document(fields)) => {
+                    for (path, operand) in fields.iter() {
+                        mine.insert(path, operand.clone());
+                    }
+                }
+                _ => {
+                    self.document.insert(operator, fields.clone());
+                }
+            }
+        }
+        self
+    }
+
+    /// Returns the update document.
+    pub fn as_document(&self) -> &Document {
+        &self.document
+    }
+}
+
+impl<T> Clone for Update<T> {
+    fn clone(&self) -> Self {
+        Update {
+            document: self.document.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T> fmt::Debug for Update<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Update").field(&self.document).finish()
+    }
+}
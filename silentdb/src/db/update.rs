@@ -0,0 +1,121 @@
+// src/db/update.rs
+
+use silentdb_data_encoding::{apply_encoded_patch, from_bytes, to_bytes, Document, Patch, Value};
+
+use super::error::DatabaseError;
+use super::path::get_path;
+use super::planner::visit_fields;
+
+/// Applies an update document such as `{"$set": {"a": 1}, "$inc": {"n": 1}}`
+/// to `document`, whose encoding is `bytes`, and returns the new encoding.
+///
+/// Supported operators are `$set`, `$unset` and `$inc`, each taking a
+/// document of dotted paths. The `_id` field cannot be changed.
+pub(crate) fn apply_update(
+    bytes: &[u8],
+    document: &Document,
+    update: &Document,
+) -> Result<Vec<u8>, DatabaseError> {
+    if update.is_empty() {
+        return Err(DatabaseError::InvalidUpdate(
+            "update document is empty".into(),
+        ));
+    }
+
+    let mut patch = Patch::new();
+    for (operator, fields) in update.iter() {
+        let Value::Document(fields) = fields else {
+            return Err(DatabaseError::InvalidUpdate(format!(
+                "{} expects a document of fields",
+                operator
+            )));
+        };
+        for (path, value) in fields.iter() {
+            if path == "_id" || path.starts_with("_id.") {
+                return Err(DatabaseError::InvalidUpdate(
+                    "the _id field cannot be changed".into(),
+                ));
+            }
+            patch = match operator.as_str() {
+                "$set" => patch.set(path, value.clone()),
+                "$unset" => patch.unset(path),
+                "$inc" => patch.set(path, increment(get_path(document, path), value, path)?),
+                _ => {
+                    return Err(DatabaseError::InvalidUpdate(format!(
+                        "unknown update operator {}",
+                        operator
+                    )))
+                }
+            };
+        }
+    }
+    Ok(apply_encoded_patch(bytes, &patch)?)
+}
+
+/// Returns the document an upsert whose `filter` matched nothing starts
+/// from: the fields fixed by the filter's equality predicates, at the top
+/// level or inside `$and`.
+///
+/// # Errors
+///
+/// Returns `Patch` if the predicates' paths conflict, such as `a` and
+/// `a.b`.
+pub(crate) fn upsert_base(filter: &Document) -> Result<Document, DatabaseError> {
+    let mut fields = Vec::new();
+    visit_fields(filter, &mut |path, value| {
+        let value = match value {
+            Value::RegularExpression { .. } => return,
+            Value::Document(operators) if operators.iter().any(|(key, _)| key.starts_with('$')) => {
+                match operators.get("$eq") {
+                    Some(value) if operators.len() == 1 => value,
+                    _ => return,
+                }
+            }
+            value => value,
+        };
+        fields.push((path.to_string(), value.clone()));
+    });
+    let patch = fields
+        .into_iter()
+        .fold(Patch::new(), |patch, (path, value)| patch.set(&path, value));
+    let bytes = apply_encoded_patch(&to_bytes(&Document::new())?, &patch)?;
+    Ok(from_bytes(&bytes)?)
+}
+
+/// Returns `current + amount`, treating a missing field as zero.
+fn increment(current: Option<&Value>, amount: &Value, path: &str) -> Result<Value, DatabaseError> {
+    let not_numeric =
+        || DatabaseError::InvalidUpdate(format!("cannot $inc non-numeric field {}", path));
+    let current = current.unwrap_or(&Value::Int32(0));
+    if let (Some(a), Some(b)) = (integer(current), integer(amount)) {
+        let sum = a + b;
+        return Ok(match (current, amount) {
+            (Value::Int32(_), Value::Int32(_)) if i32::try_from(sum).is_ok() => {
+                Value::Int32(sum as i32)
+            }
+            _ if i64::try_from(sum).is_ok() => Value::Int64(sum as i64),
+            _ if u64::try_from(sum).is_ok() => Value::UInt64(sum as u64),
+            _ => Value::Double(sum as f64),
+        });
+    }
+    match (float(current), float(amount)) {
+        (Some(a), Some(b)) => Ok(Value::Double(a + b)),
+        _ => Err(not_numeric()),
+    }
+}
+
+fn integer(value: &Value) -> Option<i128> {
+    match value {
+        Value::Int32(n) => Some(*n as i128),
+        Value::Int64(n) => Some(*n as i128),
+        Value::UInt64(n) => Some(*n as i128),
+        _ => None,
+    }
+}
+
+fn float(value: &Value) -> Option<f64> {
+    match value {
+        Value::Double(n) => Some(*n),
+        value => integer(value).map(|n| n as f64),
+    }
+}
@@ -0,0 +1,360 @@
+// src/db/users.rs
+
+use std::collections::BTreeSet;
+
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use silentdb_data_encoding::{from_bytes, to_bytes, Array, Document, Value};
+
+use super::concern::WriteConcern;
+use super::database::{DatabaseInner, Write};
+use super::error::DatabaseError;
+use crate::storage::{KeyRange, StorageError};
+
+/// Namespace holding each user's roles and credential, keyed by name.
+const USERS_NAMESPACE: &str = "$system.$users";
+/// PBKDF2 iterations salting new passwords, MongoDB's default for
+/// SCRAM-SHA-256.
+const ITERATIONS: u32 = 15_000;
+/// Length of the random salt of a new password.
+const SALT_LEN: usize = 28;
+
+/// A role granted to a user, naming what it may do.
+///
+/// Roles are named as MongoDB's built-in roles of the same scope.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Role {
+    /// Find and count documents.
+    Read,
+    /// `Read`, and insert, update and delete documents.
+    ReadWrite,
+    /// `Read`, and manage collections and indexes.
+    DbAdmin,
+    /// Create, drop and list users.
+    UserAdmin,
+    /// Everything.
+    Root,
+}
+
+/// What a command does, for checking a user's roles allow it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    Read,
+    Write,
+    DbAdmin,
+    UserAdmin,
+}
+
+impl Role {
+    /// Every role, in order.
+    pub const ALL: [Role; 5] = [
+        Role::Read,
+        Role::ReadWrite,
+        Role::DbAdmin,
+        Role::UserAdmin,
+        Role::Root,
+    ];
+
+    /// Returns the role's name, such as `"readWrite"`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Role::Read => "read",
+            Role::ReadWrite => "readWrite",
+            Role::DbAdmin => "dbAdmin",
+            Role::UserAdmin => "userAdmin",
+            Role::Root => "root",
+        }
+    }
+
+    /// Returns the role named `name`, if there is one.
+    pub fn from_name(name: &str) -> Option<Role> {
+        Role::ALL.into_iter().find(|role| role.name() == name)
+    }
+
+    /// Returns whether the role allows `action`.
+    pub fn allows(&self, action: Action) -> bool {
+        matches!(
+            (self, action),
+            (Role::Root, _)
+                | (Role::Read, Action::Read)
+                | (Role::ReadWrite, Action::Read | Action::Write)
+                | (Role::DbAdmin, Action::Read | Action::DbAdmin)
+                | (Role::UserAdmin, Action::UserAdmin)
+        )
+    }
+}
+
+/// A user of the database, from `Database::users`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserInfo {
+    /// The name the user authenticates with.
+    pub name: String,
+    /// The roles granted to the user, in order.
+    pub roles: Vec<Role>,
+}
+
+/// What is kept of a password to check SCRAM-SHA-256 proofs of it against:
+/// the salt and iteration count it was salted with, and the keys derived
+/// from it, but neither the password nor its salted form.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Credential {
+    pub(crate) salt: Vec<u8>,
+    pub(crate) iterations: u32,
+    pub(crate) stored_key: [u8; 32],
+    pub(crate) server_key: [u8; 32],
+}
+
+impl Credential {
+    /// Creates a credential for `password` with a random salt.
+    pub(crate) fn new(password: &str) -> Credential {
+        let salt: [u8; SALT_LEN] = rand::thread_rng().gen();
+        Credential::derive(password, &salt, ITERATIONS)
+    }
+
+    /// Derives the credential for `password` with `salt` and `iterations`,
+    /// as RFC 5802 does. Passwords are used as given, without SASLprep.
+    pub(crate) fn derive(password: &str, salt: &[u8], iterations: u32) -> Credential {
+        let mut salted = [0; 32];
+        pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, iterations, &mut salted);
+        Credential {
+            salt: salt.to_vec(),
+            iterations,
+            stored_key: sha256(&hmac(&salted, b"Client Key")),
+            server_key: hmac(&salted, b"Server Key"),
+        }
+    }
+
+    /// Creates a credential for a user who does not exist, with a salt
+    /// that stays the same for `name` but keys no password derives, so
+    /// they can be challenged like any other without being let in.
+    pub(crate) fn unknown(name: &str) -> Credential {
+        let mut rng = rand::thread_rng();
+        Credential {
+            salt: sha256(name.as_bytes())[..SALT_LEN].to_vec(),
+            iterations: ITERATIONS,
+            stored_key: rng.gen(),
+            server_key: rng.gen(),
+        }
+    }
+
+    /// Checks `password` is the one the credential was derived from, for
+    /// clients that send it rather than prove they know it.
+    pub(crate) fn check(&self, password: &str) -> bool {
+        let derived = Credential::derive(password, &self.salt, self.iterations);
+        let diff = derived
+            .stored_key
+            .iter()
+            .zip(self.stored_key)
+            .fold(0, |diff, (a, b)| diff | (a ^ b));
+        diff == 0
+    }
+
+    /// Checks the SCRAM-SHA-256 client `proof` of the password against
+    /// `auth_message`, returning the server's signature of it if the proof
+    /// holds.
+    pub(crate) fn verify(&self, auth_message: &[u8], proof: &[u8]) -> Option<[u8; 32]> {
+        let signature = hmac(&self.stored_key, auth_message);
+        if proof.len() != signature.len() {
+            return None;
+        }
+        let client_key: Vec<u8> = proof.iter().zip(signature).map(|(a, b)| a ^ b).collect();
+        // Compared in time independent of where they differ, so a proof
+        // cannot be guessed a byte at a time
+        let diff = sha256(&client_key)
+            .iter()
+            .zip(self.stored_key)
+            .fold(0, |diff, (a, b)| diff | (a ^ b));
+        (diff == 0).then(|| hmac(&self.server_key, auth_message))
+    }
+}
+
+/// Returns the HMAC-SHA-256 of `data` under `key`.
+fn hmac(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+/// Returns the SHA-256 digest of `data`.
+fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+/// A user as stored: its roles and credential.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct UserRecord {
+    pub(crate) roles: Vec<Role>,
+    pub(crate) credential: Credential,
+}
+
+impl UserRecord {
+    fn to_document(&self) -> Document {
+        let roles = self.roles.iter().map(|role| Value::from(role.name()));
+        let mut document = Document::new();
+        document.insert("roles", Array::from_vec(roles.collect()));
+        document.insert("salt", Value::Binary(self.credential.salt.clone()));
+        document.insert("iterations", self.credential.iterations as i64);
+        document.insert(
+            "storedKey",
+            Value::Binary(self.credential.stored_key.to_vec()),
+        );
+        document.insert(
+            "serverKey",
+            Value::Binary(self.credential.server_key.to_vec()),
+        );
+        document
+    }
+
+    fn from_document(document: &Document) -> Option<UserRecord> {
+        let Some(Value::Array(names)) = document.get("roles") else {
+            return None;
+        };
+        let roles = names
+            .iter()
+            .map(|name| match name {
+                Value::String(name) => Role::from_name(name),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let binary = |field| match document.get(field) {
+            Some(Value::Binary(bytes)) => Some(bytes.clone()),
+            _ => None,
+        };
+        let Some(Value::Int64(iterations)) = document.get("iterations") else {
+            return None;
+        };
+        Some(UserRecord {
+            roles,
+            credential: Credential {
+                salt: binary("salt")?,
+                iterations: u32::try_from(*iterations).ok()?,
+                stored_key: binary("storedKey")?.try_into().ok()?,
+                server_key: binary("serverKey")?.try_into().ok()?,
+            },
+        })
+    }
+}
+
+/// Returns `roles` without duplicates, in order.
+fn normalize(roles: &[Role]) -> Vec<Role> {
+    roles
+        .iter()
+        .copied()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+fn put(inner: &mut DatabaseInner, name: &str, record: &UserRecord) -> Result<(), DatabaseError> {
+    let write = Write::Put {
+        namespace: USERS_NAMESPACE.to_string(),
+        key: name.as_bytes().to_vec(),
+        value: to_bytes(&record.to_document())?,
+    };
+    inner.write(None, vec![write], BTreeSet::new(), WriteConcern::Journaled)
+}
+
+/// Creates the user `name` with `password` and `roles`.
+///
+/// # Errors
+///
+/// Returns `InvalidUserName` if `name` is empty, or `UserExists` if there
+/// is a user `name` already.
+pub(crate) fn create(
+    inner: &mut DatabaseInner,
+    name: &str,
+    password: &str,
+    roles: &[Role],
+) -> Result<(), DatabaseError> {
+    if name.is_empty() {
+        return Err(DatabaseError::InvalidUserName(name.to_string()));
+    }
+    if inner.get(None, USERS_NAMESPACE, name.as_bytes())?.is_some() {
+        return Err(DatabaseError::UserExists(name.to_string()));
+    }
+    let record = UserRecord {
+        roles: normalize(roles),
+        credential: Credential::new(password),
+    };
+    put(inner, name, &record)
+}
+
+/// Removes the user `name`, returning `false` if there is none.
+pub(crate) fn drop(inner: &mut DatabaseInner, name: &str) -> Result<bool, DatabaseError> {
+    if inner.get(None, USERS_NAMESPACE, name.as_bytes())?.is_none() {
+        return Ok(false);
+    }
+    let write = Write::Delete {
+        namespace: USERS_NAMESPACE.to_string(),
+        key: name.as_bytes().to_vec(),
+    };
+    inner.write(None, vec![write], BTreeSet::new(), WriteConcern::Journaled)?;
+    Ok(true)
+}
+
+/// Replaces the roles of the user `name` with `roles`.
+///
+/// # Errors
+///
+/// Returns `UserNotFound` if there is no user `name`.
+pub(crate) fn set_roles(
+    inner: &mut DatabaseInner,
+    name: &str,
+    roles: &[Role],
+) -> Result<(), DatabaseError> {
+    let mut record =
+        record(inner, name)?.ok_or_else(|| DatabaseError::UserNotFound(name.to_string()))?;
+    record.roles = normalize(roles);
+    put(inner, name, &record)
+}
+
+/// Sets the password of the user `name` to `password`.
+///
+/// # Errors
+///
+/// Returns `UserNotFound` if there is no user `name`.
+pub(crate) fn set_password(
+    inner: &mut DatabaseInner,
+    name: &str,
+    password: &str,
+) -> Result<(), DatabaseError> {
+    let mut record =
+        record(inner, name)?.ok_or_else(|| DatabaseError::UserNotFound(name.to_string()))?;
+    record.credential = Credential::new(password);
+    put(inner, name, &record)
+}
+
+/// Returns the stored user `name`, if there is one.
+pub(crate) fn record(
+    inner: &DatabaseInner,
+    name: &str,
+) -> Result<Option<UserRecord>, DatabaseError> {
+    let Some(bytes) = inner.get(None, USERS_NAMESPACE, name.as_bytes())? else {
+        return Ok(None);
+    };
+    UserRecord::from_document(&from_bytes(&bytes)?)
+        .map(Some)
+        .ok_or_else(|| bad_user(name))
+}
+
+/// Returns every user, in name order.
+pub(crate) fn list(inner: &DatabaseInner) -> Result<Vec<UserInfo>, DatabaseError> {
+    inner
+        .scan(None, USERS_NAMESPACE, &KeyRange::all(), usize::MAX)?
+        .into_iter()
+        .map(|(key, value)| {
+            let name = String::from_utf8_lossy(&key).into_owned();
+            let record =
+                UserRecord::from_document(&from_bytes(&value)?).ok_or_else(|| bad_user(&name))?;
+            Ok(UserInfo {
+                name,
+                roles: record.roles,
+            })
+        })
+        .collect()
+}
+
+fn bad_user(name: &str) -> DatabaseError {
+    StorageError::corrupt(USERS_NAMESPACE, format!("bad entry for user {}", name)).into()
+}
@@ -0,0 +1,237 @@
+// src/db/validation.rs
+
+use silentdb_data_encoding::{from_bytes, to_bytes, Document, Value};
+
+use super::database::{DatabaseInner, Write};
+use super::error::DatabaseError;
+use crate::query::Matcher;
+
+/// Suffix of the namespace holding a collection's validator.
+pub(crate) const VALIDATOR_SUFFIX: &str = ".$validator";
+/// Key of the validator in its namespace.
+const VALIDATOR_KEY: &[u8] = b"validator";
+
+/// What happens to a write that leaves a document failing its collection's
+/// validator.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ValidationAction {
+    /// The write fails with `ValidationFailed` and changes nothing.
+    #[default]
+    Error,
+    /// The write goes ahead, and is counted in `ValidationStats::warned`.
+    Warn,
+}
+
+/// Which writes a collection's validator checks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ValidationLevel {
+    /// Every insert, update and replacement.
+    #[default]
+    Strict,
+    /// Inserts, and changes to documents that passed the validator before
+    /// them, so documents that were already invalid can still be updated.
+    Moderate,
+}
+
+/// The rules the documents of a collection must follow, attached with
+/// `Collection::set_validator`.
+///
+/// The rules are a query filter, as described for `Matcher`: a document is
+/// valid if it matches it.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb::{ValidationAction, Validator};
+/// # use silentdb_data_encoding::Document;
+/// let mut exists = Document::new();
+/// exists.insert("$exists", true);
+/// let mut age = Document::new();
+/// age.insert("$gte", 0);
+/// let mut rules = Document::new();
+/// rules.insert("name", exists);
+/// rules.insert("age", age);
+/// let validator = Validator::new(rules).action(ValidationAction::Warn);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Validator {
+    pub rules: Document,
+    pub action: ValidationAction,
+    pub level: ValidationLevel,
+}
+
+impl Validator {
+    /// Creates a strict validator that rejects writes failing `rules`.
+    pub fn new(rules: Document) -> Self {
+        Validator {
+            rules,
+            action: ValidationAction::default(),
+            level: ValidationLevel::default(),
+        }
+    }
+
+    /// Sets what happens to writes failing the rules.
+    pub fn action(mut self, action: ValidationAction) -> Self {
+        self.action = action;
+        self
+    }
+
+    /// Sets which writes are checked.
+    pub fn level(mut self, level: ValidationLevel) -> Self {
+        self.level = level;
+        self
+    }
+
+    pub(crate) fn to_document(&self) -> Document {
+        let mut document = Document::new();
+        document.insert("rules", self.rules.clone());
+        let action = match self.action {
+            ValidationAction::Error => "error",
+            ValidationAction::Warn => "warn",
+        };
+        document.insert("action", action);
+        let level = match self.level {
+            ValidationLevel::Strict => "strict",
+            ValidationLevel::Moderate => "moderate",
+        };
+        document.insert("level", level);
+        document
+    }
+
+    pub(crate) fn from_document(document: &Document) -> Option<Validator> {
+        let Some(Value::Document(rules)) = document.get("rules") else {
+            return None;
+        };
+        let action = match document.get("action") {
+            Some(Value::String(action)) if action == "error" => ValidationAction::Error,
+            Some(Value::String(action)) if action == "warn" => ValidationAction::Warn,
+            _ => return None,
+        };
+        let level = match document.get("level") {
+            Some(Value::String(level)) if level == "strict" => ValidationLevel::Strict,
+            Some(Value::String(level)) if level == "moderate" => ValidationLevel::Moderate,
+            _ => return None,
+        };
+        Some(Validator {
+            rules: rules.clone(),
+            action,
+            level,
+        })
+    }
+}
+
+/// How many writes validators have rejected or let through with a
+/// warning since the database was opened, as returned by
+/// `Database::validation_stats`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationStats {
+    /// Writes failed with `ValidationFailed`.
+    pub rejected: u64,
+    /// Documents written despite failing a validator set to warn.
+    pub warned: u64,
+}
+
+/// A collection's validator, ready to check documents.
+#[derive(Debug)]
+pub(crate) struct Rules {
+    validator: Validator,
+    matcher: Matcher,
+}
+
+impl Rules {
+    /// Returns whether `document` follows the rules.
+    pub(crate) fn passes(&self, document: &Document) -> bool {
+        self.matcher.matches(document)
+    }
+
+    /// Checks a write changing a document from `before` to `after`, where
+    /// `None` means no document.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ValidationFailed` if the write must be rejected.
+    pub(crate) fn check(
+        &self,
+        inner: &mut DatabaseInner,
+        before: Option<&Document>,
+        after: &Document,
+    ) -> Result<(), DatabaseError> {
+        let checked = match (self.validator.level, before) {
+            (ValidationLevel::Moderate, Some(before)) => self.passes(before),
+            _ => true,
+        };
+        if !checked || self.passes(after) {
+            return Ok(());
+        }
+        match self.validator.action {
+            ValidationAction::Error => {
+                inner.validation.rejected += 1;
+                let id = after.get("_id").cloned().unwrap_or(Value::Null);
+                Err(DatabaseError::ValidationFailed(id))
+            }
+            ValidationAction::Warn => {
+                inner.validation.warned += 1;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Returns the validator of `collection`, as seen by transaction `txn` if
+/// given.
+pub(crate) fn load_validator(
+    inner: &DatabaseInner,
+    txn: Option<u64>,
+    collection: &str,
+) -> Result<Option<Validator>, DatabaseError> {
+    let Some(bytes) = inner.get(txn, &validator_namespace(collection), VALIDATOR_KEY)? else {
+        return Ok(None);
+    };
+    let validator = Validator::from_document(&from_bytes(&bytes)?).ok_or_else(|| {
+        DatabaseError::InvalidValidator(format!("bad validator for {}", collection))
+    })?;
+    Ok(Some(validator))
+}
+
+/// Returns the validator of `collection` ready to check documents.
+pub(crate) fn load_rules(
+    inner: &DatabaseInner,
+    txn: Option<u64>,
+    collection: &str,
+) -> Result<Option<Rules>, DatabaseError> {
+    load_validator(inner, txn, collection)?
+        .map(|validator| {
+            let matcher = Matcher::new(&validator.rules)?;
+            Ok(Rules { validator, matcher })
+        })
+        .transpose()
+}
+
+/// Returns the write setting the validator of `collection`, or removing it
+/// if `validator` is `None`.
+///
+/// # Errors
+///
+/// Returns `Query` if the validator's rules are not a valid filter.
+pub(crate) fn validator_write(
+    collection: &str,
+    validator: Option<&Validator>,
+) -> Result<Write, DatabaseError> {
+    let namespace = validator_namespace(collection);
+    let key = VALIDATOR_KEY.to_vec();
+    Ok(match validator {
+        Some(validator) => {
+            Matcher::new(&validator.rules)?;
+            Write::Put {
+                namespace,
+                key,
+                value: to_bytes(&validator.to_document())?,
+            }
+        }
+        None => Write::Delete { namespace, key },
+    })
+}
+
+fn validator_namespace(collection: &str) -> String {
+    format!("{}{}", collection, VALIDATOR_SUFFIX)
+}
@@ -0,0 +1,68 @@
+// src/db/version.rs
+
+use silentdb_data_encoding::{Document, Value};
+
+use super::database::{DatabaseInner, Write};
+use super::error::DatabaseError;
+
+/// The field holding a document's version in a versioned collection.
+pub(crate) const VERSION_FIELD: &str = "_version";
+/// Suffix of the namespace marking a collection as versioned.
+pub(crate) const VERSIONED_SUFFIX: &str = ".$versioned";
+/// Key of the mark in its namespace.
+const VERSIONED_KEY: &[u8] = b"versioned";
+
+/// Returns the version of `document`, where a document written before its
+/// collection was versioned is at version 0.
+pub(crate) fn version_of(document: &Document) -> i64 {
+    match document.get(VERSION_FIELD) {
+        Some(Value::Int64(version)) => *version,
+        _ => 0,
+    }
+}
+
+/// Sets the version of `document`, replacing `previous`, to the one after
+/// it, or to 1 for a new document.
+pub(crate) fn stamp(document: &mut Document, previous: Option<&Document>) {
+    let version = previous.map_or(0, version_of) + 1;
+    document.insert(VERSION_FIELD, version);
+}
+
+/// Gives `document`, the result of a change to `stored`, the version of
+/// `stored`, so that only the version a write would set differs.
+pub(crate) fn keep_version(document: &mut Document, stored: &Document) {
+    match stored.get(VERSION_FIELD) {
+        Some(version) => document.insert(VERSION_FIELD, version.clone()),
+        None => document.remove(VERSION_FIELD),
+    };
+}
+
+/// Returns whether `collection` is versioned, as seen by transaction `txn`
+/// if given.
+pub(crate) fn is_versioned(
+    inner: &DatabaseInner,
+    txn: Option<u64>,
+    collection: &str,
+) -> Result<bool, DatabaseError> {
+    Ok(inner
+        .get(txn, &versioned_namespace(collection), VERSIONED_KEY)?
+        .is_some())
+}
+
+/// Returns the write marking `collection` as versioned or not.
+pub(crate) fn versioned_write(collection: &str, versioned: bool) -> Write {
+    let namespace = versioned_namespace(collection);
+    let key = VERSIONED_KEY.to_vec();
+    match versioned {
+        true => Write::Put {
+            namespace,
+            key,
+            value: Vec::new(),
+        },
+        false => Write::Delete { namespace, key },
+    }
+}
+
+fn versioned_namespace(collection: &str) -> String {
+    format!("{}{}", collection, VERSIONED_SUFFIX)
+}
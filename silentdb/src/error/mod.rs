@@ -0,0 +1,105 @@
+// src/error/mod.rs
+
+mod test;
+
+use silentdb_data_encoding::{DeserializeError, SerializeError};
+
+use crate::db::DatabaseError;
+use crate::query::QueryError;
+use crate::server::ServerError;
+use crate::storage::StorageError;
+
+/// A `Result` whose error is any SilentDB error.
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Any error SilentDB returns, for applications that handle them in one
+/// place.
+///
+/// Each layer's error converts into it, so `?` works across them. An
+/// error is filed under the layer it came from, not the one that passed it
+/// on: a storage error a database operation ran into is `Storage`, not
+/// `Database`, whichever way it arrived. The wrapped error is kept as it
+/// was, so its message and `source` chain are those of the original.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use silentdb::{Database, Error};
+/// # use silentdb_data_encoding::Document;
+/// fn count(db: &Database) -> silentdb::Result<u64> {
+///     Ok(db.collection("users").count(&Document::new())?)
+/// }
+///
+/// let db = Database::open("data").unwrap();
+/// match count(&db) {
+///     Ok(n) => println!("{} users", n),
+///     Err(e) if e.is_retryable() => println!("try again: {}", e),
+///     Err(e) => println!("failed with code {}: {}", e.code(), e),
+/// }
+/// ```
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Serialize(#[from] SerializeError),
+    #[error(transparent)]
+    Deserialize(#[from] DeserializeError),
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+    #[error(transparent)]
+    Query(#[from] QueryError),
+    /// Any other failure of a database operation.
+    #[error(transparent)]
+    Database(DatabaseError),
+    /// A failure serving or talking to clients, including the I/O of their
+    /// connections.
+    #[error(transparent)]
+    Network(ServerError),
+}
+
+impl Error {
+    /// Returns the code of the error, one of the codes MongoDB uses for
+    /// the same failure where there is one, and 1 otherwise. It is the code
+    /// error replies carry, and does not depend on the layers the error
+    /// passed through.
+    pub fn code(&self) -> i32 {
+        match self {
+            Error::Serialize(_) | Error::Deserialize(_) | Error::Storage(_) => 1,
+            Error::Query(_) => 2,
+            Error::Database(e) => e.code(),
+            Error::Network(e) => e.code(),
+        }
+    }
+
+    /// Returns `true` if the operation may succeed if run again unchanged,
+    /// as after a write conflict, a lock timeout, a dropped connection or
+    /// a busy server.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Serialize(_) | Error::Deserialize(_) | Error::Query(_) => false,
+            Error::Storage(e) => e.is_retryable(),
+            Error::Database(e) => e.is_retryable(),
+            Error::Network(e) => e.is_retryable(),
+        }
+    }
+}
+
+impl From<DatabaseError> for Error {
+    fn from(e: DatabaseError) -> Self {
+        match e {
+            DatabaseError::Storage(e) => Error::Storage(e),
+            DatabaseError::Serialize(e) => Error::Serialize(e),
+            DatabaseError::Deserialize(e) => Error::Deserialize(e),
+            DatabaseError::Query(e) => Error::Query(e),
+            e => Error::Database(e),
+        }
+    }
+}
+
+impl From<ServerError> for Error {
+    fn from(e: ServerError) -> Self {
+        match e {
+            ServerError::Database(e) => Error::from(e),
+            e => Error::Network(e),
+        }
+    }
+}
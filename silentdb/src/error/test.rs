@@ -0,0 +1,69 @@
+// src/error/test.rs
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error as _;
+    use std::io;
+
+    use silentdb_data_encoding::Value;
+
+    use crate::db::DatabaseError;
+    use crate::error::Error;
+    use crate::query::QueryError;
+    use crate::server::ServerError;
+    use crate::storage::StorageError;
+
+    #[test]
+    fn test_error() {
+        // Errors are filed under the layer they came from
+        let interrupted = || io::Error::new(io::ErrorKind::Interrupted, "interrupted");
+        let storage = DatabaseError::Storage(StorageError::Io(interrupted()));
+        let e = Error::from(ServerError::Database(storage));
+        assert!(matches!(e, Error::Storage(StorageError::Io(_))));
+        assert_eq!(e.code(), 1);
+        assert!(e.is_retryable());
+        assert_eq!(e.to_string(), "I/O error: interrupted");
+        assert_eq!(e.source().unwrap().to_string(), "interrupted");
+
+        let query = Error::from(DatabaseError::Query(QueryError::InvalidSort("x".into())));
+        assert!(matches!(query, Error::Query(_)));
+        assert_eq!(query.code(), 2);
+        assert!(!query.is_retryable());
+
+        // Codes are the ones error replies carry
+        let duplicate = Error::from(DatabaseError::DuplicateKey(Value::Int32(1)));
+        assert_eq!(duplicate.code(), 11000);
+        assert!(!duplicate.is_retryable());
+        let conflict = Error::from(DatabaseError::WriteConflict("users".into()));
+        assert_eq!(conflict.code(), 112);
+        assert!(conflict.is_retryable());
+        let version = DatabaseError::VersionConflict {
+            id: Value::Int32(1),
+            expected: 2,
+            actual: 3,
+        };
+        assert!(!Error::from(version).is_retryable());
+
+        let reset = io::Error::new(io::ErrorKind::ConnectionReset, "reset");
+        let network = Error::from(ServerError::Io(reset));
+        assert!(matches!(network, Error::Network(ServerError::Io(_))));
+        assert!(network.is_retryable());
+        let unauthorized = Error::from(ServerError::Unauthorized("find".into()));
+        assert_eq!(unauthorized.code(), 13);
+        assert!(!unauthorized.is_retryable());
+
+        // The source chain is the wrapped error's
+        let failed = Error::from(DatabaseError::ImportFailed {
+            record: 3,
+            source: Box::new(DatabaseError::WriteConflict("users".into())),
+        });
+        assert_eq!(
+            failed.to_string(),
+            "Import failed at record 3: Write conflict on users"
+        );
+        assert_eq!(
+            failed.source().unwrap().to_string(),
+            "Write conflict on users"
+        );
+    }
+}
@@ -0,0 +1,98 @@
+// src/geo/cell.rs
+
+use super::geometry::{Point, Rect};
+
+/// The finest level of the cell hierarchy.
+pub(crate) const MAX_LEVEL: u32 = 30;
+
+/// A cell of a hierarchical grid over longitude and latitude.
+///
+/// Level 0 is the whole Earth, and each level splits every cell of the
+/// last into four. An id holds the cell's position as longitude and
+/// latitude bits interleaved from the most significant, two per level,
+/// followed by a marker bit, so a cell's descendants have the ids between
+/// `range_min` and `range_max`, and ids sort cells along a Z-order curve.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) struct CellId(u64);
+
+impl CellId {
+    /// Returns the cell at `level` holding `point`.
+    pub(crate) fn from_point(point: &Point, level: u32) -> CellId {
+        let (x, y) = grid(point);
+        CellId::from_grid(x, y, level)
+    }
+
+    /// Returns the cell at `level` holding the finest cell at column `x`
+    /// and row `y`.
+    fn from_grid(x: u32, y: u32, level: u32) -> CellId {
+        let mut bits = 0u64;
+        for i in (0..MAX_LEVEL).rev() {
+            bits = (bits << 2) | (((x >> i) & 1) << 1) as u64 | ((y >> i) & 1) as u64;
+        }
+        CellId(bits << 4 | 1 << 3).parent(level)
+    }
+
+    /// Returns the ancestor of the cell at `level`, which must not be finer
+    /// than the cell's own.
+    pub(crate) fn parent(self, level: u32) -> CellId {
+        let marker = 1u64 << (63 - 2 * level);
+        CellId((self.0 & !(marker | (marker - 1))) | marker)
+    }
+
+    pub(crate) fn level(self) -> u32 {
+        (63 - self.0.trailing_zeros()) / 2
+    }
+
+    /// Returns the smallest id of the cell and its descendants.
+    pub(crate) fn range_min(self) -> u64 {
+        self.0 - (self.lowest_bit() - 1)
+    }
+
+    /// Returns the largest id of the cell and its descendants.
+    pub(crate) fn range_max(self) -> u64 {
+        self.0 + (self.lowest_bit() - 1)
+    }
+
+    pub(crate) fn to_bytes(self) -> [u8; 8] {
+        self.0.to_be_bytes()
+    }
+
+    fn lowest_bit(self) -> u64 {
+        self.0 & self.0.wrapping_neg()
+    }
+}
+
+/// Returns the cells of the finest level at which at most `max_cells`
+/// cells cover `rect`.
+pub(crate) fn covering(rect: &Rect, max_cells: u64) -> Vec<CellId> {
+    let (x0, y0) = grid(&rect.low);
+    let (x1, y1) = grid(&rect.high);
+    let level = (0..=MAX_LEVEL)
+        .rev()
+        .find(|level| {
+            let shift = MAX_LEVEL - level;
+            let columns = ((x1 >> shift) - (x0 >> shift) + 1) as u64;
+            let rows = ((y1 >> shift) - (y0 >> shift) + 1) as u64;
+            columns * rows <= max_cells
+        })
+        .unwrap_or(0);
+    let shift = MAX_LEVEL - level;
+    let mut cells = Vec::new();
+    for column in (x0 >> shift)..=(x1 >> shift) {
+        for row in (y0 >> shift)..=(y1 >> shift) {
+            cells.push(CellId::from_grid(column << shift, row << shift, level));
+        }
+    }
+    cells.sort();
+    cells
+}
+
+/// Returns the column and row of the finest cell holding `point`.
+fn grid(point: &Point) -> (u32, u32) {
+    let cells = (1u64 << MAX_LEVEL) as f64;
+    let scale = |fraction: f64| (fraction * cells).clamp(0.0, cells - 1.0) as u32;
+    (
+        scale((point.lng + 180.0) / 360.0),
+        scale((point.lat + 90.0) / 180.0),
+    )
+}
@@ -0,0 +1,480 @@
+// src/geo/geometry.rs
+
+use silentdb_data_encoding::{Document, Value};
+
+/// The radius of the Earth used for distances, in meters.
+pub const EARTH_RADIUS: f64 = 6_378_100.0;
+
+/// A position on the Earth, in degrees.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Point {
+    /// The longitude, from -180 to 180.
+    pub lng: f64,
+    /// The latitude, from -90 to 90.
+    pub lat: f64,
+}
+
+impl Point {
+    /// Returns the point at `lng` and `lat`, or `None` if either is out of
+    /// range.
+    pub fn new(lng: f64, lat: f64) -> Option<Point> {
+        ((-180.0..=180.0).contains(&lng) && (-90.0..=90.0).contains(&lat))
+            .then_some(Point { lng, lat })
+    }
+
+    /// Returns the great-circle distance to `other` in meters.
+    pub fn distance(&self, other: &Point) -> f64 {
+        let (lat1, lat2) = (self.lat.to_radians(), other.lat.to_radians());
+        let dlat = lat2 - lat1;
+        let dlng = (other.lng - self.lng).to_radians();
+        let h = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlng / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS * h.sqrt().min(1.0).asin()
+    }
+
+    /// Parses a `[longitude, latitude]` array.
+    fn from_value(value: &Value) -> Option<Point> {
+        let Value::Array(coordinates) = value else {
+            return None;
+        };
+        if coordinates.len() != 2 {
+            return None;
+        }
+        Point::new(number(coordinates.get(0)?)?, number(coordinates.get(1)?)?)
+    }
+}
+
+/// A GeoJSON geometry.
+///
+/// Edges are treated as straight lines in longitude and latitude, and may
+/// not cross the antimeridian.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Geometry {
+    Point(Point),
+    /// Two or more points joined in order.
+    LineString(Vec<Point>),
+    /// An outer ring followed by any holes, each a closed ring of four or
+    /// more points whose first and last are equal.
+    Polygon(Vec<Vec<Point>>),
+}
+
+impl Geometry {
+    /// Parses a GeoJSON document such as
+    /// `{"type": "Point", "coordinates": [2.35, 48.85]}`.
+    pub fn from_document(document: &Document) -> Option<Geometry> {
+        let (Some(Value::String(kind)), Some(Value::Array(coordinates))) =
+            (document.get("type"), document.get("coordinates"))
+        else {
+            return None;
+        };
+        let points = |value: &Value| -> Option<Vec<Point>> {
+            let Value::Array(points) = value else {
+                return None;
+            };
+            points.iter().map(Point::from_value).collect()
+        };
+        match kind.as_str() {
+            "Point" => Point::from_value(&Value::Array(coordinates.clone())).map(Geometry::Point),
+            "LineString" => {
+                let line = points(&Value::Array(coordinates.clone()))?;
+                (line.len() >= 2).then_some(Geometry::LineString(line))
+            }
+            "Polygon" => {
+                let rings = coordinates.iter().map(points).collect::<Option<Vec<_>>>()?;
+                let closed = |ring: &Vec<Point>| ring.len() >= 4 && ring.first() == ring.last();
+                (!rings.is_empty() && rings.iter().all(closed)).then_some(Geometry::Polygon(rings))
+            }
+            _ => None,
+        }
+    }
+
+    /// Parses `value` as a GeoJSON document.
+    pub fn from_value(value: &Value) -> Option<Geometry> {
+        match value {
+            Value::Document(document) => Geometry::from_document(document),
+            _ => None,
+        }
+    }
+
+    /// Parses the operand of `$geoIntersects`: `{"$geometry": <geometry>}`.
+    pub(crate) fn from_operand(operand: &Value) -> Result<Geometry, String> {
+        match operand {
+            Value::Document(operand) if operand.len() == 1 => {
+                operand.get("$geometry").and_then(Geometry::from_value)
+            }
+            _ => None,
+        }
+        .ok_or_else(|| "$geoIntersects expects a GeoJSON $geometry".to_string())
+    }
+
+    /// Returns the smallest rectangle holding the geometry.
+    pub(crate) fn bounds(&self) -> Rect {
+        let mut rect = Rect::EMPTY;
+        for point in self.vertices() {
+            rect.extend(point);
+        }
+        rect
+    }
+
+    /// Returns `true` if the geometries share any point.
+    pub fn intersects(&self, other: &Geometry) -> bool {
+        let edges = other.edges();
+        self.edges()
+            .iter()
+            .any(|a| edges.iter().any(|b| segments_touch(*a, *b)))
+            || self.vertices().iter().any(|point| other.contains(point))
+            || other.vertices().iter().any(|point| self.contains(point))
+    }
+
+    /// Returns `true` if every point of the geometry lies in the polygon
+    /// `region`.
+    fn within_polygon(&self, region: &Geometry) -> bool {
+        if !self.vertices().iter().all(|point| region.contains(point)) {
+            return false;
+        }
+        // No edge may pass out of the region and back in, and no hole of
+        // the region may lie inside the geometry
+        let edges = region.edges();
+        let crosses = self
+            .edges()
+            .iter()
+            .any(|a| edges.iter().any(|b| segments_cross(*a, *b)));
+        let hole_inside = match region {
+            Geometry::Polygon(rings) => rings[1..]
+                .iter()
+                .any(|hole| hole.iter().any(|point| self.contains_strictly(point))),
+            _ => false,
+        };
+        !crosses && !hole_inside
+    }
+
+    /// Returns the distance in meters from `point` to the nearest point of
+    /// the geometry.
+    pub fn distance(&self, point: &Point) -> f64 {
+        if self.contains(point) {
+            return 0.0;
+        }
+        self.edges()
+            .iter()
+            .map(|&(a, b)| nearest_on_segment(a, b, point).distance(point))
+            .fold(f64::INFINITY, f64::min)
+    }
+
+    /// Returns `true` if `point` is on the geometry, or inside a polygon.
+    fn contains(&self, point: &Point) -> bool {
+        match self {
+            Geometry::Polygon(rings) => {
+                ring_contains(&rings[0], point)
+                    && !rings[1..]
+                        .iter()
+                        .any(|hole| ring_contains(hole, point) && !on_ring(hole, point))
+            }
+            _ => self.edges().iter().any(|&(a, b)| on_segment(a, b, point)),
+        }
+    }
+
+    /// Returns `true` if `point` is inside a polygon but not on its
+    /// boundary.
+    fn contains_strictly(&self, point: &Point) -> bool {
+        match self {
+            Geometry::Polygon(rings) => {
+                self.contains(point) && !rings.iter().any(|ring| on_ring(ring, point))
+            }
+            _ => false,
+        }
+    }
+
+    fn vertices(&self) -> Vec<Point> {
+        match self {
+            Geometry::Point(point) => vec![*point],
+            Geometry::LineString(points) => points.clone(),
+            Geometry::Polygon(rings) => rings.iter().flatten().copied().collect(),
+        }
+    }
+
+    /// Returns the geometry's edges, with a point as a zero-length edge.
+    fn edges(&self) -> Vec<(Point, Point)> {
+        let pairs = |points: &[Point]| -> Vec<(Point, Point)> {
+            points.windows(2).map(|pair| (pair[0], pair[1])).collect()
+        };
+        match self {
+            Geometry::Point(point) => vec![(*point, *point)],
+            Geometry::LineString(points) => pairs(points),
+            Geometry::Polygon(rings) => rings.iter().flat_map(|ring| pairs(ring)).collect(),
+        }
+    }
+}
+
+/// An area `$geoWithin` tests geometries against.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Region {
+    Polygon(Geometry),
+    /// The points within `radius` meters of `center`.
+    Cap {
+        center: Point,
+        radius: f64,
+    },
+}
+
+impl Region {
+    /// Parses the operand of `$geoWithin`: `{"$geometry": <Polygon>}`,
+    /// `{"$centerSphere": [[lng, lat], radians]}` or
+    /// `{"$box": [[lng, lat], [lng, lat]]}`.
+    pub(crate) fn from_operand(operand: &Value) -> Result<Region, String> {
+        let Value::Document(operand) = operand else {
+            return Err("$geoWithin expects a document".to_string());
+        };
+        let shape = match (operand.len(), operand.iter().next()) {
+            (1, Some(shape)) => shape,
+            _ => return Err("$geoWithin expects one shape".to_string()),
+        };
+        match (shape.0.as_str(), shape.1) {
+            ("$geometry", value) => match Geometry::from_value(value) {
+                Some(polygon @ Geometry::Polygon(_)) => Ok(Region::Polygon(polygon)),
+                _ => Err("$geoWithin $geometry expects a GeoJSON polygon".to_string()),
+            },
+            ("$centerSphere", Value::Array(parts)) if parts.len() == 2 => {
+                let center = parts.get(0).and_then(Point::from_value);
+                let radians = parts.get(1).and_then(number).filter(|r| *r >= 0.0);
+                match (center, radians) {
+                    (Some(center), Some(radians)) => Ok(Region::Cap {
+                        center,
+                        radius: radians * EARTH_RADIUS,
+                    }),
+                    _ => Err("$centerSphere expects [[lng, lat], radians]".to_string()),
+                }
+            }
+            ("$box", Value::Array(corners)) if corners.len() == 2 => {
+                let corners = (
+                    corners.get(0).and_then(Point::from_value),
+                    corners.get(1).and_then(Point::from_value),
+                );
+                let (Some(a), Some(b)) = corners else {
+                    return Err("$box expects two [lng, lat] corners".to_string());
+                };
+                let (low, high) = (
+                    Point {
+                        lng: a.lng.min(b.lng),
+                        lat: a.lat.min(b.lat),
+                    },
+                    Point {
+                        lng: a.lng.max(b.lng),
+                        lat: a.lat.max(b.lat),
+                    },
+                );
+                let corner = |lng, lat| Point { lng, lat };
+                Ok(Region::Polygon(Geometry::Polygon(vec![vec![
+                    low,
+                    corner(high.lng, low.lat),
+                    high,
+                    corner(low.lng, high.lat),
+                    low,
+                ]])))
+            }
+            (shape, _) => Err(format!("unknown or malformed $geoWithin shape {}", shape)),
+        }
+    }
+
+    /// Returns `true` if every point of `geometry` lies in the region.
+    pub(crate) fn contains(&self, geometry: &Geometry) -> bool {
+        match self {
+            Region::Polygon(polygon) => geometry.within_polygon(polygon),
+            Region::Cap { center, radius } => geometry
+                .vertices()
+                .iter()
+                .all(|point| point.distance(center) <= *radius),
+        }
+    }
+
+    /// Returns a rectangle holding the region.
+    pub(crate) fn bounds(&self) -> Rect {
+        match self {
+            Region::Polygon(polygon) => polygon.bounds(),
+            Region::Cap { center, radius } => Rect::around(center, *radius),
+        }
+    }
+}
+
+/// The operand of `$near`: the points from `min` to `max` meters away from
+/// `center`, nearest first.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Near {
+    pub(crate) center: Point,
+    pub(crate) min: f64,
+    pub(crate) max: f64,
+}
+
+impl Near {
+    /// Parses `{"$geometry": <Point>, "$minDistance": m, "$maxDistance": m}`,
+    /// where both distances are optional.
+    pub(crate) fn from_operand(operand: &Value) -> Result<Near, String> {
+        let Value::Document(operand) = operand else {
+            return Err("$near expects a document".to_string());
+        };
+        let distance = |name: &str| match operand.get(name) {
+            Some(value) => number(value)
+                .filter(|distance| *distance >= 0.0)
+                .map(Some)
+                .ok_or(format!("{} expects a non-negative number", name)),
+            None => Ok(None),
+        };
+        let center = match operand.get("$geometry").and_then(Geometry::from_value) {
+            Some(Geometry::Point(center)) => center,
+            _ => return Err("$near expects a GeoJSON point in $geometry".to_string()),
+        };
+        if let Some((key, _)) = operand
+            .iter()
+            .find(|(key, _)| !matches!(key.as_str(), "$geometry" | "$minDistance" | "$maxDistance"))
+        {
+            return Err(format!("unknown $near option {}", key));
+        }
+        Ok(Near {
+            center,
+            min: distance("$minDistance")?.unwrap_or(0.0),
+            max: distance("$maxDistance")?.unwrap_or(f64::INFINITY),
+        })
+    }
+
+    /// Returns the distance from the center to `geometry` if it is in
+    /// range.
+    pub(crate) fn distance(&self, geometry: &Geometry) -> Option<f64> {
+        let distance = geometry.distance(&self.center);
+        (self.min..=self.max)
+            .contains(&distance)
+            .then_some(distance)
+    }
+}
+
+/// A rectangle in longitude and latitude.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Rect {
+    pub(crate) low: Point,
+    pub(crate) high: Point,
+}
+
+impl Rect {
+    const EMPTY: Rect = Rect {
+        low: Point {
+            lng: f64::INFINITY,
+            lat: f64::INFINITY,
+        },
+        high: Point {
+            lng: f64::NEG_INFINITY,
+            lat: f64::NEG_INFINITY,
+        },
+    };
+
+    /// Returns a rectangle holding every point within `radius` meters of
+    /// `center`.
+    pub(crate) fn around(center: &Point, radius: f64) -> Rect {
+        let dlat = (radius / EARTH_RADIUS).to_degrees();
+        let (south, north) = (center.lat - dlat, center.lat + dlat);
+        // Near a pole, or for a radius past half the Earth, any longitude
+        let polar = south <= -90.0 || north >= 90.0 || dlat >= 90.0;
+        let dlng = if polar {
+            180.0
+        } else {
+            let widest = south.abs().max(north.abs()).to_radians();
+            (dlat / widest.cos()).min(180.0)
+        };
+        let (west, east) = if polar || center.lng - dlng < -180.0 || center.lng + dlng > 180.0 {
+            (-180.0, 180.0)
+        } else {
+            (center.lng - dlng, center.lng + dlng)
+        };
+        Rect {
+            low: Point {
+                lng: west,
+                lat: south.max(-90.0),
+            },
+            high: Point {
+                lng: east,
+                lat: north.min(90.0),
+            },
+        }
+    }
+
+    fn extend(&mut self, point: Point) {
+        self.low.lng = self.low.lng.min(point.lng);
+        self.low.lat = self.low.lat.min(point.lat);
+        self.high.lng = self.high.lng.max(point.lng);
+        self.high.lat = self.high.lat.max(point.lat);
+    }
+}
+
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Double(n) if n.is_finite() => Some(*n),
+        Value::Int32(n) => Some(*n as f64),
+        Value::Int64(n) => Some(*n as f64),
+        _ => None,
+    }
+}
+
+/* Planar Helpers */
+
+/// Returns twice the signed area of the triangle `a`, `b`, `c`: positive if
+/// it turns left.
+fn cross(a: Point, b: Point, c: Point) -> f64 {
+    (b.lng - a.lng) * (c.lat - a.lat) - (b.lat - a.lat) * (c.lng - a.lng)
+}
+
+fn on_segment(a: Point, b: Point, p: &Point) -> bool {
+    cross(a, b, *p) == 0.0
+        && p.lng >= a.lng.min(b.lng)
+        && p.lng <= a.lng.max(b.lng)
+        && p.lat >= a.lat.min(b.lat)
+        && p.lat <= a.lat.max(b.lat)
+}
+
+fn on_ring(ring: &[Point], point: &Point) -> bool {
+    ring.windows(2)
+        .any(|pair| on_segment(pair[0], pair[1], point))
+}
+
+/// Returns `true` if `point` is inside `ring` or on its boundary.
+fn ring_contains(ring: &[Point], point: &Point) -> bool {
+    if on_ring(ring, point) {
+        return true;
+    }
+    let mut inside = false;
+    for pair in ring.windows(2) {
+        let (a, b) = (pair[0], pair[1]);
+        if (a.lat > point.lat) != (b.lat > point.lat) {
+            let lng = a.lng + (point.lat - a.lat) / (b.lat - a.lat) * (b.lng - a.lng);
+            if point.lng < lng {
+                inside = !inside;
+            }
+        }
+    }
+    inside
+}
+
+/// Returns `true` if the segments share any point.
+fn segments_touch((a, b): (Point, Point), (c, d): (Point, Point)) -> bool {
+    segments_cross((a, b), (c, d))
+        || on_segment(a, b, &c)
+        || on_segment(a, b, &d)
+        || on_segment(c, d, &a)
+        || on_segment(c, d, &b)
+}
+
+/// Returns `true` if each segment passes strictly from one side of the
+/// other to the other.
+fn segments_cross((a, b): (Point, Point), (c, d): (Point, Point)) -> bool {
+    let (d1, d2) = (cross(c, d, a), cross(c, d, b));
+    let (d3, d4) = (cross(a, b, c), cross(a, b, d));
+    d1 * d2 < 0.0 && d3 * d4 < 0.0
+}
+
+fn nearest_on_segment(a: Point, b: Point, p: &Point) -> Point {
+    let (dx, dy) = (b.lng - a.lng, b.lat - a.lat);
+    let length = dx * dx + dy * dy;
+    if length == 0.0 {
+        return a;
+    }
+    let t = (((p.lng - a.lng) * dx + (p.lat - a.lat) * dy) / length).clamp(0.0, 1.0);
+    Point {
+        lng: a.lng + t * dx,
+        lat: a.lat + t * dy,
+    }
+}
@@ -0,0 +1,66 @@
+// src/geo/mod.rs
+
+mod cell;
+mod geometry;
+mod test;
+
+use std::collections::BTreeSet;
+use std::ops::Bound;
+
+use crate::storage::KeyRange;
+
+pub use geometry::{Geometry, Point, EARTH_RADIUS};
+pub(crate) use geometry::{Near, Rect, Region};
+
+use cell::{covering, CellId, MAX_LEVEL};
+
+/// The most cells a geometry is indexed under, or a query area is covered
+/// with.
+const MAX_CELLS: u64 = 8;
+
+/// Returns the keys a geo index files `geometry` under: the finest cell
+/// holding a point, or the cells covering another geometry's bounds.
+pub(crate) fn index_keys(geometry: &Geometry) -> Vec<[u8; 8]> {
+    let cells = match geometry {
+        Geometry::Point(point) => vec![CellId::from_point(point, MAX_LEVEL)],
+        geometry => covering(&geometry.bounds(), MAX_CELLS),
+    };
+    cells.into_iter().map(CellId::to_bytes).collect()
+}
+
+/// Returns the ranges of a geo index holding every geometry that may
+/// intersect `rect`, in key order.
+///
+/// These are the entries under a cell covering `rect` or a descendant of
+/// one, and those under an ancestor of one, which a large geometry may be
+/// filed under.
+pub(crate) fn search_ranges(rect: &Rect) -> Vec<KeyRange> {
+    let cells = covering(rect, MAX_CELLS);
+    let ancestors: BTreeSet<CellId> = cells
+        .iter()
+        .flat_map(|cell| (0..cell.level()).map(|level| cell.parent(level)))
+        .collect();
+    let mut ranges: Vec<KeyRange> = ancestors
+        .into_iter()
+        .map(|ancestor| KeyRange::prefix(&ancestor.to_bytes()))
+        .chain(cells.into_iter().map(|cell| {
+            let end = match cell.range_max().checked_add(1) {
+                Some(end) => Bound::Excluded(end.to_be_bytes().to_vec()),
+                None => Bound::Unbounded,
+            };
+            KeyRange::new(
+                Bound::Included(cell.range_min().to_be_bytes().to_vec()),
+                end,
+            )
+        }))
+        .collect();
+    ranges.sort_by(|a, b| start(a).cmp(start(b)));
+    ranges
+}
+
+fn start(range: &KeyRange) -> &[u8] {
+    match &range.start {
+        Bound::Included(start) | Bound::Excluded(start) => start,
+        Bound::Unbounded => &[],
+    }
+}
@@ -0,0 +1,199 @@
+// src/geo/test.rs
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Bound;
+
+    use silentdb_data_encoding::{Array, Document, Value};
+
+    use crate::geo::cell::{covering, CellId, MAX_LEVEL};
+    use crate::geo::{index_keys, search_ranges, Geometry, Near, Point, Rect, Region};
+
+    fn coordinates(points: &[(f64, f64)]) -> Value {
+        Value::Array(Array::from_vec(
+            points
+                .iter()
+                .map(|&(lng, lat)| Value::Array(Array::from_vec(vec![lng.into(), lat.into()])))
+                .collect(),
+        ))
+    }
+
+    fn geojson(kind: &str, coordinates: Value) -> Value {
+        let mut document = Document::new();
+        document.insert("type", kind);
+        document.insert("coordinates", coordinates);
+        Value::Document(document)
+    }
+
+    fn point(lng: f64, lat: f64) -> Geometry {
+        Geometry::Point(Point { lng, lat })
+    }
+
+    fn square(low: f64, high: f64) -> Geometry {
+        let ring = [
+            (low, low),
+            (high, low),
+            (high, high),
+            (low, high),
+            (low, low),
+        ];
+        Geometry::Polygon(vec![ring
+            .iter()
+            .map(|&(lng, lat)| Point { lng, lat })
+            .collect()])
+    }
+
+    // -------------------------------------
+    //          Geometry Tests
+    // -------------------------------------
+
+    #[test]
+    fn test_parse_geojson() {
+        let parsed = Geometry::from_value(&geojson(
+            "Point",
+            Value::Array(Array::from_vec(vec![2.35.into(), 48.85.into()])),
+        ));
+        assert_eq!(parsed, Some(point(2.35, 48.85)));
+        let line = geojson("LineString", coordinates(&[(0.0, 0.0), (1.0, 1.0)]));
+        assert!(matches!(
+            Geometry::from_value(&line),
+            Some(Geometry::LineString(points)) if points.len() == 2
+        ));
+        let ring = [(0.0, 0.0), (2.0, 0.0), (2.0, 2.0), (0.0, 2.0), (0.0, 0.0)];
+        let polygon = geojson(
+            "Polygon",
+            Value::Array(Array::from_vec(vec![coordinates(&ring)])),
+        );
+        assert_eq!(Geometry::from_value(&polygon), Some(square(0.0, 2.0)));
+
+        // Out of range coordinates, open rings and unknown types are rejected
+        let far = Value::Array(Array::from_vec(vec![200.0.into(), 0.0.into()]));
+        assert_eq!(Geometry::from_value(&geojson("Point", far)), None);
+        let open = Value::Array(Array::from_vec(vec![coordinates(&ring[..4])]));
+        assert_eq!(Geometry::from_value(&geojson("Polygon", open)), None);
+        assert_eq!(
+            Geometry::from_value(&geojson("Circle", coordinates(&[(0.0, 0.0)]))),
+            None
+        );
+        assert_eq!(Geometry::from_value(&Value::from("Point")), None);
+    }
+
+    #[test]
+    fn test_geometry_predicates() {
+        let outer = square(0.0, 10.0);
+        assert!(point(5.0, 5.0).intersects(&outer));
+        assert!(point(10.0, 5.0).intersects(&outer));
+        assert!(!point(11.0, 5.0).intersects(&outer));
+        let line = Geometry::LineString(vec![
+            Point {
+                lng: -5.0,
+                lat: 5.0,
+            },
+            Point {
+                lng: 15.0,
+                lat: 5.0,
+            },
+        ]);
+        assert!(line.intersects(&outer));
+        assert!(square(2.0, 3.0).intersects(&outer));
+
+        let region = Region::Polygon(outer.clone());
+        assert!(region.contains(&square(2.0, 3.0)));
+        assert!(region.contains(&point(0.0, 0.0)));
+        assert!(!region.contains(&line));
+        assert!(!region.contains(&square(5.0, 15.0)));
+
+        // A hole leaves points out of a polygon
+        let Geometry::Polygon(mut rings) = outer else {
+            unreachable!();
+        };
+        let Geometry::Polygon(hole) = square(4.0, 6.0) else {
+            unreachable!();
+        };
+        rings.extend(hole);
+        let holed = Region::Polygon(Geometry::Polygon(rings));
+        assert!(!holed.contains(&point(5.0, 5.0)));
+        assert!(holed.contains(&point(4.0, 5.0)));
+        assert!(!holed.contains(&square(3.0, 7.0)));
+    }
+
+    #[test]
+    fn test_distances() {
+        let paris = Point {
+            lng: 2.3522,
+            lat: 48.8566,
+        };
+        let london = Point {
+            lng: -0.1276,
+            lat: 51.5072,
+        };
+        let distance = paris.distance(&london);
+        assert!((distance - 344_000.0).abs() < 2_000.0, "{}", distance);
+        assert_eq!(
+            square(0.0, 10.0).distance(&Point { lng: 5.0, lat: 5.0 }),
+            0.0
+        );
+
+        let cap = Region::Cap {
+            center: paris,
+            radius: 400_000.0,
+        };
+        assert!(cap.contains(&Geometry::Point(london)));
+        let near = Near {
+            center: paris,
+            min: 0.0,
+            max: 300_000.0,
+        };
+        assert_eq!(near.distance(&Geometry::Point(london)), None);
+        assert_eq!(near.distance(&Geometry::Point(paris)), Some(0.0));
+    }
+
+    // -------------------------------------
+    //          Cell Tests
+    // -------------------------------------
+
+    #[test]
+    fn test_cell_hierarchy() {
+        let leaf = CellId::from_point(
+            &Point {
+                lng: 2.35,
+                lat: 48.85,
+            },
+            MAX_LEVEL,
+        );
+        assert_eq!(leaf.level(), MAX_LEVEL);
+        let id = u64::from_be_bytes(leaf.to_bytes());
+        for level in 0..MAX_LEVEL {
+            let parent = leaf.parent(level);
+            assert_eq!(parent.level(), level);
+            assert!((parent.range_min()..=parent.range_max()).contains(&id));
+        }
+        assert_eq!(leaf.parent(0).range_min(), 1);
+        assert_eq!(leaf.parent(0).range_max(), u64::MAX);
+    }
+
+    #[test]
+    fn test_coverings_find_indexed_geometries() {
+        let rect = Rect {
+            low: Point { lng: 1.0, lat: 1.0 },
+            high: Point { lng: 3.0, lat: 2.0 },
+        };
+        let cells = covering(&rect, 8);
+        assert!(!cells.is_empty() && cells.len() <= 8);
+        assert!(cells
+            .windows(2)
+            .all(|pair| pair[0].level() == pair[1].level()));
+
+        // Every geometry touching the rectangle is in a searched range,
+        // however coarse the cells it is filed under
+        let ranges = search_ranges(&rect);
+        let searched = |key: &[u8; 8]| ranges.iter().any(|range| range.contains(key));
+        for geometry in [point(2.0, 1.5), square(1.5, 1.8), square(-50.0, 50.0)] {
+            assert!(index_keys(&geometry).iter().any(searched), "{:?}", geometry);
+        }
+        assert!(!index_keys(&point(100.0, -40.0)).iter().any(searched));
+        assert!(ranges
+            .iter()
+            .all(|range| matches!(range.start, Bound::Included(_))));
+    }
+}
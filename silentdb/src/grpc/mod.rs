@@ -0,0 +1,18 @@
+// src/grpc/mod.rs
+
+//! A gRPC interface to a database, for environments standardized on gRPC
+//! rather than the wire protocols `Server` speaks. Built with the `grpc`
+//! feature, from `proto/silentdb.proto`.
+
+// Handlers return tonic's `Status`, however large clippy finds it
+#[allow(clippy::result_large_err)]
+mod service;
+mod test;
+
+/// The messages and service of `proto/silentdb.proto`, including the
+/// generated client, `silent_db_client::SilentDbClient`.
+pub mod proto {
+    tonic::include_proto!("silentdb.v1");
+}
+
+pub use service::GrpcService;
@@ -0,0 +1,348 @@
+// src/grpc/service.rs
+
+use std::time::Duration;
+
+use silentdb_data_encoding::{
+    from_bytes, from_json_str, to_bytes, Document, JsonSerializer, Serializer, Value,
+};
+use tokio::sync::mpsc::{self, Sender};
+use tokio::task;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Code, Request, Response, Status};
+
+use super::proto::document::Body;
+use super::proto::silent_db_server::{SilentDb, SilentDbServer};
+use super::proto::{
+    ChangeEvent, DeleteRequest, DeleteResponse, Encoding, FindRequest, FindResponse, InsertRequest,
+    InsertResponse, UpdateRequest, UpdateResponse, WatchRequest,
+};
+use crate::db::{Database, DatabaseError, FindOptions, ResumeToken, UpdateOptions};
+use crate::server::blocking;
+
+/// Documents per `FindResponse` when the request leaves it to the server.
+const DEFAULT_BATCH_SIZE: usize = 101;
+/// How long a watch waits for a change before checking its client is still
+/// there.
+const WATCH_POLL: Duration = Duration::from_secs(1);
+/// Change events a watch reads ahead of its client.
+const WATCH_BUFFER: usize = 16;
+
+/// The `SilentDb` service of `proto/silentdb.proto`, serving `db`.
+///
+/// Requests carry documents as BSON bytes or canonical Extended JSON, as
+/// each `Document` message chooses, and replies use the request's
+/// `encoding`. Database work runs on the runtime's blocking pool; a find
+/// reads each batch only once the client has taken the one before, and a
+/// watch holds a blocking thread until its client goes away.
+///
+/// The service does not authenticate; put it behind whatever the gRPC
+/// deployment uses for that, such as a tonic interceptor or a proxy.
+///
+/// # Examples
+///
+/// ```no_run
+/// # async fn serve() -> Result<(), Box<dyn std::error::Error>> {
+/// use silentdb::{Database, GrpcService};
+/// use tonic::transport::Server;
+///
+/// let db = Database::open("data")?;
+/// Server::builder()
+///     .add_service(GrpcService::new(db).into_server())
+///     .serve("127.0.0.1:50051".parse()?)
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct GrpcService {
+    db: Database,
+}
+
+impl GrpcService {
+    /// Creates the service, serving `db`.
+    pub fn new(db: Database) -> Self {
+        GrpcService { db }
+    }
+
+    /// Returns the service wrapped for a tonic server's `add_service`.
+    pub fn into_server(self) -> SilentDbServer<GrpcService> {
+        SilentDbServer::new(self)
+    }
+}
+
+#[tonic::async_trait]
+impl SilentDb for GrpcService {
+    type FindStream = ReceiverStream<Result<FindResponse, Status>>;
+    type WatchStream = ReceiverStream<Result<ChangeEvent, Status>>;
+
+    async fn insert(
+        &self,
+        request: Request<InsertRequest>,
+    ) -> Result<Response<InsertResponse>, Status> {
+        let request = request.into_inner();
+        let encoding = request.encoding();
+        let documents = request
+            .documents
+            .into_iter()
+            .map(|document| decode(Some(document), "documents").map(Option::unwrap_or_default))
+            .collect::<Result<Vec<_>, _>>()?;
+        let (db, collection) = (self.db.clone(), request.collection);
+        let result = blocking(move || db.collection(&collection).insert_many(documents))
+            .await
+            .map_err(status)?;
+        let inserted_ids = result
+            .inserted_ids
+            .into_iter()
+            .map(|id| encode(&id_document(id), encoding))
+            .collect::<Result<_, _>>()?;
+        Ok(Response::new(InsertResponse { inserted_ids }))
+    }
+
+    async fn find(
+        &self,
+        request: Request<FindRequest>,
+    ) -> Result<Response<Self::FindStream>, Status> {
+        let request = request.into_inner();
+        let encoding = request.encoding();
+        let filter = decode(request.filter, "filter")?.unwrap_or_default();
+        let mut options = FindOptions::new();
+        if let Some(sort) = decode(request.sort, "sort")? {
+            options = options.sort(sort);
+        }
+        if let Some(projection) = decode(request.projection, "projection")? {
+            options = options.projection(projection);
+        }
+        let skip = usize::try_from(request.skip).unwrap_or(usize::MAX);
+        let limit = match request.limit {
+            0 => usize::MAX,
+            limit => usize::try_from(limit).unwrap_or(usize::MAX),
+        };
+        let batch_size = match request.batch_size {
+            0 => DEFAULT_BATCH_SIZE,
+            batch_size => batch_size as usize,
+        };
+
+        let (db, collection) = (self.db.clone(), request.collection);
+        let cursor = blocking(move || db.collection(&collection).find_with(&filter, &options))
+            .await
+            .map_err(status)?;
+        let matches = cursor.batch_size(batch_size).skip(skip).take(limit);
+        // A channel of one batch, so the next is read only once the client
+        // has taken this one
+        let (sender, receiver) = mpsc::channel(1);
+        task::spawn_blocking(move || send_batches(matches, batch_size, encoding, sender));
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+
+    async fn update(
+        &self,
+        request: Request<UpdateRequest>,
+    ) -> Result<Response<UpdateResponse>, Status> {
+        let request = request.into_inner();
+        let encoding = request.encoding();
+        let filter = decode(request.filter, "filter")?.unwrap_or_default();
+        let change = decode(request.update, "update")?
+            .ok_or_else(|| Status::invalid_argument("update is required"))?;
+        let operators = change.iter().any(|(key, _)| key.starts_with('$'));
+        if !operators && request.multi {
+            return Err(Status::invalid_argument(
+                "a replacement cannot be a multi update",
+            ));
+        }
+        let options = UpdateOptions::new().upsert(request.upsert);
+
+        let (db, collection, multi) = (self.db.clone(), request.collection, request.multi);
+        let result = blocking(move || {
+            let collection = db.collection(&collection);
+            match (operators, multi) {
+                (true, true) => collection.update_many_with(&filter, &change, &options),
+                (true, false) => collection.update_one_with(&filter, &change, &options),
+                (false, _) => collection.replace_one_with(&filter, &change, &options),
+            }
+        })
+        .await
+        .map_err(status)?;
+        Ok(Response::new(UpdateResponse {
+            matched_count: result.matched_count,
+            modified_count: result.modified_count,
+            upserted_id: result
+                .upserted_id
+                .map(|id| encode(&id_document(id), encoding))
+                .transpose()?,
+        }))
+    }
+
+    async fn delete(
+        &self,
+        request: Request<DeleteRequest>,
+    ) -> Result<Response<DeleteResponse>, Status> {
+        let request = request.into_inner();
+        let filter = decode(request.filter, "filter")?.unwrap_or_default();
+        let (db, collection, multi) = (self.db.clone(), request.collection, request.multi);
+        let result = blocking(move || {
+            let collection = db.collection(&collection);
+            match multi {
+                true => collection.delete_many(&filter),
+                false => collection.delete_one(&filter),
+            }
+        })
+        .await
+        .map_err(status)?;
+        Ok(Response::new(DeleteResponse {
+            deleted_count: result.deleted_count,
+        }))
+    }
+
+    async fn watch(
+        &self,
+        request: Request<WatchRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        let request = request.into_inner();
+        let encoding = request.encoding();
+        let filter = decode(request.filter, "filter")?.unwrap_or_default();
+        let after = match request.resume_after.is_empty() {
+            true => None,
+            false => Some(
+                ResumeToken::from_bytes(&request.resume_after).ok_or_else(|| {
+                    Status::invalid_argument("resume_after is not a resume token")
+                })?,
+            ),
+        };
+
+        // The stream starts before the response does, so a client sees
+        // every change committed once its call returns
+        let (db, collection) = (self.db.clone(), request.collection);
+        let mut stream = blocking(move || {
+            let collection = db.collection(&collection);
+            match after {
+                Some(token) => collection.watch_after(&filter, token),
+                None => collection.watch(&filter),
+            }
+        })
+        .await
+        .map_err(status)?;
+        let (sender, receiver) = mpsc::channel(WATCH_BUFFER);
+        task::spawn_blocking(move || {
+            while !sender.is_closed() {
+                let event = match stream.next_timeout(WATCH_POLL) {
+                    Ok(Some(event)) => {
+                        encode(&event.to_document(), encoding).map(|document| ChangeEvent {
+                            event: Some(document),
+                            resume_token: event.resume_token.to_bytes().to_vec(),
+                        })
+                    }
+                    Ok(None) => continue,
+                    Err(e) => Err(status(e)),
+                };
+                let failed = event.is_err();
+                if sender.blocking_send(event).is_err() || failed {
+                    return;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+}
+
+/// Sends `matches` to `sender` in `FindResponse`s of `batch_size`, until
+/// they run out, one fails, or the client goes away.
+fn send_batches(
+    matches: impl Iterator<Item = Result<Document, DatabaseError>>,
+    batch_size: usize,
+    encoding: Encoding,
+    sender: Sender<Result<FindResponse, Status>>,
+) {
+    let mut documents = Vec::with_capacity(batch_size);
+    for document in matches {
+        let document = document
+            .map_err(status)
+            .and_then(|document| encode(&document, encoding));
+        let document = match document {
+            Ok(document) => document,
+            Err(e) => {
+                if !documents.is_empty() {
+                    let _ = sender.blocking_send(Ok(FindResponse { documents }));
+                }
+                let _ = sender.blocking_send(Err(e));
+                return;
+            }
+        };
+        documents.push(document);
+        if documents.len() == batch_size {
+            let batch = std::mem::replace(&mut documents, Vec::with_capacity(batch_size));
+            if sender
+                .blocking_send(Ok(FindResponse { documents: batch }))
+                .is_err()
+            {
+                return;
+            }
+        }
+    }
+    if !documents.is_empty() {
+        let _ = sender.blocking_send(Ok(FindResponse { documents }));
+    }
+}
+
+/// Reads the document a request sent at `field`, or `None` if it sent none.
+fn decode(
+    document: Option<super::proto::Document>,
+    field: &str,
+) -> Result<Option<Document>, Status> {
+    let decoded = match document.and_then(|document| document.body) {
+        None => return Ok(None),
+        Some(Body::Bson(bytes)) => from_bytes(&bytes),
+        Some(Body::Json(text)) => from_json_str(&text),
+    };
+    decoded
+        .map(Some)
+        .map_err(|e| Status::invalid_argument(format!("{} is not a document: {}", field, e)))
+}
+
+/// Writes `document` for a reply, as `encoding` asks.
+fn encode(document: &Document, encoding: Encoding) -> Result<super::proto::Document, Status> {
+    let body = match encoding {
+        Encoding::Bson => Body::Bson(to_bytes(document).map_err(|e| internal(&e))?),
+        Encoding::Json => {
+            let mut serializer = JsonSerializer::canonical(Vec::new(), false);
+            serializer
+                .serialize_document(document)
+                .map_err(|e| internal(&e))?;
+            Body::Json(String::from_utf8(serializer.into_inner()).map_err(|e| internal(&e))?)
+        }
+    };
+    Ok(super::proto::Document { body: Some(body) })
+}
+
+/// Returns `{_id: id}`, as `_id`s are carried in replies.
+fn id_document(id: Value) -> Document {
+    let mut document = Document::new();
+    document.insert("_id", id);
+    document
+}
+
+fn internal(error: &dyn std::error::Error) -> Status {
+    Status::internal(error.to_string())
+}
+
+/// Returns the status a failed request ends with: the gRPC code closest to
+/// what went wrong, and the error's message.
+fn status(error: DatabaseError) -> Status {
+    let code = match &error {
+        DatabaseError::Query(_)
+        | DatabaseError::Deserialize(_)
+        | DatabaseError::InvalidUpdate(_)
+        | DatabaseError::InvalidCollectionName(_)
+        | DatabaseError::InvalidCollation(_) => Code::InvalidArgument,
+        DatabaseError::DuplicateKey(_) | DatabaseError::CollectionExists(_) => Code::AlreadyExists,
+        DatabaseError::CollectionNotFound(_) => Code::NotFound,
+        DatabaseError::ValidationFailed(_) => Code::FailedPrecondition,
+        DatabaseError::WriteConflict(_)
+        | DatabaseError::Deadlock { .. }
+        | DatabaseError::VersionConflict { .. } => Code::Aborted,
+        DatabaseError::LockTimeout(_) => Code::DeadlineExceeded,
+        DatabaseError::NotLeader(_) => Code::Unavailable,
+        DatabaseError::HistoryLost(_) => Code::OutOfRange,
+        _ => Code::Internal,
+    };
+    Status::new(code, error.to_string())
+}
@@ -0,0 +1,474 @@
+// src/grpc/test.rs
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use silentdb_data_encoding::{from_bytes, from_json_str, to_bytes, Document, Value};
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::transport::{Channel, Server};
+    use tonic::Code;
+
+    use crate::db::Database;
+    use crate::grpc::proto::document::Body;
+    use crate::grpc::proto::silent_db_client::SilentDbClient;
+    use crate::grpc::proto::{
+        self, DeleteRequest, Encoding, FindRequest, InsertRequest, UpdateRequest, WatchRequest,
+    };
+    use crate::grpc::GrpcService;
+
+    /// Returns an empty scratch directory unique to this process and `name`.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("silentdb-grpc-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn doc(key: &str, value: impl Into<Value>) -> Document {
+        let mut document = Document::new();
+        document.insert(key, value);
+        document
+    }
+
+    fn bson(document: &Document) -> Option<proto::Document> {
+        Some(proto::Document {
+            body: Some(Body::Bson(to_bytes(document).unwrap())),
+        })
+    }
+
+    fn json(text: &str) -> Option<proto::Document> {
+        Some(proto::Document {
+            body: Some(Body::Json(text.to_string())),
+        })
+    }
+
+    fn read(document: &proto::Document) -> Document {
+        match &document.body {
+            Some(Body::Bson(bytes)) => from_bytes(bytes).unwrap(),
+            other => panic!("expected BSON, got {:?}", other),
+        }
+    }
+
+    /// Inserts `{_id: n, name, n}` for each of `names`, numbering them from
+    /// 1.
+    async fn insert_users(client: &mut SilentDbClient<Channel>, names: &[&str]) {
+        let documents = names
+            .iter()
+            .zip(1..)
+            .map(|(name, n)| {
+                let mut user = doc("_id", n);
+                user.insert("name", *name);
+                user.insert("n", n);
+                bson(&user).unwrap()
+            })
+            .collect();
+        client
+            .insert(InsertRequest {
+                collection: "users".to_string(),
+                documents,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    }
+
+    /// Returns the documents `request` finds, as batches of them.
+    async fn find(
+        client: &mut SilentDbClient<Channel>,
+        request: FindRequest,
+    ) -> Vec<Vec<Document>> {
+        let mut stream = client.find(request).await.unwrap().into_inner();
+        let mut batches = Vec::new();
+        while let Some(batch) = stream.message().await.unwrap() {
+            batches.push(batch.documents.iter().map(read).collect());
+        }
+        batches
+    }
+
+    /// Serves `db` on a free local port and returns a client connected to it.
+    async fn serve(db: Database) -> SilentDbClient<Channel> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::builder()
+                .add_service(GrpcService::new(db).into_server())
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        SilentDbClient::connect(format!("http://{}", addr))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_grpc_insert() {
+        let dir = scratch_dir("insert");
+        let mut client = serve(Database::open(&dir).unwrap()).await;
+
+        // Documents go in as BSON or Extended JSON
+        let mut alice = doc("_id", 1);
+        alice.insert("name", "alice");
+        let inserted = client
+            .insert(InsertRequest {
+                collection: "users".to_string(),
+                documents: vec![
+                    bson(&alice).unwrap(),
+                    json(r#"{"_id": 2, "name": "bob"}"#).unwrap(),
+                ],
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        let ids: Vec<_> = inserted.inserted_ids.iter().map(read).collect();
+        assert_eq!(ids, vec![doc("_id", 1), doc("_id", 2)]);
+
+        let duplicate = client
+            .insert(InsertRequest {
+                collection: "users".to_string(),
+                documents: vec![bson(&alice).unwrap()],
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(duplicate.code(), Code::AlreadyExists);
+        let malformed = client
+            .insert(InsertRequest {
+                collection: "users".to_string(),
+                documents: vec![json("{not json").unwrap()],
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(malformed.code(), Code::InvalidArgument);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_grpc_find() {
+        let dir = scratch_dir("find");
+        let mut client = serve(Database::open(&dir).unwrap()).await;
+        insert_users(&mut client, &["alice", "bob", "carol", "dave", "erin"]).await;
+
+        // Matches stream in batches, sorted, skipped, limited and projected
+        let batches = find(
+            &mut client,
+            FindRequest {
+                collection: "users".to_string(),
+                filter: json(r#"{"n": {"$gt": 1}}"#),
+                sort: bson(&doc("n", -1)),
+                projection: bson(&doc("name", 1)),
+                skip: 1,
+                limit: 3,
+                batch_size: 2,
+                ..Default::default()
+            },
+        )
+        .await;
+        let names: Vec<Vec<_>> = batches
+            .iter()
+            .map(|batch| batch.iter().map(|user| user.get("name").cloned()).collect())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                vec![Some(Value::from("dave")), Some(Value::from("carol"))],
+                vec![Some(Value::from("bob"))],
+            ]
+        );
+        assert!(batches.iter().flatten().all(|user| user.get("n").is_none()));
+
+        // No limit or batch size finds everything, in one default batch
+        let everyone = find(
+            &mut client,
+            FindRequest {
+                collection: "users".to_string(),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(everyone.len(), 1);
+        assert_eq!(everyone[0].len(), 5);
+
+        let malformed = client
+            .find(FindRequest {
+                collection: "users".to_string(),
+                filter: json("{not json"),
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(malformed.code(), Code::InvalidArgument);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_grpc_update() {
+        let dir = scratch_dir("update");
+        let mut client = serve(Database::open(&dir).unwrap()).await;
+        insert_users(&mut client, &["alice", "bob", "carol"]).await;
+        let update = |filter, update, multi, upsert| UpdateRequest {
+            collection: "users".to_string(),
+            filter: bson(&filter),
+            update: json(update),
+            multi,
+            upsert,
+            ..Default::default()
+        };
+
+        // Operators update the first match, or every match with multi
+        let one = client
+            .update(update(
+                Document::new(),
+                r#"{"$inc": {"n": 10}}"#,
+                false,
+                false,
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!((one.matched_count, one.modified_count), (1, 1));
+        let many = client
+            .update(update(
+                Document::new(),
+                r#"{"$set": {"seen": true}}"#,
+                true,
+                false,
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!((many.matched_count, many.modified_count), (3, 3));
+        assert_eq!(many.upserted_id, None);
+
+        // A document without operators replaces the match
+        let replaced = client
+            .update(update(doc("_id", 2), r#"{"name": "robert"}"#, false, false))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(replaced.modified_count, 1);
+        let bob = find(
+            &mut client,
+            FindRequest {
+                collection: "users".to_string(),
+                filter: bson(&doc("_id", 2)),
+                ..Default::default()
+            },
+        )
+        .await;
+        let mut robert = doc("_id", 2);
+        robert.insert("name", "robert");
+        assert_eq!(bob, vec![vec![robert]]);
+
+        // An upsert reports the _id it inserted
+        let upserted = client
+            .update(update(
+                doc("_id", 9),
+                r#"{"$set": {"name": "ivan"}}"#,
+                false,
+                true,
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(upserted.matched_count, 0);
+        assert_eq!(upserted.upserted_id.as_ref().map(read), Some(doc("_id", 9)));
+
+        let multi_replacement = client
+            .update(update(Document::new(), r#"{"name": "x"}"#, true, false))
+            .await
+            .unwrap_err();
+        assert_eq!(multi_replacement.code(), Code::InvalidArgument);
+        let missing = client
+            .update(UpdateRequest {
+                collection: "users".to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(missing.code(), Code::InvalidArgument);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_grpc_delete() {
+        let dir = scratch_dir("delete");
+        let mut client = serve(Database::open(&dir).unwrap()).await;
+        insert_users(&mut client, &["alice", "bob", "carol", "dave"]).await;
+        let delete = |filter: &str, multi| DeleteRequest {
+            collection: "users".to_string(),
+            filter: json(filter),
+            multi,
+        };
+
+        // Only the first match goes, unless multi is set
+        let one = client
+            .delete(delete(r#"{"n": {"$gt": 1}}"#, false))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(one.deleted_count, 1);
+        let many = client
+            .delete(delete(r#"{"n": {"$gt": 1}}"#, true))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(many.deleted_count, 2);
+        let left = find(
+            &mut client,
+            FindRequest {
+                collection: "users".to_string(),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(left.concat().len(), 1);
+
+        let malformed = client.delete(delete("{not json", true)).await.unwrap_err();
+        assert_eq!(malformed.code(), Code::InvalidArgument);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_grpc_watch() {
+        let dir = scratch_dir("watch");
+        let mut client = serve(Database::open(&dir).unwrap()).await;
+        let mut inserts = client
+            .watch(WatchRequest {
+                collection: "users".to_string(),
+                filter: bson(&doc("operationType", "insert")),
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        insert_users(&mut client, &["alice"]).await;
+        client
+            .delete(DeleteRequest {
+                collection: "users".to_string(),
+                filter: bson(&doc("_id", 1)),
+                multi: false,
+            })
+            .await
+            .unwrap();
+        insert_users(&mut client, &["alice", "bob"]).await;
+
+        // The filter leaves out the delete, and each event carries a token
+        // to resume after it
+        let mut events = Vec::new();
+        for _ in 0..3 {
+            events.push(inserts.message().await.unwrap().unwrap());
+        }
+        let keys: Vec<_> = events
+            .iter()
+            .map(|change| {
+                read(change.event.as_ref().unwrap())
+                    .get("documentKey")
+                    .cloned()
+            })
+            .collect();
+        let key = |n| Some(Value::from(doc("_id", n)));
+        assert_eq!(keys, vec![key(1), key(1), key(2)]);
+
+        let mut resumed = client
+            .watch(WatchRequest {
+                collection: "users".to_string(),
+                resume_after: events[0].resume_token.clone(),
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        let next = resumed.message().await.unwrap().unwrap();
+        let event = read(next.event.as_ref().unwrap());
+        assert_eq!(event.get("operationType"), Some(&Value::from("delete")));
+
+        let bad_token = client
+            .watch(WatchRequest {
+                collection: "users".to_string(),
+                resume_after: vec![1, 2, 3],
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(bad_token.code(), Code::InvalidArgument);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_grpc_json_encoding() {
+        let dir = scratch_dir("json");
+        let mut client = serve(Database::open(&dir).unwrap()).await;
+        let json_body = |document: &proto::Document| match &document.body {
+            Some(Body::Json(text)) => text.clone(),
+            other => panic!("expected JSON, got {:?}", other),
+        };
+        let mut changes = client
+            .watch(WatchRequest {
+                collection: "users".to_string(),
+                encoding: Encoding::Json as i32,
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+
+        // Replies come back as canonical Extended JSON when asked for
+        let inserted = client
+            .insert(InsertRequest {
+                collection: "users".to_string(),
+                documents: vec![json(r#"{"_id": 1, "name": "alice"}"#).unwrap()],
+                encoding: Encoding::Json as i32,
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(
+            json_body(&inserted.inserted_ids[0]),
+            r#"{"_id":{"$numberInt":"1"}}"#
+        );
+        let mut found = client
+            .find(FindRequest {
+                collection: "users".to_string(),
+                encoding: Encoding::Json as i32,
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        let batch = found.message().await.unwrap().unwrap();
+        assert_eq!(
+            json_body(&batch.documents[0]),
+            r#"{"_id":{"$numberInt":"1"},"name":"alice"}"#
+        );
+        let upserted = client
+            .update(UpdateRequest {
+                collection: "users".to_string(),
+                filter: bson(&doc("_id", 2)),
+                update: json(r#"{"$set": {"name": "bob"}}"#),
+                upsert: true,
+                encoding: Encoding::Json as i32,
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(
+            json_body(upserted.upserted_id.as_ref().unwrap()),
+            r#"{"_id":{"$numberInt":"2"}}"#
+        );
+        let change = changes.message().await.unwrap().unwrap();
+        let event = from_json_str(&json_body(change.event.as_ref().unwrap())).unwrap();
+        assert_eq!(event.get("documentKey"), Some(&Value::from(doc("_id", 1))));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
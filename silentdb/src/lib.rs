@@ -0,0 +1,52 @@
+// src/lib.rs
+
+// Declare modules
+pub mod accounting;
+pub mod db;
+pub mod error;
+pub mod geo;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod query;
+pub mod server;
+pub mod storage;
+pub mod text;
+
+// Re-export commonly used items
+pub use accounting::{
+    memory_stats, CategoryStats, MemoryCategory, MemoryScope, MemoryStats, TrackingAllocator,
+};
+pub use db::{Action, Role, UserInfo};
+pub use db::{BackupStats, Collection, Cursor, Database, DatabaseError};
+pub use db::{ChangeEvent, ChangeKind, ChangeStream, ResumeToken, UpdateDescription};
+pub use db::{CheckIssue, CheckLevel, CheckReport, Repair};
+pub use db::{
+    CheckpointStats, CollectionStats, CompactionOptions, CompactionStats, CountOptions,
+    DeleteResult, FindOneAndModifyOptions, FindOptions, IndexInfo, IndexOptions, IndexStats,
+    InsertManyResult, InsertOneResult, ReturnDocument, SortOrder, Transaction, TtlStats,
+    UpdateOptions, UpdateResult,
+};
+pub use db::{Chunk, MigrationStats, Router, ShardKey, ShardedCollection, ShardedCursor};
+pub use db::{CollectionInfo, CollectionOptions, ReadConcern, WriteConcern};
+pub use db::{ErrorPolicy, Format, ImportOptions, ImportStats};
+pub use db::{Field, Filter, TypedCollection, TypedCursor, Update};
+pub use db::{LockMode, LockStats, MaintenanceTask, RecoveryStats, RecoveryTarget};
+#[cfg(feature = "parquet")]
+pub use db::{NestedPolicy, ParquetOptions, ParquetStats};
+pub use db::{OpTime, OplogEntry, Secondary, SyncSource};
+pub use db::{RaftConfig, RaftEntry, RaftMessage, RaftNode, RaftRole};
+pub use db::{SchemaMigrationOptions, SchemaMigrationStats};
+pub use db::{ValidationAction, ValidationLevel, ValidationStats, Validator};
+pub use error::{Error, Result};
+pub use geo::{Geometry, Point};
+#[cfg(feature = "grpc")]
+pub use grpc::GrpcService;
+pub use query::{Collation, Matcher, QueryError, SqlQuery};
+pub use server::{
+    AuditOptions, Protocol, Server, ServerError, ServerHandle, ServerOptions, TlsOptions,
+};
+pub use storage::{BTreeEngine, BTreeOptions, CacheStats, CompactionStep, Compression, KeyRange};
+pub use storage::{DirArchive, SegmentArchive, SyncPolicy, Wal, WalOptions, WalRecord, WalReplay};
+pub use storage::{Encryption, KeyProvider, KeyRing, LsmEngine, LsmOptions};
+pub use storage::{EngineStats, MemoryEngine, StorageEngine, StorageError, StorageIssue};
+pub use text::{TextOptions, Tokenizer};
@@ -1,3 +1,12 @@
-fn main() {
-    println!("Hello, world!");
+// src/main.rs
+
+//! The `silentdb` shell, running commands against a server or a local data
+//! directory, interactively or as a script.
+
+mod shell;
+
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    shell::main()
 }
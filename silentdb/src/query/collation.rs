@@ -0,0 +1,144 @@
+// src/query/collation.rs
+
+use silentdb_data_encoding::{Array, Document, Value};
+
+/// Digits used to write the length of a run of digits under numeric
+/// ordering, so that longer numbers sort after shorter ones.
+const LENGTH_DIGITS: usize = 8;
+
+/// How strings compare: in queries, in the key encoding of indexes, and
+/// so in index order.
+///
+/// By default strings compare by code point. A case-insensitive collation
+/// compares them lowercased, and numeric ordering compares runs of digits
+/// by their value, so `"item 9"` sorts before `"item 10"` and `"07"`
+/// equals `"7"`. The locale is recorded with the collation, and an index
+/// only serves queries with the same collation, but the comparisons are
+/// the same for every locale.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb::Collation;
+/// let collation = Collation::new("en")
+///     .case_sensitive(false)
+///     .numeric_ordering(true);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Collation {
+    pub(crate) locale: String,
+    pub(crate) case_sensitive: bool,
+    pub(crate) numeric_ordering: bool,
+}
+
+impl Collation {
+    /// Creates a case-sensitive collation without numeric ordering for
+    /// `locale`.
+    pub fn new(locale: &str) -> Self {
+        Collation {
+            locale: locale.to_string(),
+            case_sensitive: true,
+            numeric_ordering: false,
+        }
+    }
+
+    /// Returns the collation's locale.
+    pub fn locale(&self) -> &str {
+        &self.locale
+    }
+
+    /// Sets whether strings differing only in case compare unequal. On by
+    /// default.
+    pub fn case_sensitive(mut self, case_sensitive: bool) -> Self {
+        self.case_sensitive = case_sensitive;
+        self
+    }
+
+    /// Sets whether runs of digits compare by their value. Off by default.
+    pub fn numeric_ordering(mut self, numeric_ordering: bool) -> Self {
+        self.numeric_ordering = numeric_ordering;
+        self
+    }
+
+    /// Returns `true` if strings compare by code point alone, as they do
+    /// without a collation.
+    pub(crate) fn is_simple(&self) -> bool {
+        self.case_sensitive && !self.numeric_ordering
+    }
+
+    /// Returns `value` with every string in it, including inside arrays
+    /// and embedded documents, replaced by its collation key: a string
+    /// that compares by code point the way the original compares under the
+    /// collation.
+    pub(crate) fn key(&self, value: &Value) -> Value {
+        match value {
+            Value::String(string) if !self.is_simple() => Value::String(self.string_key(string)),
+            Value::Array(array) if !self.is_simple() => {
+                Value::Array(Array::from_vec(array.iter().map(|v| self.key(v)).collect()))
+            }
+            Value::Document(document) if !self.is_simple() => {
+                let mut keyed = Document::new();
+                for (name, value) in document.iter() {
+                    keyed.insert(name.as_str(), self.key(value));
+                }
+                Value::Document(keyed)
+            }
+            value => value.clone(),
+        }
+    }
+
+    fn string_key(&self, string: &str) -> String {
+        let folded = match self.case_sensitive {
+            true => string.to_string(),
+            false => string.to_lowercase(),
+        };
+        if !self.numeric_ordering {
+            return folded;
+        }
+        // Each run of digits becomes its length without leading zeros,
+        // then its digits, so that runs compare by value
+        let mut key = String::with_capacity(folded.len());
+        let mut rest = folded.as_str();
+        while let Some(start) = rest.find(|c: char| c.is_ascii_digit()) {
+            key.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let end = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            let digits = rest[..end].trim_start_matches('0');
+            key.push_str(&format!("{:0width$}", digits.len(), width = LENGTH_DIGITS));
+            key.push_str(digits);
+            rest = &rest[end..];
+        }
+        key.push_str(rest);
+        key
+    }
+
+    pub(crate) fn to_document(&self) -> Document {
+        let mut document = Document::new();
+        document.insert("locale", self.locale.as_str());
+        document.insert("case_sensitive", self.case_sensitive);
+        document.insert("numeric_ordering", self.numeric_ordering);
+        document
+    }
+
+    pub(crate) fn from_document(document: &Document) -> Option<Collation> {
+        let (
+            Some(Value::String(locale)),
+            Some(Value::Boolean(case_sensitive)),
+            Some(Value::Boolean(numeric_ordering)),
+        ) = (
+            document.get("locale"),
+            document.get("case_sensitive"),
+            document.get("numeric_ordering"),
+        )
+        else {
+            return None;
+        };
+        Some(Collation {
+            locale: locale.clone(),
+            case_sensitive: *case_sensitive,
+            numeric_ordering: *numeric_ordering,
+        })
+    }
+}
@@ -0,0 +1,16 @@
+// src/query/error.rs
+
+/// Represents errors in query documents such as filters.
+#[derive(Debug, thiserror::Error)]
+pub enum QueryError {
+    #[error("Invalid filter: {0}")]
+    InvalidFilter(String),
+    #[error("Invalid projection: {0}")]
+    InvalidProjection(String),
+    #[error("Invalid sort: {0}")]
+    InvalidSort(String),
+    #[error("Invalid SQL: {0}")]
+    InvalidSql(String),
+    #[error("Invalid regular expression: {0}")]
+    Regex(#[from] regex::Error),
+}
@@ -0,0 +1,710 @@
+// src/query/matcher.rs
+
+use std::cmp::Ordering;
+
+use regex::{Regex, RegexBuilder};
+use silentdb_data_encoding::{DeserializeError, Document, Key, RawDocument, RawElement, Value};
+
+use super::collation::Collation;
+use super::error::QueryError;
+use crate::geo::{Geometry, Near, Region};
+
+/// `$type` code matching every numeric type, as the `"number"` alias does.
+const NUMBER_TYPE: i32 = 0;
+
+/// A compiled query filter.
+///
+/// Filters are documents mapping dotted field paths to either a value the
+/// field must equal or a document of operators:
+///
+/// * `$eq`, `$ne`, `$gt`, `$gte`, `$lt` and `$lte` compare in the BSON
+///   comparison order. Range comparisons only match values of the same type
+///   bracket, so `{"$gt": 5}` matches numbers but never strings.
+/// * `$in` and `$nin` take an array of values or regular expressions.
+/// * `$exists` takes a boolean, and `$type` a type name such as `"string"`,
+///   a BSON type number, or an array of either.
+/// * `$regex` takes a pattern, with flags from `$options` (`i`, `m`, `s`
+///   and `x`). A regular expression value in place of an operator document
+///   matches the same way.
+/// * `$elemMatch` matches arrays with an element satisfying every operator
+///   or, given a filter, an embedded document matching it.
+/// * `$not` negates a document of operators or a regular expression.
+/// * `$geoWithin` matches GeoJSON geometries inside a `$geometry` polygon,
+///   a `$centerSphere` of `[[lng, lat], radians]` or a `$box` of two
+///   corners, and `$geoIntersects` those sharing a point with a
+///   `$geometry`. `$near` takes a `$geometry` point and optional
+///   `$minDistance` and `$maxDistance` in meters, and matches geometries in
+///   that range of it.
+///
+/// At the top level, `$and` and `$or` take arrays of filters. Every field of
+/// a filter must match.
+///
+/// As in MongoDB, a path through an array applies to each embedded document
+/// in it, and a predicate matches an array field if it matches the array
+/// or any of its elements. Equality with `null` also matches missing fields,
+/// and `$ne` and `$nin` match them.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb::query::Matcher;
+/// # use silentdb_data_encoding::{Array, Document, Value};
+/// let mut age = Document::new();
+/// age.insert("$gte", 21);
+/// let mut filter = Document::new();
+/// filter.insert("age", age);
+/// filter.insert("tags", "admin");
+/// let matcher = Matcher::new(&filter).unwrap();
+///
+/// let mut user = Document::new();
+/// user.insert("age", 30);
+/// user.insert("tags", Array::from_vec(vec!["admin".into(), "ops".into()]));
+/// assert!(matcher.matches(&user));
+///
+/// user.insert("age", 18.5);
+/// assert!(!matcher.matches(&user));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Matcher {
+    root: Expr,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+    /// The path's segments are hashed once, when the filter is compiled.
+    Field {
+        path: Vec<Key>,
+        predicate: Predicate,
+    },
+}
+
+#[derive(Debug, Clone)]
+enum Predicate {
+    All(Vec<Predicate>),
+    Not(Box<Predicate>),
+    Eq(Value),
+    Compare {
+        operand: Value,
+        accept: fn(Ordering) -> bool,
+    },
+    In(Vec<Pattern>),
+    Exists(bool),
+    Type(Vec<i32>),
+    Regex(Regex),
+    ElemMatch(ElemMatch),
+    GeoWithin(Region),
+    GeoIntersects(Geometry),
+    Near(Near),
+    /// A comparison whose operands are collation keys, made against the
+    /// collation keys of the values tested.
+    Collated(Collation, Box<Predicate>),
+}
+
+/// One of the alternatives of `$in`.
+#[derive(Debug, Clone)]
+enum Pattern {
+    Value(Value),
+    Regex(Regex),
+}
+
+#[derive(Debug, Clone)]
+enum ElemMatch {
+    /// Operators every element is tested against.
+    Value(Box<Predicate>),
+    /// A filter embedded documents are matched against.
+    Document(Box<Expr>),
+}
+
+impl Matcher {
+    /// Compiles `filter`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidFilter` if the filter uses an unknown operator or an
+    /// operator with the wrong kind of operand, or `Regex` if a regular
+    /// expression does not compile.
+    pub fn new(filter: &Document) -> Result<Matcher, QueryError> {
+        Ok(Matcher {
+            root: compile_filter(filter)?,
+        })
+    }
+
+    /// Compiles `filter` to compare strings under `collation` in `$eq`,
+    /// `$ne`, `$in`, `$nin` and the range operators. Regular expressions
+    /// still match the strings as they are.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of `new`.
+    pub fn with_collation(filter: &Document, collation: &Collation) -> Result<Matcher, QueryError> {
+        let root = compile_filter(filter)?;
+        Ok(Matcher {
+            root: match collation.is_simple() {
+                true => root,
+                false => root.collate(collation),
+            },
+        })
+    }
+
+    /// Returns `true` if `document` matches the filter.
+    pub fn matches(&self, document: &Document) -> bool {
+        self.root.matches(document)
+    }
+
+    /// Returns `true` if the encoded `document` matches the filter.
+    ///
+    /// Only the fields the filter refers to are decoded, which makes this
+    /// much cheaper than decoding documents that are then discarded.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a field the filter refers to is malformed.
+    pub fn matches_raw(&self, document: RawDocument<'_>) -> Result<bool, DeserializeError> {
+        self.root.matches_raw(document)
+    }
+}
+
+/* Collation */
+
+impl Expr {
+    fn collate(self, collation: &Collation) -> Expr {
+        match self {
+            Expr::And(exprs) => {
+                Expr::And(exprs.into_iter().map(|e| e.collate(collation)).collect())
+            }
+            Expr::Or(exprs) => Expr::Or(exprs.into_iter().map(|e| e.collate(collation)).collect()),
+            Expr::Field { path, predicate } => Expr::Field {
+                path,
+                predicate: predicate.collate(collation),
+            },
+        }
+    }
+}
+
+impl Predicate {
+    /// Makes the predicate's comparisons compare collation keys.
+    fn collate(self, collation: &Collation) -> Predicate {
+        let collated = |predicate| Predicate::Collated(collation.clone(), Box::new(predicate));
+        match self {
+            Predicate::All(predicates) => Predicate::All(
+                predicates
+                    .into_iter()
+                    .map(|predicate| predicate.collate(collation))
+                    .collect(),
+            ),
+            Predicate::Not(predicate) => Predicate::Not(Box::new(predicate.collate(collation))),
+            Predicate::Eq(operand) => collated(Predicate::Eq(collation.key(&operand))),
+            Predicate::Compare { operand, accept } => collated(Predicate::Compare {
+                operand: collation.key(&operand),
+                accept,
+            }),
+            Predicate::In(patterns) => {
+                let (values, regexes): (Vec<Pattern>, Vec<Pattern>) = patterns
+                    .into_iter()
+                    .partition(|pattern| matches!(pattern, Pattern::Value(_)));
+                let values = values
+                    .into_iter()
+                    .map(|pattern| match pattern {
+                        Pattern::Value(value) => Pattern::Value(collation.key(&value)),
+                        pattern => pattern,
+                    })
+                    .collect();
+                let values = collated(Predicate::In(values));
+                if regexes.is_empty() {
+                    return values;
+                }
+                // Either kind of pattern matching, with the regular
+                // expressions tested against the strings as they are
+                let not = |predicate| Predicate::Not(Box::new(predicate));
+                not(Predicate::All(vec![
+                    not(values),
+                    not(Predicate::In(regexes)),
+                ]))
+            }
+            Predicate::ElemMatch(ElemMatch::Value(predicate)) => {
+                Predicate::ElemMatch(ElemMatch::Value(Box::new(predicate.collate(collation))))
+            }
+            Predicate::ElemMatch(ElemMatch::Document(expr)) => {
+                Predicate::ElemMatch(ElemMatch::Document(Box::new(expr.collate(collation))))
+            }
+            predicate => predicate,
+        }
+    }
+}
+
+/* Evaluation */
+
+impl Expr {
+    fn matches(&self, document: &Document) -> bool {
+        match self {
+            Expr::And(exprs) => exprs.iter().all(|expr| expr.matches(document)),
+            Expr::Or(exprs) => exprs.iter().any(|expr| expr.matches(document)),
+            Expr::Field { path, predicate } => {
+                let mut values = Vec::new();
+                resolve(document, path, &mut values);
+                predicate.test(&values)
+            }
+        }
+    }
+
+    fn matches_raw(&self, document: RawDocument<'_>) -> Result<bool, DeserializeError> {
+        match self {
+            Expr::And(exprs) => {
+                for expr in exprs {
+                    if !expr.matches_raw(document)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            Expr::Or(exprs) => {
+                for expr in exprs {
+                    if expr.matches_raw(document)? {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+            Expr::Field { path, predicate } => {
+                let mut values = Vec::new();
+                resolve_raw(document, path, &mut values)?;
+                Ok(predicate.test(&values.iter().collect::<Vec<_>>()))
+            }
+        }
+    }
+}
+
+impl Predicate {
+    /// Tests the values found at a field's path, which is empty if the
+    /// field is missing.
+    fn test(&self, values: &[&Value]) -> bool {
+        match self {
+            Predicate::All(predicates) => predicates.iter().all(|p| p.test(values)),
+            Predicate::Not(predicate) => !predicate.test(values),
+            Predicate::Exists(exists) => values.is_empty() != *exists,
+            Predicate::Collated(collation, predicate) => {
+                let keys: Vec<Value> = values.iter().map(|value| collation.key(value)).collect();
+                predicate.test(&keys.iter().collect::<Vec<_>>())
+            }
+            Predicate::ElemMatch(elem_match) => values.iter().any(|value| match value {
+                Value::Array(array) => array.iter().any(|element| elem_match.matches(element)),
+                _ => false,
+            }),
+            Predicate::Eq(Value::Null) if values.is_empty() => true,
+            Predicate::In(patterns) if values.is_empty() => patterns
+                .iter()
+                .any(|pattern| matches!(pattern, Pattern::Value(Value::Null))),
+            _ => values.iter().any(|value| {
+                self.test_value(value)
+                    || matches!(value, Value::Array(array) if array.iter().any(|e| self.test_value(e)))
+            }),
+        }
+    }
+
+    /// Tests a single value, or an element of an array value.
+    fn test_value(&self, value: &Value) -> bool {
+        match self {
+            Predicate::Eq(operand) => value.bson_cmp(operand) == Ordering::Equal,
+            Predicate::Compare { operand, accept } => {
+                value.type_order() == operand.type_order() && accept(value.bson_cmp(operand))
+            }
+            Predicate::In(patterns) => patterns.iter().any(|pattern| pattern.matches(value)),
+            Predicate::Type(codes) => codes.iter().any(|code| type_matches(*code, value)),
+            Predicate::Regex(regex) => regex_matches(regex, value),
+            Predicate::GeoWithin(region) => {
+                Geometry::from_value(value).is_some_and(|geometry| region.contains(&geometry))
+            }
+            Predicate::GeoIntersects(other) => {
+                Geometry::from_value(value).is_some_and(|geometry| geometry.intersects(other))
+            }
+            Predicate::Near(near) => Geometry::from_value(value)
+                .is_some_and(|geometry| near.distance(&geometry).is_some()),
+            _ => self.test(&[value]),
+        }
+    }
+}
+
+impl Pattern {
+    fn matches(&self, value: &Value) -> bool {
+        match self {
+            Pattern::Value(operand) => value.bson_cmp(operand) == Ordering::Equal,
+            Pattern::Regex(regex) => regex_matches(regex, value),
+        }
+    }
+}
+
+impl ElemMatch {
+    fn matches(&self, element: &Value) -> bool {
+        match self {
+            ElemMatch::Value(predicate) => predicate.test(&[element]),
+            ElemMatch::Document(expr) => {
+                matches!(element, Value::Document(document) if expr.matches(document))
+            }
+        }
+    }
+}
+
+fn regex_matches(regex: &Regex, value: &Value) -> bool {
+    match value {
+        Value::String(s) | Value::Symbol(s) => regex.is_match(s),
+        _ => false,
+    }
+}
+
+fn type_matches(code: i32, value: &Value) -> bool {
+    match code {
+        NUMBER_TYPE => matches!(
+            value,
+            Value::Int32(_) | Value::Int64(_) | Value::UInt64(_) | Value::Double(_)
+        ),
+        code => type_code(value) == code,
+    }
+}
+
+/// Returns the BSON type number of `value`.
+fn type_code(value: &Value) -> i32 {
+    match value {
+        Value::Double(_) => 1,
+        Value::String(_) => 2,
+        Value::Document(_) => 3,
+        Value::Array(_) => 4,
+        Value::Binary(_) => 5,
+        Value::Undefined => 6,
+        Value::ObjectId(_) => 7,
+        Value::Boolean(_) => 8,
+        Value::UTCDateTime(_) => 9,
+        Value::Null => 10,
+        Value::RegularExpression { .. } => 11,
+        Value::DbPointer { .. } => 12,
+        Value::JavaScriptCode(_) => 13,
+        Value::Symbol(_) => 14,
+        Value::JavaScriptCodeWithScope { .. } => 15,
+        Value::Int32(_) => 16,
+        Value::Timestamp(_) => 17,
+        Value::Int64(_) => 18,
+        Value::UInt64(_) => 19,
+        Value::MinKey => -1,
+        Value::MaxKey => 127,
+    }
+}
+
+/* Path Resolution */
+
+/// Returns the values a filter on the dotted `path` is tested against in
+/// the encoded `document`: every value found at the path, followed by the
+/// elements of each one that is an array. Empty if the path is missing.
+pub(crate) fn path_values(
+    document: RawDocument<'_>,
+    path: &str,
+) -> Result<Vec<Value>, DeserializeError> {
+    let segments: Vec<Key> = path.split('.').map(Key::new).collect();
+    let mut values = Vec::new();
+    resolve_raw(document, &segments, &mut values)?;
+    let elements: Vec<Value> = values
+        .iter()
+        .filter_map(|value| match value {
+            Value::Array(array) => Some(array.iter().cloned()),
+            _ => None,
+        })
+        .flatten()
+        .collect();
+    values.extend(elements);
+    Ok(values)
+}
+
+/// Collects the values at `path`, descending into every embedded document
+/// of an array as well as indexing it by a numeric segment.
+fn resolve<'a>(document: &'a Document, path: &[Key], out: &mut Vec<&'a Value>) {
+    if let Some(value) = document.get_key(&path[0]) {
+        resolve_value(value, &path[1..], out);
+    }
+}
+
+fn resolve_value<'a>(value: &'a Value, rest: &[Key], out: &mut Vec<&'a Value>) {
+    let Some(segment) = rest.first() else {
+        out.push(value);
+        return;
+    };
+    match value {
+        Value::Document(document) => resolve(document, rest, out),
+        Value::Array(array) => {
+            if let Some(element) = segment.as_str().parse().ok().and_then(|i| array.get(i)) {
+                resolve_value(element, &rest[1..], out);
+            }
+            for element in array.iter() {
+                if let Value::Document(document) = element {
+                    resolve(document, rest, out);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Like `resolve`, decoding only the values found.
+fn resolve_raw(
+    document: RawDocument<'_>,
+    path: &[Key],
+    out: &mut Vec<Value>,
+) -> Result<(), DeserializeError> {
+    match document.get(path[0].as_str())? {
+        Some(element) => resolve_raw_element(element, &path[1..], out),
+        None => Ok(()),
+    }
+}
+
+fn resolve_raw_element(
+    element: RawElement<'_>,
+    rest: &[Key],
+    out: &mut Vec<Value>,
+) -> Result<(), DeserializeError> {
+    let Some(segment) = rest.first() else {
+        out.push(element.value()?);
+        return Ok(());
+    };
+    if let Some(document) = element.as_document() {
+        return resolve_raw(document, rest, out);
+    }
+    if let Some(array) = element.as_array() {
+        if let Ok(index) = segment.as_str().parse() {
+            if let Some(element) = array.get(index)? {
+                resolve_raw_element(element, &rest[1..], out)?;
+            }
+        }
+        for element in array.iter() {
+            if let Some(document) = element?.as_document() {
+                resolve_raw(document, rest, out)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/* Compilation */
+
+fn invalid(message: impl Into<String>) -> QueryError {
+    QueryError::InvalidFilter(message.into())
+}
+
+fn compile_filter(filter: &Document) -> Result<Expr, QueryError> {
+    let mut exprs = Vec::with_capacity(filter.len());
+    for (key, value) in filter.iter() {
+        let expr = match key.as_str() {
+            "$and" => Expr::And(compile_filters(key, value)?),
+            "$or" => Expr::Or(compile_filters(key, value)?),
+            key if key.starts_with('$') => {
+                return Err(invalid(format!("unknown top-level operator {}", key)))
+            }
+            key => Expr::Field {
+                path: key.split('.').map(Key::new).collect(),
+                predicate: compile_value(value)?,
+            },
+        };
+        exprs.push(expr);
+    }
+    Ok(Expr::And(exprs))
+}
+
+fn compile_filters(operator: &str, value: &Value) -> Result<Vec<Expr>, QueryError> {
+    let expected = || invalid(format!("{} expects a non-empty array of filters", operator));
+    let Value::Array(filters) = value else {
+        return Err(expected());
+    };
+    if filters.is_empty() {
+        return Err(expected());
+    }
+    filters
+        .iter()
+        .map(|filter| match filter {
+            Value::Document(filter) => compile_filter(filter),
+            _ => Err(expected()),
+        })
+        .collect()
+}
+
+/// Compiles the value a field is filtered on: an operator document, a
+/// regular expression, or a value to compare with.
+fn compile_value(value: &Value) -> Result<Predicate, QueryError> {
+    match value {
+        Value::Document(operators) if is_operator_document(operators) => {
+            compile_operators(operators)
+        }
+        Value::RegularExpression { pattern, options } => {
+            Ok(Predicate::Regex(compile_regex(pattern, options)?))
+        }
+        value => Ok(Predicate::Eq(value.clone())),
+    }
+}
+
+fn is_operator_document(document: &Document) -> bool {
+    document.iter().any(|(key, _)| key.starts_with('$'))
+}
+
+fn compile_operators(operators: &Document) -> Result<Predicate, QueryError> {
+    let options = match operators.get("$options") {
+        Some(Value::String(options)) => Some(options.as_str()),
+        Some(_) => return Err(invalid("$options expects a string")),
+        None => None,
+    };
+
+    let mut predicates = Vec::with_capacity(operators.len());
+    for (operator, operand) in operators.iter() {
+        let compare = |accept: fn(Ordering) -> bool| Predicate::Compare {
+            operand: operand.clone(),
+            accept,
+        };
+        let predicate = match operator.as_str() {
+            "$eq" => Predicate::Eq(operand.clone()),
+            "$ne" => Predicate::Not(Box::new(Predicate::Eq(operand.clone()))),
+            "$gt" => compare(Ordering::is_gt),
+            "$gte" => compare(Ordering::is_ge),
+            "$lt" => compare(Ordering::is_lt),
+            "$lte" => compare(Ordering::is_le),
+            "$in" => Predicate::In(compile_patterns(operator, operand)?),
+            "$nin" => Predicate::Not(Box::new(Predicate::In(compile_patterns(
+                operator, operand,
+            )?))),
+            "$exists" => Predicate::Exists(match operand {
+                Value::Boolean(exists) => *exists,
+                _ => return Err(invalid("$exists expects a boolean")),
+            }),
+            "$type" => Predicate::Type(compile_types(operand)?),
+            "$regex" => Predicate::Regex(match operand {
+                Value::String(pattern) => compile_regex(pattern, options.unwrap_or(""))?,
+                Value::RegularExpression {
+                    pattern,
+                    options: own,
+                } => compile_regex(pattern, options.unwrap_or(own))?,
+                _ => return Err(invalid("$regex expects a string or regular expression")),
+            }),
+            "$options" if operators.contains_key("$regex") => continue,
+            "$options" => return Err(invalid("$options requires $regex")),
+            "$elemMatch" => Predicate::ElemMatch(compile_elem_match(operand)?),
+            "$geoWithin" => Predicate::GeoWithin(Region::from_operand(operand).map_err(invalid)?),
+            "$geoIntersects" => {
+                Predicate::GeoIntersects(Geometry::from_operand(operand).map_err(invalid)?)
+            }
+            "$near" => Predicate::Near(Near::from_operand(operand).map_err(invalid)?),
+            "$not" => Predicate::Not(Box::new(match operand {
+                Value::Document(operators) if is_operator_document(operators) => {
+                    compile_operators(operators)?
+                }
+                Value::RegularExpression { pattern, options } => {
+                    Predicate::Regex(compile_regex(pattern, options)?)
+                }
+                _ => {
+                    return Err(invalid(
+                        "$not expects a document of operators or a regular expression",
+                    ))
+                }
+            })),
+            operator if operator.starts_with('$') => {
+                return Err(invalid(format!("unknown operator {}", operator)))
+            }
+            field => {
+                return Err(invalid(format!(
+                    "cannot mix operators and field {} in one document",
+                    field
+                )))
+            }
+        };
+        predicates.push(predicate);
+    }
+    Ok(Predicate::All(predicates))
+}
+
+fn compile_patterns(operator: &str, operand: &Value) -> Result<Vec<Pattern>, QueryError> {
+    let Value::Array(values) = operand else {
+        return Err(invalid(format!("{} expects an array", operator)));
+    };
+    values
+        .iter()
+        .map(|value| match value {
+            Value::RegularExpression { pattern, options } => {
+                Ok(Pattern::Regex(compile_regex(pattern, options)?))
+            }
+            value => Ok(Pattern::Value(value.clone())),
+        })
+        .collect()
+}
+
+fn compile_elem_match(operand: &Value) -> Result<ElemMatch, QueryError> {
+    let Value::Document(filter) = operand else {
+        return Err(invalid("$elemMatch expects a document"));
+    };
+    // Operators other than $and and $or apply to the elements themselves
+    let on_values = filter
+        .iter()
+        .any(|(key, _)| key.starts_with('$') && key != "$and" && key != "$or");
+    Ok(if on_values {
+        ElemMatch::Value(Box::new(compile_operators(filter)?))
+    } else {
+        ElemMatch::Document(Box::new(compile_filter(filter)?))
+    })
+}
+
+fn compile_types(operand: &Value) -> Result<Vec<i32>, QueryError> {
+    match operand {
+        Value::Array(types) => types.iter().map(compile_type).collect(),
+        operand => Ok(vec![compile_type(operand)?]),
+    }
+}
+
+fn compile_type(operand: &Value) -> Result<i32, QueryError> {
+    let code = match operand {
+        Value::String(alias) => match alias.as_str() {
+            "double" => 1,
+            "string" => 2,
+            "object" => 3,
+            "array" => 4,
+            "binData" => 5,
+            "undefined" => 6,
+            "objectId" => 7,
+            "bool" => 8,
+            "date" => 9,
+            "null" => 10,
+            "regex" => 11,
+            "dbPointer" => 12,
+            "javascript" => 13,
+            "symbol" => 14,
+            "javascriptWithScope" => 15,
+            "int" => 16,
+            "timestamp" => 17,
+            "long" => 18,
+            "uint64" => 19,
+            "minKey" => -1,
+            "maxKey" => 127,
+            "number" => NUMBER_TYPE,
+            alias => return Err(invalid(format!("unknown type alias {}", alias))),
+        },
+        Value::Int32(code) => *code,
+        Value::Int64(code) => i32::try_from(*code).unwrap_or(i32::MAX),
+        Value::Double(code) if code.fract() == 0.0 => *code as i32,
+        _ => return Err(invalid("$type expects a type name or number")),
+    };
+    match code {
+        -1 | 1..=19 | 127 | NUMBER_TYPE => Ok(code),
+        code => Err(invalid(format!("unknown type number {}", code))),
+    }
+}
+
+fn compile_regex(pattern: &str, options: &str) -> Result<Regex, QueryError> {
+    let mut builder = RegexBuilder::new(pattern);
+    for option in options.chars() {
+        match option {
+            'i' => builder.case_insensitive(true),
+            'm' => builder.multi_line(true),
+            's' => builder.dot_matches_new_line(true),
+            'x' => builder.ignore_whitespace(true),
+            option => {
+                return Err(invalid(format!(
+                    "unsupported regular expression option '{}'",
+                    option
+                )))
+            }
+        };
+    }
+    Ok(builder.build()?)
+}
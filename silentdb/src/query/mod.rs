@@ -0,0 +1,13 @@
+// src/query/mod.rs
+
+mod collation;
+mod error;
+mod matcher;
+mod sql;
+mod test;
+
+pub use collation::Collation;
+pub use error::QueryError;
+pub(crate) use matcher::path_values;
+pub use matcher::Matcher;
+pub use sql::SqlQuery;
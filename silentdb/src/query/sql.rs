@@ -0,0 +1,533 @@
+// src/query/sql.rs
+
+use silentdb_data_encoding::{Array, Document, Value};
+
+use super::error::QueryError;
+
+/// Words with a meaning of their own, which a field path must quote.
+const KEYWORDS: [&str; 19] = [
+    "SELECT", "FROM", "WHERE", "ORDER", "BY", "ASC", "DESC", "LIMIT", "OFFSET", "AND", "OR", "NOT",
+    "IN", "LIKE", "BETWEEN", "IS", "NULL", "TRUE", "FALSE",
+];
+
+/// A SQL `SELECT` statement, translated into the find it stands for.
+///
+/// The statements understood are of the form
+///
+/// ```text
+/// SELECT * | <path>, ... FROM <collection>
+///     [WHERE <condition>]
+///     [ORDER BY <path> [ASC | DESC], ...]
+///     [LIMIT <count> [OFFSET <count>]]
+/// ```
+///
+/// Paths are dotted, such as `address.city`, and are quoted with `"` or
+/// `` ` `` where they are keywords or hold other characters. Conditions
+/// compare paths to literals with `=`, `!=` or `<>`, `<`, `<=`, `>` and
+/// `>=`, and combine them with `AND`, `OR`, `NOT` and parentheses; `IN`,
+/// `LIKE`, `BETWEEN` and `IS NULL` may each be negated with `NOT`.
+/// Literals are numbers, strings in single quotes, `TRUE`, `FALSE` and
+/// `NULL`.
+///
+/// Conditions match as filters do, as `Matcher` describes, rather than
+/// with SQL's three-valued logic: `NOT (age = 30)` becomes
+/// `{"age": {"$ne": 30}}`, which matches documents without an age, and a
+/// comparison with an array field matches if any element satisfies it.
+/// Selected paths are projected, leaving out `_id` unless it is selected.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb::query::SqlQuery;
+/// # use silentdb_data_encoding::from_json_str;
+/// let query = SqlQuery::parse(
+///     "SELECT name, address.city FROM users \
+///      WHERE age >= 21 AND name LIKE 'A%' ORDER BY age DESC LIMIT 10",
+/// )
+/// .unwrap();
+/// assert_eq!(query.collection, "users");
+/// assert_eq!(
+///     query.filter,
+///     from_json_str(
+///         r#"{"$and": [{"age": {"$gte": 21}},
+///                      {"name": {"$regex": "^A.*$", "$options": "s"}}]}"#
+///     )
+///     .unwrap()
+/// );
+/// assert_eq!(query.limit, Some(10));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct SqlQuery {
+    /// The collection selected from.
+    pub collection: String,
+    /// The filter the `WHERE` clause stands for, empty without one.
+    pub filter: Document,
+    /// The paths selected, as a projection, or `None` for `*`.
+    pub projection: Option<Document>,
+    /// The `ORDER BY` clause, as a sort.
+    pub sort: Option<Document>,
+    /// The documents skipped by `OFFSET`.
+    pub skip: u64,
+    /// The most documents `LIMIT` returns.
+    pub limit: Option<u64>,
+}
+
+impl SqlQuery {
+    /// Parses the `SELECT` statement `sql`, which may end with `;`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidSql` if `sql` is not a statement of the form
+    /// `SqlQuery` describes.
+    pub fn parse(sql: &str) -> Result<SqlQuery, QueryError> {
+        Parser {
+            tokens: tokenize(sql)?,
+            position: 0,
+        }
+        .statement()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// A bare word: a keyword, or a path.
+    Word(String),
+    /// A path in double quotes or backticks.
+    Quoted(String),
+    String(String),
+    Number(Value),
+    Symbol(&'static str),
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Word(word) => format!("`{}`", word),
+            Token::Quoted(path) => format!("\"{}\"", path),
+            Token::String(text) => format!("'{}'", text),
+            Token::Number(number) => number.to_string(),
+            Token::Symbol(symbol) => format!("`{}`", symbol),
+        }
+    }
+}
+
+fn invalid(message: impl Into<String>) -> QueryError {
+    QueryError::InvalidSql(message.into())
+}
+
+/// Splits `sql` into tokens.
+fn tokenize(sql: &str) -> Result<Vec<Token>, QueryError> {
+    let mut tokens = Vec::new();
+    let mut chars = sql.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        let token = match c {
+            c if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            '\'' | '"' | '`' => {
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        // A doubled quote stands for itself
+                        Some((_, q)) if q == c => match chars.peek() {
+                            Some(&(_, next)) if next == c => {
+                                chars.next();
+                                text.push(c);
+                            }
+                            _ => break,
+                        },
+                        Some((_, other)) => text.push(other),
+                        None => return Err(invalid(format!("unterminated {} at {}", c, start))),
+                    }
+                }
+                match c {
+                    '\'' => Token::String(text),
+                    _ => Token::Quoted(text),
+                }
+            }
+            c if c.is_ascii_digit() || c == '-' || c == '.' => {
+                let mut end = start;
+                while let Some(&(i, c)) = chars.peek() {
+                    let exponent_sign = (c == '-' || c == '+')
+                        && matches!(sql[..i].chars().last(), Some('e' | 'E'));
+                    if c.is_ascii_alphanumeric() || c == '.' || exponent_sign || i == start {
+                        end = i + c.len_utf8();
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                Token::Number(number(&sql[start..end])?)
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut end = start;
+                while let Some(&(i, c)) = chars.peek() {
+                    if c.is_alphanumeric() || c == '_' || c == '.' {
+                        end = i + c.len_utf8();
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                Token::Word(sql[start..end].to_string())
+            }
+            _ => {
+                let symbol = [
+                    "<=", ">=", "<>", "!=", "*", ",", "(", ")", "=", "<", ">", ";",
+                ]
+                .into_iter()
+                .find(|symbol| sql[start..].starts_with(symbol))
+                .ok_or_else(|| invalid(format!("unexpected {:?} at {}", c, start)))?;
+                for _ in 0..symbol.len() {
+                    chars.next();
+                }
+                Token::Symbol(symbol)
+            }
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// Reads a number literal: an integer of the narrowest type holding it,
+/// or a double.
+fn number(text: &str) -> Result<Value, QueryError> {
+    if let Ok(integer) = text.parse::<i64>() {
+        return Ok(match i32::try_from(integer) {
+            Ok(integer) => Value::Int32(integer),
+            Err(_) => Value::Int64(integer),
+        });
+    }
+    text.parse::<f64>()
+        .ok()
+        .filter(|double| double.is_finite())
+        .map(Value::Double)
+        .ok_or_else(|| invalid(format!("{} is not a number", text)))
+}
+
+/// Reads a statement from its tokens.
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn statement(&mut self) -> Result<SqlQuery, QueryError> {
+        self.expect_keyword("SELECT")?;
+        let projection = match self.eat_symbol("*") {
+            true => None,
+            false => {
+                let mut projection = Document::new();
+                loop {
+                    projection.insert(self.path()?, 1);
+                    if !self.eat_symbol(",") {
+                        break;
+                    }
+                }
+                if !projection.contains_key("_id") {
+                    projection.insert("_id", 0);
+                }
+                Some(projection)
+            }
+        };
+        self.expect_keyword("FROM")?;
+        let collection = self.path()?;
+        let filter = match self.eat_keyword("WHERE") {
+            true => self.or(false)?,
+            false => Document::new(),
+        };
+        let sort = match self.eat_keyword("ORDER") {
+            true => {
+                self.expect_keyword("BY")?;
+                let mut sort = Document::new();
+                loop {
+                    let path = self.path()?;
+                    let direction = match self.eat_keyword("DESC") {
+                        true => -1,
+                        false => {
+                            self.eat_keyword("ASC");
+                            1
+                        }
+                    };
+                    sort.insert(path, direction);
+                    if !self.eat_symbol(",") {
+                        break;
+                    }
+                }
+                Some(sort)
+            }
+            false => None,
+        };
+        let mut limit = None;
+        let mut skip = 0;
+        if self.eat_keyword("LIMIT") {
+            limit = Some(self.count("LIMIT")?);
+            if self.eat_keyword("OFFSET") {
+                skip = self.count("OFFSET")?;
+            }
+        }
+        self.eat_symbol(";");
+        if let Some(token) = self.tokens.get(self.position) {
+            return Err(invalid(format!("unexpected {}", token.describe())));
+        }
+        Ok(SqlQuery {
+            collection,
+            filter,
+            projection,
+            sort,
+            skip,
+            limit,
+        })
+    }
+
+    /// Reads conditions joined by `OR`, as a filter, or as the filter
+    /// matching where they do not if `negated`.
+    fn or(&mut self, negated: bool) -> Result<Document, QueryError> {
+        let mut terms = vec![self.and(negated)?];
+        while self.eat_keyword("OR") {
+            terms.push(self.and(negated)?);
+        }
+        // Not (a or b) is (not a) and (not b)
+        Ok(combine(if negated { "$and" } else { "$or" }, terms))
+    }
+
+    fn and(&mut self, negated: bool) -> Result<Document, QueryError> {
+        let mut factors = vec![self.not(negated)?];
+        while self.eat_keyword("AND") {
+            factors.push(self.not(negated)?);
+        }
+        Ok(combine(if negated { "$or" } else { "$and" }, factors))
+    }
+
+    fn not(&mut self, negated: bool) -> Result<Document, QueryError> {
+        match self.eat_keyword("NOT") {
+            true => self.not(!negated),
+            false if self.eat_symbol("(") => {
+                let filter = self.or(negated)?;
+                self.expect_symbol(")")?;
+                Ok(filter)
+            }
+            false => self.predicate(negated),
+        }
+    }
+
+    /// Reads a condition on a path.
+    fn predicate(&mut self, negated: bool) -> Result<Document, QueryError> {
+        let path = self.path()?;
+        let condition = |operator: &str, value: Value| {
+            let mut condition = Document::new();
+            condition.insert(operator, value);
+            condition
+        };
+        if self.eat_keyword("IS") {
+            let negated = negated != self.eat_keyword("NOT");
+            self.expect_keyword("NULL")?;
+            let value = match negated {
+                true => Value::Document(condition("$ne", Value::Null)),
+                false => Value::Null,
+            };
+            return Ok(field(path, value));
+        }
+        let not = self.eat_keyword("NOT");
+        let negated = negated != not;
+        let value = if self.eat_keyword("IN") {
+            self.expect_symbol("(")?;
+            let mut values = vec![self.literal()?];
+            while self.eat_symbol(",") {
+                values.push(self.literal()?);
+            }
+            self.expect_symbol(")")?;
+            let operator = if negated { "$nin" } else { "$in" };
+            Value::Document(condition(operator, Value::Array(Array::from_vec(values))))
+        } else if self.eat_keyword("LIKE") {
+            let Token::String(pattern) = self.next("a LIKE pattern")? else {
+                return Err(invalid("LIKE takes a pattern in single quotes"));
+            };
+            let regex = Value::RegularExpression {
+                pattern: like_regex(&pattern),
+                options: "s".to_string(),
+            };
+            match negated {
+                true => Value::Document(condition("$not", regex)),
+                false => regex,
+            }
+        } else if self.eat_keyword("BETWEEN") {
+            let low = self.literal()?;
+            self.expect_keyword("AND")?;
+            let high = self.literal()?;
+            if negated {
+                return Ok(combine(
+                    "$or",
+                    vec![
+                        field(path.clone(), Value::Document(condition("$lt", low))),
+                        field(path, Value::Document(condition("$gt", high))),
+                    ],
+                ));
+            }
+            let mut range = condition("$gte", low);
+            range.insert("$lte", high);
+            Value::Document(range)
+        } else if not {
+            return Err(invalid("NOT after a path must precede IN, LIKE or BETWEEN"));
+        } else {
+            let Token::Symbol(symbol) = self.next("a comparison")? else {
+                return Err(invalid(format!("expected a comparison after {}", path)));
+            };
+            let operator = match (symbol, negated) {
+                ("=", false) | ("!=" | "<>", true) => "$eq",
+                ("!=" | "<>", false) | ("=", true) => "$ne",
+                ("<", false) | (">=", true) => "$lt",
+                ("<=", false) | (">", true) => "$lte",
+                (">", false) | ("<=", true) => "$gt",
+                (">=", false) | ("<", true) => "$gte",
+                (symbol, _) => {
+                    return Err(invalid(format!(
+                        "expected a comparison, found `{}`",
+                        symbol
+                    )))
+                }
+            };
+            let value = self.literal()?;
+            match operator {
+                "$eq" => value,
+                operator => Value::Document(condition(operator, value)),
+            }
+        };
+        Ok(field(path, value))
+    }
+
+    /// Reads a field path or collection name.
+    fn path(&mut self) -> Result<String, QueryError> {
+        match self.next("a path")? {
+            Token::Quoted(path) => Ok(path),
+            Token::Word(word) if !is_keyword(&word) => Ok(word),
+            token => Err(invalid(format!(
+                "expected a path, found {}",
+                token.describe()
+            ))),
+        }
+    }
+
+    fn literal(&mut self) -> Result<Value, QueryError> {
+        match self.next("a value")? {
+            Token::String(text) => Ok(Value::String(text)),
+            Token::Number(number) => Ok(number),
+            Token::Word(word) if word.eq_ignore_ascii_case("TRUE") => Ok(Value::Boolean(true)),
+            Token::Word(word) if word.eq_ignore_ascii_case("FALSE") => Ok(Value::Boolean(false)),
+            Token::Word(word) if word.eq_ignore_ascii_case("NULL") => Ok(Value::Null),
+            token => Err(invalid(format!(
+                "expected a value, found {}",
+                token.describe()
+            ))),
+        }
+    }
+
+    fn count(&mut self, clause: &str) -> Result<u64, QueryError> {
+        match self.next("a count")? {
+            Token::Number(Value::Int32(count)) if count >= 0 => Ok(count as u64),
+            Token::Number(Value::Int64(count)) if count >= 0 => Ok(count as u64),
+            _ => Err(invalid(format!("{} takes a count", clause))),
+        }
+    }
+
+    fn next(&mut self, expected: &str) -> Result<Token, QueryError> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or_else(|| invalid(format!("expected {}, found the end", expected)))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        match self.tokens.get(self.position) {
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword) => {
+                self.position += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn eat_symbol(&mut self, symbol: &str) -> bool {
+        match self.tokens.get(self.position) {
+            Some(Token::Symbol(found)) if *found == symbol => {
+                self.position += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), QueryError> {
+        match self.eat_keyword(keyword) {
+            true => Ok(()),
+            false => Err(self.expected(keyword)),
+        }
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<(), QueryError> {
+        match self.eat_symbol(symbol) {
+            true => Ok(()),
+            false => Err(self.expected(&format!("`{}`", symbol))),
+        }
+    }
+
+    fn expected(&self, expected: &str) -> QueryError {
+        match self.tokens.get(self.position) {
+            Some(token) => invalid(format!("expected {}, found {}", expected, token.describe())),
+            None => invalid(format!("expected {}, found the end", expected)),
+        }
+    }
+}
+
+fn is_keyword(word: &str) -> bool {
+    KEYWORDS
+        .iter()
+        .any(|keyword| word.eq_ignore_ascii_case(keyword))
+}
+
+fn field(path: String, value: Value) -> Document {
+    let mut filter = Document::new();
+    filter.insert(path, value);
+    filter
+}
+
+/// Joins `filters` with the logical operator `operator`, merging those
+/// that already join theirs with it.
+fn combine(operator: &str, mut filters: Vec<Document>) -> Document {
+    if filters.len() == 1 {
+        return filters.remove(0);
+    }
+    let mut joined = Vec::new();
+    for filter in filters {
+        match filter.get(operator) {
+            Some(Value::Array(inner)) if filter.len() == 1 => joined.extend(inner.iter().cloned()),
+            _ => joined.push(Value::Document(filter)),
+        }
+    }
+    field(operator.to_string(), Value::Array(Array::from_vec(joined)))
+}
+
+/// Translates a `LIKE` pattern, where `%` stands for any run of characters
+/// and `_` for any one, into an anchored regular expression.
+fn like_regex(pattern: &str) -> String {
+    let mut regex = String::from("^");
+    let mut literal = String::new();
+    for c in pattern.chars() {
+        match c {
+            '%' | '_' => {
+                regex.push_str(&regex::escape(&literal));
+                literal.clear();
+                regex.push_str(if c == '%' { ".*" } else { "." });
+            }
+            c => literal.push(c),
+        }
+    }
+    regex.push_str(&regex::escape(&literal));
+    regex.push('$');
+    regex
+}
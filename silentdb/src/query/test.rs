@@ -0,0 +1,418 @@
+// src/query/test.rs
+
+#[cfg(test)]
+mod tests {
+    use silentdb_data_encoding::{from_json_str, to_bytes, Array, Document, RawDocument, Value};
+
+    use crate::query::{Collation, Matcher, QueryError, SqlQuery};
+
+    fn doc(key: &str, value: impl Into<Value>) -> Document {
+        let mut document = Document::new();
+        document.insert(key, value);
+        document
+    }
+
+    fn array(values: Vec<Value>) -> Value {
+        Value::Array(Array::from_vec(values))
+    }
+
+    fn regex(pattern: &str, options: &str) -> Value {
+        Value::RegularExpression {
+            pattern: pattern.to_string(),
+            options: options.to_string(),
+        }
+    }
+
+    /// Matches `document` against `filter` both decoded and encoded, and
+    /// checks the two agree.
+    fn matches(filter: &Document, document: &Document) -> bool {
+        let matcher = Matcher::new(filter).unwrap();
+        let bytes = to_bytes(document).unwrap();
+        let raw = matcher
+            .matches_raw(RawDocument::from_bytes(&bytes).unwrap())
+            .unwrap();
+        let decoded = matcher.matches(document);
+        assert_eq!(raw, decoded, "{:?} against {:?}", filter, document);
+        decoded
+    }
+
+    fn user() -> Document {
+        let mut address = doc("city", "Oslo");
+        address.insert("zip", "0150");
+        let mut first = doc("item", "pen");
+        first.insert("qty", 2);
+        let mut second = doc("item", "ink");
+        second.insert("qty", 10);
+
+        let mut user = doc("_id", 1);
+        user.insert("name", "Alice");
+        user.insert("age", 30);
+        user.insert("score", 7.5);
+        user.insert("address", address);
+        user.insert("tags", array(vec!["admin".into(), "ops".into()]));
+        user.insert("scores", array(vec![3.into(), 8.into(), 12.into()]));
+        user.insert(
+            "orders",
+            array(vec![Value::Document(first), Value::Document(second)]),
+        );
+        user.insert("deleted", Value::Null);
+        user
+    }
+
+    // -------------------------------------
+    //          Matcher Tests
+    // -------------------------------------
+
+    #[test]
+    fn test_equality() {
+        let user = user();
+        assert!(matches(&Document::new(), &user));
+        assert!(matches(&doc("age", 30.0), &user));
+        assert!(matches(&doc("age", 30i64), &user));
+        assert!(!matches(&doc("age", "30"), &user));
+        assert!(matches(&doc("address.city", "Oslo"), &user));
+        assert!(!matches(&doc("address.city.name", "Oslo"), &user));
+
+        // Arrays match as a whole, by element, and by index
+        assert!(matches(&doc("tags", "ops"), &user));
+        assert!(matches(
+            &doc("tags", array(vec!["admin".into(), "ops".into()])),
+            &user
+        ));
+        assert!(!matches(
+            &doc("tags", array(vec!["ops".into(), "admin".into()])),
+            &user
+        ));
+        assert!(matches(&doc("tags.1", "ops"), &user));
+        assert!(!matches(&doc("tags.0", "ops"), &user));
+        assert!(matches(&doc("orders.item", "ink"), &user));
+        assert!(matches(&doc("orders.0.qty", 2), &user));
+
+        // null matches null and missing fields
+        assert!(matches(&doc("deleted", Value::Null), &user));
+        assert!(matches(&doc("missing", Value::Null), &user));
+        assert!(!matches(&doc("name", Value::Null), &user));
+
+        let mut both = doc("name", "Alice");
+        both.insert("age", 31);
+        assert!(!matches(&both, &user));
+    }
+
+    #[test]
+    fn test_comparison_operators() {
+        let user = user();
+        assert!(matches(&doc("age", doc("$eq", 30)), &user));
+        assert!(matches(&doc("age", doc("$ne", 31)), &user));
+        assert!(!matches(&doc("age", doc("$ne", 30.0)), &user));
+        assert!(matches(&doc("missing", doc("$ne", 1)), &user));
+        assert!(matches(&doc("age", doc("$gt", 29.5)), &user));
+        assert!(!matches(&doc("age", doc("$gt", 30)), &user));
+        assert!(matches(&doc("age", doc("$gte", 30)), &user));
+        assert!(matches(&doc("score", doc("$lt", 8)), &user));
+        assert!(matches(&doc("score", doc("$lte", 7.5)), &user));
+
+        let mut range = doc("$gte", 18);
+        range.insert("$lt", 65);
+        assert!(matches(&doc("age", range.clone()), &user));
+        assert!(!matches(&doc("score", range), &user));
+
+        // Range comparisons never cross type brackets
+        assert!(!matches(&doc("name", doc("$gt", 1)), &user));
+        assert!(!matches(&doc("age", doc("$lt", "a")), &user));
+        assert!(!matches(&doc("missing", doc("$lt", 1)), &user));
+        assert!(matches(&doc("name", doc("$gt", "Aaron")), &user));
+
+        // Any element of an array may satisfy the comparison
+        assert!(matches(&doc("scores", doc("$gt", 10)), &user));
+        assert!(!matches(&doc("scores", doc("$gt", 12)), &user));
+        // ... even different elements for different operators
+        let mut range = doc("$gt", 4);
+        range.insert("$lt", 6);
+        assert!(matches(&doc("scores", range), &user));
+    }
+
+    #[test]
+    fn test_set_operators() {
+        let user = user();
+        let ages = array(vec![20.into(), 30.into()]);
+        assert!(matches(&doc("age", doc("$in", ages.clone())), &user));
+        assert!(!matches(&doc("age", doc("$nin", ages)), &user));
+        assert!(matches(
+            &doc("tags", doc("$in", array(vec!["x".into(), "ops".into()]))),
+            &user
+        ));
+        assert!(matches(
+            &doc("name", doc("$in", array(vec![regex("^al", "i")]))),
+            &user
+        ));
+        assert!(matches(
+            &doc("missing", doc("$in", array(vec![Value::Null]))),
+            &user
+        ));
+        assert!(matches(
+            &doc("missing", doc("$nin", array(vec![1.into()]))),
+            &user
+        ));
+        assert!(!matches(&doc("age", doc("$in", array(vec![]))), &user));
+    }
+
+    #[test]
+    fn test_exists_and_type() {
+        let user = user();
+        assert!(matches(&doc("deleted", doc("$exists", true)), &user));
+        assert!(matches(&doc("missing", doc("$exists", false)), &user));
+        assert!(!matches(&doc("address.zip", doc("$exists", false)), &user));
+
+        assert!(matches(&doc("age", doc("$type", "int")), &user));
+        assert!(matches(&doc("age", doc("$type", 16)), &user));
+        assert!(!matches(&doc("age", doc("$type", "double")), &user));
+        assert!(matches(&doc("score", doc("$type", "number")), &user));
+        assert!(matches(&doc("tags", doc("$type", "array")), &user));
+        assert!(matches(&doc("tags", doc("$type", "string")), &user));
+        assert!(matches(&doc("deleted", doc("$type", "null")), &user));
+        assert!(matches(
+            &doc(
+                "_id",
+                doc("$type", array(vec!["objectId".into(), 16.into()]))
+            ),
+            &user
+        ));
+        assert!(!matches(&doc("missing", doc("$type", "null")), &user));
+    }
+
+    #[test]
+    fn test_regex() {
+        let user = user();
+        assert!(matches(&doc("name", doc("$regex", "^Al")), &user));
+        assert!(!matches(&doc("name", doc("$regex", "^al")), &user));
+        let mut insensitive = doc("$regex", "^al");
+        insensitive.insert("$options", "i");
+        assert!(matches(&doc("name", insensitive), &user));
+        assert!(matches(&doc("name", regex("ICE$", "i")), &user));
+        assert!(matches(&doc("tags", regex("^ad", "")), &user));
+        assert!(!matches(&doc("age", regex("30", "")), &user));
+    }
+
+    #[test]
+    fn test_logical_operators() {
+        let user = user();
+        let or = doc(
+            "$or",
+            array(vec![
+                Value::Document(doc("age", 99)),
+                Value::Document(doc("name", "Alice")),
+            ]),
+        );
+        assert!(matches(&or, &user));
+        let and = doc(
+            "$and",
+            array(vec![
+                Value::Document(doc("age", 30)),
+                Value::Document(doc("name", "Bob")),
+            ]),
+        );
+        assert!(!matches(&and, &user));
+
+        assert!(matches(&doc("age", doc("$not", doc("$gt", 40))), &user));
+        assert!(!matches(&doc("age", doc("$not", doc("$gt", 20))), &user));
+        assert!(matches(&doc("missing", doc("$not", doc("$gt", 20))), &user));
+        assert!(!matches(&doc("name", doc("$not", regex("^A", ""))), &user));
+    }
+
+    #[test]
+    fn test_elem_match() {
+        let user = user();
+        // Unlike separate operators, $elemMatch needs one element to match all
+        let mut range = doc("$gt", 4);
+        range.insert("$lt", 6);
+        assert!(!matches(&doc("scores", doc("$elemMatch", range)), &user));
+        let mut range = doc("$gt", 4);
+        range.insert("$lt", 10);
+        assert!(matches(&doc("scores", doc("$elemMatch", range)), &user));
+
+        let mut order = doc("item", "ink");
+        order.insert("qty", doc("$gte", 5));
+        assert!(matches(&doc("orders", doc("$elemMatch", order)), &user));
+        let mut order = doc("item", "pen");
+        order.insert("qty", doc("$gte", 5));
+        assert!(!matches(&doc("orders", doc("$elemMatch", order)), &user));
+
+        assert!(!matches(
+            &doc("name", doc("$elemMatch", doc("$eq", "Alice"))),
+            &user
+        ));
+    }
+
+    #[test]
+    fn test_invalid_filters() {
+        for filter in [
+            doc("$nor", array(vec![])),
+            doc("$and", array(vec![])),
+            doc("$or", doc("age", 1)),
+            doc("age", doc("$near", 1)),
+            doc("age", doc("$in", 1)),
+            doc("age", doc("$exists", "yes")),
+            doc("age", doc("$type", "integer")),
+            doc("age", doc("$type", 42)),
+            doc("age", doc("$options", "i")),
+            doc("age", doc("$not", 5)),
+            doc("name", regex("a", "q")),
+            doc("age", {
+                let mut mixed = doc("$gt", 1);
+                mixed.insert("lt", 5);
+                mixed
+            }),
+        ] {
+            let result = Matcher::new(&filter);
+            assert!(
+                matches!(result, Err(QueryError::InvalidFilter(_))),
+                "{:?}",
+                filter
+            );
+        }
+        assert!(matches!(
+            Matcher::new(&doc("name", doc("$regex", "("))),
+            Err(QueryError::Regex(_))
+        ));
+    }
+
+    #[test]
+    fn test_collation() {
+        let collation = Collation::new("en")
+            .case_sensitive(false)
+            .numeric_ordering(true);
+        let collated = |filter: &Document, document: &Document| {
+            let matcher = Matcher::with_collation(filter, &collation).unwrap();
+            let bytes = to_bytes(document).unwrap();
+            let raw = matcher
+                .matches_raw(RawDocument::from_bytes(&bytes).unwrap())
+                .unwrap();
+            assert_eq!(raw, matcher.matches(document));
+            raw
+        };
+        let item = doc("name", "Item 10");
+
+        assert!(!matches(&doc("name", "item 10"), &item));
+        assert!(collated(&doc("name", "item 10"), &item));
+        assert!(collated(&doc("name", "ITEM 010"), &item));
+        assert!(collated(&doc("name", doc("$gt", "item 9")), &item));
+        assert!(!matches(&doc("name", doc("$gt", "Item 9")), &item));
+        assert!(collated(&doc("name", doc("$lt", "item 10a")), &item));
+        assert!(!collated(&doc("name", doc("$ne", "ITEM 10")), &item));
+        assert!(collated(
+            &doc(
+                "name",
+                doc("$in", array(vec!["x".into(), "item 10".into()]))
+            ),
+            &item
+        ));
+        assert!(collated(
+            &doc("tags", doc("$elemMatch", doc("$eq", "A"))),
+            &doc("tags", array(vec!["a".into()]))
+        ));
+
+        // Regular expressions see the strings as they are
+        assert!(!collated(&doc("name", regex("^item", "")), &item));
+        assert!(collated(
+            &doc(
+                "name",
+                doc("$in", array(vec![regex("^Item", ""), "x".into()]))
+            ),
+            &item
+        ));
+        assert!(!collated(
+            &doc(
+                "name",
+                doc("$nin", array(vec![regex("^I", ""), "y".into()]))
+            ),
+            &item
+        ));
+    }
+
+    #[test]
+    fn test_sql() {
+        let filter = |sql: &str| SqlQuery::parse(sql).unwrap().filter;
+        let json = |text: &str| from_json_str(text).unwrap();
+
+        let query = SqlQuery::parse(
+            "select name, `address.city` from users where age > 21 \
+             order by age desc, name limit 10 offset 20;",
+        )
+        .unwrap();
+        assert_eq!(query.collection, "users");
+        assert_eq!(query.filter, json(r#"{"age": {"$gt": 21}}"#));
+        assert_eq!(
+            query.projection,
+            Some(json(r#"{"name": 1, "address.city": 1, "_id": 0}"#))
+        );
+        assert_eq!(query.sort, Some(json(r#"{"age": -1, "name": 1}"#)));
+        assert_eq!((query.skip, query.limit), (20, Some(10)));
+        let all = SqlQuery::parse("SELECT * FROM users").unwrap();
+        assert_eq!(
+            (all.filter, all.projection, all.sort),
+            (Document::new(), None, None)
+        );
+
+        // AND binds tighter than OR, and NOT is pushed down to the fields
+        assert_eq!(
+            filter("SELECT * FROM t WHERE a = 1 OR b = 'x' AND c <> 2.5 AND d IS NULL"),
+            json(
+                r#"{"$or": [{"a": 1},
+                            {"$and": [{"b": "x"}, {"c": {"$ne": 2.5}}, {"d": null}]}]}"#
+            )
+        );
+        assert_eq!(
+            filter("SELECT * FROM t WHERE NOT (a < 1 OR b IN (1, 'two')) AND c IS NOT NULL"),
+            json(
+                r#"{"$and": [{"a": {"$gte": 1}}, {"b": {"$nin": [1, "two"]}},
+                             {"c": {"$ne": null}}]}"#
+            )
+        );
+        assert_eq!(
+            filter("SELECT * FROM t WHERE a NOT BETWEEN 1 AND 5 OR \"order\" = TRUE"),
+            json(r#"{"$or": [{"a": {"$lt": 1}}, {"a": {"$gt": 5}}, {"order": true}]}"#)
+        );
+        assert_eq!(
+            filter("SELECT * FROM t WHERE name NOT LIKE 'a.b_%' AND n >= -1e3"),
+            json(
+                r#"{"$and": [{"name": {"$not": {"$regex": "^a\\.b..*$", "$options": "s"}}},
+                             {"n": {"$gte": -1000.0}}]}"#
+            )
+        );
+
+        // The translated filters match as the conditions read
+        let user = user();
+        let matcher = |sql: &str| Matcher::new(&filter(sql)).unwrap().matches(&user);
+        assert!(matcher(
+            "SELECT * FROM t WHERE name LIKE 'Al%' AND age BETWEEN 30 AND 40"
+        ));
+        assert!(matcher(
+            "SELECT * FROM t WHERE address.city = 'Oslo' AND NOT tags = 'dev'"
+        ));
+        assert!(!matcher(
+            "SELECT * FROM t WHERE name LIKE 'al%' OR age IN (1, 2)"
+        ));
+
+        for bad in [
+            "",
+            "SELECT FROM users",
+            "SELECT * users",
+            "SELECT * FROM select",
+            "SELECT * FROM t WHERE",
+            "SELECT * FROM t WHERE a == 1",
+            "SELECT * FROM t WHERE a NOT = 1",
+            "SELECT * FROM t WHERE a = 'open",
+            "SELECT * FROM t WHERE a LIKE 1",
+            "SELECT * FROM t WHERE (a = 1",
+            "SELECT * FROM t LIMIT -1",
+            "SELECT * FROM t ORDER age",
+            "SELECT * FROM t; DROP TABLE t",
+        ] {
+            assert!(
+                matches!(SqlQuery::parse(bad), Err(QueryError::InvalidSql(_))),
+                "{}",
+                bad
+            );
+        }
+    }
+}
@@ -0,0 +1,235 @@
+// src/server/audit.rs
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use silentdb_data_encoding::{Document, JsonSerializer, Serializer, Value};
+
+use super::auth::action;
+use super::error::ServerError;
+use crate::db::{Action, Collection, Database};
+
+/// Fields redacted from audited commands unless told otherwise: passwords
+/// and SASL payloads.
+const DEFAULT_REDACTED: [&str; 2] = ["pwd", "payload"];
+/// What a redacted field's value is replaced with.
+const REDACTED: &str = "<redacted>";
+
+/// Audit log settings for a server, set with `ServerOptions::audit`.
+///
+/// The log records who ran each write and administrative command: the
+/// user the connection authenticated as, the client's address, the
+/// command with its sensitive fields redacted, and whether it succeeded.
+/// Each record is written before the reply is sent, and a command whose
+/// record cannot be written fails with that error, though what it did
+/// stands.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use silentdb::server::{AuditOptions, ServerOptions};
+/// let audit = AuditOptions::to_file("audit.jsonl")
+///     .redact("ssn")
+///     .reads(true);
+/// let options = ServerOptions::new().authentication(true).audit(audit);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditOptions {
+    pub(crate) target: AuditTarget,
+    pub(crate) redacted: Vec<String>,
+    pub(crate) reads: bool,
+}
+
+/// Where audit records go.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum AuditTarget {
+    Collection(String),
+    File(PathBuf),
+}
+
+impl AuditOptions {
+    /// Creates options inserting each record into the collection `name` of
+    /// the database served. Clients may read the collection but not write
+    /// it, so that it only ever grows.
+    pub fn to_collection(name: impl Into<String>) -> Self {
+        AuditOptions::with_target(AuditTarget::Collection(name.into()))
+    }
+
+    /// Creates options appending each record to the file at `path`, as a
+    /// line of relaxed Extended JSON. The file is created if need be, and
+    /// opened when the server is bound.
+    pub fn to_file(path: impl Into<PathBuf>) -> Self {
+        AuditOptions::with_target(AuditTarget::File(path.into()))
+    }
+
+    fn with_target(target: AuditTarget) -> Self {
+        AuditOptions {
+            target,
+            redacted: redacted_fields(None),
+            reads: false,
+        }
+    }
+
+    /// Redacts the value of every field named `field` too, at any depth,
+    /// as `pwd` and `payload` are.
+    pub fn redact(mut self, field: impl Into<String>) -> Self {
+        self.redacted.push(field.into());
+        self
+    }
+
+    /// Records reads, `find` and `count`, as well. Off by default, since
+    /// they far outnumber the rest.
+    pub fn reads(mut self, reads: bool) -> Self {
+        self.reads = reads;
+        self
+    }
+
+    /// Opens the log these options describe for `db`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be opened.
+    pub(crate) fn open(&self, db: &Database) -> Result<AuditLog, ServerError> {
+        let sink = match &self.target {
+            AuditTarget::Collection(name) => Sink::Collection(db.collection(name)),
+            AuditTarget::File(path) => Sink::File(Mutex::new(
+                OpenOptions::new().create(true).append(true).open(path)?,
+            )),
+        };
+        Ok(AuditLog {
+            sink,
+            redacted: self.redacted.clone(),
+            reads: self.reads,
+        })
+    }
+}
+
+/// An open audit log, shared by every connection of a server.
+#[derive(Debug)]
+pub(crate) struct AuditLog {
+    sink: Sink,
+    redacted: Vec<String>,
+    reads: bool,
+}
+
+#[derive(Debug)]
+enum Sink {
+    Collection(Collection),
+    File(Mutex<File>),
+}
+
+impl AuditLog {
+    /// Returns whether `command` is recorded.
+    pub(crate) fn audits(&self, command: &str) -> bool {
+        match action(command) {
+            None => false,
+            Some(Action::Read) => self.reads,
+            Some(_) => true,
+        }
+    }
+
+    /// Fails with `Unauthorized` if `command`, with `document`, would write
+    /// to the audit collection.
+    pub(crate) fn guard(&self, command: &str, document: &Document) -> Result<(), ServerError> {
+        let Sink::Collection(collection) = &self.sink else {
+            return Ok(());
+        };
+        match (action(command), document.get(command)) {
+            (Some(Action::Write), Some(Value::String(name))) if name == collection.name() => Err(
+                ServerError::Unauthorized(format!("{} is an append-only audit log", name)),
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// Records that `user`, connected from `addr`, ran `command`, with
+    /// `document`, failing with `error` if it did.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the record cannot be written.
+    pub(crate) fn record(
+        &self,
+        user: Option<&str>,
+        addr: Option<SocketAddr>,
+        command: &str,
+        document: &Document,
+        error: Option<&ServerError>,
+    ) -> Result<(), ServerError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_millis() as i64);
+        let mut record = Document::new();
+        record.insert("ts", Value::UTCDateTime(now));
+        if let Some(user) = user {
+            record.insert("user", user);
+        }
+        if let Some(addr) = addr {
+            record.insert("client", addr.to_string());
+        }
+        record.insert("command", command);
+        record.insert("document", redact(document, &self.redacted));
+        record.insert("ok", error.is_none());
+        if let Some(error) = error {
+            record.insert("code", error.code());
+            record.insert("errmsg", error.to_string());
+        }
+        match &self.sink {
+            Sink::Collection(collection) => {
+                collection.insert_one(record)?;
+            }
+            Sink::File(file) => {
+                let mut serializer = JsonSerializer::new(Vec::new(), false);
+                serializer.serialize_document(&record)?;
+                let mut line = serializer.into_inner();
+                line.push(b'\n');
+                let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+                file.write_all(&line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the fields `audit` redacts, or those redacted by default
+/// without one.
+pub(crate) fn redacted_fields(audit: Option<&AuditOptions>) -> Vec<String> {
+    match audit {
+        Some(audit) => audit.redacted.clone(),
+        None => DEFAULT_REDACTED
+            .iter()
+            .map(|field| field.to_string())
+            .collect(),
+    }
+}
+
+/// Returns `document` with the value of each field named in `redacted`
+/// replaced, in embedded documents and arrays too.
+pub(crate) fn redact(document: &Document, redacted: &[String]) -> Document {
+    let mut document = document.clone();
+    redact_fields(&mut document, redacted);
+    document
+}
+
+fn redact_fields(document: &mut Document, redacted: &[String]) {
+    for (key, value) in document.iter_mut() {
+        match redacted.iter().any(|field| field == key) {
+            true => *value = Value::String(REDACTED.to_string()),
+            false => redact_value(value, redacted),
+        }
+    }
+}
+
+fn redact_value(value: &mut Value, redacted: &[String]) {
+    match value {
+        Value::Document(document) => redact_fields(document, redacted),
+        Value::Array(array) => array
+            .iter_mut()
+            .for_each(|element| redact_value(element, redacted)),
+        _ => {}
+    }
+}
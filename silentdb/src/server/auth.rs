@@ -0,0 +1,370 @@
+// src/server/auth.rs
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use rand::Rng;
+use silentdb_data_encoding::{Document, Value};
+
+use super::error::ServerError;
+use super::listener::{Peer, ServerOptions};
+use crate::db::{Action, Credential, Database};
+
+/// The one SASL mechanism the server offers.
+pub(crate) const MECHANISM: &str = "SCRAM-SHA-256";
+/// The id of a connection's SASL conversation, of which there is only ever
+/// one at a time.
+const CONVERSATION_ID: i32 = 1;
+/// Random bytes in the server's part of a nonce.
+const NONCE_LEN: usize = 24;
+
+/// Who a connection is authenticated as, and how far through
+/// authenticating it is, and where it connected from.
+#[derive(Debug)]
+pub(crate) struct Session {
+    user: Option<String>,
+    conversation: Option<Conversation>,
+    peer: Peer,
+}
+
+/// A SCRAM-SHA-256 conversation, after the client's first message.
+#[derive(Debug)]
+enum Conversation {
+    /// The server's first message was sent, and the client's proof is due.
+    Challenged {
+        name: String,
+        /// Made up for an unknown user, who is challenged all the same so
+        /// as not to give away which users exist.
+        credential: Credential,
+        gs2_header: String,
+        client_first_bare: String,
+        server_first: String,
+        nonce: String,
+        skip_empty_exchange: bool,
+    },
+    /// The proof checked out, and the client's empty last message is due.
+    Proven { name: String },
+}
+
+/// Returns what `command` does, or `None` if anyone may run it, even
+/// before authenticating. Commands not named here are administrative.
+pub(crate) fn action(command: &str) -> Option<Action> {
+    match command {
+        "hello" | "ping" | "buildInfo" | "saslStart" | "saslContinue" | "startSession"
+        | "endSessions" => None,
+        "find" | "count" | "getMore" | "killCursors" | "watch" | "listCollections"
+        | "collStats" => Some(Action::Read),
+        "insert" | "update" | "delete" => Some(Action::Write),
+        "createUser" | "updateUser" | "dropUser" | "usersInfo" => Some(Action::UserAdmin),
+        _ => Some(Action::DbAdmin),
+    }
+}
+
+impl Session {
+    /// Creates an unauthenticated session for the client `peer`.
+    pub(crate) fn new(peer: Peer) -> Self {
+        Session {
+            user: None,
+            conversation: None,
+            peer,
+        }
+    }
+
+    /// Returns the user the session authenticated as, if it has.
+    pub(crate) fn user(&self) -> Option<&str> {
+        self.user.as_deref()
+    }
+
+    /// Returns the client the session is with.
+    pub(crate) fn peer(&self) -> &Peer {
+        &self.peer
+    }
+
+    /// Returns whether the client connected from this machine.
+    fn is_loopback(&self) -> bool {
+        self.peer
+            .addr
+            .is_some_and(|addr| addr.ip().to_canonical().is_loopback())
+    }
+
+    /// Checks the session may run `command` on a server with `options`.
+    ///
+    /// Anything goes without `ServerOptions::authentication`. With it, the
+    /// roles of the user the session authenticated as must allow the
+    /// command, as they stand now, so dropping a user or changing their
+    /// roles takes effect on their open connections too. While there are
+    /// no users, clients connected over loopback may manage them, to create
+    /// the first, as MongoDB's localhost exception allows.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Unauthorized` if the command is not allowed.
+    pub(crate) fn authorize(
+        &self,
+        db: &Database,
+        options: &ServerOptions,
+        command: &str,
+    ) -> Result<(), ServerError> {
+        let Some(action) = action(command).filter(|_| options.authentication) else {
+            return Ok(());
+        };
+        let roles = match &self.user {
+            Some(name) => db.user(name)?.map(|user| user.roles).unwrap_or_default(),
+            None => Vec::new(),
+        };
+        if roles.iter().any(|role| role.allows(action)) {
+            return Ok(());
+        }
+        if action == Action::UserAdmin && self.is_loopback() && db.users()?.is_empty() {
+            return Ok(());
+        }
+        Err(ServerError::Unauthorized(match &self.user {
+            Some(name) => format!("{} is not allowed to run {}", name, command),
+            None => format!("{} requires authentication", command),
+        }))
+    }
+
+    /// Authenticates the client `peer` as `name` with `password` sent as
+    /// is, as HTTP Basic authentication does, returning the session of that
+    /// user.
+    ///
+    /// # Errors
+    ///
+    /// Returns `AuthenticationFailed` if the password is wrong or the user
+    /// unknown, after taking as long to check either way.
+    pub(crate) fn basic(
+        db: &Database,
+        name: &str,
+        password: &str,
+        peer: Peer,
+    ) -> Result<Session, ServerError> {
+        let credential = match db.user(name)? {
+            Some(user) => user.credential,
+            None => Credential::unknown(name),
+        };
+        match credential.check(password) {
+            true => Ok(Session {
+                user: Some(name.to_string()),
+                conversation: None,
+                peer,
+            }),
+            false => Err(ServerError::AuthenticationFailed),
+        }
+    }
+
+    /// Fails with `Unauthorized` if `command`, with `document`, would write
+    /// to the audit collection of the session's audit log.
+    pub(crate) fn guard_audit(
+        &self,
+        command: &str,
+        document: &Document,
+    ) -> Result<(), ServerError> {
+        match &self.peer.audit {
+            Some(audit) => audit.guard(command, document),
+            None => Ok(()),
+        }
+    }
+
+    /// Records in the session's audit log, if it has one and audits
+    /// `command`, that the session ran it with `document`, failing with
+    /// `error` if it did.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the record cannot be written.
+    pub(crate) fn audit(
+        &self,
+        command: &str,
+        document: &Document,
+        error: Option<&ServerError>,
+    ) -> Result<(), ServerError> {
+        match &self.peer.audit {
+            Some(audit) if audit.audits(command) => audit.record(
+                self.user.as_deref(),
+                self.peer.addr,
+                command,
+                document,
+                error,
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// `{saslStart: 1, mechanism: "SCRAM-SHA-256", payload: <binary>,
+    /// options: {skipEmptyExchange}}`: starts authenticating with the
+    /// client's first message, and replies with the server's, the salt and
+    /// iteration count of the user's password and a nonce to prove it with.
+    ///
+    /// Passwords are used as given, without SASLprep, so clients must send
+    /// them unnormalized too; those in ASCII are unaffected.
+    pub(crate) fn sasl_start(
+        &mut self,
+        db: &Database,
+        command: &Document,
+    ) -> Result<Document, ServerError> {
+        self.conversation = None;
+        match command.get("mechanism") {
+            Some(Value::String(mechanism)) if mechanism == MECHANISM => {}
+            _ => {
+                return Err(ServerError::bad(
+                    "saslStart",
+                    format!("the only mechanism is {}", MECHANISM),
+                ))
+            }
+        }
+        let skip_empty_exchange = match command.get("options") {
+            Some(Value::Document(options)) => {
+                matches!(options.get("skipEmptyExchange"), Some(Value::Boolean(true)))
+            }
+            _ => false,
+        };
+        let message = payload("saslStart", command)?;
+
+        // gs2-header "n,," or "y,,", optionally naming an authorization
+        // identity, which must be the user anyway; channel binding is not
+        // offered
+        let mut parts = message.splitn(3, ',');
+        let (binding, authzid, bare) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(binding @ ("n" | "y")), Some(authzid), Some(bare)) => (binding, authzid, bare),
+            _ => return Err(ServerError::AuthenticationFailed),
+        };
+        let gs2_header = format!("{},{},", binding, authzid);
+        let mut attributes = bare.split(',');
+        let name = match attributes.next().and_then(|a| a.strip_prefix("n=")) {
+            Some(name) => unescape(name).ok_or(ServerError::AuthenticationFailed)?,
+            None => return Err(ServerError::AuthenticationFailed),
+        };
+        let client_nonce = match attributes.next().and_then(|a| a.strip_prefix("r=")) {
+            Some(nonce) if !nonce.is_empty() => nonce,
+            _ => return Err(ServerError::AuthenticationFailed),
+        };
+        if !authzid.is_empty()
+            && authzid.strip_prefix("a=").and_then(unescape) != Some(name.clone())
+        {
+            return Err(ServerError::AuthenticationFailed);
+        }
+
+        let credential = match db.user(&name)? {
+            Some(user) => user.credential,
+            None => Credential::unknown(&name),
+        };
+        let server_nonce: [u8; NONCE_LEN] = rand::thread_rng().gen();
+        let nonce = format!("{}{}", client_nonce, BASE64.encode(server_nonce));
+        let server_first = format!(
+            "r={},s={},i={}",
+            nonce,
+            BASE64.encode(&credential.salt),
+            credential.iterations
+        );
+        self.conversation = Some(Conversation::Challenged {
+            name,
+            credential,
+            gs2_header,
+            client_first_bare: bare.to_string(),
+            server_first: server_first.clone(),
+            nonce,
+            skip_empty_exchange,
+        });
+        Ok(sasl_reply(false, server_first.as_bytes()))
+    }
+
+    /// `{saslContinue: 1, conversationId, payload: <binary>}`: checks the
+    /// client's proof and replies with the server's signature to prove
+    /// itself in turn, finishing at once if the conversation started with
+    /// `skipEmptyExchange` and after the client's empty reply otherwise.
+    ///
+    /// # Errors
+    ///
+    /// Returns `AuthenticationFailed` if the proof is wrong or the user
+    /// unknown, which ends the conversation.
+    pub(crate) fn sasl_continue(&mut self, command: &Document) -> Result<Document, ServerError> {
+        match command.get("conversationId") {
+            Some(Value::Int32(CONVERSATION_ID)) | None => {}
+            Some(Value::Int64(id)) if *id == CONVERSATION_ID as i64 => {}
+            _ => return Err(ServerError::bad("saslContinue", "no such conversation")),
+        }
+        let message = payload("saslContinue", command)?;
+        match self.conversation.take() {
+            Some(Conversation::Challenged {
+                name,
+                credential,
+                gs2_header,
+                client_first_bare,
+                server_first,
+                nonce,
+                skip_empty_exchange,
+            }) => {
+                let (without_proof, proof) = message
+                    .rsplit_once(",p=")
+                    .ok_or(ServerError::AuthenticationFailed)?;
+                let mut attributes = without_proof.split(',');
+                let binding = attributes.next().and_then(|a| a.strip_prefix("c="));
+                let echoed = attributes.next().and_then(|a| a.strip_prefix("r="));
+                if binding != Some(BASE64.encode(&gs2_header).as_str())
+                    || echoed != Some(nonce.as_str())
+                {
+                    return Err(ServerError::AuthenticationFailed);
+                }
+                let proof = BASE64
+                    .decode(proof)
+                    .map_err(|_| ServerError::AuthenticationFailed)?;
+                let auth_message =
+                    format!("{},{},{}", client_first_bare, server_first, without_proof);
+                let server_signature = credential
+                    .verify(auth_message.as_bytes(), &proof)
+                    .ok_or(ServerError::AuthenticationFailed)?;
+                let server_final = format!("v={}", BASE64.encode(server_signature));
+                match skip_empty_exchange {
+                    true => self.user = Some(name),
+                    false => self.conversation = Some(Conversation::Proven { name }),
+                }
+                Ok(sasl_reply(skip_empty_exchange, server_final.as_bytes()))
+            }
+            Some(Conversation::Proven { name }) if message.is_empty() => {
+                self.user = Some(name);
+                Ok(sasl_reply(true, b""))
+            }
+            _ => Err(ServerError::AuthenticationFailed),
+        }
+    }
+}
+
+/// Returns the SASL message at `payload` of `command`.
+fn payload(command_name: &str, command: &Document) -> Result<String, ServerError> {
+    match command.get("payload") {
+        Some(Value::Binary(bytes)) => {
+            String::from_utf8(bytes.clone()).map_err(|_| ServerError::AuthenticationFailed)
+        }
+        Some(Value::String(message)) => Ok(message.clone()),
+        _ => Err(ServerError::bad(
+            command_name,
+            "payload is missing or of the wrong type",
+        )),
+    }
+}
+
+fn sasl_reply(done: bool, payload: &[u8]) -> Document {
+    let mut reply = Document::new();
+    reply.insert("conversationId", CONVERSATION_ID);
+    reply.insert("done", done);
+    reply.insert("payload", Value::Binary(payload.to_vec()));
+    reply.insert("ok", 1.0);
+    reply
+}
+
+/// Undoes the escaping of `,` as `=2C` and `=` as `=3D` in a SCRAM user
+/// name, returning `None` for any other `=`.
+fn unescape(name: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(name.len());
+    let mut rest = name;
+    while let Some(at) = rest.find('=') {
+        unescaped.push_str(&rest[..at]);
+        match rest.get(at..at + 3)? {
+            "=2C" => unescaped.push(','),
+            "=3D" => unescaped.push('='),
+            _ => return None,
+        }
+        rest = &rest[at + 3..];
+    }
+    unescaped.push_str(rest);
+    Some(unescaped)
+}
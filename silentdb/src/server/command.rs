@@ -0,0 +1,726 @@
+// src/server/command.rs
+
+use std::cell::Cell;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use silentdb_data_encoding::{from_bytes, Array, BufferPoolStats, Document, RawDocument, Value};
+
+use super::auth::{action, Session};
+use super::error::ServerError;
+use super::listener::ServerOptions;
+use super::retry::Begun;
+use crate::db::{Action, CountOptions, Database, DatabaseError, FindOptions, Role, UpdateOptions};
+use crate::query::Matcher;
+
+/// The names of the commands the server runs.
+const COMMANDS: [&str; 19] = [
+    "hello",
+    "find",
+    "insert",
+    "update",
+    "delete",
+    "count",
+    "saslStart",
+    "saslContinue",
+    "createUser",
+    "updateUser",
+    "dropUser",
+    "usersInfo",
+    "metrics",
+    "ping",
+    "buildInfo",
+    "serverStatus",
+    "listCollections",
+    "collStats",
+    "currentOp",
+];
+
+/// Documents a find streams per reply unless told otherwise.
+const DEFAULT_BATCH_SIZE: usize = 101;
+
+/// Matches streamed back for a command, as documents or errors. `Send`, to
+/// be advanced on the blocking pool batch by batch.
+pub(crate) type Matches = Box<dyn Iterator<Item = Result<Document, DatabaseError>> + Send>;
+
+/// What a command replies with.
+pub(crate) enum Reply {
+    /// A single reply document.
+    Document(Document),
+    /// Documents streamed back in replies holding up to `batch_size` each.
+    Stream { matches: Matches, batch_size: usize },
+}
+
+/// Runs the command document encoded as `bytes` against `db`, for a server
+/// with `options`, if `session` is allowed to.
+///
+/// The command is named by the first field of the document whose name is
+/// a command's, rather than strictly the first field, since documents
+/// built as maps do not keep their fields in order. The command is listed
+/// by `currentOp` while it runs, and recorded in the session's audit log,
+/// if it has one, whether or not it succeeds. A write carrying an `opId`
+/// is applied at most once, however often its client retries it.
+pub(crate) fn run(
+    db: &Database,
+    options: &ServerOptions,
+    session: &mut Session,
+    bytes: &[u8],
+) -> Result<Reply, ServerError> {
+    let raw = RawDocument::from_bytes(bytes)?;
+    let mut first = None;
+    let mut name = None;
+    for element in raw.iter() {
+        let key = element?.key();
+        first.get_or_insert(key);
+        if COMMANDS.contains(&key) {
+            name = Some(key);
+            break;
+        }
+    }
+    let Some(name) = name else {
+        return Err(ServerError::UnknownCommand(
+            first.unwrap_or_default().to_string(),
+        ));
+    };
+    let command = Command {
+        name,
+        document: from_bytes(bytes)?,
+        applied: Cell::new(false),
+    };
+    let status = Arc::clone(&session.peer().status);
+    let reply = session
+        .authorize(db, options, name)
+        .and_then(|()| session.guard_audit(name, &command.document))
+        .and_then(|()| {
+            let addr = session.peer().addr;
+            let _running = status.begin(session.user(), addr, name, &command.document);
+            match action(name) {
+                Some(Action::Write) => write(db, options, session, &command),
+                _ => dispatch(db, options, session, &command),
+            }
+        });
+    session.audit(name, &command.document, reply.as_ref().err())?;
+    reply
+}
+
+/// Runs the authorized `command`.
+fn dispatch(
+    db: &Database,
+    options: &ServerOptions,
+    session: &mut Session,
+    command: &Command,
+) -> Result<Reply, ServerError> {
+    match command.name {
+        "hello" => Ok(Reply::Document(hello(options))),
+        "find" => command.find(db, options),
+        "insert" => command.insert(db),
+        "update" => command.update(db),
+        "delete" => command.delete(db),
+        "count" => command.count(db, options),
+        "saslStart" => Ok(Reply::Document(session.sasl_start(db, &command.document)?)),
+        "saslContinue" => Ok(Reply::Document(session.sasl_continue(&command.document)?)),
+        "createUser" => command.create_user(db),
+        "updateUser" => command.update_user(db),
+        "dropUser" => command.drop_user(db),
+        "usersInfo" => command.users_info(db),
+        "metrics" => Ok(Reply::Document(metrics(db))),
+        "ping" => Ok(Reply::Document(ok())),
+        "buildInfo" => Ok(Reply::Document(build_info(options))),
+        "serverStatus" => Ok(Reply::Document(server_status(db, options, session))),
+        "listCollections" => command.list_collections(db),
+        "collStats" => command.coll_stats(db),
+        "currentOp" => Ok(Reply::Document(current_op(session))),
+        _ => unreachable!("every command is dispatched"),
+    }
+}
+
+/// Runs the authorized write `command`, unless it carries the operation id
+/// of one that already ran, in which case it is answered with that one's
+/// reply, as `RetryTable` describes. A write that failed after some of its
+/// statements ran is answered with its error from then on.
+fn write(
+    db: &Database,
+    options: &ServerOptions,
+    session: &mut Session,
+    command: &Command,
+) -> Result<Reply, ServerError> {
+    let retries = Arc::clone(&session.peer().retries);
+    let begun = retries.begin(session.user(), command.name, &command.document)?;
+    match begun {
+        Begun::Done(reply) => Ok(Reply::Document(reply)),
+        Begun::Run(pending) => match dispatch(db, options, session, command) {
+            Ok(reply) => {
+                if let Reply::Document(document) = &reply {
+                    pending.finish(document);
+                }
+                Ok(reply)
+            }
+            Err(e) => {
+                if command.applied.get() {
+                    pending.finish(&error_reply(&e));
+                }
+                Err(e)
+            }
+        },
+    }
+}
+
+/// Returns the reply to a command that failed with `error`.
+pub(crate) fn error_reply(error: &ServerError) -> Document {
+    let mut reply = Document::new();
+    reply.insert("ok", 0.0);
+    reply.insert("errmsg", error.to_string());
+    reply.insert("code", error.code());
+    reply
+}
+
+/// `{hello: 1}`: replies with the server's name and `version`, the largest
+/// request it accepts in `maxDocumentLen`, and the `commands` it runs, for
+/// clients to check what they connected to.
+fn hello(options: &ServerOptions) -> Document {
+    let mut reply = ok();
+    reply.insert("server", "silentdb");
+    reply.insert("version", env!("CARGO_PKG_VERSION"));
+    reply.insert("maxDocumentLen", options.max_document_len as i64);
+    let commands = COMMANDS.iter().map(|&name| Value::from(name)).collect();
+    reply.insert("commands", Array::from_vec(commands));
+    reply
+}
+
+/// `{metrics: 1}`: replies with the database's metrics, as
+/// `Database::metrics` returns them, in `metrics`.
+fn metrics(db: &Database) -> Document {
+    let mut reply = ok();
+    reply.insert("metrics", db.metrics());
+    reply
+}
+
+/// Returns the `bufferPool` section of `serverStatus`.
+fn buffer_pool(stats: &BufferPoolStats) -> Document {
+    let mut document = Document::new();
+    document.insert("hits", stats.hits as i64);
+    document.insert("misses", stats.misses as i64);
+    document.insert("returned", stats.returned as i64);
+    document.insert("discarded", stats.discarded as i64);
+    document.insert("idle", stats.idle as i64);
+    document.insert("idleBytes", stats.idle_bytes as i64);
+    document
+}
+
+/// `{buildInfo: 1}`: replies with the server's `version`, as a string and
+/// in `versionArray`, whether it is a `debug` build, the `bits` of its
+/// platform, the optional `features` it was built with, and the largest
+/// request it accepts in `maxDocumentLen`.
+fn build_info(options: &ServerOptions) -> Document {
+    let version = env!("CARGO_PKG_VERSION");
+    let parts = version
+        .split(['.', '-'])
+        .take(3)
+        .map(|part| Value::Int32(part.parse().unwrap_or(0)))
+        .collect();
+    let features = [
+        ("memmap2", cfg!(feature = "memmap2")),
+        ("zstd", cfg!(feature = "zstd")),
+        ("grpc", cfg!(feature = "grpc")),
+    ]
+    .into_iter()
+    .filter(|(_, enabled)| *enabled)
+    .map(|(name, _)| Value::from(name))
+    .collect();
+    let mut reply = ok();
+    reply.insert("version", version);
+    reply.insert("versionArray", Array::from_vec(parts));
+    reply.insert("debug", cfg!(debug_assertions));
+    reply.insert("bits", usize::BITS as i32);
+    reply.insert("features", Array::from_vec(features));
+    reply.insert("maxDocumentLen", options.max_document_len as i64);
+    reply
+}
+
+/// `{serverStatus: 1}`: replies with the server's `version`, its
+/// `localTime`, how long it has been up in `uptime` and `uptimeMillis`,
+/// its clients in `connections`, the database's `metrics`, and how often
+/// the buffers frames are read and written in were reused, in
+/// `bufferPool`, for monitoring.
+fn server_status(db: &Database, options: &ServerOptions, session: &Session) -> Document {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_millis() as i64);
+    let mut reply = ok();
+    reply.insert("process", "silentdb");
+    reply.insert("version", env!("CARGO_PKG_VERSION"));
+    reply.insert("localTime", Value::UTCDateTime(now));
+    for (key, value) in session.peer().status.to_document(options).iter() {
+        reply.insert(key, value.clone());
+    }
+    reply.insert("metrics", db.metrics());
+    reply.insert("bufferPool", buffer_pool(&session.peer().buffers.stats()));
+    reply
+}
+
+/// `{currentOp: 1}`: replies with the commands running, this one among
+/// them, in `inprog`, each with the user and client running it and how
+/// long it has run, and its sensitive fields redacted as the audit log
+/// redacts them.
+fn current_op(session: &Session) -> Document {
+    let mut reply = ok();
+    reply.insert("inprog", session.peer().status.current_ops());
+    reply
+}
+
+/// Returns a successful reply, to add the command's results to.
+fn ok() -> Document {
+    let mut reply = Document::new();
+    reply.insert("ok", 1.0);
+    reply
+}
+
+/// A decoded command document and the name it was dispatched under.
+struct Command<'a> {
+    name: &'a str,
+    document: Document,
+    /// Whether a statement of the write ran, so that running it again
+    /// would repeat it.
+    applied: Cell<bool>,
+}
+
+impl Command<'_> {
+    /// `{find: <collection>, filter, sort, projection, skip, limit,
+    /// batchSize, maxTimeMS}`: streams the matching documents, failing
+    /// once fetching them takes longer than `maxTimeMS`, or the server's
+    /// `max_time` if that is shorter.
+    fn find(&self, db: &Database, server: &ServerOptions) -> Result<Reply, ServerError> {
+        let collection = db.collection(self.collection()?);
+        let filter = self.document_or_empty("filter")?;
+        let mut options = FindOptions::new();
+        if let Some(sort) = self.document("sort")? {
+            options = options.sort(sort.clone());
+        }
+        if let Some(projection) = self.document("projection")? {
+            options = options.projection(projection.clone());
+        }
+        let skip = self.count_of("skip")?.unwrap_or(0);
+        let limit = self.count_of("limit")?.filter(|limit| *limit > 0);
+        let batch_size = self.count_of("batchSize")?.unwrap_or(DEFAULT_BATCH_SIZE);
+        if let Some(max_time) = self.max_time(server)? {
+            options = options.max_time(max_time);
+        }
+        let cursor = collection
+            .find_with(&filter, &options)?
+            .batch_size(batch_size);
+        let matches: Matches = match limit {
+            Some(limit) => Box::new(cursor.skip(skip).take(limit)),
+            None => Box::new(cursor.skip(skip)),
+        };
+        Ok(Reply::Stream {
+            matches,
+            batch_size: batch_size.max(1),
+        })
+    }
+
+    /// `{insert: <collection>, documents: [...]}`: inserts the documents
+    /// and replies with `n` and their `insertedIds`.
+    fn insert(&self, db: &Database) -> Result<Reply, ServerError> {
+        self.refuse_max_time()?;
+        let collection = db.collection(self.collection()?);
+        let documents = self
+            .documents("documents")?
+            .into_iter()
+            .cloned()
+            .collect::<Vec<_>>();
+        let result = collection.insert_many(documents)?;
+        let mut reply = ok();
+        reply.insert("n", result.inserted_ids.len() as i64);
+        reply.insert("insertedIds", Array::from_vec(result.inserted_ids));
+        Ok(Reply::Document(reply))
+    }
+
+    /// `{update: <collection>, updates: [{q, u, upsert, multi}, ...]}`:
+    /// applies each update, where `u` holds update operators or else
+    /// replaces the one document matched, and replies with the totals `n`
+    /// matched and `nModified`, and the documents `upserted`, each with the
+    /// `index` of its update and its `_id`.
+    fn update(&self, db: &Database) -> Result<Reply, ServerError> {
+        self.refuse_max_time()?;
+        let collection = db.collection(self.collection()?);
+        let (mut matched, mut modified) = (0, 0);
+        let mut upserted = Array::new();
+        for (index, update) in self.documents("updates")?.into_iter().enumerate() {
+            let filter = document_field(self.name, update, "q")?.ok_or_else(|| self.bad("q"))?;
+            let change = document_field(self.name, update, "u")?.ok_or_else(|| self.bad("u"))?;
+            let options = UpdateOptions::new().upsert(flag(self.name, update, "upsert")?);
+            let multi = flag(self.name, update, "multi")?;
+            let operators = change.iter().any(|(key, _)| key.starts_with('$'));
+            let result = match (operators, multi) {
+                (true, true) => collection.update_many_with(filter, change, &options)?,
+                (true, false) => collection.update_one_with(filter, change, &options)?,
+                (false, false) => collection.replace_one_with(filter, change, &options)?,
+                (false, true) => {
+                    return Err(ServerError::bad(
+                        self.name,
+                        "a replacement cannot be a multi update",
+                    ))
+                }
+            };
+            self.applied.set(true);
+            matched += result.matched_count;
+            modified += result.modified_count;
+            if let Some(id) = result.upserted_id {
+                let mut entry = Document::new();
+                entry.insert("index", index as i64);
+                entry.insert("_id", id);
+                upserted.push(entry);
+            }
+        }
+        let mut reply = ok();
+        reply.insert("n", matched as i64);
+        reply.insert("nModified", modified as i64);
+        if !upserted.is_empty() {
+            reply.insert("upserted", upserted);
+        }
+        Ok(Reply::Document(reply))
+    }
+
+    /// `{delete: <collection>, deletes: [{q, limit}, ...]}`: deletes the
+    /// documents each filter matches, or only the first with a `limit` of
+    /// 1, and replies with the total `n` deleted.
+    fn delete(&self, db: &Database) -> Result<Reply, ServerError> {
+        self.refuse_max_time()?;
+        let collection = db.collection(self.collection()?);
+        let mut deleted = 0;
+        for delete in self.documents("deletes")? {
+            let filter = document_field(self.name, delete, "q")?.ok_or_else(|| self.bad("q"))?;
+            let result = match count_field(self.name, delete, "limit")? {
+                None | Some(0) => collection.delete_many(filter)?,
+                Some(1) => collection.delete_one(filter)?,
+                Some(_) => return Err(ServerError::bad(self.name, "limit must be 0 or 1")),
+            };
+            self.applied.set(true);
+            deleted += result.deleted_count;
+        }
+        let mut reply = ok();
+        reply.insert("n", deleted as i64);
+        Ok(Reply::Document(reply))
+    }
+
+    /// `{count: <collection>, query, maxTimeMS}`: replies with the number
+    /// `n` of documents matching the query, failing once counting takes
+    /// longer than `maxTimeMS`, or the server's `max_time` if that is
+    /// shorter.
+    fn count(&self, db: &Database, server: &ServerOptions) -> Result<Reply, ServerError> {
+        let collection = db.collection(self.collection()?);
+        let mut options = CountOptions::new();
+        if let Some(max_time) = self.max_time(server)? {
+            options = options.max_time(max_time);
+        }
+        let count = collection.count_with(&self.document_or_empty("query")?, &options)?;
+        let mut reply = ok();
+        reply.insert("n", count as i64);
+        Ok(Reply::Document(reply))
+    }
+
+    /// `{listCollections: 1, filter, nameOnly}`: replies with the
+    /// collections matching `filter`, each as `{name, type, options,
+    /// info}`, or only `{name, type}` with `nameOnly`, as the first batch
+    /// of a cursor already exhausted, where MongoDB drivers look for them.
+    fn list_collections(&self, db: &Database) -> Result<Reply, ServerError> {
+        let matcher =
+            Matcher::new(&self.document_or_empty("filter")?).map_err(DatabaseError::from)?;
+        let name_only = flag(self.name, &self.document, "nameOnly")?;
+        let mut collections = Array::new();
+        for name in db.list_collections()? {
+            let mut entry = Document::new();
+            entry.insert("name", name.as_str());
+            entry.insert("type", "collection");
+            if !name_only {
+                let mut options = Document::new();
+                let mut info = Document::new();
+                info.insert("readOnly", false);
+                if let Some(catalog) = db.collection_info(&name)? {
+                    if let Some(validator) = &catalog.validator {
+                        options.insert("validator", validator.to_document());
+                    }
+                    if let Some(collation) = &catalog.collation {
+                        options.insert("collation", collation.to_document());
+                    }
+                    if let Some(created) = catalog.created {
+                        info.insert("created", Value::UTCDateTime(created));
+                    }
+                    info.insert("versioned", catalog.versioned);
+                }
+                entry.insert("options", options);
+                entry.insert("info", info);
+            }
+            if matcher.matches(&entry) {
+                collections.push(entry);
+            }
+        }
+        let database = match self.document.get("$db") {
+            Some(Value::String(name)) => name.as_str(),
+            _ => "admin",
+        };
+        let mut cursor = Document::new();
+        cursor.insert("id", 0i64);
+        cursor.insert("ns", format!("{}.$cmd.listCollections", database));
+        cursor.insert("firstBatch", collections);
+        let mut reply = ok();
+        reply.insert("cursor", cursor);
+        Ok(Reply::Document(reply))
+    }
+
+    /// `{collStats: <collection>}`: replies with the number of documents
+    /// in `count`, the bytes they take in `size`, their `avgObjSize` and
+    /// `maxObjSize`, and the number of secondary indexes in `nindexes`,
+    /// with the bytes each takes in `indexSizes` and all of them in
+    /// `totalIndexSize`, gathered by reading the collection and its
+    /// indexes.
+    fn coll_stats(&self, db: &Database) -> Result<Reply, ServerError> {
+        let name = self.collection()?;
+        let stats = db.collection(name).stats()?;
+        let mut index_sizes = Document::new();
+        for index in &stats.indexes {
+            index_sizes.insert(&index.name, index.size as i64);
+        }
+        let total_index_size: u64 = stats.indexes.iter().map(|index| index.size).sum();
+        let mut reply = ok();
+        reply.insert("ns", name);
+        reply.insert("count", stats.count as i64);
+        reply.insert("size", stats.storage_size as i64);
+        reply.insert("avgObjSize", stats.avg_size as i64);
+        reply.insert("maxObjSize", stats.max_size as i64);
+        reply.insert("nindexes", stats.indexes.len() as i32);
+        reply.insert("totalIndexSize", total_index_size as i64);
+        reply.insert("indexSizes", index_sizes);
+        Ok(Reply::Document(reply))
+    }
+
+    /// `{createUser: <name>, pwd, roles: [...]}`: creates the user, with
+    /// roles named as strings or as `{role, db}` documents, whose `db` is
+    /// ignored since there is only the one database.
+    fn create_user(&self, db: &Database) -> Result<Reply, ServerError> {
+        let password = self.string("pwd")?.ok_or_else(|| self.bad("pwd"))?;
+        let roles = self.roles()?.ok_or_else(|| self.bad("roles"))?;
+        db.create_user(self.user()?, password, &roles)?;
+        Ok(Reply::Document(ok()))
+    }
+
+    /// `{updateUser: <name>, pwd, roles: [...]}`: sets the user's password,
+    /// roles or both.
+    fn update_user(&self, db: &Database) -> Result<Reply, ServerError> {
+        let name = self.user()?;
+        let (password, roles) = (self.string("pwd")?, self.roles()?);
+        if password.is_none() && roles.is_none() {
+            return Err(ServerError::bad(self.name, "nothing to update"));
+        }
+        if let Some(roles) = roles {
+            db.set_user_roles(name, &roles)?;
+        }
+        if let Some(password) = password {
+            db.set_user_password(name, password)?;
+        }
+        Ok(Reply::Document(ok()))
+    }
+
+    /// `{dropUser: <name>}`: removes the user.
+    fn drop_user(&self, db: &Database) -> Result<Reply, ServerError> {
+        let name = self.user()?;
+        if !db.drop_user(name)? {
+            return Err(DatabaseError::UserNotFound(name.to_string()).into());
+        }
+        Ok(Reply::Document(ok()))
+    }
+
+    /// `{usersInfo: 1 | <name>}`: replies with every user, or the one
+    /// named, in `users`, each as `{_id, user, db, roles: [{role, db}]}`.
+    fn users_info(&self, db: &Database) -> Result<Reply, ServerError> {
+        let only = match self.document.get(self.name) {
+            Some(Value::String(name)) => Some(name),
+            _ => None,
+        };
+        let mut users = Array::new();
+        for user in db.users()? {
+            if only.is_some_and(|name| *name != user.name) {
+                continue;
+            }
+            let roles = user.roles.iter().map(|role| {
+                let mut entry = Document::new();
+                entry.insert("role", role.name());
+                entry.insert("db", "admin");
+                Value::from(entry)
+            });
+            let mut entry = Document::new();
+            entry.insert("_id", format!("admin.{}", user.name));
+            entry.insert("user", user.name);
+            entry.insert("db", "admin");
+            entry.insert("roles", Array::from_vec(roles.collect()));
+            users.push(entry);
+        }
+        let mut reply = ok();
+        reply.insert("users", users);
+        Ok(Reply::Document(reply))
+    }
+
+    /// Returns the collection named by the command's own field.
+    fn collection(&self) -> Result<&str, ServerError> {
+        match self.document.get(self.name) {
+            Some(Value::String(name)) => Ok(name),
+            _ => Err(ServerError::bad(
+                self.name,
+                format!("{} must name a collection", self.name),
+            )),
+        }
+    }
+
+    /// Returns the user named by the command's own field.
+    fn user(&self) -> Result<&str, ServerError> {
+        match self.document.get(self.name) {
+            Some(Value::String(name)) => Ok(name),
+            _ => Err(ServerError::bad(
+                self.name,
+                format!("{} must name a user", self.name),
+            )),
+        }
+    }
+
+    /// Returns the string at `field`, if there is one.
+    fn string(&self, field: &str) -> Result<Option<&str>, ServerError> {
+        match self.document.get(field) {
+            None | Some(Value::Null) => Ok(None),
+            Some(Value::String(value)) => Ok(Some(value.as_str())),
+            Some(_) => Err(self.bad(field)),
+        }
+    }
+
+    /// Returns the roles listed at `roles`, if there are any.
+    fn roles(&self) -> Result<Option<Vec<Role>>, ServerError> {
+        let array = match self.document.get("roles") {
+            None | Some(Value::Null) => return Ok(None),
+            Some(Value::Array(array)) => array,
+            Some(_) => return Err(self.bad("roles")),
+        };
+        array
+            .iter()
+            .map(|value| {
+                let name = match value {
+                    Value::String(name) => Some(name),
+                    Value::Document(role) => match role.get("role") {
+                        Some(Value::String(name)) => Some(name),
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                name.and_then(|name| Role::from_name(name))
+                    .ok_or_else(|| ServerError::bad(self.name, format!("unknown role {:?}", value)))
+            })
+            .collect::<Result<_, _>>()
+            .map(Some)
+    }
+
+    fn document(&self, field: &str) -> Result<Option<&Document>, ServerError> {
+        document_field(self.name, &self.document, field)
+    }
+
+    fn document_or_empty(&self, field: &str) -> Result<Document, ServerError> {
+        Ok(self.document(field)?.cloned().unwrap_or_default())
+    }
+
+    fn count_of(&self, field: &str) -> Result<Option<usize>, ServerError> {
+        count_field(self.name, &self.document, field)
+    }
+
+    /// Returns how long the command may spend reading: its `maxTimeMS`,
+    /// or the server's `max_time` if that is shorter, if either is set.
+    fn max_time(&self, server: &ServerOptions) -> Result<Option<Duration>, ServerError> {
+        let max_time = self
+            .count_of("maxTimeMS")?
+            .filter(|ms| *ms > 0)
+            .map(|ms| Duration::from_millis(ms as u64));
+        Ok(max_time.into_iter().chain(server.max_time).min())
+    }
+
+    /// Fails with `BadCommand` if the command has a `maxTimeMS`, which a
+    /// write cannot keep to, as each of its statements is applied whole
+    /// once it starts.
+    fn refuse_max_time(&self) -> Result<(), ServerError> {
+        match self.document.get("maxTimeMS") {
+            Some(_) => Err(ServerError::bad(self.name, "maxTimeMS is not supported")),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the documents of the array `field`, which must be present.
+    fn documents(&self, field: &str) -> Result<Vec<&Document>, ServerError> {
+        let Some(Value::Array(array)) = self.document.get(field) else {
+            return Err(self.bad(field));
+        };
+        array
+            .iter()
+            .map(|value| match value {
+                Value::Document(document) => Ok(document),
+                _ => Err(ServerError::bad(
+                    self.name,
+                    format!("{} must only hold documents", field),
+                )),
+            })
+            .collect()
+    }
+
+    /// Returns the error for a missing or mistyped `field`.
+    fn bad(&self, field: &str) -> ServerError {
+        ServerError::bad(
+            self.name,
+            format!("{} is missing or of the wrong type", field),
+        )
+    }
+}
+
+/// Returns the document at `field` of `document`, if there is one.
+fn document_field<'a>(
+    command: &str,
+    document: &'a Document,
+    field: &str,
+) -> Result<Option<&'a Document>, ServerError> {
+    match document.get(field) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::Document(value)) => Ok(Some(value)),
+        Some(_) => Err(ServerError::bad(
+            command,
+            format!("{} must be a document", field),
+        )),
+    }
+}
+
+/// Returns the non-negative whole number at `field` of `document`, if there
+/// is one.
+fn count_field(
+    command: &str,
+    document: &Document,
+    field: &str,
+) -> Result<Option<usize>, ServerError> {
+    let count = match document.get(field) {
+        None | Some(Value::Null) => return Ok(None),
+        Some(Value::Int32(n)) => *n as i64,
+        Some(Value::Int64(n)) => *n,
+        Some(Value::Double(n)) if n.fract() == 0.0 => *n as i64,
+        Some(_) => i64::MIN,
+    };
+    usize::try_from(count).map(Some).map_err(|_| {
+        ServerError::bad(
+            command,
+            format!("{} must be a non-negative whole number", field),
+        )
+    })
+}
+
+/// Returns the boolean at `field` of `document`, or `false` if there is
+/// none.
+fn flag(command: &str, document: &Document, field: &str) -> Result<bool, ServerError> {
+    match document.get(field) {
+        None | Some(Value::Null) => Ok(false),
+        Some(Value::Boolean(value)) => Ok(*value),
+        Some(_) => Err(ServerError::bad(
+            command,
+            format!("{} must be a boolean", field),
+        )),
+    }
+}
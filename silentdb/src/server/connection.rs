@@ -0,0 +1,163 @@
+// src/server/connection.rs
+
+use std::io::ErrorKind;
+use std::iter::Peekable;
+use std::sync::Arc;
+
+use silentdb_data_encoding::{to_bytes, Array, BufferPool, Document, Framer, PooledBuffer};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+
+use super::auth::Session;
+use super::command::{error_reply, run, Matches, Reply};
+use super::error::ServerError;
+use super::listener::{Peer, ServerOptions};
+use super::task::{blocking, linger, send, Stopping, READ_CHUNK};
+use crate::db::Database;
+
+/// Serves the client on `stream` until it disconnects or the server shuts
+/// down: reads each command document framed on it, runs it against `db` on
+/// the blocking pool, and writes the replies.
+///
+/// A find's matches are taken a batch at a time, each only once the one
+/// before has been written, so a client that stops reading its batches is
+/// left waiting on rather than buffered for.
+///
+/// # Errors
+///
+/// Returns an error if the stream fails, or a frame is malformed, after
+/// replying with the error where the stream still allows. A command that
+/// fails is replied to and does not end the connection.
+pub(crate) async fn serve_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    db: &Database,
+    mut stream: S,
+    options: &Arc<ServerOptions>,
+    peer: &Peer,
+    mut stopping: Stopping,
+) -> Result<(), ServerError> {
+    let mut framer = Framer::with_max_document_len(options.max_document_len);
+    let mut chunk = vec![0; READ_CHUNK];
+    let mut session = Session::new(peer.clone());
+    loop {
+        let frame = match framer.next_frame_in(&peer.buffers) {
+            Ok(Some(frame)) => frame,
+            Ok(None) => {
+                let Some(read) = stopping.unless(stream.read(&mut chunk)).await else {
+                    return Ok(());
+                };
+                match read {
+                    Ok(0) if framer.is_empty() => return Ok(()),
+                    Ok(0) => return Err(ServerError::Io(ErrorKind::UnexpectedEof.into())),
+                    Ok(read) => framer.push(&chunk[..read]),
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e.into()),
+                }
+                continue;
+            }
+            // The stream is out of step with its frames, so nothing after
+            // the bad one can be read
+            Err(e) => {
+                let e = ServerError::from(e);
+                send(&mut stream, &mut stopping, &to_bytes(&error_reply(&e))?).await?;
+                return Err(e);
+            }
+        };
+
+        let (db, options) = (db.clone(), Arc::clone(options));
+        let (returned, reply) = blocking(move || {
+            let reply = run(&db, &options, &mut session, &frame);
+            (session, reply)
+        })
+        .await;
+        session = returned;
+        let (mut matches, batch_size) = match reply {
+            Ok(Reply::Stream {
+                matches,
+                batch_size,
+            }) => (matches.peekable(), batch_size),
+            Ok(Reply::Document(reply)) => {
+                if !send(&mut stream, &mut stopping, &peer.buffers.encode(&reply)?).await? {
+                    return Ok(());
+                }
+                continue;
+            }
+            Err(e) => {
+                let reply = peer.buffers.encode(&error_reply(&e))?;
+                if !send(&mut stream, &mut stopping, &reply).await? {
+                    return Ok(());
+                }
+                continue;
+            }
+        };
+        loop {
+            let buffers = peer.buffers.clone();
+            let (returned, batch) = blocking(move || {
+                let batch = next_batch(&mut matches, batch_size, &buffers);
+                (matches, batch)
+            })
+            .await;
+            matches = returned;
+            let (bytes, more) = batch?;
+            if !send(&mut stream, &mut stopping, &bytes).await? {
+                return Ok(());
+            }
+            if !more {
+                break;
+            }
+        }
+    }
+}
+
+/// Refuses the client on `stream` with `error`, which it reads as the
+/// reply to its first command, and disconnects it.
+///
+/// # Errors
+///
+/// Returns an error if the stream fails.
+pub(crate) async fn refuse_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    error: &ServerError,
+    mut stopping: Stopping,
+) -> Result<(), ServerError> {
+    if send(&mut stream, &mut stopping, &to_bytes(&error_reply(error))?).await? {
+        linger(&mut stream, &mut stopping).await;
+    }
+    Ok(())
+}
+
+/// Takes up to `batch_size` of `matches`, and returns the replies to write
+/// for them and whether more follow: `{ok: 1, batch: [...], more: <bool>}`,
+/// where the last has `more` false. An error ends the stream with an error
+/// reply in place of the next batch. The replies are written into a buffer
+/// from `buffers`.
+fn next_batch(
+    matches: &mut Peekable<Matches>,
+    batch_size: usize,
+    buffers: &BufferPool,
+) -> Result<(PooledBuffer, bool), ServerError> {
+    let mut bytes = buffers.get(0);
+    let mut batch = Array::new();
+    while batch.len() < batch_size {
+        match matches.next() {
+            Some(Ok(document)) => batch.push(document),
+            Some(Err(e)) => {
+                if !batch.is_empty() {
+                    bytes.extend_from_slice(&buffers.encode(&batch_reply(batch, true))?);
+                }
+                bytes.extend_from_slice(&buffers.encode(&error_reply(&e.into()))?);
+                return Ok((bytes, false));
+            }
+            None => break,
+        }
+    }
+    let more = matches.peek().is_some();
+    bytes.extend_from_slice(&buffers.encode(&batch_reply(batch, more))?);
+    Ok((bytes, more))
+}
+
+fn batch_reply(batch: Array, more: bool) -> Document {
+    let mut reply = Document::new();
+    reply.insert("ok", 1.0);
+    reply.insert("batch", batch);
+    reply.insert("more", more);
+    reply
+}
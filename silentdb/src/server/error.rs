@@ -0,0 +1,82 @@
+// src/server/error.rs
+
+use std::io;
+
+use silentdb_data_encoding::{DeserializeError, SerializeError};
+
+use crate::db::DatabaseError;
+
+/// Represents errors that can occur serving clients.
+#[derive(Debug, thiserror::Error)]
+pub enum ServerError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Malformed request: {0}")]
+    Deserialize(#[from] DeserializeError),
+    #[error("Serialization error: {0}")]
+    Serialize(#[from] SerializeError),
+    #[error("{0}")]
+    Database(#[from] DatabaseError),
+    #[error("No such command: {0:?}")]
+    UnknownCommand(String),
+    #[error("Bad {command} command: {message}")]
+    BadCommand { command: String, message: String },
+    #[error("TLS error: {0}")]
+    Tls(String),
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+    #[error("Authentication failed")]
+    AuthenticationFailed,
+    #[error("Too many connections; the server allows {0}")]
+    TooManyConnections(usize),
+    #[error("Too many operations in progress; a connection may have {0}")]
+    TooManyOperations(usize),
+}
+
+impl ServerError {
+    /// Creates a `BadCommand` error for `command`.
+    pub(crate) fn bad(command: &str, message: impl ToString) -> Self {
+        ServerError::BadCommand {
+            command: command.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    /// Returns the code error replies carry for the error, one of the codes
+    /// MongoDB uses for the same failure where there is one.
+    pub fn code(&self) -> i32 {
+        match self {
+            ServerError::Io(_)
+            | ServerError::Serialize(_)
+            | ServerError::Tls(_)
+            | ServerError::TooManyConnections(_)
+            | ServerError::TooManyOperations(_) => 1,
+            ServerError::Deserialize(_) => 22,
+            ServerError::UnknownCommand(_) => 59,
+            ServerError::BadCommand { .. } => 9,
+            ServerError::Unauthorized(_) => 13,
+            ServerError::AuthenticationFailed => 18,
+            ServerError::Database(e) => e.code(),
+        }
+    }
+
+    /// Returns `true` if the request may succeed if sent again unchanged:
+    /// the connection failed, the server was busy, or the database error
+    /// is retryable.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ServerError::Io(e) => matches!(
+                e.kind(),
+                io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::UnexpectedEof
+                    | io::ErrorKind::TimedOut
+                    | io::ErrorKind::Interrupted
+            ),
+            ServerError::TooManyConnections(_) | ServerError::TooManyOperations(_) => true,
+            ServerError::Database(e) => e.is_retryable(),
+            _ => false,
+        }
+    }
+}
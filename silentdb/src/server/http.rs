@@ -0,0 +1,934 @@
+// src/server/http.rs
+
+use std::io::ErrorKind;
+use std::iter::Peekable;
+use std::sync::Arc;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use silentdb_data_encoding::{
+    from_json_str, from_json_value_str, to_bytes, Array, Document, JsonSerializer, Serializer,
+    Value,
+};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+
+use super::auth::Session;
+use super::command::{error_reply, run, Matches, Reply};
+use super::error::ServerError;
+use super::listener::{Peer, ServerOptions};
+use super::task::{blocking, linger, send, Stopping};
+use super::websocket::{accept_key, resume_token, serve_watch};
+use crate::db::{ChangeStream, Database, DatabaseError};
+use crate::query::SqlQuery;
+
+/// The longest request line and headers accepted, together.
+const MAX_HEAD_LEN: usize = 16 * 1024;
+/// The methods served on a collection.
+const METHODS: &str = "GET, POST, PATCH, DELETE";
+/// Where the database's metrics are served.
+const METRICS_PATH: &str = "/metrics";
+/// Where collections are served.
+const COLLECTION_PREFIX: &str = "/db/";
+/// Where SQL statements are run.
+const SQL_PATH: &str = "/sql";
+/// Where the administrative and health commands are served.
+const ADMIN_PREFIX: &str = "/admin/";
+/// The commands served under `ADMIN_PREFIX`, which only read.
+const ADMIN_COMMANDS: [&str; 6] = [
+    "ping",
+    "buildInfo",
+    "serverStatus",
+    "listCollections",
+    "collStats",
+    "currentOp",
+];
+/// Where collections' change streams are served.
+const WATCH_PREFIX: &str = "/watch/";
+/// The only WebSocket version served.
+const WEBSOCKET_VERSION: &str = "13";
+/// Query parameters with a meaning of their own, rather than matching the
+/// field of that name.
+const PARAMETERS: [&str; 10] = [
+    "filter",
+    "sort",
+    "projection",
+    "skip",
+    "limit",
+    "batchSize",
+    "maxTimeMS",
+    "upsert",
+    "multi",
+    "resumeAfter",
+];
+
+/// A request, read whole.
+struct Request {
+    method: String,
+    path: String,
+    /// The query parameters, percent-decoded, in the order given.
+    query: Vec<(String, String)>,
+    authorization: Option<String>,
+    /// The `Sec-WebSocket-Key` of a request with `Upgrade: websocket`.
+    websocket_key: Option<String>,
+    websocket_version: Option<String>,
+    body: Vec<u8>,
+    keep_alive: bool,
+}
+
+/// What reading the next request came to.
+enum Incoming {
+    Request(Request),
+    /// A request the server will not serve, to be replied to before the
+    /// connection is closed, since what follows it cannot be trusted.
+    Rejected(Response),
+    /// The client closed the connection between requests.
+    Closed,
+}
+
+/// A response with its whole body.
+struct Response {
+    status: u16,
+    headers: Vec<(&'static str, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    /// Returns a response with `document` as its body, in relaxed Extended
+    /// JSON.
+    fn json(status: u16, document: &Document) -> Response {
+        match json(document) {
+            Ok(body) => Response {
+                status,
+                headers: vec![("Content-Type", "application/json".to_string())],
+                body,
+            },
+            Err(e) => Response {
+                status: 500,
+                headers: vec![("Content-Type", "text/plain".to_string())],
+                body: e.to_string().into_bytes(),
+            },
+        }
+    }
+
+    fn encode(&self, keep_alive: bool) -> Vec<u8> {
+        let mut headers = self.headers.clone();
+        headers.push(("Content-Length", self.body.len().to_string()));
+        let mut bytes = head(self.status, &headers, keep_alive);
+        bytes.extend_from_slice(&self.body);
+        bytes
+    }
+}
+
+/// Serves an HTTP/1.1 client on `stream` until it disconnects, asks to
+/// close the connection, or the server shuts down.
+///
+/// Each request is translated into the native command it stands for, run
+/// on the blocking pool, and its reply written back as JSON. A find's
+/// matches are written as a chunked JSON array, a batch at a time, each
+/// taken only once the one before has been written. A watch upgrades the
+/// connection to a WebSocket, which carries change events until either
+/// side closes it.
+///
+/// # Errors
+///
+/// Returns an error if the stream fails, or a find fails partway through
+/// its response, which is left unfinished so the client can tell.
+pub(crate) async fn serve_http_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    db: &Database,
+    stream: S,
+    options: &Arc<ServerOptions>,
+    peer: &Peer,
+    mut stopping: Stopping,
+) -> Result<(), ServerError> {
+    let mut stream = BufReader::new(stream);
+    loop {
+        let read = read_request(&mut stream, options.max_document_len);
+        let Some(incoming) = stopping.unless(read).await else {
+            return Ok(());
+        };
+        let request = match incoming? {
+            Incoming::Request(request) => request,
+            Incoming::Rejected(response) => {
+                send(&mut stream, &mut stopping, &response.encode(false)).await?;
+                return Ok(());
+            }
+            Incoming::Closed => return Ok(()),
+        };
+
+        let keep_alive = request.keep_alive;
+        let (db, options, peer) = (db.clone(), Arc::clone(options), peer.clone());
+        let (mut matches, batch_size) =
+            match blocking(move || handle(&db, &options, &peer, request)).await {
+                Ok(Handled::Stream {
+                    matches,
+                    batch_size,
+                }) => (matches.peekable(), batch_size),
+                Ok(Handled::Watch { changes, accept }) => {
+                    let headers = [
+                        ("Upgrade", "websocket".to_string()),
+                        ("Connection", "Upgrade".to_string()),
+                        ("Sec-WebSocket-Accept", accept),
+                    ];
+                    if !send(&mut stream, &mut stopping, &head(101, &headers, true)).await? {
+                        return Ok(());
+                    }
+                    return serve_watch(&mut stream, changes, &mut stopping).await;
+                }
+                Ok(Handled::Response(response)) | Err(response) => {
+                    if !send(&mut stream, &mut stopping, &response.encode(keep_alive)).await?
+                        || !keep_alive
+                    {
+                        return Ok(());
+                    }
+                    continue;
+                }
+            };
+
+        // The first batch is read before the status is sent, so a find
+        // failing outright gets an error response of its own
+        let (returned, batch) = blocking(move || {
+            let batch = next_batch(&mut matches, batch_size);
+            (matches, batch)
+        })
+        .await;
+        matches = returned;
+        let (batch, mut more) = match batch {
+            Ok(batch) => batch,
+            Err(e) => {
+                let response = error_response(&e, true);
+                if !send(&mut stream, &mut stopping, &response.encode(keep_alive)).await?
+                    || !keep_alive
+                {
+                    return Ok(());
+                }
+                continue;
+            }
+        };
+        let headers = [
+            ("Content-Type", "application/json".to_string()),
+            ("Transfer-Encoding", "chunked".to_string()),
+        ];
+        let mut bytes = head(200, &headers, keep_alive);
+        bytes.extend(chunk(&[b"[", &batch[..]].concat()));
+        while more {
+            if !send(&mut stream, &mut stopping, &bytes).await? {
+                return Ok(());
+            }
+            let (returned, batch) = blocking(move || {
+                let batch = next_batch(&mut matches, batch_size);
+                (matches, batch)
+            })
+            .await;
+            matches = returned;
+            let (batch, more_after) = batch?;
+            more = more_after;
+            bytes = chunk(&[b",", &batch[..]].concat());
+        }
+        bytes.extend(chunk(b"]"));
+        bytes.extend_from_slice(b"0\r\n\r\n");
+        if !send(&mut stream, &mut stopping, &bytes).await? || !keep_alive {
+            return Ok(());
+        }
+    }
+}
+
+/// Refuses the client on `stream` with `error`, as a response sent before
+/// its first request is read, and disconnects it.
+///
+/// # Errors
+///
+/// Returns an error if the stream fails.
+pub(crate) async fn refuse_http_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    error: &ServerError,
+    mut stopping: Stopping,
+) -> Result<(), ServerError> {
+    let response = error_response(error, false);
+    if send(&mut stream, &mut stopping, &response.encode(false)).await? {
+        linger(&mut stream, &mut stopping).await;
+    }
+    Ok(())
+}
+
+/// What a request comes to once run.
+enum Handled {
+    Response(Response),
+    /// A find's matches, to be written as they are taken.
+    Stream {
+        matches: Matches,
+        batch_size: usize,
+    },
+    /// A change stream, to be served over the WebSocket the connection is
+    /// upgraded to, answering the client's key with `accept`.
+    Watch {
+        changes: ChangeStream,
+        accept: String,
+    },
+}
+
+/// Runs the command `request` stands for, as the user its credentials
+/// name, returning the response to it, or the error response.
+fn handle(
+    db: &Database,
+    options: &ServerOptions,
+    peer: &Peer,
+    request: Request,
+) -> Result<Handled, Response> {
+    if request.path == METRICS_PATH {
+        return metrics(db, options, peer, &request).map(Handled::Response);
+    }
+    if request.path.starts_with(WATCH_PREFIX) {
+        return watch(db, options, peer, &request);
+    }
+    if request.path.starts_with(ADMIN_PREFIX) {
+        return admin(db, options, peer, &request).map(Handled::Response);
+    }
+    let (command, status) = if request.path == SQL_PATH {
+        if request.method != "POST" {
+            let mut response = rejection(405, &format!("{} is not served", request.method));
+            response.headers.push(("Allow", "POST".to_string()));
+            return Err(response);
+        }
+        (sql_command(&request.query, &request.body), 200)
+    } else {
+        let collection = route(&request.path, COLLECTION_PREFIX)?;
+        match request.method.as_str() {
+            "GET" => (find_command(collection, &request.query), 200),
+            "POST" => (insert_command(collection, &request.body), 201),
+            "PATCH" => (
+                update_command(collection, &request.query, &request.body),
+                200,
+            ),
+            "DELETE" => (delete_command(collection, &request.query), 200),
+            method => {
+                let mut response = rejection(405, &format!("{} is not served", method));
+                response.headers.push(("Allow", METHODS.to_string()));
+                return Err(response);
+            }
+        }
+    };
+    let credentials = request.authorization.is_some();
+    let reply = session(db, options, peer, request.authorization.as_deref())
+        .and_then(|mut session| run(db, options, &mut session, &to_bytes(&command?)?));
+    match reply {
+        Ok(Reply::Document(reply)) => Ok(Handled::Response(Response::json(status, &reply))),
+        Ok(Reply::Stream {
+            matches,
+            batch_size,
+        }) => Ok(Handled::Stream {
+            matches,
+            batch_size,
+        }),
+        Err(e) => Err(error_response(&e, credentials)),
+    }
+}
+
+/// `GET /metrics`: the database's metrics in the Prometheus text format,
+/// for users allowed the `metrics` command.
+fn metrics(
+    db: &Database,
+    options: &ServerOptions,
+    peer: &Peer,
+    request: &Request,
+) -> Result<Response, Response> {
+    if request.method != "GET" {
+        let mut response = rejection(405, &format!("{} is not served", request.method));
+        response.headers.push(("Allow", "GET".to_string()));
+        return Err(response);
+    }
+    let credentials = request.authorization.is_some();
+    session(db, options, peer, request.authorization.as_deref())
+        .and_then(|session| session.authorize(db, options, "metrics"))
+        .map_err(|e| error_response(&e, credentials))?;
+    Ok(Response {
+        status: 200,
+        headers: vec![(
+            "Content-Type",
+            "text/plain; version=0.0.4; charset=utf-8".to_string(),
+        )],
+        body: db.prometheus_metrics().into_bytes(),
+    })
+}
+
+/// `GET /admin/{command}` or `GET /admin/{command}/{argument}`: one of the
+/// administrative and health commands, run as `{<command>: <argument>}`,
+/// or `{<command>: 1}` without one, such as `GET /admin/ping` for a load
+/// balancer or `GET /admin/collStats/users`. `listCollections` takes a
+/// filter as a find does.
+fn admin(
+    db: &Database,
+    options: &ServerOptions,
+    peer: &Peer,
+    request: &Request,
+) -> Result<Response, Response> {
+    let target = &request.path[ADMIN_PREFIX.len()..];
+    let (name, argument) = match target.split_once('/') {
+        Some((name, argument)) => (name, Some(argument)),
+        None => (target, None),
+    };
+    let argument = match argument.map(|argument| percent_decode(argument, false)) {
+        None => Some(Value::Int32(1)),
+        Some(argument) => argument.map(Value::from),
+    };
+    let Some(argument) = argument.filter(|_| ADMIN_COMMANDS.contains(&name)) else {
+        return Err(rejection(
+            404,
+            &format!("nothing is served at {}", request.path),
+        ));
+    };
+    if request.method != "GET" {
+        let mut response = rejection(405, &format!("{} is not served", request.method));
+        response.headers.push(("Allow", "GET".to_string()));
+        return Err(response);
+    }
+    let mut command = Document::new();
+    command.insert(name, argument);
+    let credentials = request.authorization.is_some();
+    let reply =
+        session(db, options, peer, request.authorization.as_deref()).and_then(|mut session| {
+            if name == "listCollections" {
+                command.insert("filter", filter(name, &request.query)?);
+            }
+            run(db, options, &mut session, &to_bytes(&command)?)
+        });
+    match reply {
+        Ok(Reply::Document(reply)) => Ok(Response::json(200, &reply)),
+        Ok(Reply::Stream { .. }) => unreachable!("no administrative command streams"),
+        Err(e) => Err(error_response(&e, credentials)),
+    }
+}
+
+/// `GET /watch/{collection}?filter&resumeAfter`, upgrading to a WebSocket:
+/// a change stream of the collection, for users allowed to read it. The
+/// filter is matched against change events, as `Collection::watch` does,
+/// and the stream starts after the change `resumeAfter` names, if given,
+/// or else now.
+fn watch(
+    db: &Database,
+    options: &ServerOptions,
+    peer: &Peer,
+    request: &Request,
+) -> Result<Handled, Response> {
+    if request.method != "GET" {
+        let mut response = rejection(405, &format!("{} is not served", request.method));
+        response.headers.push(("Allow", "GET".to_string()));
+        return Err(response);
+    }
+    let collection = route(&request.path, WATCH_PREFIX)?;
+    let key = match (&request.websocket_key, request.websocket_version.as_deref()) {
+        (Some(key), Some(WEBSOCKET_VERSION)) => key,
+        _ => {
+            let mut response = rejection(426, "a watch is served over a WebSocket");
+            response.headers.push(("Upgrade", "websocket".to_string()));
+            response
+                .headers
+                .push(("Sec-WebSocket-Version", WEBSOCKET_VERSION.to_string()));
+            return Err(response);
+        }
+    };
+    let credentials = request.authorization.is_some();
+    let changes = session(db, options, peer, request.authorization.as_deref())
+        .and_then(|session| session.authorize(db, options, "watch"))
+        .and_then(|()| {
+            let filter = filter("watch", &request.query)?;
+            let collection = db.collection(&collection);
+            match parameter(&request.query, "resumeAfter") {
+                Some(text) => collection.watch_after(&filter, resume_token(text)?),
+                None => collection.watch(&filter),
+            }
+            .map_err(ServerError::from)
+        })
+        .map_err(|e| error_response(&e, credentials))?;
+    Ok(Handled::Watch {
+        changes,
+        accept: accept_key(key),
+    })
+}
+
+/// Returns the session of the client `peer` as the user `authorization`
+/// names, with HTTP Basic credentials, or an unauthenticated one for none.
+/// Credentials are only checked with `ServerOptions::authentication`.
+fn session(
+    db: &Database,
+    options: &ServerOptions,
+    peer: &Peer,
+    authorization: Option<&str>,
+) -> Result<Session, ServerError> {
+    let Some(authorization) = authorization.filter(|_| options.authentication) else {
+        return Ok(Session::new(peer.clone()));
+    };
+    let credentials = authorization
+        .strip_prefix("Basic ")
+        .and_then(|encoded| BASE64.decode(encoded.trim()).ok())
+        .and_then(|decoded| String::from_utf8(decoded).ok())
+        .ok_or(ServerError::AuthenticationFailed)?;
+    let (name, password) = credentials
+        .split_once(':')
+        .ok_or(ServerError::AuthenticationFailed)?;
+    Session::basic(db, name, password, peer.clone())
+}
+
+/// Returns the collection `path` names, `{prefix}{collection}`.
+fn route(path: &str, prefix: &str) -> Result<String, Response> {
+    path.strip_prefix(prefix)
+        .filter(|collection| !collection.is_empty() && !collection.contains('/'))
+        .and_then(|collection| percent_decode(collection, false))
+        .ok_or_else(|| rejection(404, &format!("nothing is served at {}", path)))
+}
+
+/// `GET /db/{collection}?filter&sort&projection&skip&limit&batchSize&maxTimeMS`:
+/// a find, filtered as `filter` describes.
+fn find_command(collection: String, query: &[(String, String)]) -> Result<Document, ServerError> {
+    let mut command = Document::new();
+    command.insert("find", collection);
+    command.insert("filter", filter("find", query)?);
+    for name in ["sort", "projection"] {
+        if let Some(text) = parameter(query, name) {
+            command.insert(name, document("find", name, text)?);
+        }
+    }
+    for name in ["skip", "limit", "batchSize", "maxTimeMS"] {
+        if let Some(text) = parameter(query, name) {
+            command.insert(name, count("find", name, text)?);
+        }
+    }
+    Ok(command)
+}
+
+/// `POST /sql?batchSize&maxTimeMS` with a `SELECT` statement: the find it
+/// stands for, as `SqlQuery` translates it, whose rows are streamed as a
+/// find's matches are.
+fn sql_command(query: &[(String, String)], body: &[u8]) -> Result<Document, ServerError> {
+    let sql =
+        SqlQuery::parse(utf8("sql", body)?).map_err(|e| ServerError::bad("sql", e.to_string()))?;
+    let mut command = Document::new();
+    command.insert("find", sql.collection);
+    command.insert("filter", sql.filter);
+    if let Some(sort) = sql.sort {
+        command.insert("sort", sort);
+    }
+    if let Some(projection) = sql.projection {
+        command.insert("projection", projection);
+    }
+    command.insert("skip", i64::try_from(sql.skip).unwrap_or(i64::MAX));
+    if let Some(limit) = sql.limit {
+        command.insert("limit", i64::try_from(limit).unwrap_or(i64::MAX));
+    }
+    for name in ["batchSize", "maxTimeMS"] {
+        if let Some(text) = parameter(query, name) {
+            command.insert(name, count("sql", name, text)?);
+        }
+    }
+    Ok(command)
+}
+
+/// `POST /db/{collection}` with a document, or an array of them: an
+/// insert.
+fn insert_command(collection: String, body: &[u8]) -> Result<Document, ServerError> {
+    let text = utf8("insert", body)?;
+    let documents = match from_json_value_str(text)? {
+        document @ Value::Document(_) => Array::from_vec(vec![document]),
+        Value::Array(documents) => documents,
+        _ => {
+            return Err(ServerError::bad(
+                "insert",
+                "the body must be a document or an array of them",
+            ))
+        }
+    };
+    let mut command = Document::new();
+    command.insert("insert", collection);
+    command.insert("documents", documents);
+    Ok(command)
+}
+
+/// `PATCH /db/{collection}?filter&upsert&multi` with update operators: an
+/// update of every match, or only the first with `multi=false`.
+fn update_command(
+    collection: String,
+    query: &[(String, String)],
+    body: &[u8],
+) -> Result<Document, ServerError> {
+    let change = from_json_str(utf8("update", body)?)?;
+    if change.is_empty() || !change.iter().all(|(key, _)| key.starts_with('$')) {
+        return Err(ServerError::bad(
+            "update",
+            "the body must hold update operators, such as {\"$set\": {...}}",
+        ));
+    }
+    let mut update = Document::new();
+    update.insert("q", filter("update", query)?);
+    update.insert("u", change);
+    update.insert("upsert", flag("update", query, "upsert", false)?);
+    update.insert("multi", flag("update", query, "multi", true)?);
+    let mut command = Document::new();
+    command.insert("update", collection);
+    command.insert("updates", Array::from_vec(vec![Value::from(update)]));
+    if let Some(text) = parameter(query, "maxTimeMS") {
+        command.insert("maxTimeMS", count("update", "maxTimeMS", text)?);
+    }
+    Ok(command)
+}
+
+/// `DELETE /db/{collection}?filter&limit`: a delete of every match, or
+/// only the first with `limit=1`.
+fn delete_command(collection: String, query: &[(String, String)]) -> Result<Document, ServerError> {
+    let mut delete = Document::new();
+    delete.insert("q", filter("delete", query)?);
+    if let Some(text) = parameter(query, "limit") {
+        delete.insert("limit", count("delete", "limit", text)?);
+    }
+    let mut command = Document::new();
+    command.insert("delete", collection);
+    command.insert("deletes", Array::from_vec(vec![Value::from(delete)]));
+    if let Some(text) = parameter(query, "maxTimeMS") {
+        command.insert("maxTimeMS", count("delete", "maxTimeMS", text)?);
+    }
+    Ok(command)
+}
+
+/// Returns the filter of a request: the Extended JSON document of its
+/// `filter` parameter, if any, with a condition added for each parameter
+/// without a meaning of its own, that the field of its name equals its
+/// value. The value is read as JSON where it parses as such, so that
+/// `age=30` matches a number and `age={"$gt":30}` is an operator, and is a
+/// string otherwise.
+fn filter(command: &str, query: &[(String, String)]) -> Result<Document, ServerError> {
+    let mut filter = match parameter(query, "filter") {
+        Some(text) => document(command, "filter", text)?,
+        None => Document::new(),
+    };
+    for (name, text) in query {
+        if !PARAMETERS.contains(&name.as_str()) {
+            let value = from_json_value_str(text).unwrap_or_else(|_| Value::from(text.as_str()));
+            filter.insert(name, value);
+        }
+    }
+    Ok(filter)
+}
+
+fn parameter<'a>(query: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    query
+        .iter()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.as_str())
+}
+
+fn document(command: &str, name: &str, text: &str) -> Result<Document, ServerError> {
+    from_json_str(text)
+        .map_err(|e| ServerError::bad(command, format!("{} is not a JSON document: {}", name, e)))
+}
+
+fn count(command: &str, name: &str, text: &str) -> Result<Value, ServerError> {
+    match text.parse::<i64>() {
+        Ok(count) if count >= 0 => Ok(Value::Int64(count)),
+        _ => Err(ServerError::bad(
+            command,
+            format!("{} must be a count", name),
+        )),
+    }
+}
+
+fn flag(
+    command: &str,
+    query: &[(String, String)],
+    name: &str,
+    default: bool,
+) -> Result<bool, ServerError> {
+    match parameter(query, name) {
+        None => Ok(default),
+        Some("true") => Ok(true),
+        Some("false") => Ok(false),
+        Some(_) => Err(ServerError::bad(
+            command,
+            format!("{} must be true or false", name),
+        )),
+    }
+}
+
+fn utf8<'a>(command: &str, body: &'a [u8]) -> Result<&'a str, ServerError> {
+    std::str::from_utf8(body).map_err(|_| ServerError::bad(command, "the body is not UTF-8"))
+}
+
+/// Takes up to `batch_size` of `matches`, and returns them as JSON, comma
+/// separated, and whether more follow.
+fn next_batch(
+    matches: &mut Peekable<Matches>,
+    batch_size: usize,
+) -> Result<(Vec<u8>, bool), ServerError> {
+    let mut bytes = Vec::new();
+    for _ in 0..batch_size {
+        let Some(document) = matches.next() else {
+            break;
+        };
+        if !bytes.is_empty() {
+            bytes.push(b',');
+        }
+        bytes.extend(json(&document?)?);
+    }
+    Ok((bytes, matches.peek().is_some()))
+}
+
+pub(crate) fn json(document: &Document) -> Result<Vec<u8>, ServerError> {
+    let mut serializer = JsonSerializer::new(Vec::new(), false);
+    serializer.serialize_document(document)?;
+    Ok(serializer.into_inner())
+}
+
+/// Returns the response to a request that failed with `error`: its error
+/// reply, with the status closest to what went wrong. Requests refused for
+/// want of `credentials` are asked for them.
+fn error_response(error: &ServerError, credentials: bool) -> Response {
+    let status = match error {
+        ServerError::AuthenticationFailed => 401,
+        ServerError::Unauthorized(_) if !credentials => 401,
+        ServerError::Unauthorized(_) => 403,
+        ServerError::Deserialize(_) | ServerError::BadCommand { .. } => 400,
+        ServerError::Database(e) => match e {
+            DatabaseError::Query(_)
+            | DatabaseError::Deserialize(_)
+            | DatabaseError::InvalidUpdate(_)
+            | DatabaseError::InvalidCollectionName(_)
+            | DatabaseError::InvalidCollation(_)
+            | DatabaseError::ValidationFailed(_) => 400,
+            DatabaseError::CollectionNotFound(_) => 404,
+            DatabaseError::DuplicateKey(_)
+            | DatabaseError::WriteConflict(_)
+            | DatabaseError::Deadlock { .. }
+            | DatabaseError::VersionConflict { .. } => 409,
+            DatabaseError::LockTimeout(_) | DatabaseError::NotLeader(_) => 503,
+            DatabaseError::MaxTimeExpired(_) => 504,
+            _ => 500,
+        },
+        ServerError::TooManyConnections(_) => 503,
+        _ => 500,
+    };
+    let mut response = Response::json(status, &error_reply(error));
+    if status == 401 {
+        response
+            .headers
+            .push(("WWW-Authenticate", "Basic realm=\"silentdb\"".to_string()));
+    }
+    response
+}
+
+/// Returns the response to a request refused before it came to a command,
+/// with an error reply as a failed command's.
+fn rejection(status: u16, message: &str) -> Response {
+    let mut reply = Document::new();
+    reply.insert("ok", 0.0);
+    reply.insert("errmsg", message);
+    reply.insert("code", 9);
+    Response::json(status, &reply)
+}
+
+/// Reads the next request off `stream`, answering `Expect: 100-continue`
+/// before reading its body.
+///
+/// Bodies must come with a `Content-Length` of at most `max_body_len`;
+/// chunked ones are refused.
+async fn read_request<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut BufReader<S>,
+    max_body_len: usize,
+) -> Result<Incoming, ServerError> {
+    let mut lines = Vec::new();
+    let mut head_len = 0;
+    loop {
+        let mut line = Vec::new();
+        let limit = (MAX_HEAD_LEN - head_len) as u64;
+        let read = (&mut *stream)
+            .take(limit)
+            .read_until(b'\n', &mut line)
+            .await?;
+        head_len += read;
+        if read == 0 && head_len == 0 {
+            return Ok(Incoming::Closed);
+        }
+        if !line.ends_with(b"\n") {
+            if head_len == MAX_HEAD_LEN {
+                return Ok(Incoming::Rejected(rejection(
+                    431,
+                    "the request line and headers are too long",
+                )));
+            }
+            return Err(ServerError::Io(ErrorKind::UnexpectedEof.into()));
+        }
+        let Ok(line) = String::from_utf8(line) else {
+            return Ok(Incoming::Rejected(rejection(
+                400,
+                "the headers are not UTF-8",
+            )));
+        };
+        let line = line.trim_end_matches(['\r', '\n']);
+        match (line.is_empty(), lines.is_empty()) {
+            // Blank lines before a request are allowed for
+            (true, true) => {}
+            (true, false) => break,
+            (false, _) => lines.push(line.to_string()),
+        }
+    }
+
+    let mut request_line = lines[0].split(' ');
+    let (Some(method), Some(target), Some(version), None) = (
+        request_line.next(),
+        request_line.next(),
+        request_line.next(),
+        request_line.next(),
+    ) else {
+        return Ok(Incoming::Rejected(rejection(400, "malformed request line")));
+    };
+    let mut keep_alive = match version {
+        "HTTP/1.1" => true,
+        "HTTP/1.0" => false,
+        _ => {
+            return Ok(Incoming::Rejected(rejection(
+                505,
+                "only HTTP/1.1 and HTTP/1.0 are served",
+            )))
+        }
+    };
+    let mut content_len = 0;
+    let mut authorization = None;
+    let mut upgrade = false;
+    let mut websocket_key = None;
+    let mut websocket_version = None;
+    let mut expect_continue = false;
+    for line in &lines[1..] {
+        let Some((name, value)) = line.split_once(':') else {
+            return Ok(Incoming::Rejected(rejection(400, "malformed header")));
+        };
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "content-length" => match value.parse::<usize>() {
+                Ok(len) => content_len = len,
+                Err(_) => return Ok(Incoming::Rejected(rejection(400, "bad Content-Length"))),
+            },
+            "transfer-encoding" => {
+                return Ok(Incoming::Rejected(rejection(
+                    411,
+                    "chunked bodies are not accepted; send a Content-Length",
+                )))
+            }
+            "connection" => {
+                let value = value.to_ascii_lowercase();
+                if value.contains("close") {
+                    keep_alive = false;
+                } else if value.contains("keep-alive") {
+                    keep_alive = true;
+                }
+            }
+            "authorization" => authorization = Some(value.to_string()),
+            "upgrade" => upgrade = value.eq_ignore_ascii_case("websocket"),
+            "sec-websocket-key" => websocket_key = Some(value.to_string()),
+            "sec-websocket-version" => websocket_version = Some(value.to_string()),
+            "expect" => expect_continue = value.eq_ignore_ascii_case("100-continue"),
+            _ => {}
+        }
+    }
+    if content_len > max_body_len {
+        return Ok(Incoming::Rejected(rejection(
+            413,
+            &format!(
+                "the body is {} bytes, more than the {} accepted",
+                content_len, max_body_len
+            ),
+        )));
+    }
+    if expect_continue && content_len > 0 {
+        stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").await?;
+        stream.flush().await?;
+    }
+    let mut body = vec![0; content_len];
+    stream.read_exact(&mut body).await?;
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let mut parameters = Vec::new();
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+        match (percent_decode(name, true), percent_decode(value, true)) {
+            (Some(name), Some(value)) => parameters.push((name, value)),
+            _ => {
+                return Ok(Incoming::Rejected(rejection(
+                    400,
+                    "malformed query parameter",
+                )))
+            }
+        }
+    }
+    Ok(Incoming::Request(Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        query: parameters,
+        authorization,
+        websocket_key: websocket_key.filter(|_| upgrade),
+        websocket_version,
+        body,
+        keep_alive,
+    }))
+}
+
+/// Undoes the percent-encoding of `text`, and with `plus` the encoding of
+/// spaces as `+` in query strings, returning `None` if it is malformed or
+/// not UTF-8.
+fn percent_decode(text: &str, plus: bool) -> Option<String> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&byte, after)) = rest.split_first() {
+        match byte {
+            b'%' => {
+                let hex = std::str::from_utf8(after.get(..2)?).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+                rest = &after[2..];
+                continue;
+            }
+            b'+' if plus => bytes.push(b' '),
+            byte => bytes.push(byte),
+        }
+        rest = after;
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Returns the status line and headers of a response, with `Connection:
+/// close` unless the connection is kept alive.
+fn head(status: u16, headers: &[(&str, String)], keep_alive: bool) -> Vec<u8> {
+    let mut head = format!("HTTP/1.1 {} {}\r\n", status, reason(status));
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if !keep_alive {
+        head.push_str("Connection: close\r\n");
+    }
+    head.push_str("\r\n");
+    head.into_bytes()
+}
+
+/// Frames `bytes` as a chunk of a chunked body.
+fn chunk(bytes: &[u8]) -> Vec<u8> {
+    let mut chunk = format!("{:x}\r\n", bytes.len()).into_bytes();
+    chunk.extend_from_slice(bytes);
+    chunk.extend_from_slice(b"\r\n");
+    chunk
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        101 => "Switching Protocols",
+        200 => "OK",
+        201 => "Created",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        411 => "Length Required",
+        413 => "Content Too Large",
+        426 => "Upgrade Required",
+        431 => "Request Header Fields Too Large",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        505 => "HTTP Version Not Supported",
+        _ => "Internal Server Error",
+    }
+}
@@ -0,0 +1,620 @@
+// src/server/listener.rs
+
+use std::future::{self, Future};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::pin::pin;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use rustls::ServerConfig;
+use silentdb_data_encoding::{BufferPool, MAX_DOCUMENT_LEN};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime;
+use tokio::sync::{oneshot, watch, OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinSet;
+use tokio::time;
+use tokio_rustls::TlsAcceptor;
+
+use super::audit::{AuditLog, AuditOptions};
+use super::connection::{refuse_connection, serve_connection};
+use super::error::ServerError;
+use super::http::{refuse_http_connection, serve_http_connection};
+use super::retry::RetryTable;
+use super::session::SessionTable;
+use super::status::ServerStatus;
+use super::task::{blocking, Stopping};
+use super::tls::TlsOptions;
+use super::wire::{refuse_mongo_connection, serve_mongo_connection};
+use crate::db::Database;
+
+/// Clients connected at once unless told otherwise.
+const DEFAULT_MAX_CONNECTIONS: usize = 1024;
+/// Operations a connection may have in progress unless told otherwise.
+const DEFAULT_MAX_OPERATIONS: usize = 64;
+/// How long a client over the connection limit has to take its refusal.
+const REFUSAL_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a cursor may go unread unless told otherwise.
+const DEFAULT_CURSOR_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// How long a logical session may go unused unless told otherwise.
+const DEFAULT_SESSION_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+/// How long a write's operation id is remembered unless told otherwise.
+const DEFAULT_RETRY_WINDOW: Duration = Duration::from_secs(10 * 60);
+/// How often timed out sessions, cursors and operation ids are looked for.
+const REAP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Options for `Server::bind_with`.
+///
+/// # Examples
+///
+/// ```
+/// # use std::time::Duration;
+/// # use silentdb::server::{Protocol, ServerOptions};
+/// let options = ServerOptions::new()
+///     .max_document_len(1024 * 1024)
+///     .max_connections(200)
+///     .max_time(Duration::from_secs(30))
+///     .protocol(Protocol::MongoDb)
+///     .authentication(true);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerOptions {
+    pub(crate) max_document_len: usize,
+    pub(crate) max_connections: usize,
+    pub(crate) max_operations: usize,
+    pub(crate) max_time: Option<Duration>,
+    pub(crate) cursor_timeout: Duration,
+    pub(crate) session_timeout: Duration,
+    pub(crate) retry_window: Duration,
+    pub(crate) protocol: Protocol,
+    pub(crate) tls: Option<TlsOptions>,
+    pub(crate) authentication: bool,
+    pub(crate) audit: Option<AuditOptions>,
+}
+
+/// The protocol a server speaks with its clients.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Protocol {
+    /// Command documents framed by their own length, as `Server` describes.
+    #[default]
+    Native,
+    /// The MongoDB wire protocol, `OP_MSG` and the `OP_QUERY` handshake, so
+    /// that MongoDB drivers and the shell can connect.
+    MongoDb,
+    /// HTTP/1.1, with each collection a resource under `/db/{collection}`
+    /// and documents in Extended JSON, as `Server` describes.
+    Http,
+}
+
+impl ServerOptions {
+    /// Creates options accepting requests up to `MAX_DOCUMENT_LEN` bytes
+    /// from up to 1024 clients at once, in the native protocol, over plain
+    /// TCP, from anyone.
+    pub fn new() -> Self {
+        ServerOptions {
+            max_document_len: MAX_DOCUMENT_LEN,
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            max_operations: DEFAULT_MAX_OPERATIONS,
+            max_time: None,
+            cursor_timeout: DEFAULT_CURSOR_TIMEOUT,
+            session_timeout: DEFAULT_SESSION_TIMEOUT,
+            retry_window: DEFAULT_RETRY_WINDOW,
+            protocol: Protocol::Native,
+            tls: None,
+            authentication: false,
+            audit: None,
+        }
+    }
+
+    /// Sets the largest request document accepted, in bytes. A client
+    /// sending a larger one is replied to with an error and disconnected.
+    pub fn max_document_len(mut self, bytes: usize) -> Self {
+        self.max_document_len = bytes;
+        self
+    }
+
+    /// Sets how many clients may be connected at once. A client connecting
+    /// past the limit is sent a `TooManyConnections` error, in reply to its
+    /// first request where its protocol needs one, and disconnected.
+    pub fn max_connections(mut self, connections: usize) -> Self {
+        self.max_connections = connections;
+        self
+    }
+
+    /// Sets how many operations a connection may have in progress at once,
+    /// 64 by default. Connections run their commands one at a time, so
+    /// what counts against the limit are the cursors a MongoDB client left
+    /// open for `getMore`; a find that would leave one more fails with
+    /// `TooManyOperations` instead, and the connection carries on.
+    pub fn max_operations_per_connection(mut self, operations: usize) -> Self {
+        self.max_operations = operations.max(1);
+        self
+    }
+
+    /// Sets the longest a find may spend fetching its matches, or a count
+    /// counting them, unless its command's `maxTimeMS` asks for less. One
+    /// out of time fails with `MaxTimeExpired`. By default only
+    /// `maxTimeMS` limits them. Writes are not limited, and refuse a
+    /// `maxTimeMS`.
+    pub fn max_time(mut self, max_time: Duration) -> Self {
+        self.max_time = Some(max_time);
+        self
+    }
+
+    /// Sets how long a cursor a MongoDB client left open may go unread
+    /// before it is closed, 10 minutes by default.
+    pub fn cursor_timeout(mut self, timeout: Duration) -> Self {
+        self.cursor_timeout = timeout;
+        self
+    }
+
+    /// Sets how long a MongoDB client's logical session may go unused
+    /// before it ends, closing its cursors, 30 minutes by default.
+    pub fn session_timeout(mut self, timeout: Duration) -> Self {
+        self.session_timeout = timeout;
+        self
+    }
+
+    /// Sets how long the reply to a write carrying an `opId` is kept to
+    /// answer retries of it with, 10 minutes by default. A retry arriving
+    /// later runs the write again.
+    pub fn retry_window(mut self, window: Duration) -> Self {
+        self.retry_window = window;
+        self
+    }
+
+    /// Sets the protocol clients speak.
+    pub fn protocol(mut self, protocol: Protocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    /// Serves clients over TLS with `tls`, in whichever protocol, instead
+    /// of plain TCP.
+    pub fn tls(mut self, tls: TlsOptions) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Sets whether clients must authenticate as one of the database's
+    /// users, with SCRAM-SHA-256, before running commands their roles
+    /// allow. Until the first user is created, anyone may create one.
+    pub fn authentication(mut self, authentication: bool) -> Self {
+        self.authentication = authentication;
+        self
+    }
+
+    /// Records who runs each write and administrative command in the audit
+    /// log `audit` describes, opened when the server is bound.
+    pub fn audit(mut self, audit: AuditOptions) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        ServerOptions::new()
+    }
+}
+
+/// A server giving clients on the network access to a database.
+///
+/// Clients connect over TCP and send command documents, each encoded as
+/// usual, so that its length prefix frames it on the stream. A command is
+/// named by its first field whose name is a command's, with the collection
+/// it works on as that field's value:
+///
+/// ```text
+/// {hello: 1}
+/// {find: "users", filter: {age: {$gte: 21}}, sort: {age: 1}, projection,
+///  skip, limit, batchSize, maxTimeMS}
+/// {insert: "users", documents: [...]}
+/// {update: "users", updates: [{q: <filter>, u: <update>, upsert, multi}]}
+/// {delete: "users", deletes: [{q: <filter>, limit: 0 | 1}]}
+/// {count: "users", query: <filter>}
+/// {saslStart: 1, mechanism: "SCRAM-SHA-256", payload: <binary>}
+/// {saslContinue: 1, conversationId: 1, payload: <binary>}
+/// {createUser: "alice", pwd: "...", roles: ["readWrite"]}
+/// {updateUser: "alice", pwd, roles}
+/// {dropUser: "alice"}
+/// {usersInfo: 1 | "alice"}
+/// {metrics: 1}
+/// {ping: 1}
+/// {buildInfo: 1}
+/// {serverStatus: 1}
+/// {listCollections: 1, filter, nameOnly}
+/// {collStats: "users"}
+/// {currentOp: 1}
+/// ```
+///
+/// The server replies to each command in turn, with documents framed the
+/// same way. Every reply has `ok`, 1 on success; a failed command's reply
+/// has `ok: 0`, an `errmsg` and a `code`, and the connection carries on.
+/// A find streams its matches in replies of up to `batchSize` documents,
+/// `{ok: 1, batch: [...], more: true}`, the last of which has `more`
+/// false; an error partway ends the stream with an error reply instead.
+///
+/// A write, `insert`, `update` or `delete`, may carry an `opId`, binary
+/// and unique to the operation, so that a client whose connection dropped
+/// before the reply arrived can send it again safely: a write whose id
+/// already succeeded is answered with its first reply rather than applied
+/// twice, for the server's `retry_window`.
+///
+/// With `ServerOptions::authentication`, a connection authenticates as a
+/// user through `saslStart` and `saslContinue`, in the SCRAM-SHA-256
+/// exchange MongoDB drivers use, and may then run only the commands its
+/// user's roles allow; any other fails with code 13, `Unauthorized`.
+/// `hello`, `ping` and `buildInfo` need no user, so load balancers can
+/// check on the server without credentials.
+///
+/// The server runs on tokio: each connection is served by its own task,
+/// running its commands one at a time on the runtime's blocking pool, so
+/// that connections waiting on their clients take up no thread. A find's
+/// matches are read a batch at a time as the client takes them, so one
+/// that stops reading holds up only its own connection.
+///
+/// Connections are plain TCP unless the options set up TLS; to offer both,
+/// bind a server for each on its own address.
+///
+/// With `Protocol::MongoDb`, the server instead speaks enough of the
+/// MongoDB wire protocol for its drivers: the `hello` handshake, the same
+/// commands in `OP_MSG` messages, with `insert` documents also accepted as
+/// a document sequence, and finds returning a cursor to page through with
+/// `getMore` and close with `killCursors`. Commands carrying an `lsid` run
+/// in that logical session, which owns the cursors they open until
+/// `endSessions` ends it or it goes unused for the `session_timeout`;
+/// cursors left unread for the `cursor_timeout` are closed too. Error
+/// replies carry MongoDB's `codeName` too.
+///
+/// With `Protocol::Http`, the server is a JSON gateway instead, serving
+/// each collection at `/db/{collection}` with bodies in Extended JSON:
+///
+/// ```text
+/// GET    /db/users?filter={"age":{"$gte":21}}&sort={"age":1}&skip&limit
+/// GET    /db/users?name=alice
+/// POST   /db/users                  {"name": "bob"} or [{...}, ...]
+/// PATCH  /db/users?name=bob         {"$set": {"age": 30}}
+/// DELETE /db/users?name=bob
+/// GET    /metrics
+/// GET    /watch/users?filter={"operationType":"insert"}&resumeAfter
+/// GET    /admin/ping
+/// GET    /admin/collStats/users
+/// ```
+///
+/// A request's filter is its `filter` parameter, with a condition added
+/// for each parameter not otherwise meaningful that the field of its name
+/// equals its value, read as JSON where it parses and as a string where it
+/// does not. `GET` streams the matches as a chunked JSON array; the other
+/// methods reply with the command's reply, as do failures, with a status
+/// to match. `PATCH` updates every match unless `multi=false`, with
+/// `upsert=true` to insert when none do, and `DELETE` deletes every match
+/// unless `limit=1`. `/metrics` serves `Database::prometheus_metrics` for
+/// Prometheus to scrape, to users the `metrics` command is allowed.
+/// `/watch/{collection}` upgrades the connection to a WebSocket carrying
+/// the collection's change events matching the filter, each a text frame
+/// of Extended JSON whose `_id._data` a client passes back as
+/// `resumeAfter` to resume after it. `/admin/{command}` runs the
+/// administrative and health commands `ping`, `buildInfo`,
+/// `serverStatus`, `listCollections`, `collStats` and `currentOp`, with
+/// the argument following the command, if any. With
+/// `ServerOptions::authentication`, requests carry a user's credentials
+/// with HTTP Basic authentication, and so should only be made over TLS.
+///
+/// Whatever the protocol, a document larger than `max_document_len` is
+/// refused with an error reply rather than read into memory, and clients
+/// past `max_connections` are told so and disconnected. A find or count
+/// taking longer than its `maxTimeMS` fails with code 50,
+/// `MaxTimeMSExpired`, while writes refuse a `maxTimeMS`.
+/// With `ServerOptions::audit`, each write and administrative command is
+/// recorded with the user and address of the client that ran it.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use silentdb::Database;
+/// # use silentdb::server::Server;
+/// let db = Database::open("data").unwrap();
+/// let server = Server::bind(db, "127.0.0.1:27117").unwrap().spawn().unwrap();
+/// println!("listening on {}", server.local_addr());
+/// server.shutdown().unwrap();
+/// ```
+///
+/// Or on a tokio runtime of your own, until told to stop:
+///
+/// ```no_run
+/// # use silentdb::Database;
+/// # use silentdb::server::Server;
+/// # async fn example(stop: tokio::sync::oneshot::Receiver<()>) {
+/// let db = Database::open("data").unwrap();
+/// let server = Server::bind(db, "127.0.0.1:27117").unwrap();
+/// server
+///     .serve_until(async {
+///         let _ = stop.await;
+///     })
+///     .await
+///     .unwrap();
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Server {
+    db: Database,
+    listener: std::net::TcpListener,
+    options: ServerOptions,
+    tls: Option<Arc<ServerConfig>>,
+    audit: Option<Arc<AuditLog>>,
+}
+
+impl Server {
+    /// Creates a server for `db` listening on `addr`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the address cannot be bound.
+    pub fn bind<A: ToSocketAddrs>(db: Database, addr: A) -> Result<Server, ServerError> {
+        Server::bind_with(db, addr, ServerOptions::new())
+    }
+
+    /// Creates a server for `db` listening on `addr` with `options`.
+    ///
+    /// The address is bound at once, so that clients can connect as soon
+    /// as this returns, and accepted from once the server is served.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the TLS certificates or keys are bad, the audit
+    /// log cannot be opened, or the address cannot be bound.
+    pub fn bind_with<A: ToSocketAddrs>(
+        db: Database,
+        addr: A,
+        options: ServerOptions,
+    ) -> Result<Server, ServerError> {
+        let tls = options.tls.as_ref().map(TlsOptions::config).transpose()?;
+        let audit = match &options.audit {
+            Some(audit) => Some(Arc::new(audit.open(&db)?)),
+            None => None,
+        };
+        let listener = std::net::TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(Server {
+            db,
+            listener,
+            options,
+            tls,
+            audit,
+        })
+    }
+
+    /// Returns the address the server listens on, such as the port chosen
+    /// for port 0.
+    pub fn local_addr(&self) -> Result<SocketAddr, ServerError> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Accepts connections and serves them, each on its own task of the
+    /// current tokio runtime, for as long as the future is polled.
+    /// Connections failing are dropped without stopping the server.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if accepting connections fails.
+    pub async fn serve(self) -> Result<(), ServerError> {
+        self.serve_until(future::pending()).await
+    }
+
+    /// Serves connections as `serve` does until `shutdown` completes, then
+    /// stops accepting them and closes each open one once it is waiting on
+    /// its client, after finishing the command it is running, and returns
+    /// when they are all closed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if accepting connections fails, after closing the
+    /// open ones all the same.
+    pub async fn serve_until<F: Future<Output = ()>>(self, shutdown: F) -> Result<(), ServerError> {
+        let listener = TcpListener::from_std(self.listener)?;
+        let options = Arc::new(self.options);
+        let tls = self.tls.map(TlsAcceptor::from);
+        let (stop, stopping) = watch::channel(false);
+        let permits = Arc::new(Semaphore::new(options.max_connections));
+        let sessions = Arc::new(SessionTable::new(&options));
+        let status = Arc::new(ServerStatus::new(&options));
+        let retries = Arc::new(RetryTable::new(&options));
+        let buffers = BufferPool::default();
+        let mut reaper = time::interval(REAP_INTERVAL);
+        let mut connections = JoinSet::new();
+        let mut shutdown = pin!(shutdown);
+        let result = loop {
+            tokio::select! {
+                () = &mut shutdown => break Ok(()),
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, addr)) => {
+                        let peer = Peer {
+                            addr: Some(addr),
+                            audit: self.audit.clone(),
+                            sessions: Arc::clone(&sessions),
+                            status: Arc::clone(&status),
+                            retries: Arc::clone(&retries),
+                            buffers: buffers.clone(),
+                        };
+                        connections.spawn(serve_client(
+                            self.db.clone(),
+                            stream,
+                            Arc::clone(&options),
+                            tls.clone(),
+                            peer,
+                            Arc::clone(&permits).try_acquire_owned().ok(),
+                            Stopping::new(stopping.clone()),
+                        ));
+                    }
+                    Err(e) => break Err(e.into()),
+                },
+                // Reap connections as they close
+                Some(_) = connections.join_next(), if !connections.is_empty() => {}
+                _ = reaper.tick() => {
+                    let sessions = Arc::clone(&sessions);
+                    let retries = Arc::clone(&retries);
+                    blocking(move || {
+                        sessions.reap();
+                        retries.reap();
+                    })
+                    .await;
+                }
+            }
+        };
+        drop(listener);
+        let _ = stop.send(true);
+        while connections.join_next().await.is_some() {}
+        result
+    }
+
+    /// Serves connections on a runtime of the server's own, on a background
+    /// thread, returning a handle that shuts the server down.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the runtime or its thread cannot be started.
+    pub fn spawn(self) -> Result<ServerHandle, ServerError> {
+        let addr = self.local_addr()?;
+        let runtime = runtime::Builder::new_multi_thread()
+            .thread_name("silentdb-server")
+            .enable_all()
+            .build()?;
+        let (stop, stopped) = oneshot::channel();
+        let thread = thread::Builder::new()
+            .name("silentdb-server".to_string())
+            .spawn(move || {
+                runtime.block_on(self.serve_until(async {
+                    // A handle dropped without a shutdown leaves the server
+                    // running
+                    if stopped.await.is_err() {
+                        future::pending::<()>().await;
+                    }
+                }))
+            })?;
+        Ok(ServerHandle { addr, stop, thread })
+    }
+}
+
+/// The client at the other end of a connection, and what of the server's
+/// it shares with the others: the audit log, if any, the table of logical
+/// sessions and open cursors, the server's status, the replies kept for
+/// retried writes, and the pool of buffers frames are read and written in.
+#[derive(Debug, Clone)]
+pub(crate) struct Peer {
+    pub(crate) addr: Option<SocketAddr>,
+    pub(crate) audit: Option<Arc<AuditLog>>,
+    pub(crate) sessions: Arc<SessionTable>,
+    pub(crate) status: Arc<ServerStatus>,
+    pub(crate) retries: Arc<RetryTable>,
+    pub(crate) buffers: BufferPool,
+}
+
+/// Serves the client `peer` on `stream`, over TLS if `tls` is set, in the
+/// protocol `options` set, holding `permit` until it disconnects, or refuses it for
+/// want of one. A client's failure is its own, and was replied to where
+/// the stream allowed, so is not returned.
+async fn serve_client(
+    db: Database,
+    stream: TcpStream,
+    options: Arc<ServerOptions>,
+    tls: Option<TlsAcceptor>,
+    peer: Peer,
+    permit: Option<OwnedSemaphorePermit>,
+    stopping: Stopping,
+) {
+    let _ = stream.set_nodelay(true);
+    let admitted = permit.is_some();
+    let _connected = match admitted {
+        true => Some(peer.status.connect()),
+        false => {
+            peer.status.refuse();
+            None
+        }
+    };
+    let client = connect_client(&db, stream, &options, tls, &peer, admitted, stopping);
+    // A refused client only gets so long to hold up its task
+    let _ = match admitted {
+        true => client.await,
+        false => time::timeout(REFUSAL_TIMEOUT, client)
+            .await
+            .unwrap_or(Ok(())),
+    };
+    drop(permit);
+}
+
+/// Serves or refuses the client on `stream`, after the TLS handshake if
+/// `tls` is set.
+async fn connect_client(
+    db: &Database,
+    stream: TcpStream,
+    options: &Arc<ServerOptions>,
+    tls: Option<TlsAcceptor>,
+    peer: &Peer,
+    admitted: bool,
+    mut stopping: Stopping,
+) -> Result<(), ServerError> {
+    match tls {
+        Some(acceptor) => match stopping.unless(acceptor.accept(stream)).await {
+            Some(Ok(stream)) => serve_stream(db, stream, options, peer, admitted, stopping).await,
+            Some(Err(e)) => Err(ServerError::Tls(e.to_string())),
+            None => Ok(()),
+        },
+        None => serve_stream(db, stream, options, peer, admitted, stopping).await,
+    }
+}
+
+async fn serve_stream<S: AsyncRead + AsyncWrite + Unpin>(
+    db: &Database,
+    stream: S,
+    options: &Arc<ServerOptions>,
+    peer: &Peer,
+    admitted: bool,
+    stopping: Stopping,
+) -> Result<(), ServerError> {
+    if !admitted {
+        let refusal = ServerError::TooManyConnections(options.max_connections);
+        return match options.protocol {
+            Protocol::Native => refuse_connection(stream, &refusal, stopping).await,
+            Protocol::MongoDb => {
+                refuse_mongo_connection(db, stream, options, peer, &refusal, stopping).await
+            }
+            Protocol::Http => refuse_http_connection(stream, &refusal, stopping).await,
+        };
+    }
+    match options.protocol {
+        Protocol::Native => serve_connection(db, stream, options, peer, stopping).await,
+        Protocol::MongoDb => serve_mongo_connection(db, stream, options, peer, stopping).await,
+        Protocol::Http => serve_http_connection(db, stream, options, peer, stopping).await,
+    }
+}
+
+/// A handle to a server running on a background thread, from
+/// `Server::spawn`.
+#[derive(Debug)]
+pub struct ServerHandle {
+    addr: SocketAddr,
+    stop: oneshot::Sender<()>,
+    thread: JoinHandle<Result<(), ServerError>>,
+}
+
+impl ServerHandle {
+    /// Returns the address the server listens on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Stops accepting connections, closes every connection once it is
+    /// waiting on its client, including one whose client stopped reading
+    /// its replies, and waits for the server to exit. A command running at
+    /// the time finishes first.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error that stopped the accept loop, if one did.
+    pub fn shutdown(self) -> Result<(), ServerError> {
+        let _ = self.stop.send(());
+        self.thread.join().expect("server thread panicked")
+    }
+}
@@ -0,0 +1,24 @@
+// src/server/mod.rs
+
+mod audit;
+mod auth;
+mod command;
+mod connection;
+mod error;
+mod http;
+mod listener;
+mod retry;
+mod session;
+mod status;
+mod task;
+mod test;
+mod tls;
+mod websocket;
+mod wire;
+
+pub use audit::AuditOptions;
+pub use error::ServerError;
+pub use listener::{Protocol, Server, ServerHandle, ServerOptions};
+#[cfg(feature = "grpc")]
+pub(crate) use task::blocking;
+pub use tls::TlsOptions;
@@ -0,0 +1,168 @@
+// src/server/retry.rs
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use silentdb_data_encoding::{Document, Value};
+
+use super::error::ServerError;
+use super::listener::ServerOptions;
+
+/// The field of a write command holding the id its client gave the
+/// operation, so that a retry of it is not applied twice.
+pub(crate) const OP_ID: &str = "opId";
+
+/// The writes a server's clients ran with an operation id, and their
+/// replies, shared by all its connections.
+///
+/// A client that loses its connection before a write's reply arrives
+/// cannot tell whether the write was applied, so it sends it again, on a
+/// new connection, with the same `opId`. The first write with an id is
+/// run; a retry of one that succeeded is answered with its reply instead
+/// of running again, and a retry of one still running waits for it to
+/// finish. A write that failed before any of its statements ran is
+/// forgotten, so its retry runs it again, while one that failed part way is
+/// remembered with its error reply, as running it again would repeat the
+/// statements that ran. Ids are remembered per user for the server's retry
+/// window.
+pub(crate) struct RetryTable {
+    window: Duration,
+    writes: Mutex<HashMap<OpKey, Write>>,
+    finished: Condvar,
+}
+
+/// An operation id, and the user who ran it, so that one user's ids can
+/// never answer another's writes.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct OpKey {
+    user: Option<String>,
+    id: Vec<u8>,
+}
+
+enum Write {
+    Running,
+    Done { reply: Document, at: Instant },
+}
+
+/// What came of starting a write with an operation id.
+pub(crate) enum Begun<'a> {
+    /// The write is the first with its id, and is to be run.
+    Run(Pending<'a>),
+    /// The write already ran, and this was its reply, or its error reply
+    /// if it failed part way.
+    Done(Document),
+}
+
+/// A write being run with an operation id, forgotten if dropped before it
+/// finishes.
+pub(crate) struct Pending<'a> {
+    table: &'a RetryTable,
+    key: Option<OpKey>,
+}
+
+impl Pending<'_> {
+    /// Remembers that the write finished with `reply`, which retries of it
+    /// are answered with.
+    pub(crate) fn finish(mut self, reply: &Document) {
+        if let Some(key) = self.key.take() {
+            let done = Write::Done {
+                reply: reply.clone(),
+                at: Instant::now(),
+            };
+            self.table.lock().insert(key, done);
+            self.table.finished.notify_all();
+        }
+    }
+}
+
+impl Drop for Pending<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.table.lock().remove(&key);
+            self.table.finished.notify_all();
+        }
+    }
+}
+
+impl fmt::Debug for RetryTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RetryTable")
+            .field("window", &self.window)
+            .field("writes", &self.lock().len())
+            .finish()
+    }
+}
+
+impl RetryTable {
+    /// Creates an empty table remembering writes for the retry window
+    /// `options` set.
+    pub(crate) fn new(options: &ServerOptions) -> Self {
+        RetryTable {
+            window: options.retry_window,
+            writes: Mutex::new(HashMap::new()),
+            finished: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<OpKey, Write>> {
+        self.writes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Starts the write `command`, with `document`, run by `user`, waiting
+    /// for a write with the same operation id to finish if one is running.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BadCommand` if the operation id is not binary.
+    pub(crate) fn begin(
+        &self,
+        user: Option<&str>,
+        command: &str,
+        document: &Document,
+    ) -> Result<Begun<'_>, ServerError> {
+        let id = match document.get(OP_ID) {
+            None => {
+                return Ok(Begun::Run(Pending {
+                    table: self,
+                    key: None,
+                }))
+            }
+            Some(Value::Binary(id)) => id.clone(),
+            Some(_) => return Err(ServerError::bad(command, "opId must be binary")),
+        };
+        let key = OpKey {
+            user: user.map(str::to_string),
+            id,
+        };
+        let mut writes = self.lock();
+        loop {
+            match writes.get(&key) {
+                None => {
+                    writes.insert(key.clone(), Write::Running);
+                    return Ok(Begun::Run(Pending {
+                        table: self,
+                        key: Some(key),
+                    }));
+                }
+                Some(Write::Running) => {
+                    writes = self
+                        .finished
+                        .wait(writes)
+                        .unwrap_or_else(|e| e.into_inner());
+                }
+                Some(Write::Done { reply, .. }) => return Ok(Begun::Done(reply.clone())),
+            }
+        }
+    }
+
+    /// Forgets the writes that finished longer ago than the retry window.
+    pub(crate) fn reap(&self) {
+        let window = self.window;
+        self.lock().retain(|_, write| match write {
+            Write::Running => true,
+            Write::Done { at, .. } => at.elapsed() <= window,
+        });
+    }
+}
@@ -0,0 +1,392 @@
+// src/server/session.rs
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::iter::Peekable;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use silentdb_data_encoding::{Array, Document, Value};
+
+use super::command::Matches;
+use super::error::ServerError;
+use super::listener::ServerOptions;
+use crate::db::DatabaseError;
+
+/// The id a MongoDB client gives a logical session: the bytes of the UUID
+/// under `id` in its `lsid`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct SessionId(Vec<u8>);
+
+impl SessionId {
+    /// Returns a new random id.
+    pub(crate) fn generate() -> Self {
+        let mut uuid: [u8; 16] = rand::random();
+        // A version 4, variant 1 UUID
+        uuid[6] = (uuid[6] & 0x0f) | 0x40;
+        uuid[8] = (uuid[8] & 0x3f) | 0x80;
+        SessionId(uuid.to_vec())
+    }
+
+    /// Returns the id `lsid` holds, `{id: <UUID>}`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BadCommand` for `command` if `lsid` is not such a document.
+    pub(crate) fn from_lsid(command: &str, lsid: &Value) -> Result<Self, ServerError> {
+        match lsid {
+            Value::Document(lsid) => match lsid.get("id") {
+                Some(Value::Binary(id)) => Ok(SessionId(id.clone())),
+                _ => Err(ServerError::bad(
+                    command,
+                    "a session id must be {id: <UUID>}",
+                )),
+            },
+            _ => Err(ServerError::bad(
+                command,
+                "a session id must be {id: <UUID>}",
+            )),
+        }
+    }
+
+    /// Returns the id as an `lsid` document, `{id: <UUID>}`.
+    pub(crate) fn to_lsid(&self) -> Document {
+        let mut lsid = Document::new();
+        lsid.insert("id", Value::Binary(self.0.clone()));
+        lsid
+    }
+}
+
+/// A cursor a find left open for `getMore`.
+pub(crate) struct OpenCursor {
+    pub(crate) namespace: String,
+    pub(crate) matches: Peekable<Matches>,
+}
+
+/// The logical sessions of a server's MongoDB clients, and the cursors
+/// they have open, shared by all its connections.
+///
+/// A cursor opened in a session belongs to it, and may be read from any
+/// connection in the session until the session ends, as drivers may send
+/// a `getMore` over another connection of their pool. One opened outside a
+/// session belongs to its connection, and is dropped with it. Either is
+/// dropped once left unread for the server's cursor timeout, and a session
+/// with its cursors once unused for the session timeout, so that clients
+/// that never close them cannot leak them.
+pub(crate) struct SessionTable {
+    cursor_timeout: Duration,
+    session_timeout: Duration,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    sessions: HashMap<SessionId, LogicalSession>,
+    cursors: HashMap<i64, Registered>,
+    next_cursor: i64,
+    next_connection: u64,
+}
+
+struct LogicalSession {
+    last_used: Instant,
+    cursors: HashSet<i64>,
+}
+
+/// An open cursor, and who may read it.
+struct Registered {
+    cursor: OpenCursor,
+    connection: u64,
+    session: Option<SessionId>,
+    last_used: Instant,
+}
+
+impl Registered {
+    /// Returns whether `connection`, in `session`, may read the cursor.
+    fn readable_by(&self, connection: u64, session: Option<&SessionId>) -> bool {
+        match &self.session {
+            Some(owner) => session == Some(owner),
+            None => self.connection == connection,
+        }
+    }
+}
+
+impl fmt::Debug for SessionTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.lock();
+        f.debug_struct("SessionTable")
+            .field("sessions", &state.sessions.len())
+            .field("cursors", &state.cursors.len())
+            .finish()
+    }
+}
+
+impl SessionTable {
+    /// Creates an empty table timing sessions and cursors out as `options`
+    /// set.
+    pub(crate) fn new(options: &ServerOptions) -> Self {
+        SessionTable {
+            cursor_timeout: options.cursor_timeout,
+            session_timeout: options.session_timeout,
+            state: Mutex::new(State {
+                next_cursor: 1,
+                ..State::default()
+            }),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns how long a session may go unused, in whole minutes, as
+    /// `hello` reports it.
+    pub(crate) fn timeout_minutes(&self) -> i32 {
+        (self.session_timeout.as_secs() / 60).clamp(1, i32::MAX as u64) as i32
+    }
+
+    /// Returns the id of a newly opened connection.
+    pub(crate) fn connect(&self) -> u64 {
+        let mut state = self.lock();
+        state.next_connection += 1;
+        state.next_connection
+    }
+
+    /// Drops the cursors `connection` opened outside a session, as it
+    /// closes.
+    pub(crate) fn disconnect(&self, connection: u64) {
+        let mut state = self.lock();
+        let owned: Vec<i64> = state
+            .cursors
+            .iter()
+            .filter(|(_, cursor)| cursor.session.is_none() && cursor.connection == connection)
+            .map(|(id, _)| *id)
+            .collect();
+        let dropped: Vec<Registered> = owned
+            .iter()
+            .filter_map(|id| state.cursors.remove(id))
+            .collect();
+        // Dropping a cursor ends its database cursor, which takes the
+        // database's lock
+        drop(state);
+        drop(dropped);
+    }
+
+    /// Starts the session `id`, or marks it used if it is going.
+    pub(crate) fn touch(&self, id: &SessionId) {
+        let mut state = self.lock();
+        let session = state
+            .sessions
+            .entry(id.clone())
+            .or_insert_with(|| LogicalSession {
+                last_used: Instant::now(),
+                cursors: HashSet::new(),
+            });
+        session.last_used = Instant::now();
+    }
+
+    /// Registers `cursor`, opened on `connection` in `session`, and returns
+    /// its id.
+    ///
+    /// # Errors
+    ///
+    /// Returns `TooManyOperations` if `connection` already has
+    /// `max_operations` cursors open.
+    pub(crate) fn open(
+        &self,
+        cursor: OpenCursor,
+        connection: u64,
+        session: Option<&SessionId>,
+        max_operations: usize,
+    ) -> Result<i64, ServerError> {
+        let mut state = self.lock();
+        let open = state
+            .cursors
+            .values()
+            .filter(|cursor| cursor.connection == connection)
+            .count();
+        if open >= max_operations {
+            return Err(ServerError::TooManyOperations(max_operations));
+        }
+        let id = state.next_cursor;
+        state.next_cursor += 1;
+        if let Some(session) = session {
+            if let Some(session) = state.sessions.get_mut(session) {
+                session.cursors.insert(id);
+            }
+        }
+        state.cursors.insert(
+            id,
+            Registered {
+                cursor,
+                connection,
+                session: session.cloned(),
+                last_used: Instant::now(),
+            },
+        );
+        Ok(id)
+    }
+
+    /// Takes the cursor `id` out of the table for `connection`, in
+    /// `session`, to read its next batch, after which it is put back with
+    /// `put_back` if it has more.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CursorNotFound` if there is no such cursor, it is not the
+    /// caller's, or it timed out.
+    pub(crate) fn take(
+        &self,
+        id: i64,
+        connection: u64,
+        session: Option<&SessionId>,
+    ) -> Result<OpenCursor, ServerError> {
+        let mut state = self.lock();
+        let not_found = || ServerError::from(DatabaseError::CursorNotFound(id as u64));
+        let registered = state.cursors.get(&id).ok_or_else(not_found)?;
+        if !registered.readable_by(connection, session) {
+            return Err(not_found());
+        }
+        let registered = state.cursors.remove(&id).ok_or_else(not_found)?;
+        if registered.last_used.elapsed() > self.cursor_timeout {
+            forget(&mut state, id, registered.session.as_ref());
+            drop(state);
+            drop(registered);
+            return Err(not_found());
+        }
+        Ok(registered.cursor)
+    }
+
+    /// Puts the cursor `id`, taken by `connection` in `session`, back in
+    /// the table, unless its session ended in the meantime.
+    pub(crate) fn put_back(
+        &self,
+        id: i64,
+        cursor: OpenCursor,
+        connection: u64,
+        session: Option<&SessionId>,
+    ) {
+        let mut state = self.lock();
+        if let Some(session) = session {
+            if !state.sessions.contains_key(session) {
+                drop(state);
+                drop(cursor);
+                return;
+            }
+        }
+        state.cursors.insert(
+            id,
+            Registered {
+                cursor,
+                connection,
+                session: session.cloned(),
+                last_used: Instant::now(),
+            },
+        );
+    }
+
+    /// Forgets the cursor `id`, taken in `session` and read to its end.
+    pub(crate) fn finish(&self, id: i64, session: Option<&SessionId>) {
+        forget(&mut self.lock(), id, session);
+    }
+
+    /// `{killCursors: <collection>, cursors: [<id>, ...]}`: closes each of
+    /// the cursors `connection`, in `session`, may read, and replies with
+    /// which were killed and which not found.
+    pub(crate) fn kill_cursors(
+        &self,
+        ids: &[i64],
+        connection: u64,
+        session: Option<&SessionId>,
+    ) -> Document {
+        let mut state = self.lock();
+        let mut killed = Vec::new();
+        let mut not_found = Vec::new();
+        let mut dropped = Vec::new();
+        for &id in ids {
+            let readable = state
+                .cursors
+                .get(&id)
+                .is_some_and(|cursor| cursor.readable_by(connection, session));
+            if !readable {
+                not_found.push(Value::Int64(id));
+                continue;
+            }
+            let cursor = state.cursors.remove(&id).unwrap();
+            forget(&mut state, id, cursor.session.as_ref());
+            dropped.push(cursor);
+            killed.push(Value::Int64(id));
+        }
+        drop(state);
+        drop(dropped);
+        let mut reply = Document::new();
+        reply.insert("cursorsKilled", Array::from_vec(killed));
+        reply.insert("cursorsNotFound", Array::from_vec(not_found));
+        reply.insert("cursorsAlive", Array::new());
+        reply.insert("cursorsUnknown", Array::new());
+        reply.insert("ok", 1.0);
+        reply
+    }
+
+    /// Ends the sessions `ids`, closing their cursors. Sessions not going
+    /// are ignored.
+    pub(crate) fn end_sessions(&self, ids: &[SessionId]) {
+        let mut state = self.lock();
+        let mut dropped = Vec::new();
+        for id in ids {
+            if let Some(session) = state.sessions.remove(id) {
+                dropped.extend(
+                    session
+                        .cursors
+                        .iter()
+                        .filter_map(|cursor| state.cursors.remove(cursor)),
+                );
+            }
+        }
+        drop(state);
+        drop(dropped);
+    }
+
+    /// Ends the sessions unused for longer than the session timeout, and
+    /// closes their cursors and any other left unread for longer than the
+    /// cursor timeout.
+    pub(crate) fn reap(&self) {
+        let mut state = self.lock();
+        let expired: Vec<SessionId> = state
+            .sessions
+            .iter()
+            .filter(|(_, session)| session.last_used.elapsed() > self.session_timeout)
+            .map(|(id, _)| id.clone())
+            .collect();
+        let cursor_timeout = self.cursor_timeout;
+        let idle: Vec<i64> = state
+            .cursors
+            .iter()
+            .filter(|(_, cursor)| {
+                cursor.last_used.elapsed() > cursor_timeout
+                    || cursor
+                        .session
+                        .as_ref()
+                        .is_some_and(|id| expired.contains(id))
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        let mut dropped = Vec::with_capacity(idle.len());
+        for id in idle {
+            let cursor = state.cursors.remove(&id).unwrap();
+            forget(&mut state, id, cursor.session.as_ref());
+            dropped.push(cursor);
+        }
+        for id in &expired {
+            state.sessions.remove(id);
+        }
+        drop(state);
+        drop(dropped);
+    }
+}
+
+/// Removes the cursor `id` from the cursors of `session`.
+fn forget(state: &mut State, id: i64, session: Option<&SessionId>) {
+    if let Some(session) = session.and_then(|session| state.sessions.get_mut(session)) {
+        session.cursors.remove(&id);
+    }
+}
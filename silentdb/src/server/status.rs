@@ -0,0 +1,167 @@
+// src/server/status.rs
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Instant;
+
+use silentdb_data_encoding::{Array, Document, Value};
+
+use super::audit::{redact, redacted_fields};
+use super::listener::ServerOptions;
+
+/// What a server is doing, shared by all its connections: the clients
+/// connected and the commands running, for `serverStatus` and
+/// `currentOp`.
+#[derive(Debug)]
+pub(crate) struct ServerStatus {
+    started: Instant,
+    connected: AtomicU64,
+    total_connected: AtomicU64,
+    refused: AtomicU64,
+    /// Fields whose values `currentOp` hides, as the audit log does.
+    redacted: Vec<String>,
+    ops: Mutex<Ops>,
+}
+
+#[derive(Debug, Default)]
+struct Ops {
+    next: u64,
+    running: BTreeMap<u64, RunningOp>,
+}
+
+/// A command being run, as `currentOp` lists it.
+#[derive(Debug)]
+struct RunningOp {
+    user: Option<String>,
+    client: Option<SocketAddr>,
+    command: String,
+    document: Document,
+    started: Instant,
+}
+
+/// A client counted as connected until dropped.
+pub(crate) struct Connected<'a>(&'a ServerStatus);
+
+impl Drop for Connected<'_> {
+    fn drop(&mut self) {
+        self.0.connected.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A command listed by `currentOp` until dropped.
+pub(crate) struct Running<'a> {
+    status: &'a ServerStatus,
+    id: u64,
+}
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        self.status.lock().running.remove(&self.id);
+    }
+}
+
+impl ServerStatus {
+    /// Creates the status of a server with `options`, started now.
+    pub(crate) fn new(options: &ServerOptions) -> Self {
+        ServerStatus {
+            started: Instant::now(),
+            connected: AtomicU64::new(0),
+            total_connected: AtomicU64::new(0),
+            refused: AtomicU64::new(0),
+            redacted: redacted_fields(options.audit.as_ref()),
+            ops: Mutex::new(Ops::default()),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Ops> {
+        self.ops.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Counts a client as connected until the guard returned is dropped.
+    pub(crate) fn connect(&self) -> Connected<'_> {
+        self.connected.fetch_add(1, Ordering::Relaxed);
+        self.total_connected.fetch_add(1, Ordering::Relaxed);
+        Connected(self)
+    }
+
+    /// Counts a client refused for want of a connection.
+    pub(crate) fn refuse(&self) {
+        self.refused.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Lists `command`, with `document`, as run by `user` from `client`,
+    /// until the guard returned is dropped.
+    pub(crate) fn begin(
+        &self,
+        user: Option<&str>,
+        client: Option<SocketAddr>,
+        command: &str,
+        document: &Document,
+    ) -> Running<'_> {
+        let mut ops = self.lock();
+        ops.next += 1;
+        let id = ops.next;
+        ops.running.insert(
+            id,
+            RunningOp {
+                user: user.map(str::to_string),
+                client,
+                command: command.to_string(),
+                document: redact(document, &self.redacted),
+                started: Instant::now(),
+            },
+        );
+        Running { status: self, id }
+    }
+
+    /// Returns how long the server has been up, and how many clients it
+    /// has: `current`ly connected, `available` connections left under
+    /// `max_connections`, connected in all, and refused.
+    pub(crate) fn to_document(&self, options: &ServerOptions) -> Document {
+        let uptime = self.started.elapsed();
+        let current = self.connected.load(Ordering::Relaxed);
+        let mut connections = Document::new();
+        connections.insert("current", current as i64);
+        connections.insert(
+            "available",
+            (options.max_connections as u64).saturating_sub(current) as i64,
+        );
+        connections.insert(
+            "totalCreated",
+            self.total_connected.load(Ordering::Relaxed) as i64,
+        );
+        connections.insert("refused", self.refused.load(Ordering::Relaxed) as i64);
+        let mut document = Document::new();
+        document.insert("uptime", uptime.as_secs() as i64);
+        document.insert("uptimeMillis", uptime.as_millis() as i64);
+        document.insert("connections", connections);
+        document
+    }
+
+    /// Returns the commands running, oldest first, each as `{opid, active,
+    /// op, user, client, command, secs_running, microsecs_running}` with
+    /// the command's sensitive fields redacted.
+    pub(crate) fn current_ops(&self) -> Array {
+        let ops = self.lock();
+        let running = ops.running.iter().map(|(id, op)| {
+            let elapsed = op.started.elapsed();
+            let mut entry = Document::new();
+            entry.insert("opid", *id as i64);
+            entry.insert("active", true);
+            entry.insert("op", op.command.as_str());
+            if let Some(user) = &op.user {
+                entry.insert("user", user.as_str());
+            }
+            if let Some(client) = op.client {
+                entry.insert("client", client.to_string());
+            }
+            entry.insert("command", op.document.clone());
+            entry.insert("secs_running", elapsed.as_secs() as i64);
+            entry.insert("microsecs_running", elapsed.as_micros() as i64);
+            Value::Document(entry)
+        });
+        Array::from_vec(running.collect())
+    }
+}
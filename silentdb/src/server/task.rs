@@ -0,0 +1,91 @@
+// src/server/task.rs
+
+use std::future::Future;
+use std::panic;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::watch;
+use tokio::task;
+
+use super::error::ServerError;
+
+/// Bytes read from a client's stream at a time.
+pub(crate) const READ_CHUNK: usize = 64 * 1024;
+
+/// A connection's view of whether the server is shutting down.
+///
+/// Connections check it only while waiting on their client, so a command
+/// already running finishes, and its reply is written if the client takes
+/// it, before the connection closes.
+#[derive(Debug, Clone)]
+pub(crate) struct Stopping(watch::Receiver<bool>);
+
+impl Stopping {
+    pub(crate) fn new(receiver: watch::Receiver<bool>) -> Self {
+        Stopping(receiver)
+    }
+
+    /// Runs `future` to completion, unless the server starts shutting down
+    /// first, in which case it is dropped and `None` returned.
+    pub(crate) async fn unless<F: Future>(&mut self, future: F) -> Option<F::Output> {
+        tokio::select! {
+            output = future => Some(output),
+            // The sender going away stops the server as well
+            _ = self.0.wait_for(|stopping| *stopping) => None,
+        }
+    }
+}
+
+/// Writes `bytes` to `stream`, returning `false` instead if the server
+/// starts shutting down before the client takes them.
+///
+/// A client that stops reading leaves the write waiting, which holds up
+/// nothing but its own connection's task.
+pub(crate) async fn send<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    stopping: &mut Stopping,
+    bytes: &[u8],
+) -> Result<bool, ServerError> {
+    let written = stopping
+        .unless(async {
+            stream.write_all(bytes).await?;
+            stream.flush().await
+        })
+        .await;
+    match written {
+        Some(result) => result.map(|()| true).map_err(ServerError::from),
+        None => Ok(false),
+    }
+}
+
+/// Closes the writing half of `stream`, then reads and drops whatever the
+/// client still sends until it closes its end too, so that the last reply
+/// is not cut off by a reset for data left unread.
+pub(crate) async fn linger<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    stopping: &mut Stopping,
+) {
+    if stream.shutdown().await.is_err() {
+        return;
+    }
+    let mut chunk = vec![0; 1024];
+    while let Some(Ok(read)) = stopping.unless(stream.read(&mut chunk)).await {
+        if read == 0 {
+            break;
+        }
+    }
+}
+
+/// Runs `f`, which may block on the database, on the runtime's blocking
+/// pool, and returns what it does. A panic in `f` carries on in the caller,
+/// as it would have on a thread of its own.
+pub(crate) async fn blocking<F, T>(f: F) -> T
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    match task::spawn_blocking(f).await {
+        Ok(value) => value,
+        Err(e) => panic::resume_unwind(e.into_panic()),
+    }
+}
@@ -0,0 +1,1132 @@
+// src/server/test.rs
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpStream;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use silentdb_data_encoding::{
+        from_bytes, from_json_str, from_json_value_str, to_bytes, Array, BufferPool, Document,
+        Framer, Value,
+    };
+
+    use crate::db::{Database, Role};
+    use crate::server::auth::Session;
+    use crate::server::listener::Peer;
+    use crate::server::retry::RetryTable;
+    use crate::server::session::SessionTable;
+    use crate::server::status::ServerStatus;
+    use crate::server::{AuditOptions, Protocol, Server, ServerError, ServerHandle, ServerOptions};
+    use crate::storage::crc32c;
+
+    /// Returns an empty scratch directory unique to this process and `name`.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("silentdb-server-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn doc(key: &str, value: impl Into<Value>) -> Document {
+        let mut document = Document::new();
+        document.insert(key, value);
+        document
+    }
+
+    /// A client connection reading replies off the stream as they come.
+    struct Client {
+        stream: TcpStream,
+        framer: Framer,
+    }
+
+    impl Client {
+        fn connect(server: &ServerHandle) -> Client {
+            Client {
+                stream: TcpStream::connect(server.local_addr()).unwrap(),
+                framer: Framer::new(),
+            }
+        }
+
+        fn send(&mut self, command: &Document) {
+            self.stream.write_all(&to_bytes(command).unwrap()).unwrap();
+        }
+
+        fn reply(&mut self) -> Document {
+            loop {
+                if let Some(frame) = self.framer.next_frame().unwrap() {
+                    return from_bytes(&frame).unwrap();
+                }
+                assert!(self.framer.read_from(&mut self.stream).unwrap() > 0);
+            }
+        }
+
+        fn run(&mut self, command: &Document) -> Document {
+            self.send(command);
+            self.reply()
+        }
+    }
+
+    fn start(name: &str, options: ServerOptions) -> ServerHandle {
+        let db = Database::open(scratch_dir(name)).unwrap();
+        Server::bind_with(db, "127.0.0.1:0", options)
+            .unwrap()
+            .spawn()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_server_commands() {
+        let server = start("commands", ServerOptions::new());
+        let mut client = Client::connect(&server);
+        let hello = client.run(&doc("hello", 1));
+        assert_eq!(
+            hello.get("server"),
+            Some(&Value::String("silentdb".to_string()))
+        );
+
+        let mut insert = doc("insert", "users");
+        let documents = (0..5)
+            .map(|i| {
+                let mut user = doc("_id", i);
+                user.insert("age", 20 + i);
+                Value::Document(user)
+            })
+            .collect();
+        insert.insert("documents", Array::from_vec(documents));
+        let reply = client.run(&insert);
+        assert_eq!(reply.get("ok"), Some(&Value::Double(1.0)));
+        assert_eq!(reply.get("n"), Some(&Value::Int64(5)));
+
+        // Matches are streamed in batches, the last marked as such
+        let mut find = doc("find", "users");
+        find.insert("filter", doc("age", doc("$gte", 21)));
+        find.insert("sort", doc("age", -1));
+        find.insert("batchSize", 3);
+        client.send(&find);
+        let first = client.reply();
+        let second = client.reply();
+        let ages = |reply: &Document| match reply.get("batch") {
+            Some(Value::Array(batch)) => batch
+                .iter()
+                .map(|user| match user {
+                    Value::Document(user) => user.get("age").cloned().unwrap(),
+                    _ => panic!("not a document"),
+                })
+                .collect::<Vec<_>>(),
+            _ => panic!("no batch in {:?}", reply),
+        };
+        assert_eq!(
+            ages(&first),
+            vec![Value::Int32(24), Value::Int32(23), Value::Int32(22)]
+        );
+        assert_eq!(first.get("more"), Some(&Value::Boolean(true)));
+        assert_eq!(ages(&second), vec![Value::Int32(21)]);
+        assert_eq!(second.get("more"), Some(&Value::Boolean(false)));
+
+        let mut update = doc("update", "users");
+        let mut change = doc("q", doc("age", doc("$lt", 22)));
+        change.insert("u", doc("$set", doc("young", true)));
+        change.insert("multi", true);
+        update.insert("updates", Array::from_vec(vec![Value::Document(change)]));
+        let reply = client.run(&update);
+        assert_eq!(reply.get("nModified"), Some(&Value::Int64(2)));
+
+        let mut delete = doc("delete", "users");
+        let mut filter = doc("q", doc("young", true));
+        filter.insert("limit", 1);
+        delete.insert("deletes", Array::from_vec(vec![Value::Document(filter)]));
+        assert_eq!(client.run(&delete).get("n"), Some(&Value::Int64(1)));
+        assert_eq!(
+            client.run(&doc("count", "users")).get("n"),
+            Some(&Value::Int64(4))
+        );
+
+        // Failed commands are replied to and leave the connection open
+        let reply = client.run(&doc("frobnicate", "users"));
+        assert_eq!(reply.get("ok"), Some(&Value::Double(0.0)));
+        assert_eq!(reply.get("code"), Some(&Value::Int32(59)));
+        let reply = client.run(&insert);
+        assert_eq!(reply.get("code"), Some(&Value::Int32(11000)));
+        let reply = client.run(&doc("count", 7));
+        assert_eq!(reply.get("code"), Some(&Value::Int32(9)));
+        assert_eq!(
+            client.run(&doc("count", "users")).get("n"),
+            Some(&Value::Int64(4))
+        );
+
+        server.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_server_rejects_large_requests() {
+        let server = start("large", ServerOptions::new().max_document_len(64));
+        let mut client = Client::connect(&server);
+        let reply = client.run(&doc("insert", "x".repeat(100)));
+        assert_eq!(reply.get("code"), Some(&Value::Int32(22)));
+        let mut rest = Vec::new();
+        std::io::Read::read_to_end(&mut client.stream, &mut rest).unwrap();
+        assert!(rest.is_empty());
+        server.shutdown().unwrap();
+    }
+
+    /// Sends an `OP_MSG` with `flags`, `body`, and a document sequence
+    /// `sequence` if given, adding the checksum its flag asks for.
+    fn send_msg(
+        stream: &mut TcpStream,
+        request_id: i32,
+        flags: u32,
+        body: &Document,
+        sequence: Option<(&str, &[Document])>,
+    ) {
+        let mut payload = flags.to_le_bytes().to_vec();
+        payload.push(0);
+        payload.extend_from_slice(&to_bytes(body).unwrap());
+        if let Some((name, documents)) = sequence {
+            let mut section = Vec::new();
+            section.extend_from_slice(name.as_bytes());
+            section.push(0);
+            for document in documents {
+                section.extend_from_slice(&to_bytes(document).unwrap());
+            }
+            payload.push(1);
+            payload.extend_from_slice(&(section.len() as i32 + 4).to_le_bytes());
+            payload.extend_from_slice(&section);
+        }
+        let checksum = flags & 1 != 0;
+        let len = 16 + payload.len() + if checksum { 4 } else { 0 };
+        let mut message = Vec::new();
+        message.extend_from_slice(&(len as i32).to_le_bytes());
+        message.extend_from_slice(&request_id.to_le_bytes());
+        message.extend_from_slice(&0i32.to_le_bytes());
+        message.extend_from_slice(&2013i32.to_le_bytes());
+        message.extend_from_slice(&payload);
+        if checksum {
+            let crc = crc32c(&message);
+            message.extend_from_slice(&crc.to_le_bytes());
+        }
+        stream.write_all(&message).unwrap();
+    }
+
+    /// Reads an `OP_MSG` reply to `request_id`, returning its body.
+    fn read_msg(stream: &mut TcpStream, request_id: i32) -> Document {
+        let mut header = [0; 16];
+        stream.read_exact(&mut header).unwrap();
+        let field = |i: usize| i32::from_le_bytes(header[i..i + 4].try_into().unwrap());
+        assert_eq!(field(8), request_id);
+        assert_eq!(field(12), 2013);
+        let mut body = vec![0; field(0) as usize - 16];
+        stream.read_exact(&mut body).unwrap();
+        assert_eq!(&body[..5], &[0, 0, 0, 0, 0]);
+        from_bytes(&body[5..]).unwrap()
+    }
+
+    #[test]
+    fn test_server_mongo_protocol() {
+        let server = start("mongo", ServerOptions::new().protocol(Protocol::MongoDb));
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+
+        let mut hello = doc("hello", 1);
+        hello.insert("$db", "admin");
+        send_msg(&mut stream, 1, 0, &hello, None);
+        let reply = read_msg(&mut stream, 1);
+        assert_eq!(reply.get("isWritablePrimary"), Some(&Value::Boolean(true)));
+        assert_eq!(reply.get("maxWireVersion"), Some(&Value::Int32(17)));
+
+        // Inserted documents arrive as a document sequence, checksummed
+        let mut insert = doc("insert", "users");
+        insert.insert("$db", "app");
+        let users = (0..5).map(|i| doc("_id", i)).collect::<Vec<_>>();
+        send_msg(&mut stream, 2, 1, &insert, Some(("documents", &users)));
+        assert_eq!(read_msg(&mut stream, 2).get("n"), Some(&Value::Int64(5)));
+
+        let mut find = doc("find", "users");
+        find.insert("$db", "app");
+        find.insert("sort", doc("_id", 1));
+        find.insert("batchSize", 2);
+        send_msg(&mut stream, 3, 0, &find, None);
+        let reply = read_msg(&mut stream, 3);
+        let cursor = |reply: &Document, batch: &str| match reply.get("cursor") {
+            Some(Value::Document(cursor)) => {
+                let ids = match cursor.get(batch) {
+                    Some(Value::Array(batch)) => batch.len(),
+                    _ => panic!("no {} in {:?}", batch, reply),
+                };
+                (cursor.get("id").cloned().unwrap(), ids)
+            }
+            _ => panic!("no cursor in {:?}", reply),
+        };
+        let (id, count) = cursor(&reply, "firstBatch");
+        assert_ne!(id, Value::Int64(0));
+        assert_eq!(count, 2);
+
+        let mut get_more = doc("getMore", id.clone());
+        get_more.insert("collection", "users");
+        get_more.insert("$db", "app");
+        send_msg(&mut stream, 4, 0, &get_more, None);
+        assert_eq!(
+            cursor(&read_msg(&mut stream, 4), "nextBatch"),
+            (Value::Int64(0), 3)
+        );
+
+        // The exhausted cursor is closed
+        send_msg(&mut stream, 5, 0, &get_more, None);
+        let reply = read_msg(&mut stream, 5);
+        assert_eq!(reply.get("code"), Some(&Value::Int32(43)));
+        assert_eq!(
+            reply.get("codeName"),
+            Some(&Value::String("CursorNotFound".to_string()))
+        );
+
+        server.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_server_sessions() {
+        let server = start(
+            "sessions",
+            ServerOptions::new()
+                .protocol(Protocol::MongoDb)
+                .cursor_timeout(Duration::from_millis(200)),
+        );
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        let mut insert = doc("insert", "users");
+        insert.insert("$db", "app");
+        let users = (0..5).map(|i| doc("_id", i)).collect::<Vec<_>>();
+        send_msg(&mut stream, 1, 0, &insert, Some(("documents", &users)));
+        assert_eq!(read_msg(&mut stream, 1).get("n"), Some(&Value::Int64(5)));
+
+        send_msg(&mut stream, 2, 0, &doc("startSession", 1), None);
+        let reply = read_msg(&mut stream, 2);
+        let Some(Value::Document(lsid)) = reply.get("id") else {
+            panic!("no session id in {:?}", reply);
+        };
+        let lsid = lsid.clone();
+        let cursor_id = |reply: &Document| match reply.get("cursor") {
+            Some(Value::Document(cursor)) => cursor.get("id").cloned().unwrap(),
+            _ => panic!("no cursor in {:?}", reply),
+        };
+        let find = |lsid: Option<&Document>| {
+            let mut find = doc("find", "users");
+            find.insert("$db", "app");
+            find.insert("batchSize", 1);
+            if let Some(lsid) = lsid {
+                find.insert("lsid", lsid.clone());
+            }
+            find
+        };
+        let get_more = |id: &Value, lsid: Option<&Document>| {
+            let mut get_more = doc("getMore", id.clone());
+            get_more.insert("collection", "users");
+            get_more.insert("$db", "app");
+            get_more.insert("batchSize", 1);
+            if let Some(lsid) = lsid {
+                get_more.insert("lsid", lsid.clone());
+            }
+            get_more
+        };
+
+        // A session's cursor outlives the connection that opened it, and
+        // is only readable in the session
+        send_msg(&mut stream, 3, 0, &find(Some(&lsid)), None);
+        let in_session = cursor_id(&read_msg(&mut stream, 3));
+        send_msg(&mut stream, 4, 0, &find(None), None);
+        let on_connection = cursor_id(&read_msg(&mut stream, 4));
+        drop(stream);
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        send_msg(&mut stream, 5, 0, &get_more(&in_session, None), None);
+        assert_eq!(
+            read_msg(&mut stream, 5).get("code"),
+            Some(&Value::Int32(43))
+        );
+        send_msg(&mut stream, 6, 0, &get_more(&in_session, Some(&lsid)), None);
+        assert_eq!(cursor_id(&read_msg(&mut stream, 6)), in_session);
+        send_msg(&mut stream, 7, 0, &get_more(&on_connection, None), None);
+        assert_eq!(
+            read_msg(&mut stream, 7).get("code"),
+            Some(&Value::Int32(43))
+        );
+
+        // Ending the session closes its cursors
+        let mut end = doc("endSessions", Array::from_vec(vec![lsid.clone().into()]));
+        end.insert("$db", "admin");
+        send_msg(&mut stream, 8, 0, &end, None);
+        assert_eq!(
+            read_msg(&mut stream, 8).get("ok"),
+            Some(&Value::Double(1.0))
+        );
+        send_msg(&mut stream, 9, 0, &get_more(&in_session, Some(&lsid)), None);
+        assert_eq!(
+            read_msg(&mut stream, 9).get("code"),
+            Some(&Value::Int32(43))
+        );
+
+        // Cursors can be killed, and time out left unread
+        send_msg(&mut stream, 10, 0, &find(None), None);
+        let killed = cursor_id(&read_msg(&mut stream, 10));
+        let mut kill = doc("killCursors", "users");
+        kill.insert("$db", "app");
+        kill.insert(
+            "cursors",
+            Array::from_vec(vec![killed.clone(), Value::Int64(12345)]),
+        );
+        send_msg(&mut stream, 11, 0, &kill, None);
+        let reply = read_msg(&mut stream, 11);
+        assert_eq!(
+            reply.get("cursorsKilled"),
+            Some(&Value::Array(Array::from_vec(vec![killed])))
+        );
+        assert_eq!(
+            reply.get("cursorsNotFound"),
+            Some(&Value::Array(Array::from_vec(vec![Value::Int64(12345)])))
+        );
+        send_msg(&mut stream, 12, 0, &find(None), None);
+        let idle = cursor_id(&read_msg(&mut stream, 12));
+        std::thread::sleep(Duration::from_millis(400));
+        send_msg(&mut stream, 13, 0, &get_more(&idle, None), None);
+        assert_eq!(
+            read_msg(&mut stream, 13).get("code"),
+            Some(&Value::Int32(43))
+        );
+
+        server.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_server_authentication() {
+        let server = start("authentication", ServerOptions::new().authentication(true));
+        let mut client = Client::connect(&server);
+        assert_eq!(
+            client.run(&doc("hello", 1)).get("ok"),
+            Some(&Value::Double(1.0))
+        );
+        let reply = client.run(&doc("find", "users"));
+        assert_eq!(reply.get("code"), Some(&Value::Int32(13)));
+
+        // Anyone may create the first user, but only a user admin the next
+        let mut create = doc("createUser", "admin");
+        create.insert("pwd", "secret");
+        create.insert("roles", Array::from_vec(vec![Value::from("root")]));
+        assert_eq!(client.run(&create).get("ok"), Some(&Value::Double(1.0)));
+        create.insert("createUser", "other");
+        assert_eq!(client.run(&create).get("code"), Some(&Value::Int32(13)));
+
+        let mut sasl_start = doc("saslStart", 1);
+        sasl_start.insert("mechanism", "SCRAM-SHA-256");
+        sasl_start.insert("payload", Value::Binary(b"n,,n=admin,r=abc".to_vec()));
+        let reply = client.run(&sasl_start);
+        let Some(Value::Binary(payload)) = reply.get("payload") else {
+            panic!("no payload in {:?}", reply);
+        };
+        let server_first = String::from_utf8(payload.clone()).unwrap();
+        assert!(server_first.starts_with("r=abc"));
+        assert!(server_first.contains(",i=15000"));
+        let mut proof = doc("saslContinue", 1);
+        proof.insert("conversationId", 1);
+        let nonce = server_first.split(',').next().unwrap();
+        let client_final = format!(
+            "c=biws,{},p=AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=",
+            nonce
+        );
+        proof.insert("payload", Value::Binary(client_final.into_bytes()));
+        assert_eq!(client.run(&proof).get("code"), Some(&Value::Int32(18)));
+        let reply = client.run(&doc("find", "users"));
+        assert_eq!(reply.get("code"), Some(&Value::Int32(13)));
+        server.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_server_first_user_from_loopback_only() {
+        let db = Database::open(scratch_dir("first-user")).unwrap();
+        let options = ServerOptions::new().authentication(true);
+        let session = |addr: &str| {
+            Session::new(Peer {
+                addr: Some(addr.parse().unwrap()),
+                audit: None,
+                sessions: Arc::new(SessionTable::new(&options)),
+                status: Arc::new(ServerStatus::new(&options)),
+                retries: Arc::new(RetryTable::new(&options)),
+                buffers: BufferPool::default(),
+            })
+        };
+
+        // Only clients on this machine may create the first user
+        let remote = session("203.0.113.7:50000").authorize(&db, &options, "createUser");
+        assert!(matches!(remote, Err(ServerError::Unauthorized(_))));
+        for addr in ["127.0.0.1:50000", "[::1]:50000", "[::ffff:127.0.0.1]:50000"] {
+            assert!(session(addr).authorize(&db, &options, "createUser").is_ok());
+        }
+    }
+
+    /// Sends an HTTP request on `stream` and returns the status of the
+    /// response and its body, read by its length or to its last chunk.
+    fn http(stream: &mut BufReader<TcpStream>, request: &str, body: &str) -> (u16, String) {
+        let request = format!(
+            "{}\r\nContent-Length: {}\r\n\r\n{}",
+            request,
+            body.len(),
+            body
+        );
+        stream.get_mut().write_all(request.as_bytes()).unwrap();
+        let mut line = String::new();
+        stream.read_line(&mut line).unwrap();
+        let status = line.split(' ').nth(1).unwrap().parse().unwrap();
+        let (mut len, mut chunked) = (0, false);
+        loop {
+            line.clear();
+            stream.read_line(&mut line).unwrap();
+            match line.trim_end().split_once(": ") {
+                Some(("Content-Length", value)) => len = value.parse().unwrap(),
+                Some(("Transfer-Encoding", "chunked")) => chunked = true,
+                Some(_) => {}
+                None => break,
+            }
+        }
+        let mut body = Vec::new();
+        loop {
+            if chunked {
+                line.clear();
+                stream.read_line(&mut line).unwrap();
+                len = usize::from_str_radix(line.trim_end(), 16).unwrap();
+            }
+            let mut chunk = vec![0; len];
+            stream.read_exact(&mut chunk).unwrap();
+            body.extend(chunk);
+            if !chunked {
+                break;
+            }
+            stream.read_line(&mut String::new()).unwrap();
+            if len == 0 {
+                break;
+            }
+        }
+        (status, String::from_utf8(body).unwrap())
+    }
+
+    #[test]
+    fn test_server_http() {
+        let server = start("http", ServerOptions::new().protocol(Protocol::Http));
+        let mut stream = BufReader::new(TcpStream::connect(server.local_addr()).unwrap());
+
+        let users = r#"[{"_id": 1, "name": "alice", "age": 30},
+                        {"_id": 2, "name": "bob", "age": 25},
+                        {"_id": 3, "name": "carol", "age": 35}]"#;
+        let (status, body) = http(&mut stream, "POST /db/users HTTP/1.1", users);
+        assert_eq!(status, 201);
+        assert_eq!(
+            from_json_str(&body).unwrap().get("n"),
+            Some(&Value::Int32(3))
+        );
+        let (status, body) = http(&mut stream, "POST /db/users HTTP/1.1", r#"{"_id": 1}"#);
+        assert_eq!(status, 409);
+        assert_eq!(
+            from_json_str(&body).unwrap().get("code"),
+            Some(&Value::Int32(11000))
+        );
+
+        // Query parameters filter on fields, or hold an operator or a
+        // whole filter in Extended JSON
+        let (status, body) = http(
+            &mut stream,
+            "PATCH /db/users?name=bob HTTP/1.1",
+            r#"{"$set": {"age": 26}}"#,
+        );
+        assert_eq!(status, 200);
+        assert_eq!(
+            from_json_str(&body).unwrap().get("nModified"),
+            Some(&Value::Int32(1))
+        );
+        let (status, _) = http(&mut stream, "PATCH /db/users HTTP/1.1", r#"{"age": 1}"#);
+        assert_eq!(status, 400);
+        let (status, _) = http(
+            &mut stream,
+            "DELETE /db/users?filter=%7B%22age%22%3A%7B%22%24gt%22%3A30%7D%7D HTTP/1.1",
+            "",
+        );
+        assert_eq!(status, 200);
+
+        // Finds stream their matches as a chunked array
+        let (status, body) = http(
+            &mut stream,
+            "GET /db/users?age=%7B%22%24lt%22%3A100%7D&sort=%7B%22_id%22%3A1%7D&batchSize=1 HTTP/1.1",
+            "",
+        );
+        assert_eq!(status, 200);
+        let Value::Array(found) = from_json_value_str(&body).unwrap() else {
+            panic!("not an array: {}", body);
+        };
+        let names: Vec<_> = found
+            .iter()
+            .map(|user| match user {
+                Value::Document(user) => (user.get("name").cloned(), user.get("age").cloned()),
+                _ => panic!("not a document"),
+            })
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                (Some(Value::from("alice")), Some(Value::Int32(30))),
+                (Some(Value::from("bob")), Some(Value::Int32(26))),
+            ]
+        );
+
+        // SQL statements run as finds
+        let (status, body) = http(
+            &mut stream,
+            "POST /sql HTTP/1.1",
+            "SELECT name FROM users WHERE age < 30 OR name LIKE 'a%' ORDER BY name DESC",
+        );
+        assert_eq!(status, 200);
+        assert_eq!(body, r#"[{"name":"bob"},{"name":"alice"}]"#);
+        let (status, body) = http(&mut stream, "POST /sql HTTP/1.1", "SELECT * FROM");
+        assert_eq!(status, 400);
+        assert_eq!(
+            from_json_str(&body).unwrap().get("code"),
+            Some(&Value::Int32(9))
+        );
+        assert_eq!(http(&mut stream, "GET /sql HTTP/1.1", "").0, 405);
+
+        assert_eq!(http(&mut stream, "GET /other HTTP/1.1", "").0, 404);
+        assert_eq!(http(&mut stream, "PUT /db/users HTTP/1.1", "{}").0, 405);
+        server.shutdown().unwrap();
+
+        // With authentication, requests carry a user's credentials
+        let db = Database::open(scratch_dir("http-authentication")).unwrap();
+        db.create_user("alice", "secret", &[Role::Read]).unwrap();
+        let options = ServerOptions::new()
+            .protocol(Protocol::Http)
+            .authentication(true);
+        let server = Server::bind_with(db, "127.0.0.1:0", options)
+            .unwrap()
+            .spawn()
+            .unwrap();
+        let mut stream = BufReader::new(TcpStream::connect(server.local_addr()).unwrap());
+        assert_eq!(http(&mut stream, "GET /db/users HTTP/1.1", "").0, 401);
+        // alice:wrong
+        let wrong = "GET /db/users HTTP/1.1\r\nAuthorization: Basic YWxpY2U6d3Jvbmc=";
+        assert_eq!(http(&mut stream, wrong, "").0, 401);
+        // alice:secret
+        let right = "GET /db/users HTTP/1.1\r\nAuthorization: Basic YWxpY2U6c2VjcmV0";
+        assert_eq!(http(&mut stream, right, ""), (200, "[]".to_string()));
+        let insert = "POST /db/users HTTP/1.1\r\nAuthorization: Basic YWxpY2U6c2VjcmV0";
+        assert_eq!(http(&mut stream, insert, "{}").0, 403);
+        server.shutdown().unwrap();
+    }
+
+    /// Opens a WebSocket to `target` on the HTTP gateway `server`, with the
+    /// key of RFC 6455's example handshake.
+    fn websocket(server: &ServerHandle, target: &str) -> BufReader<TcpStream> {
+        let mut stream = BufReader::new(TcpStream::connect(server.local_addr()).unwrap());
+        let request = format!(
+            "GET {} HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            target
+        );
+        stream.get_mut().write_all(request.as_bytes()).unwrap();
+        let mut head = String::new();
+        while !head.ends_with("\r\n\r\n") {
+            stream.read_line(&mut head).unwrap();
+        }
+        assert!(head.starts_with("HTTP/1.1 101 "), "{}", head);
+        assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+        stream
+    }
+
+    /// Reads the next frame the server sends, returning its opcode and
+    /// payload.
+    fn frame(stream: &mut BufReader<TcpStream>) -> (u8, Vec<u8>) {
+        let mut head = [0; 2];
+        stream.read_exact(&mut head).unwrap();
+        let len = match head[1] {
+            126 => {
+                let mut len = [0; 2];
+                stream.read_exact(&mut len).unwrap();
+                u16::from_be_bytes(len) as usize
+            }
+            len => len as usize,
+        };
+        let mut payload = vec![0; len];
+        stream.read_exact(&mut payload).unwrap();
+        (head[0] & 0x0f, payload)
+    }
+
+    fn change_event(stream: &mut BufReader<TcpStream>) -> Document {
+        let (opcode, payload) = frame(stream);
+        assert_eq!(opcode, 0x1);
+        from_json_str(std::str::from_utf8(&payload).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_server_websocket_watch() {
+        let server = start("websocket", ServerOptions::new().protocol(Protocol::Http));
+        let mut watch = websocket(&server, "/watch/users?operationType=insert");
+        let mut stream = BufReader::new(TcpStream::connect(server.local_addr()).unwrap());
+        assert_eq!(
+            http(&mut stream, "POST /db/users HTTP/1.1", r#"{"_id": 1}"#).0,
+            201
+        );
+        let delete = "DELETE /db/users?_id=1 HTTP/1.1";
+        assert_eq!(http(&mut stream, delete, "").0, 200);
+        assert_eq!(
+            http(&mut stream, "POST /db/users HTTP/1.1", r#"{"_id": 2}"#).0,
+            201
+        );
+
+        // Only the inserts match, each with its resume token
+        let first = change_event(&mut watch);
+        assert_eq!(
+            first.get("operationType"),
+            Some(&Value::String("insert".to_string()))
+        );
+        assert_eq!(first.get("documentKey"), Some(&Value::from(doc("_id", 1))));
+        let Some(Value::Document(id)) = first.get("_id") else {
+            panic!("no resume token in {:?}", first);
+        };
+        let Some(Value::String(token)) = id.get("_data") else {
+            panic!("no resume token in {:?}", first);
+        };
+        let second = change_event(&mut watch);
+        assert_eq!(second.get("documentKey"), Some(&Value::from(doc("_id", 2))));
+
+        // A ping is answered, and a close, masked as clients must
+        watch
+            .get_mut()
+            .write_all(&[0x89, 0x82, 1, 2, 3, 4, b'h' ^ 1, b'i' ^ 2])
+            .unwrap();
+        assert_eq!(frame(&mut watch), (0xa, b"hi".to_vec()));
+        watch
+            .get_mut()
+            .write_all(&[0x88, 0x82, 0, 0, 0, 0, 0x03, 0xe8])
+            .unwrap();
+        assert_eq!(frame(&mut watch), (0x8, 1000u16.to_be_bytes().to_vec()));
+
+        // Resuming after the first insert picks up at the second
+        let target = format!("/watch/users?operationType=insert&resumeAfter={}", token);
+        let mut resumed = websocket(&server, &target);
+        let event = change_event(&mut resumed);
+        assert_eq!(event.get("documentKey"), Some(&Value::from(doc("_id", 2))));
+
+        // A watch must upgrade, and resume from a real token
+        assert_eq!(http(&mut stream, "GET /watch/users HTTP/1.1", "").0, 426);
+        let bad = "GET /watch/users?resumeAfter=00 HTTP/1.1\r\nUpgrade: websocket\r\n\
+                   Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13";
+        assert_eq!(http(&mut stream, bad, "").0, 400);
+        server.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_server_metrics() {
+        let server = start("metrics", ServerOptions::new());
+        let mut client = Client::connect(&server);
+        let mut insert = doc("insert", "users");
+        insert.insert("documents", Array::from_vec(vec![doc("_id", 1).into()]));
+        assert_eq!(client.run(&insert).get("n"), Some(&Value::Int64(1)));
+        let reply = client.run(&doc("metrics", 1));
+        let Some(Value::Document(metrics)) = reply.get("metrics") else {
+            panic!("no metrics in {:?}", reply);
+        };
+        let Some(Value::Document(ops)) = metrics.get("ops") else {
+            panic!("no ops in {:?}", metrics);
+        };
+        assert_eq!(
+            ops.get("insert")
+                .and_then(Value::as_document)
+                .unwrap()
+                .get("count"),
+            Some(&Value::Int64(1))
+        );
+        server.shutdown().unwrap();
+
+        // The HTTP listener serves them for Prometheus
+        let server = start(
+            "metrics-http",
+            ServerOptions::new().protocol(Protocol::Http),
+        );
+        let mut stream = BufReader::new(TcpStream::connect(server.local_addr()).unwrap());
+        assert_eq!(http(&mut stream, "POST /db/users HTTP/1.1", "{}").0, 201);
+        let (status, body) = http(&mut stream, "GET /metrics HTTP/1.1", "");
+        assert_eq!(status, 200);
+        assert!(body.contains("silentdb_op_duration_seconds_count{op=\"insert\"} 1\n"));
+        assert_eq!(http(&mut stream, "POST /metrics HTTP/1.1", "").0, 405);
+        server.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_server_admin_commands() {
+        let db = Database::open(scratch_dir("admin")).unwrap();
+        db.create_user("alice", "secret", &[Role::Read]).unwrap();
+        db.collection("users").create_index("name").unwrap();
+        let options = ServerOptions::new().authentication(true);
+        let server = Server::bind_with(db, "127.0.0.1:0", options)
+            .unwrap()
+            .spawn()
+            .unwrap();
+        let mut client = Client::connect(&server);
+
+        // Health checks need no user, the rest do
+        assert_eq!(
+            client.run(&doc("ping", 1)).get("ok"),
+            Some(&Value::Double(1.0))
+        );
+        let build = client.run(&doc("buildInfo", 1));
+        assert_eq!(
+            build.get("version"),
+            Some(&Value::String(env!("CARGO_PKG_VERSION").to_string()))
+        );
+        let reply = client.run(&doc("serverStatus", 1));
+        assert_eq!(reply.get("code"), Some(&Value::Int32(13)));
+        server.shutdown().unwrap();
+
+        let server = start("admin-open", ServerOptions::new());
+        let mut client = Client::connect(&server);
+        let mut other = Client::connect(&server);
+        assert_eq!(
+            other.run(&doc("ping", 1)).get("ok"),
+            Some(&Value::Double(1.0))
+        );
+        let mut insert = doc("insert", "users");
+        insert.insert("documents", Array::from_vec(vec![doc("_id", 1).into()]));
+        assert_eq!(client.run(&insert).get("n"), Some(&Value::Int64(1)));
+
+        let status = client.run(&doc("serverStatus", 1));
+        let Some(Value::Document(connections)) = status.get("connections") else {
+            panic!("no connections in {:?}", status);
+        };
+        assert_eq!(connections.get("current"), Some(&Value::Int64(2)));
+        assert!(matches!(status.get("metrics"), Some(Value::Document(_))));
+        // Earlier commands' frames and replies were read and written in
+        // buffers later ones reused
+        let Some(Value::Document(buffers)) = status.get("bufferPool") else {
+            panic!("no bufferPool in {:?}", status);
+        };
+        assert!(matches!(buffers.get("hits"), Some(Value::Int64(hits)) if *hits > 0));
+
+        let mut list = doc("listCollections", 1);
+        list.insert("filter", doc("name", "users"));
+        let reply = client.run(&list);
+        let Some(Value::Document(cursor)) = reply.get("cursor") else {
+            panic!("no cursor in {:?}", reply);
+        };
+        let Some(Value::Array(batch)) = cursor.get("firstBatch") else {
+            panic!("no batch in {:?}", cursor);
+        };
+        assert_eq!(batch.len(), 1);
+
+        let stats = client.run(&doc("collStats", "users"));
+        assert_eq!(stats.get("count"), Some(&Value::Int64(1)));
+        assert_eq!(stats.get("nindexes"), Some(&Value::Int32(0)));
+
+        // currentOp lists itself, the only command running
+        let reply = client.run(&doc("currentOp", 1));
+        let Some(Value::Array(running)) = reply.get("inprog") else {
+            panic!("no inprog in {:?}", reply);
+        };
+        assert_eq!(running.len(), 1);
+        let Some(Value::Document(op)) = running.iter().next() else {
+            panic!("not an op: {:?}", running);
+        };
+        assert_eq!(op.get("op"), Some(&Value::String("currentOp".to_string())));
+        server.shutdown().unwrap();
+
+        // The HTTP gateway serves them under /admin
+        let server = start("admin-http", ServerOptions::new().protocol(Protocol::Http));
+        let mut stream = BufReader::new(TcpStream::connect(server.local_addr()).unwrap());
+        assert_eq!(http(&mut stream, "POST /db/users HTTP/1.1", "{}").0, 201);
+        let (status, body) = http(&mut stream, "GET /admin/ping HTTP/1.1", "");
+        assert_eq!(status, 200);
+        assert_eq!(
+            from_json_str(&body).unwrap().get("ok"),
+            Some(&Value::Double(1.0))
+        );
+        let (status, body) = http(&mut stream, "GET /admin/collStats/users HTTP/1.1", "");
+        assert_eq!(status, 200);
+        assert_eq!(
+            from_json_str(&body).unwrap().get("count"),
+            Some(&Value::Int32(1))
+        );
+        let list = "GET /admin/listCollections?name=users HTTP/1.1";
+        assert_eq!(http(&mut stream, list, "").0, 200);
+        assert_eq!(http(&mut stream, "GET /admin/dropUser HTTP/1.1", "").0, 404);
+        assert_eq!(http(&mut stream, "POST /admin/ping HTTP/1.1", "").0, 405);
+        server.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_server_resource_limits() {
+        let server = start("limits", ServerOptions::new().max_connections(1));
+        let mut admitted = Client::connect(&server);
+        assert_eq!(
+            admitted.run(&doc("hello", 1)).get("ok"),
+            Some(&Value::Double(1.0))
+        );
+        let mut refused = Client::connect(&server);
+        assert_eq!(refused.reply().get("code"), Some(&Value::Int32(1)));
+        assert_eq!(refused.stream.read(&mut [0; 1]).unwrap_or(0), 0);
+        server.shutdown().unwrap();
+
+        // A MongoDB client may only hold so many cursors open, and an
+        // oversized document is refused without dropping the connection
+        let server = start(
+            "limits-mongo",
+            ServerOptions::new()
+                .protocol(Protocol::MongoDb)
+                .max_operations_per_connection(1)
+                .max_document_len(256),
+        );
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        let mut insert = doc("insert", "users");
+        insert.insert("$db", "app");
+        let users = (0..5).map(|i| doc("_id", i)).collect::<Vec<_>>();
+        send_msg(&mut stream, 1, 0, &insert, Some(("documents", &users)));
+        assert_eq!(read_msg(&mut stream, 1).get("n"), Some(&Value::Int64(5)));
+        let mut find = doc("find", "users");
+        find.insert("$db", "app");
+        find.insert("batchSize", 2);
+        send_msg(&mut stream, 2, 0, &find, None);
+        assert!(read_msg(&mut stream, 2).get("cursor").is_some());
+        send_msg(&mut stream, 3, 0, &find, None);
+        assert_eq!(read_msg(&mut stream, 3).get("code"), Some(&Value::Int32(1)));
+        let large = vec![doc("name", "x".repeat(300))];
+        send_msg(&mut stream, 4, 0, &insert, Some(("documents", &large)));
+        assert_eq!(
+            read_msg(&mut stream, 4).get("code"),
+            Some(&Value::Int32(22))
+        );
+
+        let mut hello = doc("hello", 1);
+        hello.insert("$db", "admin");
+        send_msg(&mut stream, 5, 0, &hello, None);
+        assert_eq!(
+            read_msg(&mut stream, 5).get("ok"),
+            Some(&Value::Double(1.0))
+        );
+        server.shutdown().unwrap();
+
+        // A find or count out of the server's time fails with
+        // MaxTimeMSExpired
+        let server = start(
+            "limits-time",
+            ServerOptions::new().max_time(Duration::from_nanos(1)),
+        );
+        let mut client = Client::connect(&server);
+        let mut insert = doc("insert", "users");
+        insert.insert("documents", Array::from_vec(vec![doc("_id", 1).into()]));
+        client.run(&insert);
+        let reply = client.run(&doc("find", "users"));
+        assert_eq!(reply.get("code"), Some(&Value::Int32(50)));
+        let reply = client.run(&doc("count", "users"));
+        assert_eq!(reply.get("code"), Some(&Value::Int32(50)));
+        server.shutdown().unwrap();
+
+        // Writes refuse a time limit they cannot keep to
+        let server = start("limits-write-time", ServerOptions::new());
+        let mut client = Client::connect(&server);
+        let mut count = doc("count", "users");
+        count.insert("maxTimeMS", 60_000);
+        assert_eq!(client.run(&count).get("n"), Some(&Value::Int64(0)));
+        let mut delete = doc("delete", "users");
+        delete.insert(
+            "deletes",
+            Array::from_vec(vec![doc("q", doc("_id", 1)).into()]),
+        );
+        delete.insert("maxTimeMS", 60_000);
+        assert_eq!(client.run(&delete).get("code"), Some(&Value::Int32(9)));
+        server.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_server_audit() {
+        let db = Database::open(scratch_dir("audit")).unwrap();
+        let options = ServerOptions::new().audit(AuditOptions::to_collection("audit"));
+        let server = Server::bind_with(db.clone(), "127.0.0.1:0", options)
+            .unwrap()
+            .spawn()
+            .unwrap();
+        let mut client = Client::connect(&server);
+        let mut insert = doc("insert", "users");
+        insert.insert("documents", Array::from_vec(vec![doc("_id", 1).into()]));
+        assert_eq!(client.run(&insert).get("n"), Some(&Value::Int64(1)));
+        let mut create_user = doc("createUser", "alice");
+        create_user.insert("pwd", "secret");
+        create_user.insert("roles", Array::from_vec(vec!["read".into()]));
+        client.run(&create_user);
+        // Reads are not recorded, and the log cannot be written to
+        client.run(&doc("find", "users"));
+        let mut forge = doc("insert", "audit");
+        forge.insert("documents", Array::from_vec(vec![doc("_id", 1).into()]));
+        assert_eq!(client.run(&forge).get("code"), Some(&Value::Int32(13)));
+        server.shutdown().unwrap();
+
+        let records = db
+            .collection("audit")
+            .find(&Document::new())
+            .unwrap()
+            .try_collect()
+            .unwrap();
+        let commands: Vec<Value> = records
+            .iter()
+            .map(|record| record.get("command").cloned().unwrap())
+            .collect();
+        assert_eq!(
+            commands,
+            vec!["insert".into(), "createUser".into(), "insert".into()]
+        );
+        assert!(matches!(records[0].get("client"), Some(Value::String(_))));
+        assert_eq!(records[0].get("ok"), Some(&Value::Boolean(true)));
+        let Some(Value::Document(document)) = records[1].get("document") else {
+            panic!("no document in {:?}", records[1]);
+        };
+        assert_eq!(
+            document.get("pwd"),
+            Some(&Value::String("<redacted>".to_string()))
+        );
+        assert_eq!(records[2].get("ok"), Some(&Value::Boolean(false)));
+
+        // A file log holds a line of JSON for each record
+        let path = scratch_dir("audit-file").with_extension("jsonl");
+        let _ = fs::remove_file(&path);
+        let options = ServerOptions::new().audit(AuditOptions::to_file(&path).redact("_id"));
+        let server = start("audit-file", options);
+        let mut client = Client::connect(&server);
+        client.run(&insert);
+        server.shutdown().unwrap();
+        let log = fs::read_to_string(&path).unwrap();
+        let records: Vec<Document> = log
+            .lines()
+            .map(|line| from_json_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 1);
+        assert!(log.contains("\"_id\":\"<redacted>\""), "{}", log);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_server_shutdown() {
+        let db = Database::open(scratch_dir("shutdown")).unwrap();
+        let documents: Vec<Document> = (0..400)
+            .map(|i| {
+                let mut document = doc("_id", i);
+                document.insert("padding", "x".repeat(64 * 1024));
+                document
+            })
+            .collect();
+        db.collection("big").insert_many(documents).unwrap();
+        let server = Server::bind_with(db, "127.0.0.1:0", ServerOptions::new())
+            .unwrap()
+            .spawn()
+            .unwrap();
+
+        // One client sits idle, and the other stops reading partway through
+        // a find far larger than the socket buffers, which leaves its
+        // connection waiting on the write rather than reading on ahead
+        let mut idle = Client::connect(&server);
+        assert_eq!(
+            idle.run(&doc("hello", 1)).get("ok"),
+            Some(&Value::Double(1.0))
+        );
+        let mut stalled = Client::connect(&server);
+        let mut find = doc("find", "big");
+        find.insert("batchSize", 1);
+        stalled.send(&find);
+        assert!(stalled.reply().get("batch").is_some());
+        server.shutdown().unwrap();
+
+        let mut received = 1;
+        loop {
+            match stalled.framer.next_frame().unwrap() {
+                Some(_) => received += 1,
+                None => match stalled.framer.read_from(&mut stalled.stream) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => {}
+                },
+            }
+        }
+        assert!(received < 400, "{} batches were buffered", received);
+        assert_eq!(idle.stream.read(&mut [0; 1]).unwrap_or(0), 0);
+    }
+
+    #[test]
+    fn test_server_retried_writes() {
+        let server = start("retried-writes", ServerOptions::new());
+        let mut client = Client::connect(&server);
+        let mut update = doc("update", "counters");
+        let mut statement = doc("q", doc("_id", 1));
+        statement.insert("u", doc("$inc", doc("n", 1)));
+        statement.insert("upsert", true);
+        update.insert("updates", Array::from_vec(vec![statement.into()]));
+        update.insert("opId", Value::Binary(vec![7; 16]));
+
+        // A retry, on another connection, is answered without applying it
+        let first = client.run(&update);
+        assert!(first.get("upserted").is_some(), "not upserted: {:?}", first);
+        let mut retry = Client::connect(&server);
+        assert_eq!(retry.run(&update), first);
+        let mut count = doc("count", "counters");
+        count.insert("query", doc("n", 1));
+        assert_eq!(client.run(&count).get("n"), Some(&Value::Int64(1)));
+
+        // Another id applies it again
+        update.insert("opId", Value::Binary(vec![8; 16]));
+        assert_eq!(client.run(&update).get("ok"), Some(&Value::Double(1.0)));
+        count.insert("query", doc("n", 2));
+        assert_eq!(client.run(&count).get("n"), Some(&Value::Int64(1)));
+
+        update.insert("opId", "not binary");
+        assert_eq!(client.run(&update).get("code"), Some(&Value::Int32(9)));
+        server.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_server_retried_writes_failed_part_way() {
+        let db = Database::open(scratch_dir("retried-part-way")).unwrap();
+        let counters = db.collection("counters");
+        counters.insert_one(doc("_id", 1)).unwrap();
+        counters.insert_one(doc("_id", 2)).unwrap();
+        let server = Server::bind_with(db.clone(), "127.0.0.1:0", ServerOptions::new())
+            .unwrap()
+            .spawn()
+            .unwrap();
+        let mut client = Client::connect(&server);
+        let statements = (1..=2)
+            .map(|id| {
+                let mut statement = doc("q", doc("_id", id));
+                statement.insert("u", doc("$inc", doc("n", 1)));
+                statement.into()
+            })
+            .collect();
+        let mut update = doc("update", "counters");
+        update.insert("updates", Array::from_vec(statements));
+        update.insert("opId", Value::Binary(vec![9; 16]));
+
+        // The second statement conflicts with an open transaction after the
+        // first ran, and a retry must not run the first again
+        let txn = db.begin();
+        txn.collection("counters")
+            .update_one(&doc("_id", 2), &doc("$set", doc("n", 0)))
+            .unwrap();
+        let failed = client.run(&update);
+        assert_eq!(failed.get("code"), Some(&Value::Int32(112)));
+        txn.abort();
+        let mut retry = Client::connect(&server);
+        assert_eq!(retry.run(&update), failed);
+        let mut count = doc("count", "counters");
+        count.insert("query", doc("n", 1));
+        assert_eq!(client.run(&count).get("n"), Some(&Value::Int64(1)));
+
+        // A write that failed before anything ran is run again
+        let txn = db.begin();
+        txn.collection("counters")
+            .update_one(&doc("_id", 1), &doc("$set", doc("n", 0)))
+            .unwrap();
+        update.insert("opId", Value::Binary(vec![10; 16]));
+        assert_eq!(client.run(&update).get("code"), Some(&Value::Int32(112)));
+        txn.abort();
+        assert_eq!(client.run(&update).get("n"), Some(&Value::Int64(2)));
+        count.insert("query", doc("n", 2));
+        assert_eq!(client.run(&count).get("n"), Some(&Value::Int64(1)));
+        server.shutdown().unwrap();
+    }
+}
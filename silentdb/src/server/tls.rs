@@ -0,0 +1,213 @@
+// src/server/tls.rs
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use rustls::sign::CertifiedKey;
+use rustls::{RootCertStore, ServerConfig};
+
+use super::error::ServerError;
+
+/// TLS settings for a server, set with `ServerOptions::tls`.
+///
+/// Certificates and keys are PEM, read when the server is bound, so a bad
+/// one fails `Server::bind_with` rather than the first client.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use silentdb::server::{ServerOptions, TlsOptions};
+/// let tls = TlsOptions::from_pem_files("server.crt", "server.key")
+///     .unwrap()
+///     .server_name_pem_files("other.example.com", "other.crt", "other.key")
+///     .unwrap()
+///     .client_ca_pem_file("clients-ca.crt", true)
+///     .unwrap();
+/// let options = ServerOptions::new().tls(tls);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsOptions {
+    pub(crate) identity: Identity,
+    pub(crate) server_names: HashMap<String, Identity>,
+    pub(crate) client_ca: Option<Vec<u8>>,
+    pub(crate) require_client_cert: bool,
+}
+
+/// A PEM certificate chain and the PEM private key it certifies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Identity {
+    certificates: Vec<u8>,
+    key: Vec<u8>,
+}
+
+impl TlsOptions {
+    /// Creates options presenting the PEM certificate chain `certificates`,
+    /// with the PEM private key `key`, to every client, and asking none for
+    /// a certificate.
+    pub fn from_pem(certificates: &[u8], key: &[u8]) -> Self {
+        TlsOptions {
+            identity: Identity {
+                certificates: certificates.to_vec(),
+                key: key.to_vec(),
+            },
+            server_names: HashMap::new(),
+            client_ca: None,
+            require_client_cert: false,
+        }
+    }
+
+    /// Creates options from a PEM certificate chain file and a PEM private
+    /// key file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a file cannot be read.
+    pub fn from_pem_files<P: AsRef<Path>, Q: AsRef<Path>>(
+        certificates: P,
+        key: Q,
+    ) -> Result<Self, ServerError> {
+        Ok(TlsOptions::from_pem(
+            &fs::read(certificates)?,
+            &fs::read(key)?,
+        ))
+    }
+
+    /// Presents the certificate chain `certificates` and `key` instead to
+    /// clients asking for the host `name` through SNI.
+    pub fn server_name(mut self, name: &str, certificates: &[u8], key: &[u8]) -> Self {
+        self.server_names.insert(
+            name.to_ascii_lowercase(),
+            Identity {
+                certificates: certificates.to_vec(),
+                key: key.to_vec(),
+            },
+        );
+        self
+    }
+
+    /// `server_name` with PEM files.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a file cannot be read.
+    pub fn server_name_pem_files<P: AsRef<Path>, Q: AsRef<Path>>(
+        self,
+        name: &str,
+        certificates: P,
+        key: Q,
+    ) -> Result<Self, ServerError> {
+        Ok(self.server_name(name, &fs::read(certificates)?, &fs::read(key)?))
+    }
+
+    /// Asks clients for a certificate issued by one of the PEM CA
+    /// certificates `certificates`, turning away those without one if
+    /// `required`, for mutual TLS. A client presenting a certificate that
+    /// does not verify is always turned away.
+    pub fn client_ca(mut self, certificates: &[u8], required: bool) -> Self {
+        self.client_ca = Some(certificates.to_vec());
+        self.require_client_cert = required;
+        self
+    }
+
+    /// `client_ca` with a PEM file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read.
+    pub fn client_ca_pem_file<P: AsRef<Path>>(
+        self,
+        certificates: P,
+        required: bool,
+    ) -> Result<Self, ServerError> {
+        Ok(self.client_ca(&fs::read(certificates)?, required))
+    }
+
+    /// Builds the rustls configuration the options describe.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a certificate or key does not parse.
+    pub(crate) fn config(&self) -> Result<Arc<ServerConfig>, ServerError> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = ServerConfig::builder_with_provider(Arc::clone(&provider))
+            .with_safe_default_protocol_versions()
+            .map_err(tls_error)?;
+        let builder = match &self.client_ca {
+            None => builder.with_no_client_auth(),
+            Some(pem) => {
+                let mut roots = RootCertStore::empty();
+                for certificate in certificates(pem)? {
+                    roots.add(certificate).map_err(tls_error)?;
+                }
+                let verifier = WebPkiClientVerifier::builder_with_provider(
+                    Arc::new(roots),
+                    Arc::clone(&provider),
+                );
+                let verifier = match self.require_client_cert {
+                    true => verifier,
+                    false => verifier.allow_unauthenticated(),
+                };
+                builder.with_client_cert_verifier(verifier.build().map_err(tls_error)?)
+            }
+        };
+        let resolver = SniResolver {
+            default: self.identity.certified_key(&provider)?,
+            names: self
+                .server_names
+                .iter()
+                .map(|(name, identity)| Ok((name.clone(), identity.certified_key(&provider)?)))
+                .collect::<Result<_, ServerError>>()?,
+        };
+        Ok(Arc::new(builder.with_cert_resolver(Arc::new(resolver))))
+    }
+}
+
+impl Identity {
+    fn certified_key(&self, provider: &CryptoProvider) -> Result<Arc<CertifiedKey>, ServerError> {
+        let certificates = certificates(&self.certificates)?;
+        if certificates.is_empty() {
+            return Err(ServerError::Tls("no certificate in PEM".to_string()));
+        }
+        let key = PrivateKeyDer::from_pem_slice(&self.key)
+            .map_err(|e| ServerError::Tls(format!("bad private key: {}", e)))?;
+        let key = provider
+            .key_provider
+            .load_private_key(key)
+            .map_err(tls_error)?;
+        Ok(Arc::new(CertifiedKey::new(certificates, key)))
+    }
+}
+
+/// Picks the certificate for the host a client names through SNI, or the
+/// default one.
+#[derive(Debug)]
+struct SniResolver {
+    default: Arc<CertifiedKey>,
+    names: HashMap<String, Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let named = hello
+            .server_name()
+            .and_then(|name| self.names.get(&name.to_ascii_lowercase()));
+        Some(Arc::clone(named.unwrap_or(&self.default)))
+    }
+}
+
+/// Returns the certificates in `pem`.
+fn certificates(pem: &[u8]) -> Result<Vec<CertificateDer<'static>>, ServerError> {
+    CertificateDer::pem_slice_iter(pem)
+        .collect::<Result<_, _>>()
+        .map_err(|e| ServerError::Tls(format!("bad certificate: {}", e)))
+}
+
+fn tls_error(error: impl std::fmt::Display) -> ServerError {
+    ServerError::Tls(error.to_string())
+}
@@ -0,0 +1,231 @@
+// src/server/websocket.rs
+
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use sha1::{Digest, Sha1};
+use silentdb_data_encoding::Document;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+use tokio::sync::mpsc;
+use tokio::task;
+
+use super::command::error_reply;
+use super::error::ServerError;
+use super::http::json;
+use super::task::{send, Stopping};
+use crate::db::{ChangeStream, ResumeToken};
+
+/// Appended to a client's `Sec-WebSocket-Key` to prove the server speaks
+/// WebSocket, by RFC 6455.
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// The longest frame accepted from a client, which has no reason to send
+/// more than a close or ping.
+const MAX_FRAME_LEN: usize = 64 * 1024;
+/// How long a watch waits for a change before checking its client is still
+/// there.
+const WATCH_POLL: Duration = Duration::from_secs(1);
+/// Change events a watch reads ahead of its client.
+const WATCH_BUFFER: usize = 16;
+
+const OP_TEXT: u8 = 0x1;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xa;
+
+/// Close codes, by RFC 6455.
+const CLOSE_NORMAL: u16 = 1000;
+const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+const CLOSE_TOO_BIG: u16 = 1009;
+const CLOSE_INTERNAL_ERROR: u16 = 1011;
+
+/// Returns the `Sec-WebSocket-Accept` answering a client's
+/// `Sec-WebSocket-Key`.
+pub(crate) fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(ACCEPT_GUID.as_bytes());
+    BASE64.encode(sha1.finalize())
+}
+
+/// Sends the changes `changes` reports to the client on `stream`, which
+/// has been upgraded to a WebSocket, until it closes the socket or the
+/// server shuts down.
+///
+/// Each change is a text frame holding the event in relaxed Extended JSON,
+/// with its resume token as `{"_id": {"_data": "<hex>"}}`, which a client
+/// passes back as `resumeAfter` to pick up where it left off. The stream
+/// is read on a blocking thread a few events ahead of the client; one
+/// that fails sends its error reply, then closes the socket.
+///
+/// # Errors
+///
+/// Returns an error if the stream fails.
+pub(crate) async fn serve_watch<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    mut changes: ChangeStream,
+    stopping: &mut Stopping,
+) -> Result<(), ServerError> {
+    let (sender, mut receiver) = mpsc::channel(WATCH_BUFFER);
+    task::spawn_blocking(move || {
+        while !sender.is_closed() {
+            let event = match changes.next_timeout(WATCH_POLL) {
+                Ok(Some(event)) => event_json(&event.to_document(), &event.resume_token.to_bytes()),
+                Ok(None) => continue,
+                Err(e) => Err(ServerError::from(e)),
+            };
+            let failed = event.is_err();
+            if sender.blocking_send(event).is_err() || failed {
+                return;
+            }
+        }
+    });
+
+    let mut incoming = Vec::new();
+    let mut chunk = vec![0; 1024];
+    loop {
+        while let Some(next) = next_frame(&mut incoming) {
+            let (opcode, payload) = match next {
+                Ok(frame) => frame,
+                Err(code) => {
+                    send(stream, stopping, &close(code)).await?;
+                    return Ok(());
+                }
+            };
+            match opcode {
+                OP_PING if !send(stream, stopping, &frame(OP_PONG, &payload)).await? => {
+                    return Ok(());
+                }
+                OP_CLOSE => {
+                    send(stream, stopping, &close(CLOSE_NORMAL)).await?;
+                    return Ok(());
+                }
+                // Clients have nothing to say beyond closing and pinging
+                _ => {}
+            }
+        }
+
+        let next = stopping
+            .unless(async {
+                tokio::select! {
+                    event = receiver.recv() => Next::Event(event),
+                    read = stream.read(&mut chunk) => Next::Read(read),
+                }
+            })
+            .await;
+        match next {
+            None => return Ok(()),
+            Some(Next::Event(Some(Ok(text)))) => {
+                if !send(stream, stopping, &frame(OP_TEXT, &text)).await? {
+                    return Ok(());
+                }
+            }
+            Some(Next::Event(Some(Err(e)))) => {
+                let reply = json(&error_reply(&e))?;
+                if send(stream, stopping, &frame(OP_TEXT, &reply)).await? {
+                    send(stream, stopping, &close(CLOSE_INTERNAL_ERROR)).await?;
+                }
+                return Ok(());
+            }
+            // The blocking thread only stops on an error, sent above
+            Some(Next::Event(None)) => return Ok(()),
+            Some(Next::Read(read)) => match read? {
+                0 => return Ok(()),
+                read => incoming.extend_from_slice(&chunk[..read]),
+            },
+        }
+    }
+}
+
+/// What a watch waited for came to.
+enum Next {
+    Event(Option<Result<Vec<u8>, ServerError>>),
+    Read(std::io::Result<usize>),
+}
+
+/// Returns the change event `document` as relaxed Extended JSON, with its
+/// resume token `token` as `_id`, as MongoDB's change streams have it.
+fn event_json(document: &Document, token: &[u8]) -> Result<Vec<u8>, ServerError> {
+    let mut id = Document::new();
+    id.insert("_data", hex::encode(token));
+    let mut event = Document::new();
+    event.insert("_id", id);
+    for (key, value) in document.iter() {
+        event.insert(key, value.clone());
+    }
+    json(&event)
+}
+
+/// Takes the next whole frame a client sent off the front of `incoming`,
+/// returning its opcode and unmasked payload, or `None` if it has not all
+/// arrived. A frame the server will not take is answered with the close
+/// code saying why.
+fn next_frame(incoming: &mut Vec<u8>) -> Option<Result<(u8, Vec<u8>), u16>> {
+    let [first, second, ..] = incoming[..] else {
+        return None;
+    };
+    let opcode = first & 0x0f;
+    // Clients must mask every frame
+    if second & 0x80 == 0 {
+        return Some(Err(CLOSE_PROTOCOL_ERROR));
+    }
+    let (len, mut offset) = match second & 0x7f {
+        126 => (
+            u16::from_be_bytes(incoming.get(2..4)?.try_into().ok()?) as u64,
+            4,
+        ),
+        127 => (
+            u64::from_be_bytes(incoming.get(2..10)?.try_into().ok()?),
+            10,
+        ),
+        len => (len as u64, 2),
+    };
+    if len > MAX_FRAME_LEN as u64 {
+        return Some(Err(CLOSE_TOO_BIG));
+    }
+    let mask: [u8; 4] = incoming.get(offset..offset + 4)?.try_into().ok()?;
+    offset += 4;
+    let end = offset + len as usize;
+    let mut payload = incoming.get(offset..end)?.to_vec();
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+    incoming.drain(..end);
+    Some(Ok((opcode, payload)))
+}
+
+/// Returns a whole, unmasked frame, as a server sends them.
+fn frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        len @ 0..=125 => frame.push(len as u8),
+        len @ 126..=0xffff => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Returns a close frame giving `code`.
+fn close(code: u16) -> Vec<u8> {
+    frame(OP_CLOSE, &code.to_be_bytes())
+}
+
+/// Returns the resume token a client passed as `resumeAfter`, in hex as
+/// events carry it.
+///
+/// # Errors
+///
+/// Returns `BadCommand` if `text` is not a resume token.
+pub(crate) fn resume_token(text: &str) -> Result<ResumeToken, ServerError> {
+    hex::decode(text)
+        .ok()
+        .and_then(|bytes| ResumeToken::from_bytes(&bytes))
+        .ok_or_else(|| ServerError::bad("watch", "resumeAfter is not a resume token"))
+}
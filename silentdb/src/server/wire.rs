@@ -0,0 +1,671 @@
+// src/server/wire.rs
+
+use std::io::ErrorKind;
+use std::iter::Peekable;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use silentdb_data_encoding::{from_bytes, to_bytes, Array, DeserializeError, Document, Value};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, BufReader};
+
+use super::auth::{Session, MECHANISM};
+use super::command::{error_reply, run, Matches, Reply};
+use super::error::ServerError;
+use super::listener::{Peer, ServerOptions};
+use super::session::{OpenCursor, SessionId, SessionTable};
+use super::task::{blocking, linger, send, Stopping};
+use crate::db::Database;
+use crate::storage::crc32c;
+
+/// The largest message accepted, as advertised by `hello`.
+const MAX_MESSAGE_LEN: usize = 48_000_000;
+/// The length of the header every message starts with.
+const HEADER_LEN: usize = 16;
+/// Documents in the first batch of a find unless told otherwise.
+const DEFAULT_BATCH_SIZE: usize = 101;
+/// The newest wire protocol version served, that of MongoDB 6.0.
+const MAX_WIRE_VERSION: i32 = 17;
+
+const OP_REPLY: i32 = 1;
+const OP_QUERY: i32 = 2004;
+const OP_MSG: i32 = 2013;
+
+/// `OP_MSG` flag: the message ends with a CRC-32C of what comes before it.
+const CHECKSUM_PRESENT: u32 = 1;
+/// `OP_MSG` flag: the sender wants no reply.
+const MORE_TO_COME: u32 = 1 << 1;
+/// `OP_MSG` flags a receiver must understand, in the low 16 bits.
+const REQUIRED_FLAGS: u32 = 0xFFFF;
+
+/// A message's header.
+#[derive(Debug, Clone, Copy)]
+struct Header {
+    request_id: i32,
+    op_code: i32,
+}
+
+/// The state of a connection speaking the MongoDB wire protocol, handed to
+/// the blocking pool for each message it handles.
+struct MongoConnection {
+    db: Database,
+    options: Arc<ServerOptions>,
+    session: Session,
+    /// The server's logical sessions and open cursors.
+    sessions: Arc<SessionTable>,
+    /// The connection's id in `sessions`.
+    id: u64,
+    next_request: i32,
+}
+
+impl Drop for MongoConnection {
+    fn drop(&mut self) {
+        self.sessions.disconnect(self.id);
+    }
+}
+
+/// Serves the MongoDB driver or shell on `stream` until it disconnects or
+/// the server shuts down.
+///
+/// `OP_MSG` commands are answered in kind, and `OP_QUERY` commands, which
+/// drivers still open connections with, with an `OP_REPLY`; other legacy
+/// messages end the connection. Cursors a find leaves open belong to the
+/// logical session the command names with its `lsid`, or else to the
+/// connection, and are dropped with it; see `SessionTable`. Their batches
+/// are only taken as the client asks for them with `getMore`.
+///
+/// # Errors
+///
+/// Returns an error if the stream fails or a message is malformed.
+pub(crate) async fn serve_mongo_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    db: &Database,
+    stream: S,
+    options: &Arc<ServerOptions>,
+    peer: &Peer,
+    mut stopping: Stopping,
+) -> Result<(), ServerError> {
+    let mut stream = BufReader::new(stream);
+    let mut connection = MongoConnection::new(db, options, peer);
+    loop {
+        let Some(message) = stopping.unless(read_message(&mut stream)).await else {
+            return Ok(());
+        };
+        let Some((header, message)) = message? else {
+            return Ok(());
+        };
+        let (returned, reply) = blocking(move || {
+            let reply = connection.handle(header, &message);
+            (connection, reply)
+        })
+        .await;
+        connection = returned;
+        if let Some(reply) = reply? {
+            if !send(&mut stream, &mut stopping, &reply).await? {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Refuses the MongoDB client on `stream` with `error`, which it gets in
+/// reply to its first message, and disconnects it.
+///
+/// # Errors
+///
+/// Returns an error if the stream fails or the message is malformed.
+pub(crate) async fn refuse_mongo_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    db: &Database,
+    stream: S,
+    options: &Arc<ServerOptions>,
+    peer: &Peer,
+    error: &ServerError,
+    mut stopping: Stopping,
+) -> Result<(), ServerError> {
+    let mut stream = BufReader::new(stream);
+    let Some(message) = stopping.unless(read_message(&mut stream)).await else {
+        return Ok(());
+    };
+    let Some((header, message)) = message? else {
+        return Ok(());
+    };
+    let mut connection = MongoConnection::new(db, options, peer);
+    let reply = connection.respond(header, &message, |_, _| error_document(error))?;
+    if let Some(reply) = reply {
+        if !send(&mut stream, &mut stopping, &reply).await? {
+            return Ok(());
+        }
+    }
+    linger(&mut stream, &mut stopping).await;
+    Ok(())
+}
+
+impl MongoConnection {
+    fn new(db: &Database, options: &Arc<ServerOptions>, peer: &Peer) -> Self {
+        MongoConnection {
+            db: db.clone(),
+            options: Arc::clone(options),
+            session: Session::new(peer.clone()),
+            sessions: Arc::clone(&peer.sessions),
+            id: peer.sessions.connect(),
+            next_request: 1,
+        }
+    }
+
+    /// Handles the message with `header`, returning the reply to send, if
+    /// the client wants one.
+    fn handle(&mut self, header: Header, message: &[u8]) -> Result<Option<Vec<u8>>, ServerError> {
+        self.respond(header, message, |connection, command| {
+            connection.run(command)
+        })
+    }
+
+    /// Replies to the message with `header` with what `answer` makes of
+    /// the command it holds, if the client wants a reply.
+    ///
+    /// A document in the message larger than `ServerOptions::max_document_len`
+    /// is answered with an error in place of the command's reply, since
+    /// the message was read whole and the stream is still in step.
+    fn respond<F>(
+        &mut self,
+        header: Header,
+        message: &[u8],
+        answer: F,
+    ) -> Result<Option<Vec<u8>>, ServerError>
+    where
+        F: FnOnce(&mut Self, Document) -> Document,
+    {
+        let max_document_len = self.options.max_document_len;
+        let command = match header.op_code {
+            OP_MSG => parse_msg(message, max_document_len),
+            OP_QUERY => parse_query(&message[HEADER_LEN..], max_document_len),
+            op_code => return Err(malformed(format!("unsupported opcode {}", op_code))),
+        };
+        let reply = match command {
+            Ok(command) => answer(self, command),
+            Err(e @ ServerError::Deserialize(DeserializeError::DocumentTooLarge { .. })) => {
+                error_document(&e)
+            }
+            Err(e) => return Err(e),
+        };
+        if header.op_code == OP_MSG {
+            if msg_flags(message)? & MORE_TO_COME != 0 {
+                return Ok(None);
+            }
+            let mut payload = vec![0; 4];
+            payload.push(0);
+            payload.extend_from_slice(&to_bytes(&reply)?);
+            return Ok(Some(self.frame(header, OP_MSG, &payload)));
+        }
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&0i32.to_le_bytes());
+        payload.extend_from_slice(&0i64.to_le_bytes());
+        payload.extend_from_slice(&0i32.to_le_bytes());
+        payload.extend_from_slice(&1i32.to_le_bytes());
+        payload.extend_from_slice(&to_bytes(&reply)?);
+        Ok(Some(self.frame(header, OP_REPLY, &payload)))
+    }
+
+    /// Runs `command`, returning its reply or the error reply for it.
+    fn run(&mut self, command: Document) -> Document {
+        self.dispatch(command)
+            .unwrap_or_else(|e| error_document(&e))
+    }
+
+    fn dispatch(&mut self, mut command: Document) -> Result<Document, ServerError> {
+        let database = match command.get("$db") {
+            Some(Value::String(name)) => name.clone(),
+            _ => "admin".to_string(),
+        };
+        if command.contains_key("hello") || command.contains_key("isMaster") {
+            return Ok(self.hello(&command, command.contains_key("isMaster")));
+        }
+        if command.contains_key("ismaster") {
+            return Ok(self.hello(&command, true));
+        }
+        let lsid = match command.get("lsid") {
+            Some(lsid) => Some(SessionId::from_lsid("lsid", lsid)?),
+            None => None,
+        };
+        if let Some(lsid) = &lsid {
+            self.sessions.touch(lsid);
+        }
+        if command.contains_key("startSession") {
+            return Ok(self.start_session());
+        }
+        if let Some(sessions) = command.get("endSessions") {
+            return self.end_sessions(sessions);
+        }
+        if command.contains_key("killCursors") {
+            self.session
+                .authorize(&self.db, &self.options, "killCursors")?;
+            return self.kill_cursors(&command, lsid.as_ref());
+        }
+        if let Some(id) = command.get("getMore") {
+            self.session.authorize(&self.db, &self.options, "getMore")?;
+            return self.get_more(id, &command, lsid.as_ref());
+        }
+        let find = match command.get("find") {
+            Some(Value::String(collection)) => Some(collection.clone()),
+            _ => None,
+        };
+        // Batches are cut here rather than by the command, which streams
+        let single_batch = matches!(command.remove("singleBatch"), Some(Value::Boolean(true)));
+        let batch_size = batch_size(command.remove("batchSize")).unwrap_or(DEFAULT_BATCH_SIZE);
+        match run(
+            &self.db,
+            &self.options,
+            &mut self.session,
+            &to_bytes(&command)?,
+        )? {
+            Reply::Document(reply) => Ok(reply),
+            Reply::Stream { matches, .. } => {
+                let collection = find.unwrap_or_default();
+                let cursor = OpenCursor {
+                    namespace: format!("{}.{}", database, collection),
+                    matches: matches.peekable(),
+                };
+                self.first_batch(cursor, batch_size, single_batch, lsid.as_ref())
+            }
+        }
+    }
+
+    /// Replies to `hello`, or to `isMaster` with `legacy`, describing a
+    /// standalone server, and listing its SASL mechanisms if the `command`
+    /// asks with `saslSupportedMechs`, as drivers do before authenticating.
+    fn hello(&self, command: &Document, legacy: bool) -> Document {
+        let mut reply = Document::new();
+        match legacy {
+            true => reply.insert("ismaster", true),
+            false => reply.insert("isWritablePrimary", true),
+        };
+        reply.insert("helloOk", true);
+        reply.insert(
+            "maxBsonObjectSize",
+            self.options.max_document_len.min(i32::MAX as usize) as i32,
+        );
+        reply.insert("maxMessageSizeBytes", MAX_MESSAGE_LEN as i32);
+        reply.insert("maxWriteBatchSize", 100_000);
+        reply.insert(
+            "logicalSessionTimeoutMinutes",
+            self.sessions.timeout_minutes(),
+        );
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_millis() as i64);
+        reply.insert("localTime", Value::UTCDateTime(now));
+        reply.insert("minWireVersion", 0);
+        reply.insert("maxWireVersion", MAX_WIRE_VERSION);
+        reply.insert("readOnly", false);
+        if command.contains_key("saslSupportedMechs") {
+            reply.insert(
+                "saslSupportedMechs",
+                Array::from_vec(vec![MECHANISM.into()]),
+            );
+        }
+        reply.insert("ok", 1.0);
+        reply
+    }
+
+    /// Replies to a find with its first batch of up to `batch_size`
+    /// documents, keeping the cursor open for `getMore` in `lsid`, or on
+    /// this connection, if there are more and it is not a `single_batch`
+    /// find.
+    fn first_batch(
+        &mut self,
+        mut cursor: OpenCursor,
+        batch_size: usize,
+        single_batch: bool,
+        lsid: Option<&SessionId>,
+    ) -> Result<Document, ServerError> {
+        let (batch, more) = take_batch(&mut cursor.matches, batch_size)?;
+        if !more || single_batch {
+            return Ok(cursor_reply(0, &cursor.namespace, "firstBatch", batch));
+        }
+        let namespace = cursor.namespace.clone();
+        let id = self
+            .sessions
+            .open(cursor, self.id, lsid, self.options.max_operations)?;
+        Ok(cursor_reply(id, &namespace, "firstBatch", batch))
+    }
+
+    /// `{getMore: <cursor id>, collection, batchSize}`: replies with the
+    /// cursor's next batch, of every match left without a `batchSize`, and
+    /// closes the cursor after the last. Only the session that opened the
+    /// cursor, or the connection for one opened outside a session, may
+    /// read it.
+    fn get_more(
+        &mut self,
+        id: &Value,
+        command: &Document,
+        lsid: Option<&SessionId>,
+    ) -> Result<Document, ServerError> {
+        let id = cursor_id("getMore", id)?;
+        let mut cursor = self.sessions.take(id, self.id, lsid)?;
+        let batch_size = batch_size(command.get("batchSize").cloned())
+            .filter(|n| *n > 0)
+            .unwrap_or(usize::MAX);
+        let taken = take_batch(&mut cursor.matches, batch_size);
+        let (batch, more) = match taken {
+            Ok(taken) => taken,
+            Err(e) => {
+                self.sessions.finish(id, lsid);
+                return Err(e);
+            }
+        };
+        let reply = cursor_reply(
+            if more { id } else { 0 },
+            &cursor.namespace,
+            "nextBatch",
+            batch,
+        );
+        match more {
+            true => self.sessions.put_back(id, cursor, self.id, lsid),
+            false => self.sessions.finish(id, lsid),
+        }
+        Ok(reply)
+    }
+
+    /// `{killCursors: <collection>, cursors: [<id>, ...]}`: closes the
+    /// cursors, of those this connection may read.
+    fn kill_cursors(
+        &mut self,
+        command: &Document,
+        lsid: Option<&SessionId>,
+    ) -> Result<Document, ServerError> {
+        let Some(Value::Array(cursors)) = command.get("cursors") else {
+            return Err(ServerError::bad(
+                "killCursors",
+                "cursors must be an array of cursor ids",
+            ));
+        };
+        let ids = cursors
+            .iter()
+            .map(|id| cursor_id("killCursors", id))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(self.sessions.kill_cursors(&ids, self.id, lsid))
+    }
+
+    /// `{startSession: 1}`: starts a logical session, and replies with its
+    /// id and how long it may go unused.
+    fn start_session(&mut self) -> Document {
+        let id = SessionId::generate();
+        self.sessions.touch(&id);
+        let mut reply = Document::new();
+        reply.insert("id", id.to_lsid());
+        reply.insert("timeoutMinutes", self.sessions.timeout_minutes());
+        reply.insert("ok", 1.0);
+        reply
+    }
+
+    /// `{endSessions: [{id: <UUID>}, ...]}`: ends the sessions, closing
+    /// their cursors.
+    fn end_sessions(&mut self, sessions: &Value) -> Result<Document, ServerError> {
+        let Value::Array(sessions) = sessions else {
+            return Err(ServerError::bad(
+                "endSessions",
+                "endSessions must be an array of session ids",
+            ));
+        };
+        let ids = sessions
+            .iter()
+            .map(|lsid| SessionId::from_lsid("endSessions", lsid))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.sessions.end_sessions(&ids);
+        let mut reply = Document::new();
+        reply.insert("ok", 1.0);
+        Ok(reply)
+    }
+
+    /// Returns a message with `op_code` and `payload` in reply to the one
+    /// with `request`.
+    fn frame(&mut self, request: Header, op_code: i32, payload: &[u8]) -> Vec<u8> {
+        let request_id = self.next_request;
+        self.next_request = self.next_request.wrapping_add(1);
+        let len = (HEADER_LEN + payload.len()) as i32;
+        let mut message = Vec::with_capacity(len as usize);
+        message.extend_from_slice(&len.to_le_bytes());
+        message.extend_from_slice(&request_id.to_le_bytes());
+        message.extend_from_slice(&request.request_id.to_le_bytes());
+        message.extend_from_slice(&op_code.to_le_bytes());
+        message.extend_from_slice(payload);
+        message
+    }
+}
+
+/// Takes up to `batch_size` documents from `matches`, and returns them and
+/// whether any are left.
+fn take_batch(
+    matches: &mut Peekable<Matches>,
+    batch_size: usize,
+) -> Result<(Array, bool), ServerError> {
+    let mut batch = Array::new();
+    while batch.len() < batch_size {
+        match matches.next() {
+            Some(document) => batch.push(document?),
+            None => return Ok((batch, false)),
+        }
+    }
+    Ok((batch, matches.peek().is_some()))
+}
+
+/// Returns the cursor id `value` holds, given to `command`.
+fn cursor_id(command: &str, value: &Value) -> Result<i64, ServerError> {
+    match value {
+        Value::Int64(id) => Ok(*id),
+        Value::Int32(id) => Ok(*id as i64),
+        _ => Err(ServerError::bad(command, "a cursor id must be an integer")),
+    }
+}
+
+/// Returns the batch size `value` holds, if it is a non-negative integer.
+fn batch_size(value: Option<Value>) -> Option<usize> {
+    match value? {
+        Value::Int32(n) => usize::try_from(n).ok(),
+        Value::Int64(n) => usize::try_from(n).ok(),
+        Value::Double(n) if n >= 0.0 && n.fract() == 0.0 => Some(n as usize),
+        _ => None,
+    }
+}
+
+fn cursor_reply(id: i64, namespace: &str, field: &str, batch: Array) -> Document {
+    let mut cursor = Document::new();
+    cursor.insert("id", id);
+    cursor.insert("ns", namespace);
+    cursor.insert(field, batch);
+    let mut reply = Document::new();
+    reply.insert("cursor", cursor);
+    reply.insert("ok", 1.0);
+    reply
+}
+
+/// Reads the next message, returning its header and the whole message,
+/// header included, or `None` if the stream ends first.
+async fn read_message<R: AsyncRead + Unpin>(
+    reader: &mut R,
+) -> Result<Option<(Header, Vec<u8>)>, ServerError> {
+    let mut message = vec![0; HEADER_LEN];
+    match reader.read_exact(&mut message[..1]).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    reader.read_exact(&mut message[1..]).await?;
+    let field = |i: usize| i32::from_le_bytes(message[i..i + 4].try_into().unwrap());
+    let len = field(0);
+    if len < HEADER_LEN as i32 || len as usize > MAX_MESSAGE_LEN {
+        return Err(malformed(format!("message length {}", len)));
+    }
+    let header = Header {
+        request_id: field(4),
+        op_code: field(12),
+    };
+    message.resize(len as usize, 0);
+    reader.read_exact(&mut message[HEADER_LEN..]).await?;
+    Ok(Some((header, message)))
+}
+
+/// Returns the error reply for `error`, with the name MongoDB gives its
+/// code.
+fn error_document(error: &ServerError) -> Document {
+    let mut reply = error_reply(error);
+    if let Some(name) = code_name(error.code()) {
+        reply.insert("codeName", name);
+    }
+    reply
+}
+
+/// Returns the flags of the `OP_MSG` `message`, checking it sets none the
+/// server does not understand.
+fn msg_flags(message: &[u8]) -> Result<u32, ServerError> {
+    let flags = u32::from_le_bytes(
+        message
+            .get(HEADER_LEN..HEADER_LEN + 4)
+            .ok_or_else(|| malformed("truncated OP_MSG"))?
+            .try_into()
+            .unwrap(),
+    );
+    if flags & REQUIRED_FLAGS & !(CHECKSUM_PRESENT | MORE_TO_COME) != 0 {
+        return Err(malformed(format!("unknown OP_MSG flags {:#x}", flags)));
+    }
+    Ok(flags)
+}
+
+/// Returns the command the `OP_MSG` `message` holds: its body section,
+/// with the documents of each document sequence section added as an array
+/// under the sequence's name. Each document may be up to
+/// `max_document_len` bytes.
+fn parse_msg(message: &[u8], max_document_len: usize) -> Result<Document, ServerError> {
+    let flags = msg_flags(message)?;
+    let body = &message[HEADER_LEN..];
+    let mut sections = &body[4..];
+    if flags & CHECKSUM_PRESENT != 0 {
+        let Some(split) = sections.len().checked_sub(4) else {
+            return Err(malformed("truncated OP_MSG checksum"));
+        };
+        let (rest, checksum) = sections.split_at(split);
+        // The checksum covers everything before it, header included
+        let expected = u32::from_le_bytes(checksum.try_into().unwrap());
+        if crc32c(&message[..message.len() - 4]) != expected {
+            return Err(malformed("OP_MSG checksum mismatch"));
+        }
+        sections = rest;
+    }
+
+    let mut command = None;
+    let mut sequences = Vec::new();
+    while let Some((&kind, rest)) = sections.split_first() {
+        match kind {
+            0 => {
+                let len = document_len(rest)?;
+                check_document_len(len, max_document_len)?;
+                command = Some(from_bytes(&rest[..len])?);
+                sections = &rest[len..];
+            }
+            1 => {
+                let len = document_len(rest)?;
+                let mut sequence = &rest[4..len];
+                let name_len = sequence
+                    .iter()
+                    .position(|&byte| byte == 0)
+                    .ok_or_else(|| malformed("unterminated sequence name"))?;
+                let name = String::from_utf8(sequence[..name_len].to_vec())
+                    .map_err(|_| malformed("sequence name is not UTF-8"))?;
+                sequence = &sequence[name_len + 1..];
+                let mut documents = Array::new();
+                while !sequence.is_empty() {
+                    let len = document_len(sequence)?;
+                    check_document_len(len, max_document_len)?;
+                    documents.push(from_bytes(&sequence[..len])?);
+                    sequence = &sequence[len..];
+                }
+                sequences.push((name, documents));
+                sections = &rest[len..];
+            }
+            kind => return Err(malformed(format!("unknown section kind {}", kind))),
+        }
+    }
+    let mut command: Document = command.ok_or_else(|| malformed("OP_MSG without a body"))?;
+    for (name, documents) in sequences {
+        command.insert(name, documents);
+    }
+    Ok(command)
+}
+
+/// Returns the command an `OP_QUERY` with `body` runs, which must be on a
+/// `$cmd` collection and up to `max_document_len` bytes.
+fn parse_query(body: &[u8], max_document_len: usize) -> Result<Document, ServerError> {
+    let rest = body
+        .get(4..)
+        .ok_or_else(|| malformed("truncated OP_QUERY"))?;
+    let name_len = rest
+        .iter()
+        .position(|&byte| byte == 0)
+        .ok_or_else(|| malformed("unterminated collection name"))?;
+    let namespace = String::from_utf8_lossy(&rest[..name_len]).into_owned();
+    let Some(database) = namespace.strip_suffix(".$cmd") else {
+        return Err(malformed("OP_QUERY is only served for commands"));
+    };
+    let query = rest
+        .get(name_len + 9..)
+        .ok_or_else(|| malformed("truncated OP_QUERY"))?;
+    let len = document_len(query)?;
+    check_document_len(len, max_document_len)?;
+    let mut command: Document = from_bytes(&query[..len])?;
+    if let Some(Value::Document(wrapped)) = command.remove("$query") {
+        command = wrapped;
+    }
+    command.insert("$db", database);
+    Ok(command)
+}
+
+/// Returns the length of the document, or the document sequence, at the
+/// start of `bytes`, checking it fits.
+fn document_len(bytes: &[u8]) -> Result<usize, ServerError> {
+    let prefix = bytes
+        .get(..4)
+        .ok_or_else(|| malformed("truncated section"))?;
+    let len = i32::from_le_bytes(prefix.try_into().unwrap());
+    match usize::try_from(len) {
+        Ok(len) if len >= 5 && len <= bytes.len() => Ok(len),
+        _ => Err(malformed(format!("section length {}", len))),
+    }
+}
+
+/// Fails with `DocumentTooLarge` if a document of `len` bytes is over
+/// `max`.
+fn check_document_len(len: usize, max: usize) -> Result<(), ServerError> {
+    match len > max {
+        true => Err(DeserializeError::DocumentTooLarge { length: len, max }.into()),
+        false => Ok(()),
+    }
+}
+
+fn malformed(message: impl ToString) -> ServerError {
+    ServerError::bad("wire protocol", message)
+}
+
+/// Returns the name MongoDB gives the error `code`, if it is one the
+/// server replies with.
+fn code_name(code: i32) -> Option<&'static str> {
+    Some(match code {
+        1 => "InternalError",
+        2 => "BadValue",
+        9 => "FailedToParse",
+        11 => "UserNotFound",
+        13 => "Unauthorized",
+        18 => "AuthenticationFailed",
+        22 => "InvalidBSON",
+        24 => "LockTimeout",
+        26 => "NamespaceNotFound",
+        50 => "MaxTimeMSExpired",
+        43 => "CursorNotFound",
+        48 => "NamespaceExists",
+        59 => "CommandNotFound",
+        67 => "CannotCreateIndex",
+        73 => "InvalidNamespace",
+        112 => "WriteConflict",
+        121 => "DocumentValidationFailure",
+        10107 => "NotWritablePrimary",
+        11000 => "DuplicateKey",
+        _ => return None,
+    })
+}
@@ -0,0 +1,107 @@
+// src/shell/args.rs
+
+use std::path::PathBuf;
+
+use super::error::ShellError;
+use super::output::Format;
+
+/// The server the shell connects to unless told otherwise.
+pub(crate) const DEFAULT_ADDRESS: &str = "127.0.0.1:27117";
+
+pub(crate) const USAGE: &str = "\
+Usage: silentdb [OPTIONS] [ADDRESS]
+
+Runs commands against the server at ADDRESS, 127.0.0.1:27117 by default,
+or against a local data directory. Commands are read from --eval, else
+interactively, else from standard input.
+
+Options:
+  --dir PATH          Open the data directory PATH instead of connecting
+  --user NAME         Authenticate as NAME
+  --password PASS     The password to authenticate with, also read from
+                      SILENTDB_PASSWORD
+  --tls-ca FILE       Connect over TLS, trusting the PEM certificates in FILE
+  --format FORMAT     Print results as a table or as JSON, one document a
+                      line; tables by default when interactive
+  --eval COMMAND      Run COMMAND and exit; may be given more than once
+  --help              Print this help
+";
+
+/// Where the shell runs its commands.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Target {
+    /// A server, by address.
+    Server(String),
+    /// A data directory, opened in-process.
+    Directory(PathBuf),
+}
+
+/// The shell's command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Args {
+    pub(crate) target: Target,
+    pub(crate) user: Option<String>,
+    pub(crate) password: Option<String>,
+    pub(crate) tls_ca: Option<PathBuf>,
+    pub(crate) format: Option<Format>,
+    pub(crate) eval: Vec<String>,
+    pub(crate) help: bool,
+}
+
+/// Parses the shell's arguments, less the program name. The password, if
+/// not given, is taken from `SILENTDB_PASSWORD`.
+///
+/// # Errors
+///
+/// Returns a `Usage` error for an unknown option, one missing its value,
+/// or a server address given along with `--dir`.
+pub(crate) fn parse(args: impl IntoIterator<Item = String>) -> Result<Args, ShellError> {
+    let mut args = args.into_iter();
+    let mut address = None;
+    let mut directory = None;
+    let mut parsed = Args {
+        target: Target::Server(DEFAULT_ADDRESS.to_string()),
+        user: None,
+        password: None,
+        tls_ca: None,
+        format: None,
+        eval: Vec::new(),
+        help: false,
+    };
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| ShellError::usage(format!("{} needs a value", name)))
+        };
+        match arg.as_str() {
+            "--dir" => directory = Some(PathBuf::from(value("--dir")?)),
+            "--user" => parsed.user = Some(value("--user")?),
+            "--password" => parsed.password = Some(value("--password")?),
+            "--tls-ca" => parsed.tls_ca = Some(PathBuf::from(value("--tls-ca")?)),
+            "--format" => parsed.format = Some(Format::from_name(&value("--format")?)?),
+            "--eval" => parsed.eval.push(value("--eval")?),
+            "--help" | "-h" => parsed.help = true,
+            option if option.starts_with('-') => {
+                return Err(ShellError::usage(format!("unknown option {}", option)))
+            }
+            _ if address.is_some() => {
+                return Err(ShellError::usage(format!("unexpected argument {}", arg)))
+            }
+            _ => address = Some(arg),
+        }
+    }
+    parsed.target = match (address, directory) {
+        (Some(_), Some(_)) => {
+            return Err(ShellError::usage(
+                "give a server address or --dir, not both",
+            ))
+        }
+        (_, Some(directory)) => Target::Directory(directory),
+        (Some(address), None) => Target::Server(address),
+        (None, None) => parsed.target,
+    };
+    if parsed.password.is_none() {
+        parsed.password = std::env::var("SILENTDB_PASSWORD").ok();
+    }
+    Ok(parsed)
+}
@@ -0,0 +1,247 @@
+// src/shell/backend.rs
+
+use silentdb::{Database, FindOptions, UpdateOptions};
+use silentdb_client::{Client, ClientOptions, TlsOptions};
+use silentdb_data_encoding::{Array, Document, Value};
+
+use super::args::{Args, Target};
+use super::command::FindArgs;
+use super::error::ShellError;
+
+/// What the shell runs its commands against.
+///
+/// Writes come back as summary documents, so that the shell prints every
+/// result the same way.
+pub(crate) enum Backend {
+    /// A data directory, opened in-process.
+    Local(Database),
+    /// A server, through the client.
+    Remote(Client),
+}
+
+impl Backend {
+    /// Opens the data directory or connects to the server `args` name.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory cannot be opened, the server cannot
+    /// be reached or authenticated with, or the `--tls-ca` file cannot be
+    /// read.
+    pub(crate) fn open(args: &Args) -> Result<Backend, ShellError> {
+        match &args.target {
+            Target::Directory(dir) => Ok(Backend::Local(Database::open(dir)?)),
+            Target::Server(address) => {
+                let mut options = ClientOptions::new();
+                if let Some(ca) = &args.tls_ca {
+                    let host = address
+                        .rsplit_once(':')
+                        .map_or(address.as_str(), |(host, _)| host);
+                    options = options.tls(TlsOptions::new(host).root_certificates_pem_file(ca)?);
+                }
+                if let Some(user) = &args.user {
+                    let password = args.password.as_deref().ok_or_else(|| {
+                        ShellError::usage("--user needs --password or SILENTDB_PASSWORD")
+                    })?;
+                    options = options.credentials(user, password);
+                }
+                Ok(Backend::Remote(Client::connect_with(
+                    address.as_str(),
+                    options,
+                )?))
+            }
+        }
+    }
+
+    pub(crate) fn find(
+        &self,
+        collection: &str,
+        filter: &Document,
+        args: &FindArgs,
+    ) -> Result<Vec<Document>, ShellError> {
+        match self {
+            Backend::Local(db) => {
+                let mut options = FindOptions::new();
+                if let Some(sort) = &args.sort {
+                    options = options.sort(sort.clone());
+                }
+                if let Some(projection) = &args.projection {
+                    options = options.projection(projection.clone());
+                }
+                let skip = usize::try_from(args.skip).unwrap_or(usize::MAX);
+                let limit = args.limit.map_or(usize::MAX, |limit| {
+                    usize::try_from(limit).unwrap_or(usize::MAX)
+                });
+                let cursor = db.collection(collection).find_with(filter, &options)?;
+                Ok(cursor.skip(skip).take(limit).collect::<Result<_, _>>()?)
+            }
+            Backend::Remote(client) => {
+                let mut options = silentdb_client::FindOptions::new().skip(args.skip);
+                if let Some(limit) = args.limit {
+                    options = options.limit(limit);
+                }
+                if let Some(sort) = &args.sort {
+                    options = options.sort(sort.clone());
+                }
+                if let Some(projection) = &args.projection {
+                    options = options.projection(projection.clone());
+                }
+                let collection = client.collection::<Document>(collection);
+                let cursor = collection.find_with(filter, &options)?;
+                let documents = cursor.collect::<Result<_, _>>()?;
+                Ok(documents)
+            }
+        }
+    }
+
+    pub(crate) fn count(
+        &self,
+        collection: &str,
+        filter: &Document,
+    ) -> Result<Document, ShellError> {
+        let count = match self {
+            Backend::Local(db) => db.collection(collection).count(filter)?,
+            Backend::Remote(client) => client.collection::<Document>(collection).count(filter)?,
+        };
+        let mut summary = Document::new();
+        summary.insert("count", count as i64);
+        Ok(summary)
+    }
+
+    pub(crate) fn insert(
+        &self,
+        collection: &str,
+        documents: Vec<Document>,
+    ) -> Result<Document, ShellError> {
+        let ids = match self {
+            Backend::Local(db) => {
+                db.collection(collection)
+                    .insert_many(documents)?
+                    .inserted_ids
+            }
+            Backend::Remote(client) => {
+                client
+                    .collection::<Document>(collection)
+                    .insert_many(documents)?
+                    .inserted_ids
+            }
+        };
+        let mut summary = Document::new();
+        summary.insert("insertedCount", ids.len() as i64);
+        summary.insert("insertedIds", Value::Array(Array::from(ids)));
+        Ok(summary)
+    }
+
+    /// Applies `update` to the first document `filter` matches, or to all of
+    /// them if `multi`, or replaces the first if `update` has no operators.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Usage` error for a replacement with `multi`, as well as
+    /// any error of the update itself.
+    pub(crate) fn update(
+        &self,
+        collection: &str,
+        filter: &Document,
+        update: &Document,
+        multi: bool,
+        upsert: bool,
+    ) -> Result<Document, ShellError> {
+        let operators = update.iter().any(|(field, _)| field.starts_with('$'));
+        if !operators && multi {
+            return Err(ShellError::usage(
+                "a replacement applies to one document; use update operators with multi",
+            ));
+        }
+        let (matched, modified, upserted_id) = match self {
+            Backend::Local(db) => {
+                let collection = db.collection(collection);
+                let options = UpdateOptions::new().upsert(upsert);
+                let result = match (operators, multi) {
+                    (true, true) => collection.update_many_with(filter, update, &options)?,
+                    (true, false) => collection.update_one_with(filter, update, &options)?,
+                    (false, _) => collection.replace_one_with(filter, update, &options)?,
+                };
+                (
+                    result.matched_count,
+                    result.modified_count,
+                    result.upserted_id,
+                )
+            }
+            Backend::Remote(client) => {
+                let collection = client.collection::<Document>(collection);
+                let options = silentdb_client::UpdateOptions::new().upsert(upsert);
+                let result = match (operators, multi) {
+                    (true, true) => collection.update_many_with(filter, update, &options)?,
+                    (true, false) => collection.update_one_with(filter, update, &options)?,
+                    (false, _) => collection.replace_one_with(filter, update.clone(), &options)?,
+                };
+                (
+                    result.matched_count,
+                    result.modified_count,
+                    result.upserted_id,
+                )
+            }
+        };
+        let mut summary = Document::new();
+        summary.insert("matchedCount", matched as i64);
+        summary.insert("modifiedCount", modified as i64);
+        if let Some(id) = upserted_id {
+            summary.insert("upsertedId", id);
+        }
+        Ok(summary)
+    }
+
+    pub(crate) fn delete(
+        &self,
+        collection: &str,
+        filter: &Document,
+        multi: bool,
+    ) -> Result<Document, ShellError> {
+        let deleted = match (self, multi) {
+            (Backend::Local(db), true) => {
+                db.collection(collection).delete_many(filter)?.deleted_count
+            }
+            (Backend::Local(db), false) => {
+                db.collection(collection).delete_one(filter)?.deleted_count
+            }
+            (Backend::Remote(client), true) => {
+                client
+                    .collection::<Document>(collection)
+                    .delete_many(filter)?
+                    .deleted_count
+            }
+            (Backend::Remote(client), false) => {
+                client
+                    .collection::<Document>(collection)
+                    .delete_one(filter)?
+                    .deleted_count
+            }
+        };
+        let mut summary = Document::new();
+        summary.insert("deletedCount", deleted as i64);
+        Ok(summary)
+    }
+
+    /// Returns a document naming each collection.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Usage` error against a server, whose protocol has no
+    /// command listing collections.
+    pub(crate) fn collections(&self) -> Result<Vec<Document>, ShellError> {
+        match self {
+            Backend::Local(db) => Ok(db
+                .list_collections()?
+                .into_iter()
+                .map(|name| {
+                    let mut document = Document::new();
+                    document.insert("name", name);
+                    document
+                })
+                .collect()),
+            Backend::Remote(_) => Err(ShellError::usage(
+                "listing collections needs a local data directory",
+            )),
+        }
+    }
+}
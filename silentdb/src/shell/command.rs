@@ -0,0 +1,362 @@
+// src/shell/command.rs
+
+use silentdb::SqlQuery;
+use silentdb_data_encoding::{from_json_value_str, Array, Document, Value};
+
+use super::error::ShellError;
+use super::output::Format;
+
+pub(crate) const HELP: &str = "\
+Commands, with documents in Extended JSON:
+  find <collection> [<filter>] [{sort, projection, skip, limit}]
+  sql SELECT <paths | *> FROM <collection> [WHERE ...] [ORDER BY ...] [LIMIT ...]
+  count <collection> [<filter>]
+  insert <collection> <document | [documents]>
+  update <collection> <filter> <update> [{multi, upsert}]
+  delete <collection> <filter> [{multi}]
+  collections
+  format table | json
+  help
+  exit
+
+update takes update operators, or a document to replace the first match
+with. update and delete affect only the first match unless multi is true.
+A SELECT statement may also be given without sql before it.
+";
+
+/// A command the shell runs.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Command {
+    Find {
+        collection: String,
+        filter: Document,
+        options: FindArgs,
+    },
+    Count {
+        collection: String,
+        filter: Document,
+    },
+    Insert {
+        collection: String,
+        documents: Vec<Document>,
+    },
+    Update {
+        collection: String,
+        filter: Document,
+        update: Document,
+        multi: bool,
+        upsert: bool,
+    },
+    Delete {
+        collection: String,
+        filter: Document,
+        multi: bool,
+    },
+    Collections,
+    Format(Format),
+    Help,
+    Exit,
+}
+
+/// The options of a `find`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct FindArgs {
+    pub(crate) sort: Option<Document>,
+    pub(crate) projection: Option<Document>,
+    pub(crate) skip: u64,
+    pub(crate) limit: Option<u64>,
+}
+
+/// Parses `line`, returning `None` for a blank line or a comment, one
+/// starting with `#` or `//`.
+///
+/// # Errors
+///
+/// Returns `Incomplete` if a document or string is left open, so that the
+/// caller can read on, and a `Usage` or `Json` error for anything else
+/// malformed.
+pub(crate) fn parse(line: &str) -> Result<Option<Command>, ShellError> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') || line.starts_with("//") {
+        return Ok(None);
+    }
+    let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    // A statement is not split into arguments, since its quotes are SQL's
+    if name == "sql" || name.eq_ignore_ascii_case("select") {
+        let statement = if name == "sql" { rest } else { line };
+        let query = SqlQuery::parse(statement)?;
+        return Ok(Some(Command::Find {
+            collection: query.collection,
+            filter: query.filter,
+            options: FindArgs {
+                sort: query.sort,
+                projection: query.projection,
+                skip: query.skip,
+                limit: query.limit,
+            },
+        }));
+    }
+    let mut args = Operands::new(name, split(rest)?);
+    let command = match name {
+        "find" => {
+            let collection = args.word("collection")?;
+            let filter = args.document_or_empty()?;
+            let mut options = FindArgs::default();
+            let mut given = args.options()?;
+            options.sort = take_document(&mut given, "sort")?;
+            options.projection = take_document(&mut given, "projection")?;
+            options.skip = take_count(&mut given, "skip")?.unwrap_or(0);
+            // As for the server, a limit of 0 is no limit
+            options.limit = take_count(&mut given, "limit")?.filter(|limit| *limit > 0);
+            no_more(name, given)?;
+            Command::Find {
+                collection,
+                filter,
+                options,
+            }
+        }
+        "count" => Command::Count {
+            collection: args.word("collection")?,
+            filter: args.document_or_empty()?,
+        },
+        "insert" => {
+            let collection = args.word("collection")?;
+            let documents = match args.value("document")? {
+                Value::Document(document) => vec![document],
+                Value::Array(documents) => documents_of(documents, "insert takes documents")?,
+                _ => return Err(ShellError::usage("insert takes documents")),
+            };
+            Command::Insert {
+                collection,
+                documents,
+            }
+        }
+        "update" => {
+            let collection = args.word("collection")?;
+            let filter = args.document("filter")?;
+            let update = args.document("update")?;
+            let mut given = args.options()?;
+            let multi = take_flag(&mut given, "multi")?;
+            let upsert = take_flag(&mut given, "upsert")?;
+            no_more(name, given)?;
+            Command::Update {
+                collection,
+                filter,
+                update,
+                multi,
+                upsert,
+            }
+        }
+        "delete" => {
+            let collection = args.word("collection")?;
+            let filter = args.document("filter")?;
+            let mut given = args.options()?;
+            let multi = take_flag(&mut given, "multi")?;
+            no_more(name, given)?;
+            Command::Delete {
+                collection,
+                filter,
+                multi,
+            }
+        }
+        "collections" => Command::Collections,
+        "format" => Command::Format(Format::from_name(&args.word("format")?)?),
+        "help" => Command::Help,
+        "exit" | "quit" => Command::Exit,
+        _ => {
+            return Err(ShellError::usage(format!(
+                "unknown command {:?}; try help",
+                name
+            )))
+        }
+    };
+    args.finish()?;
+    Ok(Some(command))
+}
+
+/// The arguments of a command, taken in order.
+struct Operands<'a> {
+    command: &'a str,
+    args: std::vec::IntoIter<Value>,
+}
+
+impl<'a> Operands<'a> {
+    fn new(command: &'a str, args: Vec<Value>) -> Self {
+        Operands {
+            command,
+            args: args.into_iter(),
+        }
+    }
+
+    fn next(&mut self) -> Option<Value> {
+        self.args.next()
+    }
+
+    fn value(&mut self, what: &str) -> Result<Value, ShellError> {
+        self.next()
+            .ok_or_else(|| ShellError::usage(format!("{} needs a {}", self.command, what)))
+    }
+
+    fn word(&mut self, what: &str) -> Result<String, ShellError> {
+        match self.value(what)? {
+            Value::String(word) => Ok(word),
+            _ => Err(ShellError::usage(format!(
+                "{} needs a {}",
+                self.command, what
+            ))),
+        }
+    }
+
+    fn document(&mut self, what: &str) -> Result<Document, ShellError> {
+        match self.value(what)? {
+            Value::Document(document) => Ok(document),
+            _ => Err(ShellError::usage(format!(
+                "{} needs a {} document",
+                self.command, what
+            ))),
+        }
+    }
+
+    fn document_or_empty(&mut self) -> Result<Document, ShellError> {
+        match self.next() {
+            None => Ok(Document::new()),
+            Some(Value::Document(document)) => Ok(document),
+            Some(_) => Err(ShellError::usage(format!(
+                "{} takes a filter document",
+                self.command
+            ))),
+        }
+    }
+
+    fn options(&mut self) -> Result<Document, ShellError> {
+        match self.next() {
+            None => Ok(Document::new()),
+            Some(Value::Document(options)) => Ok(options),
+            Some(_) => Err(ShellError::usage(format!(
+                "{} takes an options document",
+                self.command
+            ))),
+        }
+    }
+
+    fn finish(mut self) -> Result<(), ShellError> {
+        match self.next() {
+            None => Ok(()),
+            Some(extra) => Err(ShellError::usage(format!(
+                "unexpected argument {} to {}",
+                extra, self.command
+            ))),
+        }
+    }
+}
+
+/// Returns the documents of `array`, or a `Usage` error of `message` if
+/// it holds anything else.
+fn documents_of(array: Array, message: &str) -> Result<Vec<Document>, ShellError> {
+    let values: Vec<Value> = array.into();
+    values
+        .into_iter()
+        .map(|value| match value {
+            Value::Document(document) => Ok(document),
+            _ => Err(ShellError::usage(message)),
+        })
+        .collect()
+}
+
+fn take_document(options: &mut Document, name: &str) -> Result<Option<Document>, ShellError> {
+    match options.remove(name) {
+        None => Ok(None),
+        Some(Value::Document(document)) => Ok(Some(document)),
+        Some(_) => Err(ShellError::usage(format!("{} must be a document", name))),
+    }
+}
+
+fn take_count(options: &mut Document, name: &str) -> Result<Option<u64>, ShellError> {
+    let count = match options.remove(name) {
+        None => return Ok(None),
+        Some(Value::Int32(count)) => count as i64,
+        Some(Value::Int64(count)) => count,
+        Some(_) => -1,
+    };
+    u64::try_from(count)
+        .map(Some)
+        .map_err(|_| ShellError::usage(format!("{} must be a count", name)))
+}
+
+fn take_flag(options: &mut Document, name: &str) -> Result<bool, ShellError> {
+    match options.remove(name) {
+        None => Ok(false),
+        Some(Value::Boolean(flag)) => Ok(flag),
+        Some(_) => Err(ShellError::usage(format!("{} must be true or false", name))),
+    }
+}
+
+fn no_more(command: &str, options: Document) -> Result<(), ShellError> {
+    match options.iter().next() {
+        None => Ok(()),
+        Some((name, _)) => Err(ShellError::usage(format!(
+            "{} has no option {}",
+            command, name
+        ))),
+    }
+}
+
+/// Splits `text` into a command's arguments: Extended JSON values, each a
+/// document, an array or a quoted string, and bare words between them,
+/// which are taken as strings.
+///
+/// # Errors
+///
+/// Returns `Incomplete` if a document, array or string is left open, or a
+/// `Json` error if one does not parse.
+fn split(text: &str) -> Result<Vec<Value>, ShellError> {
+    let mut values = Vec::new();
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        let len = match rest.as_bytes()[0] {
+            b'{' | b'[' | b'"' => json_len(rest)?,
+            _ => rest.find(char::is_whitespace).unwrap_or(rest.len()),
+        };
+        let (arg, after) = rest.split_at(len);
+        values.push(match arg.as_bytes()[0] {
+            b'{' | b'[' | b'"' => from_json_value_str(arg)?,
+            _ => Value::from(arg),
+        });
+        rest = after.trim_start();
+    }
+    Ok(values)
+}
+
+/// Returns the length of the JSON document, array or string `text` starts
+/// with, found by matching brackets outside of strings.
+fn json_len(text: &str) -> Result<usize, ShellError> {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, byte) in text.bytes().enumerate() {
+        match (in_string, byte) {
+            (true, _) if escaped => escaped = false,
+            (true, b'\\') => escaped = true,
+            (true, b'"') => {
+                in_string = false;
+                if depth == 0 {
+                    return Ok(i + 1);
+                }
+            }
+            (true, _) => {}
+            (false, b'"') => in_string = true,
+            (false, b'{' | b'[') => depth += 1,
+            (false, b'}' | b']') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(i + 1);
+                }
+            }
+            (false, _) => {}
+        }
+    }
+    Err(ShellError::Incomplete(match in_string {
+        true => "string",
+        false => "document or array",
+    }))
+}
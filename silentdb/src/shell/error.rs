@@ -0,0 +1,43 @@
+// src/shell/error.rs
+
+use std::io;
+
+use rustyline::error::ReadlineError;
+use silentdb::{DatabaseError, QueryError};
+use silentdb_client::ClientError;
+use silentdb_data_encoding::{DeserializeError, SerializeError};
+
+/// Represents errors that can occur running shell commands.
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum ShellError {
+    #[error("{0}")]
+    Database(#[from] DatabaseError),
+    #[error("{0}")]
+    Client(#[from] ClientError),
+    #[error("{0}")]
+    Query(#[from] QueryError),
+    #[error("Bad JSON: {0}")]
+    Json(#[from] DeserializeError),
+    #[error("Serialization error: {0}")]
+    Serialize(#[from] SerializeError),
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Line editor error: {0}")]
+    Readline(#[from] ReadlineError),
+    #[error("{0}")]
+    Usage(String),
+    #[error("Unterminated {0}")]
+    Incomplete(&'static str),
+    #[error("line {line}: {source}")]
+    Line {
+        line: usize,
+        source: Box<ShellError>,
+    },
+}
+
+impl ShellError {
+    /// Creates a `Usage` error.
+    pub(crate) fn usage(message: impl ToString) -> Self {
+        ShellError::Usage(message.to_string())
+    }
+}
@@ -0,0 +1,234 @@
+// src/shell/mod.rs
+
+//! The shell behind the `silentdb` binary.
+//!
+//! Commands are read from `--eval`, else line by line from a terminal with
+//! history kept in `~/.silentdb_history`, else as a script from standard
+//! input. A document may span lines: the shell reads on until it closes.
+
+mod args;
+mod backend;
+mod command;
+mod error;
+mod output;
+mod test;
+
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+
+use args::USAGE;
+use backend::Backend;
+use command::{Command, HELP};
+use error::ShellError;
+use output::{write_documents, Format};
+
+const PROMPT: &str = "silentdb> ";
+const CONTINUATION: &str = "... ";
+const HISTORY_FILE: &str = ".silentdb_history";
+
+/// Runs the shell, returning how the process exits: 2 for bad arguments,
+/// 1 if a script or `--eval` command fails.
+pub(crate) fn main() -> ExitCode {
+    let args = match args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("silentdb: {}\n\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    if args.help {
+        print!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+    let interactive = args.eval.is_empty() && io::stdin().is_terminal();
+    let backend = match Backend::open(&args) {
+        Ok(backend) => backend,
+        Err(e) => {
+            eprintln!("silentdb: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let format = args.format.unwrap_or(match interactive {
+        true => Format::Table,
+        false => Format::Json,
+    });
+    let mut shell = Shell::new(backend, format);
+    let result = if !args.eval.is_empty() {
+        shell.eval(&args.eval, &mut io::stdout().lock())
+    } else if interactive {
+        shell.interact()
+    } else {
+        shell.script(io::stdin().lock(), &mut io::stdout().lock())
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("silentdb: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Runs commands against a backend, printing results in a format the
+/// `format` command changes.
+struct Shell {
+    backend: Backend,
+    format: Format,
+}
+
+impl Shell {
+    fn new(backend: Backend, format: Format) -> Self {
+        Shell { backend, format }
+    }
+
+    /// Runs the command `line`, writing what it prints to `out`. Returns
+    /// `false` once the command is `exit`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Incomplete` if `line` leaves a document open, or any error
+    /// parsing or running the command.
+    fn run(&mut self, line: &str, out: &mut impl Write) -> Result<bool, ShellError> {
+        let command = match command::parse(line)? {
+            Some(command) => command,
+            None => return Ok(true),
+        };
+        let backend = &self.backend;
+        let documents = match command {
+            Command::Find {
+                collection,
+                filter,
+                options,
+            } => backend.find(&collection, &filter, &options)?,
+            Command::Count { collection, filter } => vec![backend.count(&collection, &filter)?],
+            Command::Insert {
+                collection,
+                documents,
+            } => vec![backend.insert(&collection, documents)?],
+            Command::Update {
+                collection,
+                filter,
+                update,
+                multi,
+                upsert,
+            } => vec![backend.update(&collection, &filter, &update, multi, upsert)?],
+            Command::Delete {
+                collection,
+                filter,
+                multi,
+            } => vec![backend.delete(&collection, &filter, multi)?],
+            Command::Collections => backend.collections()?,
+            Command::Format(format) => {
+                self.format = format;
+                return Ok(true);
+            }
+            Command::Help => {
+                write!(out, "{}", HELP)?;
+                return Ok(true);
+            }
+            Command::Exit => return Ok(false),
+        };
+        write_documents(out, &documents, self.format)?;
+        Ok(true)
+    }
+
+    /// Runs each of `commands`, stopping at the first to fail.
+    fn eval(&mut self, commands: &[String], out: &mut impl Write) -> Result<(), ShellError> {
+        for command in commands {
+            if !self.run(command, out)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the commands of `input`, one a line but for documents spanning
+    /// several, stopping at the first to fail.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Line` error, numbering the line the failed command starts
+    /// on.
+    fn script(&mut self, input: impl BufRead, out: &mut impl Write) -> Result<(), ShellError> {
+        let mut pending = String::new();
+        let mut start = 0;
+        for (i, line) in input.lines().enumerate() {
+            if pending.is_empty() {
+                start = i + 1;
+            }
+            pending.push_str(&line?);
+            pending.push('\n');
+            match self.run(&pending, out) {
+                Ok(true) => pending.clear(),
+                Ok(false) => return Ok(()),
+                Err(ShellError::Incomplete(_)) => {}
+                Err(e) => {
+                    return Err(ShellError::Line {
+                        line: start,
+                        source: Box::new(e),
+                    })
+                }
+            }
+        }
+        match pending.is_empty() {
+            true => Ok(()),
+            false => Err(ShellError::Line {
+                line: start,
+                source: Box::new(
+                    self.run(&pending, out)
+                        .err()
+                        .unwrap_or(ShellError::Incomplete("document or array")),
+                ),
+            }),
+        }
+    }
+
+    /// Reads commands from the terminal until `exit` or end of input.
+    /// Ctrl-C abandons the command being typed.
+    fn interact(&mut self) -> Result<(), ShellError> {
+        let mut editor = DefaultEditor::new()?;
+        let history = std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE));
+        if let Some(history) = &history {
+            // There is no history yet on a first run
+            let _ = editor.load_history(history);
+        }
+        let mut out = io::stdout();
+        let mut pending = String::new();
+        loop {
+            let prompt = match pending.is_empty() {
+                true => PROMPT,
+                false => CONTINUATION,
+            };
+            let line = match editor.readline(prompt) {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) => {
+                    pending.clear();
+                    continue;
+                }
+                Err(ReadlineError::Eof) => break,
+                Err(e) => return Err(e.into()),
+            };
+            pending.push_str(&line);
+            pending.push('\n');
+            let result = self.run(&pending, &mut out);
+            if let Err(ShellError::Incomplete(_)) = result {
+                continue;
+            }
+            editor.add_history_entry(pending.trim_end())?;
+            pending.clear();
+            match result {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => eprintln!("error: {}", e),
+            }
+        }
+        if let Some(history) = &history {
+            editor.save_history(history)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,126 @@
+// src/shell/output.rs
+
+use std::io::Write;
+
+use silentdb_data_encoding::{Document, JsonSerializer, Serializer, Value};
+
+use super::error::ShellError;
+
+/// The longest a table cell is shown, in characters; longer values are cut
+/// short.
+const MAX_CELL_LEN: usize = 40;
+
+/// How the shell prints documents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Format {
+    /// A table with a column for each top-level field.
+    Table,
+    /// Relaxed Extended JSON, one document a line.
+    Json,
+}
+
+impl Format {
+    /// Returns the format named `name`, `table` or `json`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Usage` error for any other name.
+    pub(crate) fn from_name(name: &str) -> Result<Format, ShellError> {
+        match name {
+            "table" => Ok(Format::Table),
+            "json" => Ok(Format::Json),
+            _ => Err(ShellError::usage(format!(
+                "unknown format {:?}; use table or json",
+                name
+            ))),
+        }
+    }
+}
+
+/// Writes `documents` to `out` as `format` has them.
+///
+/// A table has a column for each top-level field, in the order they are
+/// first seen, with values shown as they display and cut short past
+/// `MAX_CELL_LEN` characters, and fields a document lacks left blank.
+///
+/// # Errors
+///
+/// Returns an error if `out` cannot be written to.
+pub(crate) fn write_documents(
+    out: &mut impl Write,
+    documents: &[Document],
+    format: Format,
+) -> Result<(), ShellError> {
+    match format {
+        Format::Json => {
+            for document in documents {
+                let mut serializer = JsonSerializer::new(&mut *out, false);
+                serializer.serialize_document(document)?;
+                writeln!(out)?;
+            }
+        }
+        Format::Table => {
+            if documents.is_empty() {
+                return Ok(());
+            }
+            let mut columns: Vec<&str> = Vec::new();
+            for document in documents {
+                for (field, _) in document.iter() {
+                    if !columns.contains(&field.as_str()) {
+                        columns.push(field.as_str());
+                    }
+                }
+            }
+            let rows: Vec<Vec<String>> = documents
+                .iter()
+                .map(|document| {
+                    columns
+                        .iter()
+                        .map(|column| document.get(column).map(cell).unwrap_or_default())
+                        .collect()
+                })
+                .collect();
+            let widths: Vec<usize> = columns
+                .iter()
+                .enumerate()
+                .map(|(i, column)| {
+                    rows.iter()
+                        .map(|row| row[i].chars().count())
+                        .chain([column.chars().count()])
+                        .max()
+                        .unwrap_or(0)
+                })
+                .collect();
+            let header: Vec<String> = columns.iter().map(|column| column.to_string()).collect();
+            write_row(out, &header, &widths)?;
+            let rule: Vec<String> = widths.iter().map(|width| "-".repeat(*width)).collect();
+            writeln!(out, "{}", rule.join("-+-"))?;
+            for row in &rows {
+                write_row(out, row, &widths)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn write_row(out: &mut impl Write, cells: &[String], widths: &[usize]) -> Result<(), ShellError> {
+    let padded: Vec<String> = cells
+        .iter()
+        .zip(widths)
+        .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+        .collect();
+    writeln!(out, "{}", padded.join(" | ").trim_end())?;
+    Ok(())
+}
+
+/// Returns `value` as a table shows it.
+fn cell(value: &Value) -> String {
+    let text = value.to_string().replace(['\n', '\r', '\t'], " ");
+    match text.chars().count() > MAX_CELL_LEN {
+        true => {
+            let cut: String = text.chars().take(MAX_CELL_LEN - 1).collect();
+            format!("{}…", cut)
+        }
+        false => text,
+    }
+}
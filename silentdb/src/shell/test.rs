@@ -0,0 +1,234 @@
+// src/shell/test.rs
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use silentdb::Database;
+    use silentdb_data_encoding::{from_json_str, Document, Value};
+
+    use crate::shell::args::{self, Target};
+    use crate::shell::backend::Backend;
+    use crate::shell::command::{self, Command, FindArgs};
+    use crate::shell::error::ShellError;
+    use crate::shell::output::{write_documents, Format};
+    use crate::shell::Shell;
+
+    /// Returns an empty scratch directory unique to this process and `name`.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("silentdb-shell-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn doc(json: &str) -> Document {
+        from_json_str(json).unwrap()
+    }
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_shell_args() {
+        let parsed = args::parse(strings(&["--dir", "/tmp/data", "--format", "json"])).unwrap();
+        assert_eq!(parsed.target, Target::Directory(PathBuf::from("/tmp/data")));
+        assert_eq!(parsed.format, Some(Format::Json));
+
+        let parsed = args::parse(strings(&[
+            "db.example.com:27117",
+            "--user",
+            "ada",
+            "--eval",
+            "count users",
+            "--eval",
+            "collections",
+        ]))
+        .unwrap();
+        assert_eq!(
+            parsed.target,
+            Target::Server("db.example.com:27117".to_string())
+        );
+        assert_eq!(parsed.user.as_deref(), Some("ada"));
+        assert_eq!(parsed.eval, vec!["count users", "collections"]);
+
+        for bad in [
+            &["--user"][..],
+            &["--frobnicate"],
+            &["--dir", "/tmp", "127.0.0.1:1"],
+            &["--format", "xml"],
+        ] {
+            assert!(matches!(
+                args::parse(strings(bad)),
+                Err(ShellError::Usage(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_shell_parse() {
+        assert_eq!(command::parse("   ").unwrap(), None);
+        assert_eq!(command::parse("# a comment").unwrap(), None);
+        assert_eq!(
+            command::parse(r#"find users {"age": {"$gt": 30}} {"sort": {"age": -1}, "limit": 2}"#)
+                .unwrap(),
+            Some(Command::Find {
+                collection: "users".to_string(),
+                filter: doc(r#"{"age": {"$gt": 30}}"#),
+                options: FindArgs {
+                    sort: Some(doc(r#"{"age": -1}"#)),
+                    projection: None,
+                    skip: 0,
+                    limit: Some(2),
+                },
+            })
+        );
+        assert_eq!(
+            command::parse(r#"insert users [{"name": "a } ["}, {"name": "b"}]"#).unwrap(),
+            Some(Command::Insert {
+                collection: "users".to_string(),
+                documents: vec![doc(r#"{"name": "a } ["}"#), doc(r#"{"name": "b"}"#)],
+            })
+        );
+
+        // SQL statements run as the find they stand for
+        let find = Command::Find {
+            collection: "users".to_string(),
+            filter: doc(r#"{"name": "a b"}"#),
+            options: FindArgs {
+                sort: Some(doc(r#"{"age": 1}"#)),
+                projection: Some(doc(r#"{"age": 1, "_id": 0}"#)),
+                skip: 0,
+                limit: Some(3),
+            },
+        };
+        let sql = "SELECT age FROM users WHERE name = 'a b' ORDER BY age LIMIT 3";
+        assert_eq!(command::parse(sql).unwrap(), Some(find.clone()));
+        assert_eq!(command::parse(&format!("sql {}", sql)).unwrap(), Some(find));
+        assert!(matches!(
+            command::parse("sql SELECT * FROM"),
+            Err(ShellError::Query(_))
+        ));
+
+        // Open documents and strings ask for more input
+        assert!(matches!(
+            command::parse(r#"insert users {"name": "#),
+            Err(ShellError::Incomplete(_))
+        ));
+        assert!(matches!(
+            command::parse(r#"insert users {"name": "a}"#),
+            Err(ShellError::Incomplete("string"))
+        ));
+
+        for bad in [
+            "frobnicate users",
+            "find",
+            "find users 42",
+            r#"find users {} {"skip": -1}"#,
+            r#"update users {"a": 1}"#,
+            r#"delete users {} {"mutli": true}"#,
+            "count users {} extra",
+        ] {
+            assert!(
+                matches!(command::parse(bad), Err(ShellError::Usage(_))),
+                "{}",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn test_shell_output() {
+        let documents = vec![
+            doc(r#"{"_id": 1, "name": "ada"}"#),
+            doc(r#"{"_id": 2, "city": "London"}"#),
+        ];
+        let mut out = Vec::new();
+        write_documents(&mut out, &documents, Format::Table).unwrap();
+        let table = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("_id | name | city"));
+        assert!(lines[1].starts_with("----+-"));
+        assert!(lines[3].contains("London"));
+
+        // Long values are cut short
+        let mut long = Document::new();
+        long.insert("text", "x".repeat(100));
+        let mut out = Vec::new();
+        write_documents(&mut out, &[long], Format::Table).unwrap();
+        let table = String::from_utf8(out).unwrap();
+        assert!(table.lines().all(|line| line.chars().count() <= 40));
+        assert!(table.contains('…'));
+
+        let mut out = Vec::new();
+        write_documents(&mut out, &documents, Format::Json).unwrap();
+        let json = String::from_utf8(out).unwrap();
+        let parsed: Vec<Document> = json.lines().map(doc).collect();
+        assert_eq!(parsed, documents);
+    }
+
+    #[test]
+    fn test_shell_script() {
+        let dir = scratch_dir("script");
+        let db = Database::open(&dir).unwrap();
+        let mut shell = Shell::new(Backend::Local(db.clone()), Format::Json);
+
+        let script = r#"
+            # Seed some users
+            insert users [
+                {"_id": 1, "name": "ada", "age": 36},
+                {"_id": 2, "name": "alan", "age": 41},
+                {"_id": 3, "name": "grace", "age": 85}
+            ]
+            update users {"age": {"$gt": 40}} {"$inc": {"age": 1}} {"multi": true}
+            delete users {"_id": 1}
+            find users {} {"sort": {"age": -1}, "skip": 1, "limit": 5, "projection": {"name": 1}}
+        "#;
+        let mut out = Vec::new();
+        shell.script(script.as_bytes(), &mut out).unwrap();
+        let lines: Vec<Document> = String::from_utf8(out).unwrap().lines().map(doc).collect();
+        // Relaxed JSON reads small counts back as Int32
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0].get("insertedCount"), Some(&Value::Int32(3)));
+        assert_eq!(lines[1].get("modifiedCount"), Some(&Value::Int32(2)));
+        assert_eq!(lines[2].get("deletedCount"), Some(&Value::Int32(1)));
+        assert_eq!(lines[3], doc(r#"{"_id": 2, "name": "alan"}"#));
+        assert_eq!(db.collection("users").count(&Document::new()).unwrap(), 2);
+
+        // Commands after exit are not run
+        let mut out = Vec::new();
+        shell
+            .script(
+                "exit\ndelete users {} {\"multi\": true}\n".as_bytes(),
+                &mut out,
+            )
+            .unwrap();
+        assert_eq!(db.collection("users").count(&Document::new()).unwrap(), 2);
+
+        // Errors name the line their command starts on
+        let mut out = Vec::new();
+        let err = shell
+            .script(
+                "count users\n\nupdate users\n  {} {\"a\": 1} {\"multi\": true}\n".as_bytes(),
+                &mut out,
+            )
+            .unwrap_err();
+        assert!(matches!(err, ShellError::Line { line: 3, .. }), "{}", err);
+        let err = shell
+            .script("count users\ninsert users {\"a\":\n".as_bytes(), &mut out)
+            .unwrap_err();
+        assert!(matches!(err, ShellError::Line { line: 2, .. }), "{}", err);
+
+        // The format command switches how results print
+        let mut out = Vec::new();
+        shell
+            .eval(&strings(&["format table", "collections"]), &mut out)
+            .unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "name\n-----\nusers\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
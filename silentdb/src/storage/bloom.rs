@@ -0,0 +1,81 @@
+// src/storage/bloom.rs
+
+/// A Bloom filter over byte strings.
+///
+/// `may_contain` never returns `false` for an inserted item, and returns
+/// `true` for other items at a rate set by the bits per item: about 1% at
+/// the default of 10.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct BloomFilter {
+    bits: Vec<u8>,
+    hashes: u32,
+}
+
+impl BloomFilter {
+    /// Creates an empty filter sized for `expected` items at `bits_per_item`.
+    pub(crate) fn new(expected: usize, bits_per_item: usize) -> Self {
+        let bits = (expected * bits_per_item).max(64);
+        // k = ln 2 * m / n minimizes the false positive rate
+        let hashes = ((bits_per_item as f64 * std::f64::consts::LN_2).round() as u32).clamp(1, 30);
+        BloomFilter {
+            bits: vec![0; bits.div_ceil(8)],
+            hashes,
+        }
+    }
+
+    /// Adds `item` to the filter.
+    pub(crate) fn insert(&mut self, item: &[u8]) {
+        for bit in self.probes(item) {
+            self.bits[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+
+    /// Returns `false` if `item` was definitely never inserted.
+    pub(crate) fn may_contain(&self, item: &[u8]) -> bool {
+        self.probes(item)
+            .all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    /// Encodes the filter as its hash count followed by its bits.
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(1 + self.bits.len());
+        bytes.push(self.hashes as u8);
+        bytes.extend_from_slice(&self.bits);
+        bytes
+    }
+
+    /// Decodes a filter written by `to_bytes`.
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        match bytes {
+            [hashes @ 1..=30, bits @ ..] if !bits.is_empty() => Some(BloomFilter {
+                bits: bits.to_vec(),
+                hashes: *hashes as u32,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Returns the bit positions for `item`, derived from one 64-bit hash
+    /// by double hashing.
+    fn probes(&self, item: &[u8]) -> impl Iterator<Item = usize> {
+        let hash = hash64(item);
+        let h1 = hash & 0xFFFF_FFFF;
+        let h2 = hash >> 32 | 1;
+        let len = self.bits.len() as u64 * 8;
+        (0..self.hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+    }
+}
+
+/// FNV-1a followed by a SplitMix64 finalizer to spread the low bits.
+pub(crate) fn hash64(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xCBF2_9CE4_8422_2325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01B3);
+    }
+    hash ^= hash >> 30;
+    hash = hash.wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    hash ^= hash >> 27;
+    hash = hash.wrapping_mul(0x94D0_49BB_1331_11EB);
+    hash ^ hash >> 31
+}
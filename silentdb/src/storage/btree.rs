@@ -0,0 +1,623 @@
+// src/storage/btree.rs
+
+use std::collections::HashSet;
+use std::ops::Bound;
+
+use super::engine::Entry;
+use super::error::StorageError;
+use super::pager::{PageId, Pager, ENCRYPTED_PAYLOAD_LEN, PAGE_PAYLOAD_LEN};
+
+/// Largest combined key and value length a `BTree` accepts, chosen so that
+/// every node holds at least four entries, even in an encrypted file.
+pub const MAX_ENTRY_LEN: usize = ENCRYPTED_PAYLOAD_LEN / 4 - 16;
+
+/// Nodes smaller than this after a removal are merged with or refilled from a sibling.
+const MIN_NODE_LEN: usize = PAGE_PAYLOAD_LEN / 4;
+
+const LEAF: u8 = 1;
+const INTERNAL: u8 = 2;
+/// Node type byte, entry count and the next-leaf or first-child pointer.
+const NODE_HEADER_LEN: usize = 11;
+
+/// A B+tree of byte keys and values stored in the pages of a `Pager`.
+///
+/// Keys are ordered bytewise, so keys produced by `encode_key` come back in
+/// BSON order. Values live in the leaves, which are chained for range
+/// scans. Nodes split when they outgrow a page and are merged with or
+/// refilled from a sibling when a removal leaves them less than a quarter
+/// full. The root always stays on the page it was created on, so a tree is
+/// identified by its root page alone.
+///
+/// The tree does not own the pager, so several trees can share one file.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use silentdb::storage::{BTree, Pager};
+/// use std::ops::Bound;
+///
+/// let mut pager = Pager::open("index.db").unwrap();
+/// let tree = BTree::create(&mut pager).unwrap();
+/// tree.insert(&mut pager, b"apple", b"1").unwrap();
+/// tree.insert(&mut pager, b"banana", b"2").unwrap();
+///
+/// assert_eq!(tree.get(&pager, b"apple").unwrap(), Some(b"1".to_vec()));
+/// let keys: Vec<_> = tree
+///     .range(&pager, Bound::Included(&b"b"[..]), Bound::Unbounded)
+///     .unwrap()
+///     .map(|entry| entry.unwrap().0)
+///     .collect();
+/// assert_eq!(keys, vec![b"banana".to_vec()]);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BTree {
+    root: PageId,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Leaf {
+        entries: Vec<Entry>,
+        next: PageId,
+    },
+    /// `keys[i]` is the smallest key under `children[i + 1]`.
+    Internal {
+        keys: Vec<Vec<u8>>,
+        children: Vec<PageId>,
+    },
+}
+
+/// A node split in two: the separator key and the page of the new right half.
+type Split = Option<(Vec<u8>, PageId)>;
+
+impl BTree {
+    /// Creates an empty tree on a newly allocated page.
+    pub fn create(pager: &mut Pager) -> Result<BTree, StorageError> {
+        let root = pager.allocate()?;
+        let tree = BTree { root };
+        tree.write(pager, root, &Node::empty_leaf())?;
+        Ok(tree)
+    }
+
+    /// Opens the tree whose root is on page `root`.
+    pub fn open(root: PageId) -> BTree {
+        BTree { root }
+    }
+
+    /// Returns the page the root is stored on.
+    pub fn root(&self) -> PageId {
+        self.root
+    }
+
+    /// Returns the value stored under `key`.
+    pub fn get(&self, pager: &Pager, key: &[u8]) -> Result<Option<Vec<u8>>, StorageError> {
+        let mut page = self.root;
+        loop {
+            match self.read(pager, page)? {
+                Node::Leaf { entries, .. } => {
+                    return Ok(entries
+                        .binary_search_by(|(k, _)| k.as_slice().cmp(key))
+                        .ok()
+                        .map(|i| entries[i].1.clone()))
+                }
+                Node::Internal { keys, children } => page = children[child_index(&keys, key)],
+            }
+        }
+    }
+
+    /// Stores `value` under `key`, returning the value it replaced.
+    ///
+    /// # Errors
+    ///
+    /// Returns `KeyTooLarge` if the key and value together are longer than `MAX_ENTRY_LEN`.
+    pub fn insert(
+        &self,
+        pager: &mut Pager,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<Option<Vec<u8>>, StorageError> {
+        if key.len() + value.len() > MAX_ENTRY_LEN {
+            return Err(StorageError::KeyTooLarge {
+                len: key.len() + value.len(),
+                max: MAX_ENTRY_LEN,
+            });
+        }
+
+        let (previous, split) = self.insert_at(pager, self.root, key, value)?;
+        if let Some((separator, right)) = split {
+            // Move the left half off the root page so the root keeps its id
+            let left = pager.allocate()?;
+            let left_node = self.read(pager, self.root)?;
+            self.write(pager, left, &left_node)?;
+            let root = Node::Internal {
+                keys: vec![separator],
+                children: vec![left, right],
+            };
+            self.write(pager, self.root, &root)?;
+        }
+        Ok(previous)
+    }
+
+    /// Removes `key`, returning its value if it was present.
+    pub fn remove(&self, pager: &mut Pager, key: &[u8]) -> Result<Option<Vec<u8>>, StorageError> {
+        let (removed, _) = self.remove_at(pager, self.root, key)?;
+
+        // Collapse a root left with a single child into the root page
+        while let Node::Internal { keys, children } = self.read(pager, self.root)? {
+            if !keys.is_empty() {
+                break;
+            }
+            let child = self.read(pager, children[0])?;
+            self.write(pager, self.root, &child)?;
+            pager.free(children[0])?;
+        }
+        Ok(removed)
+    }
+
+    /// Returns the entries with keys between `start` and `end`, in key order.
+    pub fn range<'a>(
+        &self,
+        pager: &'a Pager,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+    ) -> Result<BTreeRange<'a>, StorageError> {
+        // Descend to the leaf that would hold the start key
+        let mut page = self.root;
+        let (entries, next) = loop {
+            match self.read(pager, page)? {
+                Node::Leaf { entries, next } => break (entries, next),
+                Node::Internal { keys, children } => {
+                    page = match start {
+                        Bound::Included(key) | Bound::Excluded(key) => {
+                            children[child_index(&keys, key)]
+                        }
+                        Bound::Unbounded => children[0],
+                    }
+                }
+            }
+        };
+
+        let skip = match start {
+            Bound::Included(key) => entries.partition_point(|(k, _)| k.as_slice() < key),
+            Bound::Excluded(key) => entries.partition_point(|(k, _)| k.as_slice() <= key),
+            Bound::Unbounded => 0,
+        };
+        let mut entries = entries.into_iter();
+        if skip > 0 {
+            entries.nth(skip - 1);
+        }
+        Ok(BTreeRange {
+            tree: *self,
+            pager,
+            entries,
+            next,
+            end: end.map(|key| key.to_vec()),
+            done: false,
+        })
+    }
+
+    /// Frees every page of the tree, including the root.
+    pub fn destroy(self, pager: &mut Pager) -> Result<(), StorageError> {
+        let mut pages = vec![self.root];
+        while let Some(page) = pages.pop() {
+            if let Node::Internal { children, .. } = self.read(pager, page)? {
+                pages.extend(children);
+            }
+            pager.free(page)?;
+        }
+        Ok(())
+    }
+
+    /// Returns every page of the tree, root first, reading each one from
+    /// the file as `Pager::verify` does.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Corrupt` if a page fails its checksum, does not hold a
+    /// node, or is linked into the tree more than once.
+    pub fn pages(&self, pager: &Pager) -> Result<Vec<PageId>, StorageError> {
+        let mut pages = Vec::new();
+        let mut seen = HashSet::new();
+        let mut unread = vec![self.root];
+        while let Some(page) = unread.pop() {
+            if !seen.insert(page) {
+                return Err(StorageError::corrupt(
+                    format!("b+tree page {}", page),
+                    "linked in twice",
+                ));
+            }
+            let node = Node::decode(&pager.verify(page)?).ok_or_else(|| {
+                StorageError::corrupt(format!("b+tree page {}", page), "invalid node")
+            })?;
+            if let Node::Internal { children, .. } = node {
+                unread.extend(children);
+            }
+            pages.push(page);
+        }
+        Ok(pages)
+    }
+
+    fn insert_at(
+        &self,
+        pager: &mut Pager,
+        page: PageId,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<(Option<Vec<u8>>, Split), StorageError> {
+        match self.read(pager, page)? {
+            Node::Leaf { mut entries, next } => {
+                let previous = match entries.binary_search_by(|(k, _)| k.as_slice().cmp(key)) {
+                    Ok(i) => Some(std::mem::replace(&mut entries[i].1, value.to_vec())),
+                    Err(i) => {
+                        entries.insert(i, (key.to_vec(), value.to_vec()));
+                        None
+                    }
+                };
+                let split = self.store(pager, page, Node::Leaf { entries, next })?;
+                Ok((previous, split))
+            }
+            Node::Internal {
+                mut keys,
+                mut children,
+            } => {
+                let index = child_index(&keys, key);
+                let (previous, split) = self.insert_at(pager, children[index], key, value)?;
+                let Some((separator, right)) = split else {
+                    return Ok((previous, None));
+                };
+                keys.insert(index, separator);
+                children.insert(index + 1, right);
+                let split = self.store(pager, page, Node::Internal { keys, children })?;
+                Ok((previous, split))
+            }
+        }
+    }
+
+    /// Writes `node` to `page`, splitting it first if it no longer fits.
+    fn store(&self, pager: &mut Pager, page: PageId, node: Node) -> Result<Split, StorageError> {
+        if node.encoded_len() <= pager.payload_len() {
+            self.write(pager, page, &node)?;
+            return Ok(None);
+        }
+        let right_page = pager.allocate()?;
+        let (left, separator, right) = node.split(right_page);
+        self.write(pager, page, &left)?;
+        self.write(pager, right_page, &right)?;
+        Ok(Some((separator, right_page)))
+    }
+
+    /// Removes `key` below `page`, returning the removed value and whether
+    /// the node on `page` is now underfull.
+    fn remove_at(
+        &self,
+        pager: &mut Pager,
+        page: PageId,
+        key: &[u8],
+    ) -> Result<(Option<Vec<u8>>, bool), StorageError> {
+        match self.read(pager, page)? {
+            Node::Leaf { mut entries, next } => {
+                let Ok(i) = entries.binary_search_by(|(k, _)| k.as_slice().cmp(key)) else {
+                    return Ok((None, false));
+                };
+                let (_, removed) = entries.remove(i);
+                let node = Node::Leaf { entries, next };
+                self.write(pager, page, &node)?;
+                Ok((Some(removed), node.encoded_len() < MIN_NODE_LEN))
+            }
+            Node::Internal {
+                mut keys,
+                mut children,
+            } => {
+                let index = child_index(&keys, key);
+                let (removed, underfull) = self.remove_at(pager, children[index], key)?;
+                if !underfull {
+                    return Ok((removed, false));
+                }
+                self.rebalance(pager, &mut keys, &mut children, index)?;
+                let node = Node::Internal { keys, children };
+                self.write(pager, page, &node)?;
+                Ok((removed, node.encoded_len() < MIN_NODE_LEN))
+            }
+        }
+    }
+
+    /// Merges the underfull child at `index` with a sibling, or moves
+    /// entries over from the sibling if the two don't fit in one page.
+    fn rebalance(
+        &self,
+        pager: &mut Pager,
+        keys: &mut Vec<Vec<u8>>,
+        children: &mut Vec<PageId>,
+        index: usize,
+    ) -> Result<(), StorageError> {
+        if children.len() < 2 {
+            return Ok(());
+        }
+        let left = if index > 0 { index - 1 } else { index };
+        let (left_page, right_page) = (children[left], children[left + 1]);
+        let merged = match (self.read(pager, left_page)?, self.read(pager, right_page)?) {
+            (
+                Node::Leaf {
+                    entries: mut left_entries,
+                    ..
+                },
+                Node::Leaf {
+                    entries: right_entries,
+                    next,
+                },
+            ) => {
+                left_entries.extend(right_entries);
+                Node::Leaf {
+                    entries: left_entries,
+                    next,
+                }
+            }
+            (
+                Node::Internal {
+                    keys: mut left_keys,
+                    children: mut left_children,
+                },
+                Node::Internal {
+                    keys: right_keys,
+                    children: right_children,
+                },
+            ) => {
+                left_keys.push(keys[left].clone());
+                left_keys.extend(right_keys);
+                left_children.extend(right_children);
+                Node::Internal {
+                    keys: left_keys,
+                    children: left_children,
+                }
+            }
+            _ => {
+                return Err(StorageError::corrupt(
+                    format!("b+tree page {}", left_page),
+                    "siblings at different depths",
+                ))
+            }
+        };
+
+        if merged.encoded_len() <= pager.payload_len() {
+            self.write(pager, left_page, &merged)?;
+            pager.free(right_page)?;
+            keys.remove(left);
+            children.remove(left + 1);
+        } else {
+            let (left_node, separator, right_node) = merged.split(right_page);
+            self.write(pager, left_page, &left_node)?;
+            self.write(pager, right_page, &right_node)?;
+            keys[left] = separator;
+        }
+        Ok(())
+    }
+
+    fn read(&self, pager: &Pager, page: PageId) -> Result<Node, StorageError> {
+        Node::decode(&pager.read(page)?)
+            .ok_or_else(|| StorageError::corrupt(format!("b+tree page {}", page), "invalid node"))
+    }
+
+    fn write(&self, pager: &mut Pager, page: PageId, node: &Node) -> Result<(), StorageError> {
+        pager.write(page, &node.encode())
+    }
+}
+
+/// Returns the index of the child whose subtree holds `key`.
+fn child_index(keys: &[Vec<u8>], key: &[u8]) -> usize {
+    keys.partition_point(|k| k.as_slice() <= key)
+}
+
+impl Node {
+    fn empty_leaf() -> Node {
+        Node::Leaf {
+            entries: Vec::new(),
+            next: 0,
+        }
+    }
+
+    fn encoded_len(&self) -> usize {
+        NODE_HEADER_LEN
+            + match self {
+                Node::Leaf { entries, .. } => {
+                    entries.iter().map(|(k, v)| 4 + k.len() + v.len()).sum()
+                }
+                Node::Internal { keys, .. } => keys.iter().map(|k| 10 + k.len()).sum::<usize>(),
+            }
+    }
+
+    /// Splits an overfull node into two halves of roughly equal size,
+    /// returning the left half, the separator and the right half, which
+    /// will be stored on `right_page`.
+    fn split(self, right_page: PageId) -> (Node, Vec<u8>, Node) {
+        let half = self.encoded_len() / 2;
+        match self {
+            Node::Leaf { mut entries, next } => {
+                let mut size = NODE_HEADER_LEN;
+                let mut mid = 0;
+                while mid < entries.len() - 1 && size < half {
+                    size += 4 + entries[mid].0.len() + entries[mid].1.len();
+                    mid += 1;
+                }
+                let right = entries.split_off(mid.max(1));
+                let separator = right[0].0.clone();
+                (
+                    Node::Leaf {
+                        entries,
+                        next: right_page,
+                    },
+                    separator,
+                    Node::Leaf {
+                        entries: right,
+                        next,
+                    },
+                )
+            }
+            Node::Internal {
+                mut keys,
+                mut children,
+            } => {
+                let mut size = NODE_HEADER_LEN;
+                let mut mid = 0;
+                while mid < keys.len() - 2 && size < half {
+                    size += 10 + keys[mid].len();
+                    mid += 1;
+                }
+                let mid = mid.max(1);
+                let right_keys = keys.split_off(mid + 1);
+                let separator = keys.pop().unwrap();
+                let right_children = children.split_off(mid + 1);
+                (
+                    Node::Internal { keys, children },
+                    separator,
+                    Node::Internal {
+                        keys: right_keys,
+                        children: right_children,
+                    },
+                )
+            }
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.encoded_len());
+        match self {
+            Node::Leaf { entries, next } => {
+                out.push(LEAF);
+                out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+                out.extend_from_slice(&next.to_le_bytes());
+                for (key, value) in entries {
+                    out.extend_from_slice(&(key.len() as u16).to_le_bytes());
+                    out.extend_from_slice(key);
+                    out.extend_from_slice(&(value.len() as u16).to_le_bytes());
+                    out.extend_from_slice(value);
+                }
+            }
+            Node::Internal { keys, children } => {
+                out.push(INTERNAL);
+                out.extend_from_slice(&(keys.len() as u16).to_le_bytes());
+                out.extend_from_slice(&children[0].to_le_bytes());
+                for (key, child) in keys.iter().zip(&children[1..]) {
+                    out.extend_from_slice(&(key.len() as u16).to_le_bytes());
+                    out.extend_from_slice(key);
+                    out.extend_from_slice(&child.to_le_bytes());
+                }
+            }
+        }
+        out
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Node> {
+        let mut reader = NodeReader { bytes, position: 0 };
+        let kind = reader.take(1)?[0];
+        let count = reader.u16()? as usize;
+        let pointer = reader.u64()?;
+        match kind {
+            LEAF => {
+                let mut entries = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let key = reader.slice()?.to_vec();
+                    let value = reader.slice()?.to_vec();
+                    entries.push((key, value));
+                }
+                Some(Node::Leaf {
+                    entries,
+                    next: pointer,
+                })
+            }
+            INTERNAL => {
+                let mut keys = Vec::with_capacity(count);
+                let mut children = Vec::with_capacity(count + 1);
+                children.push(pointer);
+                for _ in 0..count {
+                    keys.push(reader.slice()?.to_vec());
+                    children.push(reader.u64()?);
+                }
+                Some(Node::Internal { keys, children })
+            }
+            _ => None,
+        }
+    }
+}
+
+struct NodeReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> NodeReader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.bytes.get(self.position..self.position + len)?;
+        self.position += len;
+        Some(bytes)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.take(2)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn slice(&mut self) -> Option<&'a [u8]> {
+        let len = self.u16()? as usize;
+        self.take(len)
+    }
+}
+
+/// An iterator over a key range of a `BTree`, returned by `BTree::range`.
+///
+/// The iterator is fused after the first error.
+#[derive(Debug)]
+pub struct BTreeRange<'a> {
+    tree: BTree,
+    pager: &'a Pager,
+    entries: std::vec::IntoIter<Entry>,
+    next: PageId,
+    end: Bound<Vec<u8>>,
+    done: bool,
+}
+
+impl Iterator for BTreeRange<'_> {
+    type Item = Result<Entry, StorageError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            if let Some((key, value)) = self.entries.next() {
+                let in_range = match &self.end {
+                    Bound::Included(end) => key <= *end,
+                    Bound::Excluded(end) => key < *end,
+                    Bound::Unbounded => true,
+                };
+                if !in_range {
+                    self.done = true;
+                    return None;
+                }
+                return Some(Ok((key, value)));
+            }
+
+            if self.next == 0 {
+                self.done = true;
+                return None;
+            }
+            match self.tree.read(self.pager, self.next) {
+                Ok(Node::Leaf { entries, next }) => {
+                    self.entries = entries.into_iter();
+                    self.next = next;
+                }
+                Ok(Node::Internal { .. }) => {
+                    self.done = true;
+                    return Some(Err(StorageError::corrupt(
+                        format!("b+tree page {}", self.next),
+                        "leaf chain points at an internal node",
+                    )));
+                }
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+        None
+    }
+}
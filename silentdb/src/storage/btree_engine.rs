@@ -0,0 +1,1027 @@
+// src/storage/btree_engine.rs
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+
+use super::btree::BTree;
+use super::buffer_pool::{CacheStats, DEFAULT_CACHE_SIZE};
+use super::compression::{Codec, Compression};
+use super::crc32;
+use super::encryption::Encryption;
+use super::engine::{CompactionStep, EngineStats, Entry, KeyRange, StorageEngine, StorageIssue};
+use super::error::StorageError;
+use super::mmap::MappedFile;
+use super::pager::{read_exact_at, write_all_at, PageId, Pager};
+
+/// Name of the paged file holding the trees.
+const INDEX_FILE: &str = "data.db";
+/// Name of the append-only file holding values too large to keep in a leaf.
+const DOCUMENTS_FILE: &str = "documents.dat";
+/// Name of the file live records are copied to while compacting, which
+/// replaces the documents file once every record is in it.
+const COMPACT_FILE: &str = "documents.compact";
+/// Name of the append-only file holding every compression dictionary
+/// trained, oldest first, as checksummed records.
+const DICTIONARIES_FILE: &str = "dictionaries.dat";
+
+/// Values up to this length are stored directly in the tree's leaves.
+const INLINE_MAX: usize = 64;
+const INLINE: u8 = 0;
+/// Tags of values in a records file. Which one names the documents file
+/// flips with each compaction; while one runs, the other names the file
+/// records are being copied to.
+const LOCATED: u8 = 1;
+const RELOCATED: u8 = 2;
+
+/// Offsets in the paged file's meta area of the catalog root, the tag of
+/// values in the documents file and the flag marking a compaction as
+/// running.
+const META_CATALOG: usize = 0;
+const META_TAG: usize = 8;
+const META_COMPACTING: usize = 9;
+const META_USED: usize = 10;
+/// Length and CRC-32 written before every record in the documents file.
+const RECORD_HEADER_LEN: usize = 8;
+/// Set in a record's length when the record is compressed.
+const COMPRESSED: u32 = 1 << 31;
+/// Set in a record's length when the record is encrypted.
+const ENCRYPTED: u32 = 1 << 30;
+const RECORD_FLAGS: u32 = COMPRESSED | ENCRYPTED;
+/// The documents file is mapped again once this many bytes, or a quarter
+/// of what is mapped if that is more, have been appended past the mapping.
+const REMAP_MIN_GROWTH: u64 = 1 << 20;
+
+/// Where a value is stored in the documents file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DocumentLocation {
+    /// Offset of the record's header.
+    pub offset: u64,
+    /// Length of the value as stored, excluding the record header.
+    pub len: u32,
+}
+
+/// Options for opening a `BTreeEngine`.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb::storage::{BTreeOptions, Compression};
+/// let options = BTreeOptions::new()
+///     .compression(Compression::new())
+///     .cache_size(64 * 1024 * 1024);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BTreeOptions {
+    pub(crate) compression: Option<Compression>,
+    pub(crate) encryption: Option<Encryption>,
+    pub(crate) cache_size: usize,
+}
+
+impl BTreeOptions {
+    /// Creates options storing values uncompressed and unencrypted, with
+    /// a cache of `DEFAULT_CACHE_SIZE` bytes.
+    pub fn new() -> Self {
+        BTreeOptions {
+            compression: None,
+            encryption: None,
+            cache_size: DEFAULT_CACHE_SIZE,
+        }
+    }
+
+    /// Sets how many bytes of tree pages the engine keeps in memory. See
+    /// `Pager::set_cache_size`.
+    pub fn cache_size(mut self, bytes: usize) -> Self {
+        self.cache_size = bytes;
+        self
+    }
+
+    /// Compresses the values written to the documents file. Values already
+    /// written stay as they are until the documents file is compacted.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Encrypts the engine's files. The paged file can only be encrypted
+    /// when it is created; records in the documents file are encrypted
+    /// when written, and by compaction.
+    pub fn encryption(mut self, encryption: Encryption) -> Self {
+        self.encryption = Some(encryption);
+        self
+    }
+}
+
+impl Default for BTreeOptions {
+    fn default() -> Self {
+        BTreeOptions::new()
+    }
+}
+
+/// A `StorageEngine` keeping each namespace in an on-disk `BTree`.
+///
+/// Small values are stored in the tree's leaves; larger ones, such as
+/// most documents, are appended to a separate documents file as
+/// checksummed records and the tree maps their key to a
+/// `DocumentLocation`. Replaced and deleted records are left in place
+/// until the documents file is compacted.
+///
+/// Compaction copies the live records to a new file a batch at a time,
+/// pointing their keys at the copies as it goes, while new values are
+/// written to the new file; once every record has been copied the new
+/// file replaces the documents file. A compaction interrupted by a crash
+/// carries on when the engine is next compacted.
+///
+/// A catalog tree, found through the paged file's meta area, maps
+/// namespace names to the root pages of their trees.
+///
+/// With the `zstd` feature and compression turned on in `BTreeOptions`,
+/// records are compressed where that makes them smaller, which a flag in
+/// the record's header marks. Compressed records are read whatever the
+/// options, and compaction rewrites every record it copies as the current
+/// options say.
+///
+/// With encryption turned on in `BTreeOptions`, every page of the paged
+/// file is encrypted, as are records written to the documents file and
+/// compression dictionaries, after compression. Records name the key they
+/// were encrypted with, so after a key rotation compaction re-encrypts the
+/// documents file with the current key.
+///
+/// With the `memmap2` feature, the documents file is memory-mapped, and
+/// `get_ref` returns records straight from the mapping without copying
+/// them. Records appended since the file was last mapped, and every record
+/// where mapping is unavailable, are read from the file instead.
+#[derive(Debug)]
+pub struct BTreeEngine {
+    dir: PathBuf,
+    pager: Pager,
+    catalog: BTree,
+    documents: File,
+    documents_len: u64,
+    mapped: MappedFile,
+    /// The tag of values in the documents file.
+    tag: u8,
+    compaction: Option<Compaction>,
+    /// Bytes of the documents file held by records no key points to, since
+    /// the engine was opened or last compacted.
+    dead: u64,
+    codec: Codec,
+    dictionaries: File,
+    dictionaries_len: u64,
+    encryption: Option<Encryption>,
+}
+
+/// The state of a running compaction.
+#[derive(Debug)]
+struct Compaction {
+    file: File,
+    len: u64,
+    /// Bytes of the compaction file held by records no key points to,
+    /// counted from zero again when an interrupted compaction is resumed
+    /// on opening.
+    dead: u64,
+    /// The namespace to copy records from next, and the last key copied
+    /// in it.
+    next: Option<(String, Option<Vec<u8>>)>,
+}
+
+impl BTreeEngine {
+    /// Opens the engine in `dir`, creating the directory and files if needed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the files cannot be opened or are not engine files.
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<BTreeEngine, StorageError> {
+        BTreeEngine::open_with(dir, BTreeOptions::new())
+    }
+
+    /// Opens the engine in `dir` with `options`, creating the directory and
+    /// files if needed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the files cannot be opened or are not engine
+    /// files, a compression dictionary is damaged, or the files are
+    /// encrypted and their keys are unavailable.
+    pub fn open_with<P: AsRef<Path>>(
+        dir: P,
+        options: BTreeOptions,
+    ) -> Result<BTreeEngine, StorageError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let mut pager = Pager::open_with(dir.join(INDEX_FILE), options.encryption.clone())?;
+        pager.set_cache_size(options.cache_size)?;
+        let meta = &pager.meta()[..META_USED];
+        let catalog_root =
+            u64::from_le_bytes(meta[META_CATALOG..META_CATALOG + 8].try_into().unwrap());
+        let mut tag = match meta[META_TAG] {
+            0 => LOCATED,
+            tag => tag,
+        };
+        let compacting = meta[META_COMPACTING] != 0;
+        let catalog = if catalog_root == 0 {
+            BTree::create(&mut pager)?
+        } else {
+            BTree::open(catalog_root)
+        };
+
+        // A compaction whose file is gone was interrupted after its file
+        // replaced the documents file, so only its tag is left to flip; a
+        // compaction file no compaction was marked for never got a record
+        let compact_path = dir.join(COMPACT_FILE);
+        let compaction = match (compacting, compact_path.exists()) {
+            (true, true) => {
+                let file = open_records(&compact_path)?;
+                let len = file.metadata()?.len();
+                // Records left dead before the engine was closed are not
+                // known, so are not counted
+                Some(Compaction {
+                    file,
+                    len,
+                    dead: 0,
+                    next: None,
+                })
+            }
+            (true, false) => {
+                tag = other_tag(tag);
+                None
+            }
+            (false, true) => {
+                fs::remove_file(&compact_path)?;
+                None
+            }
+            (false, false) => None,
+        };
+
+        let documents = open_records(&dir.join(DOCUMENTS_FILE))?;
+        let documents_len = documents.metadata()?.len();
+        let mapped = MappedFile::map(&documents);
+
+        let dictionaries_path = dir.join(DICTIONARIES_FILE);
+        let dictionaries = open_records(&dictionaries_path)?;
+        let (trained, dictionaries_len) = read_dictionaries(
+            &dictionaries,
+            &dictionaries_path,
+            options.encryption.as_ref(),
+        )?;
+        let codec = Codec::new(options.compression, &trained)
+            .map_err(|e| StorageError::corrupt(dictionaries_path.display(), e))?;
+
+        let mut engine = BTreeEngine {
+            dir,
+            pager,
+            catalog,
+            documents,
+            documents_len,
+            mapped,
+            tag,
+            compaction,
+            dead: 0,
+            codec,
+            dictionaries,
+            dictionaries_len,
+            encryption: options.encryption,
+        };
+        engine.write_meta()?;
+        Ok(engine)
+    }
+
+    /// Returns the directory holding the engine's files.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns where the value under `key` is stored in the documents file,
+    /// or in the file a running compaction is copying records to, or `None`
+    /// if it is missing or stored inline.
+    pub fn locate(
+        &self,
+        namespace: &str,
+        key: &[u8],
+    ) -> Result<Option<DocumentLocation>, StorageError> {
+        let Some(tree) = self.tree(namespace)? else {
+            return Ok(None);
+        };
+        Ok(tree
+            .get(&self.pager, key)?
+            .and_then(|stored| decode_location(&stored)))
+    }
+
+    /// Returns `true` if the documents file is memory-mapped.
+    pub fn is_mapped(&self) -> bool {
+        self.mapped.len() > 0
+    }
+
+    /// Returns how many compression dictionaries have been trained.
+    pub fn dictionaries(&self) -> usize {
+        self.codec.dictionaries()
+    }
+
+    /// Returns the counters of the cache of tree pages.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.pager.cache_stats()
+    }
+
+    fn tree(&self, namespace: &str) -> Result<Option<BTree>, StorageError> {
+        Ok(self
+            .catalog
+            .get(&self.pager, namespace.as_bytes())?
+            .map(|root| BTree::open(u64::from_le_bytes(root[..8].try_into().unwrap()))))
+    }
+
+    fn tree_or_create(&mut self, namespace: &str) -> Result<BTree, StorageError> {
+        if let Some(tree) = self.tree(namespace)? {
+            return Ok(tree);
+        }
+        let tree = BTree::create(&mut self.pager)?;
+        self.catalog.insert(
+            &mut self.pager,
+            namespace.as_bytes(),
+            &tree.root().to_le_bytes(),
+        )?;
+        Ok(tree)
+    }
+
+    /// Returns the value for a stored tree entry, reading it from the
+    /// documents file if it is not inline.
+    fn load(&self, mut stored: Vec<u8>) -> Result<Cow<'_, [u8]>, StorageError> {
+        match stored.first() {
+            Some(&INLINE) => {
+                stored.remove(0);
+                Ok(Cow::Owned(stored))
+            }
+            Some(&tag) => {
+                let file = self
+                    .records(tag)
+                    .ok_or_else(|| self.corrupt("bad tree value"))?;
+                let location =
+                    decode_location(&stored).ok_or_else(|| self.corrupt("bad location"))?;
+                let record_len = RECORD_HEADER_LEN + location.len as usize;
+                match self.mapped.get(location.offset, record_len) {
+                    Some(record) if tag == self.tag => {
+                        let flags = self.check_record(location, record)?;
+                        self.decode(location, &record[RECORD_HEADER_LEN..], flags)
+                    }
+                    _ => self.read_record(file, location).map(Cow::Owned),
+                }
+            }
+            None => Err(self.corrupt("bad tree value")),
+        }
+    }
+
+    /// Returns the file holding values tagged `tag`.
+    fn records(&self, tag: u8) -> Option<&File> {
+        match &self.compaction {
+            _ if tag == self.tag => Some(&self.documents),
+            Some(compaction) if tag == other_tag(self.tag) => Some(&compaction.file),
+            _ => None,
+        }
+    }
+
+    fn read_record(
+        &self,
+        file: &File,
+        location: DocumentLocation,
+    ) -> Result<Vec<u8>, StorageError> {
+        let mut record = vec![0; RECORD_HEADER_LEN + location.len as usize];
+        read_exact_at(file, &mut record, location.offset)?;
+        let flags = self.check_record(location, &record)?;
+        let value = record.split_off(RECORD_HEADER_LEN);
+        match flags {
+            0 => Ok(value),
+            flags => self.decode(location, &value, flags).map(Cow::into_owned),
+        }
+    }
+
+    /// Checks a record's header and checksum against `location`, and
+    /// returns the record's flags.
+    fn check_record(&self, location: DocumentLocation, record: &[u8]) -> Result<u32, StorageError> {
+        let len = u32::from_le_bytes(record[..4].try_into().unwrap());
+        let crc = u32::from_le_bytes(record[4..8].try_into().unwrap());
+        if len & !RECORD_FLAGS != location.len || crc32(&record[RECORD_HEADER_LEN..]) != crc {
+            return Err(self.corrupt(format!(
+                "checksum mismatch in record at offset {}",
+                location.offset
+            )));
+        }
+        Ok(len & RECORD_FLAGS)
+    }
+
+    /// Returns the value of a record with `flags` stored as `value`,
+    /// decrypting and decompressing it as they say.
+    fn decode<'a>(
+        &self,
+        location: DocumentLocation,
+        value: &'a [u8],
+        flags: u32,
+    ) -> Result<Cow<'a, [u8]>, StorageError> {
+        let mut value = Cow::Borrowed(value);
+        let at = || {
+            format!(
+                "{} at offset {}",
+                self.records_path().display(),
+                location.offset
+            )
+        };
+        if flags & ENCRYPTED != 0 {
+            let encryption = self
+                .encryption
+                .as_ref()
+                .ok_or_else(|| StorageError::Encrypted { location: at() })?;
+            value = Cow::Owned(encryption.open(&value, &[], &at())?);
+        }
+        if flags & COMPRESSED != 0 {
+            let decompressed = self
+                .codec
+                .decompress(&value)
+                .map_err(|e| StorageError::corrupt(at(), e))?;
+            value = Cow::Owned(decompressed);
+        }
+        Ok(value)
+    }
+
+    /// Returns `value` as it is to be written to a records file, and the
+    /// record's flags. A dictionary trained from the values sampled so far
+    /// is saved first.
+    fn encode<'a>(&mut self, value: &'a [u8]) -> Result<(Cow<'a, [u8]>, u32), StorageError> {
+        if let Some(dictionary) = self.codec.sample(value) {
+            let (dictionary, flags) = self.encrypt(Cow::Owned(dictionary), 0)?;
+            write_record(
+                &self.dictionaries,
+                &mut self.dictionaries_len,
+                &dictionary,
+                flags,
+            )?;
+            // Records compressed with the dictionary must never outlive it
+            self.dictionaries.sync_data()?;
+        }
+        let (value, flags) = match self.codec.compress(value) {
+            Some(compressed) => (Cow::Owned(compressed), COMPRESSED),
+            None => (Cow::Borrowed(value), 0),
+        };
+        self.encrypt(value, flags)
+    }
+
+    /// Encrypts `value` if encryption is on, adding to its `flags`.
+    fn encrypt<'a>(
+        &self,
+        value: Cow<'a, [u8]>,
+        flags: u32,
+    ) -> Result<(Cow<'a, [u8]>, u32), StorageError> {
+        match &self.encryption {
+            Some(encryption) => {
+                let sealed = encryption.seal(&value, &[], &self.records_path().display())?;
+                Ok((Cow::Owned(sealed), flags | ENCRYPTED))
+            }
+            None => Ok((value, flags)),
+        }
+    }
+
+    /// Maps the documents file again if enough has been appended to it
+    /// since it was last mapped.
+    fn remap_if_grown(&mut self) {
+        let mapped = self.mapped.len();
+        if self.documents_len - mapped >= REMAP_MIN_GROWTH.max(mapped / 4) {
+            self.mapped = MappedFile::map(&self.documents);
+        }
+    }
+
+    /// Returns the tree entry for `value`, appending it to the documents
+    /// file, or the file a running compaction is copying records to, if it
+    /// is too large to keep inline.
+    fn store(&mut self, value: &[u8]) -> Result<Vec<u8>, StorageError> {
+        if value.len() <= INLINE_MAX {
+            let mut stored = Vec::with_capacity(1 + value.len());
+            stored.push(INLINE);
+            stored.extend_from_slice(value);
+            return Ok(stored);
+        }
+        let (value, flags) = self.encode(value)?;
+        match &mut self.compaction {
+            Some(compaction) => append_record(
+                &compaction.file,
+                &mut compaction.len,
+                other_tag(self.tag),
+                &value,
+                flags,
+            ),
+            None => append_record(
+                &self.documents,
+                &mut self.documents_len,
+                self.tag,
+                &value,
+                flags,
+            ),
+        }
+    }
+
+    /// Starts a compaction: creates the file records are copied to, then
+    /// marks the compaction as running.
+    fn start_compaction(&mut self) -> Result<(), StorageError> {
+        let path = self.dir.join(COMPACT_FILE);
+        let file = open_records(&path)?;
+        file.set_len(0)?;
+        file.sync_all()?;
+        self.compaction = Some(Compaction {
+            file,
+            len: 0,
+            dead: 0,
+            next: None,
+        });
+        self.write_meta()
+    }
+
+    /// Copies the live records of the next batch of keys, about `budget`
+    /// bytes' worth, to the compaction file. Returns how many bytes it went
+    /// through and whether that was the last batch.
+    fn copy_batch(&mut self, budget: u64) -> Result<(u64, bool), StorageError> {
+        let compaction = self.compaction.as_ref().expect("no compaction running");
+        let mut next = compaction.next.clone();
+        let namespace_start = match &next {
+            Some((namespace, _)) => Bound::Included(namespace.as_bytes()),
+            None => Bound::Unbounded,
+        };
+        let mut trees = Vec::new();
+        for entry in self
+            .catalog
+            .range(&self.pager, namespace_start, Bound::Unbounded)?
+        {
+            let (name, root) = entry?;
+            let name = String::from_utf8(name).map_err(|_| self.corrupt("bad namespace name"))?;
+            trees.push((
+                name,
+                BTree::open(u64::from_le_bytes(root[..8].try_into().unwrap())),
+            ));
+        }
+
+        // Find the batch with the tree borrowed, then copy it
+        let mut work = 0;
+        let mut batch = Vec::new();
+        for (name, tree) in trees {
+            let after = match next.take() {
+                Some((namespace, after)) if namespace == name => after,
+                _ => None,
+            };
+            let start = match &after {
+                Some(key) => Bound::Excluded(key.as_slice()),
+                None => Bound::Unbounded,
+            };
+            let mut last = after.clone();
+            for entry in tree.range(&self.pager, start, Bound::Unbounded)? {
+                let (key, stored) = entry?;
+                work += (key.len() + stored.len()) as u64;
+                if stored.first() == Some(&self.tag) {
+                    let location =
+                        decode_location(&stored).ok_or_else(|| self.corrupt("bad location"))?;
+                    work += location.len as u64;
+                    batch.push((tree, key.clone(), location));
+                }
+                last = Some(key);
+                if work >= budget {
+                    break;
+                }
+            }
+            if work >= budget {
+                next = Some((name, last));
+                break;
+            }
+        }
+        for (tree, key, location) in batch {
+            let value = self.read_record(&self.documents, location)?;
+            let (value, flags) = self.encode(&value)?;
+            let compaction = self.compaction.as_mut().unwrap();
+            let stored = append_record(
+                &compaction.file,
+                &mut compaction.len,
+                other_tag(self.tag),
+                &value,
+                flags,
+            )?;
+            tree.insert(&mut self.pager, &key, &stored)?;
+        }
+        let last = next.is_none();
+        self.compaction.as_mut().unwrap().next = next;
+        Ok((work, last))
+    }
+
+    /// Replaces the documents file with the compaction file, which every
+    /// key now points into, and returns the bytes freed.
+    fn finish_compaction(&mut self) -> Result<u64, StorageError> {
+        let compaction = self.compaction.take().expect("no compaction running");
+        compaction.file.sync_all()?;
+        self.pager.sync()?;
+        fs::rename(self.dir.join(COMPACT_FILE), self.dir.join(DOCUMENTS_FILE))?;
+        let reclaimed = self.documents_len.saturating_sub(compaction.len);
+        self.documents = compaction.file;
+        self.documents_len = compaction.len;
+        self.dead = compaction.dead;
+        self.mapped = MappedFile::map(&self.documents);
+        self.tag = other_tag(self.tag);
+        self.write_meta()?;
+        self.pager.sync()?;
+        Ok(reclaimed)
+    }
+
+    /// Counts the record the tree entry `stored` points to, if any, as dead
+    /// once no key points to it.
+    fn release(&mut self, stored: &[u8]) {
+        let Some(location) = decode_location(stored) else {
+            return;
+        };
+        let len = (RECORD_HEADER_LEN + location.len as usize) as u64;
+        match &mut self.compaction {
+            // Records in the documents file all go when compaction finishes
+            Some(compaction) if stored[0] != self.tag => compaction.dead += len,
+            _ => self.dead += len,
+        }
+    }
+
+    /// Walks the catalog and every tree it lists, pushing the damage found
+    /// to `issues`, and returns the pages in use: the header page and the
+    /// pages of each tree that could be walked. With `records`, the record
+    /// each value is stored in is checked too.
+    fn check_trees(
+        &self,
+        issues: &mut Vec<StorageIssue>,
+        records: bool,
+    ) -> Result<HashSet<PageId>, StorageError> {
+        let mut used = HashSet::from([0]);
+        match self.catalog.pages(&self.pager) {
+            Ok(pages) => used.extend(pages),
+            Err(e) if e.is_key_error() => return Err(e),
+            Err(e) => {
+                issues.push(StorageIssue::BadTree {
+                    namespace: None,
+                    message: e.to_string(),
+                });
+                return Ok(used);
+            }
+        }
+        for entry in self
+            .catalog
+            .range(&self.pager, Bound::Unbounded, Bound::Unbounded)?
+        {
+            let (name, root) = entry?;
+            let namespace =
+                String::from_utf8(name).map_err(|_| self.corrupt("bad namespace name"))?;
+            let tree = BTree::open(u64::from_le_bytes(root[..8].try_into().unwrap()));
+            let pages = match tree.pages(&self.pager) {
+                Ok(pages) => pages,
+                Err(e) if e.is_key_error() => return Err(e),
+                Err(e) => {
+                    issues.push(StorageIssue::BadTree {
+                        namespace: Some(namespace),
+                        message: e.to_string(),
+                    });
+                    continue;
+                }
+            };
+            if let Some(page) = pages.into_iter().filter(|page| !used.insert(*page)).last() {
+                issues.push(StorageIssue::BadTree {
+                    namespace: Some(namespace),
+                    message: format!("page {} is also in another tree", page),
+                });
+                continue;
+            }
+            if !records {
+                continue;
+            }
+            for entry in tree.range(&self.pager, Bound::Unbounded, Bound::Unbounded)? {
+                let (key, stored) = entry?;
+                if stored.first() == Some(&INLINE) {
+                    continue;
+                }
+                match self.load(stored) {
+                    Ok(_) => {}
+                    Err(e) if e.is_key_error() => return Err(e),
+                    Err(e) => issues.push(StorageIssue::BadRecord {
+                        namespace: namespace.clone(),
+                        key,
+                        message: e.to_string(),
+                    }),
+                }
+            }
+        }
+        Ok(used)
+    }
+
+    fn write_meta(&mut self) -> Result<(), StorageError> {
+        let mut meta = [0; META_USED];
+        meta[META_CATALOG..META_CATALOG + 8].copy_from_slice(&self.catalog.root().to_le_bytes());
+        meta[META_TAG] = self.tag;
+        meta[META_COMPACTING] = self.compaction.is_some() as u8;
+        self.pager.set_meta(&meta)
+    }
+
+    fn records_path(&self) -> PathBuf {
+        self.dir.join(DOCUMENTS_FILE)
+    }
+
+    fn corrupt(&self, message: impl ToString) -> StorageError {
+        StorageError::corrupt(self.records_path().display(), message)
+    }
+}
+
+fn other_tag(tag: u8) -> u8 {
+    if tag == LOCATED {
+        RELOCATED
+    } else {
+        LOCATED
+    }
+}
+
+fn open_records(path: &Path) -> Result<File, StorageError> {
+    Ok(OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?)
+}
+
+/// Appends `value` as a checksummed record with `flags` at `*len` in
+/// `file`, and returns where it was written.
+fn write_record(
+    file: &File,
+    len: &mut u64,
+    value: &[u8],
+    flags: u32,
+) -> Result<DocumentLocation, StorageError> {
+    let location = DocumentLocation {
+        offset: *len,
+        len: value.len() as u32,
+    };
+    let mut record = Vec::with_capacity(RECORD_HEADER_LEN + value.len());
+    record.extend_from_slice(&(location.len | flags).to_le_bytes());
+    record.extend_from_slice(&crc32(value).to_le_bytes());
+    record.extend_from_slice(value);
+    write_all_at(file, &record, location.offset)?;
+    *len += record.len() as u64;
+    Ok(location)
+}
+
+/// Appends `value` as a checksummed record at `*len` in `file` and returns
+/// the tree entry locating it, tagged `tag`.
+fn append_record(
+    file: &File,
+    len: &mut u64,
+    tag: u8,
+    value: &[u8],
+    flags: u32,
+) -> Result<Vec<u8>, StorageError> {
+    let location = write_record(file, len, value, flags)?;
+    let mut stored = Vec::with_capacity(13);
+    stored.push(tag);
+    stored.extend_from_slice(&location.offset.to_le_bytes());
+    stored.extend_from_slice(&location.len.to_le_bytes());
+    Ok(stored)
+}
+
+/// Reads every dictionary in the dictionaries file, and returns them with
+/// the length of the file. A record torn by a crash while it was written
+/// ends the file, and is cut off: nothing was compressed with it.
+fn read_dictionaries(
+    file: &File,
+    path: &Path,
+    encryption: Option<&Encryption>,
+) -> Result<(Vec<Vec<u8>>, u64), StorageError> {
+    let file_len = file.metadata()?.len();
+    let mut dictionaries = Vec::new();
+    let mut offset = 0;
+    while offset + RECORD_HEADER_LEN as u64 <= file_len {
+        let mut header = [0; RECORD_HEADER_LEN];
+        read_exact_at(file, &mut header, offset)?;
+        let flags = u32::from_le_bytes(header[..4].try_into().unwrap());
+        let len = flags & !RECORD_FLAGS;
+        let crc = u32::from_le_bytes(header[4..].try_into().unwrap());
+        let end = offset + (RECORD_HEADER_LEN + len as usize) as u64;
+        if end > file_len {
+            break;
+        }
+        let mut dictionary = vec![0; len as usize];
+        read_exact_at(file, &mut dictionary, offset + RECORD_HEADER_LEN as u64)?;
+        if crc32(&dictionary) != crc {
+            break;
+        }
+        if flags & ENCRYPTED != 0 {
+            let location = format!("{} at offset {}", path.display(), offset);
+            let encryption = encryption.ok_or_else(|| StorageError::Encrypted {
+                location: location.clone(),
+            })?;
+            dictionary = encryption.open(&dictionary, &[], &location)?;
+        }
+        dictionaries.push(dictionary);
+        offset = end;
+    }
+    if offset < file_len {
+        file.set_len(offset)?;
+    }
+    Ok((dictionaries, offset))
+}
+
+fn decode_location(stored: &[u8]) -> Option<DocumentLocation> {
+    match stored {
+        [LOCATED | RELOCATED, rest @ ..] if rest.len() == 12 => Some(DocumentLocation {
+            offset: u64::from_le_bytes(rest[..8].try_into().unwrap()),
+            len: u32::from_le_bytes(rest[8..].try_into().unwrap()),
+        }),
+        _ => None,
+    }
+}
+
+impl StorageEngine for BTreeEngine {
+    fn get(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>, StorageError> {
+        Ok(self.get_ref(namespace, key)?.map(Cow::into_owned))
+    }
+
+    fn get_ref(&self, namespace: &str, key: &[u8]) -> Result<Option<Cow<'_, [u8]>>, StorageError> {
+        let Some(tree) = self.tree(namespace)? else {
+            return Ok(None);
+        };
+        match tree.get(&self.pager, key)? {
+            Some(stored) => Ok(Some(self.load(stored)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn put(&mut self, namespace: &str, key: &[u8], value: &[u8]) -> Result<(), StorageError> {
+        let tree = self.tree_or_create(namespace)?;
+        let stored = self.store(value)?;
+        if let Some(previous) = tree.insert(&mut self.pager, key, &stored)? {
+            self.release(&previous);
+        }
+        self.remap_if_grown();
+        Ok(())
+    }
+
+    fn delete(&mut self, namespace: &str, key: &[u8]) -> Result<bool, StorageError> {
+        let Some(tree) = self.tree(namespace)? else {
+            return Ok(false);
+        };
+        match tree.remove(&mut self.pager, key)? {
+            Some(previous) => {
+                self.release(&previous);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn scan(
+        &self,
+        namespace: &str,
+        range: &KeyRange,
+        limit: usize,
+    ) -> Result<Vec<Entry>, StorageError> {
+        let Some(tree) = self.tree(namespace)? else {
+            return Ok(Vec::new());
+        };
+        let mut entries = Vec::new();
+        for entry in tree
+            .range(&self.pager, range.start_bound(), range.end_bound())?
+            .take(limit)
+        {
+            let (key, stored) = entry?;
+            entries.push((key, self.load(stored)?.into_owned()));
+        }
+        Ok(entries)
+    }
+
+    fn namespaces(&self) -> Result<Vec<String>, StorageError> {
+        let mut names = Vec::new();
+        for entry in self.catalog.range(
+            &self.pager,
+            std::ops::Bound::Unbounded,
+            std::ops::Bound::Unbounded,
+        )? {
+            let (name, _) = entry?;
+            names.push(String::from_utf8(name).map_err(|_| self.corrupt("bad namespace name"))?);
+        }
+        Ok(names)
+    }
+
+    fn drop_namespace(&mut self, namespace: &str) -> Result<bool, StorageError> {
+        let Some(tree) = self.tree(namespace)? else {
+            return Ok(false);
+        };
+        let mut records = Vec::new();
+        for entry in tree.range(&self.pager, Bound::Unbounded, Bound::Unbounded)? {
+            let (_, stored) = entry?;
+            if stored.first() != Some(&INLINE) {
+                records.push(stored);
+            }
+        }
+        for stored in records {
+            self.release(&stored);
+        }
+        self.catalog.remove(&mut self.pager, namespace.as_bytes())?;
+        tree.destroy(&mut self.pager)?;
+        Ok(true)
+    }
+
+    fn flush(&mut self) -> Result<(), StorageError> {
+        self.documents.sync_data()?;
+        if let Some(compaction) = &self.compaction {
+            compaction.file.sync_data()?;
+        }
+        self.pager.sync()
+    }
+
+    fn verify(&mut self) -> Result<Vec<StorageIssue>, StorageError> {
+        self.flush()?;
+        let mut issues = Vec::new();
+        let used = self.check_trees(&mut issues, true)?;
+        let intact = issues
+            .iter()
+            .all(|issue| !matches!(issue, StorageIssue::BadTree { .. }));
+
+        let mut free = HashSet::new();
+        let mut page = self.pager.free_head();
+        let mut walked = true;
+        while page != 0 {
+            let problem = if page >= self.pager.page_count() {
+                Some(format!("page {} is past the end of the file", page))
+            } else if used.contains(&page) {
+                Some(format!("page {} is free but in use", page))
+            } else if !free.insert(page) {
+                Some(format!("page {} is on the list twice", page))
+            } else {
+                None
+            };
+            if let Some(message) = problem {
+                issues.push(StorageIssue::BadFreeList(message));
+                walked = false;
+                break;
+            }
+            match self.pager.verify(page) {
+                Ok(payload) => page = u64::from_le_bytes(payload[..8].try_into().unwrap()),
+                Err(e) if e.is_key_error() => return Err(e),
+                Err(e) => {
+                    issues.push(StorageIssue::BadPage {
+                        page,
+                        message: e.to_string(),
+                    });
+                    walked = false;
+                    break;
+                }
+            }
+        }
+
+        // Pages past a break in the free list, or under a damaged tree,
+        // would look leaked
+        if intact && walked {
+            let leaked = (1..self.pager.page_count())
+                .filter(|page| !used.contains(page) && !free.contains(page))
+                .count();
+            if leaked > 0 {
+                issues.push(StorageIssue::LeakedPages(leaked as u64));
+            }
+        }
+        Ok(issues)
+    }
+
+    fn repair(&mut self) -> Result<(), StorageError> {
+        self.flush()?;
+        let mut issues = Vec::new();
+        let used = self.check_trees(&mut issues, false)?;
+        // Every page under a damaged tree would be freed for reuse
+        if let Some(issue) = issues.first() {
+            return Err(StorageError::corrupt(
+                self.pager.path().display(),
+                format!("cannot rebuild the free list: {}", issue),
+            ));
+        }
+        let free: Vec<PageId> = (1..self.pager.page_count())
+            .filter(|page| !used.contains(page))
+            .collect();
+        self.pager.set_free_list(&free)?;
+        self.flush()
+    }
+
+    fn compact_step(&mut self, budget: u64) -> Result<CompactionStep, StorageError> {
+        if self.compaction.is_none() {
+            self.start_compaction()?;
+        }
+        let (work, last) = self.copy_batch(budget)?;
+        let reclaimed = if last { self.finish_compaction()? } else { 0 };
+        Ok(CompactionStep {
+            work,
+            reclaimed,
+            done: last,
+        })
+    }
+
+    fn stats(&self) -> EngineStats {
+        let copied = self
+            .compaction
+            .as_ref()
+            .map_or(0, |compaction| compaction.dead);
+        EngineStats {
+            cache: Some(self.cache_stats()),
+            compaction_debt: self.dead + copied,
+        }
+    }
+}
@@ -0,0 +1,189 @@
+// src/storage/buffer_pool.rs
+
+use std::collections::HashMap;
+
+use super::pager::{PageId, PAGE_SIZE};
+
+/// Memory a pager's buffer pool may use unless told otherwise.
+pub const DEFAULT_CACHE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Counters of a buffer pool, as returned by `Pager::cache_stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Reads answered from the pool.
+    pub hits: u64,
+    /// Reads that went to the file.
+    pub misses: u64,
+    /// Pages dropped from the pool to make room for others.
+    pub evictions: u64,
+    /// Dirty pages written to the file.
+    pub writebacks: u64,
+    /// Pages the pool holds.
+    pub pages: usize,
+    /// Pages the pool holds that the file does not have yet.
+    pub dirty: usize,
+    /// Pages the pool may hold.
+    pub capacity: usize,
+}
+
+/// A page held by the pool.
+#[derive(Debug)]
+struct Frame {
+    id: PageId,
+    payload: Vec<u8>,
+    /// Set when the page is used and cleared as the clock hand passes, so
+    /// pages used since the hand last came round are kept.
+    referenced: bool,
+    dirty: bool,
+}
+
+/// A fixed number of page payloads kept in memory, evicted by the clock
+/// algorithm.
+///
+/// Only clean pages are evicted. When every page is dirty, the pool asks
+/// for all of them to be written back at once, so the file only ever
+/// holds its pages as of some moment rather than a mix of old and new.
+#[derive(Debug)]
+pub(crate) struct BufferPool {
+    frames: Vec<Frame>,
+    table: HashMap<PageId, usize>,
+    capacity: usize,
+    hand: usize,
+    stats: CacheStats,
+}
+
+impl BufferPool {
+    /// Creates a pool of at most `bytes` of pages. A pool too small for a
+    /// page holds none, and every read and write goes to the file.
+    pub(crate) fn new(bytes: usize) -> Self {
+        BufferPool {
+            frames: Vec::new(),
+            table: HashMap::new(),
+            capacity: bytes / PAGE_SIZE,
+            hand: 0,
+            stats: CacheStats::default(),
+        }
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the payload of page `id` if the pool holds it, counting the
+    /// read as a hit or a miss.
+    pub(crate) fn get(&mut self, id: PageId) -> Option<Vec<u8>> {
+        match self.table.get(&id) {
+            Some(&index) => {
+                let frame = &mut self.frames[index];
+                frame.referenced = true;
+                self.stats.hits += 1;
+                Some(frame.payload.clone())
+            }
+            None => {
+                self.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Returns whether `put` of page `id` needs room the pool can only make
+    /// once its pages are written back, because every one is dirty.
+    pub(crate) fn needs_write_back(&self, id: PageId) -> bool {
+        self.capacity > 0
+            && self.frames.len() == self.capacity
+            && !self.table.contains_key(&id)
+            && self.frames.iter().all(|frame| frame.dirty)
+    }
+
+    /// Stores `payload` as the contents of page `id`, dirty if the file
+    /// does not have it yet, evicting a clean page if the pool is full.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a dirty page would need evicting: callers write back the
+    /// dirty pages first when `needs_write_back`.
+    pub(crate) fn put(&mut self, id: PageId, payload: Vec<u8>, dirty: bool) {
+        if let Some(&index) = self.table.get(&id) {
+            let frame = &mut self.frames[index];
+            frame.payload = payload;
+            frame.referenced = true;
+            frame.dirty |= dirty;
+            return;
+        }
+        if self.capacity == 0 {
+            return;
+        }
+        let frame = Frame {
+            id,
+            payload,
+            referenced: true,
+            dirty,
+        };
+        if self.frames.len() < self.capacity {
+            self.table.insert(id, self.frames.len());
+            self.frames.push(frame);
+            return;
+        }
+        let index = self.victim();
+        self.table.remove(&self.frames[index].id);
+        self.table.insert(id, index);
+        self.frames[index] = frame;
+        self.stats.evictions += 1;
+    }
+
+    /// Advances the clock hand to a clean page not used since the hand
+    /// last passed it, and returns its frame.
+    fn victim(&mut self) -> usize {
+        // Two turns clear every reference bit, so a third finds no page
+        // only if all are dirty
+        for _ in 0..3 * self.frames.len() {
+            let index = self.hand;
+            self.hand = (self.hand + 1) % self.frames.len();
+            let frame = &mut self.frames[index];
+            if frame.dirty {
+                continue;
+            }
+            if !frame.referenced {
+                return index;
+            }
+            frame.referenced = false;
+        }
+        panic!("buffer pool has no clean page to evict");
+    }
+
+    /// Marks every dirty page clean and returns them, in page order, for
+    /// the caller to write back.
+    pub(crate) fn take_dirty(&mut self) -> Vec<(PageId, Vec<u8>)> {
+        let mut dirty: Vec<(PageId, Vec<u8>)> = self
+            .frames
+            .iter_mut()
+            .filter(|frame| frame.dirty)
+            .map(|frame| {
+                frame.dirty = false;
+                (frame.id, frame.payload.clone())
+            })
+            .collect();
+        dirty.sort_by_key(|(id, _)| *id);
+        self.stats.writebacks += dirty.len() as u64;
+        dirty
+    }
+
+    /// Drops every page, which must all be clean, and sets the pool to
+    /// hold at most `bytes` of pages from now on.
+    pub(crate) fn resize(&mut self, bytes: usize) {
+        debug_assert!(self.frames.iter().all(|frame| !frame.dirty));
+        self.frames.clear();
+        self.table.clear();
+        self.hand = 0;
+        self.capacity = bytes / PAGE_SIZE;
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        CacheStats {
+            pages: self.frames.len(),
+            dirty: self.frames.iter().filter(|frame| frame.dirty).count(),
+            capacity: self.capacity,
+            ..self.stats
+        }
+    }
+}
@@ -0,0 +1,46 @@
+// src/storage/checksum.rs
+
+/// Lookup table for the IEEE CRC-32 polynomial (reflected).
+const CRC32_TABLE: [u32; 256] = table(0xEDB8_8320);
+/// Lookup table for the Castagnoli CRC-32C polynomial (reflected).
+const CRC32C_TABLE: [u32; 256] = table(0x82F6_3B78);
+
+/// Builds the lookup table for the reflected `polynomial`.
+const fn table(polynomial: u32) -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ polynomial
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Computes the IEEE CRC-32 of `bytes`, as used by zlib and PNG.
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    checksum(&CRC32_TABLE, bytes)
+}
+
+/// Computes the Castagnoli CRC-32C of `bytes`, as used by iSCSI and the
+/// MongoDB wire protocol.
+pub(crate) fn crc32c(bytes: &[u8]) -> u32 {
+    checksum(&CRC32C_TABLE, bytes)
+}
+
+fn checksum(table: &[u32; 256], bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc = table[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
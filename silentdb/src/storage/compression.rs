@@ -0,0 +1,258 @@
+// src/storage/compression.rs
+
+use std::fmt;
+
+#[cfg(feature = "zstd")]
+use std::collections::HashMap;
+
+#[cfg(feature = "zstd")]
+use zstd::bulk::{Compressor, Decompressor};
+#[cfg(feature = "zstd")]
+use zstd::dict::DecoderDictionary;
+
+/// Samples longer than this are cut short before training on them.
+const MAX_SAMPLE_LEN: usize = 16 * 1024;
+
+/// Options for compressing the values a `BTreeEngine` keeps in its
+/// documents file.
+///
+/// Values are compressed one by one with Zstandard, and kept compressed
+/// only where that makes them smaller. Once enough values have been
+/// stored, a dictionary is trained from a sample of them and later values
+/// are compressed with it, which helps most with many small documents
+/// sharing field names and text.
+///
+/// Compression needs the `zstd` feature; without it, values are stored
+/// as they are.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb::storage::{BTreeOptions, Compression};
+/// let options = BTreeOptions::new().compression(
+///     Compression::new()
+///         .level(6)
+///         .dictionary_samples(512),
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Compression {
+    pub(crate) level: i32,
+    pub(crate) dictionary_samples: usize,
+    pub(crate) dictionary_size: usize,
+}
+
+impl Compression {
+    /// Creates options compressing at level 3 and training a 16 KiB
+    /// dictionary from the first 128 values stored.
+    pub fn new() -> Self {
+        Compression {
+            level: 3,
+            dictionary_samples: 128,
+            dictionary_size: 16 * 1024,
+        }
+    }
+
+    /// Sets the Zstandard compression level, from 1 to 22. Higher levels
+    /// trade write speed for smaller values.
+    pub fn level(mut self, level: i32) -> Self {
+        self.level = level.clamp(1, 22);
+        self
+    }
+
+    /// Sets how many values are sampled before a dictionary is trained.
+    pub fn dictionary_samples(mut self, samples: usize) -> Self {
+        self.dictionary_samples = samples;
+        self
+    }
+
+    /// Sets the largest size in bytes of a trained dictionary.
+    pub fn dictionary_size(mut self, bytes: usize) -> Self {
+        self.dictionary_size = bytes.max(256);
+        self
+    }
+
+    /// Compresses values without ever training a dictionary.
+    pub fn without_dictionary(mut self) -> Self {
+        self.dictionary_samples = 0;
+        self
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::new()
+    }
+}
+
+/// Compresses and decompresses records, and trains the dictionary they
+/// are compressed with.
+///
+/// Every dictionary ever trained is kept for decompressing, since records
+/// compressed with one stay until they are rewritten by a compaction;
+/// Zstandard frames name the dictionary they need. Values are compressed
+/// with the latest.
+pub(crate) struct Codec {
+    options: Option<Compression>,
+    /// Values sampled for training, while still waiting for enough.
+    samples: Option<Vec<Vec<u8>>>,
+    #[cfg(feature = "zstd")]
+    compressor: Option<Compressor<'static>>,
+    #[cfg(feature = "zstd")]
+    dictionaries: HashMap<u32, DecoderDictionary<'static>>,
+    #[cfg(not(feature = "zstd"))]
+    dictionaries: usize,
+}
+
+impl Codec {
+    /// Creates a codec compressing as `options` say, if at all, with the
+    /// dictionaries trained so far, oldest first.
+    #[cfg(feature = "zstd")]
+    pub(crate) fn new(
+        options: Option<Compression>,
+        dictionaries: &[Vec<u8>],
+    ) -> Result<Codec, String> {
+        let mut codec = Codec {
+            options: None,
+            samples: None,
+            compressor: None,
+            dictionaries: HashMap::new(),
+        };
+        for dictionary in dictionaries {
+            let id = zstd::zstd_safe::get_dict_id_from_dict(dictionary)
+                .ok_or("bad compression dictionary")?;
+            codec
+                .dictionaries
+                .insert(id.get(), DecoderDictionary::copy(dictionary));
+        }
+        if let Some(options) = &options {
+            let compressor = match dictionaries.last() {
+                Some(dictionary) => Compressor::with_dictionary(options.level, dictionary),
+                None => Compressor::new(options.level),
+            };
+            codec.compressor = Some(compressor.map_err(|e| e.to_string())?);
+            if dictionaries.is_empty() && options.dictionary_samples > 0 {
+                codec.samples = Some(Vec::with_capacity(options.dictionary_samples));
+            }
+        }
+        codec.options = options;
+        Ok(codec)
+    }
+
+    /// Creates a codec that stores values as they are.
+    #[cfg(not(feature = "zstd"))]
+    pub(crate) fn new(
+        options: Option<Compression>,
+        dictionaries: &[Vec<u8>],
+    ) -> Result<Codec, String> {
+        Ok(Codec {
+            options,
+            samples: None,
+            dictionaries: dictionaries.len(),
+        })
+    }
+
+    /// Returns the number of dictionaries trained.
+    pub(crate) fn dictionaries(&self) -> usize {
+        #[cfg(feature = "zstd")]
+        return self.dictionaries.len();
+        #[cfg(not(feature = "zstd"))]
+        return self.dictionaries;
+    }
+
+    /// Samples `value` for training, and once enough values are sampled,
+    /// trains a dictionary from them and returns it. Later values are
+    /// compressed with it, so it must be saved before they are.
+    ///
+    /// If no dictionary can be trained from the samples, values go on
+    /// being compressed without one.
+    pub(crate) fn sample(&mut self, value: &[u8]) -> Option<Vec<u8>> {
+        let samples = self.samples.as_mut()?;
+        samples.push(value[..value.len().min(MAX_SAMPLE_LEN)].to_vec());
+        let options = self.options.as_ref()?;
+        if samples.len() < options.dictionary_samples {
+            return None;
+        }
+        let samples = self.samples.take()?;
+        self.train(&samples)
+    }
+
+    #[cfg(feature = "zstd")]
+    fn train(&mut self, samples: &[Vec<u8>]) -> Option<Vec<u8>> {
+        let options = self.options.as_ref()?;
+        let dictionary = zstd::dict::from_samples(samples, options.dictionary_size).ok()?;
+        let id = zstd::zstd_safe::get_dict_id_from_dict(&dictionary)?;
+        let compressor = Compressor::with_dictionary(options.level, &dictionary).ok()?;
+        self.compressor = Some(compressor);
+        self.dictionaries
+            .insert(id.get(), DecoderDictionary::copy(&dictionary));
+        Some(dictionary)
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    fn train(&mut self, samples: &[Vec<u8>]) -> Option<Vec<u8>> {
+        let _ = samples;
+        None
+    }
+
+    /// Returns `value` compressed, or `None` if it is not compressed
+    /// because compression is off or would not make it smaller.
+    #[cfg(feature = "zstd")]
+    pub(crate) fn compress(&mut self, value: &[u8]) -> Option<Vec<u8>> {
+        let compressed = self.compressor.as_mut()?.compress(value).ok()?;
+        (compressed.len() < value.len()).then_some(compressed)
+    }
+
+    /// Returns `None`: values are stored as they are without the `zstd`
+    /// feature.
+    #[cfg(not(feature = "zstd"))]
+    pub(crate) fn compress(&mut self, value: &[u8]) -> Option<Vec<u8>> {
+        let _ = value;
+        None
+    }
+
+    /// Decompresses a value `compress` returned.
+    ///
+    /// # Errors
+    ///
+    /// Returns a description of the problem if `compressed` is damaged or
+    /// needs a dictionary the codec does not have.
+    #[cfg(feature = "zstd")]
+    pub(crate) fn decompress(&self, compressed: &[u8]) -> Result<Vec<u8>, String> {
+        let len = zstd::zstd_safe::get_frame_content_size(compressed)
+            .ok()
+            .flatten()
+            .ok_or("bad compressed record")?;
+        let decompressor = match zstd::zstd_safe::get_dict_id_from_frame(compressed) {
+            Some(id) => {
+                let dictionary = self
+                    .dictionaries
+                    .get(&id.get())
+                    .ok_or_else(|| format!("missing compression dictionary {id}"))?;
+                Decompressor::with_prepared_dictionary(dictionary)
+            }
+            None => Decompressor::new(),
+        };
+        decompressor
+            .and_then(|mut decompressor| decompressor.decompress(compressed, len as usize))
+            .map_err(|e| format!("bad compressed record: {e}"))
+    }
+
+    /// Fails: compressed records cannot be read without the `zstd`
+    /// feature.
+    #[cfg(not(feature = "zstd"))]
+    pub(crate) fn decompress(&self, compressed: &[u8]) -> Result<Vec<u8>, String> {
+        let _ = compressed;
+        Err("compressed record needs the zstd feature".to_string())
+    }
+}
+
+impl fmt::Debug for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Codec")
+            .field("options", &self.options)
+            .field("sampled", &self.samples.as_ref().map(Vec::len))
+            .field("dictionaries", &self.dictionaries())
+            .finish()
+    }
+}
@@ -0,0 +1,288 @@
+// src/storage/encryption.rs
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use rand::RngCore;
+
+use super::error::StorageError;
+
+/// Identifies an encryption key. Every encrypted page and record names the
+/// key it was encrypted with, so older keys keep decrypting what they
+/// encrypted after a newer one takes over.
+pub type KeyId = u32;
+
+/// An AES-256 key.
+pub type Key = [u8; KEY_LEN];
+
+/// Length in bytes of an encryption key.
+pub const KEY_LEN: usize = 32;
+
+/// Length of the nonce before, and the tag after, each encrypted value.
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// Bytes encryption adds to a value: the key id and nonce before it and
+/// the authentication tag after it.
+pub(crate) const SEAL_OVERHEAD: usize = 4 + NONCE_LEN + TAG_LEN;
+
+/// A source of encryption keys, such as a key management service.
+///
+/// Implementations must keep returning every key that may still have
+/// encrypted something: data is only re-encrypted with the current key
+/// when it is next written, or when the engine holding it is compacted.
+pub trait KeyProvider: Send + Sync {
+    /// Returns the id of the key new data is encrypted with.
+    fn current_key_id(&self) -> KeyId;
+
+    /// Returns the key `id`, or `None` if it is unavailable.
+    fn key(&self, id: KeyId) -> Option<Key>;
+}
+
+/// A `KeyProvider` holding its keys in memory.
+///
+/// Keys can be listed in text as `id:hex` entries, separated by
+/// whitespace or commas, where `hex` is the key's 64 hex digits. The last
+/// entry is the current key, and `#` starts a comment running to the end
+/// of the line.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb::storage::KeyRing;
+/// let old = [1; 32];
+/// let new = [2; 32];
+/// let keys = KeyRing::new(1, old).rotate(2, new);
+///
+/// let listed = format!("1:{}\n2:{}", "01".repeat(32), "02".repeat(32));
+/// assert_eq!(KeyRing::parse(&listed).unwrap(), keys);
+/// ```
+#[derive(Clone, PartialEq, Eq)]
+pub struct KeyRing {
+    current: KeyId,
+    keys: HashMap<KeyId, Key>,
+}
+
+impl KeyRing {
+    /// Creates a key ring holding the key `id`, which is current.
+    pub fn new(id: KeyId, key: Key) -> Self {
+        KeyRing {
+            current: id,
+            keys: HashMap::from([(id, key)]),
+        }
+    }
+
+    /// Adds the key `id` for decrypting only.
+    pub fn with_key(mut self, id: KeyId, key: Key) -> Self {
+        self.keys.insert(id, key);
+        self
+    }
+
+    /// Adds the key `id` and makes it current, keeping the others for
+    /// decrypting.
+    pub fn rotate(mut self, id: KeyId, key: Key) -> Self {
+        self.keys.insert(id, key);
+        self.current = id;
+        self
+    }
+
+    /// Parses keys listed in text.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidKeys` if an entry is malformed or there is none.
+    pub fn parse(text: &str) -> Result<KeyRing, StorageError> {
+        let mut ring: Option<KeyRing> = None;
+        let entries = text
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or_default())
+            .flat_map(|line| line.split(|c: char| c == ',' || c.is_whitespace()))
+            .filter(|entry| !entry.is_empty());
+        for entry in entries {
+            let invalid = || StorageError::InvalidKeys(format!("bad entry {:?}", entry));
+            let (id, hex_key) = entry.split_once(':').ok_or_else(invalid)?;
+            let id = id.parse().map_err(|_| invalid())?;
+            let key = hex::decode(hex_key)
+                .ok()
+                .and_then(|key| Key::try_from(key).ok())
+                .ok_or_else(invalid)?;
+            ring = Some(match ring {
+                Some(ring) => ring.rotate(id, key),
+                None => KeyRing::new(id, key),
+            });
+        }
+        ring.ok_or_else(|| StorageError::InvalidKeys("no keys listed".to_string()))
+    }
+
+    /// Reads keys listed in the environment variable `name`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidKeys` if the variable is unset or its keys are
+    /// malformed.
+    pub fn from_env(name: &str) -> Result<KeyRing, StorageError> {
+        let text = std::env::var(name)
+            .map_err(|e| StorageError::InvalidKeys(format!("{}: {}", name, e)))?;
+        KeyRing::parse(&text)
+    }
+
+    /// Reads keys listed in the file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an I/O error if the file cannot be read, or `InvalidKeys` if
+    /// its keys are malformed.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<KeyRing, StorageError> {
+        KeyRing::parse(&fs::read_to_string(path)?)
+    }
+}
+
+impl KeyProvider for KeyRing {
+    fn current_key_id(&self) -> KeyId {
+        self.current
+    }
+
+    fn key(&self, id: KeyId) -> Option<Key> {
+        self.keys.get(&id).copied()
+    }
+}
+
+impl fmt::Debug for KeyRing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut ids: Vec<_> = self.keys.keys().collect();
+        ids.sort();
+        f.debug_struct("KeyRing")
+            .field("current", &self.current)
+            .field("ids", &ids)
+            .finish()
+    }
+}
+
+/// Encryption at rest with AES-256-GCM, under keys from a `KeyProvider`.
+///
+/// Each value is encrypted with a random nonce and authenticated, so
+/// tampering is caught as well as damage. Clones share the provider and
+/// the ciphers made from its keys.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb::storage::{BTreeOptions, Encryption, KeyRing, WalOptions};
+/// let encryption = Encryption::new(KeyRing::new(1, [7; 32]));
+/// let engine = BTreeOptions::new().encryption(encryption.clone());
+/// let wal = WalOptions::new().encryption(encryption);
+/// ```
+#[derive(Clone)]
+pub struct Encryption {
+    keys: Arc<dyn KeyProvider>,
+    ciphers: Arc<Mutex<HashMap<KeyId, Aes256Gcm>>>,
+}
+
+impl Encryption {
+    /// Creates an encryption with keys from `keys`.
+    pub fn new<P: KeyProvider + 'static>(keys: P) -> Self {
+        Encryption::with_provider(Arc::new(keys))
+    }
+
+    /// Creates an encryption with keys from a shared provider.
+    pub fn with_provider(keys: Arc<dyn KeyProvider>) -> Self {
+        Encryption {
+            keys,
+            ciphers: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Returns the cipher for the key `id`.
+    fn cipher(&self, id: KeyId, location: &dyn fmt::Display) -> Result<Aes256Gcm, StorageError> {
+        let mut ciphers = self.ciphers.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(cipher) = ciphers.get(&id) {
+            return Ok(cipher.clone());
+        }
+        let key = self.keys.key(id).ok_or(StorageError::KeyUnavailable {
+            location: location.to_string(),
+            id,
+        })?;
+        let cipher = Aes256Gcm::new(&key.into());
+        ciphers.insert(id, cipher.clone());
+        Ok(cipher)
+    }
+
+    /// Encrypts `plaintext` with the current key, binding it to `aad`, and
+    /// returns it prefixed with the key id and nonce and followed by the
+    /// tag.
+    pub(crate) fn seal(
+        &self,
+        plaintext: &[u8],
+        aad: &[u8],
+        location: &dyn fmt::Display,
+    ) -> Result<Vec<u8>, StorageError> {
+        let id = self.keys.current_key_id();
+        let cipher = self.cipher(id, location)?;
+        let mut nonce = [0; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: plaintext,
+                    aad,
+                },
+            )
+            .map_err(|_| StorageError::corrupt(location, "encryption failed"))?;
+        let mut sealed = Vec::with_capacity(plaintext.len() + SEAL_OVERHEAD);
+        sealed.extend_from_slice(&id.to_le_bytes());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    /// Decrypts a value `seal` returned for the same `aad`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `KeyUnavailable` if its key is, or `Corrupt` if it fails
+    /// authentication.
+    pub(crate) fn open(
+        &self,
+        sealed: &[u8],
+        aad: &[u8],
+        location: &dyn fmt::Display,
+    ) -> Result<Vec<u8>, StorageError> {
+        if sealed.len() < SEAL_OVERHEAD {
+            return Err(StorageError::corrupt(location, "encrypted value too short"));
+        }
+        let id = KeyId::from_le_bytes(sealed[..4].try_into().unwrap());
+        let cipher = self.cipher(id, location)?;
+        cipher
+            .decrypt(
+                Nonce::from_slice(&sealed[4..4 + NONCE_LEN]),
+                Payload {
+                    msg: &sealed[4 + NONCE_LEN..],
+                    aad,
+                },
+            )
+            .map_err(|_| StorageError::corrupt(location, "decryption failed"))
+    }
+}
+
+impl fmt::Debug for Encryption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Encryption")
+            .field("current_key_id", &self.keys.current_key_id())
+            .finish()
+    }
+}
+
+impl PartialEq for Encryption {
+    /// Encryptions are equal if they share a provider.
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.keys, &other.keys)
+    }
+}
+
+impl Eq for Encryption {}
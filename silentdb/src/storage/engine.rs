@@ -0,0 +1,261 @@
+// src/storage/engine.rs
+
+use std::borrow::Cow;
+use std::fmt;
+use std::ops::Bound;
+
+use super::buffer_pool::CacheStats;
+use super::error::StorageError;
+
+/// A key and the value stored under it.
+pub type Entry = (Vec<u8>, Vec<u8>);
+
+/// A range of keys, as used by `StorageEngine::scan`.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb::storage::KeyRange;
+/// let range = KeyRange::prefix(b"user:");
+/// assert!(range.contains(b"user:42"));
+/// assert!(!range.contains(b"users"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KeyRange {
+    pub start: Bound<Vec<u8>>,
+    pub end: Bound<Vec<u8>>,
+}
+
+impl KeyRange {
+    /// Creates a range between two bounds.
+    pub fn new(start: Bound<Vec<u8>>, end: Bound<Vec<u8>>) -> Self {
+        KeyRange { start, end }
+    }
+
+    /// Creates a range covering every key.
+    pub fn all() -> Self {
+        KeyRange::new(Bound::Unbounded, Bound::Unbounded)
+    }
+
+    /// Creates a range covering every key that starts with `prefix`.
+    pub fn prefix(prefix: &[u8]) -> Self {
+        // The first key past the prefix is the prefix with its last
+        // non-0xFF byte incremented and everything after it dropped
+        let mut end = prefix.to_vec();
+        while let Some(last) = end.pop() {
+            if last != 0xFF {
+                end.push(last + 1);
+                return KeyRange::new(Bound::Included(prefix.to_vec()), Bound::Excluded(end));
+            }
+        }
+        KeyRange::new(Bound::Included(prefix.to_vec()), Bound::Unbounded)
+    }
+
+    /// Returns the part of this range after `key`, for resuming a scan.
+    pub fn after(&self, key: &[u8]) -> Self {
+        KeyRange::new(Bound::Excluded(key.to_vec()), self.end.clone())
+    }
+
+    /// Returns `true` if `key` is in the range.
+    pub fn contains(&self, key: &[u8]) -> bool {
+        let after_start = match &self.start {
+            Bound::Included(start) => key >= start.as_slice(),
+            Bound::Excluded(start) => key > start.as_slice(),
+            Bound::Unbounded => true,
+        };
+        let before_end = match &self.end {
+            Bound::Included(end) => key <= end.as_slice(),
+            Bound::Excluded(end) => key < end.as_slice(),
+            Bound::Unbounded => true,
+        };
+        after_start && before_end
+    }
+
+    /// Returns the start bound as a borrowed slice.
+    pub fn start_bound(&self) -> Bound<&[u8]> {
+        self.start.as_ref().map(Vec::as_slice)
+    }
+
+    /// Returns the end bound as a borrowed slice.
+    pub fn end_bound(&self) -> Bound<&[u8]> {
+        self.end.as_ref().map(Vec::as_slice)
+    }
+}
+
+/// Progress made by one call to `StorageEngine::compact_step`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompactionStep {
+    /// Bytes of live data read or rewritten by the step.
+    pub work: u64,
+    /// Bytes of disk space the step freed.
+    pub reclaimed: u64,
+    /// Whether compaction has finished, leaving nothing to reclaim.
+    pub done: bool,
+}
+
+/// The state of an engine, as returned by `StorageEngine::stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EngineStats {
+    /// The counters of the engine's page cache, if it has one.
+    pub cache: Option<CacheStats>,
+    /// Bytes held by replaced and deleted values that compaction would
+    /// reclaim.
+    pub compaction_debt: u64,
+}
+
+/// Damage found in an engine's files by `StorageEngine::verify`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StorageIssue {
+    /// A page outside every tree, such as a free page, fails its checksum
+    /// or cannot be read.
+    BadPage { page: u64, message: String },
+    /// A page of the tree holding `namespace`, or of the catalog of
+    /// namespaces if `None`, fails its checksum or is linked in wrongly, so
+    /// entries under it may be lost.
+    BadTree {
+        namespace: Option<String>,
+        message: String,
+    },
+    /// The record holding the value under `key` in `namespace` fails its
+    /// checksum or cannot be decoded.
+    BadRecord {
+        namespace: String,
+        key: Vec<u8>,
+        message: String,
+    },
+    /// The list of free pages is broken.
+    BadFreeList(String),
+    /// This many pages are neither in a tree nor free, so their space is
+    /// lost until the free list is rebuilt.
+    LeakedPages(u64),
+}
+
+impl StorageIssue {
+    /// Returns `true` if `StorageEngine::repair` fixes the issue. Bad
+    /// records are fixed by deleting them; damaged trees cannot be fixed.
+    pub fn is_repairable(&self) -> bool {
+        matches!(
+            self,
+            StorageIssue::BadPage { .. }
+                | StorageIssue::BadFreeList(_)
+                | StorageIssue::LeakedPages(_)
+        )
+    }
+}
+
+impl fmt::Display for StorageIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageIssue::BadPage { page, message } => write!(f, "page {}: {}", page, message),
+            StorageIssue::BadTree {
+                namespace: Some(namespace),
+                message,
+            } => write!(f, "tree of {}: {}", namespace, message),
+            StorageIssue::BadTree {
+                namespace: None,
+                message,
+            } => write!(f, "catalog tree: {}", message),
+            StorageIssue::BadRecord {
+                namespace,
+                key,
+                message,
+            } => write!(f, "{} key {}: {}", namespace, hex::encode(key), message),
+            StorageIssue::BadFreeList(message) => write!(f, "free list: {}", message),
+            StorageIssue::LeakedPages(count) => {
+                write!(f, "{} pages are neither used nor free", count)
+            }
+        }
+    }
+}
+
+/// An ordered key-value store holding the documents and indexes of a database.
+///
+/// Data is grouped into namespaces, such as one per collection and one per
+/// index, each an independent map from byte keys to byte values ordered
+/// bytewise. Namespaces are created by the first `put` into them.
+///
+/// Scans return at most `limit` entries; callers page through larger ranges
+/// by resuming with `KeyRange::after` the last key they saw, so no borrow of
+/// the engine is held between batches.
+///
+/// Writes are not durable until `flush` returns. Higher layers make them
+/// crash-safe by logging them to a `Wal` first and replaying it on startup,
+/// which is why every write is idempotent.
+pub trait StorageEngine: Send {
+    /// Returns the value stored under `key` in `namespace`.
+    fn get(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>, StorageError>;
+
+    /// Returns the value stored under `key` in `namespace`, borrowed from
+    /// the engine when it can hand one out without copying it.
+    ///
+    /// The default copies the value with `get`.
+    fn get_ref(&self, namespace: &str, key: &[u8]) -> Result<Option<Cow<'_, [u8]>>, StorageError> {
+        Ok(self.get(namespace, key)?.map(Cow::Owned))
+    }
+
+    /// Stores `value` under `key` in `namespace`, replacing any previous value.
+    fn put(&mut self, namespace: &str, key: &[u8], value: &[u8]) -> Result<(), StorageError>;
+
+    /// Removes `key` from `namespace`, returning `true` if it was present.
+    fn delete(&mut self, namespace: &str, key: &[u8]) -> Result<bool, StorageError>;
+
+    /// Returns up to `limit` entries of `namespace` in `range`, in key order.
+    fn scan(
+        &self,
+        namespace: &str,
+        range: &KeyRange,
+        limit: usize,
+    ) -> Result<Vec<Entry>, StorageError>;
+
+    /// Returns the names of every namespace, in order.
+    fn namespaces(&self) -> Result<Vec<String>, StorageError>;
+
+    /// Removes a namespace and everything in it, returning `true` if it existed.
+    fn drop_namespace(&mut self, namespace: &str) -> Result<bool, StorageError>;
+
+    /// Forces every write made so far to stable storage.
+    fn flush(&mut self) -> Result<(), StorageError>;
+
+    /// Does a bounded piece of compaction, going through about `budget`
+    /// bytes of live data to reclaim the space held by replaced and
+    /// deleted entries.
+    ///
+    /// Compaction runs across calls, so other operations can be
+    /// interleaved with its steps; callers repeat steps until one reports
+    /// `done`, and the next call starts over. Engines with nothing to
+    /// compact keep the default, which reports `done` at once.
+    fn compact_step(&mut self, budget: u64) -> Result<CompactionStep, StorageError> {
+        let _ = budget;
+        Ok(CompactionStep {
+            done: true,
+            ..CompactionStep::default()
+        })
+    }
+
+    /// Checks the engine's files for damage: checksums, the structures
+    /// linking pages and records together, and the records values are
+    /// stored in. Every write made so far is flushed first.
+    ///
+    /// Engines with nothing to check keep the default, which finds nothing.
+    fn verify(&mut self) -> Result<Vec<StorageIssue>, StorageError> {
+        Ok(Vec::new())
+    }
+
+    /// Rebuilds what can be rebuilt from the rest of the engine's files,
+    /// such as the list of free pages, fixing the issues `verify` reports
+    /// as repairable, and flushes the engine.
+    ///
+    /// Engines with nothing to rebuild keep the default, which does
+    /// nothing.
+    fn repair(&mut self) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    /// Returns the state of the engine's cache and the space compaction
+    /// would reclaim.
+    ///
+    /// Engines with neither keep the default, which reports nothing.
+    fn stats(&self) -> EngineStats {
+        EngineStats::default()
+    }
+}
@@ -0,0 +1,58 @@
+// src/storage/error.rs
+
+use std::io;
+
+use silentdb_data_encoding::{DeserializeError, SerializeError};
+
+/// Represents errors that can occur in the storage layer.
+#[derive(Debug, thiserror::Error)]
+pub enum StorageError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Serialization error: {0}")]
+    Serialize(#[from] SerializeError),
+    #[error("Deserialization error: {0}")]
+    Deserialize(#[from] DeserializeError),
+    #[error("Key and value of {len} bytes exceed maximum of {max}")]
+    KeyTooLarge { len: usize, max: usize },
+    #[error("Corrupt data in {location}: {message}")]
+    Corrupt { location: String, message: String },
+    #[error("Invalid encryption keys: {0}")]
+    InvalidKeys(String),
+    #[error("Key {id} needed for {location} is unavailable")]
+    KeyUnavailable { location: String, id: u32 },
+    #[error("{location} is encrypted but no keys were given")]
+    Encrypted { location: String },
+    #[error("{location} is not encrypted")]
+    NotEncrypted { location: String },
+}
+
+impl StorageError {
+    /// Creates a `Corrupt` error for data found at `location`.
+    pub(crate) fn corrupt(location: impl ToString, message: impl ToString) -> Self {
+        StorageError::Corrupt {
+            location: location.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    /// Returns `true` if the I/O that failed may succeed if tried again.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            StorageError::Io(e) => matches!(
+                e.kind(),
+                io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ),
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if the error is a missing key rather than damage, so
+    /// the data it was reading must be left alone.
+    pub(crate) fn is_key_error(&self) -> bool {
+        matches!(
+            self,
+            StorageError::KeyUnavailable { .. } | StorageError::Encrypted { .. }
+        )
+    }
+}
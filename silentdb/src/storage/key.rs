@@ -0,0 +1,308 @@
+// src/storage/key.rs
+
+use silentdb_data_encoding::{Array, Document, ObjectId, Value};
+
+use super::error::StorageError;
+
+/* Type Tags */
+
+const TAG_MIN_KEY: u8 = 0x01;
+const TAG_NULL: u8 = 0x05;
+const TAG_NUMBER: u8 = 0x0A;
+const TAG_STRING: u8 = 0x0F;
+const TAG_DOCUMENT: u8 = 0x14;
+const TAG_ARRAY: u8 = 0x19;
+const TAG_BINARY: u8 = 0x1E;
+const TAG_OBJECT_ID: u8 = 0x23;
+const TAG_BOOLEAN: u8 = 0x28;
+const TAG_DATE: u8 = 0x2D;
+const TAG_TIMESTAMP: u8 = 0x32;
+const TAG_REGEX: u8 = 0x37;
+const TAG_DB_POINTER: u8 = 0x3C;
+const TAG_CODE: u8 = 0x41;
+const TAG_CODE_WITH_SCOPE: u8 = 0x46;
+const TAG_MAX_KEY: u8 = 0xF0;
+
+/// Marks another element inside an encoded document or array.
+const MORE: u8 = 0x01;
+/// Ends an encoded document, array or escaped string.
+const END: u8 = 0x00;
+
+/// Encodes `value` so that comparing encodings byte by byte gives the same
+/// result as `Value::bson_cmp`.
+///
+/// Encodings are self-delimiting, so keys for several values can be
+/// concatenated and still compare field by field. Values that compare equal
+/// encode identically, which makes the encoding lossy: numbers lose their
+/// type, symbols become strings, `Undefined` becomes `Null`, and documents
+/// are written in key order.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb::storage::encode_key;
+/// # use silentdb_data_encoding::Value;
+/// assert!(encode_key(&Value::Int32(2)) < encode_key(&Value::Double(2.5)));
+/// assert_eq!(encode_key(&Value::Int32(1)), encode_key(&Value::Double(1.0)));
+/// ```
+pub fn encode_key(value: &Value) -> Vec<u8> {
+    let mut out = Vec::new();
+    encode_key_into(value, &mut out);
+    out
+}
+
+/// Appends the key encoding of `value` to `out`. See `encode_key`.
+pub fn encode_key_into(value: &Value, out: &mut Vec<u8>) {
+    match value {
+        Value::MinKey => out.push(TAG_MIN_KEY),
+        Value::Null | Value::Undefined => out.push(TAG_NULL),
+        Value::Int32(n) => encode_number(*n as f64, Some(*n as i128), out),
+        Value::Int64(n) => encode_number(*n as f64, Some(*n as i128), out),
+        Value::UInt64(n) => encode_number(*n as f64, Some(*n as i128), out),
+        Value::Double(n) => {
+            let exact = (n.is_finite() && n.fract() == 0.0 && n.abs() < 1e30).then_some(*n as i128);
+            encode_number(*n, exact, out)
+        }
+        Value::String(s) | Value::Symbol(s) => {
+            out.push(TAG_STRING);
+            escape(s.as_bytes(), out);
+        }
+        Value::Document(document) => {
+            out.push(TAG_DOCUMENT);
+            encode_document(document, out);
+        }
+        Value::Array(array) => {
+            out.push(TAG_ARRAY);
+            for item in array.iter() {
+                out.push(MORE);
+                encode_key_into(item, out);
+            }
+            out.push(END);
+        }
+        Value::Binary(bytes) => {
+            out.push(TAG_BINARY);
+            out.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+            out.extend_from_slice(bytes);
+        }
+        Value::ObjectId(id) => {
+            out.push(TAG_OBJECT_ID);
+            out.extend_from_slice(id.as_bytes());
+        }
+        Value::Boolean(b) => out.extend_from_slice(&[TAG_BOOLEAN, *b as u8]),
+        Value::UTCDateTime(n) => {
+            out.push(TAG_DATE);
+            out.extend_from_slice(&flip_sign(*n));
+        }
+        Value::Timestamp(n) => {
+            out.push(TAG_TIMESTAMP);
+            out.extend_from_slice(&flip_sign(*n));
+        }
+        Value::RegularExpression { pattern, options } => {
+            out.push(TAG_REGEX);
+            escape(pattern.as_bytes(), out);
+            escape(options.as_bytes(), out);
+        }
+        Value::DbPointer { namespace, id } => {
+            out.push(TAG_DB_POINTER);
+            escape(namespace.as_bytes(), out);
+            out.extend_from_slice(id.as_bytes());
+        }
+        Value::JavaScriptCode(code) => {
+            out.push(TAG_CODE);
+            escape(code.as_bytes(), out);
+        }
+        Value::JavaScriptCodeWithScope { code, scope } => {
+            out.push(TAG_CODE_WITH_SCOPE);
+            escape(code.as_bytes(), out);
+            encode_document(scope, out);
+        }
+        Value::MaxKey => out.push(TAG_MAX_KEY),
+    }
+}
+
+/// Decodes one value from the start of `bytes`, returning it and the number
+/// of bytes it used.
+///
+/// Numbers decode as the narrowest of `Int32`, `Int64` and `UInt64` that
+/// holds them, or as `Double` if they are not integers.
+///
+/// # Errors
+///
+/// Returns `Corrupt` if `bytes` does not start with a valid key encoding.
+pub fn decode_key(bytes: &[u8]) -> Result<(Value, usize), StorageError> {
+    let mut reader = KeyReader { bytes, position: 0 };
+    let value = reader.value()?;
+    Ok((value, reader.position))
+}
+
+fn encode_number(value: f64, exact: Option<i128>, out: &mut Vec<u8>) {
+    out.push(TAG_NUMBER);
+    let bits = if value.is_nan() {
+        // NaN sorts below negative infinity
+        0
+    } else {
+        // Normalize -0.0, then flip so byte order matches numeric order
+        let bits = (value + 0.0).to_bits();
+        if bits >> 63 == 1 {
+            !bits
+        } else {
+            bits | 1 << 63
+        }
+    };
+    out.extend_from_slice(&bits.to_be_bytes());
+    // Integers that round to the same double are told apart by their exact value
+    let exact = exact.unwrap_or(0) as u128 ^ 1 << 127;
+    out.extend_from_slice(&exact.to_be_bytes());
+}
+
+fn encode_document(document: &Document, out: &mut Vec<u8>) {
+    let mut fields: Vec<_> = document.iter().collect();
+    fields.sort_by(|a, b| a.0.cmp(b.0));
+    for (key, value) in fields {
+        out.push(MORE);
+        escape(key.as_bytes(), out);
+        encode_key_into(value, out);
+    }
+    out.push(END);
+}
+
+/// Writes `bytes` with each null byte escaped as `00 FF`, followed by `00 00`.
+fn escape(bytes: &[u8], out: &mut Vec<u8>) {
+    for byte in bytes {
+        out.push(*byte);
+        if *byte == 0 {
+            out.push(0xFF);
+        }
+    }
+    out.extend_from_slice(&[0, 0]);
+}
+
+fn flip_sign(n: i64) -> [u8; 8] {
+    ((n as u64) ^ 1 << 63).to_be_bytes()
+}
+
+struct KeyReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> KeyReader<'a> {
+    fn corrupt(&self, message: &str) -> StorageError {
+        StorageError::corrupt(format!("index key at offset {}", self.position), message)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], StorageError> {
+        let bytes = self
+            .bytes
+            .get(self.position..self.position + len)
+            .ok_or_else(|| self.corrupt("truncated key"))?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, StorageError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u64(&mut self) -> Result<u64, StorageError> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn i64(&mut self) -> Result<i64, StorageError> {
+        Ok((self.u64()? ^ 1 << 63) as i64)
+    }
+
+    fn object_id(&mut self) -> Result<ObjectId, StorageError> {
+        Ok(ObjectId::from_bytes(self.take(12)?.try_into().unwrap()))
+    }
+
+    fn unescape(&mut self) -> Result<Vec<u8>, StorageError> {
+        let mut out = Vec::new();
+        loop {
+            match self.u8()? {
+                0 => match self.u8()? {
+                    0 => return Ok(out),
+                    0xFF => out.push(0),
+                    _ => return Err(self.corrupt("invalid escape")),
+                },
+                byte => out.push(byte),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, StorageError> {
+        String::from_utf8(self.unescape()?).map_err(|_| self.corrupt("invalid UTF-8"))
+    }
+
+    fn document(&mut self) -> Result<Document, StorageError> {
+        let mut document = Document::new();
+        while self.u8()? == MORE {
+            let key = self.string()?;
+            document.insert(key, self.value()?);
+        }
+        Ok(document)
+    }
+
+    fn number(&mut self) -> Result<Value, StorageError> {
+        let bits = self.u64()?;
+        let exact = (u128::from_be_bytes(self.take(16)?.try_into().unwrap()) ^ 1 << 127) as i128;
+        let value = match bits {
+            0 => f64::NAN,
+            bits if bits >> 63 == 1 => f64::from_bits(bits & !(1 << 63)),
+            bits => f64::from_bits(!bits),
+        };
+        if !(value.is_finite() && value.fract() == 0.0 && value.abs() < 1e30) {
+            return Ok(Value::Double(value));
+        }
+        Ok(if let Ok(n) = i32::try_from(exact) {
+            Value::Int32(n)
+        } else if let Ok(n) = i64::try_from(exact) {
+            Value::Int64(n)
+        } else if let Ok(n) = u64::try_from(exact) {
+            Value::UInt64(n)
+        } else {
+            Value::Double(value)
+        })
+    }
+
+    fn value(&mut self) -> Result<Value, StorageError> {
+        let value = match self.u8()? {
+            TAG_MIN_KEY => Value::MinKey,
+            TAG_NULL => Value::Null,
+            TAG_NUMBER => self.number()?,
+            TAG_STRING => Value::String(self.string()?),
+            TAG_DOCUMENT => Value::Document(self.document()?),
+            TAG_ARRAY => {
+                let mut array = Array::new();
+                while self.u8()? == MORE {
+                    array.push(self.value()?);
+                }
+                Value::Array(array)
+            }
+            TAG_BINARY => {
+                let len = u32::from_be_bytes(self.take(4)?.try_into().unwrap());
+                Value::Binary(self.take(len as usize)?.to_vec())
+            }
+            TAG_OBJECT_ID => Value::ObjectId(self.object_id()?),
+            TAG_BOOLEAN => Value::Boolean(self.u8()? != 0),
+            TAG_DATE => Value::UTCDateTime(self.i64()?),
+            TAG_TIMESTAMP => Value::Timestamp(self.i64()?),
+            TAG_REGEX => Value::RegularExpression {
+                pattern: self.string()?,
+                options: self.string()?,
+            },
+            TAG_DB_POINTER => Value::DbPointer {
+                namespace: self.string()?,
+                id: self.object_id()?,
+            },
+            TAG_CODE => Value::JavaScriptCode(self.string()?),
+            TAG_CODE_WITH_SCOPE => Value::JavaScriptCodeWithScope {
+                code: self.string()?,
+                scope: self.document()?,
+            },
+            TAG_MAX_KEY => Value::MaxKey,
+            tag => return Err(self.corrupt(&format!("unknown key tag {:#04x}", tag))),
+        };
+        Ok(value)
+    }
+}
@@ -0,0 +1,944 @@
+// src/storage/lsm.rs
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Write};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+
+use silentdb_data_encoding::{
+    from_bytes, to_bytes, Array, Document, DocumentFileIterator, Encoder, Value,
+};
+
+use super::crc32;
+use super::engine::{CompactionStep, Entry, KeyRange, StorageEngine};
+use super::error::StorageError;
+use super::pager::read_exact_at;
+use super::BloomFilter;
+
+/// Every this many entries, a run's sparse index records where one starts.
+const INDEX_INTERVAL: u64 = 16;
+/// Magic bytes ending every run file.
+const RUN_MAGIC: [u8; 4] = *b"SDBR";
+/// Footer offset, footer CRC-32 and magic written at the end of every run.
+const TRAILER_LEN: usize = 16;
+/// Keys deleted per batch when dropping a namespace.
+const DROP_BATCH: usize = 1024;
+
+/// Options for opening an `LsmEngine`.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb::storage::LsmOptions;
+/// let options = LsmOptions::new()
+///     .memtable_size(1024 * 1024)
+///     .level0_runs(8);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LsmOptions {
+    pub(crate) memtable_size: usize,
+    pub(crate) level0_runs: usize,
+    pub(crate) level_size: u64,
+    pub(crate) level_ratio: u64,
+    pub(crate) bloom_bits: usize,
+}
+
+impl LsmOptions {
+    /// Creates options with a 4 MiB memtable, four level-0 runs, a 10 MiB
+    /// level 1 growing tenfold per level, and 10 bloom filter bits per key.
+    pub fn new() -> Self {
+        LsmOptions {
+            memtable_size: 4 * 1024 * 1024,
+            level0_runs: 4,
+            level_size: 10 * 1024 * 1024,
+            level_ratio: 10,
+            bloom_bits: 10,
+        }
+    }
+
+    /// Sets the approximate size in bytes at which the memtable is flushed
+    /// to a new level-0 run.
+    pub fn memtable_size(mut self, bytes: usize) -> Self {
+        self.memtable_size = bytes.max(1);
+        self
+    }
+
+    /// Sets how many runs level 0 may hold before they are compacted into
+    /// level 1.
+    pub fn level0_runs(mut self, runs: usize) -> Self {
+        self.level0_runs = runs.max(1);
+        self
+    }
+
+    /// Sets the size in bytes at which level 1 is compacted into level 2.
+    pub fn level_size(mut self, bytes: u64) -> Self {
+        self.level_size = bytes.max(1);
+        self
+    }
+
+    /// Sets how many times larger each level past level 1 may grow than the
+    /// one before it.
+    pub fn level_ratio(mut self, ratio: u64) -> Self {
+        self.level_ratio = ratio.max(2);
+        self
+    }
+
+    /// Sets the bloom filter bits per key in each run. More bits mean fewer
+    /// runs read for keys they do not hold.
+    pub fn bloom_bits(mut self, bits: usize) -> Self {
+        self.bloom_bits = bits.max(1);
+        self
+    }
+}
+
+impl Default for LsmOptions {
+    fn default() -> Self {
+        LsmOptions::new()
+    }
+}
+
+/// A `StorageEngine` built as a log-structured merge tree, for write-heavy
+/// workloads where the random page writes of a `BTreeEngine` dominate.
+///
+/// Writes go to an in-memory memtable. When it fills, it is written out
+/// as a sorted run: a file of entry documents written back to back, in
+/// the same format `DocumentFileIterator` reads, followed by a footer with
+/// a sparse index and a bloom filter of the run's keys. Deletes are
+/// written as tombstones that hide older entries.
+///
+/// New runs land in level 0, where runs may overlap. Once level 0 holds
+/// `level0_runs` runs they are merged with level 1, and once a deeper
+/// level outgrows its size it is merged into the next one, so every level
+/// past 0 is a single sorted run. Tombstones are dropped when merged into
+/// the deepest level.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use silentdb::storage::{LsmEngine, LsmOptions, StorageEngine};
+/// let mut engine = LsmEngine::open("data/lsm", LsmOptions::new()).unwrap();
+/// engine.put("users", b"alice", b"{}").unwrap();
+/// engine.flush().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct LsmEngine {
+    dir: PathBuf,
+    options: LsmOptions,
+    memtable: Memtable,
+    /// Runs by level, newest first within each level.
+    levels: Vec<Vec<Run>>,
+    next_run_id: u64,
+}
+
+impl LsmEngine {
+    /// Opens the engine in `dir`, creating the directory if needed.
+    ///
+    /// Leftovers of an interrupted flush or compaction are removed.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Corrupt` if a run file is damaged, or an I/O error if the
+    /// directory cannot be read.
+    pub fn open<P: AsRef<Path>>(dir: P, options: LsmOptions) -> Result<LsmEngine, StorageError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let mut levels: Vec<Vec<Run>> = vec![Vec::new()];
+        let mut next_run_id = 1;
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            match path.extension().and_then(|ext| ext.to_str()) {
+                Some("tmp") => fs::remove_file(&path)?,
+                Some("run") => {
+                    let Some((level, id)) = parse_run_name(&path) else {
+                        continue;
+                    };
+                    if levels.len() <= level {
+                        levels.resize_with(level + 1, Vec::new);
+                    }
+                    levels[level].push(Run::open(path, id)?);
+                    next_run_id = next_run_id.max(id + 1);
+                }
+                _ => {}
+            }
+        }
+        for level in &mut levels {
+            level.sort_by_key(|run| std::cmp::Reverse(run.id));
+        }
+
+        Ok(LsmEngine {
+            dir,
+            options,
+            memtable: Memtable::default(),
+            levels,
+            next_run_id,
+        })
+    }
+
+    /// Returns the directory holding the engine's runs.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns the number of runs in each level, starting at level 0.
+    pub fn run_counts(&self) -> Vec<usize> {
+        self.levels.iter().map(Vec::len).collect()
+    }
+
+    /// Flushes the memtable and merges every run into a single run in the
+    /// deepest level, dropping tombstones and replaced entries.
+    pub fn compact(&mut self) -> Result<(), StorageError> {
+        self.flush_memtable()?;
+        let Some(deepest) = self.levels.iter().rposition(|level| !level.is_empty()) else {
+            return Ok(());
+        };
+        if deepest == 0 {
+            return self.compact_level(0);
+        }
+        for level in 0..deepest {
+            if !self.levels[level].is_empty() {
+                self.compact_level(level)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the level `compact_step` merges into the next one: the
+    /// shallowest holding runs above the deepest, or level 0 if it is the
+    /// deepest and holds more than one run.
+    fn next_merge(&self) -> Option<usize> {
+        let deepest = self.levels.iter().rposition(|level| !level.is_empty())?;
+        if deepest == 0 {
+            return (self.levels[0].len() > 1).then_some(0);
+        }
+        (0..deepest).find(|&level| !self.levels[level].is_empty())
+    }
+
+    /// Returns the total size of the runs on disk.
+    fn disk_size(&self) -> u64 {
+        self.runs().map(|run| run.file_len).sum()
+    }
+
+    /// Writes the memtable out as a new level-0 run and compacts any level
+    /// that has grown too large.
+    fn flush_memtable(&mut self) -> Result<(), StorageError> {
+        if self.memtable.len == 0 {
+            return Ok(());
+        }
+        let memtable = std::mem::take(&mut self.memtable);
+        let bottom = self.levels.iter().all(Vec::is_empty);
+        let id = self.take_run_id();
+        let mut writer =
+            RunWriter::create(&self.dir, 0, id, memtable.len, self.options.bloom_bits)?;
+        for (namespace, entries) in &memtable.namespaces {
+            for (key, value) in entries {
+                if bottom && value.is_none() {
+                    continue;
+                }
+                writer.add(namespace, key, value.as_deref())?;
+            }
+        }
+        if let Some(run) = writer.finish()? {
+            self.levels[0].insert(0, run);
+        }
+        self.compact_if_needed()
+    }
+
+    fn compact_if_needed(&mut self) -> Result<(), StorageError> {
+        if self.levels[0].len() >= self.options.level0_runs {
+            self.compact_level(0)?;
+        }
+        let mut limit = self.options.level_size;
+        let mut level = 1;
+        while level < self.levels.len() {
+            let size: u64 = self.levels[level].iter().map(|run| run.file_len).sum();
+            if size > limit {
+                self.compact_level(level)?;
+            }
+            limit = limit.saturating_mul(self.options.level_ratio);
+            level += 1;
+        }
+        Ok(())
+    }
+
+    /// Merges every run in `level` with the runs of the next level into a
+    /// single run in the next level.
+    fn compact_level(&mut self, level: usize) -> Result<(), StorageError> {
+        let target = level + 1;
+        if self.levels.len() <= target {
+            self.levels.resize_with(target + 1, Vec::new);
+        }
+        let bottom = self.levels[target + 1..].iter().all(Vec::is_empty);
+        let id = self.take_run_id();
+        let inputs: Vec<&Run> = self.levels[level]
+            .iter()
+            .chain(&self.levels[target])
+            .collect();
+        let expected = inputs.iter().map(|run| run.count as usize).sum();
+
+        let mut sources: Vec<Source> = Vec::with_capacity(inputs.len());
+        for run in &inputs {
+            sources.push(Box::new(run.cursor(0)?));
+        }
+        let mut writer =
+            RunWriter::create(&self.dir, target, id, expected, self.options.bloom_bits)?;
+        for entry in Merged::new(sources) {
+            let entry = entry?;
+            if bottom && entry.value.is_none() {
+                continue;
+            }
+            writer.add(&entry.namespace, &entry.key, entry.value.as_deref())?;
+        }
+        let output = writer.finish()?;
+
+        // The output is complete on disk, so the inputs can go, the next
+        // level's first. A bottom merge drops tombstones, and the entries
+        // they hid must go before they do: a crash in between leaves this
+        // level's runs, read before the output, whose tombstones still hide
+        // those entries until they are merged again
+        let inputs = std::mem::take(&mut self.levels[target])
+            .into_iter()
+            .chain(std::mem::take(&mut self.levels[level]));
+        for run in inputs {
+            fs::remove_file(&run.path)?;
+        }
+        self.levels[target].extend(output);
+        Ok(())
+    }
+
+    fn take_run_id(&mut self) -> u64 {
+        let id = self.next_run_id;
+        self.next_run_id += 1;
+        id
+    }
+
+    /// Returns the runs in order from newest to oldest.
+    fn runs(&self) -> impl Iterator<Item = &Run> {
+        self.levels.iter().flatten()
+    }
+
+    fn write(
+        &mut self,
+        namespace: &str,
+        key: &[u8],
+        value: Option<&[u8]>,
+    ) -> Result<(), StorageError> {
+        self.memtable.insert(namespace, key, value);
+        if self.memtable.size >= self.options.memtable_size {
+            self.flush_memtable()?;
+        }
+        Ok(())
+    }
+}
+
+impl StorageEngine for LsmEngine {
+    fn get(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>, StorageError> {
+        if let Some(value) = self.memtable.get(namespace, key) {
+            return Ok(value.cloned());
+        }
+        for run in self.runs() {
+            if let Some(value) = run.get(namespace, key)? {
+                return Ok(value);
+            }
+        }
+        Ok(None)
+    }
+
+    fn put(&mut self, namespace: &str, key: &[u8], value: &[u8]) -> Result<(), StorageError> {
+        self.write(namespace, key, Some(value))
+    }
+
+    fn delete(&mut self, namespace: &str, key: &[u8]) -> Result<bool, StorageError> {
+        if self.get(namespace, key)?.is_none() {
+            return Ok(false);
+        }
+        self.write(namespace, key, None)?;
+        Ok(true)
+    }
+
+    fn scan(
+        &self,
+        namespace: &str,
+        range: &KeyRange,
+        limit: usize,
+    ) -> Result<Vec<Entry>, StorageError> {
+        if limit == 0 || is_empty_range(range) {
+            return Ok(Vec::new());
+        }
+
+        let mut sources: Vec<Source> = Vec::new();
+        if let Some(entries) = self.memtable.namespaces.get(namespace) {
+            sources.push(Box::new(
+                entries
+                    .range::<[u8], _>((range.start_bound(), range.end_bound()))
+                    .map(|(key, value)| {
+                        Ok(RunEntry {
+                            namespace: namespace.to_string(),
+                            key: key.clone(),
+                            value: value.clone(),
+                        })
+                    }),
+            ));
+        }
+        for run in self.runs() {
+            if run.namespaces.iter().any(|name| name == namespace) {
+                sources.push(Box::new(run.scan(namespace, range)?));
+            }
+        }
+
+        let mut entries = Vec::new();
+        for entry in Merged::new(sources) {
+            let entry = entry?;
+            if let Some(value) = entry.value {
+                entries.push((entry.key, value));
+                if entries.len() == limit {
+                    break;
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    fn namespaces(&self) -> Result<Vec<String>, StorageError> {
+        let mut candidates: BTreeSet<&str> = self
+            .memtable
+            .namespaces
+            .keys()
+            .map(String::as_str)
+            .collect();
+        candidates.extend(
+            self.runs()
+                .flat_map(|run| run.namespaces.iter().map(String::as_str)),
+        );
+
+        let mut names = Vec::new();
+        for namespace in candidates {
+            // A namespace whose every key is deleted no longer exists
+            if !self.scan(namespace, &KeyRange::all(), 1)?.is_empty() {
+                names.push(namespace.to_string());
+            }
+        }
+        Ok(names)
+    }
+
+    fn drop_namespace(&mut self, namespace: &str) -> Result<bool, StorageError> {
+        let mut range = KeyRange::all();
+        let mut existed = false;
+        loop {
+            let batch = self.scan(namespace, &range, DROP_BATCH)?;
+            let Some((last, _)) = batch.last() else {
+                return Ok(existed);
+            };
+            range = range.after(last);
+            existed = true;
+            for (key, _) in batch {
+                self.write(namespace, &key, None)?;
+            }
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), StorageError> {
+        // Runs are synced as they are written, so this only has to write one
+        self.flush_memtable()
+    }
+
+    /// Merges one level into the next, whatever the budget, so a step
+    /// rewrites as much as that level and the next hold. Steps are done
+    /// once every run has been merged into the deepest level.
+    fn compact_step(&mut self, budget: u64) -> Result<CompactionStep, StorageError> {
+        let _ = budget;
+        let Some(level) = self.next_merge() else {
+            return Ok(CompactionStep {
+                done: true,
+                ..CompactionStep::default()
+            });
+        };
+        let before = self.disk_size();
+        let work = self.levels[level..]
+            .iter()
+            .take(2)
+            .flatten()
+            .map(|run| run.file_len)
+            .sum();
+        self.compact_level(level)?;
+        Ok(CompactionStep {
+            work,
+            reclaimed: before.saturating_sub(self.disk_size()),
+            done: self.next_merge().is_none(),
+        })
+    }
+}
+
+/// Recent writes, with deletes kept as `None` until flushed as tombstones.
+#[derive(Debug, Default)]
+struct Memtable {
+    namespaces: BTreeMap<String, BTreeMap<Vec<u8>, Option<Vec<u8>>>>,
+    /// Approximate bytes held, counting every write.
+    size: usize,
+    len: usize,
+}
+
+impl Memtable {
+    /// Returns `Some(None)` if `key` was deleted, or `None` if the memtable
+    /// has nothing for it.
+    fn get(&self, namespace: &str, key: &[u8]) -> Option<Option<&Vec<u8>>> {
+        self.namespaces.get(namespace)?.get(key).map(Option::as_ref)
+    }
+
+    fn insert(&mut self, namespace: &str, key: &[u8], value: Option<&[u8]>) {
+        self.size += namespace.len() + key.len() + value.map_or(0, <[u8]>::len) + 32;
+        let entries = match self.namespaces.get_mut(namespace) {
+            Some(entries) => entries,
+            None => self.namespaces.entry(namespace.to_string()).or_default(),
+        };
+        if entries
+            .insert(key.to_vec(), value.map(<[u8]>::to_vec))
+            .is_none()
+        {
+            self.len += 1;
+        }
+    }
+}
+
+/// One entry of a run: a value, or a tombstone if `value` is `None`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RunEntry {
+    namespace: String,
+    key: Vec<u8>,
+    value: Option<Vec<u8>>,
+}
+
+impl RunEntry {
+    fn position(&self) -> (&str, &[u8]) {
+        (&self.namespace, &self.key)
+    }
+
+    fn to_document(&self) -> Document {
+        let mut document = Document::new();
+        document.insert("n", self.namespace.as_str());
+        document.insert("k", Value::Binary(self.key.clone()));
+        if let Some(value) = &self.value {
+            document.insert("v", Value::Binary(value.clone()));
+        }
+        document
+    }
+
+    fn from_document(mut document: Document) -> Option<RunEntry> {
+        let (Some(Value::String(namespace)), Some(Value::Binary(key))) =
+            (document.remove("n"), document.remove("k"))
+        else {
+            return None;
+        };
+        let value = match document.remove("v") {
+            Some(Value::Binary(value)) => Some(value),
+            Some(_) => return None,
+            None => None,
+        };
+        Some(RunEntry {
+            namespace,
+            key,
+            value,
+        })
+    }
+}
+
+/// Where a run's sparse index says an entry starts.
+#[derive(Debug)]
+struct IndexEntry {
+    namespace: String,
+    key: Vec<u8>,
+    offset: u64,
+}
+
+/// A sorted run on disk, with its footer loaded.
+#[derive(Debug)]
+struct Run {
+    id: u64,
+    path: PathBuf,
+    /// Offset of the footer, where the entries end.
+    entries_len: u64,
+    file_len: u64,
+    count: u64,
+    bloom: BloomFilter,
+    index: Vec<IndexEntry>,
+    namespaces: Vec<String>,
+}
+
+impl Run {
+    fn open(path: PathBuf, id: u64) -> Result<Run, StorageError> {
+        let file = File::open(&path)?;
+        let file_len = file.metadata()?.len();
+        let corrupt = |message: &str| StorageError::corrupt(path.display(), message);
+        if file_len < TRAILER_LEN as u64 {
+            return Err(corrupt("run is too short"));
+        }
+
+        let mut trailer = [0; TRAILER_LEN];
+        read_exact_at(&file, &mut trailer, file_len - TRAILER_LEN as u64)?;
+        let entries_len = u64::from_le_bytes(trailer[..8].try_into().unwrap());
+        let crc = u32::from_le_bytes(trailer[8..12].try_into().unwrap());
+        if trailer[12..] != RUN_MAGIC || entries_len > file_len - TRAILER_LEN as u64 {
+            return Err(corrupt("missing run trailer"));
+        }
+        let mut footer = vec![0; (file_len - TRAILER_LEN as u64 - entries_len) as usize];
+        read_exact_at(&file, &mut footer, entries_len)?;
+        if crc32(&footer) != crc {
+            return Err(corrupt("checksum mismatch in run footer"));
+        }
+
+        let footer = from_bytes(&footer)?;
+        let (
+            Some(Value::Int64(count)),
+            Some(Value::Binary(bloom)),
+            Some(Value::Array(index)),
+            Some(Value::Array(namespaces)),
+        ) = (
+            footer.get("count"),
+            footer.get("bloom"),
+            footer.get("index"),
+            footer.get("namespaces"),
+        )
+        else {
+            return Err(corrupt("bad run footer"));
+        };
+        let bloom = BloomFilter::from_bytes(bloom).ok_or_else(|| corrupt("bad bloom filter"))?;
+        let index = index
+            .iter()
+            .map(|entry| {
+                let entry = entry.as_document()?;
+                match (entry.get("n"), entry.get("k"), entry.get("o")) {
+                    (
+                        Some(Value::String(namespace)),
+                        Some(Value::Binary(key)),
+                        Some(Value::Int64(offset)),
+                    ) => Some(IndexEntry {
+                        namespace: namespace.clone(),
+                        key: key.clone(),
+                        offset: *offset as u64,
+                    }),
+                    _ => None,
+                }
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| corrupt("bad run index"))?;
+        let namespaces = namespaces
+            .iter()
+            .map(|name| name.as_str().map(str::to_string))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| corrupt("bad run namespaces"))?;
+
+        Ok(Run {
+            id,
+            path,
+            entries_len,
+            file_len,
+            count: *count as u64,
+            bloom,
+            index,
+            namespaces,
+        })
+    }
+
+    /// Returns a cursor over the entries from `offset` to the footer.
+    fn cursor(&self, offset: u64) -> Result<RunCursor, StorageError> {
+        Ok(RunCursor {
+            documents: DocumentFileIterator::open_at(&self.path, offset)?,
+            end: self.entries_len,
+            path: self.path.clone(),
+        })
+    }
+
+    /// Returns the offset of the last indexed entry at or before
+    /// `(namespace, key)`, where a scan for it should start.
+    fn seek(&self, namespace: &str, key: &[u8]) -> Option<u64> {
+        let after = self.index.partition_point(|entry| {
+            (entry.namespace.as_str(), entry.key.as_slice()) <= (namespace, key)
+        });
+        after.checked_sub(1).map(|i| self.index[i].offset)
+    }
+
+    /// Returns `Some(None)` if the run holds a tombstone for `key`, or
+    /// `None` if it holds nothing for it.
+    fn get(&self, namespace: &str, key: &[u8]) -> Result<Option<Option<Vec<u8>>>, StorageError> {
+        if !self.bloom.may_contain(&bloom_key(namespace, key)) {
+            return Ok(None);
+        }
+        let Some(offset) = self.seek(namespace, key) else {
+            return Ok(None);
+        };
+        for entry in self.cursor(offset)?.take(INDEX_INTERVAL as usize) {
+            let entry = entry?;
+            match entry.position().cmp(&(namespace, key)) {
+                Ordering::Less => continue,
+                Ordering::Equal => return Ok(Some(entry.value)),
+                Ordering::Greater => break,
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the entries of `namespace` in `range`, tombstones included.
+    fn scan<'a>(
+        &self,
+        namespace: &'a str,
+        range: &'a KeyRange,
+    ) -> Result<impl Iterator<Item = Result<RunEntry, StorageError>> + 'a, StorageError> {
+        let start = match range.start_bound() {
+            Bound::Included(key) | Bound::Excluded(key) => key,
+            Bound::Unbounded => &[],
+        };
+        let offset = self.seek(namespace, start).unwrap_or(0);
+        let before_start = move |entry: &RunEntry| match entry.namespace.as_str().cmp(namespace) {
+            Ordering::Less => true,
+            Ordering::Equal => !range.contains(&entry.key) && !past_end(range, &entry.key),
+            Ordering::Greater => false,
+        };
+        Ok(self
+            .cursor(offset)?
+            .skip_while(move |entry| matches!(entry, Ok(entry) if before_start(entry)))
+            .take_while(move |entry| match entry {
+                Ok(entry) => entry.namespace == namespace && range.contains(&entry.key),
+                Err(_) => true,
+            }))
+    }
+}
+
+/// Reads a run's entries in order, stopping at its footer.
+struct RunCursor {
+    documents: DocumentFileIterator<BufReader<File>>,
+    end: u64,
+    path: PathBuf,
+}
+
+impl Iterator for RunCursor {
+    type Item = Result<RunEntry, StorageError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.documents.position() >= self.end {
+            return None;
+        }
+        let result = match self.documents.next()? {
+            Ok(document) => RunEntry::from_document(document)
+                .ok_or_else(|| StorageError::corrupt(self.path.display(), "bad run entry")),
+            Err(error) => Err(error.into()),
+        };
+        if result.is_err() {
+            // Stop after the first error
+            self.end = 0;
+        }
+        Some(result)
+    }
+}
+
+/// Writes a new run to a temporary file, renaming it into place once its
+/// footer is synced.
+struct RunWriter {
+    path: PathBuf,
+    tmp_path: PathBuf,
+    file: BufWriter<File>,
+    encoder: Encoder,
+    offset: u64,
+    count: u64,
+    bloom: BloomFilter,
+    index: Array,
+    namespaces: Vec<String>,
+    id: u64,
+}
+
+impl RunWriter {
+    fn create(
+        dir: &Path,
+        level: usize,
+        id: u64,
+        expected: usize,
+        bloom_bits: usize,
+    ) -> Result<RunWriter, StorageError> {
+        let path = dir.join(format!("L{}-{:020}.run", level, id));
+        let tmp_path = path.with_extension("tmp");
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp_path)?;
+        Ok(RunWriter {
+            path,
+            tmp_path,
+            file: BufWriter::new(file),
+            encoder: Encoder::new(),
+            offset: 0,
+            count: 0,
+            bloom: BloomFilter::new(expected, bloom_bits),
+            index: Array::new(),
+            namespaces: Vec::new(),
+            id,
+        })
+    }
+
+    /// Appends an entry, which must sort after every entry added before it.
+    fn add(
+        &mut self,
+        namespace: &str,
+        key: &[u8],
+        value: Option<&[u8]>,
+    ) -> Result<(), StorageError> {
+        if self.count.is_multiple_of(INDEX_INTERVAL) {
+            let mut entry = Document::new();
+            entry.insert("n", namespace);
+            entry.insert("k", Value::Binary(key.to_vec()));
+            entry.insert("o", self.offset as i64);
+            self.index.push(Value::Document(entry));
+        }
+        if self.namespaces.last().map(String::as_str) != Some(namespace) {
+            self.namespaces.push(namespace.to_string());
+        }
+        self.bloom.insert(&bloom_key(namespace, key));
+
+        let entry = RunEntry {
+            namespace: namespace.to_string(),
+            key: key.to_vec(),
+            value: value.map(<[u8]>::to_vec),
+        };
+        let bytes = self.encoder.encode(&entry.to_document())?;
+        self.file.write_all(bytes)?;
+        self.offset += bytes.len() as u64;
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Writes the footer and moves the run into place, or discards it and
+    /// returns `None` if no entries were added.
+    fn finish(mut self) -> Result<Option<Run>, StorageError> {
+        if self.count == 0 {
+            drop(self.file);
+            fs::remove_file(&self.tmp_path)?;
+            return Ok(None);
+        }
+
+        let namespaces = Array::from_vec(
+            self.namespaces
+                .iter()
+                .map(|name| Value::from(name.as_str()))
+                .collect(),
+        );
+        let mut footer = Document::new();
+        footer.insert("count", self.count as i64);
+        footer.insert("bloom", Value::Binary(self.bloom.to_bytes()));
+        footer.insert("index", std::mem::take(&mut self.index));
+        footer.insert("namespaces", namespaces);
+        let footer = to_bytes(&footer)?;
+
+        self.file.write_all(&footer)?;
+        self.file.write_all(&self.offset.to_le_bytes())?;
+        self.file.write_all(&crc32(&footer).to_le_bytes())?;
+        self.file.write_all(&RUN_MAGIC)?;
+        let file = self.file.into_inner().map_err(|error| error.into_error())?;
+        file.sync_all()?;
+        fs::rename(&self.tmp_path, &self.path)?;
+
+        Run::open(self.path, self.id).map(Some)
+    }
+}
+
+type Source<'a> = Box<dyn Iterator<Item = Result<RunEntry, StorageError>> + 'a>;
+
+/// Merges sources that are each sorted by namespace and key, yielding the
+/// entry from the earliest source wherever several hold the same key.
+struct Merged<'a> {
+    sources: Vec<Source<'a>>,
+    heads: Vec<Option<RunEntry>>,
+    started: bool,
+}
+
+impl<'a> Merged<'a> {
+    fn new(sources: Vec<Source<'a>>) -> Self {
+        let heads = sources.iter().map(|_| None).collect();
+        Merged {
+            sources,
+            heads,
+            started: false,
+        }
+    }
+
+    fn advance(&mut self, source: usize) -> Result<(), StorageError> {
+        self.heads[source] = self.sources[source].next().transpose()?;
+        Ok(())
+    }
+
+    fn next_entry(&mut self) -> Result<Option<RunEntry>, StorageError> {
+        if !self.started {
+            self.started = true;
+            for source in 0..self.sources.len() {
+                self.advance(source)?;
+            }
+        }
+
+        let mut newest: Option<usize> = None;
+        for (source, head) in self.heads.iter().enumerate() {
+            let Some(head) = head else {
+                continue;
+            };
+            match newest {
+                Some(i) if self.heads[i].as_ref().unwrap().position() <= head.position() => {}
+                _ => newest = Some(source),
+            }
+        }
+        let Some(newest) = newest else {
+            return Ok(None);
+        };
+
+        let entry = self.heads[newest].take().unwrap();
+        for source in 0..self.heads.len() {
+            let shadowed = match &self.heads[source] {
+                Some(head) => head.position() == entry.position(),
+                None => source == newest,
+            };
+            if shadowed {
+                self.advance(source)?;
+            }
+        }
+        Ok(Some(entry))
+    }
+}
+
+impl Iterator for Merged<'_> {
+    type Item = Result<RunEntry, StorageError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry().transpose()
+    }
+}
+
+fn bloom_key(namespace: &str, key: &[u8]) -> Vec<u8> {
+    let mut bloom_key = Vec::with_capacity(namespace.len() + 1 + key.len());
+    bloom_key.extend_from_slice(namespace.as_bytes());
+    bloom_key.push(0);
+    bloom_key.extend_from_slice(key);
+    bloom_key
+}
+
+/// Parses a run file name of the form `L<level>-<id>.run`.
+fn parse_run_name(path: &Path) -> Option<(usize, u64)> {
+    let stem = path.file_stem()?.to_str()?;
+    let (level, id) = stem.strip_prefix('L')?.split_once('-')?;
+    Some((level.parse().ok()?, id.parse().ok()?))
+}
+
+fn past_end(range: &KeyRange, key: &[u8]) -> bool {
+    match range.end_bound() {
+        Bound::Included(end) => key > end,
+        Bound::Excluded(end) => key >= end,
+        Bound::Unbounded => false,
+    }
+}
+
+/// Returns `true` if no key can fall in `range`.
+fn is_empty_range(range: &KeyRange) -> bool {
+    match (range.start_bound(), range.end_bound()) {
+        (Bound::Included(start), Bound::Included(end)) => start > end,
+        (Bound::Included(start) | Bound::Excluded(start), Bound::Excluded(end))
+        | (Bound::Excluded(start), Bound::Included(end)) => start >= end,
+        _ => false,
+    }
+}
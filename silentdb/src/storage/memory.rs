@@ -0,0 +1,126 @@
+// src/storage/memory.rs
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use super::engine::{Entry, KeyRange, StorageEngine};
+use super::error::StorageError;
+
+/// A `StorageEngine` keeping every namespace in a `BTreeMap` in memory.
+///
+/// Nothing is written to disk, so `flush` does nothing and the contents
+/// are lost when the engine is dropped. It suits tests, caches that can be
+/// rebuilt and embedded uses that do not need durability; a database over
+/// it still logs its writes to its `Wal`, and rebuilds the engine from the
+/// log when opened again.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb::storage::{KeyRange, MemoryEngine, StorageEngine};
+/// let mut engine = MemoryEngine::new();
+/// engine.put("users", b"alice", b"{}").unwrap();
+/// assert_eq!(engine.get("users", b"alice").unwrap(), Some(b"{}".to_vec()));
+/// assert_eq!(engine.scan("users", &KeyRange::all(), 10).unwrap().len(), 1);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MemoryEngine {
+    namespaces: BTreeMap<String, BTreeMap<Vec<u8>, Vec<u8>>>,
+}
+
+impl MemoryEngine {
+    /// Creates an empty engine.
+    pub fn new() -> Self {
+        MemoryEngine::default()
+    }
+
+    /// Returns the number of entries across every namespace.
+    pub fn len(&self) -> usize {
+        self.namespaces.values().map(BTreeMap::len).sum()
+    }
+
+    /// Returns `true` if no namespace holds an entry.
+    pub fn is_empty(&self) -> bool {
+        self.namespaces.values().all(BTreeMap::is_empty)
+    }
+}
+
+impl StorageEngine for MemoryEngine {
+    fn get(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>, StorageError> {
+        Ok(self
+            .namespaces
+            .get(namespace)
+            .and_then(|entries| entries.get(key))
+            .cloned())
+    }
+
+    fn get_ref(&self, namespace: &str, key: &[u8]) -> Result<Option<Cow<'_, [u8]>>, StorageError> {
+        Ok(self
+            .namespaces
+            .get(namespace)
+            .and_then(|entries| entries.get(key))
+            .map(|value| Cow::Borrowed(value.as_slice())))
+    }
+
+    fn put(&mut self, namespace: &str, key: &[u8], value: &[u8]) -> Result<(), StorageError> {
+        if !self.namespaces.contains_key(namespace) {
+            self.namespaces
+                .insert(namespace.to_string(), BTreeMap::new());
+        }
+        self.namespaces
+            .get_mut(namespace)
+            .unwrap()
+            .insert(key.to_vec(), value.to_vec());
+        Ok(())
+    }
+
+    fn delete(&mut self, namespace: &str, key: &[u8]) -> Result<bool, StorageError> {
+        Ok(self
+            .namespaces
+            .get_mut(namespace)
+            .is_some_and(|entries| entries.remove(key).is_some()))
+    }
+
+    fn scan(
+        &self,
+        namespace: &str,
+        range: &KeyRange,
+        limit: usize,
+    ) -> Result<Vec<Entry>, StorageError> {
+        let Some(entries) = self.namespaces.get(namespace) else {
+            return Ok(Vec::new());
+        };
+        if is_empty_range(range) {
+            return Ok(Vec::new());
+        }
+        Ok(entries
+            .range::<[u8], _>((range.start_bound(), range.end_bound()))
+            .take(limit)
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect())
+    }
+
+    fn namespaces(&self) -> Result<Vec<String>, StorageError> {
+        Ok(self.namespaces.keys().cloned().collect())
+    }
+
+    fn drop_namespace(&mut self, namespace: &str) -> Result<bool, StorageError> {
+        Ok(self.namespaces.remove(namespace).is_some())
+    }
+
+    fn flush(&mut self) -> Result<(), StorageError> {
+        Ok(())
+    }
+}
+
+/// Returns `true` if no key can be in `range`. `BTreeMap::range` panics
+/// on some such ranges rather than returning nothing.
+fn is_empty_range(range: &KeyRange) -> bool {
+    match (range.start_bound(), range.end_bound()) {
+        (Bound::Included(start), Bound::Included(end)) => start > end,
+        (Bound::Included(start) | Bound::Excluded(start), Bound::Excluded(end))
+        | (Bound::Excluded(start), Bound::Included(end)) => start >= end,
+        _ => false,
+    }
+}
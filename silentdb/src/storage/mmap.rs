@@ -0,0 +1,63 @@
+// src/storage/mmap.rs
+
+use std::fs::File;
+
+#[cfg(feature = "memmap2")]
+use memmap2::Mmap;
+
+/// A read-only mapping of an append-only file, for reading records without
+/// copying them.
+///
+/// Without the `memmap2` feature, or where the file cannot be mapped,
+/// nothing is mapped and every lookup misses, so callers fall back to
+/// reading the file.
+#[derive(Debug, Default)]
+pub(crate) struct MappedFile {
+    #[cfg(feature = "memmap2")]
+    map: Option<Mmap>,
+}
+
+impl MappedFile {
+    /// Maps the current contents of `file`.
+    ///
+    /// The bytes mapped must never change: records may be appended to the
+    /// file, and show up once it is mapped again, but the file must not be
+    /// rewritten or truncated while the mapping is alive.
+    #[cfg(feature = "memmap2")]
+    pub(crate) fn map(file: &File) -> MappedFile {
+        // SAFETY: callers only map files they append to, so the mapped
+        // bytes are never modified while the mapping is alive
+        let map = match file.metadata() {
+            Ok(metadata) if metadata.len() > 0 => unsafe { Mmap::map(file) }.ok(),
+            _ => None,
+        };
+        MappedFile { map }
+    }
+
+    /// Maps the current contents of `file`.
+    #[cfg(not(feature = "memmap2"))]
+    pub(crate) fn map(file: &File) -> MappedFile {
+        let _ = file;
+        MappedFile::default()
+    }
+
+    /// Returns the number of bytes mapped.
+    pub(crate) fn len(&self) -> u64 {
+        self.bytes().len() as u64
+    }
+
+    /// Returns the `len` bytes at `offset`, or `None` if they are not all
+    /// mapped.
+    pub(crate) fn get(&self, offset: u64, len: usize) -> Option<&[u8]> {
+        let start = usize::try_from(offset).ok()?;
+        self.bytes().get(start..start.checked_add(len)?)
+    }
+
+    fn bytes(&self) -> &[u8] {
+        #[cfg(feature = "memmap2")]
+        if let Some(map) = &self.map {
+            return map;
+        }
+        &[]
+    }
+}
@@ -0,0 +1,35 @@
+// src/storage/mod.rs
+
+mod bloom;
+mod btree;
+mod btree_engine;
+mod buffer_pool;
+mod checksum;
+mod compression;
+mod encryption;
+mod engine;
+mod error;
+mod key;
+mod lsm;
+mod memory;
+mod mmap;
+mod pager;
+mod test;
+mod wal;
+
+pub(crate) use bloom::{hash64, BloomFilter};
+pub use btree::{BTree, BTreeRange, MAX_ENTRY_LEN};
+pub use btree_engine::{BTreeEngine, BTreeOptions, DocumentLocation};
+pub use buffer_pool::{CacheStats, DEFAULT_CACHE_SIZE};
+pub(crate) use checksum::{crc32, crc32c};
+pub use compression::Compression;
+pub use encryption::{Encryption, Key, KeyId, KeyProvider, KeyRing, KEY_LEN};
+pub use engine::{CompactionStep, EngineStats, Entry, KeyRange, StorageEngine, StorageIssue};
+pub use error::StorageError;
+pub use key::{decode_key, encode_key, encode_key_into};
+pub use lsm::{LsmEngine, LsmOptions};
+pub use memory::MemoryEngine;
+pub use pager::{
+    PageId, Pager, ENCRYPTED_PAYLOAD_LEN, META_LEN, PAGE_HEADER_LEN, PAGE_PAYLOAD_LEN, PAGE_SIZE,
+};
+pub use wal::{DirArchive, Lsn, SegmentArchive, SyncPolicy, Wal, WalOptions, WalRecord, WalReplay};
@@ -0,0 +1,438 @@
+// src/storage/pager.rs
+
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+use super::buffer_pool::{BufferPool, CacheStats, DEFAULT_CACHE_SIZE};
+use super::crc32;
+use super::encryption::{Encryption, SEAL_OVERHEAD};
+use super::error::StorageError;
+use crate::accounting::{MemoryCategory, MemoryScope};
+
+/// Size of every page in a paged file.
+pub const PAGE_SIZE: usize = 4096;
+
+/// Size of the header at the start of every page: a CRC-32 of the rest of
+/// the page, a flags byte and three reserved bytes.
+pub const PAGE_HEADER_LEN: usize = 8;
+
+/// Bytes available to the page's owner after the header.
+pub const PAGE_PAYLOAD_LEN: usize = PAGE_SIZE - PAGE_HEADER_LEN;
+
+/// Bytes available to the page's owner in an encrypted file, where the
+/// header also holds the id of the key the page is encrypted with and its
+/// nonce, and the page ends with an authentication tag.
+pub const ENCRYPTED_PAYLOAD_LEN: usize = PAGE_PAYLOAD_LEN - SEAL_OVERHEAD;
+
+/// Set in a page's flags byte when the page is encrypted.
+const ENCRYPTED: u8 = 1;
+
+/// Identifies a page by its index in the file. Page 0 is the file header,
+/// so 0 doubles as "no page".
+pub type PageId = u64;
+
+/// Magic bytes at the start of a paged file's header page.
+const FILE_MAGIC: [u8; 4] = *b"SDBP";
+/// Version of the paged file layout.
+const FILE_VERSION: u32 = 1;
+/// Bytes of the header page's payload available to the file's owner.
+pub const META_LEN: usize = 256;
+
+/// A file of fixed-size, checksummed pages with a free list.
+///
+/// The first page holds the page count, the head of the free list and a
+/// small meta area the owner can use to find its root structures. Every
+/// page is checksummed on write and verified on read.
+///
+/// Pages are cached in a buffer pool of `DEFAULT_CACHE_SIZE` bytes unless
+/// set otherwise with `set_cache_size`. Written pages stay in the pool
+/// until `sync`, or until the pool fills with written pages, when they are
+/// all written to the file together; dropping the pager writes them too.
+///
+/// A file created with an `Encryption` has every page, the first
+/// included, encrypted and authenticated against its page number, which
+/// leaves `ENCRYPTED_PAYLOAD_LEN` bytes to the owner. Such a file can only
+/// be opened with the keys its pages were encrypted with.
+#[derive(Debug)]
+pub struct Pager {
+    path: PathBuf,
+    file: File,
+    page_count: u64,
+    free_head: PageId,
+    meta: Vec<u8>,
+    encryption: Option<Encryption>,
+    pool: Mutex<BufferPool>,
+}
+
+impl Pager {
+    /// Opens the paged file at `path`, creating it if it does not exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Corrupt` if the file exists but is not a paged file, or an
+    /// I/O error if it cannot be opened.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Pager, StorageError> {
+        Pager::open_with(path, None)
+    }
+
+    /// Opens the paged file at `path`, creating it if it does not exist,
+    /// with its pages encrypted by `encryption` if given.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Encrypted` or `NotEncrypted` if the file exists and is
+    /// encrypted only one of the two ways, `KeyUnavailable` if its key is,
+    /// `Corrupt` if it is not a paged file, or an I/O error if it cannot
+    /// be opened.
+    pub fn open_with<P: AsRef<Path>>(
+        path: P,
+        encryption: Option<Encryption>,
+    ) -> Result<Pager, StorageError> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+
+        let mut pager = Pager {
+            path,
+            file,
+            page_count: 1,
+            free_head: 0,
+            meta: vec![0; META_LEN],
+            encryption,
+            pool: Mutex::new(BufferPool::new(DEFAULT_CACHE_SIZE)),
+        };
+        if pager.file.metadata()?.len() == 0 {
+            pager.write_header()?;
+            pager.write_back(&mut pager.pool())?;
+            return Ok(pager);
+        }
+
+        let (header, encrypted) = pager.read_page(0)?;
+        match (encrypted, pager.encryption.is_some()) {
+            (true, false) => {
+                return Err(StorageError::Encrypted {
+                    location: pager.path.display().to_string(),
+                })
+            }
+            (false, true) => {
+                return Err(StorageError::NotEncrypted {
+                    location: pager.path.display().to_string(),
+                })
+            }
+            _ => {}
+        }
+        if header[..4] != FILE_MAGIC {
+            return Err(StorageError::corrupt(
+                pager.path.display(),
+                "not a paged file",
+            ));
+        }
+        let version = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        if version != FILE_VERSION {
+            return Err(StorageError::corrupt(
+                pager.path.display(),
+                format!("unsupported paged file version {}", version),
+            ));
+        }
+        pager.page_count = u64::from_le_bytes(header[8..16].try_into().unwrap());
+        pager.free_head = u64::from_le_bytes(header[16..24].try_into().unwrap());
+        pager.meta = header[24..24 + META_LEN].to_vec();
+        Ok(pager)
+    }
+
+    /// Returns the path of the file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the number of pages in the file, including the header page.
+    pub fn page_count(&self) -> u64 {
+        self.page_count
+    }
+
+    /// Returns the number of bytes each page holds for its owner.
+    pub fn payload_len(&self) -> usize {
+        match self.encryption {
+            Some(_) => ENCRYPTED_PAYLOAD_LEN,
+            None => PAGE_PAYLOAD_LEN,
+        }
+    }
+
+    /// Returns the owner's meta area from the header page.
+    pub fn meta(&self) -> &[u8] {
+        &self.meta
+    }
+
+    /// Replaces the start of the owner's meta area with `meta`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `meta` is longer than `META_LEN`.
+    pub fn set_meta(&mut self, meta: &[u8]) -> Result<(), StorageError> {
+        self.meta[..meta.len()].copy_from_slice(meta);
+        self.write_header()
+    }
+
+    /// Sets how many bytes of pages the buffer pool may hold, first
+    /// writing every written page to the file. Below `PAGE_SIZE`, nothing
+    /// is cached and pages are written as soon as they are.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a written page cannot be written to the file.
+    pub fn set_cache_size(&mut self, bytes: usize) -> Result<(), StorageError> {
+        self.write_back(&mut self.pool())?;
+        self.pool().resize(bytes);
+        Ok(())
+    }
+
+    /// Returns the buffer pool's counters.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.pool().stats()
+    }
+
+    /// Reads the payload of page `id`, verifying its checksum if it is not
+    /// in the buffer pool.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Corrupt` if the page is past the end of the file or fails
+    /// its checksum or authentication, or `KeyUnavailable` if the key it
+    /// was encrypted with is.
+    pub fn read(&self, id: PageId) -> Result<Vec<u8>, StorageError> {
+        if let Some(payload) = self.pool().get(id) {
+            return Ok(payload);
+        }
+        let payload = self.verify(id)?;
+        // The pool keeps a copy, which is what the cache holds
+        let _scope = MemoryScope::enter(MemoryCategory::Cache);
+        self.cache(id, payload.clone(), false)?;
+        Ok(payload)
+    }
+
+    /// Reads the payload of page `id` from the file, bypassing the buffer
+    /// pool, and checks it as `read` does. A page written since the last
+    /// `sync` is read as it was before.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors `read` does.
+    pub fn verify(&self, id: PageId) -> Result<Vec<u8>, StorageError> {
+        let (payload, encrypted) = self.read_page(id)?;
+        if encrypted != self.encryption.is_some() {
+            return Err(StorageError::corrupt(
+                self.path.display(),
+                format!("page {} is not encrypted like the file", id),
+            ));
+        }
+        Ok(payload)
+    }
+
+    fn pool(&self) -> MutexGuard<'_, BufferPool> {
+        self.pool.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Puts page `id` in the buffer pool, first writing back the written
+    /// pages if they fill it.
+    fn cache(&self, id: PageId, payload: Vec<u8>, dirty: bool) -> Result<(), StorageError> {
+        let mut pool = self.pool();
+        if pool.needs_write_back(id) {
+            self.write_back(&mut pool)?;
+        }
+        pool.put(id, payload, dirty);
+        Ok(())
+    }
+
+    /// Writes every page written to the buffer pool since it was last
+    /// written back to the file.
+    fn write_back(&self, pool: &mut BufferPool) -> Result<(), StorageError> {
+        for (id, payload) in pool.take_dirty() {
+            self.write_page(id, &payload)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the payload of page `id`, decrypting it if it is encrypted,
+    /// and returns whether it was.
+    fn read_page(&self, id: PageId) -> Result<(Vec<u8>, bool), StorageError> {
+        if id >= self.page_count {
+            return Err(StorageError::corrupt(
+                self.path.display(),
+                format!("page {} is past the end of the file", id),
+            ));
+        }
+        let mut page = vec![0; PAGE_SIZE];
+        read_exact_at(&self.file, &mut page, id * PAGE_SIZE as u64)?;
+        let crc = u32::from_le_bytes(page[..4].try_into().unwrap());
+        if crc32(&page[4..]) != crc {
+            return Err(StorageError::corrupt(
+                self.path.display(),
+                format!("checksum mismatch on page {}", id),
+            ));
+        }
+        if page[4] & ENCRYPTED == 0 {
+            page.drain(..PAGE_HEADER_LEN);
+            return Ok((page, false));
+        }
+        let encryption = self
+            .encryption
+            .as_ref()
+            .ok_or_else(|| StorageError::Encrypted {
+                location: self.path.display().to_string(),
+            })?;
+        let location = format!("{} page {}", self.path.display(), id);
+        let payload = encryption.open(&page[PAGE_HEADER_LEN..], &id.to_le_bytes(), &location)?;
+        Ok((payload, true))
+    }
+
+    /// Writes `payload` as the contents of page `id`, zero-padding it to
+    /// `payload_len`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `payload` is longer than `payload_len`.
+    pub fn write(&mut self, id: PageId, payload: &[u8]) -> Result<(), StorageError> {
+        assert!(
+            payload.len() <= self.payload_len(),
+            "page payload too large"
+        );
+        let mut padded = payload.to_vec();
+        padded.resize(self.payload_len(), 0);
+        if self.pool().capacity() == 0 {
+            return self.write_page(id, &padded);
+        }
+        self.cache(id, padded, true)
+    }
+
+    /// Writes `payload`, `payload_len` bytes, to the file as page `id`.
+    fn write_page(&self, id: PageId, payload: &[u8]) -> Result<(), StorageError> {
+        let mut page = vec![0; PAGE_SIZE];
+        match &self.encryption {
+            Some(encryption) => {
+                let location = format!("{} page {}", self.path.display(), id);
+                let sealed = encryption.seal(payload, &id.to_le_bytes(), &location)?;
+                page[4] = ENCRYPTED;
+                page[PAGE_HEADER_LEN..].copy_from_slice(&sealed);
+            }
+            None => page[PAGE_HEADER_LEN..PAGE_HEADER_LEN + payload.len()].copy_from_slice(payload),
+        }
+        let crc = crc32(&page[4..]);
+        page[..4].copy_from_slice(&crc.to_le_bytes());
+        write_all_at(&self.file, &page, id * PAGE_SIZE as u64)?;
+        Ok(())
+    }
+
+    /// Returns a page for the caller to write, reusing a freed page if there is one.
+    pub fn allocate(&mut self) -> Result<PageId, StorageError> {
+        if self.free_head != 0 {
+            let id = self.free_head;
+            let page = self.read(id)?;
+            self.free_head = u64::from_le_bytes(page[..8].try_into().unwrap());
+            self.write_header()?;
+            return Ok(id);
+        }
+        let id = self.page_count;
+        self.page_count += 1;
+        self.write(id, &[])?;
+        self.write_header()?;
+        Ok(id)
+    }
+
+    /// Returns page `id` to the free list.
+    pub fn free(&mut self, id: PageId) -> Result<(), StorageError> {
+        self.write(id, &self.free_head.to_le_bytes())?;
+        self.free_head = id;
+        self.write_header()
+    }
+
+    /// Returns the first page of the free list, or 0 if it is empty. Each
+    /// free page starts with the id of the next one.
+    pub fn free_head(&self) -> PageId {
+        self.free_head
+    }
+
+    /// Replaces the free list with `pages`, in order, rewriting each one.
+    pub fn set_free_list(&mut self, pages: &[PageId]) -> Result<(), StorageError> {
+        self.free_head = 0;
+        for &id in pages.iter().rev() {
+            self.write(id, &self.free_head.to_le_bytes())?;
+            self.free_head = id;
+        }
+        self.write_header()
+    }
+
+    /// Writes the pages held in the buffer pool to the file and forces
+    /// every written page to stable storage.
+    pub fn sync(&mut self) -> Result<(), StorageError> {
+        self.write_back(&mut self.pool())?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    fn write_header(&mut self) -> Result<(), StorageError> {
+        let mut header = Vec::with_capacity(24 + META_LEN);
+        header.extend_from_slice(&FILE_MAGIC);
+        header.extend_from_slice(&FILE_VERSION.to_le_bytes());
+        header.extend_from_slice(&self.page_count.to_le_bytes());
+        header.extend_from_slice(&self.free_head.to_le_bytes());
+        header.extend_from_slice(&self.meta);
+        self.write(0, &header)
+    }
+}
+
+impl Drop for Pager {
+    fn drop(&mut self) {
+        // Errors cannot be reported here; pages that fail to be written
+        // are recovered like any write lost since the last sync
+        let _ = self.write_back(&mut self.pool());
+    }
+}
+
+/// Reads exactly `buf.len()` bytes at `offset` without moving a shared cursor.
+#[cfg(unix)]
+pub(crate) fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+/// Reads exactly `buf.len()` bytes at `offset` without moving a shared cursor.
+#[cfg(windows)]
+pub(crate) fn read_exact_at(
+    file: &File,
+    mut buf: &mut [u8],
+    mut offset: u64,
+) -> std::io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset)? {
+            0 => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            n => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes all of `buf` at `offset`.
+#[cfg(unix)]
+pub(crate) fn write_all_at(file: &File, buf: &[u8], offset: u64) -> std::io::Result<()> {
+    std::os::unix::fs::FileExt::write_all_at(file, buf, offset)
+}
+
+/// Writes all of `buf` at `offset`.
+#[cfg(windows)]
+pub(crate) fn write_all_at(file: &File, mut buf: &[u8], mut offset: u64) -> std::io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        let n = file.seek_write(buf, offset)?;
+        buf = &buf[n..];
+        offset += n as u64;
+    }
+    Ok(())
+}
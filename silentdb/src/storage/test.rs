@@ -0,0 +1,1307 @@
+// src/storage/test.rs
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+    use std::fs;
+    use std::ops::Bound;
+    use std::path::PathBuf;
+
+    use silentdb_data_encoding::{Array, Document, ObjectId, RawDocument, Value};
+
+    use crate::storage::{
+        crc32, crc32c, decode_key, encode_key, BTree, BTreeEngine, BTreeOptions, BloomFilter,
+        Compression, Encryption, KeyProvider, KeyRange, KeyRing, LsmEngine, LsmOptions,
+        MemoryEngine, Pager, StorageEngine, StorageError, StorageIssue, SyncPolicy, Wal,
+        WalOptions, MAX_ENTRY_LEN, PAGE_SIZE,
+    };
+
+    /// Returns an empty scratch directory unique to this process and `name`.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("silentdb-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn operation(n: i32) -> Document {
+        let mut document = Document::new();
+        document.insert("n", n);
+        document
+    }
+
+    /// Returns the payloads of every record from `from` on.
+    fn replayed(wal: &Wal, from: u64) -> Vec<Document> {
+        wal.replay(from)
+            .unwrap()
+            .map(|record| record.unwrap().payload)
+            .collect()
+    }
+
+    // -------------------------------------
+    //          Checksum Tests
+    // -------------------------------------
+
+    #[test]
+    fn test_crc32_known_values() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32c(b""), 0);
+        assert_eq!(crc32c(b"123456789"), 0xE306_9283);
+    }
+
+    // -------------------------------------
+    //          WAL Tests
+    // -------------------------------------
+
+    #[test]
+    fn test_wal_append_and_replay() {
+        let dir = scratch_dir("wal-replay");
+        let mut wal = Wal::open(&dir, WalOptions::new()).unwrap();
+        assert_eq!(wal.last_lsn(), None);
+        for n in 1..=3 {
+            assert_eq!(wal.append(&operation(n)).unwrap(), n as u64);
+        }
+        assert_eq!(wal.last_lsn(), Some(3));
+        assert_eq!(
+            replayed(&wal, 1),
+            vec![operation(1), operation(2), operation(3)]
+        );
+        assert_eq!(replayed(&wal, 3), vec![operation(3)]);
+        assert!(replayed(&wal, 4).is_empty());
+    }
+
+    #[test]
+    fn test_wal_reopen_continues_sequence() {
+        let dir = scratch_dir("wal-reopen");
+        {
+            let mut wal = Wal::open(&dir, WalOptions::new()).unwrap();
+            wal.append(&operation(1)).unwrap();
+            wal.append(&operation(2)).unwrap();
+        }
+        let mut wal = Wal::open(&dir, WalOptions::new()).unwrap();
+        assert_eq!(wal.next_lsn(), 3);
+        assert_eq!(wal.append(&operation(3)).unwrap(), 3);
+        assert_eq!(replayed(&wal, 1).len(), 3);
+    }
+
+    #[test]
+    fn test_wal_truncates_torn_tail() {
+        let dir = scratch_dir("wal-torn");
+        {
+            let mut wal = Wal::open(&dir, WalOptions::new()).unwrap();
+            wal.append(&operation(1)).unwrap();
+            wal.append(&operation(2)).unwrap();
+        }
+        let segment = dir.join(format!("{:020}.wal", 1));
+        let len = fs::metadata(&segment).unwrap().len();
+        let file = fs::OpenOptions::new().write(true).open(&segment).unwrap();
+        file.set_len(len - 3).unwrap();
+
+        let mut wal = Wal::open(&dir, WalOptions::new()).unwrap();
+        assert_eq!(replayed(&wal, 1), vec![operation(1)]);
+        assert_eq!(wal.append(&operation(3)).unwrap(), 2);
+        assert_eq!(replayed(&wal, 1), vec![operation(1), operation(3)]);
+    }
+
+    #[test]
+    fn test_wal_treats_damaged_final_record_as_torn() {
+        let dir = scratch_dir("wal-damaged");
+        {
+            let mut wal = Wal::open(&dir, WalOptions::new()).unwrap();
+            wal.append(&operation(1)).unwrap();
+            wal.append(&operation(2)).unwrap();
+        }
+        let segment = dir.join(format!("{:020}.wal", 1));
+        let mut bytes = fs::read(&segment).unwrap();
+        let last = bytes.len() - 3;
+        bytes[last] ^= 0xFF;
+        fs::write(&segment, &bytes).unwrap();
+
+        let wal = Wal::open(&dir, WalOptions::new()).unwrap();
+        assert_eq!(wal.next_lsn(), 2);
+        assert_eq!(replayed(&wal, 1), vec![operation(1)]);
+    }
+
+    #[test]
+    fn test_wal_truncates_zero_filled_tail() {
+        let dir = scratch_dir("wal-zeros");
+        {
+            let mut wal = Wal::open(&dir, WalOptions::new()).unwrap();
+            wal.append(&operation(1)).unwrap();
+            wal.append(&operation(2)).unwrap();
+        }
+        let segment = dir.join(format!("{:020}.wal", 1));
+        let len = fs::metadata(&segment).unwrap().len();
+        let file = fs::OpenOptions::new().write(true).open(&segment).unwrap();
+        file.set_len(len + 4096).unwrap();
+
+        let mut wal = Wal::open(&dir, WalOptions::new()).unwrap();
+        assert_eq!(fs::metadata(&segment).unwrap().len(), len);
+        assert_eq!(replayed(&wal, 1), vec![operation(1), operation(2)]);
+        assert_eq!(wal.append(&operation(3)).unwrap(), 3);
+        assert_eq!(
+            replayed(&wal, 1),
+            vec![operation(1), operation(2), operation(3)]
+        );
+    }
+
+    #[test]
+    fn test_wal_rejects_damage_before_the_tail() {
+        let dir = scratch_dir("wal-corrupt");
+        {
+            let mut wal = Wal::open(&dir, WalOptions::new()).unwrap();
+            wal.append(&operation(1)).unwrap();
+            wal.append(&operation(2)).unwrap();
+        }
+        let segment = dir.join(format!("{:020}.wal", 1));
+        let mut bytes = fs::read(&segment).unwrap();
+        let first_len = i32::from_le_bytes(bytes[..4].try_into().unwrap()) as usize;
+        bytes[first_len - 3] ^= 0xFF;
+        fs::write(&segment, &bytes).unwrap();
+
+        assert!(matches!(
+            Wal::open(&dir, WalOptions::new()),
+            Err(StorageError::Corrupt { .. }) | Err(StorageError::Deserialize(_))
+        ));
+    }
+
+    #[test]
+    fn test_wal_rotates_segments() {
+        let dir = scratch_dir("wal-rotate");
+        let options = WalOptions::new().segment_size(1);
+        {
+            let mut wal = Wal::open(&dir, options.clone()).unwrap();
+            for n in 1..=4 {
+                wal.append(&operation(n)).unwrap();
+            }
+        }
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 4);
+
+        let wal = Wal::open(&dir, options).unwrap();
+        assert_eq!(wal.next_lsn(), 5);
+        assert_eq!(replayed(&wal, 3), vec![operation(3), operation(4)]);
+    }
+
+    #[test]
+    fn test_wal_checkpoint_removes_old_segments() {
+        let dir = scratch_dir("wal-checkpoint");
+        let options = WalOptions::new().segment_size(1);
+        let mut wal = Wal::open(&dir, options.clone()).unwrap();
+        for n in 1..=5 {
+            wal.append(&operation(n)).unwrap();
+        }
+        let size = wal.size();
+        assert_eq!(wal.checkpoint_lsn(), 0);
+
+        // Segments are only removed once every record in them is covered
+        let removed = wal.checkpoint(3).unwrap();
+        assert!(removed > 0);
+        assert_eq!(wal.size(), size - removed);
+        assert_eq!(wal.first_lsn(), 4);
+        assert_eq!(replayed(&wal, 1), vec![operation(4), operation(5)]);
+
+        drop(wal);
+        let mut wal = Wal::open(&dir, options.clone()).unwrap();
+        assert_eq!(wal.checkpoint_lsn(), 3);
+        assert_eq!(wal.next_lsn(), 6);
+
+        // Checkpointing the last record empties the log
+        wal.checkpoint(5).unwrap();
+        assert_eq!(wal.size(), 0);
+        assert_eq!(wal.first_lsn(), 6);
+        assert!(replayed(&wal, 1).is_empty());
+        drop(wal);
+        let mut wal = Wal::open(&dir, options).unwrap();
+        assert_eq!(wal.append(&operation(6)).unwrap(), 6);
+        assert_eq!(replayed(&wal, 1), vec![operation(6)]);
+
+        fs::write(dir.join("checkpoint"), b"damaged").unwrap();
+        assert!(Wal::open(&dir, WalOptions::new()).is_err());
+    }
+
+    #[test]
+    fn test_wal_sync_policies() {
+        for (name, policy) in [
+            ("always", SyncPolicy::Always),
+            ("commit", SyncPolicy::OnCommit),
+            ("interval", SyncPolicy::Interval(std::time::Duration::ZERO)),
+        ] {
+            let dir = scratch_dir(&format!("wal-sync-{}", name));
+            let mut wal = Wal::open(&dir, WalOptions::new().sync_policy(policy)).unwrap();
+            wal.append(&operation(1)).unwrap();
+            wal.commit().unwrap();
+            let record = wal.replay(1).unwrap().next().unwrap().unwrap();
+            assert_eq!(record.lsn, 1);
+            assert_eq!(record.payload.get("n"), Some(&Value::Int32(1)));
+        }
+    }
+
+    // -------------------------------------
+    //          Key Encoding Tests
+    // -------------------------------------
+
+    fn key_samples() -> Vec<Value> {
+        let mut small = Document::new();
+        small.insert("a", 1);
+        let mut larger = small.clone();
+        larger.insert("b", "x");
+        vec![
+            Value::MinKey,
+            Value::Null,
+            Value::Double(f64::NAN),
+            Value::Double(f64::NEG_INFINITY),
+            Value::Int64(i64::MIN),
+            Value::Int32(-5),
+            Value::Double(-0.5),
+            Value::Double(0.0),
+            Value::Int32(1),
+            Value::Double(1.5),
+            Value::Int64(1 << 53),
+            Value::Int64((1 << 53) + 1),
+            Value::UInt64(u64::MAX),
+            Value::Double(f64::INFINITY),
+            Value::String(String::new()),
+            Value::String("a".to_string()),
+            Value::String("a\0".to_string()),
+            Value::String("ab".to_string()),
+            Value::Document(Document::new()),
+            Value::Document(small),
+            Value::Document(larger),
+            Value::Array(Array::new()),
+            Value::Array(Array::from_vec(vec![Value::Int32(1)])),
+            Value::Array(Array::from_vec(vec![Value::Int32(1), Value::Null])),
+            Value::Binary(vec![9]),
+            Value::Binary(vec![0, 0]),
+            Value::ObjectId(ObjectId::from_bytes([1; 12])),
+            Value::Boolean(false),
+            Value::Boolean(true),
+            Value::UTCDateTime(-1),
+            Value::UTCDateTime(1),
+            Value::Timestamp(7),
+            Value::RegularExpression {
+                pattern: "a".to_string(),
+                options: "i".to_string(),
+            },
+            Value::JavaScriptCode("f()".to_string()),
+            Value::MaxKey,
+        ]
+    }
+
+    #[test]
+    fn test_key_order_matches_bson_order() {
+        let samples = key_samples();
+        for a in &samples {
+            for b in &samples {
+                assert_eq!(
+                    encode_key(a).cmp(&encode_key(b)),
+                    a.bson_cmp(b),
+                    "{:?} vs {:?}",
+                    a,
+                    b
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_key_round_trip() {
+        for value in key_samples() {
+            let mut bytes = encode_key(&value);
+            let len = bytes.len();
+            bytes.push(0xAB);
+            let (decoded, used) = decode_key(&bytes).unwrap();
+            assert_eq!(used, len);
+            assert_eq!(decoded.bson_cmp(&value), Ordering::Equal, "{:?}", value);
+        }
+        assert_eq!(
+            decode_key(&encode_key(&Value::Double(2.0))).unwrap().0,
+            Value::Int32(2)
+        );
+        assert!(decode_key(&[0x99]).is_err());
+    }
+
+    // -------------------------------------
+    //          B+Tree Tests
+    // -------------------------------------
+
+    /// Returns `0..n` in a fixed scrambled order.
+    fn scrambled(n: u32) -> Vec<u32> {
+        let mut keys: Vec<u32> = (0..n).collect();
+        let mut state = 0x2545_F491u32;
+        for i in (1..keys.len()).rev() {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            keys.swap(i, state as usize % (i + 1));
+        }
+        keys
+    }
+
+    fn tree_key(n: u32) -> Vec<u8> {
+        format!("key-{:06}", n).into_bytes()
+    }
+
+    fn tree_keys(
+        pager: &Pager,
+        tree: &BTree,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+    ) -> Vec<Vec<u8>> {
+        tree.range(pager, start, end)
+            .unwrap()
+            .map(|entry| entry.unwrap().0)
+            .collect()
+    }
+
+    #[test]
+    fn test_btree_insert_get_and_range() {
+        let dir = scratch_dir("btree-insert");
+        fs::create_dir_all(&dir).unwrap();
+        let mut pager = Pager::open(dir.join("tree.db")).unwrap();
+        let tree = BTree::create(&mut pager).unwrap();
+
+        let value = vec![7u8; 100];
+        for n in scrambled(3000) {
+            assert_eq!(tree.insert(&mut pager, &tree_key(n), &value).unwrap(), None);
+        }
+        assert!(pager.page_count() > 10);
+        assert_eq!(
+            tree.get(&pager, &tree_key(1234)).unwrap(),
+            Some(value.clone())
+        );
+        assert_eq!(tree.get(&pager, b"missing").unwrap(), None);
+        assert_eq!(
+            tree.insert(&mut pager, &tree_key(5), b"new").unwrap(),
+            Some(value.clone())
+        );
+
+        let all = tree_keys(&pager, &tree, Bound::Unbounded, Bound::Unbounded);
+        assert_eq!(all, (0..3000).map(tree_key).collect::<Vec<_>>());
+
+        let (start, end) = (tree_key(100), tree_key(200));
+        let range = tree_keys(
+            &pager,
+            &tree,
+            Bound::Excluded(&start),
+            Bound::Included(&end),
+        );
+        assert_eq!(range, (101..=200).map(tree_key).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_btree_remove_merges_and_reuses_pages() {
+        let dir = scratch_dir("btree-remove");
+        fs::create_dir_all(&dir).unwrap();
+        let mut pager = Pager::open(dir.join("tree.db")).unwrap();
+        let tree = BTree::create(&mut pager).unwrap();
+
+        let value = vec![1u8; 200];
+        for n in scrambled(2000) {
+            tree.insert(&mut pager, &tree_key(n), &value).unwrap();
+        }
+        let pages = pager.page_count();
+
+        for n in scrambled(2000).into_iter().filter(|n| n % 2 == 0) {
+            assert_eq!(
+                tree.remove(&mut pager, &tree_key(n)).unwrap(),
+                Some(value.clone())
+            );
+        }
+        assert_eq!(tree.remove(&mut pager, &tree_key(0)).unwrap(), None);
+        let odd = tree_keys(&pager, &tree, Bound::Unbounded, Bound::Unbounded);
+        assert_eq!(
+            odd,
+            (0..2000)
+                .filter(|n| n % 2 == 1)
+                .map(tree_key)
+                .collect::<Vec<_>>()
+        );
+
+        for n in scrambled(2000).into_iter().filter(|n| n % 2 == 1) {
+            tree.remove(&mut pager, &tree_key(n)).unwrap();
+        }
+        assert!(tree_keys(&pager, &tree, Bound::Unbounded, Bound::Unbounded).is_empty());
+
+        // Freed pages are reused by new inserts
+        for n in scrambled(2000) {
+            tree.insert(&mut pager, &tree_key(n), &value).unwrap();
+        }
+        assert_eq!(pager.page_count(), pages);
+    }
+
+    #[test]
+    fn test_btree_persists_and_rejects_large_entries() {
+        let dir = scratch_dir("btree-reopen");
+        fs::create_dir_all(&dir).unwrap();
+        let root = {
+            let mut pager = Pager::open(dir.join("tree.db")).unwrap();
+            let tree = BTree::create(&mut pager).unwrap();
+            for n in 0..500 {
+                tree.insert(&mut pager, &tree_key(n), b"v").unwrap();
+            }
+            let large = vec![0u8; MAX_ENTRY_LEN];
+            assert!(matches!(
+                tree.insert(&mut pager, b"k", &large),
+                Err(StorageError::KeyTooLarge { .. })
+            ));
+            tree.root()
+        };
+
+        let pager = Pager::open(dir.join("tree.db")).unwrap();
+        let tree = BTree::open(root);
+        assert_eq!(
+            tree.get(&pager, &tree_key(499)).unwrap(),
+            Some(b"v".to_vec())
+        );
+        assert_eq!(
+            tree_keys(&pager, &tree, Bound::Unbounded, Bound::Unbounded).len(),
+            500
+        );
+    }
+
+    #[test]
+    fn test_pager_detects_corrupt_pages() {
+        let dir = scratch_dir("pager-corrupt");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("tree.db");
+        let page = {
+            let mut pager = Pager::open(&path).unwrap();
+            let page = pager.allocate().unwrap();
+            pager.write(page, b"hello").unwrap();
+            page
+        };
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[page as usize * 4096 + 100] ^= 1;
+        fs::write(&path, &bytes).unwrap();
+
+        let pager = Pager::open(&path).unwrap();
+        assert!(matches!(
+            pager.read(page),
+            Err(StorageError::Corrupt { .. })
+        ));
+    }
+
+    #[test]
+    fn test_pager_buffer_pool() {
+        let dir = scratch_dir("pager-pool");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("pages.db");
+        let file_pages = |path: &PathBuf| fs::metadata(path).unwrap().len() / PAGE_SIZE as u64;
+        let payload = |n: u64| format!("page {}", n).into_bytes();
+        let pages = {
+            let mut pager = Pager::open(&path).unwrap();
+            pager.set_cache_size(4 * PAGE_SIZE).unwrap();
+            let pages: Vec<_> = (0..3).map(|_| pager.allocate().unwrap()).collect();
+            for &page in &pages {
+                pager.write(page, &payload(page)).unwrap();
+            }
+            // Written pages, the header included, stay in the pool until
+            // it fills with them
+            assert_eq!(file_pages(&path), 1);
+            let stats = pager.cache_stats();
+            assert_eq!(stats.dirty, 4);
+            let fourth = pager.allocate().unwrap();
+            pager.write(fourth, &payload(fourth)).unwrap();
+            assert_eq!(file_pages(&path), 4);
+            assert_eq!(pager.cache_stats().writebacks, stats.writebacks + 4);
+
+            // Clean pages are evicted for others, and read back on a miss
+            let before = pager.cache_stats();
+            for &page in &pages {
+                assert_eq!(&pager.read(page).unwrap()[..6], &payload(page)[..]);
+            }
+            let after = pager.cache_stats();
+            assert_eq!(after.hits + after.misses, before.hits + before.misses + 3);
+            assert!(after.evictions > 0);
+            assert_eq!((after.pages, after.capacity), (4, 4));
+            pager.sync().unwrap();
+            assert_eq!(pager.cache_stats().dirty, 0);
+
+            // Pages written since are written when the pager is dropped
+            pager.write(pages[0], b"rewritten").unwrap();
+            pages
+        };
+
+        let mut pager = Pager::open(&path).unwrap();
+        assert_eq!(&pager.read(pages[0]).unwrap()[..9], b"rewritten");
+        assert_eq!(&pager.read(pages[1]).unwrap()[..6], &payload(pages[1])[..]);
+        assert_eq!(pager.cache_stats().hits, 0);
+        assert_eq!(&pager.read(pages[1]).unwrap()[..6], &payload(pages[1])[..]);
+        assert_eq!(pager.cache_stats().hits, 1);
+
+        // Without a pool, every read and write goes to the file
+        pager.set_cache_size(0).unwrap();
+        let page = pager.allocate().unwrap();
+        pager.write(page, b"direct").unwrap();
+        assert_eq!(file_pages(&path), page + 1);
+        let hits = pager.cache_stats().hits;
+        pager.read(page).unwrap();
+        assert_eq!(pager.cache_stats().hits, hits);
+        assert_eq!(pager.cache_stats().pages, 0);
+    }
+
+    // -------------------------------------
+    //          B+Tree Engine Tests
+    // -------------------------------------
+
+    #[test]
+    fn test_btree_engine_crud() {
+        let dir = scratch_dir("engine-crud");
+        let mut engine = BTreeEngine::open(&dir).unwrap();
+        let large = vec![5u8; 1000];
+        engine.put("users", b"a", b"small").unwrap();
+        engine.put("users", b"b", &large).unwrap();
+        engine.put("orders", b"a", b"other").unwrap();
+
+        assert_eq!(engine.get("users", b"a").unwrap(), Some(b"small".to_vec()));
+        assert_eq!(engine.get("users", b"b").unwrap(), Some(large.clone()));
+        assert!(engine.locate("users", b"b").unwrap().is_some());
+        assert!(engine.locate("users", b"a").unwrap().is_none());
+        assert_eq!(engine.get("missing", b"a").unwrap(), None);
+        assert_eq!(engine.namespaces().unwrap(), vec!["orders", "users"]);
+
+        assert!(engine.delete("users", b"a").unwrap());
+        assert!(!engine.delete("users", b"a").unwrap());
+        assert!(engine.drop_namespace("orders").unwrap());
+        assert!(!engine.drop_namespace("orders").unwrap());
+        assert_eq!(engine.namespaces().unwrap(), vec!["users"]);
+    }
+
+    #[test]
+    fn test_btree_engine_scan_in_batches() {
+        let dir = scratch_dir("engine-scan");
+        let mut engine = BTreeEngine::open(&dir).unwrap();
+        for n in scrambled(250) {
+            engine.put("c", &tree_key(n), &n.to_le_bytes()).unwrap();
+        }
+
+        let mut range = KeyRange::prefix(b"key-");
+        let mut seen = Vec::new();
+        loop {
+            let batch = engine.scan("c", &range, 64).unwrap();
+            let Some((last, _)) = batch.last() else {
+                break;
+            };
+            range = range.after(last);
+            seen.extend(batch.into_iter().map(|(key, _)| key));
+        }
+        assert_eq!(seen, (0..250).map(tree_key).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_btree_engine_reopen() {
+        let dir = scratch_dir("engine-reopen");
+        let large = vec![3u8; 500];
+        {
+            let mut engine = BTreeEngine::open(&dir).unwrap();
+            engine.put("c", b"k", &large).unwrap();
+            engine.flush().unwrap();
+        }
+        let engine = BTreeEngine::open(&dir).unwrap();
+        assert_eq!(engine.get("c", b"k").unwrap(), Some(large));
+    }
+
+    /// Runs compaction steps of `budget` bytes until done, calling `between`
+    /// with the step number after each unfinished one. Returns the bytes
+    /// reclaimed and the number of steps.
+    fn compact_all(
+        engine: &mut dyn StorageEngine,
+        budget: u64,
+        mut between: impl FnMut(&mut dyn StorageEngine, usize),
+    ) -> (u64, usize) {
+        let mut reclaimed = 0;
+        for step in 1.. {
+            let progress = engine.compact_step(budget).unwrap();
+            reclaimed += progress.reclaimed;
+            if progress.done {
+                return (reclaimed, step);
+            }
+            between(engine, step);
+        }
+        unreachable!()
+    }
+
+    #[test]
+    fn test_btree_engine_compaction() {
+        let dir = scratch_dir("engine-compact");
+        let mut engine = BTreeEngine::open(&dir).unwrap();
+        let value = |n: u32, round: u8| vec![round; 200 + n as usize];
+        for round in 0..4 {
+            for n in 0..100 {
+                engine.put("c", &tree_key(n), &value(n, round)).unwrap();
+            }
+        }
+        for n in (0..100).step_by(3) {
+            engine.delete("c", &tree_key(n)).unwrap();
+        }
+        engine.put("d", b"small", b"inline").unwrap();
+        let documents = dir.join("documents.dat");
+        let before = fs::metadata(&documents).unwrap().len();
+
+        // Writes interleaved with the steps land in the new file
+        let (reclaimed, steps) = compact_all(&mut engine, 2000, |engine, step| {
+            let n = step as u32 % 100;
+            engine.put("c", &tree_key(n), &value(n, 9)).unwrap();
+        });
+        assert!(steps > 5, "{}", steps);
+        let after = fs::metadata(&documents).unwrap().len();
+        assert_eq!(reclaimed, before - after);
+        assert!(after < before / 3, "{} -> {}", before, after);
+        assert!(!dir.join("documents.compact").exists());
+
+        let check = |engine: &BTreeEngine| {
+            for n in 0..100 {
+                let expected = match n {
+                    n if (1..steps as u32).contains(&n) => Some(value(n, 9)),
+                    n if n % 3 == 0 => None,
+                    n => Some(value(n, 3)),
+                };
+                assert_eq!(engine.get("c", &tree_key(n)).unwrap(), expected, "{}", n);
+            }
+            assert_eq!(engine.get("d", b"small").unwrap(), Some(b"inline".to_vec()));
+        };
+        check(&engine);
+        engine.flush().unwrap();
+        drop(engine);
+        let mut engine = BTreeEngine::open(&dir).unwrap();
+        check(&engine);
+
+        // A compaction cut short carries on after a reopen
+        for n in 0..100 {
+            engine.put("c", &tree_key(n), &value(n, 4)).unwrap();
+        }
+        assert!(!engine.compact_step(1000).unwrap().done);
+        engine.flush().unwrap();
+        drop(engine);
+        let mut engine = BTreeEngine::open(&dir).unwrap();
+        assert!(dir.join("documents.compact").exists());
+        assert_eq!(engine.get("c", &tree_key(7)).unwrap(), Some(value(7, 4)));
+        let (reclaimed, _) = compact_all(&mut engine, 1 << 20, |_, _| {});
+        assert!(reclaimed > 0);
+        for n in 0..100 {
+            assert_eq!(engine.get("c", &tree_key(n)).unwrap(), Some(value(n, 4)));
+        }
+    }
+
+    /// Returns a text-heavy value of a few hundred bytes, different for
+    /// each `n`.
+    fn text_value(n: u32) -> Vec<u8> {
+        const WORDS: [&str; 12] = [
+            "storage",
+            "engine",
+            "document",
+            "collection",
+            "index",
+            "query",
+            "page",
+            "record",
+            "compaction",
+            "dictionary",
+            "sample",
+            "value",
+        ];
+        let mut state = n.wrapping_mul(2654435761).wrapping_add(1);
+        let mut words = Vec::new();
+        for _ in 0..60 {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            words.push(WORDS[(state >> 16) as usize % WORDS.len()]);
+        }
+        format!(
+            r#"{{"_id": {n}, "name": "user {n}", "bio": "{}"}}"#,
+            words.join(" ")
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn test_btree_engine_compression() {
+        let dir = scratch_dir("engine-compression");
+        let options = BTreeOptions::new().compression(Compression::new().dictionary_samples(50));
+        let mut engine = BTreeEngine::open_with(&dir, options.clone()).unwrap();
+        let mut raw = 0;
+        for n in 0..300 {
+            let value = text_value(n);
+            raw += value.len() as u64;
+            engine.put("c", &tree_key(n), &value).unwrap();
+        }
+        engine.put("c", b"small", b"inline").unwrap();
+        for n in 0..300 {
+            assert_eq!(engine.get("c", &tree_key(n)).unwrap(), Some(text_value(n)));
+        }
+        let documents = dir.join("documents.dat");
+        let stored = fs::metadata(&documents).unwrap().len();
+        if cfg!(feature = "zstd") {
+            assert_eq!(engine.dictionaries(), 1);
+            assert!(stored < raw / 2, "{} -> {}", raw, stored);
+        } else {
+            assert_eq!(engine.dictionaries(), 0);
+            assert!(stored > raw);
+        }
+        engine.flush().unwrap();
+        drop(engine);
+
+        // Compressed records are read without compression turned on, and
+        // compaction rewrites them uncompressed
+        let mut engine = BTreeEngine::open(&dir).unwrap();
+        engine.put("c", &tree_key(300), &text_value(300)).unwrap();
+        for n in 0..=300 {
+            assert_eq!(engine.get("c", &tree_key(n)).unwrap(), Some(text_value(n)));
+        }
+        compact_all(&mut engine, 1 << 20, |_, _| {});
+        assert!(fs::metadata(&documents).unwrap().len() > raw);
+        engine.flush().unwrap();
+        drop(engine);
+
+        // And compressed again once it is turned back on
+        let mut engine = BTreeEngine::open_with(&dir, options).unwrap();
+        compact_all(&mut engine, 1 << 20, |_, _| {});
+        let recompressed = fs::metadata(&documents).unwrap().len();
+        if cfg!(feature = "zstd") {
+            assert!(recompressed < raw / 2, "{} -> {}", raw, recompressed);
+        }
+        for n in 0..=300 {
+            assert_eq!(engine.get("c", &tree_key(n)).unwrap(), Some(text_value(n)));
+        }
+        assert_eq!(engine.get("c", b"small").unwrap(), Some(b"inline".to_vec()));
+    }
+
+    #[test]
+    fn test_btree_engine_get_ref() {
+        let dir = scratch_dir("engine-get-ref");
+        let record = |n: i32| {
+            let mut document = operation(n);
+            document.insert("padding", "x".repeat(100));
+            silentdb_data_encoding::to_bytes(&document).unwrap()
+        };
+        {
+            let mut engine = BTreeEngine::open(&dir).unwrap();
+            for n in 0..50 {
+                engine.put("c", &tree_key(n as u32), &record(n)).unwrap();
+            }
+            engine.flush().unwrap();
+        }
+
+        // Records written before the engine was opened come from the mapping
+        // when there is one, and later ones from the file
+        let mut engine = BTreeEngine::open(&dir).unwrap();
+        let last = record(50);
+        engine.put("c", &tree_key(50), &last).unwrap();
+        engine.put("c", b"inline", b"small").unwrap();
+        assert_eq!(engine.is_mapped(), cfg!(feature = "memmap2"));
+        for n in 0..=50 {
+            let value = engine.get_ref("c", &tree_key(n as u32)).unwrap().unwrap();
+            let borrowed = matches!(value, std::borrow::Cow::Borrowed(_));
+            assert_eq!(borrowed, engine.is_mapped() && n < 50, "{}", n);
+            let document = RawDocument::from_bytes(&value).unwrap();
+            assert_eq!(
+                document.get("n").unwrap().unwrap().value().unwrap(),
+                Value::Int32(n)
+            );
+            assert_eq!(
+                engine.get("c", &tree_key(n as u32)).unwrap(),
+                Some(value.into_owned())
+            );
+        }
+        assert_eq!(
+            engine.get_ref("c", b"inline").unwrap().as_deref(),
+            Some(&b"small"[..])
+        );
+        assert_eq!(engine.get_ref("c", b"missing").unwrap(), None);
+
+        // Compaction replaces the mapping along with the file
+        while !engine.compact_step(1 << 20).unwrap().done {}
+        assert_eq!(engine.is_mapped(), cfg!(feature = "memmap2"));
+        assert_eq!(
+            engine.get_ref("c", &tree_key(50)).unwrap().as_deref(),
+            Some(last.as_slice())
+        );
+    }
+
+    #[test]
+    fn test_btree_engine_verify() {
+        let dir = scratch_dir("engine-verify");
+        let record = |n: i32| {
+            let mut document = operation(n);
+            document.insert("padding", "x".repeat(100));
+            silentdb_data_encoding::to_bytes(&document).unwrap()
+        };
+        {
+            let mut engine = BTreeEngine::open(&dir).unwrap();
+            for n in 0..50 {
+                engine.put("c", &tree_key(n as u32), &record(n)).unwrap();
+            }
+            // Dropping a namespace puts its pages on the free list
+            for n in 0..500 {
+                engine.put("d", &tree_key(n), b"inline").unwrap();
+            }
+            engine.drop_namespace("d").unwrap();
+            assert_eq!(engine.verify().unwrap(), Vec::new());
+        }
+
+        // The last record written is at the end of the documents file
+        let documents = dir.join("documents.dat");
+        let mut bytes = fs::read(&documents).unwrap();
+        *bytes.last_mut().unwrap() ^= 0xFF;
+        fs::write(&documents, bytes).unwrap();
+
+        let mut engine = BTreeEngine::open(&dir).unwrap();
+        let issues = engine.verify().unwrap();
+        assert_eq!(issues.len(), 1);
+        assert!(matches!(
+            &issues[0],
+            StorageIssue::BadRecord { namespace, key, .. }
+                if namespace == "c" && *key == tree_key(49)
+        ));
+        assert!(!issues[0].is_repairable());
+
+        engine.delete("c", &tree_key(49)).unwrap();
+        engine.repair().unwrap();
+        assert_eq!(engine.verify().unwrap(), Vec::new());
+        assert_eq!(engine.get("c", &tree_key(48)).unwrap(), Some(record(48)));
+    }
+
+    #[test]
+    fn test_key_range_prefix() {
+        assert_eq!(
+            KeyRange::prefix(&[1, 0xFF]),
+            KeyRange::new(Bound::Included(vec![1, 0xFF]), Bound::Excluded(vec![2]))
+        );
+        assert_eq!(KeyRange::prefix(&[0xFF]).end, Bound::Unbounded);
+        assert!(KeyRange::all().contains(b""));
+    }
+
+    // -------------------------------------
+    //          LSM Engine Tests
+    // -------------------------------------
+
+    /// Options small enough that a few hundred writes flush and compact.
+    fn small_lsm() -> LsmOptions {
+        LsmOptions::new()
+            .memtable_size(2048)
+            .level0_runs(3)
+            .level_size(4 * 1024)
+            .level_ratio(4)
+    }
+
+    fn lsm_keys(engine: &LsmEngine, namespace: &str) -> Vec<Vec<u8>> {
+        engine
+            .scan(namespace, &KeyRange::all(), usize::MAX)
+            .unwrap()
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect()
+    }
+
+    #[test]
+    fn test_bloom_filter_has_no_false_negatives() {
+        let mut bloom = BloomFilter::new(1000, 10);
+        for n in 0..1000u32 {
+            bloom.insert(&n.to_le_bytes());
+        }
+        assert!((0..1000u32).all(|n| bloom.may_contain(&n.to_le_bytes())));
+
+        let false_positives = (1000..11_000u32)
+            .filter(|n| bloom.may_contain(&n.to_le_bytes()))
+            .count();
+        assert!(false_positives < 300, "{} false positives", false_positives);
+        assert_eq!(BloomFilter::from_bytes(&bloom.to_bytes()), Some(bloom));
+    }
+
+    #[test]
+    fn test_lsm_engine_crud() {
+        let dir = scratch_dir("lsm-crud");
+        let mut engine = LsmEngine::open(&dir, LsmOptions::new()).unwrap();
+        engine.put("users", b"a", b"first").unwrap();
+        engine.put("orders", b"a", b"other").unwrap();
+        engine.flush().unwrap();
+        engine.put("users", b"a", b"second").unwrap();
+        engine.put("users", b"b", b"b").unwrap();
+
+        assert_eq!(engine.get("users", b"a").unwrap(), Some(b"second".to_vec()));
+        assert_eq!(engine.get("orders", b"a").unwrap(), Some(b"other".to_vec()));
+        assert_eq!(engine.get("missing", b"a").unwrap(), None);
+        assert_eq!(engine.namespaces().unwrap(), vec!["orders", "users"]);
+
+        assert!(engine.delete("orders", b"a").unwrap());
+        assert!(!engine.delete("orders", b"a").unwrap());
+        assert_eq!(engine.get("orders", b"a").unwrap(), None);
+        assert_eq!(engine.namespaces().unwrap(), vec!["users"]);
+
+        assert!(engine.drop_namespace("users").unwrap());
+        assert!(!engine.drop_namespace("users").unwrap());
+        assert!(engine.namespaces().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_lsm_engine_matches_model_across_compactions() {
+        let dir = scratch_dir("lsm-model");
+        let mut engine = LsmEngine::open(&dir, small_lsm()).unwrap();
+        let mut model = std::collections::BTreeMap::new();
+        for (i, n) in scrambled(600).into_iter().enumerate() {
+            let key = tree_key(n % 200);
+            if i % 5 == 4 {
+                assert_eq!(
+                    engine.delete("c", &key).unwrap(),
+                    model.remove(&key).is_some()
+                );
+            } else {
+                engine.put("c", &key, &n.to_le_bytes()).unwrap();
+                model.insert(key, n.to_le_bytes().to_vec());
+            }
+        }
+        assert!(engine.run_counts().len() > 2, "{:?}", engine.run_counts());
+        assert!(engine.run_counts()[0] < 3);
+
+        let expected: Vec<_> = model.into_iter().collect();
+        assert_eq!(
+            engine.scan("c", &KeyRange::all(), usize::MAX).unwrap(),
+            expected
+        );
+        for (key, value) in &expected {
+            assert_eq!(engine.get("c", key).unwrap().as_ref(), Some(value));
+        }
+
+        engine.compact().unwrap();
+        let counts = engine.run_counts();
+        assert_eq!(counts.iter().sum::<usize>(), 1, "{:?}", counts);
+        assert_eq!(counts.last(), Some(&1));
+        assert_eq!(
+            engine.scan("c", &KeyRange::all(), usize::MAX).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_lsm_engine_compaction_steps() {
+        let dir = scratch_dir("lsm-compact");
+        let mut engine = LsmEngine::open(&dir, small_lsm()).unwrap();
+        for round in 0..3 {
+            for n in scrambled(200) {
+                engine.put("c", &tree_key(n), &[round; 40]).unwrap();
+            }
+        }
+        engine.flush().unwrap();
+        assert!(engine.run_counts().iter().sum::<usize>() > 1);
+
+        let (reclaimed, _) = compact_all(&mut engine, 0, |_, _| {});
+        assert!(reclaimed > 0);
+        assert_eq!(engine.run_counts().iter().sum::<usize>(), 1);
+        assert!(engine.compact_step(0).unwrap().done);
+        let entries = engine.scan("c", &KeyRange::all(), usize::MAX).unwrap();
+        assert_eq!(entries.len(), 200);
+        assert!(entries.iter().all(|(_, value)| value == &[2; 40]));
+    }
+
+    #[test]
+    fn test_lsm_engine_scan_in_batches() {
+        let dir = scratch_dir("lsm-scan");
+        let mut engine = LsmEngine::open(&dir, small_lsm()).unwrap();
+        for n in scrambled(250) {
+            engine.put("c", &tree_key(n), &n.to_le_bytes()).unwrap();
+            engine.put("d", &tree_key(n), b"").unwrap();
+        }
+        for n in (0..250).step_by(2) {
+            engine.delete("c", &tree_key(n)).unwrap();
+        }
+
+        let mut range = KeyRange::prefix(b"key-");
+        let mut seen = Vec::new();
+        loop {
+            let batch = engine.scan("c", &range, 16).unwrap();
+            let Some((last, _)) = batch.last() else {
+                break;
+            };
+            range = range.after(last);
+            seen.extend(batch.into_iter().map(|(key, _)| key));
+        }
+        assert_eq!(seen, (1..250).step_by(2).map(tree_key).collect::<Vec<_>>());
+
+        let bounded = KeyRange::new(Bound::Excluded(tree_key(10)), Bound::Included(tree_key(20)));
+        let keys: Vec<_> = engine
+            .scan("d", &bounded, usize::MAX)
+            .unwrap()
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(keys, (11..=20).map(tree_key).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_lsm_engine_reopen() {
+        let dir = scratch_dir("lsm-reopen");
+        {
+            let mut engine = LsmEngine::open(&dir, small_lsm()).unwrap();
+            for n in 0..300 {
+                engine.put("c", &tree_key(n), &[7; 40]).unwrap();
+            }
+            engine.delete("c", &tree_key(0)).unwrap();
+            engine.flush().unwrap();
+        }
+        // A run left half-written by a crash is discarded
+        fs::write(dir.join("L0-99999999999999999999.tmp"), b"partial").unwrap();
+
+        let engine = LsmEngine::open(&dir, small_lsm()).unwrap();
+        assert_eq!(engine.get("c", &tree_key(0)).unwrap(), None);
+        assert_eq!(engine.get("c", &tree_key(299)).unwrap(), Some(vec![7; 40]));
+        assert_eq!(
+            lsm_keys(&engine, "c"),
+            (1..300).map(tree_key).collect::<Vec<_>>()
+        );
+        assert!(!dir.join("L0-99999999999999999999.tmp").exists());
+    }
+
+    #[test]
+    fn test_lsm_engine_compaction_crash_keeps_deletes() {
+        let dir = scratch_dir("lsm-compaction-crash");
+        let level0_runs = |dir: &std::path::Path| -> Vec<_> {
+            fs::read_dir(dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .filter(|path| {
+                    let name = path.file_name().unwrap().to_str().unwrap();
+                    name.starts_with("L0-") && name.ends_with(".run")
+                })
+                .collect()
+        };
+        let mut engine = LsmEngine::open(&dir, LsmOptions::new()).unwrap();
+        engine.put("c", b"a", b"old").unwrap();
+        engine.put("c", b"b", b"old").unwrap();
+        engine.compact().unwrap();
+        assert_eq!(engine.run_counts(), vec![0, 1]);
+        engine.delete("c", b"a").unwrap();
+        engine.put("c", b"b", b"new").unwrap();
+        engine.flush().unwrap();
+
+        // A bottom merge drops the tombstone. Crashing after the level-1
+        // input is removed, but before the level-0 one is, leaves the
+        // latter behind
+        let runs: Vec<_> = level0_runs(&dir)
+            .into_iter()
+            .map(|path| {
+                let bytes = fs::read(&path).unwrap();
+                (path, bytes)
+            })
+            .collect();
+        assert_eq!(runs.len(), 1);
+        engine.compact().unwrap();
+        drop(engine);
+        assert!(level0_runs(&dir).is_empty());
+        for (path, bytes) in runs {
+            fs::write(path, bytes).unwrap();
+        }
+
+        let mut engine = LsmEngine::open(&dir, LsmOptions::new()).unwrap();
+        assert_eq!(engine.run_counts(), vec![1, 1]);
+        assert_eq!(engine.get("c", b"a").unwrap(), None);
+        assert_eq!(engine.get("c", b"b").unwrap(), Some(b"new".to_vec()));
+        engine.compact().unwrap();
+        assert_eq!(engine.get("c", b"a").unwrap(), None);
+        assert_eq!(lsm_keys(&engine, "c"), vec![b"b".to_vec()]);
+    }
+
+    #[test]
+    fn test_lsm_engine_rejects_damaged_run() {
+        let dir = scratch_dir("lsm-damaged");
+        {
+            let mut engine = LsmEngine::open(&dir, LsmOptions::new()).unwrap();
+            engine.put("c", b"k", b"v").unwrap();
+            engine.flush().unwrap();
+        }
+        let run = fs::read_dir(&dir).unwrap().next().unwrap().unwrap().path();
+        let mut bytes = fs::read(&run).unwrap();
+        let footer = bytes.len() - 20;
+        bytes[footer] ^= 0xFF;
+        fs::write(&run, bytes).unwrap();
+
+        assert!(matches!(
+            LsmEngine::open(&dir, LsmOptions::new()),
+            Err(StorageError::Corrupt { .. })
+        ));
+    }
+
+    // -------------------------------------
+    //          Encryption Tests
+    // -------------------------------------
+
+    fn contains(path: &std::path::Path, needle: &[u8]) -> bool {
+        let bytes = fs::read(path).unwrap();
+        bytes.windows(needle.len()).any(|window| window == needle)
+    }
+
+    #[test]
+    fn test_key_ring_parsing() {
+        let listed = format!(
+            "# rotated yearly\n7:{}, 8:{}  # current\n",
+            "ab".repeat(32),
+            "cd".repeat(32)
+        );
+        let keys = KeyRing::parse(&listed).unwrap();
+        assert_eq!(keys.current_key_id(), 8);
+        assert_eq!(keys.key(7), Some([0xab; 32]));
+        assert_eq!(keys.key(9), None);
+        assert_eq!(keys, KeyRing::new(7, [0xab; 32]).rotate(8, [0xcd; 32]));
+
+        let short = format!("7:{}", "ab".repeat(31));
+        for bad in ["", "# nothing", "7", "x:00", &short] {
+            assert!(
+                matches!(KeyRing::parse(bad), Err(StorageError::InvalidKeys(_))),
+                "{:?}",
+                bad
+            );
+        }
+        let path = scratch_dir("keys-file");
+        fs::write(&path, &listed).unwrap();
+        assert_eq!(KeyRing::from_file(&path).unwrap(), keys);
+    }
+
+    #[test]
+    fn test_btree_engine_encryption() {
+        let dir = scratch_dir("engine-encryption");
+        let keys = KeyRing::new(1, [1; 32]);
+        let encrypted =
+            |keys: &KeyRing| BTreeOptions::new().encryption(Encryption::new(keys.clone()));
+        let value = |n: u32| format!("{} secret value {}", "-".repeat(80), n).into_bytes();
+        {
+            let options = encrypted(&keys).compression(Compression::new().dictionary_samples(20));
+            let mut engine = BTreeEngine::open_with(&dir, options).unwrap();
+            for n in 0..50 {
+                engine.put("c", &tree_key(n), &value(n)).unwrap();
+            }
+            engine
+                .put("secret namespace", b"k", b"secret inline")
+                .unwrap();
+            engine.flush().unwrap();
+        }
+        for file in ["data.db", "documents.dat", "dictionaries.dat"] {
+            assert!(!contains(&dir.join(file), b"secret"), "{}", file);
+        }
+
+        // Only the right keys open the engine
+        assert!(matches!(
+            BTreeEngine::open(&dir),
+            Err(StorageError::Encrypted { .. })
+        ));
+        assert!(matches!(
+            BTreeEngine::open_with(&dir, encrypted(&KeyRing::new(2, [1; 32]))),
+            Err(StorageError::KeyUnavailable { id: 1, .. })
+        ));
+        let plain = scratch_dir("engine-not-encrypted");
+        BTreeEngine::open(&plain).unwrap();
+        assert!(matches!(
+            BTreeEngine::open_with(&plain, encrypted(&keys)),
+            Err(StorageError::NotEncrypted { .. })
+        ));
+
+        // After a rotation, old data is read with the old key while new
+        // and compacted records are written with the new one
+        let rotated = keys.clone().rotate(2, [2; 32]);
+        let mut engine = BTreeEngine::open_with(&dir, encrypted(&rotated)).unwrap();
+        assert_eq!(
+            engine.get("secret namespace", b"k").unwrap(),
+            Some(b"secret inline".to_vec())
+        );
+        engine.put("c", &tree_key(50), &value(50)).unwrap();
+        compact_all(&mut engine, 1 << 20, |_, _| {});
+        for n in 0..=50 {
+            assert_eq!(
+                engine.get("c", &tree_key(n)).unwrap(),
+                Some(value(n)),
+                "{}",
+                n
+            );
+        }
+        engine.flush().unwrap();
+        drop(engine);
+        let engine = BTreeEngine::open_with(&dir, encrypted(&rotated)).unwrap();
+        assert_eq!(engine.get("c", &tree_key(7)).unwrap(), Some(value(7)));
+        assert_eq!(engine.dictionaries(), usize::from(cfg!(feature = "zstd")));
+    }
+
+    #[test]
+    fn test_wal_encryption() {
+        let dir = scratch_dir("wal-encryption");
+        let keys = KeyRing::new(1, [5; 32]);
+        let encrypted =
+            |keys: &KeyRing| WalOptions::new().encryption(Encryption::new(keys.clone()));
+        let mut secret = operation(1);
+        secret.insert("note", "secret note");
+        {
+            let mut wal = Wal::open(&dir, WalOptions::new()).unwrap();
+            wal.append(&operation(0)).unwrap();
+            drop(wal);
+            let mut wal = Wal::open(&dir, encrypted(&keys)).unwrap();
+            wal.append(&secret).unwrap();
+            wal.append(&operation(2)).unwrap();
+        }
+        let segment = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.extension().is_some_and(|ext| ext == "wal"))
+            .unwrap();
+        assert!(!contains(&segment, b"secret"));
+        let len = fs::metadata(&segment).unwrap().len();
+
+        // A missing key fails the open and leaves the records alone
+        assert!(matches!(
+            Wal::open(&dir, WalOptions::new()),
+            Err(StorageError::Encrypted { .. })
+        ));
+        assert!(matches!(
+            Wal::open(&dir, encrypted(&KeyRing::new(2, [5; 32]))),
+            Err(StorageError::KeyUnavailable { id: 1, .. })
+        ));
+        assert_eq!(fs::metadata(&segment).unwrap().len(), len);
+
+        let wal = Wal::open(&dir, encrypted(&keys.rotate(2, [6; 32]))).unwrap();
+        assert_eq!(replayed(&wal, 1), vec![operation(0), secret, operation(2)]);
+    }
+
+    // -------------------------------------
+    //          Memory Engine Tests
+    // -------------------------------------
+
+    #[test]
+    fn test_memory_engine_matches_btree_engine() {
+        let dir = scratch_dir("memory-model");
+        let mut disk = BTreeEngine::open(&dir).unwrap();
+        let mut memory = MemoryEngine::new();
+        for (i, n) in scrambled(500).into_iter().enumerate() {
+            let namespace = ["a", "b"][n as usize % 2];
+            let key = tree_key(n % 150);
+            if i % 4 == 3 {
+                assert_eq!(
+                    memory.delete(namespace, &key).unwrap(),
+                    disk.delete(namespace, &key).unwrap()
+                );
+            } else {
+                let value = vec![n as u8; n as usize % 100];
+                memory.put(namespace, &key, &value).unwrap();
+                disk.put(namespace, &key, &value).unwrap();
+            }
+        }
+        assert_eq!(memory.namespaces().unwrap(), disk.namespaces().unwrap());
+        let ranges = [
+            KeyRange::all(),
+            KeyRange::prefix(b"key-00001"),
+            KeyRange::new(Bound::Excluded(tree_key(20)), Bound::Included(tree_key(90))),
+            // Empty ranges return nothing rather than panicking
+            KeyRange::new(Bound::Excluded(tree_key(5)), Bound::Excluded(tree_key(5))),
+            KeyRange::new(Bound::Included(tree_key(9)), Bound::Included(tree_key(3))),
+        ];
+        for namespace in ["a", "b", "missing"] {
+            for range in &ranges {
+                for limit in [1, 7, usize::MAX] {
+                    assert_eq!(
+                        memory.scan(namespace, range, limit).unwrap(),
+                        disk.scan(namespace, range, limit).unwrap()
+                    );
+                }
+            }
+        }
+
+        assert!(memory.drop_namespace("a").unwrap());
+        assert!(!memory.drop_namespace("a").unwrap());
+        assert_eq!(memory.get("a", &tree_key(1)).unwrap(), None);
+        assert_eq!(memory.namespaces().unwrap(), vec!["b"]);
+        assert!(!memory.is_empty());
+        assert!(memory.compact_step(0).unwrap().done);
+    }
+}
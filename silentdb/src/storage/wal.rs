@@ -0,0 +1,823 @@
+// src/storage/wal.rs
+
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use silentdb_data_encoding::{
+    from_bytes, peek_document_len, to_bytes, Document, Value, MIN_DOCUMENT_LEN,
+};
+
+use super::crc32;
+use super::encryption::Encryption;
+use super::error::StorageError;
+
+/// Name of the file in the log directory recording the last checkpoint.
+const CHECKPOINT_FILE: &str = "checkpoint";
+
+/// A log sequence number: the position of a record in the write-ahead log.
+///
+/// Sequence numbers start at 1 and increase by one for every record.
+pub type Lsn = u64;
+
+/// When the write-ahead log forces appended records to stable storage.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Sync after every appended record.
+    #[default]
+    Always,
+    /// Sync when a record is appended or a commit is made at least this
+    /// long after the previous sync. There is no background timer, so an
+    /// idle log should be synced explicitly with `Wal::sync`.
+    Interval(Duration),
+    /// Sync only on `Wal::commit`.
+    OnCommit,
+}
+
+/// Somewhere to keep the write-ahead log's segments once they are sealed,
+/// such as another disk or an object store, so the records in them
+/// outlive checkpoints and can be replayed on top of a base backup.
+///
+/// Archiving must be idempotent: a segment whose archiving failed, or
+/// that was sealed before the log was last opened, is offered again.
+pub trait SegmentArchive: Send + Sync {
+    /// Archives the sealed segment at `path`, whose first record has the
+    /// sequence number `first_lsn`. The segment is removed only once this
+    /// succeeds.
+    fn archive(&self, path: &Path, first_lsn: Lsn) -> Result<(), StorageError>;
+}
+
+/// A `SegmentArchive` copying segments into a directory, under the names
+/// they have in the log, so the directory can be read back with
+/// `Wal::read_archive`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirArchive {
+    dir: PathBuf,
+}
+
+impl DirArchive {
+    /// Creates an archive in `dir`, which is created on first use.
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        DirArchive {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+impl SegmentArchive for DirArchive {
+    /// Copies the segment unless a copy of the same size is already there,
+    /// writing it under a temporary name first so a crash cannot leave a
+    /// partial one behind.
+    fn archive(&self, path: &Path, first_lsn: Lsn) -> Result<(), StorageError> {
+        let archived = segment_path(&self.dir, first_lsn);
+        let len = fs::metadata(path)?.len();
+        if fs::metadata(&archived).is_ok_and(|archived| archived.len() == len) {
+            return Ok(());
+        }
+        fs::create_dir_all(&self.dir)?;
+        let partial = archived.with_extension("partial");
+        fs::copy(path, &partial)?;
+        File::open(&partial)?.sync_all()?;
+        fs::rename(&partial, &archived)?;
+        Ok(())
+    }
+}
+
+/// The archive a log's segments go to, compared by identity.
+#[derive(Clone)]
+pub(crate) struct ArchiveHook(Arc<dyn SegmentArchive>);
+
+impl fmt::Debug for ArchiveHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ArchiveHook")
+    }
+}
+
+impl PartialEq for ArchiveHook {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for ArchiveHook {}
+
+/// Options for opening a `Wal`.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb::{SyncPolicy, WalOptions};
+/// let options = WalOptions::new()
+///     .segment_size(4 * 1024 * 1024)
+///     .sync_policy(SyncPolicy::OnCommit);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalOptions {
+    pub(crate) segment_size: u64,
+    pub(crate) sync: SyncPolicy,
+    pub(crate) encryption: Option<Encryption>,
+    pub(crate) archive: Option<ArchiveHook>,
+}
+
+impl WalOptions {
+    /// Default size at which a new segment is started.
+    pub const DEFAULT_SEGMENT_SIZE: u64 = 16 * 1024 * 1024;
+
+    /// Creates options with 16 MiB segments that sync after every record.
+    pub fn new() -> Self {
+        WalOptions {
+            segment_size: WalOptions::DEFAULT_SEGMENT_SIZE,
+            sync: SyncPolicy::default(),
+            encryption: None,
+            archive: None,
+        }
+    }
+
+    /// Sets the size after which appends go to a new segment. Segments may
+    /// exceed it by one record.
+    pub fn segment_size(mut self, bytes: u64) -> Self {
+        self.segment_size = bytes.max(1);
+        self
+    }
+
+    /// Sets when appended records are synced to disk.
+    pub fn sync_policy(mut self, policy: SyncPolicy) -> Self {
+        self.sync = policy;
+        self
+    }
+
+    /// Encrypts the payloads of appended records. Records already in the
+    /// log stay as they are.
+    pub fn encryption(mut self, encryption: Encryption) -> Self {
+        self.encryption = Some(encryption);
+        self
+    }
+
+    /// Hands each segment to `archive` once it is sealed, and keeps it
+    /// through checkpoints until it has been archived.
+    pub fn archive<A: SegmentArchive + 'static>(self, archive: A) -> Self {
+        self.archive_with(Arc::new(archive))
+    }
+
+    /// Hands sealed segments to a shared archive, as `archive` does.
+    pub fn archive_with(mut self, archive: Arc<dyn SegmentArchive>) -> Self {
+        self.archive = Some(ArchiveHook(archive));
+        self
+    }
+}
+
+impl Default for WalOptions {
+    fn default() -> Self {
+        WalOptions::new()
+    }
+}
+
+/// A record read back from the write-ahead log.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WalRecord {
+    pub lsn: Lsn,
+    pub payload: Document,
+}
+
+/// A segment file and the sequence number of its first record.
+#[derive(Debug, Clone)]
+struct Segment {
+    first_lsn: Lsn,
+    path: PathBuf,
+    /// The segment's size, once it is no longer appended to.
+    len: u64,
+    /// Whether the segment has been handed to the log's archive.
+    archived: bool,
+}
+
+/// An append-only, segmented write-ahead log of documents.
+///
+/// Each segment is a sequence of BSON documents of the form
+/// `{lsn, crc, payload}`, where `payload` holds the encoded record and `crc`
+/// its CRC-32, so segments can be read with any document-sequence tool.
+/// Segment files are named after their first sequence number.
+///
+/// With encryption turned on in `WalOptions`, each payload is encrypted
+/// and authenticated against its sequence number, and the record gets an
+/// `encrypted: true` field. Encrypted records name the key they were
+/// encrypted with, and reading one whose key is unavailable is an error
+/// rather than a torn record, so opening such a log fails.
+///
+/// Opening a log recovers it: a record torn by a crash at the end of the
+/// last segment is truncated away, and `replay` then yields every record
+/// that made it to disk, in order.
+///
+/// Once the records up to some sequence number are safely applied
+/// elsewhere, `checkpoint` records that number and removes the segments
+/// holding only those records, so the log does not grow without bound.
+/// With an archive set in `WalOptions`, segments are archived as they are
+/// sealed, and only removed once they have been.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use silentdb::{Wal, WalOptions};
+/// # use silentdb_data_encoding::Document;
+/// let mut wal = Wal::open("data/wal", WalOptions::new()).unwrap();
+/// for record in wal.replay(1).unwrap() {
+///     let record = record.unwrap();
+///     // Re-apply record.payload ...
+/// }
+///
+/// let mut operation = Document::new();
+/// operation.insert("op", "insert");
+/// let lsn = wal.append(&operation).unwrap();
+/// wal.commit().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct Wal {
+    dir: PathBuf,
+    options: WalOptions,
+    segments: Vec<Segment>,
+    file: File,
+    segment_len: u64,
+    next_lsn: Lsn,
+    checkpoint_lsn: Lsn,
+    last_sync: Instant,
+    unsynced: bool,
+    syncs: u64,
+}
+
+impl Wal {
+    /// Opens the log in `dir`, creating the directory if needed, and
+    /// recovers it after a crash.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Corrupt` if a record before the end of the last segment or
+    /// the checkpoint file is damaged, or an I/O error if the directory
+    /// cannot be read. Damage in earlier segments is reported by `replay`.
+    pub fn open<P: AsRef<Path>>(dir: P, options: WalOptions) -> Result<Wal, StorageError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let checkpoint_lsn = read_checkpoint(&dir)?;
+
+        let mut segments = list_segments(&dir)?;
+        if segments.is_empty() {
+            let first_lsn = checkpoint_lsn + 1;
+            segments.push(Segment {
+                first_lsn,
+                path: segment_path(&dir, first_lsn),
+                len: 0,
+                archived: false,
+            });
+        }
+
+        // Only the last segment can have been cut short by a crash
+        let last = segments.last().unwrap();
+        let bytes = match fs::read(&last.path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err.into()),
+        };
+        let mut reader = SegmentReader::new(
+            &last.path,
+            bytes,
+            last.first_lsn,
+            true,
+            options.encryption.clone(),
+        );
+        let mut next_lsn = last.first_lsn;
+        for record in &mut reader {
+            next_lsn = record?.lsn + 1;
+        }
+        let valid_len = reader.position as u64;
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&last.path)?;
+        if file.metadata()?.len() != valid_len {
+            file.set_len(valid_len)?;
+            file.sync_all()?;
+        }
+
+        Ok(Wal {
+            dir,
+            options,
+            segments,
+            file,
+            segment_len: valid_len,
+            next_lsn,
+            checkpoint_lsn,
+            last_sync: Instant::now(),
+            unsynced: false,
+            syncs: 0,
+        })
+    }
+
+    /// Returns the directory holding the segments.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns the sequence number the next appended record will get.
+    pub fn next_lsn(&self) -> Lsn {
+        self.next_lsn
+    }
+
+    /// Returns the sequence number of the last record, if any.
+    pub fn last_lsn(&self) -> Option<Lsn> {
+        self.next_lsn.checked_sub(1).filter(|lsn| *lsn > 0)
+    }
+
+    /// Returns the sequence number of the oldest record kept, or of the
+    /// next record if the log is empty.
+    pub fn first_lsn(&self) -> Lsn {
+        self.segments[0].first_lsn
+    }
+
+    /// Returns the sequence number recorded by the last `checkpoint`, or 0
+    /// if there has been none.
+    pub fn checkpoint_lsn(&self) -> Lsn {
+        self.checkpoint_lsn
+    }
+
+    /// Returns the total size of the segments kept, in bytes.
+    pub fn size(&self) -> u64 {
+        let sealed = &self.segments[..self.segments.len() - 1];
+        sealed.iter().map(|segment| segment.len).sum::<u64>() + self.segment_len
+    }
+
+    /// Returns how many times records have been synced to stable storage
+    /// since the log was opened.
+    pub fn syncs(&self) -> u64 {
+        self.syncs
+    }
+
+    /// Records that every record up to and including `lsn` has been
+    /// applied durably elsewhere, and removes the segments holding only
+    /// such records. Returns the number of bytes removed.
+    ///
+    /// The checkpoint survives reopening the log; callers replaying the
+    /// log only need the records after `checkpoint_lsn`. If `lsn` is the
+    /// last record, a new segment is started so the current one can go too.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `lsn` has not been appended yet.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the checkpoint cannot be written or a segment
+    /// cannot be removed.
+    pub fn checkpoint(&mut self, lsn: Lsn) -> Result<u64, StorageError> {
+        assert!(lsn < self.next_lsn, "checkpoint past the end of the log");
+        self.sync()?;
+        write_checkpoint(&self.dir, lsn)?;
+        self.checkpoint_lsn = self.checkpoint_lsn.max(lsn);
+        if lsn + 1 == self.next_lsn && self.segment_len > 0 {
+            self.rotate()?;
+        }
+
+        // A segment only holds records up to `lsn` if the next one starts
+        // right after it
+        self.archive_sealed()?;
+        let mut removed = 0;
+        while self.segments.len() > 1
+            && self.segments[1].first_lsn <= lsn + 1
+            && (self.options.archive.is_none() || self.segments[0].archived)
+        {
+            let segment = self.segments.remove(0);
+            fs::remove_file(&segment.path)?;
+            removed += segment.len;
+        }
+        Ok(removed)
+    }
+
+    /// Appends `payload` to the log and returns its sequence number.
+    ///
+    /// The record is synced according to the log's `SyncPolicy`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the payload cannot be encoded or written.
+    pub fn append(&mut self, payload: &Document) -> Result<Lsn, StorageError> {
+        let lsn = self.append_deferred(payload)?;
+        match self.options.sync {
+            SyncPolicy::Always => self.sync()?,
+            SyncPolicy::Interval(interval) => self.sync_if_due(interval)?,
+            SyncPolicy::OnCommit => {}
+        }
+        Ok(lsn)
+    }
+
+    /// Appends `payload` to the log like `append`, but leaves it to a later
+    /// `commit` or `sync` to sync the record, whatever the log's
+    /// `SyncPolicy`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the payload cannot be encoded or written.
+    pub fn append_deferred(&mut self, payload: &Document) -> Result<Lsn, StorageError> {
+        if self.segment_len >= self.options.segment_size {
+            self.rotate()?;
+        }
+
+        let lsn = self.next_lsn;
+        let mut payload = to_bytes(payload)?;
+        let mut record = Document::new_with_capacity(4);
+        record.insert("lsn", lsn);
+        if let Some(encryption) = &self.options.encryption {
+            let location = format!("{} record {}", self.dir.display(), lsn);
+            payload = encryption.seal(&payload, &lsn.to_le_bytes(), &location)?;
+            record.insert("encrypted", true);
+        }
+        record.insert("crc", crc32(&payload) as i64);
+        record.insert("payload", payload);
+        let bytes = to_bytes(&record)?;
+
+        self.file.write_all(&bytes)?;
+        self.segment_len += bytes.len() as u64;
+        self.next_lsn += 1;
+        self.unsynced = true;
+        Ok(lsn)
+    }
+
+    /// Marks a commit point: under `SyncPolicy::OnCommit` everything
+    /// appended so far is synced before this returns.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if syncing fails.
+    pub fn commit(&mut self) -> Result<(), StorageError> {
+        match self.options.sync {
+            SyncPolicy::Interval(interval) => self.sync_if_due(interval),
+            _ => self.sync(),
+        }
+    }
+
+    /// Forces every appended record to stable storage.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if syncing fails.
+    pub fn sync(&mut self) -> Result<(), StorageError> {
+        if self.unsynced {
+            self.file.sync_data()?;
+            self.unsynced = false;
+            self.syncs += 1;
+        }
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+
+    /// Seals the current segment, if it holds any records, and archives
+    /// every sealed segment not archived yet, so the log's archive holds
+    /// every record appended so far.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a segment cannot be synced or archived.
+    pub fn seal(&mut self) -> Result<(), StorageError> {
+        if self.segment_len > 0 {
+            self.rotate()?;
+        }
+        self.archive_sealed()
+    }
+
+    /// Returns the records with sequence numbers of at least `from`, in order.
+    ///
+    /// Records removed by a checkpoint are skipped: replay starts at
+    /// `first_lsn` if `from` is before it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a segment cannot be opened. Damaged records are
+    /// reported by the iterator.
+    pub fn replay(&self, from: Lsn) -> Result<WalReplay, StorageError> {
+        // Skip segments that end before `from`
+        let start = self
+            .segments
+            .iter()
+            .rposition(|segment| segment.first_lsn <= from)
+            .unwrap_or(0);
+        Ok(WalReplay {
+            segments: self.segments[start..].to_vec(),
+            current: None,
+            from,
+            failed: false,
+            encryption: self.options.encryption.clone(),
+        })
+    }
+
+    /// Returns the records with sequence numbers of at least `from` in the
+    /// segments archived in `dir` by a `DirArchive`, in order, decrypting
+    /// them with the encryption in `options` if they were encrypted.
+    ///
+    /// Archived segments are read as they are, so records missing from the
+    /// archive are skipped: callers needing every record should check the
+    /// sequence numbers they get.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the archive cannot be listed. Damaged records are
+    /// reported by the iterator.
+    pub fn read_archive<P: AsRef<Path>>(
+        dir: P,
+        from: Lsn,
+        options: &WalOptions,
+    ) -> Result<WalReplay, StorageError> {
+        let segments = list_segments(dir.as_ref())?;
+        let start = segments
+            .iter()
+            .rposition(|segment| segment.first_lsn <= from)
+            .unwrap_or(0);
+        Ok(WalReplay {
+            segments: segments[start..].to_vec(),
+            current: None,
+            from,
+            failed: false,
+            encryption: options.encryption.clone(),
+        })
+    }
+
+    /// Returns the options the log was opened with.
+    pub fn options(&self) -> &WalOptions {
+        &self.options
+    }
+
+    /// Hands the sealed segments not archived yet to the log's archive.
+    fn archive_sealed(&mut self) -> Result<(), StorageError> {
+        let Some(ArchiveHook(archive)) = &self.options.archive else {
+            return Ok(());
+        };
+        let sealed = self.segments.len() - 1;
+        for segment in &mut self.segments[..sealed] {
+            if !segment.archived {
+                archive.archive(&segment.path, segment.first_lsn)?;
+                segment.archived = true;
+            }
+        }
+        Ok(())
+    }
+
+    fn sync_if_due(&mut self, interval: Duration) -> Result<(), StorageError> {
+        if self.last_sync.elapsed() >= interval {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    /// Syncs the current segment and starts a new one at `next_lsn`.
+    fn rotate(&mut self) -> Result<(), StorageError> {
+        self.sync()?;
+        let path = segment_path(&self.dir, self.next_lsn);
+        self.file = OpenOptions::new().create(true).append(true).open(&path)?;
+        self.segments.last_mut().unwrap().len = self.segment_len;
+        self.segments.push(Segment {
+            first_lsn: self.next_lsn,
+            path,
+            len: 0,
+            archived: false,
+        });
+        self.segment_len = 0;
+        // A failed archive is retried by the next rotation or checkpoint,
+        // which keeps the segment until then
+        let _ = self.archive_sealed();
+        Ok(())
+    }
+}
+
+/// An iterator over the records of a `Wal`, returned by `Wal::replay`.
+///
+/// The iterator is fused after the first error.
+#[derive(Debug)]
+pub struct WalReplay {
+    segments: Vec<Segment>,
+    current: Option<SegmentReader>,
+    from: Lsn,
+    failed: bool,
+    encryption: Option<Encryption>,
+}
+
+impl Iterator for WalReplay {
+    type Item = Result<WalRecord, StorageError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        loop {
+            if let Some(reader) = &mut self.current {
+                match reader.next() {
+                    Some(Ok(record)) if record.lsn < self.from => continue,
+                    Some(Ok(record)) => return Some(Ok(record)),
+                    Some(Err(err)) => {
+                        self.failed = true;
+                        return Some(Err(err));
+                    }
+                    None => self.current = None,
+                }
+            }
+
+            if self.segments.is_empty() {
+                return None;
+            }
+            let segment = self.segments.remove(0);
+            match fs::read(&segment.path) {
+                Ok(bytes) => {
+                    self.current = Some(SegmentReader::new(
+                        &segment.path,
+                        bytes,
+                        segment.first_lsn,
+                        self.segments.is_empty(),
+                        self.encryption.clone(),
+                    ))
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => {
+                    self.failed = true;
+                    return Some(Err(err.into()));
+                }
+            }
+        }
+    }
+}
+
+/// Reads the records of one segment held in memory.
+///
+/// A record cut off by the end of the segment ends the iteration without an
+/// error; `position` is then the length of the intact prefix. In the last
+/// segment, so does a tail of zeros, which a file system can leave behind
+/// when it grew the file but crashed before the data reached it.
+#[derive(Debug)]
+struct SegmentReader {
+    path: PathBuf,
+    bytes: Vec<u8>,
+    position: usize,
+    expected_lsn: Lsn,
+    last: bool,
+    encryption: Option<Encryption>,
+}
+
+impl SegmentReader {
+    fn new(
+        path: &Path,
+        bytes: Vec<u8>,
+        first_lsn: Lsn,
+        last: bool,
+        encryption: Option<Encryption>,
+    ) -> Self {
+        SegmentReader {
+            path: path.to_path_buf(),
+            bytes,
+            position: 0,
+            expected_lsn: first_lsn,
+            last,
+            encryption,
+        }
+    }
+
+    fn read_record(&self, bytes: &[u8]) -> Result<WalRecord, StorageError> {
+        let corrupt = |message: &str| {
+            StorageError::corrupt(
+                format!("{} at offset {}", self.path.display(), self.position),
+                message,
+            )
+        };
+        let location = || format!("{} at offset {}", self.path.display(), self.position);
+        let record = from_bytes(bytes)?;
+        let lsn = match record.get("lsn") {
+            Some(Value::UInt64(lsn)) => *lsn,
+            _ => return Err(corrupt("record has no lsn")),
+        };
+        let (Some(Value::Int64(crc)), Some(Value::Binary(payload))) =
+            (record.get("crc"), record.get("payload"))
+        else {
+            return Err(corrupt("record has no checksummed payload"));
+        };
+        if crc32(payload) as i64 != *crc {
+            return Err(corrupt("checksum mismatch"));
+        }
+        if lsn != self.expected_lsn {
+            return Err(corrupt(&format!(
+                "expected lsn {} but found {}",
+                self.expected_lsn, lsn
+            )));
+        }
+        if record.get("encrypted") != Some(&Value::Boolean(true)) {
+            return Ok(WalRecord {
+                lsn,
+                payload: from_bytes(payload)?,
+            });
+        }
+        let encryption = self
+            .encryption
+            .as_ref()
+            .ok_or_else(|| StorageError::Encrypted {
+                location: location(),
+            })?;
+        let payload = encryption.open(payload, &lsn.to_le_bytes(), &location())?;
+        Ok(WalRecord {
+            lsn,
+            payload: from_bytes(&payload)?,
+        })
+    }
+}
+
+impl Iterator for SegmentReader {
+    type Item = Result<WalRecord, StorageError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = &self.bytes[self.position..];
+        let len = peek_document_len(rest)?;
+        if len > rest.len() {
+            // A torn write at the end of the segment
+            return None;
+        }
+        if self.last && len < MIN_DOCUMENT_LEN {
+            // No record is this short, so this is space the file system
+            // allocated but never wrote, such as a run of zeros
+            return None;
+        }
+        let record = self.read_record(&rest[..len]);
+        match &record {
+            Ok(_) => {
+                self.position += len;
+                self.expected_lsn += 1;
+            }
+            // A damaged final record is treated as torn, but one whose key
+            // is missing is not damaged
+            Err(e) if self.position + len == self.bytes.len() && !e.is_key_error() => return None,
+            Err(_) => {}
+        }
+        Some(record)
+    }
+}
+
+fn segment_path(dir: &Path, first_lsn: Lsn) -> PathBuf {
+    dir.join(format!("{:020}.wal", first_lsn))
+}
+
+/// Lists the segment files in `dir`, ordered by first sequence number.
+fn list_segments(dir: &Path) -> Result<Vec<Segment>, StorageError> {
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("wal") {
+            continue;
+        }
+        let first_lsn = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse().ok());
+        if let Some(first_lsn) = first_lsn {
+            let len = fs::metadata(&path)?.len();
+            segments.push(Segment {
+                first_lsn,
+                path,
+                len,
+                archived: false,
+            });
+        }
+    }
+    segments.sort_by_key(|segment| segment.first_lsn);
+    Ok(segments)
+}
+
+/// Reads the sequence number recorded by the last checkpoint in `dir`, or
+/// 0 if there is none.
+fn read_checkpoint(dir: &Path) -> Result<Lsn, StorageError> {
+    let path = dir.join(CHECKPOINT_FILE);
+    let bytes = match fs::read(&path) {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err.into()),
+    };
+    let record = from_bytes(&bytes)?;
+    match (record.get("lsn"), record.get("crc")) {
+        (Some(Value::UInt64(lsn)), Some(Value::Int64(crc)))
+            if crc32(&lsn.to_le_bytes()) as i64 == *crc =>
+        {
+            Ok(*lsn)
+        }
+        _ => Err(StorageError::corrupt(path.display(), "bad checkpoint")),
+    }
+}
+
+/// Records `lsn` as the last checkpoint in `dir`, replacing the previous
+/// one atomically.
+fn write_checkpoint(dir: &Path, lsn: Lsn) -> Result<(), StorageError> {
+    let mut record = Document::new_with_capacity(2);
+    record.insert("lsn", lsn);
+    record.insert("crc", crc32(&lsn.to_le_bytes()) as i64);
+    let partial = dir.join(format!("{}.tmp", CHECKPOINT_FILE));
+    let mut file = File::create(&partial)?;
+    file.write_all(&to_bytes(&record)?)?;
+    file.sync_all()?;
+    fs::rename(&partial, dir.join(CHECKPOINT_FILE))?;
+    Ok(())
+}
@@ -0,0 +1,178 @@
+// src/text/analyzer.rs
+
+use std::collections::BTreeSet;
+
+use silentdb_data_encoding::{Array, Document, Value};
+
+use super::stem::stem;
+
+/// Common English words left out of text indexes unless told otherwise.
+const ENGLISH_STOP_WORDS: &str = "\
+    a about above after again against all am an and any are as at be because been before \
+    being below between both but by can did do does doing down during each few for from \
+    further had has have having he her here hers herself him himself his how i if in into is \
+    it its itself just me more most my myself no nor not now of off on once only or other \
+    our ours ourselves out over own same she should so some such than that the their theirs \
+    them themselves then there these they this those through to too under until up very was \
+    we were what when where which while who whom why will with you your yours yourself \
+    yourselves";
+
+/// How text is split into words.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tokenizer {
+    /// Words are separated by whitespace, with punctuation trimmed from
+    /// their ends, so `e-mail` stays one word.
+    Whitespace,
+    /// Words are runs of letters and digits in any script, with
+    /// apostrophes inside them dropped, so `e-mail` is two words and
+    /// `don't` is `dont`.
+    Unicode,
+}
+
+/// How a text index turns strings into the terms it indexes and searches
+/// for: a tokenizer splits text into words, which are lowercased, dropped if
+/// they are stop words, and optionally stemmed.
+///
+/// # Examples
+///
+/// ```
+/// # use silentdb::TextOptions;
+/// let options = TextOptions::new();
+/// assert_eq!(
+///     options.terms("The quick foxes were jumping"),
+///     vec!["quick", "fox", "jump"]
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextOptions {
+    pub(crate) tokenizer: Tokenizer,
+    pub(crate) stemming: bool,
+    pub(crate) stop_words: BTreeSet<String>,
+}
+
+impl Default for TextOptions {
+    fn default() -> Self {
+        TextOptions {
+            tokenizer: Tokenizer::Unicode,
+            stemming: true,
+            stop_words: ENGLISH_STOP_WORDS
+                .split_whitespace()
+                .map(str::to_string)
+                .collect(),
+        }
+    }
+}
+
+impl TextOptions {
+    /// Returns the default options: the Unicode tokenizer, English
+    /// stemming and English stop words.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn tokenizer(mut self, tokenizer: Tokenizer) -> Self {
+        self.tokenizer = tokenizer;
+        self
+    }
+
+    /// Sets whether words are reduced to their English stems.
+    pub fn stemming(mut self, stemming: bool) -> Self {
+        self.stemming = stemming;
+        self
+    }
+
+    /// Replaces the stop words, which are matched before stemming and
+    /// regardless of case.
+    pub fn stop_words<I, S>(mut self, stop_words: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.stop_words = stop_words
+            .into_iter()
+            .map(|word| word.as_ref().to_lowercase())
+            .collect();
+        self
+    }
+
+    /// Returns the terms of `text`, in order and with repeats.
+    pub fn terms(&self, text: &str) -> Vec<String> {
+        let words: Vec<String> = match self.tokenizer {
+            Tokenizer::Whitespace => text
+                .split_whitespace()
+                .map(|word| word.trim_matches(|c: char| !c.is_alphanumeric()))
+                .filter(|word| !word.is_empty())
+                .map(str::to_lowercase)
+                .collect(),
+            Tokenizer::Unicode => {
+                let mut words = Vec::new();
+                let mut word = String::new();
+                let mut chars = text.chars().peekable();
+                while let Some(c) = chars.next() {
+                    if c.is_alphanumeric() {
+                        word.extend(c.to_lowercase());
+                        continue;
+                    }
+                    let inner_apostrophe = matches!(c, '\'' | '\u{2019}')
+                        && !word.is_empty()
+                        && chars.peek().is_some_and(|next| next.is_alphanumeric());
+                    if !inner_apostrophe && !word.is_empty() {
+                        words.push(std::mem::take(&mut word));
+                    }
+                }
+                if !word.is_empty() {
+                    words.push(word);
+                }
+                words
+            }
+        };
+        words
+            .into_iter()
+            .filter(|word| !self.stop_words.contains(word))
+            .map(|word| if self.stemming { stem(&word) } else { word })
+            .collect()
+    }
+
+    pub(crate) fn to_document(&self) -> Document {
+        let mut document = Document::new();
+        let tokenizer = match self.tokenizer {
+            Tokenizer::Whitespace => "whitespace",
+            Tokenizer::Unicode => "unicode",
+        };
+        document.insert("tokenizer", tokenizer);
+        document.insert("stemming", self.stemming);
+        let stop_words = self
+            .stop_words
+            .iter()
+            .map(|word| Value::from(word.as_str()))
+            .collect();
+        document.insert("stop_words", Array::from_vec(stop_words));
+        document
+    }
+
+    pub(crate) fn from_document(document: &Document) -> Option<TextOptions> {
+        let tokenizer = match document.get("tokenizer") {
+            Some(Value::String(name)) if name == "whitespace" => Tokenizer::Whitespace,
+            Some(Value::String(name)) if name == "unicode" => Tokenizer::Unicode,
+            _ => return None,
+        };
+        let Some(Value::Boolean(stemming)) = document.get("stemming") else {
+            return None;
+        };
+        let Some(Value::Array(stop_words)) = document.get("stop_words") else {
+            return None;
+        };
+        let stop_words = stop_words
+            .iter()
+            .map(|word| match word {
+                Value::String(word) => Some(word.clone()),
+                _ => None,
+            })
+            .collect::<Option<_>>()?;
+        Some(TextOptions {
+            tokenizer,
+            stemming: *stemming,
+            stop_words,
+        })
+    }
+}
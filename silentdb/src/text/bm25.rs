@@ -0,0 +1,34 @@
+// src/text/bm25.rs
+
+/// How quickly repeats of a term stop raising a document's score.
+const K1: f64 = 1.2;
+/// How much a document's length discounts its term counts, from none at 0
+/// to full at 1.
+const B: f64 = 0.75;
+
+/// Counts over the documents of a text index that term scores depend on.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Corpus {
+    /// The number of indexed documents.
+    pub(crate) documents: u64,
+    /// The total number of terms over all of them.
+    pub(crate) terms: u64,
+}
+
+impl Corpus {
+    /// Returns the BM25 score a term contributes to a document holding it
+    /// `frequency` times among `length` terms, when `containing` documents
+    /// hold it.
+    ///
+    /// Rare terms weigh more than common ones, and each repeat of a term
+    /// adds less than the last.
+    pub(crate) fn score(&self, frequency: u32, length: u32, containing: u64) -> f64 {
+        let documents = self.documents as f64;
+        let containing = containing as f64;
+        let idf = ((documents - containing + 0.5) / (containing + 0.5) + 1.0).ln();
+        let average = self.terms as f64 / documents.max(1.0);
+        let frequency = frequency as f64;
+        let norm = K1 * (1.0 - B + B * length as f64 / average.max(1.0));
+        idf * frequency * (K1 + 1.0) / (frequency + norm)
+    }
+}
@@ -0,0 +1,9 @@
+// src/text/mod.rs
+
+mod analyzer;
+mod bm25;
+mod stem;
+mod test;
+
+pub use analyzer::{TextOptions, Tokenizer};
+pub(crate) use bm25::Corpus;
@@ -0,0 +1,222 @@
+// src/text/stem.rs
+
+/// Reduces an English word to its stem with the Porter algorithm, so that
+/// forms like `connected`, `connecting` and `connections` share the stem
+/// `connect`.
+///
+/// Expects a lowercase word. Words of two letters or fewer, and words with
+/// anything but ASCII letters, are returned as they are.
+pub(crate) fn stem(word: &str) -> String {
+    if word.len() <= 2 || !word.bytes().all(|b| b.is_ascii_lowercase()) {
+        return word.to_string();
+    }
+    let mut stemmer = Stemmer {
+        word: word.as_bytes().to_vec(),
+    };
+    stemmer.step1a();
+    stemmer.step1b();
+    stemmer.step1c();
+    stemmer.step2();
+    stemmer.step3();
+    stemmer.step4();
+    stemmer.step5();
+    String::from_utf8(stemmer.word).expect("stems are ASCII")
+}
+
+struct Stemmer {
+    word: Vec<u8>,
+}
+
+impl Stemmer {
+    /// Returns `true` if the letter at `i` is a consonant, where `y` is one
+    /// unless it follows a consonant.
+    fn consonant(&self, i: usize) -> bool {
+        match self.word[i] {
+            b'a' | b'e' | b'i' | b'o' | b'u' => false,
+            b'y' => i == 0 || !self.consonant(i - 1),
+            _ => true,
+        }
+    }
+
+    /// Returns the number of vowel-consonant sequences in the first `len`
+    /// letters, the `m` of the algorithm.
+    fn measure(&self, len: usize) -> usize {
+        let mut m = 0;
+        let mut vowel = false;
+        for i in 0..len {
+            if self.consonant(i) {
+                if vowel {
+                    m += 1;
+                }
+                vowel = false;
+            } else {
+                vowel = true;
+            }
+        }
+        m
+    }
+
+    fn has_vowel(&self, len: usize) -> bool {
+        (0..len).any(|i| !self.consonant(i))
+    }
+
+    /// Returns `true` if the first `len` letters end in a double consonant.
+    fn double_consonant(&self, len: usize) -> bool {
+        len >= 2 && self.word[len - 1] == self.word[len - 2] && self.consonant(len - 1)
+    }
+
+    /// Returns `true` if the first `len` letters end consonant-vowel-
+    /// consonant, with the last consonant not `w`, `x` or `y`.
+    fn cvc(&self, len: usize) -> bool {
+        len >= 3
+            && self.consonant(len - 3)
+            && !self.consonant(len - 2)
+            && self.consonant(len - 1)
+            && !matches!(self.word[len - 1], b'w' | b'x' | b'y')
+    }
+
+    /// Returns the length of the stem left by removing `suffix`, if the
+    /// word ends with it.
+    fn stem_len(&self, suffix: &str) -> Option<usize> {
+        self.word
+            .ends_with(suffix.as_bytes())
+            .then(|| self.word.len() - suffix.len())
+    }
+
+    fn replace(&mut self, len: usize, replacement: &str) {
+        self.word.truncate(len);
+        self.word.extend_from_slice(replacement.as_bytes());
+    }
+
+    /// Replaces the first suffix of `rules` the word ends with, if the
+    /// stem before it has a measure above `min_measure`.
+    fn replace_suffix(&mut self, rules: &[(&str, &str)], min_measure: usize) {
+        for (suffix, replacement) in rules {
+            if let Some(len) = self.stem_len(suffix) {
+                if self.measure(len) > min_measure {
+                    self.replace(len, replacement);
+                }
+                return;
+            }
+        }
+    }
+
+    fn step1a(&mut self) {
+        for (suffix, replacement) in [("sses", "ss"), ("ies", "i"), ("ss", "ss"), ("s", "")] {
+            if let Some(len) = self.stem_len(suffix) {
+                self.replace(len, replacement);
+                return;
+            }
+        }
+    }
+
+    fn step1b(&mut self) {
+        if let Some(len) = self.stem_len("eed") {
+            if self.measure(len) > 0 {
+                self.replace(len, "ee");
+            }
+            return;
+        }
+        let Some(len) = ["ed", "ing"]
+            .iter()
+            .find_map(|suffix| self.stem_len(suffix))
+            .filter(|&len| self.has_vowel(len))
+        else {
+            return;
+        };
+        self.word.truncate(len);
+        if ["at", "bl", "iz"]
+            .iter()
+            .any(|s| self.word.ends_with(s.as_bytes()))
+        {
+            self.word.push(b'e');
+        } else if self.double_consonant(len) && !matches!(self.word[len - 1], b'l' | b's' | b'z') {
+            self.word.pop();
+        } else if self.measure(len) == 1 && self.cvc(len) {
+            self.word.push(b'e');
+        }
+    }
+
+    fn step1c(&mut self) {
+        if let Some(len) = self.stem_len("y") {
+            if self.has_vowel(len) {
+                self.replace(len, "i");
+            }
+        }
+    }
+
+    fn step2(&mut self) {
+        self.replace_suffix(
+            &[
+                ("ational", "ate"),
+                ("tional", "tion"),
+                ("enci", "ence"),
+                ("anci", "ance"),
+                ("izer", "ize"),
+                ("abli", "able"),
+                ("alli", "al"),
+                ("entli", "ent"),
+                ("eli", "e"),
+                ("ousli", "ous"),
+                ("ization", "ize"),
+                ("ation", "ate"),
+                ("ator", "ate"),
+                ("alism", "al"),
+                ("iveness", "ive"),
+                ("fulness", "ful"),
+                ("ousness", "ous"),
+                ("aliti", "al"),
+                ("iviti", "ive"),
+                ("biliti", "ble"),
+            ],
+            0,
+        );
+    }
+
+    fn step3(&mut self) {
+        self.replace_suffix(
+            &[
+                ("icate", "ic"),
+                ("ative", ""),
+                ("alize", "al"),
+                ("iciti", "ic"),
+                ("ical", "ic"),
+                ("ful", ""),
+                ("ness", ""),
+            ],
+            0,
+        );
+    }
+
+    fn step4(&mut self) {
+        const SUFFIXES: [&str; 19] = [
+            "al", "ance", "ence", "er", "ic", "able", "ible", "ant", "ement", "ment", "ent", "ion",
+            "ou", "ism", "ate", "iti", "ous", "ive", "ize",
+        ];
+        // The longest matching suffix applies, so "ement" beats "ent"
+        let Some((suffix, len)) = SUFFIXES
+            .iter()
+            .filter_map(|suffix| Some((*suffix, self.stem_len(suffix)?)))
+            .min_by_key(|(_, len)| *len)
+        else {
+            return;
+        };
+        let allowed = suffix != "ion" || (len > 0 && matches!(self.word[len - 1], b's' | b't'));
+        if allowed && self.measure(len) > 1 {
+            self.word.truncate(len);
+        }
+    }
+
+    fn step5(&mut self) {
+        if let Some(len) = self.stem_len("e") {
+            let m = self.measure(len);
+            if m > 1 || (m == 1 && !self.cvc(len)) {
+                self.word.truncate(len);
+            }
+        }
+        let len = self.word.len();
+        if self.measure(len) > 1 && self.double_consonant(len) && self.word[len - 1] == b'l' {
+            self.word.pop();
+        }
+    }
+}
@@ -0,0 +1,86 @@
+// src/text/test.rs
+
+#[cfg(test)]
+mod tests {
+    use crate::text::stem::stem;
+    use crate::text::{Corpus, TextOptions, Tokenizer};
+
+    // -------------------------------------
+    //          Analyzer Tests
+    // -------------------------------------
+
+    #[test]
+    fn test_tokenizers() {
+        let plain = TextOptions::new()
+            .stemming(false)
+            .stop_words(Vec::<String>::new());
+        let text = "Don't e-mail the CAFÉ, (please)!";
+        assert_eq!(
+            plain.terms(text),
+            vec!["dont", "e", "mail", "the", "café", "please"]
+        );
+        assert_eq!(
+            plain.tokenizer(Tokenizer::Whitespace).terms(text),
+            vec!["don't", "e-mail", "the", "café", "please"]
+        );
+        assert!(TextOptions::new().terms(" ,;- ").is_empty());
+    }
+
+    #[test]
+    fn test_stop_words_and_stemming() {
+        let options = TextOptions::new();
+        assert_eq!(
+            options.terms("The runners were running to the races"),
+            vec!["runner", "run", "race"]
+        );
+        let custom = TextOptions::new().stop_words(["Runners"]);
+        assert_eq!(custom.terms("the runners"), vec!["the"]);
+    }
+
+    #[test]
+    fn test_porter_stemmer() {
+        for (word, expected) in [
+            ("caresses", "caress"),
+            ("ponies", "poni"),
+            ("cats", "cat"),
+            ("feed", "feed"),
+            ("agreed", "agre"),
+            ("plastered", "plaster"),
+            ("motoring", "motor"),
+            ("sing", "sing"),
+            ("conflated", "conflat"),
+            ("hopping", "hop"),
+            ("falling", "fall"),
+            ("filing", "file"),
+            ("happy", "happi"),
+            ("relational", "relat"),
+            ("conditional", "condit"),
+            ("digitizer", "digit"),
+            ("hopefulness", "hope"),
+            ("electrical", "electr"),
+            ("replacement", "replac"),
+            ("adoption", "adopt"),
+            ("generalization", "gener"),
+            ("controlling", "control"),
+            ("connections", "connect"),
+            ("is", "is"),
+            ("naïve", "naïve"),
+        ] {
+            assert_eq!(stem(word), expected, "{}", word);
+        }
+    }
+
+    #[test]
+    fn test_bm25() {
+        let corpus = Corpus {
+            documents: 10,
+            terms: 100,
+        };
+        // Rarer terms, more repeats and shorter documents score higher
+        assert!(corpus.score(1, 10, 1) > corpus.score(1, 10, 5));
+        assert!(corpus.score(3, 10, 1) > corpus.score(1, 10, 1));
+        assert!(corpus.score(1, 5, 1) > corpus.score(1, 20, 1));
+        assert!(corpus.score(10, 10, 1) < 2.2 * corpus.score(1, 10, 1) * 10.0);
+        assert!(corpus.score(1, 10, 10) > 0.0);
+    }
+}
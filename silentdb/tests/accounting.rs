@@ -0,0 +1,94 @@
+// tests/accounting.rs
+
+// A global allocator is process-wide, so these tests get a binary of their
+// own rather than running every unit test of the crate with accounting on.
+
+use std::alloc::System;
+
+use silentdb::{memory_stats, Database, MemoryCategory, MemoryScope, TrackingAllocator};
+use silentdb_data_encoding::{Document, Value};
+
+#[global_allocator]
+static ALLOCATOR: TrackingAllocator<System> = TrackingAllocator::new(System);
+
+#[test]
+fn test_memory_scopes_nest() {
+    let before = memory_stats();
+    let sort = MemoryScope::enter(MemoryCategory::Sort);
+    let outer: Vec<u8> = Vec::with_capacity(1 << 20);
+    {
+        let _cursor = MemoryScope::enter(MemoryCategory::Cursor);
+        let inner: Vec<u8> = Vec::with_capacity(1 << 16);
+        drop(inner);
+    }
+    // Leaving the inner scope attributes to the outer one again
+    let after_inner: Vec<u8> = Vec::with_capacity(1 << 18);
+    drop(sort);
+    let after = memory_stats();
+
+    assert!(after.tracking);
+    assert!(after.sort.bytes - before.sort.bytes >= (1 << 20) + (1 << 18));
+    assert!(after.sort.allocations - before.sort.allocations >= 2);
+    assert!(after.cursor.bytes - before.cursor.bytes >= 1 << 16);
+    assert_eq!(after.category(MemoryCategory::Sort), after.sort);
+    drop((outer, after_inner));
+}
+
+#[test]
+fn test_memory_realloc_growth() {
+    let mut buffer: Vec<u8> = Vec::with_capacity(1 << 20);
+    let before = memory_stats();
+    {
+        let _cursor = MemoryScope::enter(MemoryCategory::Cursor);
+        buffer.reserve_exact(2 << 20);
+    }
+    let after = memory_stats();
+
+    // Growing a block counts its extra bytes to the scope it grew in
+    assert!(after.cursor.bytes - before.cursor.bytes >= 1 << 20);
+    assert!(after.cursor.allocations > before.cursor.allocations);
+    drop(buffer);
+}
+
+#[test]
+fn test_memory_live_and_peak() {
+    let buffer: Vec<u8> = Vec::with_capacity(4 << 20);
+    let held = memory_stats();
+    drop(buffer);
+    let freed = memory_stats();
+
+    assert!(held.live >= 4 << 20);
+    assert!(held.peak >= held.live);
+    // Freeing lowers what is live but not the peak
+    assert!(freed.peak >= 4 << 20);
+    assert!(freed.peak >= freed.live);
+}
+
+#[test]
+fn test_memory_metrics() {
+    let dir = std::env::temp_dir().join(format!("silentdb-accounting-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let db = Database::open(&dir).unwrap();
+    let users = db.collection("users");
+    for id in 0..10 {
+        let mut user = Document::new();
+        user.insert("_id", id);
+        user.insert("name", "x".repeat(100));
+        users.insert_one(user).unwrap();
+    }
+    let mut cursor = users.find(&Document::new()).unwrap().batch_size(4);
+    cursor.next().unwrap().unwrap();
+
+    // The cursor decoded what it read
+    let metrics = db.metrics();
+    let Some(Value::Document(memory)) = metrics.get("memory") else {
+        panic!("no memory in {:?}", metrics);
+    };
+    assert!(matches!(memory.get("peak"), Some(Value::Int64(n)) if *n > 0));
+    let Some(Value::Document(decode)) = memory.get("decode") else {
+        panic!("no decode in {:?}", memory);
+    };
+    assert!(matches!(decode.get("bytes"), Some(Value::Int64(n)) if *n > 0));
+    let text = db.prometheus_metrics();
+    assert!(text.contains("silentdb_memory_allocated_bytes_total{category=\"cursor\"} "));
+}